@@ -0,0 +1,179 @@
+//! TOML config files with environment overrides.
+//!
+//! Environment variables named `<PREFIX>_<KEY>` override keys of the file;
+//! `__` separates the keys of nested tables, so `GROUPBOT_PROXY__PORT=9150`
+//! sets `port` in `[proxy]`. Values are read as TOML if they parse
+//! (`true`, `9150`, `["a", "b"]`) and as strings otherwise.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use toml::{Table, Value};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    /// An environment override names a key inside a non-table value.
+    Env(String),
+}
+
+impl std::error::Error for ConfigError {}
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read config: {}", e),
+            ConfigError::Parse(e) => write!(f, "failed to parse config: {}", e),
+            ConfigError::Serialize(e) => write!(f, "failed to write config: {}", e),
+            ConfigError::Env(var) => write!(f, "invalid config override {}", var),
+        }
+    }
+}
+
+/// A DHT node to bootstrap from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootstrapNode {
+    pub host: String,
+    pub port: u16,
+    /// DHT public key, hex.
+    pub public_key: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyType {
+    #[default]
+    None,
+    Socks5,
+    Http,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    #[serde(rename = "type")]
+    pub kind: ProxyType,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            kind: ProxyType::None,
+            host: "127.0.0.1".to_string(),
+            port: 9050,
+        }
+    }
+}
+
+impl ProxyConfig {
+    /// A local Tor daemon.
+    pub fn tor() -> Self {
+        Self {
+            kind: ProxyType::Socks5,
+            ..Self::default()
+        }
+    }
+}
+
+/// Loads the config at `path`, or the defaults if `None`, with overrides
+/// from the environment.
+pub fn load<T: DeserializeOwned>(path: Option<&Path>, env_prefix: &str) -> Result<T, ConfigError> {
+    let text = match path {
+        Some(path) => fs::read_to_string(path).map_err(ConfigError::Io)?,
+        None => String::new(),
+    };
+    parse(&text, env_prefix, std::env::vars())
+}
+
+/// Parses `text`, applying the overrides in `vars` whose names start with
+/// `env_prefix`.
+pub fn parse<T: DeserializeOwned>(
+    text: &str,
+    env_prefix: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<T, ConfigError> {
+    let mut table: Table = toml::from_str(text).map_err(ConfigError::Parse)?;
+    let prefix = format!("{}_", env_prefix);
+    for (name, value) in vars {
+        let Some(key) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        let path: Vec<String> = key.split("__").map(str::to_lowercase).collect();
+        set(&mut table, &path, env_value(&value)).map_err(|()| ConfigError::Env(name.clone()))?;
+    }
+    Value::Table(table).try_into().map_err(ConfigError::Parse)
+}
+
+fn env_value(value: &str) -> Value {
+    toml::from_str::<Table>(&format!("v = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn set(table: &mut Table, path: &[String], value: Value) -> Result<(), ()> {
+    match path {
+        [] => Err(()),
+        [key] => {
+            table.insert(key.clone(), value);
+            Ok(())
+        }
+        [key, rest @ ..] => match table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(inner) => set(inner, rest, value),
+            _ => Err(()),
+        },
+    }
+}
+
+/// Writes `config` to `path` as TOML, failing if the file exists.
+pub fn write_default<T: Serialize>(path: &Path, config: &T) -> Result<(), ConfigError> {
+    let text = toml::to_string_pretty(config).map_err(ConfigError::Serialize)?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| io::Write::write_all(&mut file, text.as_bytes()))
+        .map_err(ConfigError::Io)
+}
+
+/// Notices changes to a config file by its modification time.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+        Self { path, modified }
+    }
+
+    /// Whether the file changed since the last call.
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
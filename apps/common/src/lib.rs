@@ -0,0 +1,4 @@
+//! Setup shared by the bots in `apps/`.
+
+pub mod config;
+pub mod logging;
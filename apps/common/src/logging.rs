@@ -0,0 +1,281 @@
+//! Log output for long-running bots.
+//!
+//! Logs go to stdout and, if a directory is configured, to files rotated
+//! by time or by size. Either output can be text or one JSON object per
+//! line. Levels are set per module in the `[logging]` section of the bot's
+//! config file:
+//!
+//! ```toml
+//! [logging]
+//! level = "info"
+//! format = "json"
+//! directory = "logs"
+//! rotation = "daily"
+//! max_files = 7
+//!
+//! [logging.modules]
+//! merkle_tox_core = "debug"
+//! ```
+//!
+//! `RUST_LOG`, if set, is applied on top of the file. Levels can be changed
+//! while running with [`LogGuard::reload`]; the outputs cannot.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation as AppenderRotation};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// When log files are rotated. Ignored if `max_size_mb` is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Level of modules without an override.
+    pub level: String,
+    /// Level overrides by module path, e.g. `merkle_tox_core::engine`.
+    pub modules: BTreeMap<String, String>,
+    pub format: LogFormat,
+    pub stdout: bool,
+    /// Directory of the log files. No files are written if absent.
+    pub directory: Option<PathBuf>,
+    /// Name of the log files, before the rotation suffix. Defaults to the
+    /// bot's name.
+    pub file_prefix: Option<String>,
+    pub rotation: Rotation,
+    /// Rotates files once they grow past this size instead of by time.
+    pub max_size_mb: Option<u64>,
+    /// Rotated files kept besides the current one.
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+            format: LogFormat::Text,
+            stdout: true,
+            directory: None,
+            file_prefix: None,
+            rotation: Rotation::Daily,
+            max_size_mb: None,
+            max_files: 7,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(io::Error),
+    Filter(String),
+    Init(String),
+}
+
+impl std::error::Error for LogError {}
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::Io(e) => write!(f, "failed to open log output: {}", e),
+            LogError::Filter(e) => write!(f, "invalid log level: {}", e),
+            LogError::Init(e) => write!(f, "failed to install logger: {}", e),
+        }
+    }
+}
+
+impl LogConfig {
+    /// Filter directives: the default level, then the module overrides,
+    /// then `env` (the value of `RUST_LOG`), later ones taking precedence.
+    pub fn filter(&self, env: Option<&str>) -> Result<EnvFilter, LogError> {
+        let mut directives = vec![self.level.clone()];
+        directives.extend(
+            self.modules
+                .iter()
+                .map(|(module, level)| format!("{}={}", module, level)),
+        );
+        directives.extend(env.filter(|env| !env.is_empty()).map(str::to_string));
+        EnvFilter::try_new(directives.join(",")).map_err(|e| LogError::Filter(e.to_string()))
+    }
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+/// Flushes buffered file output when dropped. Keep it until exit.
+#[must_use]
+pub struct LogGuard {
+    filter: reload::Handle<EnvFilter, Layered<Vec<BoxedLayer>, Registry>>,
+    _guards: Vec<WorkerGuard>,
+}
+
+impl LogGuard {
+    /// Applies the levels of `config`.
+    pub fn reload(&self, config: &LogConfig) -> Result<(), LogError> {
+        let filter = config.filter(std::env::var("RUST_LOG").ok().as_deref())?;
+        self.filter
+            .reload(filter)
+            .map_err(|e| LogError::Init(e.to_string()))
+    }
+}
+
+fn layer<W>(writer: W, format: LogFormat, ansi: bool) -> BoxedLayer
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Installs the global logger of the bot called `name`.
+pub fn init(name: &str, config: &LogConfig) -> Result<LogGuard, LogError> {
+    let filter = config.filter(std::env::var("RUST_LOG").ok().as_deref())?;
+    let mut layers: Vec<BoxedLayer> = Vec::new();
+    let mut guards = Vec::new();
+    if config.stdout {
+        let (writer, guard) = tracing_appender::non_blocking(io::stdout());
+        layers.push(layer(
+            writer,
+            config.format,
+            config.format == LogFormat::Text,
+        ));
+        guards.push(guard);
+    }
+    if let Some(directory) = &config.directory {
+        fs::create_dir_all(directory).map_err(LogError::Io)?;
+        let prefix = config.file_prefix.as_deref().unwrap_or(name);
+        let (writer, guard) = match config.max_size_mb {
+            Some(max_size_mb) => tracing_appender::non_blocking(
+                SizeRollingFile::open(
+                    directory.join(format!("{}.log", prefix)),
+                    max_size_mb * MIB,
+                    config.max_files,
+                )
+                .map_err(LogError::Io)?,
+            ),
+            None => tracing_appender::non_blocking(
+                RollingFileAppender::builder()
+                    .rotation(match config.rotation {
+                        Rotation::Minutely => AppenderRotation::MINUTELY,
+                        Rotation::Hourly => AppenderRotation::HOURLY,
+                        Rotation::Daily => AppenderRotation::DAILY,
+                        Rotation::Never => AppenderRotation::NEVER,
+                    })
+                    .filename_prefix(prefix)
+                    .filename_suffix("log")
+                    .max_log_files(config.max_files + 1)
+                    .build(directory)
+                    .map_err(|e| LogError::Init(e.to_string()))?,
+            ),
+        };
+        layers.push(layer(writer, config.format, false));
+        guards.push(guard);
+    }
+    let (filter, handle) = reload::Layer::new(filter);
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()
+        .map_err(|e| LogError::Init(e.to_string()))?;
+    Ok(LogGuard {
+        filter: handle,
+        _guards: guards,
+    })
+}
+
+/// A log file that is rotated once it reaches a size. Rotated files are
+/// renamed to `<path>.1` (the newest) up to `<path>.<max_files>`.
+pub struct SizeRollingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingFile {
+    /// Opens `path` for appending.
+    pub fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files > 0 {
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A record larger than the limit still goes into a file of its own.
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
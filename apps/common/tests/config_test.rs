@@ -0,0 +1,120 @@
+use bot_common::config::{self, ConfigError, ConfigWatcher};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    nick: String,
+    admins: Vec<String>,
+    tor: bool,
+    proxy: Proxy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Proxy {
+    host: String,
+    port: u16,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nick: "bot".to_string(),
+            admins: Vec::new(),
+            tor: true,
+            proxy: Proxy::default(),
+        }
+    }
+}
+
+impl Default for Proxy {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 9050,
+        }
+    }
+}
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_file_and_env_overrides() {
+    let text = r#"
+        nick = "from-file"
+        tor = false
+
+        [proxy]
+        port = 9150
+    "#;
+    let config: Config = config::parse(text, "BOT", []).unwrap();
+    assert_eq!(config.nick, "from-file");
+    assert!(!config.tor);
+    assert_eq!(config.proxy.host, "127.0.0.1");
+    assert_eq!(config.proxy.port, 9150);
+
+    let config: Config = config::parse(
+        text,
+        "BOT",
+        vars(&[
+            ("BOT_NICK", "from-env"),
+            ("BOT_TOR", "true"),
+            ("BOT_PROXY__HOST", "10.0.0.1"),
+            ("BOT_ADMINS", r#"["aa", "bb"]"#),
+            ("OTHER_NICK", "ignored"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.nick, "from-env");
+    assert!(config.tor);
+    assert_eq!(config.proxy.host, "10.0.0.1");
+    assert_eq!(config.proxy.port, 9150);
+    assert_eq!(config.admins, vec!["aa", "bb"]);
+}
+
+#[test]
+fn test_invalid_config() {
+    assert!(matches!(
+        config::parse::<Config>("nickname = \"x\"", "BOT", []),
+        Err(ConfigError::Parse(_))
+    ));
+    assert!(matches!(
+        config::parse::<Config>("", "BOT", vars(&[("BOT_PROXY__PORT", "high")])),
+        Err(ConfigError::Parse(_))
+    ));
+    assert!(matches!(
+        config::parse::<Config>("", "BOT", vars(&[("BOT_NICK__FIRST", "x")])),
+        Err(ConfigError::Parse(_))
+    ));
+    assert!(matches!(
+        config::parse::<Config>("nick = \"x\"", "BOT", vars(&[("BOT_NICK__FIRST", "x")])),
+        Err(ConfigError::Env(var)) if var == "BOT_NICK__FIRST"
+    ));
+}
+
+#[test]
+fn test_write_default_and_watch() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bot.toml");
+    config::write_default(&path, &Config::default()).unwrap();
+    let loaded: Config = config::load(Some(&path), "BOT_TEST_UNSET").unwrap();
+    assert_eq!(loaded, Config::default());
+    // An existing config is never overwritten.
+    assert!(matches!(
+        config::write_default(&path, &Config::default()),
+        Err(ConfigError::Io(_))
+    ));
+
+    let mut watcher = ConfigWatcher::new(path.clone());
+    assert!(!watcher.changed());
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10))
+        .unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+}
@@ -0,0 +1,96 @@
+use bot_common::config;
+use bot_common::logging::{LogConfig, LogFormat, Rotation, SizeRollingFile};
+use serde::Deserialize;
+use std::io::Write;
+use tracing::level_filters::LevelFilter;
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    logging: LogConfig,
+}
+
+fn parse(text: &str) -> Result<LogConfig, config::ConfigError> {
+    config::parse::<ConfigFile>(text, "TEST", []).map(|file| file.logging)
+}
+
+#[test]
+fn test_parse_config() {
+    let config = parse(
+        r#"
+        [bot]
+        name = "ignored"
+
+        [logging]
+        level = "warn"
+        format = "json"
+        directory = "logs"
+        rotation = "hourly"
+        max_files = 3
+
+        [logging.modules]
+        merkle_tox_core = "debug"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.level, "warn");
+    assert_eq!(config.format, LogFormat::Json);
+    assert_eq!(config.directory.as_deref(), Some("logs".as_ref()));
+    assert_eq!(config.rotation, Rotation::Hourly);
+    assert_eq!(config.max_files, 3);
+    assert_eq!(config.modules["merkle_tox_core"], "debug");
+    assert!(config.stdout);
+
+    assert_eq!(parse("").unwrap(), LogConfig::default());
+    assert!(parse("[logging]\nlevle = \"debug\"").is_err());
+    assert!(parse("[logging]\nformat = \"xml\"").is_err());
+}
+
+#[test]
+fn test_filter_directives() {
+    let mut config = LogConfig::default();
+    config
+        .modules
+        .insert("merkle_tox_core".to_string(), "trace".to_string());
+    let filter = config.filter(None).unwrap();
+    assert_eq!(filter.max_level_hint(), Some(LevelFilter::TRACE));
+
+    config.modules.clear();
+    assert_eq!(
+        config.filter(None).unwrap().max_level_hint(),
+        Some(LevelFilter::INFO)
+    );
+    // RUST_LOG overrides the config.
+    assert_eq!(
+        config.filter(Some("debug")).unwrap().max_level_hint(),
+        Some(LevelFilter::DEBUG)
+    );
+
+    config
+        .modules
+        .insert("merkle_tox_core".to_string(), "loud".to_string());
+    assert!(config.filter(None).is_err());
+}
+
+#[test]
+fn test_size_rotation() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bot.log");
+    let mut file = SizeRollingFile::open(path.clone(), 10, 2).unwrap();
+    for line in ["aaaaaa\n", "bbbbbb\n", "cccccc\n", "dddddd\n"] {
+        file.write_all(line.as_bytes()).unwrap();
+    }
+    file.flush().unwrap();
+
+    let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+    assert_eq!(read("bot.log"), "dddddd\n");
+    assert_eq!(read("bot.log.1"), "cccccc\n");
+    assert_eq!(read("bot.log.2"), "bbbbbb\n");
+    // Only `max_files` rotated files are kept.
+    assert!(!dir.path().join("bot.log.3").exists());
+
+    // Reopening continues the current file.
+    let mut file = SizeRollingFile::open(path, 10, 2).unwrap();
+    file.write_all(b"e\n").unwrap();
+    assert_eq!(read("bot.log"), "dddddd\ne\n");
+}
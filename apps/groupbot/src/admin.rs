@@ -0,0 +1,102 @@
+//! Bot roles. Identities are 32-byte keys: a Tox public key for legacy
+//! senders, a logical identity key for Merkle-Tox senders.
+
+use crate::storage::PluginStorage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use toxcore::types::{PUBLIC_KEY_SIZE, PublicKey};
+
+/// Storage key of the role table.
+const ROLES_KEY: &str = "roles";
+
+/// What a sender may do, in increasing order of trust.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    Member,
+    Moderator,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Role::Member),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role {:?}", s)),
+        }
+    }
+}
+
+/// Parses a public key given in hex, either bare or as a Tox address (the
+/// key followed by nospam and checksum).
+pub fn parse_public_key(s: &str) -> Option<PublicKey> {
+    let bytes = hex::decode(s).ok()?;
+    if bytes.len() != PUBLIC_KEY_SIZE && bytes.len() != PUBLIC_KEY_SIZE + 6 {
+        return None;
+    }
+    let mut pk = [0u8; PUBLIC_KEY_SIZE];
+    pk.copy_from_slice(&bytes[..PUBLIC_KEY_SIZE]);
+    Some(PublicKey(pk))
+}
+
+/// Roles granted above `Role::Member`, persisted in the bot's storage.
+pub struct Roles {
+    storage: PluginStorage,
+}
+
+impl Roles {
+    pub fn new(storage: PluginStorage) -> Self {
+        Self { storage }
+    }
+
+    fn table(&self) -> BTreeMap<String, Role> {
+        self.storage.get(ROLES_KEY).unwrap_or_default()
+    }
+
+    /// Whether no roles were granted yet.
+    pub fn is_empty(&self) -> bool {
+        self.table().is_empty()
+    }
+
+    pub fn get(&self, pk: &PublicKey) -> Role {
+        self.table()
+            .get(&hex::encode(pk.0))
+            .copied()
+            .unwrap_or(Role::Member)
+    }
+
+    /// Grants `role` to `pk`; `Role::Member` revokes what it had.
+    pub fn set(&self, pk: &PublicKey, role: Role) -> Result<(), Box<dyn Error>> {
+        let mut table = self.table();
+        if role == Role::Member {
+            table.remove(&hex::encode(pk.0));
+        } else {
+            table.insert(hex::encode(pk.0), role);
+        }
+        self.storage.put(ROLES_KEY, &table)?;
+        self.storage.flush()
+    }
+
+    /// Granted roles, highest first.
+    pub fn list(&self) -> Vec<(String, Role)> {
+        let mut roles: Vec<_> = self.table().into_iter().collect();
+        roles.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        roles
+    }
+}
@@ -0,0 +1,154 @@
+//! Config file of the bot and the command line flags that override it.
+//!
+//! `admins`, `bridges` and the log levels are applied again when the file
+//! changes; everything else takes a restart. Admins removed from the file
+//! keep their role until it is revoked with `!role`.
+
+use crate::relay::BridgeLink;
+use bot_common::config::{BootstrapNode, ProxyConfig, ProxyType};
+use bot_common::logging::LogConfig;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Prefix of the environment variables overriding the config.
+pub const ENV_PREFIX: &str = "GROUPBOT";
+
+#[derive(Parser, Debug, Clone)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// Config file, see `--write-default-config`.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Writes the default config to this path and exits.
+    #[arg(long, value_name = "PATH")]
+    pub write_default_config: Option<PathBuf>,
+    #[arg(short, long)]
+    pub savefile: Option<PathBuf>,
+    #[arg(long)]
+    pub password: Option<String>,
+    #[arg(long)]
+    pub nick: Option<String>,
+    #[arg(long)]
+    pub status_message: Option<String>,
+    #[arg(long)]
+    pub nodes_url: Option<String>,
+    #[arg(long)]
+    pub github_path: Option<PathBuf>,
+    /// Connects through a local Tor daemon. Overrides `[proxy]`.
+    #[arg(long, action = clap::ArgAction::Set)]
+    pub tor: Option<bool>,
+    /// Mirrors a legacy chat and a Merkle-Tox conversation, e.g.
+    /// `group:0=<conversation id hex>`. May be repeated.
+    #[arg(long = "bridge", value_name = "LEGACY=CONVERSATION", value_parser = parse_bridge)]
+    pub bridges: Vec<String>,
+    /// Grants the admin role to a Tox public key or Merkle-Tox identity (hex).
+    /// May be repeated.
+    #[arg(long = "admin", value_name = "PUBLIC_KEY")]
+    pub admins: Vec<String>,
+    /// Serves Prometheus metrics at `/metrics` on this address, e.g.
+    /// `127.0.0.1:9464`.
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Tox profile. Defaults to `apps/groupbot/groupbot.tox` when run with
+    /// `bazel run`.
+    pub savefile: Option<PathBuf>,
+    /// Merkle-Tox store and plugin data. Defaults to `merkle_tox_groupbot`
+    /// next to the savefile.
+    pub store_dir: Option<PathBuf>,
+    pub nick: String,
+    pub status_message: String,
+    /// Node list to bootstrap from if `bootstrap_nodes` is empty.
+    pub nodes_url: String,
+    pub bootstrap_nodes: Vec<BootstrapNode>,
+    /// Tox public keys or Merkle-Tox identities (hex) granted the admin role.
+    pub admins: Vec<String>,
+    /// `LEGACY=CONVERSATION` links, as for `--bridge`.
+    pub bridges: Vec<String>,
+    pub proxy: ProxyConfig,
+    pub metrics_addr: Option<SocketAddr>,
+    pub plugins: PluginsConfig,
+    pub logging: LogConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// Checkout of the GitHub backup answering issue lookups.
+    pub github_path: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            savefile: None,
+            store_dir: None,
+            nick: "ToxGroupBot".to_string(),
+            status_message: "Tox Group Bot".to_string(),
+            nodes_url: "https://nodes.tox.chat/json".to_string(),
+            bootstrap_nodes: Vec::new(),
+            admins: Vec::new(),
+            bridges: Vec::new(),
+            proxy: ProxyConfig::tor(),
+            metrics_addr: None,
+            plugins: PluginsConfig::default(),
+            logging: LogConfig::default(),
+        }
+    }
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self {
+            github_path: PathBuf::from("tools/toktok-backup"),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file named by `args`, with the environment and
+    /// then `args` applied on top.
+    pub fn load(args: &Args) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config: Self = bot_common::config::load(args.config.as_deref(), ENV_PREFIX)?;
+        if let Some(savefile) = &args.savefile {
+            config.savefile = Some(savefile.clone());
+        }
+        if let Some(nick) = &args.nick {
+            config.nick = nick.clone();
+        }
+        if let Some(status_message) = &args.status_message {
+            config.status_message = status_message.clone();
+        }
+        if let Some(nodes_url) = &args.nodes_url {
+            config.nodes_url = nodes_url.clone();
+        }
+        if let Some(github_path) = &args.github_path {
+            config.plugins.github_path = github_path.clone();
+        }
+        match args.tor {
+            Some(true) => config.proxy.kind = ProxyType::Socks5,
+            Some(false) => config.proxy.kind = ProxyType::None,
+            None => {}
+        }
+        if args.metrics_addr.is_some() {
+            config.metrics_addr = args.metrics_addr;
+        }
+        config.admins.extend(args.admins.iter().cloned());
+        config.bridges.extend(args.bridges.iter().cloned());
+        Ok(config)
+    }
+
+    pub fn bridge_links(&self) -> Result<Vec<BridgeLink>, String> {
+        self.bridges.iter().map(|link| link.parse()).collect()
+    }
+}
+
+fn parse_bridge(s: &str) -> Result<String, String> {
+    s.parse::<BridgeLink>().map(|_| s.to_string())
+}
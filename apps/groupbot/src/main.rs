@@ -1,55 +1,44 @@
+use bot_common::config::{ConfigWatcher, ProxyType};
+use bot_common::logging::{self, LogGuard};
 use clap::Parser;
-use merkle_tox_client::MerkleToxClient;
-use merkle_tox_core::dag::{ConversationId, LogicalIdentityPk, PhysicalDeviceSk};
+use merkle_tox_client::MerkleToxClientManager;
+use merkle_tox_core::dag::{Content, ConversationId, LogicalIdentityPk, PhysicalDeviceSk};
 use merkle_tox_core::node::MerkleToxNode;
-use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport};
+use merkle_tox_core::{NodeEvent, Transport};
 use merkle_tox_fs::FsStore;
+use merkle_tox_tox::metrics::{self, Metrics};
 use merkle_tox_tox::{ToxMerkleBridge, ToxTransport};
 use parking_lot::ReentrantMutex;
 use serde::Deserialize;
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
 use tracing::{debug, error, info};
 
 use toxcore::tox::events::Event;
-use toxcore::tox::{
-    ConferenceNumber, GroupNumber, Options, Tox, ToxProxyType, ToxSavedataType, encryptsave,
-};
+use toxcore::tox::{Chat, ConferenceNumber, GroupNumber, Options, Profile, Tox, ToxProxyType};
 use toxcore::types::{
     ConferencePeerNumber, DhtId, FriendNumber, GroupPeerNumber, MessageType, PUBLIC_KEY_SIZE,
     PublicKey, ToxConferenceType, ToxConnection,
 };
 
+mod admin;
+mod config;
 mod plugin;
 mod plugins;
+mod relay;
+mod storage;
 
-use plugin::{CommandContext, CommandSource, Plugin};
-use plugins::{Echo, Forwarder, GitHub};
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(short, long)]
-    savefile: Option<String>,
-    #[arg(long)]
-    password: Option<String>,
-    #[arg(long, default_value = "ToxGroupBot")]
-    nick: String,
-    #[arg(long, default_value = "Tox Group Bot")]
-    status_message: String,
-    #[arg(long, default_value = "https://nodes.tox.chat/json")]
-    nodes_url: String,
-    #[arg(long, default_value = "tools/toktok-backup")]
-    github_path: String,
-    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
-    tor: bool,
-}
+use admin::{Role, Roles};
+use config::{Args, Config};
+use plugin::{CommandContext, CommandSource, OutgoingMessage, Plugin, PluginContext};
+use plugins::{Echo, Forwarder, GitHub, Remind};
+use relay::{LegacyChat, Relay};
+use storage::PluginStorage;
 
 enum BotEvent {
     SelfConnectionStatus(ToxConnection),
@@ -57,92 +46,78 @@ enum BotEvent {
     FriendConnectionStatus(FriendNumber, ToxConnection),
     FriendMessage(FriendNumber, MessageType, String),
     GroupMessage(GroupNumber, GroupPeerNumber, MessageType, String),
+    GroupPeerName(GroupNumber, GroupPeerNumber, String),
     ConferenceMessage(ConferenceNumber, ConferencePeerNumber, MessageType, String),
+    ConferencePeerName(ConferenceNumber, ConferencePeerNumber, String),
     ConferenceInvite(FriendNumber, ToxConferenceType, Vec<u8>),
     GroupInvite(FriendNumber, Vec<u8>),
     MerkleToxMessage(ConversationId, LogicalIdentityPk, String),
+    MerkleToxRedaction(ConversationId, LogicalIdentityPk),
 }
 
-type BotClient = MerkleToxClient<ToxTransport, FsStore>;
-type ClientMap = HashMap<ConversationId, Arc<BotClient>>;
+type ClientManager = MerkleToxClientManager<ToxTransport, FsStore>;
+
+/// Time the node gets to flush its state on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
 
 struct GroupBot {
     tox: Arc<ReentrantMutex<Tox>>,
     bridge: Arc<Mutex<ToxMerkleBridge<FsStore>>>,
-    clients: Arc<Mutex<ClientMap>>,
-    plugins: Vec<Box<dyn Plugin>>,
-    savefile: Option<PathBuf>,
-    #[allow(dead_code)]
-    password: Option<String>,
+    clients: Arc<ClientManager>,
+    plugins: Vec<LoadedPlugin>,
+    outgoing_rx: tokio::sync::mpsc::UnboundedReceiver<OutgoingMessage>,
+    relay: Relay,
+    roles: Roles,
+    self_pk: LogicalIdentityPk,
+    profile: Option<Profile>,
+    metrics: Arc<Metrics>,
+    args: Args,
+    config_watcher: Option<ConfigWatcher>,
+    log_guard: LogGuard,
 }
 
-struct Dispatcher {
-    node: Arc<Mutex<MerkleToxNode<ToxTransport, FsStore>>>,
-    clients: Arc<Mutex<ClientMap>>,
-    bot_event_tx: tokio::sync::mpsc::UnboundedSender<BotEvent>,
+struct LoadedPlugin {
+    plugin: Box<dyn Plugin>,
+    ctx: PluginContext,
+    next_tick: Option<Instant>,
 }
 
-impl NodeEventHandler for Dispatcher {
-    fn handle_event(&self, event: NodeEvent) {
-        let node = self.node.clone();
-        let clients = self.clients.clone();
-        let tx = self.bot_event_tx.clone();
-
-        tokio::spawn(async move {
-            match event {
-                NodeEvent::NodeVerified {
-                    conversation_id,
-                    node: merkle_node,
-                    hash,
-                } => {
-                    let mut clients_lock = clients.lock().await;
-                    let client = if let Some(c) = clients_lock.get(&conversation_id) {
-                        c.clone()
-                    } else {
-                        info!("Discovered conversation: {:?}", conversation_id);
-                        let c = Arc::new(MerkleToxClient::new(node, conversation_id));
-                        if let Err(e) = c.refresh_state().await {
-                            error!("Failed to refresh state for {:?}: {}", conversation_id, e);
-                        }
-                        clients_lock.insert(conversation_id, c.clone());
-                        c
-                    };
-
-                    if let merkle_tox_core::dag::Content::Text(text) = &merkle_node.content
-                        && let Err(e) = tx.send(BotEvent::MerkleToxMessage(
-                            conversation_id,
-                            merkle_node.author_pk,
-                            text.clone(),
-                        ))
-                    {
-                        error!("Failed to send MerkleToxMessage to channel: {}", e);
-                    }
-
-                    if let Err(e) = client
-                        .handle_event(NodeEvent::NodeVerified {
-                            conversation_id,
-                            hash,
-                            node: merkle_node,
-                        })
-                        .await
-                    {
-                        error!("Error handling node event: {}", e);
-                    }
-                }
-                NodeEvent::PeerHandshakeComplete { peer_pk } => {
-                    let clients_lock = clients.lock().await;
-                    for client in clients_lock.values() {
-                        if let Err(e) = client
-                            .handle_event(NodeEvent::PeerHandshakeComplete { peer_pk })
-                            .await
-                        {
-                            error!("Error handling PeerHandshakeComplete: {}", e);
-                        }
-                    }
-                }
-                _ => {}
+/// Forwards the Merkle-Tox messages the bot reacts to, once the clients
+/// have applied them.
+async fn forward_bot_events(
+    mut rx: broadcast::Receiver<NodeEvent>,
+    tx: tokio::sync::mpsc::UnboundedSender<BotEvent>,
+) {
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                error!("Dropped {} Merkle-Tox event(s)", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let NodeEvent::NodeVerified {
+            conversation_id,
+            node,
+            ..
+        } = event
+        else {
+            continue;
+        };
+        let bot_event = match node.content {
+            Content::Text(text) => {
+                BotEvent::MerkleToxMessage(conversation_id, node.author_pk, text)
             }
-        });
+            Content::Redaction { .. } => {
+                BotEvent::MerkleToxRedaction(conversation_id, node.author_pk)
+            }
+            _ => continue,
+        };
+        if let Err(e) = tx.send(bot_event) {
+            error!("Failed to send Merkle-Tox event to channel: {}", e);
+            break;
+        }
     }
 }
 
@@ -151,14 +126,17 @@ use merkle_tox_core::vfs::StdFileSystem;
 impl GroupBot {
     async fn new(
         tox: Tox,
-        args: &Args,
-        savefile: Option<PathBuf>,
+        args: Args,
+        config: &Config,
+        log_guard: LogGuard,
+        profile: Option<Profile>,
         bot_event_tx: tokio::sync::mpsc::UnboundedSender<BotEvent>,
-    ) -> Self {
+    ) -> Result<Self, Box<dyn Error>> {
         let plugins: Vec<Box<dyn Plugin>> = vec![
             Box::new(Forwarder),
             Box::new(Echo),
-            Box::new(GitHub::new(PathBuf::from(&args.github_path))),
+            Box::new(GitHub::new(config.plugins.github_path.clone())),
+            Box::new(Remind),
         ];
 
         let self_sk = tox.secret_key();
@@ -175,16 +153,36 @@ impl GroupBot {
             Arc::new(merkle_tox_core::clock::SystemTimeProvider),
         );
 
-        let store_path = if let Some(ref path) = savefile {
-            path.parent()
+        let store_path = match (&config.store_dir, &profile) {
+            (Some(store_dir), _) => store_dir.clone(),
+            (None, Some(profile)) => profile
+                .path()
+                .parent()
                 .unwrap_or_else(|| Path::new(""))
-                .join("merkle_tox_groupbot")
-        } else {
-            PathBuf::from("merkle_tox_groupbot")
+                .join("merkle_tox_groupbot"),
+            (None, None) => PathBuf::from("merkle_tox_groupbot"),
         };
         if let Err(e) = fs::create_dir_all(&store_path) {
             error!("Failed to create directory {}: {}", store_path.display(), e);
         }
+
+        let (outgoing_tx, outgoing_rx) = tokio::sync::mpsc::unbounded_channel();
+        let now = Instant::now();
+        let plugins = plugins
+            .into_iter()
+            .map(|plugin| {
+                let storage = PluginStorage::open(store_path.join("plugins"), plugin.name());
+                LoadedPlugin {
+                    ctx: PluginContext::new(tox_shared.clone(), outgoing_tx.clone(), storage),
+                    next_tick: plugin.tick_interval().map(|interval| now + interval),
+                    plugin,
+                }
+            })
+            .collect();
+
+        let roles = Roles::new(PluginStorage::open(store_path.clone(), "roles"));
+        grant_admins(&roles, &config.admins);
+
         let store =
             FsStore::new(store_path, Arc::new(StdFileSystem)).expect("Failed to create FsStore");
         let node = MerkleToxNode::new(
@@ -195,45 +193,135 @@ impl GroupBot {
         );
         let node_arc = Arc::new(Mutex::new(node));
         let bridge = Arc::new(Mutex::new(ToxMerkleBridge::with_node(node_arc.clone())));
-        let clients = Arc::new(Mutex::new(ClientMap::new()));
-
-        let dispatcher = Dispatcher {
-            node: node_arc.clone(),
-            clients: clients.clone(),
-            bot_event_tx,
-        };
+        let clients = ClientManager::new(node_arc.clone());
+        tokio::spawn(forward_bot_events(clients.subscribe(), bot_event_tx));
 
+        let metrics = Metrics::new();
         {
             let mut node_lock = node_arc.lock().await;
-            node_lock.set_event_handler(Arc::new(dispatcher));
+            node_lock.set_event_handler(metrics.observe(clients.event_handler()));
         }
 
-        Self {
+        let config_watcher = args.config.clone().map(ConfigWatcher::new);
+        Ok(Self {
             tox: tox_shared,
             bridge,
             clients,
             plugins,
-            savefile,
-            password: args.password.clone(),
+            outgoing_rx,
+            relay: Relay::new(config.bridge_links()?),
+            roles,
+            self_pk: self_pk.to_logical(),
+            profile,
+            metrics,
+            args,
+            config_watcher,
+            log_guard,
+        })
+    }
+
+    /// Applies the reloadable parts of the config file if it changed.
+    fn reload_config(&mut self) {
+        let Some(watcher) = &mut self.config_watcher else {
+            return;
+        };
+        if !watcher.changed() {
+            return;
+        }
+        info!("Reloading {}", watcher.path().display());
+        let config = match Config::load(&self.args) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Keeping the previous config: {}", e);
+                return;
+            }
+        };
+        match config.bridge_links() {
+            Ok(links) => self.relay.set_links(links),
+            Err(e) => error!("Keeping the previous bridges: {}", e),
+        }
+        grant_admins(&self.roles, &config.admins);
+        if let Err(e) = self.log_guard.reload(&config.logging) {
+            error!("Keeping the previous log levels: {}", e);
         }
     }
 
     fn save(&self) -> Result<(), Box<dyn Error>> {
-        if let Some(path) = &self.savefile {
-            let mut data = self.tox.lock().savedata();
-            if let Some(password) = &self.password {
-                data = encryptsave::encrypt(&data, password.as_bytes())?;
-            }
-            fs::write(path, data)?;
+        if let Some(profile) = &self.profile {
+            profile.save(&self.tox.lock())?;
         }
         Ok(())
     }
 
-    fn is_admin(&self, pk: &PublicKey) -> bool {
+    /// Role of `pk`. Until a role is granted, friend 0 is the admin.
+    fn role_of(&self, pk: &PublicKey) -> Role {
+        if !self.roles.is_empty() {
+            return self.roles.get(pk);
+        }
         match self.tox.lock().friend(FriendNumber(0)).public_key() {
-            Ok(admin_pk) => admin_pk == *pk,
-            Err(_) => false,
+            Ok(admin_pk) if admin_pk == *pk => Role::Admin,
+            _ => Role::Member,
+        }
+    }
+
+    /// `!admin list`, `!admin add <public key> [role]`, `!admin remove <public key>`.
+    fn admin_command(&self, role: Role, args: &[String]) -> String {
+        let usage =
+            "Usage: !admin list | add <public key> [member|moderator|admin] | remove <public key>";
+        match args.first().map(String::as_str) {
+            Some("list") => {
+                let roles = self.roles.list();
+                if roles.is_empty() {
+                    return "No roles granted; friend 0 is the admin.".to_string();
+                }
+                roles
+                    .iter()
+                    .map(|(pk, role)| format!("{} {}", role, pk))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            Some(action @ ("add" | "remove")) => {
+                if role < Role::Admin {
+                    return "You must be an admin to use this command.".to_string();
+                }
+                let Some(pk) = args.get(1).and_then(|s| admin::parse_public_key(s)) else {
+                    return usage.to_string();
+                };
+                let new_role = match (action, args.get(2)) {
+                    ("remove", _) => Role::Member,
+                    (_, None) => Role::Admin,
+                    (_, Some(name)) => match name.parse() {
+                        Ok(role) => role,
+                        Err(e) => return e,
+                    },
+                };
+                match self.roles.set(&pk, new_role) {
+                    Ok(()) => format!("{} is now {}.", hex::encode(pk.0), new_role),
+                    Err(e) => format!("Error saving roles: {}", e),
+                }
+            }
+            _ => usage.to_string(),
+        }
+    }
+
+    /// `!health`: the problems found by the node's self-diagnostics.
+    async fn health_report(&self) -> String {
+        let node = self.bridge.lock().await.node.clone();
+        let report = node.lock().await.diagnostics();
+        let sessions = report.sessions.iter().filter(|s| s.online).count();
+        let mut lines = vec![format!(
+            "{} conversation(s), {} online session(s), {:.1} MiB stored, clock offset {} ms.",
+            report.conversations.len(),
+            sessions,
+            report.disk_usage_bytes as f64 / (1024.0 * 1024.0),
+            report.clock.consensus_offset_ms
+        )];
+        let problems = report.problems();
+        if problems.is_empty() {
+            lines.push("No problems found.".to_string());
         }
+        lines.extend(problems.iter().map(|p| format!("- {}", p)));
+        lines.join("\n")
     }
 
     async fn handle_command(&mut self, context: &CommandContext, message: &str) -> Option<String> {
@@ -251,11 +339,12 @@ impl GroupBot {
 
         let cmd = &parts[0];
         let args = &parts[1..];
+        let role = self.role_of(&context.sender_pk);
 
         // Built-in commands
         match cmd.as_str() {
             "save" => {
-                if !self.is_admin(&context.sender_pk) {
+                if role < Role::Admin {
                     return Some("You must be an admin to use this command.".to_string());
                 }
                 if let Err(e) = self.save() {
@@ -264,7 +353,7 @@ impl GroupBot {
                 return Some("State saved.".to_string());
             }
             "nick" => {
-                if !self.is_admin(&context.sender_pk) {
+                if role < Role::Admin {
                     return Some("You must be an admin to use this command.".to_string());
                 }
                 if args.is_empty() {
@@ -276,33 +365,42 @@ impl GroupBot {
                     if let Err(e) = tox.set_name(new_nick.as_bytes()) {
                         error!("Failed to set nick: {}", e);
                     }
-                    if let Err(e) = tox.group(GroupNumber(0)).self_set_name(new_nick.as_bytes()) {
-                        error!("Failed to set group nick: {}", e);
+                    for chat in tox.chats() {
+                        if let Chat::Group(group) = chat
+                            && let Err(e) = group.self_set_name(new_nick.as_bytes())
+                        {
+                            error!("Failed to set group nick: {}", e);
+                        }
                     }
                 }
                 return Some(format!("Nickname set to {}", new_nick));
             }
+            "admin" => return Some(self.admin_command(role, args)),
+            "health" => return Some(self.health_report().await),
             "leave" => {
-                if !self.is_admin(&context.sender_pk) {
+                if role < Role::Admin {
                     return Some("You must be an admin to use this command.".to_string());
                 }
                 {
                     let tox = self.tox.lock();
-                    if let Err(e) = tox.conference(ConferenceNumber(0)).delete() {
-                        error!("Failed to delete conference: {}", e);
-                    }
-                    if let Err(e) = tox.group(GroupNumber(0)).leave(Some(b"Goodbye!")) {
-                        error!("Failed to leave group: {}", e);
+                    for chat in tox.chats() {
+                        if let Err(e) = chat.leave(Some(b"Goodbye!")) {
+                            error!("Failed to leave {:?}: {}", chat.number(), e);
+                        }
                     }
                 }
-                return Some("Left conference and group 0.".to_string());
+                return Some("Left all conferences and groups.".to_string());
             }
             _ => {}
         }
 
-        for plugin in &mut self.plugins {
+        for LoadedPlugin { plugin, ctx, .. } in &mut self.plugins {
             if plugin.name() == cmd || (cmd == "gh" && plugin.name() == "gh") {
-                match plugin.on_command(&self.tox.lock(), context, args) {
+                let required = plugin.required_role(args);
+                if role < required {
+                    return Some(format!("This command requires the {} role.", required));
+                }
+                match plugin.on_command(ctx, context, args).await {
                     Ok(Some(reply)) => return Some(reply),
                     Ok(None) => {}
                     Err(e) => return Some(format!("Error in plugin {}: {}", plugin.name(), e)),
@@ -346,8 +444,7 @@ impl GroupBot {
                 }
             }
             CommandSource::MerkleTox(conversation_id) => {
-                let clients = self.clients.lock().await;
-                if let Some(client) = clients.get(conversation_id)
+                if let Some(client) = self.clients.get(conversation_id).await
                     && let Err(e) = client.send_message(text.to_string()).await
                 {
                     error!("Failed to send MerkleTox message: {}", e);
@@ -356,6 +453,79 @@ impl GroupBot {
         }
     }
 
+    /// Mirrors a legacy message (or, with `message` `None`, just the sender's
+    /// current name) into the bridged conversation, if any.
+    async fn relay_from_legacy(
+        &mut self,
+        chat: LegacyChat,
+        peer: u32,
+        name: &str,
+        message: Option<&str>,
+    ) {
+        let Some(conversation_id) = self.relay.conversation_for(chat) else {
+            return;
+        };
+        let source = CommandSource::MerkleTox(conversation_id);
+        let normal = MessageType::TOX_MESSAGE_TYPE_NORMAL;
+        if let Some(notice) = self.relay.rename(chat, peer, name) {
+            self.send_reply(&source, normal, &notice).await;
+        }
+        if let Some(message) = message {
+            self.send_reply(&source, normal, &relay::relayed(name, message))
+                .await;
+        }
+    }
+
+    /// Mirrors `text` into the legacy chats bridged to `conversation_id`.
+    async fn relay_to_legacy(&self, conversation_id: &ConversationId, text: &str) {
+        for chat in self.relay.legacy_for(conversation_id) {
+            self.send_reply(&chat.into(), MessageType::TOX_MESSAGE_TYPE_NORMAL, text)
+                .await;
+        }
+    }
+
+    /// Runs the plugin jobs that are due.
+    async fn run_ticks(&mut self, now: Instant) {
+        for LoadedPlugin {
+            plugin,
+            ctx,
+            next_tick,
+        } in &mut self.plugins
+        {
+            let Some(due) = *next_tick else {
+                continue;
+            };
+            if now < due {
+                continue;
+            }
+            if let Err(e) = plugin.tick(ctx).await {
+                error!("Plugin {} tick error: {}", plugin.name(), e);
+            }
+            *next_tick = plugin.tick_interval().map(|interval| now + interval);
+        }
+    }
+
+    /// Writes plugin state changed since the last flush.
+    fn flush_plugin_storage(&self) {
+        for loaded in &self.plugins {
+            if let Err(e) = loaded.ctx.storage().flush() {
+                error!(
+                    "Failed to save state of plugin {}: {}",
+                    loaded.plugin.name(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// Sends the messages plugins queued since the last iteration.
+    async fn send_outgoing(&mut self) {
+        while let Ok(msg) = self.outgoing_rx.try_recv() {
+            self.send_reply(&msg.destination, msg.message_type, &msg.text)
+                .await;
+        }
+    }
+
     async fn run(
         &mut self,
         shutdown: Arc<AtomicBool>,
@@ -363,22 +533,15 @@ impl GroupBot {
     ) -> Result<(), Box<dyn Error>> {
         {
             let tox = self.tox.lock();
-            let conferences = tox.conference_chatlist();
-            if !conferences
-                .iter()
-                .any(|c| c.number() == ConferenceNumber(0))
-            {
-                error!("Conference 0 not found in chatlist! Invitation logic will fail.");
-            } else {
-                info!("Conference 0 found.");
-            }
-            if tox.group_count() == 0 {
-                error!("No groups found! Group invitation logic will fail.");
+            let chats = tox.chats();
+            if chats.is_empty() {
+                error!("No conferences or groups found! Invitation logic will fail.");
             } else {
-                info!("Group 0 found (assuming first group is 0).");
+                info!("Friends will be invited to {} chat(s).", chats.len());
             }
         }
         let mut last_save = Instant::now();
+        let mut last_config_check = Instant::now();
         let mut loop_count = 0;
         let mut last_loop_report = Instant::now();
 
@@ -402,9 +565,11 @@ impl GroupBot {
 
             if let Ok(events) = self.tox.lock().events() {
                 for event in &events {
+                    self.metrics.record_tox_event(&event);
+
                     // Dispatch to plugins
-                    for plugin in &mut self.plugins {
-                        if let Err(e) = plugin.on_event(&self.tox.lock(), &event) {
+                    for LoadedPlugin { plugin, ctx, .. } in &mut self.plugins {
+                        if let Err(e) = plugin.on_event(ctx, &event).await {
                             error!("Plugin {} error: {}", plugin.name(), e);
                         }
                     }
@@ -449,6 +614,20 @@ impl GroupBot {
                                 String::from_utf8_lossy(e.message()).into_owned(),
                             ));
                         }
+                        Event::GroupPeerName(e) => {
+                            bot_events.push(BotEvent::GroupPeerName(
+                                e.group_number(),
+                                e.peer_id(),
+                                String::from_utf8_lossy(e.name()).into_owned(),
+                            ));
+                        }
+                        Event::ConferencePeerName(e) => {
+                            bot_events.push(BotEvent::ConferencePeerName(
+                                e.conference_number(),
+                                e.peer_number(),
+                                String::from_utf8_lossy(e.name()).into_owned(),
+                            ));
+                        }
                         Event::ConferenceMessage(e) => {
                             bot_events.push(BotEvent::ConferenceMessage(
                                 e.conference_number(),
@@ -496,22 +675,17 @@ impl GroupBot {
                         if status != ToxConnection::TOX_CONNECTION_NONE {
                             let tox = self.tox.lock();
                             let friend = tox.friend(friend_number);
-                            debug!(
-                                "Inviting friend {} to conference 0 and group 0",
-                                friend_number.0
-                            );
-
-                            if let Err(e) = tox.conference(ConferenceNumber(0)).invite(&friend) {
-                                error!(
-                                    "Failed to invite friend {} to conference 0: {}",
-                                    friend_number.0, e
-                                );
-                            }
-                            if let Err(e) = tox.group(GroupNumber(0)).invite_friend(&friend) {
-                                error!(
-                                    "Failed to invite friend {} to group 0: {}",
-                                    friend_number.0, e
-                                );
+                            debug!("Inviting friend {} to our chats", friend_number.0);
+
+                            for chat in tox.chats() {
+                                if let Err(e) = chat.invite(&friend) {
+                                    error!(
+                                        "Failed to invite friend {} to {:?}: {}",
+                                        friend_number.0,
+                                        chat.number(),
+                                        e
+                                    );
+                                }
                             }
                         }
                     }
@@ -529,7 +703,22 @@ impl GroupBot {
                         }
                     }
                     BotEvent::GroupMessage(group_number, peer_id, message_type, message) => {
-                        if message.starts_with('!') {
+                        if !relay::is_command(&message) {
+                            let (ours, peer_name) = {
+                                let tox = self.tox.lock();
+                                let group = tox.group(group_number);
+                                (
+                                    group.self_peer_id().is_ok_and(|id| id == peer_id),
+                                    group.peer_name(peer_id).unwrap_or_default(),
+                                )
+                            };
+                            if !ours {
+                                let chat = LegacyChat::Group(group_number);
+                                let name = String::from_utf8_lossy(&peer_name).into_owned();
+                                self.relay_from_legacy(chat, peer_id.0, &name, Some(&message))
+                                    .await;
+                            }
+                        } else if message.starts_with('!') {
                             let (pk, peer_name) = {
                                 let tox = self.tox.lock();
                                 let group = tox.group(group_number);
@@ -566,7 +755,22 @@ impl GroupBot {
                         message_type,
                         message,
                     ) => {
-                        if message.starts_with('!') {
+                        if !relay::is_command(&message) {
+                            let (ours, peer_name) = {
+                                let tox = self.tox.lock();
+                                let conf = tox.conference(conference_number);
+                                (
+                                    conf.peer_number_is_ours(peer_number).unwrap_or(true),
+                                    conf.peer_name(peer_number).unwrap_or_default(),
+                                )
+                            };
+                            if !ours {
+                                let chat = LegacyChat::Conference(conference_number);
+                                let name = String::from_utf8_lossy(&peer_name).into_owned();
+                                self.relay_from_legacy(chat, peer_number.0, &name, Some(&message))
+                                    .await;
+                            }
+                        } else if message.starts_with('!') {
                             let (ours, pk, peer_name) = {
                                 let tox = self.tox.lock();
                                 let conf = tox.conference(conference_number);
@@ -623,8 +827,46 @@ impl GroupBot {
                             }
                         }
                     }
+                    BotEvent::GroupPeerName(group_number, peer_id, name) => {
+                        let ours = self
+                            .tox
+                            .lock()
+                            .group(group_number)
+                            .self_peer_id()
+                            .is_ok_and(|id| id == peer_id);
+                        if !ours {
+                            let chat = LegacyChat::Group(group_number);
+                            self.relay_from_legacy(chat, peer_id.0, &name, None).await;
+                        }
+                    }
+                    BotEvent::ConferencePeerName(conference_number, peer_number, name) => {
+                        let ours = self
+                            .tox
+                            .lock()
+                            .conference(conference_number)
+                            .peer_number_is_ours(peer_number)
+                            .unwrap_or(true);
+                        if !ours {
+                            let chat = LegacyChat::Conference(conference_number);
+                            self.relay_from_legacy(chat, peer_number.0, &name, None)
+                                .await;
+                        }
+                    }
+                    BotEvent::MerkleToxRedaction(conversation_id, sender_pk) => {
+                        if sender_pk != self.self_pk {
+                            let notice =
+                                format!("* {} deleted a message", relay::member_name(&sender_pk));
+                            self.relay_to_legacy(&conversation_id, &notice).await;
+                        }
+                    }
                     BotEvent::MerkleToxMessage(conversation_id, sender_pk, message) => {
-                        if message.starts_with('!') {
+                        if !relay::is_command(&message) {
+                            if sender_pk != self.self_pk {
+                                let text =
+                                    relay::relayed(&relay::member_name(&sender_pk), &message);
+                                self.relay_to_legacy(&conversation_id, &text).await;
+                            }
+                        } else if message.starts_with('!') {
                             let context = CommandContext {
                                 source: CommandSource::MerkleTox(conversation_id),
                                 sender_pk: PublicKey(*sender_pk.as_bytes()),
@@ -640,6 +882,15 @@ impl GroupBot {
                 }
             }
 
+            self.run_ticks(now).await;
+            self.send_outgoing().await;
+            self.flush_plugin_storage();
+
+            if now.duration_since(last_config_check) > Duration::from_secs(5) {
+                self.reload_config();
+                last_config_check = now;
+            }
+
             if now.duration_since(last_save) > Duration::from_secs(600) {
                 if let Err(e) = self.save() {
                     error!("Failed to save state during periodic save: {}", e);
@@ -651,7 +902,11 @@ impl GroupBot {
             let tox_interval = self.tox.lock().iteration_interval();
             let next_tox_wakeup = now + Duration::from_millis(tox_interval as u64);
 
-            let sleep_until = next_mt_wakeup.min(next_tox_wakeup);
+            let sleep_until = self
+                .plugins
+                .iter()
+                .filter_map(|p| p.next_tick)
+                .fold(next_mt_wakeup.min(next_tox_wakeup), Instant::min);
             let sleep_duration = sleep_until.saturating_duration_since(Instant::now());
 
             if !sleep_duration.is_zero() {
@@ -659,10 +914,27 @@ impl GroupBot {
             }
         }
 
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        let summary = self.bridge.lock().await.shutdown(deadline).await;
+        info!("Merkle-Tox node shut down: {:?}", summary);
+        self.flush_plugin_storage();
         self.save()?;
         Ok(())
     }
 }
+fn grant_admins(roles: &Roles, admins: &[String]) {
+    for admin in admins {
+        match admin::parse_public_key(admin) {
+            Some(pk) => {
+                if let Err(e) = roles.set(&pk, Role::Admin) {
+                    error!("Failed to grant admin role to {}: {}", admin, e);
+                }
+            }
+            None => error!("Invalid admin public key: {}", admin),
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 struct Node {
     ipv4: String,
@@ -707,6 +979,12 @@ fn bootstrap(tox: &Tox, nodes: &[Node]) {
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
+    if let Some(path) = &args.write_default_config {
+        bot_common::config::write_default(path, &Config::default())?;
+        return Ok(());
+    }
+    let config = Config::load(&args)?;
+    let log_guard = logging::init("groupbot", &config.logging)?;
 
     let mut opts = Options::new()?;
     opts.set_experimental_owned_data(true);
@@ -715,50 +993,52 @@ async fn main() -> Result<(), Box<dyn Error>> {
     opts.set_local_discovery_enabled(false);
     opts.set_udp_enabled(false);
 
-    if args.tor {
-        opts.set_proxy_type(ToxProxyType::TOX_PROXY_TYPE_SOCKS5);
-        opts.set_proxy_host("127.0.0.1")?;
-        opts.set_proxy_port(9050);
+    match config.proxy.kind {
+        ProxyType::None => {}
+        ProxyType::Socks5 => opts.set_proxy_type(ToxProxyType::TOX_PROXY_TYPE_SOCKS5),
+        ProxyType::Http => opts.set_proxy_type(ToxProxyType::TOX_PROXY_TYPE_HTTP),
+    }
+    if config.proxy.kind != ProxyType::None {
+        opts.set_proxy_host(&config.proxy.host)?;
+        opts.set_proxy_port(config.proxy.port);
     }
 
-    let savefile = args.savefile.clone().or_else(|| {
+    let savefile = config.savefile.clone().or_else(|| {
         std::env::var("BUILD_WORKSPACE_DIRECTORY")
             .ok()
-            .map(|base| format!("{}/rs-toxcore-c/apps/groupbot/groupbot.tox", base))
+            .map(|base| PathBuf::from(base).join("rs-toxcore-c/apps/groupbot/groupbot.tox"))
     });
 
-    if let Some(savefile) = &savefile
-        && Path::new(savefile).exists()
-    {
-        let mut data = fs::read(savefile)?;
-        if encryptsave::is_data_encrypted(&data) {
-            if let Some(password) = &args.password {
-                data = encryptsave::decrypt(&data, password.as_bytes())?;
-            } else {
-                return Err("Password required to decrypt save data".into());
-            }
-        }
-        opts.set_savedata_type(ToxSavedataType::TOX_SAVEDATA_TYPE_TOX_SAVE);
-        opts.set_savedata_data(&data)?;
+    let profile = savefile
+        .map(|path| Profile::new(path).with_password(args.password.as_deref().map(str::as_bytes)));
+    if let Some(profile) = &profile {
+        profile.apply(&mut opts)?;
     }
 
     let tox = Tox::new(opts)?;
     if tox.name().is_empty() {
-        tox.set_name(args.nick.as_bytes())?;
+        tox.set_name(config.nick.as_bytes())?;
     }
     if tox.status_message().is_empty() {
-        tox.set_status_message(args.status_message.as_bytes())?;
+        tox.set_status_message(config.status_message.as_bytes())?;
     }
 
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
     info!("Bot ID: {}", hex::encode(tox.address().0));
 
-    let nodes = fetch_nodes(&args.nodes_url).await.unwrap_or_default();
+    let nodes = if config.bootstrap_nodes.is_empty() {
+        fetch_nodes(&config.nodes_url).await.unwrap_or_default()
+    } else {
+        config
+            .bootstrap_nodes
+            .iter()
+            .map(|node| Node {
+                ipv4: node.host.clone(),
+                port: node.port,
+                public_key: node.public_key.clone(),
+                last_ping: 0,
+            })
+            .collect()
+    };
     bootstrap(&tox, &nodes);
 
     let shutdown = Arc::new(AtomicBool::new(false));
@@ -771,7 +1051,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
     });
 
     let (bot_event_tx, bot_event_rx) = tokio::sync::mpsc::unbounded_channel();
-    let mut bot = GroupBot::new(tox, &args, savefile.map(PathBuf::from), bot_event_tx).await;
+    let mut bot = GroupBot::new(tox, args, &config, log_guard, profile, bot_event_tx).await?;
+    if let Some(addr) = config.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        info!("Serving metrics on http://{}/metrics", addr);
+        let node = bot.bridge.lock().await.node.clone();
+        tokio::spawn(metrics::serve(listener, bot.metrics.clone(), node));
+    }
     bot.run(shutdown, bot_event_rx).await?;
 
     Ok(())
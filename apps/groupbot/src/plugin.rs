@@ -1,4 +1,13 @@
+use parking_lot::ReentrantMutex;
 use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::admin::Role;
+use crate::storage::PluginStorage;
 use toxcore::tox::Tox;
 use toxcore::tox::events::Event;
 use toxcore::types::{ConferenceNumber, FriendNumber, GroupNumber, MessageType, PublicKey};
@@ -22,21 +31,108 @@ pub struct CommandContext {
     pub message_type: MessageType,
 }
 
+pub type PluginResult<T> = Result<T, Box<dyn Error>>;
+
+/// Future returned by plugin handlers. Handlers run on the bot loop, one at a
+/// time; long-running work should be spawned and report back through
+/// `PluginContext::send`.
+pub type PluginFuture<'a, T> = Pin<Box<dyn Future<Output = PluginResult<T>> + 'a>>;
+
+/// A message a plugin wants sent outside of a command reply.
+#[derive(Clone, Debug)]
+pub struct OutgoingMessage {
+    pub destination: CommandSource,
+    pub message_type: MessageType,
+    pub text: String,
+}
+
+/// Handle given to a plugin. Cheap to clone and usable from spawned tasks.
+#[derive(Clone)]
+pub struct PluginContext {
+    tox: Arc<ReentrantMutex<Tox>>,
+    outgoing: UnboundedSender<OutgoingMessage>,
+    storage: PluginStorage,
+}
+
+impl PluginContext {
+    pub fn new(
+        tox: Arc<ReentrantMutex<Tox>>,
+        outgoing: UnboundedSender<OutgoingMessage>,
+        storage: PluginStorage,
+    ) -> Self {
+        Self {
+            tox,
+            outgoing,
+            storage,
+        }
+    }
+
+    /// The plugin's own key-value store. Survives restarts.
+    pub fn storage(&self) -> &PluginStorage {
+        &self.storage
+    }
+
+    /// Runs `f` with the Tox instance locked. Do not hold on to anything
+    /// borrowed from it across an `.await`.
+    pub fn with_tox<R>(&self, f: impl FnOnce(&Tox) -> R) -> R {
+        f(&self.tox.lock())
+    }
+
+    /// Sends `text` to `destination` from the bot loop.
+    pub fn send(&self, destination: CommandSource, text: impl Into<String>) {
+        self.send_typed(destination, MessageType::TOX_MESSAGE_TYPE_NORMAL, text);
+    }
+
+    pub fn send_typed(
+        &self,
+        destination: CommandSource,
+        message_type: MessageType,
+        text: impl Into<String>,
+    ) {
+        // Only fails when the bot is shutting down.
+        let _ = self.outgoing.send(OutgoingMessage {
+            destination,
+            message_type,
+            text: text.into(),
+        });
+    }
+}
+
 pub trait Plugin: Send + Sync {
     fn name(&self) -> &str;
 
     /// Handle any Tox event.
-    fn on_event(&mut self, _bot: &Tox, _event: &Event) -> Result<(), Box<dyn Error>> {
-        Ok(())
-    }
-
-    /// Handle commands starting with `!`.
-    fn on_command(
-        &mut self,
-        _bot: &Tox,
-        _context: &CommandContext,
-        _args: &[String],
-    ) -> Result<Option<String>, Box<dyn Error>> {
-        Ok(None)
+    fn on_event<'a>(
+        &'a mut self,
+        _ctx: &'a PluginContext,
+        _event: &'a Event<'_>,
+    ) -> PluginFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Lowest role allowed to run the command with `args`.
+    fn required_role(&self, _args: &[String]) -> Role {
+        Role::Member
+    }
+
+    /// Handle commands starting with `!`. The returned text is sent back to
+    /// the command's source.
+    fn on_command<'a>(
+        &'a mut self,
+        _ctx: &'a PluginContext,
+        _context: &'a CommandContext,
+        _args: &'a [String],
+    ) -> PluginFuture<'a, Option<String>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// How often `tick` runs, if at all.
+    fn tick_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Periodic job, run every `tick_interval`.
+    fn tick<'a>(&'a mut self, _ctx: &'a PluginContext) -> PluginFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
     }
 }
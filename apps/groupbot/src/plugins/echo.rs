@@ -1,6 +1,4 @@
-use crate::plugin::{CommandContext, Plugin};
-use std::error::Error;
-use toxcore::tox::Tox;
+use crate::plugin::{CommandContext, Plugin, PluginContext, PluginFuture};
 
 pub struct Echo;
 
@@ -9,12 +7,12 @@ impl Plugin for Echo {
         "echo"
     }
 
-    fn on_command(
-        &mut self,
-        _bot: &Tox,
-        _context: &CommandContext,
-        args: &[String],
-    ) -> Result<Option<String>, Box<dyn Error>> {
-        Ok(Some(format!("{:?}", args)))
+    fn on_command<'a>(
+        &'a mut self,
+        _ctx: &'a PluginContext,
+        _context: &'a CommandContext,
+        args: &'a [String],
+    ) -> PluginFuture<'a, Option<String>> {
+        Box::pin(async move { Ok(Some(format!("{:?}", args))) })
     }
 }
@@ -1,17 +1,12 @@
-use crate::plugin::Plugin;
-use std::error::Error;
+use crate::plugin::{Plugin, PluginContext, PluginFuture, PluginResult};
 use toxcore::tox::Tox;
 use toxcore::tox::events::Event;
 use toxcore::types::{ConferenceNumber, GroupNumber};
 
 pub struct Forwarder;
 
-impl Plugin for Forwarder {
-    fn name(&self) -> &str {
-        "forwarder"
-    }
-
-    fn on_event(&mut self, bot: &Tox, event: &Event) -> Result<(), Box<dyn Error>> {
+impl Forwarder {
+    fn forward(bot: &Tox, event: &Event) -> PluginResult<()> {
         match event {
             Event::GroupMessage(e) => {
                 let group_number = e.group_number();
@@ -63,3 +58,17 @@ impl Plugin for Forwarder {
         Ok(())
     }
 }
+
+impl Plugin for Forwarder {
+    fn name(&self) -> &str {
+        "forwarder"
+    }
+
+    fn on_event<'a>(
+        &'a mut self,
+        ctx: &'a PluginContext,
+        event: &'a Event<'_>,
+    ) -> PluginFuture<'a, ()> {
+        Box::pin(async move { ctx.with_tox(|bot| Self::forward(bot, event)) })
+    }
+}
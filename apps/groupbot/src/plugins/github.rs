@@ -1,9 +1,8 @@
-use crate::plugin::{CommandContext, Plugin};
+use crate::plugin::{CommandContext, Plugin, PluginContext, PluginFuture, PluginResult};
 use serde::Deserialize;
 use std::error::Error;
 use std::fs;
 use std::path::PathBuf;
-use toxcore::tox::Tox;
 
 #[derive(Deserialize, Debug, Clone)]
 struct User {
@@ -87,17 +86,8 @@ impl GitHub {
     }
 }
 
-impl Plugin for GitHub {
-    fn name(&self) -> &str {
-        "gh"
-    }
-
-    fn on_command(
-        &mut self,
-        _bot: &Tox,
-        _context: &CommandContext,
-        args: &[String],
-    ) -> Result<Option<String>, Box<dyn Error>> {
+impl GitHub {
+    fn lookup(&self, args: &[String]) -> PluginResult<Option<String>> {
         if args.len() != 1 {
             return Ok(None);
         }
@@ -139,3 +129,18 @@ impl Plugin for GitHub {
         }
     }
 }
+
+impl Plugin for GitHub {
+    fn name(&self) -> &str {
+        "gh"
+    }
+
+    fn on_command<'a>(
+        &'a mut self,
+        _ctx: &'a PluginContext,
+        _context: &'a CommandContext,
+        args: &'a [String],
+    ) -> PluginFuture<'a, Option<String>> {
+        Box::pin(async move { self.lookup(args) })
+    }
+}
@@ -1,7 +1,9 @@
 pub mod echo;
 pub mod forwarder;
 pub mod github;
+pub mod remind;
 
 pub use echo::Echo;
 pub use forwarder::Forwarder;
 pub use github::GitHub;
+pub use remind::Remind;
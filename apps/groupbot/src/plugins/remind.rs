@@ -0,0 +1,120 @@
+use crate::plugin::{CommandContext, CommandSource, Plugin, PluginContext, PluginFuture};
+use merkle_tox_core::dag::ConversationId;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use toxcore::types::{ConferenceNumber, FriendNumber, GroupNumber};
+
+/// Longest reminder accepted, in minutes (one week).
+const MAX_MINUTES: u64 = 7 * 24 * 60;
+/// Storage key of the pending reminders.
+const PENDING_KEY: &str = "pending";
+
+/// Where a reminder goes, in a form that can be stored.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum Destination {
+    Friend(u32),
+    Group(u32),
+    Conference(u32),
+    MerkleTox([u8; 32]),
+}
+
+impl From<&CommandSource> for Destination {
+    fn from(source: &CommandSource) -> Self {
+        match source {
+            CommandSource::Friend(n) => Self::Friend(n.0),
+            CommandSource::Group(n) => Self::Group(n.0),
+            CommandSource::Conference(n) => Self::Conference(n.0),
+            CommandSource::MerkleTox(id) => Self::MerkleTox(*id.as_bytes()),
+        }
+    }
+}
+
+impl From<&Destination> for CommandSource {
+    fn from(destination: &Destination) -> Self {
+        match destination {
+            Destination::Friend(n) => Self::Friend(FriendNumber(*n)),
+            Destination::Group(n) => Self::Group(GroupNumber(*n)),
+            Destination::Conference(n) => Self::Conference(ConferenceNumber(*n)),
+            Destination::MerkleTox(id) => Self::MerkleTox(ConversationId::from(*id)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Reminder {
+    /// Unix time in seconds.
+    due: u64,
+    destination: Destination,
+    text: String,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// `!remind <minutes> <text>`: repeats `text` where it was asked after the
+/// given number of minutes. Pending reminders are kept in the plugin's
+/// storage and survive restarts.
+pub struct Remind;
+
+impl Plugin for Remind {
+    fn name(&self) -> &str {
+        "remind"
+    }
+
+    fn on_command<'a>(
+        &'a mut self,
+        ctx: &'a PluginContext,
+        context: &'a CommandContext,
+        args: &'a [String],
+    ) -> PluginFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let usage = || Some("Usage: !remind <minutes> <text>".to_string());
+            let Some((minutes, text)) = args.split_first() else {
+                return Ok(usage());
+            };
+            let Ok(minutes) = minutes.parse::<u64>() else {
+                return Ok(usage());
+            };
+            if text.is_empty() || minutes == 0 || minutes > MAX_MINUTES {
+                return Ok(usage());
+            }
+
+            let storage = ctx.storage();
+            let mut pending: Vec<Reminder> = storage.get(PENDING_KEY).unwrap_or_default();
+            pending.push(Reminder {
+                due: unix_now() + minutes * 60,
+                destination: Destination::from(&context.source),
+                text: text.join(" "),
+            });
+            storage.put(PENDING_KEY, &pending)?;
+            Ok(Some(format!("I'll remind you in {} minute(s).", minutes)))
+        })
+    }
+
+    fn tick_interval(&self) -> Option<Duration> {
+        Some(Duration::from_secs(10))
+    }
+
+    fn tick<'a>(&'a mut self, ctx: &'a PluginContext) -> PluginFuture<'a, ()> {
+        Box::pin(async move {
+            let storage = ctx.storage();
+            let pending: Vec<Reminder> = storage.get(PENDING_KEY).unwrap_or_default();
+            let now = unix_now();
+            let (due, later): (Vec<_>, Vec<_>) = pending.into_iter().partition(|r| r.due <= now);
+            if due.is_empty() {
+                return Ok(());
+            }
+            for reminder in &due {
+                ctx.send(
+                    CommandSource::from(&reminder.destination),
+                    format!("Reminder: {}", reminder.text),
+                );
+            }
+            storage.put(PENDING_KEY, &later)
+        })
+    }
+}
@@ -0,0 +1,127 @@
+//! Relays messages between legacy Tox groups/conferences and Merkle-Tox
+//! conversations.
+//!
+//! Each link, given with `--bridge` or in `bridges` of the config file, pairs
+//! one legacy chat with one conversation. Messages are mirrored both ways
+//! with the sender's name prefixed; name changes and redactions become
+//! notices. The bot never relays what it authored itself, so a message
+//! cannot bounce back to where it came from.
+
+use crate::plugin::CommandSource;
+use merkle_tox_core::dag::{ConversationId, LogicalIdentityPk};
+use std::collections::HashMap;
+use std::str::FromStr;
+use toxcore::types::{ConferenceNumber, GroupNumber};
+
+/// A legacy chat that can be bridged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LegacyChat {
+    Group(GroupNumber),
+    Conference(ConferenceNumber),
+}
+
+impl From<LegacyChat> for CommandSource {
+    fn from(chat: LegacyChat) -> Self {
+        match chat {
+            LegacyChat::Group(n) => CommandSource::Group(n),
+            LegacyChat::Conference(n) => CommandSource::Conference(n),
+        }
+    }
+}
+
+/// One bridge link, written `group:<n>=<conversation>` or
+/// `conference:<n>=<conversation>` with the conversation id in hex.
+#[derive(Clone, Debug)]
+pub struct BridgeLink {
+    pub legacy: LegacyChat,
+    pub conversation: ConversationId,
+}
+
+impl FromStr for BridgeLink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (legacy, conversation) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected LEGACY=CONVERSATION, got {:?}", s))?;
+        let (kind, number) = legacy
+            .split_once(':')
+            .ok_or_else(|| format!("expected group:<n> or conference:<n>, got {:?}", legacy))?;
+        let number: u32 = number
+            .parse()
+            .map_err(|e| format!("bad chat number {:?}: {}", number, e))?;
+        let legacy = match kind {
+            "group" => LegacyChat::Group(GroupNumber(number)),
+            "conference" => LegacyChat::Conference(ConferenceNumber(number)),
+            _ => return Err(format!("unknown chat kind {:?}", kind)),
+        };
+        let bytes: [u8; 32] = hex::decode(conversation)
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| format!("bad conversation id {:?}", conversation))?;
+        Ok(Self {
+            legacy,
+            conversation: ConversationId::from(bytes),
+        })
+    }
+}
+
+/// The configured links and the legacy nicknames seen so far.
+pub struct Relay {
+    links: Vec<BridgeLink>,
+    /// Last known name of each legacy peer, to announce renames.
+    names: HashMap<(LegacyChat, u32), String>,
+}
+
+impl Relay {
+    pub fn new(links: Vec<BridgeLink>) -> Self {
+        Self {
+            links,
+            names: HashMap::new(),
+        }
+    }
+
+    /// Replaces the links, keeping the known names.
+    pub fn set_links(&mut self, links: Vec<BridgeLink>) {
+        self.links = links;
+    }
+
+    /// The conversation `chat` is bridged to.
+    pub fn conversation_for(&self, chat: LegacyChat) -> Option<ConversationId> {
+        self.links
+            .iter()
+            .find(|l| l.legacy == chat)
+            .map(|l| l.conversation)
+    }
+
+    /// The legacy chats bridged to `conversation`.
+    pub fn legacy_for(&self, conversation: &ConversationId) -> Vec<LegacyChat> {
+        self.links
+            .iter()
+            .filter(|l| l.conversation == *conversation)
+            .map(|l| l.legacy)
+            .collect()
+    }
+
+    /// Records the current name of a legacy peer. Returns the notice to relay
+    /// if the peer was known under another name.
+    pub fn rename(&mut self, chat: LegacyChat, peer: u32, name: &str) -> Option<String> {
+        let old = self.names.insert((chat, peer), name.to_string())?;
+        (old != name).then(|| format!("* {} is now known as {}", old, name))
+    }
+}
+
+/// Whether `text` is a bot command; commands are not relayed.
+pub fn is_command(text: &str) -> bool {
+    text.starts_with('!') || text.starts_with('~')
+}
+
+/// Text of a relayed message.
+pub fn relayed(name: &str, text: &str) -> String {
+    format!("<{}> {}", name, text)
+}
+
+/// Name shown in legacy chats for a Merkle-Tox member.
+pub fn member_name(pk: &LogicalIdentityPk) -> String {
+    hex::encode(&pk.as_bytes()[..4])
+}
@@ -0,0 +1,77 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::error;
+
+struct StorageInner {
+    path: PathBuf,
+    values: BTreeMap<String, serde_json::Value>,
+    dirty: bool,
+}
+
+/// Key-value store private to one plugin, kept as a JSON file named after the
+/// plugin. Writes are buffered and flushed by the bot loop.
+#[derive(Clone)]
+pub struct PluginStorage {
+    inner: Arc<Mutex<StorageInner>>,
+}
+
+impl PluginStorage {
+    /// Opens the store of plugin `name` under `dir`. A missing or unreadable
+    /// file starts an empty store.
+    pub fn open(dir: PathBuf, name: &str) -> Self {
+        let path = dir.join(format!("{}.json", name));
+        let values = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                error!("Failed to parse {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            inner: Arc::new(Mutex::new(StorageInner {
+                path,
+                values,
+                dirty: false,
+            })),
+        }
+    }
+
+    /// Returns the value stored under `key`, or `None` if there is none or it
+    /// does not decode as `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let inner = self.inner.lock();
+        let value = inner.values.get(key)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    pub fn put<T: Serialize>(&self, key: &str, value: &T) -> Result<(), Box<dyn Error>> {
+        let value = serde_json::to_value(value)?;
+        let mut inner = self.inner.lock();
+        inner.values.insert(key.to_string(), value);
+        inner.dirty = true;
+        Ok(())
+    }
+
+    /// Writes pending changes to disk.
+    pub fn flush(&self) -> Result<(), Box<dyn Error>> {
+        let mut inner = self.inner.lock();
+        if !inner.dirty {
+            return Ok(());
+        }
+        if let Some(dir) = inner.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec_pretty(&inner.values)?;
+        let tmp_path = inner.path.with_extension("json.tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &inner.path)?;
+        inner.dirty = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+//! The hosted node, its conversations, and the events sent to subscribers.
+
+use merkle_tox_client::state::{ChatMessage, ChatState, MemberRole};
+use merkle_tox_client::{MerkleToxClient, MerkleToxClientManager};
+use merkle_tox_core::dag::{Content, ConversationId, PhysicalDevicePk};
+use merkle_tox_core::diagnostics::{Diagnostics, SessionState};
+use merkle_tox_core::error::MerkleToxResult;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::{NodeEvent, Transport};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast};
+use tracing::error;
+
+/// Number of events buffered for each subscriber before it lags.
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Friend management of the transport the node runs over.
+pub trait Friends: Send + Sync {
+    /// Address others add this daemon with.
+    fn address(&self) -> String;
+    fn list(&self) -> Vec<Friend>;
+    /// Sends a friend request to the hex `address`.
+    fn add(&self, address: &str, message: &str) -> Result<(), String>;
+    /// Accepts a friend request from the hex `public_key`.
+    fn accept(&self, public_key: &str) -> Result<(), String>;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Friend {
+    pub public_key: String,
+    pub name: String,
+    pub connected: bool,
+}
+
+/// Event pushed to subscribed RPC clients.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A message from another device was verified.
+    Message {
+        conversation_id: String,
+        message: Message,
+    },
+    /// A conversation's state changed; fetch it again with
+    /// `conversations.get`.
+    ConversationChanged {
+        conversation_id: String,
+    },
+    PeerConnected {
+        public_key: String,
+    },
+    BlobAvailable {
+        hash: String,
+    },
+    FriendRequest {
+        public_key: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub hash: String,
+    pub author_pk: String,
+    pub timestamp_ms: i64,
+    /// None for content other than text.
+    pub text: Option<String>,
+    pub redacted: bool,
+}
+
+impl From<&ChatMessage> for Message {
+    fn from(m: &ChatMessage) -> Self {
+        Self {
+            hash: hex::encode(m.hash.as_bytes()),
+            author_pk: hex::encode(m.author_pk.as_bytes()),
+            timestamp_ms: m.timestamp,
+            text: match &m.content {
+                Content::Text(text) => Some(text.clone()),
+                _ => None,
+            },
+            redacted: m.is_redacted,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Member {
+    pub public_key: String,
+    pub admin: bool,
+    pub devices: Vec<String>,
+}
+
+/// Summary of a conversation, as listed by `conversations.list`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationSummary {
+    pub conversation_id: String,
+    pub title: String,
+    pub topic: String,
+    pub members: usize,
+    pub messages: usize,
+}
+
+/// State of a conversation, as returned by `conversations.get`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Conversation {
+    pub conversation_id: String,
+    pub title: String,
+    pub topic: String,
+    pub members: Vec<Member>,
+    /// The most recent messages, oldest first.
+    pub messages: Vec<Message>,
+}
+
+impl ConversationSummary {
+    pub fn new(state: &ChatState) -> Self {
+        Self {
+            conversation_id: hex::encode(state.conversation_id.as_bytes()),
+            title: state.title.clone(),
+            topic: state.topic.clone(),
+            members: state.members.len(),
+            messages: state.messages.len(),
+        }
+    }
+}
+
+impl Conversation {
+    /// The state with its last `limit` messages.
+    pub fn new(state: &ChatState, limit: usize) -> Self {
+        let mut members: Vec<Member> = state
+            .members
+            .values()
+            .map(|m| {
+                let mut devices: Vec<String> = m
+                    .devices
+                    .iter()
+                    .map(|pk| hex::encode(pk.as_bytes()))
+                    .collect();
+                devices.sort();
+                Member {
+                    public_key: hex::encode(m.public_key.as_bytes()),
+                    admin: m.role == MemberRole::Admin,
+                    devices,
+                }
+            })
+            .collect();
+        members.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+        let skip = state.messages.len().saturating_sub(limit);
+        Self {
+            conversation_id: hex::encode(state.conversation_id.as_bytes()),
+            title: state.title.clone(),
+            topic: state.topic.clone(),
+            members,
+            messages: state.messages[skip..].iter().map(Message::from).collect(),
+        }
+    }
+}
+
+/// Self-diagnostics of the node, as returned by `node.health`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    pub healthy: bool,
+    pub problems: Vec<String>,
+    pub clock_offset_ms: i64,
+    pub peer_clock_offset_ms: i64,
+    pub disk_usage_bytes: u64,
+    pub conversations: Vec<ConversationHealth>,
+    pub sessions: Vec<SessionHealth>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationHealth {
+    pub conversation_id: String,
+    pub established: bool,
+    pub verified_nodes: usize,
+    pub speculative_nodes: usize,
+    pub orphan_nodes: usize,
+    pub missing_parents: Vec<String>,
+    pub opaque_nodes: usize,
+    /// Other authorized devices, and those of them whose sender key for
+    /// the current epoch is known. Absent until the conversation is
+    /// established.
+    pub devices: Option<usize>,
+    pub covered_devices: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionHealth {
+    pub peer: String,
+    pub conversation_id: String,
+    pub active: bool,
+    pub online: bool,
+    pub queued_messages: usize,
+}
+
+impl From<&Diagnostics> for Health {
+    fn from(report: &Diagnostics) -> Self {
+        let problems = report.problems();
+        Self {
+            healthy: problems.is_empty(),
+            problems: problems.iter().map(ToString::to_string).collect(),
+            clock_offset_ms: report.clock.consensus_offset_ms,
+            peer_clock_offset_ms: report.clock.peer_median_offset_ms,
+            disk_usage_bytes: report.disk_usage_bytes,
+            conversations: report
+                .conversations
+                .iter()
+                .map(|c| ConversationHealth {
+                    conversation_id: hex::encode(c.conversation_id.as_bytes()),
+                    established: c.established,
+                    verified_nodes: c.verified_nodes,
+                    speculative_nodes: c.speculative_nodes,
+                    orphan_nodes: c.integrity.orphan_nodes,
+                    missing_parents: c
+                        .integrity
+                        .missing_parents
+                        .iter()
+                        .map(|h| hex::encode(h.as_bytes()))
+                        .collect(),
+                    opaque_nodes: c.integrity.opaque_nodes,
+                    devices: c.ratchets.as_ref().map(|r| r.devices),
+                    covered_devices: c.ratchets.as_ref().map(|r| r.covered),
+                })
+                .collect(),
+            sessions: report
+                .sessions
+                .iter()
+                .map(|s| SessionHealth {
+                    peer: hex::encode(s.peer.as_bytes()),
+                    conversation_id: hex::encode(s.conversation_id.as_bytes()),
+                    active: s.state == SessionState::Active,
+                    online: s.online,
+                    queued_messages: s.queued_messages,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub type Client<T, S> = MerkleToxClient<T, S>;
+
+/// The node shared by the main loop and the RPC connections.
+pub struct Host<T: Transport + 'static, S: NodeStore + BlobStore + 'static> {
+    pub node: Arc<Mutex<MerkleToxNode<T, S>>>,
+    pub friends: Arc<dyn Friends>,
+    clients: Arc<MerkleToxClientManager<T, S>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl<T: Transport + 'static, S: NodeStore + BlobStore + Send + 'static> Host<T, S> {
+    /// Hosts `node`, loading the conversations in its store.
+    pub async fn new(
+        node: Arc<Mutex<MerkleToxNode<T, S>>>,
+        friends: Arc<dyn Friends>,
+        conversations: Vec<ConversationId>,
+    ) -> Arc<Self> {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let clients = MerkleToxClientManager::new(node.clone());
+        let host = Arc::new(Self {
+            node,
+            friends,
+            clients,
+            events,
+        });
+        for conversation_id in conversations {
+            if let Err(e) = host.open(conversation_id).await {
+                error!("Failed to load conversation {:?}: {}", conversation_id, e);
+            }
+        }
+        let mut rx = host.clients.subscribe();
+        host.clients.attach().await;
+        let weak = Arc::downgrade(&host);
+        tokio::spawn(async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        error!("Dropped {} node event(s)", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let Some(host) = weak.upgrade() else {
+                    break;
+                };
+                host.dispatch(event);
+            }
+        });
+        host
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Sends `event` to the subscribers, if any.
+    pub fn publish(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    pub async fn client(&self, conversation_id: &ConversationId) -> Option<Arc<Client<T, S>>> {
+        self.clients.get(conversation_id).await
+    }
+
+    pub async fn clients(&self) -> Vec<Arc<Client<T, S>>> {
+        self.clients.clients().await
+    }
+
+    /// The client of a conversation, created and loaded if needed.
+    pub async fn open(
+        &self,
+        conversation_id: ConversationId,
+    ) -> MerkleToxResult<Arc<Client<T, S>>> {
+        self.clients.get_or_create(conversation_id).await
+    }
+
+    /// Joins a conversation, syncing it from `peer_pk` if given.
+    pub async fn join(
+        &self,
+        conversation_id: ConversationId,
+        peer_pk: Option<PhysicalDevicePk>,
+    ) -> MerkleToxResult<()> {
+        {
+            let mut node = self.node.lock().await;
+            let node = &mut *node;
+            let effects = node
+                .engine
+                .start_sync(conversation_id, peer_pk, &node.store);
+            let now = node.time_provider.now_instant();
+            let now_ms = node.time_provider.now_system_ms() as u64;
+            let mut next_wakeup = now;
+            node.process_effects(effects, now, now_ms, &mut next_wakeup)?;
+        }
+        self.open(conversation_id).await.map(|_| ())
+    }
+
+    /// Publishes a node event the clients have handled.
+    fn dispatch(&self, event: NodeEvent) {
+        let conversation_id = match &event {
+            NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node,
+            } => {
+                if let Content::Text(text) = &node.content {
+                    self.publish(Event::Message {
+                        conversation_id: hex::encode(conversation_id.as_bytes()),
+                        message: Message {
+                            hash: hex::encode(hash.as_bytes()),
+                            author_pk: hex::encode(node.author_pk.as_bytes()),
+                            timestamp_ms: node.network_timestamp,
+                            text: Some(text.clone()),
+                            redacted: false,
+                        },
+                    });
+                }
+                conversation_id
+            }
+            NodeEvent::NodeInvalidated {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeExpired {
+                conversation_id, ..
+            } => conversation_id,
+            NodeEvent::PeerHandshakeComplete { peer_pk } => {
+                self.publish(Event::PeerConnected {
+                    public_key: hex::encode(peer_pk.as_bytes()),
+                });
+                return;
+            }
+            NodeEvent::BlobAvailable { hash } => {
+                self.publish(Event::BlobAvailable {
+                    hash: hex::encode(hash.as_bytes()),
+                });
+                return;
+            }
+            _ => return,
+        };
+        self.publish(Event::ConversationChanged {
+            conversation_id: hex::encode(conversation_id.as_bytes()),
+        });
+    }
+}
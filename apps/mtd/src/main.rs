@@ -0,0 +1,308 @@
+//! mtd: hosts a Merkle-Tox node and exposes it to local UIs over JSON-RPC.
+//!
+//! The node runs here; user interfaces in any language attach to the Unix
+//! socket (see `rpc` for the methods) and may come and go while the node
+//! keeps syncing.
+
+mod host;
+mod rpc;
+mod server;
+
+use clap::Parser;
+use host::{Event as HostEvent, Friend, Friends, Host};
+use merkle_tox_core::clock::SystemTimeProvider;
+use merkle_tox_core::crypto::ed25519_public_key_from_seed;
+use merkle_tox_core::dag::{PhysicalDevicePk, PhysicalDeviceSk};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_tox::{ToxMerkleBridge, ToxTransport};
+use parking_lot::ReentrantMutex;
+use serde::Deserialize;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use toxcore::tox::events::Event;
+use toxcore::tox::{Options, Profile, Tox, ToxConnection, ToxProxyType};
+use toxcore::types::{ADDRESS_SIZE, Address, DhtId, PUBLIC_KEY_SIZE, PublicKey};
+use tracing::{error, info};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[arg(short, long)]
+    savefile: Option<String>,
+    #[arg(long)]
+    password: Option<String>,
+    /// Directory of the Merkle-Tox store.
+    #[arg(long, default_value = "mtd")]
+    store: PathBuf,
+    /// Path of the RPC socket. Defaults to `mtd.sock` in the store directory.
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    #[arg(long, default_value = "mtd")]
+    nick: String,
+    #[arg(long, default_value = "https://nodes.tox.chat/json")]
+    nodes_url: String,
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    tor: bool,
+}
+
+/// Time the node gets to flush its state on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Friend management backed by the Tox instance.
+struct ToxFriends {
+    tox: Arc<ReentrantMutex<Tox>>,
+    /// Set when the friend list changed and the profile needs saving.
+    dirty: Arc<AtomicBool>,
+}
+
+impl Friends for ToxFriends {
+    fn address(&self) -> String {
+        hex::encode(self.tox.lock().address().0)
+    }
+
+    fn list(&self) -> Vec<Friend> {
+        let tox = self.tox.lock();
+        tox.friend_list()
+            .iter()
+            .filter_map(|friend| {
+                Some(Friend {
+                    public_key: hex::encode(friend.public_key().ok()?.0),
+                    name: String::from_utf8_lossy(&friend.name().unwrap_or_default()).into_owned(),
+                    connected: friend
+                        .connection_status()
+                        .is_ok_and(|status| status != ToxConnection::TOX_CONNECTION_NONE),
+                })
+            })
+            .collect()
+    }
+
+    fn add(&self, address: &str, message: &str) -> Result<(), String> {
+        let address = hex::decode(address)
+            .ok()
+            .and_then(|bytes| <[u8; ADDRESS_SIZE]>::try_from(bytes).ok())
+            .ok_or("invalid address")?;
+        self.tox
+            .lock()
+            .friend_add(&Address(address), message.as_bytes())
+            .map_err(|e| e.to_string())?;
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn accept(&self, public_key: &str) -> Result<(), String> {
+        let public_key = hex::decode(public_key)
+            .ok()
+            .and_then(|bytes| <[u8; PUBLIC_KEY_SIZE]>::try_from(bytes).ok())
+            .ok_or("invalid public_key")?;
+        self.tox
+            .lock()
+            .friend_add_norequest(&PublicKey(public_key))
+            .map_err(|e| e.to_string())?;
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+struct Daemon {
+    tox: Arc<ReentrantMutex<Tox>>,
+    bridge: ToxMerkleBridge<FsStore>,
+    host: Arc<Host<ToxTransport, FsStore>>,
+    profile: Option<Profile>,
+    dirty: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    async fn new(
+        tox: Tox,
+        store_path: PathBuf,
+        profile: Option<Profile>,
+        dirty: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        let store = FsStore::new(store_path, Arc::new(StdFileSystem))?;
+        let conversations = store.conversations();
+        let self_sk = tox.secret_key();
+        let tox = Arc::new(ReentrantMutex::new(tox));
+        let transport = ToxTransport { tox: tox.clone() };
+        // Derive Ed25519 identity from Tox X25519 secret key.
+        let self_pk = PhysicalDevicePk::from(ed25519_public_key_from_seed(&self_sk));
+        let engine = MerkleToxEngine::with_sk(
+            self_pk,
+            self_pk.to_logical(),
+            PhysicalDeviceSk::from(self_sk),
+            rand::SeedableRng::from_entropy(),
+            Arc::new(SystemTimeProvider),
+        );
+        let node = Arc::new(Mutex::new(MerkleToxNode::new(
+            engine,
+            transport,
+            store,
+            Arc::new(SystemTimeProvider),
+        )));
+        let bridge = ToxMerkleBridge::with_node(node.clone());
+        let dirty = Arc::new(AtomicBool::new(dirty));
+        let friends = Arc::new(ToxFriends {
+            tox: tox.clone(),
+            dirty: dirty.clone(),
+        });
+        let host = Host::new(node, friends, conversations).await;
+        Ok(Self {
+            tox,
+            bridge,
+            host,
+            profile,
+            dirty,
+        })
+    }
+
+    fn save(&self) {
+        if let Some(profile) = &self.profile {
+            if let Err(e) = profile.save(&self.tox.lock()) {
+                error!("Failed to save savedata: {}", e);
+            } else {
+                self.dirty.store(false, Ordering::SeqCst);
+            }
+        }
+    }
+
+    async fn run(&self, shutdown: Arc<AtomicBool>) {
+        let mut last_save = Instant::now();
+        while !shutdown.load(Ordering::SeqCst) {
+            if self.dirty.load(Ordering::SeqCst) && last_save.elapsed() > Duration::from_secs(30) {
+                self.save();
+                last_save = Instant::now();
+            }
+
+            if let Ok(events) = self.tox.lock().events() {
+                for event in &events {
+                    if self.bridge.handle_event(&event).await.is_some() {
+                        continue;
+                    }
+                    // Friend requests are left to the attached UIs.
+                    if let Event::FriendRequest(e) = event {
+                        let public_key = hex::encode(e.public_key().0);
+                        info!("Friend request from {}", public_key);
+                        self.host.publish(HostEvent::FriendRequest {
+                            public_key,
+                            message: String::from_utf8_lossy(e.message()).into_owned(),
+                        });
+                    }
+                }
+            }
+
+            let next_mt_wakeup = self.bridge.poll().await;
+            let tox_interval = self.tox.lock().iteration_interval();
+            let next_tox_wakeup = Instant::now() + Duration::from_millis(tox_interval as u64);
+            let sleep_until = next_mt_wakeup.min(next_tox_wakeup);
+            tokio::time::sleep_until(sleep_until.into()).await;
+        }
+        let summary = self.bridge.shutdown(Instant::now() + SHUTDOWN_GRACE).await;
+        info!("Merkle-Tox node shut down: {:?}", summary);
+        if self.dirty.load(Ordering::SeqCst) {
+            self.save();
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Node {
+    ipv4: String,
+    port: u16,
+    public_key: String,
+    last_ping: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct NodesResponse {
+    nodes: Vec<Node>,
+}
+
+async fn fetch_nodes(url: &str) -> Result<Vec<Node>, Box<dyn Error>> {
+    let resp: NodesResponse = reqwest::get(url).await?.json().await?;
+    let mut nodes = resp.nodes;
+    nodes.sort_by(|a, b| b.last_ping.cmp(&a.last_ping));
+    Ok(nodes)
+}
+
+fn bootstrap(tox: &Tox, nodes: &[Node]) {
+    for node in nodes.iter().take(4) {
+        if let Ok(pk_bytes) = hex::decode(&node.public_key)
+            && pk_bytes.len() == PUBLIC_KEY_SIZE
+        {
+            let mut pk_arr = [0u8; PUBLIC_KEY_SIZE];
+            pk_arr.copy_from_slice(&pk_bytes);
+            let dht_id = DhtId(pk_arr);
+            if let Err(e) = tox.bootstrap(&node.ipv4, node.port, &dht_id) {
+                error!("Failed to bootstrap to node {}: {}", node.ipv4, e);
+            }
+            if let Err(e) = tox.add_tcp_relay(&node.ipv4, node.port, &dht_id) {
+                error!("Failed to add tcp relay {}: {}", node.ipv4, e);
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    std::fs::create_dir_all(&args.store)?;
+
+    let mut opts = Options::new()?;
+    opts.set_experimental_owned_data(true);
+    if args.tor {
+        opts.set_proxy_type(ToxProxyType::TOX_PROXY_TYPE_SOCKS5);
+        opts.set_proxy_host("127.0.0.1")?;
+        opts.set_proxy_port(9050);
+        opts.set_udp_enabled(false);
+    }
+
+    let profile = args
+        .savefile
+        .map(|path| Profile::new(path).with_password(args.password.as_deref().map(str::as_bytes)));
+    let loaded = match &profile {
+        Some(profile) => profile.apply(&mut opts)?,
+        None => false,
+    };
+
+    let tox = Tox::new(opts)?;
+    if tox.name().is_empty() {
+        tox.set_name(args.nick.as_bytes())?;
+    }
+    info!("Tox ID: {}", hex::encode(tox.address().0));
+
+    let nodes = fetch_nodes(&args.nodes_url).await.unwrap_or_default();
+    bootstrap(&tox, &nodes);
+
+    let daemon = Daemon::new(tox, args.store.join("merkle_tox"), profile, !loaded).await?;
+
+    let socket = args.socket.unwrap_or_else(|| args.store.join("mtd.sock"));
+    let listener = server::bind(&socket)?;
+    tokio::spawn(server::serve(listener, daemon.host.clone()));
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown_ctrlc = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Shutting down...");
+            shutdown_ctrlc.store(true, Ordering::SeqCst);
+        }
+    });
+
+    daemon.run(shutdown).await;
+    let _ = std::fs::remove_file(&socket);
+    Ok(())
+}
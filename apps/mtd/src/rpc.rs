@@ -0,0 +1,303 @@
+//! JSON-RPC 2.0 methods of the daemon.
+//!
+//! Keys, hashes and conversation IDs are hex strings. Methods:
+//!
+//! - `node.info`: identity and Tox address of the daemon.
+//! - `node.health`: self-diagnostics: store integrity, key coverage, clock
+//!   offset, sessions and disk usage, with the problems found.
+//! - `friends.list`, `friends.add {address, message}`,
+//!   `friends.accept {public_key}`.
+//! - `conversations.list`, `conversations.get {conversation_id, limit?}`,
+//!   `conversations.join {conversation_id, peer_pk?}`,
+//!   `conversations.join_invite {link}`,
+//!   `conversations.invite_link {conversation_id, expires_at_ms}`,
+//!   `conversations.leave {conversation_id}`.
+//! - `messages.send {conversation_id, text}`.
+//! - `devices.authorize {conversation_id, device_pk, expires_at_ms?}`,
+//!   `devices.revoke {conversation_id, device_pk, reason?}`.
+//! - `events.subscribe`: the connection receives `event` notifications.
+
+use crate::host::{Conversation, ConversationSummary, Health, Host};
+use merkle_tox_core::Transport;
+use merkle_tox_core::dag::{ConversationId, NodeHash, Permissions, PhysicalDevicePk};
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The node rejected the call.
+pub const NODE_ERROR: i64 = -32000;
+pub const UNKNOWN_CONVERSATION: i64 = -32001;
+
+/// Messages returned by `conversations.get` without a `limit`.
+const DEFAULT_MESSAGE_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// Absent for notifications, which get no response.
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response {
+    pub jsonrpc: &'static str,
+    pub id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn new(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(value) => (Some(value), None),
+            Err(e) => (None, Some(e)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<MerkleToxError> for RpcError {
+    fn from(e: MerkleToxError) -> Self {
+        Self::new(NODE_ERROR, e.to_string())
+    }
+}
+
+/// Notification carrying an event to a subscribed connection.
+pub fn notification(event: &crate::host::Event) -> Value {
+    json!({"jsonrpc": "2.0", "method": "event", "params": event})
+}
+
+#[derive(Deserialize)]
+struct ConversationParams {
+    conversation_id: String,
+}
+
+#[derive(Deserialize)]
+struct GetParams {
+    conversation_id: String,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct JoinParams {
+    conversation_id: String,
+    peer_pk: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JoinInviteParams {
+    link: String,
+}
+
+#[derive(Deserialize)]
+struct InviteLinkParams {
+    conversation_id: String,
+    expires_at_ms: i64,
+}
+
+#[derive(Deserialize)]
+struct SendParams {
+    conversation_id: String,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    conversation_id: String,
+    device_pk: String,
+    /// Never expires if absent.
+    expires_at_ms: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct RevokeParams {
+    conversation_id: String,
+    device_pk: String,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct AddFriendParams {
+    address: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct AcceptFriendParams {
+    public_key: String,
+}
+
+fn params<P: DeserializeOwned>(params: Value) -> Result<P, RpcError> {
+    // Methods without parameters accept a missing `params`.
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn key(hex_key: &str, what: &str) -> Result<[u8; 32], RpcError> {
+    hex::decode(hex_key)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("invalid {}", what)))
+}
+
+fn conversation_id(hex_key: &str) -> Result<ConversationId, RpcError> {
+    key(hex_key, "conversation_id").map(ConversationId::from)
+}
+
+fn hash(hash: NodeHash) -> Value {
+    json!({"hash": hex::encode(hash.as_bytes())})
+}
+
+/// Runs a method other than `events.subscribe`, which the connection
+/// handles itself.
+pub async fn call<T, S>(
+    host: &Host<T, S>,
+    method: &str,
+    params_value: Value,
+) -> Result<Value, RpcError>
+where
+    T: Transport + 'static,
+    S: NodeStore + BlobStore + Send + 'static,
+{
+    let client = async |id: &str| {
+        let id = conversation_id(id)?;
+        host.client(&id)
+            .await
+            .ok_or_else(|| RpcError::new(UNKNOWN_CONVERSATION, "conversation not joined"))
+    };
+    match method {
+        "node.info" => {
+            let identity = host.node.lock().await.engine.self_logical_pk;
+            Ok(json!({
+                "identity": hex::encode(identity.as_bytes()),
+                "address": host.friends.address(),
+            }))
+        }
+        "node.health" => {
+            let report = host.node.lock().await.diagnostics();
+            Ok(json!(Health::from(&report)))
+        }
+        "friends.list" => Ok(json!(host.friends.list())),
+        "friends.add" => {
+            let p: AddFriendParams = params(params_value)?;
+            host.friends
+                .add(&p.address, &p.message)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+            Ok(Value::Null)
+        }
+        "friends.accept" => {
+            let p: AcceptFriendParams = params(params_value)?;
+            host.friends
+                .accept(&p.public_key)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+            Ok(Value::Null)
+        }
+        "conversations.list" => {
+            let mut summaries = Vec::new();
+            for client in host.clients().await {
+                summaries.push(ConversationSummary::new(&client.state().await));
+            }
+            summaries.sort_by(|a, b| a.conversation_id.cmp(&b.conversation_id));
+            Ok(json!(summaries))
+        }
+        "conversations.get" => {
+            let p: GetParams = params(params_value)?;
+            let state = client(&p.conversation_id).await?.state().await;
+            let limit = p.limit.unwrap_or(DEFAULT_MESSAGE_LIMIT);
+            Ok(json!(Conversation::new(&state, limit)))
+        }
+        "conversations.join" => {
+            let p: JoinParams = params(params_value)?;
+            let id = conversation_id(&p.conversation_id)?;
+            let peer_pk = p
+                .peer_pk
+                .map(|pk| key(&pk, "peer_pk").map(PhysicalDevicePk::from))
+                .transpose()?;
+            host.join(id, peer_pk).await?;
+            Ok(Value::Null)
+        }
+        "conversations.join_invite" => {
+            let p: JoinInviteParams = params(params_value)?;
+            let token = host
+                .node
+                .lock()
+                .await
+                .join_from_invite(&p.link)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+            host.open(token.conversation_id).await?;
+            Ok(json!({"conversation_id": hex::encode(token.conversation_id.as_bytes())}))
+        }
+        "conversations.invite_link" => {
+            let p: InviteLinkParams = params(params_value)?;
+            let link = client(&p.conversation_id)
+                .await?
+                .create_invite_link(p.expires_at_ms)
+                .await?;
+            Ok(json!({"link": link}))
+        }
+        "conversations.leave" => {
+            let p: ConversationParams = params(params_value)?;
+            Ok(hash(client(&p.conversation_id).await?.leave().await?))
+        }
+        "messages.send" => {
+            let p: SendParams = params(params_value)?;
+            let client = client(&p.conversation_id).await?;
+            Ok(hash(client.send_message(p.text).await?))
+        }
+        "devices.authorize" => {
+            let p: AuthorizeParams = params(params_value)?;
+            let device_pk = PhysicalDevicePk::from(key(&p.device_pk, "device_pk")?);
+            let client = client(&p.conversation_id).await?;
+            let expires_at = p.expires_at_ms.unwrap_or(i64::MAX);
+            Ok(hash(
+                client
+                    .authorize_device(device_pk, Permissions::ALL, expires_at)
+                    .await?,
+            ))
+        }
+        "devices.revoke" => {
+            let p: RevokeParams = params(params_value)?;
+            let device_pk = PhysicalDevicePk::from(key(&p.device_pk, "device_pk")?);
+            let client = client(&p.conversation_id).await?;
+            Ok(hash(client.revoke_device(device_pk, p.reason).await?))
+        }
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {}", method),
+        )),
+    }
+}
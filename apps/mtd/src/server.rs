@@ -0,0 +1,141 @@
+//! Local RPC endpoint: a Unix socket carrying one JSON-RPC message per line.
+
+use crate::host::Host;
+use crate::rpc::{self, INVALID_REQUEST, PARSE_ERROR, Request, Response, RpcError};
+use merkle_tox_core::Transport;
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use serde_json::Value;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+/// Binds the socket at `path`, replacing a stale one. Only the owner may
+/// connect.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("RPC socket listening on {}", path.display());
+    Ok(listener)
+}
+
+/// Accepts connections until the listener fails.
+pub async fn serve<T, S>(listener: UnixListener, host: Arc<Host<T, S>>)
+where
+    T: Transport + 'static,
+    S: NodeStore + BlobStore + Send + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let host = host.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, host).await {
+                        debug!("RPC connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("Failed to accept RPC connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_connection<T, S>(stream: UnixStream, host: Arc<Host<T, S>>) -> io::Result<()>
+where
+    T: Transport + 'static,
+    S: NodeStore + BlobStore + Send + 'static,
+{
+    let (reader, mut writer) = stream.into_split();
+    // Responses and event notifications share the writer.
+    let (out_tx, mut out_rx) = mpsc::unbounded_channel::<Value>();
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = out_rx.recv().await {
+            let mut line = message.to_string();
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subscription = None;
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request: Request = match serde_json::from_str::<Value>(&line) {
+            Err(e) => {
+                let error = RpcError::new(PARSE_ERROR, e.to_string());
+                let _ = out_tx.send(response(Value::Null, Err(error)));
+                continue;
+            }
+            Ok(value) => match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(e) => {
+                    let error = RpcError::new(INVALID_REQUEST, e.to_string());
+                    let _ = out_tx.send(response(Value::Null, Err(error)));
+                    continue;
+                }
+            },
+        };
+        let result = if request.jsonrpc != "2.0" {
+            Err(RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))
+        } else if request.method == "events.subscribe" {
+            if subscription.is_none() {
+                subscription = Some(tokio::spawn(forward_events(
+                    host.subscribe(),
+                    out_tx.clone(),
+                )));
+            }
+            Ok(Value::Null)
+        } else {
+            rpc::call(&host, &request.method, request.params).await
+        };
+        if let Some(id) = request.id {
+            let _ = out_tx.send(response(id, result));
+        }
+    }
+
+    if let Some(subscription) = subscription {
+        subscription.abort();
+    }
+    drop(out_tx);
+    let _ = writer_task.await;
+    Ok(())
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    serde_json::to_value(Response::new(id, result)).unwrap_or(Value::Null)
+}
+
+async fn forward_events(
+    mut events: tokio::sync::broadcast::Receiver<crate::host::Event>,
+    out: mpsc::UnboundedSender<Value>,
+) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if out.send(rpc::notification(&event)).is_err() {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                warn!("RPC subscriber missed {} events", missed);
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
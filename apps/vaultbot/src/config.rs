@@ -0,0 +1,119 @@
+//! Config file of the bot and the command line flags that override it.
+//!
+//! The quotas and the log levels are applied again when the file changes;
+//! everything else takes a restart.
+
+use crate::quota::QuotaConfig;
+use bot_common::config::{BootstrapNode, ProxyConfig};
+use bot_common::logging::LogConfig;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Prefix of the environment variables overriding the config.
+pub const ENV_PREFIX: &str = "VAULTBOT";
+
+#[derive(Parser, Debug, Clone)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// Config file, see `--write-default-config`.
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Writes the default config to this path and exits.
+    #[arg(long, value_name = "PATH")]
+    pub write_default_config: Option<PathBuf>,
+    #[arg(short, long)]
+    pub savedata: Option<PathBuf>,
+    #[arg(short = 't', long)]
+    pub storage: Option<PathBuf>,
+    /// Disk quota for the whole store in MiB.
+    #[arg(long)]
+    pub quota_mb: Option<u64>,
+    /// Disk quota for each conversation in MiB.
+    #[arg(long)]
+    pub conversation_quota_mb: Option<u64>,
+    /// Number of recent messages pushed to newly joined devices.
+    #[arg(long)]
+    pub history_window: Option<usize>,
+    /// Serves Prometheus metrics at `/metrics` on this address, e.g.
+    /// `127.0.0.1:9464`.
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Archives without ever posting to the conversations.
+    #[arg(long)]
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub savedata: Option<PathBuf>,
+    /// Merkle-Tox store and the cached node list.
+    pub storage: PathBuf,
+    /// Disk quota for the whole store in MiB. Unlimited if not set.
+    pub quota_mb: Option<u64>,
+    /// Disk quota for each conversation in MiB. Unlimited if not set.
+    pub conversation_quota_mb: Option<u64>,
+    /// Number of recent messages pushed to newly joined devices.
+    pub history_window: usize,
+    pub metrics_addr: Option<SocketAddr>,
+    /// Archives without ever posting, so that no member permission is
+    /// needed. Status commands are not answered.
+    pub read_only: bool,
+    /// Nodes to bootstrap from instead of the fetched node list.
+    pub bootstrap_nodes: Vec<BootstrapNode>,
+    pub proxy: ProxyConfig,
+    pub logging: LogConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            savedata: None,
+            storage: PathBuf::from("vault_storage"),
+            quota_mb: None,
+            conversation_quota_mb: None,
+            history_window: 100,
+            metrics_addr: None,
+            read_only: false,
+            bootstrap_nodes: Vec::new(),
+            proxy: ProxyConfig::default(),
+            logging: LogConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config file named by `args`, with the environment and
+    /// then `args` applied on top.
+    pub fn load(args: &Args) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config: Self = bot_common::config::load(args.config.as_deref(), ENV_PREFIX)?;
+        if let Some(savedata) = &args.savedata {
+            config.savedata = Some(savedata.clone());
+        }
+        if let Some(storage) = &args.storage {
+            config.storage = storage.clone();
+        }
+        if args.quota_mb.is_some() {
+            config.quota_mb = args.quota_mb;
+        }
+        if args.conversation_quota_mb.is_some() {
+            config.conversation_quota_mb = args.conversation_quota_mb;
+        }
+        if let Some(history_window) = args.history_window {
+            config.history_window = history_window;
+        }
+        if args.metrics_addr.is_some() {
+            config.metrics_addr = args.metrics_addr;
+        }
+        if args.read_only {
+            config.read_only = true;
+        }
+        Ok(config)
+    }
+
+    pub fn quota(&self) -> QuotaConfig {
+        QuotaConfig::from_mib(self.quota_mb, self.conversation_quota_mb)
+    }
+}
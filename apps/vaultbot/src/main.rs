@@ -1,15 +1,23 @@
+mod config;
+mod quota;
+
+use bot_common::config::{ConfigWatcher, ProxyType};
+use bot_common::logging::{self, LogGuard};
 use clap::Parser;
-use merkle_tox_client::MerkleToxClient;
-use merkle_tox_core::dag::{ConversationId, PhysicalDeviceSk};
+use config::{Args, Config};
+use merkle_tox_client::MerkleToxClientManager;
+use merkle_tox_client::policy::HistoryServerPolicy;
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::dag::{Content, PhysicalDeviceSk};
 use merkle_tox_core::node::MerkleToxNode;
-use merkle_tox_core::{NodeEvent, NodeEventHandler};
 use merkle_tox_fs::FsStore;
+use merkle_tox_tox::metrics::{self, Metrics};
 use merkle_tox_tox::{ToxMerkleBridge, ToxTransport};
 use parking_lot::ReentrantMutex;
+use quota::{QUOTA_CHECK_INTERVAL, QuotaConfig};
 use rand::SeedableRng;
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::io;
@@ -17,9 +25,9 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
 use toxcore::tox::events::Event;
-use toxcore::tox::{Options, Tox, ToxSavedataType};
+use toxcore::tox::{Options, Profile, Tox, ToxProxyType};
 use toxcore::types::{DhtId, PUBLIC_KEY_SIZE};
 use tracing::{error, info};
 
@@ -41,71 +49,69 @@ struct NodesResponse {
     nodes: Vec<Node>,
 }
 
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(short, long)]
-    savedata: Option<String>,
-    #[arg(short = 't', long, default_value = "vault_storage")]
-    storage: String,
-}
-
-type VaultClient = MerkleToxClient<ToxTransport, FsStore>;
-type ClientMap = HashMap<ConversationId, Arc<VaultClient>>;
-
-struct Dispatcher {
-    node: Arc<Mutex<MerkleToxNode<ToxTransport, FsStore>>>,
-    clients: Arc<Mutex<ClientMap>>,
-}
-
-impl NodeEventHandler for Dispatcher {
-    fn handle_event(&self, event: NodeEvent) {
-        let node = self.node.clone();
-        let clients = self.clients.clone();
-        tokio::spawn(async move {
-            match event {
-                NodeEvent::NodeVerified {
-                    conversation_id, ..
-                }
-                | NodeEvent::NodeSpeculative {
-                    conversation_id, ..
-                } => {
-                    let mut clients_lock = clients.lock().await;
-                    let client = if let Some(c) = clients_lock.get(&conversation_id) {
-                        c.clone()
-                    } else {
-                        info!("Discovered conversation: {:?}", conversation_id);
-                        let c = Arc::new(MerkleToxClient::new(node, conversation_id));
-                        if let Err(e) = c.refresh_state().await {
-                            error!("Failed to refresh state for {:?}: {}", conversation_id, e);
-                        }
-                        clients_lock.insert(conversation_id, c.clone());
-                        c
-                    };
-                    if let Err(e) = client.handle_event(event).await {
-                        error!("Error handling node event: {}", e);
-                    }
-                }
-                NodeEvent::PeerHandshakeComplete { .. } => {
-                    let clients_lock = clients.lock().await;
-                    for client in clients_lock.values() {
-                        let _ = client.handle_event(event.clone()).await;
-                    }
-                }
-                _ => {}
+/// Command answered in any mirrored conversation.
+const STATUS_COMMAND: &str = "!vault status";
+
+/// Time the node gets to flush its state on shutdown.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+type ClientManager = MerkleToxClientManager<ToxTransport, FsStore>;
+
+/// Answers [`STATUS_COMMAND`] in the mirrored conversations.
+async fn answer_status_commands(
+    clients: Arc<ClientManager>,
+    quota: Arc<parking_lot::Mutex<QuotaConfig>>,
+) {
+    let mut rx = clients.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(n)) => {
+                error!("Dropped {} node event(s)", n);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let NodeEvent::NodeVerified {
+            conversation_id,
+            node: n,
+            ..
+        } = event
+        else {
+            continue;
+        };
+        if !matches!(&n.content, Content::Text(text) if text.trim() == STATUS_COMMAND) {
+            continue;
+        }
+        let report = {
+            let node = clients.node().lock().await;
+            if n.author_pk == node.engine.self_logical_pk {
+                continue;
             }
-        });
+            quota::status_report(&node.store, &quota.lock(), &conversation_id)
+        };
+        if let Some(client) = clients.get(&conversation_id).await
+            && let Err(e) = client.send_message(report).await
+        {
+            error!("Failed to send vault status: {}", e);
+        }
     }
 }
 
 struct VaultBot {
     tox: Arc<ReentrantMutex<Tox>>,
+    node: Arc<Mutex<MerkleToxNode<ToxTransport, FsStore>>>,
     bridge: Arc<Mutex<ToxMerkleBridge<FsStore>>>,
-    _clients: Arc<Mutex<ClientMap>>,
+    _clients: Arc<ClientManager>,
     _storage_path: PathBuf,
-    savedata_path: Option<PathBuf>,
+    profile: Option<Profile>,
     shutdown: Arc<AtomicBool>,
     dirty: bool,
+    quota: Arc<parking_lot::Mutex<QuotaConfig>>,
+    metrics: Arc<Metrics>,
+    args: Args,
+    config_watcher: Option<ConfigWatcher>,
+    log_guard: LogGuard,
 }
 
 use merkle_tox_core::vfs::StdFileSystem;
@@ -113,11 +119,15 @@ use merkle_tox_core::vfs::StdFileSystem;
 impl VaultBot {
     fn new(
         tox: Tox,
-        storage_path: PathBuf,
-        savedata_path: Option<PathBuf>,
+        args: Args,
+        config: &Config,
+        log_guard: LogGuard,
+        profile: Option<Profile>,
         shutdown: Arc<AtomicBool>,
         dirty: bool,
     ) -> Self {
+        let storage_path = config.storage.clone();
+        let quota = Arc::new(parking_lot::Mutex::new(config.quota()));
         let store_path = storage_path.join("merkle_tox");
         let store =
             FsStore::new(store_path, Arc::new(StdFileSystem)).expect("Failed to create FsStore");
@@ -130,13 +140,14 @@ impl VaultBot {
         let self_pk = merkle_tox_core::dag::PhysicalDevicePk::from(
             merkle_tox_core::crypto::ed25519_public_key_from_seed(&self_sk),
         );
-        let engine = merkle_tox_core::engine::MerkleToxEngine::with_sk(
+        let mut engine = merkle_tox_core::engine::MerkleToxEngine::with_sk(
             self_pk,
             self_pk.to_logical(),
             PhysicalDeviceSk::from(self_sk),
             rand::SeedableRng::from_entropy(),
             Arc::new(merkle_tox_core::clock::SystemTimeProvider),
         );
+        engine.config.read_only = config.read_only;
         let node = MerkleToxNode::new(
             engine,
             transport,
@@ -145,46 +156,92 @@ impl VaultBot {
         );
         let node_arc = Arc::new(Mutex::new(node));
         let bridge = Arc::new(Mutex::new(ToxMerkleBridge::with_node(node_arc.clone())));
-        let clients = Arc::new(Mutex::new(HashMap::new()));
-
-        let dispatcher = Dispatcher {
-            node: node_arc.clone(),
-            clients: clients.clone(),
-        };
+        let clients = ClientManager::with_policy(
+            node_arc.clone(),
+            Arc::new(HistoryServerPolicy::new(config.history_window)),
+        );
+        if !config.read_only {
+            tokio::spawn(answer_status_commands(clients.clone(), quota.clone()));
+        }
 
+        let metrics = Metrics::new();
         {
             let mut node_lock = node_arc.blocking_lock();
-            node_lock.set_event_handler(Arc::new(dispatcher));
+            node_lock.set_event_handler(metrics.observe(clients.event_handler()));
         }
 
         Self {
             tox: tox_shared,
+            node: node_arc,
             bridge,
             _clients: clients,
             _storage_path: storage_path,
-            savedata_path,
+            profile,
             shutdown,
             dirty,
+            quota,
+            metrics,
+            config_watcher: args.config.clone().map(ConfigWatcher::new),
+            args,
+            log_guard,
+        }
+    }
+
+    /// Applies the quotas and log levels of the config file if it changed.
+    fn reload_config(&mut self) {
+        let Some(watcher) = &mut self.config_watcher else {
+            return;
+        };
+        if !watcher.changed() {
+            return;
+        }
+        info!("Reloading {}", watcher.path().display());
+        let config = match Config::load(&self.args) {
+            Ok(config) => config,
+            Err(e) => {
+                error!("Keeping the previous config: {}", e);
+                return;
+            }
+        };
+        *self.quota.lock() = config.quota();
+        if let Err(e) = self.log_guard.reload(&config.logging) {
+            error!("Keeping the previous log levels: {}", e);
         }
     }
 
     fn save(&mut self) {
-        if let Some(path) = &self.savedata_path {
-            let data = self.tox.lock().savedata();
-            if let Err(e) = fs::write(path, data) {
+        if let Some(profile) = &self.profile {
+            if let Err(e) = profile.save(&self.tox.lock()) {
                 error!("Failed to save savedata: {}", e);
             } else {
-                info!("Savedata saved to {:?}", path);
+                info!("Savedata saved to {:?}", profile.path());
                 self.dirty = false;
             }
         }
     }
 
+    /// Prunes old content if the store is over quota.
+    async fn enforce_quota(&self) {
+        let node = self.node.lock().await;
+        let quota = *self.quota.lock();
+        match quota::enforce(&node.store, &quota) {
+            Ok(0) => {}
+            Ok(reclaimed) => info!("Quota enforcement reclaimed {} bytes", reclaimed),
+            Err(e) => error!("Quota enforcement failed: {}", e),
+        }
+    }
+
     async fn run(&mut self) {
         let mut last_save = Instant::now();
+        let mut last_quota_check = Instant::now();
+        let mut last_config_check = Instant::now();
+        self.enforce_quota().await;
         loop {
             if self.shutdown.load(Ordering::SeqCst) {
                 info!("Graceful shutdown...");
+                let deadline = Instant::now() + SHUTDOWN_GRACE;
+                let summary = self.bridge.lock().await.shutdown(deadline).await;
+                info!("Merkle-Tox node shut down: {:?}", summary);
                 if self.dirty {
                     self.save();
                 }
@@ -197,9 +254,20 @@ impl VaultBot {
                 last_save = Instant::now();
             }
 
+            if last_config_check.elapsed() > Duration::from_secs(5) {
+                self.reload_config();
+                last_config_check = Instant::now();
+            }
+
+            if last_quota_check.elapsed() > QUOTA_CHECK_INTERVAL {
+                self.enforce_quota().await;
+                last_quota_check = Instant::now();
+            }
+
             if let Ok(events) = self.tox.lock().events() {
                 // Auto-accept friends
                 for event in &events {
+                    self.metrics.record_tox_event(&event);
                     if self
                         .bridge
                         .lock()
@@ -326,23 +394,31 @@ fn bootstrap_network(tox: &Tox, nodes: &[Node]) {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt::init();
     let args = Args::parse();
+    if let Some(path) = &args.write_default_config {
+        bot_common::config::write_default(path, &Config::default())?;
+        return Ok(());
+    }
+    let config = Config::load(&args)?;
+    let log_guard = logging::init("vaultbot", &config.logging)?;
 
-    let storage_path = PathBuf::from(&args.storage);
-    fs::create_dir_all(&storage_path)?;
+    fs::create_dir_all(&config.storage)?;
 
-    let mut loaded = false;
     let mut opts = Options::new()?;
-    let savedata_path = args.savedata.map(PathBuf::from);
-    if let Some(path) = &savedata_path
-        && path.exists()
-    {
-        let data = fs::read(path)?;
-        opts.set_savedata_type(ToxSavedataType::TOX_SAVEDATA_TYPE_TOX_SAVE);
-        let _ = opts.set_savedata_data(&data);
-        loaded = true;
+    match config.proxy.kind {
+        ProxyType::None => {}
+        ProxyType::Socks5 => opts.set_proxy_type(ToxProxyType::TOX_PROXY_TYPE_SOCKS5),
+        ProxyType::Http => opts.set_proxy_type(ToxProxyType::TOX_PROXY_TYPE_HTTP),
+    }
+    if config.proxy.kind != ProxyType::None {
+        opts.set_proxy_host(&config.proxy.host)?;
+        opts.set_proxy_port(config.proxy.port);
     }
+    let profile = config.savedata.clone().map(Profile::new);
+    let loaded = match &profile {
+        Some(profile) => profile.apply(&mut opts)?,
+        None => false,
+    };
 
     let tox = Tox::new(opts)?;
 
@@ -355,13 +431,41 @@ async fn main() -> Result<(), Box<dyn Error>> {
         }
     });
 
-    let mut bot = VaultBot::new(tox, storage_path.clone(), savedata_path, shutdown, !loaded);
+    let mut bot = VaultBot::new(tox, args, &config, log_guard, profile, shutdown, !loaded);
+
+    if let Some(addr) = config.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        info!("Serving metrics on http://{}/metrics", addr);
+        tokio::spawn(metrics::serve(
+            listener,
+            bot.metrics.clone(),
+            bot.node.clone(),
+        ));
+    }
 
     let address = bot.tox.lock().address();
     println!("VaultBot started! Tox ID: {:?}", address);
 
     // Bootstrap
-    let nodes = setup_nodes(&storage_path).await;
+    let nodes = if config.bootstrap_nodes.is_empty() {
+        setup_nodes(&config.storage).await
+    } else {
+        config
+            .bootstrap_nodes
+            .iter()
+            .map(|node| Node {
+                ipv4: node.host.clone(),
+                ipv6: String::new(),
+                port: node.port,
+                tcp_ports: None,
+                public_key: node.public_key.clone(),
+                status_udp: true,
+                status_tcp: true,
+                maintainer: String::new(),
+                location: String::new(),
+            })
+            .collect()
+    };
     bootstrap_network(&bot.tox.lock(), &nodes);
 
     bot.run().await;
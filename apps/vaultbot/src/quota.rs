@@ -0,0 +1,165 @@
+//! Disk quota enforcement.
+//!
+//! When the store grows past its configured limits, the oldest message
+//! content is pruned first. Admin-track nodes, control nodes (including
+//! anchors), key material and the current heads are never touched, so the
+//! conversation state can always be rebuilt from what remains. Pruned nodes
+//! keep their headers and stay known for reconciliation; their payloads are
+//! reclaimed by the store's garbage collector.
+
+use merkle_tox_core::dag::{ConversationId, NodeHash, NodeType};
+use merkle_tox_core::engine::retention::is_expirable;
+use merkle_tox_core::error::MerkleToxResult;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_fs::{FsStore, GcPolicy};
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// How often the bot checks the quota.
+pub const QUOTA_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of nodes pruned before the store is garbage collected and
+/// measured again.
+const PRUNE_BATCH: usize = 256;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Storage limits. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuotaConfig {
+    /// Limit for the whole store, blobs included.
+    pub total_bytes: Option<u64>,
+    /// Limit for each conversation's nodes, blobs excluded.
+    pub conversation_bytes: Option<u64>,
+}
+
+impl QuotaConfig {
+    pub fn from_mib(total_mib: Option<u64>, conversation_mib: Option<u64>) -> Self {
+        Self {
+            total_bytes: total_mib.map(|m| m * MIB),
+            conversation_bytes: conversation_mib.map(|m| m * MIB),
+        }
+    }
+}
+
+/// Disk usage of one conversation.
+#[derive(Debug, Clone)]
+pub struct ConversationUsage {
+    pub conversation_id: ConversationId,
+    pub bytes: u64,
+    pub verified: usize,
+    pub speculative: usize,
+}
+
+/// Usage of every conversation in the store, largest first.
+pub fn usage(store: &FsStore) -> Vec<ConversationUsage> {
+    let mut usage: Vec<_> = store
+        .conversations()
+        .into_iter()
+        .map(|conversation_id| {
+            let (verified, speculative) = store.get_node_counts(&conversation_id);
+            ConversationUsage {
+                conversation_id,
+                bytes: store.conversation_size_bytes(&conversation_id),
+                verified,
+                speculative,
+            }
+        })
+        .collect();
+    usage.sort_by_key(|u| std::cmp::Reverse(u.bytes));
+    usage
+}
+
+/// Prunes the oldest content of `conversation_id` until at least `excess`
+/// bytes were reclaimed or nothing prunable is left. Returns the number of
+/// bytes reclaimed.
+fn shrink(store: &FsStore, conversation_id: &ConversationId, excess: u64) -> MerkleToxResult<u64> {
+    let heads: HashSet<NodeHash> = store.get_heads(conversation_id).into_iter().collect();
+    let start = store.conversation_size_bytes(conversation_id);
+    let mut size = start;
+    while start.saturating_sub(size) < excess {
+        // Ordered by rank, so the oldest content comes first. Pruned nodes
+        // are no longer returned.
+        let batch: Vec<NodeHash> = store
+            .get_verified_nodes_by_type(conversation_id, NodeType::Content)?
+            .into_iter()
+            .filter(|n| is_expirable(&n.content))
+            .map(|n| n.hash())
+            .filter(|h| !heads.contains(h))
+            .take(PRUNE_BATCH)
+            .collect();
+        if batch.is_empty() {
+            break;
+        }
+        for hash in &batch {
+            store.prune_node(conversation_id, hash)?;
+        }
+        store.gc(conversation_id, &GcPolicy::default())?;
+        size = store.conversation_size_bytes(conversation_id);
+    }
+    Ok(start.saturating_sub(size))
+}
+
+/// Brings the store back under `config`. Conversations over their own cap
+/// are shrunk first; if the store as a whole is still over quota, the
+/// largest conversations give up their oldest content. Returns the number
+/// of bytes reclaimed.
+pub fn enforce(store: &FsStore, config: &QuotaConfig) -> MerkleToxResult<u64> {
+    let mut reclaimed = 0;
+    if let Some(cap) = config.conversation_bytes {
+        for conversation in usage(store) {
+            if conversation.bytes > cap {
+                reclaimed += shrink(
+                    store,
+                    &conversation.conversation_id,
+                    conversation.bytes - cap,
+                )?;
+            }
+        }
+    }
+    if let Some(quota) = config.total_bytes {
+        let mut total = store.size_bytes();
+        for conversation in usage(store) {
+            if total <= quota {
+                break;
+            }
+            let freed = shrink(store, &conversation.conversation_id, total - quota)?;
+            total = total.saturating_sub(freed);
+            reclaimed += freed;
+        }
+    }
+    Ok(reclaimed)
+}
+
+fn format_mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / MIB as f64)
+}
+
+fn format_limit(limit: Option<u64>) -> String {
+    limit.map_or_else(|| "unlimited".to_string(), format_mib)
+}
+
+/// Reply to `!vault status` in `conversation_id`.
+pub fn status_report(
+    store: &FsStore,
+    config: &QuotaConfig,
+    conversation_id: &ConversationId,
+) -> String {
+    let usage = usage(store);
+    let mut report = format!(
+        "Vault: {} of {} used by {} conversation(s).",
+        format_mib(store.size_bytes()),
+        format_limit(config.total_bytes),
+        usage.len(),
+    );
+    if let Some(this) = usage.iter().find(|u| u.conversation_id == *conversation_id) {
+        report.push_str(&format!(
+            " This conversation: {} of {}, {} node(s) stored, {} awaiting verification.",
+            format_mib(this.bytes),
+            format_limit(config.conversation_bytes),
+            this.verified,
+            this.speculative,
+        ));
+    }
+    report
+}
@@ -0,0 +1,159 @@
+use merkle_tox_core::Transport;
+use merkle_tox_core::cas::CHUNK_SIZE;
+use merkle_tox_core::dag::NodeHash;
+use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use std::future::Future;
+use std::io::{self, Read, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio::sync::Mutex;
+
+type ChunkFetch = Pin<Box<dyn Future<Output = io::Result<Vec<u8>>> + Send>>;
+
+/// Streams a stored blob through [`AsyncRead`] and [`AsyncSeek`]. Chunks are
+/// fetched one at a time on the blocking thread pool, holding the node lock
+/// only while a chunk is read.
+pub struct BlobStream<T: Transport + 'static, S: NodeStore + BlobStore + 'static> {
+    node: Arc<Mutex<MerkleToxNode<T, S>>>,
+    hash: NodeHash,
+    size: u64,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+    /// Chunk being fetched and its offset.
+    fetch: Option<(u64, ChunkFetch)>,
+}
+
+impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> BlobStream<T, S> {
+    pub(crate) fn new(node: Arc<Mutex<MerkleToxNode<T, S>>>, hash: NodeHash, size: u64) -> Self {
+        Self {
+            node,
+            hash,
+            size,
+            pos: 0,
+            buf: Vec::new(),
+            buf_start: 0,
+            fetch: None,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn start_fetch(&self, offset: u64) -> ChunkFetch {
+        let node = self.node.clone();
+        let hash = self.hash;
+        let length = CHUNK_SIZE.min(self.size - offset) as u32;
+        Box::pin(async move {
+            let node = node.lock_owned().await;
+            tokio::task::spawn_blocking(move || node.store.get_chunk(&hash, offset, length))
+                .await
+                .map_err(io::Error::other)?
+                .map_err(|e| match e {
+                    MerkleToxError::Io(e) => e,
+                    e => io::Error::other(e.to_string()),
+                })
+        })
+    }
+}
+
+impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> AsyncRead for BlobStream<T, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        out: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.pos >= this.size || out.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let buf_end = this.buf_start + this.buf.len() as u64;
+        if this.pos < this.buf_start || this.pos >= buf_end {
+            let start = this.pos - this.pos % CHUNK_SIZE;
+            // A seek may have moved past the chunk being fetched.
+            if this
+                .fetch
+                .as_ref()
+                .is_none_or(|(offset, _)| *offset != start)
+            {
+                this.fetch = Some((start, this.start_fetch(start)));
+            }
+            let (_, fetch) = this.fetch.as_mut().expect("fetch started");
+            let chunk = ready!(fetch.as_mut().poll(cx));
+            this.fetch = None;
+            this.buf = chunk?;
+            this.buf_start = start;
+            if this.pos >= start + this.buf.len() as u64 {
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+        }
+        let off = (this.pos - this.buf_start) as usize;
+        let n = out.remaining().min(this.buf.len() - off);
+        out.put_slice(&this.buf[off..off + n]);
+        this.pos += n as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> AsyncSeek for BlobStream<T, S> {
+    fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(d) => this.size.checked_add_signed(d),
+            SeekFrom::Current(d) => this.pos.checked_add_signed(d),
+        };
+        this.pos = new_pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset")
+        })?;
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Ok(self.pos))
+    }
+}
+
+/// Reads `reader` once, hashing it and staging its chunks in the store.
+/// Blocks, and locks the node for one chunk at a time. Returns the blob hash
+/// and size.
+pub(crate) fn stage_blob<T: Transport + 'static, S: NodeStore + BlobStore + 'static>(
+    node: &Mutex<MerkleToxNode<T, S>>,
+    staging_id: u64,
+    mut reader: impl Read,
+) -> MerkleToxResult<(NodeHash, u64)> {
+    let mut buf = vec![0u8; CHUNK_SIZE as usize];
+    let mut hasher = blake3::Hasher::new();
+    let mut size = 0;
+    loop {
+        let n = read_chunk(&mut reader, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        node.blocking_lock()
+            .store
+            .put_staged_chunk(staging_id, size, &buf[..n])?;
+        size += n as u64;
+    }
+    Ok((NodeHash::from(*hasher.finalize().as_bytes()), size))
+}
+
+/// Fills `buf` from `reader`, returning fewer bytes only at end of input.
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -1,20 +1,37 @@
+pub mod blob;
+pub mod manager;
+pub mod markdown;
 pub mod policy;
 pub mod state;
 
+pub use crate::blob::BlobStream;
+pub use crate::manager::MerkleToxClientManager;
+
 use crate::policy::{DefaultPolicy, PolicyHandler};
-use crate::state::{ChatState, MemberInfo, MemberRole};
+use crate::state::{
+    ChatMessage, ChatState, MemberRole, StickerPack, count_unread, mark_read, materialize,
+};
 use ed25519_dalek::SigningKey;
 use merkle_tox_core::dag::{
-    Content, ControlAction, ConversationId, EmojiSource, InviteAction, LogicalIdentityPk,
-    MerkleNode, NodeHash, NodeType, Permissions, PhysicalDevicePk,
+    Content, ControlAction, ConversationId, EmojiSource, HistoryVisibility, InviteAction,
+    LogicalIdentityPk, MerkleNode, NodeHash, NodeType, Permissions, PhysicalDevicePk, Profile,
+    Sticker, ValidationError, validate_sticker_pack,
 };
 use merkle_tox_core::engine::Effect;
+use merkle_tox_core::engine::config::RotationPolicy;
+use merkle_tox_core::engine::recovery::CompromiseRecovery;
+use merkle_tox_core::engine::vault::KEY_VAULT_MIME_TYPE;
+use merkle_tox_core::entities::TextEntities;
 use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
-use merkle_tox_core::identity::sign_delegation;
+use merkle_tox_core::fingerprint::SafetyNumber;
+use merkle_tox_core::identity::{IdentityError, sign_delegation};
 use merkle_tox_core::node::MerkleToxNode;
-use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::sync::{BlobStore, NodeStore, StateSnapshot, SyncPolicy, SyncRange};
 use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport};
+use std::io::Read;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
 use tokio::sync::{Mutex, RwLock, mpsc};
 use tracing::{debug, error, info};
 
@@ -29,6 +46,24 @@ impl NodeEventHandler for ClientEventBridge {
     }
 }
 
+/// Staging ids of blobs being stored, unique within the process.
+static NEXT_STAGING_ID: AtomicU64 = AtomicU64::new(0);
+
+/// First delay before pushing undelivered messages again, doubled on each
+/// attempt up to [`QUEUE_RETRY_MAX_MS`].
+pub const QUEUE_RETRY_BASE_MS: i64 = 2_000;
+pub const QUEUE_RETRY_MAX_MS: i64 = 300_000;
+/// How often [`MerkleToxClient::start`] and [`MerkleToxClientManager`] call
+/// [`MerkleToxClient::retry_queued`].
+pub const QUEUE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Backoff for pushing undelivered messages.
+#[derive(Debug, Default)]
+struct QueueRetry {
+    attempts: u32,
+    next_retry_ms: i64,
+}
+
 /// A high-level client for Merkle-Tox conversations.
 /// Manages the materialized view and automated orchestration policies.
 pub struct MerkleToxClient<T: Transport + 'static, S: NodeStore + BlobStore + 'static> {
@@ -36,6 +71,9 @@ pub struct MerkleToxClient<T: Transport + 'static, S: NodeStore + BlobStore + 's
     policy: Arc<dyn PolicyHandler>,
     state: Arc<RwLock<ChatState>>,
     conversation_id: ConversationId,
+    /// Verified nodes applied since the last state snapshot.
+    nodes_since_snapshot: AtomicUsize,
+    queue_retry: Mutex<QueueRetry>,
 }
 
 impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient<T, S> {
@@ -50,6 +88,8 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
             policy: Arc::new(DefaultPolicy),
             state,
             conversation_id,
+            nodes_since_snapshot: AtomicUsize::new(0),
+            queue_retry: Mutex::default(),
         }
     }
 
@@ -68,6 +108,8 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
             policy,
             state,
             conversation_id,
+            nodes_since_snapshot: AtomicUsize::new(0),
+            queue_retry: Mutex::default(),
         }
     }
 
@@ -89,6 +131,19 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
             }
         });
 
+        let client = Arc::downgrade(&self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(QUEUE_POLL_INTERVAL).await;
+                let Some(client) = client.upgrade() else {
+                    break;
+                };
+                if let Err(e) = client.retry_queued().await {
+                    error!("Failed to push queued messages: {}", e);
+                }
+            }
+        });
+
         // Initial state refresh from the Admin track.
         if let Err(e) = self.refresh_state().await {
             error!("Failed to refresh initial state: {}", e);
@@ -114,6 +169,35 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
                     self.orchestrate_actions(&node).await?;
                 }
             }
+            NodeEvent::NodeExpired {
+                conversation_id,
+                hash,
+            } => {
+                if conversation_id == self.conversation_id {
+                    self.state.write().await.messages.retain(|m| m.hash != hash);
+                    // The snapshot must not bring the message back.
+                    if self.policy.snapshot_interval().is_some() {
+                        self.save_snapshot().await?;
+                    }
+                }
+            }
+            NodeEvent::NodeDelivered {
+                conversation_id,
+                hash,
+                device_pk,
+            } => {
+                if conversation_id == self.conversation_id
+                    && let Some(msg) = self
+                        .state
+                        .write()
+                        .await
+                        .messages
+                        .iter_mut()
+                        .find(|m| m.hash == hash)
+                {
+                    msg.delivered_to.insert(device_pk);
+                }
+            }
             NodeEvent::PeerHandshakeComplete { peer_pk } => {
                 debug!("Checking auto-authorize for peer {:?}", peer_pk);
                 self.check_auto_authorize(&peer_pk).await?;
@@ -125,125 +209,33 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
     }
 
     async fn apply_node_to_state(&self, hash: &NodeHash, node: &MerkleNode) -> MerkleToxResult<()> {
-        let mut state = self.state.write().await;
-        self.apply_node_internal(&mut state, hash, node);
-        Ok(())
-    }
-
-    fn apply_node_internal(&self, state: &mut ChatState, hash: &NodeHash, node: &MerkleNode) {
-        // Update heads and rank
-        state.heads.retain(|h| !node.parents.contains(h));
-        if !state.heads.contains(hash) {
-            state.heads.push(*hash);
+        {
+            let node_lock = self.node.lock().await;
+            let mut state = self.state.write().await;
+            materialize(&mut state, hash, node);
+            let self_pk = node_lock.engine.self_logical_pk;
+            mark_read(&mut state, &node_lock.store, &self_pk, node);
+            count_unread(&mut state, &self_pk, node);
+            state.verified_nodes += 1;
         }
-        state.max_verified_rank = state.max_verified_rank.max(node.topological_rank);
-
-        match &node.content {
-            Content::Text(_)
-            | Content::Blob { .. }
-            | Content::Location { .. }
-            | Content::Custom { .. } => {
-                state.messages.push(crate::state::ChatMessage {
-                    hash: *hash,
-                    author_pk: node.author_pk,
-                    timestamp: node.network_timestamp,
-                    content: node.content.clone(),
-                    reactions: Default::default(),
-                    is_redacted: false,
-                });
-            }
-            Content::Reaction { target_hash, emoji } => {
-                if let Some(msg) = state.messages.iter_mut().find(|m| m.hash == *target_hash) {
-                    let emoji_str = match emoji {
-                        merkle_tox_core::dag::EmojiSource::Unicode(s) => s.clone(),
-                        merkle_tox_core::dag::EmojiSource::Custom { shortcode, .. } => {
-                            shortcode.clone()
-                        }
-                    };
-                    msg.reactions
-                        .entry(emoji_str)
-                        .or_default()
-                        .insert(node.author_pk);
-                }
-            }
-            Content::Redaction { target_hash, .. } => {
-                if let Some(msg) = state.messages.iter_mut().find(|m| m.hash == *target_hash) {
-                    msg.is_redacted = true;
-                }
-            }
-            Content::Control(action) => match action {
-                ControlAction::SetTitle(title) => {
-                    state.title = title.clone();
-                }
-                ControlAction::SetTopic(topic) => {
-                    state.topic = topic.clone();
-                }
-                ControlAction::AuthorizeDevice { cert } => {
-                    let member =
-                        state
-                            .members
-                            .entry(node.author_pk)
-                            .or_insert_with(|| MemberInfo {
-                                public_key: node.author_pk,
-                                role: MemberRole::Member,
-                                joined_at: node.network_timestamp,
-                                devices: Default::default(),
-                            });
-                    member.devices.insert(cert.device_pk);
-                    state.authorized_devices.insert(cert.device_pk);
-                }
-                ControlAction::RevokeDevice {
-                    target_device_pk, ..
-                } => {
-                    state.authorized_devices.remove(target_device_pk);
-                    for member in state.members.values_mut() {
-                        member.devices.remove(target_device_pk);
-                    }
-                }
-                ControlAction::Invite(invite) => {
-                    state
-                        .members
-                        .entry(invite.invitee_pk)
-                        .or_insert_with(|| MemberInfo {
-                            public_key: invite.invitee_pk,
-                            role: if invite.role == 1 {
-                                MemberRole::Admin
-                            } else {
-                                MemberRole::Member
-                            },
-                            joined_at: node.network_timestamp,
-                            devices: Default::default(),
-                        });
-                }
-                ControlAction::Announcement {
-                    pre_keys,
-                    last_resort_key,
-                } => {
-                    state
-                        .announcements
-                        .insert(node.sender_pk, (pre_keys.clone(), last_resort_key.clone()));
-                }
-                ControlAction::HandshakePulse => {
-                    // HandshakePulse is ephemeral/action-oriented,
-                    // usually doesn't need to be in materialized state.
-                }
-                _ => {}
-            },
-            Content::HistoryExport { .. }
-            | Content::LegacyBridge { .. }
-            | Content::SenderKeyDistribution { .. } => {
-                // Not for UI state.
-            }
-            _ => {}
+        if let Some(interval) = self.policy.snapshot_interval()
+            && self.nodes_since_snapshot.fetch_add(1, Ordering::Relaxed) + 1 >= interval
+        {
+            self.save_snapshot().await?;
         }
+        Ok(())
     }
 
-    async fn orchestrate_actions(&self, node: &MerkleNode) -> MerkleToxResult<()> {
+    pub(crate) async fn orchestrate_actions(&self, node: &MerkleNode) -> MerkleToxResult<()> {
         // Auto-Key Exchange and Automated Onboarding logic
         let mut node_lock = self.node.lock().await;
         let now = node_lock.engine.clock.network_time_ms();
         let self_pk = node_lock.engine.self_pk;
         let cid = self.conversation_id;
+        // Read-only observers only serve history.
+        let read_only = node_lock.engine.config.read_only;
+        // Public conversations exchange no keys.
+        let public = node_lock.engine.is_public(cid);
 
         match &node.content {
             Content::Control(ControlAction::AuthorizeDevice { .. })
@@ -251,17 +243,20 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
             | Content::Control(ControlAction::Invite(_))
             | Content::Control(ControlAction::Leave(_)) => {
                 let ctx = merkle_tox_core::identity::CausalContext::global();
-                if node_lock.engine.identity_manager.is_admin(
-                    &ctx,
-                    cid,
-                    &self_pk,
-                    &self_pk.to_logical(),
-                    now,
-                    u64::MAX,
-                ) && (self.policy.should_rotate_keys(&*self.state.read().await)
-                    || node_lock
-                        .engine
-                        .check_rotation_triggers(self.conversation_id))
+                if !read_only
+                    && !public
+                    && node_lock.engine.identity_manager.is_admin(
+                        &ctx,
+                        cid,
+                        &self_pk,
+                        &self_pk.to_logical(),
+                        now,
+                        u64::MAX,
+                    )
+                    && (self.policy.should_rotate_keys(&*self.state.read().await)
+                        || node_lock
+                            .engine
+                            .check_rotation_triggers(self.conversation_id))
                 {
                     let node_ref = &mut *node_lock;
                     let effects = node_ref
@@ -276,19 +271,79 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
                 }
             }
             Content::Control(ControlAction::Announcement { pre_keys, .. }) => {
+                // Serve history to a device we have not synced with before.
+                if node.sender_pk != self_pk
+                    && let Some(window) = self.policy.history_push_window(node.sender_pk.as_bytes())
+                {
+                    let node_ref = &mut *node_lock;
+                    let effects =
+                        node_ref
+                            .engine
+                            .push_history(node.sender_pk, cid, window, &node_ref.store);
+                    if !effects.is_empty() {
+                        info!("Pushing history to new device {:?}", node.sender_pk);
+                    }
+                    let now_inst = node_ref.time_provider.now_instant();
+                    let now_ms = node_ref.time_provider.now_system_ms() as u64;
+                    let mut dummy_wakeup = now_inst;
+                    for effect in effects {
+                        node_ref.process_effect(effect, now_inst, now_ms, &mut dummy_wakeup)?;
+                    }
+                }
+
                 // A peer has published their ephemeral keys.
                 // If we are an admin and have the conversation key, we should share it with them.
                 let ctx = merkle_tox_core::identity::CausalContext::global();
-                if node_lock.engine.identity_manager.is_admin(
-                    &ctx,
-                    cid,
-                    &self_pk,
-                    &self_pk.to_logical(),
-                    now,
-                    u64::MAX,
-                ) {
+                if !read_only
+                    && node_lock.engine.identity_manager.is_admin(
+                        &ctx,
+                        cid,
+                        &self_pk,
+                        &self_pk.to_logical(),
+                        now,
+                        u64::MAX,
+                    )
+                {
+                    // A joiner proving one of our invites, or the
+                    // conversation's join proof-of-work, is admitted first.
+                    let is_member = node_lock
+                        .engine
+                        .identity_manager
+                        .list_members(cid)
+                        .iter()
+                        .any(|(pk, _, _)| *pk == node.author_pk);
+                    let role = if is_member {
+                        None
+                    } else if let Some(role) = node_lock.engine.check_invite_proof(cid, node) {
+                        info!("Admitting {:?} via invite link", node.author_pk);
+                        Some(role)
+                    } else if node_lock.engine.check_join_proof(cid, node) {
+                        info!("Admitting {:?} via join proof-of-work", node.author_pk);
+                        Some(0)
+                    } else {
+                        None
+                    };
+                    if let Some(role) = role {
+                        let node_ref = &mut *node_lock;
+                        let effects = node_ref.engine.author_node(
+                            cid,
+                            Content::Control(ControlAction::Invite(InviteAction {
+                                invitee_pk: node.author_pk,
+                                role,
+                            })),
+                            Vec::new(),
+                            &node_ref.store,
+                        )?;
+                        let now_inst = node_ref.time_provider.now_instant();
+                        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+                        let mut dummy_wakeup = now_inst;
+                        for effect in effects {
+                            node_ref.process_effect(effect, now_inst, now_ms, &mut dummy_wakeup)?;
+                        }
+                    }
+
                     // Use the first valid pre-key; K_conv_0 is derived internally per spec §2.C.
-                    if let Some(spk) = pre_keys.iter().find(|k| k.expires_at > now) {
+                    if !public && let Some(spk) = pre_keys.iter().find(|k| k.expires_at > now) {
                         info!(
                             "Automatically sharing conversation key with {:?} via X3DH",
                             node.sender_pk
@@ -310,9 +365,10 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
                 }
             }
             Content::Control(ControlAction::HandshakePulse) => {
-                if self
-                    .policy
-                    .should_respond_to_pulse(node.sender_pk.as_bytes())
+                if !read_only
+                    && self
+                        .policy
+                        .should_respond_to_pulse(node.sender_pk.as_bytes())
                 {
                     info!(
                         "Responding to HandshakePulse from {:?} with fresh Announcement",
@@ -334,14 +390,19 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
     }
 
     async fn check_auto_authorize(&self, peer_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
-        let self_pk = {
+        let (self_pk, peer_read_only) = {
             let node_lock = self.node.lock().await;
-            node_lock.engine.self_pk
+            (
+                node_lock.engine.self_pk,
+                node_lock.engine.peer_is_read_only(peer_pk),
+            )
         };
 
-        if self
-            .policy
-            .should_authorize(self_pk.as_bytes(), peer_pk.as_bytes())
+        // Observers cannot use an authorization.
+        if !peer_read_only
+            && self
+                .policy
+                .should_authorize(self_pk.as_bytes(), peer_pk.as_bytes())
         {
             info!("Policy allows auto-authorization of {:?}", peer_pk);
             // Check if we are admin to perform authorization
@@ -361,6 +422,191 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
         self.author_node(Content::Text(text), Vec::new()).await
     }
 
+    /// Appends a text message with mentions, URLs and formatting to the
+    /// history. Fails if the entities are not valid for the text (see
+    /// [`TextEntities::validate`]).
+    pub async fn send_message_with_entities(
+        &self,
+        text: String,
+        entities: TextEntities,
+    ) -> MerkleToxResult<NodeHash> {
+        entities.validate(&text)?;
+        self.author_node(Content::Text(text), entities.to_metadata())
+            .await
+    }
+
+    /// Marks all messages of the conversation as read.
+    pub async fn clear_unread(&self) {
+        let mut state = self.state.write().await;
+        state.unread_messages = 0;
+        state.unread_mentions = 0;
+    }
+
+    /// Appends a text message to the history, also without connected
+    /// peers. Until a device acknowledges it, the message is listed by
+    /// [`pending_messages`](Self::pending_messages), can still be changed
+    /// with [`edit_queued`](Self::edit_queued) and
+    /// [`delete_queued`](Self::delete_queued), and is pushed to the
+    /// conversation's peers again by [`retry_queued`](Self::retry_queued).
+    pub async fn queue_message(&self, text: String) -> MerkleToxResult<NodeHash> {
+        let hash = self.send_message(text).await?;
+        let now_ms = self.node.lock().await.time_provider.now_system_ms() as i64;
+        *self.queue_retry.lock().await = QueueRetry {
+            attempts: 0,
+            next_retry_ms: now_ms + QUEUE_RETRY_BASE_MS,
+        };
+        Ok(hash)
+    }
+
+    /// Messages sent by this device that no device has acknowledged yet,
+    /// oldest first.
+    pub async fn pending_messages(&self) -> Vec<ChatMessage> {
+        let node_lock = self.node.lock().await;
+        let state = self.state.read().await;
+        state
+            .messages
+            .iter()
+            .filter(|m| !m.is_redacted && m.delivered_to.is_empty())
+            .filter(|m| {
+                node_lock
+                    .store
+                    .get_node(&m.hash)
+                    .is_some_and(|n| n.sender_pk == node_lock.engine.self_pk)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces the text of a pending message.
+    pub async fn edit_queued(&self, hash: NodeHash, text: String) -> MerkleToxResult<NodeHash> {
+        self.check_pending(&hash).await?;
+        self.author_node(
+            Content::Edit {
+                target_hash: hash,
+                new_text: text,
+            },
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Redacts a pending message.
+    pub async fn delete_queued(&self, hash: NodeHash) -> MerkleToxResult<NodeHash> {
+        self.check_pending(&hash).await?;
+        self.author_node(
+            Content::Redaction {
+                target_hash: hash,
+                reason: String::new(),
+            },
+            Vec::new(),
+        )
+        .await
+    }
+
+    async fn check_pending(&self, hash: &NodeHash) -> MerkleToxResult<()> {
+        if self
+            .pending_messages()
+            .await
+            .iter()
+            .any(|m| m.hash == *hash)
+        {
+            Ok(())
+        } else {
+            Err(MerkleToxError::Other(
+                "Message is not pending delivery".to_string(),
+            ))
+        }
+    }
+
+    /// Pushes the pending messages to the conversation's peers again, if
+    /// the backoff allows it. Returns the number of nodes sent.
+    pub async fn retry_queued(&self) -> MerkleToxResult<usize> {
+        let pending: Vec<NodeHash> = self
+            .pending_messages()
+            .await
+            .iter()
+            .map(|m| m.hash)
+            .collect();
+        let mut retry = self.queue_retry.lock().await;
+        if pending.is_empty() {
+            *retry = QueueRetry::default();
+            return Ok(0);
+        }
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        if (now_ms as i64) < retry.next_retry_ms {
+            return Ok(0);
+        }
+        let effects = node_ref
+            .engine
+            .resend(self.conversation_id, &pending, &node_ref.store);
+        let sent = effects
+            .iter()
+            .filter(|e| matches!(e, Effect::SendPacket(..)))
+            .count();
+        let mut dummy_wakeup = now;
+        node_ref.process_effects(effects, now, now_ms, &mut dummy_wakeup)?;
+
+        let delay = (QUEUE_RETRY_BASE_MS << retry.attempts.min(16)).min(QUEUE_RETRY_MAX_MS);
+        retry.attempts += 1;
+        retry.next_retry_ms = now_ms as i64 + delay;
+        if sent > 0 {
+            debug!(
+                "Pushed {} undelivered node(s), next try in {}ms",
+                sent, delay
+            );
+        }
+        Ok(sent)
+    }
+
+    /// Schedules a text message for delivery once the network clock reaches
+    /// `deliver_at_ms`. The message stays local and is persisted in the store
+    /// until then; the node authors it from its poll loop.
+    pub async fn send_message_at(
+        &self,
+        text: String,
+        deliver_at_ms: i64,
+    ) -> MerkleToxResult<ScheduledHandle<T, S>> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let (id, effects) = node_ref.engine.schedule_message(
+            self.conversation_id,
+            Content::Text(text),
+            Vec::new(),
+            deliver_at_ms,
+        );
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        node_ref.process_effects(effects, now, now_ms, &mut dummy_wakeup)?;
+        Ok(ScheduledHandle {
+            id,
+            deliver_at_ms,
+            node: self.node.clone(),
+        })
+    }
+
+    /// Returns handles to the messages of this conversation that are still
+    /// waiting for delivery, including those scheduled before a restart.
+    pub async fn scheduled_messages(&self) -> Vec<ScheduledHandle<T, S>> {
+        let node_lock = self.node.lock().await;
+        let mut handles: Vec<_> = node_lock
+            .engine
+            .scheduled_messages
+            .values()
+            .filter(|m| m.conversation_id == self.conversation_id)
+            .map(|m| ScheduledHandle {
+                id: m.id,
+                deliver_at_ms: m.deliver_at_ms,
+                node: self.node.clone(),
+            })
+            .collect();
+        handles.sort_by_key(|h| (h.deliver_at_ms, h.id));
+        handles
+    }
+
     /// Reacts to a previous message with an emoji.
     pub async fn send_reaction(
         &self,
@@ -387,6 +633,115 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
         .await
     }
 
+    /// Starts a poll. Votes are accepted until [`close_poll`](Self::close_poll)
+    /// or, if given, the network time `closes_at` in ms.
+    pub async fn create_poll(
+        &self,
+        question: String,
+        options: Vec<String>,
+        multi_choice: bool,
+        closes_at: Option<i64>,
+    ) -> MerkleToxResult<NodeHash> {
+        self.author_node(
+            Content::Poll {
+                question,
+                options,
+                multi_choice,
+                closes_at,
+            },
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Votes for the options `choices` of a poll. Each identity votes once.
+    pub async fn vote(
+        &self,
+        poll_hash: NodeHash,
+        mut choices: Vec<u32>,
+    ) -> MerkleToxResult<NodeHash> {
+        choices.sort_unstable();
+        choices.dedup();
+        self.author_node(Content::PollVote { poll_hash, choices }, Vec::new())
+            .await
+    }
+
+    /// Closes a poll of this identity, or any poll as an admin.
+    pub async fn close_poll(&self, poll_hash: NodeHash) -> MerkleToxResult<NodeHash> {
+        self.author_node(Content::PollClose { poll_hash }, Vec::new())
+            .await
+    }
+
+    /// Installs a sticker pack in this conversation, replacing an earlier
+    /// pack of this identity with the same name. `stickers` holds the
+    /// shortcode, MIME type and image of each sticker.
+    pub async fn install_sticker_pack(
+        &self,
+        name: String,
+        stickers: Vec<(String, String, Vec<u8>)>,
+    ) -> MerkleToxResult<NodeHash> {
+        let mut pack: Vec<Sticker> = stickers
+            .iter()
+            .map(|(shortcode, mime_type, image)| Sticker {
+                shortcode: shortcode.clone(),
+                hash: NodeHash::from([0; 32]),
+                size: image.len() as u64,
+                mime_type: mime_type.clone(),
+            })
+            .collect();
+        // Check before storing images for a pack peers would reject.
+        validate_sticker_pack(&name, &pack)?;
+        for (sticker, (_, _, image)) in pack.iter_mut().zip(stickers) {
+            sticker.hash = self.store_blob(std::io::Cursor::new(image)).await?.0;
+        }
+        self.author_node(
+            Content::StickerPack {
+                name,
+                stickers: pack,
+            },
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Sticker packs installed in this conversation.
+    pub async fn sticker_packs(&self) -> Vec<StickerPack> {
+        self.state.read().await.sticker_packs.clone()
+    }
+
+    /// Publishes the profile of this identity in the conversation. The
+    /// avatar image is stored as a blob that peers fetch with the profile.
+    pub async fn set_profile(
+        &self,
+        display_name: String,
+        status: String,
+        avatar: Option<Vec<u8>>,
+    ) -> MerkleToxResult<NodeHash> {
+        let avatar = match avatar {
+            Some(image) => Some(self.store_blob(std::io::Cursor::new(image)).await?.0),
+            None => None,
+        };
+        self.author_node(
+            Content::Control(ControlAction::SetProfile(Profile {
+                display_name,
+                avatar,
+                status,
+            })),
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// The latest profile `identity_pk` published in this conversation.
+    pub async fn profile(&self, identity_pk: &LogicalIdentityPk) -> Option<Profile> {
+        self.state
+            .read()
+            .await
+            .members
+            .get(identity_pk)
+            .and_then(|m| m.profile.clone())
+    }
+
     /// Sends a geo-location.
     pub async fn send_location(
         &self,
@@ -417,6 +772,99 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
             .await
     }
 
+    /// Sets how long messages are kept before they expire. 0 disables
+    /// expiry. Requires ADMIN.
+    pub async fn set_retention(&self, max_age_ms: u64) -> MerkleToxResult<NodeHash> {
+        self.author_node(
+            Content::Control(ControlAction::SetRetention { max_age_ms }),
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Sets which history members may fetch from peers. Identities in
+    /// `history_access` may fetch all of it regardless. Requires ADMIN.
+    pub async fn set_history_visibility(
+        &self,
+        visibility: HistoryVisibility,
+        history_access: Vec<LogicalIdentityPk>,
+    ) -> MerkleToxResult<NodeHash> {
+        self.author_node(
+            Content::Control(ControlAction::SetHistoryVisibility {
+                visibility,
+                history_access,
+            }),
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Closes the conversation for good: no member can write to it
+    /// afterwards. Requires ADMIN.
+    pub async fn close_conversation(&self) -> MerkleToxResult<NodeHash> {
+        self.author_node(
+            Content::Control(ControlAction::CloseConversation),
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Sets the number of admins who must approve revocations, removals of
+    /// other members and closing the conversation. Requires ADMIN.
+    pub async fn set_admin_quorum(&self, threshold: u8) -> MerkleToxResult<NodeHash> {
+        self.author_node(
+            Content::Control(ControlAction::SetAdminQuorum { threshold }),
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Approves the pending admin action in node `hash`. Requires ADMIN.
+    pub async fn co_sign(&self, hash: NodeHash) -> MerkleToxResult<NodeHash> {
+        self.author_node(Content::Control(ControlAction::CoSign(hash)), Vec::new())
+            .await
+    }
+
+    /// Creates a channel named `name` in this conversation. Requires ADMIN.
+    /// The channel is a conversation of its own, with the members and
+    /// permissions of this one; returns its conversation id.
+    pub async fn create_channel(&self, name: String) -> MerkleToxResult<ConversationId> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let (channel_id, effects) =
+            node_ref
+                .engine
+                .create_channel(self.conversation_id, name, &node_ref.store)?;
+
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+        }
+        Ok(channel_id)
+    }
+
+    /// Safety number to compare with `identity_pk` out of band, bound to
+    /// this conversation.
+    pub async fn safety_number(&self, identity_pk: LogicalIdentityPk) -> SafetyNumber {
+        let self_pk = self.node.lock().await.engine.self_logical_pk;
+        SafetyNumber::for_conversation(&self.conversation_id, &self_pk, &identity_pk)
+    }
+
+    /// Publishes that the safety number of `identity_pk` was verified.
+    /// Requires ADMIN.
+    pub async fn verify_identity(
+        &self,
+        identity_pk: LogicalIdentityPk,
+    ) -> MerkleToxResult<NodeHash> {
+        self.author_node(
+            Content::Control(ControlAction::VerifiedIdentity(identity_pk)),
+            Vec::new(),
+        )
+        .await
+    }
+
     /// Invites a new member to the conversation.
     pub async fn invite(
         &self,
@@ -450,6 +898,34 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
         .await
     }
 
+    /// Declares a device compromised: revokes it and re-keys the
+    /// conversation for the remaining devices in one step. Needs admin
+    /// rights.
+    pub async fn recover_from_compromise(
+        &self,
+        device_pk: PhysicalDevicePk,
+    ) -> MerkleToxResult<CompromiseRecovery> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let effects = node_ref.engine.recover_from_compromise(
+            self.conversation_id,
+            device_pk,
+            &node_ref.store,
+        )?;
+
+        let mut recovery = None;
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            if let Effect::EmitEvent(NodeEvent::CompromiseRecovered(r)) = &effect {
+                recovery = Some(r.clone());
+            }
+            node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+        }
+        recovery.ok_or_else(|| MerkleToxError::Other("Compromise recovery incomplete".to_string()))
+    }
+
     /// Manually authorize a device.
     pub async fn authorize_device(
         &self,
@@ -533,34 +1009,54 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
         mime_type: String,
         data: Vec<u8>,
     ) -> MerkleToxResult<NodeHash> {
-        let blob_hash = NodeHash::from(*blake3::hash(&data).as_bytes());
-        let size = data.len() as u64;
+        self.send_blob_from_reader(name, mime_type, std::io::Cursor::new(data))
+            .await
+    }
 
-        {
-            let node_lock = self.node.lock().await;
-            let info = merkle_tox_core::cas::BlobInfo {
-                hash: blob_hash,
-                size,
-                bao_root: None, // Simplified: no outboard proof for small/medium blobs
-                status: merkle_tox_core::cas::BlobStatus::Available,
-                received_mask: None,
-                decryption_key: None,
-            };
-            node_lock.store.put_blob_info(info)?;
+    /// Sends a large binary asset read from `reader`. The data is read once,
+    /// on the blocking thread pool, and hashed and stored one chunk at a time,
+    /// so it never has to fit in memory.
+    pub async fn send_blob_from_reader<R: Read + Send + 'static>(
+        &self,
+        name: String,
+        mime_type: String,
+        reader: R,
+    ) -> MerkleToxResult<NodeHash> {
+        self.send_blob_inner(name, mime_type, reader, None).await
+    }
 
-            // Write chunks to store
-            let chunk_size = 64 * 1024;
-            for (i, chunk) in data.chunks(chunk_size).enumerate() {
-                node_lock.store.put_chunk(
-                    &self.conversation_id,
-                    &blob_hash,
-                    (i * chunk_size) as u64,
-                    chunk,
-                    None,
-                )?;
+    /// Sends a blob together with a small inline preview (e.g. an image
+    /// thumbnail) that recipients can show before the blob is downloaded.
+    /// The preview is limited to `MAX_THUMBNAIL_SIZE` bytes.
+    pub async fn send_blob_with_preview<R: Read + Send + 'static>(
+        &self,
+        name: String,
+        mime_type: String,
+        reader: R,
+        preview: Vec<u8>,
+    ) -> MerkleToxResult<NodeHash> {
+        self.send_blob_inner(name, mime_type, reader, Some(preview))
+            .await
+    }
+
+    async fn send_blob_inner<R: Read + Send + 'static>(
+        &self,
+        name: String,
+        mime_type: String,
+        reader: R,
+        preview: Option<Vec<u8>>,
+    ) -> MerkleToxResult<NodeHash> {
+        if let Some(preview) = &preview
+            && preview.len() > tox_proto::constants::MAX_THUMBNAIL_SIZE
+        {
+            // Fail before storing any chunks.
+            return Err(ValidationError::PreviewTooLarge {
+                actual: preview.len(),
+                max: tox_proto::constants::MAX_THUMBNAIL_SIZE,
             }
+            .into());
         }
-
+        let (blob_hash, size) = self.store_blob(reader).await?;
         self.author_node(
             Content::Blob {
                 hash: blob_hash,
@@ -568,12 +1064,219 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
                 mime_type,
                 size,
                 metadata: Vec::new(),
+                preview,
             },
             Vec::new(),
         )
         .await
     }
 
+    /// Sends a voice note. The audio is stored as a CAS blob; `waveform`
+    /// holds amplitude samples for rendering before it is downloaded.
+    pub async fn send_voice<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+        duration_ms: u32,
+        waveform: Vec<u8>,
+        codec: String,
+    ) -> MerkleToxResult<NodeHash> {
+        let (hash, size) = self.store_blob(reader).await?;
+        self.author_node(
+            Content::Voice {
+                hash,
+                size,
+                duration_ms,
+                waveform,
+                codec,
+            },
+            Vec::new(),
+        )
+        .await
+    }
+
+    /// Hashes and stores the data of `reader` as a blob referenced by this
+    /// conversation. Returns the blob hash and size.
+    async fn store_blob<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+    ) -> MerkleToxResult<(NodeHash, u64)> {
+        let staging_id = NEXT_STAGING_ID.fetch_add(1, Ordering::Relaxed);
+        let node = self.node.clone();
+        let staged =
+            tokio::task::spawn_blocking(move || blob::stage_blob(&node, staging_id, reader))
+                .await
+                .map_err(|e| MerkleToxError::Io(std::io::Error::other(e)))
+                .and_then(|r| r);
+
+        let node_lock = self.node.lock().await;
+        match staged {
+            Ok((blob_hash, size)) => {
+                node_lock.store.commit_staged_blob(
+                    &self.conversation_id,
+                    staging_id,
+                    &blob_hash,
+                    size,
+                )?;
+                Ok((blob_hash, size))
+            }
+            Err(e) => {
+                let _ = node_lock.store.discard_staged_blob(staging_id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Opens a stored blob for streaming reads with `tokio::io`. Chunks are
+    /// read on the blocking thread pool as the stream is polled.
+    pub async fn open_blob(&self, hash: &NodeHash) -> MerkleToxResult<BlobStream<T, S>> {
+        let size = {
+            let node_lock = self.node.lock().await;
+            node_lock
+                .store
+                .get_blob_info(hash)
+                .filter(|_| node_lock.store.has_blob(hash))
+                .ok_or(MerkleToxError::BlobNotFound(*hash))?
+                .size
+        };
+        Ok(BlobStream::new(self.node.clone(), *hash, size))
+    }
+
+    /// Opens the image of a sticker or custom emoji. If it is not stored
+    /// yet, asks the conversation's peers for it and returns `None`; it can
+    /// be opened once the download completes.
+    pub async fn open_sticker(&self, hash: &NodeHash) -> MerkleToxResult<Option<BlobStream<T, S>>> {
+        {
+            let mut node_lock = self.node.lock().await;
+            if !node_lock.store.has_blob(hash) {
+                let node_ref = &mut *node_lock;
+                let effects =
+                    node_ref
+                        .engine
+                        .request_blob(self.conversation_id, *hash, &node_ref.store);
+                let now = node_ref.time_provider.now_instant();
+                let now_ms = node_ref.time_provider.now_system_ms() as u64;
+                let mut dummy_wakeup = now;
+                for effect in effects {
+                    node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+                }
+                return Ok(None);
+            }
+        }
+        self.open_blob(hash).await.map(Some)
+    }
+
+    /// Aborts an in-progress blob download and marks the blob as cancelled.
+    /// Returns false if the blob was not being downloaded.
+    pub async fn cancel_blob(&self, hash: &NodeHash) -> MerkleToxResult<bool> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let effects = node_ref.engine.cancel_blob(hash);
+        if effects.is_empty() {
+            return Ok(false);
+        }
+
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+        }
+        Ok(true)
+    }
+
+    /// Exports the keys of all established conversations into a vault
+    /// encrypted under `passphrase` and shares it as a blob in this
+    /// conversation, so that a vaultbot keeps a copy.
+    pub async fn backup_keys(&self, passphrase: &[u8]) -> MerkleToxResult<NodeHash> {
+        let vault = self.node.lock().await.engine.export_key_vault(passphrase)?;
+        self.send_blob(
+            "keys.mtxvault".to_string(),
+            KEY_VAULT_MIME_TYPE.to_string(),
+            vault,
+        )
+        .await
+    }
+
+    /// Restores conversation keys from a vault created by `backup_keys`.
+    pub async fn restore_keys(&self, vault: &[u8], passphrase: &[u8]) -> MerkleToxResult<()> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let effects = node_ref.engine.restore_from_vault(vault, passphrase)?;
+
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+        }
+        Ok(())
+    }
+
+    /// Creates an invite link for this conversation, valid until
+    /// `expires_at`. Whoever opens it with `MerkleToxNode::join_from_invite`
+    /// is admitted as a member when their Announcement reaches this device.
+    /// Requires this device to be an admin.
+    pub async fn create_invite_link(&self, expires_at: i64) -> MerkleToxResult<String> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let cert = node_ref
+            .engine
+            .own_certificate(self.conversation_id, &node_ref.store)
+            .ok_or(MerkleToxError::Identity(IdentityError::NoTrustPath))?;
+        let self_pk = node_ref.engine.self_pk;
+        let (token, effects) = node_ref
+            .engine
+            .create_invite(cert, vec![self_pk], 0, expires_at)?;
+
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+        }
+        Ok(token.to_link())
+    }
+
+    /// Limits how much history of this conversation is synced. With
+    /// `SyncPolicy::Recent`, `ChatState::history_truncated_before` reports
+    /// where the synced history starts after the next `refresh_state`.
+    pub async fn set_sync_policy(&self, policy: SyncPolicy) -> MerkleToxResult<()> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let effects = node_ref
+            .engine
+            .set_sync_policy(self.conversation_id, policy);
+
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_ref.process_effect(effect, now, now_ms, &mut dummy_wakeup)?;
+        }
+        Ok(())
+    }
+
+    /// Sets when this device rotates the conversation key while it is an
+    /// admin. Rotations are reported as `NodeEvent::EpochRotated`.
+    pub async fn set_rotation_policy(&self, policy: RotationPolicy) {
+        let mut node_lock = self.node.lock().await;
+        node_lock
+            .engine
+            .set_rotation_policy(self.conversation_id, policy);
+    }
+
+    /// Tells the node whether this conversation is on screen. The visible
+    /// conversation syncs ahead of all others sharing the node.
+    pub async fn set_foreground(&self, foreground: bool) {
+        let mut node_lock = self.node.lock().await;
+        let scheduler = &mut node_lock.engine.sync_scheduler;
+        if foreground {
+            scheduler.set_foreground(Some(self.conversation_id));
+        } else if scheduler.foreground() == Some(self.conversation_id) {
+            scheduler.set_foreground(None);
+        }
+    }
+
     async fn author_node(&self, content: Content, metadata: Vec<u8>) -> MerkleToxResult<NodeHash> {
         let mut node_lock = self.node.lock().await;
         let cid = self.conversation_id;
@@ -601,39 +1304,184 @@ impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> MerkleToxClient
         self.state.read().await.clone()
     }
 
-    /// Performs a full rebuild of the materialized state from the Admin Track.
+    /// Rebuilds the materialized state from the store.
+    ///
+    /// Starts from the saved state snapshot and applies only the nodes
+    /// stored after it. Without a usable snapshot, all verified nodes are
+    /// replayed, the Admin track first.
     pub async fn refresh_state(&self) -> MerkleToxResult<()> {
         let node_lock = self.node.lock().await;
-        let admin_nodes = node_lock
-            .store
-            .get_verified_nodes_by_type(&self.conversation_id, NodeType::Admin)?;
-        let content_nodes = node_lock
-            .store
-            .get_verified_nodes_by_type(&self.conversation_id, NodeType::Content)?;
-
-        let mut new_state = ChatState {
-            conversation_id: self.conversation_id,
-            ..Default::default()
+        let store = &node_lock.store;
+        let cid = self.conversation_id;
+        let self_pk = node_lock.engine.self_logical_pk;
+
+        let (mut new_state, replayed) = match self.load_snapshot(store) {
+            Some(mut state) => {
+                let range = SyncRange {
+                    min_rank: state.max_verified_rank + 1,
+                    max_rank: u64::MAX,
+                };
+                let hashes = store.get_node_hashes_in_range(&cid, &range)?;
+                let mut nodes: Vec<_> = store.get_nodes(&hashes).into_iter().flatten().collect();
+                // Same order as a full replay.
+                nodes.sort_by_cached_key(|n| {
+                    (
+                        n.node_type() != NodeType::Admin,
+                        n.topological_rank,
+                        n.hash(),
+                    )
+                });
+                for n in &nodes {
+                    materialize(&mut state, &n.hash(), n);
+                    mark_read(&mut state, store, &self_pk, n);
+                    count_unread(&mut state, &self_pk, n);
+                }
+                debug!("Applied {} node(s) after the state snapshot", nodes.len());
+                (state, nodes.len())
+            }
+            None => {
+                let admin_nodes = store.get_verified_nodes_by_type(&cid, NodeType::Admin)?;
+                let content_nodes = store.get_verified_nodes_by_type(&cid, NodeType::Content)?;
+                let mut state = ChatState {
+                    conversation_id: cid,
+                    ..Default::default()
+                };
+                for n in admin_nodes.iter().chain(&content_nodes) {
+                    materialize(&mut state, &n.hash(), n);
+                    mark_read(&mut state, store, &self_pk, n);
+                    count_unread(&mut state, &self_pk, n);
+                }
+                (state, admin_nodes.len() + content_nodes.len())
+            }
         };
 
-        for n in admin_nodes {
-            self.apply_node_internal(&mut new_state, &n.hash(), &n);
-        }
-        for n in content_nodes {
-            self.apply_node_internal(&mut new_state, &n.hash(), &n);
-        }
+        // Pruned nodes are not replayed but count towards the snapshot.
+        let covered = SyncRange {
+            min_rank: 0,
+            max_rank: new_state.max_verified_rank,
+        };
+        new_state.verified_nodes = store.get_node_hashes_in_range(&cid, &covered)?.len() as u64;
 
-        let mut all_heads = node_lock.store.get_heads(&self.conversation_id);
-        for h in node_lock.store.get_admin_heads(&self.conversation_id) {
+        let mut all_heads = store.get_heads(&cid);
+        for h in store.get_admin_heads(&cid) {
             if !all_heads.contains(&h) {
                 all_heads.push(h);
             }
         }
         new_state.heads = all_heads;
+        new_state.history_truncated_before = store.get_history_horizon(&cid);
+
+        // Acknowledgements are not part of the DAG.
+        let own_ranks = new_state
+            .messages
+            .iter()
+            .filter(|m| m.author_pk == self_pk)
+            .filter_map(|m| store.get_rank(&m.hash));
+        if let Some(min_rank) = own_ranks.min() {
+            let acknowledged = node_lock.engine.acknowledged_nodes(&cid, min_rank, store);
+            let sent = |hash: &NodeHash| {
+                store
+                    .get_node(hash)
+                    .is_some_and(|n| n.sender_pk == node_lock.engine.self_pk)
+            };
+            for msg in new_state.messages.iter_mut() {
+                if msg.author_pk != self_pk || !sent(&msg.hash) {
+                    continue;
+                }
+                msg.delivered_to.extend(
+                    acknowledged
+                        .iter()
+                        .filter(|(_, hashes)| hashes.contains(&msg.hash))
+                        .map(|(device, _)| *device),
+                );
+            }
+        }
+
+        if let Some(interval) = self.policy.snapshot_interval() {
+            if replayed >= interval {
+                store.put_state_snapshot(&self.snapshot_of(&new_state)?)?;
+                self.nodes_since_snapshot.store(0, Ordering::Relaxed);
+            } else {
+                self.nodes_since_snapshot.store(replayed, Ordering::Relaxed);
+            }
+        }
 
         let mut state = self.state.write().await;
         *state = new_state;
 
         Ok(())
     }
+
+    /// Saves the materialized state to the store, so that the next
+    /// [`refresh_state`](Self::refresh_state) only applies newer nodes.
+    /// Called every [`PolicyHandler::snapshot_interval`] nodes.
+    pub async fn save_snapshot(&self) -> MerkleToxResult<()> {
+        let snapshot = self.snapshot_of(&*self.state.read().await)?;
+        self.nodes_since_snapshot.store(0, Ordering::Relaxed);
+        self.node.lock().await.store.put_state_snapshot(&snapshot)
+    }
+
+    fn snapshot_of(&self, state: &ChatState) -> MerkleToxResult<StateSnapshot> {
+        Ok(StateSnapshot {
+            conversation_id: self.conversation_id,
+            rank: state.max_verified_rank,
+            verified_nodes: state.verified_nodes,
+            heads: state.heads.clone(),
+            data: tox_proto::serialize(state)?,
+        })
+    }
+
+    /// Loads the saved state snapshot, unless it is stale: verified nodes at
+    /// or below its rank were stored or removed after it was taken.
+    fn load_snapshot(&self, store: &S) -> Option<ChatState> {
+        self.policy.snapshot_interval()?;
+        let snapshot = store.get_state_snapshot(&self.conversation_id)?;
+        let range = SyncRange {
+            min_rank: 0,
+            max_rank: snapshot.rank,
+        };
+        let stored = store
+            .get_node_hashes_in_range(&self.conversation_id, &range)
+            .ok()?
+            .len() as u64;
+        if stored != snapshot.verified_nodes {
+            debug!("State snapshot is stale, replaying all nodes");
+            return None;
+        }
+        let state: ChatState = tox_proto::deserialize(&snapshot.data).ok()?;
+        (state.conversation_id == self.conversation_id).then_some(state)
+    }
+}
+
+/// Handle to a message scheduled with [`MerkleToxClient::send_message_at`].
+pub struct ScheduledHandle<T: Transport + 'static, S: NodeStore + BlobStore + 'static> {
+    id: u64,
+    deliver_at_ms: i64,
+    node: Arc<Mutex<MerkleToxNode<T, S>>>,
+}
+
+impl<T: Transport + 'static, S: NodeStore + BlobStore + 'static> ScheduledHandle<T, S> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn deliver_at_ms(&self) -> i64 {
+        self.deliver_at_ms
+    }
+
+    /// Cancels the message. Returns false if it was already delivered or
+    /// cancelled.
+    pub async fn cancel(&self) -> MerkleToxResult<bool> {
+        let mut node_lock = self.node.lock().await;
+        let node_ref = &mut *node_lock;
+        let effects = node_ref.engine.cancel_scheduled_message(self.id);
+        if effects.is_empty() {
+            return Ok(false);
+        }
+        let now = node_ref.time_provider.now_instant();
+        let now_ms = node_ref.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        node_ref.process_effects(effects, now, now_ms, &mut dummy_wakeup)?;
+        Ok(true)
+    }
 }
@@ -0,0 +1,274 @@
+//! One node, many conversations.
+//!
+//! A node has a single event handler, but each [`MerkleToxClient`] follows
+//! one conversation. [`MerkleToxClientManager`] owns the handler: it creates
+//! a client the first time a conversation shows up in an event, hands the
+//! event to that client (or to all clients, for peer events) and then
+//! republishes it on one stream for the application. It also has the
+//! clients push their undelivered messages again, and publishes and looks
+//! up profiles across conversations.
+
+use crate::policy::{DefaultPolicy, PolicyHandler};
+use crate::{MerkleToxClient, QUEUE_POLL_INTERVAL};
+use merkle_tox_core::dag::{ConversationId, LogicalIdentityPk, NodeHash, Profile};
+use merkle_tox_core::error::MerkleToxResult;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, broadcast, mpsc};
+use tracing::{error, info};
+
+/// Events kept for subscribers that fall behind.
+pub const EVENT_BUFFER: usize = 1024;
+
+/// Owns the clients of all conversations of a node.
+pub struct MerkleToxClientManager<T: Transport + 'static, S: NodeStore + BlobStore + 'static> {
+    node: Arc<Mutex<MerkleToxNode<T, S>>>,
+    policy: Arc<dyn PolicyHandler>,
+    clients: Mutex<HashMap<ConversationId, Arc<MerkleToxClient<T, S>>>>,
+    queue: mpsc::UnboundedSender<NodeEvent>,
+    events: broadcast::Sender<NodeEvent>,
+}
+
+impl<T: Transport + 'static, S: NodeStore + BlobStore + Send + 'static>
+    MerkleToxClientManager<T, S>
+{
+    /// Creates a manager whose clients use [`DefaultPolicy`].
+    ///
+    /// Events reach the manager once [`attach`](Self::attach) installed it on
+    /// the node, or through [`event_handler`](Self::event_handler). Must be
+    /// called within a tokio runtime.
+    pub fn new(node: Arc<Mutex<MerkleToxNode<T, S>>>) -> Arc<Self> {
+        Self::with_policy(node, Arc::new(DefaultPolicy))
+    }
+
+    /// Creates a manager whose clients use `policy`.
+    pub fn with_policy(
+        node: Arc<Mutex<MerkleToxNode<T, S>>>,
+        policy: Arc<dyn PolicyHandler>,
+    ) -> Arc<Self> {
+        let (queue, mut rx) = mpsc::unbounded_channel();
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let manager = Arc::new(Self {
+            node,
+            policy,
+            clients: Default::default(),
+            queue,
+            events,
+        });
+        let weak = Arc::downgrade(&manager);
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                let Some(manager) = weak.upgrade() else {
+                    break;
+                };
+                manager.dispatch(event).await;
+            }
+        });
+        let weak = Arc::downgrade(&manager);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(QUEUE_POLL_INTERVAL).await;
+                let Some(manager) = weak.upgrade() else {
+                    break;
+                };
+                for client in manager.clients().await {
+                    if let Err(e) = client.retry_queued().await {
+                        error!("Failed to push queued messages: {}", e);
+                    }
+                }
+            }
+        });
+        manager
+    }
+
+    /// Makes the manager the event handler of the node.
+    pub async fn attach(&self) {
+        let handler = self.event_handler();
+        self.node.lock().await.set_event_handler(handler);
+    }
+
+    /// Handler queueing node events for the manager, for applications that
+    /// wrap it before installing it on the node. Events are handled in
+    /// order, outside the node lock.
+    pub fn event_handler(&self) -> Arc<dyn NodeEventHandler> {
+        Arc::new(EventQueue {
+            tx: self.queue.clone(),
+        })
+    }
+
+    pub fn node(&self) -> &Arc<Mutex<MerkleToxNode<T, S>>> {
+        &self.node
+    }
+
+    /// Node events, each published after the clients handled it.
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn get(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<Arc<MerkleToxClient<T, S>>> {
+        self.clients.lock().await.get(conversation_id).cloned()
+    }
+
+    pub async fn clients(&self) -> Vec<Arc<MerkleToxClient<T, S>>> {
+        self.clients.lock().await.values().cloned().collect()
+    }
+
+    /// Publishes the profile of this identity in every open conversation.
+    /// Conversations where it cannot be published are logged and skipped.
+    /// Returns the hashes of the published profile nodes.
+    pub async fn publish_profile(
+        &self,
+        display_name: String,
+        status: String,
+        avatar: Option<Vec<u8>>,
+    ) -> Vec<NodeHash> {
+        let mut published = Vec::new();
+        for client in self.clients().await {
+            match client
+                .set_profile(display_name.clone(), status.clone(), avatar.clone())
+                .await
+            {
+                Ok(hash) => published.push(hash),
+                Err(e) => error!(
+                    "Failed to publish profile in {:?}: {}",
+                    client.conversation_id, e
+                ),
+            }
+        }
+        published
+    }
+
+    /// The latest profile of an identity across all open conversations.
+    pub async fn profile(&self, identity_pk: &LogicalIdentityPk) -> Option<Profile> {
+        let mut latest = None;
+        for client in self.clients().await {
+            let state = client.state().await;
+            if let Some(member) = state.members.get(identity_pk)
+                && let (Some(profile), Some(version)) = (&member.profile, member.profile_version)
+                && latest.as_ref().is_none_or(|(v, _)| *v < version)
+            {
+                latest = Some((version, profile.clone()));
+            }
+        }
+        latest.map(|(_, profile)| profile)
+    }
+
+    /// Creates a channel named `name` in `parent_id` and returns its client.
+    /// Requires ADMIN in the parent.
+    pub async fn create_channel(
+        &self,
+        parent_id: ConversationId,
+        name: String,
+    ) -> MerkleToxResult<Arc<MerkleToxClient<T, S>>> {
+        let parent = self.get_or_create(parent_id).await?;
+        let channel_id = parent.create_channel(name).await?;
+        self.get_or_create(channel_id).await
+    }
+
+    /// The client of a conversation, created and loaded from the store if
+    /// needed.
+    pub async fn get_or_create(
+        &self,
+        conversation_id: ConversationId,
+    ) -> MerkleToxResult<Arc<MerkleToxClient<T, S>>> {
+        self.open(conversation_id).await.map(|(client, _)| client)
+    }
+
+    /// Like [`get_or_create`](Self::get_or_create), also telling whether the
+    /// client was created.
+    async fn open(
+        &self,
+        conversation_id: ConversationId,
+    ) -> MerkleToxResult<(Arc<MerkleToxClient<T, S>>, bool)> {
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(&conversation_id) {
+            return Ok((client.clone(), false));
+        }
+        info!("Opening conversation {:?}", conversation_id);
+        let client = Arc::new(MerkleToxClient::with_policy(
+            self.node.clone(),
+            conversation_id,
+            self.policy.clone(),
+        ));
+        client.refresh_state().await?;
+        clients.insert(conversation_id, client.clone());
+        Ok((client, true))
+    }
+
+    /// Hands `event` to the clients it concerns, then publishes it.
+    pub async fn dispatch(&self, event: NodeEvent) {
+        match &event {
+            NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node,
+            } => {
+                // A client created now loads the node from the store along
+                // with the rest of the conversation.
+                let stored = self.node.lock().await.store.is_verified(hash);
+                match self.open(*conversation_id).await {
+                    Ok((client, true)) if stored => {
+                        if let Err(e) = client.orchestrate_actions(node).await {
+                            error!("Error handling node event: {}", e);
+                        }
+                    }
+                    Ok((client, _)) => {
+                        if let Err(e) = client.handle_event(event.clone()).await {
+                            error!("Error handling node event: {}", e);
+                        }
+                    }
+                    Err(e) => error!("Failed to open conversation {:?}: {}", conversation_id, e),
+                }
+            }
+            NodeEvent::NodeSpeculative {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeInvalidated {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeExpired {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeDelivered {
+                conversation_id, ..
+            } => match self.get_or_create(*conversation_id).await {
+                Ok(client) => {
+                    if let Err(e) = client.handle_event(event.clone()).await {
+                        error!("Error handling node event: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to open conversation {:?}: {}", conversation_id, e),
+            },
+            NodeEvent::ChannelCreated { channel_id, .. } => {
+                if let Err(e) = self.get_or_create(*channel_id).await {
+                    error!("Failed to open channel {:?}: {}", channel_id, e);
+                }
+            }
+            NodeEvent::PeerHandshakeComplete { .. } => {
+                for client in self.clients().await {
+                    if let Err(e) = client.handle_event(event.clone()).await {
+                        error!("Error handling PeerHandshakeComplete: {}", e);
+                    }
+                }
+            }
+            _ => {}
+        }
+        let _ = self.events.send(event);
+    }
+}
+
+struct EventQueue {
+    tx: mpsc::UnboundedSender<NodeEvent>,
+}
+
+impl NodeEventHandler for EventQueue {
+    fn handle_event(&self, event: NodeEvent) {
+        let _ = self.tx.send(event);
+    }
+}
@@ -0,0 +1,198 @@
+//! Conversion between text entities and a markdown subset.
+//!
+//! Supported are `**bold**`, `_italic_`, `` `code` ``, `||spoiler||` and
+//! `[label](url)`. Delimiters need no surrounding spaces, and a backslash
+//! makes the next character literal. Unmatched delimiters are plain text.
+//! Mentions and URLs have no markdown form: they are plain text in markdown.
+
+use merkle_tox_core::entities::{EntityKind, TextEntities, TextEntity};
+use std::cmp::Reverse;
+
+/// Characters escaped outside code spans.
+const SPECIAL: &[char] = &['\\', '*', '_', '`', '|', '[', ']'];
+
+/// Delimiters of the formatting entities, in the order they are tried.
+const DELIMITERS: &[(&str, EntityKind)] = &[
+    ("**", EntityKind::Bold),
+    ("||", EntityKind::Spoiler),
+    ("_", EntityKind::Italic),
+];
+
+/// Renders `text` with its formatting entities as markdown. The entities
+/// must be valid for the text, as those of a
+/// [`ChatMessage`](crate::state::ChatMessage) are.
+pub fn to_markdown(text: &str, entities: &[TextEntity]) -> String {
+    let entities: Vec<&TextEntity> = entities
+        .iter()
+        .filter(|e| opener(&e.kind).is_some())
+        .collect();
+    let mut out = String::with_capacity(text.len());
+    let mut open: Vec<&TextEntity> = Vec::new();
+    let mut next = 0;
+    let chars = text.char_indices().map(|(pos, c)| (pos, Some(c)));
+    for (pos, c) in chars.chain([(text.len(), None)]) {
+        while let Some(e) = open.pop_if(|e| e.end as usize <= pos) {
+            out.push_str(&closer(&e.kind));
+        }
+        while let Some(e) = entities.get(next).filter(|e| e.start as usize == pos) {
+            out.push_str(opener(&e.kind).unwrap_or_default());
+            open.push(e);
+            next += 1;
+        }
+        let Some(c) = c else {
+            break;
+        };
+        let in_code = open.iter().any(|e| e.kind == EntityKind::Code);
+        if (in_code && matches!(c, '`' | '\\')) || (!in_code && SPECIAL.contains(&c)) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Parses markdown into plain text and its formatting entities, in
+/// canonical order.
+pub fn from_markdown(markdown: &str) -> (String, TextEntities) {
+    let mut text = String::with_capacity(markdown.len());
+    let mut entities = Vec::new();
+    parse(markdown, &mut text, &mut entities);
+    entities.retain(|e| e.start < e.end);
+    // Stable: of two equal ranges, the outer one stays first.
+    entities.sort_by_key(|e| (e.start, Reverse(e.end)));
+    (text, TextEntities { entities })
+}
+
+fn opener(kind: &EntityKind) -> Option<&'static str> {
+    match kind {
+        EntityKind::Bold => Some("**"),
+        EntityKind::Italic => Some("_"),
+        EntityKind::Code => Some("`"),
+        EntityKind::Spoiler => Some("||"),
+        EntityKind::Link(_) => Some("["),
+        EntityKind::Mention(_) | EntityKind::Url => None,
+    }
+}
+
+fn closer(kind: &EntityKind) -> String {
+    match kind {
+        EntityKind::Link(url) => {
+            let mut out = String::from("](");
+            for c in url.chars() {
+                if matches!(c, ')' | '\\') {
+                    out.push('\\');
+                }
+                out.push(c);
+            }
+            out.push(')');
+            out
+        }
+        kind => opener(kind).unwrap_or_default().to_string(),
+    }
+}
+
+/// Appends the text of `markdown` to `text`, and its entities, outer ones
+/// before the ones they contain, to `entities`.
+fn parse(markdown: &str, text: &mut String, entities: &mut Vec<TextEntity>) {
+    let mut i = 0;
+    while let Some(c) = markdown[i..].chars().next() {
+        let rest = &markdown[i..];
+        if c == '\\'
+            && let Some(escaped) = rest[1..].chars().next()
+        {
+            text.push(escaped);
+            i += 1 + escaped.len_utf8();
+            continue;
+        }
+        if let Some((code, len)) = code_span(rest) {
+            let start = text.len() as u32;
+            text.push_str(&code);
+            entities.push(TextEntity {
+                start,
+                end: text.len() as u32,
+                kind: EntityKind::Code,
+            });
+            i += len;
+            continue;
+        }
+        let span = link(rest).map(|(label, url, len)| (label, EntityKind::Link(url), len));
+        let span = span.or_else(|| {
+            DELIMITERS.iter().find_map(|(delim, kind)| {
+                let inner = rest.strip_prefix(delim)?;
+                let end = find_closing(inner, delim).filter(|&end| end > 0)?;
+                Some((&inner[..end], kind.clone(), end + 2 * delim.len()))
+            })
+        });
+        if let Some((inner, kind, len)) = span {
+            let index = entities.len();
+            let start = text.len() as u32;
+            entities.push(TextEntity {
+                start,
+                end: start,
+                kind,
+            });
+            parse(inner, text, entities);
+            entities[index].end = text.len() as u32;
+            i += len;
+            continue;
+        }
+        text.push(c);
+        i += c.len_utf8();
+    }
+}
+
+/// The content and length of the code span `markdown` starts with.
+fn code_span(markdown: &str) -> Option<(String, usize)> {
+    let mut chars = markdown.strip_prefix('`')?.char_indices();
+    let mut code = String::new();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '`' if code.is_empty() => return None,
+            '`' => return Some((code, pos + 2)),
+            '\\' => match chars.clone().next() {
+                Some((_, escaped @ ('`' | '\\'))) => {
+                    code.push(escaped);
+                    chars.next();
+                }
+                _ => code.push(c),
+            },
+            c => code.push(c),
+        }
+    }
+    None
+}
+
+/// The label, URL and length of the link `markdown` starts with.
+fn link(markdown: &str) -> Option<(&str, String, usize)> {
+    let inner = markdown.strip_prefix('[')?;
+    let label_end = find_closing(inner, "](").filter(|&end| end > 0)?;
+    let mut chars = inner[label_end + 2..].char_indices();
+    let mut url = String::new();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            ')' if url.is_empty() => return None,
+            ')' => return Some((&inner[..label_end], url, 1 + label_end + 2 + pos + 1)),
+            '\\' => url.push(chars.next().map_or(c, |(_, escaped)| escaped)),
+            c => url.push(c),
+        }
+    }
+    None
+}
+
+/// Position of the first `delim` in `markdown` that is neither escaped nor
+/// in a code span.
+fn find_closing(markdown: &str, delim: &str) -> Option<usize> {
+    let mut i = 0;
+    while let Some(c) = markdown[i..].chars().next() {
+        let rest = &markdown[i..];
+        if rest.starts_with(delim) {
+            return Some(i);
+        }
+        i += match c {
+            '\\' => 1 + rest[1..].chars().next().map_or(0, char::len_utf8),
+            '`' => code_span(rest).map_or(1, |(_, len)| len),
+            c => c.len_utf8(),
+        };
+    }
+    None
+}
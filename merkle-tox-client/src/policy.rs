@@ -1,6 +1,9 @@
 use crate::state::ChatState;
 use merkle_tox_core::dag::PublicKey;
 
+/// Nodes applied between state snapshots under [`DefaultPolicy`].
+pub const DEFAULT_SNAPSHOT_INTERVAL: usize = 1000;
+
 pub trait PolicyHandler: Send + Sync {
     /// Decide whether to automatically authorize a device.
     fn should_authorize(&self, author_pk: &PublicKey, device_pk: &PublicKey) -> bool;
@@ -10,6 +13,20 @@ pub trait PolicyHandler: Send + Sync {
 
     /// Decide whether to respond to a HandshakePulse by announcing new keys.
     fn should_respond_to_pulse(&self, sender_pk: &PublicKey) -> bool;
+
+    /// Number of recent content nodes to push, along with the admin track,
+    /// to a device announcing itself in the conversation for the first time.
+    /// `None` leaves it to the device to start reconciliation.
+    fn history_push_window(&self, _device_pk: &PublicKey) -> Option<usize> {
+        None
+    }
+
+    /// Number of verified nodes applied to the materialized state between
+    /// snapshots saved to the store. `None` disables snapshots, so every
+    /// start replays the whole conversation.
+    fn snapshot_interval(&self) -> Option<usize> {
+        Some(DEFAULT_SNAPSHOT_INTERVAL)
+    }
 }
 
 pub struct DefaultPolicy;
@@ -33,3 +50,35 @@ impl PolicyHandler for DefaultPolicy {
         true
     }
 }
+
+/// Policy for archive bots such as the vaultbot: behaves like
+/// `DefaultPolicy`, but serves history to newly joined devices without
+/// waiting for them to ask.
+pub struct HistoryServerPolicy {
+    /// Number of recent content nodes pushed to a new device.
+    pub content_window: usize,
+}
+
+impl HistoryServerPolicy {
+    pub fn new(content_window: usize) -> Self {
+        Self { content_window }
+    }
+}
+
+impl PolicyHandler for HistoryServerPolicy {
+    fn should_authorize(&self, author_pk: &PublicKey, device_pk: &PublicKey) -> bool {
+        DefaultPolicy.should_authorize(author_pk, device_pk)
+    }
+
+    fn should_rotate_keys(&self, state: &ChatState) -> bool {
+        DefaultPolicy.should_rotate_keys(state)
+    }
+
+    fn should_respond_to_pulse(&self, sender_pk: &PublicKey) -> bool {
+        DefaultPolicy.should_respond_to_pulse(sender_pk)
+    }
+
+    fn history_push_window(&self, _device_pk: &PublicKey) -> Option<usize> {
+        Some(self.content_window)
+    }
+}
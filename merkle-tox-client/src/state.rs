@@ -1,26 +1,67 @@
+use merkle_tox_core::channel::{ChannelInfo, channel_conversation_id};
 use merkle_tox_core::dag::{
-    Content, ConversationId, LogicalIdentityPk, NodeHash, PhysicalDevicePk, SignedPreKey,
+    Content, ControlAction, ConversationId, EmojiSource, HistoryVisibility, LogicalIdentityPk,
+    MerkleNode, NodeHash, PhysicalDevicePk, Profile, SignedPreKey, Sticker,
 };
-use std::collections::{HashMap, HashSet};
+use merkle_tox_core::entities::{EntityKind, TextEntities, TextEntity, mentions};
+use merkle_tox_core::sync::NodeStore;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tox_proto::ToxProto;
+
+/// Position of a node in conflict resolution order: `(topological_rank, hash)`.
+/// Among concurrent writes to the same field, the highest version wins.
+pub type NodeVersion = (u64, NodeHash);
+
+/// Position of a profile in conflict resolution order. Network time comes
+/// first, so profiles published to different conversations compare too.
+pub type ProfileVersion = (i64, NodeVersion);
 
 /// The current materialized state of a conversation.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, ToxProto)]
 pub struct ChatState {
     pub conversation_id: ConversationId,
     pub title: String,
     pub topic: String,
+    /// Messages older than this many milliseconds expire. 0 keeps history.
+    pub retention_ms: u64,
+    /// Versions of the nodes that set `title`, `topic` and `retention_ms`.
+    pub title_version: Option<NodeVersion>,
+    pub topic_version: Option<NodeVersion>,
+    pub retention_version: Option<NodeVersion>,
+    /// Which history members may fetch, and who may fetch all of it.
+    pub history_visibility: HistoryVisibility,
+    pub history_access: HashSet<LogicalIdentityPk>,
+    pub history_visibility_version: Option<NodeVersion>,
     /// Author PK -> Member information
     pub members: HashMap<LogicalIdentityPk, MemberInfo>,
     /// Set of all authorized device PKs in the conversation
     pub authorized_devices: HashSet<PhysicalDevicePk>,
     /// Latest announcement per device: Device PK -> (PreKeys, LastResortKey)
     pub announcements: HashMap<PhysicalDevicePk, (Vec<SignedPreKey>, SignedPreKey)>,
+    /// Identities whose safety number an admin verified: Identity PK ->
+    /// verifying admin PK
+    pub verified_identities: HashMap<LogicalIdentityPk, LogicalIdentityPk>,
     /// Recent messages in the conversation
     pub messages: Vec<ChatMessage>,
     /// The hashes of the current DAG heads
     pub heads: Vec<NodeHash>,
     /// The topological rank of the highest verified node processed
     pub max_verified_rank: u64,
+    /// Number of verified nodes processed, including ones pruned since.
+    pub verified_nodes: u64,
+    /// History before this rank was not synced (see `SyncPolicy::Recent`).
+    pub history_truncated_before: Option<u64>,
+    /// Messages of other members since this identity last wrote or
+    /// cleared them.
+    pub unread_messages: u64,
+    /// How many of the unread messages mention this identity.
+    pub unread_mentions: u64,
+    /// Sticker packs installed in the conversation.
+    pub sticker_packs: Vec<StickerPack>,
+    /// Channels created in the conversation, in the order they were applied.
+    pub channels: Vec<ChannelInfo>,
+    /// An admin closed the conversation; it takes no new messages.
+    pub archived: bool,
 }
 
 impl Default for ChatState {
@@ -29,38 +70,490 @@ impl Default for ChatState {
             conversation_id: ConversationId::from([0u8; 32]),
             title: String::new(),
             topic: String::new(),
+            retention_ms: 0,
+            title_version: None,
+            topic_version: None,
+            retention_version: None,
+            history_visibility: HistoryVisibility::Shared,
+            history_access: HashSet::new(),
+            history_visibility_version: None,
             members: HashMap::new(),
             authorized_devices: HashSet::new(),
             announcements: HashMap::new(),
+            verified_identities: HashMap::new(),
             messages: Vec::new(),
             heads: Vec::new(),
             max_verified_rank: 0,
+            verified_nodes: 0,
+            history_truncated_before: None,
+            unread_messages: 0,
+            unread_mentions: 0,
+            sticker_packs: Vec::new(),
+            channels: Vec::new(),
+            archived: false,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, ToxProto)]
 pub struct ChatMessage {
     pub hash: NodeHash,
     pub author_pk: LogicalIdentityPk,
     pub timestamp: i64,
     pub content: Content,
+    /// Mentions, URLs and formatting of the text.
+    pub entities: Vec<TextEntity>,
     /// Reactions to this message: Emoji -> Set of User PKs
     pub reactions: HashMap<String, HashSet<LogicalIdentityPk>>,
+    /// Images of the custom emoji among `reactions`: Shortcode -> Blob hash
+    pub custom_emoji: HashMap<String, NodeHash>,
     pub is_redacted: bool,
+    /// Version of the edit that set the current text, if edited.
+    pub edit_version: Option<NodeVersion>,
+    /// Devices that acknowledged having this message. Only tracked for
+    /// messages sent by this device.
+    pub delivered_to: HashSet<PhysicalDevicePk>,
+    /// Other members that wrote after receiving this message. Only tracked
+    /// for messages of this identity.
+    pub read_by: HashSet<LogicalIdentityPk>,
+    /// Votes, if the message is a poll.
+    pub poll: Option<PollState>,
+}
+
+/// Votes on a poll.
+#[derive(Debug, Clone, Default, PartialEq, ToxProto)]
+pub struct PollState {
+    /// The counted vote of each identity: its first by version.
+    pub votes: HashMap<LogicalIdentityPk, (NodeVersion, Vec<u32>)>,
+    /// Closed by a PollClose node. Polls also close at their `closes_at`.
+    pub closed: bool,
+}
+
+impl PollState {
+    /// Number of votes for each of `options` options.
+    pub fn tally(&self, options: usize) -> Vec<u64> {
+        let mut counts = vec![0; options];
+        for (_, choices) in self.votes.values() {
+            for &choice in choices {
+                if let Some(count) = counts.get_mut(choice as usize) {
+                    *count += 1;
+                }
+            }
+        }
+        counts
+    }
+}
+
+/// A sticker pack. A later pack of the same author and name replaces it.
+#[derive(Debug, Clone, PartialEq, ToxProto)]
+pub struct StickerPack {
+    pub name: String,
+    pub author_pk: LogicalIdentityPk,
+    pub stickers: Vec<Sticker>,
+    pub version: NodeVersion,
+}
+
+/// How far a message of this identity got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryState {
+    /// Stored locally; no other device acknowledged it yet.
+    Sent,
+    /// Acknowledged by this many devices.
+    Delivered(usize),
+    /// Another member wrote after receiving it.
+    Read,
+}
+
+impl ChatMessage {
+    /// Inline preview of a blob message, available before the blob itself
+    /// is downloaded. Redacted messages have no preview.
+    pub fn preview(&self) -> Option<&[u8]> {
+        match &self.content {
+            Content::Blob {
+                preview: Some(preview),
+                ..
+            } if !self.is_redacted => Some(preview),
+            _ => None,
+        }
+    }
+
+    /// Number of votes for each option, if the message is a poll.
+    pub fn tally(&self) -> Option<Vec<u64>> {
+        match (&self.content, &self.poll) {
+            (Content::Poll { options, .. }, Some(poll)) => Some(poll.tally(options.len())),
+            _ => None,
+        }
+    }
+
+    /// Whether the message mentions `pk`.
+    pub fn mentions(&self, pk: &LogicalIdentityPk) -> bool {
+        self.entities
+            .iter()
+            .any(|e| e.kind == EntityKind::Mention(*pk))
+    }
+
+    pub fn delivery(&self) -> DeliveryState {
+        if !self.read_by.is_empty() {
+            DeliveryState::Read
+        } else if !self.delivered_to.is_empty() {
+            DeliveryState::Delivered(self.delivered_to.len())
+        } else {
+            DeliveryState::Sent
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, ToxProto)]
 pub struct MemberInfo {
     pub public_key: LogicalIdentityPk,
     pub role: MemberRole,
     pub joined_at: i64,
     /// Device PKs belonging to this member
     pub devices: HashSet<PhysicalDevicePk>,
+    /// The member's latest profile, if it published one.
+    pub profile: Option<Profile>,
+    pub profile_version: Option<ProfileVersion>,
+}
+
+impl MemberInfo {
+    /// The member's display name, if its profile sets one.
+    pub fn display_name(&self) -> Option<&str> {
+        self.profile
+            .as_ref()
+            .map(|p| p.display_name.as_str())
+            .filter(|n| !n.is_empty())
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ToxProto)]
 pub enum MemberRole {
     Admin,
     Member,
 }
+
+/// Applies a verified node to the materialized state.
+///
+/// Nodes may arrive in any order. Title, topic, retention and history
+/// visibility are last-writer-wins registers ordered by
+/// `(topological_rank, hash)`, so every device settles on the same value for
+/// concurrent admin edits. Member profiles are ordered by network time
+/// first (see [`ProfileVersion`]).
+pub fn materialize(state: &mut ChatState, hash: &NodeHash, node: &MerkleNode) {
+    // Update heads and rank
+    state.heads.retain(|h| !node.parents.contains(h));
+    if !state.heads.contains(hash) {
+        state.heads.push(*hash);
+    }
+    state.max_verified_rank = state.max_verified_rank.max(node.topological_rank);
+    let version = (node.topological_rank, *hash);
+
+    match &node.content {
+        Content::Text(_)
+        | Content::Blob { .. }
+        | Content::Voice { .. }
+        | Content::Poll { .. }
+        | Content::Location { .. }
+        | Content::Custom { .. } => {
+            state.messages.push(ChatMessage {
+                hash: *hash,
+                author_pk: node.author_pk,
+                timestamp: node.network_timestamp,
+                content: node.content.clone(),
+                entities: TextEntities::of(node),
+                reactions: Default::default(),
+                custom_emoji: Default::default(),
+                is_redacted: false,
+                edit_version: None,
+                delivered_to: Default::default(),
+                read_by: Default::default(),
+                poll: matches!(node.content, Content::Poll { .. }).then(PollState::default),
+            });
+        }
+        Content::PollVote { poll_hash, choices } => {
+            if let Some(poll) = state
+                .messages
+                .iter_mut()
+                .find(|m| m.hash == *poll_hash)
+                .and_then(|m| m.poll.as_mut())
+            {
+                let vote = poll
+                    .votes
+                    .entry(node.author_pk)
+                    .or_insert_with(|| (version, choices.clone()));
+                if version < vote.0 {
+                    *vote = (version, choices.clone());
+                }
+            }
+        }
+        Content::PollClose { poll_hash } => {
+            if let Some(poll) = state
+                .messages
+                .iter_mut()
+                .find(|m| m.hash == *poll_hash)
+                .and_then(|m| m.poll.as_mut())
+            {
+                poll.closed = true;
+            }
+        }
+        Content::StickerPack { name, stickers } => {
+            let installed = state
+                .sticker_packs
+                .iter_mut()
+                .find(|p| p.author_pk == node.author_pk && p.name == *name);
+            match installed {
+                Some(pack) if pack.version < version => {
+                    pack.stickers = stickers.clone();
+                    pack.version = version;
+                }
+                Some(_) => {}
+                None => state.sticker_packs.push(StickerPack {
+                    name: name.clone(),
+                    author_pk: node.author_pk,
+                    stickers: stickers.clone(),
+                    version,
+                }),
+            }
+        }
+        Content::Reaction { target_hash, emoji } => {
+            if let Some(msg) = state.messages.iter_mut().find(|m| m.hash == *target_hash) {
+                let emoji_str = match emoji {
+                    EmojiSource::Unicode(s) => s.clone(),
+                    EmojiSource::Custom { hash, shortcode } => {
+                        msg.custom_emoji
+                            .entry(shortcode.clone())
+                            .or_insert(NodeHash::from(*hash));
+                        shortcode.clone()
+                    }
+                };
+                msg.reactions
+                    .entry(emoji_str)
+                    .or_default()
+                    .insert(node.author_pk);
+            }
+        }
+        Content::Edit {
+            target_hash,
+            new_text,
+        } => {
+            // Authors only edit their own text messages.
+            if let Some(msg) = state.messages.iter_mut().find(|m| m.hash == *target_hash)
+                && msg.author_pk == node.author_pk
+                && matches!(msg.content, Content::Text(_))
+                && supersedes(&mut msg.edit_version, version)
+            {
+                msg.content = Content::Text(new_text.clone());
+                msg.entities = TextEntities::of(node);
+            }
+        }
+        Content::Redaction { target_hash, .. } => {
+            if let Some(msg) = state.messages.iter_mut().find(|m| m.hash == *target_hash) {
+                msg.is_redacted = true;
+            }
+        }
+        Content::Control(action) => match action {
+            ControlAction::SetTitle(title) => {
+                if supersedes(&mut state.title_version, version) {
+                    state.title = title.clone();
+                }
+            }
+            ControlAction::SetTopic(topic) => {
+                if supersedes(&mut state.topic_version, version) {
+                    state.topic = topic.clone();
+                }
+            }
+            ControlAction::SetRetention { max_age_ms } => {
+                if supersedes(&mut state.retention_version, version) {
+                    state.retention_ms = *max_age_ms;
+                }
+            }
+            ControlAction::SetHistoryVisibility {
+                visibility,
+                history_access,
+            } => {
+                if supersedes(&mut state.history_visibility_version, version) {
+                    state.history_visibility = *visibility;
+                    state.history_access = history_access.iter().copied().collect();
+                }
+            }
+            ControlAction::AuthorizeDevice { cert } => {
+                let member = state
+                    .members
+                    .entry(node.author_pk)
+                    .or_insert_with(|| MemberInfo {
+                        public_key: node.author_pk,
+                        role: MemberRole::Member,
+                        joined_at: node.network_timestamp,
+                        devices: Default::default(),
+                        profile: None,
+                        profile_version: None,
+                    });
+                member.devices.insert(cert.device_pk);
+                state.authorized_devices.insert(cert.device_pk);
+            }
+            ControlAction::RevokeDevice {
+                target_device_pk, ..
+            } => {
+                state.authorized_devices.remove(target_device_pk);
+                for member in state.members.values_mut() {
+                    member.devices.remove(target_device_pk);
+                }
+            }
+            ControlAction::Invite(invite) => {
+                state
+                    .members
+                    .entry(invite.invitee_pk)
+                    .or_insert_with(|| MemberInfo {
+                        public_key: invite.invitee_pk,
+                        role: if invite.role == 1 {
+                            MemberRole::Admin
+                        } else {
+                            MemberRole::Member
+                        },
+                        joined_at: node.network_timestamp,
+                        devices: Default::default(),
+                        profile: None,
+                        profile_version: None,
+                    });
+            }
+            ControlAction::SetProfile(profile) => {
+                let member = state
+                    .members
+                    .entry(node.author_pk)
+                    .or_insert_with(|| MemberInfo {
+                        public_key: node.author_pk,
+                        role: MemberRole::Member,
+                        joined_at: node.network_timestamp,
+                        devices: Default::default(),
+                        profile: None,
+                        profile_version: None,
+                    });
+                if supersedes(
+                    &mut member.profile_version,
+                    (node.network_timestamp, version),
+                ) {
+                    member.profile = Some(profile.clone());
+                }
+            }
+            ControlAction::Announcement {
+                pre_keys,
+                last_resort_key,
+            } => {
+                state
+                    .announcements
+                    .insert(node.sender_pk, (pre_keys.clone(), last_resort_key.clone()));
+            }
+            ControlAction::VerifiedIdentity(identity_pk) => {
+                state
+                    .verified_identities
+                    .entry(*identity_pk)
+                    .or_insert(node.author_pk);
+            }
+            ControlAction::CreateChannel { channel_id, name } => {
+                let conversation_id = channel_conversation_id(&state.conversation_id, channel_id);
+                if !state
+                    .channels
+                    .iter()
+                    .any(|c| c.conversation_id == conversation_id)
+                {
+                    state.channels.push(ChannelInfo {
+                        conversation_id,
+                        parent_id: state.conversation_id,
+                        name: name.clone(),
+                        created_at: node.network_timestamp,
+                    });
+                }
+            }
+            ControlAction::CloseConversation => {
+                state.archived = true;
+            }
+            ControlAction::HandshakePulse => {
+                // HandshakePulse is ephemeral/action-oriented,
+                // usually doesn't need to be in materialized state.
+            }
+            _ => {}
+        },
+        Content::HistoryExport { .. }
+        | Content::LegacyBridge { .. }
+        | Content::SenderKeyDistribution { .. } => {
+            // Not for UI state.
+        }
+        _ => {}
+    }
+}
+
+/// Marks the messages of `self_pk` that `node`, written by another member,
+/// descends from as read by its author.
+pub fn mark_read(
+    state: &mut ChatState,
+    store: &dyn NodeStore,
+    self_pk: &LogicalIdentityPk,
+    node: &MerkleNode,
+) {
+    let reader = node.author_pk;
+    if reader == *self_pk {
+        return;
+    }
+    let mut unread: HashMap<NodeHash, usize> = state
+        .messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.author_pk == *self_pk && !m.read_by.contains(&reader))
+        .map(|(i, m)| (m.hash, i))
+        .collect();
+    let Some(floor) = unread.keys().filter_map(|h| store.get_rank(h)).min() else {
+        return;
+    };
+
+    let mut seen: HashSet<NodeHash> = node.parents.iter().copied().collect();
+    let mut queue: VecDeque<NodeHash> = node.parents.iter().copied().collect();
+    while let Some(hash) = queue.pop_front() {
+        if let Some(i) = unread.remove(&hash) {
+            state.messages[i].read_by.insert(reader);
+            if unread.is_empty() {
+                break;
+            }
+        }
+        let Some(parent) = store.get_node(&hash) else {
+            continue;
+        };
+        for p in parent.parents {
+            if store.get_rank(&p).is_some_and(|r| r >= floor) && seen.insert(p) {
+                queue.push_back(p);
+            }
+        }
+    }
+}
+
+/// Counts `node` towards the unread messages and mentions of `self_pk`.
+/// Writing to the conversation marks everything before as read.
+pub fn count_unread(state: &mut ChatState, self_pk: &LogicalIdentityPk, node: &MerkleNode) {
+    if node.author_pk == *self_pk {
+        state.unread_messages = 0;
+        state.unread_mentions = 0;
+        return;
+    }
+    if !matches!(
+        node.content,
+        Content::Text(_)
+            | Content::Blob { .. }
+            | Content::Voice { .. }
+            | Content::Poll { .. }
+            | Content::Location { .. }
+            | Content::Custom { .. }
+    ) {
+        return;
+    }
+    state.unread_messages += 1;
+    if mentions(node, self_pk) {
+        state.unread_mentions += 1;
+    }
+}
+
+/// Records `version` in a register if it is newer than the current one.
+fn supersedes<V: Ord + Copy>(current: &mut Option<V>, version: V) -> bool {
+    if current.is_some_and(|c| c >= version) {
+        return false;
+    }
+    *current = Some(version);
+    true
+}
@@ -1,18 +1,30 @@
 use merkle_tox_client::MerkleToxClient;
-use merkle_tox_client::state::MemberRole;
+use merkle_tox_client::policy::{DefaultPolicy, HistoryServerPolicy, PolicyHandler};
+use merkle_tox_client::state::{
+    ChatMessage, ChatState, DeliveryState, MemberRole, count_unread, mark_read, materialize,
+};
 use merkle_tox_core::clock::ManualTimeProvider;
 use merkle_tox_core::dag::{
-    Content, ConversationId, LogicalIdentityPk, Permissions, PhysicalDevicePk, PhysicalDeviceSk,
+    Content, ControlAction, ConversationId, Ed25519Signature, EmojiSource, HistoryVisibility,
+    LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash, Permissions, PhysicalDevicePk,
+    PhysicalDeviceSk, Profile, PublicKey,
 };
+use merkle_tox_core::engine::conversation::{FLAG_PUBLIC, join_pow_flags};
+use merkle_tox_core::engine::vault::KEY_VAULT_MIME_TYPE;
 use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::entities::{EntityKind, TextEntities, TextEntity};
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::fingerprint::SafetyNumber;
 use merkle_tox_core::identity::sign_delegation;
 use merkle_tox_core::node::MerkleToxNode;
-use merkle_tox_core::sync::NodeStore;
-use merkle_tox_core::{Transport, TransportError};
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::{NodeEvent, Transport, TransportError};
 use merkle_tox_sqlite::Storage;
 use rand::{SeedableRng, rngs::StdRng};
+use std::io::{Read, SeekFrom};
 use std::sync::Arc;
 use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio::sync::Mutex;
 
 struct MockTransport {
@@ -643,3 +655,1833 @@ async fn test_client_automated_x3dh_onboarding() {
         "Bob should have received the conversation key via automated X3DH"
     );
 }
+
+#[tokio::test]
+async fn test_client_streaming_blob_roundtrip() {
+    let self_sk = [10u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAA; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    // Several chunks with a partial one at the end.
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let blob_hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+    let msg_hash = client
+        .send_blob_from_reader(
+            "data.bin".to_string(),
+            "application/octet-stream".to_string(),
+            std::io::Cursor::new(data.clone()),
+        )
+        .await
+        .unwrap();
+
+    {
+        let node_lock = node.lock().await;
+        assert!(node_lock.store.has_blob(&blob_hash));
+        let msg = node_lock.store.get_node(&msg_hash).unwrap();
+        assert!(matches!(
+            msg.content,
+            Content::Blob { hash, size: 300_000, .. } if hash == blob_hash
+        ));
+    }
+
+    let mut reader = client.open_blob(&blob_hash).await.unwrap();
+    let mut read_back = Vec::new();
+    reader.read_to_end(&mut read_back).await.unwrap();
+    assert_eq!(read_back, data);
+
+    // Seeking into a later chunk fetches it.
+    reader.seek(SeekFrom::Start(200_000)).await.unwrap();
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).await.unwrap();
+    assert_eq!(tail, data[200_000..]);
+
+    // The source is read only once, so it need not be seekable.
+    let zeros_hash = NodeHash::from(*blake3::hash(&[0u8; 150_000]).as_bytes());
+    client
+        .send_blob_from_reader(
+            "zeros.bin".to_string(),
+            "application/octet-stream".to_string(),
+            std::io::repeat(0).take(150_000),
+        )
+        .await
+        .unwrap();
+    assert!(node.lock().await.store.has_blob(&zeros_hash));
+
+    assert!(client.open_blob(&NodeHash::from([0u8; 32])).await.is_err());
+}
+
+#[tokio::test]
+async fn test_client_blob_preview() {
+    let self_sk = [11u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAB; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let thumbnail = vec![0xFFu8; 1024];
+    let msg_hash = client
+        .send_blob_with_preview(
+            "photo.jpg".to_string(),
+            "image/jpeg".to_string(),
+            std::io::Cursor::new(vec![1u8; 100_000]),
+            thumbnail.clone(),
+        )
+        .await
+        .unwrap();
+
+    let msg = node.lock().await.store.get_node(&msg_hash).unwrap();
+    let mut chat_msg = ChatMessage {
+        hash: msg_hash,
+        author_pk: msg.author_pk,
+        timestamp: msg.network_timestamp,
+        content: msg.content,
+        entities: Vec::new(),
+        reactions: Default::default(),
+        custom_emoji: Default::default(),
+        is_redacted: false,
+        edit_version: None,
+        delivered_to: Default::default(),
+        read_by: Default::default(),
+        poll: None,
+    };
+    assert_eq!(chat_msg.preview(), Some(&thumbnail[..]));
+    chat_msg.is_redacted = true;
+    assert_eq!(chat_msg.preview(), None);
+
+    // Oversized previews are rejected before anything is stored.
+    let data = vec![2u8; 10];
+    let res = client
+        .send_blob_with_preview(
+            "big.jpg".to_string(),
+            "image/jpeg".to_string(),
+            std::io::Cursor::new(data.clone()),
+            vec![0u8; 32_769],
+        )
+        .await;
+    assert!(res.is_err());
+    let blob_hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+    assert!(node.lock().await.store.get_blob_info(&blob_hash).is_none());
+}
+
+#[tokio::test]
+async fn test_client_send_voice() {
+    let self_sk = [12u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAC; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let audio = vec![3u8; 70_000];
+    let audio_hash = NodeHash::from(*blake3::hash(&audio).as_bytes());
+    let msg_hash = client
+        .send_voice(
+            std::io::Cursor::new(audio),
+            4_500,
+            vec![0, 64, 128, 255],
+            "opus".to_string(),
+        )
+        .await
+        .unwrap();
+
+    let msg = {
+        let node_lock = node.lock().await;
+        assert!(node_lock.store.has_blob(&audio_hash));
+        assert_eq!(node_lock.store.blob_ref_count(&audio_hash), 1);
+        node_lock.store.get_node(&msg_hash).unwrap()
+    };
+    client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash: msg_hash,
+            node: msg,
+        })
+        .await
+        .unwrap();
+
+    let state = client.state().await;
+    assert_eq!(state.messages.len(), 1);
+    match &state.messages[0].content {
+        Content::Voice {
+            hash,
+            size,
+            duration_ms,
+            waveform,
+            codec,
+        } => {
+            assert_eq!(*hash, audio_hash);
+            assert_eq!(*size, 70_000);
+            assert_eq!(*duration_ms, 4_500);
+            assert_eq!(waveform, &vec![0, 64, 128, 255]);
+            assert_eq!(codec, "opus");
+        }
+        other => panic!("unexpected content: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_client_send_message_at() {
+    let self_sk = [13u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAD; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(
+        engine,
+        transport,
+        store,
+        tp.clone(),
+    )));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let reminder = client
+        .send_message_at("reminder".to_string(), 10_000)
+        .await
+        .unwrap();
+    let cancelled = client
+        .send_message_at("cancelled".to_string(), 20_000)
+        .await
+        .unwrap();
+    assert_eq!(client.scheduled_messages().await.len(), 2);
+    assert_eq!(node.lock().await.store.get_scheduled_messages().len(), 2);
+
+    assert!(cancelled.cancel().await.unwrap());
+    assert!(!cancelled.cancel().await.unwrap());
+    let pending = client.scheduled_messages().await;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].id(), reminder.id());
+    assert_eq!(pending[0].deliver_at_ms(), 10_000);
+
+    // Nothing is authored before the delivery time.
+    node.lock().await.poll();
+    assert_eq!(
+        node.lock().await.store.get_node_counts(&conversation_id),
+        (0, 0)
+    );
+
+    tp.advance(std::time::Duration::from_secs(30));
+    node.lock().await.poll();
+    let node_lock = node.lock().await;
+    assert!(node_lock.store.get_scheduled_messages().is_empty());
+    let (verified, speculative) = node_lock.store.get_node_counts(&conversation_id);
+    assert_eq!(verified + speculative, 1);
+    drop(node_lock);
+    assert!(!reminder.cancel().await.unwrap());
+}
+
+#[tokio::test]
+async fn test_client_retention() {
+    let self_sk = [14u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAE; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let msg_hash = client.send_message("ephemeral".to_string()).await.unwrap();
+    let policy_hash = client.set_retention(60_000).await.unwrap();
+    for hash in [msg_hash, policy_hash] {
+        let node_data = node.lock().await.store.get_node(&hash).unwrap();
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node: node_data,
+            })
+            .await
+            .unwrap();
+    }
+
+    let state = client.state().await;
+    assert_eq!(state.retention_ms, 60_000);
+    assert_eq!(state.messages.len(), 1);
+
+    client
+        .handle_event(NodeEvent::NodeExpired {
+            conversation_id,
+            hash: msg_hash,
+        })
+        .await
+        .unwrap();
+    assert!(client.state().await.messages.is_empty());
+}
+
+#[tokio::test]
+async fn test_client_backup_and_restore_keys() {
+    let self_sk = [15u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAF; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let msg_hash = client.backup_keys(b"passphrase").await.unwrap();
+    let (blob_hash, mime_type) = match node.lock().await.store.get_node(&msg_hash).unwrap().content
+    {
+        Content::Blob {
+            hash, mime_type, ..
+        } => (hash, mime_type),
+        other => panic!("unexpected content: {:?}", other),
+    };
+    assert_eq!(mime_type, KEY_VAULT_MIME_TYPE);
+
+    let mut reader = client.open_blob(&blob_hash).await.unwrap();
+    let mut vault = Vec::new();
+    reader.read_to_end(&mut vault).await.unwrap();
+    assert!(client.restore_keys(&vault, b"wrong").await.is_err());
+    client.restore_keys(&vault, b"passphrase").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client_join_from_invite_link() {
+    let alice_sk = [10u8; 32];
+    let alice_signing_key = ed25519_dalek::SigningKey::from_bytes(&alice_sk);
+    let alice_master_pk = LogicalIdentityPk::from(alice_signing_key.verifying_key().to_bytes());
+    let alice_device_pk = PhysicalDevicePk::from(alice_signing_key.verifying_key().to_bytes());
+    let bob_sk = [20u8; 32];
+    let bob_signing_key = ed25519_dalek::SigningKey::from_bytes(&bob_sk);
+    let bob_master_pk = LogicalIdentityPk::from(bob_signing_key.verifying_key().to_bytes());
+    let bob_device_pk = PhysicalDevicePk::from(bob_signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAA; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+
+    let alice_node = Arc::new(Mutex::new(MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            alice_device_pk,
+            alice_master_pk,
+            PhysicalDeviceSk::from(alice_sk),
+            StdRng::seed_from_u64(0),
+            tp.clone(),
+        ),
+        MockTransport {
+            local_pk: alice_device_pk,
+        },
+        Storage::open_in_memory().unwrap(),
+        tp.clone(),
+    )));
+    let alice_client = MerkleToxClient::new(alice_node.clone(), conversation_id);
+    let bob_node = Arc::new(Mutex::new(MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            bob_device_pk,
+            bob_master_pk,
+            PhysicalDeviceSk::from(bob_sk),
+            StdRng::seed_from_u64(1),
+            tp.clone(),
+        ),
+        MockTransport {
+            local_pk: bob_device_pk,
+        },
+        Storage::open_in_memory().unwrap(),
+        tp,
+    )));
+
+    // Alice founds the conversation. Unlike the X3DH onboarding test, Bob
+    // is not a member yet.
+    {
+        let mut node_lock = alice_node.lock().await;
+        let MerkleToxNode { engine, store, .. } = &mut *node_lock;
+        engine
+            .identity_manager
+            .add_member(conversation_id, alice_master_pk, 1, 0);
+        let cert = sign_delegation(
+            &alice_signing_key,
+            alice_device_pk,
+            Permissions::ALL,
+            i64::MAX,
+            conversation_id,
+        );
+        engine
+            .identity_manager
+            .authorize_device(
+                &merkle_tox_core::identity::CausalContext::global(),
+                conversation_id,
+                alice_master_pk,
+                &cert,
+                0,
+                0,
+                NodeHash::from([0u8; 32]),
+            )
+            .unwrap();
+        let effects = engine
+            .rotate_conversation_key(conversation_id, store)
+            .unwrap();
+        let now = node_lock.time_provider.now_instant();
+        let now_ms = node_lock.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_lock
+                .process_effect(effect, now, now_ms, &mut dummy_wakeup)
+                .unwrap();
+        }
+    }
+
+    let link = alice_client.create_invite_link(3_600_000).await.unwrap();
+
+    // Bob opens the link, which announces his device with the invite proof.
+    let announcement = {
+        let mut node_lock = bob_node.lock().await;
+        let token = node_lock.join_from_invite(&link).unwrap();
+        assert_eq!(token.conversation_id, conversation_id);
+        assert_eq!(token.bootstrap_devices, vec![alice_device_pk]);
+        let mut heads = node_lock.store.get_heads(&conversation_id);
+        heads.extend(node_lock.store.get_admin_heads(&conversation_id));
+        heads
+            .iter()
+            .filter_map(|h| node_lock.store.get_node(h))
+            .find(|n| {
+                matches!(
+                    n.content,
+                    Content::Control(ControlAction::Announcement { .. })
+                )
+            })
+            .unwrap()
+    };
+    assert!(!announcement.metadata.is_empty());
+    assert!(
+        bob_node
+            .lock()
+            .await
+            .join_from_invite("mtox-invite:AAAA")
+            .is_err()
+    );
+
+    alice_client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash: announcement.hash(),
+            node: announcement,
+        })
+        .await
+        .unwrap();
+
+    // Alice admitted Bob and shared the conversation key with him.
+    let admin_nodes = {
+        let node_lock = alice_node.lock().await;
+        assert!(
+            node_lock
+                .engine
+                .identity_manager
+                .list_members(conversation_id)
+                .iter()
+                .any(|(pk, role, _)| *pk == bob_master_pk && *role == 0)
+        );
+        node_lock
+            .store
+            .get_verified_nodes_by_type(&conversation_id, merkle_tox_core::dag::NodeType::Admin)
+            .unwrap()
+    };
+    // The first KeyWrap comes from Alice's own key rotation.
+    let key_wraps: Vec<_> = admin_nodes
+        .into_iter()
+        .filter(|n| matches!(n.content, Content::KeyWrap { .. }))
+        .collect();
+    assert_eq!(
+        key_wraps.len(),
+        2,
+        "Alice should have shared the conversation key"
+    );
+
+    {
+        let mut node_lock = bob_node.lock().await;
+        for key_wrap in key_wraps {
+            let MerkleToxNode { engine, store, .. } = &mut *node_lock;
+            let effects = engine
+                .handle_node(conversation_id, key_wrap, store, None)
+                .unwrap();
+            let now = node_lock.time_provider.now_instant();
+            let now_ms = node_lock.time_provider.now_system_ms() as u64;
+            let mut dummy_wakeup = now;
+            for effect in effects {
+                node_lock
+                    .process_effect(effect, now, now_ms, &mut dummy_wakeup)
+                    .unwrap();
+            }
+        }
+        assert!(
+            node_lock
+                .engine
+                .conversations
+                .get(&conversation_id)
+                .is_some_and(|c| c.is_established())
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_client_join_with_pow() {
+    let alice_sk = [10u8; 32];
+    let alice_signing_key = ed25519_dalek::SigningKey::from_bytes(&alice_sk);
+    let alice_master_pk = LogicalIdentityPk::from(alice_signing_key.verifying_key().to_bytes());
+    let alice_device_pk = PhysicalDevicePk::from(alice_signing_key.verifying_key().to_bytes());
+    let bob_sk = [20u8; 32];
+    let bob_signing_key = ed25519_dalek::SigningKey::from_bytes(&bob_sk);
+    let bob_master_pk = LogicalIdentityPk::from(bob_signing_key.verifying_key().to_bytes());
+    let bob_device_pk = PhysicalDevicePk::from(bob_signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAA; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+
+    let alice_node = Arc::new(Mutex::new(MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            alice_device_pk,
+            alice_master_pk,
+            PhysicalDeviceSk::from(alice_sk),
+            StdRng::seed_from_u64(0),
+            tp.clone(),
+        ),
+        MockTransport {
+            local_pk: alice_device_pk,
+        },
+        Storage::open_in_memory().unwrap(),
+        tp.clone(),
+    )));
+    let alice_client = MerkleToxClient::new(alice_node.clone(), conversation_id);
+    let bob_node = Arc::new(Mutex::new(MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            bob_device_pk,
+            bob_master_pk,
+            PhysicalDeviceSk::from(bob_sk),
+            StdRng::seed_from_u64(1),
+            tp.clone(),
+        ),
+        MockTransport {
+            local_pk: bob_device_pk,
+        },
+        Storage::open_in_memory().unwrap(),
+        tp,
+    )));
+
+    // Alice founds a conversation that does not admit devices by
+    // proof-of-work yet.
+    {
+        let mut node_lock = alice_node.lock().await;
+        let MerkleToxNode { engine, store, .. } = &mut *node_lock;
+        engine
+            .identity_manager
+            .add_member(conversation_id, alice_master_pk, 1, 0);
+        let cert = sign_delegation(
+            &alice_signing_key,
+            alice_device_pk,
+            Permissions::ALL,
+            i64::MAX,
+            conversation_id,
+        );
+        engine
+            .identity_manager
+            .authorize_device(
+                &merkle_tox_core::identity::CausalContext::global(),
+                conversation_id,
+                alice_master_pk,
+                &cert,
+                0,
+                0,
+                NodeHash::from([0u8; 32]),
+            )
+            .unwrap();
+        let effects = engine
+            .rotate_conversation_key(conversation_id, store)
+            .unwrap();
+        let now = node_lock.time_provider.now_instant();
+        let now_ms = node_lock.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_lock
+                .process_effect(effect, now, now_ms, &mut dummy_wakeup)
+                .unwrap();
+        }
+    }
+
+    let announcement = {
+        let mut node_lock = bob_node.lock().await;
+        node_lock
+            .join_with_pow(conversation_id, &[alice_device_pk], 8)
+            .unwrap();
+        let mut heads = node_lock.store.get_heads(&conversation_id);
+        heads.extend(node_lock.store.get_admin_heads(&conversation_id));
+        heads
+            .iter()
+            .filter_map(|h| node_lock.store.get_node(h))
+            .find(|n| {
+                matches!(
+                    n.content,
+                    Content::Control(ControlAction::Announcement { .. })
+                )
+            })
+            .unwrap()
+    };
+    let is_member = |node_lock: &MerkleToxNode<MockTransport, Storage>| {
+        node_lock
+            .engine
+            .identity_manager
+            .list_members(conversation_id)
+            .iter()
+            .any(|(pk, role, _)| *pk == bob_master_pk && *role == 0)
+    };
+
+    alice_client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash: announcement.hash(),
+            node: announcement.clone(),
+        })
+        .await
+        .unwrap();
+    assert!(!is_member(&*alice_node.lock().await));
+
+    alice_node
+        .lock()
+        .await
+        .engine
+        .conversations
+        .get_mut(&conversation_id)
+        .unwrap()
+        .set_genesis_flags(join_pow_flags(8));
+    alice_client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash: announcement.hash(),
+            node: announcement,
+        })
+        .await
+        .unwrap();
+    assert!(is_member(&*alice_node.lock().await));
+}
+
+#[tokio::test]
+async fn test_client_public_conversation() {
+    let alice_sk = [10u8; 32];
+    let alice_signing_key = ed25519_dalek::SigningKey::from_bytes(&alice_sk);
+    let alice_master_pk = LogicalIdentityPk::from(alice_signing_key.verifying_key().to_bytes());
+    let alice_device_pk = PhysicalDevicePk::from(alice_signing_key.verifying_key().to_bytes());
+    let bob_sk = [20u8; 32];
+    let bob_signing_key = ed25519_dalek::SigningKey::from_bytes(&bob_sk);
+    let bob_master_pk = LogicalIdentityPk::from(bob_signing_key.verifying_key().to_bytes());
+    let bob_device_pk = PhysicalDevicePk::from(bob_signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAA; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+
+    let alice_node = Arc::new(Mutex::new(MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            alice_device_pk,
+            alice_master_pk,
+            PhysicalDeviceSk::from(alice_sk),
+            StdRng::seed_from_u64(0),
+            tp.clone(),
+        ),
+        MockTransport {
+            local_pk: alice_device_pk,
+        },
+        Storage::open_in_memory().unwrap(),
+        tp.clone(),
+    )));
+    let alice_client = MerkleToxClient::new(alice_node.clone(), conversation_id);
+    let bob_node = Arc::new(Mutex::new(MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            bob_device_pk,
+            bob_master_pk,
+            PhysicalDeviceSk::from(bob_sk),
+            StdRng::seed_from_u64(1),
+            tp.clone(),
+        ),
+        MockTransport {
+            local_pk: bob_device_pk,
+        },
+        Storage::open_in_memory().unwrap(),
+        tp,
+    )));
+
+    // Alice founds a public conversation admitting devices by proof-of-work.
+    {
+        let mut node_lock = alice_node.lock().await;
+        let MerkleToxNode { engine, store, .. } = &mut *node_lock;
+        engine
+            .identity_manager
+            .add_member(conversation_id, alice_master_pk, 1, 0);
+        let cert = sign_delegation(
+            &alice_signing_key,
+            alice_device_pk,
+            Permissions::ALL,
+            i64::MAX,
+            conversation_id,
+        );
+        engine
+            .identity_manager
+            .authorize_device(
+                &merkle_tox_core::identity::CausalContext::global(),
+                conversation_id,
+                alice_master_pk,
+                &cert,
+                0,
+                0,
+                NodeHash::from([0u8; 32]),
+            )
+            .unwrap();
+        let effects = engine
+            .rotate_conversation_key(conversation_id, store)
+            .unwrap();
+        engine
+            .conversations
+            .get_mut(&conversation_id)
+            .unwrap()
+            .set_genesis_flags(FLAG_PUBLIC | join_pow_flags(8));
+        let now = node_lock.time_provider.now_instant();
+        let now_ms = node_lock.time_provider.now_system_ms() as u64;
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node_lock
+                .process_effect(effect, now, now_ms, &mut dummy_wakeup)
+                .unwrap();
+        }
+    }
+
+    let announcement = {
+        let mut node_lock = bob_node.lock().await;
+        node_lock
+            .join_with_pow(conversation_id, &[alice_device_pk], 8)
+            .unwrap();
+        let mut heads = node_lock.store.get_heads(&conversation_id);
+        heads.extend(node_lock.store.get_admin_heads(&conversation_id));
+        heads
+            .iter()
+            .filter_map(|h| node_lock.store.get_node(h))
+            .find(|n| {
+                matches!(
+                    n.content,
+                    Content::Control(ControlAction::Announcement { .. })
+                )
+            })
+            .unwrap()
+    };
+    let key_wraps = |node_lock: &MerkleToxNode<MockTransport, Storage>| {
+        node_lock
+            .store
+            .get_verified_nodes_by_type(&conversation_id, merkle_tox_core::dag::NodeType::Admin)
+            .unwrap()
+            .iter()
+            .filter(|n| matches!(n.content, Content::KeyWrap { .. }))
+            .count()
+    };
+    let key_wraps_before = key_wraps(&*alice_node.lock().await);
+
+    alice_client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash: announcement.hash(),
+            node: announcement,
+        })
+        .await
+        .unwrap();
+
+    // Bob is admitted, but no conversation key is wrapped for him.
+    let node_lock = alice_node.lock().await;
+    assert!(
+        node_lock
+            .engine
+            .identity_manager
+            .list_members(conversation_id)
+            .iter()
+            .any(|(pk, _, _)| *pk == bob_master_pk)
+    );
+    assert_eq!(key_wraps(&node_lock), key_wraps_before);
+}
+
+#[tokio::test]
+async fn test_client_read_only() {
+    let self_sk = [16u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB0; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    engine.config.read_only = true;
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    assert!(matches!(
+        client.send_message("hello".to_string()).await,
+        Err(MerkleToxError::ReadOnly)
+    ));
+
+    // Pulses are not answered with an Announcement.
+    let mut pulse = merkle_tox_core::testing::test_node();
+    pulse.content = Content::Control(ControlAction::HandshakePulse);
+    client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash: pulse.hash(),
+            node: pulse,
+        })
+        .await
+        .unwrap();
+    assert!(
+        node.lock()
+            .await
+            .store
+            .get_heads(&conversation_id)
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_concurrent_title_edits_converge() {
+    let set_title = |title: &str, rank: u64, author: u8| {
+        let mut node = merkle_tox_core::testing::test_node();
+        node.author_pk = LogicalIdentityPk::from([author; 32]);
+        node.topological_rank = rank;
+        node.content = Content::Control(ControlAction::SetTitle(title.to_string()));
+        node
+    };
+    // Two admins rename the room concurrently at the same rank, after an
+    // earlier rename that both have seen.
+    let base = set_title("Base", 2, 1);
+    let alice = set_title("Alice's", 3, 1);
+    let bob = set_title("Bob's", 3, 2);
+    let winner = if alice.hash() > bob.hash() {
+        "Alice's"
+    } else {
+        "Bob's"
+    };
+
+    let orders = [
+        [&base, &alice, &bob],
+        [&bob, &alice, &base],
+        [&alice, &base, &bob],
+    ];
+    for order in orders {
+        let mut state = ChatState::default();
+        for node in order {
+            materialize(&mut state, &node.hash(), node);
+        }
+        assert_eq!(state.title, winner);
+        assert_eq!(state.title_version.map(|(rank, _)| rank), Some(3));
+    }
+
+    // A later rename that saw both wins regardless of hash.
+    let merged = set_title("Merged", 4, 1);
+    let mut state = ChatState::default();
+    for node in [&merged, &alice, &bob] {
+        materialize(&mut state, &node.hash(), node);
+    }
+    assert_eq!(state.title, "Merged");
+}
+
+#[test]
+fn test_newest_profile_wins() {
+    let set_profile = |name: &str, rank: u64, timestamp: i64| {
+        let mut node = merkle_tox_core::testing::test_node();
+        node.author_pk = LogicalIdentityPk::from([1u8; 32]);
+        node.topological_rank = rank;
+        node.network_timestamp = timestamp;
+        node.content = Content::Control(ControlAction::SetProfile(Profile {
+            display_name: name.to_string(),
+            ..Default::default()
+        }));
+        node
+    };
+    let old = set_profile("Old", 5, 1000);
+    let new = set_profile("New", 3, 2000);
+    let unnamed = set_profile("", 4, 3000);
+
+    for order in [[&old, &new], [&new, &old]] {
+        let mut state = ChatState::default();
+        for node in order {
+            materialize(&mut state, &node.hash(), node);
+        }
+        let member = &state.members[&old.author_pk];
+        assert_eq!(member.display_name(), Some("New"));
+        assert_eq!(member.profile_version.map(|(t, _)| t), Some(2000));
+    }
+
+    let mut state = ChatState::default();
+    materialize(&mut state, &unnamed.hash(), &unnamed);
+    assert_eq!(state.members[&unnamed.author_pk].display_name(), None);
+}
+
+#[test]
+fn test_closed_conversation_is_archived() {
+    let mut node = merkle_tox_core::testing::test_node();
+    node.content = Content::Control(ControlAction::CloseConversation);
+    let mut state = ChatState::default();
+    assert!(!state.archived);
+    materialize(&mut state, &node.hash(), &node);
+    assert!(state.archived);
+}
+
+#[tokio::test]
+async fn test_history_server_policy_syncs_new_device() {
+    let conversation_id = ConversationId::from([0xAA; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let make_node = |sk: [u8; 32], seed: u64| {
+        let pk = ed25519_dalek::SigningKey::from_bytes(&sk)
+            .verifying_key()
+            .to_bytes();
+        let device_pk = PhysicalDevicePk::from(pk);
+        let engine = MerkleToxEngine::with_sk(
+            device_pk,
+            LogicalIdentityPk::from(pk),
+            PhysicalDeviceSk::from(sk),
+            StdRng::seed_from_u64(seed),
+            tp.clone(),
+        );
+        let transport = MockTransport {
+            local_pk: device_pk,
+        };
+        let store = Storage::open_in_memory().unwrap();
+        (
+            device_pk,
+            Arc::new(Mutex::new(MerkleToxNode::new(
+                engine,
+                transport,
+                store,
+                tp.clone(),
+            ))),
+        )
+    };
+    let (_, vault_node) = make_node([10u8; 32], 0);
+    let (bob_device_pk, bob_node) = make_node([20u8; 32], 1);
+    let vault_client = MerkleToxClient::with_policy(
+        vault_node.clone(),
+        conversation_id,
+        Arc::new(HistoryServerPolicy::new(100)),
+    );
+
+    // Bob's new device announces itself.
+    let events: Vec<NodeEvent> = {
+        let mut node_lock = bob_node.lock().await;
+        let MerkleToxNode { engine, store, .. } = &mut *node_lock;
+        let effects = engine.author_announcement(conversation_id, store).unwrap();
+        effects
+            .into_iter()
+            .filter_map(|e| match e {
+                Effect::EmitEvent(ev) => Some(ev),
+                _ => None,
+            })
+            .collect()
+    };
+    assert!(!events.is_empty());
+    for e in events {
+        vault_client.handle_event(e).await.unwrap();
+    }
+
+    // The vault opened a sync session with the new device on its own.
+    let node_lock = vault_node.lock().await;
+    assert!(
+        node_lock
+            .engine
+            .sessions
+            .contains_key(&(bob_device_pk, conversation_id))
+    );
+}
+
+#[tokio::test]
+async fn test_client_recover_from_compromise() {
+    let self_sk = [10u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xAA; 32]);
+
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    engine
+        .identity_manager
+        .add_member(conversation_id, self_master_pk, 1, 0);
+    let cert = sign_delegation(
+        &signing_key,
+        self_device_pk,
+        Permissions::ALL,
+        i64::MAX,
+        conversation_id,
+    );
+    engine
+        .identity_manager
+        .authorize_device(
+            &merkle_tox_core::identity::CausalContext::global(),
+            conversation_id,
+            self_master_pk,
+            &cert,
+            0,
+            0,
+            NodeHash::from([0u8; 32]),
+        )
+        .unwrap();
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let stolen_pk = PhysicalDevicePk::from([22u8; 32]);
+    client
+        .authorize_device(stolen_pk, Permissions::MESSAGE, i64::MAX)
+        .await
+        .unwrap();
+    client.refresh_state().await.unwrap();
+    assert!(client.state().await.authorized_devices.contains(&stolen_pk));
+
+    let recovery = client.recover_from_compromise(stolen_pk).await.unwrap();
+    assert_eq!(recovery.device_pk, stolen_pk);
+    assert!(!recovery.rewrapped.contains(&stolen_pk));
+    assert!(
+        node.lock()
+            .await
+            .store
+            .get_node(&recovery.revocation)
+            .is_some()
+    );
+
+    client.refresh_state().await.unwrap();
+    assert!(!client.state().await.authorized_devices.contains(&stolen_pk));
+    assert!(
+        client
+            .recover_from_compromise(self_device_pk)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_client_verify_identity() {
+    let self_sk = [17u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB1; 32]);
+    let other_pk = LogicalIdentityPk::from([0x77; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    assert_eq!(
+        client.safety_number(other_pk).await,
+        SafetyNumber::for_conversation(&conversation_id, &other_pk, &self_master_pk)
+    );
+
+    let hash = client.verify_identity(other_pk).await.unwrap();
+    let node_data = node.lock().await.store.get_node(&hash).unwrap();
+    client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash,
+            node: node_data,
+        })
+        .await
+        .unwrap();
+    let state = client.state().await;
+    assert_eq!(
+        state.verified_identities.get(&other_pk),
+        Some(&self_master_pk)
+    );
+}
+
+#[tokio::test]
+async fn test_client_history_visibility() {
+    let self_sk = [18u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB2; 32]);
+    let granted_pk = LogicalIdentityPk::from([0x78; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let hash = client
+        .set_history_visibility(HistoryVisibility::SinceJoin, vec![granted_pk])
+        .await
+        .unwrap();
+    let node_data = node.lock().await.store.get_node(&hash).unwrap();
+    client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash,
+            node: node_data,
+        })
+        .await
+        .unwrap();
+
+    let state = client.state().await;
+    assert_eq!(state.history_visibility, HistoryVisibility::SinceJoin);
+    assert!(state.history_access.contains(&granted_pk));
+    assert_eq!(
+        node.lock()
+            .await
+            .engine
+            .history_visibility(&conversation_id),
+        HistoryVisibility::SinceJoin
+    );
+}
+
+struct SnapshotEveryTwo;
+
+impl PolicyHandler for SnapshotEveryTwo {
+    fn should_authorize(&self, author_pk: &PublicKey, device_pk: &PublicKey) -> bool {
+        DefaultPolicy.should_authorize(author_pk, device_pk)
+    }
+
+    fn should_rotate_keys(&self, state: &ChatState) -> bool {
+        DefaultPolicy.should_rotate_keys(state)
+    }
+
+    fn should_respond_to_pulse(&self, sender_pk: &PublicKey) -> bool {
+        DefaultPolicy.should_respond_to_pulse(sender_pk)
+    }
+
+    fn snapshot_interval(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+#[tokio::test]
+async fn test_client_state_snapshot() {
+    let self_sk = [19u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB3; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let policy = Arc::new(SnapshotEveryTwo);
+    let client = MerkleToxClient::with_policy(node.clone(), conversation_id, policy.clone());
+
+    let apply = async |hash: NodeHash| {
+        let node_data = node.lock().await.store.get_node(&hash).unwrap();
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node: node_data,
+            })
+            .await
+            .unwrap();
+    };
+    apply(client.set_title("First".to_string()).await.unwrap()).await;
+    assert!(
+        node.lock()
+            .await
+            .store
+            .get_state_snapshot(&conversation_id)
+            .is_none()
+    );
+    apply(client.send_message("one".to_string()).await.unwrap()).await;
+    let mut snapshot = node
+        .lock()
+        .await
+        .store
+        .get_state_snapshot(&conversation_id)
+        .unwrap();
+    assert_eq!(snapshot.verified_nodes, 2);
+    assert_eq!(snapshot.rank, client.state().await.max_verified_rank);
+    // Not covered by the snapshot.
+    apply(client.send_message("two".to_string()).await.unwrap()).await;
+
+    // Mark the snapshot to tell whether a refresh starts from it.
+    let mut saved: ChatState = tox_proto::deserialize(&snapshot.data).unwrap();
+    saved.title = "From snapshot".to_string();
+    snapshot.data = tox_proto::serialize(&saved).unwrap();
+    node.lock()
+        .await
+        .store
+        .put_state_snapshot(&snapshot)
+        .unwrap();
+
+    let texts = |state: &ChatState| -> Vec<String> {
+        state
+            .messages
+            .iter()
+            .filter_map(|m| match &m.content {
+                Content::Text(t) => Some(t.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let client2 = MerkleToxClient::with_policy(node.clone(), conversation_id, policy.clone());
+    client2.refresh_state().await.unwrap();
+    let state = client2.state().await;
+    assert_eq!(state.title, "From snapshot");
+    assert_eq!(texts(&state), vec!["one", "two"]);
+    assert_eq!(state.verified_nodes, 3);
+    assert_eq!(state.heads, client.state().await.heads);
+
+    // A snapshot missing a node at or below its rank is not used.
+    snapshot.verified_nodes -= 1;
+    node.lock()
+        .await
+        .store
+        .put_state_snapshot(&snapshot)
+        .unwrap();
+    client2.refresh_state().await.unwrap();
+    let state = client2.state().await;
+    assert_eq!(state.title, "First");
+    assert_eq!(texts(&state), vec!["one", "two"]);
+    assert_eq!(state.verified_nodes, 3);
+}
+
+#[tokio::test]
+async fn test_client_delivery_state() {
+    let self_sk = [20u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB4; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let apply = async |hash: NodeHash| {
+        let node_data = node.lock().await.store.get_node(&hash).unwrap();
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node: node_data,
+            })
+            .await
+            .unwrap();
+    };
+    let first = client.send_message("one".to_string()).await.unwrap();
+    apply(first).await;
+    let second = client.send_message("two".to_string()).await.unwrap();
+    apply(second).await;
+    let delivery = async |hash: NodeHash| {
+        let state = client.state().await;
+        state
+            .messages
+            .iter()
+            .find(|m| m.hash == hash)
+            .unwrap()
+            .delivery()
+    };
+    assert_eq!(delivery(first).await, DeliveryState::Sent);
+
+    let peer = PhysicalDevicePk::from([7u8; 32]);
+    client
+        .handle_event(NodeEvent::NodeDelivered {
+            conversation_id,
+            hash: first,
+            device_pk: peer,
+        })
+        .await
+        .unwrap();
+    assert_eq!(delivery(first).await, DeliveryState::Delivered(1));
+    assert_eq!(delivery(second).await, DeliveryState::Sent);
+
+    // A reply from another member on top of the second message reads both.
+    let reader = LogicalIdentityPk::from([8u8; 32]);
+    let reply = {
+        let node_lock = node.lock().await;
+        let parent = node_lock.store.get_node(&second).unwrap();
+        MerkleNode {
+            parents: vec![second],
+            author_pk: reader,
+            sender_pk: peer,
+            sequence_number: 1,
+            topological_rank: parent.topological_rank + 1,
+            network_timestamp: parent.network_timestamp + 1,
+            content: Content::Text("seen".to_string()),
+            metadata: vec![],
+            authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+            pow_nonce: 0,
+        }
+    };
+    {
+        let mut state = client.state().await;
+        let node_lock = node.lock().await;
+        mark_read(&mut state, &node_lock.store, &self_master_pk, &reply);
+        for msg in &state.messages {
+            assert_eq!(msg.delivery(), DeliveryState::Read);
+            assert!(msg.read_by.contains(&reader));
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_client_queued_messages() {
+    let self_sk = [22u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB5; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let apply = async |hash: NodeHash| {
+        let node_data = node.lock().await.store.get_node(&hash).unwrap();
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node: node_data,
+            })
+            .await
+            .unwrap();
+    };
+    let draft = client.queue_message("helo".to_string()).await.unwrap();
+    apply(draft).await;
+    let other = client.queue_message("oops".to_string()).await.unwrap();
+    apply(other).await;
+    let pending = async || -> Vec<NodeHash> {
+        let messages = client.pending_messages().await;
+        messages.iter().map(|m| m.hash).collect()
+    };
+    assert_eq!(pending().await, vec![draft, other]);
+
+    apply(
+        client
+            .edit_queued(draft, "hello".to_string())
+            .await
+            .unwrap(),
+    )
+    .await;
+    apply(client.delete_queued(other).await.unwrap()).await;
+    let state = client.state().await;
+    assert!(matches!(&state.messages[0].content, Content::Text(t) if t == "hello"));
+    assert!(state.messages[1].is_redacted);
+    assert_eq!(pending().await, vec![draft]);
+
+    // Without peers there is nobody to push to.
+    assert_eq!(client.retry_queued().await.unwrap(), 0);
+
+    client
+        .handle_event(NodeEvent::NodeDelivered {
+            conversation_id,
+            hash: draft,
+            device_pk: PhysicalDevicePk::from([7u8; 32]),
+        })
+        .await
+        .unwrap();
+    assert!(pending().await.is_empty());
+    assert!(
+        client
+            .edit_queued(draft, "too late".to_string())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_client_unread_mentions() {
+    let self_sk = [23u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB6; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let bob = LogicalIdentityPk::from([9u8; 32]);
+    let mention = |pk| TextEntities {
+        entities: vec![TextEntity {
+            start: 3,
+            end: 7,
+            kind: EntityKind::Mention(pk),
+        }],
+    };
+    assert!(
+        client
+            .send_message_with_entities("hi".to_string(), mention(bob))
+            .await
+            .is_err()
+    );
+    let hash = client
+        .send_message_with_entities("hi @bob".to_string(), mention(bob))
+        .await
+        .unwrap();
+    let own = node.lock().await.store.get_node(&hash).unwrap();
+    client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash,
+            node: own.clone(),
+        })
+        .await
+        .unwrap();
+    assert!(client.state().await.messages[0].mentions(&bob));
+
+    let from_bob = |seq: u64, text: &str, metadata: Vec<u8>| MerkleNode {
+        parents: vec![hash],
+        author_pk: bob,
+        sender_pk: PhysicalDevicePk::from([9u8; 32]),
+        sequence_number: seq,
+        topological_rank: own.topological_rank + seq,
+        network_timestamp: own.network_timestamp + seq as i64,
+        content: Content::Text(text.to_string()),
+        metadata,
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    };
+    let replies = [
+        from_bob(1, "hi", vec![]),
+        from_bob(2, "yo @you", mention(self_master_pk).to_metadata()),
+    ];
+    for reply in &replies {
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash: reply.hash(),
+                node: reply.clone(),
+            })
+            .await
+            .unwrap();
+    }
+    let state = client.state().await;
+    assert_eq!((state.unread_messages, state.unread_mentions), (2, 1));
+    assert!(state.messages[2].mentions(&self_master_pk));
+
+    client.clear_unread().await;
+    let state = client.state().await;
+    assert_eq!((state.unread_messages, state.unread_mentions), (0, 0));
+
+    // Writing to the conversation reads everything before.
+    let mut state = ChatState::default();
+    for reply in &replies {
+        materialize(&mut state, &reply.hash(), reply);
+        count_unread(&mut state, &self_master_pk, reply);
+    }
+    assert_eq!(state.unread_messages, 2);
+    count_unread(&mut state, &self_master_pk, &own);
+    assert_eq!((state.unread_messages, state.unread_mentions), (0, 0));
+}
+
+#[tokio::test]
+async fn test_client_polls() {
+    let self_sk = [24u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB7; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let apply = async |hash: NodeHash| {
+        let node_data = node.lock().await.store.get_node(&hash).unwrap();
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node: node_data,
+            })
+            .await
+            .unwrap();
+    };
+    let options = vec!["yes".to_string(), "no".to_string()];
+    assert!(
+        client
+            .create_poll("Ship it?".to_string(), vec!["yes".to_string()], false, None)
+            .await
+            .is_err()
+    );
+    let poll = client
+        .create_poll("Ship it?".to_string(), options, false, None)
+        .await
+        .unwrap();
+    apply(poll).await;
+    assert!(client.vote(poll, vec![0, 1]).await.is_err());
+    apply(client.vote(poll, vec![0]).await.unwrap()).await;
+    assert!(client.vote(poll, vec![1]).await.is_err());
+
+    // Of two concurrent votes of one identity, the first by version counts.
+    let voter = LogicalIdentityPk::from([9u8; 32]);
+    let parent = node.lock().await.store.get_node(&poll).unwrap();
+    let votes: Vec<MerkleNode> = (0..2u64)
+        .map(|choice| MerkleNode {
+            parents: vec![poll],
+            author_pk: voter,
+            sender_pk: PhysicalDevicePk::from([9u8; 32]),
+            sequence_number: choice + 1,
+            topological_rank: parent.topological_rank + 1,
+            network_timestamp: parent.network_timestamp + 1,
+            content: Content::PollVote {
+                poll_hash: poll,
+                choices: vec![choice as u32],
+            },
+            metadata: vec![],
+            authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+            pow_nonce: 0,
+        })
+        .collect();
+    let first = votes.iter().min_by_key(|v| v.hash()).unwrap();
+    for v in votes.iter().rev() {
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash: v.hash(),
+                node: v.clone(),
+            })
+            .await
+            .unwrap();
+    }
+    let Content::PollVote { choices, .. } = &first.content else {
+        unreachable!()
+    };
+    let mut expected = vec![1, 0];
+    expected[choices[0] as usize] += 1;
+    let state = client.state().await;
+    assert_eq!(state.messages[0].tally(), Some(expected));
+    assert!(!state.messages[0].poll.as_ref().unwrap().closed);
+
+    apply(client.close_poll(poll).await.unwrap()).await;
+    let state = client.state().await;
+    assert!(state.messages[0].poll.as_ref().unwrap().closed);
+}
+
+#[tokio::test]
+async fn test_client_sticker_packs() {
+    let self_sk = [25u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB8; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let apply = async |hash: NodeHash| {
+        let node_data = node.lock().await.store.get_node(&hash).unwrap();
+        client
+            .handle_event(NodeEvent::NodeVerified {
+                conversation_id,
+                hash,
+                node: node_data,
+            })
+            .await
+            .unwrap();
+    };
+    let sticker = |shortcode: &str, image: &[u8]| {
+        (
+            shortcode.to_string(),
+            "image/png".to_string(),
+            image.to_vec(),
+        )
+    };
+
+    // Invalid packs are rejected before their images are stored.
+    assert!(
+        client
+            .install_sticker_pack("cats".to_string(), vec![sticker("a b", b"spaced")])
+            .await
+            .is_err()
+    );
+    let spaced = NodeHash::from(*blake3::hash(b"spaced").as_bytes());
+    assert!(!node.lock().await.store.has_blob(&spaced));
+
+    apply(
+        client
+            .install_sticker_pack("cats".to_string(), vec![sticker("cat", b"meow")])
+            .await
+            .unwrap(),
+    )
+    .await;
+    apply(
+        client
+            .install_sticker_pack(
+                "cats".to_string(),
+                vec![sticker("cat", b"meow"), sticker("kitten", b"mew")],
+            )
+            .await
+            .unwrap(),
+    )
+    .await;
+    let packs = client.sticker_packs().await;
+    assert_eq!(packs.len(), 1);
+    assert_eq!(packs[0].author_pk, self_master_pk);
+    let kitten = packs[0].stickers[1].clone();
+    assert_eq!(kitten.shortcode, "kitten");
+    assert_eq!(kitten.size, 3);
+
+    // Reactions resolve custom emoji to their images.
+    let msg = client.send_message("hi".to_string()).await.unwrap();
+    apply(msg).await;
+    apply(
+        client
+            .send_reaction(
+                msg,
+                EmojiSource::Custom {
+                    hash: *kitten.hash.as_bytes(),
+                    shortcode: kitten.shortcode.clone(),
+                },
+            )
+            .await
+            .unwrap(),
+    )
+    .await;
+    let state = client.state().await;
+    let image = state.messages[0].custom_emoji["kitten"];
+    assert_eq!(image, kitten.hash);
+    assert!(state.messages[0].reactions["kitten"].contains(&self_master_pk));
+
+    let mut reader = client.open_sticker(&image).await.unwrap().unwrap();
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data).await.unwrap();
+    assert_eq!(data, b"mew");
+
+    // Missing images are fetched on demand.
+    let missing = NodeHash::from([0x55; 32]);
+    assert!(client.open_sticker(&missing).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_client_set_profile() {
+    let self_sk = [26u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let conversation_id = ConversationId::from([0xB9; 32]);
+
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = Arc::new(Mutex::new(MerkleToxNode::new(engine, transport, store, tp)));
+    let client = MerkleToxClient::new(node.clone(), conversation_id);
+
+    let long_name = "x".repeat(merkle_tox_core::dag::MAX_DISPLAY_NAME_LEN + 1);
+    assert!(
+        client
+            .set_profile(long_name, String::new(), None)
+            .await
+            .is_err()
+    );
+
+    let hash = client
+        .set_profile(
+            "Alice".to_string(),
+            "Away".to_string(),
+            Some(b"avatar".to_vec()),
+        )
+        .await
+        .unwrap();
+    let profile_node = node.lock().await.store.get_node(&hash).unwrap();
+    // Profiles are device-signed, like other control actions.
+    assert!(matches!(
+        profile_node.authentication,
+        NodeAuth::Signature(_)
+    ));
+    client
+        .handle_event(NodeEvent::NodeVerified {
+            conversation_id,
+            hash,
+            node: profile_node,
+        })
+        .await
+        .unwrap();
+
+    let profile = client.profile(&self_master_pk).await.unwrap();
+    assert_eq!(profile.display_name, "Alice");
+    assert_eq!(profile.status, "Away");
+    let avatar = profile.avatar.unwrap();
+    assert_eq!(avatar, NodeHash::from(*blake3::hash(b"avatar").as_bytes()));
+    assert!(node.lock().await.store.has_blob(&avatar));
+}
@@ -0,0 +1,225 @@
+use merkle_tox_client::MerkleToxClientManager;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ConversationId, KConv, LogicalIdentityPk, NodeHash, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::conversation::Established;
+use merkle_tox_core::engine::{Conversation, ConversationData, Effect, MerkleToxEngine};
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::{NodeEvent, Transport, TransportError};
+use merkle_tox_sqlite::Storage;
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, broadcast};
+
+struct MockTransport {
+    local_pk: PhysicalDevicePk,
+}
+
+impl Transport for MockTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.local_pk
+    }
+    fn send_raw(&self, _to: PhysicalDevicePk, _data: Vec<u8>) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
+
+type Node = Arc<Mutex<MerkleToxNode<MockTransport, Storage>>>;
+
+fn make_node() -> Node {
+    make_node_with_time().0
+}
+
+fn make_node_with_time() -> (Node, Arc<ManualTimeProvider>) {
+    let self_sk = [21u8; 32];
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&self_sk);
+    let self_master_pk = LogicalIdentityPk::from(signing_key.verifying_key().to_bytes());
+    let self_device_pk = PhysicalDevicePk::from(signing_key.verifying_key().to_bytes());
+    let transport = MockTransport {
+        local_pk: self_device_pk,
+    };
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let engine = MerkleToxEngine::with_sk(
+        self_device_pk,
+        self_master_pk,
+        PhysicalDeviceSk::from(self_sk),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = Storage::open_in_memory().unwrap();
+    let node = MerkleToxNode::new(engine, transport, store, tp.clone());
+    (Arc::new(Mutex::new(node)), tp)
+}
+
+/// Authors a text message, passing the effects through the node so that
+/// its event handler sees them.
+async fn send_text(node: &Node, conversation_id: ConversationId, text: &str) -> NodeHash {
+    let mut node_lock = node.lock().await;
+    let node_ref = &mut *node_lock;
+    let effects = node_ref
+        .engine
+        .author_node(
+            conversation_id,
+            Content::Text(text.to_string()),
+            vec![],
+            &node_ref.store,
+        )
+        .unwrap();
+    let hash = effects
+        .iter()
+        .find_map(|e| match e {
+            Effect::WriteStore(_, n, _) => Some(n.hash()),
+            _ => None,
+        })
+        .unwrap();
+    let now = node_ref.time_provider.now_instant();
+    let now_ms = node_ref.time_provider.now_system_ms() as u64;
+    let mut dummy_wakeup = now;
+    node_ref
+        .process_effects(effects, now, now_ms, &mut dummy_wakeup)
+        .unwrap();
+    hash
+}
+
+async fn next_verified(rx: &mut broadcast::Receiver<NodeEvent>) -> (ConversationId, NodeHash) {
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if let NodeEvent::NodeVerified {
+            conversation_id,
+            hash,
+            ..
+        } = event
+        {
+            return (conversation_id, hash);
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_manager_discovers_conversations() {
+    let node = make_node();
+    let manager = MerkleToxClientManager::new(node.clone());
+    manager.attach().await;
+    let mut rx = manager.subscribe();
+
+    let conv_a = ConversationId::from([0xC1; 32]);
+    let conv_b = ConversationId::from([0xC2; 32]);
+    let a1 = send_text(&node, conv_a, "a1").await;
+    assert_eq!(next_verified(&mut rx).await, (conv_a, a1));
+    let b1 = send_text(&node, conv_b, "b1").await;
+    assert_eq!(next_verified(&mut rx).await, (conv_b, b1));
+    let a2 = send_text(&node, conv_a, "a2").await;
+    assert_eq!(next_verified(&mut rx).await, (conv_a, a2));
+
+    assert_eq!(manager.clients().await.len(), 2);
+    // The node that revealed a conversation is applied once.
+    let state = manager.get(&conv_a).await.unwrap().state().await;
+    let hashes: Vec<_> = state.messages.iter().map(|m| m.hash).collect();
+    assert_eq!(hashes, vec![a1, a2]);
+    let state = manager.get(&conv_b).await.unwrap().state().await;
+    assert_eq!(state.messages.len(), 1);
+
+    // Created on demand, and the same client afterwards.
+    let conv_c = ConversationId::from([0xC3; 32]);
+    assert!(manager.get(&conv_c).await.is_none());
+    let c = manager.get_or_create(conv_c).await.unwrap();
+    assert!(Arc::ptr_eq(
+        &c,
+        &manager.get_or_create(conv_c).await.unwrap()
+    ));
+    assert_eq!(manager.clients().await.len(), 3);
+}
+
+#[tokio::test]
+async fn test_manager_profiles_across_conversations() {
+    let (node, tp) = make_node_with_time();
+    let manager = MerkleToxClientManager::new(node.clone());
+    manager.attach().await;
+    let mut rx = manager.subscribe();
+    let self_pk = node.lock().await.engine.self_logical_pk;
+
+    let conv_a = ConversationId::from([0xD1; 32]);
+    let conv_b = ConversationId::from([0xD2; 32]);
+    let a1 = send_text(&node, conv_a, "a1").await;
+    assert_eq!(next_verified(&mut rx).await, (conv_a, a1));
+    let b1 = send_text(&node, conv_b, "b1").await;
+    assert_eq!(next_verified(&mut rx).await, (conv_b, b1));
+    assert!(manager.profile(&self_pk).await.is_none());
+
+    let published = manager
+        .publish_profile("Old".to_string(), String::new(), None)
+        .await;
+    assert_eq!(published.len(), 2);
+    for _ in 0..2 {
+        next_verified(&mut rx).await;
+    }
+    assert_eq!(manager.profile(&self_pk).await.unwrap().display_name, "Old");
+
+    // A newer profile in one conversation wins, though that conversation's
+    // ranks are lower.
+    for text in ["b2", "b3", "b4"] {
+        send_text(&node, conv_b, text).await;
+        next_verified(&mut rx).await;
+    }
+    tp.advance(Duration::from_secs(1));
+    let client_a = manager.get(&conv_a).await.unwrap();
+    client_a
+        .set_profile("New".to_string(), String::new(), None)
+        .await
+        .unwrap();
+    next_verified(&mut rx).await;
+    assert_eq!(manager.profile(&self_pk).await.unwrap().display_name, "New");
+    let client_b = manager.get(&conv_b).await.unwrap();
+    assert_eq!(
+        client_b.profile(&self_pk).await.unwrap().display_name,
+        "Old"
+    );
+}
+
+#[tokio::test]
+async fn test_manager_creates_channels() {
+    let node = make_node();
+    let manager = MerkleToxClientManager::new(node.clone());
+    manager.attach().await;
+    let mut rx = manager.subscribe();
+
+    let parent_id = ConversationId::from([0xE1; 32]);
+    {
+        let mut node_lock = node.lock().await;
+        let k_conv = KConv::from([0x42; 32]);
+        node_lock
+            .store
+            .put_conversation_key(&parent_id, 0, k_conv.clone())
+            .unwrap();
+        node_lock.engine.conversations.insert(
+            parent_id,
+            Conversation::Established(ConversationData::<Established>::new(parent_id, k_conv, 0)),
+        );
+    }
+    let p1 = send_text(&node, parent_id, "p1").await;
+    assert_eq!(next_verified(&mut rx).await, (parent_id, p1));
+
+    let channel = manager
+        .create_channel(parent_id, "general".to_string())
+        .await
+        .unwrap();
+    let channel_id = channel.state().await.conversation_id;
+    assert_eq!(next_verified(&mut rx).await.0, parent_id);
+    let parent = manager.get(&parent_id).await.unwrap().state().await;
+    assert_eq!(parent.channels.len(), 1);
+    assert_eq!(parent.channels[0].conversation_id, channel_id);
+    assert_eq!(parent.channels[0].name, "general");
+
+    // Channel messages go to the channel's client only.
+    let c1 = send_text(&node, channel_id, "c1").await;
+    assert_eq!(next_verified(&mut rx).await, (channel_id, c1));
+    assert_eq!(channel.state().await.messages.len(), 1);
+    let parent = manager.get(&parent_id).await.unwrap().state().await;
+    assert_eq!(parent.messages.len(), 1);
+}
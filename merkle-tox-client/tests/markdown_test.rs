@@ -0,0 +1,87 @@
+use merkle_tox_client::markdown::{from_markdown, to_markdown};
+use merkle_tox_core::dag::LogicalIdentityPk;
+use merkle_tox_core::entities::{EntityKind, TextEntity};
+
+fn entity(start: u32, end: u32, kind: EntityKind) -> TextEntity {
+    TextEntity { start, end, kind }
+}
+
+#[test]
+fn test_markdown_round_trip() {
+    let link = EntityKind::Link("https://tox.chat/a_(b)".to_string());
+    let cases = [
+        ("plain", vec![]),
+        (
+            "bold italic",
+            vec![
+                entity(0, 11, EntityKind::Bold),
+                entity(5, 11, EntityKind::Italic),
+            ],
+        ),
+        ("run `cargo *`", vec![entity(4, 13, EntityKind::Code)]),
+        (
+            "the end is near",
+            vec![
+                entity(4, 15, EntityKind::Spoiler),
+                entity(4, 7, EntityKind::Bold),
+            ],
+        ),
+        (
+            "click here",
+            vec![entity(6, 10, link.clone()), entity(6, 10, EntityKind::Bold)],
+        ),
+        (
+            "ab",
+            vec![
+                entity(0, 1, EntityKind::Bold),
+                entity(1, 2, EntityKind::Bold),
+            ],
+        ),
+        ("2*3_4|5 [x] \\", vec![]),
+        ("ünï", vec![entity(0, 2, EntityKind::Italic)]),
+    ];
+    for (text, entities) in cases {
+        let markdown = to_markdown(text, &entities);
+        let (parsed, parsed_entities) = from_markdown(&markdown);
+        assert_eq!(
+            (parsed.as_str(), &parsed_entities.entities),
+            (text, &entities),
+            "{}",
+            markdown
+        );
+        assert!(parsed_entities.validate(&parsed).is_ok());
+    }
+}
+
+#[test]
+fn test_markdown_parsing() {
+    let (text, entities) = from_markdown("**hi** _there_ ||x|| `a\\`b` [site](https://tox.chat)");
+    assert_eq!(text, "hi there x a`b site");
+    assert_eq!(
+        entities.entities,
+        vec![
+            entity(0, 2, EntityKind::Bold),
+            entity(3, 8, EntityKind::Italic),
+            entity(9, 10, EntityKind::Spoiler),
+            entity(11, 14, EntityKind::Code),
+            entity(15, 19, EntityKind::Link("https://tox.chat".to_string())),
+        ]
+    );
+
+    // Unmatched and empty delimiters are text.
+    for markdown in [
+        "**open", "_", "****", "``", "[x]", "[](y)", "[x]()", "a \\**b",
+    ] {
+        let (text, entities) = from_markdown(markdown);
+        assert!(entities.entities.is_empty(), "{}", markdown);
+        assert_eq!(text, markdown.replace("\\*", "*"));
+    }
+
+    // Mentions have no markdown form.
+    let mention = entity(
+        0,
+        4,
+        EntityKind::Mention(LogicalIdentityPk::from([1u8; 32])),
+    );
+    assert_eq!(to_markdown("@bob", &[mention]), "@bob");
+}
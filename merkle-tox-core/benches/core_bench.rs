@@ -107,6 +107,7 @@ fn bench_merkle_node(c: &mut Criterion) {
         mime_type: "application/octet-stream".to_string(),
         size: 1024 * 1024,
         metadata: vec![0; 32],
+        preview: None,
     });
     g.bench_function("serialize_blob_node", |b| {
         b.iter(|| black_box(serialize(black_box(&blob_node)).unwrap()))
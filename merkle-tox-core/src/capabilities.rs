@@ -0,0 +1,108 @@
+//! Protocol version and feature negotiation.
+//!
+//! Peers exchange their protocol version and feature bits in `CapsAnnounce`
+//! and `CapsAck`. Each side computes the same [`Negotiated`] result: the
+//! lower of the two versions, and the protocol extensions both announced.
+//! A session only uses what was negotiated, so a peer that knows fewer
+//! features, or none, keeps getting the baseline protocol. Messages that
+//! need an extension the session did not negotiate are dropped on receipt
+//! (see [`required_feature`]).
+//!
+//! A new message type or encoding takes the next free feature bit, adds it
+//! to [`SUPPORTED_FEATURES`] and to [`FEATURE_NAMES`], and is registered in
+//! [`required_feature`].
+
+use crate::ProtocolMessage;
+
+/// Protocol version this engine speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version this engine still talks to. Announcements of
+/// older versions are answered with a `HandshakeError`.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// `CapsAnnounce` feature bit: the peer accepts `SyncHeadsDelta`, standalone
+/// or piggybacked on `MerkleNode` messages.
+pub const FEATURE_DELTA_HEADS: u64 = 0x08;
+/// `CapsAnnounce` feature bit: the sender is a read-only observer. It syncs
+/// and verifies conversations but never authors nodes.
+pub const FEATURE_READ_ONLY: u64 = 0x10;
+/// `CapsAnnounce` feature bit: the peer reads engine messages sent behind a
+/// routing header (see [`crate::classify`]).
+pub const FEATURE_ROUTING_HEADER: u64 = 0x20;
+/// `CapsAnnounce` feature bit: the peer unpacks `Batch` messages of
+/// coalesced small messages (see `tox_sequenced::coalesce`).
+pub const FEATURE_BATCH: u64 = 0x40;
+/// Feature bits advertised in `CapsAnnounce` and `CapsAck`.
+pub const SUPPORTED_FEATURES: u64 = FEATURE_DELTA_HEADS | FEATURE_ROUTING_HEADER | FEATURE_BATCH;
+/// Feature bits describing the sender rather than a protocol extension. They
+/// are kept from the peer's announcement without being negotiated.
+pub const ROLE_FEATURES: u64 = FEATURE_READ_ONLY;
+
+/// Names of the known feature bits, for logs and diagnostics.
+pub const FEATURE_NAMES: &[(u64, &str)] = &[
+    (FEATURE_DELTA_HEADS, "delta-heads"),
+    (FEATURE_READ_ONLY, "read-only"),
+    (FEATURE_ROUTING_HEADER, "routing-header"),
+    (FEATURE_BATCH, "batch"),
+];
+
+/// Names of the known bits set in `features`.
+pub fn feature_names(features: u64) -> Vec<&'static str> {
+    FEATURE_NAMES
+        .iter()
+        .filter(|(bit, _)| features & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// What a session agreed on with its peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub version: u32,
+    /// Extensions both peers support, and the peer's role bits.
+    pub features: u64,
+}
+
+impl Negotiated {
+    /// Before or without a handshake: the oldest version and no features.
+    pub const BASELINE: Self = Self {
+        version: MIN_PROTOCOL_VERSION,
+        features: 0,
+    };
+
+    /// Whether `feature` was negotiated (or, for a role bit, announced).
+    pub fn has(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+}
+
+impl Default for Negotiated {
+    fn default() -> Self {
+        Self::BASELINE
+    }
+}
+
+/// Negotiates with a peer that announced `peer_version` and
+/// `peer_features`, given the features this engine announced. Bits this
+/// engine does not know are ignored. `None` if the peer's version is too
+/// old to talk to.
+pub fn negotiate(local_features: u64, peer_version: u32, peer_features: u64) -> Option<Negotiated> {
+    if peer_version < MIN_PROTOCOL_VERSION {
+        return None;
+    }
+    Some(Negotiated {
+        version: peer_version.min(PROTOCOL_VERSION),
+        features: (local_features & peer_features & SUPPORTED_FEATURES)
+            | (peer_features & ROLE_FEATURES),
+    })
+}
+
+/// The feature a peer must have negotiated before sending `msg`.
+pub fn required_feature(msg: &ProtocolMessage) -> Option<u64> {
+    match msg {
+        ProtocolMessage::SyncHeadsDelta(_) | ProtocolMessage::MerkleNodeWithHeads { .. } => {
+            Some(FEATURE_DELTA_HEADS)
+        }
+        _ => None,
+    }
+}
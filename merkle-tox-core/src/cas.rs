@@ -1,8 +1,11 @@
 use crate::dag::{NodeHash, PhysicalDevicePk};
+use crate::error::{MerkleToxError, MerkleToxResult};
+use crate::sync::BlobStore;
 use std::collections::{HashMap, HashSet};
-use std::io::Read;
-use std::time::{Duration, Instant};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::Duration;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 #[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
 pub enum BlobStatus {
@@ -10,6 +13,15 @@ pub enum BlobStatus {
     Downloading,
     Available,
     Error,
+    /// Download aborted locally; not restarted by peer announcements.
+    Cancelled,
+}
+
+impl BlobStatus {
+    /// True for blobs whose download has not finished or been abandoned.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, BlobStatus::Pending | BlobStatus::Downloading)
+    }
 }
 
 /// Metadata for large binary object.
@@ -63,6 +75,111 @@ impl BlobData {
 
 pub const CHUNK_SIZE: u64 = 64 * 1024; // 64KB
 pub const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often a download without seeders re-queries connected peers.
+pub const BLOB_QUERY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Download limits applied to a single blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwarmConfig {
+    /// Maximum chunk requests in flight across all seeders.
+    pub max_concurrent_chunks: usize,
+    /// Maximum chunk requests in flight to a single seeder.
+    pub max_chunks_per_peer: usize,
+    /// Download rate cap in bytes per second; `None` is unlimited.
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_chunks: 16,
+            max_chunks_per_peer: 4,
+            max_bytes_per_sec: None,
+        }
+    }
+}
+
+/// Streams a blob through [`Read`] and [`Seek`], fetching one chunk at a time
+/// so the whole blob never has to be held in memory.
+pub struct BlobReader<F> {
+    fetch: F,
+    size: u64,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_start: u64,
+}
+
+impl<F: FnMut(u64, u32) -> MerkleToxResult<Vec<u8>>> BlobReader<F> {
+    /// `fetch(offset, length)` returns `length` bytes of the blob at `offset`.
+    pub fn new(size: u64, fetch: F) -> Self {
+        Self {
+            fetch,
+            size,
+            pos: 0,
+            buf: Vec::new(),
+            buf_start: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+/// Opens an available blob in `store` for streaming reads.
+pub fn open_blob<'a, S: BlobStore + ?Sized>(
+    store: &'a S,
+    hash: &NodeHash,
+) -> MerkleToxResult<impl Read + Seek + use<'a, S>> {
+    let info = store
+        .get_blob_info(hash)
+        .filter(|_| store.has_blob(hash))
+        .ok_or(MerkleToxError::BlobNotFound(*hash))?;
+    let hash = *hash;
+    Ok(BlobReader::new(info.size, move |offset, length| {
+        store.get_chunk(&hash, offset, length)
+    }))
+}
+
+impl<F: FnMut(u64, u32) -> MerkleToxResult<Vec<u8>>> Read for BlobReader<F> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size || out.is_empty() {
+            return Ok(0);
+        }
+        let buf_end = self.buf_start + self.buf.len() as u64;
+        if self.pos < self.buf_start || self.pos >= buf_end {
+            let start = self.pos - self.pos % CHUNK_SIZE;
+            let length = CHUNK_SIZE.min(self.size - start) as u32;
+            self.buf = (self.fetch)(start, length).map_err(|e| match e {
+                MerkleToxError::Io(e) => e,
+                e => io::Error::other(e.to_string()),
+            })?;
+            self.buf_start = start;
+            if self.pos >= start + self.buf.len() as u64 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        let off = (self.pos - self.buf_start) as usize;
+        let n = out.len().min(self.buf.len() - off);
+        out[..n].copy_from_slice(&self.buf[off..off + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<F: FnMut(u64, u32) -> MerkleToxResult<Vec<u8>>> Seek for BlobReader<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(d) => self.size.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative offset")
+        })?;
+        Ok(self.pos)
+    }
+}
 
 /// Tracks received blob chunks.
 pub struct ChunkTracker {
@@ -110,6 +227,18 @@ impl ChunkTracker {
         true
     }
 
+    /// Number of blob bytes received so far.
+    pub fn received_bytes(&self) -> u64 {
+        let num_chunks = self.total_size.div_ceil(CHUNK_SIZE);
+        let mut received = 0;
+        for i in 0..num_chunks {
+            if self.is_received(i) {
+                received += CHUNK_SIZE.min(self.total_size - i * CHUNK_SIZE);
+            }
+        }
+        received
+    }
+
     pub fn next_missing(&self, hint: u64) -> Option<u64> {
         let num_chunks = self.total_size.div_ceil(CHUNK_SIZE);
         for i in hint..num_chunks {
@@ -137,19 +266,47 @@ pub struct SwarmSync {
     pub seeders: HashSet<PhysicalDevicePk>,
     /// Chunks currently being fetched: chunk_index to (peer_pk, start_time)
     pub active_fetches: HashMap<u64, (PhysicalDevicePk, Instant)>,
+    pub config: SwarmConfig,
+    /// Last time peers were queried for seeders.
+    pub last_query: Option<Instant>,
+    /// Token bucket for `max_bytes_per_sec`: (available bytes, last refill).
+    rate_budget: Option<(u64, Instant)>,
 }
 
 impl SwarmSync {
+    /// Starts a download. Chunks already marked in `info.received_mask` are
+    /// not requested again, so a download persisted by a previous run
+    /// resumes where it stopped.
     pub fn new(info: BlobInfo) -> Self {
-        let tracker = ChunkTracker::new(info.hash, info.size);
+        let mut tracker = ChunkTracker::new(info.hash, info.size);
+        if let Some(mask) = &info.received_mask {
+            for (dst, src) in tracker.received_mask.iter_mut().zip(mask) {
+                *dst |= src;
+            }
+        }
         Self {
             info,
             tracker,
             seeders: HashSet::new(),
             active_fetches: HashMap::new(),
+            config: SwarmConfig::default(),
+            last_query: None,
+            rate_budget: None,
         }
     }
 
+    pub fn with_config(mut self, config: SwarmConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Returns the chunks fetched so far in the form stored in `BlobInfo`.
+    pub fn progress_info(&self) -> BlobInfo {
+        let mut info = self.info.clone();
+        info.received_mask = Some(self.tracker.received_mask.clone());
+        info
+    }
+
     pub fn add_seeder(&mut self, peer: PhysicalDevicePk) {
         self.seeders.insert(peer);
     }
@@ -166,6 +323,27 @@ impl SwarmSync {
             .retain(|_, (_, start)| now.saturating_duration_since(*start) < FETCH_TIMEOUT);
     }
 
+    /// Refills the rate budget and returns the bytes that may be requested
+    /// now, or `None` if the download is not rate limited.
+    fn refill_budget(&mut self, now: Instant) -> Option<u64> {
+        let rate = self.config.max_bytes_per_sec?;
+        // Allow bursts of one second of traffic, but at least one chunk so
+        // low limits still make progress.
+        let burst = rate.max(CHUNK_SIZE);
+        let (tokens, last) = self.rate_budget.get_or_insert((burst, now));
+        let elapsed = now.saturating_duration_since(*last);
+        let refill = (elapsed.as_secs_f64() * rate as f64) as u64;
+        if refill > 0 {
+            *tokens = tokens.saturating_add(refill).min(burst);
+            *last = now;
+        }
+        Some(*tokens)
+    }
+
+    fn chunk_len(&self, chunk_idx: u64) -> u64 {
+        CHUNK_SIZE.min(self.info.size.saturating_sub(chunk_idx * CHUNK_SIZE))
+    }
+
     /// Selects next chunk requests for available seeders.
     pub fn next_requests(
         &mut self,
@@ -174,6 +352,12 @@ impl SwarmSync {
     ) -> Vec<(PhysicalDevicePk, BlobReq)> {
         let mut reqs = Vec::new();
         let mut hint = 0;
+        let max_total_requests = max_total_requests.min(
+            self.config
+                .max_concurrent_chunks
+                .saturating_sub(self.active_fetches.len()),
+        );
+        let mut budget = self.refill_budget(now);
 
         // Count in-flight requests per peer
         let mut in_flight_per_peer: HashMap<PhysicalDevicePk, usize> = HashMap::new();
@@ -190,11 +374,16 @@ impl SwarmSync {
 
             if let Some(chunk_idx) = self.tracker.next_missing(hint) {
                 if !self.active_fetches.contains_key(&chunk_idx) {
+                    let len = self.chunk_len(chunk_idx);
+                    if budget.is_some_and(|b| b < len) {
+                        break;
+                    }
                     // Pick seeder with least in-flight below limit
+                    let per_peer = self.config.max_chunks_per_peer;
                     let seeder = self
                         .seeders
                         .iter()
-                        .filter(|p| in_flight_per_peer.get(*p).copied().unwrap_or(0) < 4)
+                        .filter(|p| in_flight_per_peer.get(*p).copied().unwrap_or(0) < per_peer)
                         .min_by_key(|p| (in_flight_per_peer.get(*p).copied().unwrap_or(0), *p));
 
                     if let Some(seeder) = seeder {
@@ -210,6 +399,9 @@ impl SwarmSync {
                         ));
                         self.active_fetches.insert(chunk_idx, (seeder, now));
                         *in_flight_per_peer.entry(seeder).or_default() += 1;
+                        if let Some(b) = &mut budget {
+                            *b -= len;
+                        }
                     }
                 }
                 hint = chunk_idx + 1;
@@ -217,6 +409,9 @@ impl SwarmSync {
                 break;
             }
         }
+        if let (Some(b), Some((tokens, _))) = (budget, &mut self.rate_budget) {
+            *tokens = b;
+        }
         reqs
     }
 
@@ -243,10 +438,24 @@ impl SwarmSync {
             next = next.min(*start + FETCH_TIMEOUT);
         }
 
-        // 2. Poll ASAP if missing chunks not in flight and seeders available
-        let busy_peers: HashSet<PhysicalDevicePk> =
-            self.active_fetches.values().map(|(p, _)| *p).collect();
-        let has_available_seeder = self.seeders.iter().any(|p| !busy_peers.contains(p));
+        // 2. Re-query peers while nobody is known to have the blob
+        if self.seeders.is_empty()
+            && let Some(last_query) = self.last_query
+        {
+            next = next.min((last_query + BLOB_QUERY_INTERVAL).max(now));
+        }
+
+        // 3. Poll ASAP if missing chunks not in flight and seeders available
+        if self.active_fetches.len() >= self.config.max_concurrent_chunks {
+            return next;
+        }
+        let mut in_flight_per_peer: HashMap<PhysicalDevicePk, usize> = HashMap::new();
+        for (peer, _) in self.active_fetches.values() {
+            *in_flight_per_peer.entry(*peer).or_default() += 1;
+        }
+        let has_available_seeder = self.seeders.iter().any(|p| {
+            in_flight_per_peer.get(p).copied().unwrap_or(0) < self.config.max_chunks_per_peer
+        });
 
         if has_available_seeder {
             let num_chunks = self.tracker.total_size.div_ceil(CHUNK_SIZE);
@@ -254,6 +463,16 @@ impl SwarmSync {
                 if !self.tracker.is_received(chunk_idx)
                     && !self.active_fetches.contains_key(&chunk_idx)
                 {
+                    // 4. Wait for the rate budget to cover the chunk
+                    if let (Some(rate), Some((tokens, last))) =
+                        (self.config.max_bytes_per_sec, self.rate_budget)
+                    {
+                        let missing = self.chunk_len(chunk_idx).saturating_sub(tokens);
+                        if missing > 0 {
+                            let wait = Duration::from_secs_f64(missing as f64 / rate.max(1) as f64);
+                            return next.min((last + wait).max(now));
+                        }
+                    }
                     return now;
                 }
             }
@@ -0,0 +1,222 @@
+//! Channels of a conversation.
+//!
+//! An admin creates a channel with a `CreateChannel` node on the parent
+//! conversation's Admin track. The channel has its own DAG under an id
+//! derived from the parent's (see [`channel_conversation_id`]), but no Admin
+//! nodes: its members, permissions and Genesis flags are those of the
+//! parent. The channel key of each epoch is derived from the parent's key of
+//! that epoch, so every member of the parent can read every channel, and a
+//! rotation of the parent re-keys its channels without a key exchange.
+
+use crate::NodeEvent;
+use crate::crypto::derive_channel_k_conv;
+use crate::dag::{Content, ControlAction, ConversationId, MerkleNode};
+use crate::engine::{Conversation, Effect, MerkleToxEngine};
+use crate::error::MerkleToxResult;
+use crate::sync::NodeStore;
+use rand::RngCore;
+use tox_proto::ToxProto;
+use tracing::debug;
+
+/// A channel, as created by a `CreateChannel` node.
+#[derive(Debug, Clone, PartialEq, Eq, ToxProto)]
+pub struct ChannelInfo {
+    /// Conversation holding the channel's messages.
+    pub conversation_id: ConversationId,
+    /// Conversation whose members, permissions and keys the channel has.
+    pub parent_id: ConversationId,
+    pub name: String,
+    /// Network timestamp of the `CreateChannel` node.
+    pub created_at: i64,
+}
+
+/// Conversation id of channel `channel_id` of `parent_id`.
+pub fn channel_conversation_id(
+    parent_id: &ConversationId,
+    channel_id: &[u8; 32],
+) -> ConversationId {
+    let mut input = [0u8; 64];
+    input[..32].copy_from_slice(parent_id.as_bytes());
+    input[32..].copy_from_slice(channel_id);
+    ConversationId::from(blake3::derive_key("merkle-tox v1 channel", &input))
+}
+
+impl MerkleToxEngine {
+    /// Creates a channel named `name` in `parent_id`. Requires ADMIN in the
+    /// parent. Returns the channel's conversation id.
+    pub fn create_channel(
+        &mut self,
+        parent_id: ConversationId,
+        name: String,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<(ConversationId, Vec<Effect>)> {
+        let mut channel_id = [0u8; 32];
+        self.rng.lock().fill_bytes(&mut channel_id);
+        let effects = self.author_node(
+            parent_id,
+            Content::Control(ControlAction::CreateChannel { channel_id, name }),
+            Vec::new(),
+            store,
+        )?;
+        Ok((channel_conversation_id(&parent_id, &channel_id), effects))
+    }
+
+    /// Channels of `parent_id`, oldest first.
+    pub fn channels(&self, parent_id: ConversationId) -> Vec<ChannelInfo> {
+        let mut channels: Vec<_> = self
+            .channels
+            .values()
+            .filter(|c| c.parent_id == parent_id)
+            .cloned()
+            .collect();
+        channels.sort_by_key(|c| (c.created_at, c.conversation_id));
+        channels
+    }
+
+    /// The channel `conversation_id` is, if it is one.
+    pub fn channel(&self, conversation_id: ConversationId) -> Option<&ChannelInfo> {
+        self.channels.get(&conversation_id)
+    }
+
+    /// Registers the channel created by `node` in `parent_id`. Returns the
+    /// channel if it was not known yet.
+    pub(crate) fn register_channel(
+        &mut self,
+        parent_id: ConversationId,
+        node: &MerkleNode,
+        channel_id: &[u8; 32],
+        name: &str,
+    ) -> Option<ChannelInfo> {
+        let conversation_id = channel_conversation_id(&parent_id, channel_id);
+        if self.channels.contains_key(&conversation_id) {
+            return None;
+        }
+        let info = ChannelInfo {
+            conversation_id,
+            parent_id,
+            name: name.to_string(),
+            created_at: node.network_timestamp,
+        };
+        self.identity_manager
+            .register_channel(conversation_id, parent_id);
+        self.channels.insert(conversation_id, info.clone());
+        Some(info)
+    }
+
+    /// Applies a verified `CreateChannel` node: loads the channel and starts
+    /// syncing it with the peers the parent syncs with.
+    pub(crate) fn apply_create_channel(
+        &mut self,
+        parent_id: ConversationId,
+        node: &MerkleNode,
+        channel_id: &[u8; 32],
+        name: &str,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let Some(info) = self.register_channel(parent_id, node, channel_id, name) else {
+            return Ok(Vec::new());
+        };
+        debug!(
+            "Channel {:?} ({}) created in {:?}",
+            info.conversation_id, info.name, parent_id
+        );
+
+        let peers: Vec<_> = self
+            .sessions
+            .keys()
+            .filter(|(_, cid)| *cid == parent_id)
+            .map(|(pk, _)| *pk)
+            .collect();
+        let mut effects = Vec::new();
+        if peers.is_empty() {
+            self.load_conversation_state(info.conversation_id, store)?;
+        }
+        for peer_pk in peers {
+            effects.extend(self.start_sync(info.conversation_id, Some(peer_pk), store));
+        }
+        effects.push(Effect::EmitEvent(NodeEvent::ChannelCreated {
+            conversation_id: parent_id,
+            channel_id: info.conversation_id,
+            name: info.name,
+        }));
+        Ok(effects)
+    }
+
+    /// Gives the loaded channels of `parent_id` the parent's Genesis flags and
+    /// the keys of the parent's epochs they lack.
+    pub(crate) fn sync_channel_keys(&mut self, parent_id: ConversationId) {
+        let channel_ids: Vec<_> = self
+            .channels
+            .values()
+            .filter(|c| {
+                c.parent_id == parent_id && self.conversations.contains_key(&c.conversation_id)
+            })
+            .map(|c| c.conversation_id)
+            .collect();
+        let Some(parent) = self.conversations.get(&parent_id) else {
+            return;
+        };
+        if channel_ids.is_empty() {
+            return;
+        }
+        let flags = parent.genesis_flags();
+        let mut parent_keys: Vec<_> = match parent {
+            Conversation::Established(em) => em
+                .state
+                .epochs
+                .iter()
+                .map(|(epoch, keys)| (*epoch, keys.k_conv.clone()))
+                .collect(),
+            Conversation::Pending(_) => Vec::new(),
+        };
+        parent_keys.sort_by_key(|(epoch, _)| *epoch);
+
+        let now = self.clock.network_time_ms();
+        for channel_id in channel_ids {
+            let Some(mut conv) = self.conversations.remove(&channel_id) else {
+                continue;
+            };
+            conv.set_genesis_flags(flags);
+            for (epoch, parent_k_conv) in &parent_keys {
+                if let Conversation::Established(em) = &conv
+                    && em.get_keys(*epoch).is_some()
+                {
+                    continue;
+                }
+                let k_conv = derive_channel_k_conv(parent_k_conv, &channel_id);
+                conv = match conv {
+                    Conversation::Pending(p) => {
+                        Conversation::Established(p.establish(k_conv, now, *epoch))
+                    }
+                    Conversation::Established(mut em) => {
+                        em.add_epoch(*epoch, k_conv);
+                        Conversation::Established(em)
+                    }
+                };
+            }
+            self.conversations.insert(channel_id, conv);
+        }
+    }
+
+    /// Loads the channels of `parent_id` not loaded yet, and brings the keys
+    /// of the loaded ones up to date.
+    pub(crate) fn load_channels(
+        &mut self,
+        parent_id: ConversationId,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<()> {
+        let unloaded: Vec<_> = self
+            .channels
+            .values()
+            .filter(|c| {
+                c.parent_id == parent_id && !self.conversations.contains_key(&c.conversation_id)
+            })
+            .map(|c| c.conversation_id)
+            .collect();
+        for channel_id in unloaded {
+            self.load_conversation_state(channel_id, store)?;
+        }
+        self.sync_channel_keys(parent_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+//! Classification of packets without an engine, for push wakeups.
+//!
+//! A mobile push extension receives a packet while the app is not running
+//! and has to decide, within a tight time and memory budget, whether to wake
+//! it. It cannot open the store or decrypt anything. So engine messages
+//! start with a [`RoutingHeader`] in the clear: the kind of message and the
+//! conversation it belongs to. [`classify_packet`] reads it from the first
+//! packet of the message, skipping the rest of the payload.
+//!
+//! Peers that predate the header cannot read it, so the node only sends it
+//! to peers that negotiated [`FEATURE_ROUTING_HEADER`], and never on the
+//! capability handshake itself. [`decode_message`] accepts messages with or
+//! without it: a serialized message never starts with the header version.
+//!
+//! The header only repeats what the message itself carries unencrypted (the
+//! conversation ID, and for DAG nodes whether they are content or admin
+//! nodes); Tox encrypts the packets between peers. Receivers drop messages
+//! whose header does not match their content, so the header cannot claim
+//! one conversation while carrying another's data.
+
+use crate::ProtocolMessage;
+use crate::capabilities::{FEATURE_ROUTING_HEADER, Negotiated};
+use crate::dag::{ConversationId, WireFlags};
+use tox_sequenced::Packet;
+use tox_sequenced::coalesce::peek_batch;
+use tox_sequenced::protocol::MessageType;
+
+/// Version of the routing header format.
+pub const ROUTING_HEADER_VERSION: u8 = 1;
+
+/// What a packet carries, from least to most in need of the app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum PacketKind {
+    /// Transport control: acknowledgments, pings and MTU probes.
+    Control = 0,
+    /// A fragment of a message after the first, which was classified.
+    Continuation = 1,
+    /// Sync heads, reconciliation and fetch requests.
+    Sync = 2,
+    /// Blob queries, availability and chunks.
+    Blob = 3,
+    /// Capability handshake.
+    Handshake = 4,
+    /// Admin nodes and gossip, key wrap acknowledgments and re-inclusion.
+    Admin = 5,
+    /// A content node: a new message in the conversation.
+    Content = 6,
+    /// Not readable: malformed, or from a newer protocol version.
+    Unknown = 7,
+}
+
+impl PacketKind {
+    fn from_u8(code: u8) -> Self {
+        match code {
+            0 => PacketKind::Control,
+            1 => PacketKind::Continuation,
+            2 => PacketKind::Sync,
+            3 => PacketKind::Blob,
+            4 => PacketKind::Handshake,
+            5 => PacketKind::Admin,
+            6 => PacketKind::Content,
+            _ => PacketKind::Unknown,
+        }
+    }
+}
+
+/// Cleartext header in front of an engine message: version, kind,
+/// whether a conversation ID follows, and the 32-byte conversation ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutingHeader {
+    pub kind: PacketKind,
+    pub conversation_id: Option<ConversationId>,
+}
+
+impl RoutingHeader {
+    /// The header for `msg`.
+    pub fn of(msg: &ProtocolMessage) -> Self {
+        let kind = match msg {
+            ProtocolMessage::CapsAnnounce { .. }
+            | ProtocolMessage::CapsAck { .. }
+            | ProtocolMessage::HandshakeError { .. } => PacketKind::Handshake,
+            ProtocolMessage::SyncHeads(_)
+            | ProtocolMessage::SyncHeadsDelta(_)
+            | ProtocolMessage::SyncSketch(_)
+            | ProtocolMessage::SyncShardChecksums { .. }
+            | ProtocolMessage::SyncReconFail { .. }
+            | ProtocolMessage::SyncRateLimited { .. }
+            | ProtocolMessage::ReconPowChallenge { .. }
+            | ProtocolMessage::ReconPowSolution { .. }
+            | ProtocolMessage::FetchBatchReq(_) => PacketKind::Sync,
+            ProtocolMessage::MerkleNode { node, .. }
+            | ProtocolMessage::MerkleNodeWithHeads { node, .. } => {
+                if node.flags.contains(WireFlags::ENCRYPTED) {
+                    PacketKind::Content
+                } else {
+                    PacketKind::Admin
+                }
+            }
+            ProtocolMessage::AdminGossip { .. }
+            | ProtocolMessage::KeywrapAck { .. }
+            | ProtocolMessage::ReinclusionRequest { .. }
+            | ProtocolMessage::ReinclusionResponse { .. } => PacketKind::Admin,
+            ProtocolMessage::BlobQuery(_)
+            | ProtocolMessage::BlobAvail(_)
+            | ProtocolMessage::BlobReq(_)
+            | ProtocolMessage::BlobData(_) => PacketKind::Blob,
+        };
+        Self {
+            kind,
+            conversation_id: msg.conversation_id(),
+        }
+    }
+
+    /// Appends the encoded header to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(ROUTING_HEADER_VERSION);
+        out.push(self.kind as u8);
+        match &self.conversation_id {
+            Some(id) => {
+                out.push(1);
+                out.extend_from_slice(id.as_bytes());
+            }
+            None => out.push(0),
+        }
+    }
+
+    /// Reads a header from the start of `data`, returning it and the bytes
+    /// after it. `None` if it is malformed or of another version.
+    pub fn decode(data: &[u8]) -> Option<(Self, &[u8])> {
+        let (&[version, kind, has_conversation], rest) = data.split_first_chunk::<3>()?;
+        if version != ROUTING_HEADER_VERSION {
+            return None;
+        }
+        let (conversation_id, rest) = match has_conversation {
+            0 => (None, rest),
+            1 => {
+                let (id, rest) = rest.split_first_chunk::<32>()?;
+                (Some(ConversationId::from(*id)), rest)
+            }
+            _ => return None,
+        };
+        let header = Self {
+            kind: PacketKind::from_u8(kind),
+            conversation_id,
+        };
+        Some((header, rest))
+    }
+}
+
+/// Whether `msg` is sent behind a routing header to a peer with which the
+/// node `negotiated`. Handshake messages never are, so that peers without
+/// the header can still read them.
+pub fn has_routing_header(msg: &ProtocolMessage, negotiated: &Negotiated) -> bool {
+    negotiated.has(FEATURE_ROUTING_HEADER) && RoutingHeader::of(msg).kind != PacketKind::Handshake
+}
+
+/// Serializes `msg` as the node sends it to a peer with which it
+/// `negotiated`: behind its routing header if [`has_routing_header`].
+pub fn encode_message(
+    msg: &ProtocolMessage,
+    negotiated: &Negotiated,
+) -> tox_proto::Result<Vec<u8>> {
+    let body = tox_proto::serialize(msg)?;
+    if !has_routing_header(msg, negotiated) {
+        return Ok(body);
+    }
+    let mut data = Vec::with_capacity(35 + body.len());
+    RoutingHeader::of(msg).encode(&mut data);
+    data.extend_from_slice(&body);
+    Ok(data)
+}
+
+/// Reads a message sent by [`encode_message`]. If it has a routing header,
+/// checks that the header matches it.
+pub fn decode_message(data: &[u8]) -> tox_proto::Result<ProtocolMessage> {
+    if data.first() != Some(&ROUTING_HEADER_VERSION) {
+        return tox_proto::deserialize(data);
+    }
+    let Some((header, body)) = RoutingHeader::decode(data) else {
+        return Err(tox_proto::Error::Deserialize(
+            "malformed routing header".to_string(),
+        ));
+    };
+    let msg: ProtocolMessage = tox_proto::deserialize(body)?;
+    if RoutingHeader::of(&msg) != header {
+        return Err(tox_proto::Error::Deserialize(format!(
+            "routing header {:?} does not match {}",
+            header,
+            msg.name()
+        )));
+    }
+    Ok(msg)
+}
+
+/// What [`classify_packet`] found out about a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketSummary {
+    pub kind: PacketKind,
+    /// Conversation of the message, for conversation-scoped messages.
+    pub conversation_id: Option<ConversationId>,
+}
+
+impl PacketSummary {
+    const UNKNOWN: Self = Self::of(PacketKind::Unknown);
+
+    const fn of(kind: PacketKind) -> Self {
+        Self {
+            kind,
+            conversation_id: None,
+        }
+    }
+
+    /// Whether the app should be woken for the packet: it carries content
+    /// or admin traffic, or could not be read.
+    pub fn should_wake(&self) -> bool {
+        matches!(
+            self.kind,
+            PacketKind::Content | PacketKind::Admin | PacketKind::Unknown
+        )
+    }
+}
+
+/// Classifies a raw transport packet from its routing header, without
+/// reassembling or decrypting anything. A batch of messages is classified
+/// as its message most in need of the app.
+pub fn classify_packet(bytes: &[u8]) -> PacketSummary {
+    let Ok(packet) = tox_proto::deserialize::<Packet>(bytes) else {
+        return PacketSummary::UNKNOWN;
+    };
+    let Some((message_type, payload)) = packet.peek_message() else {
+        return match packet {
+            Packet::Data { fragment_index, .. } if fragment_index.0 != 0 => {
+                PacketSummary::of(PacketKind::Continuation)
+            }
+            Packet::Data { .. } | Packet::Datagram { .. } => PacketSummary::UNKNOWN,
+            _ => PacketSummary::of(PacketKind::Control),
+        };
+    };
+    if message_type != MessageType::Batch {
+        return summarize(message_type, payload);
+    }
+    peek_batch(payload)
+        .into_iter()
+        .map(|(message_type, payload)| summarize(message_type, payload))
+        .max_by_key(|summary| summary.kind)
+        .unwrap_or(PacketSummary::UNKNOWN)
+}
+
+/// Summarizes a message from its routing header. Handshake messages, sent
+/// without one, are recognized by their message type.
+fn summarize(message_type: MessageType, payload: &[u8]) -> PacketSummary {
+    match RoutingHeader::decode(payload) {
+        Some((header, _)) => PacketSummary {
+            kind: header.kind,
+            conversation_id: header.conversation_id,
+        },
+        None => match message_type {
+            MessageType::CapsAnnounce | MessageType::CapsAck | MessageType::HandshakeError => {
+                PacketSummary::of(PacketKind::Handshake)
+            }
+            _ => PacketSummary::UNKNOWN,
+        },
+    }
+}
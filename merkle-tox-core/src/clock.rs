@@ -1,7 +1,8 @@
 use crate::dag::{LogicalIdentityPk, PhysicalDevicePk};
 use std::collections::BTreeMap;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::Duration;
+use tox_proto::time::Instant;
 pub use tox_sequenced::time::{ManualTimeProvider, SystemTimeProvider, TimeProvider};
 
 /// Maximum clock slewing rate (1% drift).
@@ -9,6 +10,39 @@ const MAX_SLEW_RATE: f64 = 0.01;
 /// Threshold for jumping instead of slewing (10 minutes).
 const SLEW_THRESHOLD_MS: i64 = 10 * 60 * 1000;
 
+/// Limits on how far timestamps and peer clocks may stray.
+///
+/// `max_offset` caps how far peers can move our network clock from the local
+/// OS clock. With at least `outlier_min_peers` identities reporting, samples
+/// further than `outlier_threshold` from their median are left out of the
+/// consensus. Received nodes stamped more than `max_future_skew` ahead of
+/// network time, or more than `max_parent_skew` before their oldest parent,
+/// are quarantined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockPolicy {
+    pub max_offset: Duration,
+    pub outlier_threshold: Duration,
+    pub outlier_min_peers: usize,
+    pub max_future_skew: Duration,
+    pub max_parent_skew: Duration,
+    /// Peer consensus this far from the local clock raises
+    /// `NodeEvent::ClockSkewWarning`.
+    pub warn_offset: Duration,
+}
+
+impl Default for ClockPolicy {
+    fn default() -> Self {
+        Self {
+            max_offset: Duration::from_secs(5 * 60),
+            outlier_threshold: Duration::from_secs(2 * 60),
+            outlier_min_peers: 3,
+            max_future_skew: Duration::from_secs(10 * 60),
+            max_parent_skew: Duration::from_secs(10 * 60),
+            warn_offset: Duration::from_secs(2 * 60),
+        }
+    }
+}
+
 /// Median-based consensus clock with slewing and Byzantine resilience.
 ///
 /// Spec: Multiple devices of same identity averaged into single per-identity
@@ -19,6 +53,9 @@ pub struct NetworkClock {
     peer_offsets: BTreeMap<LogicalIdentityPk, Vec<(i64, u32)>>,
     /// Reverse mapping from device PK to logical identity for convenience.
     device_to_identity: BTreeMap<PhysicalDevicePk, LogicalIdentityPk>,
+    policy: ClockPolicy,
+    /// Median peer offset before capping at `policy.max_offset` (ms).
+    peer_median_offset: i64,
     /// Target offset (ms).
     target_offset: i64,
     /// Current offset (ms).
@@ -43,6 +80,8 @@ impl NetworkClock {
         Self {
             peer_offsets: BTreeMap::new(),
             device_to_identity: BTreeMap::new(),
+            policy: ClockPolicy::default(),
+            peer_median_offset: 0,
             target_offset: 0,
             current_offset: 0.0,
             last_slew_instant: now_inst,
@@ -57,6 +96,15 @@ impl NetworkClock {
         &*self.time_provider
     }
 
+    pub fn policy(&self) -> &ClockPolicy {
+        &self.policy
+    }
+
+    /// Replaces the policy. Takes effect with the next offset sample.
+    pub fn set_policy(&mut self, policy: ClockPolicy) {
+        self.policy = policy;
+    }
+
     /// Records time offset sample from trusted peer.
     /// Uses device PK as logical identity (single-device identity).
     pub fn update_peer_offset(&mut self, peer: PhysicalDevicePk, offset: i64) {
@@ -117,8 +165,16 @@ impl NetworkClock {
             return;
         }
 
-        // Step 2: Weighted median across per-identity offsets.
+        // Step 2: Drop identities far from the plain median, so a few
+        // wild clocks cannot drag the weighted median along.
         identity_samples.sort_unstable_by_key(|s| s.0);
+        if identity_samples.len() >= self.policy.outlier_min_peers {
+            let median = identity_samples[(identity_samples.len() - 1) / 2].0;
+            let threshold = self.policy.outlier_threshold.as_millis() as u64;
+            identity_samples.retain(|&(offset, _)| offset.abs_diff(median) <= threshold);
+        }
+
+        // Step 3: Weighted median across per-identity offsets.
 
         let total_weight: u64 = identity_samples.iter().map(|s| s.1 as u64).sum();
         let target_weight = total_weight / 2;
@@ -143,8 +199,10 @@ impl NetworkClock {
             }
         }
 
-        // Hard-cap at ±5 minutes from local OS clock.
-        target = target.clamp(-300_000, 300_000);
+        // Hard-cap relative to the local OS clock.
+        self.peer_median_offset = target;
+        let max_offset = self.policy.max_offset.as_millis() as i64;
+        target = target.clamp(-max_offset, max_offset);
 
         // First sample: jump directly to target.
         if self.target_offset == 0 && self.current_offset == 0.0 && self.peer_offsets.len() == 1 {
@@ -202,6 +260,12 @@ impl NetworkClock {
         self.current_offset as i64
     }
 
+    /// How far peers think the local OS clock is off (ms), before capping.
+    /// Positive when the local clock is behind.
+    pub fn peer_median_offset(&self) -> i64 {
+        self.peer_median_offset
+    }
+
     /// Returns computed target offset (before slewing) for testing.
     pub fn consensus_target_offset(&self) -> i64 {
         self.target_offset
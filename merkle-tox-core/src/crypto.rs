@@ -420,6 +420,20 @@ pub fn derive_k_iblt(k_conv: &KConv, conversation_id: &crate::dag::ConversationI
     result
 }
 
+/// Derives a channel's K_conv from its parent's K_conv of the same epoch,
+/// so that every member of the parent holds the channel key.
+pub fn derive_channel_k_conv(
+    parent_k_conv: &KConv,
+    channel_id: &crate::dag::ConversationId,
+) -> KConv {
+    let mut material = [0u8; 64];
+    material[0..32].copy_from_slice(parent_k_conv.as_bytes());
+    material[32..64].copy_from_slice(channel_id.as_bytes());
+    let result = KConv::from(derive_key("merkle-tox v1 channel-key", &material));
+    material.zeroize();
+    result
+}
+
 /// Derives K_header_export from K_conv for HistoryExport room-wide encryption.
 pub fn derive_k_header_export(k_conv: &KConv) -> HeaderKey {
     HeaderKey::from(derive_key("merkle-tox v1 header-export", k_conv.as_bytes()))
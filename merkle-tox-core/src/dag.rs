@@ -58,6 +58,27 @@ pub enum EmojiSource {
     Custom { hash: [u8; 32], shortcode: String },
 }
 
+/// Custom emoji or sticker of a sticker pack. Reactions refer to it with
+/// [`EmojiSource::Custom`].
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct Sticker {
+    pub shortcode: String,
+    /// CAS blob of the image.
+    pub hash: NodeHash,
+    pub size: u64,
+    pub mime_type: String,
+}
+
+/// How an identity presents itself. Published by the identity in each of
+/// its conversations.
+#[derive(Debug, Clone, Default, ToxProto, PartialEq, Eq)]
+pub struct Profile {
+    pub display_name: String,
+    /// CAS blob of the avatar image.
+    pub avatar: Option<NodeHash>,
+    pub status: String,
+}
+
 #[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
 pub struct MemberInfo {
     pub public_key: LogicalIdentityPk,
@@ -83,6 +104,16 @@ pub struct InviteAction {
     pub role: u8,
 }
 
+/// Which part of the history members may fetch from peers.
+#[derive(Debug, Clone, Copy, ToxProto, PartialEq, Eq, Hash)]
+pub enum HistoryVisibility {
+    /// Members fetch the whole history.
+    Shared,
+    /// Members only fetch messages from their Invite onwards, unless granted
+    /// history access.
+    SinceJoin,
+}
+
 #[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
 pub struct SignedPreKey {
     pub public_key: EphemeralX25519Pk,
@@ -141,6 +172,39 @@ pub enum ControlAction {
         basis_hash: NodeHash,
         cert: DelegationCertificate,
     },
+    /// Messages older than `max_age_ms` expire. 0 keeps history forever.
+    SetRetention {
+        max_age_ms: u64,
+    },
+    /// The identity's safety number was compared out of band.
+    VerifiedIdentity(LogicalIdentityPk),
+    /// Which history members may fetch. Identities in `history_access`
+    /// fetch the whole history regardless.
+    SetHistoryVisibility {
+        visibility: HistoryVisibility,
+        history_access: Vec<LogicalIdentityPk>,
+    },
+    /// The author's profile, replacing its earlier ones.
+    SetProfile(Profile),
+    /// Creates a channel: a conversation with its own DAG, whose members,
+    /// permissions and keys are those of this conversation. Its id is
+    /// derived from this conversation's and `channel_id` (see
+    /// [`crate::channel::channel_conversation_id`]).
+    CreateChannel {
+        channel_id: [u8; 32],
+        name: String,
+    },
+    /// Closes the conversation for good. Content nodes descending from it
+    /// are rejected; Admin nodes are still accepted.
+    CloseConversation,
+    /// Number of admins who must approve RevokeDevice, Leave of another
+    /// member, CloseConversation and SetAdminQuorum before they take
+    /// effect. 0 and 1 let any admin act alone.
+    SetAdminQuorum {
+        threshold: u8,
+    },
+    /// Approves the pending admin action in the node with this hash.
+    CoSign(NodeHash),
 }
 
 #[derive(Debug, Clone, ToxProto, PartialEq)]
@@ -183,6 +247,11 @@ pub enum Content {
         mime_type: String,
         size: u64,
         metadata: Vec<u8>,
+        /// Inline thumbnail shown while the blob downloads, at most
+        /// `MAX_THUMBNAIL_SIZE` bytes. Omitted from the encoding when absent,
+        /// so blobs written before previews existed keep their hash.
+        #[tox(omit_none)]
+        preview: Option<Vec<u8>>,
     },
     // 7: Location
     Location {
@@ -212,7 +281,41 @@ pub enum Content {
         message_type: u8,
         dedup_id: NodeHash,
     },
-    // 12: Unknown. Forward compatibility catch-all for unrecognized content types.
+    // 12: Voice. Audio stored in CAS like a Blob.
+    Voice {
+        hash: NodeHash,
+        size: u64,
+        duration_ms: u32,
+        /// Amplitude samples for rendering the note before it is downloaded.
+        waveform: Vec<u8>,
+        /// Audio codec, e.g. "opus".
+        codec: String,
+    },
+    // 13: Poll. Answered by PollVote nodes until its close.
+    Poll {
+        question: String,
+        options: Vec<String>,
+        /// Whether a vote may pick more than one option.
+        multi_choice: bool,
+        /// Network time in ms after which votes are invalid.
+        closes_at: Option<i64>,
+    },
+    // 14: PollVote. Each identity votes once per poll.
+    PollVote {
+        poll_hash: NodeHash,
+        /// Indices of the chosen options, in ascending order.
+        choices: Vec<u32>,
+    },
+    // 15: PollClose. Ends a poll before its `closes_at`, if any.
+    PollClose {
+        poll_hash: NodeHash,
+    },
+    // 16: StickerPack. Images are CAS blobs fetched when first displayed.
+    StickerPack {
+        name: String,
+        stickers: Vec<Sticker>,
+    },
+    // 17: Unknown. Forward compatibility catch-all for unrecognized content types.
     // Passes validation but triggers no side effects.
     #[tox(catch_all)]
     Unknown {
@@ -223,7 +326,9 @@ pub enum Content {
 
 impl Content {
     /// Returns the node type classification for this content.
-    /// Admin = Genesis, AuthorizeDevice, RevokeDevice, Snapshot, AnchorSnapshot, KeyWrap, SoftAnchor.
+    /// Admin = Genesis, AuthorizeDevice, RevokeDevice, Snapshot, AnchorSnapshot, KeyWrap, SoftAnchor,
+    /// SetRetention, VerifiedIdentity, SetHistoryVisibility, CreateChannel,
+    /// CloseConversation, SetAdminQuorum, CoSign.
     /// Content = everything else.
     pub fn node_type(&self) -> NodeType {
         match self {
@@ -234,11 +339,79 @@ impl Content {
                 | ControlAction::RevokeDevice { .. }
                 | ControlAction::Snapshot(_)
                 | ControlAction::AnchorSnapshot { .. }
-                | ControlAction::SoftAnchor { .. },
+                | ControlAction::SoftAnchor { .. }
+                | ControlAction::SetRetention { .. }
+                | ControlAction::VerifiedIdentity(_)
+                | ControlAction::SetHistoryVisibility { .. }
+                | ControlAction::CreateChannel { .. }
+                | ControlAction::CloseConversation
+                | ControlAction::SetAdminQuorum { .. }
+                | ControlAction::CoSign(_),
             ) => NodeType::Admin,
             _ => NodeType::Content,
         }
     }
+
+    /// Whether a channel may hold this content. Membership and keys are its
+    /// parent's, so Admin nodes and onboarding nodes are not.
+    pub fn allowed_in_channel(&self) -> bool {
+        self.node_type() == NodeType::Content
+            && !matches!(
+                self,
+                Content::Control(
+                    ControlAction::Invite(_)
+                        | ControlAction::Leave(_)
+                        | ControlAction::Announcement { .. }
+                        | ControlAction::HandshakePulse
+                )
+            )
+    }
+
+    /// Checks the rules of polls, sticker packs, profiles and channels that
+    /// need no other nodes.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        crate::poll::validate_content(self)?;
+        match self {
+            Content::StickerPack { name, stickers } => validate_sticker_pack(name, stickers),
+            Content::Control(ControlAction::SetProfile(profile)) => {
+                if profile.display_name.len() > MAX_DISPLAY_NAME_LEN {
+                    return Err(ValidationError::InvalidProfile(format!(
+                        "display name longer than {} bytes",
+                        MAX_DISPLAY_NAME_LEN
+                    )));
+                }
+                if profile.status.len() > MAX_STATUS_LEN {
+                    return Err(ValidationError::InvalidProfile(format!(
+                        "status longer than {} bytes",
+                        MAX_STATUS_LEN
+                    )));
+                }
+                Ok(())
+            }
+            Content::Control(ControlAction::CreateChannel { name, .. }) => {
+                if name.is_empty() || name.len() > MAX_CHANNEL_NAME_LEN {
+                    return Err(ValidationError::InvalidChannel(format!(
+                        "name must be 1 to {} bytes",
+                        MAX_CHANNEL_NAME_LEN
+                    )));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the hash of the CAS blob this content refers to, if any.
+    pub fn blob_hash(&self) -> Option<&NodeHash> {
+        match self {
+            Content::Blob { hash, .. }
+            | Content::Voice { hash, .. }
+            | Content::Control(ControlAction::SetProfile(Profile {
+                avatar: Some(hash), ..
+            })) => Some(hash),
+            _ => None,
+        }
+    }
 }
 
 /// Logical representation of Merkle node.
@@ -316,6 +489,10 @@ pub const MAX_SOFT_ANCHOR_CHAIN: u64 = 3;
 pub const MAX_PARENTS: usize = 16;
 pub const MAX_ANCESTRY_HOPS: u64 = 500;
 pub const MAX_METADATA_SIZE: usize = 32 * 1024; // 32KB
+pub const MAX_PACK_STICKERS: usize = 256;
+pub const MAX_DISPLAY_NAME_LEN: usize = 128;
+pub const MAX_STATUS_LEN: usize = 512;
+pub const MAX_CHANNEL_NAME_LEN: usize = 128;
 
 #[derive(Debug, thiserror::Error, PartialEq, Eq)]
 pub enum ValidationError {
@@ -323,6 +500,8 @@ pub enum ValidationError {
     MaxParentsExceeded { actual: usize, max: usize },
     #[error("Metadata too large: {actual} bytes (max {max})")]
     MaxMetadataExceeded { actual: usize, max: usize },
+    #[error("Blob preview too large: {actual} bytes (max {max})")]
+    PreviewTooLarge { actual: usize, max: usize },
     #[error("Too many speculative nodes")]
     TooManySpeculativeNodes,
     #[error("Too many verified nodes for this device")]
@@ -375,6 +554,42 @@ pub enum ValidationError {
     InvalidReactionTarget,
     #[error("LegacyBridge dedup_id does not match derivation")]
     InvalidLegacyBridgeDedup,
+    #[error("Invalid text entity: {0}")]
+    InvalidTextEntity(String),
+    #[error("Invalid poll: {0}")]
+    InvalidPoll(String),
+    #[error("Poll vote or close must reference a Poll node")]
+    InvalidPollTarget,
+    #[error("Invalid poll vote: {0}")]
+    InvalidPollVote(String),
+    #[error("Poll is closed")]
+    PollClosed,
+    #[error("Identity already voted in this poll")]
+    DuplicatePollVote,
+    #[error("Poll close permission denied: not poll author and lacks ADMIN")]
+    PollClosePermissionDenied,
+    #[error("Invalid sticker pack: {0}")]
+    InvalidStickerPack(String),
+    #[error("Invalid profile: {0}")]
+    InvalidProfile(String),
+    #[error("Invalid channel: {0}")]
+    InvalidChannel(String),
+    #[error("Node not allowed in a channel")]
+    NotAllowedInChannel,
+    #[error("Conversation is closed")]
+    ConversationClosed,
+}
+
+/// Conversation-wide rules a node is validated against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValidationRules {
+    /// Content nodes are cleartext and device-signed (Genesis
+    /// `FLAG_PUBLIC`).
+    pub public: bool,
+    /// The conversation is a channel. Membership, permissions and keys come
+    /// from its parent, so its DAG holds no Admin nodes and no membership or
+    /// key exchange actions.
+    pub channel: bool,
 }
 
 /// Wire-format fields 1 to 6 of WireNode, used as signature input.
@@ -433,6 +648,33 @@ pub fn validate_pow_v2(creator_pk: &[u8; 32], genesis_action: &ControlAction) ->
     count_leading_zeros(hash.as_bytes()) >= POW_DIFFICULTY
 }
 
+/// Checks that a sticker pack is named and has 1 to `MAX_PACK_STICKERS`
+/// stickers with distinct shortcodes of ASCII letters, digits, `_` and `-`.
+pub fn validate_sticker_pack(name: &str, stickers: &[Sticker]) -> Result<(), ValidationError> {
+    let invalid = |reason: &str| Err(ValidationError::InvalidStickerPack(reason.to_string()));
+    if name.is_empty() {
+        return invalid("empty name");
+    }
+    if stickers.is_empty() || stickers.len() > MAX_PACK_STICKERS {
+        return invalid(&format!("needs 1 to {} stickers", MAX_PACK_STICKERS));
+    }
+    let mut seen = HashSet::new();
+    for sticker in stickers {
+        let valid = !sticker.shortcode.is_empty()
+            && sticker
+                .shortcode
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-');
+        if !valid {
+            return invalid(&format!("invalid shortcode {:?}", sticker.shortcode));
+        }
+        if !seen.insert(&sticker.shortcode) {
+            return invalid(&format!("duplicate shortcode {:?}", sticker.shortcode));
+        }
+    }
+    Ok(())
+}
+
 impl MerkleNode {
     /// Validates Proof-of-Work for Genesis node.
     ///
@@ -538,10 +780,13 @@ impl MerkleNode {
     }
 
     /// Whether this node skips per-message ratchet advancement.
-    /// Exception nodes skip because they are cleartext. HistoryExport also
+    /// Exception nodes skip because they are cleartext, as do the
+    /// device-signed Content nodes of public conversations. HistoryExport also
     /// skips because it uses room-wide export keys, not the per-sender ratchet.
     pub fn skips_ratchet(&self) -> bool {
-        self.is_exception_node() || matches!(self.content, Content::HistoryExport { .. })
+        self.is_exception_node()
+            || matches!(self.content, Content::HistoryExport { .. })
+            || matches!(self.authentication, NodeAuth::Signature(_))
     }
 
     /// Verifies the signature of an Admin node.
@@ -567,10 +812,22 @@ impl MerkleNode {
 
     /// Validates the node against the protocol rules.
     pub fn validate<L: NodeLookup + ?Sized>(
+        &self,
+        conversation_id: &ConversationId,
+        lookup: &L,
+    ) -> Result<(), ValidationError> {
+        self.validate_with(conversation_id, lookup, ValidationRules::default())
+    }
+
+    /// Validates the node against the protocol rules of a conversation with
+    /// the given `rules`.
+    pub fn validate_with<L: NodeLookup + ?Sized>(
         &self,
         _conversation_id: &ConversationId,
         lookup: &L,
+        rules: ValidationRules,
     ) -> Result<(), ValidationError> {
+        let public = rules.public;
         // 0. Hard Limits
         if self.parents.len() > MAX_PARENTS {
             return Err(ValidationError::MaxParentsExceeded {
@@ -594,6 +851,18 @@ impl MerkleNode {
             });
         }
 
+        if let Content::Blob {
+            preview: Some(preview),
+            ..
+        } = &self.content
+            && preview.len() > tox_proto::constants::MAX_THUMBNAIL_SIZE
+        {
+            return Err(ValidationError::PreviewTooLarge {
+                actual: preview.len(),
+                max: tox_proto::constants::MAX_THUMBNAIL_SIZE,
+            });
+        }
+
         // Message size check: metadata + serialized content must not exceed MAX_MESSAGE_SIZE.
         let content_size = tox_proto::serialize(&self.content)
             .map(|v| v.len())
@@ -606,8 +875,16 @@ impl MerkleNode {
             });
         }
 
+        crate::entities::validate_node(self)?;
+        self.content.validate()?;
+
         let node_type = self.node_type();
 
+        // Channels take membership and keys from their parent.
+        if rules.channel && !self.content.allowed_in_channel() {
+            return Err(ValidationError::NotAllowedInChannel);
+        }
+
         // SenderKeyDistribution: epoch 0 uses Signature, epoch n>0 uses EphemeralSignature (DARE §2).
         let is_skd = matches!(self.content, Content::SenderKeyDistribution { .. });
         // All Control actions use device Signature (they're administrative
@@ -618,13 +895,15 @@ impl MerkleNode {
 
         // 1. Authentication Rule: Admin nodes (including KeyWrap) MUST use Signature.
         //    SKD and Announcement/HandshakePulse accept both Signature and EphemeralSignature.
-        //    Other content nodes MUST use EphemeralSignature.
+        //    Other content nodes MUST use EphemeralSignature, unless the
+        //    conversation is public.
         match (&self.authentication, node_type, allows_device_sig) {
             (NodeAuth::Signature(_), NodeType::Admin, _) => {}
             (NodeAuth::EphemeralSignature(_), NodeType::Content, false) => {}
             // SKD and pre-setup nodes allow both Signature and EphemeralSignature
             (NodeAuth::Signature(_), NodeType::Content, true) => {}
             (NodeAuth::EphemeralSignature(_), NodeType::Content, true) => {}
+            (NodeAuth::Signature(_), NodeType::Content, false) if public => {}
             (NodeAuth::Signature(_), NodeType::Content, false) => {
                 return Err(ValidationError::ContentNodeShouldUseMac);
             }
@@ -641,9 +920,10 @@ impl MerkleNode {
         }
 
         // 2. Admin Authenticity: applies to Admin nodes and any device-signed
-        //    Content exception (SKD epoch 0, Announcement, HandshakePulse).
+        //    Content exception (SKD epoch 0, Announcement, HandshakePulse) or
+        //    Content node of a public conversation.
         let is_device_signed = matches!(self.authentication, NodeAuth::Signature(_));
-        let is_device_signed_content = allows_device_sig && is_device_signed;
+        let is_device_signed_content = (allows_device_sig || public) && is_device_signed;
         if node_type == NodeType::Admin || is_device_signed_content {
             // 0. PoW check for Genesis
             if !self.validate_pow() {
@@ -726,7 +1006,8 @@ impl MerkleNode {
         }
 
         // 5. Ancestry Trust Cap: Content nodes must be within MAX_ANCESTRY_HOPS of an Admin node.
-        if node_type == NodeType::Content && !self.parents.is_empty() {
+        //    Channels have no Admin nodes; their authority is the parent's.
+        if node_type == NodeType::Content && !self.parents.is_empty() && !rules.channel {
             let mut min_distance = u64::MAX;
             for parent_hash in &self.parents {
                 if let Some(dist) = lookup.get_admin_distance(parent_hash) {
@@ -1018,3 +1299,190 @@ pub enum NodeType {
     Admin,
     Content,
 }
+
+/// A broken structural invariant found by [`validate_graph`].
+#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
+pub enum GraphViolation {
+    #[error("Node {node:?} references missing parent {parent:?}")]
+    MissingParent { node: NodeHash, parent: NodeHash },
+    #[error("Node {node:?} has rank {actual}, expected {expected}")]
+    RankViolation {
+        node: NodeHash,
+        actual: u64,
+        expected: u64,
+    },
+    #[error("Sender {sender:?} skipped sequence numbers between {after} and {next}")]
+    SequenceGap {
+        sender: PhysicalDevicePk,
+        after: u64,
+        next: u64,
+    },
+    #[error("Sender {sender:?} used sequence number {seq} more than once")]
+    DuplicateSequence { sender: PhysicalDevicePk, seq: u64 },
+    #[error("Head {head:?} (admin: {admin}) is not a verified node")]
+    UnknownHead { head: NodeHash, admin: bool },
+    #[error("Head {head:?} (admin: {admin}) has child {child:?}")]
+    StaleHead {
+        head: NodeHash,
+        child: NodeHash,
+        admin: bool,
+    },
+    #[error("Node {node:?} (admin: {admin}) has no children but is not a head")]
+    MissingHead { node: NodeHash, admin: bool },
+}
+
+/// Checks the structural invariants of a conversation's DAG in `store`.
+///
+/// - every parent of a stored node is stored, and ranks are
+///   `max(parent_ranks) + 1`;
+/// - each sender's sequence numbers are unique and without gaps;
+/// - the heads are exactly the verified nodes without verified children on
+///   their track (admin heads for the admin chain, heads for content).
+///
+/// Below a history horizon parents and sequence numbers are expected to be
+/// missing, so those checks are relaxed. Returns all violations found.
+pub fn validate_graph(
+    store: &dyn crate::sync::NodeStore,
+    conversation_id: &ConversationId,
+) -> Result<(), Vec<GraphViolation>> {
+    use std::collections::{BTreeMap, HashMap};
+
+    let horizon = store.get_history_horizon(conversation_id);
+    let verified: HashSet<NodeHash> = store
+        .get_node_hashes_in_range(
+            conversation_id,
+            &crate::sync::SyncRange {
+                min_rank: 0,
+                max_rank: u64::MAX,
+            },
+        )
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let mut nodes: HashMap<NodeHash, MerkleNode> = verified
+        .iter()
+        .filter_map(|h| store.get_node(h).map(|n| (*h, n)))
+        .collect();
+    for node in store.get_speculative_nodes(conversation_id) {
+        nodes.insert(node.hash(), node);
+    }
+
+    let mut violations = Vec::new();
+    let mut sequences: HashMap<PhysicalDevicePk, BTreeMap<u64, usize>> = HashMap::new();
+    // Parents referenced by verified nodes of the same track.
+    let mut has_child: HashMap<NodeHash, NodeHash> = HashMap::new();
+    let mut has_admin_child: HashMap<NodeHash, NodeHash> = HashMap::new();
+
+    let mut hashes: Vec<&NodeHash> = nodes.keys().collect();
+    hashes.sort_unstable();
+    for hash in hashes {
+        let node = &nodes[hash];
+        *sequences
+            .entry(node.sender_pk)
+            .or_default()
+            .entry(node.sequence_number)
+            .or_default() += 1;
+
+        let below_horizon = horizon.is_some_and(|h| node.topological_rank <= h);
+        let mut max_parent_rank = None;
+        let mut parents_complete = true;
+        for parent in &node.parents {
+            match nodes.get(parent) {
+                Some(p) => {
+                    max_parent_rank = max_parent_rank.max(Some(p.topological_rank));
+                }
+                None => {
+                    parents_complete = false;
+                    if !below_horizon {
+                        violations.push(GraphViolation::MissingParent {
+                            node: *hash,
+                            parent: *parent,
+                        });
+                    }
+                }
+            }
+            if verified.contains(hash) {
+                if node.node_type() == NodeType::Admin {
+                    has_admin_child.insert(*parent, *hash);
+                } else {
+                    has_child.insert(*parent, *hash);
+                }
+            }
+        }
+        if parents_complete {
+            let expected = max_parent_rank.map_or(0, |r| r + 1);
+            if node.topological_rank != expected {
+                violations.push(GraphViolation::RankViolation {
+                    node: *hash,
+                    actual: node.topological_rank,
+                    expected,
+                });
+            }
+        }
+    }
+
+    let mut senders: Vec<_> = sequences.into_iter().collect();
+    senders.sort_unstable_by_key(|(sender, _)| *sender);
+    for (sender, seqs) in senders {
+        let mut prev: Option<u64> = None;
+        for (seq, count) in seqs {
+            if count > 1 {
+                violations.push(GraphViolation::DuplicateSequence { sender, seq });
+            }
+            if let Some(after) = prev
+                && seq != after + 1
+                && horizon.is_none()
+            {
+                violations.push(GraphViolation::SequenceGap {
+                    sender,
+                    after,
+                    next: seq,
+                });
+            }
+            prev = Some(seq);
+        }
+    }
+
+    for (admin, heads, children) in [
+        (
+            true,
+            store.get_admin_heads(conversation_id),
+            &has_admin_child,
+        ),
+        (false, store.get_heads(conversation_id), &has_child),
+    ] {
+        let heads: HashSet<NodeHash> = heads.into_iter().collect();
+        let mut sorted: Vec<&NodeHash> = heads.iter().collect();
+        sorted.sort_unstable();
+        for head in sorted {
+            if !verified.contains(head) {
+                violations.push(GraphViolation::UnknownHead { head: *head, admin });
+            } else if let Some(child) = children.get(head) {
+                violations.push(GraphViolation::StaleHead {
+                    head: *head,
+                    child: *child,
+                    admin,
+                });
+            }
+        }
+        let mut tips: Vec<&NodeHash> = verified
+            .iter()
+            .filter(|h| {
+                let is_admin = nodes
+                    .get(*h)
+                    .is_some_and(|n| n.node_type() == NodeType::Admin);
+                is_admin == admin && !children.contains_key(*h) && !heads.contains(*h)
+            })
+            .collect();
+        tips.sort_unstable();
+        for node in tips {
+            violations.push(GraphViolation::MissingHead { node: *node, admin });
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
@@ -0,0 +1,277 @@
+//! Self-diagnostics of a node, answering "is this node healthy?".
+//!
+//! [`MerkleToxNode::diagnostics`](crate::node::MerkleToxNode::diagnostics)
+//! collects a [`Diagnostics`] report; [`Diagnostics::problems`] lists what
+//! in it needs attention.
+
+use crate::capabilities::Negotiated;
+use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use crate::engine::MerkleToxEngine;
+use crate::engine::conversation::Conversation;
+use crate::event_log::short_hex;
+use crate::sync::{NodeStore, SyncRange};
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+/// Health report of a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub pk: PhysicalDevicePk,
+    pub clock: ClockReport,
+    /// Total store size.
+    pub disk_usage_bytes: u64,
+    pub conversations: Vec<ConversationReport>,
+    pub sessions: Vec<SessionReport>,
+}
+
+/// State of the network clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockReport {
+    /// Offset applied to the local clock (ms).
+    pub consensus_offset_ms: i64,
+    /// How far peers think the local clock is off (ms), before capping.
+    pub peer_median_offset_ms: i64,
+    /// Offset from which the skew is reported as a problem.
+    pub warn_offset: Duration,
+}
+
+impl ClockReport {
+    pub fn is_skewed(&self) -> bool {
+        self.peer_median_offset_ms.unsigned_abs() >= self.warn_offset.as_millis() as u64
+    }
+}
+
+/// Store integrity and key coverage of one conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationReport {
+    pub conversation_id: ConversationId,
+    /// Whether the conversation is in memory; unloaded conversations have
+    /// no key state to report.
+    pub loaded: bool,
+    pub established: bool,
+    pub verified_nodes: usize,
+    pub speculative_nodes: usize,
+    pub heads: usize,
+    pub integrity: StoreIntegrity,
+    /// `None` unless the conversation is established.
+    pub ratchets: Option<RatchetCoverage>,
+}
+
+/// Gaps in the stored DAG of a conversation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreIntegrity {
+    /// Stored nodes with at least one parent missing from the store.
+    pub orphan_nodes: usize,
+    /// Parents referenced by stored nodes but missing from the store.
+    pub missing_parents: Vec<NodeHash>,
+    /// Received nodes that could not be decrypted yet.
+    pub opaque_nodes: usize,
+    /// Rank below which history was deliberately not synced. Parents of
+    /// nodes at or below it are not reported missing.
+    pub history_horizon: Option<u64>,
+}
+
+/// Which authorized devices this node can decrypt in the current epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RatchetCoverage {
+    pub epoch: u64,
+    /// Authorized devices other than this one.
+    pub devices: usize,
+    /// Devices with a ratchet or a received sender key in `epoch`.
+    pub covered: usize,
+    /// Devices falling back to the key derived from the conversation key.
+    pub uncovered: Vec<PhysicalDevicePk>,
+    /// Message keys kept for out-of-order delivery.
+    pub skipped_keys: usize,
+}
+
+/// Sync state with one peer in one conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionReport {
+    pub peer: PhysicalDevicePk,
+    pub conversation_id: ConversationId,
+    pub state: SessionState,
+    /// Version and features agreed on with the peer.
+    pub negotiated: Negotiated,
+    pub online: bool,
+    /// Messages waiting for room in the transport session.
+    pub queued_messages: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Handshake,
+    Active,
+}
+
+/// Something in a [`Diagnostics`] report that needs attention.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    ClockSkew {
+        offset_ms: i64,
+    },
+    MissingParents {
+        conversation_id: ConversationId,
+        count: usize,
+    },
+    OpaqueNodes {
+        conversation_id: ConversationId,
+        count: usize,
+    },
+    NoConversationKey {
+        conversation_id: ConversationId,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::ClockSkew { offset_ms } => {
+                write!(f, "peers see the local clock off by {} ms", offset_ms)
+            }
+            Problem::MissingParents {
+                conversation_id,
+                count,
+            } => write!(
+                f,
+                "conv={}: {} parent node(s) missing from the store",
+                short_hex(conversation_id.as_bytes()),
+                count
+            ),
+            Problem::OpaqueNodes {
+                conversation_id,
+                count,
+            } => write!(
+                f,
+                "conv={}: {} node(s) not decrypted",
+                short_hex(conversation_id.as_bytes()),
+                count
+            ),
+            Problem::NoConversationKey { conversation_id } => write!(
+                f,
+                "conv={}: no conversation key yet",
+                short_hex(conversation_id.as_bytes())
+            ),
+        }
+    }
+}
+
+impl Diagnostics {
+    /// Problems found in the report, most severe first.
+    pub fn problems(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        if self.clock.is_skewed() {
+            problems.push(Problem::ClockSkew {
+                offset_ms: self.clock.peer_median_offset_ms,
+            });
+        }
+        for c in &self.conversations {
+            if !c.integrity.missing_parents.is_empty() {
+                problems.push(Problem::MissingParents {
+                    conversation_id: c.conversation_id,
+                    count: c.integrity.missing_parents.len(),
+                });
+            }
+        }
+        for c in &self.conversations {
+            if c.integrity.opaque_nodes > 0 {
+                problems.push(Problem::OpaqueNodes {
+                    conversation_id: c.conversation_id,
+                    count: c.integrity.opaque_nodes,
+                });
+            }
+            if c.loaded && !c.established {
+                problems.push(Problem::NoConversationKey {
+                    conversation_id: c.conversation_id,
+                });
+            }
+        }
+        problems
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.problems().is_empty()
+    }
+}
+
+/// Checks that every parent of a stored node of `conversation_id` is stored.
+pub fn check_integrity<S: NodeStore + ?Sized>(
+    store: &S,
+    conversation_id: &ConversationId,
+) -> StoreIntegrity {
+    let history_horizon = store.get_history_horizon(conversation_id);
+    let mut nodes = store.get_speculative_nodes(conversation_id);
+    let range = SyncRange {
+        min_rank: 0,
+        max_rank: u64::MAX,
+    };
+    let hashes = store
+        .get_node_hashes_in_range(conversation_id, &range)
+        .unwrap_or_default();
+    // Pruned nodes keep only their header and are skipped.
+    nodes.extend(store.get_nodes(&hashes).into_iter().flatten());
+
+    let mut orphan_nodes = 0;
+    let mut missing = HashSet::new();
+    for node in &nodes {
+        if history_horizon.is_some_and(|horizon| node.topological_rank <= horizon) {
+            continue;
+        }
+        let mut orphan = false;
+        for parent in &node.parents {
+            if !store.has_node(parent) {
+                missing.insert(*parent);
+                orphan = true;
+            }
+        }
+        if orphan {
+            orphan_nodes += 1;
+        }
+    }
+    let mut missing_parents: Vec<_> = missing.into_iter().collect();
+    missing_parents.sort();
+    StoreIntegrity {
+        orphan_nodes,
+        missing_parents,
+        opaque_nodes: store
+            .get_opaque_node_hashes(conversation_id)
+            .map_or(0, |h| h.len()),
+        history_horizon,
+    }
+}
+
+/// Ratchet coverage of `conversation_id`, if it is established.
+pub fn ratchet_coverage(
+    engine: &MerkleToxEngine,
+    conversation_id: &ConversationId,
+) -> Option<RatchetCoverage> {
+    let Some(Conversation::Established(em)) = engine.conversations.get(conversation_id) else {
+        return None;
+    };
+    let epoch = em.current_epoch();
+    let devices: Vec<_> = engine
+        .get_authorized_devices(conversation_id)
+        .into_iter()
+        .filter(|pk| *pk != engine.self_pk)
+        .collect();
+    let uncovered: Vec<_> = devices
+        .iter()
+        .filter(|pk| {
+            let ratchet = em
+                .state
+                .sender_ratchets
+                .get(pk)
+                .is_some_and(|&(_, _, _, e)| e == epoch);
+            !ratchet && !em.state.sender_keys.contains_key(&(**pk, epoch))
+        })
+        .copied()
+        .collect();
+    Some(RatchetCoverage {
+        epoch,
+        devices: devices.len(),
+        covered: devices.len() - uncovered.len(),
+        uncovered,
+        skipped_keys: em.state.skipped_keys.len(),
+    })
+}
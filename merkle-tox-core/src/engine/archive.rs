@@ -0,0 +1,244 @@
+//! Unloading idle conversations.
+//!
+//! Every loaded conversation keeps its epoch keys, ratchets and skipped
+//! message keys in memory. [`MerkleToxEngine::unload_conversation`] writes
+//! the part of that state the store does not already have to an
+//! [`ArchivedConversation`] and drops it. The next packet, node or authoring
+//! call for the conversation loads it again from the store and the archive.
+
+use crate::crypto::ConversationKeys;
+use crate::dag::{ConversationId, HeaderKey, KConv, MessageKey, PhysicalDevicePk, SenderKey};
+use crate::engine::conversation::{self, ConversationData};
+use crate::engine::vault::VaultRatchet;
+use crate::engine::{Conversation, Effect, MerkleToxEngine};
+use crate::error::MerkleToxResult;
+use crate::sync::NodeStore;
+use std::collections::HashMap;
+use tox_proto::ToxProto;
+use tracing::debug;
+
+/// In-memory key state of an established conversation.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct ArchivedKeys {
+    pub current_epoch: u64,
+    pub epochs: Vec<(u64, KConv)>,
+    pub ratchets: Vec<VaultRatchet>,
+    /// Keys of messages skipped over, with the time they were derived.
+    pub skipped_keys: Vec<(PhysicalDevicePk, u64, MessageKey, i64)>,
+    pub sender_keys: Vec<(PhysicalDevicePk, u64, SenderKey)>,
+    pub jit_headers: Vec<(PhysicalDevicePk, u64, HeaderKey)>,
+    pub shared_keys_sent_to: Vec<PhysicalDevicePk>,
+    pub message_count: u32,
+    pub last_rotation_time_ms: i64,
+    pub identity_pending: bool,
+    pub self_message_count: u32,
+    pub self_last_rekey_time_ms: i64,
+}
+
+/// State of an unloaded conversation that cannot be rebuilt from its nodes.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct ArchivedConversation {
+    pub conversation_id: ConversationId,
+    pub genesis_flags: u64,
+    /// `None` for a conversation that was still pending.
+    pub keys: Option<ArchivedKeys>,
+}
+
+impl ArchivedConversation {
+    fn from_conversation(conv: &Conversation) -> Self {
+        match conv {
+            Conversation::Pending(p) => ArchivedConversation {
+                conversation_id: p.id,
+                genesis_flags: p.state.genesis_flags,
+                keys: None,
+            },
+            Conversation::Established(em) => ArchivedConversation {
+                conversation_id: em.id,
+                genesis_flags: em.state.genesis_flags,
+                keys: Some(ArchivedKeys::from_established(em)),
+            },
+        }
+    }
+
+    fn into_conversation(self) -> Conversation {
+        let Some(keys) = self.keys else {
+            let mut p = ConversationData::<conversation::Pending>::new(self.conversation_id);
+            p.state.genesis_flags = self.genesis_flags;
+            return Conversation::Pending(p);
+        };
+        let mut em = keys.into_established(self.conversation_id);
+        em.state.genesis_flags = self.genesis_flags;
+        Conversation::Established(em)
+    }
+}
+
+impl ArchivedKeys {
+    fn from_established(em: &ConversationData<conversation::Established>) -> Self {
+        let s = &em.state;
+        let mut epochs: Vec<(u64, KConv)> = s
+            .epochs
+            .iter()
+            .map(|(epoch, keys)| (*epoch, keys.k_conv.clone()))
+            .collect();
+        epochs.sort_by_key(|(epoch, _)| *epoch);
+
+        let mut ratchets: Vec<VaultRatchet> = s
+            .sender_ratchets
+            .iter()
+            .map(|(pk, (last_seq, key, last_hash, epoch))| VaultRatchet {
+                sender_pk: *pk,
+                epoch: *epoch,
+                last_seq: *last_seq,
+                next_chain_key: key.clone(),
+                last_node_hash: *last_hash,
+            })
+            .collect();
+        ratchets.sort_by_key(|r| r.sender_pk);
+
+        let mut skipped_keys: Vec<_> = s
+            .skipped_keys
+            .iter()
+            .map(|((pk, seq), (key, time))| (*pk, *seq, key.clone(), *time))
+            .collect();
+        skipped_keys.sort_by_key(|(pk, seq, _, _)| (*pk, *seq));
+
+        let mut sender_keys: Vec<_> = s
+            .sender_keys
+            .iter()
+            .map(|((pk, epoch), key)| (*pk, *epoch, key.clone()))
+            .collect();
+        sender_keys.sort_by_key(|(pk, epoch, _)| (*pk, *epoch));
+
+        let mut jit_headers: Vec<_> = s
+            .jit_headers
+            .iter()
+            .map(|((pk, epoch), key)| (*pk, *epoch, key.clone()))
+            .collect();
+        jit_headers.sort_by_key(|(pk, epoch, _)| (*pk, *epoch));
+
+        let mut shared_keys_sent_to: Vec<_> = s.shared_keys_sent_to.iter().copied().collect();
+        shared_keys_sent_to.sort();
+
+        ArchivedKeys {
+            current_epoch: s.current_epoch,
+            epochs,
+            ratchets,
+            skipped_keys,
+            sender_keys,
+            jit_headers,
+            shared_keys_sent_to,
+            message_count: s.message_count,
+            last_rotation_time_ms: s.last_rotation_time_ms,
+            identity_pending: s.identity_pending,
+            self_message_count: s.self_message_count,
+            self_last_rekey_time_ms: s.self_last_rekey_time_ms,
+        }
+    }
+
+    fn into_established(self, id: ConversationId) -> ConversationData<conversation::Established> {
+        ConversationData {
+            id,
+            state: conversation::Established {
+                epochs: self
+                    .epochs
+                    .into_iter()
+                    .map(|(epoch, k_conv)| (epoch, ConversationKeys::derive(&k_conv)))
+                    .collect(),
+                sender_ratchets: self
+                    .ratchets
+                    .into_iter()
+                    .map(|r| {
+                        (
+                            r.sender_pk,
+                            (r.last_seq, r.next_chain_key, r.last_node_hash, r.epoch),
+                        )
+                    })
+                    .collect(),
+                skipped_keys: self
+                    .skipped_keys
+                    .into_iter()
+                    .map(|(pk, seq, key, time)| ((pk, seq), (key, time)))
+                    .collect(),
+                current_epoch: self.current_epoch,
+                message_count: self.message_count,
+                last_rotation_time_ms: self.last_rotation_time_ms,
+                vouchers: HashMap::new(),
+                sender_keys: self
+                    .sender_keys
+                    .into_iter()
+                    .map(|(pk, epoch, key)| ((pk, epoch), key))
+                    .collect(),
+                shared_keys_sent_to: self.shared_keys_sent_to.into_iter().collect(),
+                jit_headers: self
+                    .jit_headers
+                    .into_iter()
+                    .map(|(pk, epoch, key)| ((pk, epoch), key))
+                    .collect(),
+                identity_pending: self.identity_pending,
+                genesis_flags: 0,
+                self_message_count: self.self_message_count,
+                self_last_rekey_time_ms: self.self_last_rekey_time_ms,
+            },
+        }
+    }
+}
+
+impl MerkleToxEngine {
+    /// Drops a conversation's state from memory. The returned effect
+    /// archives what the store does not already have; the conversation is
+    /// loaded again on its next use. Returns no effects if the
+    /// conversation is not loaded.
+    ///
+    /// Sync sessions with peers are kept. Archives are only read back for
+    /// conversations unloaded by this engine; after a restart, conversations
+    /// are loaded from their nodes as usual. Stores without archive support
+    /// fail the effect, so do not unload conversations with them.
+    pub fn unload_conversation(&mut self, conversation_id: ConversationId) -> Vec<Effect> {
+        let Some(conv) = self.conversations.remove(&conversation_id) else {
+            return Vec::new();
+        };
+        let archive = ArchivedConversation::from_conversation(&conv);
+        self.pending_cache
+            .lock()
+            .last_verified_sequences
+            .retain(|(cid, _), _| *cid != conversation_id);
+        self.unloaded_conversations.insert(conversation_id);
+        debug!("Unloaded conversation {:?}", conversation_id);
+        vec![Effect::WriteArchivedConversation(archive)]
+    }
+
+    /// Whether `conversation_id` was unloaded and not used since.
+    pub fn is_unloaded(&self, conversation_id: &ConversationId) -> bool {
+        self.unloaded_conversations.contains(conversation_id)
+    }
+
+    /// Loads an unloaded conversation back from the store. Does nothing for
+    /// conversations that were not unloaded.
+    pub fn ensure_conversation_loaded(
+        &mut self,
+        conversation_id: ConversationId,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<()> {
+        if self.unloaded_conversations.contains(&conversation_id) {
+            self.load_conversation_state(conversation_id, store)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the state `load_conversation_state` rebuilt with the
+    /// archived one, if the conversation was unloaded.
+    pub(crate) fn restore_archived_conversation(
+        &mut self,
+        conversation_id: ConversationId,
+        store: &dyn NodeStore,
+    ) {
+        if !self.unloaded_conversations.remove(&conversation_id) {
+            return;
+        }
+        if let Some(archive) = store.get_archived_conversation(&conversation_id) {
+            debug!("Reloaded conversation {:?}", conversation_id);
+            self.conversations
+                .insert(conversation_id, archive.into_conversation());
+        }
+    }
+}
@@ -4,6 +4,7 @@ use crate::dag::{
     EphemeralX25519Pk, EphemeralX25519Sk, KConv, MerkleNode, NodeAuth, NodeHash, NodeLookup,
     NodeType, PhysicalDevicePk, SenderKey, ValidationError, WireNode,
 };
+use crate::engine::config::RotationTrigger;
 use crate::engine::{
     Conversation, ConversationData, Effect, EngineStore, KeyWrapPending, MerkleToxEngine,
     conversation,
@@ -13,8 +14,6 @@ use crate::sync::NodeStore;
 use ed25519_dalek::{Signer, SigningKey};
 use rand::RngCore;
 
-const MESSAGES_PER_EPOCH: u32 = 5000;
-const EPOCH_DURATION_MS: i64 = 7 * 24 * 60 * 60 * 1000;
 /// Re-anchor every N content messages so joining devices have fresh anchor.
 const MESSAGES_PER_ANCHOR: u32 = 400;
 /// SoftAnchor auto-trigger: minimum admin-distance hops before considering.
@@ -23,6 +22,15 @@ const SOFT_ANCHOR_MIN_HOPS: u64 = 400;
 const SOFT_ANCHOR_MAX_HOPS: u64 = 450;
 
 impl MerkleToxEngine {
+    /// Fails if this engine is a read-only observer (see
+    /// `EngineConfig::read_only`).
+    fn ensure_can_author(&self) -> MerkleToxResult<()> {
+        if self.config.read_only {
+            return Err(MerkleToxError::ReadOnly);
+        }
+        Ok(())
+    }
+
     /// Authors KeyWrap node using X3DH for initial key exchange with peer.
     /// Per spec §2.C, K_conv_0 derived internally from SK_shared; caller
     /// does not supply k_conv. Alice's conversation state updated to
@@ -34,6 +42,21 @@ impl MerkleToxEngine {
         peer_spk: EphemeralX25519Pk,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
+        self.ensure_can_author()?;
+        if self.is_public(conversation_id) {
+            tracing::debug!(
+                "Skipping key exchange in public conversation {:?}",
+                conversation_id
+            );
+            return Ok(Vec::new());
+        }
+        if self.channel(conversation_id).is_some() {
+            tracing::debug!(
+                "Skipping key exchange in channel {:?}, keyed by its parent",
+                conversation_id
+            );
+            return Ok(Vec::new());
+        }
         self.clear_pending();
 
         // Enforce handshake retry cap: max 3 retries per 10-min window
@@ -167,7 +190,19 @@ impl MerkleToxEngine {
         metadata: Vec<u8>,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
+        self.ensure_can_author()?;
         self.clear_pending();
+        self.ensure_conversation_loaded(conversation_id, store)?;
+        if self.channel(conversation_id).is_some() && !content.allowed_in_channel() {
+            return Err(MerkleToxError::Validation(
+                ValidationError::NotAllowedInChannel,
+            ));
+        }
+        if content.node_type() == NodeType::Content && self.is_closed(conversation_id) {
+            return Err(MerkleToxError::Validation(
+                ValidationError::ConversationClosed,
+            ));
+        }
 
         // Guard: Spec §5 Observer Mode requires devices in Pending state or
         // Established with identity_pending=true MUST NOT author new nodes.
@@ -219,6 +254,20 @@ impl MerkleToxEngine {
             }
         }
 
+        // Reject oversized previews here; peers would drop the node anyway.
+        if let Content::Blob {
+            preview: Some(preview),
+            ..
+        } = &content
+            && preview.len() > tox_proto::constants::MAX_THUMBNAIL_SIZE
+        {
+            return Err(crate::dag::ValidationError::PreviewTooLarge {
+                actual: preview.len(),
+                max: tox_proto::constants::MAX_THUMBNAIL_SIZE,
+            }
+            .into());
+        }
+
         // Block authoring if self is in trust-restored observer mode.
         // Only Announcement and HandshakePulse are allowed (needed for healing protocol).
         if let Some(&_heal_ts) = self
@@ -239,8 +288,8 @@ impl MerkleToxEngine {
 
         // Check for automatic rotation
         let mut all_effects = Vec::new();
-        if self.check_rotation_triggers(conversation_id) {
-            let effects = self.rotate_conversation_key(conversation_id, store)?;
+        if let Some(trigger) = self.rotation_trigger(conversation_id) {
+            let effects = self.rotate_conversation_key_for(conversation_id, trigger, store)?;
             all_effects.extend(effects);
             // Signal application layer to create history snapshot at epoch boundary.
             all_effects.push(Effect::HistorySnapshotNeeded(conversation_id));
@@ -257,6 +306,7 @@ impl MerkleToxEngine {
         // devices lacking SenderKey/ratchet state this epoch.
         // If any exist, author JIT SenderKeyDistribution first.
         if !matches!(&content, Content::Control(_) | Content::KeyWrap { .. })
+            && !self.is_public(conversation_id)
             && let Some(Conversation::Established(em)) = self.conversations.get(&conversation_id)
         {
             let now = self.clock.network_time_ms();
@@ -299,8 +349,9 @@ impl MerkleToxEngine {
 
         // After content message, check if re-anchoring
         // threshold crossed. If so, auto-author Snapshot so
-        // new devices have fresh trust anchor.
-        if is_content_node {
+        // new devices have fresh trust anchor. Channels have no Admin
+        // nodes and no ancestry cap, so they need no anchors.
+        if is_content_node && self.channel(conversation_id).is_none() {
             let should_anchor = if let Some(Conversation::Established(em)) =
                 self.conversations.get(&conversation_id)
             {
@@ -367,8 +418,10 @@ impl MerkleToxEngine {
         store: &dyn NodeStore,
         use_epoch: Option<u64>,
     ) -> MerkleToxResult<Vec<Effect>> {
+        self.ensure_can_author()?;
         let now = self.clock.network_time_ms();
         let author_pk = self.self_logical_pk;
+        let is_public = self.is_public(conversation_id);
 
         let (node, node_hash, wire_node) = {
             let overlay = EngineStore {
@@ -420,6 +473,14 @@ impl MerkleToxEngine {
                     + 1
             };
 
+            // Never stamp a node earlier than its parents, even if the
+            // network clock was slewed back since they were authored.
+            let network_timestamp = parents
+                .iter()
+                .filter_map(|h| overlay.get_node(h))
+                .map(|p| p.network_timestamp)
+                .fold(now, i64::max);
+
             let current_epoch = if let Some(Conversation::Established(em)) =
                 self.conversations.get(&conversation_id)
             {
@@ -464,7 +525,7 @@ impl MerkleToxEngine {
                 sender_pk: self.self_pk,
                 sequence_number,
                 topological_rank,
-                network_timestamp: now,
+                network_timestamp,
                 content,
                 metadata,
                 authentication: NodeAuth::EphemeralSignature(crate::dag::Ed25519Signature::from(
@@ -473,6 +534,10 @@ impl MerkleToxEngine {
                 pow_nonce: 0,
             };
 
+            // Peers would drop these invalid nodes anyway.
+            node.content.validate()?;
+            crate::poll::validate_against_poll(&node, &overlay)?;
+
             // Admin nodes (including KeyWrap) are Ed25519-signed.
             // SKD uses device Signature if no prior epoch ephemeral key
             // (first-ever SKD), otherwise uses EphemeralSignature from
@@ -500,6 +565,7 @@ impl MerkleToxEngine {
 
             // Three signing/packing paths:
             // 1. Device-signed exception nodes (Admin, KeyWrap, first-epoch SKD)
+            //    and Content nodes of public conversations
             // 2. Ephemeral-signed exception nodes (subsequent-epoch SKD)
             // 3. Content nodes (encrypt-then-sign)
             //
@@ -507,8 +573,14 @@ impl MerkleToxEngine {
             // node.serialize_for_auth() == wire.serialize_for_auth().
             // Content nodes are encrypted, requiring wire (ciphertext) signature.
             let is_exception = node.is_exception_node();
+            // Public conversations have no keys to encrypt Content with.
+            let is_public_content = is_public && !is_exception;
 
-            if node_type == NodeType::Admin || is_skd_needs_device_sig || is_content_control {
+            if node_type == NodeType::Admin
+                || is_skd_needs_device_sig
+                || is_content_control
+                || is_public_content
+            {
                 // Path 1: Device signature on exception node.
                 if let Some(sk) = &self.self_sk {
                     let signing_key = SigningKey::from_bytes(sk.as_bytes());
@@ -694,6 +766,13 @@ impl MerkleToxEngine {
                 // Content node was already packed during encrypt-then-sign
                 overlay.put_wire_node(&conversation_id, &hash, wire.clone())?;
                 wire_node = Some(wire);
+            } else if is_public {
+                // Public conversations pack every node in cleartext, with or
+                // without a conversation key.
+                if let Ok(wire) = node.pack_wire(&crate::crypto::PackKeys::Exception, true) {
+                    overlay.put_wire_node(&conversation_id, &hash, wire.clone())?;
+                    wire_node = Some(wire);
+                }
             } else if let Some(Conversation::Established(em)) =
                 self.conversations.get_mut(&conversation_id)
             {
@@ -812,7 +891,8 @@ impl MerkleToxEngine {
         match verified_node.content() {
             Content::Control(ControlAction::AuthorizeDevice { .. })
             | Content::Control(ControlAction::RevokeDevice { .. })
-            | Content::Control(ControlAction::Leave(_)) => {
+            | Content::Control(ControlAction::Leave(_))
+            | Content::Control(ControlAction::CoSign(_)) => {
                 let inv_effects = self.revalidate_all_verified_nodes(conversation_id, store);
                 effects.extend(inv_effects);
             }
@@ -851,6 +931,18 @@ impl MerkleToxEngine {
         conversation_id: ConversationId,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
+        self.author_announcement_with_metadata(conversation_id, Vec::new(), store)
+    }
+
+    /// Authors an Announcement node carrying `metadata`, e.g. an
+    /// `InviteProof`.
+    pub fn author_announcement_with_metadata(
+        &mut self,
+        conversation_id: ConversationId,
+        metadata: Vec<u8>,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        self.ensure_can_author()?;
         self.clear_pending();
 
         let mut pre_keys = Vec::new();
@@ -910,7 +1002,7 @@ impl MerkleToxEngine {
             last_resort_key,
         });
 
-        let result = self.author_node(conversation_id, content, Vec::new(), store);
+        let result = self.author_node(conversation_id, content, metadata, store);
         if result.is_ok() {
             self.last_announcement_time_ms
                 .insert(conversation_id, self.clock.network_time_ms());
@@ -920,16 +1012,28 @@ impl MerkleToxEngine {
 
     /// Checks if a conversation key rotation is triggered by message count or time.
     pub fn check_rotation_triggers(&mut self, conversation_id: ConversationId) -> bool {
+        self.rotation_trigger(conversation_id).is_some()
+    }
+
+    /// The `RotationPolicy` limit the current epoch of a conversation has
+    /// reached, if any. Public conversations have no key to rotate, and
+    /// channels rotate with their parent.
+    pub fn rotation_trigger(&mut self, conversation_id: ConversationId) -> Option<RotationTrigger> {
         let now = self.clock.network_time_ms();
-        if let Some(Conversation::Established(em)) = self.conversations.get(&conversation_id) {
-            if em.state.message_count >= MESSAGES_PER_EPOCH {
-                return true;
-            }
-            if now - em.state.last_rotation_time_ms >= EPOCH_DURATION_MS {
-                return true;
-            }
+        let policy = self.rotation_policy(&conversation_id);
+        if self.is_public(conversation_id) || self.channel(conversation_id).is_some() {
+            return None;
+        }
+        let Some(Conversation::Established(em)) = self.conversations.get(&conversation_id) else {
+            return None;
+        };
+        if em.state.message_count >= policy.max_messages {
+            Some(RotationTrigger::MessageCount)
+        } else if now - em.state.last_rotation_time_ms >= policy.max_age.as_millis() as i64 {
+            Some(RotationTrigger::Age)
+        } else {
+            None
         }
-        false
     }
 
     const MESSAGES_PER_SENDER_REKEY: u32 = 5000;
@@ -938,6 +1042,9 @@ impl MerkleToxEngine {
     /// Checks if this device's per-sender SenderKey rekey is due.
     pub fn check_sender_rekey_triggers(&mut self, conversation_id: ConversationId) -> bool {
         let now = self.clock.network_time_ms();
+        if self.is_public(conversation_id) {
+            return false;
+        }
         if let Some(Conversation::Established(em)) = self.conversations.get(&conversation_id) {
             em.state.self_message_count >= Self::MESSAGES_PER_SENDER_REKEY
                 || now - em.state.self_last_rekey_time_ms >= Self::SENDER_REKEY_DURATION_MS
@@ -954,6 +1061,7 @@ impl MerkleToxEngine {
         conversation_id: ConversationId,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
+        self.ensure_can_author()?;
         let current_epoch =
             if let Some(Conversation::Established(em)) = self.conversations.get(&conversation_id) {
                 em.current_epoch()
@@ -1104,7 +1212,7 @@ impl MerkleToxEngine {
         conversation_id: ConversationId,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
-        self.rotate_conversation_key_impl(conversation_id, store, false)
+        self.rotate_conversation_key_for(conversation_id, RotationTrigger::Manual, store)
     }
 
     /// Post-revocation rotation: includes devices with only last-resort keys.
@@ -1113,15 +1221,19 @@ impl MerkleToxEngine {
         conversation_id: ConversationId,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
-        self.rotate_conversation_key_impl(conversation_id, store, true)
+        self.rotate_conversation_key_for(conversation_id, RotationTrigger::Revocation, store)
     }
 
-    fn rotate_conversation_key_impl(
+    /// Rotates the conversation key, reporting `trigger` in the
+    /// `NodeEvent::EpochRotated` it emits.
+    pub(crate) fn rotate_conversation_key_for(
         &mut self,
         conversation_id: ConversationId,
+        trigger: RotationTrigger,
         store: &dyn NodeStore,
-        post_revocation: bool,
     ) -> MerkleToxResult<Vec<Effect>> {
+        self.ensure_can_author()?;
+        let post_revocation = trigger == RotationTrigger::Revocation;
         self.clear_pending();
         let now = self.clock.network_time_ms();
         let mut new_k_conv_bytes = [0u8; 32];
@@ -1263,6 +1375,11 @@ impl MerkleToxEngine {
             self.self_ephemeral_signing_keys.remove(&old_gen);
         }
 
+        effects.push(Effect::EmitEvent(NodeEvent::EpochRotated {
+            conversation_id,
+            epoch: new_generation,
+            trigger: Some(trigger),
+        }));
         Ok(effects)
     }
 
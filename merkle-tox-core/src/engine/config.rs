@@ -0,0 +1,318 @@
+use crate::clock::ClockPolicy;
+use crate::dag::{ConversationId, MerkleNode, PhysicalDevicePk};
+use crate::engine::MerkleToxEngine;
+use crate::sync::Tier;
+use rand::Rng;
+use std::time::Duration;
+use tracing::warn;
+
+/// Default fraction of Content node signatures checked in light mode
+/// (one in `DEFAULT_SPOT_CHECK_INTERVAL`).
+pub const DEFAULT_SPOT_CHECK_INTERVAL: u32 = 16;
+
+/// How thoroughly incoming nodes are verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerificationLevel {
+    /// Verify the signature of every node.
+    #[default]
+    Full,
+    /// Verify every Admin and key distribution node, but only the signatures
+    /// of a random sample of Content nodes, on average one in
+    /// `spot_check_interval`. A device failing a spot check is verified in
+    /// full from then on.
+    ///
+    /// Meant for resource-constrained peers such as bots on small boards.
+    /// Unchecked Content nodes are stored speculatively: they are not marked
+    /// verified and do not advance the verified heads. Content keys are
+    /// shared by the whole group and only the signature proves which device
+    /// wrote a node, so any member can forge content that goes unchecked.
+    Light { spot_check_interval: u32 },
+}
+
+impl VerificationLevel {
+    /// Light verification with the default sample rate.
+    pub fn light() -> Self {
+        Self::Light {
+            spot_check_interval: DEFAULT_SPOT_CHECK_INTERVAL,
+        }
+    }
+}
+
+/// Sizing of the IBLT sketches used for set reconciliation.
+///
+/// The first sketch for a range is sized from the divergence last observed
+/// with the peer, or `initial_tier` if nothing is known yet. A sketch that
+/// fails to decode is resent one tier larger, up to `max_tier`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncConfig {
+    pub initial_tier: Tier,
+    pub max_tier: Tier,
+    /// Capacity to allow over the divergence estimate, in percent.
+    pub estimate_headroom_percent: u32,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            initial_tier: Tier::Small,
+            max_tier: Tier::Large,
+            estimate_headroom_percent: 150,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Tier of the first sketch for a range, given the number of
+    /// differences last observed with the peer.
+    pub fn start_tier(&self, estimate: Option<usize>) -> Tier {
+        let tier = match estimate {
+            Some(differences) => Tier::for_differences(
+                differences.saturating_mul(self.estimate_headroom_percent as usize) / 100,
+            ),
+            None => self.initial_tier,
+        };
+        tier.min(self.max_tier)
+    }
+}
+
+/// Limits on the reconciliation work this engine does for peers.
+///
+/// Each received sketch counts towards its sender's load. Past
+/// `free_sketches_per_window` sketches per `load_window`, every sketch needs
+/// a PoW solution and each further sketch adds one bit of difficulty, up to
+/// `max_difficulty`. Load decays by half per elapsed window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconPolicy {
+    /// Difficulty demanded for Medium and Large sketches from idle peers.
+    pub base_difficulty: u32,
+    pub max_difficulty: u32,
+    pub free_sketches_per_window: u32,
+    pub load_window: Duration,
+    /// Peer/conversation pairs reconciling at once. Tiny gossip sketches
+    /// are exempt.
+    pub max_concurrent_recons: usize,
+    /// A reconciliation counts as finished this long after its last message.
+    pub recon_idle_timeout: Duration,
+    /// Minimum time between two rounds started by the same peer for a
+    /// conversation.
+    pub round_cooldown: Duration,
+}
+
+impl Default for ReconPolicy {
+    fn default() -> Self {
+        Self {
+            base_difficulty: crate::sync::DEFAULT_RECON_DIFFICULTY,
+            max_difficulty: 24,
+            free_sketches_per_window: 16,
+            load_window: Duration::from_secs(60),
+            max_concurrent_recons: 32,
+            recon_idle_timeout: Duration::from_secs(10),
+            round_cooldown: Duration::from_secs(5),
+        }
+    }
+}
+
+/// When an admin device rotates a conversation key on its own.
+///
+/// A key is rotated once `max_messages` messages were sent or `max_age` has
+/// passed in its epoch. With `rotate_on_membership_change`, an admin also
+/// rotates after inviting a member, authorizing a device or seeing a member
+/// leave. Revocations always rotate the key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub max_messages: u32,
+    pub max_age: Duration,
+    pub rotate_on_membership_change: bool,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_messages: 5000,
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+            rotate_on_membership_change: false,
+        }
+    }
+}
+
+/// Why this device rotated a conversation key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RotationTrigger {
+    /// `RotationPolicy::max_messages` reached.
+    MessageCount,
+    /// `RotationPolicy::max_age` reached.
+    Age,
+    MembershipChange,
+    Revocation,
+    /// A peer asked for a fresh key (HandshakePulse or reinclusion).
+    PeerRequest,
+    /// Rotated through `rotate_conversation_key`.
+    Manual,
+}
+
+/// Limits on what a single sender may leave in the opaque store, and when
+/// misbehaving senders get blacklisted.
+///
+/// Senders collect spam points for offenses such as undecodable messages or
+/// wrong PoW solutions. Points halve every `score_half_life`; reaching
+/// `blacklist_score` blacklists the sender for the next tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuarantinePolicy {
+    /// Opaque wire nodes kept per sender and conversation.
+    pub max_opaque_nodes_per_sender: usize,
+    /// Opaque bytes kept per sender and conversation.
+    pub max_opaque_bytes_per_sender: usize,
+    pub blacklist_score: u32,
+    pub score_half_life: Duration,
+}
+
+impl Default for QuarantinePolicy {
+    fn default() -> Self {
+        Self {
+            max_opaque_nodes_per_sender: tox_proto::constants::MAX_OPAQUE_REQUESTS_PER_VOUCHER,
+            max_opaque_bytes_per_sender: tox_proto::constants::OPAQUE_STORE_QUOTA / 4,
+            blacklist_score: 100,
+            score_half_life: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Engine-wide behaviour settings.
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    pub verification_level: VerificationLevel,
+    pub sync: SyncConfig,
+    pub recon: ReconPolicy,
+    /// Received wire nodes verified per `Effect::VerifyBatch`. 0 verifies
+    /// each node as it arrives.
+    pub verify_batch_size: usize,
+    pub clock_policy: ClockPolicy,
+    pub quarantine: QuarantinePolicy,
+    /// Follow and verify conversations without authoring nodes, e.g. for
+    /// auditors and archive bots. Authoring fails with
+    /// `MerkleToxError::ReadOnly`, and `FEATURE_READ_ONLY` is advertised to
+    /// peers.
+    pub read_only: bool,
+}
+
+impl MerkleToxEngine {
+    /// Whether the signature of `node` may be skipped under the configured
+    /// verification level, storing it speculatively. Only Content nodes of
+    /// authorized devices that have not failed a spot check qualify.
+    pub(crate) fn skip_content_signature(
+        &self,
+        conversation_id: ConversationId,
+        node: &MerkleNode,
+        is_authorized: bool,
+    ) -> bool {
+        let VerificationLevel::Light {
+            spot_check_interval,
+        } = self.config.verification_level
+        else {
+            return false;
+        };
+        if !is_authorized
+            || node.skips_ratchet()
+            || self
+                .spot_check_failures
+                .contains(&(conversation_id, node.sender_pk))
+        {
+            return false;
+        }
+        spot_check_interval > 1 && self.rng.lock().gen_range(0..spot_check_interval) != 0
+    }
+
+    /// Records a failed Content signature check. In light mode the sender is
+    /// verified in full afterwards.
+    pub(crate) fn record_spot_check_failure(
+        &mut self,
+        conversation_id: ConversationId,
+        sender_pk: PhysicalDevicePk,
+    ) {
+        if matches!(
+            self.config.verification_level,
+            VerificationLevel::Light { .. }
+        ) && self
+            .spot_check_failures
+            .insert((conversation_id, sender_pk))
+        {
+            warn!(
+                "Spot check failed for {:?} in {:?}; verifying all of its nodes",
+                sender_pk, conversation_id
+            );
+        }
+    }
+}
+
+impl MerkleToxEngine {
+    /// Tier of the first sketch for a range reconciled with `peer`.
+    pub fn recon_start_tier(
+        &self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+    ) -> Tier {
+        self.config
+            .sync
+            .start_tier(self.recon_estimates.get(&(peer, conversation_id)).copied())
+    }
+
+    /// Applies the sketch sizing config and divergence estimate to the
+    /// session with `peer`.
+    pub(crate) fn refresh_recon_tiers(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+    ) {
+        let start_tier = self.recon_start_tier(peer, conversation_id);
+        let max_tier = self.config.sync.max_tier;
+        if let Some(session) = self.sessions.get_mut(&(peer, conversation_id)) {
+            let common = session.common_mut();
+            common.start_tier = start_tier;
+            common.max_tier = max_tier;
+        }
+    }
+
+    /// Records that a sketch of `tier` exchanged with `peer` could not be
+    /// decoded, so the divergence is larger than the tier can hold.
+    pub(crate) fn record_recon_failed(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        tier: Tier,
+    ) {
+        let estimate = self
+            .recon_estimates
+            .entry((peer, conversation_id))
+            .or_insert(0);
+        *estimate = (*estimate).max(tier.d_max() + 1);
+    }
+
+    /// Records the outcome of decoding a sketch of `tier` from `peer`:
+    /// the number of differences, or `None` if decoding failed.
+    pub(crate) fn record_sketch_outcome(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        tier: Tier,
+        decoded: Option<usize>,
+    ) {
+        match decoded {
+            Some(differences) => {
+                self.recon_estimates
+                    .insert((peer, conversation_id), differences);
+            }
+            None => {
+                self.record_recon_failed(peer, conversation_id, tier);
+                // Smaller sketches failing is the normal signal to escalate;
+                // only a failing sketch of the largest tier counts against
+                // the peer.
+                if tier == Tier::Large {
+                    if let Some(budget) = self.sketch_cpu_budgets.get_mut(&peer) {
+                        budget.remaining_ms = 0.0;
+                    }
+                    self.blacklist_escalate(peer);
+                }
+            }
+        }
+    }
+}
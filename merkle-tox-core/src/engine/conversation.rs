@@ -10,6 +10,25 @@ use std::collections::{HashMap, HashSet};
 pub const FLAG_ADMIN_ONLY_INVITE: u64 = 0x01;
 /// Genesis flag: any member with MESSAGE permission may invite.
 pub const FLAG_MEMBER_INVITE: u64 = 0x02;
+/// Genesis flag: public conversation. Content nodes are device-signed
+/// cleartext, no conversation keys are exchanged, and history is served to
+/// anyone.
+pub const FLAG_PUBLIC: u64 = 0x04;
+/// Genesis flag bits 8..16: leading zero bits of the proof-of-work a new
+/// device attaches to its first Announcement to join without an invite.
+/// 0 disables joining by proof-of-work.
+pub const JOIN_POW_SHIFT: u32 = 8;
+pub const JOIN_POW_MASK: u64 = 0xFF << JOIN_POW_SHIFT;
+
+/// Genesis flags requiring a join proof-of-work of `difficulty` bits.
+pub fn join_pow_flags(difficulty: u8) -> u64 {
+    (difficulty as u64) << JOIN_POW_SHIFT
+}
+
+/// Join proof-of-work difficulty encoded in Genesis flags.
+pub fn join_pow_difficulty(flags: u64) -> u32 {
+    ((flags & JOIN_POW_MASK) >> JOIN_POW_SHIFT) as u32
+}
 
 #[derive(Clone)]
 pub struct Pending {
@@ -81,6 +100,22 @@ impl Conversation {
         }
     }
 
+    pub fn is_public(&self) -> bool {
+        self.genesis_flags() & FLAG_PUBLIC != 0
+    }
+
+    /// Key of the reconciliation sketches exchanged for this conversation.
+    /// Public conversations use unkeyed sketches, as their members hold no
+    /// conversation key.
+    pub fn iblt_key(&self) -> Option<[u8; 32]> {
+        match self {
+            Conversation::Established(em) if !self.is_public() => em
+                .get_keys(em.current_epoch())
+                .map(|k| crate::crypto::derive_k_iblt(&k.k_conv, &em.id)),
+            _ => None,
+        }
+    }
+
     pub fn set_genesis_flags(&mut self, flags: u64) {
         match self {
             Conversation::Pending(c) => c.state.genesis_flags = flags,
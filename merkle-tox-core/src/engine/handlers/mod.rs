@@ -1,11 +1,19 @@
+use crate::capabilities::{
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, feature_names, negotiate, required_feature,
+};
 use crate::cas::{BlobData, SwarmSync};
-use crate::dag::{ConversationId, PhysicalDevicePk};
+use crate::dag::{ConversationId, MerkleNode, NodeHash, PhysicalDevicePk, WireNode};
+use crate::engine::config::RotationTrigger;
+use crate::engine::quarantine::SpamOffense;
 use crate::engine::session::{Active, Handshake, PeerSession, SyncSession};
 use crate::engine::{CpuBudget, Effect, EngineStore, MerkleToxEngine};
 use crate::error::MerkleToxResult;
-use crate::sync::{BlobStore, DecodingResult, NodeStore, Tier};
+use crate::event_log::{ProtocolEventKind, short_hex};
+use crate::sync::{
+    BlobStore, DecodingResult, FLAG_HEADS_RESYNC, NodeStore, SyncHeads, SyncHeadsDelta, Tier,
+};
 use crate::{NodeEvent, ProtocolMessage};
-use tracing::{debug, info};
+use tracing::{debug, debug_span, info};
 
 impl MerkleToxEngine {
     /// Handles an incoming protocol message from a peer.
@@ -15,8 +23,186 @@ impl MerkleToxEngine {
         message: ProtocolMessage,
         store: &dyn NodeStore,
         blob_store: Option<&dyn BlobStore>,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let conversation_id = message.conversation_id();
+        let span = debug_span!(
+            "handle_message",
+            peer = %short_hex(sender_pk.as_bytes()),
+            conversation = %conversation_id
+                .map(|c| short_hex(c.as_bytes()))
+                .unwrap_or_default(),
+            message = message.name(),
+        );
+        let _enter = span.enter();
+
+        let now_ms = self.clock.network_time_ms();
+        self.event_log.record(
+            now_ms,
+            Some(sender_pk),
+            conversation_id,
+            ProtocolEventKind::Received(message.name()),
+        );
+        let mut result = self.dispatch_message(sender_pk, message, store, blob_store);
+        if let Ok(effects) = &mut result {
+            self.piggyback_heads(effects, store);
+        }
+        match &result {
+            Ok(effects) => self.log_effects(now_ms, effects),
+            Err(e) => self.event_log.record(
+                now_ms,
+                Some(sender_pk),
+                conversation_id,
+                ProtocolEventKind::Error(e.to_string()),
+            ),
+        }
+        result
+    }
+
+    /// Attaches pending head deltas to the last `MerkleNode` sent to each
+    /// delta-capable peer, so no separate head announcement is needed.
+    fn piggyback_heads(&mut self, effects: &mut [Effect], store: &dyn NodeStore) {
+        for effect in effects.iter_mut().rev() {
+            let Effect::SendPacket(peer, message) = effect else {
+                continue;
+            };
+            let ProtocolMessage::MerkleNode {
+                conversation_id,
+                hash,
+                node,
+            } = message
+            else {
+                continue;
+            };
+            let Some(PeerSession::Active(s)) = self.sessions.get_mut(&(*peer, *conversation_id))
+            else {
+                continue;
+            };
+            if !s.common.heads_dirty || !s.supports_delta_heads() {
+                continue;
+            }
+            s.common.heads_dirty = false;
+            if let Some(heads) = s.make_sync_heads_delta(store) {
+                *message = ProtocolMessage::MerkleNodeWithHeads {
+                    conversation_id: *conversation_id,
+                    hash: *hash,
+                    node: node.clone(),
+                    heads,
+                };
+            }
+        }
+    }
+
+    /// Negotiates with `sender_pk` from its `CapsAnnounce` or `CapsAck`,
+    /// updates its active sessions and activates those still in the
+    /// handshake. Returns false if its protocol version is too old; those
+    /// sessions get a `HandshakeError`.
+    fn complete_handshake(
+        &mut self,
+        sender_pk: PhysicalDevicePk,
+        version: u32,
+        features: u64,
+        store: &dyn NodeStore,
+        effects: &mut Vec<Effect>,
+    ) -> bool {
+        let handshaking: Vec<ConversationId> = self
+            .sessions
+            .iter()
+            .filter(|((pk, _), session)| {
+                pk == &sender_pk && matches!(session, PeerSession::Handshake(_))
+            })
+            .map(|((_, cid), _)| *cid)
+            .collect();
+        let Some(negotiated) = negotiate(self.advertised_features(), version, features) else {
+            info!(
+                "Refusing handshake with {:?}: protocol version {} is older than {}",
+                sender_pk, version, MIN_PROTOCOL_VERSION
+            );
+            for conversation_id in handshaking {
+                effects.push(Effect::SendPacket(
+                    sender_pk,
+                    ProtocolMessage::HandshakeError {
+                        conversation_id,
+                        reason: format!("unsupported protocol version {}", version),
+                    },
+                ));
+            }
+            return false;
+        };
+        debug!(
+            "Negotiated version {} with {:?}: {:?}",
+            negotiated.version,
+            sender_pk,
+            feature_names(negotiated.features)
+        );
+        self.peer_capabilities.insert(sender_pk, negotiated);
+        // A later announcement replaces what active sessions agreed on.
+        for ((pk, _), session) in self.sessions.iter_mut() {
+            if pk == &sender_pk {
+                session.common_mut().negotiated = negotiated;
+            }
+        }
+
+        for cid in handshaking {
+            if let Some(PeerSession::Handshake(s)) = self.sessions.remove(&(sender_pk, cid)) {
+                let mut active = s.activate_with(negotiated);
+                // Send heads immediately on handshake
+                effects.push(Effect::SendPacket(
+                    sender_pk,
+                    ProtocolMessage::SyncHeads(active.make_initial_sync_heads(store)),
+                ));
+                self.sessions
+                    .insert((sender_pk, cid), PeerSession::Active(active));
+            }
+        }
+        true
+    }
+
+    fn handle_sync_heads_delta(
+        &mut self,
+        sender_pk: PhysicalDevicePk,
+        delta: SyncHeadsDelta,
+        store: &dyn NodeStore,
+        effects: &mut Vec<Effect>,
+    ) {
+        let conv_id = delta.conversation_id;
+        let fetch_now = self.may_fetch_now(sender_pk, conv_id);
+        let Some(PeerSession::Active(s)) = self.sessions.get_mut(&(sender_pk, conv_id)) else {
+            return;
+        };
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        if !s.handle_sync_heads_delta(delta, &overlay) {
+            effects.push(Effect::SendPacket(
+                sender_pk,
+                ProtocolMessage::SyncHeads(SyncHeads {
+                    conversation_id: conv_id,
+                    heads: Vec::new(),
+                    flags: FLAG_HEADS_RESYNC,
+                    anchor_hash: None,
+                }),
+            ));
+        }
+        if fetch_now && let Some(req) = s.next_fetch_batch(tox_proto::constants::MAX_BATCH_SIZE) {
+            effects.push(Effect::SendPacket(
+                sender_pk,
+                ProtocolMessage::FetchBatchReq(req),
+            ));
+        }
+    }
+
+    fn dispatch_message(
+        &mut self,
+        sender_pk: PhysicalDevicePk,
+        message: ProtocolMessage,
+        store: &dyn NodeStore,
+        blob_store: Option<&dyn BlobStore>,
     ) -> MerkleToxResult<Vec<Effect>> {
         self.clear_pending();
+        if let Some(conversation_id) = message.conversation_id() {
+            self.ensure_conversation_loaded(conversation_id, store)?;
+        }
 
         // Blacklist check: reject messages from blacklisted peers
         let now_bl = self.clock.network_time_ms();
@@ -30,86 +216,57 @@ impl MerkleToxEngine {
             return Ok(Vec::new());
         }
 
+        if let Some(feature) = required_feature(&message) {
+            let negotiated = message
+                .conversation_id()
+                .and_then(|cid| self.sessions.get(&(sender_pk, cid)))
+                .map_or_else(|| self.negotiated(&sender_pk), |s| s.common().negotiated);
+            if !negotiated.has(feature) {
+                debug!(
+                    "Dropping {} from {:?}: {:?} not negotiated",
+                    message.name(),
+                    sender_pk,
+                    feature_names(feature)
+                );
+                return Ok(Vec::new());
+            }
+        }
+
         debug!(
             "Engine handling message from {:?}: {:?}",
             sender_pk, message
         );
         let mut effects = Vec::new();
+        let announced_heads = match &message {
+            ProtocolMessage::SyncHeads(heads) => Some(heads.conversation_id),
+            ProtocolMessage::SyncHeadsDelta(delta) => Some(delta.conversation_id),
+            ProtocolMessage::MerkleNodeWithHeads {
+                conversation_id, ..
+            } => Some(*conversation_id),
+            _ => None,
+        };
 
         match message {
-            ProtocolMessage::CapsAnnounce {
-                version: _,
-                features,
-            } => {
-                let mut sessions_to_activate = Vec::new();
-                for ((peer_pk, cid), session) in self.sessions.iter() {
-                    if peer_pk == &sender_pk
-                        && let PeerSession::Handshake(_) = session
-                    {
-                        sessions_to_activate.push(*cid);
-                    }
-                }
-
-                for cid in sessions_to_activate {
-                    if let Some(PeerSession::Handshake(s)) = self.sessions.remove(&(sender_pk, cid))
-                    {
-                        let mut active = s.activate(features);
-                        // Send heads immediately on handshake
-                        effects.push(Effect::SendPacket(
-                            sender_pk,
-                            ProtocolMessage::SyncHeads(
-                                active.make_sync_heads_with_store(0, Some(store)),
-                            ),
-                        ));
-                        active.common.heads_dirty = false;
-                        self.sessions
-                            .insert((sender_pk, cid), PeerSession::Active(active));
-                    }
+            ProtocolMessage::CapsAnnounce { version, features } => {
+                if self.complete_handshake(sender_pk, version, features, store, &mut effects) {
+                    effects.push(Effect::SendPacket(
+                        sender_pk,
+                        ProtocolMessage::CapsAck {
+                            version: PROTOCOL_VERSION,
+                            features: self.advertised_features(),
+                        },
+                    ));
+                    effects.push(Effect::EmitEvent(NodeEvent::PeerHandshakeComplete {
+                        peer_pk: sender_pk,
+                    }));
                 }
-
-                effects.push(Effect::SendPacket(
-                    sender_pk,
-                    ProtocolMessage::CapsAck {
-                        version: 1,
-                        features: 0,
-                    },
-                ));
-                effects.push(Effect::EmitEvent(NodeEvent::PeerHandshakeComplete {
-                    peer_pk: sender_pk,
-                }));
             }
-            ProtocolMessage::CapsAck {
-                version: _,
-                features,
-            } => {
-                let mut sessions_to_activate = Vec::new();
-                for ((peer_pk, cid), session) in self.sessions.iter() {
-                    if peer_pk == &sender_pk
-                        && let PeerSession::Handshake(_) = session
-                    {
-                        sessions_to_activate.push(*cid);
-                    }
-                }
-
-                for cid in sessions_to_activate {
-                    if let Some(PeerSession::Handshake(s)) = self.sessions.remove(&(sender_pk, cid))
-                    {
-                        let mut active = s.activate(features);
-                        // Send heads immediately on handshake
-                        effects.push(Effect::SendPacket(
-                            sender_pk,
-                            ProtocolMessage::SyncHeads(
-                                active.make_sync_heads_with_store(0, Some(store)),
-                            ),
-                        ));
-                        active.common.heads_dirty = false;
-                        self.sessions
-                            .insert((sender_pk, cid), PeerSession::Active(active));
-                    }
+            ProtocolMessage::CapsAck { version, features } => {
+                if self.complete_handshake(sender_pk, version, features, store, &mut effects) {
+                    effects.push(Effect::EmitEvent(NodeEvent::PeerHandshakeComplete {
+                        peer_pk: sender_pk,
+                    }));
                 }
-                effects.push(Effect::EmitEvent(NodeEvent::PeerHandshakeComplete {
-                    peer_pk: sender_pk,
-                }));
             }
             ProtocolMessage::SyncHeads(heads) => {
                 let conv_id = heads.conversation_id;
@@ -132,10 +289,13 @@ impl MerkleToxEngine {
                         && let Some(PeerSession::Handshake(s)) =
                             self.sessions.remove(&(sender_pk, conv_id))
                     {
-                        self.sessions
-                            .insert((sender_pk, conv_id), PeerSession::Active(s.activate(0)));
+                        self.sessions.insert(
+                            (sender_pk, conv_id),
+                            PeerSession::Active(s.activate_with(self.negotiated(&sender_pk))),
+                        );
                     }
 
+                    let fetch_now = self.may_fetch_now(sender_pk, conv_id);
                     if let Some(PeerSession::Active(s)) =
                         self.sessions.get_mut(&(sender_pk, conv_id))
                     {
@@ -147,7 +307,10 @@ impl MerkleToxEngine {
                             },
                         );
 
-                        if let Some(req) = s.next_fetch_batch(tox_proto::constants::MAX_BATCH_SIZE)
+                        // Otherwise the next poll fetches in priority order.
+                        if fetch_now
+                            && let Some(req) =
+                                s.next_fetch_batch(tox_proto::constants::MAX_BATCH_SIZE)
                         {
                             effects.push(Effect::SendPacket(
                                 sender_pk,
@@ -178,24 +341,44 @@ impl MerkleToxEngine {
                         && let Some(PeerSession::Handshake(s)) =
                             self.sessions.remove(&(sender_pk, conv_id))
                     {
-                        self.sessions
-                            .insert((sender_pk, conv_id), PeerSession::Active(s.activate(0)));
+                        self.sessions.insert(
+                            (sender_pk, conv_id),
+                            PeerSession::Active(s.activate_with(self.negotiated(&sender_pk))),
+                        );
                     }
 
+                    let tier = Tier::from_cell_count(sketch.cells.len());
+                    let pow_difficulty = match self.admit_sketch(sender_pk, conv_id, tier, now) {
+                        Ok(difficulty) => difficulty,
+                        Err(retry_after) => {
+                            effects.push(Effect::SendPacket(
+                                sender_pk,
+                                rate_limited(conv_id, retry_after),
+                            ));
+                            return Ok(effects);
+                        }
+                    };
+
+                    let floor = self.history_floor(conv_id, &sender_pk);
                     if let Some(PeerSession::Active(s)) =
                         self.sessions.get_mut(&(sender_pk, conv_id))
                     {
-                        // Protection: Medium and Large sketches require PoW
-                        let tier = Tier::from_cell_count(sketch.cells.len());
-                        if tier == Tier::Medium || tier == Tier::Large {
-                            let nonce =
-                                s.generate_challenge(sketch.clone(), now, &mut self.rng.lock());
+                        // Protection: Medium and Large sketches, and any
+                        // sketch from a peer over its load allowance, require PoW
+                        if let Some(difficulty) = pow_difficulty {
+                            let difficulty = difficulty.max(s.common.effective_difficulty);
+                            let nonce = s.generate_challenge_with_difficulty(
+                                sketch.clone(),
+                                difficulty,
+                                now,
+                                &mut self.rng.lock(),
+                            );
                             effects.push(Effect::SendPacket(
                                 sender_pk,
                                 ProtocolMessage::ReconPowChallenge {
                                     conversation_id: sketch.conversation_id,
                                     nonce,
-                                    difficulty: s.common.effective_difficulty,
+                                    difficulty,
                                 },
                             ));
                             return Ok(effects);
@@ -231,10 +414,9 @@ impl MerkleToxEngine {
                             }
                             _ => None,
                         };
-                        let k_iblt =
-                            keys.map(|k| crate::crypto::derive_k_iblt(&k.k_conv, &conv_id));
+                        let k_iblt = self.conversations.get(&conv_id).and_then(|c| c.iblt_key());
 
-                        let sketch_ok = process_sketch(
+                        let decoded = process_sketch(
                             s,
                             sender_pk,
                             sketch,
@@ -242,17 +424,12 @@ impl MerkleToxEngine {
                                 store,
                                 cache: &self.pending_cache,
                             },
+                            floor,
                             keys,
                             k_iblt,
                             &mut effects,
                         )?;
-                        if !sketch_ok {
-                            // Drain remaining budget and escalate blacklist
-                            if let Some(budget) = self.sketch_cpu_budgets.get_mut(&sender_pk) {
-                                budget.remaining_ms = 0.0;
-                            }
-                            self.blacklist_escalate(sender_pk);
-                        }
+                        self.record_sketch_outcome(sender_pk, conv_id, tier, decoded);
                     }
                 }
             }
@@ -260,11 +437,34 @@ impl MerkleToxEngine {
                 conversation_id,
                 range,
             } => {
+                self.refresh_recon_tiers(sender_pk, conversation_id);
+                let k_iblt = self
+                    .conversations
+                    .get(&conversation_id)
+                    .and_then(|c| c.iblt_key());
+                let mut failed = None;
                 if let Some(PeerSession::Active(session)) =
                     self.sessions.get_mut(&(sender_pk, conversation_id))
                 {
-                    session.handle_sync_recon_fail(range);
-                    // The next poll will trigger a larger sketch if needed
+                    failed = session.get_iblt_tier(&range);
+                    session.handle_sync_recon_fail(range.clone());
+                    // Resend right away with the larger sketch rather than
+                    // waiting for the next reconciliation round.
+                    if let Some(tier) = session.get_iblt_tier(&range) {
+                        let overlay = EngineStore {
+                            store,
+                            cache: &self.pending_cache,
+                        };
+                        effects.push(Effect::SendPacket(
+                            sender_pk,
+                            ProtocolMessage::SyncSketch(
+                                session.make_sync_sketch_keyed(range, tier, &overlay, k_iblt)?,
+                            ),
+                        ));
+                    }
+                }
+                if let Some(tier) = failed {
+                    self.record_recon_failed(sender_pk, conversation_id, tier);
                 }
             }
             ProtocolMessage::SyncShardChecksums {
@@ -274,6 +474,13 @@ impl MerkleToxEngine {
                 let conv_id = conversation_id;
                 {
                     let now = self.clock.time_provider().now_instant();
+                    if let Err(retry_after) = self.admit_recon_round(sender_pk, conv_id, now) {
+                        effects.push(Effect::SendPacket(
+                            sender_pk,
+                            rate_limited(conv_id, retry_after),
+                        ));
+                        return Ok(effects);
+                    }
                     let entry = self.sessions.entry((sender_pk, conv_id));
                     let session = entry.or_insert_with(|| {
                         PeerSession::Handshake(SyncSession::<Handshake>::new(
@@ -291,10 +498,13 @@ impl MerkleToxEngine {
                         && let Some(PeerSession::Handshake(s)) =
                             self.sessions.remove(&(sender_pk, conv_id))
                     {
-                        self.sessions
-                            .insert((sender_pk, conv_id), PeerSession::Active(s.activate(0)));
+                        self.sessions.insert(
+                            (sender_pk, conv_id),
+                            PeerSession::Active(s.activate_with(self.negotiated(&sender_pk))),
+                        );
                     }
 
+                    self.refresh_recon_tiers(sender_pk, conv_id);
                     if let Some(PeerSession::Active(s)) =
                         self.sessions.get_mut(&(sender_pk, conv_id))
                     {
@@ -302,12 +512,7 @@ impl MerkleToxEngine {
                             store,
                             cache: &self.pending_cache,
                         };
-                        let k_iblt = match self.conversations.get(&conv_id) {
-                            Some(crate::engine::Conversation::Established(em)) => em
-                                .get_keys(em.current_epoch())
-                                .map(|k| crate::crypto::derive_k_iblt(&k.k_conv, &conv_id)),
-                            _ => None,
-                        };
+                        let k_iblt = self.conversations.get(&conv_id).and_then(|c| c.iblt_key());
                         let different = s.handle_sync_shard_checksums(shards, &overlay)?;
                         for range in different {
                             if let Some(tier) = s.get_iblt_tier(&range) {
@@ -406,23 +611,29 @@ impl MerkleToxEngine {
                 nonce,
                 solution,
             } => {
+                let floor = self.history_floor(conversation_id, &sender_pk);
                 if let Some(PeerSession::Active(session)) =
                     self.sessions.get_mut(&(sender_pk, conversation_id))
                 {
                     let now = self.clock.time_provider().now_instant();
-                    if session.verify_solution(nonce, solution, now)
-                        && let Some(sketch) = session.take_pending_sketch(nonce)
-                    {
+                    let challenged = session.common.pending_challenges.contains_key(&nonce);
+                    let valid = session.verify_solution(nonce, solution, now);
+                    if challenged && !valid {
+                        self.report_spam(sender_pk, SpamOffense::InvalidPow);
+                    } else if valid && let Some(sketch) = session.take_pending_sketch(nonce) {
                         let keys = match self.conversations.get(&conversation_id) {
                             Some(crate::engine::Conversation::Established(em)) => {
                                 em.get_keys(em.current_epoch())
                             }
                             _ => None,
                         };
-                        let k_iblt =
-                            keys.map(|k| crate::crypto::derive_k_iblt(&k.k_conv, &conversation_id));
+                        let k_iblt = self
+                            .conversations
+                            .get(&conversation_id)
+                            .and_then(|c| c.iblt_key());
 
-                        let sketch_ok = process_sketch(
+                        let tier = Tier::from_cell_count(sketch.cells.len());
+                        let decoded = process_sketch(
                             session,
                             sender_pk,
                             sketch,
@@ -430,16 +641,12 @@ impl MerkleToxEngine {
                                 store,
                                 cache: &self.pending_cache,
                             },
+                            floor,
                             keys,
                             k_iblt,
                             &mut effects,
                         )?;
-                        if !sketch_ok {
-                            if let Some(budget) = self.sketch_cpu_budgets.get_mut(&sender_pk) {
-                                budget.remaining_ms = 0.0;
-                            }
-                            self.blacklist_escalate(sender_pk);
-                        }
+                        self.record_sketch_outcome(sender_pk, conversation_id, tier, decoded);
                     }
                 }
             }
@@ -450,10 +657,17 @@ impl MerkleToxEngine {
                         store,
                         cache: &self.pending_cache,
                     };
+                    let floor = self.history_floor(conv_id, &sender_pk);
 
                     for hash in req.hashes {
-                        // 1. Try to find an existing wire node (already encrypted)
-                        if let Some(wire_node) = overlay.get_wire_node(&hash) {
+                        if let Some(wire_node) = super::outbox::wire_node_for(&overlay, &hash) {
+                            if !super::history::is_visible(&wire_node, floor) {
+                                debug!(
+                                    "Withholding {:?} from {:?}: before its join",
+                                    hash, sender_pk
+                                );
+                                continue;
+                            }
                             effects.push(Effect::SendPacket(
                                 sender_pk,
                                 ProtocolMessage::MerkleNode {
@@ -462,22 +676,6 @@ impl MerkleToxEngine {
                                     node: wire_node,
                                 },
                             ));
-                            continue;
-                        }
-
-                        // 2. Fallback: pack the node on the fly
-                        if let Some(node) = overlay.get_node(&hash) {
-                            let pack_keys = crate::crypto::PackKeys::Exception;
-                            if let Ok(wire_node) = node.pack_wire(&pack_keys, true) {
-                                effects.push(Effect::SendPacket(
-                                    sender_pk,
-                                    ProtocolMessage::MerkleNode {
-                                        conversation_id: conv_id,
-                                        hash,
-                                        node: wire_node,
-                                    },
-                                ));
-                            }
                         }
                     }
                 }
@@ -487,150 +685,25 @@ impl MerkleToxEngine {
                 hash,
                 node: wire_node,
             } => {
-                let conv_id = conversation_id;
-                {
-                    let mut unpacked = None;
-
-                    // Always store the wire node so we can re-distribute it and try to unpack later
-                    effects.push(Effect::WriteWireNode(conv_id, hash, wire_node.clone()));
-                    if let Some(PeerSession::Active(_session)) =
-                        self.sessions.get_mut(&(sender_pk, conv_id))
-                    {
-                        let overlay = EngineStore {
-                            store,
-                            cache: &self.pending_cache,
-                        };
-                        overlay.put_wire_node(&conv_id, &hash, wire_node.clone())?;
-                    }
-
-                    // Try exception (cleartext) unpack first: covers Admin, KeyWrap, etc.
-                    if !wire_node.flags.contains(crate::dag::WireFlags::ENCRYPTED)
-                        && let Ok(mut node) =
-                            crate::dag::MerkleNode::unpack_wire_exception(&wire_node)
-                    {
-                        // unpack_wire_exception sets author_pk = sender_pk.to_logical(),
-                        // which is only correct for admin nodes. For SKD/KeyWrap/HistoryExport
-                        // nodes, sender_pk is a device key and author_pk should be the
-                        // corresponding master (logical) key. Resolve via identity_manager.
-                        let all_senders = self
-                            .identity_manager
-                            .list_all_authorized_sender_pairs(conv_id);
-                        if let Some((_, logical_pk)) =
-                            all_senders.iter().find(|(d, _)| *d == node.sender_pk)
-                        {
-                            node.author_pk = *logical_pk;
-                        }
-                        unpacked = Some(node);
-                    }
-
-                    // For encrypted content nodes, use sender identification
-                    if unpacked.is_none()
-                        && let Some(crate::engine::Conversation::Established(em)) =
-                            self.conversations.get(&conv_id)
-                    {
-                        let mut all_senders = self
-                            .identity_manager
-                            .list_all_authorized_sender_pairs(conv_id);
-                        // Also try the network-level sender as a candidate
-                        if !all_senders.iter().any(|(d, _)| *d == sender_pk) {
-                            all_senders.push((sender_pk, sender_pk.to_logical()));
-                        }
-                        unpacked = em.identify_sender_and_unpack(&wire_node, &all_senders);
-
-                        // Fallback: try HistoryExport room-wide export keys
-                        if unpacked.is_none() {
-                            unpacked = em.try_unpack_history_export(&wire_node, &all_senders);
-                        }
-                    }
-
-                    if let Some(node) = unpacked {
-                        // Use handle_node_internal_ext directly (not handle_node)
-                        // to avoid clearing the pending cache. The wire node was
-                        // stored in the cache above and must remain accessible
-                        // for encrypt-then-sign verification.
-                        let node_effects =
-                            self.handle_node_internal_ext(conv_id, node, store, blob_store, true)?;
-                        effects.extend(node_effects);
-                        // Remove from opaque tracking if it was previously stored
-                        if let Some((total, entries)) = self.opaque_store_usage.get_mut(&conv_id)
-                            && let Some(pos) = entries.iter().position(|(h, _, _, _)| *h == hash)
-                        {
-                            *total -= entries[pos].1;
-                            entries.swap_remove(pos);
-                        }
-                    } else {
-                        debug!(
-                            "Failed to unpack wire node: {}",
-                            hex::encode(hash.as_bytes())
-                        );
-                        // Track opaque store usage for quota enforcement
-                        let wire_size = wire_node.payload_data.len()
-                            + wire_node.encrypted_routing.len()
-                            + wire_node.parents.len() * 32;
-                        let now_ms = self.clock.network_time_ms();
-                        let (total, entries) = self
-                            .opaque_store_usage
-                            .entry(conv_id)
-                            .or_insert_with(|| (0, Vec::new()));
-                        // Per-sender opaque quota
-                        let sender_count = entries
-                            .iter()
-                            .filter(|(_, _, _, spk)| *spk == sender_pk)
-                            .count();
-                        if sender_count >= tox_proto::constants::MAX_OPAQUE_REQUESTS_PER_VOUCHER {
-                            debug!(
-                                "Per-sender opaque quota exceeded for {:?} in {:?}",
-                                sender_pk, conv_id
-                            );
-                        } else {
-                            *total += wire_size;
-                            entries.push((hash, wire_size, now_ms, sender_pk));
-                        }
-                        // Evict cold-first, then by lowest rank within tier
-                        // Filter out promotion-locked entries before eviction
-                        while *total > tox_proto::constants::OPAQUE_STORE_QUOTA
-                            && entries
-                                .iter()
-                                .any(|(h, _, _, _)| !self.promotion_locked.contains(h))
-                        {
-                            let max_rank = store
-                                .get_heads(&conv_id)
-                                .iter()
-                                .filter_map(|h| store.get_rank(h))
-                                .max()
-                                .unwrap_or(0);
-                            let hot_cutoff =
-                                max_rank.saturating_sub(tox_proto::constants::HOT_WINDOW_RANKS);
-                            entries.sort_by(|a, b| {
-                                let a_locked = self.promotion_locked.contains(&a.0);
-                                let b_locked = self.promotion_locked.contains(&b.0);
-                                // Locked entries sort LAST (never evicted)
-                                match (a_locked, b_locked) {
-                                    (true, false) => return std::cmp::Ordering::Greater,
-                                    (false, true) => return std::cmp::Ordering::Less,
-                                    _ => {}
-                                }
-                                let a_rank = store.get_rank(&a.0).unwrap_or(0);
-                                let b_rank = store.get_rank(&b.0).unwrap_or(0);
-                                let a_cold = a_rank < hot_cutoff;
-                                let b_cold = b_rank < hot_cutoff;
-                                // Cold before hot; within same tier, lowest rank first
-                                match (a_cold, b_cold) {
-                                    (true, false) => std::cmp::Ordering::Less,
-                                    (false, true) => std::cmp::Ordering::Greater,
-                                    _ => a_rank.cmp(&b_rank),
-                                }
-                            });
-                            let (evicted_hash, evicted_size, _, _) = entries.remove(0);
-                            *total -= evicted_size;
-                            effects.push(Effect::DeleteWireNode(conv_id, evicted_hash));
-                        }
-                        if let Some(PeerSession::Active(session)) =
-                            self.sessions.get_mut(&(sender_pk, conv_id))
-                        {
-                            session.on_wire_node_received(hash, &wire_node, store);
-                        }
-                    }
+                if self.config.verify_batch_size > 0 {
+                    effects.extend(self.queue_wire_node(
+                        sender_pk,
+                        conversation_id,
+                        hash,
+                        wire_node,
+                        store,
+                        blob_store,
+                    )?);
+                } else {
+                    self.handle_wire_node(
+                        sender_pk,
+                        conversation_id,
+                        hash,
+                        wire_node,
+                        store,
+                        blob_store,
+                        &mut effects,
+                    )?;
                 }
             }
             ProtocolMessage::BlobQuery(hash) => {
@@ -660,15 +733,31 @@ impl MerkleToxEngine {
                     }
                 } else if let Some(bs) = blob_store
                     && !bs.has_blob(&blob_hash)
+                    && let stored = bs.get_blob_info(&blob_hash)
+                    && stored
+                        .as_ref()
+                        .is_none_or(|i| i.status != crate::cas::BlobStatus::Cancelled)
                 {
                     tracing::debug!(
                         "Starting swarm sync for blob {:?} with seeder {:?}",
                         blob_hash,
                         sender_pk
                     );
-                    let mut local_info = info.clone();
-                    local_info.status = crate::cas::BlobStatus::Pending;
-                    let mut sync = SwarmSync::new(local_info.clone());
+                    // Keep the chunks a previous partial download already stored.
+                    let local_info = match stored {
+                        Some(mut stored)
+                            if stored.status.is_incomplete() && stored.size == info.size =>
+                        {
+                            stored.bao_root = stored.bao_root.or(info.bao_root);
+                            stored
+                        }
+                        _ => crate::cas::BlobInfo {
+                            status: crate::cas::BlobStatus::Pending,
+                            received_mask: None,
+                            ..info.clone()
+                        },
+                    };
+                    let mut sync = SwarmSync::new(local_info.clone()).with_config(self.blob_config);
                     sync.add_seeder(sender_pk);
                     self.blob_syncs.insert(blob_hash, sync);
                     effects.push(Effect::WriteBlobInfo(local_info));
@@ -711,6 +800,11 @@ impl MerkleToxEngine {
                             data.data.clone(),
                             Some(data.proof.clone()),
                         ));
+                        effects.push(Effect::EmitEvent(NodeEvent::BlobProgress {
+                            hash: blob_hash,
+                            received: sync.tracker.received_bytes(),
+                            total: sync.info.size,
+                        }));
 
                         if sync.tracker.is_complete() {
                             let mut info = sync.info.clone();
@@ -771,7 +865,11 @@ impl MerkleToxEngine {
                             ));
                         } else {
                             // Issue a fresh KeyWrap via rotate_conversation_key
-                            match self.rotate_conversation_key(conversation_id, store) {
+                            match self.rotate_conversation_key_for(
+                                conversation_id,
+                                RotationTrigger::PeerRequest,
+                                store,
+                            ) {
                                 Ok(rotation_effects) => {
                                     effects.extend(rotation_effects);
                                     effects.push(Effect::SendPacket(
@@ -840,6 +938,27 @@ impl MerkleToxEngine {
                     session.common.heads_dirty = true;
                 }
             }
+            ProtocolMessage::SyncHeadsDelta(delta) => {
+                self.handle_sync_heads_delta(sender_pk, delta, store, &mut effects);
+            }
+            ProtocolMessage::MerkleNodeWithHeads {
+                conversation_id,
+                hash,
+                node,
+                heads,
+            } => {
+                self.handle_sync_heads_delta(sender_pk, heads, store, &mut effects);
+                effects.extend(self.dispatch_message(
+                    sender_pk,
+                    ProtocolMessage::MerkleNode {
+                        conversation_id,
+                        hash,
+                        node,
+                    },
+                    store,
+                    blob_store,
+                )?);
+            }
             ProtocolMessage::HandshakeError {
                 conversation_id,
                 reason,
@@ -864,67 +983,249 @@ impl MerkleToxEngine {
             }
         }
 
+        if let Some(cid) = announced_heads {
+            self.record_acked_heads(sender_pk, cid, store, &mut effects);
+        }
+
         Ok(effects)
     }
 }
 
-/// Returns true on success, false on decode failure.
+impl MerkleToxEngine {
+    /// Stores a received wire node and verifies it, or keeps it opaque if it
+    /// cannot be unpacked yet.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn handle_wire_node(
+        &mut self,
+        sender_pk: PhysicalDevicePk,
+        conv_id: ConversationId,
+        hash: NodeHash,
+        wire_node: WireNode,
+        store: &dyn NodeStore,
+        blob_store: Option<&dyn BlobStore>,
+        effects: &mut Vec<Effect>,
+    ) -> MerkleToxResult<()> {
+        // Always store the wire node so we can re-distribute it and try to unpack later
+        effects.push(Effect::WriteWireNode(conv_id, hash, wire_node.clone()));
+        if let Some(PeerSession::Active(_session)) = self.sessions.get_mut(&(sender_pk, conv_id)) {
+            let overlay = EngineStore {
+                store,
+                cache: &self.pending_cache,
+            };
+            overlay.put_wire_node(&conv_id, &hash, wire_node.clone())?;
+        }
+
+        let unpacked = self.unpack_received_wire_node(sender_pk, conv_id, &wire_node);
+
+        if let Some(node) = unpacked {
+            // Use handle_node_internal_ext directly (not handle_node)
+            // to avoid clearing the pending cache. The wire node was
+            // stored in the cache above and must remain accessible
+            // for encrypt-then-sign verification.
+            let node_effects =
+                self.handle_node_internal_ext(conv_id, node, store, blob_store, true)?;
+            effects.extend(node_effects);
+            // Remove from opaque tracking if it was previously stored
+            if let Some((total, entries)) = self.opaque_store_usage.get_mut(&conv_id)
+                && let Some(pos) = entries.iter().position(|(h, _, _, _)| *h == hash)
+            {
+                *total -= entries[pos].1;
+                entries.swap_remove(pos);
+            }
+        } else {
+            debug!(
+                "Failed to unpack wire node: {}",
+                hex::encode(hash.as_bytes())
+            );
+            // Track opaque store usage for quota enforcement
+            let wire_size = wire_node.payload_data.len()
+                + wire_node.encrypted_routing.len()
+                + wire_node.parents.len() * 32;
+            let now_ms = self.clock.network_time_ms();
+            let quota = self.config.quarantine;
+            let (total, entries) = self
+                .opaque_store_usage
+                .entry(conv_id)
+                .or_insert_with(|| (0, Vec::new()));
+            // Per-sender opaque quota
+            let (sender_count, sender_bytes) = entries
+                .iter()
+                .filter(|(_, _, _, spk)| *spk == sender_pk)
+                .fold((0, 0), |(count, bytes), (_, size, _, _)| {
+                    (count + 1, bytes + size)
+                });
+            let tracked = entries.iter().any(|(h, _, _, _)| *h == hash);
+            let over_quota = !tracked
+                && (sender_count >= quota.max_opaque_nodes_per_sender
+                    || sender_bytes + wire_size > quota.max_opaque_bytes_per_sender);
+            if over_quota {
+                debug!(
+                    "Per-sender opaque quota exceeded for {:?} in {:?}",
+                    sender_pk, conv_id
+                );
+            } else if !tracked {
+                *total += wire_size;
+                entries.push((hash, wire_size, now_ms, sender_pk));
+            }
+            // Evict cold-first, then by lowest rank within tier
+            // Filter out promotion-locked entries before eviction
+            while *total > tox_proto::constants::OPAQUE_STORE_QUOTA
+                && entries
+                    .iter()
+                    .any(|(h, _, _, _)| !self.promotion_locked.contains(h))
+            {
+                let max_rank = store
+                    .get_heads(&conv_id)
+                    .iter()
+                    .filter_map(|h| store.get_rank(h))
+                    .max()
+                    .unwrap_or(0);
+                let hot_cutoff = max_rank.saturating_sub(tox_proto::constants::HOT_WINDOW_RANKS);
+                entries.sort_by(|a, b| {
+                    let a_locked = self.promotion_locked.contains(&a.0);
+                    let b_locked = self.promotion_locked.contains(&b.0);
+                    // Locked entries sort LAST (never evicted)
+                    match (a_locked, b_locked) {
+                        (true, false) => return std::cmp::Ordering::Greater,
+                        (false, true) => return std::cmp::Ordering::Less,
+                        _ => {}
+                    }
+                    let a_rank = store.get_rank(&a.0).unwrap_or(0);
+                    let b_rank = store.get_rank(&b.0).unwrap_or(0);
+                    let a_cold = a_rank < hot_cutoff;
+                    let b_cold = b_rank < hot_cutoff;
+                    // Cold before hot; within same tier, lowest rank first
+                    match (a_cold, b_cold) {
+                        (true, false) => std::cmp::Ordering::Less,
+                        (false, true) => std::cmp::Ordering::Greater,
+                        _ => a_rank.cmp(&b_rank),
+                    }
+                });
+                let (evicted_hash, evicted_size, _, _) = entries.remove(0);
+                *total -= evicted_size;
+                effects.push(Effect::DeleteWireNode(conv_id, evicted_hash));
+            }
+            if over_quota {
+                // Untracked wire nodes would never be evicted.
+                effects.retain(
+                    |e| !matches!(e, Effect::WriteWireNode(cid, h, _) if *cid == conv_id && *h == hash),
+                );
+                self.pending_cache.lock().wire_nodes.remove(&hash);
+                self.report_spam(sender_pk, SpamOffense::QuotaExceeded);
+                return Ok(());
+            }
+            if let Some(PeerSession::Active(session)) = self.sessions.get_mut(&(sender_pk, conv_id))
+            {
+                session.on_wire_node_received(hash, &wire_node, store);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpacks a wire node received from `sender_pk`, trying every
+    /// authorized sender of the conversation.
+    pub(crate) fn unpack_received_wire_node(
+        &self,
+        sender_pk: PhysicalDevicePk,
+        conv_id: ConversationId,
+        wire_node: &WireNode,
+    ) -> Option<MerkleNode> {
+        let mut unpacked = None;
+        // Try exception (cleartext) unpack first: covers Admin, KeyWrap, etc.
+        if !wire_node.flags.contains(crate::dag::WireFlags::ENCRYPTED)
+            && let Ok(mut node) = crate::dag::MerkleNode::unpack_wire_exception(wire_node)
+        {
+            // unpack_wire_exception sets author_pk = sender_pk.to_logical(),
+            // which is only correct for admin nodes. For SKD/KeyWrap/HistoryExport
+            // nodes, sender_pk is a device key and author_pk should be the
+            // corresponding master (logical) key. Resolve via identity_manager.
+            let all_senders = self
+                .identity_manager
+                .list_all_authorized_sender_pairs(conv_id);
+            if let Some((_, logical_pk)) = all_senders.iter().find(|(d, _)| *d == node.sender_pk) {
+                node.author_pk = *logical_pk;
+            }
+            unpacked = Some(node);
+        }
+
+        // For encrypted content nodes, use sender identification
+        if unpacked.is_none()
+            && let Some(crate::engine::Conversation::Established(em)) =
+                self.conversations.get(&conv_id)
+        {
+            let mut all_senders = self
+                .identity_manager
+                .list_all_authorized_sender_pairs(conv_id);
+            // Also try the network-level sender as a candidate
+            if !all_senders.iter().any(|(d, _)| *d == sender_pk) {
+                all_senders.push((sender_pk, sender_pk.to_logical()));
+            }
+            unpacked = em.identify_sender_and_unpack(wire_node, &all_senders);
+
+            // Fallback: try HistoryExport room-wide export keys
+            if unpacked.is_none() {
+                unpacked = em.try_unpack_history_export(wire_node, &all_senders);
+            }
+        }
+        unpacked
+    }
+}
+
+/// Asks a peer to pause reconciliation for `retry_after`.
+fn rate_limited(
+    conversation_id: ConversationId,
+    retry_after: std::time::Duration,
+) -> ProtocolMessage {
+    ProtocolMessage::SyncRateLimited {
+        conversation_id,
+        retry_after_ms: retry_after.as_millis().clamp(1, u32::MAX as u128) as u32,
+    }
+}
+
+/// Returns the number of differences decoded, or `None` on decode failure.
+/// Nodes the sender is missing are only sent if they are visible from its
+/// history `floor`.
+#[allow(clippy::too_many_arguments)]
 fn process_sketch(
     session: &mut SyncSession<Active>,
     sender_pk: PhysicalDevicePk,
     sketch: tox_reconcile::SyncSketch,
     store: &dyn NodeStore,
+    floor: Option<u64>,
     _keys: Option<&crate::crypto::ConversationKeys>,
     k_iblt: Option<[u8; 32]>,
     effects: &mut Vec<Effect>,
-) -> MerkleToxResult<bool> {
-    let decode_ok;
+) -> MerkleToxResult<Option<usize>> {
+    let decoded;
     match session.handle_sync_sketch_keyed(sketch.clone(), store, k_iblt)? {
         DecodingResult::Success {
-            missing_locally: _,
+            missing_locally,
             missing_remotely,
         } => {
-            decode_ok = true;
+            decoded = Some(missing_locally.len() + missing_remotely.len());
             for hash in missing_remotely {
-                // Prefer cached wire nodes; fall back to exception packing
-                if let Some(wire_node) = store.get_wire_node(&hash) {
-                    effects.push(Effect::SendPacket(
-                        sender_pk,
-                        ProtocolMessage::MerkleNode {
-                            conversation_id: sketch.conversation_id,
-                            hash,
-                            node: wire_node,
-                        },
-                    ));
-                } else if let Some(node) = store.get_node(&hash) {
-                    // Re-pack as exception (cleartext): content nodes should have
-                    // been stored as wire nodes when first authored/received.
-                    let pack_keys = crate::crypto::PackKeys::Exception;
-                    if let Ok(wire_node) = node.pack_wire(&pack_keys, true) {
-                        debug!(
-                            "Sending node {} as result of sketch",
-                            hex::encode(hash.as_bytes())
-                        );
-                        effects.push(Effect::SendPacket(
-                            sender_pk,
-                            ProtocolMessage::MerkleNode {
-                                conversation_id: sketch.conversation_id,
-                                hash,
-                                node: wire_node,
-                            },
-                        ));
-                    } else {
-                        debug!(
-                            "Failed to pack node {} for sending",
-                            hex::encode(hash.as_bytes())
-                        );
-                    }
-                } else {
+                let Some(wire_node) = super::outbox::wire_node_for(store, &hash) else {
                     debug!(
                         "Node {} not found in store for sending",
                         hex::encode(hash.as_bytes())
                     );
+                    continue;
+                };
+                if !super::history::is_visible(&wire_node, floor) {
+                    debug!(
+                        "Withholding {:?} from {:?}: before its join",
+                        hash, sender_pk
+                    );
+                    continue;
                 }
+                effects.push(Effect::SendPacket(
+                    sender_pk,
+                    ProtocolMessage::MerkleNode {
+                        conversation_id: sketch.conversation_id,
+                        hash,
+                        node: wire_node,
+                    },
+                ));
             }
         }
         DecodingResult::Failed => {
@@ -935,7 +1236,7 @@ fn process_sketch(
                     range: sketch.range,
                 },
             ));
-            decode_ok = false;
+            decoded = None;
         }
     }
 
@@ -945,5 +1246,5 @@ fn process_sketch(
             ProtocolMessage::FetchBatchReq(req),
         ));
     }
-    Ok(decode_ok)
+    Ok(decoded)
 }
@@ -0,0 +1,119 @@
+//! Who may fetch which part of a conversation's history.
+//!
+//! A `SetHistoryVisibility` node restricts what this device serves in
+//! response to FetchBatchReq. Under `HistoryVisibility::SinceJoin`, content
+//! nodes ranked below the requester's Invite are withheld unless its
+//! identity was granted history access. Admin nodes are always served, so
+//! new members can still verify the admin chain.
+
+use crate::dag::{
+    ConversationId, HistoryVisibility, LogicalIdentityPk, NodeAuth, NodeHash, PhysicalDevicePk,
+    WireNode,
+};
+use crate::engine::MerkleToxEngine;
+use std::collections::HashSet;
+use tracing::debug;
+
+/// History visibility of a conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryAccessPolicy {
+    pub visibility: HistoryVisibility,
+    /// Identities that fetch the whole history regardless.
+    pub history_access: HashSet<LogicalIdentityPk>,
+    /// Rank and hash of the SetHistoryVisibility node. Concurrent updates
+    /// resolve to the highest pair.
+    pub rank: u64,
+    pub hash: NodeHash,
+}
+
+impl MerkleToxEngine {
+    /// Records a verified SetHistoryVisibility node. Returns false if a
+    /// concurrent update with a higher rank or hash is already in effect.
+    pub(crate) fn apply_history_visibility(
+        &mut self,
+        conversation_id: ConversationId,
+        visibility: HistoryVisibility,
+        history_access: &[LogicalIdentityPk],
+        rank: u64,
+        hash: NodeHash,
+    ) -> bool {
+        if let Some(current) = self.history_policies.get(&conversation_id)
+            && (current.rank, current.hash) >= (rank, hash)
+        {
+            return false;
+        }
+        debug!(
+            "History visibility for {:?} set to {:?}",
+            conversation_id, visibility
+        );
+        self.history_policies.insert(
+            conversation_id,
+            HistoryAccessPolicy {
+                visibility,
+                history_access: history_access.iter().copied().collect(),
+                rank,
+                hash,
+            },
+        );
+        true
+    }
+
+    /// Records the rank of an Invite. A member invited more than once keeps
+    /// its earliest rank.
+    pub(crate) fn record_join_rank(
+        &mut self,
+        conversation_id: ConversationId,
+        logical_pk: LogicalIdentityPk,
+        rank: u64,
+    ) {
+        self.join_ranks
+            .entry((conversation_id, logical_pk))
+            .and_modify(|r| *r = (*r).min(rank))
+            .or_insert(rank);
+    }
+
+    /// History visibility of a conversation; `Shared` unless set.
+    pub fn history_visibility(&self, conversation_id: &ConversationId) -> HistoryVisibility {
+        self.history_policies
+            .get(conversation_id)
+            .map_or(HistoryVisibility::Shared, |p| p.visibility)
+    }
+
+    /// Lowest rank of content nodes served to `device_pk`, or None if it may
+    /// fetch the whole history. Identities granted history access and
+    /// members that were not invited (such as the founder) are not
+    /// restricted; devices of unknown identity get no content history.
+    /// Public conversations serve their whole history to anyone.
+    pub fn history_floor(
+        &self,
+        conversation_id: ConversationId,
+        device_pk: &PhysicalDevicePk,
+    ) -> Option<u64> {
+        if self.is_public(conversation_id) {
+            return None;
+        }
+        let policy = self.history_policies.get(&conversation_id)?;
+        if policy.visibility == HistoryVisibility::Shared {
+            return None;
+        }
+        let Some(logical_pk) = self
+            .identity_manager
+            .resolve_logical_pk(conversation_id, device_pk)
+        else {
+            return Some(u64::MAX);
+        };
+        if policy.history_access.contains(&logical_pk) {
+            return None;
+        }
+        self.join_ranks.get(&(conversation_id, logical_pk)).copied()
+    }
+}
+
+/// Whether `wire_node` may be served to a requester whose history starts at
+/// `floor`.
+pub(crate) fn is_visible(wire_node: &WireNode, floor: Option<u64>) -> bool {
+    floor.is_none_or(|floor| {
+        wire_node.topological_rank >= floor
+            || matches!(wire_node.authentication, NodeAuth::Signature(_))
+    })
+}
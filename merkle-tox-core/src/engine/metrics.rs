@@ -0,0 +1,133 @@
+use crate::dag::{ConversationId, PhysicalDevicePk};
+use crate::engine::priority::SyncPriority;
+use crate::engine::session::PeerSession;
+use crate::engine::{Conversation, MerkleToxEngine};
+use crate::sync::NodeStore;
+use std::collections::HashMap;
+
+/// Point-in-time view of the engine, for dashboards and monitoring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineMetrics {
+    /// Ordered by conversation id.
+    pub conversations: Vec<ConversationMetrics>,
+    /// Ordered by conversation id, then peer.
+    pub sessions: Vec<SessionMetrics>,
+    pub traffic: HashMap<PhysicalDevicePk, PeerTraffic>,
+    /// Consensus offset of network time from local time.
+    pub clock_offset_ms: i64,
+    /// Nodes authored or received in the current batch, not yet written.
+    pub pending_cache_nodes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversationMetrics {
+    pub conversation_id: ConversationId,
+    /// Whether the conversation key is known.
+    pub established: bool,
+    pub current_epoch: Option<u64>,
+    pub verified_nodes: usize,
+    /// Nodes stored but not yet verified.
+    pub speculative_nodes: usize,
+    pub sync_priority: SyncPriority,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPhase {
+    Handshake,
+    Active,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionMetrics {
+    pub peer_pk: PhysicalDevicePk,
+    pub conversation_id: ConversationId,
+    pub phase: SyncPhase,
+    pub reachable: bool,
+    /// Nodes known to be missing, including those requested.
+    pub missing_nodes: usize,
+    pub in_flight_fetches: usize,
+    /// Shard checksum rounds started with the peer.
+    pub recon_rounds: u64,
+}
+
+/// Protocol message traffic with one peer, as reported by the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeerTraffic {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+}
+
+impl MerkleToxEngine {
+    /// Takes a snapshot of conversation, session and traffic counters.
+    pub fn metrics(&self, store: &dyn NodeStore) -> EngineMetrics {
+        let now_ms = self.clock.time_provider().now_system_ms() + self.clock.consensus_offset();
+
+        let mut conversations: Vec<ConversationMetrics> = self
+            .conversations
+            .iter()
+            .map(|(cid, conv)| {
+                let (verified_nodes, speculative_nodes) = store.get_node_counts(cid);
+                let (established, current_epoch) = match conv {
+                    Conversation::Established(em) => (true, Some(em.current_epoch())),
+                    Conversation::Pending(_) => (false, None),
+                };
+                ConversationMetrics {
+                    conversation_id: *cid,
+                    established,
+                    current_epoch,
+                    verified_nodes,
+                    speculative_nodes,
+                    sync_priority: self.sync_scheduler.priority(cid, now_ms),
+                }
+            })
+            .collect();
+        conversations.sort_by_key(|c| c.conversation_id);
+
+        let mut sessions: Vec<SessionMetrics> = self
+            .sessions
+            .iter()
+            .map(|((peer_pk, cid), session)| {
+                let common = session.common();
+                SessionMetrics {
+                    peer_pk: *peer_pk,
+                    conversation_id: *cid,
+                    phase: match session {
+                        PeerSession::Handshake(_) => SyncPhase::Handshake,
+                        PeerSession::Active(_) => SyncPhase::Active,
+                    },
+                    reachable: common.reachable,
+                    missing_nodes: common.missing_admin_nodes.len()
+                        + common.missing_nodes_hot.len()
+                        + common.missing_nodes_cold.len(),
+                    in_flight_fetches: common.in_flight_fetches.len(),
+                    recon_rounds: common.recon_rounds,
+                }
+            })
+            .collect();
+        sessions.sort_by_key(|s| (s.conversation_id, s.peer_pk));
+
+        EngineMetrics {
+            conversations,
+            sessions,
+            traffic: self.traffic.clone(),
+            clock_offset_ms: self.clock.consensus_offset(),
+            pending_cache_nodes: self.pending_cache_len(),
+        }
+    }
+
+    /// Records an outgoing protocol message of `bytes` serialized bytes.
+    pub fn record_sent(&mut self, peer_pk: PhysicalDevicePk, bytes: usize) {
+        let traffic = self.traffic.entry(peer_pk).or_default();
+        traffic.messages_sent += 1;
+        traffic.bytes_sent += bytes as u64;
+    }
+
+    /// Records an incoming protocol message of `bytes` serialized bytes.
+    pub fn record_received(&mut self, peer_pk: PhysicalDevicePk, bytes: usize) {
+        let traffic = self.traffic.entry(peer_pk).or_default();
+        traffic.messages_received += 1;
+        traffic.bytes_received += bytes as u64;
+    }
+}
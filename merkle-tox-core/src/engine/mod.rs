@@ -1,6 +1,7 @@
 use self::session::{Handshake, PeerSession, SyncSession};
 use crate::ProtocolMessage;
-use crate::cas::SwarmSync;
+use crate::capabilities::{self, Negotiated};
+use crate::cas::{BLOB_QUERY_INTERVAL, SwarmConfig, SwarmSync};
 use crate::clock::{NetworkClock, TimeProvider};
 use crate::crypto::ed25519_sk_to_x25519;
 use crate::dag::NodeLookup;
@@ -10,21 +11,36 @@ use crate::dag::{
     PhysicalDeviceDhSk, PhysicalDevicePk, PhysicalDeviceSk,
 };
 use crate::error::MerkleToxResult;
+use crate::event_log::{ProtocolEventLog, short_hex};
 use crate::identity::IdentityManager;
-use crate::sync::{NodeStore, SyncRange, Tier};
+use crate::sync::{BlobStore, NodeStore, SyncPolicy, SyncRange, Tier};
+pub mod archive;
 pub mod authoring;
+pub mod config;
 pub mod conversation;
 pub mod handlers;
+pub mod history;
+pub mod metrics;
+pub mod outbox;
+pub mod priority;
 pub mod processor;
+pub mod quarantine;
+pub mod quorum;
+pub mod recon;
+pub mod recovery;
+pub mod retention;
+pub mod scheduled;
 pub mod session;
+pub mod vault;
 pub use self::conversation::{Conversation, ConversationData};
 pub use self::processor::{VerificationStatus, VerifiedNode};
 use parking_lot::Mutex;
 use rand::rngs::StdRng;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tracing::{debug, info};
+use std::time::Duration;
+use tox_proto::time::Instant;
+use tracing::{debug, info, warn};
 
 pub struct MerkleToxEngine {
     pub self_pk: PhysicalDevicePk,
@@ -33,10 +49,69 @@ pub struct MerkleToxEngine {
     pub self_dh_sk: Option<PhysicalDeviceDhSk>,
     pub identity_manager: IdentityManager,
     pub clock: NetworkClock,
+    pub config: config::EngineConfig,
+    /// Whether a `NodeEvent::ClockSkewWarning` is outstanding.
+    pub(crate) clock_skew_warned: bool,
     /// Maps (Peer PK, Conversation ID) to SyncSession.
     pub sessions: HashMap<(PhysicalDevicePk, ConversationId), PeerSession>,
     pub conversations: HashMap<ConversationId, Conversation>,
+    /// Conversations unloaded to an archive and not used since (see `archive`).
+    pub unloaded_conversations: HashSet<ConversationId>,
+    /// Received wire nodes waiting for batched signature verification.
+    pub verify_pipeline: processor::batch::VerifyPipeline,
     pub blob_syncs: HashMap<NodeHash, SwarmSync>,
+    /// Download limits applied to newly started blob syncs.
+    pub blob_config: SwarmConfig,
+    /// Messages waiting for their delivery time, by id.
+    pub scheduled_messages: HashMap<u64, scheduled::ScheduledMessage>,
+    /// Retention window per conversation, from the winning SetRetention node.
+    pub retention_policies: HashMap<ConversationId, retention::RetentionPolicy>,
+    /// What the expiry sweep does with expired messages.
+    pub retention_mode: retention::RetentionMode,
+    pub(crate) last_retention_sweep: Option<Instant>,
+    /// Timestamp to continue an expiry sweep from when the last batch was full.
+    pub(crate) retention_cursors: HashMap<ConversationId, i64>,
+    /// Nodes the expiry sweep already handled, so stores that keep returning
+    /// them (pruning unsupported or failed) are not pruned and redacted again.
+    pub(crate) retention_expired: HashMap<ConversationId, HashSet<NodeHash>>,
+    /// History visibility per conversation, from the winning
+    /// SetHistoryVisibility node.
+    pub history_policies: HashMap<ConversationId, history::HistoryAccessPolicy>,
+    /// Rank of each member's Invite.
+    pub join_ranks: HashMap<(ConversationId, LogicalIdentityPk), u64>,
+    /// Channels of all conversations, by the channel's conversation id.
+    pub channels: HashMap<ConversationId, crate::channel::ChannelInfo>,
+    /// CloseConversation nodes of each closed conversation.
+    pub closed_conversations: HashMap<ConversationId, HashSet<NodeHash>>,
+    /// History sync policy per conversation; absent means `SyncPolicy::Full`.
+    pub sync_policies: HashMap<ConversationId, SyncPolicy>,
+    /// Key rotation policy per conversation; absent means the default.
+    pub rotation_policies: HashMap<ConversationId, config::RotationPolicy>,
+    /// Orders fetching across conversations.
+    pub sync_scheduler: priority::SyncScheduler,
+    /// Differences last observed when reconciling with each peer, used to
+    /// size the first sketch of later rounds (see `config::SyncConfig`).
+    pub recon_estimates: HashMap<(PhysicalDevicePk, ConversationId), usize>,
+    /// Load and concurrency of reconciliations peers run against us.
+    pub(crate) recon_guard: recon::ReconGuard,
+    /// Heads each device last acknowledged, per conversation (see `outbox`).
+    pub acked_heads: HashMap<(ConversationId, PhysicalDevicePk), Vec<NodeHash>>,
+    /// Peers whose send queue is backed up; sync tasks for them are paused.
+    pub backpressured_peers: HashSet<PhysicalDevicePk>,
+    /// Result of the last capability handshake with each peer. Sessions
+    /// activated without a handshake of their own start from it.
+    pub peer_capabilities: HashMap<PhysicalDevicePk, Negotiated>,
+    /// Recent protocol events, for debugging sync.
+    pub event_log: ProtocolEventLog,
+    /// Protocol traffic per peer, reported by the node.
+    pub(crate) traffic: HashMap<PhysicalDevicePk, metrics::PeerTraffic>,
+    /// Devices that failed a light-mode spot check and are verified in full.
+    pub spot_check_failures: HashSet<(ConversationId, PhysicalDevicePk)>,
+    /// Content nodes light mode stored speculatively without checking their
+    /// signature. Re-verification sweeps skip them.
+    pub unchecked_nodes: HashMap<ConversationId, HashSet<NodeHash>>,
+    /// Invites issued by this device that are still accepted, by id.
+    pub invite_grants: HashMap<crate::invite::InviteId, crate::invite::InviteGrant>,
     /// Maps generated ephemeral Public Key to Private Key.
     pub ephemeral_keys: HashMap<EphemeralX25519Pk, EphemeralX25519Sk>,
     /// Maps peer_pk to last seen announcement.
@@ -98,6 +173,8 @@ pub struct MerkleToxEngine {
     pub keywrap_ack_counts: HashMap<ConversationId, (u32, u32)>, // (acks_received, total_recipients)
     /// Blacklist state per peer for 3-tier exponential escalation.
     pub peer_blacklist: HashMap<PhysicalDevicePk, BlacklistState>,
+    /// Spam scores of senders (see `quarantine`).
+    pub quarantine: quarantine::Quarantine,
     /// Last time a gossip sketch was broadcast per conversation.
     pub last_gossip_time: HashMap<ConversationId, Instant>,
     /// Our DelegationCertificate per conversation, captured from our
@@ -277,6 +354,27 @@ pub enum Effect {
     },
     /// Signal application layer to create a history snapshot for CAS upload.
     HistorySnapshotNeeded(ConversationId),
+    WriteScheduledMessage(scheduled::ScheduledMessage),
+    DeleteScheduledMessage(u64),
+    /// Deletes the payload of a node past the conversation's retention window.
+    PruneNode(ConversationId, NodeHash),
+    /// Records the rank before which history was not synced (`None` clears).
+    WriteHistoryHorizon(ConversationId, Option<u64>),
+    /// Records the heads a device acknowledged.
+    WriteAckedHeads(outbox::AckedHeads),
+    /// Stores the state of an unloaded conversation.
+    WriteArchivedConversation(archive::ArchivedConversation),
+    /// Records a blacklisted device.
+    WriteBlacklistEntry(quarantine::BlacklistEntry),
+    /// Removes a device from the blacklist.
+    DeleteBlacklistEntry(PhysicalDevicePk),
+    /// Records an invite issued by this device.
+    WriteInviteGrant(crate::invite::InviteGrant),
+    /// Removes a revoked or expired invite.
+    DeleteInviteGrant(crate::invite::InviteId),
+    /// Signature checks to run, off the engine if possible. The result goes
+    /// to `handle_verify_result`.
+    VerifyBatch(processor::batch::VerifyBatch),
 }
 
 impl MerkleToxEngine {
@@ -293,9 +391,37 @@ impl MerkleToxEngine {
             self_dh_sk: None,
             identity_manager: IdentityManager::new(),
             clock: NetworkClock::new(time_provider),
+            config: config::EngineConfig::default(),
+            clock_skew_warned: false,
             sessions: HashMap::new(),
             conversations: HashMap::new(),
+            unloaded_conversations: HashSet::new(),
+            verify_pipeline: processor::batch::VerifyPipeline::default(),
             blob_syncs: HashMap::new(),
+            blob_config: SwarmConfig::default(),
+            scheduled_messages: HashMap::new(),
+            retention_policies: HashMap::new(),
+            retention_mode: retention::RetentionMode::default(),
+            last_retention_sweep: None,
+            retention_cursors: HashMap::new(),
+            retention_expired: HashMap::new(),
+            history_policies: HashMap::new(),
+            join_ranks: HashMap::new(),
+            channels: HashMap::new(),
+            closed_conversations: HashMap::new(),
+            sync_policies: HashMap::new(),
+            rotation_policies: HashMap::new(),
+            sync_scheduler: priority::SyncScheduler::default(),
+            recon_estimates: HashMap::new(),
+            recon_guard: recon::ReconGuard::default(),
+            acked_heads: HashMap::new(),
+            backpressured_peers: HashSet::new(),
+            peer_capabilities: HashMap::new(),
+            traffic: HashMap::new(),
+            event_log: ProtocolEventLog::default(),
+            spot_check_failures: HashSet::new(),
+            unchecked_nodes: HashMap::new(),
+            invite_grants: HashMap::new(),
             ephemeral_keys: HashMap::new(),
             peer_announcements: HashMap::new(),
             highest_handled_pulse: HashMap::new(),
@@ -320,6 +446,7 @@ impl MerkleToxEngine {
             handshake_retry_state: HashMap::new(),
             keywrap_ack_counts: HashMap::new(),
             peer_blacklist: HashMap::new(),
+            quarantine: quarantine::Quarantine::default(),
             last_gossip_time: HashMap::new(),
             promotion_locked: HashSet::new(),
             sketch_cpu_budgets: HashMap::new(),
@@ -365,121 +492,9 @@ impl MerkleToxEngine {
         // 1. Reconstruct Identity state from verified Admin nodes
         let admin_nodes = store.get_verified_nodes_by_type(&conversation_id, NodeType::Admin)?;
         for node in &admin_nodes {
-            let mut admin_ancestor_hashes = std::collections::HashSet::new();
-            let mut stack = node.parents.clone();
-            let mut visited = std::collections::HashSet::new();
-
-            while let Some(parent_hash) = stack.pop() {
-                if visited.insert(parent_hash)
-                    && let Some(parent_node) = store.get_node(&parent_hash)
-                {
-                    if parent_node.node_type() == crate::dag::NodeType::Admin {
-                        admin_ancestor_hashes.insert(parent_hash);
-                    }
-                    if let Some(cached) = self.admin_ancestors_cache.lock().get(&parent_hash) {
-                        admin_ancestor_hashes.extend(cached.iter().cloned());
-                    } else {
-                        stack.extend(parent_node.parents.clone());
-                    }
-                }
-            }
-            self.admin_ancestors_cache.lock().put(
-                node.hash(),
-                std::sync::Arc::new(admin_ancestor_hashes.clone()),
-            );
-
-            let ctx = crate::identity::CausalContext {
-                evaluating_node_hash: node.hash(),
-                admin_ancestor_hashes,
-            };
-
-            if let Content::Control(action) = &node.content {
-                match action {
-                    ControlAction::Genesis {
-                        creator_pk,
-                        created_at,
-                        ..
-                    } => {
-                        self.identity_manager.add_member(
-                            conversation_id,
-                            *creator_pk,
-                            0,
-                            *created_at,
-                        );
-                    }
-                    ControlAction::AuthorizeDevice { cert } => {
-                        let _ = self.identity_manager.authorize_device(
-                            &ctx,
-                            conversation_id,
-                            node.author_pk,
-                            cert,
-                            node.network_timestamp,
-                            node.topological_rank,
-                            node.hash(),
-                        );
-                    }
-                    ControlAction::RevokeDevice {
-                        target_device_pk, ..
-                    } => {
-                        self.identity_manager.revoke_device(
-                            conversation_id,
-                            node.sender_pk,
-                            node.author_pk,
-                            *target_device_pk,
-                            node.topological_rank,
-                            node.network_timestamp,
-                            node.hash(),
-                        );
-                    }
-                    ControlAction::Invite(invite) => {
-                        self.identity_manager.add_member(
-                            conversation_id,
-                            invite.invitee_pk,
-                            invite.role,
-                            node.network_timestamp,
-                        );
-                    }
-                    ControlAction::Leave(logical_pk) => {
-                        self.identity_manager.remove_member(
-                            conversation_id,
-                            node.sender_pk,
-                            node.author_pk,
-                            *logical_pk,
-                            node.topological_rank,
-                            node.network_timestamp,
-                            node.hash(),
-                        );
-                    }
-                    ControlAction::Announcement {
-                        pre_keys,
-                        last_resort_key,
-                    } => {
-                        use ed25519_dalek::{Verifier, VerifyingKey};
-                        let valid_pre_keys =
-                            if let Ok(vk) = VerifyingKey::from_bytes(node.sender_pk.as_bytes()) {
-                                pre_keys
-                                    .iter()
-                                    .filter(|spk| {
-                                        let sig = ed25519_dalek::Signature::from_bytes(
-                                            spk.signature.as_ref(),
-                                        );
-                                        vk.verify(spk.public_key.as_bytes(), &sig).is_ok()
-                                    })
-                                    .cloned()
-                                    .collect()
-                            } else {
-                                Vec::new()
-                            };
-                        self.peer_announcements.insert(
-                            node.sender_pk,
-                            ControlAction::Announcement {
-                                pre_keys: valid_pre_keys,
-                                last_resort_key: last_resort_key.clone(),
-                            },
-                        );
-                    }
-                    _ => {}
-                }
+            let ctx = self.causal_context(node, store);
+            if self.takes_effect(conversation_id, node, &ctx) {
+                self.replay_admin_node(conversation_id, node, store);
             }
         }
 
@@ -501,8 +516,22 @@ impl MerkleToxEngine {
         }
         drop(cache);
 
-        // 3. Load Conversation Keys and Ratchet State
-        let keys = store.get_conversation_keys(&conversation_id)?;
+        // 3. Load Conversation Keys and Ratchet State. Channel keys are
+        // derived from the parent's.
+        let channel_parent = self.identity_manager.channel_parent(conversation_id);
+        let keys = match channel_parent {
+            Some(parent_id) => store
+                .get_conversation_keys(&parent_id)?
+                .into_iter()
+                .map(|(epoch, k_conv)| {
+                    (
+                        epoch,
+                        crate::crypto::derive_channel_k_conv(&k_conv, &conversation_id),
+                    )
+                })
+                .collect(),
+            None => store.get_conversation_keys(&conversation_id)?,
+        };
         if !keys.is_empty() {
             let now = self.clock.network_time_ms();
             let metadata = store.get_epoch_metadata(&conversation_id)?;
@@ -552,9 +581,275 @@ impl MerkleToxEngine {
                 )),
             );
         }
+
+        // 4. Restore in-memory state kept only in the archive
+        self.restore_archived_conversation(conversation_id, store);
+
+        // 5. Channels follow the parent's flags and current keys
+        match channel_parent {
+            Some(parent_id) => self.sync_channel_keys(parent_id),
+            None => self.load_channels(conversation_id, store)?,
+        }
         Ok(())
     }
 
+    /// Replays the Admin track effects of verified `node` into the identity
+    /// state.
+    fn replay_admin_node(
+        &mut self,
+        conversation_id: ConversationId,
+        node: &MerkleNode,
+        store: &dyn NodeStore,
+    ) {
+        let ctx = self.causal_context(node, store);
+        if let Content::Control(action) = &node.content {
+            match action {
+                ControlAction::Genesis {
+                    creator_pk,
+                    created_at,
+                    ..
+                } => {
+                    self.identity_manager
+                        .add_member(conversation_id, *creator_pk, 0, *created_at);
+                }
+                ControlAction::AuthorizeDevice { cert } => {
+                    let _ = self.identity_manager.authorize_device(
+                        &ctx,
+                        conversation_id,
+                        node.author_pk,
+                        cert,
+                        node.network_timestamp,
+                        node.topological_rank,
+                        node.hash(),
+                    );
+                }
+                ControlAction::RevokeDevice {
+                    target_device_pk, ..
+                } => {
+                    self.identity_manager.revoke_device(
+                        conversation_id,
+                        node.sender_pk,
+                        node.author_pk,
+                        *target_device_pk,
+                        node.topological_rank,
+                        node.network_timestamp,
+                        node.hash(),
+                    );
+                }
+                ControlAction::Invite(invite) => {
+                    self.identity_manager.add_member(
+                        conversation_id,
+                        invite.invitee_pk,
+                        invite.role,
+                        node.network_timestamp,
+                    );
+                    self.record_join_rank(
+                        conversation_id,
+                        invite.invitee_pk,
+                        node.topological_rank,
+                    );
+                }
+                ControlAction::Leave(logical_pk) => {
+                    self.identity_manager.remove_member(
+                        conversation_id,
+                        node.sender_pk,
+                        node.author_pk,
+                        *logical_pk,
+                        node.topological_rank,
+                        node.network_timestamp,
+                        node.hash(),
+                    );
+                }
+                ControlAction::Announcement {
+                    pre_keys,
+                    last_resort_key,
+                } => {
+                    use ed25519_dalek::{Verifier, VerifyingKey};
+                    let valid_pre_keys = if let Ok(vk) =
+                        VerifyingKey::from_bytes(node.sender_pk.as_bytes())
+                    {
+                        pre_keys
+                            .iter()
+                            .filter(|spk| {
+                                let sig =
+                                    ed25519_dalek::Signature::from_bytes(spk.signature.as_ref());
+                                vk.verify(spk.public_key.as_bytes(), &sig).is_ok()
+                            })
+                            .cloned()
+                            .collect()
+                    } else {
+                        Vec::new()
+                    };
+                    self.peer_announcements.insert(
+                        node.sender_pk,
+                        ControlAction::Announcement {
+                            pre_keys: valid_pre_keys,
+                            last_resort_key: last_resort_key.clone(),
+                        },
+                    );
+                }
+                ControlAction::SetRetention { max_age_ms } => {
+                    self.apply_retention(
+                        conversation_id,
+                        *max_age_ms,
+                        node.topological_rank,
+                        node.hash(),
+                    );
+                }
+                ControlAction::SetHistoryVisibility {
+                    visibility,
+                    history_access,
+                } => {
+                    self.apply_history_visibility(
+                        conversation_id,
+                        *visibility,
+                        history_access,
+                        node.topological_rank,
+                        node.hash(),
+                    );
+                }
+                ControlAction::CreateChannel { channel_id, name } => {
+                    self.register_channel(conversation_id, node, channel_id, name);
+                }
+                ControlAction::CloseConversation => {
+                    self.apply_close(conversation_id, node.hash());
+                }
+                ControlAction::SetAdminQuorum { threshold } => {
+                    self.identity_manager.set_admin_quorum(
+                        conversation_id,
+                        *threshold,
+                        node.topological_rank,
+                        node.hash(),
+                    );
+                }
+                ControlAction::CoSign(hash) => {
+                    if self.identity_manager.co_sign_admin_action(
+                        conversation_id,
+                        *hash,
+                        node.author_pk,
+                    ) && let Some(target) = store.get_node(hash)
+                    {
+                        self.replay_admin_node(conversation_id, &target, store);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Causal context of `node`: the Admin nodes among its ancestors.
+    pub(crate) fn causal_context(
+        &self,
+        node: &MerkleNode,
+        store: &dyn NodeStore,
+    ) -> crate::identity::CausalContext {
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        let mut admin_ancestor_hashes = HashSet::new();
+        let mut stack = node.parents.clone();
+        let mut visited = HashSet::new();
+
+        while let Some(parent_hash) = stack.pop() {
+            if visited.insert(parent_hash)
+                && let Some(parent_node) = overlay.get_node(&parent_hash)
+            {
+                if parent_node.node_type() == NodeType::Admin {
+                    admin_ancestor_hashes.insert(parent_hash);
+                }
+                if let Some(cached) = self.admin_ancestors_cache.lock().get(&parent_hash) {
+                    admin_ancestor_hashes.extend(cached.iter().cloned());
+                } else {
+                    stack.extend(parent_node.parents.clone());
+                }
+            }
+        }
+        self.admin_ancestors_cache.lock().put(
+            node.hash(),
+            std::sync::Arc::new(admin_ancestor_hashes.clone()),
+        );
+
+        crate::identity::CausalContext {
+            evaluating_node_hash: node.hash(),
+            admin_ancestor_hashes,
+        }
+    }
+
+    /// Feature bits this engine advertises in `CapsAnnounce` and `CapsAck`.
+    pub fn advertised_features(&self) -> u64 {
+        if self.config.read_only {
+            capabilities::SUPPORTED_FEATURES | capabilities::FEATURE_READ_ONLY
+        } else {
+            capabilities::SUPPORTED_FEATURES
+        }
+    }
+
+    /// The `CapsAnnounce` this engine sends to start a handshake.
+    pub fn caps_announce(&self) -> ProtocolMessage {
+        ProtocolMessage::CapsAnnounce {
+            version: capabilities::PROTOCOL_VERSION,
+            features: self.advertised_features(),
+        }
+    }
+
+    /// What the last capability handshake with `peer_pk` agreed on; the
+    /// baseline if there was none.
+    pub fn negotiated(&self, peer_pk: &PhysicalDevicePk) -> Negotiated {
+        self.peer_capabilities
+            .get(peer_pk)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether `peer_pk` announced itself as a read-only observer in any
+    /// active session.
+    pub fn peer_is_read_only(&self, peer_pk: &PhysicalDevicePk) -> bool {
+        self.sessions.iter().any(|((pk, _), session)| {
+            pk == peer_pk && matches!(session, PeerSession::Active(s) if s.peer_is_read_only())
+        })
+    }
+
+    /// Whether the conversation's Genesis flags it as public (see
+    /// [`conversation::FLAG_PUBLIC`]).
+    pub fn is_public(&self, conversation_id: ConversationId) -> bool {
+        self.conversations
+            .get(&conversation_id)
+            .is_some_and(|c| c.is_public())
+    }
+
+    pub fn is_closed(&self, conversation_id: ConversationId) -> bool {
+        self.closed_conversations.contains_key(&conversation_id)
+    }
+
+    /// Whether a node with admin ancestors `admin_ancestors` was written
+    /// after the conversation was closed.
+    pub(crate) fn is_after_close(
+        &self,
+        conversation_id: ConversationId,
+        admin_ancestors: &HashSet<NodeHash>,
+    ) -> bool {
+        self.closed_conversations
+            .get(&conversation_id)
+            .is_some_and(|closes| !closes.is_disjoint(admin_ancestors))
+    }
+
+    /// Records a verified CloseConversation node.
+    pub(crate) fn apply_close(&mut self, conversation_id: ConversationId, hash: NodeHash) {
+        self.closed_conversations
+            .entry(conversation_id)
+            .or_default()
+            .insert(hash);
+    }
+
+    /// Rules the nodes of a conversation are validated against.
+    pub fn validation_rules(&self, conversation_id: ConversationId) -> crate::dag::ValidationRules {
+        crate::dag::ValidationRules {
+            public: self.is_public(conversation_id),
+            channel: self.channels.contains_key(&conversation_id),
+        }
+    }
+
     /// Registers conversation and optionally initiates sync with peer.
     pub fn start_sync(
         &mut self,
@@ -598,6 +893,11 @@ impl MerkleToxEngine {
                     )
                 });
 
+            session.common_mut().rank_window = self
+                .sync_policies
+                .get(&conversation_id)
+                .and_then(SyncPolicy::rank_window);
+
             // Update limits if session already existed
             if min_rank > 0 || min_timestamp > 0 {
                 let common = session.common_mut();
@@ -606,13 +906,7 @@ impl MerkleToxEngine {
                 common.min_timestamp = min_timestamp;
             }
 
-            effects.push(Effect::SendPacket(
-                peer,
-                ProtocolMessage::CapsAnnounce {
-                    version: 1,
-                    features: 0,
-                },
-            ));
+            effects.push(Effect::SendPacket(peer, self.caps_announce()));
         }
         effects
     }
@@ -638,6 +932,65 @@ impl MerkleToxEngine {
         effects
     }
 
+    /// Sets how much history of a conversation is synced. Applies to
+    /// existing and future sync sessions. Returning to `SyncPolicy::Full`
+    /// clears the history marker; older nodes are then backfilled by
+    /// reconciliation.
+    pub fn set_sync_policy(
+        &mut self,
+        conversation_id: ConversationId,
+        policy: SyncPolicy,
+    ) -> Vec<Effect> {
+        let window = policy.rank_window();
+        for ((_, cid), session) in self.sessions.iter_mut() {
+            if *cid == conversation_id {
+                let c = session.common_mut();
+                if window.is_none() && c.rank_window.is_some() {
+                    c.min_rank = 0;
+                    c.recon_dirty = true;
+                }
+                c.rank_window = window;
+            }
+        }
+
+        let mut effects = Vec::new();
+        if policy == SyncPolicy::Full {
+            self.sync_policies.remove(&conversation_id);
+            effects.push(Effect::WriteHistoryHorizon(conversation_id, None));
+        } else {
+            self.sync_policies.insert(conversation_id, policy);
+        }
+        effects
+    }
+
+    pub fn sync_policy(&self, conversation_id: &ConversationId) -> SyncPolicy {
+        self.sync_policies
+            .get(conversation_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets when this device rotates the key of a conversation it
+    /// administers.
+    pub fn set_rotation_policy(
+        &mut self,
+        conversation_id: ConversationId,
+        policy: config::RotationPolicy,
+    ) {
+        if policy == config::RotationPolicy::default() {
+            self.rotation_policies.remove(&conversation_id);
+        } else {
+            self.rotation_policies.insert(conversation_id, policy);
+        }
+    }
+
+    pub fn rotation_policy(&self, conversation_id: &ConversationId) -> config::RotationPolicy {
+        self.rotation_policies
+            .get(conversation_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
     /// Sends reinclusion request to admin for trust-restored conversation.
     pub fn request_reinclusion(
         &self,
@@ -655,11 +1008,121 @@ impl MerkleToxEngine {
         )]
     }
 
-    // Periodic background tasks (e.g., CAS swarm requests, background reconciliation).
+    /// Asks the connected peers of a conversation for a blob that nodes do
+    /// not fetch on arrival, such as sticker images. The download starts
+    /// once a peer announces it has the blob. Returns no effects if the blob
+    /// is stored, already downloading, or was cancelled.
+    pub fn request_blob(
+        &mut self,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        blob_store: &dyn BlobStore,
+    ) -> Vec<Effect> {
+        if self.blob_syncs.contains_key(&hash)
+            || blob_store.has_blob(&hash)
+            || blob_store
+                .get_blob_info(&hash)
+                .is_some_and(|i| i.status == crate::cas::BlobStatus::Cancelled)
+        {
+            return Vec::new();
+        }
+        self.sessions
+            .iter()
+            .filter(|((_, cid), session)| {
+                *cid == conversation_id && matches!(session, PeerSession::Active(_))
+            })
+            .map(|((pk, _), _)| Effect::SendPacket(*pk, ProtocolMessage::BlobQuery(hash)))
+            .collect()
+    }
+
+    /// Aborts the download of a blob. No further chunks are requested, chunks
+    /// still in flight are dropped on arrival without being written, and the
+    /// stored BlobInfo is marked cancelled so peer announcements do not
+    /// restart the transfer. Returns no effects if the blob is not downloading.
+    pub fn cancel_blob(&mut self, hash: &NodeHash) -> Vec<Effect> {
+        let Some(sync) = self.blob_syncs.remove(hash) else {
+            return Vec::new();
+        };
+        let mut info = sync.progress_info();
+        info.status = crate::cas::BlobStatus::Cancelled;
+        vec![Effect::WriteBlobInfo(info)]
+    }
+
+    /// Overrides the download limits of a blob that is currently syncing.
+    /// Returns false if the blob is not downloading.
+    pub fn set_blob_limits(&mut self, hash: &NodeHash, config: SwarmConfig) -> bool {
+        match self.blob_syncs.get_mut(hash) {
+            Some(sync) => {
+                sync.config = config;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Restarts downloads left pending or partially fetched by a previous
+    /// run. Chunks already in the store are not requested again; seeders are
+    /// discovered by querying connected peers on the next poll. Returns the
+    /// number of resumed downloads.
+    pub fn resume_blob_downloads(&mut self, blob_store: &dyn BlobStore) -> usize {
+        let mut resumed = 0;
+        for info in blob_store.get_incomplete_blobs() {
+            if self.blob_syncs.contains_key(&info.hash) {
+                continue;
+            }
+            debug!("Resuming download of blob {:?}", info.hash);
+            self.blob_syncs.insert(
+                info.hash,
+                SwarmSync::new(info).with_config(self.blob_config),
+            );
+            resumed += 1;
+        }
+        resumed
+    }
+
+    /// Periodic background tasks (e.g., CAS swarm requests, background
+    /// reconciliation).
     pub fn poll(&mut self, now: Instant, store: &dyn NodeStore) -> MerkleToxResult<Vec<Effect>> {
+        let span = tracing::debug_span!("poll", device = %short_hex(self.self_pk.as_bytes()));
+        let _enter = span.enter();
+        let effects = self.poll_tasks(now, store)?;
+        let now_ms = self.clock.network_time_ms();
+        self.log_effects(now_ms, &effects);
+        Ok(effects)
+    }
+
+    /// Records a peer's clock offset under `config.clock_policy`.
+    pub fn update_peer_clock_offset(&mut self, peer: PhysicalDevicePk, offset_ms: i64) {
+        self.clock.set_policy(self.config.clock_policy);
+        self.clock.update_peer_offset(peer, offset_ms);
+    }
+
+    /// Warns once when peers put the local clock further off than
+    /// `ClockPolicy::warn_offset`.
+    fn check_clock_skew(&mut self) -> Option<crate::NodeEvent> {
+        let offset_ms = self.clock.peer_median_offset();
+        let limit = self.config.clock_policy.warn_offset.as_millis() as u64;
+        let skewed = offset_ms.unsigned_abs() > limit;
+        let newly_skewed = skewed && !self.clock_skew_warned;
+        self.clock_skew_warned = skewed;
+        if !newly_skewed {
+            return None;
+        }
+        warn!(
+            "Local clock is {}ms off the network consensus; check the system time",
+            offset_ms
+        );
+        Some(crate::NodeEvent::ClockSkewWarning { offset_ms })
+    }
+
+    fn poll_tasks(&mut self, now: Instant, store: &dyn NodeStore) -> MerkleToxResult<Vec<Effect>> {
         self.clear_pending();
 
         let mut effects = Vec::new();
+        if let Some(event) = self.check_clock_skew() {
+            effects.push(Effect::EmitEvent(event));
+        }
+        self.save_blacklist(&mut effects);
         let mut next_wakeup = now + Duration::from_secs(3600);
 
         // 0. Check for automatic rotation
@@ -746,9 +1209,14 @@ impl MerkleToxEngine {
             }
         }
 
-        let conv_ids: Vec<ConversationId> = self.conversations.keys().cloned().collect();
+        // Read-only observers never rotate keys.
+        let conv_ids: Vec<ConversationId> = if self.config.read_only {
+            Vec::new()
+        } else {
+            self.conversations.keys().cloned().collect()
+        };
         for cid in conv_ids {
-            if self.check_rotation_triggers(cid) {
+            if let Some(trigger) = self.rotation_trigger(cid) {
                 // Only rotate if admin. Use global context: it bypasses
                 // per-node causal ancestry check, appropriate for this
                 // proactive self-check (no specific node evaluated).
@@ -764,7 +1232,7 @@ impl MerkleToxEngine {
 
                 if is_admin {
                     info!("Automatic rotation triggered for conversation {:?}", cid);
-                    let conv_effects = self.rotate_conversation_key(cid, store)?;
+                    let conv_effects = self.rotate_conversation_key_for(cid, trigger, store)?;
                     effects.extend(conv_effects);
                     // New nodes advertised via heads_dirty in SyncSessions
                 }
@@ -815,7 +1283,7 @@ impl MerkleToxEngine {
         // Collect expired pending info before retain drops it.
         let mut removed_pending: HashMap<NodeHash, KeyWrapPending> = HashMap::new();
         self.keywrap_pending.retain(|hash, pending| {
-            if pending.created_at.elapsed() < KEYWRAP_ACK_TIMEOUT {
+            if now.saturating_duration_since(pending.created_at) < KEYWRAP_ACK_TIMEOUT {
                 true // keep: still waiting
             } else if pending.attempts < MAX_KEYWRAP_RETRIES {
                 debug!(
@@ -857,8 +1325,32 @@ impl MerkleToxEngine {
         }
 
         // Handle Blob requests
+        let mut active_peers: Vec<PhysicalDevicePk> = self
+            .sessions
+            .iter()
+            .filter(|(_, s)| matches!(s, PeerSession::Active(_)) && s.common().reachable)
+            .map(|((pk, _), _)| *pk)
+            .filter(|pk| !self.backpressured_peers.contains(pk))
+            .collect();
+        active_peers.sort();
+        active_peers.dedup();
         for sync in self.blob_syncs.values_mut() {
             sync.clear_stalled_fetches(now);
+            // Look for seeders of downloads nobody has announced yet.
+            if sync.seeders.is_empty()
+                && !active_peers.is_empty()
+                && sync
+                    .last_query
+                    .is_none_or(|t| now.saturating_duration_since(t) >= BLOB_QUERY_INTERVAL)
+            {
+                for peer in &active_peers {
+                    effects.push(Effect::SendPacket(
+                        *peer,
+                        ProtocolMessage::BlobQuery(sync.info.hash),
+                    ));
+                }
+                sync.last_query = Some(now);
+            }
             let reqs = sync.next_requests(4, now);
             for (peer, req) in reqs {
                 tracing::debug!("Generated BlobReq for {:?} from {:?}", req.hash, peer);
@@ -867,9 +1359,27 @@ impl MerkleToxEngine {
             next_wakeup = next_wakeup.min(sync.next_wakeup(now));
         }
 
-        // Handle SyncSession heads advertisements and background fetching
-        for ((peer_pk, cid), session) in self.sessions.iter_mut() {
-            if !session.common().reachable {
+        // Handle SyncSession heads advertisements and background fetching,
+        // most urgent conversations first.
+        let mut horizons: HashMap<ConversationId, u64> = HashMap::new();
+        let mut fetch_slots = priority::FetchSlots::in_flight(self);
+        let session_keys = self
+            .sync_scheduler
+            .order(self.sessions.keys().copied().collect(), now_ms);
+        for (peer_pk, cid) in &session_keys {
+            let Some(session) = self.sessions.get_mut(&(*peer_pk, *cid)) else {
+                continue;
+            };
+            let common = session.common_mut();
+            common.rank_window = self
+                .sync_policies
+                .get(cid)
+                .and_then(SyncPolicy::rank_window);
+            if let Some(rank) = common.truncated_at.take() {
+                let h = horizons.entry(*cid).or_insert(rank);
+                *h = (*h).min(rank);
+            }
+            if !session.common().reachable || self.backpressured_peers.contains(peer_pk) {
                 continue;
             }
             if let PeerSession::Active(s) = session {
@@ -880,18 +1390,19 @@ impl MerkleToxEngine {
                 s.common
                     .pending_sketches
                     .retain(|nonce, _| s.common.pending_challenges.contains_key(nonce));
+                s.common
+                    .challenge_difficulties
+                    .retain(|nonce, _| s.common.pending_challenges.contains_key(nonce));
 
                 // Clear expired rate_limited_until
                 if s.common.rate_limited_until.is_some_and(|u| now >= u) {
                     s.common.rate_limited_until = None;
                 }
 
-                if s.common.heads_dirty {
-                    effects.push(Effect::SendPacket(
-                        *peer_pk,
-                        ProtocolMessage::SyncHeads(s.make_sync_heads_with_store(0, Some(store))),
-                    ));
-                    s.common.heads_dirty = false;
+                if s.common.heads_dirty
+                    && let Some(announcement) = s.make_heads_announcement(store)
+                {
+                    effects.push(Effect::SendPacket(*peer_pk, announcement));
                 }
 
                 // Guard recon with rate-limited check
@@ -915,6 +1426,7 @@ impl MerkleToxEngine {
                             ));
                             s.common.recon_dirty = false;
                             s.common.last_recon_time = now;
+                            s.common.recon_rounds += 1;
                         }
                         Err(e) => {
                             debug!("Failed to compute shard checksums for {:?}: {}", cid, e);
@@ -934,15 +1446,28 @@ impl MerkleToxEngine {
                     ));
                 }
 
-                // Periodic background fetch of missing nodes
-                if let Some(req) = s.next_fetch_batch(tox_proto::constants::MAX_BATCH_SIZE) {
+                // Periodic background fetch of missing nodes. A conversation
+                // without fetches in flight needs a free slot on the peer.
+                let deferred = s.common.in_flight_fetches.is_empty()
+                    && s.has_fetchable_missing()
+                    && !fetch_slots.take(
+                        *peer_pk,
+                        self.sync_scheduler.priority(cid, now_ms),
+                        self.sync_scheduler.max_fetches_per_peer,
+                    );
+                if !deferred
+                    && let Some(req) = s.next_fetch_batch(tox_proto::constants::MAX_BATCH_SIZE)
+                {
                     effects.push(Effect::SendPacket(
                         *peer_pk,
                         ProtocolMessage::FetchBatchReq(req),
                     ));
                 }
 
-                let session_wakeup = s.next_wakeup(now);
+                let mut session_wakeup = s.next_wakeup(now);
+                if deferred {
+                    session_wakeup = session_wakeup.max(now + priority::FETCH_DEFER_INTERVAL);
+                }
                 if session_wakeup <= now {
                     debug!(
                         "Session {:?} requesting immediate wakeup: heads_dirty={}, recon_dirty={}, missing_hot={}, missing_cold={}",
@@ -961,6 +1486,12 @@ impl MerkleToxEngine {
             }
         }
 
+        for (cid, rank) in horizons {
+            if store.get_history_horizon(&cid).is_none_or(|h| rank < h) {
+                effects.push(Effect::WriteHistoryHorizon(cid, Some(rank)));
+            }
+        }
+
         // Multicast Gossip: broadcast Tiny IBLT sketch every 60s per conversation
         let gossip_convs: Vec<ConversationId> = self.conversations.keys().cloned().collect();
         for cid in gossip_convs {
@@ -981,15 +1512,10 @@ impl MerkleToxEngine {
                     .max()
                     .unwrap_or(0);
                 let range = SyncRange {
-                    min_rank: 0,
+                    min_rank: self.sync_policy(&cid).horizon(max_rank),
                     max_rank,
                 };
-                let k_iblt = match self.conversations.get(&cid) {
-                    Some(Conversation::Established(em)) => em
-                        .get_keys(em.current_epoch())
-                        .map(|k| crate::crypto::derive_k_iblt(&k.k_conv, &cid)),
-                    _ => None,
-                };
+                let k_iblt = self.conversations.get(&cid).and_then(|c| c.iblt_key());
                 let mut iblt =
                     tox_reconcile::IbltSketch::new_keyed(Tier::Tiny.cell_count(), k_iblt);
                 if let Ok(hashes) = overlay.get_node_hashes_in_range(&cid, &range) {
@@ -1004,7 +1530,10 @@ impl MerkleToxEngine {
                 };
                 // Send to all active peers for this conversation
                 for ((peer_pk, peer_cid), session) in &self.sessions {
-                    if *peer_cid == cid && matches!(session, PeerSession::Active(_)) {
+                    if *peer_cid == cid
+                        && matches!(session, PeerSession::Active(_))
+                        && !self.backpressured_peers.contains(peer_pk)
+                    {
                         effects.push(Effect::SendPacket(
                             *peer_pk,
                             ProtocolMessage::SyncSketch(sketch.clone()),
@@ -1018,6 +1547,20 @@ impl MerkleToxEngine {
             next_wakeup = next_wakeup.min(next_gossip);
         }
 
+        // Deliver scheduled messages that are due.
+        let (scheduled_effects, next_due) = self.poll_scheduled_messages(now, now_ms, store);
+        effects.extend(scheduled_effects);
+        if let Some(next_due) = next_due {
+            next_wakeup = next_wakeup.min(next_due);
+        }
+
+        // Expire messages past the retention window.
+        let (retention_effects, next_sweep) = self.poll_retention(now, now_ms, store);
+        effects.extend(retention_effects);
+        if let Some(next_sweep) = next_sweep {
+            next_wakeup = next_wakeup.min(next_sweep);
+        }
+
         effects.push(Effect::ScheduleWakeup(
             Task::SwarmSync(NodeHash::from([0u8; 32])),
             next_wakeup,
@@ -1145,6 +1688,16 @@ impl MerkleToxEngine {
         }
     }
 
+    /// Pauses or resumes sync tasks (heads announcements, reconciliation,
+    /// fetches and gossip) for a peer whose send queue is backed up.
+    pub fn set_peer_backpressure(&mut self, peer_pk: PhysicalDevicePk, paused: bool) {
+        if paused {
+            self.backpressured_peers.insert(peer_pk);
+        } else {
+            self.backpressured_peers.remove(&peer_pk);
+        }
+    }
+
     /// Escalates blacklist tier for a peer (called on IBLT decode failure,
     /// Bao root mismatch, or other protocol violations).
     pub fn blacklist_escalate(&mut self, peer_pk: PhysicalDevicePk) {
@@ -1157,6 +1710,7 @@ impl MerkleToxEngine {
                 expires_at_ms: 0,
             });
         state.escalate(now);
+        self.quarantine.unsaved.insert(peer_pk);
     }
 
     /// Returns true if a peer is currently blacklisted.
@@ -1511,4 +2065,34 @@ impl<'a> crate::sync::NodeStore for EngineStore<'a> {
     ) -> crate::error::MerkleToxResult<()> {
         Ok(())
     }
+    fn get_history_horizon(&self, conversation_id: &ConversationId) -> Option<u64> {
+        self.store.get_history_horizon(conversation_id)
+    }
+    fn get_archived_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<archive::ArchivedConversation> {
+        self.store.get_archived_conversation(conversation_id)
+    }
+    fn get_scheduled_messages(&self) -> Vec<scheduled::ScheduledMessage> {
+        self.store.get_scheduled_messages()
+    }
+    fn get_acked_heads(&self) -> Vec<outbox::AckedHeads> {
+        self.store.get_acked_heads()
+    }
+    fn get_state_snapshot(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<crate::sync::StateSnapshot> {
+        self.store.get_state_snapshot(conversation_id)
+    }
+    fn get_blacklist(&self) -> Vec<quarantine::BlacklistEntry> {
+        self.store.get_blacklist()
+    }
+    fn get_invite_grants(&self) -> Vec<crate::invite::InviteGrant> {
+        self.store.get_invite_grants()
+    }
+    fn get_suspended_state(&self) -> Option<crate::suspend::SuspendedState> {
+        self.store.get_suspended_state()
+    }
 }
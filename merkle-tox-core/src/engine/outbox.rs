@@ -0,0 +1,411 @@
+//! Store-and-forward: the heads each device has acknowledged are persisted,
+//! and nodes a device is missing are pushed to it as soon as it comes back
+//! online, instead of waiting for the next reconciliation round.
+
+use crate::dag::{ConversationId, NodeHash, NodeLookup, NodeType, PhysicalDevicePk, WireNode};
+use crate::engine::history;
+use crate::engine::session::PeerSession;
+use crate::engine::{Effect, EngineStore, MerkleToxEngine};
+use crate::sync::NodeStore;
+use crate::{NodeEvent, ProtocolMessage};
+use std::collections::{HashMap, HashSet, VecDeque};
+use tox_proto::ToxProto;
+use tracing::debug;
+
+/// Most nodes pushed to a device on reconnect. Larger gaps are left to
+/// reconciliation.
+pub const MAX_OUTBOX_PUSH: usize = 256;
+
+/// The heads a device last acknowledged in a conversation: the heads and
+/// admin anchor it announced, as far as we have them.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct AckedHeads {
+    pub conversation_id: ConversationId,
+    pub device_pk: PhysicalDevicePk,
+    pub heads: Vec<NodeHash>,
+}
+
+impl MerkleToxEngine {
+    /// Loads the acknowledged heads recorded by a previous run. Returns how
+    /// many were loaded.
+    pub fn load_outbox(&mut self, store: &dyn NodeStore) -> usize {
+        let mut loaded = 0;
+        for acked in store.get_acked_heads() {
+            let key = (acked.conversation_id, acked.device_pk);
+            if self.acked_heads.insert(key, acked.heads).is_none() {
+                loaded += 1;
+            }
+        }
+        loaded
+    }
+
+    /// Records the heads and admin anchor `peer` announced for
+    /// `conversation_id`, as far as we know them.
+    pub(crate) fn record_acked_heads(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        store: &dyn NodeStore,
+        effects: &mut Vec<Effect>,
+    ) {
+        let Some(session) = self.sessions.get(&(peer, conversation_id)) else {
+            return;
+        };
+        let common = session.common();
+        let mut heads: Vec<NodeHash> = common
+            .remote_heads
+            .iter()
+            .chain(&common.remote_anchor_hash)
+            .filter(|h| store.has_node(h))
+            .copied()
+            .collect();
+        if heads.is_empty() {
+            // The peer is ahead of us; keep what it acknowledged before.
+            return;
+        }
+        heads.sort_unstable();
+        let key = (conversation_id, peer);
+        if self.acked_heads.get(&key) == Some(&heads) {
+            return;
+        }
+        let previous = self.acked_heads.insert(key, heads.clone());
+        effects.push(Effect::WriteAckedHeads(AckedHeads {
+            conversation_id,
+            device_pk: peer,
+            heads: heads.clone(),
+        }));
+
+        for hash in self.newly_delivered(previous.as_deref(), &heads, store) {
+            effects.push(Effect::EmitEvent(NodeEvent::NodeDelivered {
+                conversation_id,
+                hash,
+                device_pk: peer,
+            }));
+        }
+    }
+
+    /// Nodes sent by this device that `heads` acknowledge and `previous`
+    /// did not, oldest first. Nodes ranked below all of `previous` count as
+    /// acknowledged before; on a first acknowledgement, so do nodes ranked
+    /// below all of `heads`.
+    fn newly_delivered(
+        &self,
+        previous: Option<&[NodeHash]>,
+        heads: &[NodeHash],
+        store: &dyn NodeStore,
+    ) -> Vec<NodeHash> {
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        let floor = previous
+            .unwrap_or(heads)
+            .iter()
+            .filter_map(|h| overlay.get_rank(h))
+            .min()
+            .unwrap_or(0);
+        let known = match previous {
+            Some(previous) => acknowledged_closure(&overlay, previous, floor),
+            None => HashSet::new(),
+        };
+        let mut delivered: Vec<_> = acknowledged_closure(&overlay, heads, floor)
+            .into_iter()
+            .filter(|h| !known.contains(h))
+            .filter_map(|h| overlay.get_node(&h))
+            .filter(|n| n.sender_pk == self.self_pk)
+            .map(|n| (n.topological_rank, n.hash()))
+            .collect();
+        delivered.sort_unstable();
+        delivered.into_iter().map(|(_, hash)| hash).collect()
+    }
+
+    /// Records the heads every peer with a sync session last announced,
+    /// including heads that reached the store only since. Called on shutdown
+    /// so the next run pushes from there.
+    pub fn persist_acked_heads(&mut self, store: &dyn NodeStore) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        let sessions: Vec<_> = self.sessions.keys().copied().collect();
+        for (peer, conversation_id) in sessions {
+            self.record_acked_heads(peer, conversation_id, store, &mut effects);
+        }
+        effects
+    }
+
+    /// Nodes of `conversation_id` ranked `min_rank` or higher that each
+    /// device has acknowledged: its acknowledged heads and their ancestors.
+    pub fn acknowledged_nodes(
+        &self,
+        conversation_id: &ConversationId,
+        min_rank: u64,
+        store: &dyn NodeStore,
+    ) -> HashMap<PhysicalDevicePk, HashSet<NodeHash>> {
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        self.acked_heads
+            .iter()
+            .filter(|((cid, _), _)| cid == conversation_id)
+            .map(|((_, device), heads)| (*device, acknowledged_closure(&overlay, heads, min_rank)))
+            .collect()
+    }
+
+    /// Nodes of `conversation_id` that `peer` has not acknowledged, oldest
+    /// first, leaving out those its history visibility withholds. Empty if
+    /// the peer never acknowledged anything, or if it is missing more than
+    /// `MAX_OUTBOX_PUSH` nodes.
+    pub fn outbox_pending(
+        &self,
+        peer: &PhysicalDevicePk,
+        conversation_id: &ConversationId,
+        store: &dyn NodeStore,
+    ) -> Vec<NodeHash> {
+        let Some(acked) = self.acked_heads.get(&(*conversation_id, *peer)) else {
+            return Vec::new();
+        };
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        let floor = acked
+            .iter()
+            .filter_map(|h| overlay.get_rank(h))
+            .min()
+            .unwrap_or(0);
+        // Everything the acknowledged heads descend from, down to the lowest
+        // of them.
+        let known = acknowledged_closure(&overlay, acked, floor);
+        // The peer may claim heads older than its join.
+        let history_floor = self.history_floor(*conversation_id, peer);
+
+        let mut pending = Vec::new();
+        let mut seen = HashSet::new();
+        let mut queue: VecDeque<NodeHash> = overlay
+            .get_heads(conversation_id)
+            .into_iter()
+            .chain(overlay.get_admin_heads(conversation_id))
+            .collect();
+        while let Some(hash) = queue.pop_front() {
+            if known.contains(&hash) || !seen.insert(hash) {
+                continue;
+            }
+            let Some(rank) = overlay.get_rank(&hash) else {
+                continue;
+            };
+            if rank < floor {
+                continue;
+            }
+            queue.extend(parents(&overlay, &hash));
+            if history_floor.is_some()
+                && !wire_node_for(&overlay, &hash)
+                    .is_some_and(|node| history::is_visible(&node, history_floor))
+            {
+                continue;
+            }
+            if pending.len() == MAX_OUTBOX_PUSH {
+                debug!(
+                    "{:?} is missing too many nodes of {:?} to push",
+                    peer, conversation_id
+                );
+                return Vec::new();
+            }
+            pending.push((rank, hash));
+        }
+        pending.sort_unstable();
+        pending.into_iter().map(|(_, hash)| hash).collect()
+    }
+
+    /// Pushes the nodes `peer` is missing in every conversation it has
+    /// acknowledged heads in. Called when the peer comes back online.
+    pub fn flush_outbox(&mut self, peer: PhysicalDevicePk, store: &dyn NodeStore) -> Vec<Effect> {
+        let mut conversations: Vec<ConversationId> = self
+            .acked_heads
+            .keys()
+            .filter(|(_, device)| *device == peer)
+            .map(|(cid, _)| *cid)
+            .collect();
+        conversations.sort_unstable();
+
+        let mut effects = Vec::new();
+        for cid in conversations {
+            let pending = self.outbox_pending(&peer, &cid, store);
+            if pending.is_empty() {
+                continue;
+            }
+            debug!("Pushing {} nodes of {:?} to {:?}", pending.len(), cid, peer);
+            let overlay = EngineStore {
+                store,
+                cache: &self.pending_cache,
+            };
+            for hash in pending {
+                if let Some(node) = wire_node_for(&overlay, &hash) {
+                    effects.push(Effect::SendPacket(
+                        peer,
+                        ProtocolMessage::MerkleNode {
+                            conversation_id: cid,
+                            hash,
+                            node,
+                        },
+                    ));
+                }
+            }
+            if let Some(PeerSession::Active(s)) = self.sessions.get_mut(&(peer, cid)) {
+                s.common.heads_dirty = true;
+            }
+        }
+        effects
+    }
+
+    /// Pushes the nodes `hashes` of `conversation_id` to every peer with a
+    /// sync session in it that has not acknowledged them and may see them.
+    /// Used to retry the propagation of nodes no device has acknowledged
+    /// yet.
+    pub fn resend(
+        &mut self,
+        conversation_id: ConversationId,
+        hashes: &[NodeHash],
+        store: &dyn NodeStore,
+    ) -> Vec<Effect> {
+        let mut peers: Vec<PhysicalDevicePk> = self
+            .sessions
+            .keys()
+            .filter(|(_, cid)| *cid == conversation_id)
+            .map(|(peer, _)| *peer)
+            .collect();
+        peers.sort_unstable();
+
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        let floor = hashes
+            .iter()
+            .filter_map(|h| overlay.get_rank(h))
+            .min()
+            .unwrap_or(0);
+        let mut effects = Vec::new();
+        for peer in peers {
+            let known = self
+                .acked_heads
+                .get(&(conversation_id, peer))
+                .map(|heads| acknowledged_closure(&overlay, heads, floor))
+                .unwrap_or_default();
+            let history_floor = self.history_floor(conversation_id, &peer);
+            for hash in hashes.iter().filter(|h| !known.contains(h)) {
+                if let Some(node) = wire_node_for(&overlay, hash)
+                    && history::is_visible(&node, history_floor)
+                {
+                    effects.push(Effect::SendPacket(
+                        peer,
+                        ProtocolMessage::MerkleNode {
+                            conversation_id,
+                            hash: *hash,
+                            node,
+                        },
+                    ));
+                }
+            }
+            if let Some(PeerSession::Active(s)) = self.sessions.get_mut(&(peer, conversation_id)) {
+                s.common.heads_dirty = true;
+            }
+        }
+        effects
+    }
+
+    /// Pushes the admin track and the last `content_window` content nodes of
+    /// `conversation_id` to `peer`, a device that just joined, and opens a
+    /// sync session so reconciliation fills in the rest. Does nothing if the
+    /// peer has acknowledged heads before; `flush_outbox` covers those.
+    /// Content the history visibility withholds from the peer is skipped.
+    pub fn push_history(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        content_window: usize,
+        store: &dyn NodeStore,
+    ) -> Vec<Effect> {
+        if self.acked_heads.contains_key(&(conversation_id, peer)) {
+            return Vec::new();
+        }
+        let mut effects = self.start_sync(conversation_id, Some(peer), store);
+
+        let mut hashes: Vec<NodeHash> = Vec::new();
+        for (node_type, window) in [
+            (NodeType::Admin, usize::MAX),
+            (NodeType::Content, content_window.min(MAX_OUTBOX_PUSH)),
+        ] {
+            match store.get_verified_nodes_by_type(&conversation_id, node_type) {
+                Ok(nodes) => {
+                    let skip = nodes.len().saturating_sub(window);
+                    hashes.extend(nodes.iter().skip(skip).map(|n| n.hash()));
+                }
+                Err(e) => debug!("Failed to list {:?} nodes: {}", node_type, e),
+            }
+        }
+        debug!(
+            "Pushing {} nodes of {:?} to new device {:?}",
+            hashes.len(),
+            conversation_id,
+            peer
+        );
+
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        let floor = self.history_floor(conversation_id, &peer);
+        for hash in hashes {
+            if let Some(node) = wire_node_for(&overlay, &hash)
+                && history::is_visible(&node, floor)
+            {
+                effects.push(Effect::SendPacket(
+                    peer,
+                    ProtocolMessage::MerkleNode {
+                        conversation_id,
+                        hash,
+                        node,
+                    },
+                ));
+            }
+        }
+        effects
+    }
+}
+
+/// `heads` and their ancestors ranked `floor` or higher.
+fn acknowledged_closure(
+    store: &dyn NodeStore,
+    heads: &[NodeHash],
+    floor: u64,
+) -> HashSet<NodeHash> {
+    let mut known: HashSet<NodeHash> = heads.iter().copied().collect();
+    let mut queue: VecDeque<NodeHash> = heads.iter().copied().collect();
+    while let Some(hash) = queue.pop_front() {
+        for parent in parents(store, &hash) {
+            if store.get_rank(&parent).is_some_and(|r| r >= floor) && known.insert(parent) {
+                queue.push_back(parent);
+            }
+        }
+    }
+    known
+}
+
+/// Parents of a stored node, verified or not.
+fn parents(store: &dyn NodeStore, hash: &NodeHash) -> Vec<NodeHash> {
+    store
+        .get_node(hash)
+        .map(|n| n.parents)
+        .or_else(|| store.get_wire_node(hash).map(|w| w.parents))
+        .unwrap_or_default()
+}
+
+/// The wire form of a node for sending: the stored one, or the node packed
+/// on the fly.
+pub(crate) fn wire_node_for(store: &dyn NodeStore, hash: &NodeHash) -> Option<WireNode> {
+    if let Some(wire_node) = store.get_wire_node(hash) {
+        return Some(wire_node);
+    }
+    let node = store.get_node(hash)?;
+    node.pack_wire(&crate::crypto::PackKeys::Exception, true)
+        .ok()
+}
@@ -0,0 +1,179 @@
+use crate::dag::{ConversationId, PhysicalDevicePk};
+use crate::engine::MerkleToxEngine;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Conversations with a node newer than this count as recently active.
+pub const RECENT_ACTIVITY_WINDOW_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Default number of conversations that may fetch from one peer at a time.
+pub const DEFAULT_MAX_FETCHES_PER_PEER: usize = 4;
+
+/// How long a conversation waiting for a fetch slot sleeps before retrying.
+pub const FETCH_DEFER_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Order in which conversations are synced. Lower values go first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SyncPriority {
+    /// The conversation the user is looking at.
+    Foreground,
+    /// A conversation with recent activity.
+    Recent,
+    /// Everything else, and conversations the application archived.
+    Archived,
+}
+
+/// Decides which conversations fetch missing nodes first when many
+/// conversations share the same peers.
+///
+/// Sessions are polled in priority order. A conversation with fetches in
+/// flight to a peer holds one of that peer's `max_fetches_per_peer` slots
+/// until they are answered; further conversations wait for a free slot. The
+/// foreground conversation is never deferred.
+#[derive(Debug, Clone)]
+pub struct SyncScheduler {
+    foreground: Option<ConversationId>,
+    archived: HashSet<ConversationId>,
+    last_activity_ms: HashMap<ConversationId, i64>,
+    pub recent_window_ms: i64,
+    pub max_fetches_per_peer: usize,
+}
+
+impl Default for SyncScheduler {
+    fn default() -> Self {
+        Self {
+            foreground: None,
+            archived: HashSet::new(),
+            last_activity_ms: HashMap::new(),
+            recent_window_ms: RECENT_ACTIVITY_WINDOW_MS,
+            max_fetches_per_peer: DEFAULT_MAX_FETCHES_PER_PEER,
+        }
+    }
+}
+
+impl SyncScheduler {
+    /// Marks the conversation the user is looking at, or none.
+    pub fn set_foreground(&mut self, conversation_id: Option<ConversationId>) {
+        self.foreground = conversation_id;
+    }
+
+    pub fn foreground(&self) -> Option<ConversationId> {
+        self.foreground
+    }
+
+    /// Moves a conversation to the lowest priority regardless of activity.
+    pub fn set_archived(&mut self, conversation_id: ConversationId, archived: bool) {
+        if archived {
+            self.archived.insert(conversation_id);
+        } else {
+            self.archived.remove(&conversation_id);
+        }
+    }
+
+    /// Records a node timestamp of a conversation.
+    pub fn record_activity(&mut self, conversation_id: ConversationId, timestamp_ms: i64) {
+        let last = self
+            .last_activity_ms
+            .entry(conversation_id)
+            .or_insert(timestamp_ms);
+        *last = (*last).max(timestamp_ms);
+    }
+
+    pub fn priority(&self, conversation_id: &ConversationId, now_ms: i64) -> SyncPriority {
+        if self.foreground.as_ref() == Some(conversation_id) {
+            SyncPriority::Foreground
+        } else if !self.archived.contains(conversation_id)
+            && self
+                .last_activity_ms
+                .get(conversation_id)
+                .is_some_and(|&ts| now_ms - ts <= self.recent_window_ms)
+        {
+            SyncPriority::Recent
+        } else {
+            SyncPriority::Archived
+        }
+    }
+
+    /// Orders session keys by priority, most urgent first. Ties keep a
+    /// stable order by conversation and peer.
+    pub fn order(
+        &self,
+        mut keys: Vec<(PhysicalDevicePk, ConversationId)>,
+        now_ms: i64,
+    ) -> Vec<(PhysicalDevicePk, ConversationId)> {
+        keys.sort_by_key(|(peer, cid)| (self.priority(cid, now_ms), *cid, *peer));
+        keys
+    }
+}
+
+/// Fetch slots of each peer in use during one poll.
+#[derive(Debug, Default)]
+pub(crate) struct FetchSlots {
+    used: HashMap<PhysicalDevicePk, usize>,
+}
+
+impl FetchSlots {
+    /// Slots held by conversations with fetches in flight.
+    pub(crate) fn in_flight(engine: &MerkleToxEngine) -> Self {
+        let mut slots = Self::default();
+        for ((peer, _), session) in &engine.sessions {
+            if !session.common().in_flight_fetches.is_empty() {
+                *slots.used.entry(*peer).or_insert(0) += 1;
+            }
+        }
+        slots
+    }
+
+    /// Takes a slot on `peer`. The foreground conversation always gets one.
+    pub(crate) fn take(
+        &mut self,
+        peer: PhysicalDevicePk,
+        priority: SyncPriority,
+        limit: usize,
+    ) -> bool {
+        let used = self.used.entry(peer).or_insert(0);
+        if priority != SyncPriority::Foreground && *used >= limit {
+            return false;
+        }
+        *used += 1;
+        true
+    }
+}
+
+impl MerkleToxEngine {
+    /// Hints which conversation the user is looking at, so that it syncs
+    /// before all others. `None` clears the hint.
+    pub fn set_foreground_conversation(&mut self, conversation_id: Option<ConversationId>) {
+        self.sync_scheduler.set_foreground(conversation_id);
+    }
+
+    /// Moves a conversation to the back of the sync queue, or restores it.
+    pub fn set_conversation_archived(&mut self, conversation_id: ConversationId, archived: bool) {
+        self.sync_scheduler.set_archived(conversation_id, archived);
+    }
+
+    pub fn sync_priority(&mut self, conversation_id: &ConversationId) -> SyncPriority {
+        let now_ms = self.clock.network_time_ms();
+        self.sync_scheduler.priority(conversation_id, now_ms)
+    }
+
+    /// Whether a session may request missing nodes from `peer` right away
+    /// rather than waiting for its turn in the next poll.
+    pub(crate) fn may_fetch_now(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+    ) -> bool {
+        let holds_slot = self
+            .sessions
+            .get(&(peer, conversation_id))
+            .is_some_and(|s| !s.common().in_flight_fetches.is_empty());
+        let priority = self.sync_priority(&conversation_id);
+        holds_slot
+            || FetchSlots::in_flight(self).take(
+                peer,
+                priority,
+                self.sync_scheduler.max_fetches_per_peer,
+            )
+    }
+}
@@ -0,0 +1,280 @@
+//! Batched signature verification.
+//!
+//! With [`EngineConfig::verify_batch_size`](crate::engine::config::EngineConfig)
+//! set, received wire nodes are queued instead of handled one by one. The
+//! engine unpacks the queued nodes, collects the Ed25519 checks of their
+//! Content signatures into an [`Effect::VerifyBatch`] and waits for the host
+//! to run them, on as many threads as it likes, and pass the
+//! [`VerifyResult`] to [`MerkleToxEngine::handle_verify_result`]. The queued
+//! nodes are then handled in arrival order, skipping the checks that passed.
+//!
+//! Only one batch is in flight at a time; nodes arriving meanwhile wait for
+//! the next one.
+
+use crate::dag::{
+    Content, ControlAction, ConversationId, Ed25519Signature, EphemeralSigningPk, MerkleNode,
+    NodeAuth, NodeHash, PhysicalDevicePk, WireNode,
+};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::error::MerkleToxResult;
+use crate::sync::{BlobStore, NodeStore};
+use std::collections::HashSet;
+use tracing::debug;
+
+/// One Ed25519 signature to check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureCheck {
+    pub node_hash: NodeHash,
+    pub public_key: EphemeralSigningPk,
+    pub message: Vec<u8>,
+    pub signature: Ed25519Signature,
+}
+
+impl SignatureCheck {
+    pub fn verify(&self) -> bool {
+        let Ok(vk) = ed25519_dalek::VerifyingKey::from_bytes(self.public_key.as_bytes()) else {
+            return false;
+        };
+        let sig = ed25519_dalek::Signature::from_bytes(self.signature.as_bytes());
+        vk.verify_strict(&self.message, &sig).is_ok()
+    }
+}
+
+/// Signature checks for the host to run off the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyBatch {
+    pub id: u64,
+    pub checks: Vec<SignatureCheck>,
+}
+
+/// Outcome of a [`VerifyBatch`], one entry per check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyResult {
+    pub id: u64,
+    pub valid: Vec<bool>,
+}
+
+impl VerifyBatch {
+    /// Runs the checks on the calling thread.
+    pub fn verify(&self) -> VerifyResult {
+        VerifyResult {
+            id: self.id,
+            valid: self.checks.iter().map(SignatureCheck::verify).collect(),
+        }
+    }
+
+    /// Runs the checks on up to `threads` scoped threads.
+    pub fn verify_parallel(&self, threads: usize) -> VerifyResult {
+        let chunk_size = self.checks.len().div_ceil(threads.max(1)).max(1);
+        if chunk_size >= self.checks.len() {
+            return self.verify();
+        }
+        let valid = std::thread::scope(|scope| {
+            let workers: Vec<_> = self
+                .checks
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope
+                        .spawn(move || chunk.iter().map(SignatureCheck::verify).collect::<Vec<_>>())
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("signature check panicked"))
+                .collect()
+        });
+        VerifyResult { id: self.id, valid }
+    }
+}
+
+/// A received wire node waiting to be handled.
+#[derive(Debug)]
+struct QueuedNode {
+    sender_pk: PhysicalDevicePk,
+    conversation_id: ConversationId,
+    hash: NodeHash,
+    node: WireNode,
+}
+
+/// The batch the host is running.
+#[derive(Debug)]
+struct InFlightBatch {
+    id: u64,
+    nodes: Vec<QueuedNode>,
+    /// Node and key of each check, in batch order.
+    checked: Vec<(NodeHash, EphemeralSigningPk)>,
+}
+
+/// Wire nodes waiting for, or being covered by, a [`VerifyBatch`].
+#[derive(Debug, Default)]
+pub struct VerifyPipeline {
+    next_id: u64,
+    queue: Vec<QueuedNode>,
+    in_flight: Option<InFlightBatch>,
+    /// Signatures checked by the host, consulted by the verification path.
+    pub(crate) valid_signatures: HashSet<(NodeHash, EphemeralSigningPk)>,
+}
+
+impl VerifyPipeline {
+    /// Number of wire nodes queued or in flight.
+    pub fn pending(&self) -> usize {
+        self.queue.len() + self.in_flight.as_ref().map_or(0, |b| b.nodes.len())
+    }
+
+    pub fn is_in_flight(&self) -> bool {
+        self.in_flight.is_some()
+    }
+}
+
+/// Epoch of the ephemeral key that signed `node`. A SenderKeyDistribution
+/// for epoch n is signed with the key of epoch n-1.
+pub(crate) fn signing_key_epoch(node: &MerkleNode) -> u64 {
+    let epoch = node.sequence_number >> 32;
+    if matches!(node.content, Content::SenderKeyDistribution { .. }) {
+        epoch.saturating_sub(1)
+    } else {
+        epoch
+    }
+}
+
+impl MerkleToxEngine {
+    /// Queues a received wire node for the next batch. Returns the batch
+    /// once the queue is full.
+    pub(crate) fn queue_wire_node(
+        &mut self,
+        sender_pk: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        node: WireNode,
+        store: &dyn NodeStore,
+        blob_store: Option<&dyn BlobStore>,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        self.verify_pipeline.queue.push(QueuedNode {
+            sender_pk,
+            conversation_id,
+            hash,
+            node,
+        });
+        if self.verify_pipeline.queue.len() < self.config.verify_batch_size {
+            return Ok(Vec::new());
+        }
+        self.flush_verify_queue(store, blob_store)
+    }
+
+    /// Starts a batch for the queued wire nodes, unless one is in flight.
+    /// Queued nodes without a signature to check are handled right away.
+    ///
+    /// Hosts call this when polling so a partly filled queue does not wait
+    /// for more nodes.
+    pub fn flush_verify_queue(
+        &mut self,
+        store: &dyn NodeStore,
+        blob_store: Option<&dyn BlobStore>,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        if self.verify_pipeline.in_flight.is_some() || self.verify_pipeline.queue.is_empty() {
+            return Ok(Vec::new());
+        }
+        let nodes = std::mem::take(&mut self.verify_pipeline.queue);
+        let checks: Vec<SignatureCheck> = nodes
+            .iter()
+            .filter_map(|q| self.signature_check(q.sender_pk, q.conversation_id, &q.node))
+            .collect();
+        if checks.is_empty() {
+            return self.handle_queued_wire_nodes(nodes, store, blob_store);
+        }
+
+        let id = self.verify_pipeline.next_id;
+        self.verify_pipeline.next_id += 1;
+        debug!(
+            "Verifying {} signatures for {} wire nodes in batch {}",
+            checks.len(),
+            nodes.len(),
+            id
+        );
+        let checked = checks.iter().map(|c| (c.node_hash, c.public_key)).collect();
+        self.verify_pipeline.in_flight = Some(InFlightBatch { id, nodes, checked });
+        Ok(vec![Effect::VerifyBatch(VerifyBatch { id, checks })])
+    }
+
+    /// Completes a batch: handles its wire nodes and starts the next batch.
+    /// Results for unknown batches are ignored.
+    pub fn handle_verify_result(
+        &mut self,
+        result: VerifyResult,
+        store: &dyn NodeStore,
+        blob_store: Option<&dyn BlobStore>,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        self.clear_pending();
+        let batch = match self.verify_pipeline.in_flight.take() {
+            Some(batch) if batch.id == result.id => batch,
+            other => {
+                self.verify_pipeline.in_flight = other;
+                return Ok(Vec::new());
+            }
+        };
+        for (key, valid) in batch.checked.into_iter().zip(result.valid) {
+            if valid {
+                self.verify_pipeline.valid_signatures.insert(key);
+            }
+        }
+        let result = self.handle_queued_wire_nodes(batch.nodes, store, blob_store);
+        self.verify_pipeline.valid_signatures.clear();
+        let mut effects = result?;
+        effects.extend(self.flush_verify_queue(store, blob_store)?);
+        Ok(effects)
+    }
+
+    fn handle_queued_wire_nodes(
+        &mut self,
+        nodes: Vec<QueuedNode>,
+        store: &dyn NodeStore,
+        blob_store: Option<&dyn BlobStore>,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let mut effects = Vec::new();
+        // Nodes stay in the pending cache so later ones can build on them.
+        for q in nodes {
+            self.ensure_conversation_loaded(q.conversation_id, store)?;
+            self.handle_wire_node(
+                q.sender_pk,
+                q.conversation_id,
+                q.hash,
+                q.node,
+                store,
+                blob_store,
+                &mut effects,
+            )?;
+        }
+        Ok(effects)
+    }
+
+    /// The Content signature check the verification path would do for
+    /// `wire_node`, if its sender's signing key is known.
+    fn signature_check(
+        &self,
+        sender_pk: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        wire_node: &WireNode,
+    ) -> Option<SignatureCheck> {
+        let node = self.unpack_received_wire_node(sender_pk, conversation_id, wire_node)?;
+        let NodeAuth::EphemeralSignature(signature) = &node.authentication else {
+            return None;
+        };
+        if node.is_exception_node()
+            || matches!(
+                node.content,
+                Content::Control(ControlAction::Genesis { .. })
+            )
+        {
+            return None;
+        }
+        let public_key = *self
+            .peer_ephemeral_signing_keys
+            .get(&(node.sender_pk, signing_key_epoch(&node)))?;
+        Some(SignatureCheck {
+            node_hash: node.hash(),
+            public_key,
+            message: wire_node.serialize_for_auth(),
+            signature: *signature,
+        })
+    }
+}
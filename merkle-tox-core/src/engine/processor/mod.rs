@@ -1,5 +1,6 @@
 use crate::dag::{Content, MerkleNode, NodeHash};
 
+pub mod batch;
 pub mod side_effects;
 pub mod verification;
 
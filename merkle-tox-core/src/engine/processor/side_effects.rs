@@ -1,9 +1,15 @@
-use crate::dag::{Content, ControlAction, ConversationId};
+use crate::NodeEvent;
+use crate::dag::{Content, ControlAction, ConversationId, LogicalIdentityPk};
+use crate::engine::config::RotationTrigger;
 use crate::engine::processor::VerifiedNode;
 use crate::engine::{Conversation, Effect, MerkleToxEngine};
 use crate::error::MerkleToxResult;
+use crate::identity::CausalContext;
 use crate::sync::NodeStore;
 
+/// Minimum time between rotations requested by other devices.
+const ROTATION_DEBOUNCE_MS: i64 = 5 * 60 * 1000;
+
 impl MerkleToxEngine {
     /// Applies administrative and cryptographic side-effects of verified node.
     pub fn apply_side_effects(
@@ -12,43 +18,95 @@ impl MerkleToxEngine {
         node: &VerifiedNode,
         store: &dyn NodeStore,
     ) -> MerkleToxResult<Vec<Effect>> {
-        let (node_ref, content) = (node.node(), node.content());
+        let node_ref = node.node();
         let mut effects = Vec::new();
+        self.sync_scheduler
+            .record_activity(conversation_id, node_ref.network_timestamp);
 
-        let mut admin_ancestor_hashes = std::collections::HashSet::new();
-        let mut stack = node_ref.parents.clone();
-        let mut visited = std::collections::HashSet::new();
+        let ctx = self.causal_context(node_ref, store);
 
-        let overlay = crate::engine::EngineStore {
-            store,
-            cache: &self.pending_cache,
-        };
+        // Apply Administrative Actions, unless they wait for the admin
+        // quorum.
+        if self.takes_effect(conversation_id, node_ref, &ctx) {
+            effects.extend(self.apply_actions(conversation_id, node, &ctx, store)?);
+        }
 
-        while let Some(parent_hash) = stack.pop() {
-            if visited.insert(parent_hash)
-                && let Some(parent_node) = overlay.get_node(&parent_hash)
+        // Advance ratchet if keys are available (exception nodes skip ratchet advancement
+        // but still track sequence numbers for ordering).
+        let now = self.clock.network_time_ms();
+        if node.node().skips_ratchet() {
+            if let Some(Conversation::Established(em)) =
+                self.conversations.get_mut(&conversation_id)
             {
-                if parent_node.node_type() == crate::dag::NodeType::Admin {
-                    admin_ancestor_hashes.insert(parent_hash);
-                }
-                if let Some(cached) = self.admin_ancestors_cache.lock().get(&parent_hash) {
-                    admin_ancestor_hashes.extend(cached.iter().cloned());
-                } else {
-                    stack.extend(parent_node.parents.clone());
+                let epoch = node_ref.sequence_number >> 32;
+                em.track_sender_seq(node_ref.sender_pk, node_ref.sequence_number, epoch);
+            }
+        } else if let Some(Conversation::Established(em)) =
+            self.conversations.get_mut(&conversation_id)
+        {
+            if let Some((_, k_next)) =
+                em.peek_keys(&node_ref.sender_pk, node_ref.sequence_number, now)
+            {
+                tracing::debug!(
+                    "Advancing ratchet for node {} (sender={}, seq={})",
+                    hex::encode(node.hash().as_bytes()),
+                    hex::encode(node_ref.sender_pk.as_bytes()),
+                    node_ref.sequence_number
+                );
+                let node_epoch = node_ref.sequence_number >> 32;
+                let prev_hash = em.commit_node_key(
+                    node_ref.sender_pk,
+                    node_ref.sequence_number,
+                    k_next.clone(),
+                    node.hash(),
+                    node_epoch,
+                );
+                effects.push(Effect::WriteRatchetKey(
+                    conversation_id,
+                    node.hash(),
+                    k_next,
+                    node_epoch,
+                ));
+
+                // Purge previous ratchet key from persistent storage.
+                if let Some(prev) = prev_hash {
+                    tracing::debug!(
+                        "Purging old ratchet key for previous node {}",
+                        hex::encode(prev.as_bytes())
+                    );
+                    effects.push(Effect::DeleteRatchetKey(conversation_id, prev));
                 }
+            } else {
+                tracing::debug!(
+                    "Ratchet NOT advanced for node {}: peek_keys returned None",
+                    hex::encode(node.hash().as_bytes())
+                );
             }
         }
-        self.admin_ancestors_cache.lock().put(
-            node.hash(),
-            std::sync::Arc::new(admin_ancestor_hashes.clone()),
-        );
 
-        let ctx = crate::identity::CausalContext {
-            evaluating_node_hash: node.hash(),
-            admin_ancestor_hashes,
+        // Channels take on the parent's new epochs and flags.
+        self.sync_channel_keys(conversation_id);
+
+        let overlay = crate::engine::EngineStore {
+            store,
+            cache: &self.pending_cache,
         };
 
-        // Apply Administrative Actions
+        effects.extend(update_heads(conversation_id, node, &overlay)?);
+        Ok(effects)
+    }
+
+    /// Applies the administrative and cryptographic actions of verified
+    /// node.
+    pub(crate) fn apply_actions(
+        &mut self,
+        conversation_id: ConversationId,
+        node: &VerifiedNode,
+        ctx: &CausalContext,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let (node_ref, content) = (node.node(), node.content());
+        let mut effects = Vec::new();
         match content {
             Content::Control(ControlAction::Genesis {
                 creator_pk,
@@ -78,7 +136,7 @@ impl MerkleToxEngine {
             }
             Content::Control(ControlAction::AuthorizeDevice { cert }) => {
                 self.identity_manager.authorize_device(
-                    &ctx,
+                    ctx,
                     conversation_id,
                     node_ref.author_pk,
                     cert,
@@ -90,10 +148,21 @@ impl MerkleToxEngine {
                 if cert.device_pk == self.self_pk {
                     self.self_certs.insert(conversation_id, cert.clone());
                 }
+                if node_ref.sender_pk == self.self_pk {
+                    effects.extend(self.rotate_on_membership_change(
+                        conversation_id,
+                        node,
+                        ctx,
+                        store,
+                    ));
+                }
             }
             Content::Control(ControlAction::RevokeDevice {
                 target_device_pk, ..
             }) => {
+                let owner = self
+                    .identity_manager
+                    .resolve_logical_pk(conversation_id, target_device_pk);
                 self.identity_manager.revoke_device(
                     conversation_id,
                     node_ref.sender_pk,
@@ -103,6 +172,19 @@ impl MerkleToxEngine {
                     node_ref.network_timestamp,
                     node.hash(),
                 );
+                if let Some(member_pk) = owner
+                    && !self.has_active_device(
+                        conversation_id,
+                        member_pk,
+                        node_ref.network_timestamp,
+                    )
+                {
+                    effects.push(Effect::EmitEvent(NodeEvent::MemberLeft {
+                        conversation_id,
+                        member_pk,
+                        hash: node.hash(),
+                    }));
+                }
                 // Purge vouches from revoked device (§5: immediate purge)
                 if let Some(conv) = self.conversations.get_mut(&conversation_id) {
                     for vouch_set in conv.vouchers_mut().values_mut() {
@@ -118,7 +200,7 @@ impl MerkleToxEngine {
                     let is_admin_revoke = self
                         .identity_manager
                         .get_permissions(
-                            &ctx,
+                            ctx,
                             conversation_id,
                             &self.self_pk,
                             &self.self_logical_pk,
@@ -136,14 +218,42 @@ impl MerkleToxEngine {
                 }
             }
             Content::Control(ControlAction::Invite(invite)) => {
+                if !self.is_member(conversation_id, invite.invitee_pk) {
+                    effects.push(Effect::EmitEvent(NodeEvent::MemberJoined {
+                        conversation_id,
+                        member_pk: invite.invitee_pk,
+                        role: invite.role,
+                        hash: node.hash(),
+                    }));
+                }
                 self.identity_manager.add_member(
                     conversation_id,
                     invite.invitee_pk,
                     invite.role,
                     node_ref.network_timestamp,
                 );
+                self.record_join_rank(
+                    conversation_id,
+                    invite.invitee_pk,
+                    node_ref.topological_rank,
+                );
+                if node_ref.sender_pk == self.self_pk {
+                    effects.extend(self.rotate_on_membership_change(
+                        conversation_id,
+                        node,
+                        ctx,
+                        store,
+                    ));
+                }
             }
             Content::Control(ControlAction::Leave(logical_pk)) => {
+                if self.is_member(conversation_id, *logical_pk) {
+                    effects.push(Effect::EmitEvent(NodeEvent::MemberLeft {
+                        conversation_id,
+                        member_pk: *logical_pk,
+                        hash: node.hash(),
+                    }));
+                }
                 self.identity_manager.remove_member(
                     conversation_id,
                     node_ref.sender_pk,
@@ -153,6 +263,16 @@ impl MerkleToxEngine {
                     node_ref.network_timestamp,
                     node.hash(),
                 );
+                // Members leave on their own, so any admin may rotate;
+                // the debounce keeps them from all doing it.
+                if node_ref.sender_pk != self.self_pk && !self.rotated_recently(conversation_id) {
+                    effects.extend(self.rotate_on_membership_change(
+                        conversation_id,
+                        node,
+                        ctx,
+                        store,
+                    ));
+                }
             }
             Content::Control(ControlAction::Announcement {
                 pre_keys,
@@ -214,6 +334,55 @@ impl MerkleToxEngine {
                         .insert((conversation_id, self.self_pk), now_ms);
                 }
             }
+            Content::Control(ControlAction::SetRetention { max_age_ms }) => {
+                self.apply_retention(
+                    conversation_id,
+                    *max_age_ms,
+                    node_ref.topological_rank,
+                    node.hash(),
+                );
+            }
+            Content::Control(ControlAction::SetHistoryVisibility {
+                visibility,
+                history_access,
+            }) => {
+                self.apply_history_visibility(
+                    conversation_id,
+                    *visibility,
+                    history_access,
+                    node_ref.topological_rank,
+                    node.hash(),
+                );
+            }
+            Content::Control(ControlAction::CreateChannel { channel_id, name }) => {
+                effects.extend(self.apply_create_channel(
+                    conversation_id,
+                    node_ref,
+                    channel_id,
+                    name,
+                    store,
+                )?);
+            }
+            Content::Control(ControlAction::CloseConversation) => {
+                self.apply_close(conversation_id, node.hash());
+            }
+            Content::Control(ControlAction::SetAdminQuorum { threshold }) => {
+                self.identity_manager.set_admin_quorum(
+                    conversation_id,
+                    *threshold,
+                    node_ref.topological_rank,
+                    node.hash(),
+                );
+            }
+            Content::Control(ControlAction::CoSign(hash)) => {
+                if self.identity_manager.co_sign_admin_action(
+                    conversation_id,
+                    *hash,
+                    node_ref.author_pk,
+                ) {
+                    effects.extend(self.apply_co_signed(conversation_id, *hash, store)?);
+                }
+            }
             Content::Control(ControlAction::SoftAnchor { .. }) => {
                 // SoftAnchor resets 500-hop ancestry trust cap.
                 // Update latest anchor hash so future KeyWraps reference it.
@@ -234,7 +403,7 @@ impl MerkleToxEngine {
                     let is_admin = self
                         .identity_manager
                         .get_permissions(
-                            &ctx,
+                            ctx,
                             conversation_id,
                             &self.self_pk,
                             &self.self_logical_pk,
@@ -245,21 +414,15 @@ impl MerkleToxEngine {
                         .contains(crate::dag::Permissions::ADMIN);
 
                     if is_admin {
-                        let should_rotate = if let Some(Conversation::Established(em)) =
-                            self.conversations.get(&conversation_id)
-                        {
-                            now - em.state.last_rotation_time_ms >= 300_000 // 5 minutes
-                        } else {
-                            true
-                        };
-
-                        if should_rotate {
+                        if !self.rotated_recently(conversation_id) {
                             tracing::debug!(
                                 "HandshakePulse received, executing debounced key rotation."
                             );
-                            if let Ok(mut r_effects) =
-                                self.rotate_conversation_key(conversation_id, store)
-                            {
+                            if let Ok(mut r_effects) = self.rotate_conversation_key_for(
+                                conversation_id,
+                                RotationTrigger::PeerRequest,
+                                store,
+                            ) {
                                 effects.append(&mut r_effects);
                             }
                         } else {
@@ -317,67 +480,89 @@ impl MerkleToxEngine {
             }
             _ => {}
         }
+        Ok(effects)
+    }
+}
 
-        // Advance ratchet if keys are available (exception nodes skip ratchet advancement
-        // but still track sequence numbers for ordering).
-        let now = self.clock.network_time_ms();
-        if node.node().skips_ratchet() {
-            if let Some(Conversation::Established(em)) =
-                self.conversations.get_mut(&conversation_id)
-            {
-                let epoch = node_ref.sequence_number >> 32;
-                em.track_sender_seq(node_ref.sender_pk, node_ref.sequence_number, epoch);
-            }
-        } else if let Some(Conversation::Established(em)) =
-            self.conversations.get_mut(&conversation_id)
-        {
-            if let Some((_, k_next)) =
-                em.peek_keys(&node_ref.sender_pk, node_ref.sequence_number, now)
-            {
-                tracing::debug!(
-                    "Advancing ratchet for node {} (sender={}, seq={})",
-                    hex::encode(node.hash().as_bytes()),
-                    hex::encode(node_ref.sender_pk.as_bytes()),
-                    node_ref.sequence_number
-                );
-                let node_epoch = node_ref.sequence_number >> 32;
-                let prev_hash = em.commit_node_key(
-                    node_ref.sender_pk,
-                    node_ref.sequence_number,
-                    k_next.clone(),
-                    node.hash(),
-                    node_epoch,
-                );
-                effects.push(Effect::WriteRatchetKey(
+impl MerkleToxEngine {
+    fn is_member(&self, conversation_id: ConversationId, member_pk: LogicalIdentityPk) -> bool {
+        self.identity_manager
+            .list_members(conversation_id)
+            .iter()
+            .any(|(pk, _, _)| *pk == member_pk)
+    }
+
+    /// Whether any device `member_pk` delegated to is authorized at `now_ms`.
+    /// The master key itself does not count.
+    fn has_active_device(
+        &self,
+        conversation_id: ConversationId,
+        member_pk: LogicalIdentityPk,
+        now_ms: i64,
+    ) -> bool {
+        self.identity_manager
+            .list_authorized_devices_for_author(conversation_id, member_pk)
+            .iter()
+            .filter(|device_pk| **device_pk != member_pk.to_physical())
+            .any(|device_pk| {
+                self.identity_manager.is_authorized(
+                    &CausalContext::global(),
                     conversation_id,
-                    node.hash(),
-                    k_next,
-                    node_epoch,
-                ));
+                    device_pk,
+                    &member_pk,
+                    now_ms,
+                    u64::MAX,
+                )
+            })
+    }
 
-                // Purge previous ratchet key from persistent storage.
-                if let Some(prev) = prev_hash {
-                    tracing::debug!(
-                        "Purging old ratchet key for previous node {}",
-                        hex::encode(prev.as_bytes())
-                    );
-                    effects.push(Effect::DeleteRatchetKey(conversation_id, prev));
-                }
-            } else {
-                tracing::debug!(
-                    "Ratchet NOT advanced for node {}: peek_keys returned None",
-                    hex::encode(node.hash().as_bytes())
-                );
+    /// Whether the conversation key was rotated within the debounce window.
+    fn rotated_recently(&mut self, conversation_id: ConversationId) -> bool {
+        let now = self.clock.network_time_ms();
+        match self.conversations.get(&conversation_id) {
+            Some(Conversation::Established(em)) => {
+                now - em.state.last_rotation_time_ms < ROTATION_DEBOUNCE_MS
             }
+            _ => false,
         }
+    }
 
-        let overlay = crate::engine::EngineStore {
-            store,
-            cache: &self.pending_cache,
-        };
-
-        effects.extend(update_heads(conversation_id, node, &overlay)?);
-        Ok(effects)
+    /// Rotates the key after a membership change if this device is an
+    /// admin and the conversation's `RotationPolicy` asks for it.
+    fn rotate_on_membership_change(
+        &mut self,
+        conversation_id: ConversationId,
+        node: &VerifiedNode,
+        ctx: &CausalContext,
+        store: &dyn NodeStore,
+    ) -> Vec<Effect> {
+        if !self
+            .rotation_policy(&conversation_id)
+            .rotate_on_membership_change
+        {
+            return Vec::new();
+        }
+        let now = self.clock.network_time_ms();
+        let is_admin = self
+            .identity_manager
+            .get_permissions(
+                ctx,
+                conversation_id,
+                &self.self_pk,
+                &self.self_logical_pk,
+                now,
+                node.node().topological_rank,
+            )
+            .unwrap_or(crate::dag::Permissions::NONE)
+            .contains(crate::dag::Permissions::ADMIN);
+        if !is_admin {
+            return Vec::new();
+        }
+        self.rotate_conversation_key_for(conversation_id, RotationTrigger::MembershipChange, store)
+            .unwrap_or_else(|e| {
+                tracing::debug!("Membership change rotation failed: {}", e);
+                Vec::new()
+            })
     }
 }
 
@@ -393,7 +578,7 @@ fn update_heads(
     if node_ref.node_type() == crate::dag::NodeType::Admin {
         let mut heads = overlay.get_admin_heads(&conversation_id);
         heads.retain(|h| !node_ref.parents.contains(h));
-        if !heads.contains(&hash) {
+        if !heads.contains(&hash) && !is_ancestor_of(node_ref, &heads, overlay) {
             heads.push(hash);
         }
         overlay.set_admin_heads(&conversation_id, heads.clone())?;
@@ -401,7 +586,7 @@ fn update_heads(
     } else {
         let mut heads = overlay.get_heads(&conversation_id);
         heads.retain(|h| !node_ref.parents.contains(h));
-        if !heads.contains(&hash) {
+        if !heads.contains(&hash) && !is_ancestor_of(node_ref, &heads, overlay) {
             heads.push(hash);
         }
         overlay.set_heads(&conversation_id, heads.clone())?;
@@ -410,3 +595,33 @@ fn update_heads(
 
     Ok(effects)
 }
+
+/// Returns true if `node` is an ancestor of one of `heads`. Bootstrap nodes
+/// are verified before a speculative parent, so that parent can be verified
+/// after one of its children and must not become a head again.
+fn is_ancestor_of(
+    node: &crate::dag::MerkleNode,
+    heads: &[crate::dag::NodeHash],
+    store: &dyn NodeStore,
+) -> bool {
+    let hash = node.hash();
+    let mut visited = std::collections::HashSet::new();
+    let mut stack: Vec<_> = heads.to_vec();
+    while let Some(current) = stack.pop() {
+        let Some(current) = store.get_node(&current) else {
+            continue;
+        };
+        if current.topological_rank <= node.topological_rank {
+            continue;
+        }
+        for parent in current.parents {
+            if parent == hash {
+                return true;
+            }
+            if visited.insert(parent) {
+                stack.push(parent);
+            }
+        }
+    }
+    false
+}
@@ -4,6 +4,7 @@ use crate::dag::{
     NodeHash, NodeType, Permissions,
 };
 use crate::engine::processor::VerifiedNode;
+use crate::engine::processor::batch::signing_key_epoch;
 use crate::engine::{Conversation, ConversationData, Effect, MerkleToxEngine, conversation};
 use crate::error::{MerkleToxError, MerkleToxResult};
 use crate::sync::{BlobStore, NodeStore};
@@ -22,6 +23,7 @@ impl MerkleToxEngine {
         blob_store: Option<&dyn BlobStore>,
     ) -> MerkleToxResult<Vec<Effect>> {
         self.clear_pending();
+        self.ensure_conversation_loaded(conversation_id, store)?;
         self.handle_node_internal_ext(conversation_id, node, store, blob_store, true)
     }
 
@@ -50,6 +52,12 @@ impl MerkleToxEngine {
         // Collect OPK IDs consumed during verification for deferred deletion
         // (cannot mutate self.ephemeral_keys while overlay holds &self.pending_cache).
         let mut opk_ids_to_consume: Vec<NodeHash> = Vec::new();
+        // Set when a Content signature is checked and wrong (deferred for
+        // the same reason).
+        let mut failed_signature = false;
+        // Set when light mode skips the signature check of a Content node,
+        // which is then only stored speculatively.
+        let mut unchecked = false;
 
         let (verified, authentic) = {
             let overlay = crate::engine::EngineStore {
@@ -72,7 +80,8 @@ impl MerkleToxEngine {
             }
 
             // 1. Validate DAG rules
-            let structurally_valid = match node.validate(&conversation_id, &overlay) {
+            let rules = self.validation_rules(conversation_id);
+            let structurally_valid = match node.validate_with(&conversation_id, &overlay, rules) {
                 Ok(_) => true,
                 Err(crate::dag::ValidationError::MissingParents(_))
                 | Err(crate::dag::ValidationError::TopologicalRankViolation { .. }) => false,
@@ -111,6 +120,15 @@ impl MerkleToxEngine {
                 std::sync::Arc::new(admin_ancestor_hashes.clone()),
             );
 
+            // A closed conversation takes no content written after its close.
+            if node.node_type() == NodeType::Content
+                && self.is_after_close(conversation_id, &admin_ancestor_hashes)
+            {
+                return Err(MerkleToxError::Validation(
+                    crate::dag::ValidationError::ConversationClosed,
+                ));
+            }
+
             let ctx = crate::identity::CausalContext {
                 evaluating_node_hash: node.hash(),
                 admin_ancestor_hashes,
@@ -158,22 +176,28 @@ impl MerkleToxEngine {
                 }
             }
 
-            if min_parent_ts != i64::MAX && node.network_timestamp < min_parent_ts - 600_000 {
+            let policy = &self.config.clock_policy;
+            let max_parent_skew = policy.max_parent_skew.as_millis() as i64;
+            if min_parent_ts != i64::MAX && node.network_timestamp < min_parent_ts - max_parent_skew
+            {
                 debug!(
-                    "Node {} quarantined: timestamp {} < oldest parent timestamp {} - 10min",
+                    "Node {} quarantined: timestamp {} < oldest parent timestamp {} - {}ms",
                     hex::encode(node_hash.as_bytes()),
                     node.network_timestamp,
-                    min_parent_ts
+                    min_parent_ts,
+                    max_parent_skew
                 );
                 quarantined = true;
             }
 
-            if node.network_timestamp > now + 10 * 60 * 1000 {
+            let max_future_skew = policy.max_future_skew.as_millis() as i64;
+            if node.network_timestamp > now + max_future_skew {
                 debug!(
-                    "Node {} quarantined: timestamp {} is too far in the future (now={} + 10min)",
+                    "Node {} quarantined: timestamp {} is too far in the future (now={} + {}ms)",
                     hex::encode(node_hash.as_bytes()),
                     node.network_timestamp,
-                    now
+                    now,
+                    max_future_skew
                 );
                 quarantined = true;
             }
@@ -236,6 +260,31 @@ impl MerkleToxEngine {
                 // If target not found, allow speculatively (parents may arrive later)
             }
 
+            // Poll validation: votes must fit their poll, and only its author
+            // or an ADMIN closes it
+            crate::poll::validate_against_poll(&node, &overlay)?;
+            if let Content::PollClose { poll_hash } = &node.content
+                && let Some(poll_node) = overlay.get_node(poll_hash)
+                && poll_node.author_pk != node.author_pk
+            {
+                let perms = self
+                    .identity_manager
+                    .get_permissions(
+                        &ctx,
+                        conversation_id,
+                        &node.sender_pk,
+                        &node.author_pk,
+                        node.network_timestamp,
+                        node.topological_rank,
+                    )
+                    .unwrap_or(Permissions::NONE);
+                if !perms.contains(Permissions::ADMIN) {
+                    return Err(MerkleToxError::Validation(
+                        crate::dag::ValidationError::PollClosePermissionDenied,
+                    ));
+                }
+            }
+
             // Reaction validation: target must be a content node (not admin)
             if let Content::Reaction { target_hash, .. } = &node.content
                 && let Some(target_node) = overlay.get_node(target_hash)
@@ -491,6 +540,13 @@ impl MerkleToxEngine {
                                     est
                                 }
                                 Conversation::Established(mut e) => {
+                                    if *generation > e.current_epoch() {
+                                        effects.push(Effect::EmitEvent(NodeEvent::EpochRotated {
+                                            conversation_id,
+                                            epoch: *generation,
+                                            trigger: None,
+                                        }));
+                                    }
                                     e.add_epoch(*generation, k_conv.clone());
                                     e
                                 }
@@ -657,8 +713,11 @@ impl MerkleToxEngine {
                                     received_mask: None,
                                     decryption_key: k_export,
                                 };
-                                self.blob_syncs
-                                    .insert(*blob_hash, crate::cas::SwarmSync::new(info));
+                                let mut sync =
+                                    crate::cas::SwarmSync::new(info).with_config(self.blob_config);
+                                // Queried right below.
+                                sync.last_query = Some(self.clock.time_provider().now_instant());
+                                self.blob_syncs.insert(*blob_hash, sync);
                                 // Trigger immediate blob fetch from peers
                                 for ((pk, cid), session) in &self.sessions {
                                     if *cid == conversation_id
@@ -694,17 +753,13 @@ impl MerkleToxEngine {
                             authentic = true;
                         }
                     }
+                } else if self.skip_content_signature(conversation_id, &node, is_authorized) {
+                    unchecked = true;
                 } else {
                     // Content nodes: verify ephemeral signature against stored
                     // peer ephemeral signing public key for sender's epoch.
-                    let epoch = node.sequence_number >> 32;
                     // DARE §2: SKD for epoch n is signed with epoch n-1's key.
-                    let lookup_epoch =
-                        if matches!(&node.content, Content::SenderKeyDistribution { .. }) {
-                            epoch.saturating_sub(1)
-                        } else {
-                            epoch
-                        };
+                    let lookup_epoch = signing_key_epoch(&node);
                     if let Some(epk) = self
                         .peer_ephemeral_signing_keys
                         .get(&(node.sender_pk, lookup_epoch))
@@ -717,7 +772,14 @@ impl MerkleToxEngine {
                             // (ciphertext) first. Fall back to plaintext
                             // auth data for direct API / legacy compat.
                             if !node.is_exception_node() {
-                                if let Some(wire) = overlay.get_wire_node(&node_hash) {
+                                if self
+                                    .verify_pipeline
+                                    .valid_signatures
+                                    .contains(&(node_hash, *epk))
+                                {
+                                    // Checked against the wire node by a VerifyBatch.
+                                    authentic = true;
+                                } else if let Some(wire) = overlay.get_wire_node(&node_hash) {
                                     let wire_auth = wire.serialize_for_auth();
                                     if vk.verify_strict(&wire_auth, &ed_sig).is_ok() {
                                         authentic = true;
@@ -735,6 +797,7 @@ impl MerkleToxEngine {
                                     authentic = true;
                                 }
                             }
+                            failed_signature = !authentic;
                         }
                     }
                 }
@@ -848,7 +911,16 @@ impl MerkleToxEngine {
                 overlay.put_node(&conversation_id, node.clone(), true)?;
             } else {
                 let (_, spec_count) = overlay.get_node_counts(&conversation_id);
-                if spec_count >= MAX_SPECULATIVE_NODES_PER_CONVERSATION {
+                // Unchecked nodes come from authorized devices and must not
+                // crowd out nodes still waiting for parents or authorization.
+                let unchecked_count = self
+                    .unchecked_nodes
+                    .get(&conversation_id)
+                    .map_or(0, std::collections::HashSet::len);
+                if !unchecked
+                    && spec_count.saturating_sub(unchecked_count)
+                        >= MAX_SPECULATIVE_NODES_PER_CONVERSATION
+                {
                     warn!(
                         "Too many speculative nodes for conversation {:?}, rejecting node {}",
                         conversation_id,
@@ -868,6 +940,15 @@ impl MerkleToxEngine {
         for opk_id in opk_ids_to_consume {
             self.consume_opk_sk(&opk_id);
         }
+        if failed_signature && !node.is_exception_node() {
+            self.record_spot_check_failure(conversation_id, node.sender_pk);
+        }
+        if unchecked && !verified {
+            self.unchecked_nodes
+                .entry(conversation_id)
+                .or_default()
+                .insert(node_hash);
+        }
 
         // Ensure conversation entry exists
         self.conversations
@@ -923,7 +1004,8 @@ impl MerkleToxEngine {
                 match verified_node.content() {
                     Content::Control(ControlAction::AuthorizeDevice { .. })
                     | Content::Control(ControlAction::RevokeDevice { .. })
-                    | Content::Control(ControlAction::Leave(_)) => {
+                    | Content::Control(ControlAction::Leave(_))
+                    | Content::Control(ControlAction::CoSign(_)) => {
                         effects.extend(self.revalidate_all_verified_nodes(conversation_id, store));
                     }
                     _ => {}
@@ -968,11 +1050,13 @@ impl MerkleToxEngine {
                     node,
                 }));
             } else {
+                let mentioned = self.mention_event(conversation_id, node_hash, &node);
                 effects.push(Effect::EmitEvent(NodeEvent::NodeVerified {
                     conversation_id,
                     hash: node_hash,
                     node,
                 }));
+                effects.extend(mentioned);
             }
         } else {
             effects.push(Effect::EmitEvent(NodeEvent::NodeSpeculative {
@@ -1050,6 +1134,11 @@ impl MerkleToxEngine {
         let required = match &node.content {
             Content::Text(_)
             | Content::Blob { .. }
+            | Content::Voice { .. }
+            | Content::Poll { .. }
+            | Content::PollVote { .. }
+            | Content::PollClose { .. }
+            | Content::StickerPack { .. }
             | Content::Reaction { .. }
             | Content::Location { .. }
             | Content::Edit { .. }
@@ -1066,8 +1155,17 @@ impl MerkleToxEngine {
                 | ControlAction::SetTopic(_)
                 | ControlAction::Snapshot(_)
                 | ControlAction::AnchorSnapshot { .. }
+                | ControlAction::SetRetention { .. }
+                | ControlAction::VerifiedIdentity(_)
+                | ControlAction::SetHistoryVisibility { .. }
+                | ControlAction::CreateChannel { .. }
+                | ControlAction::CloseConversation
+                | ControlAction::SetAdminQuorum { .. }
+                | ControlAction::CoSign(_)
                 | ControlAction::Genesis { .. } => Permissions::ADMIN,
-                ControlAction::SoftAnchor { .. } => Permissions::MESSAGE,
+                ControlAction::SoftAnchor { .. } | ControlAction::SetProfile(_) => {
+                    Permissions::MESSAGE
+                }
                 ControlAction::Invite(_) => {
                     // Check genesis flags: FLAG_MEMBER_INVITE (0x02) allows MESSAGE-level invite
                     let flags = self
@@ -1121,6 +1219,9 @@ impl MerkleToxEngine {
                     if let Err(e) = store.mark_verified(&conversation_id, &node.hash()) {
                         error!("Failed to mark node verified: {}", e);
                     } else {
+                        if let Some(unchecked) = self.unchecked_nodes.get_mut(&conversation_id) {
+                            unchecked.remove(&node.hash());
+                        }
                         effects.extend(v_effects);
                         effects.push(Effect::WriteStore(conversation_id, node.clone(), true));
                         let is_identity_pending = self
@@ -1141,6 +1242,7 @@ impl MerkleToxEngine {
                                 hash: node.hash(),
                                 node: node.clone(),
                             }));
+                            effects.extend(self.mention_event(conversation_id, node.hash(), &node));
                         }
                         // Vouch for parents of newly verified node
                         for ((_, cid), session) in self.sessions.iter_mut() {
@@ -1185,6 +1287,15 @@ impl MerkleToxEngine {
 
             for node in speculative {
                 let node_hash = node.hash();
+                // Light mode accepted these without a signature check; checking
+                // them on every sweep would cost more than full verification.
+                if self
+                    .unchecked_nodes
+                    .get(&conversation_id)
+                    .is_some_and(|unchecked| unchecked.contains(&node_hash))
+                {
+                    continue;
+                }
                 // Only attempt to verify if the node is already known to be authentic
                 // OR if it's an Admin node (which are always "authentic" for this purpose as they use signatures)
                 let is_authentic = match &node.authentication {
@@ -1339,7 +1450,8 @@ impl MerkleToxEngine {
             };
 
             // 1. Structural check (including parents)
-            let structurally_valid = match node.validate(&conversation_id, &overlay) {
+            let rules = self.validation_rules(conversation_id);
+            let structurally_valid = match node.validate_with(&conversation_id, &overlay, rules) {
                 Ok(_) => true,
                 Err(crate::dag::ValidationError::MissingParents(_))
                 | Err(crate::dag::ValidationError::TopologicalRankViolation { .. }) => false,
@@ -1363,21 +1475,28 @@ impl MerkleToxEngine {
             }
 
             let mut quarantined = false;
-            if min_parent_ts_vn != i64::MAX && node.network_timestamp < min_parent_ts_vn - 600_000 {
+            let policy = &self.config.clock_policy;
+            let max_parent_skew = policy.max_parent_skew.as_millis() as i64;
+            if min_parent_ts_vn != i64::MAX
+                && node.network_timestamp < min_parent_ts_vn - max_parent_skew
+            {
                 debug!(
-                    "Node {} failed verification: network_timestamp {} < min_parent_ts {} - 10min",
+                    "Node {} failed verification: network_timestamp {} < min_parent_ts {} - {}ms",
                     hex::encode(node.hash().as_bytes()),
                     node.network_timestamp,
-                    min_parent_ts_vn
+                    min_parent_ts_vn,
+                    max_parent_skew
                 );
                 quarantined = true;
             }
 
-            if node.network_timestamp > now + 10 * 60 * 1000 {
+            let max_future_skew = policy.max_future_skew.as_millis() as i64;
+            if node.network_timestamp > now + max_future_skew {
                 debug!(
-                    "Node {} failed verification: network_timestamp {} > now + 10min",
+                    "Node {} failed verification: network_timestamp {} > now + {}ms",
                     hex::encode(node.hash().as_bytes()),
-                    node.network_timestamp
+                    node.network_timestamp,
+                    max_future_skew
                 );
                 quarantined = true;
             }
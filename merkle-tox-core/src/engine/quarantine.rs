@@ -0,0 +1,228 @@
+//! Quarantine of misbehaving senders.
+//!
+//! Wire nodes that cannot be unpacked yet are kept opaque, within per-sender
+//! quotas (see [`QuarantinePolicy`](crate::engine::config::QuarantinePolicy)).
+//! Offenses such as exceeding those quotas, sending undecodable messages or
+//! wrong PoW solutions add to the sender's spam score. A sender whose score
+//! reaches the limit is blacklisted, and blacklist entries are persisted so
+//! they survive restarts.
+
+use crate::NodeEvent;
+use crate::dag::PhysicalDevicePk;
+use crate::engine::{BlacklistState, Effect, MerkleToxEngine};
+use crate::sync::NodeStore;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Duration;
+use tox_proto::ToxProto;
+use tracing::{debug, warn};
+
+/// Something a sender did that counts against it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpamOffense {
+    /// A message or wire node that failed to decode.
+    DecodeFailure,
+    /// A PoW solution that does not meet the challenge.
+    InvalidPow,
+    /// An opaque wire node over the sender's quota.
+    QuotaExceeded,
+}
+
+impl SpamOffense {
+    /// Spam points added for the offense.
+    pub fn weight(self) -> u32 {
+        match self {
+            SpamOffense::DecodeFailure => 10,
+            SpamOffense::InvalidPow => 20,
+            SpamOffense::QuotaExceeded => 5,
+        }
+    }
+}
+
+/// Persisted blacklist state of a device.
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct BlacklistEntry {
+    pub device_pk: PhysicalDevicePk,
+    pub tier: u8,
+    pub expires_at_ms: i64,
+}
+
+/// What is held against a sender.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantineReport {
+    pub device_pk: PhysicalDevicePk,
+    /// Opaque wire nodes kept from the sender, across conversations.
+    pub opaque_nodes: usize,
+    pub opaque_bytes: usize,
+    pub spam_score: u32,
+    /// Blacklist tier reached, 0 if never blacklisted.
+    pub blacklist_tier: u8,
+    /// End of the blacklisting, if it is in effect.
+    pub blacklisted_until: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SpamScore {
+    points: u32,
+    updated_ms: i64,
+}
+
+impl SpamScore {
+    /// Points left at `now_ms`, and the time they were last halved.
+    fn decayed(&self, now_ms: i64, half_life: Duration) -> (u32, i64) {
+        let half_life_ms = (half_life.as_millis() as i64).max(1);
+        let halvings = (now_ms - self.updated_ms).max(0) / half_life_ms;
+        let points = if halvings >= 32 {
+            0
+        } else {
+            self.points >> halvings
+        };
+        (points, self.updated_ms + halvings * half_life_ms)
+    }
+}
+
+/// Spam scores and blacklist changes not yet persisted.
+#[derive(Debug, Default)]
+pub struct Quarantine {
+    scores: HashMap<PhysicalDevicePk, SpamScore>,
+    pub(crate) unsaved: HashSet<PhysicalDevicePk>,
+}
+
+impl MerkleToxEngine {
+    /// Loads the blacklist saved by a previous run. Returns how many entries
+    /// were loaded.
+    pub fn load_blacklist(&mut self, store: &dyn NodeStore) -> usize {
+        let mut loaded = 0;
+        for entry in store.get_blacklist() {
+            let state = BlacklistState {
+                tier: entry.tier,
+                expires_at_ms: entry.expires_at_ms,
+            };
+            if self.peer_blacklist.insert(entry.device_pk, state).is_none() {
+                loaded += 1;
+            }
+        }
+        loaded
+    }
+
+    /// Adds the spam points of `offense` to `peer`'s score. Returns true if
+    /// this got the peer blacklisted.
+    pub fn report_spam(&mut self, peer: PhysicalDevicePk, offense: SpamOffense) -> bool {
+        let now = self.clock.network_time_ms();
+        let policy = self.config.quarantine;
+        let score = self.quarantine.scores.entry(peer).or_default();
+        let (points, updated_ms) = score.decayed(now, policy.score_half_life);
+        score.points = points.saturating_add(offense.weight());
+        score.updated_ms = updated_ms;
+        debug!(
+            "Spam offense {:?} by {:?}, score {}",
+            offense, peer, score.points
+        );
+        if score.points < policy.blacklist_score {
+            return false;
+        }
+        self.quarantine.scores.remove(&peer);
+        self.blacklist_escalate(peer);
+        warn!("Blacklisted {:?} for reaching the spam score limit", peer);
+        true
+    }
+
+    /// Network time without advancing the clock.
+    fn quarantine_now_ms(&self) -> i64 {
+        self.clock.time_provider().now_system_ms() + self.clock.consensus_offset()
+    }
+
+    /// Current spam score of `peer`.
+    pub fn spam_score(&self, peer: &PhysicalDevicePk) -> u32 {
+        let now = self.quarantine_now_ms();
+        self.quarantine.scores.get(peer).map_or(0, |score| {
+            score.decayed(now, self.config.quarantine.score_half_life).0
+        })
+    }
+
+    /// Opaque nodes and bytes kept from `peer` in all conversations.
+    fn opaque_usage(&self, peer: &PhysicalDevicePk) -> (usize, usize) {
+        self.opaque_store_usage
+            .values()
+            .flat_map(|(_, entries)| entries)
+            .filter(|(_, _, _, sender)| sender == peer)
+            .fold((0, 0), |(count, bytes), (_, size, _, _)| {
+                (count + 1, bytes + size)
+            })
+    }
+
+    /// What is held against `peer`.
+    pub fn quarantine_report(&self, peer: &PhysicalDevicePk) -> QuarantineReport {
+        let (opaque_nodes, opaque_bytes) = self.opaque_usage(peer);
+        let blacklist = self.peer_blacklist.get(peer);
+        let now = self.quarantine_now_ms();
+        QuarantineReport {
+            device_pk: *peer,
+            opaque_nodes,
+            opaque_bytes,
+            spam_score: self.spam_score(peer),
+            blacklist_tier: blacklist.map_or(0, |bl| bl.tier),
+            blacklisted_until: blacklist
+                .filter(|bl| bl.is_active(now))
+                .map(|bl| bl.expires_at_ms),
+        }
+    }
+
+    /// Reports for every sender with opaque nodes, spam points or a
+    /// blacklist entry, ordered by device.
+    pub fn quarantine_reports(&self) -> Vec<QuarantineReport> {
+        let peers: BTreeSet<PhysicalDevicePk> = self
+            .opaque_store_usage
+            .values()
+            .flat_map(|(_, entries)| entries.iter().map(|(_, _, _, sender)| *sender))
+            .chain(self.quarantine.scores.keys().copied())
+            .chain(self.peer_blacklist.keys().copied())
+            .collect();
+        peers
+            .iter()
+            .map(|peer| self.quarantine_report(peer))
+            .collect()
+    }
+
+    /// Forgives `peer`: deletes the opaque nodes kept from it, resets its
+    /// spam score and lifts its blacklisting, including the tier reached.
+    pub fn clear_quarantine(&mut self, peer: &PhysicalDevicePk) -> Vec<Effect> {
+        let mut effects = Vec::new();
+        for (cid, (total, entries)) in &mut self.opaque_store_usage {
+            entries.retain(|(hash, size, _, sender)| {
+                if sender != peer || self.promotion_locked.contains(hash) {
+                    return true;
+                }
+                *total -= size;
+                effects.push(Effect::DeleteWireNode(*cid, *hash));
+                false
+            });
+        }
+        self.quarantine.scores.remove(peer);
+        self.quarantine.unsaved.remove(peer);
+        if self.peer_blacklist.remove(peer).is_some() {
+            effects.push(Effect::DeleteBlacklistEntry(*peer));
+        }
+        effects
+    }
+
+    /// Persists blacklist entries changed since the last call.
+    pub(crate) fn save_blacklist(&mut self, effects: &mut Vec<Effect>) {
+        let mut unsaved: Vec<PhysicalDevicePk> = self.quarantine.unsaved.drain().collect();
+        unsaved.sort_unstable();
+        for device_pk in unsaved {
+            let Some(state) = self.peer_blacklist.get(&device_pk) else {
+                continue;
+            };
+            effects.push(Effect::WriteBlacklistEntry(BlacklistEntry {
+                device_pk,
+                tier: state.tier,
+                expires_at_ms: state.expires_at_ms,
+            }));
+            effects.push(Effect::EmitEvent(NodeEvent::PeerBlacklisted {
+                device_pk,
+                tier: state.tier,
+                expires_at_ms: state.expires_at_ms,
+            }));
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Admin quorum for destructive admin actions.
+//!
+//! With a quorum of M set by `SetAdminQuorum`, revoking a device, removing
+//! another member, closing the conversation and changing the quorum take
+//! effect only once M distinct admins approved them: the author, and the
+//! authors of `CoSign` nodes naming the action. Until then the action is
+//! verified and stored, but pending. This keeps a single compromised admin
+//! from taking over the group.
+
+use crate::dag::{Content, ControlAction, ConversationId, MerkleNode, NodeHash};
+use crate::engine::processor::VerifiedNode;
+use crate::engine::{Effect, EngineStore, MerkleToxEngine};
+use crate::error::MerkleToxResult;
+use crate::identity::{CausalContext, PendingAdminAction};
+use crate::sync::NodeStore;
+
+/// Whether the action in `node` waits for the admin quorum.
+pub fn requires_quorum(node: &MerkleNode) -> bool {
+    match &node.content {
+        Content::Control(
+            ControlAction::RevokeDevice { .. }
+            | ControlAction::CloseConversation
+            | ControlAction::SetAdminQuorum { .. },
+        ) => true,
+        Content::Control(ControlAction::Leave(target_pk)) => *target_pk != node.author_pk,
+        _ => false,
+    }
+}
+
+impl MerkleToxEngine {
+    /// Approves the pending admin action in node `hash`. Requires ADMIN.
+    pub fn co_sign(
+        &mut self,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        self.author_node(
+            conversation_id,
+            Content::Control(ControlAction::CoSign(hash)),
+            Vec::new(),
+            store,
+        )
+    }
+
+    /// Admin actions of `conversation_id` waiting for co-signatures.
+    pub fn pending_admin_actions(
+        &self,
+        conversation_id: ConversationId,
+    ) -> Vec<PendingAdminAction> {
+        self.identity_manager.pending_admin_actions(conversation_id)
+    }
+
+    /// Whether the action of verified `node`, with causal context `ctx`,
+    /// takes effect now. Actions needing the quorum are recorded as pending
+    /// otherwise.
+    pub(crate) fn takes_effect(
+        &mut self,
+        conversation_id: ConversationId,
+        node: &MerkleNode,
+        ctx: &CausalContext,
+    ) -> bool {
+        !requires_quorum(node)
+            || self.identity_manager.propose_admin_action(
+                ctx,
+                conversation_id,
+                node.hash(),
+                node.author_pk,
+            )
+    }
+
+    /// Applies the admin action in node `hash`, whose quorum a CoSign just
+    /// completed.
+    pub(crate) fn apply_co_signed(
+        &mut self,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let overlay = EngineStore {
+            store,
+            cache: &self.pending_cache,
+        };
+        let Some(node) = overlay.get_node(&hash) else {
+            return Ok(Vec::new());
+        };
+        let ctx = self.causal_context(&node, store);
+        tracing::debug!("Admin action {:?} reached its quorum", hash);
+        let content = node.content.clone();
+        self.apply_actions(
+            conversation_id,
+            &VerifiedNode::new(node, content),
+            &ctx,
+            store,
+        )
+    }
+}
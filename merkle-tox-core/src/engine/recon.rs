@@ -0,0 +1,120 @@
+//! Admission control for reconciliation requests from peers: PoW difficulty
+//! tuned to each peer's recent load, per-peer round cooldowns and a cap on
+//! concurrent reconciliations (see `config::ReconPolicy`).
+
+use crate::dag::{ConversationId, PhysicalDevicePk};
+use crate::engine::MerkleToxEngine;
+use crate::sync::Tier;
+use std::collections::HashMap;
+use std::time::Duration;
+use tox_proto::time::Instant;
+
+/// Sketches received from one peer in the current load window.
+#[derive(Debug, Clone, Copy)]
+struct PeerLoad {
+    window_start: Instant,
+    sketches: u32,
+}
+
+/// Reconciliation load state, keyed by peer and conversation.
+#[derive(Debug, Default)]
+pub struct ReconGuard {
+    load: HashMap<PhysicalDevicePk, PeerLoad>,
+    /// Last message of each reconciliation in progress.
+    active: HashMap<(PhysicalDevicePk, ConversationId), Instant>,
+    /// Start of the last round each peer began per conversation.
+    last_round: HashMap<(PhysicalDevicePk, ConversationId), Instant>,
+}
+
+impl MerkleToxEngine {
+    /// PoW difficulty currently demanded from `peer` for a sketch.
+    pub fn recon_difficulty(&self, peer: &PhysicalDevicePk) -> u32 {
+        let policy = &self.config.recon;
+        let sketches = self.recon_guard.load.get(peer).map_or(0, |l| l.sketches);
+        let excess = sketches.saturating_sub(policy.free_sketches_per_window);
+        policy
+            .base_difficulty
+            .saturating_add(excess)
+            .min(policy.max_difficulty.max(policy.base_difficulty))
+    }
+
+    /// Admits a reconciliation round (`SyncShardChecksums`) started by
+    /// `peer`. Returns the time to wait if the peer is in its cooldown or
+    /// too many reconciliations are running.
+    pub(crate) fn admit_recon_round(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let cooldown = self.config.recon.round_cooldown;
+        let guard = &mut self.recon_guard;
+        guard
+            .last_round
+            .retain(|_, start| now.saturating_duration_since(*start) < cooldown);
+        if let Some(start) = guard.last_round.get(&(peer, conversation_id)) {
+            return Err(cooldown - now.saturating_duration_since(*start));
+        }
+        self.mark_recon_active(peer, conversation_id, now)?;
+        self.recon_guard
+            .last_round
+            .insert((peer, conversation_id), now);
+        Ok(())
+    }
+
+    /// Admits a sketch of `tier` from `peer` and counts it towards the
+    /// peer's load. Returns the PoW difficulty to challenge with, if any, or
+    /// the time to wait if too many reconciliations are running.
+    pub(crate) fn admit_sketch(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        tier: Tier,
+        now: Instant,
+    ) -> Result<Option<u32>, Duration> {
+        if tier != Tier::Tiny {
+            self.mark_recon_active(peer, conversation_id, now)?;
+        }
+
+        let window = self.config.recon.load_window;
+        let load = self.recon_guard.load.entry(peer).or_insert(PeerLoad {
+            window_start: now,
+            sketches: 0,
+        });
+        let elapsed = now.saturating_duration_since(load.window_start);
+        if !window.is_zero() && elapsed >= window {
+            let windows = (elapsed.as_millis() / window.as_millis()).min(31) as u32;
+            load.sketches >>= windows;
+            load.window_start = now;
+        }
+        load.sketches = load.sketches.saturating_add(1);
+
+        let difficulty = self.recon_difficulty(&peer);
+        let loaded = difficulty > self.config.recon.base_difficulty;
+        Ok((loaded || tier == Tier::Medium || tier == Tier::Large).then_some(difficulty))
+    }
+
+    fn mark_recon_active(
+        &mut self,
+        peer: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let policy = &self.config.recon;
+        let idle = policy.recon_idle_timeout;
+        let active = &mut self.recon_guard.active;
+        active.retain(|_, last| now.saturating_duration_since(*last) < idle);
+        if !active.contains_key(&(peer, conversation_id))
+            && active.len() >= policy.max_concurrent_recons
+        {
+            let retry_after = active
+                .values()
+                .map(|last| idle - now.saturating_duration_since(*last))
+                .min()
+                .unwrap_or(idle);
+            return Err(retry_after);
+        }
+        active.insert((peer, conversation_id), now);
+        Ok(())
+    }
+}
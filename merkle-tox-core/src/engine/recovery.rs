@@ -0,0 +1,134 @@
+//! Recovery from a compromised device.
+//!
+//! Declaring a device compromised revokes it, forgets the keys it published
+//! and rotates the conversation key. The rotation wraps the new key for every
+//! remaining device, including those that only have a last-resort pre-key
+//! left, and distributes a fresh sender key, so nothing the compromised
+//! device held decrypts or authenticates later messages.
+
+use crate::NodeEvent;
+use crate::dag::{Content, ControlAction, ConversationId, NodeHash, PhysicalDevicePk};
+use crate::engine::config::RotationTrigger;
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::error::{MerkleToxError, MerkleToxResult};
+use crate::identity::CausalContext;
+use crate::sync::NodeStore;
+use tracing::info;
+
+/// Reason recorded in the RevokeDevice node.
+pub const COMPROMISE_REASON: &str = "compromised";
+
+/// Outcome of [`MerkleToxEngine::recover_from_compromise`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompromiseRecovery {
+    pub conversation_id: ConversationId,
+    pub device_pk: PhysicalDevicePk,
+    /// The RevokeDevice node.
+    pub revocation: NodeHash,
+    /// Epoch of the new conversation key.
+    pub epoch: u64,
+    /// Devices the new key was wrapped for.
+    pub rewrapped: Vec<PhysicalDevicePk>,
+}
+
+impl MerkleToxEngine {
+    /// Revokes `device_pk` as compromised and moves the conversation to a
+    /// new key only the remaining devices receive. Ends with a
+    /// `NodeEvent::CompromiseRecovered` effect summarizing the recovery.
+    ///
+    /// Needs admin rights; a device cannot recover from its own compromise.
+    pub fn recover_from_compromise(
+        &mut self,
+        conversation_id: ConversationId,
+        device_pk: PhysicalDevicePk,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        if device_pk == self.self_pk {
+            return Err(MerkleToxError::Other(
+                "Cannot recover from compromise of this device".to_string(),
+            ));
+        }
+        let now = self.clock.network_time_ms();
+        if !self.identity_manager.is_admin(
+            &CausalContext::global(),
+            conversation_id,
+            &self.self_pk,
+            &self.self_logical_pk,
+            now,
+            u64::MAX,
+        ) {
+            return Err(MerkleToxError::NotAuthorized);
+        }
+
+        // Keys the device published are known to the attacker.
+        self.peer_announcements.remove(&device_pk);
+        self.peer_ephemeral_signing_keys
+            .retain(|(pk, _), _| *pk != device_pk);
+
+        let mut effects = self.author_node(
+            conversation_id,
+            Content::Control(ControlAction::RevokeDevice {
+                target_device_pk: device_pk,
+                reason: COMPROMISE_REASON.to_string(),
+            }),
+            Vec::new(),
+            store,
+        )?;
+        let revocation = effects
+            .iter()
+            .find_map(|e| match e {
+                Effect::WriteStore(_, node, _)
+                    if matches!(
+                        node.content,
+                        Content::Control(ControlAction::RevokeDevice { .. })
+                    ) =>
+                {
+                    Some(node.hash())
+                }
+                _ => None,
+            })
+            .ok_or_else(|| MerkleToxError::Other("RevokeDevice was not authored".to_string()))?;
+
+        // Authoring the revocation rotates the key as a side effect; rotate
+        // here if that did not happen.
+        let rotated = effects
+            .iter()
+            .any(|e| matches!(e, Effect::EmitEvent(NodeEvent::EpochRotated { .. })));
+        if !rotated {
+            effects.extend(self.rotate_conversation_key_for(
+                conversation_id,
+                RotationTrigger::Revocation,
+                store,
+            )?);
+        }
+
+        let rewrapped = effects
+            .iter()
+            .filter_map(|e| match e {
+                Effect::WriteStore(_, node, _) => match &node.content {
+                    Content::KeyWrap { wrapped_keys, .. } => Some(wrapped_keys),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .flatten()
+            .map(|w| w.recipient_pk)
+            .collect();
+        let recovery = CompromiseRecovery {
+            conversation_id,
+            device_pk,
+            revocation,
+            epoch: self.get_current_generation(&conversation_id) as u64,
+            rewrapped,
+        };
+        info!(
+            "Recovered {:?} from compromise of {:?}: epoch {}, {} devices re-keyed",
+            conversation_id,
+            device_pk,
+            recovery.epoch,
+            recovery.rewrapped.len()
+        );
+        effects.push(Effect::EmitEvent(NodeEvent::CompromiseRecovered(recovery)));
+        Ok(effects)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::NodeEvent;
+use crate::dag::{Content, ConversationId, NodeHash};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::sync::NodeStore;
+use std::time::Duration;
+use tox_proto::time::Instant;
+use tracing::{debug, warn};
+
+/// How often the engine looks for expired messages.
+pub const RETENTION_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of nodes examined per conversation in one sweep. Larger
+/// backlogs are worked off over consecutive polls.
+pub const RETENTION_SWEEP_BATCH: usize = 256;
+
+/// Reason given in Redactions authored for expired messages.
+pub const RETENTION_REDACTION_REASON: &str = "expired";
+
+/// What the expiry sweep does with messages past the retention window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetentionMode {
+    /// Delete expired payloads from the local store only.
+    #[default]
+    Prune,
+    /// Additionally author a Redaction for expired messages of our own
+    /// identity, so peers that do not enforce retention hide them as well.
+    Redact,
+}
+
+/// Retention window of a conversation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// 0 keeps history forever.
+    pub max_age_ms: u64,
+    /// Rank and hash of the SetRetention node. Concurrent updates resolve to
+    /// the highest pair.
+    pub rank: u64,
+    pub hash: NodeHash,
+}
+
+/// Message content that expires. Control, key, history and sticker pack
+/// nodes are kept because the conversation state depends on them.
+pub fn is_expirable(content: &Content) -> bool {
+    matches!(
+        content,
+        Content::Text(_)
+            | Content::Blob { .. }
+            | Content::Voice { .. }
+            | Content::Poll { .. }
+            | Content::PollVote { .. }
+            | Content::PollClose { .. }
+            | Content::Location { .. }
+            | Content::Edit { .. }
+            | Content::Reaction { .. }
+            | Content::Redaction { .. }
+            | Content::LegacyBridge { .. }
+            | Content::Custom { .. }
+    )
+}
+
+impl MerkleToxEngine {
+    /// Records a verified SetRetention node. Returns false if a concurrent
+    /// update with a higher rank or hash is already in effect.
+    pub(crate) fn apply_retention(
+        &mut self,
+        conversation_id: ConversationId,
+        max_age_ms: u64,
+        rank: u64,
+        hash: NodeHash,
+    ) -> bool {
+        if let Some(current) = self.retention_policies.get(&conversation_id)
+            && (current.rank, current.hash) >= (rank, hash)
+        {
+            return false;
+        }
+        debug!(
+            "Retention for {:?} set to {} ms",
+            conversation_id, max_age_ms
+        );
+        self.retention_policies.insert(
+            conversation_id,
+            RetentionPolicy {
+                max_age_ms,
+                rank,
+                hash,
+            },
+        );
+        self.retention_cursors.remove(&conversation_id);
+        // Apply a shortened window right away.
+        self.last_retention_sweep = None;
+        true
+    }
+
+    /// Returns the retention window of a conversation in milliseconds, or
+    /// None if history is kept forever.
+    pub fn retention(&self, conversation_id: &ConversationId) -> Option<u64> {
+        self.retention_policies
+            .get(conversation_id)
+            .map(|p| p.max_age_ms)
+            .filter(|&ms| ms > 0)
+    }
+
+    /// Prunes (and in `RetentionMode::Redact`, redacts) messages older than
+    /// the retention window. Returns the effects and the time of the next
+    /// sweep, if any conversation has a retention window.
+    pub(crate) fn poll_retention(
+        &mut self,
+        now: Instant,
+        now_ms: i64,
+        store: &dyn NodeStore,
+    ) -> (Vec<Effect>, Option<Instant>) {
+        let policies: Vec<(ConversationId, u64)> = self
+            .retention_policies
+            .iter()
+            .filter(|(_, p)| p.max_age_ms > 0)
+            .map(|(cid, p)| (*cid, p.max_age_ms))
+            .collect();
+        if policies.is_empty() {
+            return (Vec::new(), None);
+        }
+
+        if self.retention_cursors.is_empty()
+            && let Some(last) = self.last_retention_sweep
+            && now < last + RETENTION_SWEEP_INTERVAL
+        {
+            return (Vec::new(), Some(last + RETENTION_SWEEP_INTERVAL));
+        }
+        self.last_retention_sweep = Some(now);
+
+        let mut effects = Vec::new();
+        for (conversation_id, max_age_ms) in policies {
+            let cutoff = now_ms.saturating_sub(max_age_ms.min(i64::MAX as u64) as i64);
+            let from_ms = self
+                .retention_cursors
+                .remove(&conversation_id)
+                .unwrap_or(i64::MIN);
+            let nodes = match store.get_nodes_by_time_range(
+                &conversation_id,
+                from_ms,
+                cutoff,
+                RETENTION_SWEEP_BATCH,
+            ) {
+                Ok(nodes) => nodes,
+                Err(e) => {
+                    warn!("Retention sweep failed for {:?}: {}", conversation_id, e);
+                    continue;
+                }
+            };
+            if nodes.len() == RETENTION_SWEEP_BATCH
+                && let Some(last) = nodes.last()
+            {
+                // Step past a batch that shares a single timestamp.
+                let next = if last.network_timestamp == from_ms {
+                    from_ms + 1
+                } else {
+                    last.network_timestamp
+                };
+                self.retention_cursors.insert(conversation_id, next);
+            }
+
+            for node in nodes {
+                if !is_expirable(&node.content) {
+                    continue;
+                }
+                let hash = node.hash();
+                if !self
+                    .retention_expired
+                    .entry(conversation_id)
+                    .or_default()
+                    .insert(hash)
+                {
+                    continue;
+                }
+                if self.retention_mode == RetentionMode::Redact
+                    && node.author_pk == self.self_logical_pk
+                    && !matches!(node.content, Content::Redaction { .. })
+                {
+                    let redaction = Content::Redaction {
+                        target_hash: hash,
+                        reason: RETENTION_REDACTION_REASON.to_string(),
+                    };
+                    match self.author_node(conversation_id, redaction, Vec::new(), store) {
+                        Ok(fx) => effects.extend(fx),
+                        Err(e) => warn!("Failed to redact expired node: {}", e),
+                    }
+                }
+                effects.push(Effect::PruneNode(conversation_id, hash));
+                effects.push(Effect::EmitEvent(NodeEvent::NodeExpired {
+                    conversation_id,
+                    hash,
+                }));
+            }
+        }
+
+        let next = if self.retention_cursors.is_empty() {
+            now + RETENTION_SWEEP_INTERVAL
+        } else {
+            now
+        };
+        (effects, Some(next))
+    }
+}
@@ -0,0 +1,107 @@
+use crate::dag::{Content, ConversationId};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::sync::NodeStore;
+use rand::RngCore;
+use std::time::Duration;
+use tox_proto::ToxProto;
+use tox_proto::time::Instant;
+use tracing::{debug, warn};
+
+/// Message held back locally until the network clock reaches
+/// `deliver_at_ms`. It is not part of the DAG before it is authored.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct ScheduledMessage {
+    pub id: u64,
+    pub conversation_id: ConversationId,
+    pub deliver_at_ms: i64,
+    pub content: Content,
+    pub metadata: Vec<u8>,
+}
+
+impl MerkleToxEngine {
+    /// Schedules `content` to be authored once the network time passes
+    /// `deliver_at_ms`. Returns the id used to cancel it and the effect
+    /// persisting it.
+    pub fn schedule_message(
+        &mut self,
+        conversation_id: ConversationId,
+        content: Content,
+        metadata: Vec<u8>,
+        deliver_at_ms: i64,
+    ) -> (u64, Vec<Effect>) {
+        let mut id = self.rng.lock().next_u64();
+        while self.scheduled_messages.contains_key(&id) {
+            id = self.rng.lock().next_u64();
+        }
+        let msg = ScheduledMessage {
+            id,
+            conversation_id,
+            deliver_at_ms,
+            content,
+            metadata,
+        };
+        self.scheduled_messages.insert(id, msg.clone());
+        (id, vec![Effect::WriteScheduledMessage(msg)])
+    }
+
+    /// Cancels a scheduled message that has not been delivered yet. Returns
+    /// no effects if the id is unknown or the message was already sent.
+    pub fn cancel_scheduled_message(&mut self, id: u64) -> Vec<Effect> {
+        match self.scheduled_messages.remove(&id) {
+            Some(_) => vec![Effect::DeleteScheduledMessage(id)],
+            None => Vec::new(),
+        }
+    }
+
+    /// Loads messages scheduled by a previous run. Returns how many were
+    /// loaded.
+    pub fn load_scheduled_messages(&mut self, store: &dyn NodeStore) -> usize {
+        let mut loaded = 0;
+        for msg in store.get_scheduled_messages() {
+            if self.scheduled_messages.insert(msg.id, msg).is_none() {
+                loaded += 1;
+            }
+        }
+        loaded
+    }
+
+    /// Authors every scheduled message that is due. Returns the effects and
+    /// the time the next message becomes due, if any.
+    pub(crate) fn poll_scheduled_messages(
+        &mut self,
+        now: Instant,
+        now_ms: i64,
+        store: &dyn NodeStore,
+    ) -> (Vec<Effect>, Option<Instant>) {
+        let due: Vec<u64> = self
+            .scheduled_messages
+            .values()
+            .filter(|m| m.deliver_at_ms <= now_ms)
+            .map(|m| m.id)
+            .collect();
+
+        let mut effects = Vec::new();
+        for id in due {
+            let Some(msg) = self.scheduled_messages.remove(&id) else {
+                continue;
+            };
+            match self.author_node(msg.conversation_id, msg.content, msg.metadata, store) {
+                Ok(fx) => {
+                    debug!("Delivered scheduled message {}", id);
+                    effects.extend(fx);
+                }
+                // Not retried: the conversation state that made authoring fail
+                // (e.g. observer mode) rarely resolves by itself.
+                Err(e) => warn!("Dropping scheduled message {}: {}", id, e),
+            }
+            effects.push(Effect::DeleteScheduledMessage(id));
+        }
+
+        let next = self
+            .scheduled_messages
+            .values()
+            .map(|m| now + Duration::from_millis((m.deliver_at_ms - now_ms).max(0) as u64))
+            .min();
+        (effects, next)
+    }
+}
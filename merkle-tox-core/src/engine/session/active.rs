@@ -1,12 +1,15 @@
+use crate::ProtocolMessage;
 use crate::dag::{LogicalIdentityPk, MerkleNode, NodeHash, PhysicalDevicePk, PowNonce, ShardHash};
 use crate::engine::session::SyncSession;
 use crate::error::{MerkleToxError, MerkleToxResult};
 use crate::sync::{
-    BlobStore, DecodingResult, FetchBatchReq, NodeStore, SyncHeads, SyncRange, Tier,
+    BlobStore, DecodingResult, FEATURE_DELTA_HEADS, FEATURE_READ_ONLY, FLAG_HEADS_RESYNC,
+    FetchBatchReq, NodeStore, SyncHeads, SyncHeadsDelta, SyncRange, Tier,
 };
 use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::constants::MAX_HEADS_SYNC;
+use tox_proto::time::Instant;
 use tox_reconcile::IbltSketch;
 use tracing::{debug, info};
 
@@ -22,7 +25,7 @@ impl SyncSession<Active> {
                 .iblt_tiers
                 .get(range)
                 .copied()
-                .unwrap_or(Tier::Small),
+                .unwrap_or(self.common.start_tier),
         )
     }
 
@@ -31,13 +34,9 @@ impl SyncSession<Active> {
             return;
         }
 
-        let current = self.get_iblt_tier(range).unwrap_or(Tier::Small);
-        let next = match current {
-            Tier::Tiny => Some(Tier::Small),
-            Tier::Small => Some(Tier::Medium),
-            Tier::Medium => Some(Tier::Large),
-            Tier::Large => None, // Exhausted
-        };
+        let current = self.get_iblt_tier(range).unwrap_or(self.common.start_tier);
+        // Exhausted once the largest allowed tier has failed.
+        let next = current.next().filter(|t| *t <= self.common.max_tier);
 
         if let Some(n) = next {
             self.common.iblt_tiers.insert(range.clone(), n);
@@ -63,6 +62,10 @@ impl SyncSession<Active> {
     ) {
         self.common.in_flight_fetches.remove(&hash);
 
+        if self.past_horizon(wire.topological_rank, &wire.parents, store) {
+            return;
+        }
+
         for parent in &wire.parents {
             if !store.has_node(parent)
                 && !self.common.missing_nodes_hot.contains(parent)
@@ -75,6 +78,23 @@ impl SyncSession<Active> {
         }
     }
 
+    /// Advances the sync horizon to `rank` and returns whether a node at
+    /// `rank` lies at or below it, in which case its parents are not
+    /// fetched. Always false without a rank window.
+    fn past_horizon(&mut self, rank: u64, parents: &[NodeHash], store: &dyn NodeStore) -> bool {
+        let Some(window) = self.common.rank_window else {
+            return false;
+        };
+        self.common.min_rank = self.common.min_rank.max(rank.saturating_sub(window));
+        if rank > self.common.min_rank {
+            return false;
+        }
+        if parents.iter().any(|p| !store.has_node(p)) {
+            self.common.truncated_at = Some(self.common.truncated_at.map_or(rank, |r| r.min(rank)));
+        }
+        true
+    }
+
     /// Enqueues a missing hash into hot or cold queue based on rank relative to max head.
     pub fn enqueue_missing(
         &mut self,
@@ -112,24 +132,71 @@ impl SyncSession<Active> {
             return;
         }
 
+        if heads.flags & FLAG_HEADS_RESYNC != 0 {
+            self.common.announced_heads = None;
+            self.common.heads_dirty = true;
+        }
+
         if let Some(anchor) = heads.anchor_hash {
             self.common.remote_anchor_hash = Some(anchor);
         }
 
         for head in heads.heads {
-            let known = self.common.remote_heads.contains(&head);
-            if !known {
-                self.common.remote_heads.insert(head);
+            self.add_remote_head(head, store);
+        }
+    }
+
+    /// Applies a head delta from the peer. Returns false if the delta does
+    /// not follow the last applied announcement and a full head set should
+    /// be requested with `FLAG_HEADS_RESYNC`.
+    pub fn handle_sync_heads_delta(
+        &mut self,
+        delta: SyncHeadsDelta,
+        store: &dyn NodeStore,
+    ) -> bool {
+        if delta.conversation_id != self.conversation_id {
+            return true;
+        }
+
+        match delta.base_version {
+            // Full head set: supersedes everything up to its version.
+            None if delta.version > self.common.remote_heads_version => {
+                self.common.heads_resync_requested = false;
             }
-            if !store.has_node(&head)
-                && !self.common.missing_nodes_hot.contains(&head)
-                && !self.common.missing_nodes_cold.contains(&head)
-                && !self.common.in_flight_fetches.contains(&head)
-            {
-                // Heads are tips (always hot).
-                self.common.missing_nodes_hot.push_back(head);
+            Some(base) if base == self.common.remote_heads_version => {}
+            // Already covered by a later announcement.
+            None => return true,
+            Some(base) if base < self.common.remote_heads_version => return true,
+            Some(_) => {
+                let first_gap = !self.common.heads_resync_requested;
+                self.common.heads_resync_requested = true;
+                return !first_gap;
             }
         }
+        self.common.remote_heads_version = delta.version;
+
+        if let Some(anchor) = delta.anchor_hash {
+            self.common.remote_anchor_hash = Some(anchor);
+        }
+        for head in &delta.removed {
+            self.common.remote_heads.remove(head);
+        }
+        for head in delta.added {
+            self.add_remote_head(head, store);
+        }
+        true
+    }
+
+    fn add_remote_head(&mut self, head: NodeHash, store: &dyn NodeStore) {
+        self.common.remote_heads.insert(head);
+        if !store.has_node(&head)
+            && !self.common.missing_nodes_hot.contains(&head)
+            && !self.common.missing_nodes_cold.contains(&head)
+            && !self.common.in_flight_fetches.contains(&head)
+        {
+            // Heads are tips (always hot).
+            self.common.missing_nodes_hot.push_back(head);
+        }
     }
 
     pub fn handle_sync_sketch(
@@ -260,13 +327,20 @@ impl SyncSession<Active> {
         }
         self.common.heads_dirty = true;
 
-        if let crate::dag::Content::Blob { hash, .. } = &node.content
+        if let Some(hash) = node.content.blob_hash()
             && let Some(bs) = blob_store
             && !bs.has_blob(hash)
         {
             self.common.missing_blobs.insert(*hash);
         }
 
+        // Recent-history policy: the horizon follows the highest rank seen.
+        // Admin nodes are always followed so authorship stays verifiable.
+        let is_admin = node.node_type() == crate::dag::NodeType::Admin;
+        if !is_admin && self.past_horizon(node.topological_rank, &node.parents, store) {
+            return;
+        }
+
         if matches!(
             &node.content,
             crate::dag::Content::Control(
@@ -286,7 +360,6 @@ impl SyncSession<Active> {
         }
 
         // Backfill count check for "last N messages" shallow sync
-        let is_admin = node.node_type() == crate::dag::NodeType::Admin;
         if self.common.shallow && self.common.max_backfill_nodes > 0 {
             if !is_admin {
                 self.common.backfill_count += 1;
@@ -336,6 +409,92 @@ impl SyncSession<Active> {
         }
     }
 
+    /// Whether head announcements to this peer may be delta-encoded.
+    pub fn supports_delta_heads(&self) -> bool {
+        self.common.negotiated.has(FEATURE_DELTA_HEADS)
+    }
+
+    /// Whether the peer announced itself as a read-only observer.
+    pub fn peer_is_read_only(&self) -> bool {
+        self.common.negotiated.has(FEATURE_READ_ONLY)
+    }
+
+    /// Full `SyncHeads` sent on activation. Later deltas are computed
+    /// against the heads it carries.
+    pub fn make_initial_sync_heads(&mut self, store: &dyn NodeStore) -> SyncHeads {
+        let heads = self.make_sync_heads_with_store(0, Some(store));
+        self.common.announced_heads = Some(heads.heads.iter().cloned().collect());
+        self.common.announced_version = 0;
+        self.common.heads_dirty = false;
+        heads
+    }
+
+    /// Next head delta for this peer, or `None` if the peer does not support
+    /// deltas or already knows our heads. Records the delta as announced.
+    pub fn make_sync_heads_delta(&mut self, store: &dyn NodeStore) -> Option<SyncHeadsDelta> {
+        if !self.supports_delta_heads() {
+            return None;
+        }
+        let (base_version, mut added, removed) = match &self.common.announced_heads {
+            Some(announced) => {
+                let added: Vec<NodeHash> = self
+                    .common
+                    .local_heads
+                    .difference(announced)
+                    .cloned()
+                    .collect();
+                let removed: Vec<NodeHash> = announced
+                    .difference(&self.common.local_heads)
+                    .cloned()
+                    .collect();
+                if added.is_empty() && removed.is_empty() {
+                    return None;
+                }
+                (Some(self.common.announced_version), added, removed)
+            }
+            None => (
+                None,
+                self.common.local_heads.iter().cloned().collect(),
+                Vec::new(),
+            ),
+        };
+        added.truncate(MAX_HEADS_SYNC);
+
+        let mut announced = self.common.announced_heads.take().unwrap_or_default();
+        for head in &removed {
+            announced.remove(head);
+        }
+        announced.extend(added.iter().cloned());
+        self.common.announced_heads = Some(announced);
+        self.common.announced_version += 1;
+
+        Some(SyncHeadsDelta {
+            conversation_id: self.conversation_id,
+            base_version,
+            version: self.common.announced_version,
+            added,
+            removed,
+            anchor_hash: store
+                .get_admin_heads(&self.conversation_id)
+                .first()
+                .cloned(),
+        })
+    }
+
+    /// Head announcement for a dirty session: a delta if negotiated (nothing
+    /// if the heads did not change), otherwise the full `SyncHeads`.
+    pub fn make_heads_announcement(&mut self, store: &dyn NodeStore) -> Option<ProtocolMessage> {
+        self.common.heads_dirty = false;
+        if self.supports_delta_heads() {
+            self.make_sync_heads_delta(store)
+                .map(ProtocolMessage::SyncHeadsDelta)
+        } else {
+            Some(ProtocolMessage::SyncHeads(
+                self.make_sync_heads_with_store(0, Some(store)),
+            ))
+        }
+    }
+
     pub fn make_sync_sketch(
         &self,
         range: SyncRange,
@@ -377,7 +536,12 @@ impl SyncSession<Active> {
             .max()
             .unwrap_or(0);
 
-        for start_rank in (0..=max_rank).step_by(crate::sync::SHARD_SIZE as usize) {
+        // Shards entirely below the sync horizon are not advertised.
+        let first_shard = match self.common.rank_window {
+            Some(_) => self.common.min_rank - self.common.min_rank % crate::sync::SHARD_SIZE,
+            None => 0,
+        };
+        for start_rank in (first_shard..=max_rank).step_by(crate::sync::SHARD_SIZE as usize) {
             let range = SyncRange {
                 min_rank: start_rank,
                 max_rank: start_rank + crate::sync::SHARD_SIZE - 1,
@@ -403,10 +567,18 @@ impl SyncSession<Active> {
         let local_map: HashMap<_, _> = local_shards.into_iter().collect();
 
         for (range, remote_checksum) in remote_shards {
-            if let Some(local_checksum) = local_map.get(&range) {
-                if local_checksum != &remote_checksum {
-                    different_shards.push(range);
+            if local_map.get(&range) == Some(&remote_checksum) {
+                continue;
+            }
+            if self.common.rank_window.is_some() {
+                // Only reconcile the part of the shard inside the horizon.
+                if range.max_rank < self.common.min_rank {
+                    continue;
                 }
+                different_shards.push(SyncRange {
+                    min_rank: range.min_rank.max(self.common.min_rank),
+                    ..range
+                });
             } else {
                 different_shards.push(range);
             }
@@ -414,18 +586,20 @@ impl SyncSession<Active> {
         Ok(different_shards)
     }
 
-    pub fn next_wakeup(&self, now: Instant) -> Instant {
-        let mut wakeup = now + Duration::from_secs(3600);
-
-        let has_fetchable_missing = self
-            .common
+    /// Whether some missing node is not yet requested.
+    pub fn has_fetchable_missing(&self) -> bool {
+        self.common
             .missing_admin_nodes
             .iter()
             .chain(self.common.missing_nodes_hot.iter())
             .chain(self.common.missing_nodes_cold.iter())
-            .any(|h| !self.common.in_flight_fetches.contains(h));
+            .any(|h| !self.common.in_flight_fetches.contains(h))
+    }
+
+    pub fn next_wakeup(&self, now: Instant) -> Instant {
+        let mut wakeup = now + Duration::from_secs(3600);
 
-        if self.common.heads_dirty || self.common.recon_dirty || has_fetchable_missing {
+        if self.common.heads_dirty || self.common.recon_dirty || self.has_fetchable_missing() {
             wakeup = now;
         }
 
@@ -464,6 +638,31 @@ impl SyncSession<Active> {
         nonce
     }
 
+    /// Like `generate_challenge`, but demands `difficulty` leading zero bits
+    /// if that is more than the session's `effective_difficulty`.
+    pub fn generate_challenge_with_difficulty(
+        &mut self,
+        sketch: tox_reconcile::SyncSketch,
+        difficulty: u32,
+        now: Instant,
+        rng: &mut rand::rngs::StdRng,
+    ) -> PowNonce {
+        let nonce = self.generate_challenge(sketch, now, rng);
+        if difficulty > self.common.effective_difficulty {
+            self.common.challenge_difficulties.insert(nonce, difficulty);
+        }
+        nonce
+    }
+
+    /// Difficulty a solution for `nonce` must meet.
+    pub fn challenge_difficulty(&self, nonce: &PowNonce) -> u32 {
+        self.common
+            .challenge_difficulties
+            .get(nonce)
+            .copied()
+            .unwrap_or(self.common.effective_difficulty)
+    }
+
     pub fn take_pending_sketch(&mut self, nonce: PowNonce) -> Option<tox_reconcile::SyncSketch> {
         self.common.pending_sketches.remove(&nonce)
     }
@@ -472,30 +671,17 @@ impl SyncSession<Active> {
         if let Some(expiry) = self.common.pending_challenges.get(&nonce) {
             if *expiry < now {
                 self.common.pending_challenges.remove(&nonce);
+                self.common.challenge_difficulties.remove(&nonce);
                 return false;
             }
         } else {
             return false;
         }
 
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(nonce.as_bytes());
-        hasher.update(&solution.to_le_bytes());
-        let hash = hasher.finalize();
-
-        let mut leading_zeros = 0;
-        for &byte in hash.as_bytes().iter() {
-            if byte == 0 {
-                leading_zeros += 8;
-            } else {
-                leading_zeros += byte.leading_zeros();
-                break;
-            }
-        }
-
-        let success = leading_zeros >= self.common.effective_difficulty;
+        let success = pow_leading_zeros(nonce, solution) >= self.challenge_difficulty(&nonce);
         if success {
             self.common.pending_challenges.remove(&nonce);
+            self.common.challenge_difficulties.remove(&nonce);
         }
         success
     }
@@ -551,27 +737,29 @@ impl SyncSession<Active> {
     }
 }
 
-pub fn solve_challenge(nonce: PowNonce, difficulty: u32) -> u64 {
-    let mut solution = 0u64;
-    loop {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(nonce.as_bytes());
-        hasher.update(&solution.to_le_bytes());
-        let hash = hasher.finalize();
-
-        let mut leading_zeros = 0;
-        for &byte in hash.as_bytes().iter() {
-            if byte == 0 {
-                leading_zeros += 8;
-            } else {
-                leading_zeros += byte.leading_zeros();
-                break;
-            }
+/// Number of leading zero bits of `blake3(nonce || solution)`.
+pub fn pow_leading_zeros(nonce: PowNonce, solution: u64) -> u32 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(nonce.as_bytes());
+    hasher.update(&solution.to_le_bytes());
+    let hash = hasher.finalize();
+
+    let mut leading_zeros = 0;
+    for &byte in hash.as_bytes().iter() {
+        if byte == 0 {
+            leading_zeros += 8;
+        } else {
+            leading_zeros += byte.leading_zeros();
+            break;
         }
+    }
+    leading_zeros
+}
 
-        if leading_zeros >= difficulty {
-            return solution;
-        }
+pub fn solve_challenge(nonce: PowNonce, difficulty: u32) -> u64 {
+    let mut solution = 0u64;
+    while pow_leading_zeros(nonce, solution) < difficulty {
         solution += 1;
     }
+    solution
 }
@@ -1,9 +1,11 @@
+use crate::capabilities::Negotiated;
 use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk};
 use crate::engine::session::{SessionCommon, SyncSession};
 use crate::sync::{NodeStore, SyncHeads};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::constants::MAX_HEADS_SYNC;
+use tox_proto::time::Instant;
 
 pub struct Handshake;
 
@@ -29,22 +31,32 @@ impl SyncSession<Handshake> {
                 missing_nodes_cold: VecDeque::new(),
                 in_flight_fetches: HashSet::new(),
                 missing_blobs: HashSet::new(),
-                peer_features: 0,
+                negotiated: Negotiated::BASELINE,
                 time_samples: Vec::new(),
                 vouchers: HashMap::new(),
                 iblt_tiers: HashMap::new(),
+                start_tier: crate::sync::Tier::Small,
+                max_tier: crate::sync::Tier::Large,
                 exhausted_iblt_ranges: HashSet::new(),
                 heads_dirty: true,
+                announced_heads: None,
+                announced_version: 0,
+                remote_heads_version: 0,
+                heads_resync_requested: false,
                 recon_dirty: true,
                 last_recon_time: now,
                 effective_difficulty: crate::sync::DEFAULT_RECON_DIFFICULTY,
                 difficulty_votes: HashMap::new(),
                 pending_challenges: HashMap::new(),
                 pending_sketches: HashMap::new(),
+                challenge_difficulties: HashMap::new(),
                 rate_limited_until: None,
                 max_backfill_nodes: 0,
                 backfill_count: 0,
                 remote_anchor_hash: None,
+                rank_window: None,
+                truncated_at: None,
+                recon_rounds: 0,
             },
             state: Handshake,
         }
@@ -110,9 +122,21 @@ impl SyncSession<Handshake> {
         }
     }
 
+    /// Activates the session with the baseline version and `features`.
     pub fn activate(self, features: u64) -> SyncSession<crate::engine::session::active::Active> {
+        self.activate_with(Negotiated {
+            features,
+            ..Negotiated::BASELINE
+        })
+    }
+
+    /// Activates the session with the result of the capability handshake.
+    pub fn activate_with(
+        self,
+        negotiated: Negotiated,
+    ) -> SyncSession<crate::engine::session::active::Active> {
         let mut common = self.common;
-        common.peer_features = features;
+        common.negotiated = negotiated;
         SyncSession {
             conversation_id: self.conversation_id,
             common,
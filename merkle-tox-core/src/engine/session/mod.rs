@@ -1,7 +1,8 @@
+use crate::capabilities::Negotiated;
 use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk, PowNonce};
 use crate::sync::{SyncRange, Tier};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::time::Instant;
+use tox_proto::time::Instant;
 
 pub mod active;
 pub mod handshake;
@@ -24,18 +25,34 @@ pub struct SessionCommon {
     pub missing_nodes_cold: VecDeque<NodeHash>,
     pub in_flight_fetches: HashSet<NodeHash>,
     pub missing_blobs: HashSet<NodeHash>,
-    pub peer_features: u64,
+    /// Version and features agreed on in the handshake.
+    pub negotiated: Negotiated,
     pub time_samples: Vec<i64>,
     pub vouchers: HashMap<NodeHash, HashSet<PhysicalDevicePk>>,
     pub iblt_tiers: HashMap<SyncRange, Tier>,
+    /// Tier of the first sketch for a range not in `iblt_tiers`.
+    pub start_tier: Tier,
+    /// Largest tier tried before a range is marked exhausted.
+    pub max_tier: Tier,
     pub exhausted_iblt_ranges: HashSet<SyncRange>,
     pub heads_dirty: bool,
+    /// Heads the peer last heard from us. `None` forces the next delta
+    /// announcement to carry the complete head set.
+    pub announced_heads: Option<HashSet<NodeHash>>,
+    /// Version of our last head announcement to this peer.
+    pub announced_version: u32,
+    /// Version of the peer's head announcements reflected in `remote_heads`.
+    pub remote_heads_version: u32,
+    /// A full head set was requested after a delta could not be applied.
+    pub heads_resync_requested: bool,
     pub recon_dirty: bool,
     pub last_recon_time: Instant,
     pub effective_difficulty: u32,
     pub difficulty_votes: HashMap<PhysicalDevicePk, u32>,
     pub pending_challenges: HashMap<PowNonce, Instant>,
     pub pending_sketches: HashMap<PowNonce, tox_reconcile::SyncSketch>,
+    /// Difficulty of challenges issued above `effective_difficulty`.
+    pub challenge_difficulties: HashMap<PowNonce, u32>,
     /// When set, recon/sketch activity with this peer is paused until this instant.
     pub rate_limited_until: Option<Instant>,
     /// When > 0, limit backfill to this many content nodes from heads.
@@ -44,6 +61,15 @@ pub struct SessionCommon {
     pub backfill_count: u64,
     /// Earliest admin head advertised by remote peer (for divergence detection).
     pub remote_anchor_hash: Option<NodeHash>,
+    /// Sync horizon from the conversation's `SyncPolicy::Recent`. Content
+    /// more than this many ranks below the highest received rank is not
+    /// fetched, and `min_rank` follows the horizon.
+    pub rank_window: Option<u64>,
+    /// Lowest rank of a node whose parents were skipped because of the
+    /// horizon. Drained by the engine into the store's history marker.
+    pub truncated_at: Option<u64>,
+    /// Shard checksum rounds started with this peer.
+    pub recon_rounds: u64,
 }
 
 pub struct SyncSession<S> {
@@ -0,0 +1,336 @@
+//! Passphrase-protected backup of conversation key material.
+//!
+//! A vault holds the epoch keys, sender keys, ratchet heads and ephemeral
+//! signing keys of every established conversation, so history stays readable
+//! after the device that received them is lost. The blob is opaque and can be
+//! stored anywhere, e.g. as a blob on a vaultbot.
+//!
+//! Layout: `[magic "MTXK"][version u8][salt 16][nonce 24][ciphertext]`. The
+//! plaintext is a ToxProto-encoded [`KeyVault`], sealed with
+//! XChaCha20-Poly1305 under a key derived from the passphrase with Argon2id.
+//! The header is bound as associated data. The Argon2id cost is fixed per
+//! vault version, so a vault opens regardless of the argon2 crate's defaults.
+
+use crate::dag::{
+    ChainKey, ConversationId, EphemeralSigningPk, KConv, NodeHash, PhysicalDevicePk, SenderKey,
+};
+use crate::engine::conversation::{self, ConversationData};
+use crate::engine::{Conversation, Effect, MerkleToxEngine};
+use crate::error::{MerkleToxError, MerkleToxResult};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use tox_proto::ToxProto;
+use tracing::info;
+use zeroize::Zeroizing;
+
+const VAULT_MAGIC: &[u8; 4] = b"MTXK";
+const VAULT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = VAULT_MAGIC.len() + 1 + SALT_LEN + NONCE_LEN;
+
+// Argon2id cost of version 1 vaults. Changing these requires a new version.
+const KDF_M_COST_KIB: u32 = 19 * 1024;
+const KDF_T_COST: u32 = 2;
+const KDF_P_COST: u32 = 1;
+
+/// MIME type of key vaults shared as blobs.
+pub const KEY_VAULT_MIME_TYPE: &str = "application/x-merkle-tox-key-vault";
+
+/// Ratchet head of one sender.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct VaultRatchet {
+    pub sender_pk: PhysicalDevicePk,
+    pub epoch: u64,
+    pub last_seq: u64,
+    pub next_chain_key: ChainKey,
+    pub last_node_hash: Option<NodeHash>,
+}
+
+/// Key material of one conversation.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct VaultConversation {
+    pub conversation_id: ConversationId,
+    pub current_epoch: u64,
+    pub epochs: Vec<(u64, KConv)>,
+    pub sender_keys: Vec<(PhysicalDevicePk, u64, SenderKey)>,
+    pub ratchets: Vec<VaultRatchet>,
+    /// Ephemeral signing keys needed to verify content nodes, including
+    /// the ones of the exporting device.
+    pub signing_keys: Vec<(PhysicalDevicePk, u64, EphemeralSigningPk)>,
+}
+
+/// Decrypted contents of a key vault.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct KeyVault {
+    pub device_pk: PhysicalDevicePk,
+    pub created_at_ms: i64,
+    pub conversations: Vec<VaultConversation>,
+}
+
+fn vault_cipher(passphrase: &[u8], salt: &[u8]) -> MerkleToxResult<XChaCha20Poly1305> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    let params = argon2::Params::new(KDF_M_COST_KIB, KDF_T_COST, KDF_P_COST, Some(32))
+        .map_err(|e| MerkleToxError::Crypto(e.to_string()))?;
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+        .hash_password_into(passphrase, salt, key.as_mut())
+        .map_err(|e| MerkleToxError::Crypto(e.to_string()))?;
+    Ok(XChaCha20Poly1305::new(key.as_ref().into()))
+}
+
+/// Encrypts `vault` under `passphrase`. `salt` and `nonce` must be random.
+pub fn seal_vault(
+    vault: &KeyVault,
+    passphrase: &[u8],
+    salt: [u8; SALT_LEN],
+    nonce: [u8; NONCE_LEN],
+) -> MerkleToxResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(HEADER_LEN);
+    out.extend_from_slice(VAULT_MAGIC);
+    out.push(VAULT_VERSION);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+
+    let plaintext = Zeroizing::new(tox_proto::serialize(vault)?);
+    let ciphertext = vault_cipher(passphrase, &salt)?
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &out,
+            },
+        )
+        .map_err(|_| MerkleToxError::Crypto("Vault encryption failed".to_string()))?;
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Decrypts a vault produced by [`seal_vault`]. Fails with
+/// `MerkleToxError::Crypto` on a wrong passphrase or a corrupted blob.
+pub fn open_vault(blob: &[u8], passphrase: &[u8]) -> MerkleToxResult<KeyVault> {
+    if blob.len() < HEADER_LEN || &blob[..VAULT_MAGIC.len()] != VAULT_MAGIC {
+        return Err(MerkleToxError::Crypto("Not a key vault".to_string()));
+    }
+    if blob[VAULT_MAGIC.len()] != VAULT_VERSION {
+        return Err(MerkleToxError::Crypto(format!(
+            "Unsupported key vault version {}",
+            blob[VAULT_MAGIC.len()]
+        )));
+    }
+    let (header, ciphertext) = blob.split_at(HEADER_LEN);
+    let salt = &header[VAULT_MAGIC.len() + 1..VAULT_MAGIC.len() + 1 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let plaintext = Zeroizing::new(
+        vault_cipher(passphrase, salt)?
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: header,
+                },
+            )
+            .map_err(|_| {
+                MerkleToxError::Crypto("Wrong passphrase or corrupted key vault".to_string())
+            })?,
+    );
+    Ok(tox_proto::deserialize(&plaintext)?)
+}
+
+impl MerkleToxEngine {
+    /// Collects the key material of all established conversations.
+    pub fn key_vault(&mut self) -> KeyVault {
+        let created_at_ms = self.clock.network_time_ms();
+        let mut conversations: Vec<VaultConversation> = self
+            .conversations
+            .values()
+            .filter_map(|conv| match conv {
+                Conversation::Established(em) => Some(self.vault_conversation(em)),
+                Conversation::Pending(_) => None,
+            })
+            .collect();
+        conversations.sort_by_key(|c| c.conversation_id);
+        KeyVault {
+            device_pk: self.self_pk,
+            created_at_ms,
+            conversations,
+        }
+    }
+
+    fn vault_conversation(
+        &self,
+        em: &ConversationData<conversation::Established>,
+    ) -> VaultConversation {
+        let mut epochs: Vec<(u64, KConv)> = em
+            .state
+            .epochs
+            .iter()
+            .map(|(epoch, keys)| (*epoch, keys.k_conv.clone()))
+            .collect();
+        epochs.sort_by_key(|(epoch, _)| *epoch);
+
+        let mut sender_keys: Vec<(PhysicalDevicePk, u64, SenderKey)> = em
+            .state
+            .sender_keys
+            .iter()
+            .map(|((pk, epoch), key)| (*pk, *epoch, key.clone()))
+            .collect();
+        sender_keys.sort_by_key(|(pk, epoch, _)| (*pk, *epoch));
+
+        let mut ratchets: Vec<VaultRatchet> = em
+            .state
+            .sender_ratchets
+            .iter()
+            .map(|(pk, (last_seq, key, last_hash, epoch))| VaultRatchet {
+                sender_pk: *pk,
+                epoch: *epoch,
+                last_seq: *last_seq,
+                next_chain_key: key.clone(),
+                last_node_hash: *last_hash,
+            })
+            .collect();
+        ratchets.sort_by_key(|r| r.sender_pk);
+
+        let own_keys = self.self_ephemeral_signing_keys.iter().map(|(epoch, sk)| {
+            (
+                self.self_pk,
+                *epoch,
+                EphemeralSigningPk::from(sk.verifying_key().to_bytes()),
+            )
+        });
+        let mut signing_keys: Vec<(PhysicalDevicePk, u64, EphemeralSigningPk)> = self
+            .peer_ephemeral_signing_keys
+            .iter()
+            .filter(|((pk, epoch), _)| {
+                em.state.sender_keys.contains_key(&(*pk, *epoch))
+                    || em.state.sender_ratchets.contains_key(pk)
+            })
+            .map(|((pk, epoch), key)| (*pk, *epoch, *key))
+            .chain(own_keys)
+            .collect();
+        signing_keys.sort_by_key(|(pk, epoch, _)| (*pk, *epoch));
+
+        VaultConversation {
+            conversation_id: em.id,
+            current_epoch: em.state.current_epoch,
+            epochs,
+            sender_keys,
+            ratchets,
+            signing_keys,
+        }
+    }
+
+    /// Exports the key material of all established conversations as a
+    /// vault encrypted under `passphrase`.
+    pub fn export_key_vault(&mut self, passphrase: &[u8]) -> MerkleToxResult<Vec<u8>> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        {
+            let mut rng = self.rng.lock();
+            rng.fill_bytes(&mut salt);
+            rng.fill_bytes(&mut nonce);
+        }
+        seal_vault(&self.key_vault(), passphrase, salt, nonce)
+    }
+
+    /// Decrypts a vault and merges its key material into the engine. Keys
+    /// already known are kept. Returns the effects persisting the restored
+    /// epoch keys and ratchet heads; the latter take effect when the
+    /// conversation is next loaded from the store.
+    pub fn restore_from_vault(
+        &mut self,
+        blob: &[u8],
+        passphrase: &[u8],
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let vault = open_vault(blob, passphrase)?;
+        let mut effects = Vec::new();
+        for vc in vault.conversations {
+            effects.extend(self.restore_vault_conversation(vc));
+        }
+        Ok(effects)
+    }
+
+    fn restore_vault_conversation(&mut self, vc: VaultConversation) -> Vec<Effect> {
+        let cid = vc.conversation_id;
+        let Some((_, first_key)) = vc.epochs.first() else {
+            return Vec::new();
+        };
+        let now_ms = self.clock.network_time_ms();
+
+        let conv = self.conversations.remove(&cid);
+        let mut em = match conv {
+            Some(Conversation::Established(em)) => em,
+            Some(Conversation::Pending(p)) => {
+                let mut em = ConversationData::<conversation::Established>::new(
+                    cid,
+                    first_key.clone(),
+                    now_ms,
+                );
+                em.state.epochs.clear();
+                em.state.vouchers = p.state.vouchers;
+                em.state.genesis_flags = p.state.genesis_flags;
+                em
+            }
+            None => {
+                let mut em = ConversationData::<conversation::Established>::new(
+                    cid,
+                    first_key.clone(),
+                    now_ms,
+                );
+                em.state.epochs.clear();
+                em
+            }
+        };
+
+        let mut effects = Vec::new();
+        for (epoch, k_conv) in &vc.epochs {
+            if !em.state.epochs.contains_key(epoch) {
+                em.state
+                    .epochs
+                    .insert(*epoch, crate::crypto::ConversationKeys::derive(k_conv));
+                effects.push(Effect::WriteConversationKey(cid, *epoch, k_conv.clone()));
+            }
+        }
+        if vc.current_epoch > em.state.current_epoch
+            && em.state.epochs.contains_key(&vc.current_epoch)
+        {
+            em.state.current_epoch = vc.current_epoch;
+        }
+
+        for (pk, epoch, key) in vc.sender_keys {
+            em.state.sender_keys.entry((pk, epoch)).or_insert(key);
+        }
+
+        // Ratchet heads are only persisted. Loading them into memory would
+        // start decryption at the head and make older history unreadable,
+        // which is instead re-derived from the sender keys.
+        for r in vc.ratchets {
+            if let Some(hash) = r.last_node_hash
+                && !em.state.sender_ratchets.contains_key(&r.sender_pk)
+            {
+                effects.push(Effect::WriteRatchetKey(
+                    cid,
+                    hash,
+                    r.next_chain_key,
+                    r.epoch,
+                ));
+            }
+        }
+
+        for (pk, epoch, key) in vc.signing_keys {
+            self.peer_ephemeral_signing_keys
+                .entry((pk, epoch))
+                .or_insert(key);
+        }
+
+        info!(
+            "Restored {} epoch keys for {:?} from key vault",
+            vc.epochs.len(),
+            cid
+        );
+        self.conversations
+            .insert(cid, Conversation::Established(em));
+        effects
+    }
+}
@@ -0,0 +1,207 @@
+//! Entities of text messages: mentions, URLs and formatting, as ranges of
+//! the text.
+//!
+//! Entities travel in the metadata of Text and Edit nodes, so clients that
+//! do not know them still show the plain text. Mentions name identities, not
+//! display names, and keep working when a member is renamed.
+//!
+//! Entity lists are canonical: sorted by start, longer ranges first, and
+//! ranges either nest or do not overlap at all. The engine rejects nodes
+//! with invalid entities (see [`TextEntities::validate`]), so renderers can
+//! turn them into a tree without further checks.
+
+use crate::NodeEvent;
+use crate::dag::{
+    Content, ConversationId, LogicalIdentityPk, MerkleNode, NodeHash, ValidationError,
+};
+use crate::engine::{Effect, MerkleToxEngine};
+use tox_proto::ToxProto;
+
+#[derive(Debug, Clone, PartialEq, Eq, ToxProto)]
+pub enum EntityKind {
+    /// The range names a member, e.g. "@alice".
+    Mention(LogicalIdentityPk),
+    /// The range is a URL.
+    Url,
+    Bold,
+    Italic,
+    /// Monospace text, shown as is.
+    Code,
+    /// Hidden until the reader reveals it.
+    Spoiler,
+    /// The range links to a URL other than its text.
+    Link(String),
+}
+
+impl EntityKind {
+    /// Whether the entity may contain other entities.
+    fn is_container(&self) -> bool {
+        matches!(
+            self,
+            EntityKind::Bold | EntityKind::Italic | EntityKind::Spoiler | EntityKind::Link(_)
+        )
+    }
+
+    /// Whether readers can follow the entity; these do not nest.
+    fn is_interactive(&self) -> bool {
+        matches!(
+            self,
+            EntityKind::Mention(_) | EntityKind::Url | EntityKind::Link(_)
+        )
+    }
+}
+
+/// A range of a text message. `start` and `end` are byte offsets into the
+/// UTF-8 text.
+#[derive(Debug, Clone, PartialEq, Eq, ToxProto)]
+pub struct TextEntity {
+    pub start: u32,
+    pub end: u32,
+    pub kind: EntityKind,
+}
+
+impl TextEntity {
+    /// Whether the range is non-empty and falls on character boundaries of
+    /// `text`.
+    pub fn fits(&self, text: &str) -> bool {
+        let (start, end) = (self.start as usize, self.end as usize);
+        start < end && text.is_char_boundary(start) && text.is_char_boundary(end)
+    }
+
+    /// The part of `text` the entity covers. `text` must fit the entity.
+    pub fn slice<'a>(&self, text: &'a str) -> &'a str {
+        &text[self.start as usize..self.end as usize]
+    }
+}
+
+/// The entities of a text message, carried in its node's metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq, ToxProto)]
+pub struct TextEntities {
+    pub entities: Vec<TextEntity>,
+}
+
+impl TextEntities {
+    /// Extracts entities from node metadata.
+    pub fn from_metadata(metadata: &[u8]) -> Option<Self> {
+        tox_proto::deserialize(metadata).ok()
+    }
+
+    /// Node metadata carrying the entities; empty if there are none.
+    pub fn to_metadata(&self) -> Vec<u8> {
+        if self.entities.is_empty() {
+            return Vec::new();
+        }
+        tox_proto::serialize(self).expect("Failed to serialize text entities")
+    }
+
+    /// The entities of a Text or Edit node; none if they are invalid.
+    pub fn of(node: &MerkleNode) -> Vec<TextEntity> {
+        let Some(text) = text_of(node) else {
+            return Vec::new();
+        };
+        match Self::from_metadata(&node.metadata) {
+            Some(entities) if entities.validate(text).is_ok() => entities.entities,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Checks that the entities are canonical for `text`: each fits the
+    /// text, they are sorted by start and then by descending end, and any
+    /// two ranges are disjoint or nested. Only bold, italic, spoiler and
+    /// link entities contain others, never one of the same kind, and links,
+    /// mentions and URLs do not nest in each other. Of two equal ranges, the
+    /// later one is inside the earlier one.
+    pub fn validate(&self, text: &str) -> Result<(), ValidationError> {
+        let invalid = |e: &TextEntity, reason: &str| {
+            Err(ValidationError::InvalidTextEntity(format!(
+                "{:?} at {}..{}: {}",
+                e.kind, e.start, e.end, reason
+            )))
+        };
+        let mut open: Vec<&TextEntity> = Vec::new();
+        let mut previous: Option<&TextEntity> = None;
+        for e in &self.entities {
+            if !e.fits(text) {
+                return invalid(e, "does not fit the text");
+            }
+            if let EntityKind::Link(url) = &e.kind
+                && (url.is_empty() || url.chars().any(char::is_control))
+            {
+                return invalid(e, "bad link target");
+            }
+            if previous.is_some_and(|p| {
+                (p.start, std::cmp::Reverse(p.end)) > (e.start, std::cmp::Reverse(e.end))
+            }) {
+                return invalid(e, "out of order");
+            }
+            previous = Some(e);
+
+            while open.last().is_some_and(|o| o.end <= e.start) {
+                open.pop();
+            }
+            if open.last().is_some_and(|o| o.end < e.end) {
+                return invalid(e, "overlaps without nesting");
+            }
+            for outer in &open {
+                if !outer.kind.is_container() {
+                    return invalid(e, "nested in an entity that holds no others");
+                }
+                if std::mem::discriminant(&outer.kind) == std::mem::discriminant(&e.kind)
+                    || (outer.kind.is_interactive() && e.kind.is_interactive())
+                {
+                    return invalid(e, "nested in an entity of the same kind");
+                }
+            }
+            open.push(e);
+        }
+        Ok(())
+    }
+}
+
+/// The text of a Text or Edit node.
+fn text_of(node: &MerkleNode) -> Option<&str> {
+    match &node.content {
+        Content::Text(text) => Some(text),
+        Content::Edit { new_text, .. } => Some(new_text),
+        _ => None,
+    }
+}
+
+/// Validates the entities of a Text or Edit node. Metadata that does not
+/// hold entities is left alone.
+pub(crate) fn validate_node(node: &MerkleNode) -> Result<(), ValidationError> {
+    if node.metadata.is_empty() {
+        return Ok(());
+    }
+    match (text_of(node), TextEntities::from_metadata(&node.metadata)) {
+        (Some(text), Some(entities)) => entities.validate(text),
+        _ => Ok(()),
+    }
+}
+
+/// Whether `node` mentions `pk`.
+pub fn mentions(node: &MerkleNode, pk: &LogicalIdentityPk) -> bool {
+    TextEntities::of(node)
+        .iter()
+        .any(|e| e.kind == EntityKind::Mention(*pk))
+}
+
+impl MerkleToxEngine {
+    /// The `Mentioned` event for a verified node, if another member wrote it
+    /// and it mentions this identity.
+    pub(crate) fn mention_event(
+        &self,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        node: &MerkleNode,
+    ) -> Option<Effect> {
+        if node.author_pk == self.self_logical_pk || !mentions(node, &self.self_logical_pk) {
+            return None;
+        }
+        Some(Effect::EmitEvent(NodeEvent::Mentioned {
+            conversation_id,
+            hash,
+            author_pk: node.author_pk,
+        }))
+    }
+}
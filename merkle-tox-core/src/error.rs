@@ -41,6 +41,8 @@ pub enum MerkleToxError {
     Storage(String),
     #[error("Not authorized")]
     NotAuthorized,
+    #[error("Cannot author nodes: engine is a read-only observer")]
+    ReadOnly,
     #[error("Other error: {0}")]
     Other(String),
 }
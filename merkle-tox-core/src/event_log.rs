@@ -0,0 +1,206 @@
+//! Bounded log of protocol events for debugging sync between peers.
+//!
+//! The engine records every protocol message it handles or emits, and every
+//! node it stores, into a [`ProtocolEventLog`]. The log keeps the most recent
+//! events and can be dumped on demand, e.g. when a conversation stops
+//! converging. Dumps of two peers can be lined up by network timestamp.
+
+use crate::ProtocolMessage;
+use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use crate::engine::{Effect, MerkleToxEngine};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Default number of events kept by the engine.
+pub const DEFAULT_EVENT_LOG_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolEventKind {
+    /// A protocol message arrived from the peer.
+    Received(&'static str),
+    /// A protocol message was queued for the peer.
+    Sent(&'static str),
+    /// Missing nodes were requested from the peer.
+    FetchRequested { count: usize },
+    /// A node was written to the store.
+    NodeStored { hash: NodeHash, verified: bool },
+    /// Handling a message from the peer failed.
+    Error(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolEvent {
+    /// Position in the log since creation; gaps show dropped events.
+    pub seq: u64,
+    /// Network time in milliseconds.
+    pub timestamp_ms: i64,
+    pub peer_pk: Option<PhysicalDevicePk>,
+    pub conversation_id: Option<ConversationId>,
+    pub kind: ProtocolEventKind,
+}
+
+/// First four bytes of a key or hash in hex, for compact log lines.
+pub(crate) fn short_hex(bytes: &[u8; 32]) -> String {
+    hex::encode(&bytes[..4])
+}
+
+impl fmt::Display for ProtocolEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} t={}", self.seq, self.timestamp_ms)?;
+        if let Some(peer) = &self.peer_pk {
+            write!(f, " peer={}", short_hex(peer.as_bytes()))?;
+        }
+        if let Some(cid) = &self.conversation_id {
+            write!(f, " conv={}", short_hex(cid.as_bytes()))?;
+        }
+        match &self.kind {
+            ProtocolEventKind::Received(name) => write!(f, " recv {}", name),
+            ProtocolEventKind::Sent(name) => write!(f, " send {}", name),
+            ProtocolEventKind::FetchRequested { count } => write!(f, " fetch {} nodes", count),
+            ProtocolEventKind::NodeStored { hash, verified } => write!(
+                f,
+                " store {} {}",
+                short_hex(hash.as_bytes()),
+                if *verified { "verified" } else { "speculative" }
+            ),
+            ProtocolEventKind::Error(e) => write!(f, " error {}", e),
+        }
+    }
+}
+
+/// Ring buffer of the most recent protocol events.
+#[derive(Debug, Clone)]
+pub struct ProtocolEventLog {
+    events: VecDeque<ProtocolEvent>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl Default for ProtocolEventLog {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_LOG_CAPACITY)
+    }
+}
+
+impl ProtocolEventLog {
+    /// Creates a log keeping up to `capacity` events. A capacity of 0
+    /// disables recording.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity.min(DEFAULT_EVENT_LOG_CAPACITY)),
+            capacity,
+            next_seq: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, dropping the oldest events if needed.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.events.len() > capacity {
+            self.events.pop_front();
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        timestamp_ms: i64,
+        peer_pk: Option<PhysicalDevicePk>,
+        conversation_id: Option<ConversationId>,
+        kind: ProtocolEventKind,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(ProtocolEvent {
+            seq: self.next_seq,
+            timestamp_ms,
+            peer_pk,
+            conversation_id,
+            kind,
+        });
+        self.next_seq += 1;
+    }
+
+    /// Events from oldest to newest.
+    pub fn events(&self) -> impl Iterator<Item = &ProtocolEvent> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Formats all events, one per line.
+    pub fn dump(&self) -> String {
+        self.dump_filtered(|_| true)
+    }
+
+    /// Formats the events involving `conversation_id`, one per line.
+    pub fn dump_conversation(&self, conversation_id: &ConversationId) -> String {
+        self.dump_filtered(|e| e.conversation_id.as_ref() == Some(conversation_id))
+    }
+
+    fn dump_filtered(&self, filter: impl Fn(&ProtocolEvent) -> bool) -> String {
+        let mut out = String::new();
+        for event in self.events.iter().filter(|e| filter(e)) {
+            out.push_str(&event.to_string());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl MerkleToxEngine {
+    /// Records outgoing messages and stored nodes among `effects`.
+    pub(crate) fn log_effects(&mut self, timestamp_ms: i64, effects: &[Effect]) {
+        if !self.event_log.is_enabled() {
+            return;
+        }
+        for effect in effects {
+            let (peer_pk, conversation_id, kind) = match effect {
+                Effect::SendPacket(peer_pk, ProtocolMessage::FetchBatchReq(req)) => (
+                    Some(*peer_pk),
+                    Some(req.conversation_id),
+                    ProtocolEventKind::FetchRequested {
+                        count: req.hashes.len(),
+                    },
+                ),
+                Effect::SendPacket(peer_pk, msg) => (
+                    Some(*peer_pk),
+                    msg.conversation_id(),
+                    ProtocolEventKind::Sent(msg.name()),
+                ),
+                Effect::WriteStore(cid, node, verified) => (
+                    None,
+                    Some(*cid),
+                    ProtocolEventKind::NodeStored {
+                        hash: node.hash(),
+                        verified: *verified,
+                    },
+                ),
+                _ => continue,
+            };
+            self.event_log
+                .record(timestamp_ms, peer_pk, conversation_id, kind);
+        }
+    }
+}
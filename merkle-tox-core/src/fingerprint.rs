@@ -0,0 +1,117 @@
+//! Safety numbers for out-of-band identity verification.
+//!
+//! Two users compare a [`SafetyNumber`] in person, over a call, or by
+//! scanning each other's QR code. The number is derived from both logical
+//! identity keys, so it only matches on both screens if neither side was
+//! given a substituted key. After comparing, an admin may publish a
+//! `ControlAction::VerifiedIdentity` node so other members see the identity
+//! as verified.
+
+use crate::dag::{ConversationId, LogicalIdentityPk};
+use std::fmt;
+
+/// Scheme prefix of safety number QR payloads.
+pub const SAFETY_NUMBER_QR_PREFIX: &str = "mtox-verify:";
+
+/// Digits in the fingerprint of one identity.
+pub const FINGERPRINT_DIGITS: usize = 30;
+
+/// Digits shown per group.
+const GROUP_DIGITS: usize = 5;
+
+/// Decimal fingerprint of `identity_pk`, optionally bound to a conversation.
+///
+/// Each 5-digit group comes from 40 bits of a Blake3 hash reduced mod
+/// 100000, giving about 100 bits per fingerprint.
+pub fn fingerprint(
+    identity_pk: &LogicalIdentityPk,
+    conversation_id: Option<&ConversationId>,
+) -> String {
+    let mut hasher = blake3::Hasher::new_derive_key("merkle-tox v1 safety number");
+    hasher.update(identity_pk.as_bytes());
+    if let Some(conversation_id) = conversation_id {
+        hasher.update(conversation_id.as_bytes());
+    }
+    let hash = hasher.finalize();
+    hash.as_bytes()
+        .chunks_exact(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, b| (acc << 8) | u64::from(*b));
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+/// Safety number of a pair of identities. Both sides derive the same number
+/// regardless of which identity is their own.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SafetyNumber {
+    digits: String,
+}
+
+impl SafetyNumber {
+    /// Safety number valid across all conversations.
+    pub fn new(a: &LogicalIdentityPk, b: &LogicalIdentityPk) -> Self {
+        Self::derive(a, b, None)
+    }
+
+    /// Safety number bound to one conversation.
+    pub fn for_conversation(
+        conversation_id: &ConversationId,
+        a: &LogicalIdentityPk,
+        b: &LogicalIdentityPk,
+    ) -> Self {
+        Self::derive(a, b, Some(conversation_id))
+    }
+
+    fn derive(
+        a: &LogicalIdentityPk,
+        b: &LogicalIdentityPk,
+        conversation_id: Option<&ConversationId>,
+    ) -> Self {
+        let (first, second) = if a.as_bytes() <= b.as_bytes() {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        Self {
+            digits: fingerprint(first, conversation_id) + &fingerprint(second, conversation_id),
+        }
+    }
+
+    /// The 60 digits without separators.
+    pub fn digits(&self) -> &str {
+        &self.digits
+    }
+
+    /// Payload to encode as a QR code.
+    pub fn to_qr_payload(&self) -> String {
+        format!("{}{}", SAFETY_NUMBER_QR_PREFIX, self.digits)
+    }
+
+    /// Parses a scanned QR payload. Returns None if it is not a safety
+    /// number.
+    pub fn from_qr_payload(payload: &str) -> Option<Self> {
+        let digits = payload.strip_prefix(SAFETY_NUMBER_QR_PREFIX)?;
+        if digits.len() != 2 * FINGERPRINT_DIGITS || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(Self {
+            digits: digits.to_string(),
+        })
+    }
+}
+
+/// Groups of five digits separated by spaces, as shown to users.
+impl fmt::Display for SafetyNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, group) in self.digits.as_bytes().chunks(GROUP_DIGITS).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            // Digits are ASCII.
+            f.write_str(std::str::from_utf8(group).map_err(|_| fmt::Error)?)?;
+        }
+        Ok(())
+    }
+}
@@ -125,6 +125,17 @@ pub struct RevocationRecord {
     pub revocation_hash: NodeHash,
 }
 
+/// A destructive admin action that has not reached the admin quorum yet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PendingAdminAction {
+    /// Hash of the node holding the action.
+    pub hash: NodeHash,
+    /// Admins needed, from the quorum among the action's ancestors.
+    pub threshold: u8,
+    /// Admins who authored or co-signed it, sorted.
+    pub signers: Vec<LogicalIdentityPk>,
+}
+
 pub struct CausalContext {
     pub evaluating_node_hash: NodeHash,
     pub admin_ancestor_hashes: HashSet<NodeHash>,
@@ -152,6 +163,18 @@ pub struct IdentityManager {
     logical_members: HashMap<(ConversationId, LogicalIdentityPk), (u8, i64)>,
     /// Mapping of (ConversationID, Revoked Device PK) to List of Revocation Records
     revoked_devices: HashMap<(ConversationId, PhysicalDevicePk), Vec<RevocationRecord>>,
+    /// Mapping of channel ConversationID to its parent's. Channels have the
+    /// membership of their parent.
+    channel_parents: HashMap<ConversationId, ConversationId>,
+    /// Mapping of ConversationID to the SetAdminQuorum nodes in effect, by
+    /// hash, with their (Admin quorum, Rank). None means a quorum of one.
+    admin_quorums: HashMap<ConversationId, HashMap<NodeHash, (u8, u64)>>,
+    /// Mapping of (ConversationID, Admin node hash) to the admins who
+    /// authored or co-signed it.
+    admin_signers: HashMap<(ConversationId, NodeHash), HashSet<LogicalIdentityPk>>,
+    /// Mapping of (ConversationID, Admin node hash) to the quorum the
+    /// pending action needs.
+    pending_admin_actions: HashMap<(ConversationId, NodeHash), u8>,
     /// Cache of verified paths to avoid redundant recursive checks.
     /// (ConversationID, Device PK, Logical PK, EvaluatingNodeHash) -> min_expires_at
     path_cache: Mutex<
@@ -179,6 +202,10 @@ impl IdentityManager {
             authorized_devices: HashMap::new(),
             logical_members: HashMap::new(),
             revoked_devices: HashMap::new(),
+            channel_parents: HashMap::new(),
+            admin_quorums: HashMap::new(),
+            admin_signers: HashMap::new(),
+            pending_admin_actions: HashMap::new(),
             path_cache: Mutex::new(lru::LruCache::new(
                 std::num::NonZeroUsize::new(1000).unwrap(),
             )),
@@ -225,6 +252,152 @@ impl IdentityManager {
         })
     }
 
+    /// Gives channel `channel_id` the membership of `parent_id`.
+    pub fn register_channel(&mut self, channel_id: ConversationId, parent_id: ConversationId) {
+        self.channel_parents.insert(channel_id, parent_id);
+    }
+
+    /// The parent of channel `conversation_id`, if it is a channel.
+    pub fn channel_parent(&self, conversation_id: ConversationId) -> Option<ConversationId> {
+        self.channel_parents.get(&conversation_id).copied()
+    }
+
+    /// Number of admins needed to approve a destructive admin action
+    /// authored now, at the current heads.
+    pub fn admin_quorum(&self, conversation_id: ConversationId) -> u8 {
+        self.latest_admin_quorum(conversation_id, |_| true)
+    }
+
+    /// Number of admins needed to approve the destructive admin action of
+    /// `ctx`. Only SetAdminQuorum nodes among its ancestors count, so
+    /// concurrent changes do not depend on the order nodes arrive in.
+    pub fn admin_quorum_at(&self, ctx: &CausalContext, conversation_id: ConversationId) -> u8 {
+        self.latest_admin_quorum(conversation_id, |hash| {
+            ctx.admin_ancestor_hashes.contains(hash)
+        })
+    }
+
+    /// Quorum of the highest (rank, hash) SetAdminQuorum node in effect
+    /// accepted by `filter`.
+    fn latest_admin_quorum(
+        &self,
+        conversation_id: ConversationId,
+        filter: impl Fn(&NodeHash) -> bool,
+    ) -> u8 {
+        self.admin_quorums
+            .get(&conversation_id)
+            .and_then(|quorums| {
+                quorums
+                    .iter()
+                    .filter(|(hash, _)| filter(hash))
+                    .max_by_key(|(hash, (_, rank))| (*rank, **hash))
+            })
+            .map_or(1, |(_, (threshold, _))| (*threshold).max(1))
+    }
+
+    /// Records a SetAdminQuorum node. Concurrent updates resolve to the
+    /// highest (rank, hash).
+    pub fn set_admin_quorum(
+        &mut self,
+        conversation_id: ConversationId,
+        threshold: u8,
+        rank: u64,
+        hash: NodeHash,
+    ) {
+        self.admin_quorums
+            .entry(conversation_id)
+            .or_default()
+            .insert(hash, (threshold, rank));
+    }
+
+    /// Records a destructive admin action authored by `author_pk`, with the
+    /// causal context `ctx`. Returns true if it takes effect now; otherwise
+    /// it waits for co-signatures.
+    pub fn propose_admin_action(
+        &mut self,
+        ctx: &CausalContext,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        author_pk: LogicalIdentityPk,
+    ) -> bool {
+        let threshold = self.admin_quorum_at(ctx, conversation_id);
+        let signers = self
+            .admin_signers
+            .entry((conversation_id, hash))
+            .or_default();
+        signers.insert(author_pk);
+        if signers.len() >= threshold as usize {
+            self.pending_admin_actions.remove(&(conversation_id, hash));
+            return true;
+        }
+        debug!(
+            "Admin action {:?} needs {} of {} admins",
+            hash,
+            threshold,
+            signers.len()
+        );
+        self.pending_admin_actions
+            .insert((conversation_id, hash), threshold);
+        false
+    }
+
+    /// Records a co-signature of admin action `hash` by `signer_pk`. The
+    /// co-signature may arrive before the action. Returns true if it
+    /// completes the quorum of a pending action, which takes effect now.
+    pub fn co_sign_admin_action(
+        &mut self,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        signer_pk: LogicalIdentityPk,
+    ) -> bool {
+        let signers = self
+            .admin_signers
+            .entry((conversation_id, hash))
+            .or_default();
+        signers.insert(signer_pk);
+        let count = signers.len();
+        match self.pending_admin_actions.get(&(conversation_id, hash)) {
+            Some(threshold) if count >= *threshold as usize => {
+                self.pending_admin_actions.remove(&(conversation_id, hash));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Destructive admin actions waiting for co-signatures, sorted by hash.
+    pub fn pending_admin_actions(
+        &self,
+        conversation_id: ConversationId,
+    ) -> Vec<PendingAdminAction> {
+        let mut pending: Vec<_> = self
+            .pending_admin_actions
+            .iter()
+            .filter(|((cid, _), _)| *cid == conversation_id)
+            .map(|((_, hash), threshold)| {
+                let mut signers: Vec<_> = self
+                    .admin_signers
+                    .get(&(conversation_id, *hash))
+                    .map(|s| s.iter().copied().collect())
+                    .unwrap_or_default();
+                signers.sort_unstable();
+                PendingAdminAction {
+                    hash: *hash,
+                    threshold: *threshold,
+                    signers,
+                }
+            })
+            .collect();
+        pending.sort_by_key(|p| p.hash);
+        pending
+    }
+
+    /// The conversation whose membership applies in `conversation_id`.
+    fn membership_of(&self, conversation_id: ConversationId) -> ConversationId {
+        self.channel_parent(conversation_id)
+            .unwrap_or(conversation_id)
+    }
+
     /// Records logical member.
     pub fn add_member(
         &mut self,
@@ -277,6 +450,7 @@ impl IdentityManager {
         &self,
         conversation_id: ConversationId,
     ) -> Vec<(LogicalIdentityPk, u8, i64)> {
+        let conversation_id = self.membership_of(conversation_id);
         let mut members: Vec<_> = self
             .logical_members
             .iter()
@@ -289,6 +463,7 @@ impl IdentityManager {
 
     /// Returns founder's LogicalIdentityPk for conversation.
     pub fn get_founder(&self, conversation_id: &ConversationId) -> Option<LogicalIdentityPk> {
+        let conversation_id = &self.membership_of(*conversation_id);
         self.logical_members
             .iter()
             .find_map(|(&(cid, pk), &(role, _))| {
@@ -585,7 +760,7 @@ impl IdentityManager {
         device_pk: &PhysicalDevicePk,
     ) -> bool {
         self.authorized_devices
-            .contains_key(&(conversation_id, *device_pk))
+            .contains_key(&(self.membership_of(conversation_id), *device_pk))
     }
 
     pub fn is_authorized(
@@ -601,6 +776,19 @@ impl IdentityManager {
             return true;
         }
 
+        // Channels follow the current membership of their parent, whose
+        // Admin nodes are not in their DAG.
+        if let Some(parent) = self.channel_parent(conversation_id) {
+            return self.is_authorized(
+                &CausalContext::global(),
+                parent,
+                device_pk,
+                logical_pk,
+                now_ms,
+                u64::MAX,
+            );
+        }
+
         if let Some(&expires_at) = self.path_cache.lock().get(&(
             conversation_id,
             *device_pk,
@@ -751,6 +939,16 @@ impl IdentityManager {
         now_ms: i64,
         rank: u64,
     ) -> Option<Permissions> {
+        if let Some(parent) = self.channel_parent(conversation_id) {
+            return self.get_permissions(
+                &CausalContext::global(),
+                parent,
+                device_pk,
+                logical_pk,
+                now_ms,
+                u64::MAX,
+            );
+        }
         let perms = self.get_permissions_recursive(
             ctx,
             conversation_id,
@@ -891,6 +1089,7 @@ impl IdentityManager {
         &self,
         conversation_id: ConversationId,
     ) -> Vec<PhysicalDevicePk> {
+        let conversation_id = self.membership_of(conversation_id);
         let mut pks: Vec<_> = self
             .authorized_devices
             .iter()
@@ -909,6 +1108,14 @@ impl IdentityManager {
         now_ms: i64,
         rank: u64,
     ) -> Vec<PhysicalDevicePk> {
+        if let Some(parent) = self.channel_parent(conversation_id) {
+            return self.list_active_authorized_devices(
+                &CausalContext::global(),
+                parent,
+                now_ms,
+                u64::MAX,
+            );
+        }
         let members = self.list_members(conversation_id);
         let mut active_devices = Vec::new();
 
@@ -947,6 +1154,7 @@ impl IdentityManager {
         conversation_id: ConversationId,
         device_pk: &PhysicalDevicePk,
     ) -> Option<LogicalIdentityPk> {
+        let conversation_id = self.membership_of(conversation_id);
         if let Some((_, _)) = self
             .logical_members
             .get(&(conversation_id, device_pk.to_logical()))
@@ -976,6 +1184,7 @@ impl IdentityManager {
         &self,
         conversation_id: ConversationId,
     ) -> Vec<(PhysicalDevicePk, LogicalIdentityPk)> {
+        let conversation_id = self.membership_of(conversation_id);
         let mut pairs = Vec::new();
         for ((cid, device_pk), records) in &self.authorized_devices {
             if *cid == conversation_id {
@@ -1007,7 +1216,7 @@ impl IdentityManager {
         device_pk: &PhysicalDevicePk,
     ) -> Option<(u64, NodeHash)> {
         self.authorized_devices
-            .get(&(conversation_id, *device_pk))
+            .get(&(self.membership_of(conversation_id), *device_pk))
             .and_then(|records| {
                 records
                     .iter()
@@ -1023,6 +1232,7 @@ impl IdentityManager {
         conversation_id: ConversationId,
         logical_pk: LogicalIdentityPk,
     ) -> Vec<PhysicalDevicePk> {
+        let conversation_id = self.membership_of(conversation_id);
         let mut pks: Vec<_> = self
             .authorized_devices
             .iter()
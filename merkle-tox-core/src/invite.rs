@@ -0,0 +1,380 @@
+//! Out-of-band invite links.
+//!
+//! An admin device signs an [`InviteToken`] and shares it as a link (or QR
+//! code). The token names the conversation, proves the signing device belongs
+//! to the inviter, lists devices to bootstrap sync from, and carries a random
+//! bearer secret. The joiner announces itself with an [`InviteProof`] in the
+//! Announcement metadata; the inviter checks it against its
+//! [`InviteGrant`]s and admits the joiner with an Invite node.
+
+use crate::dag::{
+    Content, ControlAction, ConversationId, DelegationCertificate, Ed25519Signature,
+    LogicalIdentityPk, MerkleNode, NodeHash, NodeType, Permissions, PhysicalDevicePk,
+};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::error::{MerkleToxError, MerkleToxResult};
+use crate::identity::{CausalContext, IdentityError, sign_delegation, verify_delegation};
+use crate::sync::NodeStore;
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use ed25519_dalek::{Signature as DalekSignature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+use tox_proto::ToxProto;
+use tracing::debug;
+
+/// Scheme prefix of invite links.
+pub const INVITE_LINK_PREFIX: &str = "mtox-invite:";
+
+/// Current invite token version.
+pub const INVITE_VERSION: u8 = 1;
+
+/// Identifies an invite without revealing its secret.
+pub type InviteId = [u8; 32];
+
+/// Signed invitation to join a conversation.
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct InviteToken {
+    pub version: u8,
+    pub conversation_id: ConversationId,
+    pub inviter_pk: LogicalIdentityPk,
+    /// Certificate of the device that signed the token, issued by
+    /// `inviter_pk`.
+    pub inviter_cert: DelegationCertificate,
+    /// Devices the joiner syncs with first.
+    pub bootstrap_devices: Vec<PhysicalDevicePk>,
+    /// Role granted to the joiner (1 = admin).
+    pub role: u8,
+    pub expires_at: i64,
+    /// Bearer secret. Anyone holding the token can join until it expires.
+    pub secret: [u8; 32],
+    pub signature: Ed25519Signature,
+}
+
+#[derive(ToxProto)]
+struct InviteSignData {
+    version: u8,
+    conversation_id: ConversationId,
+    inviter_pk: LogicalIdentityPk,
+    inviter_cert: DelegationCertificate,
+    bootstrap_devices: Vec<PhysicalDevicePk>,
+    role: u8,
+    expires_at: i64,
+    secret: [u8; 32],
+}
+
+/// Proof of knowledge of an invite secret, bound to the joining device so
+/// that it cannot be replayed by another device that sees the Announcement.
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct InviteProof {
+    pub invite_id: InviteId,
+    pub mac: [u8; 32],
+}
+
+/// Invite issued by this device.
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct InviteGrant {
+    pub conversation_id: ConversationId,
+    pub secret: [u8; 32],
+    pub role: u8,
+    pub expires_at: i64,
+}
+
+impl InviteGrant {
+    pub fn id(&self) -> InviteId {
+        invite_id(&self.secret)
+    }
+}
+
+fn invite_id(secret: &[u8; 32]) -> InviteId {
+    blake3::derive_key("merkle-tox v1 invite id", secret)
+}
+
+fn invite_mac(secret: &[u8; 32], device_pk: &PhysicalDevicePk) -> [u8; 32] {
+    *blake3::keyed_hash(secret, device_pk.as_bytes()).as_bytes()
+}
+
+impl InviteToken {
+    fn sign_data(&self) -> InviteSignData {
+        InviteSignData {
+            version: self.version,
+            conversation_id: self.conversation_id,
+            inviter_pk: self.inviter_pk,
+            inviter_cert: self.inviter_cert.clone(),
+            bootstrap_devices: self.bootstrap_devices.clone(),
+            role: self.role,
+            expires_at: self.expires_at,
+            secret: self.secret,
+        }
+    }
+
+    /// Creates a token signed by the device key matching `inviter_cert`.
+    pub fn sign(
+        signing_key: &SigningKey,
+        inviter_pk: LogicalIdentityPk,
+        inviter_cert: DelegationCertificate,
+        bootstrap_devices: Vec<PhysicalDevicePk>,
+        role: u8,
+        expires_at: i64,
+        secret: [u8; 32],
+    ) -> Self {
+        let mut token = Self {
+            version: INVITE_VERSION,
+            conversation_id: inviter_cert.conversation_id,
+            inviter_pk,
+            inviter_cert,
+            bootstrap_devices,
+            role,
+            expires_at,
+            secret,
+            signature: Ed25519Signature::from([0u8; 64]),
+        };
+        let signed_data =
+            tox_proto::serialize(&token.sign_data()).expect("Failed to serialize sign data");
+        token.signature = Ed25519Signature::from(signing_key.sign(&signed_data).to_bytes());
+        token
+    }
+
+    /// Checks the inviter's certificate and signature, and that neither has
+    /// expired.
+    pub fn verify(&self, now_ms: i64) -> Result<(), IdentityError> {
+        if self.expires_at < now_ms {
+            return Err(IdentityError::Expired(self.expires_at, now_ms));
+        }
+        if self.inviter_cert.conversation_id != self.conversation_id {
+            return Err(IdentityError::ConversationIdMismatch);
+        }
+        verify_delegation(&self.inviter_cert, self.inviter_pk, now_ms)?;
+        if !self.inviter_cert.permissions.contains(Permissions::ADMIN) {
+            return Err(IdentityError::PermissionEscalation);
+        }
+
+        let verifying_key = VerifyingKey::from_bytes(self.inviter_cert.device_pk.as_bytes())
+            .map_err(|_| IdentityError::InvalidSignature)?;
+        let signed_data =
+            tox_proto::serialize(&self.sign_data()).map_err(|_| IdentityError::InvalidSignature)?;
+        verifying_key
+            .verify(
+                &signed_data,
+                &DalekSignature::from_bytes(self.signature.as_ref()),
+            )
+            .map_err(|_| IdentityError::InvalidSignature)
+    }
+
+    pub fn id(&self) -> InviteId {
+        invite_id(&self.secret)
+    }
+
+    /// Builds the proof `device_pk` attaches to its Announcement.
+    pub fn proof(&self, device_pk: &PhysicalDevicePk) -> InviteProof {
+        InviteProof {
+            invite_id: self.id(),
+            mac: invite_mac(&self.secret, device_pk),
+        }
+    }
+
+    /// Encodes the token as an `mtox-invite:` link.
+    pub fn to_link(&self) -> String {
+        let bytes = tox_proto::serialize(self).expect("Failed to serialize invite token");
+        format!("{}{}", INVITE_LINK_PREFIX, URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Decodes a link created by [`InviteToken::to_link`]. The token is not
+    /// verified.
+    pub fn from_link(link: &str) -> MerkleToxResult<Self> {
+        let encoded = link
+            .trim()
+            .strip_prefix(INVITE_LINK_PREFIX)
+            .ok_or_else(|| MerkleToxError::Other("Not an invite link".to_string()))?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|e| MerkleToxError::Other(format!("Invalid invite link: {}", e)))?;
+        let token: Self = tox_proto::deserialize(&bytes)?;
+        if token.version != INVITE_VERSION {
+            return Err(MerkleToxError::Other(format!(
+                "Unsupported invite version {}",
+                token.version
+            )));
+        }
+        Ok(token)
+    }
+}
+
+impl InviteProof {
+    /// Extracts a proof from node metadata.
+    pub fn from_metadata(metadata: &[u8]) -> Option<Self> {
+        tox_proto::deserialize(metadata).ok()
+    }
+
+    pub fn to_metadata(&self) -> Vec<u8> {
+        tox_proto::serialize(self).expect("Failed to serialize invite proof")
+    }
+}
+
+impl MerkleToxEngine {
+    /// Issues an invite token for the conversation of `self_cert`, signed by
+    /// this device. `self_cert` must authorize this device with ADMIN
+    /// permission. Returns the token and the effects persisting its grant
+    /// and dropping expired ones.
+    pub fn create_invite(
+        &mut self,
+        self_cert: DelegationCertificate,
+        bootstrap_devices: Vec<PhysicalDevicePk>,
+        role: u8,
+        expires_at: i64,
+    ) -> MerkleToxResult<(InviteToken, Vec<Effect>)> {
+        let Some(sk) = &self.self_sk else {
+            return Err(MerkleToxError::Crypto("Missing signing key".to_string()));
+        };
+        if self_cert.device_pk != self.self_pk {
+            return Err(MerkleToxError::Identity(IdentityError::NoTrustPath));
+        }
+        let signing_key = SigningKey::from_bytes(sk.as_bytes());
+        let mut secret = [0u8; 32];
+        self.rng.lock().fill_bytes(&mut secret);
+
+        let token = InviteToken::sign(
+            &signing_key,
+            self.self_logical_pk,
+            self_cert,
+            bootstrap_devices,
+            role,
+            expires_at,
+            secret,
+        );
+        let mut effects = self.expire_invites();
+        let grant = InviteGrant {
+            conversation_id: token.conversation_id,
+            secret,
+            role,
+            expires_at,
+        };
+        self.invite_grants.insert(token.id(), grant.clone());
+        effects.push(Effect::WriteInviteGrant(grant));
+        Ok((token, effects))
+    }
+
+    /// Loads the invites issued by a previous run. Returns how many were
+    /// loaded; expired invites are skipped.
+    pub fn load_invite_grants(&mut self, store: &dyn NodeStore) -> usize {
+        let now = self.clock.network_time_ms();
+        let mut loaded = 0;
+        for grant in store.get_invite_grants() {
+            if grant.expires_at >= now && self.invite_grants.insert(grant.id(), grant).is_none() {
+                loaded += 1;
+            }
+        }
+        loaded
+    }
+
+    /// Forgets expired invites and returns the effects deleting them.
+    fn expire_invites(&mut self) -> Vec<Effect> {
+        let now = self.clock.network_time_ms();
+        let expired: Vec<InviteId> = self
+            .invite_grants
+            .iter()
+            .filter(|(_, g)| g.expires_at < now)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .map(|id| {
+                self.invite_grants.remove(&id);
+                Effect::DeleteInviteGrant(id)
+            })
+            .collect()
+    }
+
+    /// Returns the certificate authorizing this device in a conversation,
+    /// self-signing one if this device holds the logical identity key.
+    pub fn own_certificate(
+        &self,
+        conversation_id: ConversationId,
+        store: &dyn NodeStore,
+    ) -> Option<DelegationCertificate> {
+        let admin_nodes = store
+            .get_verified_nodes_by_type(&conversation_id, NodeType::Admin)
+            .unwrap_or_default();
+        let found = admin_nodes.into_iter().rev().find_map(|n| match n.content {
+            Content::Control(ControlAction::AuthorizeDevice { cert })
+                if cert.device_pk == self.self_pk =>
+            {
+                Some(cert)
+            }
+            _ => None,
+        });
+        if found.is_some() {
+            return found;
+        }
+        let sk = self.self_sk.as_ref()?;
+        (self.self_pk.to_logical() == self.self_logical_pk).then(|| {
+            sign_delegation(
+                &SigningKey::from_bytes(sk.as_bytes()),
+                self.self_pk,
+                Permissions::ALL,
+                i64::MAX,
+                conversation_id,
+            )
+        })
+    }
+
+    /// Prepares joining a conversation from a verified invite: trusts the
+    /// inviter's device, starts syncing with the bootstrap devices and
+    /// authors an Announcement carrying the invite proof, so that the
+    /// inviter admits us and shares the conversation key.
+    pub fn accept_invite(
+        &mut self,
+        token: &InviteToken,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let cid = token.conversation_id;
+        self.identity_manager
+            .add_member(cid, token.inviter_pk, 1, 0);
+        self.identity_manager.authorize_device(
+            &CausalContext::global(),
+            cid,
+            token.inviter_pk,
+            &token.inviter_cert,
+            0,
+            0,
+            NodeHash::from([0u8; 32]),
+        )?;
+
+        let mut effects = Vec::new();
+        for device_pk in &token.bootstrap_devices {
+            effects.extend(self.start_sync(cid, Some(*device_pk), store));
+        }
+        let proof = token.proof(&self.self_pk);
+        effects.extend(self.author_announcement_with_metadata(cid, proof.to_metadata(), store)?);
+        Ok(effects)
+    }
+
+    /// Stops accepting an invite. Returns no effects if it was unknown.
+    pub fn revoke_invite(&mut self, id: &InviteId) -> Vec<Effect> {
+        match self.invite_grants.remove(id) {
+            Some(_) => vec![Effect::DeleteInviteGrant(*id)],
+            None => Vec::new(),
+        }
+    }
+
+    /// Checks whether an Announcement carries a valid proof for one of our
+    /// invites. Returns the role to grant its author.
+    pub fn check_invite_proof(
+        &mut self,
+        conversation_id: ConversationId,
+        node: &MerkleNode,
+    ) -> Option<u8> {
+        let proof = InviteProof::from_metadata(&node.metadata)?;
+        let now = self.clock.network_time_ms();
+
+        let grant = self.invite_grants.get(&proof.invite_id)?;
+        let mac_ok: bool = invite_mac(&grant.secret, &node.sender_pk)
+            .ct_eq(&proof.mac)
+            .into();
+        if grant.expires_at < now || grant.conversation_id != conversation_id || !mac_ok {
+            debug!("Rejecting invite proof from {:?}", node.sender_pk);
+            return None;
+        }
+        Some(grant.role)
+    }
+}
@@ -0,0 +1,109 @@
+//! Proof-of-work admission to open conversations.
+//!
+//! A conversation whose Genesis flags set a join difficulty (see
+//! [`join_pow_difficulty`]) admits devices without an invite. A joining
+//! device announces itself with a [`JoinProof`] in the Announcement
+//! metadata: a solution to a challenge derived from the conversation and the
+//! announcing device, so that every new device pays for its own admission.
+//! Admins check the proof and admit the joiner with an Invite node.
+
+use crate::dag::{ConversationId, MerkleNode, PhysicalDevicePk, PowNonce};
+use crate::engine::conversation::join_pow_difficulty;
+use crate::engine::session::active::{pow_leading_zeros, solve_challenge};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::error::MerkleToxResult;
+use crate::sync::NodeStore;
+use tox_proto::ToxProto;
+use tracing::debug;
+
+/// Solution to the join challenge of the announcing device.
+#[derive(Debug, Clone, Copy, ToxProto, PartialEq, Eq)]
+pub struct JoinProof {
+    pub solution: u64,
+}
+
+/// Challenge `device_pk` solves to join `conversation_id`.
+pub fn join_challenge(conversation_id: &ConversationId, device_pk: &PhysicalDevicePk) -> PowNonce {
+    let mut input = [0u8; 64];
+    input[..32].copy_from_slice(conversation_id.as_bytes());
+    input[32..].copy_from_slice(device_pk.as_bytes());
+    PowNonce::from(blake3::derive_key("merkle-tox v1 join pow", &input))
+}
+
+impl JoinProof {
+    /// Solves the join challenge of `device_pk`. Takes about `2^difficulty`
+    /// hashes.
+    pub fn solve(
+        conversation_id: &ConversationId,
+        device_pk: &PhysicalDevicePk,
+        difficulty: u32,
+    ) -> Self {
+        Self {
+            solution: solve_challenge(join_challenge(conversation_id, device_pk), difficulty),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        conversation_id: &ConversationId,
+        device_pk: &PhysicalDevicePk,
+        difficulty: u32,
+    ) -> bool {
+        pow_leading_zeros(join_challenge(conversation_id, device_pk), self.solution) >= difficulty
+    }
+
+    /// Extracts a proof from node metadata.
+    pub fn from_metadata(metadata: &[u8]) -> Option<Self> {
+        tox_proto::deserialize(metadata).ok()
+    }
+
+    pub fn to_metadata(&self) -> Vec<u8> {
+        tox_proto::serialize(self).expect("Failed to serialize join proof")
+    }
+}
+
+impl MerkleToxEngine {
+    /// Joins an open conversation: starts syncing with the bootstrap devices
+    /// and authors an Announcement carrying a join proof of `difficulty`
+    /// bits, so that an admin admits us and shares the conversation key.
+    pub fn join_with_pow(
+        &mut self,
+        conversation_id: ConversationId,
+        bootstrap_devices: &[PhysicalDevicePk],
+        difficulty: u32,
+        store: &dyn NodeStore,
+    ) -> MerkleToxResult<Vec<Effect>> {
+        let mut effects = Vec::new();
+        for device_pk in bootstrap_devices {
+            effects.extend(self.start_sync(conversation_id, Some(*device_pk), store));
+        }
+        let proof = JoinProof::solve(&conversation_id, &self.self_pk, difficulty);
+        effects.extend(self.author_announcement_with_metadata(
+            conversation_id,
+            proof.to_metadata(),
+            store,
+        )?);
+        Ok(effects)
+    }
+
+    /// Checks whether an Announcement carries a proof meeting the
+    /// conversation's join difficulty. Always false for conversations that
+    /// do not admit devices by proof-of-work.
+    pub fn check_join_proof(&self, conversation_id: ConversationId, node: &MerkleNode) -> bool {
+        let difficulty = self
+            .conversations
+            .get(&conversation_id)
+            .map_or(0, |c| join_pow_difficulty(c.genesis_flags()));
+        if difficulty == 0 {
+            return false;
+        }
+        let Some(proof) = JoinProof::from_metadata(&node.metadata) else {
+            return false;
+        };
+        if !proof.verify(&conversation_id, &node.sender_pk, difficulty) {
+            debug!("Rejecting join proof from {:?}", node.sender_pk);
+            return false;
+        }
+        true
+    }
+}
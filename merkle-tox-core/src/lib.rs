@@ -1,19 +1,36 @@
 pub mod builder;
+pub mod capabilities;
 pub mod cas;
+pub mod channel;
+pub mod classify;
 pub mod clock;
 pub mod crypto;
 pub mod dag;
+pub mod diagnostics;
 pub mod engine;
+pub mod entities;
 pub mod error;
+pub mod event_log;
+pub mod fingerprint;
 pub mod identity;
+pub mod invite;
+pub mod join_pow;
 pub mod node;
+pub mod poll;
+pub mod rate_limit;
+pub mod replay;
+pub mod subscription;
+pub mod suspend;
 pub mod sync;
 pub mod testing;
 pub mod vfs;
 pub mod viz;
 
-use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk, PowNonce, ShardHash};
+use crate::dag::{
+    ConversationId, LogicalIdentityPk, NodeHash, PhysicalDevicePk, PowNonce, ShardHash,
+};
 use std::io;
+use std::time::Duration;
 use tox_proto::ToxProto;
 
 /// Transport layer errors.
@@ -36,6 +53,21 @@ pub trait Transport: Send + Sync {
 
     /// Sends raw lossy packet to destination.
     fn send_raw(&self, to: PhysicalDevicePk, data: Vec<u8>) -> Result<(), TransportError>;
+
+    /// Largest packet the path to `to` carries, if known.
+    fn path_mtu(&self, _to: &PhysicalDevicePk) -> Option<usize> {
+        None
+    }
+
+    /// Whether packets to `to` go through a TCP relay.
+    fn is_relayed(&self, _to: &PhysicalDevicePk) -> bool {
+        false
+    }
+
+    /// Round-trip time to `to` as measured by the transport, if known.
+    fn estimated_rtt(&self, _to: &PhysicalDevicePk) -> Option<Duration> {
+        None
+    }
 }
 
 /// High-level message types for Merkle-Tox protocol.
@@ -110,6 +142,95 @@ pub enum ProtocolMessage {
         conversation_id: ConversationId,
         hash: NodeHash,
     },
+    /// Delta-encoded head announcement (`FEATURE_DELTA_HEADS`).
+    SyncHeadsDelta(sync::SyncHeadsDelta),
+    /// `MerkleNode` with the sender's head delta piggybacked on it.
+    MerkleNodeWithHeads {
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        node: dag::WireNode,
+        heads: sync::SyncHeadsDelta,
+    },
+}
+
+impl ProtocolMessage {
+    /// Variant name, for logs and tracing spans.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProtocolMessage::CapsAnnounce { .. } => "CapsAnnounce",
+            ProtocolMessage::CapsAck { .. } => "CapsAck",
+            ProtocolMessage::SyncHeads(_) => "SyncHeads",
+            ProtocolMessage::SyncSketch(_) => "SyncSketch",
+            ProtocolMessage::SyncShardChecksums { .. } => "SyncShardChecksums",
+            ProtocolMessage::SyncReconFail { .. } => "SyncReconFail",
+            ProtocolMessage::SyncRateLimited { .. } => "SyncRateLimited",
+            ProtocolMessage::KeywrapAck { .. } => "KeywrapAck",
+            ProtocolMessage::ReconPowChallenge { .. } => "ReconPowChallenge",
+            ProtocolMessage::ReconPowSolution { .. } => "ReconPowSolution",
+            ProtocolMessage::FetchBatchReq(_) => "FetchBatchReq",
+            ProtocolMessage::MerkleNode { .. } => "MerkleNode",
+            ProtocolMessage::BlobQuery(_) => "BlobQuery",
+            ProtocolMessage::BlobAvail(_) => "BlobAvail",
+            ProtocolMessage::BlobReq(_) => "BlobReq",
+            ProtocolMessage::BlobData(_) => "BlobData",
+            ProtocolMessage::ReinclusionRequest { .. } => "ReinclusionRequest",
+            ProtocolMessage::ReinclusionResponse { .. } => "ReinclusionResponse",
+            ProtocolMessage::HandshakeError { .. } => "HandshakeError",
+            ProtocolMessage::AdminGossip { .. } => "AdminGossip",
+            ProtocolMessage::SyncHeadsDelta(_) => "SyncHeadsDelta",
+            ProtocolMessage::MerkleNodeWithHeads { .. } => "MerkleNodeWithHeads",
+        }
+    }
+
+    /// Conversation the message belongs to, if it is conversation-scoped.
+    pub fn conversation_id(&self) -> Option<ConversationId> {
+        match self {
+            ProtocolMessage::SyncHeads(heads) => Some(heads.conversation_id),
+            ProtocolMessage::SyncHeadsDelta(delta) => Some(delta.conversation_id),
+            ProtocolMessage::SyncSketch(sketch) => Some(sketch.conversation_id),
+            ProtocolMessage::FetchBatchReq(req) => Some(req.conversation_id),
+            ProtocolMessage::SyncShardChecksums {
+                conversation_id, ..
+            }
+            | ProtocolMessage::SyncReconFail {
+                conversation_id, ..
+            }
+            | ProtocolMessage::SyncRateLimited {
+                conversation_id, ..
+            }
+            | ProtocolMessage::ReconPowChallenge {
+                conversation_id, ..
+            }
+            | ProtocolMessage::ReconPowSolution {
+                conversation_id, ..
+            }
+            | ProtocolMessage::MerkleNode {
+                conversation_id, ..
+            }
+            | ProtocolMessage::MerkleNodeWithHeads {
+                conversation_id, ..
+            }
+            | ProtocolMessage::ReinclusionRequest {
+                conversation_id, ..
+            }
+            | ProtocolMessage::ReinclusionResponse {
+                conversation_id, ..
+            }
+            | ProtocolMessage::HandshakeError {
+                conversation_id, ..
+            }
+            | ProtocolMessage::AdminGossip {
+                conversation_id, ..
+            } => Some(*conversation_id),
+            ProtocolMessage::CapsAnnounce { .. }
+            | ProtocolMessage::CapsAck { .. }
+            | ProtocolMessage::KeywrapAck { .. }
+            | ProtocolMessage::BlobQuery(_)
+            | ProtocolMessage::BlobAvail(_)
+            | ProtocolMessage::BlobReq(_)
+            | ProtocolMessage::BlobData(_) => None,
+        }
+    }
 }
 
 /// Events emitted by Merkle-Tox engine/node for orchestration.
@@ -139,13 +260,105 @@ pub enum NodeEvent {
         conversation_id: ConversationId,
         hash: NodeHash,
     },
+    /// Node passed the conversation's retention window and its payload was
+    /// deleted.
+    NodeExpired {
+        conversation_id: ConversationId,
+        hash: NodeHash,
+    },
+    /// A device acknowledged a node this device sent: its announced heads
+    /// descend from it. May be repeated for the same device.
+    NodeDelivered {
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        device_pk: PhysicalDevicePk,
+    },
+    /// A verified node of another member mentions this identity. Follows
+    /// the node's `NodeVerified` event.
+    Mentioned {
+        conversation_id: ConversationId,
+        hash: NodeHash,
+        author_pk: LogicalIdentityPk,
+    },
+    /// A verified Invite added `member_pk` to the conversation.
+    MemberJoined {
+        conversation_id: ConversationId,
+        member_pk: LogicalIdentityPk,
+        role: u8,
+        hash: NodeHash,
+    },
+    /// `member_pk` is no longer in the conversation: a verified Leave
+    /// removed it, or a RevokeDevice revoked its last device.
+    MemberLeft {
+        conversation_id: ConversationId,
+        member_pk: LogicalIdentityPk,
+        hash: NodeHash,
+    },
     /// Handshake with peer completed.
     PeerHandshakeComplete { peer_pk: PhysicalDevicePk },
+    /// Blob chunk received and verified. `received` and `total` are in bytes.
+    BlobProgress {
+        hash: NodeHash,
+        received: u64,
+        total: u64,
+    },
     /// Blob downloaded and verified.
     BlobAvailable { hash: NodeHash },
+    /// Peers agree the local clock is off by `offset_ms` (positive when it
+    /// is behind), more than `ClockPolicy::warn_offset`. Raised again only
+    /// after the offset has come back within the limit.
+    ClockSkewWarning { offset_ms: i64 },
+    /// Conversation moved to a new key epoch. `trigger` says why when this
+    /// device rotated the key; it is `None` for rotations by other admins.
+    EpochRotated {
+        conversation_id: ConversationId,
+        epoch: u64,
+        trigger: Option<engine::config::RotationTrigger>,
+    },
+    /// An admin created channel `channel_id` in `conversation_id`.
+    ChannelCreated {
+        conversation_id: ConversationId,
+        channel_id: ConversationId,
+        name: String,
+    },
+    /// A device was revoked as compromised and the conversation re-keyed.
+    CompromiseRecovered(engine::recovery::CompromiseRecovery),
+    /// A device was blacklisted until `expires_at_ms` (network time).
+    PeerBlacklisted {
+        device_pk: PhysicalDevicePk,
+        tier: u8,
+        expires_at_ms: i64,
+    },
+    /// A peer went over its rate limit for `message`; its requests of that
+    /// kind are dropped until the limit allows them again. Emitted once per
+    /// episode.
+    PeerThrottled {
+        device_pk: PhysicalDevicePk,
+        message: rate_limit::LimitedMessage,
+    },
 }
 
 /// Trait for receiving engine events.
 pub trait NodeEventHandler: Send + Sync {
     fn handle_event(&self, event: NodeEvent);
 }
+
+/// Whether a node sent or received a `ProtocolMessage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageDirection {
+    Sent,
+    Received,
+}
+
+/// Trait for observing every protocol message a node exchanges with its
+/// peers, e.g. for packet capture. Sent messages are reported when the
+/// engine hands them to the transport layer, received ones once they are
+/// reassembled and decoded.
+pub trait MessageObserver: Send + Sync {
+    fn observe(
+        &self,
+        direction: MessageDirection,
+        peer_pk: PhysicalDevicePk,
+        msg: &ProtocolMessage,
+    );
+}
@@ -1,13 +1,42 @@
+use crate::capabilities::FEATURE_BATCH;
+use crate::classify;
 use crate::clock::TimeProvider;
 use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use crate::diagnostics::{
+    self, ClockReport, ConversationReport, Diagnostics, SessionReport, SessionState,
+};
+use crate::engine::quarantine::SpamOffense;
+use crate::engine::session::PeerSession;
 use crate::engine::{Effect, MerkleToxEngine};
-use crate::sync::{BlobStore, NodeStore};
-use crate::{NodeEventHandler, ProtocolMessage, Transport};
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tox_sequenced::{MessageType, Packet, SequenceSession, SessionEvent};
-use tracing::{debug, error};
+use crate::invite::InviteToken;
+use crate::rate_limit::{Admission, LimitedMessage, RateLimitPolicy, RateLimiter};
+use crate::replay::{RecordedInput, Recorder};
+use crate::subscription::{EventFilter, Subscribers};
+use crate::sync::{BlobStore, NodeStore, StoreWrite};
+use crate::{
+    MessageDirection, MessageObserver, NodeEvent, NodeEventHandler, ProtocolMessage, Transport,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+use tox_proto::time::Instant;
+use tox_sequenced::{
+    MessageType, Packet, PathQuality, SequenceSession, SequencedError, SessionEvent,
+};
+use tracing::{debug, error, warn};
+
+/// Most messages held for one peer while its session is full or the peer is
+/// offline.
+pub const MAX_QUEUED_MESSAGES: usize = 512;
+/// Most bytes held for one peer.
+pub const MAX_QUEUED_BYTES: usize = 4 * 1024 * 1024;
+/// Queue length at which the engine pauses sync tasks for the peer.
+pub const BACKPRESSURE_HIGH: usize = 256;
+/// Queue length at which they resume.
+pub const BACKPRESSURE_LOW: usize = 64;
+/// Age after which queued sync messages are dropped; the engine regenerates
+/// them from current state.
+pub const STALE_SYNC_AGE: Duration = Duration::from_secs(30);
 
 /// Node status snapshot for observability.
 pub struct NodeStatus {
@@ -22,6 +51,94 @@ pub struct NodeStatus {
     pub sessions: HashMap<PhysicalDevicePk, SessionStatus>,
 }
 
+/// A message waiting for room in a peer's session.
+#[derive(Debug, Clone)]
+pub struct QueuedMessage {
+    pub msg: ProtocolMessage,
+    /// Serialized size.
+    pub size: usize,
+    pub queued_at: Instant,
+}
+
+/// Bounded queue of messages for one peer.
+#[derive(Debug, Default)]
+pub struct SendQueue {
+    messages: VecDeque<QueuedMessage>,
+    bytes: usize,
+}
+
+impl SendQueue {
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &QueuedMessage> {
+        self.messages.iter()
+    }
+
+    /// Queues a message. A newer head announcement or shard checksum
+    /// replaces a queued one for the same conversation. When the queue is
+    /// full, the oldest sync message makes room; if there is none, the new
+    /// message is refused and `false` returned.
+    pub fn push(&mut self, msg: ProtocolMessage, size: usize, now: Instant) -> bool {
+        if let Some(pos) = self.messages.iter().position(|q| supersedes(&msg, &q.msg)) {
+            self.remove(pos);
+        }
+        while self.messages.len() >= MAX_QUEUED_MESSAGES || self.bytes + size > MAX_QUEUED_BYTES {
+            match self.messages.iter().position(|q| is_sync_message(&q.msg)) {
+                Some(pos) => {
+                    self.remove(pos);
+                }
+                None => return false,
+            }
+        }
+        self.bytes += size;
+        self.messages.push_back(QueuedMessage {
+            msg,
+            size,
+            queued_at: now,
+        });
+        true
+    }
+
+    /// Drops sync messages queued longer than `STALE_SYNC_AGE`.
+    pub fn expire(&mut self, now: Instant) {
+        let bytes = &mut self.bytes;
+        self.messages.retain(|q| {
+            let stale = is_sync_message(&q.msg)
+                && now.saturating_duration_since(q.queued_at) >= STALE_SYNC_AGE;
+            if stale {
+                *bytes -= q.size;
+            }
+            !stale
+        });
+    }
+
+    fn front(&self) -> Option<&QueuedMessage> {
+        self.messages.front()
+    }
+
+    fn pop_front(&mut self) -> Option<QueuedMessage> {
+        let q = self.messages.pop_front()?;
+        self.bytes -= q.size;
+        Some(q)
+    }
+
+    fn remove(&mut self, pos: usize) -> Option<QueuedMessage> {
+        let q = self.messages.remove(pos)?;
+        self.bytes -= q.size;
+        Some(q)
+    }
+}
+
 pub struct SessionStatus {
     pub cwnd: usize,
     pub in_flight_bytes: usize,
@@ -29,14 +146,44 @@ pub struct SessionStatus {
     pub retransmit_count: u64,
 }
 
+/// What `MerkleToxNode::shutdown` flushed, and what it left behind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Received wire nodes verified and stored during shutdown.
+    pub nodes_flushed: usize,
+    /// Received wire nodes still unverified. Peers send them again.
+    pub nodes_dropped: usize,
+    /// Sessions that sent their final ACKs.
+    pub sessions_flushed: usize,
+    /// Messages sent but unacknowledged, or still queued.
+    pub undelivered_messages: usize,
+    /// Whether `deadline` passed before every session was flushed.
+    pub deadline_exceeded: bool,
+    /// Whether the store made its writes durable.
+    pub store_flushed: bool,
+}
+
 /// Transport-agnostic Merkle-Tox node orchestrating engine, reliability, and storage.
 pub struct MerkleToxNode<T: Transport, S: NodeStore + BlobStore> {
     pub engine: MerkleToxEngine,
     pub transport: T,
     pub store: S,
     pub sessions: HashMap<PhysicalDevicePk, SequenceSession>,
+    /// Messages waiting for room in a session, or for the peer to come back.
+    pub send_queues: HashMap<PhysicalDevicePk, SendQueue>,
+    /// Peers the transport reports offline.
+    pub offline_peers: HashSet<PhysicalDevicePk>,
     pub time_provider: Arc<dyn TimeProvider>,
     pub event_handler: Option<Arc<dyn NodeEventHandler>>,
+    /// Filtered event receivers, see [`subscribe`](Self::subscribe).
+    pub subscribers: Subscribers,
+    pub message_observer: Option<Arc<dyn MessageObserver>>,
+    /// Limits on expensive requests from peers.
+    pub rate_limiter: RateLimiter,
+    /// Recording of the node's inputs, if enabled.
+    pub recorder: Option<Recorder>,
+    /// Set by `shutdown`; packets and new messages are ignored from then on.
+    pub shut_down: bool,
 }
 
 impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
@@ -80,29 +227,171 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
         }
     }
 
+    /// Health report covering every conversation in the store. Reads all
+    /// stored nodes, so it is meant for occasional checks, not polling.
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut conversation_ids: Vec<_> = self
+            .engine
+            .conversations
+            .keys()
+            .chain(&self.engine.unloaded_conversations)
+            .copied()
+            .collect();
+        conversation_ids.sort();
+        conversation_ids.dedup();
+
+        let conversations = conversation_ids
+            .into_iter()
+            .map(|cid| {
+                let (verified_nodes, speculative_nodes) = self.store.get_node_counts(&cid);
+                let conversation = self.engine.conversations.get(&cid);
+                ConversationReport {
+                    conversation_id: cid,
+                    loaded: conversation.is_some(),
+                    established: conversation.is_some_and(|c| c.is_established()),
+                    verified_nodes,
+                    speculative_nodes,
+                    heads: self.store.get_heads(&cid).len(),
+                    integrity: diagnostics::check_integrity(&self.store, &cid),
+                    ratchets: diagnostics::ratchet_coverage(&self.engine, &cid),
+                }
+            })
+            .collect();
+
+        let mut sessions: Vec<_> = self
+            .engine
+            .sessions
+            .iter()
+            .map(|((peer, cid), session)| SessionReport {
+                peer: *peer,
+                conversation_id: *cid,
+                state: match session {
+                    PeerSession::Handshake(_) => SessionState::Handshake,
+                    PeerSession::Active(_) => SessionState::Active,
+                },
+                negotiated: session.common().negotiated,
+                online: !self.offline_peers.contains(peer),
+                queued_messages: self.send_queues.get(peer).map_or(0, SendQueue::len),
+            })
+            .collect();
+        sessions.sort_by_key(|s| (s.peer, s.conversation_id));
+
+        Diagnostics {
+            pk: self.engine.self_pk,
+            clock: ClockReport {
+                consensus_offset_ms: self.engine.clock.consensus_offset(),
+                peer_median_offset_ms: self.engine.clock.peer_median_offset(),
+                warn_offset: self.engine.clock.policy().warn_offset,
+            },
+            disk_usage_bytes: self.store.size_bytes(),
+            conversations,
+            sessions,
+        }
+    }
+
+    /// Creates a node. Blob downloads left unfinished in `store` resume;
+    /// scheduled messages, acknowledged heads, the blacklist and issued
+    /// invites are reloaded.
     pub fn new(
-        engine: MerkleToxEngine,
+        mut engine: MerkleToxEngine,
         transport: T,
         store: S,
         time_provider: Arc<dyn TimeProvider>,
     ) -> Self {
+        engine.resume_blob_downloads(&store);
+        engine.load_scheduled_messages(&store);
+        engine.load_outbox(&store);
+        engine.load_blacklist(&store);
+        engine.load_invite_grants(&store);
         Self {
             engine,
             transport,
             store,
             sessions: HashMap::new(),
+            send_queues: HashMap::new(),
+            offline_peers: HashSet::new(),
             time_provider,
             event_handler: None,
+            subscribers: Subscribers::default(),
+            message_observer: None,
+            rate_limiter: RateLimiter::default(),
+            recorder: None,
+            shut_down: false,
         }
     }
 
+    /// Joins a conversation from an `mtox-invite:` link. The inviter admits
+    /// this device once it receives our Announcement; the conversation key
+    /// then arrives via X3DH.
+    pub fn join_from_invite(&mut self, link: &str) -> crate::error::MerkleToxResult<InviteToken> {
+        let token = InviteToken::from_link(link)?;
+        token.verify(self.engine.clock.network_time_ms())?;
+        let effects = self.engine.accept_invite(&token, &self.store)?;
+
+        let now = self.time_provider.now_instant();
+        let now_ms = self.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+        self.process_effects(effects, now, now_ms, &mut next_wakeup)?;
+        Ok(token)
+    }
+
+    /// Joins an open conversation by proof-of-work. `difficulty` must meet
+    /// the join difficulty in the conversation's Genesis flags.
+    pub fn join_with_pow(
+        &mut self,
+        conversation_id: ConversationId,
+        bootstrap_devices: &[PhysicalDevicePk],
+        difficulty: u32,
+    ) -> crate::error::MerkleToxResult<()> {
+        let effects = self.engine.join_with_pow(
+            conversation_id,
+            bootstrap_devices,
+            difficulty,
+            &self.store,
+        )?;
+
+        let now = self.time_provider.now_instant();
+        let now_ms = self.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+        self.process_effects(effects, now, now_ms, &mut next_wakeup)
+    }
+
     pub fn set_event_handler(&mut self, handler: Arc<dyn NodeEventHandler>) {
         self.event_handler = Some(handler);
     }
 
+    /// Receives the events matching `filter`, alongside the event handler,
+    /// until the receiver is dropped.
+    pub fn subscribe(&self, filter: EventFilter) -> mpsc::Receiver<NodeEvent> {
+        self.subscribers.subscribe(filter)
+    }
+
+    /// Hands `event` to the subscribers and the event handler.
+    fn emit(&self, event: NodeEvent) {
+        self.subscribers.publish(&event);
+        if let Some(handler) = &self.event_handler {
+            handler.handle_event(event);
+        }
+    }
+
+    pub fn set_message_observer(&mut self, observer: Arc<dyn MessageObserver>) {
+        self.message_observer = Some(observer);
+    }
+
+    pub fn set_rate_limit_policy(&mut self, policy: RateLimitPolicy) {
+        self.rate_limiter.set_policy(policy);
+    }
+
     /// Handles incoming raw packet.
     pub fn handle_packet(&mut self, from: PhysicalDevicePk, data: &[u8]) {
+        if self.shut_down {
+            return;
+        }
         let now = self.time_provider.now_instant();
+        if self.recorder.is_some() {
+            let data = data.to_vec();
+            self.record(now, RecordedInput::Packet { from, data });
+        }
         match tox_proto::deserialize::<Packet>(data) {
             Ok(packet) => {
                 match &packet {
@@ -129,15 +418,7 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                     }
                     _ => {}
                 }
-                if !self.sessions.contains_key(&from) {
-                    let s = SequenceSession::new_at(
-                        now,
-                        self.time_provider.clone(),
-                        &mut *self.engine.rng.lock(),
-                    );
-                    self.sessions.insert(from, s);
-                }
-                let session = self.sessions.get_mut(&from).unwrap();
+                let session = self.session_mut(from, now);
                 let responses = session.handle_packet(packet, now);
                 if !responses.is_empty() {
                     tracing::debug!("Generated {} responses to {:?}", responses.len(), from);
@@ -150,6 +431,8 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                     }
                 }
                 self.process_session_events(from, now);
+                // Acks may have freed room in the session.
+                self.drain_send_queue(from, now);
             }
             Err(e) => {
                 error!("Failed to deserialize packet from {:?}: {}", from, e);
@@ -170,15 +453,28 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                 None => break,
             };
 
-            if let SessionEvent::MessageCompleted(_id, _mtype, payload) = event {
+            if let SessionEvent::MessageCompleted(message_id, _mtype, payload) = event {
+                let span = tracing::debug_span!(
+                    "message",
+                    peer = %crate::event_log::short_hex(peer_pk.as_bytes()),
+                    message_id = ?message_id,
+                );
+                let _enter = span.enter();
                 tracing::debug!(
                     "Message completed from {:?}: type={:?}, len={}",
                     peer_pk,
                     _mtype,
                     payload.len()
                 );
-                match tox_proto::deserialize::<ProtocolMessage>(&payload) {
+                self.engine.record_received(peer_pk, payload.len());
+                match classify::decode_message(&payload) {
                     Ok(proto_msg) => {
+                        if let Some(observer) = &self.message_observer {
+                            observer.observe(MessageDirection::Received, peer_pk, &proto_msg);
+                        }
+                        if !self.admit(peer_pk, &proto_msg, now) {
+                            continue;
+                        }
                         match self.engine.handle_message(
                             peer_pk,
                             proto_msg,
@@ -187,6 +483,16 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                         ) {
                             Ok(effects) => {
                                 tracing::debug!("Engine generated {} effects", effects.len());
+                                // Replies arrive packet by packet; let the small
+                                // ones wait to share packets.
+                                let (replies, effects): (Vec<_>, Vec<_>) = effects
+                                    .into_iter()
+                                    .partition(|e| matches!(e, Effect::SendPacket(..)));
+                                for reply in replies {
+                                    if let Effect::SendPacket(to, msg) = reply {
+                                        self.queue_message(to, msg, now, true);
+                                    }
+                                }
                                 let mut dummy_wakeup = now;
                                 if let Err(e) =
                                     self.process_effects(effects, now, 0, &mut dummy_wakeup)
@@ -204,6 +510,7 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                             "Failed to deserialize protocol message from {:?}: {}",
                             peer_pk, e
                         );
+                        self.engine.report_spam(peer_pk, SpamOffense::DecodeFailure);
                     }
                 }
             }
@@ -216,40 +523,51 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
         let now = self.time_provider.now_instant();
         let now_ms = self.time_provider.now_system_ms() as u64;
         let mut next_wakeup = now + Duration::from_secs(3600);
+        if self.shut_down {
+            return next_wakeup;
+        }
+        self.record(now, RecordedInput::Poll);
 
         // 1. Poll Engine for background tasks (e.g., CAS swarm requests)
-        let engine_effects = match self.engine.poll(now, &self.store) {
+        let mut engine_effects = match self.engine.poll(now, &self.store) {
             Ok(res) => res,
             Err(e) => {
                 error!("Engine poll failed: {}", e);
                 Vec::new()
             }
         };
+        // Verify wire nodes left in a partly filled batch.
+        match self
+            .engine
+            .flush_verify_queue(&self.store, Some(&self.store))
+        {
+            Ok(effects) => engine_effects.extend(effects),
+            Err(e) => error!("Failed to flush verification queue: {}", e),
+        }
 
         if let Err(e) = self.process_effects(engine_effects, now, now_ms, &mut next_wakeup) {
             error!("Failed to process poll effects: {}", e);
         }
 
-        // 2. Poll Sessions for outgoing packets
+        // 2. Move queued messages into sessions with room
+        let queued: Vec<_> = self.send_queues.keys().copied().collect();
+        for peer_pk in queued {
+            self.drain_send_queue(peer_pk, now);
+        }
+
+        // 3. Poll Sessions for outgoing packets
         for (peer_pk, session) in &mut self.sessions {
             let pk = *peer_pk;
             let transport = &self.transport;
-            session.flush_packets(
-                now,
-                now_ms,
-                &mut |packet| match tox_proto::serialize(&packet) {
-                    Ok(data) => transport.send_raw(pk, data).is_ok(),
-                    Err(e) => {
-                        error!("Failed to serialize packet for {:?}: {}", pk, e);
-                        false
-                    }
-                },
-            );
+            session.set_path(path_quality(transport, &pk));
+            session.flush_packets(now, now_ms, &mut |packet| {
+                send_packet(transport, pk, &packet)
+            });
 
             // Update consensus clock offset from transport PING/PONG.
             let offset = session.clock_offset();
             if offset != 0 {
-                self.engine.clock.update_peer_offset(*peer_pk, offset);
+                self.engine.update_peer_clock_offset(*peer_pk, offset);
             }
 
             session.cleanup(now);
@@ -266,6 +584,10 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
         next_wakeup
     }
 
+    /// Processes `effects` in order. Node, wire node, ratchet key and head
+    /// writes are grouped into one `NodeStore::write_batch`; any other store
+    /// effect commits the pending group first. Events are held back until
+    /// the writes before them are committed.
     pub fn process_effects(
         &mut self,
         effects: Vec<Effect>,
@@ -273,39 +595,64 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
         now_ms: u64,
         next_wakeup: &mut Instant,
     ) -> crate::error::MerkleToxResult<()> {
+        let mut writes = Vec::new();
+        let mut events = Vec::new();
         for effect in effects {
-            self.process_effect(effect, now, now_ms, next_wakeup)?;
+            match effect {
+                Effect::WriteStore(cid, node, verified) => {
+                    writes.push(StoreWrite::Node(cid, node, verified));
+                }
+                Effect::WriteWireNode(cid, hash, node) => {
+                    writes.push(StoreWrite::WireNode(cid, hash, node));
+                }
+                Effect::WriteRatchetKey(cid, hash, key, epoch_id) => {
+                    writes.push(StoreWrite::RatchetKey(cid, hash, key, epoch_id));
+                }
+                Effect::DeleteRatchetKey(cid, hash) => {
+                    writes.push(StoreWrite::DeleteRatchetKey(cid, hash));
+                }
+                Effect::UpdateHeads(cid, heads, is_admin) => {
+                    writes.push(StoreWrite::Heads(cid, heads, is_admin));
+                }
+                Effect::EmitEvent(event) => events.push(event),
+                Effect::SendPacket(..) | Effect::ScheduleWakeup(..) => {
+                    self.process_effect(effect, now, now_ms, next_wakeup)?;
+                }
+                effect => {
+                    self.commit_writes(&mut writes, &mut events)?;
+                    self.process_effect(effect, now, now_ms, next_wakeup)?;
+                }
+            }
         }
+        self.commit_writes(&mut writes, &mut events)?;
         self.engine.clear_pending();
         Ok(())
     }
 
+    fn commit_writes(
+        &self,
+        writes: &mut Vec<StoreWrite>,
+        events: &mut Vec<crate::NodeEvent>,
+    ) -> crate::error::MerkleToxResult<()> {
+        if !writes.is_empty() {
+            self.store.write_batch(std::mem::take(writes))?;
+        }
+        for event in events.drain(..) {
+            self.emit(event);
+        }
+        Ok(())
+    }
+
     pub fn process_effect(
         &mut self,
         effect: Effect,
         now: Instant,
-        _now_ms: u64,
+        now_ms: u64,
         next_wakeup: &mut Instant,
     ) -> crate::error::MerkleToxResult<()> {
         match effect {
             Effect::SendPacket(peer_pk, msg) => {
-                if !self.sessions.contains_key(&peer_pk) {
-                    let s = SequenceSession::new_at(
-                        now,
-                        self.time_provider.clone(),
-                        &mut *self.engine.rng.lock(),
-                    );
-                    self.sessions.insert(peer_pk, s);
-                }
-                let session = self.sessions.get_mut(&peer_pk).unwrap();
-                let mtype = get_message_type(&msg);
-                if let Ok(payload) = tox_proto::serialize(&msg)
-                    && let Err(e) = session.send_message(mtype, &payload, now)
-                {
-                    error!("Failed to queue engine message: {:?}", e);
-                    // Transport queuing failure is usually non-fatal for DAG state.
-                    // Execution continues after logging.
-                }
+                self.queue_message(peer_pk, msg, now, false);
             }
             Effect::WriteStore(cid, node, verified) => {
                 self.store.put_node(&cid, node, verified)?;
@@ -342,11 +689,7 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                 self.store
                     .put_chunk(&cid, &hash, offset, &data, proof.as_deref())?;
             }
-            Effect::EmitEvent(ne) => {
-                if let Some(handler) = &self.event_handler {
-                    handler.handle_event(ne);
-                }
-            }
+            Effect::EmitEvent(ne) => self.emit(ne),
             Effect::ScheduleWakeup(_task, time) => {
                 *next_wakeup = (*next_wakeup).min(time);
             }
@@ -357,36 +700,372 @@ impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
                 // Application-layer trigger: caller should compile history snapshot,
                 // encrypt, upload to CAS, and call author_history_key_export().
             }
+            Effect::WriteScheduledMessage(msg) => {
+                self.store.put_scheduled_message(&msg)?;
+            }
+            Effect::DeleteScheduledMessage(id) => {
+                self.store.remove_scheduled_message(id)?;
+            }
+            Effect::PruneNode(cid, hash) => {
+                self.store.prune_node(&cid, &hash)?;
+            }
+            Effect::WriteHistoryHorizon(cid, min_rank) => {
+                self.store.set_history_horizon(&cid, min_rank)?;
+            }
+            Effect::WriteAckedHeads(acked) => {
+                self.store.put_acked_heads(&acked)?;
+            }
+            Effect::WriteArchivedConversation(archive) => {
+                self.store.put_archived_conversation(&archive)?;
+            }
+            Effect::WriteBlacklistEntry(entry) => {
+                self.store.put_blacklist_entry(&entry)?;
+            }
+            Effect::DeleteBlacklistEntry(device_pk) => {
+                self.store.remove_blacklist_entry(&device_pk)?;
+            }
+            Effect::WriteInviteGrant(grant) => {
+                self.store.put_invite_grant(&grant)?;
+            }
+            Effect::DeleteInviteGrant(id) => {
+                self.store.remove_invite_grant(&id)?;
+            }
+            Effect::VerifyBatch(batch) => {
+                let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+                let result = batch.verify_parallel(threads);
+                let effects =
+                    self.engine
+                        .handle_verify_result(result, &self.store, Some(&self.store))?;
+                self.process_effects(effects, now, now_ms, next_wakeup)?;
+            }
         }
         Ok(())
     }
 
-    /// Explicitly sends message to peer.
-    pub fn send_message(&mut self, to: PhysicalDevicePk, msg: ProtocolMessage) {
-        let now = self.time_provider.now_instant();
-        if !self.sessions.contains_key(&to) {
-            let s = SequenceSession::new_at(
+    /// Queues an engine message on the peer's session, coalescing it with
+    /// other small messages if `coalesce` is set and the peer negotiated
+    /// batches. Messages the session has no room for, or for an offline
+    /// peer, wait in the peer's send queue.
+    fn queue_message(
+        &mut self,
+        peer_pk: PhysicalDevicePk,
+        msg: ProtocolMessage,
+        now: Instant,
+        coalesce: bool,
+    ) {
+        let negotiated = self.engine.negotiated(&peer_pk);
+        let payload = match classify::encode_message(&msg, &negotiated) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize message for {:?}: {}", peer_pk, e);
+                return;
+            }
+        };
+        if let Some(observer) = &self.message_observer {
+            observer.observe(MessageDirection::Sent, peer_pk, &msg);
+        }
+        let waiting = self
+            .send_queues
+            .get(&peer_pk)
+            .is_some_and(|q| !q.is_empty());
+        if !waiting && !self.offline_peers.contains(&peer_pk) {
+            let session = self.session_mut(peer_pk, now);
+            let mtype = get_message_type(&msg);
+            let result = if coalesce && negotiated.has(FEATURE_BATCH) {
+                session.send_coalesced(mtype, &payload, now)
+            } else {
+                session.send_message(mtype, &payload, now).map(|_| ())
+            };
+            match result {
+                Ok(()) => {
+                    self.engine.record_sent(peer_pk, payload.len());
+                    return;
+                }
+                Err(SequencedError::QueueFull) => {}
+                Err(e) => {
+                    // Transport queuing failure is usually non-fatal for DAG state.
+                    // Execution continues after logging.
+                    error!("Failed to queue engine message: {:?}", e);
+                    return;
+                }
+            }
+        }
+        let queue = self.send_queues.entry(peer_pk).or_default();
+        if !queue.push(msg, payload.len(), now) {
+            warn!("Send queue for {:?} full; dropping message", peer_pk);
+        }
+        self.update_backpressure(peer_pk);
+    }
+
+    /// Applies the rate limits to a message from `peer_pk`. Returns false if
+    /// it must be dropped.
+    fn admit(&mut self, peer_pk: PhysicalDevicePk, msg: &ProtocolMessage, now: Instant) -> bool {
+        match self.rate_limiter.check(peer_pk, msg, now) {
+            Admission::Allowed => true,
+            Admission::Throttled => {
+                warn!("Throttling {} requests from {:?}", msg.name(), peer_pk);
+                if let Some(message) = LimitedMessage::of(msg) {
+                    self.emit(NodeEvent::PeerThrottled {
+                        device_pk: peer_pk,
+                        message,
+                    });
+                }
+                false
+            }
+            Admission::StillThrottled => {
+                debug!("Dropped {} from throttled {:?}", msg.name(), peer_pk);
+                false
+            }
+        }
+    }
+
+    /// Moves queued messages for `peer_pk` into its session while it has
+    /// room, dropping stale sync messages first.
+    fn drain_send_queue(&mut self, peer_pk: PhysicalDevicePk, now: Instant) {
+        let Some(queue) = self.send_queues.get_mut(&peer_pk) else {
+            return;
+        };
+        queue.expire(now);
+        if !self.offline_peers.contains(&peer_pk) && !queue.is_empty() {
+            self.session_mut(peer_pk, now);
+            let negotiated = self.engine.negotiated(&peer_pk);
+            let (Some(queue), Some(session)) = (
+                self.send_queues.get_mut(&peer_pk),
+                self.sessions.get_mut(&peer_pk),
+            ) else {
+                return;
+            };
+            while let Some(queued) = queue.front() {
+                let result = classify::encode_message(&queued.msg, &negotiated)
+                    .map_err(|e| SequencedError::SerializationError(e.to_string()))
+                    .and_then(|payload| {
+                        session.send_message(get_message_type(&queued.msg), &payload, now)
+                    });
+                match result {
+                    Ok(_) => {
+                        let size = queue.pop_front().map_or(0, |q| q.size);
+                        self.engine.record_sent(peer_pk, size);
+                    }
+                    Err(SequencedError::QueueFull) => break,
+                    Err(e) => {
+                        error!("Failed to queue engine message: {:?}", e);
+                        queue.pop_front();
+                    }
+                }
+            }
+        }
+        self.update_backpressure(peer_pk);
+        if self
+            .send_queues
+            .get(&peer_pk)
+            .is_some_and(SendQueue::is_empty)
+        {
+            self.send_queues.remove(&peer_pk);
+        }
+    }
+
+    /// Pauses the engine's sync tasks for a peer whose queue passes
+    /// `BACKPRESSURE_HIGH`, and resumes them below `BACKPRESSURE_LOW`.
+    fn update_backpressure(&mut self, peer_pk: PhysicalDevicePk) {
+        let len = self.send_queues.get(&peer_pk).map_or(0, SendQueue::len);
+        let paused = self.engine.backpressured_peers.contains(&peer_pk);
+        if !paused && len >= BACKPRESSURE_HIGH {
+            debug!("Pausing sync with {:?}: {} messages queued", peer_pk, len);
+            self.engine.set_peer_backpressure(peer_pk, true);
+        } else if paused && len <= BACKPRESSURE_LOW {
+            debug!("Resuming sync with {:?}", peer_pk);
+            self.engine.set_peer_backpressure(peer_pk, false);
+        }
+    }
+
+    /// Number of messages waiting in the send queue for `peer_pk`.
+    pub fn queued_messages(&self, peer_pk: &PhysicalDevicePk) -> usize {
+        self.send_queues.get(peer_pk).map_or(0, SendQueue::len)
+    }
+
+    /// The session for `peer_pk`, created if needed.
+    pub(crate) fn session_mut(
+        &mut self,
+        peer_pk: PhysicalDevicePk,
+        now: Instant,
+    ) -> &mut SequenceSession {
+        self.sessions.entry(peer_pk).or_insert_with(|| {
+            let mut s = SequenceSession::new_at(
                 now,
                 self.time_provider.clone(),
                 &mut *self.engine.rng.lock(),
             );
-            self.sessions.insert(to, s);
-        }
-        let session = self.sessions.get_mut(&to).unwrap();
-        if let Ok(payload) = tox_proto::serialize(&msg)
-            && let Err(e) = session.send_message(get_message_type(&msg), &payload, now)
-        {
-            error!("Failed to queue explicit message: {:?}", e);
+            s.set_path(path_quality(&self.transport, &peer_pk));
+            s
+        })
+    }
+
+    /// Explicitly sends message to peer.
+    pub fn send_message(&mut self, to: PhysicalDevicePk, msg: ProtocolMessage) {
+        if self.shut_down {
+            debug!("Dropping message to {:?}: node is shut down", to);
+            return;
         }
+        let now = self.time_provider.now_instant();
+        self.queue_message(to, msg, now, false);
     }
 
     /// Updates peer availability.
-    /// Removes transient reliability session when peer goes offline.
+    /// Removes transient reliability session when peer goes offline; messages
+    /// for it wait in its send queue until it is back. When it comes back,
+    /// the nodes it has not acknowledged are pushed to it.
     pub fn set_peer_available(&mut self, peer: PhysicalDevicePk, available: bool) {
-        if !available {
+        if self.recorder.is_some() {
+            let now = self.time_provider.now_instant();
+            self.record(now, RecordedInput::PeerAvailable { peer, available });
+        }
+        if available {
+            self.offline_peers.remove(&peer);
+        } else {
             self.sessions.remove(&peer);
+            self.offline_peers.insert(peer);
         }
         self.engine.set_peer_reachable(peer, available);
+
+        if available {
+            let effects = self.engine.flush_outbox(peer, &self.store);
+            let now = self.time_provider.now_instant();
+            let now_ms = self.time_provider.now_system_ms() as u64;
+            let mut next_wakeup = now;
+            if let Err(e) = self.process_effects(effects, now, now_ms, &mut next_wakeup) {
+                error!("Failed to push outbox to {:?}: {}", peer, e);
+            }
+        }
+    }
+
+    /// Shuts the node down for process exit: stops taking packets and
+    /// messages, verifies and stores the wire nodes still queued, persists
+    /// the heads peers acknowledged, sends every session's final ACKs and
+    /// flushes the store. Sessions not reached by `deadline` are skipped; the
+    /// store is flushed regardless.
+    pub fn shutdown(&mut self, deadline: Instant) -> ShutdownSummary {
+        self.shut_down = true;
+        self.recorder = None;
+        let mut summary = ShutdownSummary::default();
+        let now = self.time_provider.now_instant();
+        let now_ms = self.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+
+        summary.nodes_flushed = self.drain_verify_queue(Some(deadline));
+        summary.nodes_dropped = self.engine.verify_pipeline.pending();
+
+        let effects = self.engine.persist_acked_heads(&self.store);
+        if let Err(e) = self.process_effects(effects, now, now_ms, &mut next_wakeup) {
+            error!("Failed to persist acknowledged heads: {}", e);
+        }
+
+        for (peer_pk, session) in &mut self.sessions {
+            if self.time_provider.now_instant() >= deadline {
+                summary.deadline_exceeded = true;
+                break;
+            }
+            let transport = &self.transport;
+            session.flush_acks(now, &mut |packet| send_packet(transport, *peer_pk, &packet));
+            summary.sessions_flushed += 1;
+        }
+        summary.undelivered_messages = self
+            .sessions
+            .values()
+            .map(|s| s.outgoing_status(now).len())
+            .chain(self.send_queues.values().map(SendQueue::len))
+            .sum();
+
+        match self.store.flush() {
+            Ok(()) => summary.store_flushed = true,
+            Err(e) => error!("Failed to flush store on shutdown: {}", e),
+        }
+        debug!("Node shut down: {:?}", summary);
+        summary
+    }
+
+    /// Verifies and stores the wire nodes still queued for verification,
+    /// until `deadline` if given. Returns the number taken off the queue.
+    pub(crate) fn drain_verify_queue(&mut self, deadline: Option<Instant>) -> usize {
+        let now = self.time_provider.now_instant();
+        let now_ms = self.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+        // Batches are verified inline, so each round empties the queue
+        // unless verification fails.
+        let queued = self.engine.verify_pipeline.pending();
+        while self.engine.verify_pipeline.pending() > 0
+            && deadline.is_none_or(|d| self.time_provider.now_instant() < d)
+        {
+            let before = self.engine.verify_pipeline.pending();
+            let result = self
+                .engine
+                .flush_verify_queue(&self.store, Some(&self.store))
+                .and_then(|effects| self.process_effects(effects, now, now_ms, &mut next_wakeup));
+            if let Err(e) = result {
+                error!("Failed to verify queued nodes: {}", e);
+                break;
+            }
+            if self.engine.verify_pipeline.pending() >= before {
+                break;
+            }
+        }
+        queued.saturating_sub(self.engine.verify_pipeline.pending())
+    }
+}
+
+/// Sends `packet` to `to`. Returns whether the transport took it.
+fn send_packet<T: Transport>(transport: &T, to: PhysicalDevicePk, packet: &Packet) -> bool {
+    match tox_proto::serialize(packet) {
+        Ok(data) => transport.send_raw(to, data).is_ok(),
+        Err(e) => {
+            error!("Failed to serialize packet for {:?}: {}", to, e);
+            false
+        }
+    }
+}
+
+/// Path to `peer` as reported by the transport.
+fn path_quality<T: Transport>(transport: &T, peer: &PhysicalDevicePk) -> PathQuality {
+    PathQuality {
+        mtu: transport.path_mtu(peer),
+        relayed: transport.is_relayed(peer),
+        rtt: transport.estimated_rtt(peer),
+    }
+}
+
+/// Messages the engine regenerates from its state, safe to drop.
+fn is_sync_message(msg: &ProtocolMessage) -> bool {
+    matches!(
+        msg,
+        ProtocolMessage::SyncHeads(_)
+            | ProtocolMessage::SyncHeadsDelta(_)
+            | ProtocolMessage::SyncSketch(_)
+            | ProtocolMessage::SyncShardChecksums { .. }
+            | ProtocolMessage::SyncReconFail { .. }
+            | ProtocolMessage::SyncRateLimited { .. }
+            | ProtocolMessage::ReconPowChallenge { .. }
+            | ProtocolMessage::ReconPowSolution { .. }
+            | ProtocolMessage::FetchBatchReq(_)
+            | ProtocolMessage::BlobQuery(_)
+    )
+}
+
+/// Whether `new` makes the queued `old` pointless.
+fn supersedes(new: &ProtocolMessage, old: &ProtocolMessage) -> bool {
+    match (new, old) {
+        (ProtocolMessage::SyncHeads(new), ProtocolMessage::SyncHeads(old)) => {
+            new.conversation_id == old.conversation_id
+        }
+        (
+            ProtocolMessage::SyncShardChecksums {
+                conversation_id: new,
+                ..
+            },
+            ProtocolMessage::SyncShardChecksums {
+                conversation_id: old,
+                ..
+            },
+        ) => new == old,
+        _ => false,
     }
 }
 
@@ -412,5 +1091,7 @@ fn get_message_type(msg: &ProtocolMessage) -> MessageType {
         ProtocolMessage::ReinclusionResponse { .. } => MessageType::ReinclusionResponse,
         ProtocolMessage::HandshakeError { .. } => MessageType::HandshakeError,
         ProtocolMessage::AdminGossip { .. } => MessageType::AdminGossip,
+        ProtocolMessage::SyncHeadsDelta(_) => MessageType::SyncHeads,
+        ProtocolMessage::MerkleNodeWithHeads { .. } => MessageType::MerkleNode,
     }
 }
@@ -0,0 +1,122 @@
+//! Polls: a Poll node asks a question, PollVote nodes answer it and a
+//! PollClose node ends it early.
+//!
+//! Whether a vote is valid depends only on the vote and its ancestry, so all
+//! devices agree on it: a vote is invalid after `closes_at`, and if it
+//! descends from the close of its poll or from an earlier vote of the same
+//! identity. Concurrent votes of one identity are all valid; clients count
+//! the first in `(topological_rank, hash)` order.
+
+use crate::dag::{Content, MerkleNode, NodeHash, ValidationError};
+use crate::sync::NodeStore;
+use std::collections::HashSet;
+
+pub const MAX_POLL_OPTIONS: usize = 32;
+
+/// Checks the parts of Poll and PollVote content that need no other nodes.
+pub(crate) fn validate_content(content: &Content) -> Result<(), ValidationError> {
+    let invalid = |reason: &str| Err(ValidationError::InvalidPoll(reason.to_string()));
+    match content {
+        Content::Poll {
+            question, options, ..
+        } => {
+            if question.is_empty() {
+                return invalid("empty question");
+            }
+            if options.len() < 2 || options.len() > MAX_POLL_OPTIONS {
+                return invalid(&format!("needs 2 to {} options", MAX_POLL_OPTIONS));
+            }
+            let mut seen = HashSet::new();
+            if options.iter().any(|o| o.is_empty() || !seen.insert(o)) {
+                return invalid("options must be non-empty and distinct");
+            }
+        }
+        Content::PollVote { choices, .. } => {
+            if choices.is_empty() || !choices.is_sorted_by(|a, b| a < b) {
+                return Err(ValidationError::InvalidPollVote(
+                    "choices must be non-empty, ascending and distinct".to_string(),
+                ));
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The poll a PollVote or PollClose node refers to.
+pub fn poll_target(content: &Content) -> Option<&NodeHash> {
+    match content {
+        Content::PollVote { poll_hash, .. } | Content::PollClose { poll_hash } => Some(poll_hash),
+        _ => None,
+    }
+}
+
+/// Checks a PollVote or PollClose node against its poll, if the poll is
+/// known. Permission to close a poll is checked by the engine.
+pub(crate) fn validate_against_poll(
+    node: &MerkleNode,
+    store: &dyn NodeStore,
+) -> Result<(), ValidationError> {
+    let Some(poll_hash) = poll_target(&node.content) else {
+        return Ok(());
+    };
+    // If the poll is not found, allow speculatively (parents may arrive later).
+    let Some(poll) = store.get_node(poll_hash) else {
+        return Ok(());
+    };
+    let Content::Poll {
+        options,
+        multi_choice,
+        closes_at,
+        ..
+    } = &poll.content
+    else {
+        return Err(ValidationError::InvalidPollTarget);
+    };
+    let Content::PollVote { choices, .. } = &node.content else {
+        return Ok(());
+    };
+
+    if choices.iter().any(|&c| c as usize >= options.len()) {
+        return Err(ValidationError::InvalidPollVote(
+            "choice out of range".to_string(),
+        ));
+    }
+    if !multi_choice && choices.len() > 1 {
+        return Err(ValidationError::InvalidPollVote(
+            "poll allows a single choice".to_string(),
+        ));
+    }
+    if closes_at.is_some_and(|t| node.network_timestamp > t) {
+        return Err(ValidationError::PollClosed);
+    }
+
+    // Votes and closes of the poll are written after it, so the walk stops
+    // at its rank.
+    let mut stack = node.parents.clone();
+    let mut visited = HashSet::new();
+    while let Some(hash) = stack.pop() {
+        if !visited.insert(hash) {
+            continue;
+        }
+        let Some(ancestor) = store.get_node(&hash) else {
+            continue;
+        };
+        if ancestor.topological_rank <= poll.topological_rank {
+            continue;
+        }
+        match &ancestor.content {
+            Content::PollClose { poll_hash: p } if p == poll_hash => {
+                return Err(ValidationError::PollClosed);
+            }
+            Content::PollVote { poll_hash: p, .. }
+                if p == poll_hash && ancestor.author_pk == node.author_pk =>
+            {
+                return Err(ValidationError::DuplicatePollVote);
+            }
+            _ => {}
+        }
+        stack.extend(ancestor.parents);
+    }
+    Ok(())
+}
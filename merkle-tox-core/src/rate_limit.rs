@@ -0,0 +1,170 @@
+//! Per-peer rate limits on inbound requests that are expensive to serve.
+//!
+//! Each peer gets a token bucket per limited message kind. A request takes
+//! one token; a peer with an empty bucket has its requests dropped until the
+//! bucket refills. Dropped requests are not answered: the requester's fetch
+//! timeouts and the next sync round recover from them.
+
+use crate::ProtocolMessage;
+use crate::dag::PhysicalDevicePk;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tox_proto::time::Instant;
+
+/// Message kinds subject to rate limiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LimitedMessage {
+    FetchBatchReq,
+    BlobReq,
+    SyncSketch,
+}
+
+impl LimitedMessage {
+    /// The limited kind of `msg`, if any.
+    pub fn of(msg: &ProtocolMessage) -> Option<Self> {
+        match msg {
+            ProtocolMessage::FetchBatchReq(_) => Some(LimitedMessage::FetchBatchReq),
+            ProtocolMessage::BlobReq(_) => Some(LimitedMessage::BlobReq),
+            ProtocolMessage::SyncSketch(_) => Some(LimitedMessage::SyncSketch),
+            _ => None,
+        }
+    }
+}
+
+/// Token bucket parameters: up to `burst` requests at once, then one every
+/// `interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub interval: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, interval: Duration) -> Self {
+        Self { burst, interval }
+    }
+}
+
+/// Limits per message kind, applied to each peer separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    pub fetch_batch_req: RateLimit,
+    /// Blob downloads request several chunks per poll, so this one is
+    /// generous.
+    pub blob_req: RateLimit,
+    pub sync_sketch: RateLimit,
+}
+
+impl RateLimitPolicy {
+    pub fn limit(&self, kind: LimitedMessage) -> RateLimit {
+        match kind {
+            LimitedMessage::FetchBatchReq => self.fetch_batch_req,
+            LimitedMessage::BlobReq => self.blob_req,
+            LimitedMessage::SyncSketch => self.sync_sketch,
+        }
+    }
+}
+
+impl Default for RateLimitPolicy {
+    fn default() -> Self {
+        Self {
+            fetch_batch_req: RateLimit::new(64, Duration::from_millis(50)),
+            blob_req: RateLimit::new(256, Duration::from_millis(5)),
+            sync_sketch: RateLimit::new(16, Duration::from_secs(1)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, limit: RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let interval = limit.interval.as_secs_f64();
+        let earned = if interval > 0.0 {
+            elapsed.as_secs_f64() / interval
+        } else {
+            f64::INFINITY
+        };
+        self.tokens = (self.tokens + earned).min(f64::from(limit.burst));
+        self.last_refill = now;
+    }
+}
+
+/// Outcome of [`RateLimiter::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Not limited, or within the limit.
+    Allowed,
+    /// Over the limit; the first rejection since the peer was last allowed
+    /// this kind of request.
+    Throttled,
+    /// Over the limit again.
+    StillThrottled,
+}
+
+/// Token buckets of all peers.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    buckets: HashMap<(PhysicalDevicePk, LimitedMessage), TokenBucket>,
+    throttled: HashSet<(PhysicalDevicePk, LimitedMessage)>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    pub fn policy(&self) -> RateLimitPolicy {
+        self.policy
+    }
+
+    /// Replaces the limits. Buckets keep their tokens, capped at the new
+    /// burst on the next refill.
+    pub fn set_policy(&mut self, policy: RateLimitPolicy) {
+        self.policy = policy;
+    }
+
+    /// Takes a token for `msg` from `peer`'s bucket.
+    pub fn check(
+        &mut self,
+        peer: PhysicalDevicePk,
+        msg: &ProtocolMessage,
+        now: Instant,
+    ) -> Admission {
+        let Some(kind) = LimitedMessage::of(msg) else {
+            return Admission::Allowed;
+        };
+        let limit = self.policy.limit(kind);
+        let bucket = self
+            .buckets
+            .entry((peer, kind))
+            .or_insert_with(|| TokenBucket {
+                tokens: f64::from(limit.burst),
+                last_refill: now,
+            });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            self.throttled.remove(&(peer, kind));
+            Admission::Allowed
+        } else if self.throttled.insert((peer, kind)) {
+            Admission::Throttled
+        } else {
+            Admission::StillThrottled
+        }
+    }
+
+    /// Whether `peer` is currently over its limit for `kind`.
+    pub fn is_throttled(&self, peer: &PhysicalDevicePk, kind: LimitedMessage) -> bool {
+        self.throttled.contains(&(*peer, kind))
+    }
+}
@@ -0,0 +1,265 @@
+//! Recording and replay of a node's inputs, for reproducing bug reports.
+//!
+//! The node does no IO of its own: its state follows from the packets it
+//! receives, the times it is polled at and its RNG. A recording holds those
+//! inputs; replaying it against a copy of the store the node started from
+//! rebuilds the same engine state, step by step, in a test or a debugger.
+//!
+//! Layout: `[magic "MTXR"][version u8]`, then frames of
+//! `[length u32 LE][ToxProto value]`: one [`RecordingHeader`] followed by
+//! [`Record`]s. A frame cut short by a crash ends the recording.
+//!
+//! Recording starts from the node's current state, so enable it right after
+//! creating the node. Nodes authored locally through the engine are not
+//! recorded; they reach the replayed node only if they are in the store.
+//! The header holds the device's secret keys: handle a recording like the
+//! keys themselves.
+
+use crate::clock::ManualTimeProvider;
+use crate::dag::{
+    ConversationId, LogicalIdentityPk, PhysicalDeviceDhSk, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use crate::engine::MerkleToxEngine;
+use crate::error::{MerkleToxError, MerkleToxResult};
+use crate::node::MerkleToxNode;
+use crate::sync::{BlobStore, NodeStore};
+use crate::{Transport, TransportError};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tox_proto::ToxProto;
+use tox_proto::time::Instant;
+use tracing::warn;
+
+const RECORDING_MAGIC: &[u8; 4] = b"MTXR";
+const RECORDING_VERSION: u8 = 1;
+
+/// Identity and starting state of the recorded node.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct RecordingHeader {
+    pub self_pk: PhysicalDevicePk,
+    pub self_logical_pk: LogicalIdentityPk,
+    pub self_sk: Option<PhysicalDeviceSk>,
+    pub self_dh_sk: Option<PhysicalDeviceDhSk>,
+    /// Seed the engine's RNG was reset to when recording started.
+    pub rng_seed: u64,
+    /// System time in milliseconds when recording started.
+    pub system_ms: i64,
+    /// Conversations the engine had loaded, loaded again on replay.
+    pub conversations: Vec<ConversationId>,
+}
+
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub enum RecordedInput {
+    /// `MerkleToxNode::handle_packet`.
+    Packet {
+        from: PhysicalDevicePk,
+        data: Vec<u8>,
+    },
+    /// `MerkleToxNode::poll`, i.e. a timer firing.
+    Poll,
+    /// `MerkleToxNode::set_peer_available`.
+    PeerAvailable {
+        peer: PhysicalDevicePk,
+        available: bool,
+    },
+}
+
+/// One input and the time it arrived at.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct Record {
+    /// Monotonic time since recording started, in microseconds.
+    pub elapsed_us: u64,
+    pub system_ms: i64,
+    pub input: RecordedInput,
+}
+
+/// Writes the inputs of a node to a recording file.
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Creates the file at `path` and writes the header. `start` is the
+    /// monotonic time `header.system_ms` was read at.
+    pub fn create(path: &Path, header: &RecordingHeader, start: Instant) -> MerkleToxResult<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(RECORDING_MAGIC)?;
+        out.write_all(&[RECORDING_VERSION])?;
+        let mut recorder = Self { out, start };
+        recorder.write_frame(&tox_proto::serialize(header)?)?;
+        Ok(recorder)
+    }
+
+    /// Appends `input`, received at `now`. Each record is flushed, so a
+    /// recording survives a crash of the node.
+    pub fn record(
+        &mut self,
+        now: Instant,
+        system_ms: i64,
+        input: RecordedInput,
+    ) -> MerkleToxResult<()> {
+        let record = Record {
+            elapsed_us: now.saturating_duration_since(self.start).as_micros() as u64,
+            system_ms,
+            input,
+        };
+        self.write_frame(&tox_proto::serialize(&record)?)
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> MerkleToxResult<()> {
+        self.out.write_all(&(frame.len() as u32).to_le_bytes())?;
+        self.out.write_all(frame)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the recording at `path`.
+pub fn read_recording(path: &Path) -> MerkleToxResult<(RecordingHeader, Vec<Record>)> {
+    let bytes = std::fs::read(path)?;
+    let prefix = RECORDING_MAGIC.len() + 1;
+    if bytes.len() < prefix || &bytes[..RECORDING_MAGIC.len()] != RECORDING_MAGIC {
+        return Err(MerkleToxError::Other("Not a node recording".to_string()));
+    }
+    if bytes[RECORDING_MAGIC.len()] != RECORDING_VERSION {
+        return Err(MerkleToxError::Other(format!(
+            "Unsupported recording version {}",
+            bytes[RECORDING_MAGIC.len()]
+        )));
+    }
+
+    let mut frames = Vec::new();
+    let mut rest = &bytes[prefix..];
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() - 4 < len {
+            break;
+        }
+        frames.push(&rest[4..4 + len]);
+        rest = &rest[4 + len..];
+    }
+
+    let mut frames = frames.into_iter();
+    let header = frames
+        .next()
+        .ok_or_else(|| MerkleToxError::Other("Recording has no header".to_string()))?;
+    let header = tox_proto::deserialize(header)?;
+    let records = frames
+        .map(tox_proto::deserialize)
+        .collect::<Result<Vec<Record>, _>>()?;
+    Ok((header, records))
+}
+
+/// Transport of a replayed node. Sends nothing; keeps what the node sent.
+pub struct ReplayTransport {
+    pub local_pk: PhysicalDevicePk,
+    pub sent: Mutex<Vec<(PhysicalDevicePk, Vec<u8>)>>,
+}
+
+impl Transport for ReplayTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.local_pk
+    }
+
+    fn send_raw(&self, to: PhysicalDevicePk, data: Vec<u8>) -> Result<(), TransportError> {
+        self.sent.lock().push((to, data));
+        Ok(())
+    }
+}
+
+impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
+    /// Records every packet, poll and peer availability change to `path`,
+    /// for [`MerkleToxNode::replay`]. Resets the engine's RNG to a seed kept
+    /// in the recording. Replaces a recording in progress.
+    pub fn enable_recording(&mut self, path: impl AsRef<Path>) -> MerkleToxResult<()> {
+        let rng_seed = self.engine.rng.lock().next_u64();
+        let mut conversations: Vec<ConversationId> =
+            self.engine.conversations.keys().copied().collect();
+        conversations.sort();
+        let header = RecordingHeader {
+            self_pk: self.engine.self_pk,
+            self_logical_pk: self.engine.self_logical_pk,
+            self_sk: self.engine.self_sk.clone(),
+            self_dh_sk: self.engine.self_dh_sk.clone(),
+            rng_seed,
+            system_ms: self.time_provider.now_system_ms(),
+            conversations,
+        };
+        let start = self.time_provider.now_instant();
+        self.recorder = Some(Recorder::create(path.as_ref(), &header, start)?);
+        *self.engine.rng.lock() = StdRng::seed_from_u64(rng_seed);
+        Ok(())
+    }
+
+    /// Stops recording.
+    pub fn disable_recording(&mut self) {
+        self.recorder = None;
+    }
+
+    /// Appends `input` to the recording, if one is in progress. Recording
+    /// stops if writing fails.
+    pub(crate) fn record(&mut self, now: Instant, input: RecordedInput) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.record(now, self.time_provider.now_system_ms(), input) {
+            warn!("Recording stopped: {}", e);
+            self.recorder = None;
+        }
+    }
+}
+
+impl<S: NodeStore + BlobStore> MerkleToxNode<ReplayTransport, S> {
+    /// Rebuilds a node from the recording at `path`. `store` must hold what
+    /// the recorded node's store held when recording started. Every input is
+    /// fed again at its recorded time; packets the node sends are kept in
+    /// `transport.sent`.
+    pub fn replay(path: impl AsRef<Path>, store: S) -> MerkleToxResult<Self> {
+        let (header, records) = read_recording(path.as_ref())?;
+        let start = Instant::now();
+        let time = Arc::new(ManualTimeProvider::new(start, header.system_ms));
+
+        let mut engine = MerkleToxEngine::new(
+            header.self_pk,
+            header.self_logical_pk,
+            StdRng::seed_from_u64(header.rng_seed),
+            time.clone(),
+        );
+        engine.self_sk = header.self_sk;
+        engine.self_dh_sk = header.self_dh_sk;
+        let transport = ReplayTransport {
+            local_pk: header.self_pk,
+            sent: Mutex::new(Vec::new()),
+        };
+        let mut node = Self::new(engine, transport, store, time.clone());
+        for conversation_id in header.conversations {
+            node.engine
+                .load_conversation_state(conversation_id, &node.store)?;
+        }
+        *node.engine.rng.lock() = StdRng::seed_from_u64(header.rng_seed);
+
+        for record in records {
+            time.set_time(
+                start + Duration::from_micros(record.elapsed_us),
+                record.system_ms,
+            );
+            match record.input {
+                RecordedInput::Packet { from, data } => node.handle_packet(from, &data),
+                RecordedInput::Poll => {
+                    node.poll();
+                }
+                RecordedInput::PeerAvailable { peer, available } => {
+                    node.set_peer_available(peer, available)
+                }
+            }
+        }
+        Ok(node)
+    }
+}
@@ -0,0 +1,175 @@
+//! Filtered subscriptions to node events.
+//!
+//! Besides its single [`NodeEventHandler`](crate::NodeEventHandler), a node
+//! hands its events to any number of subscribers, each of which only
+//! receives the events matching its [`EventFilter`]. A subscription ends when
+//! its receiver is dropped; the node forgets it the next time an event
+//! matches it.
+
+use crate::NodeEvent;
+use crate::dag::ConversationId;
+use parking_lot::Mutex;
+use std::sync::mpsc;
+
+/// The variant of a [`NodeEvent`], without its data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    NodeVerified,
+    NodeSpeculative,
+    NodeIdentityPending,
+    NodeInvalidated,
+    NodeExpired,
+    NodeDelivered,
+    Mentioned,
+    MemberJoined,
+    MemberLeft,
+    PeerHandshakeComplete,
+    BlobProgress,
+    BlobAvailable,
+    ClockSkewWarning,
+    EpochRotated,
+    ChannelCreated,
+    CompromiseRecovered,
+    PeerBlacklisted,
+    PeerThrottled,
+}
+
+impl NodeEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            NodeEvent::NodeVerified { .. } => EventKind::NodeVerified,
+            NodeEvent::NodeSpeculative { .. } => EventKind::NodeSpeculative,
+            NodeEvent::NodeIdentityPending { .. } => EventKind::NodeIdentityPending,
+            NodeEvent::NodeInvalidated { .. } => EventKind::NodeInvalidated,
+            NodeEvent::NodeExpired { .. } => EventKind::NodeExpired,
+            NodeEvent::NodeDelivered { .. } => EventKind::NodeDelivered,
+            NodeEvent::Mentioned { .. } => EventKind::Mentioned,
+            NodeEvent::MemberJoined { .. } => EventKind::MemberJoined,
+            NodeEvent::MemberLeft { .. } => EventKind::MemberLeft,
+            NodeEvent::PeerHandshakeComplete { .. } => EventKind::PeerHandshakeComplete,
+            NodeEvent::BlobProgress { .. } => EventKind::BlobProgress,
+            NodeEvent::BlobAvailable { .. } => EventKind::BlobAvailable,
+            NodeEvent::ClockSkewWarning { .. } => EventKind::ClockSkewWarning,
+            NodeEvent::EpochRotated { .. } => EventKind::EpochRotated,
+            NodeEvent::ChannelCreated { .. } => EventKind::ChannelCreated,
+            NodeEvent::CompromiseRecovered(_) => EventKind::CompromiseRecovered,
+            NodeEvent::PeerBlacklisted { .. } => EventKind::PeerBlacklisted,
+            NodeEvent::PeerThrottled { .. } => EventKind::PeerThrottled,
+        }
+    }
+
+    /// The conversation the event is about, if it is about one.
+    pub fn conversation_id(&self) -> Option<ConversationId> {
+        match self {
+            NodeEvent::NodeVerified {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeSpeculative {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeIdentityPending {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeInvalidated {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeExpired {
+                conversation_id, ..
+            }
+            | NodeEvent::NodeDelivered {
+                conversation_id, ..
+            }
+            | NodeEvent::Mentioned {
+                conversation_id, ..
+            }
+            | NodeEvent::MemberJoined {
+                conversation_id, ..
+            }
+            | NodeEvent::MemberLeft {
+                conversation_id, ..
+            }
+            | NodeEvent::EpochRotated {
+                conversation_id, ..
+            }
+            | NodeEvent::ChannelCreated {
+                conversation_id, ..
+            } => Some(*conversation_id),
+            NodeEvent::CompromiseRecovered(recovery) => Some(recovery.conversation_id),
+            NodeEvent::PeerHandshakeComplete { .. }
+            | NodeEvent::BlobProgress { .. }
+            | NodeEvent::BlobAvailable { .. }
+            | NodeEvent::ClockSkewWarning { .. }
+            | NodeEvent::PeerBlacklisted { .. }
+            | NodeEvent::PeerThrottled { .. } => None,
+        }
+    }
+}
+
+/// Which events a subscriber receives. The default filter matches all
+/// events.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Only events about this conversation. Events not about any
+    /// conversation (peer, blob and clock events) do not match.
+    pub conversation: Option<ConversationId>,
+    /// Only events of these kinds; any kind if empty.
+    pub kinds: Vec<EventKind>,
+}
+
+impl EventFilter {
+    /// Events about `conversation_id`, of any kind.
+    pub fn conversation(conversation_id: ConversationId) -> Self {
+        Self {
+            conversation: Some(conversation_id),
+            kinds: Vec::new(),
+        }
+    }
+
+    /// Events of the given kinds, about any conversation.
+    pub fn kinds(kinds: impl IntoIterator<Item = EventKind>) -> Self {
+        Self {
+            conversation: None,
+            kinds: kinds.into_iter().collect(),
+        }
+    }
+
+    pub fn matches(&self, event: &NodeEvent) -> bool {
+        if let Some(cid) = &self.conversation
+            && event.conversation_id().as_ref() != Some(cid)
+        {
+            return false;
+        }
+        self.kinds.is_empty() || self.kinds.contains(&event.kind())
+    }
+}
+
+/// The subscribers of a node.
+#[derive(Default)]
+pub struct Subscribers {
+    subscribers: Mutex<Vec<(EventFilter, mpsc::Sender<NodeEvent>)>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&self, filter: EventFilter) -> mpsc::Receiver<NodeEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().push((filter, tx));
+        rx
+    }
+
+    /// Sends `event` to the matching subscribers, dropping those whose
+    /// receiver is gone.
+    pub fn publish(&self, event: &NodeEvent) {
+        self.subscribers
+            .lock()
+            .retain(|(filter, tx)| !filter.matches(event) || tx.send(event.clone()).is_ok());
+    }
+
+    /// Number of subscriptions, including ended ones not yet noticed.
+    pub fn len(&self) -> usize {
+        self.subscribers.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,249 @@
+//! Suspend and resume of a node's transient state, for mobile apps that the
+//! OS kills between push wakeups.
+//!
+//! The store holds the DAG, but sync progress lives in memory: the nodes a
+//! peer is known to have that are still to be fetched, the peers seeding a
+//! blob being downloaded, and the RTT and MTU measured on each path.
+//! [`MerkleToxNode::suspend`] writes these to the store as a
+//! [`SuspendedState`]; [`MerkleToxNode::resume`] on a new node over the same
+//! store picks up from there instead of starting over.
+//!
+//! Sessions resume in the handshake: the peer may have restarted too, so
+//! capabilities are exchanged again, but fetching continues from the saved
+//! queues. Fetches in flight are lost with the process and are queued first.
+//! The engine's pending cache only lives for one engine call and the node
+//! commits it after each, so there is nothing to save from it; wire nodes
+//! queued for verification are verified and stored instead. Congestion
+//! windows restart from slow start, as after any idle period.
+
+use crate::Transport;
+use crate::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use crate::engine::Effect;
+use crate::engine::session::PeerSession;
+use crate::error::MerkleToxResult;
+use crate::node::MerkleToxNode;
+use crate::sync::{BlobStore, NodeStore};
+use std::collections::VecDeque;
+use tox_proto::ToxProto;
+use tox_sequenced::PathEstimates;
+use tracing::debug;
+
+/// Transient state of a suspended node, kept in the store until resumed.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct SuspendedState {
+    /// System time in milliseconds when the node was suspended.
+    pub suspended_at_ms: i64,
+    pub sessions: Vec<SuspendedSession>,
+    pub blob_downloads: Vec<SuspendedBlobDownload>,
+    pub paths: Vec<SuspendedPath>,
+}
+
+/// Progress of a sync session with one peer in one conversation.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct SuspendedSession {
+    pub peer_pk: PhysicalDevicePk,
+    pub conversation_id: ConversationId,
+    pub shallow: bool,
+    pub min_rank: u64,
+    pub min_timestamp: i64,
+    pub max_backfill_nodes: u64,
+    pub backfill_count: u64,
+    pub remote_heads: Vec<NodeHash>,
+    pub remote_anchor_hash: Option<NodeHash>,
+    /// Missing nodes not yet in the store, in fetch order.
+    pub missing_admin_nodes: Vec<NodeHash>,
+    pub missing_nodes_hot: Vec<NodeHash>,
+    pub missing_nodes_cold: Vec<NodeHash>,
+    pub missing_blobs: Vec<NodeHash>,
+}
+
+/// Seeders of a blob being downloaded. The chunks fetched so far are in the
+/// blob's `BlobInfo`.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct SuspendedBlobDownload {
+    pub hash: NodeHash,
+    pub seeders: Vec<PhysicalDevicePk>,
+}
+
+/// What the reliability session with a peer measured about the path.
+#[derive(Debug, Clone, ToxProto, PartialEq)]
+pub struct SuspendedPath {
+    pub peer_pk: PhysicalDevicePk,
+    pub estimates: PathEstimates,
+}
+
+impl SuspendedSession {
+    fn new(
+        peer_pk: PhysicalDevicePk,
+        conversation_id: ConversationId,
+        session: &PeerSession,
+        store: &dyn NodeStore,
+    ) -> Self {
+        let common = session.common();
+        let missing = |queue: &VecDeque<NodeHash>| -> Vec<NodeHash> {
+            queue
+                .iter()
+                .copied()
+                .filter(|h| !store.has_node(h))
+                .collect()
+        };
+        let mut in_flight: Vec<NodeHash> = common
+            .in_flight_fetches
+            .iter()
+            .copied()
+            .filter(|h| !store.has_node(h))
+            .collect();
+        in_flight.sort();
+        in_flight.extend(missing(&common.missing_nodes_hot));
+
+        let mut remote_heads: Vec<NodeHash> = common.remote_heads.iter().copied().collect();
+        remote_heads.sort();
+        let mut missing_blobs: Vec<NodeHash> = common.missing_blobs.iter().copied().collect();
+        missing_blobs.sort();
+
+        Self {
+            peer_pk,
+            conversation_id,
+            shallow: common.shallow,
+            min_rank: common.min_rank,
+            min_timestamp: common.min_timestamp,
+            max_backfill_nodes: common.max_backfill_nodes,
+            backfill_count: common.backfill_count,
+            remote_heads,
+            remote_anchor_hash: common.remote_anchor_hash,
+            missing_admin_nodes: missing(&common.missing_admin_nodes),
+            missing_nodes_hot: in_flight,
+            missing_nodes_cold: missing(&common.missing_nodes_cold),
+            missing_blobs,
+        }
+    }
+}
+
+impl<T: Transport, S: NodeStore + BlobStore> MerkleToxNode<T, S> {
+    /// Saves the node's transient state to the store, for [`resume`] after
+    /// the process is killed: sync session progress, blob download progress
+    /// and seeders, and path estimates. Wire nodes queued for verification
+    /// are verified and stored first, and the store is flushed. The node
+    /// keeps running; a node that is not killed need not resume.
+    ///
+    /// [`resume`]: MerkleToxNode::resume
+    pub fn suspend(&mut self) -> MerkleToxResult<()> {
+        let now = self.time_provider.now_instant();
+        let now_ms = self.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+        self.drain_verify_queue(None);
+
+        let mut effects = Vec::new();
+        let mut blob_downloads = Vec::new();
+        for (hash, sync) in &self.engine.blob_syncs {
+            effects.push(Effect::WriteBlobInfo(sync.progress_info()));
+            let mut seeders: Vec<PhysicalDevicePk> = sync.seeders.iter().copied().collect();
+            seeders.sort();
+            blob_downloads.push(SuspendedBlobDownload {
+                hash: *hash,
+                seeders,
+            });
+        }
+        blob_downloads.sort_by_key(|d| d.hash);
+        effects.extend(self.engine.persist_acked_heads(&self.store));
+        self.process_effects(effects, now, now_ms, &mut next_wakeup)?;
+
+        let mut sessions: Vec<SuspendedSession> = self
+            .engine
+            .sessions
+            .iter()
+            .map(|((peer_pk, cid), session)| {
+                SuspendedSession::new(*peer_pk, *cid, session, &self.store)
+            })
+            .collect();
+        sessions.sort_by_key(|s| (s.peer_pk, s.conversation_id));
+        let mut paths: Vec<SuspendedPath> = self
+            .sessions
+            .iter()
+            .map(|(peer_pk, session)| SuspendedPath {
+                peer_pk: *peer_pk,
+                estimates: session.estimates(),
+            })
+            .collect();
+        paths.sort_by_key(|p| p.peer_pk);
+
+        let state = SuspendedState {
+            suspended_at_ms: self.time_provider.now_system_ms(),
+            sessions,
+            blob_downloads,
+            paths,
+        };
+        debug!(
+            "Suspending node: {} sessions, {} blob downloads",
+            state.sessions.len(),
+            state.blob_downloads.len()
+        );
+        self.store.put_suspended_state(&state)?;
+        self.store.flush()
+    }
+
+    /// Restores the state saved by [`suspend`] and removes it from the store.
+    /// Sessions start a new handshake with their peer and continue fetching
+    /// where they stopped; sessions already running are left alone. Load
+    /// the node's conversations first. Returns false if there was nothing to
+    /// resume.
+    ///
+    /// [`suspend`]: MerkleToxNode::suspend
+    pub fn resume(&mut self) -> MerkleToxResult<bool> {
+        let Some(state) = self.store.get_suspended_state() else {
+            return Ok(false);
+        };
+        let now = self.time_provider.now_instant();
+        let now_ms = self.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+
+        // Seeded before the handshake goes out through them.
+        for path in state.paths {
+            self.session_mut(path.peer_pk, now)
+                .seed_estimates(path.estimates);
+        }
+
+        let mut effects = Vec::new();
+        for saved in state.sessions {
+            let key = (saved.peer_pk, saved.conversation_id);
+            let running = self.engine.sessions.contains_key(&key);
+            effects.extend(self.engine.start_shallow_sync(
+                saved.conversation_id,
+                Some(saved.peer_pk),
+                &self.store,
+                saved.min_rank,
+                saved.min_timestamp,
+            ));
+            if running {
+                continue;
+            }
+            let Some(session) = self.engine.sessions.get_mut(&key) else {
+                continue;
+            };
+            let common = session.common_mut();
+            common.shallow = saved.shallow;
+            common.max_backfill_nodes = saved.max_backfill_nodes;
+            common.backfill_count = saved.backfill_count;
+            common.remote_heads = saved.remote_heads.into_iter().collect();
+            common.remote_anchor_hash = saved.remote_anchor_hash;
+            common.missing_admin_nodes = saved.missing_admin_nodes.into();
+            common.missing_nodes_hot = saved.missing_nodes_hot.into();
+            common.missing_nodes_cold = saved.missing_nodes_cold.into();
+            common.missing_blobs = saved.missing_blobs.into_iter().collect();
+        }
+
+        self.engine.resume_blob_downloads(&self.store);
+        for download in state.blob_downloads {
+            if let Some(sync) = self.engine.blob_syncs.get_mut(&download.hash) {
+                for seeder in download.seeders {
+                    sync.add_seeder(seeder);
+                }
+            }
+        }
+
+        self.process_effects(effects, now, now_ms, &mut next_wakeup)?;
+        self.store.remove_suspended_state()?;
+        debug!("Resumed node suspended at {} ms", state.suspended_at_ms);
+        Ok(true)
+    }
+}
@@ -0,0 +1,549 @@
+//! In-memory cache in front of a [`NodeStore`].
+//!
+//! Verification and the client look up the same recent nodes over and over.
+//! [`CachedStore`] keeps the most recently used decoded nodes and child
+//! lookups so those calls do not reach the disk or database.
+
+use crate::cas::BlobInfo;
+use crate::dag::{
+    ChainKey, ConversationId, KConv, LogicalIdentityPk, MerkleNode, NodeHash, NodeLookup, NodeType,
+    PhysicalDevicePk, WireNode,
+};
+use crate::engine::archive::ArchivedConversation;
+use crate::engine::outbox::AckedHeads;
+use crate::engine::quarantine::BlacklistEntry;
+use crate::engine::scheduled::ScheduledMessage;
+use crate::error::MerkleToxResult;
+use crate::invite::{InviteGrant, InviteId};
+use crate::suspend::SuspendedState;
+use crate::sync::{
+    BlobStore, GlobalStore, NodeStore, ReconciliationStore, StateSnapshot, StoreWrite, SyncRange,
+};
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::num::NonZeroUsize;
+
+/// Number of nodes kept by [`CachedStore::new`].
+pub const DEFAULT_NODE_CACHE_SIZE: usize = 4096;
+
+struct Caches {
+    nodes: LruCache<NodeHash, MerkleNode>,
+    has_children: LruCache<NodeHash, bool>,
+    /// Bumped by every write. A lookup only caches its result if no write
+    /// happened while it was reading from the inner store.
+    generation: u64,
+}
+
+/// Wraps a store with a size-bounded LRU cache of nodes and child lookups.
+///
+/// Entries are invalidated by writes made through the wrapper; writes made
+/// to the inner store directly are not seen.
+pub struct CachedStore<S> {
+    inner: S,
+    caches: Mutex<Caches>,
+}
+
+impl<S: NodeStore> CachedStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self::with_capacity(inner, DEFAULT_NODE_CACHE_SIZE)
+    }
+
+    /// Caches up to `capacity` nodes and `capacity` child lookups.
+    pub fn with_capacity(inner: S, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            inner,
+            caches: Mutex::new(Caches {
+                nodes: LruCache::new(capacity),
+                has_children: LruCache::new(capacity),
+                generation: 0,
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Number of nodes currently cached.
+    pub fn cached_nodes(&self) -> usize {
+        self.caches.lock().nodes.len()
+    }
+
+    /// Drops all cached entries.
+    pub fn clear(&self) {
+        let mut caches = self.caches.lock();
+        caches.nodes.clear();
+        caches.has_children.clear();
+        caches.generation += 1;
+    }
+
+    /// Drops cached entries for `hashes`. Called after a write, whether it
+    /// succeeded or not.
+    fn invalidate(&self, hashes: impl IntoIterator<Item = NodeHash>) {
+        let mut caches = self.caches.lock();
+        for hash in hashes {
+            caches.nodes.pop(&hash);
+            caches.has_children.pop(&hash);
+        }
+        caches.generation += 1;
+    }
+}
+
+/// A written node and its parents, whose child lookups change.
+fn touched(node: &MerkleNode) -> impl Iterator<Item = NodeHash> + '_ {
+    std::iter::once(node.hash()).chain(node.parents.iter().copied())
+}
+
+impl<S: NodeStore> NodeLookup for CachedStore<S> {
+    fn get_node_type(&self, hash: &NodeHash) -> Option<NodeType> {
+        self.inner.get_node_type(hash)
+    }
+    fn get_rank(&self, hash: &NodeHash) -> Option<u64> {
+        self.inner.get_rank(hash)
+    }
+    fn get_admin_distance(&self, hash: &NodeHash) -> Option<u64> {
+        self.inner.get_admin_distance(hash)
+    }
+    fn contains_node(&self, hash: &NodeHash) -> bool {
+        self.inner.contains_node(hash)
+    }
+    fn has_children(&self, hash: &NodeHash) -> bool {
+        let generation = {
+            let mut caches = self.caches.lock();
+            if let Some(has_children) = caches.has_children.get(hash) {
+                return *has_children;
+            }
+            caches.generation
+        };
+        let has_children = self.inner.has_children(hash);
+        let mut caches = self.caches.lock();
+        if caches.generation == generation {
+            caches.has_children.put(*hash, has_children);
+        }
+        has_children
+    }
+    fn get_soft_anchor_chain_length(&self, hash: &NodeHash) -> Option<u64> {
+        self.inner.get_soft_anchor_chain_length(hash)
+    }
+}
+
+impl<S: NodeStore> NodeStore for CachedStore<S> {
+    fn get_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash> {
+        self.inner.get_heads(conversation_id)
+    }
+    fn set_heads(
+        &self,
+        conversation_id: &ConversationId,
+        heads: Vec<NodeHash>,
+    ) -> MerkleToxResult<()> {
+        self.inner.set_heads(conversation_id, heads)
+    }
+    fn get_admin_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash> {
+        self.inner.get_admin_heads(conversation_id)
+    }
+    fn set_admin_heads(
+        &self,
+        conversation_id: &ConversationId,
+        heads: Vec<NodeHash>,
+    ) -> MerkleToxResult<()> {
+        self.inner.set_admin_heads(conversation_id, heads)
+    }
+    fn has_node(&self, hash: &NodeHash) -> bool {
+        self.inner.has_node(hash)
+    }
+    fn is_verified(&self, hash: &NodeHash) -> bool {
+        self.inner.is_verified(hash)
+    }
+    fn get_node(&self, hash: &NodeHash) -> Option<MerkleNode> {
+        let generation = {
+            let mut caches = self.caches.lock();
+            if let Some(node) = caches.nodes.get(hash) {
+                return Some(node.clone());
+            }
+            caches.generation
+        };
+        let node = self.inner.get_node(hash)?;
+        let mut caches = self.caches.lock();
+        if caches.generation == generation {
+            caches.nodes.put(*hash, node.clone());
+        }
+        Some(node)
+    }
+    fn get_wire_node(&self, hash: &NodeHash) -> Option<WireNode> {
+        self.inner.get_wire_node(hash)
+    }
+    fn put_node(
+        &self,
+        conversation_id: &ConversationId,
+        node: MerkleNode,
+        verified: bool,
+    ) -> MerkleToxResult<()> {
+        let hashes: Vec<NodeHash> = touched(&node).collect();
+        let result = self.inner.put_node(conversation_id, node, verified);
+        self.invalidate(hashes);
+        result
+    }
+    fn put_nodes(
+        &self,
+        conversation_id: &ConversationId,
+        nodes: Vec<(MerkleNode, bool)>,
+    ) -> MerkleToxResult<()> {
+        let hashes: Vec<NodeHash> = nodes.iter().flat_map(|(node, _)| touched(node)).collect();
+        let result = self.inner.put_nodes(conversation_id, nodes);
+        self.invalidate(hashes);
+        result
+    }
+    fn get_nodes(&self, hashes: &[NodeHash]) -> Vec<Option<MerkleNode>> {
+        let mut missing = Vec::new();
+        let (mut nodes, generation) = {
+            let mut caches = self.caches.lock();
+            let nodes: Vec<Option<MerkleNode>> = hashes
+                .iter()
+                .enumerate()
+                .map(|(i, hash)| {
+                    let node = caches.nodes.get(hash).cloned();
+                    if node.is_none() {
+                        missing.push(i);
+                    }
+                    node
+                })
+                .collect();
+            (nodes, caches.generation)
+        };
+        if missing.is_empty() {
+            return nodes;
+        }
+
+        let missing_hashes: Vec<NodeHash> = missing.iter().map(|&i| hashes[i]).collect();
+        let fetched = self.inner.get_nodes(&missing_hashes);
+        let mut caches = self.caches.lock();
+        let cache = caches.generation == generation;
+        for (i, node) in missing.into_iter().zip(fetched) {
+            if cache && let Some(node) = &node {
+                caches.nodes.put(hashes[i], node.clone());
+            }
+            nodes[i] = node;
+        }
+        nodes
+    }
+    fn put_wire_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+        node: WireNode,
+    ) -> MerkleToxResult<()> {
+        self.inner.put_wire_node(conversation_id, hash, node)
+    }
+    fn remove_wire_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.inner.remove_wire_node(conversation_id, hash)
+    }
+    fn get_speculative_nodes(&self, conversation_id: &ConversationId) -> Vec<MerkleNode> {
+        self.inner.get_speculative_nodes(conversation_id)
+    }
+    fn mark_verified(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.inner.mark_verified(conversation_id, hash)
+    }
+    fn get_last_sequence_number(
+        &self,
+        conversation_id: &ConversationId,
+        sender_pk: &PhysicalDevicePk,
+    ) -> u64 {
+        self.inner
+            .get_last_sequence_number(conversation_id, sender_pk)
+    }
+    fn get_node_counts(&self, conversation_id: &ConversationId) -> (usize, usize) {
+        self.inner.get_node_counts(conversation_id)
+    }
+    fn get_verified_nodes_by_type(
+        &self,
+        conversation_id: &ConversationId,
+        node_type: NodeType,
+    ) -> MerkleToxResult<Vec<MerkleNode>> {
+        self.inner
+            .get_verified_nodes_by_type(conversation_id, node_type)
+    }
+    fn get_node_hashes_in_range(
+        &self,
+        conversation_id: &ConversationId,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Vec<NodeHash>> {
+        self.inner.get_node_hashes_in_range(conversation_id, range)
+    }
+    fn get_nodes_by_author(
+        &self,
+        conversation_id: &ConversationId,
+        author_pk: &LogicalIdentityPk,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Vec<MerkleNode>> {
+        self.inner
+            .get_nodes_by_author(conversation_id, author_pk, range)
+    }
+    fn get_nodes_by_time_range(
+        &self,
+        conversation_id: &ConversationId,
+        from_ms: i64,
+        to_ms: i64,
+        limit: usize,
+    ) -> MerkleToxResult<Vec<MerkleNode>> {
+        self.inner
+            .get_nodes_by_time_range(conversation_id, from_ms, to_ms, limit)
+    }
+    fn prune_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<bool> {
+        let result = self.inner.prune_node(conversation_id, hash);
+        self.invalidate([*hash]);
+        result
+    }
+    fn get_opaque_node_hashes(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Vec<NodeHash>> {
+        self.inner.get_opaque_node_hashes(conversation_id)
+    }
+    fn size_bytes(&self) -> u64 {
+        self.inner.size_bytes()
+    }
+    fn put_conversation_key(
+        &self,
+        conversation_id: &ConversationId,
+        epoch: u64,
+        k_conv: KConv,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .put_conversation_key(conversation_id, epoch, k_conv)
+    }
+    fn get_conversation_keys(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Vec<(u64, KConv)>> {
+        self.inner.get_conversation_keys(conversation_id)
+    }
+    fn update_epoch_metadata(
+        &self,
+        conversation_id: &ConversationId,
+        message_count: u32,
+        last_rotation_time: i64,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .update_epoch_metadata(conversation_id, message_count, last_rotation_time)
+    }
+    fn get_epoch_metadata(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Option<(u32, i64)>> {
+        self.inner.get_epoch_metadata(conversation_id)
+    }
+    fn put_ratchet_key(
+        &self,
+        conversation_id: &ConversationId,
+        node_hash: &NodeHash,
+        chain_key: ChainKey,
+        epoch_id: u64,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .put_ratchet_key(conversation_id, node_hash, chain_key, epoch_id)
+    }
+    fn get_ratchet_key(
+        &self,
+        conversation_id: &ConversationId,
+        node_hash: &NodeHash,
+    ) -> MerkleToxResult<Option<(ChainKey, u64)>> {
+        self.inner.get_ratchet_key(conversation_id, node_hash)
+    }
+    fn remove_ratchet_key(
+        &self,
+        conversation_id: &ConversationId,
+        node_hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.inner.remove_ratchet_key(conversation_id, node_hash)
+    }
+    fn set_history_horizon(
+        &self,
+        conversation_id: &ConversationId,
+        min_rank: Option<u64>,
+    ) -> MerkleToxResult<()> {
+        self.inner.set_history_horizon(conversation_id, min_rank)
+    }
+    fn get_history_horizon(&self, conversation_id: &ConversationId) -> Option<u64> {
+        self.inner.get_history_horizon(conversation_id)
+    }
+    fn put_scheduled_message(&self, msg: &ScheduledMessage) -> MerkleToxResult<()> {
+        self.inner.put_scheduled_message(msg)
+    }
+    fn remove_scheduled_message(&self, id: u64) -> MerkleToxResult<()> {
+        self.inner.remove_scheduled_message(id)
+    }
+    fn get_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        self.inner.get_scheduled_messages()
+    }
+    fn put_acked_heads(&self, acked: &AckedHeads) -> MerkleToxResult<()> {
+        self.inner.put_acked_heads(acked)
+    }
+    fn get_acked_heads(&self) -> Vec<AckedHeads> {
+        self.inner.get_acked_heads()
+    }
+    fn put_archived_conversation(&self, archive: &ArchivedConversation) -> MerkleToxResult<()> {
+        self.inner.put_archived_conversation(archive)
+    }
+    fn get_archived_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<ArchivedConversation> {
+        self.inner.get_archived_conversation(conversation_id)
+    }
+    fn put_state_snapshot(&self, snapshot: &StateSnapshot) -> MerkleToxResult<()> {
+        self.inner.put_state_snapshot(snapshot)
+    }
+    fn get_state_snapshot(&self, conversation_id: &ConversationId) -> Option<StateSnapshot> {
+        self.inner.get_state_snapshot(conversation_id)
+    }
+    fn put_blacklist_entry(&self, entry: &BlacklistEntry) -> MerkleToxResult<()> {
+        self.inner.put_blacklist_entry(entry)
+    }
+    fn remove_blacklist_entry(&self, device_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
+        self.inner.remove_blacklist_entry(device_pk)
+    }
+    fn get_blacklist(&self) -> Vec<BlacklistEntry> {
+        self.inner.get_blacklist()
+    }
+    fn put_invite_grant(&self, grant: &InviteGrant) -> MerkleToxResult<()> {
+        self.inner.put_invite_grant(grant)
+    }
+    fn remove_invite_grant(&self, id: &InviteId) -> MerkleToxResult<()> {
+        self.inner.remove_invite_grant(id)
+    }
+    fn get_invite_grants(&self) -> Vec<InviteGrant> {
+        self.inner.get_invite_grants()
+    }
+    fn put_suspended_state(&self, state: &SuspendedState) -> MerkleToxResult<()> {
+        self.inner.put_suspended_state(state)
+    }
+    fn get_suspended_state(&self) -> Option<SuspendedState> {
+        self.inner.get_suspended_state()
+    }
+    fn remove_suspended_state(&self) -> MerkleToxResult<()> {
+        self.inner.remove_suspended_state()
+    }
+    fn write_batch(&self, writes: Vec<StoreWrite>) -> MerkleToxResult<()> {
+        let hashes: Vec<NodeHash> = writes
+            .iter()
+            .filter_map(|write| match write {
+                StoreWrite::Node(_, node, _) => Some(node),
+                _ => None,
+            })
+            .flat_map(touched)
+            .collect();
+        let result = self.inner.write_batch(writes);
+        self.invalidate(hashes);
+        result
+    }
+    fn flush(&self) -> MerkleToxResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: NodeStore + BlobStore> BlobStore for CachedStore<S> {
+    fn has_blob(&self, hash: &NodeHash) -> bool {
+        self.inner.has_blob(hash)
+    }
+    fn get_blob_info(&self, hash: &NodeHash) -> Option<BlobInfo> {
+        self.inner.get_blob_info(hash)
+    }
+    fn put_blob_info(&self, info: BlobInfo) -> MerkleToxResult<()> {
+        self.inner.put_blob_info(info)
+    }
+    fn put_chunk(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+        offset: u64,
+        data: &[u8],
+        proof: Option<&[u8]>,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .put_chunk(conversation_id, hash, offset, data, proof)
+    }
+    fn get_chunk(&self, hash: &NodeHash, offset: u64, length: u32) -> MerkleToxResult<Vec<u8>> {
+        self.inner.get_chunk(hash, offset, length)
+    }
+    fn get_chunk_with_proof(
+        &self,
+        hash: &NodeHash,
+        offset: u64,
+        length: u32,
+    ) -> MerkleToxResult<(Vec<u8>, Vec<u8>)> {
+        self.inner.get_chunk_with_proof(hash, offset, length)
+    }
+    fn add_blob_ref(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.inner.add_blob_ref(conversation_id, hash)
+    }
+    fn blob_ref_count(&self, hash: &NodeHash) -> usize {
+        self.inner.blob_ref_count(hash)
+    }
+    fn get_incomplete_blobs(&self) -> Vec<BlobInfo> {
+        self.inner.get_incomplete_blobs()
+    }
+    fn put_staged_chunk(&self, staging_id: u64, offset: u64, data: &[u8]) -> MerkleToxResult<()> {
+        self.inner.put_staged_chunk(staging_id, offset, data)
+    }
+    fn commit_staged_blob(
+        &self,
+        conversation_id: &ConversationId,
+        staging_id: u64,
+        hash: &NodeHash,
+        size: u64,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .commit_staged_blob(conversation_id, staging_id, hash, size)
+    }
+    fn discard_staged_blob(&self, staging_id: u64) -> MerkleToxResult<()> {
+        self.inner.discard_staged_blob(staging_id)
+    }
+}
+
+impl<S: NodeStore + GlobalStore> GlobalStore for CachedStore<S> {
+    fn get_global_offset(&self) -> Option<i64> {
+        self.inner.get_global_offset()
+    }
+    fn set_global_offset(&self, offset: i64) -> MerkleToxResult<()> {
+        self.inner.set_global_offset(offset)
+    }
+}
+
+impl<S: NodeStore + ReconciliationStore> ReconciliationStore for CachedStore<S> {
+    fn put_sketch(
+        &self,
+        conversation_id: &ConversationId,
+        range: &SyncRange,
+        sketch: &[u8],
+    ) -> MerkleToxResult<()> {
+        self.inner.put_sketch(conversation_id, range, sketch)
+    }
+    fn get_sketch(
+        &self,
+        conversation_id: &ConversationId,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Option<Vec<u8>>> {
+        self.inner.get_sketch(conversation_id, range)
+    }
+}
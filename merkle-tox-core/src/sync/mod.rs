@@ -1,12 +1,22 @@
 use crate::cas::BlobInfo;
 use crate::dag::{
-    ChainKey, ConversationId, KConv, NodeHash, NodeLookup, NodeType, PhysicalDevicePk,
+    ChainKey, ConversationId, KConv, LogicalIdentityPk, NodeHash, NodeLookup, NodeType,
+    PhysicalDevicePk,
 };
-use crate::error::MerkleToxResult;
+use crate::engine::archive::ArchivedConversation;
+use crate::engine::outbox::AckedHeads;
+use crate::engine::quarantine::BlacklistEntry;
+use crate::engine::scheduled::ScheduledMessage;
+use crate::error::{MerkleToxError, MerkleToxResult};
+use crate::invite::{InviteGrant, InviteId};
+use crate::suspend::SuspendedState;
 use std::time::Duration;
 use tox_proto::ToxProto;
 pub use tox_reconcile::{SyncRange, Tier};
 
+pub mod cache;
+pub use cache::CachedStore;
+
 /// Advertises current DAG tips to peer.
 #[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
 pub struct SyncHeads {
@@ -20,6 +30,24 @@ pub struct SyncHeads {
     pub anchor_hash: Option<NodeHash>,
 }
 
+/// Changes to the advertised DAG tips since an earlier announcement.
+///
+/// Sent instead of `SyncHeads` to peers that negotiated
+/// [`FEATURE_DELTA_HEADS`], either on its own or attached to a `MerkleNode`.
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct SyncHeadsDelta {
+    pub conversation_id: ConversationId,
+    /// Version of the announcement this delta applies to, or `None` when
+    /// `added` is the sender's complete head set.
+    pub base_version: Option<u32>,
+    /// Version of the sender's heads after applying this delta.
+    pub version: u32,
+    pub added: Vec<NodeHash>,
+    pub removed: Vec<NodeHash>,
+    /// Hash of earliest known admin head (for 500-hop trust bridging).
+    pub anchor_hash: Option<NodeHash>,
+}
+
 /// Request for batch of nodes by hash.
 #[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
 pub struct FetchBatchReq {
@@ -28,10 +56,76 @@ pub struct FetchBatchReq {
 }
 
 pub const FLAG_CAS_INVENTORY: u64 = 0x01;
+/// Set in `SyncHeads::flags` when a head delta could not be applied; the
+/// peer answers with its complete head set.
+pub const FLAG_HEADS_RESYNC: u64 = 0x02;
+
+pub use crate::capabilities::{
+    FEATURE_DELTA_HEADS, FEATURE_READ_ONLY, ROLE_FEATURES, SUPPORTED_FEATURES,
+};
 
 pub const SHARD_SIZE: u64 = 1000;
 
+/// How much history of a conversation is reconciled and fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Sync the whole DAG.
+    #[default]
+    Full,
+    /// Sync only nodes within `max_rank_window` ranks of the highest known
+    /// rank. Older content is neither advertised nor fetched; the Admin
+    /// track is still followed so authorship can be verified.
+    Recent { max_rank_window: u64 },
+}
+
+impl SyncPolicy {
+    pub fn rank_window(&self) -> Option<u64> {
+        match self {
+            SyncPolicy::Full => None,
+            SyncPolicy::Recent { max_rank_window } => Some(*max_rank_window),
+        }
+    }
+
+    /// Lowest rank inside the horizon when the highest known rank is
+    /// `max_rank`.
+    pub fn horizon(&self, max_rank: u64) -> u64 {
+        self.rank_window()
+            .map_or(0, |window| max_rank.saturating_sub(window))
+    }
+}
+
+/// Materialized view of a conversation saved by a client, so that it can
+/// start from it instead of replaying every node.
+#[derive(Debug, Clone, ToxProto, PartialEq, Eq)]
+pub struct StateSnapshot {
+    pub conversation_id: ConversationId,
+    /// Highest rank of the nodes applied to the view.
+    pub rank: u64,
+    /// Number of verified nodes at or below `rank` applied to the view. The
+    /// snapshot is stale if the store holds a different number.
+    pub verified_nodes: u64,
+    /// Heads of the DAG when the snapshot was taken.
+    pub heads: Vec<NodeHash>,
+    /// The serialized view; opaque to the store.
+    pub data: Vec<u8>,
+}
+
+/// A write grouped with others by [`NodeStore::write_batch`].
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum StoreWrite {
+    Node(ConversationId, crate::dag::MerkleNode, bool), // cid, node, verified
+    WireNode(ConversationId, NodeHash, crate::dag::WireNode),
+    RatchetKey(ConversationId, NodeHash, ChainKey, u64), // cid, hash, key, epoch_id
+    DeleteRatchetKey(ConversationId, NodeHash),
+    Heads(ConversationId, Vec<NodeHash>, bool), // cid, heads, is_admin
+}
+
 /// Trait for interacting with local DAG storage.
+///
+/// Writes of optional state such as scheduled messages or the blacklist
+/// fail by default, so that a store which cannot persist them is noticed
+/// instead of silently losing the state on restart.
 pub trait NodeStore: NodeLookup + Send + Sync {
     /// Returns current heads of local DAG for conversation.
     fn get_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash>;
@@ -73,6 +167,23 @@ pub trait NodeStore: NodeLookup + Send + Sync {
         verified: bool,
     ) -> MerkleToxResult<()>;
 
+    /// Persists several nodes of one conversation, parents before children.
+    fn put_nodes(
+        &self,
+        conversation_id: &ConversationId,
+        nodes: Vec<(crate::dag::MerkleNode, bool)>,
+    ) -> MerkleToxResult<()> {
+        for (node, verified) in nodes {
+            self.put_node(conversation_id, node, verified)?;
+        }
+        Ok(())
+    }
+
+    /// Retrieves nodes by hash, with `None` for nodes not in the store.
+    fn get_nodes(&self, hashes: &[NodeHash]) -> Vec<Option<crate::dag::MerkleNode>> {
+        hashes.iter().map(|hash| self.get_node(hash)).collect()
+    }
+
     /// Persists wire node to store.
     fn put_wire_node(
         &self,
@@ -125,6 +236,69 @@ pub trait NodeStore: NodeLookup + Send + Sync {
         range: &SyncRange,
     ) -> MerkleToxResult<Vec<NodeHash>>;
 
+    /// Returns verified nodes by author with rank in range, ordered by rank.
+    ///
+    /// The default implementation scans all verified nodes; indexed backends
+    /// should override it.
+    fn get_nodes_by_author(
+        &self,
+        conversation_id: &ConversationId,
+        author_pk: &LogicalIdentityPk,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Vec<crate::dag::MerkleNode>> {
+        let mut nodes = Vec::new();
+        for node_type in [NodeType::Admin, NodeType::Content] {
+            nodes.extend(
+                self.get_verified_nodes_by_type(conversation_id, node_type)?
+                    .into_iter()
+                    .filter(|n| {
+                        n.author_pk == *author_pk
+                            && n.topological_rank >= range.min_rank
+                            && n.topological_rank <= range.max_rank
+                    }),
+            );
+        }
+        nodes.sort_by_cached_key(|n| (n.topological_rank, n.hash()));
+        Ok(nodes)
+    }
+
+    /// Returns up to `limit` verified nodes with network timestamp in
+    /// `[from_ms, to_ms)`, ordered by timestamp.
+    ///
+    /// The default implementation scans all verified nodes; indexed backends
+    /// should override it.
+    fn get_nodes_by_time_range(
+        &self,
+        conversation_id: &ConversationId,
+        from_ms: i64,
+        to_ms: i64,
+        limit: usize,
+    ) -> MerkleToxResult<Vec<crate::dag::MerkleNode>> {
+        let mut nodes = Vec::new();
+        for node_type in [NodeType::Admin, NodeType::Content] {
+            nodes.extend(
+                self.get_verified_nodes_by_type(conversation_id, node_type)?
+                    .into_iter()
+                    .filter(|n| n.network_timestamp >= from_ms && n.network_timestamp < to_ms),
+            );
+        }
+        nodes.sort_by_cached_key(|n| (n.network_timestamp, n.hash()));
+        nodes.truncate(limit);
+        Ok(nodes)
+    }
+
+    /// Deletes the payload of an expired node. The header (type, rank,
+    /// parents) is kept so the DAG stays connected; `get_node` returns None
+    /// afterwards. Returns false if the node is unknown, already pruned, or
+    /// the backend does not support pruning.
+    fn prune_node(
+        &self,
+        _conversation_id: &ConversationId,
+        _hash: &NodeHash,
+    ) -> MerkleToxResult<bool> {
+        Ok(false)
+    }
+
     /// Returns hashes of unpacked wire nodes.
     fn get_opaque_node_hashes(
         &self,
@@ -186,6 +360,186 @@ pub trait NodeStore: NodeLookup + Send + Sync {
         conversation_id: &ConversationId,
         node_hash: &NodeHash,
     ) -> MerkleToxResult<()>;
+
+    /// Records that history before `min_rank` was deliberately not synced.
+    /// `None` clears the marker.
+    fn set_history_horizon(
+        &self,
+        _conversation_id: &ConversationId,
+        _min_rank: Option<u64>,
+    ) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist history horizons".to_string(),
+        ))
+    }
+
+    /// Returns the rank before which history was not synced, if any.
+    fn get_history_horizon(&self, _conversation_id: &ConversationId) -> Option<u64> {
+        None
+    }
+
+    // Scheduled messages
+
+    /// Persists message waiting for its delivery time.
+    fn put_scheduled_message(&self, _msg: &ScheduledMessage) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist scheduled messages".to_string(),
+        ))
+    }
+
+    /// Deletes delivered or cancelled scheduled message.
+    fn remove_scheduled_message(&self, _id: u64) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist scheduled messages".to_string(),
+        ))
+    }
+
+    /// Retrieves all pending scheduled messages.
+    fn get_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        Vec::new()
+    }
+
+    // Outbox
+
+    /// Persists the heads a device acknowledged in a conversation, replacing
+    /// the previous record.
+    fn put_acked_heads(&self, _acked: &AckedHeads) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist acknowledged heads".to_string(),
+        ))
+    }
+
+    /// Retrieves the acknowledged heads of all devices.
+    fn get_acked_heads(&self) -> Vec<AckedHeads> {
+        Vec::new()
+    }
+
+    // Archive
+
+    /// Persists the state of an unloaded conversation, replacing the
+    /// previous archive. Stores that cannot keep archives fail, as the
+    /// unloaded state would be lost otherwise.
+    fn put_archived_conversation(&self, _archive: &ArchivedConversation) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot archive conversations".to_string(),
+        ))
+    }
+
+    /// Retrieves the archived state of a conversation.
+    fn get_archived_conversation(
+        &self,
+        _conversation_id: &ConversationId,
+    ) -> Option<ArchivedConversation> {
+        None
+    }
+
+    // Snapshots
+
+    /// Persists a client state snapshot, replacing the previous one of the
+    /// conversation.
+    fn put_state_snapshot(&self, _snapshot: &StateSnapshot) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist state snapshots".to_string(),
+        ))
+    }
+
+    /// Retrieves the latest state snapshot of a conversation.
+    fn get_state_snapshot(&self, _conversation_id: &ConversationId) -> Option<StateSnapshot> {
+        None
+    }
+
+    // Blacklist
+
+    /// Persists the blacklist state of a device, replacing the previous
+    /// entry.
+    fn put_blacklist_entry(&self, _entry: &BlacklistEntry) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist the blacklist".to_string(),
+        ))
+    }
+
+    /// Removes a device from the blacklist.
+    fn remove_blacklist_entry(&self, _device_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist the blacklist".to_string(),
+        ))
+    }
+
+    /// Retrieves all blacklist entries.
+    fn get_blacklist(&self) -> Vec<BlacklistEntry> {
+        Vec::new()
+    }
+
+    // Invites
+
+    /// Persists an invite issued by this device, replacing a previous one
+    /// with the same id. Stores that cannot keep invites fail, as the invite
+    /// would stop working on restart otherwise.
+    fn put_invite_grant(&self, _grant: &InviteGrant) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist invites".to_string(),
+        ))
+    }
+
+    /// Removes a revoked or expired invite.
+    fn remove_invite_grant(&self, _id: &InviteId) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist invites".to_string(),
+        ))
+    }
+
+    /// Retrieves all invites issued by this device.
+    fn get_invite_grants(&self) -> Vec<InviteGrant> {
+        Vec::new()
+    }
+
+    // Suspend
+
+    /// Persists the transient state of a suspended node, replacing the
+    /// previous one.
+    fn put_suspended_state(&self, _state: &SuspendedState) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist suspended state".to_string(),
+        ))
+    }
+
+    /// Retrieves the state saved by the last suspend.
+    fn get_suspended_state(&self) -> Option<SuspendedState> {
+        None
+    }
+
+    /// Removes the saved state, once resumed.
+    fn remove_suspended_state(&self) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot persist suspended state".to_string(),
+        ))
+    }
+
+    // Batching
+
+    /// Applies `writes` in order. Stores with transactions commit them as
+    /// one; by default they are applied one at a time.
+    fn write_batch(&self, writes: Vec<StoreWrite>) -> MerkleToxResult<()> {
+        for write in writes {
+            match write {
+                StoreWrite::Node(cid, node, verified) => self.put_node(&cid, node, verified)?,
+                StoreWrite::WireNode(cid, hash, node) => self.put_wire_node(&cid, &hash, node)?,
+                StoreWrite::RatchetKey(cid, hash, key, epoch_id) => {
+                    self.put_ratchet_key(&cid, &hash, key, epoch_id)?
+                }
+                StoreWrite::DeleteRatchetKey(cid, hash) => self.remove_ratchet_key(&cid, &hash)?,
+                StoreWrite::Heads(cid, heads, true) => self.set_admin_heads(&cid, heads)?,
+                StoreWrite::Heads(cid, heads, false) => self.set_heads(&cid, heads)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the writes so far durable, e.g. before the process exits.
+    /// Stores writing through on every call need not override it.
+    fn flush(&self) -> MerkleToxResult<()> {
+        Ok(())
+    }
 }
 
 /// Trait for persisting large binary assets.
@@ -220,6 +574,63 @@ pub trait BlobStore: Send + Sync {
         offset: u64,
         length: u32,
     ) -> MerkleToxResult<(Vec<u8>, Vec<u8>)>;
+
+    /// Records that conversation references blob. Blob data is shared between
+    /// conversations; each conversation holds at most one reference.
+    fn add_blob_ref(
+        &self,
+        _conversation_id: &ConversationId,
+        _hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        Ok(())
+    }
+
+    /// Returns number of conversations referencing blob.
+    fn blob_ref_count(&self, _hash: &NodeHash) -> usize {
+        0
+    }
+
+    /// Returns metadata of blobs whose download is pending or in progress,
+    /// so transfers can be resumed after a restart.
+    fn get_incomplete_blobs(&self) -> Vec<BlobInfo> {
+        Vec::new()
+    }
+
+    /// Writes a chunk of a locally added blob whose hash is not known yet,
+    /// so that its source is read only once. Writing at offset 0 starts the
+    /// staged blob over.
+    fn put_staged_chunk(
+        &self,
+        _staging_id: u64,
+        _offset: u64,
+        _data: &[u8],
+    ) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot stage blobs".to_string(),
+        ))
+    }
+
+    /// Stores a staged blob of `size` bytes as `hash`, referenced by
+    /// `conversation_id`. If the blob is already stored, the staged data is
+    /// dropped.
+    fn commit_staged_blob(
+        &self,
+        _conversation_id: &ConversationId,
+        _staging_id: u64,
+        _hash: &NodeHash,
+        _size: u64,
+    ) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot stage blobs".to_string(),
+        ))
+    }
+
+    /// Drops a staged blob that will not be committed.
+    fn discard_staged_blob(&self, _staging_id: u64) -> MerkleToxResult<()> {
+        Err(MerkleToxError::Storage(
+            "Store cannot stage blobs".to_string(),
+        ))
+    }
 }
 
 /// Trait for persisting reconciliation sketches (e.g., IBLTs).
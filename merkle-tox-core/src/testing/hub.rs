@@ -6,7 +6,8 @@ use rand::{Rng, SeedableRng, rngs::StdRng};
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use tox_proto::time::Instant;
 
 #[derive(Debug, Eq, PartialEq)]
 struct DelayedPacket {
@@ -42,6 +43,16 @@ pub enum LossModel {
     GilbertElliot { p: f32, r: f32 },
 }
 
+/// Traffic counters of a `VirtualHub`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HubStats {
+    /// Packets handed to the hub, including those it then dropped.
+    pub packets_sent: u64,
+    pub bytes_sent: u64,
+    /// Packets lost to blackouts, partitions or the loss model.
+    pub packets_dropped: u64,
+}
+
 type NodeSender = Sender<(PhysicalDevicePk, Vec<u8>)>;
 type GatewaySender = Sender<(PhysicalDevicePk, PhysicalDevicePk, Vec<u8>)>;
 
@@ -68,6 +79,7 @@ pub struct VirtualHub {
     time_provider: Arc<dyn TimeProvider>,
     /// Seeded RNG for deterministic simulation.
     rng: Mutex<StdRng>,
+    stats: Mutex<HubStats>,
 }
 
 impl VirtualHub {
@@ -84,6 +96,7 @@ impl VirtualHub {
             latency: Mutex::new(Duration::ZERO),
             time_provider,
             rng: Mutex::new(StdRng::seed_from_u64(4)),
+            stats: Mutex::new(HubStats::default()),
         }
     }
 
@@ -119,6 +132,11 @@ impl VirtualHub {
         self.blackouts.lock().unwrap().insert(pk, until);
     }
 
+    /// Traffic routed through the hub so far.
+    pub fn stats(&self) -> HubStats {
+        *self.stats.lock().unwrap()
+    }
+
     /// Registers a gateway that will receive packets destined for unknown PhysicalDevicePks.
     pub fn register_gateway(&self) -> Receiver<(PhysicalDevicePk, PhysicalDevicePk, Vec<u8>)> {
         let (tx, rx) = unbounded();
@@ -170,14 +188,21 @@ impl VirtualHub {
     /// Routes a packet from one virtual node to another, applying impairments.
     pub fn route(&self, from: PhysicalDevicePk, to: PhysicalDevicePk, data: Vec<u8>) {
         let now = self.time_provider.now_instant();
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.packets_sent += 1;
+            stats.bytes_sent += data.len() as u64;
+        }
 
         // 1. Blackout Engine
         if self.is_blacked_out(&from, now) || self.is_blacked_out(&to, now) {
+            self.count_drop();
             return;
         }
 
         // 2. Partition Table
         if !self.can_communicate(&from, &to) {
+            self.count_drop();
             return;
         }
 
@@ -206,6 +231,7 @@ impl VirtualHub {
         };
 
         if should_drop {
+            self.count_drop();
             return;
         }
 
@@ -242,6 +268,10 @@ impl VirtualHub {
         }
     }
 
+    fn count_drop(&self) {
+        self.stats.lock().unwrap().packets_dropped += 1;
+    }
+
     /// Polls the hub to deliver packets that have completed their delay.
     pub fn poll(&self) {
         let now = self.time_provider.now_instant();
@@ -62,6 +62,21 @@ impl TestIdentity {
         )
     }
 
+    /// An engine for this identity's device, without any conversation state.
+    pub fn engine(
+        &self,
+        seed: u64,
+        tp: &std::sync::Arc<crate::clock::ManualTimeProvider>,
+    ) -> crate::engine::MerkleToxEngine {
+        crate::engine::MerkleToxEngine::with_sk(
+            self.device_pk,
+            self.master_pk,
+            crate::dag::PhysicalDeviceSk::from(self.device_sk.to_bytes()),
+            rand::SeedableRng::seed_from_u64(seed),
+            tp.clone(),
+        )
+    }
+
     /// Authorizes the device in the given engine.
     pub fn authorize_in_engine(
         &self,
@@ -189,6 +204,16 @@ impl TestRoom {
             register_test_ephemeral_key(engine, &self.keys, &id.device_pk);
         }
     }
+
+    /// The device of identity `idx` with its own store, set up by
+    /// `setup_engine` and seeded with `idx`.
+    pub fn peer(
+        &self,
+        idx: usize,
+        tp: &std::sync::Arc<crate::clock::ManualTimeProvider>,
+    ) -> crate::testing::TestPeer {
+        crate::testing::TestPeer::new(self, &self.identities[idx], idx as u64, tp)
+    }
 }
 
 /// Helper to create a delegation certificate signed by an issuer,
@@ -0,0 +1,945 @@
+use crate::cas::{BlobInfo, BlobStatus, CHUNK_SIZE};
+use crate::dag::{
+    ChainKey, ConversationId, KConv, MerkleNode, NodeHash, NodeLookup, NodeType, PhysicalDevicePk,
+    WireNode,
+};
+use crate::engine::archive::ArchivedConversation;
+use crate::engine::outbox::AckedHeads;
+use crate::engine::quarantine::BlacklistEntry;
+use crate::engine::scheduled::ScheduledMessage;
+use crate::error::{MerkleToxError, MerkleToxResult};
+use crate::invite::{InviteGrant, InviteId};
+use crate::suspend::SuspendedState;
+use crate::sync::{
+    BlobStore, GlobalStore, NodeStore, ReconciliationStore, StateSnapshot, StoreWrite, SyncRange,
+};
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
+
+/// Per-conversation state held by [`MemStore`].
+#[derive(Default)]
+struct MemConversation {
+    nodes: HashMap<NodeHash, StoredNode>,
+    wire_nodes: HashMap<NodeHash, WireNode>,
+    children: HashMap<NodeHash, Vec<NodeHash>>,
+    last_seq_numbers: HashMap<PhysicalDevicePk, u64>,
+    heads: Vec<NodeHash>,
+    admin_heads: Vec<NodeHash>,
+    keys: BTreeMap<u64, KConv>,
+    ratchet_keys: HashMap<NodeHash, (ChainKey, u64)>,
+    epoch_metadata: Option<(u32, i64)>,
+    sketches: HashMap<SyncRange, Vec<u8>>,
+    history_horizon: Option<u64>,
+    acked_heads: HashMap<PhysicalDevicePk, AckedHeads>,
+    archive: Option<ArchivedConversation>,
+    snapshot: Option<StateSnapshot>,
+}
+
+struct StoredNode {
+    node: MerkleNode,
+    verified: bool,
+    admin_distance: u64,
+    pruned: bool,
+}
+
+struct StoredBlob {
+    info: BlobInfo,
+    data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct MemInner {
+    conversations: HashMap<ConversationId, MemConversation>,
+    node_to_conv: HashMap<NodeHash, ConversationId>,
+    blobs: HashMap<NodeHash, StoredBlob>,
+    blob_refs: HashMap<NodeHash, HashSet<ConversationId>>,
+    global_offset: Option<i64>,
+    scheduled: BTreeMap<u64, ScheduledMessage>,
+    blacklist: HashMap<PhysicalDevicePk, BlacklistEntry>,
+    invite_grants: HashMap<InviteId, InviteGrant>,
+    suspended_state: Option<SuspendedState>,
+}
+
+impl MemInner {
+    fn stored(&self, hash: &NodeHash) -> Option<&StoredNode> {
+        let conv_id = self.node_to_conv.get(hash)?;
+        self.conversations.get(conv_id)?.nodes.get(hash)
+    }
+
+    fn apply(&mut self, write: StoreWrite) {
+        match write {
+            StoreWrite::Node(conversation_id, node, verified) => {
+                self.put_node(conversation_id, node, verified)
+            }
+            StoreWrite::WireNode(conversation_id, hash, node) => {
+                self.conversations
+                    .entry(conversation_id)
+                    .or_default()
+                    .wire_nodes
+                    .insert(hash, node);
+            }
+            StoreWrite::RatchetKey(conversation_id, hash, chain_key, epoch_id) => {
+                self.conversations
+                    .entry(conversation_id)
+                    .or_default()
+                    .ratchet_keys
+                    .insert(hash, (chain_key, epoch_id));
+            }
+            StoreWrite::DeleteRatchetKey(conversation_id, hash) => {
+                if let Some(ctx) = self.conversations.get_mut(&conversation_id) {
+                    ctx.ratchet_keys.remove(&hash);
+                }
+            }
+            StoreWrite::Heads(conversation_id, heads, is_admin) => {
+                let ctx = self.conversations.entry(conversation_id).or_default();
+                if is_admin {
+                    ctx.admin_heads = heads;
+                } else {
+                    ctx.heads = heads;
+                }
+            }
+        }
+    }
+
+    fn put_node(&mut self, conversation_id: ConversationId, node: MerkleNode, verified: bool) {
+        let hash = node.hash();
+        let admin_distance = if node.node_type() == NodeType::Admin {
+            0
+        } else {
+            node.parents
+                .iter()
+                .filter_map(|p| self.stored(p).map(|s| s.admin_distance))
+                .min()
+                .map_or(u64::MAX, |d| d.saturating_add(1))
+        };
+
+        let ctx = self.conversations.entry(conversation_id).or_default();
+        let entry = ctx.last_seq_numbers.entry(node.sender_pk).or_insert(0);
+        if node.sequence_number > *entry {
+            *entry = node.sequence_number;
+        }
+        let pruned = match ctx.nodes.get(&hash) {
+            Some(existing) => existing.pruned,
+            None => {
+                for parent in &node.parents {
+                    ctx.children.entry(*parent).or_default().push(hash);
+                }
+                false
+            }
+        };
+        ctx.nodes.insert(
+            hash,
+            StoredNode {
+                node,
+                verified,
+                admin_distance,
+                pruned,
+            },
+        );
+        self.node_to_conv.insert(hash, conversation_id);
+    }
+}
+
+/// A complete, conversation-scoped in-memory implementation of the store
+/// traits.
+///
+/// Unlike [`InMemoryStore`](super::InMemoryStore), which takes shortcuts
+/// convenient for unit tests (global sequence numbers, synthetic admin
+/// distances), `MemStore` follows the same semantics as the persistent
+/// backends. It is suitable for integration tests and for ephemeral nodes
+/// that do not need to survive a restart.
+#[derive(Default)]
+pub struct MemStore {
+    inner: RwLock<MemInner>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the IDs of all conversations that have stored state.
+    pub fn conversations(&self) -> Vec<ConversationId> {
+        self.inner.read().conversations.keys().copied().collect()
+    }
+}
+
+impl NodeLookup for MemStore {
+    fn get_node_type(&self, hash: &NodeHash) -> Option<NodeType> {
+        self.inner.read().stored(hash).map(|s| s.node.node_type())
+    }
+
+    fn get_rank(&self, hash: &NodeHash) -> Option<u64> {
+        self.inner
+            .read()
+            .stored(hash)
+            .map(|s| s.node.topological_rank)
+    }
+
+    fn get_admin_distance(&self, hash: &NodeHash) -> Option<u64> {
+        self.inner.read().stored(hash).map(|s| s.admin_distance)
+    }
+
+    fn contains_node(&self, hash: &NodeHash) -> bool {
+        self.inner.read().node_to_conv.contains_key(hash)
+    }
+
+    fn has_children(&self, hash: &NodeHash) -> bool {
+        let inner = self.inner.read();
+        inner
+            .node_to_conv
+            .get(hash)
+            .and_then(|c| inner.conversations.get(c))
+            .and_then(|ctx| ctx.children.get(hash))
+            .is_some_and(|c| !c.is_empty())
+    }
+
+    fn get_soft_anchor_chain_length(&self, hash: &NodeHash) -> Option<u64> {
+        let node = self.get_node(hash)?;
+        if let crate::dag::Content::Control(crate::dag::ControlAction::SoftAnchor {
+            basis_hash,
+            ..
+        }) = &node.content
+        {
+            let parent_count = self.get_soft_anchor_chain_length(basis_hash).unwrap_or(0);
+            Some(1 + parent_count)
+        } else {
+            Some(0)
+        }
+    }
+}
+
+impl NodeStore for MemStore {
+    fn get_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash> {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .map(|c| c.heads.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_heads(
+        &self,
+        conversation_id: &ConversationId,
+        heads: Vec<NodeHash>,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .apply(StoreWrite::Heads(*conversation_id, heads, false));
+        Ok(())
+    }
+
+    fn get_admin_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash> {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .map(|c| c.admin_heads.clone())
+            .unwrap_or_default()
+    }
+
+    fn set_admin_heads(
+        &self,
+        conversation_id: &ConversationId,
+        heads: Vec<NodeHash>,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .apply(StoreWrite::Heads(*conversation_id, heads, true));
+        Ok(())
+    }
+
+    fn has_node(&self, hash: &NodeHash) -> bool {
+        self.contains_node(hash)
+    }
+
+    fn is_verified(&self, hash: &NodeHash) -> bool {
+        self.inner.read().stored(hash).is_some_and(|s| s.verified)
+    }
+
+    fn get_node(&self, hash: &NodeHash) -> Option<MerkleNode> {
+        self.inner
+            .read()
+            .stored(hash)
+            .filter(|s| !s.pruned)
+            .map(|s| s.node.clone())
+    }
+
+    fn get_wire_node(&self, hash: &NodeHash) -> Option<WireNode> {
+        self.inner
+            .read()
+            .conversations
+            .values()
+            .find_map(|c| c.wire_nodes.get(hash).cloned())
+    }
+
+    fn put_node(
+        &self,
+        conversation_id: &ConversationId,
+        node: MerkleNode,
+        verified: bool,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .apply(StoreWrite::Node(*conversation_id, node, verified));
+        Ok(())
+    }
+
+    fn put_nodes(
+        &self,
+        conversation_id: &ConversationId,
+        nodes: Vec<(MerkleNode, bool)>,
+    ) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        for (node, verified) in nodes {
+            inner.put_node(*conversation_id, node, verified);
+        }
+        Ok(())
+    }
+
+    fn get_nodes(&self, hashes: &[NodeHash]) -> Vec<Option<MerkleNode>> {
+        let inner = self.inner.read();
+        hashes
+            .iter()
+            .map(|hash| {
+                inner
+                    .stored(hash)
+                    .filter(|s| !s.pruned)
+                    .map(|s| s.node.clone())
+            })
+            .collect()
+    }
+
+    fn put_wire_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+        node: WireNode,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .apply(StoreWrite::WireNode(*conversation_id, *hash, node));
+        Ok(())
+    }
+
+    fn remove_wire_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        if let Some(ctx) = self.inner.write().conversations.get_mut(conversation_id) {
+            ctx.wire_nodes.remove(hash);
+        }
+        Ok(())
+    }
+
+    fn get_speculative_nodes(&self, conversation_id: &ConversationId) -> Vec<MerkleNode> {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .map(|c| {
+                c.nodes
+                    .values()
+                    .filter(|s| !s.verified)
+                    .map(|s| s.node.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn mark_verified(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        if let Some(stored) = self
+            .inner
+            .write()
+            .conversations
+            .get_mut(conversation_id)
+            .and_then(|c| c.nodes.get_mut(hash))
+        {
+            stored.verified = true;
+        }
+        Ok(())
+    }
+
+    fn get_last_sequence_number(
+        &self,
+        conversation_id: &ConversationId,
+        sender_pk: &PhysicalDevicePk,
+    ) -> u64 {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.last_seq_numbers.get(sender_pk).copied())
+            .unwrap_or(0)
+    }
+
+    fn get_node_counts(&self, conversation_id: &ConversationId) -> (usize, usize) {
+        let inner = self.inner.read();
+        let Some(ctx) = inner.conversations.get(conversation_id) else {
+            return (0, 0);
+        };
+        let verified = ctx.nodes.values().filter(|s| s.verified).count();
+        (verified, ctx.nodes.len() - verified)
+    }
+
+    fn get_verified_nodes_by_type(
+        &self,
+        conversation_id: &ConversationId,
+        node_type: NodeType,
+    ) -> MerkleToxResult<Vec<MerkleNode>> {
+        let inner = self.inner.read();
+        let Some(ctx) = inner.conversations.get(conversation_id) else {
+            return Ok(Vec::new());
+        };
+        let mut res: Vec<(NodeHash, MerkleNode)> = ctx
+            .nodes
+            .iter()
+            .filter(|(_, s)| s.verified && !s.pruned && s.node.node_type() == node_type)
+            .map(|(h, s)| (*h, s.node.clone()))
+            .collect();
+        res.sort_by(|(ha, a), (hb, b)| {
+            a.topological_rank
+                .cmp(&b.topological_rank)
+                .then_with(|| ha.as_bytes().cmp(hb.as_bytes()))
+        });
+        Ok(res.into_iter().map(|(_, n)| n).collect())
+    }
+
+    fn get_node_hashes_in_range(
+        &self,
+        conversation_id: &ConversationId,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Vec<NodeHash>> {
+        let inner = self.inner.read();
+        Ok(inner
+            .conversations
+            .get(conversation_id)
+            .map(|c| {
+                c.nodes
+                    .iter()
+                    .filter(|(_, s)| {
+                        s.verified
+                            && s.node.topological_rank >= range.min_rank
+                            && s.node.topological_rank <= range.max_rank
+                    })
+                    .map(|(h, _)| *h)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn prune_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<bool> {
+        let mut inner = self.inner.write();
+        let Some(ctx) = inner.conversations.get_mut(conversation_id) else {
+            return Ok(false);
+        };
+        let Some(stored) = ctx.nodes.get_mut(hash) else {
+            return Ok(false);
+        };
+        if stored.pruned {
+            return Ok(false);
+        }
+        stored.pruned = true;
+        ctx.wire_nodes.remove(hash);
+        Ok(true)
+    }
+
+    fn get_opaque_node_hashes(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Vec<NodeHash>> {
+        Ok(self
+            .inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .map(|c| {
+                c.wire_nodes
+                    .keys()
+                    .filter(|h| !c.nodes.contains_key(h))
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn size_bytes(&self) -> u64 {
+        let inner = self.inner.read();
+        let mut total = 0u64;
+        for ctx in inner.conversations.values() {
+            for s in ctx.nodes.values() {
+                total += tox_proto::serialize(&s.node).map_or(0, |b| b.len() as u64);
+            }
+            for w in ctx.wire_nodes.values() {
+                total += w.payload_data.len() as u64;
+            }
+            total += ctx.sketches.values().map(|s| s.len() as u64).sum::<u64>();
+        }
+        total += inner
+            .blobs
+            .values()
+            .map(|b| b.data.len() as u64)
+            .sum::<u64>();
+        total
+    }
+
+    fn put_conversation_key(
+        &self,
+        conversation_id: &ConversationId,
+        epoch: u64,
+        k_conv: KConv,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .conversations
+            .entry(*conversation_id)
+            .or_default()
+            .keys
+            .insert(epoch, k_conv);
+        Ok(())
+    }
+
+    fn get_conversation_keys(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Vec<(u64, KConv)>> {
+        Ok(self
+            .inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .map(|c| c.keys.iter().map(|(e, k)| (*e, k.clone())).collect())
+            .unwrap_or_default())
+    }
+
+    fn update_epoch_metadata(
+        &self,
+        conversation_id: &ConversationId,
+        message_count: u32,
+        last_rotation_time: i64,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .conversations
+            .entry(*conversation_id)
+            .or_default()
+            .epoch_metadata = Some((message_count, last_rotation_time));
+        Ok(())
+    }
+
+    fn get_epoch_metadata(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Option<(u32, i64)>> {
+        Ok(self
+            .inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.epoch_metadata))
+    }
+
+    fn put_ratchet_key(
+        &self,
+        conversation_id: &ConversationId,
+        node_hash: &NodeHash,
+        chain_key: ChainKey,
+        epoch_id: u64,
+    ) -> MerkleToxResult<()> {
+        self.inner.write().apply(StoreWrite::RatchetKey(
+            *conversation_id,
+            *node_hash,
+            chain_key,
+            epoch_id,
+        ));
+        Ok(())
+    }
+
+    fn get_ratchet_key(
+        &self,
+        conversation_id: &ConversationId,
+        node_hash: &NodeHash,
+    ) -> MerkleToxResult<Option<(ChainKey, u64)>> {
+        Ok(self
+            .inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.ratchet_keys.get(node_hash).cloned()))
+    }
+
+    fn remove_ratchet_key(
+        &self,
+        conversation_id: &ConversationId,
+        node_hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .apply(StoreWrite::DeleteRatchetKey(*conversation_id, *node_hash));
+        Ok(())
+    }
+
+    fn set_history_horizon(
+        &self,
+        conversation_id: &ConversationId,
+        min_rank: Option<u64>,
+    ) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        match min_rank {
+            Some(_) => {
+                inner
+                    .conversations
+                    .entry(*conversation_id)
+                    .or_default()
+                    .history_horizon = min_rank;
+            }
+            None => {
+                if let Some(ctx) = inner.conversations.get_mut(conversation_id) {
+                    ctx.history_horizon = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_history_horizon(&self, conversation_id: &ConversationId) -> Option<u64> {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.history_horizon)
+    }
+
+    fn put_scheduled_message(&self, msg: &ScheduledMessage) -> MerkleToxResult<()> {
+        self.inner.write().scheduled.insert(msg.id, msg.clone());
+        Ok(())
+    }
+
+    fn remove_scheduled_message(&self, id: u64) -> MerkleToxResult<()> {
+        self.inner.write().scheduled.remove(&id);
+        Ok(())
+    }
+
+    fn get_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        self.inner.read().scheduled.values().cloned().collect()
+    }
+
+    fn put_acked_heads(&self, acked: &AckedHeads) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .conversations
+            .entry(acked.conversation_id)
+            .or_default()
+            .acked_heads
+            .insert(acked.device_pk, acked.clone());
+        Ok(())
+    }
+
+    fn get_acked_heads(&self) -> Vec<AckedHeads> {
+        self.inner
+            .read()
+            .conversations
+            .values()
+            .flat_map(|c| c.acked_heads.values().cloned())
+            .collect()
+    }
+
+    fn put_archived_conversation(&self, archive: &ArchivedConversation) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .conversations
+            .entry(archive.conversation_id)
+            .or_default()
+            .archive = Some(archive.clone());
+        Ok(())
+    }
+
+    fn get_archived_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<ArchivedConversation> {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.archive.clone())
+    }
+
+    fn put_state_snapshot(&self, snapshot: &StateSnapshot) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .conversations
+            .entry(snapshot.conversation_id)
+            .or_default()
+            .snapshot = Some(snapshot.clone());
+        Ok(())
+    }
+
+    fn get_state_snapshot(&self, conversation_id: &ConversationId) -> Option<StateSnapshot> {
+        self.inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.snapshot.clone())
+    }
+
+    fn put_blacklist_entry(&self, entry: &BlacklistEntry) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .blacklist
+            .insert(entry.device_pk, entry.clone());
+        Ok(())
+    }
+
+    fn remove_blacklist_entry(&self, device_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
+        self.inner.write().blacklist.remove(device_pk);
+        Ok(())
+    }
+
+    fn get_blacklist(&self) -> Vec<BlacklistEntry> {
+        self.inner.read().blacklist.values().cloned().collect()
+    }
+
+    fn put_invite_grant(&self, grant: &InviteGrant) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .invite_grants
+            .insert(grant.id(), grant.clone());
+        Ok(())
+    }
+
+    fn remove_invite_grant(&self, id: &InviteId) -> MerkleToxResult<()> {
+        self.inner.write().invite_grants.remove(id);
+        Ok(())
+    }
+
+    fn get_invite_grants(&self) -> Vec<InviteGrant> {
+        self.inner.read().invite_grants.values().cloned().collect()
+    }
+
+    fn put_suspended_state(&self, state: &SuspendedState) -> MerkleToxResult<()> {
+        self.inner.write().suspended_state = Some(state.clone());
+        Ok(())
+    }
+
+    fn get_suspended_state(&self) -> Option<SuspendedState> {
+        self.inner.read().suspended_state.clone()
+    }
+
+    fn remove_suspended_state(&self) -> MerkleToxResult<()> {
+        self.inner.write().suspended_state = None;
+        Ok(())
+    }
+
+    fn write_batch(&self, writes: Vec<StoreWrite>) -> MerkleToxResult<()> {
+        // Readers see either none or all of the batch.
+        let mut inner = self.inner.write();
+        for write in writes {
+            inner.apply(write);
+        }
+        Ok(())
+    }
+}
+
+impl BlobStore for MemStore {
+    fn has_blob(&self, hash: &NodeHash) -> bool {
+        self.inner
+            .read()
+            .blobs
+            .get(hash)
+            .is_some_and(|b| b.info.status == BlobStatus::Available)
+    }
+
+    fn get_blob_info(&self, hash: &NodeHash) -> Option<BlobInfo> {
+        self.inner.read().blobs.get(hash).map(|b| b.info.clone())
+    }
+
+    fn put_blob_info(&self, info: BlobInfo) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        match inner.blobs.get_mut(&info.hash) {
+            Some(blob) => blob.info = info,
+            None => {
+                let data = vec![0u8; info.size as usize];
+                inner.blobs.insert(info.hash, StoredBlob { info, data });
+            }
+        }
+        Ok(())
+    }
+
+    fn put_chunk(
+        &self,
+        _conversation_id: &ConversationId,
+        hash: &NodeHash,
+        offset: u64,
+        data: &[u8],
+        _proof: Option<&[u8]>,
+    ) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        let blob = inner
+            .blobs
+            .get_mut(hash)
+            .ok_or(MerkleToxError::BlobNotFound(*hash))?;
+        if blob.info.status == BlobStatus::Available {
+            return Ok(());
+        }
+
+        let start = (offset as usize).min(blob.data.len());
+        let end = (start + data.len()).min(blob.data.len());
+        blob.data[start..end].copy_from_slice(&data[..end - start]);
+
+        let num_chunks = blob.info.size.div_ceil(CHUNK_SIZE);
+        let mut mask = blob
+            .info
+            .received_mask
+            .take()
+            .unwrap_or_else(|| vec![0u8; num_chunks.div_ceil(8) as usize]);
+        let first_chunk = offset / CHUNK_SIZE;
+        let last_chunk = (offset + data.len().max(1) as u64 - 1) / CHUNK_SIZE;
+        for chunk_idx in first_chunk..=last_chunk.min(num_chunks.saturating_sub(1)) {
+            if let Some(byte) = mask.get_mut((chunk_idx / 8) as usize) {
+                *byte |= 1 << (chunk_idx % 8);
+            }
+        }
+
+        let complete = (0..num_chunks).all(|i| mask[(i / 8) as usize] & (1 << (i % 8)) != 0);
+        if complete {
+            let (_, root) = bao::encode::outboard(&blob.data);
+            blob.info.bao_root = Some(*root.as_bytes());
+            blob.info.status = BlobStatus::Available;
+        } else {
+            blob.info.status = BlobStatus::Downloading;
+        }
+        blob.info.received_mask = Some(mask);
+        Ok(())
+    }
+
+    fn get_chunk(&self, hash: &NodeHash, offset: u64, length: u32) -> MerkleToxResult<Vec<u8>> {
+        let inner = self.inner.read();
+        let blob = inner
+            .blobs
+            .get(hash)
+            .ok_or(MerkleToxError::BlobNotFound(*hash))?;
+        let end = offset
+            .checked_add(length as u64)
+            .filter(|&e| e <= blob.data.len() as u64)
+            .ok_or_else(|| {
+                MerkleToxError::Io(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "failed to fill whole buffer",
+                ))
+            })?;
+        Ok(blob.data[offset as usize..end as usize].to_vec())
+    }
+
+    fn get_chunk_with_proof(
+        &self,
+        hash: &NodeHash,
+        offset: u64,
+        length: u32,
+    ) -> MerkleToxResult<(Vec<u8>, Vec<u8>)> {
+        let data = self.get_chunk(hash, offset, length)?;
+        let inner = self.inner.read();
+        let blob = inner
+            .blobs
+            .get(hash)
+            .ok_or(MerkleToxError::BlobNotFound(*hash))?;
+
+        let (outboard, _root) = bao::encode::outboard(&blob.data);
+        let mut proof = Vec::new();
+        let mut extractor = bao::encode::SliceExtractor::new_outboard(
+            std::io::Cursor::new(&blob.data),
+            std::io::Cursor::new(outboard),
+            offset,
+            length as u64,
+        );
+        extractor
+            .read_to_end(&mut proof)
+            .map_err(MerkleToxError::Io)?;
+        Ok((data, proof))
+    }
+
+    fn add_blob_ref(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .blob_refs
+            .entry(*hash)
+            .or_default()
+            .insert(*conversation_id);
+        Ok(())
+    }
+
+    fn blob_ref_count(&self, hash: &NodeHash) -> usize {
+        self.inner
+            .read()
+            .blob_refs
+            .get(hash)
+            .map_or(0, HashSet::len)
+    }
+
+    fn get_incomplete_blobs(&self) -> Vec<BlobInfo> {
+        self.inner
+            .read()
+            .blobs
+            .values()
+            .filter(|b| b.info.status.is_incomplete())
+            .map(|b| b.info.clone())
+            .collect()
+    }
+}
+
+impl GlobalStore for MemStore {
+    fn get_global_offset(&self) -> Option<i64> {
+        self.inner.read().global_offset
+    }
+
+    fn set_global_offset(&self, offset: i64) -> MerkleToxResult<()> {
+        self.inner.write().global_offset = Some(offset);
+        Ok(())
+    }
+}
+
+impl ReconciliationStore for MemStore {
+    fn put_sketch(
+        &self,
+        conversation_id: &ConversationId,
+        range: &SyncRange,
+        sketch: &[u8],
+    ) -> MerkleToxResult<()> {
+        self.inner
+            .write()
+            .conversations
+            .entry(*conversation_id)
+            .or_default()
+            .sketches
+            .insert(range.clone(), sketch.to_vec());
+        Ok(())
+    }
+
+    fn get_sketch(
+        &self,
+        conversation_id: &ConversationId,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Option<Vec<u8>>> {
+        Ok(self
+            .inner
+            .read()
+            .conversations
+            .get(conversation_id)
+            .and_then(|c| c.sketches.get(range).cloned()))
+    }
+}
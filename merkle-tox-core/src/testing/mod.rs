@@ -2,17 +2,23 @@ pub mod cas;
 pub mod gateway;
 pub mod hub;
 pub mod identity;
+pub mod mem;
+pub mod peer;
+pub mod sim;
 pub mod store;
 
 pub use cas::{create_available_blob_info, create_blob_data, create_blob_info};
 pub use gateway::MerkleToxGateway;
-pub use hub::{SimulatedTransport, VirtualHub};
+pub use hub::{HubStats, SimulatedTransport, VirtualHub};
 pub use identity::{
     TestIdentity, TestRoom, create_admin_node, create_dummy_node, create_msg,
     create_signed_content_node, make_cert, random_signing_key, register_test_ephemeral_key,
     sign_admin_node, sign_content_node, sign_content_node_with_key, test_ephemeral_signing_key,
     test_node, test_pack_content_keys, transfer_ephemeral_keys,
 };
+pub use mem::MemStore;
+pub use peer::{TestPeer, find_sent, sent_to};
+pub use sim::{SimConfig, SimEvent, SimNetwork, SimPeer, SimStats};
 pub use store::{InMemoryStore, ManagedStore, delegate_store};
 
 /// Create a Genesis node with valid proof-of-work for testing.
@@ -150,6 +156,36 @@ pub fn apply_effects(effects: Vec<crate::engine::Effect>, store: &dyn crate::syn
             crate::engine::Effect::WriteEpochMetadata(cid, count, time) => {
                 let _ = store.update_epoch_metadata(&cid, count, time);
             }
+            crate::engine::Effect::WriteScheduledMessage(msg) => {
+                let _ = store.put_scheduled_message(&msg);
+            }
+            crate::engine::Effect::DeleteScheduledMessage(id) => {
+                let _ = store.remove_scheduled_message(id);
+            }
+            crate::engine::Effect::PruneNode(cid, hash) => {
+                let _ = store.prune_node(&cid, &hash);
+            }
+            crate::engine::Effect::WriteHistoryHorizon(cid, min_rank) => {
+                let _ = store.set_history_horizon(&cid, min_rank);
+            }
+            crate::engine::Effect::WriteAckedHeads(acked) => {
+                let _ = store.put_acked_heads(&acked);
+            }
+            crate::engine::Effect::WriteArchivedConversation(archive) => {
+                let _ = store.put_archived_conversation(&archive);
+            }
+            crate::engine::Effect::WriteBlacklistEntry(entry) => {
+                let _ = store.put_blacklist_entry(&entry);
+            }
+            crate::engine::Effect::DeleteBlacklistEntry(device_pk) => {
+                let _ = store.remove_blacklist_entry(&device_pk);
+            }
+            crate::engine::Effect::WriteInviteGrant(grant) => {
+                let _ = store.put_invite_grant(&grant);
+            }
+            crate::engine::Effect::DeleteInviteGrant(id) => {
+                let _ = store.remove_invite_grant(&id);
+            }
             _ => {}
         }
     }
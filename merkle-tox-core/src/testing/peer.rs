@@ -0,0 +1,183 @@
+use crate::ProtocolMessage;
+use crate::clock::ManualTimeProvider;
+use crate::dag::{Content, ConversationId, NodeHash, PhysicalDevicePk};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::sync::NodeStore;
+use crate::testing::{
+    InMemoryStore, TestIdentity, TestRoom, apply_effects, get_all_nodes_from_effects,
+    transfer_ephemeral_keys,
+};
+use std::sync::Arc;
+
+/// One device of a `TestRoom`: an engine with its own store.
+pub struct TestPeer {
+    pub engine: MerkleToxEngine,
+    pub store: InMemoryStore,
+    pub pk: PhysicalDevicePk,
+}
+
+impl TestPeer {
+    /// Creates the device of `id` with every room member authorized.
+    pub fn new(
+        room: &TestRoom,
+        id: &TestIdentity,
+        seed: u64,
+        tp: &Arc<ManualTimeProvider>,
+    ) -> Self {
+        let mut engine = id.engine(seed, tp);
+        let store = InMemoryStore::new();
+        room.setup_engine(&mut engine, &store);
+        Self {
+            engine,
+            store,
+            pk: id.device_pk,
+        }
+    }
+
+    /// Authors `content` and applies the resulting effects to the store.
+    pub fn author(&mut self, conversation_id: ConversationId, content: Content) -> Vec<Effect> {
+        let effects = self
+            .engine
+            .author_node(conversation_id, content, Vec::new(), &self.store)
+            .unwrap();
+        apply_effects(effects.clone(), &self.store);
+        effects
+    }
+
+    /// Authors `content` and returns the hashes of the nodes written, in
+    /// order. Key distribution nodes come before the authored node.
+    pub fn author_nodes(
+        &mut self,
+        conversation_id: ConversationId,
+        content: Content,
+    ) -> Vec<NodeHash> {
+        get_all_nodes_from_effects(&self.author(conversation_id, content))
+            .iter()
+            .map(|n| n.hash())
+            .collect()
+    }
+
+    /// Authors `content` and returns the hash of the authored node.
+    pub fn author_hash(&mut self, conversation_id: ConversationId, content: Content) -> NodeHash {
+        *self
+            .author_nodes(conversation_id, content)
+            .last()
+            .expect("authoring writes a node")
+    }
+
+    /// Authors a text message and returns its hash.
+    pub fn author_text(&mut self, conversation_id: ConversationId, text: &str) -> NodeHash {
+        self.author_hash(conversation_id, Content::Text(text.to_string()))
+    }
+
+    /// Starts sync sessions between `self` and `other` and exchanges their
+    /// `CapsAnnounce` messages.
+    pub fn handshake(&mut self, other: &mut TestPeer, conversation_id: ConversationId) {
+        self.exchange_caps(other, conversation_id, None);
+    }
+
+    /// Like [`TestPeer::handshake`], but `other` sees `features` in the
+    /// announcement of `self`.
+    pub fn handshake_announcing(
+        &mut self,
+        other: &mut TestPeer,
+        conversation_id: ConversationId,
+        features: u64,
+    ) {
+        self.exchange_caps(other, conversation_id, Some(features));
+    }
+
+    fn exchange_caps(
+        &mut self,
+        other: &mut TestPeer,
+        conversation_id: ConversationId,
+        features: Option<u64>,
+    ) {
+        let from_self = self
+            .engine
+            .start_sync(conversation_id, Some(other.pk), &self.store);
+        let from_other = other
+            .engine
+            .start_sync(conversation_id, Some(self.pk), &other.store);
+        let is_caps = |m: &ProtocolMessage| matches!(m, ProtocolMessage::CapsAnnounce { .. });
+        let msg = match (find_sent(&from_self, other.pk, is_caps), features) {
+            (Some(ProtocolMessage::CapsAnnounce { version, .. }), Some(features)) => {
+                ProtocolMessage::CapsAnnounce { version, features }
+            }
+            (msg, _) => msg.expect("start_sync announces capabilities"),
+        };
+        other
+            .engine
+            .handle_message(self.pk, msg, &other.store, None)
+            .unwrap();
+        let msg =
+            find_sent(&from_other, self.pk, is_caps).expect("start_sync announces capabilities");
+        self.engine
+            .handle_message(other.pk, msg, &self.store, None)
+            .unwrap();
+    }
+
+    /// Hands the nodes written in `effects` to the engine, in order, and
+    /// applies the results. Returns the effects of handling them.
+    pub fn receive_nodes(
+        &mut self,
+        conversation_id: ConversationId,
+        effects: &[Effect],
+    ) -> Vec<Effect> {
+        let mut received = Vec::new();
+        for node in get_all_nodes_from_effects(effects) {
+            let effects = self
+                .engine
+                .handle_node(conversation_id, node, &self.store, None)
+                .unwrap();
+            received.extend(effects.clone());
+            apply_effects(effects, &self.store);
+        }
+        received
+    }
+
+    /// Receives the node `hash` from the store of `from` as a `MerkleNode`
+    /// message and applies the results. Returns the effects of handling it.
+    pub fn receive_from(
+        &mut self,
+        from: &TestPeer,
+        conversation_id: ConversationId,
+        hash: NodeHash,
+    ) -> Vec<Effect> {
+        transfer_ephemeral_keys(&from.engine, &mut self.engine);
+        let msg = ProtocolMessage::MerkleNode {
+            conversation_id,
+            hash,
+            node: from.store.get_wire_node(&hash).unwrap(),
+        };
+        let effects = self
+            .engine
+            .handle_message(from.pk, msg, &self.store, None)
+            .unwrap();
+        apply_effects(effects.clone(), &self.store);
+        effects
+    }
+}
+
+/// Messages in `effects` sent to `to`.
+pub fn sent_to(effects: &[Effect], to: PhysicalDevicePk) -> Vec<ProtocolMessage> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(pk, msg) if *pk == to => Some(msg.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// First message in `effects` sent to `to` that matches `select`.
+pub fn find_sent(
+    effects: &[Effect],
+    to: PhysicalDevicePk,
+    select: impl Fn(&ProtocolMessage) -> bool,
+) -> Option<ProtocolMessage> {
+    effects.iter().find_map(|e| match e {
+        Effect::SendPacket(pk, msg) if *pk == to && select(msg) => Some(msg.clone()),
+        _ => None,
+    })
+}
@@ -0,0 +1,481 @@
+//! Deterministic multi-engine simulation.
+//!
+//! [`SimNetwork`] runs several [`MerkleToxEngine`]s against in-memory stores
+//! and a shared virtual clock, delivering `ProtocolMessage`s between them
+//! directly, without Tox. Latency, jitter, loss, partitions and peer outages
+//! are driven by a seeded RNG and a time-ordered schedule, so a run with the
+//! same seed and inputs is reproducible.
+//!
+//! Engines expect the reliable delivery `tox-sequenced` provides, so lost
+//! messages are modelled as retransmissions that arrive late. Partitions and
+//! outages drop messages outright, like a broken friend connection, and
+//! restart sync on the affected links once the peers can reach each other
+//! again.
+
+use crate::ProtocolMessage;
+use crate::clock::{ManualTimeProvider, TimeProvider};
+use crate::dag::{Content, ConversationId, NodeHash, PhysicalDevicePk, PhysicalDeviceSk};
+use crate::engine::{Effect, MerkleToxEngine};
+use crate::error::MerkleToxResult;
+use crate::sync::NodeStore;
+use crate::testing::{InMemoryStore, TestRoom, apply_effects, transfer_ephemeral_keys};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::Duration;
+use tox_proto::time::Instant;
+
+/// Network parameters of a simulation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimConfig {
+    /// Base one-way delay of every message.
+    pub latency: Duration,
+    /// Extra delay drawn uniformly from `0..=jitter` per message.
+    pub jitter: Duration,
+    /// Probability that a transmission is lost and has to be retried.
+    pub loss: f64,
+    /// Delay added by each retransmission of a lost message.
+    pub retransmit_timeout: Duration,
+    /// Virtual time advanced by each [`SimNetwork::step`].
+    pub tick: Duration,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::from_millis(50),
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            retransmit_timeout: Duration::from_secs(1),
+            tick: Duration::from_millis(10),
+        }
+    }
+}
+
+/// A change to the network applied at a scheduled virtual time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimEvent {
+    /// Splits the peers into groups (by index) that can only reach each other.
+    /// Peers not listed in any group are isolated.
+    Partition(Vec<Vec<usize>>),
+    /// Removes all partitions.
+    Heal,
+    /// Changes the loss probability.
+    SetLoss(f64),
+    /// Changes the base latency and jitter.
+    SetLatency { latency: Duration, jitter: Duration },
+    /// Takes a peer offline: it is not polled, and messages to or from it are
+    /// dropped.
+    Offline(usize),
+    /// Brings a peer back online.
+    Online(usize),
+}
+
+/// One simulated device.
+pub struct SimPeer {
+    pub engine: MerkleToxEngine,
+    pub store: InMemoryStore,
+    pub online: bool,
+}
+
+/// Message counters of a simulation run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub retransmitted: u64,
+    pub dropped: u64,
+}
+
+/// Retransmissions after which a message is given up on.
+const MAX_RETRANSMITS: u32 = 16;
+
+struct InFlight {
+    deliver_at: Instant,
+    seq: u64,
+    from: usize,
+    to: usize,
+    message: ProtocolMessage,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for InFlight {}
+
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Min-heap on delivery time; the sequence number keeps equal times FIFO.
+        (other.deliver_at, other.seq).cmp(&(self.deliver_at, self.seq))
+    }
+}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct Scheduled {
+    at: Instant,
+    seq: u64,
+    event: SimEvent,
+}
+
+/// A virtual network of engines driven by a manual clock.
+pub struct SimNetwork {
+    pub peers: Vec<SimPeer>,
+    pub config: SimConfig,
+    time_provider: Arc<ManualTimeProvider>,
+    rng: StdRng,
+    in_flight: BinaryHeap<InFlight>,
+    schedule: Vec<Scheduled>,
+    partitions: Vec<Vec<usize>>,
+    /// Peer pairs syncing a conversation, restarted after reconnecting.
+    links: Vec<(usize, usize, ConversationId)>,
+    next_seq: u64,
+    start: Instant,
+    stats: SimStats,
+}
+
+impl SimNetwork {
+    /// Creates an empty network. `seed` drives all network randomness.
+    pub fn new(seed: u64, config: SimConfig) -> Self {
+        let start = Instant::now();
+        Self {
+            peers: Vec::new(),
+            config,
+            time_provider: Arc::new(ManualTimeProvider::new(start, 1_000_000)),
+            rng: StdRng::seed_from_u64(seed),
+            in_flight: BinaryHeap::new(),
+            schedule: Vec::new(),
+            partitions: Vec::new(),
+            links: Vec::new(),
+            next_seq: 0,
+            start,
+            stats: SimStats::default(),
+        }
+    }
+
+    /// Creates a network with one engine per identity of `room`, each set up
+    /// with the room's conversation and syncing with every other peer.
+    pub fn with_room(room: &TestRoom, seed: u64, config: SimConfig) -> Self {
+        let mut sim = Self::new(seed, config);
+        for (i, id) in room.identities.iter().enumerate() {
+            let mut engine = MerkleToxEngine::with_sk(
+                id.device_pk,
+                id.master_pk,
+                PhysicalDeviceSk::from(id.device_sk.to_bytes()),
+                StdRng::seed_from_u64(seed.wrapping_add(i as u64 + 1)),
+                sim.time_provider.clone(),
+            );
+            let store = InMemoryStore::new();
+            room.setup_engine(&mut engine, &store);
+            sim.add_peer(engine, store);
+        }
+        for a in 0..sim.peers.len() {
+            for b in (a + 1)..sim.peers.len() {
+                sim.connect(a, b, room.conv_id);
+            }
+        }
+        sim
+    }
+
+    /// The virtual clock. Engines added to the network must use it.
+    pub fn time_provider(&self) -> Arc<ManualTimeProvider> {
+        self.time_provider.clone()
+    }
+
+    /// Virtual time elapsed since the network was created.
+    pub fn elapsed(&self) -> Duration {
+        self.time_provider.now_instant().duration_since(self.start)
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.stats
+    }
+
+    /// Adds a peer and returns its index.
+    pub fn add_peer(&mut self, engine: MerkleToxEngine, store: InMemoryStore) -> usize {
+        self.peers.push(SimPeer {
+            engine,
+            store,
+            online: true,
+        });
+        self.peers.len() - 1
+    }
+
+    pub fn index_of(&self, pk: &PhysicalDevicePk) -> Option<usize> {
+        self.peers.iter().position(|p| &p.engine.self_pk == pk)
+    }
+
+    /// Starts syncing `conversation_id` between peers `a` and `b`.
+    pub fn connect(&mut self, a: usize, b: usize, conversation_id: ConversationId) {
+        if !self.links.contains(&(a, b, conversation_id)) {
+            self.links.push((a, b, conversation_id));
+        }
+        self.start_sync(a, b, conversation_id);
+    }
+
+    /// Authors `content` on peer `idx` and makes its ephemeral signing keys
+    /// known to all other peers, standing in for key distribution.
+    pub fn author(
+        &mut self,
+        idx: usize,
+        conversation_id: ConversationId,
+        content: Content,
+    ) -> MerkleToxResult<NodeHash> {
+        let peer = &mut self.peers[idx];
+        let effects = peer
+            .engine
+            .author_node(conversation_id, content, Vec::new(), &peer.store)?;
+        let hash = effects
+            .iter()
+            .find_map(|e| match e {
+                Effect::WriteStore(_, node, _) => Some(node.hash()),
+                _ => None,
+            })
+            .expect("authoring always writes the new node");
+        self.process_effects(idx, effects);
+        self.share_ephemeral_keys(idx);
+        Ok(hash)
+    }
+
+    /// Copies the ephemeral signing keys of peer `idx` to all other peers.
+    pub fn share_ephemeral_keys(&mut self, idx: usize) {
+        let (before, rest) = self.peers.split_at_mut(idx);
+        let (source, after) = rest.split_first_mut().expect("peer index out of range");
+        for peer in before.iter_mut().chain(after.iter_mut()) {
+            transfer_ephemeral_keys(&source.engine, &mut peer.engine);
+        }
+    }
+
+    /// Schedules `event` to be applied `after` the current virtual time.
+    pub fn schedule(&mut self, after: Duration, event: SimEvent) {
+        let at = self.time_provider.now_instant() + after;
+        let seq = self.next_seq();
+        self.schedule.push(Scheduled { at, seq, event });
+    }
+
+    /// Applies `event` immediately.
+    pub fn apply(&mut self, event: SimEvent) {
+        let was_reachable: Vec<bool> = self
+            .links
+            .iter()
+            .map(|&(a, b, _)| self.can_reach(a, b))
+            .collect();
+        match event {
+            SimEvent::Partition(groups) => self.partitions = groups,
+            SimEvent::Heal => self.partitions.clear(),
+            SimEvent::SetLoss(loss) => self.config.loss = loss,
+            SimEvent::SetLatency { latency, jitter } => {
+                self.config.latency = latency;
+                self.config.jitter = jitter;
+            }
+            SimEvent::Offline(idx) => self.peers[idx].online = false,
+            SimEvent::Online(idx) => self.peers[idx].online = true,
+        }
+
+        for (i, (a, b, cid)) in self.links.clone().into_iter().enumerate() {
+            let reachable = self.can_reach(a, b);
+            if reachable == was_reachable[i] {
+                continue;
+            }
+            let (pk_a, pk_b) = (self.peers[a].engine.self_pk, self.peers[b].engine.self_pk);
+            self.peers[a].engine.set_peer_reachable(pk_b, reachable);
+            self.peers[b].engine.set_peer_reachable(pk_a, reachable);
+            if reachable {
+                self.start_sync(a, b, cid);
+            }
+        }
+    }
+
+    /// Returns true if peers `a` and `b` can currently exchange messages.
+    pub fn can_reach(&self, a: usize, b: usize) -> bool {
+        if !self.peers[a].online || !self.peers[b].online {
+            return false;
+        }
+        self.partitions.is_empty()
+            || self
+                .partitions
+                .iter()
+                .any(|group| group.contains(&a) && group.contains(&b))
+    }
+
+    /// Advances virtual time by one tick: applies due events, delivers due
+    /// messages and polls every online engine.
+    pub fn step(&mut self) {
+        self.time_provider.advance(self.config.tick);
+        let now = self.time_provider.now_instant();
+
+        let mut due = Vec::new();
+        self.schedule.retain(|s| {
+            if s.at <= now {
+                due.push((s.at, s.seq, s.event.clone()));
+                false
+            } else {
+                true
+            }
+        });
+        due.sort_by_key(|(at, seq, _)| (*at, *seq));
+        for (_, _, event) in due {
+            self.apply(event);
+        }
+
+        while self.in_flight.peek().is_some_and(|m| m.deliver_at <= now) {
+            let msg = self.in_flight.pop().expect("peeked");
+            self.deliver(msg);
+        }
+
+        for idx in 0..self.peers.len() {
+            if !self.peers[idx].online {
+                continue;
+            }
+            let peer = &mut self.peers[idx];
+            if let Ok(effects) = peer.engine.poll(now, &peer.store) {
+                self.process_effects(idx, effects);
+            }
+        }
+    }
+
+    /// Steps until `duration` of virtual time has passed.
+    pub fn run_for(&mut self, duration: Duration) {
+        let end = self.time_provider.now_instant() + duration;
+        while self.time_provider.now_instant() < end {
+            self.step();
+        }
+    }
+
+    /// Steps until `done` holds or `timeout` of virtual time has passed.
+    /// Returns whether `done` held.
+    pub fn run_until(&mut self, timeout: Duration, mut done: impl FnMut(&Self) -> bool) -> bool {
+        let end = self.time_provider.now_instant() + timeout;
+        loop {
+            if done(self) {
+                return true;
+            }
+            if self.time_provider.now_instant() >= end {
+                return false;
+            }
+            self.step();
+        }
+    }
+
+    /// Steps until all peers converge on `conversation_id` or `timeout` passes.
+    pub fn run_until_converged(
+        &mut self,
+        conversation_id: ConversationId,
+        timeout: Duration,
+    ) -> bool {
+        self.run_until(timeout, |sim| sim.is_converged(&conversation_id))
+    }
+
+    /// Sorted heads of `conversation_id` on peer `idx`.
+    pub fn heads(&self, idx: usize, conversation_id: &ConversationId) -> Vec<NodeHash> {
+        let mut heads = self.peers[idx].store.get_heads(conversation_id);
+        heads.sort_unstable();
+        heads
+    }
+
+    /// Returns true if every peer has the same heads and the same number of
+    /// verified nodes for `conversation_id`, with no speculative nodes left.
+    pub fn is_converged(&self, conversation_id: &ConversationId) -> bool {
+        let Some(first) = self.peers.first() else {
+            return true;
+        };
+        let heads = self.heads(0, conversation_id);
+        let counts = first.store.get_node_counts(conversation_id);
+        counts.1 == 0
+            && (1..self.peers.len()).all(|idx| {
+                self.heads(idx, conversation_id) == heads
+                    && self.peers[idx].store.get_node_counts(conversation_id) == counts
+            })
+    }
+
+    fn start_sync(&mut self, a: usize, b: usize, conversation_id: ConversationId) {
+        for (from, to) in [(a, b), (b, a)] {
+            let to_pk = self.peers[to].engine.self_pk;
+            let peer = &mut self.peers[from];
+            let effects = peer
+                .engine
+                .start_sync(conversation_id, Some(to_pk), &peer.store);
+            self.process_effects(from, effects);
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        seq
+    }
+
+    fn process_effects(&mut self, from: usize, effects: Vec<Effect>) {
+        let mut store_effects = Vec::new();
+        for effect in effects {
+            match effect {
+                Effect::SendPacket(to_pk, message) => self.send(from, to_pk, message),
+                other => store_effects.push(other),
+            }
+        }
+        apply_effects(store_effects, &self.peers[from].store);
+    }
+
+    fn send(&mut self, from: usize, to_pk: PhysicalDevicePk, message: ProtocolMessage) {
+        self.stats.sent += 1;
+        let Some(to) = self.index_of(&to_pk) else {
+            self.stats.dropped += 1;
+            return;
+        };
+        if !self.can_reach(from, to) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let loss = self.config.loss.clamp(0.0, 1.0);
+        let mut delay = self.config.latency;
+        let mut retransmits = 0;
+        while self.rng.gen_bool(loss) {
+            if retransmits == MAX_RETRANSMITS {
+                self.stats.dropped += 1;
+                return;
+            }
+            retransmits += 1;
+            delay += self.config.retransmit_timeout;
+        }
+        self.stats.retransmitted += u64::from(retransmits);
+        let jitter_ms = self.config.jitter.as_millis() as u64;
+        if jitter_ms > 0 {
+            delay += Duration::from_millis(self.rng.gen_range(0..=jitter_ms));
+        }
+        let deliver_at = self.time_provider.now_instant() + delay;
+        let seq = self.next_seq();
+        self.in_flight.push(InFlight {
+            deliver_at,
+            seq,
+            from,
+            to,
+            message,
+        });
+    }
+
+    fn deliver(&mut self, msg: InFlight) {
+        // Partitions and outages that start while a message is in flight
+        // still drop it.
+        if !self.can_reach(msg.from, msg.to) {
+            self.stats.dropped += 1;
+            return;
+        }
+        self.stats.delivered += 1;
+        let from_pk = self.peers[msg.from].engine.self_pk;
+        let peer = &mut self.peers[msg.to];
+        if let Ok(effects) =
+            peer.engine
+                .handle_message(from_pk, msg.message, &peer.store, Some(&peer.store))
+        {
+            self.process_effects(msg.to, effects);
+        }
+    }
+}
@@ -2,8 +2,14 @@ use crate::cas::{BlobInfo, BlobStatus, CHUNK_SIZE};
 use crate::dag::{
     ChainKey, ConversationId, KConv, MerkleNode, NodeHash, NodeType, PhysicalDevicePk,
 };
+use crate::engine::archive::ArchivedConversation;
+use crate::engine::outbox::AckedHeads;
+use crate::engine::quarantine::BlacklistEntry;
+use crate::engine::scheduled::ScheduledMessage;
 use crate::error::{MerkleToxError, MerkleToxResult};
-use crate::sync::{FullStore, SyncRange};
+use crate::invite::{InviteGrant, InviteId};
+use crate::suspend::SuspendedState;
+use crate::sync::{FullStore, StateSnapshot, SyncRange};
 use std::collections::{HashMap, HashSet};
 use std::sync::RwLock;
 
@@ -20,11 +26,21 @@ pub struct InMemoryStore {
     pub heads: RwLock<HashMap<ConversationId, Vec<NodeHash>>>,
     pub admin_heads: RwLock<HashMap<ConversationId, Vec<NodeHash>>>,
     pub blobs: RwLock<HashMap<NodeHash, (BlobInfo, Vec<u8>)>>,
+    pub staged_blobs: RwLock<HashMap<u64, Vec<u8>>>,
     pub keys: RwLock<HashMap<(ConversationId, u64), KConv>>,
     pub ratchet_keys: RwLock<HashMap<(ConversationId, NodeHash), (ChainKey, u64)>>,
     pub meta: RwLock<HashMap<ConversationId, (u32, i64)>>,
     pub sketches: RwLock<HashMap<(ConversationId, SyncRange), Vec<u8>>>,
     pub global_offset: RwLock<Option<i64>>,
+    pub scheduled: RwLock<HashMap<u64, ScheduledMessage>>,
+    pub pruned: RwLock<HashSet<NodeHash>>,
+    pub history_horizons: RwLock<HashMap<ConversationId, u64>>,
+    pub acked_heads: RwLock<HashMap<(ConversationId, PhysicalDevicePk), AckedHeads>>,
+    pub archived_conversations: RwLock<HashMap<ConversationId, ArchivedConversation>>,
+    pub blacklist: RwLock<HashMap<PhysicalDevicePk, BlacklistEntry>>,
+    pub invite_grants: RwLock<HashMap<InviteId, InviteGrant>>,
+    pub state_snapshots: RwLock<HashMap<ConversationId, StateSnapshot>>,
+    pub suspended_state: RwLock<Option<SuspendedState>>,
 }
 
 impl InMemoryStore {
@@ -157,6 +173,9 @@ impl crate::sync::NodeStore for InMemoryStore {
             .is_some_and(|(_, v)| *v)
     }
     fn get_node(&self, hash: &NodeHash) -> Option<MerkleNode> {
+        if self.pruned.read().unwrap().contains(hash) {
+            return None;
+        }
         self.nodes.read().unwrap().get(hash).map(|(n, _)| n.clone())
     }
     fn get_wire_node(&self, hash: &NodeHash) -> Option<crate::dag::WireNode> {
@@ -236,6 +255,13 @@ impl crate::sync::NodeStore for InMemoryStore {
         self.opaque_nodes.write().unwrap().remove(hash);
         Ok(())
     }
+    fn prune_node(&self, conv_id: &ConversationId, hash: &NodeHash) -> MerkleToxResult<bool> {
+        if !self.nodes.read().unwrap().contains_key(hash) {
+            return Ok(false);
+        }
+        self.remove_wire_node(conv_id, hash)?;
+        Ok(self.pruned.write().unwrap().insert(*hash))
+    }
     fn get_opaque_node_hashes(
         &self,
         _conversation_id: &ConversationId,
@@ -295,10 +321,11 @@ impl crate::sync::NodeStore for InMemoryStore {
         node_type: NodeType,
     ) -> MerkleToxResult<Vec<MerkleNode>> {
         let nodes = self.nodes.read().unwrap();
+        let pruned = self.pruned.read().unwrap();
         let mut res: Vec<_> = nodes
-            .values()
-            .filter(|(n, v)| *v && n.node_type() == node_type)
-            .map(|(n, _)| n.clone())
+            .iter()
+            .filter(|(h, (n, v))| *v && n.node_type() == node_type && !pruned.contains(*h))
+            .map(|(_, (n, _))| n.clone())
             .collect();
         res.sort_by_key(|n| n.topological_rank);
         Ok(res)
@@ -396,6 +423,121 @@ impl crate::sync::NodeStore for InMemoryStore {
             .remove(&(*conversation_id, *node_hash));
         Ok(())
     }
+    fn put_scheduled_message(&self, msg: &ScheduledMessage) -> MerkleToxResult<()> {
+        self.scheduled.write().unwrap().insert(msg.id, msg.clone());
+        Ok(())
+    }
+    fn remove_scheduled_message(&self, id: u64) -> MerkleToxResult<()> {
+        self.scheduled.write().unwrap().remove(&id);
+        Ok(())
+    }
+    fn get_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        self.scheduled.read().unwrap().values().cloned().collect()
+    }
+    fn put_acked_heads(&self, acked: &AckedHeads) -> MerkleToxResult<()> {
+        self.acked_heads
+            .write()
+            .unwrap()
+            .insert((acked.conversation_id, acked.device_pk), acked.clone());
+        Ok(())
+    }
+    fn get_acked_heads(&self) -> Vec<AckedHeads> {
+        self.acked_heads.read().unwrap().values().cloned().collect()
+    }
+    fn put_archived_conversation(&self, archive: &ArchivedConversation) -> MerkleToxResult<()> {
+        self.archived_conversations
+            .write()
+            .unwrap()
+            .insert(archive.conversation_id, archive.clone());
+        Ok(())
+    }
+    fn get_archived_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<ArchivedConversation> {
+        self.archived_conversations
+            .read()
+            .unwrap()
+            .get(conversation_id)
+            .cloned()
+    }
+    fn put_state_snapshot(&self, snapshot: &StateSnapshot) -> MerkleToxResult<()> {
+        self.state_snapshots
+            .write()
+            .unwrap()
+            .insert(snapshot.conversation_id, snapshot.clone());
+        Ok(())
+    }
+    fn get_state_snapshot(&self, conversation_id: &ConversationId) -> Option<StateSnapshot> {
+        self.state_snapshots
+            .read()
+            .unwrap()
+            .get(conversation_id)
+            .cloned()
+    }
+    fn put_blacklist_entry(&self, entry: &BlacklistEntry) -> MerkleToxResult<()> {
+        self.blacklist
+            .write()
+            .unwrap()
+            .insert(entry.device_pk, entry.clone());
+        Ok(())
+    }
+    fn remove_blacklist_entry(&self, device_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
+        self.blacklist.write().unwrap().remove(device_pk);
+        Ok(())
+    }
+    fn get_blacklist(&self) -> Vec<BlacklistEntry> {
+        self.blacklist.read().unwrap().values().cloned().collect()
+    }
+    fn put_invite_grant(&self, grant: &InviteGrant) -> MerkleToxResult<()> {
+        self.invite_grants
+            .write()
+            .unwrap()
+            .insert(grant.id(), grant.clone());
+        Ok(())
+    }
+    fn remove_invite_grant(&self, id: &InviteId) -> MerkleToxResult<()> {
+        self.invite_grants.write().unwrap().remove(id);
+        Ok(())
+    }
+    fn get_invite_grants(&self) -> Vec<InviteGrant> {
+        self.invite_grants
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+    fn put_suspended_state(&self, state: &SuspendedState) -> MerkleToxResult<()> {
+        *self.suspended_state.write().unwrap() = Some(state.clone());
+        Ok(())
+    }
+    fn get_suspended_state(&self) -> Option<SuspendedState> {
+        self.suspended_state.read().unwrap().clone()
+    }
+    fn remove_suspended_state(&self) -> MerkleToxResult<()> {
+        *self.suspended_state.write().unwrap() = None;
+        Ok(())
+    }
+    fn set_history_horizon(
+        &self,
+        conversation_id: &ConversationId,
+        min_rank: Option<u64>,
+    ) -> MerkleToxResult<()> {
+        let mut horizons = self.history_horizons.write().unwrap();
+        match min_rank {
+            Some(rank) => horizons.insert(*conversation_id, rank),
+            None => horizons.remove(conversation_id),
+        };
+        Ok(())
+    }
+    fn get_history_horizon(&self, conversation_id: &ConversationId) -> Option<u64> {
+        self.history_horizons
+            .read()
+            .unwrap()
+            .get(conversation_id)
+            .copied()
+    }
 }
 
 impl crate::sync::BlobStore for InMemoryStore {
@@ -483,6 +625,65 @@ impl crate::sync::BlobStore for InMemoryStore {
     ) -> MerkleToxResult<(Vec<u8>, Vec<u8>)> {
         Ok((self.get_chunk(hash, offset, length)?, Vec::new()))
     }
+    fn get_incomplete_blobs(&self) -> Vec<BlobInfo> {
+        self.blobs
+            .read()
+            .unwrap()
+            .values()
+            .filter(|(i, _)| i.status.is_incomplete())
+            .map(|(i, _)| i.clone())
+            .collect()
+    }
+    fn put_staged_chunk(&self, staging_id: u64, offset: u64, data: &[u8]) -> MerkleToxResult<()> {
+        let mut staged = self.staged_blobs.write().unwrap();
+        let buffer = staged.entry(staging_id).or_default();
+        if offset == 0 {
+            buffer.clear();
+        }
+        let end = offset as usize + data.len();
+        if buffer.len() < end {
+            buffer.resize(end, 0);
+        }
+        buffer[offset as usize..end].copy_from_slice(data);
+        Ok(())
+    }
+    fn commit_staged_blob(
+        &self,
+        _cid: &ConversationId,
+        staging_id: u64,
+        hash: &NodeHash,
+        size: u64,
+    ) -> MerkleToxResult<()> {
+        let mut data = self
+            .staged_blobs
+            .write()
+            .unwrap()
+            .remove(&staging_id)
+            .unwrap_or_default();
+        if self.has_blob(hash) {
+            return Ok(());
+        }
+        data.resize(size as usize, 0);
+        let num_chunks = size.div_ceil(CHUNK_SIZE);
+        let mut mask = vec![0u8; num_chunks.div_ceil(8) as usize];
+        for i in 0..num_chunks {
+            mask[(i / 8) as usize] |= 1 << (i % 8);
+        }
+        let info = BlobInfo {
+            hash: *hash,
+            size,
+            bao_root: None,
+            status: BlobStatus::Available,
+            received_mask: Some(mask),
+            decryption_key: None,
+        };
+        self.blobs.write().unwrap().insert(*hash, (info, data));
+        Ok(())
+    }
+    fn discard_staged_blob(&self, staging_id: u64) -> MerkleToxResult<()> {
+        self.staged_blobs.write().unwrap().remove(&staging_id);
+        Ok(())
+    }
 }
 
 impl crate::sync::GlobalStore for InMemoryStore {
@@ -601,6 +802,19 @@ macro_rules! __delegate_store {
             ) -> $crate::error::MerkleToxResult<()> {
                 self.$field.put_node(conversation_id, node, verified)
             }
+            fn put_nodes(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                nodes: Vec<($crate::dag::MerkleNode, bool)>,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_nodes(conversation_id, nodes)
+            }
+            fn get_nodes(
+                &self,
+                hashes: &[$crate::dag::NodeHash],
+            ) -> Vec<Option<$crate::dag::MerkleNode>> {
+                self.$field.get_nodes(hashes)
+            }
             fn put_wire_node(
                 &self,
                 conversation_id: &$crate::dag::ConversationId,
@@ -658,6 +872,32 @@ macro_rules! __delegate_store {
             ) -> $crate::error::MerkleToxResult<Vec<$crate::dag::NodeHash>> {
                 self.$field.get_node_hashes_in_range(conversation_id, range)
             }
+            fn get_nodes_by_author(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                author_pk: &$crate::dag::LogicalIdentityPk,
+                range: &$crate::sync::SyncRange,
+            ) -> $crate::error::MerkleToxResult<Vec<$crate::dag::MerkleNode>> {
+                self.$field
+                    .get_nodes_by_author(conversation_id, author_pk, range)
+            }
+            fn get_nodes_by_time_range(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                from_ms: i64,
+                to_ms: i64,
+                limit: usize,
+            ) -> $crate::error::MerkleToxResult<Vec<$crate::dag::MerkleNode>> {
+                self.$field
+                    .get_nodes_by_time_range(conversation_id, from_ms, to_ms, limit)
+            }
+            fn prune_node(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                hash: &$crate::dag::NodeHash,
+            ) -> $crate::error::MerkleToxResult<bool> {
+                self.$field.prune_node(conversation_id, hash)
+            }
             fn get_opaque_node_hashes(
                 &self,
                 conversation_id: &$crate::dag::ConversationId,
@@ -724,6 +964,115 @@ macro_rules! __delegate_store {
             ) -> $crate::error::MerkleToxResult<()> {
                 self.$field.remove_ratchet_key(conversation_id, node_hash)
             }
+            fn put_scheduled_message(
+                &self,
+                msg: &$crate::engine::scheduled::ScheduledMessage,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_scheduled_message(msg)
+            }
+            fn remove_scheduled_message(&self, id: u64) -> $crate::error::MerkleToxResult<()> {
+                self.$field.remove_scheduled_message(id)
+            }
+            fn get_scheduled_messages(&self) -> Vec<$crate::engine::scheduled::ScheduledMessage> {
+                self.$field.get_scheduled_messages()
+            }
+            fn put_acked_heads(
+                &self,
+                acked: &$crate::engine::outbox::AckedHeads,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_acked_heads(acked)
+            }
+            fn get_acked_heads(&self) -> Vec<$crate::engine::outbox::AckedHeads> {
+                self.$field.get_acked_heads()
+            }
+            fn put_archived_conversation(
+                &self,
+                archive: &$crate::engine::archive::ArchivedConversation,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_archived_conversation(archive)
+            }
+            fn get_archived_conversation(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+            ) -> Option<$crate::engine::archive::ArchivedConversation> {
+                self.$field.get_archived_conversation(conversation_id)
+            }
+            fn put_state_snapshot(
+                &self,
+                snapshot: &$crate::sync::StateSnapshot,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_state_snapshot(snapshot)
+            }
+            fn get_state_snapshot(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+            ) -> Option<$crate::sync::StateSnapshot> {
+                self.$field.get_state_snapshot(conversation_id)
+            }
+            fn put_blacklist_entry(
+                &self,
+                entry: &$crate::engine::quarantine::BlacklistEntry,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_blacklist_entry(entry)
+            }
+            fn remove_blacklist_entry(
+                &self,
+                device_pk: &$crate::dag::PhysicalDevicePk,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.remove_blacklist_entry(device_pk)
+            }
+            fn get_blacklist(&self) -> Vec<$crate::engine::quarantine::BlacklistEntry> {
+                self.$field.get_blacklist()
+            }
+            fn put_invite_grant(
+                &self,
+                grant: &$crate::invite::InviteGrant,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_invite_grant(grant)
+            }
+            fn remove_invite_grant(
+                &self,
+                id: &$crate::invite::InviteId,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.remove_invite_grant(id)
+            }
+            fn get_invite_grants(&self) -> Vec<$crate::invite::InviteGrant> {
+                self.$field.get_invite_grants()
+            }
+            fn put_suspended_state(
+                &self,
+                state: &$crate::suspend::SuspendedState,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_suspended_state(state)
+            }
+            fn get_suspended_state(&self) -> Option<$crate::suspend::SuspendedState> {
+                self.$field.get_suspended_state()
+            }
+            fn remove_suspended_state(&self) -> $crate::error::MerkleToxResult<()> {
+                self.$field.remove_suspended_state()
+            }
+            fn write_batch(
+                &self,
+                writes: Vec<$crate::sync::StoreWrite>,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.write_batch(writes)
+            }
+            fn flush(&self) -> $crate::error::MerkleToxResult<()> {
+                self.$field.flush()
+            }
+            fn set_history_horizon(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                min_rank: Option<u64>,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.set_history_horizon(conversation_id, min_rank)
+            }
+            fn get_history_horizon(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+            ) -> Option<u64> {
+                self.$field.get_history_horizon(conversation_id)
+            }
         }
 
         impl $crate::sync::BlobStore for $target {
@@ -766,6 +1115,40 @@ macro_rules! __delegate_store {
             ) -> $crate::error::MerkleToxResult<(Vec<u8>, Vec<u8>)> {
                 self.$field.get_chunk_with_proof(hash, offset, length)
             }
+            fn add_blob_ref(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                hash: &$crate::dag::NodeHash,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.add_blob_ref(conversation_id, hash)
+            }
+            fn blob_ref_count(&self, hash: &$crate::dag::NodeHash) -> usize {
+                self.$field.blob_ref_count(hash)
+            }
+            fn get_incomplete_blobs(&self) -> Vec<$crate::cas::BlobInfo> {
+                self.$field.get_incomplete_blobs()
+            }
+            fn put_staged_chunk(
+                &self,
+                staging_id: u64,
+                offset: u64,
+                data: &[u8],
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field.put_staged_chunk(staging_id, offset, data)
+            }
+            fn commit_staged_blob(
+                &self,
+                conversation_id: &$crate::dag::ConversationId,
+                staging_id: u64,
+                hash: &$crate::dag::NodeHash,
+                size: u64,
+            ) -> $crate::error::MerkleToxResult<()> {
+                self.$field
+                    .commit_staged_blob(conversation_id, staging_id, hash, size)
+            }
+            fn discard_staged_blob(&self, staging_id: u64) -> $crate::error::MerkleToxResult<()> {
+                self.$field.discard_staged_blob(staging_id)
+            }
         }
 
         impl $crate::sync::GlobalStore for $target {
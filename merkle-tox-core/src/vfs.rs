@@ -1,10 +1,10 @@
-use crate::clock::TimeProvider;
+use crate::clock::{SystemTimeProvider, TimeProvider};
 use std::collections::BTreeMap;
 use std::fmt::{self, Debug};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, Cursor, Read, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
@@ -13,6 +13,12 @@ pub trait FileHandle: Read + Write + Seek + Send + Sync + Debug {
     fn metadata(&self) -> io::Result<FileMetadata>;
     fn try_lock_exclusive(&self) -> io::Result<()>;
     fn try_lock_shared(&self) -> io::Result<()>;
+
+    /// Writes the file's data through to the storage device. Handles
+    /// without a device only flush.
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +113,9 @@ impl FileHandle for File {
     fn set_len(&mut self, size: u64) -> io::Result<()> {
         File::set_len(self, size)
     }
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
     fn metadata(&self) -> io::Result<FileMetadata> {
         let meta = self.metadata()?;
         Ok(FileMetadata {
@@ -115,14 +124,27 @@ impl FileHandle for File {
             modified: meta.modified().unwrap_or(SystemTime::now()),
         })
     }
+    #[cfg(not(target_arch = "wasm32"))]
     fn try_lock_exclusive(&self) -> io::Result<()> {
         fs2::FileExt::try_lock_exclusive(self)
     }
+    #[cfg(not(target_arch = "wasm32"))]
     fn try_lock_shared(&self) -> io::Result<()> {
         fs2::FileExt::try_lock_shared(self)
     }
+    // No other process shares the files of a wasm32 module.
+    #[cfg(target_arch = "wasm32")]
+    fn try_lock_exclusive(&self) -> io::Result<()> {
+        Ok(())
+    }
+    #[cfg(target_arch = "wasm32")]
+    fn try_lock_shared(&self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
+/// File system held in memory. Also the storage for wasm32 builds, which
+/// have no file system.
 #[derive(Debug, Clone)]
 pub struct MemFileSystem {
     inner: Arc<RwLock<MemFiles>>,
@@ -151,11 +173,11 @@ impl MemFileSystem {
     }
 
     fn now(&self) -> SystemTime {
-        if let Some(tp) = &self.time_provider {
-            UNIX_EPOCH + Duration::from_millis(tp.now_system_ms() as u64)
-        } else {
-            SystemTime::now()
-        }
+        let now_ms = match &self.time_provider {
+            Some(tp) => tp.now_system_ms(),
+            None => SystemTimeProvider.now_system_ms(),
+        };
+        UNIX_EPOCH + Duration::from_millis(now_ms as u64)
     }
 }
 
@@ -378,6 +400,9 @@ pub struct FaultInjectingFileSystem {
     fail_probability: Arc<AtomicU64>, // scaled by 10^6
     enospc_at: Arc<AtomicU64>,
     total_written: Arc<AtomicU64>,
+    crash_at: Arc<AtomicU64>,
+    corrupt_at: Arc<AtomicU64>,
+    crashed: Arc<AtomicBool>,
 }
 
 impl FaultInjectingFileSystem {
@@ -387,9 +412,42 @@ impl FaultInjectingFileSystem {
             fail_probability: Arc::new(AtomicU64::new(0)),
             enospc_at: Arc::new(AtomicU64::new(u64::MAX)),
             total_written: Arc::new(AtomicU64::new(0)),
+            crash_at: Arc::new(AtomicU64::new(u64::MAX)),
+            corrupt_at: Arc::new(AtomicU64::new(u64::MAX)),
+            crashed: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Simulates a power loss once `limit` bytes have been written in total.
+    /// The write crossing the limit is torn: only the bytes before the limit
+    /// reach the inner file system. Every mutation after that fails.
+    pub fn set_crash_at(&self, limit: u64) {
+        self.crash_at.store(limit, Ordering::SeqCst);
+    }
+
+    /// Flips the lowest bit of the byte at write position `at`, counted over
+    /// all bytes written through this file system.
+    pub fn set_corrupt_at(&self, at: u64) {
+        self.corrupt_at.store(at, Ordering::SeqCst);
+    }
+
+    /// Returns true once a crash set up with [`Self::set_crash_at`] happened.
+    pub fn has_crashed(&self) -> bool {
+        self.crashed.load(Ordering::SeqCst)
+    }
+
+    /// Total number of bytes written so far, for picking crash points.
+    pub fn bytes_written(&self) -> u64 {
+        self.total_written.load(Ordering::SeqCst)
+    }
+
+    fn check_crashed(&self) -> io::Result<()> {
+        if self.crashed.load(Ordering::SeqCst) {
+            return Err(io::Error::other("Simulated crash"));
+        }
+        Ok(())
+    }
+
     pub fn set_fail_probability(&self, prob: f64) {
         self.fail_probability
             .store((prob * 1_000_000.0) as u64, Ordering::SeqCst);
@@ -431,16 +489,29 @@ impl FileSystem for FaultInjectingFileSystem {
         self.inner.read(path)
     }
     fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.check_crashed()?;
+        let start = self.total_written.load(Ordering::SeqCst);
+        let contents = corrupt(contents, start, self.corrupt_at.load(Ordering::SeqCst));
+        let crash_at = self.crash_at.load(Ordering::SeqCst);
+        if crash_at < start + contents.len() as u64 {
+            let keep = crash_at.saturating_sub(start) as usize;
+            self.total_written.fetch_add(keep as u64, Ordering::SeqCst);
+            self.crashed.store(true, Ordering::SeqCst);
+            self.inner.write(path, &contents[..keep])?;
+            return Err(io::Error::other("Simulated crash"));
+        }
         self.check_write(contents.len() as u64)?;
-        self.inner.write(path, contents)
+        self.inner.write(path, &contents)
     }
     fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.check_crashed()?;
         if self.should_fail() {
             return Err(io::Error::other("Injected fault"));
         }
         self.inner.rename(from, to)
     }
     fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.check_crashed()?;
         self.inner.remove_file(path)
     }
     fn remove_dir(&self, path: &Path) -> io::Result<()> {
@@ -465,25 +536,53 @@ impl FileSystem for FaultInjectingFileSystem {
         create: bool,
         truncate: bool,
     ) -> io::Result<Box<dyn FileHandle>> {
+        if create || truncate {
+            self.check_crashed()?;
+        }
         let handle = self.inner.open(path, write, create, truncate)?;
         Ok(Box::new(FaultInjectingHandle {
             inner: handle,
             fail_probability: self.fail_probability.clone(),
             enospc_at: self.enospc_at.clone(),
             total_written: self.total_written.clone(),
+            crash_at: self.crash_at.clone(),
+            corrupt_at: self.corrupt_at.clone(),
+            crashed: self.crashed.clone(),
         }))
     }
 }
 
+/// Returns `data` with the lowest bit of the byte at stream position
+/// `corrupt_at` flipped, if that position falls inside the write.
+fn corrupt(data: &[u8], start: u64, corrupt_at: u64) -> std::borrow::Cow<'_, [u8]> {
+    if corrupt_at >= start && corrupt_at < start + data.len() as u64 {
+        let mut owned = data.to_vec();
+        owned[(corrupt_at - start) as usize] ^= 0x01;
+        std::borrow::Cow::Owned(owned)
+    } else {
+        std::borrow::Cow::Borrowed(data)
+    }
+}
+
 #[derive(Debug)]
 struct FaultInjectingHandle {
     inner: Box<dyn FileHandle>,
     fail_probability: Arc<AtomicU64>,
     enospc_at: Arc<AtomicU64>,
     total_written: Arc<AtomicU64>,
+    crash_at: Arc<AtomicU64>,
+    corrupt_at: Arc<AtomicU64>,
+    crashed: Arc<AtomicBool>,
 }
 
 impl FaultInjectingHandle {
+    fn check_crashed(&self) -> io::Result<()> {
+        if self.crashed.load(Ordering::SeqCst) {
+            return Err(io::Error::other("Simulated crash"));
+        }
+        Ok(())
+    }
+
     fn should_fail(&self) -> bool {
         let prob = self.fail_probability.load(Ordering::SeqCst);
         if prob == 0 {
@@ -516,10 +615,22 @@ impl Read for FaultInjectingHandle {
 
 impl Write for FaultInjectingHandle {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_crashed()?;
+        let start = self.total_written.load(Ordering::SeqCst);
+        let buf = corrupt(buf, start, self.corrupt_at.load(Ordering::SeqCst));
+        let crash_at = self.crash_at.load(Ordering::SeqCst);
+        if crash_at < start + buf.len() as u64 {
+            let keep = crash_at.saturating_sub(start) as usize;
+            self.total_written.fetch_add(keep as u64, Ordering::SeqCst);
+            self.crashed.store(true, Ordering::SeqCst);
+            self.inner.write_all(&buf[..keep])?;
+            return Err(io::Error::other("Simulated crash"));
+        }
         self.check_write(buf.len() as u64)?;
-        self.inner.write(buf)
+        self.inner.write(&buf)
     }
     fn flush(&mut self) -> io::Result<()> {
+        self.check_crashed()?;
         if self.should_fail() {
             return Err(io::Error::other("Injected fault on flush"));
         }
@@ -535,9 +646,14 @@ impl Seek for FaultInjectingHandle {
 
 impl FileHandle for FaultInjectingHandle {
     fn set_len(&mut self, size: u64) -> io::Result<()> {
+        self.check_crashed()?;
         self.check_write(size)?;
         self.inner.set_len(size)
     }
+    fn sync(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.inner.sync()
+    }
     fn metadata(&self) -> io::Result<FileMetadata> {
         self.inner.metadata()
     }
@@ -1,63 +1,241 @@
-use crate::dag::{Content, ConversationId, MerkleNode, NodeHash, NodeType};
-use crate::sync::NodeStore;
-use std::collections::HashSet;
-use std::fmt::Write;
+//! Export of conversation DAGs for visualization and debugging.
+//!
+//! Nodes are read from the store in rank batches, so the streaming writers
+//! ([`write_dot`], [`write_json_lines`]) never hold the whole graph in memory.
 
-/// Exports conversation DAG to Graphviz .dot format string.
-pub fn export_dot(conversation_id: &ConversationId, store: &dyn NodeStore) -> String {
-    let mut dot = String::new();
-    dot.push_str("digraph DAG {\n");
-    dot.push_str("  rankdir=LR;\n");
-    dot.push_str("  node [shape=box, fontname=\"Courier\"];\n");
+use crate::dag::{Content, ConversationId, LogicalIdentityPk, MerkleNode, NodeHash, NodeType};
+use crate::sync::{NodeStore, SyncRange};
+use std::io::{self, Write};
+
+/// Number of ranks read from the store at a time.
+const EXPORT_RANK_BATCH: u64 = 1024;
 
-    let mut visited = HashSet::new();
-    let mut stack = store.get_heads(conversation_id);
+/// One node of an exported graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VizNode {
+    pub hash: NodeHash,
+    pub parents: Vec<NodeHash>,
+    pub rank: u64,
+    pub node_type: NodeType,
+    pub verified: bool,
+    pub author_pk: LogicalIdentityPk,
+    pub sequence_number: u64,
+    pub timestamp: i64,
+    /// Short description of the content, e.g. `Text: hello`.
+    pub summary: String,
+}
 
-    while let Some(hash) = stack.pop() {
-        if !visited.insert(hash) {
-            continue;
+impl VizNode {
+    fn new(hash: NodeHash, node: &MerkleNode, verified: bool) -> Self {
+        Self {
+            hash,
+            parents: node.parents.clone(),
+            rank: node.topological_rank,
+            node_type: node.node_type(),
+            verified,
+            author_pk: node.author_pk,
+            sequence_number: node.sequence_number,
+            timestamp: node.network_timestamp,
+            summary: summarize_content(&node.content),
         }
+    }
 
-        if let Some(node) = store.get_node(&hash) {
-            let label = format_node_label(&hash, &node);
-            let color = match node.node_type() {
-                NodeType::Admin => "lightblue",
-                NodeType::Content => "white",
-            };
+    /// Fill color derived from the author key, so each author's nodes share
+    /// a color across exports.
+    pub fn author_color(&self) -> String {
+        let b = self.author_pk.as_bytes();
+        format!(
+            "#{:02x}{:02x}{:02x}",
+            b[0] / 2 + 0x80,
+            b[1] / 2 + 0x80,
+            b[2] / 2 + 0x80
+        )
+    }
+}
 
-            writeln!(
-                dot,
-                "  \"{}\" [label=\"{}\", fillcolor=\"{}\", style=filled];",
-                hex::encode(hash.as_bytes()),
-                label,
-                color
-            )
-            .unwrap();
-
-            for parent in &node.parents {
-                writeln!(
-                    dot,
-                    "  \"{}\" -> \"{}\";",
-                    hex::encode(hash.as_bytes()),
-                    hex::encode(parent.as_bytes())
-                )
-                .unwrap();
-                stack.push(*parent);
+/// Calls `f` for every node of the conversation, verified and speculative,
+/// ordered by rank and then hash. Speculative nodes come last.
+pub fn visit_nodes<E>(
+    conversation_id: &ConversationId,
+    store: &dyn NodeStore,
+    mut f: impl FnMut(VizNode) -> Result<(), E>,
+) -> Result<(), E> {
+    let max_rank = store
+        .get_heads(conversation_id)
+        .into_iter()
+        .chain(store.get_admin_heads(conversation_id))
+        .filter_map(|h| store.get_node(&h).map(|n| n.topological_rank))
+        .max();
+
+    if let Some(max_rank) = max_rank {
+        let mut min_rank = 0;
+        while min_rank <= max_rank {
+            let range = SyncRange {
+                min_rank,
+                max_rank: min_rank.saturating_add(EXPORT_RANK_BATCH - 1).min(max_rank),
+            };
+            let mut batch: Vec<VizNode> = store
+                .get_node_hashes_in_range(conversation_id, &range)
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|h| store.get_node(&h).map(|n| VizNode::new(h, &n, true)))
+                .collect();
+            batch.sort_unstable_by_key(|n| (n.rank, n.hash));
+            for node in batch {
+                f(node)?;
             }
+            if range.max_rank == u64::MAX {
+                break;
+            }
+            min_rank = range.max_rank + 1;
         }
     }
 
-    dot.push_str("}\n");
-    dot
+    let mut speculative: Vec<VizNode> = store
+        .get_speculative_nodes(conversation_id)
+        .into_iter()
+        .map(|n| VizNode::new(n.hash(), &n, false))
+        .collect();
+    speculative.sort_unstable_by_key(|n| (n.rank, n.hash));
+    for node in speculative {
+        f(node)?;
+    }
+    Ok(())
 }
 
-fn format_node_label(hash: &NodeHash, node: &MerkleNode) -> String {
-    let hash_short = &hex::encode(hash.as_bytes())[0..8];
-    let author_short = &hex::encode(node.author_pk.as_bytes())[0..8];
-    let content_summary = match &node.content {
+/// Exports conversation DAG to Graphviz .dot format string.
+pub fn export_dot(conversation_id: &ConversationId, store: &dyn NodeStore) -> String {
+    let mut out = Vec::new();
+    write_dot(conversation_id, store, &mut out).expect("writing to a Vec cannot fail");
+    String::from_utf8(out).expect("dot output is UTF-8")
+}
+
+/// Streams the conversation DAG in Graphviz .dot format.
+///
+/// Nodes are filled with their author's color. Admin nodes are drawn with a
+/// double border, speculative nodes with a dashed one.
+pub fn write_dot(
+    conversation_id: &ConversationId,
+    store: &dyn NodeStore,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    writeln!(out, "digraph DAG {{")?;
+    writeln!(out, "  rankdir=LR;")?;
+    writeln!(out, "  node [shape=box, fontname=\"Courier\"];")?;
+
+    visit_nodes::<io::Error>(conversation_id, store, |node| {
+        let id = hex::encode(node.hash.as_bytes());
+        let style = if node.verified {
+            "filled"
+        } else {
+            "filled,dashed"
+        };
+        let peripheries = match node.node_type {
+            NodeType::Admin => 2,
+            NodeType::Content => 1,
+        };
+        writeln!(
+            out,
+            "  \"{}\" [label=\"{}\", fillcolor=\"{}\", style=\"{}\", peripheries={}];",
+            id,
+            format_node_label(&node),
+            node.author_color(),
+            style,
+            peripheries
+        )?;
+        for parent in &node.parents {
+            writeln!(
+                out,
+                "  \"{}\" -> \"{}\";",
+                id,
+                hex::encode(parent.as_bytes())
+            )?;
+        }
+        Ok(())
+    })?;
+
+    writeln!(out, "}}")
+}
+
+/// Exports the conversation DAG as a JSON document:
+/// `{"conversation_id": "..", "nodes": [..]}`.
+pub fn export_json(conversation_id: &ConversationId, store: &dyn NodeStore) -> String {
+    let mut out = String::new();
+    out.push_str("{\"conversation_id\":\"");
+    out.push_str(&hex::encode(conversation_id.as_bytes()));
+    out.push_str("\",\"nodes\":[");
+    let mut first = true;
+    let _ = visit_nodes::<()>(conversation_id, store, |node| {
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        out.push_str(&node_json(&node));
+        Ok(())
+    });
+    out.push_str("]}");
+    out
+}
+
+/// Streams the conversation DAG as JSON Lines, one node object per line.
+pub fn write_json_lines(
+    conversation_id: &ConversationId,
+    store: &dyn NodeStore,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    visit_nodes(conversation_id, store, |node| {
+        writeln!(out, "{}", node_json(&node))
+    })
+}
+
+fn node_json(node: &VizNode) -> String {
+    let parents: Vec<String> = node
+        .parents
+        .iter()
+        .map(|p| format!("\"{}\"", hex::encode(p.as_bytes())))
+        .collect();
+    format!(
+        "{{\"hash\":\"{}\",\"parents\":[{}],\"rank\":{},\"type\":\"{}\",\"verified\":{},\
+         \"author\":\"{}\",\"color\":\"{}\",\"seq\":{},\"timestamp\":{},\"summary\":{}}}",
+        hex::encode(node.hash.as_bytes()),
+        parents.join(","),
+        node.rank,
+        match node.node_type {
+            NodeType::Admin => "admin",
+            NodeType::Content => "content",
+        },
+        node.verified,
+        hex::encode(node.author_pk.as_bytes()),
+        node.author_color(),
+        node.sequence_number,
+        node.timestamp,
+        json_string(&node.summary)
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn summarize_content(content: &Content) -> String {
+    match content {
         Content::Text(t) => {
-            let truncated = if t.len() > 20 {
-                format!("{}...", &t[0..17])
+            let truncated = if t.chars().count() > 20 {
+                format!("{}...", t.chars().take(17).collect::<String>())
             } else {
                 t.clone()
             };
@@ -65,6 +243,13 @@ fn format_node_label(hash: &NodeHash, node: &MerkleNode) -> String {
         }
         Content::Control(c) => format!("Ctrl: {:?}", c),
         Content::Blob { name, .. } => format!("Blob: {}", name),
+        Content::Voice { duration_ms, .. } => format!("Voice: {}ms", duration_ms),
+        Content::Poll { question, .. } => format!("Poll: {}", question),
+        Content::PollVote { choices, .. } => format!("Vote: {:?}", choices),
+        Content::PollClose { .. } => "PollClose".to_string(),
+        Content::StickerPack { name, stickers } => {
+            format!("StickerPack: {} ({})", name, stickers.len())
+        }
         Content::Reaction { emoji, .. } => format!("Reaction: {:?}", emoji),
         Content::Redaction { reason, .. } => format!("Redaction: {}", reason),
         Content::KeyWrap { generation, .. } => format!("KeyWrap: gen={}", generation),
@@ -75,11 +260,20 @@ fn format_node_label(hash: &NodeHash, node: &MerkleNode) -> String {
         Content::Edit { .. } => "Edit".to_string(),
         Content::Custom { .. } => "Custom".to_string(),
         Content::Unknown { discriminant, .. } => format!("Unknown({})", discriminant),
-    };
+    }
+}
 
+fn format_node_label(node: &VizNode) -> String {
+    let hash_short = &hex::encode(node.hash.as_bytes())[0..8];
+    let author_short = &hex::encode(node.author_pk.as_bytes())[0..8];
     format!(
-        "ID: {}\nAuthor: {}\nRank: {}\n{}",
-        hash_short, author_short, node.topological_rank, content_summary
+        "ID: {}\\nAuthor: {}\\nRank: {}\\n{}",
+        hash_short,
+        author_short,
+        node.rank,
+        node.summary
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
     )
-    .replace("\"", "\\\"")
 }
@@ -0,0 +1,133 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::Content;
+use merkle_tox_core::engine::config::SyncConfig;
+use merkle_tox_core::sync::Tier;
+use merkle_tox_core::testing::{TestRoom, apply_effects, find_sent};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn sketch_tier(msg: &ProtocolMessage) -> Tier {
+    match msg {
+        ProtocolMessage::SyncSketch(sketch) => Tier::from_cell_count(sketch.cells.len()),
+        other => panic!("expected SyncSketch, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_sync_config_start_tier() {
+    let config = SyncConfig::default();
+    assert_eq!(config.start_tier(None), Tier::Small);
+    assert_eq!(config.start_tier(Some(5)), Tier::Tiny);
+    // 100 differences with 50% headroom need Medium.
+    assert_eq!(config.start_tier(Some(100)), Tier::Medium);
+    assert_eq!(config.start_tier(Some(10_000)), Tier::Large);
+
+    let capped = SyncConfig {
+        initial_tier: Tier::Tiny,
+        max_tier: Tier::Medium,
+        ..SyncConfig::default()
+    };
+    assert_eq!(capped.start_tier(None), Tier::Tiny);
+    assert_eq!(capped.start_tier(Some(10_000)), Tier::Medium);
+}
+
+#[test]
+fn test_sketch_escalates_after_decode_failure() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+    a.handshake(&mut b, cid);
+
+    // A holds history B has never seen: more than a Small sketch can decode.
+    for i in 0..60 {
+        let effects = a
+            .engine
+            .author_node(cid, Content::Text(format!("m{}", i)), vec![], &a.store)
+            .unwrap();
+        apply_effects(effects, &a.store);
+    }
+
+    // A starts a round; B answers the differing shard with a Small sketch.
+    let effects = a.engine.poll(tp.now_instant(), &a.store).unwrap();
+    let checksums = find_sent(&effects, b.pk, |m| {
+        matches!(m, ProtocolMessage::SyncShardChecksums { .. })
+    })
+    .expect("A should send shard checksums");
+    let effects = b
+        .engine
+        .handle_message(a.pk, checksums, &b.store, None)
+        .unwrap();
+    let sketch = find_sent(&effects, a.pk, |m| {
+        matches!(m, ProtocolMessage::SyncSketch(_))
+    })
+    .unwrap();
+    assert_eq!(sketch_tier(&sketch), Tier::Small);
+
+    // A fails to decode it, reports back, and does not punish B.
+    let effects = a
+        .engine
+        .handle_message(b.pk, sketch, &a.store, None)
+        .unwrap();
+    let fail = find_sent(&effects, b.pk, |m| {
+        matches!(m, ProtocolMessage::SyncReconFail { .. })
+    })
+    .expect("A should report the decode failure");
+    assert!(!a.engine.is_blacklisted(&b.pk, tp.now_system_ms()));
+    assert_eq!(a.engine.recon_start_tier(b.pk, cid), Tier::Medium);
+
+    // B resends the range right away, one tier larger.
+    let effects = b.engine.handle_message(a.pk, fail, &b.store, None).unwrap();
+    let sketch = find_sent(&effects, a.pk, |m| {
+        matches!(m, ProtocolMessage::SyncSketch(_))
+    })
+    .expect("B should retry with a larger sketch");
+    assert_eq!(sketch_tier(&sketch), Tier::Medium);
+    assert_eq!(b.engine.recon_start_tier(a.pk, cid), Tier::Medium);
+
+    // Medium sketches go through the PoW challenge before decoding.
+    let effects = a
+        .engine
+        .handle_message(b.pk, sketch, &a.store, None)
+        .unwrap();
+    let challenge = find_sent(&effects, b.pk, |m| {
+        matches!(m, ProtocolMessage::ReconPowChallenge { .. })
+    })
+    .unwrap();
+    let effects = b
+        .engine
+        .handle_message(a.pk, challenge, &b.store, None)
+        .unwrap();
+    let solution = find_sent(&effects, a.pk, |m| {
+        matches!(m, ProtocolMessage::ReconPowSolution { .. })
+    })
+    .unwrap();
+    let effects = a
+        .engine
+        .handle_message(b.pk, solution, &a.store, None)
+        .unwrap();
+    assert!(
+        find_sent(&effects, b.pk, |m| matches!(
+            m,
+            ProtocolMessage::MerkleNode { .. } | ProtocolMessage::MerkleNodeWithHeads { .. }
+        ))
+        .is_some()
+    );
+    assert!(
+        find_sent(&effects, b.pk, |m| matches!(
+            m,
+            ProtocolMessage::SyncReconFail { .. }
+        ))
+        .is_none()
+    );
+
+    // The decoded divergence sizes the next round.
+    let differences = a.engine.recon_estimates[&(b.pk, cid)];
+    assert!(differences > Tier::Small.d_max() && differences <= Tier::Medium.d_max());
+    assert_eq!(
+        a.engine.recon_start_tier(b.pk, cid),
+        SyncConfig::default().start_tier(Some(differences))
+    );
+}
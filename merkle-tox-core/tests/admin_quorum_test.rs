@@ -0,0 +1,241 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, ControlAction, MerkleNode};
+use merkle_tox_core::engine::quorum::requires_quorum;
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::identity::CausalContext;
+use merkle_tox_core::testing::{
+    TestIdentity, TestPeer, TestRoom, apply_effects, get_all_nodes_from_effects,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The Admin node holding `action` in `effects`.
+fn admin_node(effects: &[Effect], action: fn(&ControlAction) -> bool) -> MerkleNode {
+    get_all_nodes_from_effects(effects)
+        .into_iter()
+        .find(|n| matches!(&n.content, Content::Control(a) if action(a)))
+        .unwrap()
+}
+
+fn is_authorized(engine: &MerkleToxEngine, room: &TestRoom, id: &TestIdentity) -> bool {
+    engine.identity_manager.is_authorized(
+        &CausalContext::global(),
+        room.conv_id,
+        &id.device_pk,
+        &id.master_pk,
+        2000,
+        u64::MAX,
+    )
+}
+
+/// Alice, Bob and Carol are admins; Alice sets a quorum of 2, which Carol
+/// receives.
+fn quorum_of_two(room: &TestRoom) -> (TestPeer, TestPeer) {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let mut carol = room.peer(2, &tp);
+    let effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::SetAdminQuorum { threshold: 2 }),
+    );
+    carol.receive_nodes(room.conv_id, &effects);
+    for p in [&alice, &carol] {
+        assert_eq!(p.engine.identity_manager.admin_quorum(room.conv_id), 2);
+    }
+    (alice, carol)
+}
+
+#[test]
+fn test_destructive_actions_require_quorum() {
+    let room = TestRoom::new(3);
+    let id = &room.identities[0];
+    let node = |content: Content| {
+        let mut node = merkle_tox_core::testing::test_node();
+        node.author_pk = id.master_pk;
+        node.content = content;
+        node
+    };
+    let other = room.identities[1].master_pk;
+    assert!(requires_quorum(&node(Content::Control(
+        ControlAction::CloseConversation
+    ))));
+    assert!(requires_quorum(&node(Content::Control(
+        ControlAction::Leave(other)
+    ))));
+    assert!(!requires_quorum(&node(Content::Control(
+        ControlAction::Leave(id.master_pk)
+    ))));
+    assert!(!requires_quorum(&node(Content::Control(
+        ControlAction::SetTitle("title".to_string())
+    ))));
+}
+
+#[test]
+fn test_revocation_takes_effect_once_co_signed() {
+    let room = TestRoom::new(3);
+    let (mut alice, mut carol) = quorum_of_two(&room);
+    let bob = &room.identities[1];
+
+    let effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::RevokeDevice {
+            target_device_pk: bob.device_pk,
+            reason: "compromised".to_string(),
+        }),
+    );
+    let revoke = admin_node(&effects, |a| {
+        matches!(a, ControlAction::RevokeDevice { .. })
+    });
+    carol.receive_nodes(room.conv_id, &effects);
+
+    for p in [&alice, &carol] {
+        assert!(is_authorized(&p.engine, &room, bob));
+        let pending = p.engine.pending_admin_actions(room.conv_id);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].hash, revoke.hash());
+        assert_eq!(pending[0].threshold, 2);
+        assert_eq!(pending[0].signers, vec![room.identities[0].master_pk]);
+    }
+
+    // Alice signing again does not count twice.
+    let effects = alice
+        .engine
+        .co_sign(room.conv_id, revoke.hash(), &alice.store)
+        .unwrap();
+    apply_effects(effects, &alice.store);
+    assert!(is_authorized(&alice.engine, &room, bob));
+
+    let effects = carol
+        .engine
+        .co_sign(room.conv_id, revoke.hash(), &carol.store)
+        .unwrap();
+    apply_effects(effects.clone(), &carol.store);
+    assert!(!is_authorized(&carol.engine, &room, bob));
+    assert!(carol.engine.pending_admin_actions(room.conv_id).is_empty());
+
+    let received = alice.receive_nodes(room.conv_id, &effects);
+    assert!(!is_authorized(&alice.engine, &room, bob));
+    assert!(received.iter().any(|e| matches!(
+        e,
+        Effect::EmitEvent(NodeEvent::MemberLeft { member_pk, hash, .. })
+            if *member_pk == bob.master_pk && *hash == revoke.hash()
+    )));
+
+    // The outcome survives a restart.
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut restarted = room.identities[0].engine(3, &tp);
+    restarted
+        .load_conversation_state(room.conv_id, &alice.store)
+        .unwrap();
+    assert!(restarted.pending_admin_actions(room.conv_id).is_empty());
+    assert!(!is_authorized(&restarted, &room, bob));
+}
+
+#[test]
+fn test_lowering_quorum_requires_quorum() {
+    let room = TestRoom::new(3);
+    let (mut alice, mut carol) = quorum_of_two(&room);
+
+    let lower_effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::SetAdminQuorum { threshold: 1 }),
+    );
+    let lower = admin_node(&lower_effects, |a| {
+        matches!(a, ControlAction::SetAdminQuorum { .. })
+    });
+    assert_eq!(alice.engine.identity_manager.admin_quorum(room.conv_id), 2);
+
+    let effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::CloseConversation),
+    );
+    assert!(!alice.engine.is_closed(room.conv_id));
+
+    // A restart keeps both pending.
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut restarted = room.identities[0].engine(3, &tp);
+    restarted
+        .load_conversation_state(room.conv_id, &alice.store)
+        .unwrap();
+    assert_eq!(restarted.pending_admin_actions(room.conv_id).len(), 2);
+    assert!(!restarted.is_closed(room.conv_id));
+
+    // Carol co-signs the close before she has seen it.
+    let co_sign = carol
+        .engine
+        .co_sign(
+            room.conv_id,
+            admin_node(&effects, |a| matches!(a, ControlAction::CloseConversation)).hash(),
+            &carol.store,
+        )
+        .unwrap();
+    apply_effects(co_sign, &carol.store);
+    carol.receive_nodes(room.conv_id, &lower_effects);
+    carol.receive_nodes(room.conv_id, &effects);
+    assert!(carol.engine.is_closed(room.conv_id));
+    assert_eq!(
+        carol.engine.pending_admin_actions(room.conv_id)[0].hash,
+        lower.hash()
+    );
+    assert_eq!(carol.engine.identity_manager.admin_quorum(room.conv_id), 2);
+}
+
+#[test]
+fn test_concurrent_quorum_change_is_order_independent() {
+    let room = TestRoom::new(3);
+    let bob = &room.identities[1];
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let mut carol = room.peer(2, &tp);
+
+    // Alice revokes Bob while Carol, unaware of it, raises the quorum on a
+    // branch of higher rank.
+    let revoke_effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::RevokeDevice {
+            target_device_pk: bob.device_pk,
+            reason: "compromised".to_string(),
+        }),
+    );
+    let revoke = admin_node(&revoke_effects, |a| {
+        matches!(a, ControlAction::RevokeDevice { .. })
+    });
+    let mut quorum_effects = Vec::new();
+    for _ in 0..2 {
+        quorum_effects.extend(carol.author(
+            room.conv_id,
+            Content::Control(ControlAction::SetRetention { max_age_ms: 0 }),
+        ));
+    }
+    quorum_effects.extend(carol.author(
+        room.conv_id,
+        Content::Control(ControlAction::SetAdminQuorum { threshold: 2 }),
+    ));
+    let quorum = admin_node(&quorum_effects, |a| {
+        matches!(a, ControlAction::SetAdminQuorum { .. })
+    });
+    assert!(quorum.topological_rank > revoke.topological_rank);
+
+    let mut revoke_first = TestPeer::new(&room, &room.identities[0], 4, &tp);
+    revoke_first.receive_nodes(room.conv_id, &revoke_effects);
+    revoke_first.receive_nodes(room.conv_id, &quorum_effects);
+    let mut quorum_first = TestPeer::new(&room, &room.identities[0], 5, &tp);
+    quorum_first.receive_nodes(room.conv_id, &quorum_effects);
+    quorum_first.receive_nodes(room.conv_id, &revoke_effects);
+
+    for p in [&revoke_first, &quorum_first] {
+        assert!(!is_authorized(&p.engine, &room, bob));
+        assert!(p.engine.pending_admin_actions(room.conv_id).is_empty());
+        assert_eq!(p.engine.identity_manager.admin_quorum(room.conv_id), 2);
+
+        // Replaying the stored nodes agrees.
+        let mut restarted = room.identities[0].engine(6, &tp);
+        restarted
+            .load_conversation_state(room.conv_id, &p.store)
+            .unwrap();
+        assert!(!is_authorized(&restarted, &room, bob));
+        assert!(restarted.pending_admin_actions(room.conv_id).is_empty());
+        assert_eq!(restarted.identity_manager.admin_quorum(room.conv_id), 2);
+    }
+}
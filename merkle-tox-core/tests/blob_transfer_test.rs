@@ -0,0 +1,222 @@
+use merkle_tox_core::cas::{BlobStatus, CHUNK_SIZE, SwarmConfig, SwarmSync};
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{LogicalIdentityPk, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::sync::BlobStore;
+use merkle_tox_core::testing::{InMemoryStore, create_available_blob_info, create_blob_data};
+use merkle_tox_core::{NodeEvent, ProtocolMessage};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const SIZE: u64 = CHUNK_SIZE * 2 + 10;
+
+fn setup() -> (MerkleToxEngine, InMemoryStore, NodeHash, PhysicalDevicePk) {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let engine = MerkleToxEngine::new(
+        PhysicalDevicePk::from([1u8; 32]),
+        LogicalIdentityPk::from([1u8; 32]),
+        StdRng::seed_from_u64(0),
+        tp,
+    );
+    (
+        engine,
+        InMemoryStore::new(),
+        NodeHash::from([0xAAu8; 32]),
+        PhysicalDevicePk::from([2u8; 32]),
+    )
+}
+
+fn handle(
+    engine: &mut MerkleToxEngine,
+    store: &InMemoryStore,
+    peer: PhysicalDevicePk,
+    msg: ProtocolMessage,
+) -> Vec<Effect> {
+    let effects = engine
+        .handle_message(peer, msg, store, Some(store))
+        .unwrap();
+    for effect in &effects {
+        if let Effect::WriteBlobInfo(info) = effect {
+            store.put_blob_info(info.clone()).unwrap();
+        }
+    }
+    effects
+}
+
+fn announce(
+    engine: &mut MerkleToxEngine,
+    store: &InMemoryStore,
+    hash: NodeHash,
+    peer: PhysicalDevicePk,
+) {
+    handle(
+        engine,
+        store,
+        peer,
+        ProtocolMessage::BlobAvail(create_available_blob_info(hash, SIZE)),
+    );
+}
+
+fn chunk(hash: NodeHash, index: u64) -> ProtocolMessage {
+    let len = CHUNK_SIZE.min(SIZE - index * CHUNK_SIZE) as usize;
+    ProtocolMessage::BlobData(create_blob_data(hash, index * CHUNK_SIZE, vec![7u8; len]))
+}
+
+fn progress(effects: &[Effect]) -> Option<(u64, u64)> {
+    effects.iter().find_map(|e| match e {
+        Effect::EmitEvent(NodeEvent::BlobProgress {
+            received, total, ..
+        }) => Some((*received, *total)),
+        _ => None,
+    })
+}
+
+#[test]
+fn test_blob_progress_events() {
+    let (mut engine, store, hash, peer) = setup();
+    announce(&mut engine, &store, hash, peer);
+
+    let effects = handle(&mut engine, &store, peer, chunk(hash, 0));
+    assert_eq!(progress(&effects), Some((CHUNK_SIZE, SIZE)));
+
+    // The short last chunk counts with its real length.
+    let effects = handle(&mut engine, &store, peer, chunk(hash, 2));
+    assert_eq!(progress(&effects), Some((CHUNK_SIZE + 10, SIZE)));
+
+    let effects = handle(&mut engine, &store, peer, chunk(hash, 1));
+    assert_eq!(progress(&effects), Some((SIZE, SIZE)));
+    assert!(
+        effects
+            .iter()
+            .any(|e| matches!(e, Effect::EmitEvent(NodeEvent::BlobAvailable { .. })))
+    );
+}
+
+#[test]
+fn test_cancel_blob_stops_download() {
+    let (mut engine, store, hash, peer) = setup();
+    assert!(engine.cancel_blob(&hash).is_empty());
+
+    announce(&mut engine, &store, hash, peer);
+    handle(&mut engine, &store, peer, chunk(hash, 0));
+
+    let effects = engine.cancel_blob(&hash);
+    let info = match effects.as_slice() {
+        [Effect::WriteBlobInfo(info)] => info.clone(),
+        other => panic!("unexpected effects: {:?}", other.len()),
+    };
+    assert_eq!(info.status, BlobStatus::Cancelled);
+    assert_eq!(info.received_mask.as_deref(), Some(&[0b001][..]));
+    store.put_blob_info(info).unwrap();
+    assert!(!engine.blob_syncs.contains_key(&hash));
+
+    // Chunks still in flight are dropped.
+    let effects = handle(&mut engine, &store, peer, chunk(hash, 1));
+    assert!(effects.is_empty());
+
+    // Seeders announcing the blob again do not restart the download.
+    announce(&mut engine, &store, hash, PhysicalDevicePk::from([3u8; 32]));
+    assert!(!engine.blob_syncs.contains_key(&hash));
+    assert_eq!(
+        store.get_blob_info(&hash).unwrap().status,
+        BlobStatus::Cancelled
+    );
+}
+
+fn requested_offsets(effects: &[Effect]) -> Vec<u64> {
+    let mut offsets: Vec<u64> = effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(_, ProtocolMessage::BlobReq(req)) => Some(req.offset),
+            _ => None,
+        })
+        .collect();
+    offsets.sort();
+    offsets
+}
+
+#[test]
+fn test_restart_resumes_from_received_mask() {
+    let (mut engine, store, hash, peer) = setup();
+    announce(&mut engine, &store, hash, peer);
+    handle(&mut engine, &store, peer, chunk(hash, 1));
+    // Persist progress the way the node does for a running download.
+    let info = engine.blob_syncs[&hash].progress_info();
+    store.put_blob_info(info).unwrap();
+
+    // A fresh engine picks the download up from the store.
+    let (mut engine, _, _, _) = setup();
+    assert_eq!(engine.resume_blob_downloads(&store), 1);
+    assert_eq!(engine.resume_blob_downloads(&store), 0);
+    announce(&mut engine, &store, hash, peer);
+
+    let effects = engine.poll(Instant::now(), &store).unwrap();
+    assert_eq!(requested_offsets(&effects), vec![0, CHUNK_SIZE * 2]);
+}
+
+#[test]
+fn test_announcement_resumes_partial_download() {
+    let (mut engine, store, hash, peer) = setup();
+    announce(&mut engine, &store, hash, peer);
+    handle(&mut engine, &store, peer, chunk(hash, 0));
+    let info = engine.blob_syncs[&hash].progress_info();
+    store.put_blob_info(info).unwrap();
+
+    // Without an explicit resume, the next announcement keeps the stored mask.
+    let (mut engine, _, _, _) = setup();
+    announce(&mut engine, &store, hash, peer);
+    assert!(engine.blob_syncs[&hash].tracker.is_received(0));
+    let effects = engine.poll(Instant::now(), &store).unwrap();
+    assert_eq!(
+        requested_offsets(&effects),
+        vec![CHUNK_SIZE, CHUNK_SIZE * 2]
+    );
+}
+
+#[test]
+fn test_blob_limits_cap_requests() {
+    let (mut engine, store, hash, peer) = setup();
+    announce(&mut engine, &store, hash, peer);
+    announce(&mut engine, &store, hash, PhysicalDevicePk::from([3u8; 32]));
+    assert!(engine.set_blob_limits(
+        &hash,
+        SwarmConfig {
+            max_concurrent_chunks: 1,
+            ..SwarmConfig::default()
+        },
+    ));
+    assert!(!engine.set_blob_limits(&NodeHash::from([0u8; 32]), SwarmConfig::default()));
+
+    let now = Instant::now();
+    let effects = engine.poll(now, &store).unwrap();
+    assert_eq!(requested_offsets(&effects), vec![0]);
+    let effects = engine.poll(now, &store).unwrap();
+    assert!(requested_offsets(&effects).is_empty());
+
+    handle(&mut engine, &store, peer, chunk(hash, 0));
+    let effects = engine.poll(now, &store).unwrap();
+    assert_eq!(requested_offsets(&effects), vec![CHUNK_SIZE]);
+}
+
+#[test]
+fn test_blob_rate_limit() {
+    let hash = NodeHash::from([0xAAu8; 32]);
+    let mut sync =
+        SwarmSync::new(create_available_blob_info(hash, SIZE)).with_config(SwarmConfig {
+            max_bytes_per_sec: Some(CHUNK_SIZE),
+            ..SwarmConfig::default()
+        });
+    sync.add_seeder(PhysicalDevicePk::from([2u8; 32]));
+    sync.add_seeder(PhysicalDevicePk::from([3u8; 32]));
+
+    let now = Instant::now();
+    assert_eq!(sync.next_requests(4, now).len(), 1);
+    assert!(sync.next_requests(4, now).is_empty());
+    assert_eq!(sync.next_wakeup(now), now + Duration::from_secs(1));
+
+    let later = now + Duration::from_millis(500);
+    assert!(sync.next_requests(4, later).is_empty());
+    let later = now + Duration::from_secs(1);
+    assert_eq!(sync.next_requests(4, later).len(), 1);
+}
@@ -0,0 +1,91 @@
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
+    NodeLookup, PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{CachedStore, NodeStore, StoreWrite};
+use merkle_tox_core::testing::InMemoryStore;
+
+fn make_node(seq: u64, parents: Vec<NodeHash>) -> MerkleNode {
+    MerkleNode {
+        parents,
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64,
+        content: Content::Text(format!("message {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+#[test]
+fn test_lookups_are_served_from_cache() {
+    let store = CachedStore::new(InMemoryStore::new());
+    let node = make_node(1, vec![]);
+    let hash = node.hash();
+    store.put_node(&conv_id(), node.clone(), true).unwrap();
+    assert_eq!(store.cached_nodes(), 0);
+
+    assert_eq!(store.get_node(&hash), Some(node.clone()));
+    assert_eq!(store.cached_nodes(), 1);
+
+    // The inner store no longer has the node, but the cache does.
+    store.inner().nodes.write().unwrap().remove(&hash);
+    assert_eq!(store.get_node(&hash), Some(node.clone()));
+    assert_eq!(store.get_nodes(&[hash]), vec![Some(node)]);
+
+    store.clear();
+    assert_eq!(store.get_node(&hash), None);
+}
+
+#[test]
+fn test_writes_invalidate_entries() {
+    let store = CachedStore::new(InMemoryStore::new());
+    let parent = make_node(1, vec![]);
+    store.put_node(&conv_id(), parent.clone(), true).unwrap();
+    assert!(!store.has_children(&parent.hash()));
+    assert_eq!(store.get_node(&parent.hash()), Some(parent.clone()));
+
+    let child = make_node(2, vec![parent.hash()]);
+    store.put_node(&conv_id(), child.clone(), true).unwrap();
+    assert!(store.has_children(&parent.hash()));
+
+    let grandchild = make_node(3, vec![child.hash()]);
+    assert!(!store.has_children(&child.hash()));
+    store
+        .write_batch(vec![StoreWrite::Node(conv_id(), grandchild, false)])
+        .unwrap();
+    assert!(store.has_children(&child.hash()));
+
+    assert!(store.prune_node(&conv_id(), &parent.hash()).unwrap());
+    assert_eq!(store.get_node(&parent.hash()), None);
+}
+
+#[test]
+fn test_get_nodes_mixes_cached_and_stored() {
+    let store = CachedStore::with_capacity(InMemoryStore::new(), 2);
+    let nodes: Vec<MerkleNode> = (1..=4).map(|seq| make_node(seq, vec![])).collect();
+    store
+        .put_nodes(
+            &conv_id(),
+            nodes.iter().map(|n| (n.clone(), true)).collect(),
+        )
+        .unwrap();
+    store.get_node(&nodes[1].hash());
+
+    let unknown = NodeHash::from([9u8; 32]);
+    let mut hashes: Vec<NodeHash> = nodes.iter().map(|n| n.hash()).collect();
+    hashes.push(unknown);
+    let fetched = store.get_nodes(&hashes);
+    let expected: Vec<Option<MerkleNode>> = nodes.iter().cloned().map(Some).chain([None]).collect();
+    assert_eq!(fetched, expected);
+
+    // Bounded by capacity.
+    assert_eq!(store.cached_nodes(), 2);
+}
@@ -0,0 +1,214 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::capabilities::{
+    FEATURE_DELTA_HEADS, FEATURE_READ_ONLY, MIN_PROTOCOL_VERSION, Negotiated, PROTOCOL_VERSION,
+    SUPPORTED_FEATURES, feature_names, negotiate,
+};
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::session::PeerSession;
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::sync::{SyncHeads, SyncHeadsDelta};
+use merkle_tox_core::testing::InMemoryStore;
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::Instant;
+
+fn conv() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+fn peer() -> PhysicalDevicePk {
+    PhysicalDevicePk::from([2u8; 32])
+}
+
+fn setup() -> (MerkleToxEngine, InMemoryStore) {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let self_pk = PhysicalDevicePk::from([1; 32]);
+    let mut engine = MerkleToxEngine::new(
+        self_pk,
+        self_pk.to_logical(),
+        rand::rngs::StdRng::seed_from_u64(0),
+        tp,
+    );
+    let store = InMemoryStore::new();
+    engine.start_sync(conv(), Some(peer()), &store);
+    (engine, store)
+}
+
+fn announce(
+    engine: &mut MerkleToxEngine,
+    store: &InMemoryStore,
+    version: u32,
+    features: u64,
+) -> Vec<Effect> {
+    engine
+        .handle_message(
+            peer(),
+            ProtocolMessage::CapsAnnounce { version, features },
+            store,
+            None,
+        )
+        .unwrap()
+}
+
+fn delta(conversation_id: ConversationId, head: NodeHash) -> ProtocolMessage {
+    ProtocolMessage::SyncHeadsDelta(SyncHeadsDelta {
+        conversation_id,
+        base_version: None,
+        version: 1,
+        added: vec![head],
+        removed: vec![],
+        anchor_hash: None,
+    })
+}
+
+fn remote_heads_contain(engine: &MerkleToxEngine, cid: ConversationId, head: &NodeHash) -> bool {
+    engine.sessions[&(peer(), cid)]
+        .common()
+        .remote_heads
+        .contains(head)
+}
+
+#[test]
+fn test_negotiate() {
+    let negotiated = negotiate(FEATURE_DELTA_HEADS, 7, FEATURE_DELTA_HEADS | 0x8000).unwrap();
+    assert_eq!(negotiated.version, PROTOCOL_VERSION);
+    assert_eq!(negotiated.features, FEATURE_DELTA_HEADS);
+
+    // Extensions need both sides; roles only the peer's.
+    let negotiated =
+        negotiate(0, PROTOCOL_VERSION, FEATURE_DELTA_HEADS | FEATURE_READ_ONLY).unwrap();
+    assert!(!negotiated.has(FEATURE_DELTA_HEADS));
+    assert!(negotiated.has(FEATURE_READ_ONLY));
+
+    assert_eq!(
+        negotiate(FEATURE_DELTA_HEADS, MIN_PROTOCOL_VERSION - 1, 0),
+        None
+    );
+    assert_eq!(Negotiated::default(), Negotiated::BASELINE);
+    assert_eq!(Negotiated::BASELINE.features, 0);
+    assert_eq!(
+        feature_names(FEATURE_DELTA_HEADS | FEATURE_READ_ONLY | 0x8000),
+        vec!["delta-heads", "read-only"]
+    );
+}
+
+#[test]
+fn test_handshake_stores_negotiation() {
+    let (mut engine, store) = setup();
+    let effects = announce(&mut engine, &store, 7, FEATURE_DELTA_HEADS | 0x8000);
+
+    let expected = Negotiated {
+        version: PROTOCOL_VERSION,
+        features: FEATURE_DELTA_HEADS,
+    };
+    let session = &engine.sessions[&(peer(), conv())];
+    assert!(matches!(session, PeerSession::Active(_)));
+    assert_eq!(session.common().negotiated, expected);
+    assert_eq!(engine.negotiated(&peer()), expected);
+    assert!(effects.iter().any(|e| matches!(
+        e,
+        Effect::SendPacket(_, ProtocolMessage::CapsAck { version, .. }) if *version == PROTOCOL_VERSION
+    )));
+
+    // A conversation the peer starts later inherits the negotiation.
+    let other = ConversationId::from([3; 32]);
+    engine
+        .handle_message(
+            peer(),
+            ProtocolMessage::SyncHeads(SyncHeads {
+                conversation_id: other,
+                heads: vec![],
+                flags: 0,
+                anchor_hash: None,
+            }),
+            &store,
+            None,
+        )
+        .unwrap();
+    assert_eq!(
+        engine.sessions[&(peer(), other)].common().negotiated,
+        expected
+    );
+}
+
+#[test]
+fn test_old_version_is_refused() {
+    let (mut engine, store) = setup();
+    let effects = announce(
+        &mut engine,
+        &store,
+        MIN_PROTOCOL_VERSION - 1,
+        FEATURE_DELTA_HEADS,
+    );
+
+    assert!(matches!(
+        engine.sessions[&(peer(), conv())],
+        PeerSession::Handshake(_)
+    ));
+    assert_eq!(engine.negotiated(&peer()), Negotiated::BASELINE);
+    assert!(effects.iter().any(|e| matches!(
+        e,
+        Effect::SendPacket(_, ProtocolMessage::HandshakeError { conversation_id, .. })
+            if *conversation_id == conv()
+    )));
+    assert!(
+        !effects
+            .iter()
+            .any(|e| matches!(e, Effect::SendPacket(_, ProtocolMessage::CapsAck { .. })))
+    );
+}
+
+#[test]
+fn test_unnegotiated_messages_are_dropped() {
+    let head = NodeHash::from([9; 32]);
+
+    let (mut engine, store) = setup();
+    announce(&mut engine, &store, PROTOCOL_VERSION, 0);
+    engine
+        .handle_message(peer(), delta(conv(), head), &store, None)
+        .unwrap();
+    assert!(!remote_heads_contain(&engine, conv(), &head));
+
+    let (mut engine, store) = setup();
+    announce(&mut engine, &store, PROTOCOL_VERSION, FEATURE_DELTA_HEADS);
+    engine
+        .handle_message(peer(), delta(conv(), head), &store, None)
+        .unwrap();
+    assert!(remote_heads_contain(&engine, conv(), &head));
+}
+
+#[test]
+fn test_later_announcement_updates_sessions() {
+    let (mut engine, store) = setup();
+    announce(&mut engine, &store, PROTOCOL_VERSION, 0);
+    assert_eq!(
+        engine.sessions[&(peer(), conv())].common().negotiated,
+        Negotiated::BASELINE
+    );
+
+    // The peer's own engine announces everything it supports.
+    let (peer_engine, _) = setup();
+    let ProtocolMessage::CapsAnnounce { version, features } = peer_engine.caps_announce() else {
+        panic!("expected a CapsAnnounce");
+    };
+    assert_eq!(version, PROTOCOL_VERSION);
+    assert_eq!(features, SUPPORTED_FEATURES);
+    announce(&mut engine, &store, version, features);
+
+    let expected = Negotiated {
+        version: PROTOCOL_VERSION,
+        features: SUPPORTED_FEATURES,
+    };
+    assert_eq!(engine.negotiated(&peer()), expected);
+    assert_eq!(
+        engine.sessions[&(peer(), conv())].common().negotiated,
+        expected
+    );
+
+    let head = NodeHash::from([9; 32]);
+    engine
+        .handle_message(peer(), delta(conv(), head), &store, None)
+        .unwrap();
+    assert!(remote_heads_contain(&engine, conv(), &head));
+}
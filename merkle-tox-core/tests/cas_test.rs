@@ -1,8 +1,9 @@
-use merkle_tox_core::cas::{BlobStatus, CHUNK_SIZE, FETCH_TIMEOUT, SwarmSync};
-use merkle_tox_core::dag::{NodeHash, PhysicalDevicePk};
-use merkle_tox_core::testing::{create_blob_data, create_blob_info};
+use merkle_tox_core::cas::{BlobStatus, CHUNK_SIZE, FETCH_TIMEOUT, SwarmSync, open_blob};
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::sync::BlobStore;
+use merkle_tox_core::testing::{InMemoryStore, create_blob_data, create_blob_info};
 use std::collections::HashMap;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::time::{Duration, Instant};
 
 #[test]
@@ -310,3 +311,46 @@ fn test_swarm_sync_resumption() {
 }
 
 // end of file
+
+#[test]
+fn test_blob_reader_streams_chunks() {
+    let store = InMemoryStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let data: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+    let hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+
+    let mut info = create_blob_info(hash, data.len() as u64);
+    info.status = BlobStatus::Pending;
+    store.put_blob_info(info).unwrap();
+    assert!(open_blob(&store, &hash).is_err());
+    for (i, chunk) in data.chunks(CHUNK_SIZE as usize).enumerate() {
+        store
+            .put_chunk(&conv_id, &hash, i as u64 * CHUNK_SIZE, chunk, None)
+            .unwrap();
+    }
+
+    let mut reader = open_blob(&store, &hash).unwrap();
+    let mut read_back = Vec::new();
+    reader.read_to_end(&mut read_back).unwrap();
+    assert_eq!(read_back, data);
+
+    // Reads across a chunk boundary after seeking.
+    let start = CHUNK_SIZE - 10;
+    assert_eq!(reader.seek(SeekFrom::Start(start)).unwrap(), start);
+    let mut buf = [0u8; 20];
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf[..], &data[start as usize..start as usize + 20]);
+
+    assert_eq!(
+        reader.seek(SeekFrom::End(-4)).unwrap(),
+        data.len() as u64 - 4
+    );
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, &data[data.len() - 4..]);
+    assert!(
+        reader
+            .seek(SeekFrom::Current(-(data.len() as i64) - 1))
+            .is_err()
+    );
+}
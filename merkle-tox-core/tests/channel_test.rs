@@ -0,0 +1,216 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::channel::channel_conversation_id;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::crypto::derive_channel_k_conv;
+use merkle_tox_core::dag::{
+    Content, ControlAction, ConversationId, InviteAction, MerkleNode, ValidationError,
+};
+use merkle_tox_core::engine::{Conversation, Effect};
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{
+    TestIdentity, TestPeer, TestRoom, apply_effects, create_admin_node, get_all_nodes_from_effects,
+    is_verified_in_effects, transfer_ephemeral_keys, transfer_wire_nodes,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Alice creates channel "general"; Bob receives it.
+fn create_general(room: &TestRoom) -> (TestPeer, TestPeer, ConversationId) {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let mut bob = room.peer(1, &tp);
+    let (channel_id, effects) = alice
+        .engine
+        .create_channel(room.conv_id, "general".to_string(), &alice.store)
+        .unwrap();
+    apply_effects(effects.clone(), &alice.store);
+    bob.receive_nodes(room.conv_id, &effects);
+    (alice, bob, channel_id)
+}
+
+fn channel_text(effects: &[Effect]) -> MerkleNode {
+    get_all_nodes_from_effects(effects)
+        .into_iter()
+        .find(|n| matches!(n.content, Content::Text(_)))
+        .unwrap()
+}
+
+#[test]
+fn test_create_channel() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let mut bob = room.peer(1, &tp);
+    let (channel_id, effects) = alice
+        .engine
+        .create_channel(room.conv_id, "general".to_string(), &alice.store)
+        .unwrap();
+    let node = get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| {
+            matches!(
+                n.content,
+                Content::Control(ControlAction::CreateChannel { .. })
+            )
+        })
+        .unwrap();
+    let Content::Control(ControlAction::CreateChannel { channel_id: id, .. }) = &node.content
+    else {
+        unreachable!();
+    };
+    assert_eq!(channel_id, channel_conversation_id(&room.conv_id, id));
+    apply_effects(effects, &alice.store);
+
+    let received = bob.receive_nodes(
+        room.conv_id,
+        &[Effect::WriteStore(room.conv_id, node, true)],
+    );
+    assert!(received.iter().any(|e| matches!(
+        e,
+        Effect::EmitEvent(NodeEvent::ChannelCreated { conversation_id, channel_id: c, name })
+            if *conversation_id == room.conv_id && *c == channel_id && name == "general"
+    )));
+
+    for p in [&alice, &bob] {
+        let channels = p.engine.channels(room.conv_id);
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].conversation_id, channel_id);
+        assert_eq!(channels[0].name, "general");
+        assert_eq!(p.engine.channel(channel_id), Some(&channels[0]));
+        assert!(p.engine.validation_rules(channel_id).channel);
+
+        // Members and keys come from the parent.
+        assert_eq!(
+            p.engine.identity_manager.list_members(channel_id),
+            p.engine.identity_manager.list_members(room.conv_id)
+        );
+        let Some(Conversation::Established(em)) = p.engine.conversations.get(&channel_id) else {
+            panic!("channel has no key");
+        };
+        assert_eq!(
+            em.get_keys(0).unwrap().k_conv,
+            derive_channel_k_conv(&room.k_conv.into(), &channel_id)
+        );
+    }
+}
+
+#[test]
+fn test_channel_messages_verify_for_members() {
+    let room = TestRoom::new(2);
+    let (mut alice, mut bob, channel_id) = create_general(&room);
+
+    let effects = alice
+        .engine
+        .author_node(
+            channel_id,
+            Content::Text("hello channel".to_string()),
+            Vec::new(),
+            &alice.store,
+        )
+        .unwrap();
+    apply_effects(effects.clone(), &alice.store);
+    let text = channel_text(&effects);
+
+    transfer_ephemeral_keys(&alice.engine, &mut bob.engine);
+    transfer_wire_nodes(&effects, &bob.store);
+    let received = bob.receive_nodes(channel_id, &effects);
+    assert!(is_verified_in_effects(&received));
+    assert!(bob.store.is_verified(&text.hash()));
+
+    // No anchors or key exchange nodes appear in the channel.
+    assert!(
+        get_all_nodes_from_effects(&effects)
+            .iter()
+            .all(|n| !matches!(n.content, Content::Control(_) | Content::KeyWrap { .. }))
+    );
+}
+
+#[test]
+fn test_channel_rejects_admin_nodes() {
+    let room = TestRoom::new(2);
+    let (mut alice, mut bob, channel_id) = create_general(&room);
+
+    let id = &room.identities[0];
+    let node = create_admin_node(
+        &channel_id,
+        id.master_pk,
+        &id.master_sk,
+        vec![],
+        ControlAction::Invite(InviteAction {
+            invitee_pk: TestIdentity::new().master_pk,
+            role: 0,
+        }),
+        0,
+        1,
+        1000,
+    );
+    let result = bob.engine.handle_node(channel_id, node, &bob.store, None);
+    assert!(matches!(
+        result,
+        Err(MerkleToxError::Validation(
+            ValidationError::NotAllowedInChannel
+        ))
+    ));
+
+    let result = alice.engine.author_node(
+        channel_id,
+        Content::Control(ControlAction::HandshakePulse),
+        Vec::new(),
+        &alice.store,
+    );
+    assert!(matches!(
+        result,
+        Err(MerkleToxError::Validation(
+            ValidationError::NotAllowedInChannel
+        ))
+    ));
+}
+
+#[test]
+fn test_channel_keys_follow_parent_rotation() {
+    let room = TestRoom::new(2);
+    let (mut alice, _bob, channel_id) = create_general(&room);
+
+    let effects = alice
+        .engine
+        .rotate_conversation_key(room.conv_id, &alice.store)
+        .unwrap();
+    apply_effects(effects, &alice.store);
+
+    let parent_k_conv = match &alice.engine.conversations[&room.conv_id] {
+        Conversation::Established(em) => em.get_keys(1).unwrap().k_conv.clone(),
+        Conversation::Pending(_) => panic!("parent has no key"),
+    };
+    let Some(Conversation::Established(em)) = alice.engine.conversations.get(&channel_id) else {
+        panic!("channel has no key");
+    };
+    assert_eq!(em.current_epoch(), 1);
+    assert_eq!(
+        em.get_keys(1).unwrap().k_conv,
+        derive_channel_k_conv(&parent_k_conv, &channel_id)
+    );
+    assert!(!alice.engine.check_rotation_triggers(channel_id));
+}
+
+#[test]
+fn test_channels_are_restored_from_store() {
+    let room = TestRoom::new(2);
+    let (alice, _bob, channel_id) = create_general(&room);
+
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut restarted = room.identities[0].engine(2, &tp);
+    restarted
+        .load_conversation_state(room.conv_id, &alice.store)
+        .unwrap();
+    assert_eq!(
+        restarted.channels(room.conv_id),
+        alice.engine.channels(room.conv_id)
+    );
+    assert!(
+        restarted
+            .conversations
+            .get(&channel_id)
+            .is_some_and(|c| c.is_established())
+    );
+}
@@ -0,0 +1,299 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::capabilities::{
+    FEATURE_ROUTING_HEADER, Negotiated, PROTOCOL_VERSION, SUPPORTED_FEATURES,
+};
+use merkle_tox_core::classify::{
+    PacketKind, PacketSummary, ROUTING_HEADER_VERSION, RoutingHeader, classify_packet,
+    decode_message, encode_message,
+};
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{
+    Content, ConversationId, KConv, NodeHash, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport, VirtualHub};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::coalesce::encode_batch;
+use tox_sequenced::protocol::{
+    FragmentCount, FragmentIndex, MessageId, MessageType, OutboundEnvelope, Packet, TimestampMs,
+};
+
+type Node = MerkleToxNode<SimulatedTransport, InMemoryStore>;
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([0x42u8; 32])
+}
+
+fn node(seed: u8, hub: &Arc<VirtualHub>, time: &Arc<ManualTimeProvider>) -> Node {
+    let sk = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+    let pk = PhysicalDevicePk::from(sk.verifying_key().to_bytes());
+    let engine = MerkleToxEngine::with_sk(
+        pk,
+        pk.to_logical(),
+        PhysicalDeviceSk::from(sk.to_bytes()),
+        StdRng::seed_from_u64(seed as u64),
+        time.clone(),
+    );
+    let store = InMemoryStore::new();
+    store
+        .put_conversation_key(&conv_id(), 0, KConv::from([0xAAu8; 32]))
+        .unwrap();
+    let mut node = MerkleToxNode::new(
+        engine,
+        SimulatedTransport::new(pk, hub.clone()),
+        store,
+        time.clone(),
+    );
+    node.engine
+        .load_conversation_state(conv_id(), &node.store)
+        .unwrap();
+    node
+}
+
+/// A session that negotiated the routing header.
+fn with_header() -> Negotiated {
+    Negotiated {
+        version: 1,
+        features: FEATURE_ROUTING_HEADER,
+    }
+}
+
+fn gossip() -> ProtocolMessage {
+    ProtocolMessage::AdminGossip {
+        conversation_id: conv_id(),
+        hash: NodeHash::from([1u8; 32]),
+    }
+}
+
+fn envelope(message_type: MessageType, payload: &[u8]) -> Vec<u8> {
+    tox_proto::serialize(&OutboundEnvelope {
+        message_type,
+        payload,
+    })
+    .unwrap()
+}
+
+fn data(index: u16, data: &[u8]) -> Vec<u8> {
+    tox_proto::serialize(&Packet::Data {
+        message_id: MessageId(1),
+        fragment_index: FragmentIndex(index),
+        total_fragments: FragmentCount(2),
+        data: data.to_vec(),
+    })
+    .unwrap()
+}
+
+#[test]
+fn test_message_round_trips_behind_header() {
+    let msg = gossip();
+    let encoded = encode_message(&msg, &with_header()).unwrap();
+    let (header, _) = RoutingHeader::decode(&encoded).unwrap();
+    assert_eq!(header.kind, PacketKind::Admin);
+    assert_eq!(header.conversation_id, Some(conv_id()));
+    assert_eq!(decode_message(&encoded).unwrap(), msg);
+
+    // The handshake stays readable for peers without the header.
+    let caps = ProtocolMessage::CapsAnnounce {
+        version: 1,
+        features: 0,
+    };
+    let encoded = encode_message(&caps, &with_header()).unwrap();
+    assert_eq!(encoded, tox_proto::serialize(&caps).unwrap());
+    assert_eq!(decode_message(&encoded).unwrap(), caps);
+}
+
+#[test]
+fn test_header_needs_negotiation() {
+    let msg = gossip();
+    let encoded = encode_message(&msg, &Negotiated::BASELINE).unwrap();
+    assert_eq!(encoded, tox_proto::serialize(&msg).unwrap());
+    assert_ne!(encoded[0], ROUTING_HEADER_VERSION);
+    assert_eq!(decode_message(&encoded).unwrap(), msg);
+
+    // Without a header, the packet cannot be classified and wakes the app.
+    let summary = classify_packet(&data(0, &envelope(MessageType::AdminGossip, &encoded)));
+    assert_eq!(summary.kind, PacketKind::Unknown);
+    assert!(summary.should_wake());
+}
+
+#[test]
+fn test_mismatched_header_is_rejected() {
+    let mut encoded = encode_message(&gossip(), &with_header()).unwrap();
+    encoded[1] = PacketKind::Content as u8;
+    assert!(decode_message(&encoded).is_err());
+
+    let mut encoded = encode_message(&gossip(), &with_header()).unwrap();
+    encoded[3] ^= 0xFF;
+    assert!(decode_message(&encoded).is_err());
+
+    let mut encoded = encode_message(&gossip(), &with_header()).unwrap();
+    encoded[0] = 2;
+    assert!(decode_message(&encoded).is_err());
+    assert!(decode_message(&[]).is_err());
+}
+
+#[test]
+fn test_classify_fragments_batches_and_control() {
+    let msg = ProtocolMessage::HandshakeError {
+        conversation_id: conv_id(),
+        reason: "x".repeat(3000),
+    };
+    let message = envelope(
+        MessageType::HandshakeError,
+        &encode_message(&msg, &with_header()).unwrap(),
+    );
+    let (head, tail) = message.split_at(1000);
+    assert_eq!(
+        classify_packet(&data(0, head)),
+        PacketSummary {
+            kind: PacketKind::Handshake,
+            conversation_id: None,
+        }
+    );
+    assert_eq!(
+        classify_packet(&data(1, tail)).kind,
+        PacketKind::Continuation
+    );
+
+    let rate_limited = ProtocolMessage::SyncRateLimited {
+        conversation_id: conv_id(),
+        retry_after_ms: 100,
+    };
+    let batch = encode_batch(&[
+        (
+            MessageType::SyncRateLimited,
+            encode_message(&rate_limited, &with_header()).unwrap(),
+        ),
+        (
+            MessageType::AdminGossip,
+            encode_message(&gossip(), &with_header()).unwrap(),
+        ),
+    ])
+    .unwrap();
+    let summary = classify_packet(&data(0, &envelope(MessageType::Batch, &batch)));
+    assert_eq!(summary.kind, PacketKind::Admin);
+    assert!(summary.should_wake());
+
+    let ping = tox_proto::serialize(&Packet::Ping { t1: TimestampMs(0) }).unwrap();
+    assert_eq!(classify_packet(&ping).kind, PacketKind::Control);
+    assert!(!classify_packet(&ping).should_wake());
+    assert_eq!(classify_packet(&[0xFF, 0x00]).kind, PacketKind::Unknown);
+    assert_eq!(
+        classify_packet(&data(0, &envelope(MessageType::MerkleNode, &[9; 40]))).kind,
+        PacketKind::Unknown
+    );
+}
+
+#[test]
+fn test_classify_node_traffic() {
+    let time = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time.clone()));
+    let mut alice = node(1, &hub, &time);
+    let mut bob = node(2, &hub, &time);
+    let alice_rx = hub.register(alice.engine.self_pk);
+    let bob_rx = hub.register(bob.engine.self_pk);
+
+    let now = time.now_instant();
+    let mut wakeup = now;
+    let effects = alice
+        .engine
+        .author_node(
+            conv_id(),
+            Content::Text("hello".to_string()),
+            vec![],
+            &alice.store,
+        )
+        .unwrap();
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+    let effects = alice
+        .engine
+        .start_sync(conv_id(), Some(bob.engine.self_pk), &alice.store);
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+
+    let mut kinds = Vec::new();
+    let start = time.now_instant();
+    while bob.store.get_node_counts(&conv_id()) == (0, 0) {
+        assert!(time.now_instant().duration_since(start) < Duration::from_secs(10));
+        alice.poll();
+        while let Ok((from, data)) = alice_rx.try_recv() {
+            alice.handle_packet(from, &data);
+        }
+        bob.poll();
+        while let Ok((from, data)) = bob_rx.try_recv() {
+            let summary = classify_packet(&data);
+            if summary.kind == PacketKind::Content {
+                assert_eq!(summary.conversation_id, Some(conv_id()));
+            }
+            kinds.push(summary.kind);
+            bob.handle_packet(from, &data);
+        }
+        hub.poll();
+        time.advance(Duration::from_millis(100));
+    }
+
+    // The headers did not get in the way of the sync.
+    assert!(kinds.contains(&PacketKind::Handshake));
+    assert!(kinds.contains(&PacketKind::Content));
+    assert!(!kinds.contains(&PacketKind::Unknown));
+}
+
+/// Message types Alice sends a peer that announced `features`, up to the
+/// replies to the announcement.
+fn sent_to_peer_announcing(features: u64) -> Vec<MessageType> {
+    let time = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time.clone()));
+    let mut alice = node(1, &hub, &time);
+    let peer = PhysicalDevicePk::from([9u8; 32]);
+
+    let now = time.now_instant();
+    let mut wakeup = now;
+    let effects = alice.engine.start_sync(conv_id(), Some(peer), &alice.store);
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+
+    let caps = ProtocolMessage::CapsAnnounce {
+        version: PROTOCOL_VERSION,
+        features,
+    };
+    let announce = tox_proto::serialize(&Packet::Data {
+        message_id: MessageId(0),
+        fragment_index: FragmentIndex(0),
+        total_fragments: FragmentCount(1),
+        data: envelope(
+            MessageType::CapsAnnounce,
+            &tox_proto::serialize(&caps).unwrap(),
+        ),
+    })
+    .unwrap();
+    alice.handle_packet(peer, &announce);
+    time.advance(Duration::from_millis(100));
+    alice.poll();
+
+    alice.sessions[&peer]
+        .outgoing_status(time.now_instant())
+        .into_iter()
+        .map(|s| s.message_type)
+        .collect()
+}
+
+#[test]
+fn test_batches_need_negotiation() {
+    let types = sent_to_peer_announcing(SUPPORTED_FEATURES);
+    assert!(types.contains(&MessageType::Batch));
+    assert!(!types.contains(&MessageType::CapsAck));
+
+    // A peer that does not know batches gets each reply on its own.
+    let types = sent_to_peer_announcing(0);
+    assert!(types.contains(&MessageType::CapsAck));
+    assert!(types.contains(&MessageType::SyncHeads));
+    assert!(!types.contains(&MessageType::Batch));
+}
@@ -0,0 +1,212 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::{ClockPolicy, ManualTimeProvider, NetworkClock};
+use merkle_tox_core::crypto::ConversationKeys;
+use merkle_tox_core::dag::{
+    Content, ConversationId, KConv, MerkleNode, NodeHash, Permissions, PhysicalDevicePk,
+};
+use merkle_tox_core::engine::{
+    Conversation, ConversationData, Effect, MerkleToxEngine, conversation,
+};
+use merkle_tox_core::identity::CausalContext;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{
+    InMemoryStore, TestIdentity, apply_effects, create_signed_content_node, get_node_from_effects,
+    register_test_ephemeral_key,
+};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn peer(i: u8) -> PhysicalDevicePk {
+    PhysicalDevicePk::from([i; 32])
+}
+
+#[test]
+fn test_outliers_are_left_out_of_consensus() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut clock = NetworkClock::new(tp);
+    for (i, offset) in [0, 10_000, 240_000, 240_000].into_iter().enumerate() {
+        clock.update_peer_offset(peer(i as u8), offset);
+    }
+    // The plain median is 10s; both +4min samples are over 2min away.
+    assert_eq!(clock.consensus_target_offset(), 5_000);
+
+    clock.set_policy(ClockPolicy {
+        outlier_min_peers: usize::MAX,
+        ..ClockPolicy::default()
+    });
+    clock.update_peer_offset(peer(0), 0);
+    assert_eq!(clock.consensus_target_offset(), 125_000);
+}
+
+#[test]
+fn test_offset_is_capped_by_policy() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut clock = NetworkClock::new(tp);
+    clock.set_policy(ClockPolicy {
+        max_offset: Duration::from_secs(60),
+        ..ClockPolicy::default()
+    });
+    clock.update_peer_offset(peer(1), 180_000);
+    assert_eq!(clock.consensus_target_offset(), 60_000);
+    assert_eq!(clock.peer_median_offset(), 180_000);
+}
+
+fn skew_warnings(effects: &[Effect]) -> Vec<i64> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::ClockSkewWarning { offset_ms }) => Some(*offset_ms),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_skewed_local_clock_warns_once() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine =
+        MerkleToxEngine::new(peer(1), peer(1).to_logical(), StdRng::seed_from_u64(0), tp);
+    let store = InMemoryStore::new();
+    let poll = |engine: &mut MerkleToxEngine| {
+        let now = engine.clock.time_provider().now_instant();
+        skew_warnings(&engine.poll(now, &store).unwrap())
+    };
+
+    for i in 2..5 {
+        engine.update_peer_clock_offset(peer(i), 30_000);
+    }
+    assert!(poll(&mut engine).is_empty());
+
+    for i in 2..5 {
+        engine.update_peer_clock_offset(peer(i), -180_000);
+    }
+    assert_eq!(poll(&mut engine), vec![-180_000]);
+    assert!(poll(&mut engine).is_empty());
+
+    // Warns again after the clock came back within the limit.
+    for i in 2..5 {
+        engine.update_peer_clock_offset(peer(i), 0);
+    }
+    assert!(poll(&mut engine).is_empty());
+    for i in 2..5 {
+        engine.update_peer_clock_offset(peer(i), 180_000);
+    }
+    assert_eq!(poll(&mut engine), vec![180_000]);
+}
+
+struct Room {
+    engine: MerkleToxEngine,
+    store: InMemoryStore,
+    conv_id: ConversationId,
+    keys: ConversationKeys,
+    remote: TestIdentity,
+}
+
+fn room() -> Room {
+    let self_pk = peer(1);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine =
+        MerkleToxEngine::new(self_pk, self_pk.to_logical(), StdRng::seed_from_u64(0), tp);
+    let conv_id = ConversationId::from([0xAAu8; 32]);
+    let k_conv = KConv::from([0x42u8; 32]);
+    engine.conversations.insert(
+        conv_id,
+        Conversation::Established(ConversationData::<conversation::Established>::new(
+            conv_id,
+            k_conv.clone(),
+            0,
+        )),
+    );
+    let keys = ConversationKeys::derive(&k_conv);
+
+    let remote = TestIdentity::new();
+    engine
+        .identity_manager
+        .add_member(conv_id, remote.master_pk, 0, 0);
+    let cert = remote.make_device_cert_for(Permissions::ALL, i64::MAX, conv_id);
+    engine
+        .identity_manager
+        .authorize_device(
+            &CausalContext::global(),
+            conv_id,
+            remote.master_pk,
+            &cert,
+            0,
+            0,
+            NodeHash::from([0u8; 32]),
+        )
+        .unwrap();
+    register_test_ephemeral_key(&mut engine, &keys, &remote.device_pk);
+    Room {
+        engine,
+        store: InMemoryStore::new(),
+        conv_id,
+        keys,
+        remote,
+    }
+}
+
+fn remote_node(room: &Room, network_timestamp: i64) -> MerkleNode {
+    create_signed_content_node(
+        &room.conv_id,
+        &room.keys,
+        room.remote.master_pk,
+        room.remote.device_pk,
+        vec![],
+        Content::Text("hello".to_string()),
+        0,
+        1,
+        network_timestamp,
+    )
+}
+
+#[test]
+fn test_future_skew_follows_policy() {
+    let mut room = room();
+    room.engine.config.clock_policy.max_future_skew = Duration::from_secs(60);
+    let future_ts = room.engine.clock.network_time_ms() + 2 * 60 * 1000;
+    let node = remote_node(&room, future_ts);
+    let hash = node.hash();
+
+    let effects = room
+        .engine
+        .handle_node(room.conv_id, node, &room.store, None)
+        .unwrap();
+    apply_effects(effects, &room.store);
+    assert!(!room.store.is_verified(&hash));
+
+    room.engine.config.clock_policy.max_future_skew = Duration::from_secs(5 * 60);
+    let effects = room
+        .engine
+        .reverify_speculative_for_conversation(room.conv_id, &room.store);
+    apply_effects(effects, &room.store);
+    assert!(room.store.is_verified(&hash));
+}
+
+#[test]
+fn test_authored_timestamp_not_before_parents() {
+    let mut room = room();
+    let parent_ts = room.engine.clock.network_time_ms() + 5 * 60 * 1000;
+    let parent = remote_node(&room, parent_ts);
+    let parent_hash = parent.hash();
+    let effects = room
+        .engine
+        .handle_node(room.conv_id, parent, &room.store, None)
+        .unwrap();
+    apply_effects(effects, &room.store);
+    assert!(room.store.is_verified(&parent_hash));
+
+    let effects = room
+        .engine
+        .author_node(
+            room.conv_id,
+            Content::Text("reply".to_string()),
+            vec![],
+            &room.store,
+        )
+        .unwrap();
+    let node = get_node_from_effects(effects);
+    assert!(node.parents.contains(&parent_hash));
+    assert_eq!(node.network_timestamp, parent_ts);
+}
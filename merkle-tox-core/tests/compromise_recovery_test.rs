@@ -0,0 +1,84 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, ControlAction};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::engine::recovery::{COMPROMISE_REASON, CompromiseRecovery};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{TestRoom, apply_effects, get_all_nodes_from_effects};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn recovery(effects: &[Effect]) -> CompromiseRecovery {
+    effects
+        .iter()
+        .find_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::CompromiseRecovered(r)) => Some(r.clone()),
+            _ => None,
+        })
+        .expect("no CompromiseRecovered event")
+}
+
+#[test]
+fn test_recovery_revokes_and_rekeys() {
+    let room = TestRoom::new(3);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let c = room.peer(2, &tp);
+    let cid = room.conv_id;
+    assert!(
+        a.engine
+            .peer_ephemeral_signing_keys
+            .contains_key(&(c.pk, 0))
+    );
+
+    let effects = a
+        .engine
+        .recover_from_compromise(cid, c.pk, &a.store)
+        .unwrap();
+    apply_effects(effects.clone(), &a.store);
+    let recovery = recovery(&effects);
+    assert_eq!(recovery.conversation_id, cid);
+    assert_eq!(recovery.device_pk, c.pk);
+    assert_eq!(recovery.epoch, 1);
+    assert!(recovery.rewrapped.contains(&b.pk));
+    assert!(!recovery.rewrapped.contains(&c.pk));
+    assert_eq!(a.engine.get_current_generation(&cid), 1);
+    assert!(
+        !a.engine
+            .peer_ephemeral_signing_keys
+            .keys()
+            .any(|(pk, _)| *pk == c.pk)
+    );
+
+    let revocation = a.store.get_node(&recovery.revocation).unwrap();
+    assert_eq!(
+        revocation.content,
+        Content::Control(ControlAction::RevokeDevice {
+            target_device_pk: c.pk,
+            reason: COMPROMISE_REASON.to_string(),
+        })
+    );
+
+    // The remaining device follows to the new epoch.
+    for node in get_all_nodes_from_effects(&effects) {
+        let effects = b.engine.handle_node(cid, node, &b.store, None).unwrap();
+        apply_effects(effects, &b.store);
+    }
+    assert!(b.store.is_verified(&recovery.revocation));
+    assert_eq!(b.engine.get_current_generation(&cid), 1);
+}
+
+#[test]
+fn test_cannot_recover_from_own_compromise() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let self_pk = a.pk;
+    assert!(
+        a.engine
+            .recover_from_compromise(room.conv_id, self_pk, &a.store)
+            .is_err()
+    );
+    assert_eq!(a.engine.get_current_generation(&room.conv_id), 0);
+}
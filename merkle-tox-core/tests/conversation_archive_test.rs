@@ -0,0 +1,120 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, NodeHash};
+use merkle_tox_core::engine::{Conversation, Effect, MerkleToxEngine};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{TestPeer, TestRoom, apply_effects};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn peer(room: &TestRoom, index: usize) -> TestPeer {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1_000_000));
+    room.peer(index, &tp)
+}
+
+fn established(
+    engine: &MerkleToxEngine,
+    room: &TestRoom,
+) -> merkle_tox_core::engine::conversation::Established {
+    match engine.conversations.get(&room.conv_id) {
+        Some(Conversation::Established(em)) => em.state.clone(),
+        _ => panic!("conversation not established"),
+    }
+}
+
+#[test]
+fn test_unload_frees_conversation() {
+    let room = TestRoom::new(2);
+    let mut alice = peer(&room, 0);
+    alice.author_text(room.conv_id, "hello");
+
+    let effects = alice.engine.unload_conversation(room.conv_id);
+    assert!(matches!(
+        effects.as_slice(),
+        [Effect::WriteArchivedConversation(a)] if a.conversation_id == room.conv_id
+    ));
+    apply_effects(effects, &alice.store);
+
+    assert!(!alice.engine.conversations.contains_key(&room.conv_id));
+    assert!(alice.engine.is_unloaded(&room.conv_id));
+    assert!(
+        alice
+            .store
+            .get_archived_conversation(&room.conv_id)
+            .is_some()
+    );
+
+    // Unloading twice is a no-op.
+    assert!(alice.engine.unload_conversation(room.conv_id).is_empty());
+}
+
+#[test]
+fn test_authoring_reloads_ratchet_state() {
+    let room = TestRoom::new(2);
+    let mut alice = peer(&room, 0);
+    // A random SenderKey cannot be derived again from the store.
+    let effects = alice
+        .engine
+        .sender_rekey(room.conv_id, &alice.store)
+        .unwrap();
+    apply_effects(effects, &alice.store);
+    alice.author_text(room.conv_id, "one");
+    alice.author_text(room.conv_id, "two");
+    let before = established(&alice.engine, &room);
+
+    let effects = alice.engine.unload_conversation(room.conv_id);
+    apply_effects(effects, &alice.store);
+    alice
+        .engine
+        .ensure_conversation_loaded(room.conv_id, &alice.store)
+        .unwrap();
+    let reloaded = established(&alice.engine, &room);
+    assert!(!alice.engine.is_unloaded(&room.conv_id));
+    assert_eq!(reloaded.current_epoch, before.current_epoch);
+    assert_eq!(reloaded.sender_keys, before.sender_keys);
+    assert_eq!(reloaded.sender_ratchets, before.sender_ratchets);
+    assert_eq!(reloaded.jit_headers, before.jit_headers);
+    assert_eq!(reloaded.shared_keys_sent_to, before.shared_keys_sent_to);
+    assert_eq!(reloaded.self_message_count, before.self_message_count);
+
+    // Unload again and author without reloading explicitly.
+    let effects = alice.engine.unload_conversation(room.conv_id);
+    apply_effects(effects, &alice.store);
+    alice.author_text(room.conv_id, "three");
+    assert!(!alice.engine.is_unloaded(&room.conv_id));
+    assert_eq!(
+        established(&alice.engine, &room).self_message_count,
+        before.self_message_count + 1
+    );
+}
+
+#[test]
+fn test_receiving_reloads_conversation() {
+    let room = TestRoom::new(2);
+    let mut alice = peer(&room, 0);
+    let mut bob = peer(&room, 1);
+    let cid = room.conv_id;
+    alice.handshake(&mut bob, cid);
+
+    let nodes: Vec<Vec<NodeHash>> = ["one", "two"]
+        .iter()
+        .map(|text| alice.author_nodes(cid, Content::Text(text.to_string())))
+        .collect();
+
+    for hash in &nodes[0] {
+        bob.receive_from(&alice, cid, *hash);
+    }
+    let effects = bob.engine.unload_conversation(cid);
+    apply_effects(effects, &bob.store);
+
+    for hash in &nodes[1] {
+        bob.receive_from(&alice, cid, *hash);
+    }
+    for hash in &nodes[1] {
+        assert!(bob.store.is_verified(hash));
+    }
+    assert!(!bob.engine.is_unloaded(&cid));
+    let ratchet =
+        established(&bob.engine, &room).sender_ratchets[&room.identities[0].device_pk].clone();
+    let last = alice.store.get_node(nodes[1].last().unwrap()).unwrap();
+    assert_eq!(ratchet.0, last.sequence_number);
+}
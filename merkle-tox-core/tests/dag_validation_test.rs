@@ -1,9 +1,11 @@
 use ed25519_dalek::{Signer, SigningKey};
 use merkle_tox_core::dag::{
-    Content, ControlAction, ConversationId, Ed25519Signature, LogicalIdentityPk, MAX_METADATA_SIZE,
-    MAX_PARENTS, MerkleNode, NodeAuth, NodeHash, PhysicalDevicePk,
+    Content, ControlAction, ConversationId, Ed25519Signature, LogicalIdentityPk,
+    MAX_DISPLAY_NAME_LEN, MAX_METADATA_SIZE, MAX_PARENTS, MAX_STATUS_LEN, MerkleNode, NodeAuth,
+    NodeHash, PhysicalDevicePk, Profile,
 };
 use merkle_tox_core::testing::{InMemoryStore, TestIdentity, TestRoom, sign_admin_node, test_node};
+use tox_proto::constants::MAX_THUMBNAIL_SIZE;
 
 #[test]
 fn test_validate_max_parents() {
@@ -32,6 +34,66 @@ fn test_validate_max_metadata() {
     ));
 }
 
+#[test]
+fn test_validate_blob_preview_size() {
+    let blob = |preview_len: usize| Content::Blob {
+        hash: NodeHash::from([1u8; 32]),
+        name: "photo.jpg".to_string(),
+        mime_type: "image/jpeg".to_string(),
+        size: 1_000_000,
+        metadata: vec![],
+        preview: Some(vec![0u8; preview_len]),
+    };
+    let lookup = InMemoryStore::new();
+    let conv_id = ConversationId::from([0xAAu8; 32]);
+
+    let mut node = test_node();
+    node.content = blob(MAX_THUMBNAIL_SIZE + 1);
+    assert!(matches!(
+        node.validate(&conv_id, &lookup),
+        Err(merkle_tox_core::dag::ValidationError::PreviewTooLarge { .. })
+    ));
+
+    node.content = blob(MAX_THUMBNAIL_SIZE);
+    assert!(!matches!(
+        node.validate(&conv_id, &lookup),
+        Err(merkle_tox_core::dag::ValidationError::PreviewTooLarge { .. })
+    ));
+}
+
+#[test]
+fn test_validate_profile_size() {
+    let profile = |name_len: usize, status_len: usize| {
+        Content::Control(ControlAction::SetProfile(Profile {
+            display_name: "n".repeat(name_len),
+            avatar: Some(NodeHash::from([1u8; 32])),
+            status: "s".repeat(status_len),
+        }))
+    };
+    let lookup = InMemoryStore::new();
+    let conv_id = ConversationId::from([0xAAu8; 32]);
+
+    let mut node = test_node();
+    for content in [
+        profile(MAX_DISPLAY_NAME_LEN + 1, 0),
+        profile(0, MAX_STATUS_LEN + 1),
+    ] {
+        node.content = content;
+        assert!(matches!(
+            node.validate(&conv_id, &lookup),
+            Err(merkle_tox_core::dag::ValidationError::InvalidProfile(_))
+        ));
+    }
+
+    node.content = profile(MAX_DISPLAY_NAME_LEN, MAX_STATUS_LEN);
+    assert!(!matches!(
+        node.validate(&conv_id, &lookup),
+        Err(merkle_tox_core::dag::ValidationError::InvalidProfile(_))
+    ));
+    // The avatar is fetched along with the profile.
+    assert_eq!(node.content.blob_hash(), Some(&NodeHash::from([1u8; 32])));
+}
+
 #[test]
 fn test_validate_first_node_rank() {
     let mut node = test_node();
@@ -0,0 +1,189 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{Content, PhysicalDevicePk};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::sync::{FLAG_HEADS_RESYNC, FetchBatchReq, NodeStore, SyncHeadsDelta};
+use merkle_tox_core::testing::{
+    SimConfig, SimNetwork, TestPeer, TestRoom, apply_effects, sent_to, transfer_ephemeral_keys,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn head_announcements(effects: &[Effect], to: PhysicalDevicePk) -> Vec<ProtocolMessage> {
+    sent_to(effects, to)
+        .into_iter()
+        .filter(|m| {
+            matches!(
+                m,
+                ProtocolMessage::SyncHeads(_)
+                    | ProtocolMessage::SyncHeadsDelta(_)
+                    | ProtocolMessage::MerkleNodeWithHeads { .. }
+            )
+        })
+        .collect()
+}
+
+fn setup() -> (TestRoom, Arc<ManualTimeProvider>, TestPeer, TestPeer) {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let a = room.peer(0, &tp);
+    let b = room.peer(1, &tp);
+    (room, tp, a, b)
+}
+
+#[test]
+fn test_poll_sends_head_delta() {
+    let (room, tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    a.handshake(&mut b, cid);
+    let old_heads = a.store.get_heads(&cid);
+
+    let node = a.author_text(cid, "hello");
+    let effects = a.engine.poll(tp.now_instant(), &a.store).unwrap();
+    let announced = head_announcements(&effects, b.pk);
+    assert_eq!(
+        announced,
+        vec![ProtocolMessage::SyncHeadsDelta(SyncHeadsDelta {
+            conversation_id: cid,
+            base_version: Some(0),
+            version: 1,
+            added: vec![node],
+            removed: old_heads,
+            anchor_hash: a.store.get_admin_heads(&cid).first().cloned(),
+        })]
+    );
+
+    // Nothing changed since: no announcement at all.
+    let effects = a.engine.poll(tp.now_instant(), &a.store).unwrap();
+    assert!(head_announcements(&effects, b.pk).is_empty());
+
+    // The receiver fetches the new head.
+    let effects = b
+        .engine
+        .handle_message(a.pk, announced[0].clone(), &b.store, None)
+        .unwrap();
+    assert!(
+        sent_to(&effects, a.pk).contains(&ProtocolMessage::FetchBatchReq(FetchBatchReq {
+            conversation_id: cid,
+            hashes: vec![node],
+        }))
+    );
+}
+
+#[test]
+fn test_legacy_peer_gets_full_heads() {
+    let (room, tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    a.handshake_announcing(&mut b, cid, 0);
+
+    let node = b.author_text(cid, "hello");
+    let effects = b.engine.poll(tp.now_instant(), &b.store).unwrap();
+    match head_announcements(&effects, a.pk).as_slice() {
+        [ProtocolMessage::SyncHeads(heads)] => assert_eq!(heads.heads, vec![node]),
+        other => panic!("expected full SyncHeads, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_delta_gap_requests_full_heads() {
+    let (room, tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    a.handshake(&mut b, cid);
+
+    let gap = ProtocolMessage::SyncHeadsDelta(SyncHeadsDelta {
+        conversation_id: cid,
+        base_version: Some(3),
+        version: 4,
+        added: vec![],
+        removed: vec![],
+        anchor_hash: None,
+    });
+    let effects = b
+        .engine
+        .handle_message(a.pk, gap.clone(), &b.store, None)
+        .unwrap();
+    let request = match head_announcements(&effects, a.pk).as_slice() {
+        [msg @ ProtocolMessage::SyncHeads(heads)] if heads.flags & FLAG_HEADS_RESYNC != 0 => {
+            msg.clone()
+        }
+        other => panic!("expected resync request, got {:?}", other),
+    };
+
+    // Further gaps do not repeat the request.
+    let effects = b.engine.handle_message(a.pk, gap, &b.store, None).unwrap();
+    assert!(head_announcements(&effects, a.pk).is_empty());
+
+    a.engine
+        .handle_message(b.pk, request, &a.store, None)
+        .unwrap();
+    let effects = a.engine.poll(tp.now_instant(), &a.store).unwrap();
+    match head_announcements(&effects, b.pk).as_slice() {
+        [ProtocolMessage::SyncHeadsDelta(delta)] => {
+            assert_eq!(delta.base_version, None);
+            assert_eq!(delta.version, 1);
+            let mut added = delta.added.clone();
+            added.sort_unstable();
+            let mut heads = a.store.get_heads(&cid);
+            heads.sort_unstable();
+            assert_eq!(added, heads);
+        }
+        other => panic!("expected full delta, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_head_delta_piggybacks_on_node() {
+    let (room, tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    a.handshake(&mut b, cid);
+    transfer_ephemeral_keys(&a.engine, &mut b.engine);
+
+    let node = a.author_text(cid, "hello");
+    let effects = a
+        .engine
+        .handle_message(
+            b.pk,
+            ProtocolMessage::FetchBatchReq(FetchBatchReq {
+                conversation_id: cid,
+                hashes: vec![node],
+            }),
+            &a.store,
+            None,
+        )
+        .unwrap();
+    let reply = match head_announcements(&effects, b.pk).as_slice() {
+        [msg @ ProtocolMessage::MerkleNodeWithHeads { hash, heads, .. }] => {
+            assert_eq!(*hash, node);
+            assert_eq!(heads.added, vec![node]);
+            msg.clone()
+        }
+        other => panic!("expected piggybacked heads, got {:?}", other),
+    };
+
+    // The heads went out with the node; poll has nothing left to announce.
+    let effects = a.engine.poll(tp.now_instant(), &a.store).unwrap();
+    assert!(head_announcements(&effects, b.pk).is_empty());
+
+    let effects = b
+        .engine
+        .handle_message(a.pk, reply, &b.store, None)
+        .unwrap();
+    apply_effects(effects, &b.store);
+    assert!(b.store.has_node(&node));
+}
+
+#[test]
+fn test_sim_network_converges_with_delta_heads() {
+    let room = TestRoom::new(4);
+    let config = SimConfig {
+        jitter: Duration::from_millis(80),
+        ..SimConfig::default()
+    };
+    let mut sim = SimNetwork::with_room(&room, 5, config);
+    for i in 0..12 {
+        sim.author(i % 4, room.conv_id, Content::Text(format!("m{}", i)))
+            .unwrap();
+        sim.run_for(Duration::from_millis(150));
+    }
+    assert!(sim.run_until_converged(room.conv_id, Duration::from_secs(60)));
+}
@@ -0,0 +1,135 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, KConv, MerkleNode, PhysicalDevicePk, PhysicalDeviceSk};
+use merkle_tox_core::diagnostics::{Problem, check_integrity};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport, VirtualHub, test_node};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn node(parents: &[&MerkleNode], rank: u64, seq: u64) -> MerkleNode {
+    let mut node = test_node();
+    node.parents = parents.iter().map(|p| p.hash()).collect();
+    node.topological_rank = rank;
+    node.sequence_number = seq;
+    node
+}
+
+fn node_at(
+    store: &InMemoryStore,
+    conv_id: &ConversationId,
+    parents: &[&MerkleNode],
+    rank: u64,
+    seq: u64,
+) -> MerkleNode {
+    let n = node(parents, rank, seq);
+    store.put_node(conv_id, n.clone(), true).unwrap();
+    n
+}
+
+fn make_node() -> MerkleToxNode<SimulatedTransport, InMemoryStore> {
+    let time_provider = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time_provider.clone()));
+    let sk = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+    let pk = PhysicalDevicePk::from(sk.verifying_key().to_bytes());
+    let engine = MerkleToxEngine::with_sk(
+        pk,
+        pk.to_logical(),
+        PhysicalDeviceSk::from(sk.to_bytes()),
+        StdRng::seed_from_u64(1),
+        time_provider.clone(),
+    );
+    let transport = SimulatedTransport::new(pk, hub);
+    MerkleToxNode::new(engine, transport, InMemoryStore::new(), time_provider)
+}
+
+#[test]
+fn test_healthy_node() {
+    let mut mt = make_node();
+    let conv_id = ConversationId::from([0x11u8; 32]);
+    mt.store
+        .put_conversation_key(&conv_id, 0, KConv::from([0xAAu8; 32]))
+        .unwrap();
+    mt.engine
+        .load_conversation_state(conv_id, &mt.store)
+        .unwrap();
+    let root = node_at(&mt.store, &conv_id, &[], 0, 1);
+    node_at(&mt.store, &conv_id, &[&root], 1, 2);
+
+    let report = mt.diagnostics();
+    assert_eq!(report.pk, mt.engine.self_pk);
+    assert_eq!(report.conversations.len(), 1);
+    let conv = &report.conversations[0];
+    assert!(conv.loaded && conv.established);
+    assert_eq!(conv.verified_nodes, 2);
+    assert_eq!(conv.integrity.orphan_nodes, 0);
+    assert!(conv.integrity.missing_parents.is_empty());
+    let ratchets = conv.ratchets.as_ref().unwrap();
+    assert_eq!(ratchets.covered, ratchets.devices);
+    assert!(report.problems().is_empty());
+    assert!(report.is_healthy());
+}
+
+#[test]
+fn test_problems_are_reported() {
+    let mut mt = make_node();
+    let conv_id = ConversationId::from([0x22u8; 32]);
+    mt.engine
+        .load_conversation_state(conv_id, &mt.store)
+        .unwrap();
+
+    let root = node(&[], 0, 1);
+    let lost = node(&[&root], 1, 2);
+    let a = node_at(&mt.store, &conv_id, &[], 0, 1);
+    node_at(&mt.store, &conv_id, &[&a, &lost], 2, 3);
+    let speculative = node(&[&lost], 2, 4);
+    mt.store.put_node(&conv_id, speculative, false).unwrap();
+
+    mt.engine
+        .clock
+        .update_peer_offset(PhysicalDevicePk::from([9u8; 32]), 10 * 60 * 1000);
+
+    let report = mt.diagnostics();
+    let conv = &report.conversations[0];
+    assert!(!conv.established);
+    assert!(conv.ratchets.is_none());
+    assert_eq!(conv.integrity.orphan_nodes, 2);
+    assert_eq!(conv.integrity.missing_parents, vec![lost.hash()]);
+    assert!(report.clock.is_skewed());
+
+    let problems = report.problems();
+    assert_eq!(
+        problems,
+        vec![
+            Problem::ClockSkew {
+                offset_ms: 10 * 60 * 1000
+            },
+            Problem::MissingParents {
+                conversation_id: conv_id,
+                count: 1
+            },
+            Problem::NoConversationKey {
+                conversation_id: conv_id
+            },
+        ]
+    );
+    assert!(problems[1].to_string().contains("1 parent node(s) missing"));
+}
+
+#[test]
+fn test_history_horizon_hides_expected_gaps() {
+    let store = InMemoryStore::new();
+    let conv_id = ConversationId::from([0x33u8; 32]);
+    let lost = node(&[], 4, 1);
+    let boundary = node_at(&store, &conv_id, &[&lost], 5, 2);
+    node_at(&store, &conv_id, &[&boundary], 6, 3);
+
+    assert_eq!(check_integrity(&store, &conv_id).orphan_nodes, 1);
+    store.set_history_horizon(&conv_id, Some(5)).unwrap();
+    let integrity = check_integrity(&store, &conv_id);
+    assert_eq!(integrity.history_horizon, Some(5));
+    assert_eq!(integrity.orphan_nodes, 0);
+    assert!(integrity.missing_parents.is_empty());
+}
@@ -3,6 +3,7 @@ use merkle_tox_core::dag::{
     ChainKey, Content, ConversationId, Ed25519Signature, KConv, MerkleNode, NodeHash, NodeType,
     PhysicalDevicePk,
 };
+use merkle_tox_core::engine::quarantine::BlacklistEntry;
 use merkle_tox_core::engine::{Effect, MerkleToxEngine};
 use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
 use merkle_tox_core::node::MerkleToxNode;
@@ -304,3 +305,26 @@ fn test_pending_cache_cleared_on_success() {
     // Cache SHOULD be cleared on success
     assert_eq!(node.engine.pending_cache_len(), 0);
 }
+
+#[test]
+fn test_store_without_optional_state_rejects_writes() {
+    // FailingStore keeps the default methods for optional state, so writes
+    // fail loudly instead of being dropped.
+    let (store, _) = FailingStore::new();
+    let entry = BlacklistEntry {
+        device_pk: PhysicalDevicePk::from([1u8; 32]),
+        tier: 1,
+        expires_at_ms: 1000,
+    };
+    assert!(matches!(
+        store.put_blacklist_entry(&entry),
+        Err(MerkleToxError::Storage(_))
+    ));
+    assert!(store.remove_suspended_state().is_err());
+    assert!(
+        store
+            .set_history_horizon(&ConversationId::from([1u8; 32]), Some(5))
+            .is_err()
+    );
+    assert!(store.get_blacklist().is_empty());
+}
@@ -0,0 +1,112 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, delegate_store};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+struct DummyTransport(PhysicalDevicePk);
+impl merkle_tox_core::Transport for DummyTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.0
+    }
+    fn send_raw(
+        &self,
+        _to: PhysicalDevicePk,
+        _data: Vec<u8>,
+    ) -> Result<(), merkle_tox_core::TransportError> {
+        Ok(())
+    }
+}
+
+struct SharedStore {
+    store: Arc<InMemoryStore>,
+}
+delegate_store!(SharedStore, store);
+
+/// Records whether each announced node was already stored.
+struct StoredOnEvent {
+    store: Arc<InMemoryStore>,
+    seen: Mutex<Vec<(NodeHash, bool)>>,
+}
+
+impl merkle_tox_core::NodeEventHandler for StoredOnEvent {
+    fn handle_event(&self, event: NodeEvent) {
+        if let NodeEvent::NodeVerified { hash, .. } = event {
+            self.seen
+                .lock()
+                .unwrap()
+                .push((hash, self.store.has_node(&hash)));
+        }
+    }
+}
+
+fn make_node(seq: u64, parents: Vec<NodeHash>) -> MerkleNode {
+    MerkleNode {
+        parents,
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64,
+        content: Content::Text(format!("message {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+#[test]
+fn test_events_follow_batched_writes() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let pk = PhysicalDevicePk::from([2u8; 32]);
+    let engine = MerkleToxEngine::new(pk, pk.to_logical(), StdRng::seed_from_u64(0), tp.clone());
+    let store = Arc::new(InMemoryStore::new());
+    let mut node = MerkleToxNode::new(
+        engine,
+        DummyTransport(pk),
+        SharedStore {
+            store: store.clone(),
+        },
+        tp,
+    );
+    let handler = Arc::new(StoredOnEvent {
+        store: store.clone(),
+        seen: Mutex::new(Vec::new()),
+    });
+    node.set_event_handler(handler.clone());
+
+    let cid = ConversationId::from([1u8; 32]);
+    let first = make_node(1, vec![]);
+    let second = make_node(2, vec![first.hash()]);
+    let mut effects = Vec::new();
+    for n in [&first, &second] {
+        // The event comes before the write it announces.
+        effects.push(Effect::EmitEvent(NodeEvent::NodeVerified {
+            conversation_id: cid,
+            hash: n.hash(),
+            node: n.clone(),
+        }));
+        effects.push(Effect::WriteStore(cid, n.clone(), true));
+        effects.push(Effect::UpdateHeads(cid, vec![n.hash()], false));
+    }
+
+    let now = Instant::now();
+    let mut next_wakeup = now;
+    node.process_effects(effects, now, 0, &mut next_wakeup)
+        .unwrap();
+
+    assert_eq!(
+        *handler.seen.lock().unwrap(),
+        vec![(first.hash(), true), (second.hash(), true)]
+    );
+    assert_eq!(store.get_heads(&cid), vec![second.hash()]);
+    assert!(store.is_verified(&second.hash()));
+}
@@ -0,0 +1,210 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, MerkleNode, ValidationError};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::entities::{EntityKind, TextEntities, TextEntity, mentions};
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{
+    InMemoryStore, TestRoom, create_signed_content_node, sign_content_node,
+};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn entity(start: u32, end: u32, kind: EntityKind) -> TextEntity {
+    TextEntity { start, end, kind }
+}
+
+#[test]
+fn test_entities_round_trip_through_metadata() {
+    let room = TestRoom::new(2);
+    let bob = room.identities[1].master_pk;
+    let entities = TextEntities {
+        entities: vec![
+            entity(3, 7, EntityKind::Mention(bob)),
+            entity(8, 27, EntityKind::Url),
+        ],
+    };
+    let metadata = entities.to_metadata();
+    assert_eq!(TextEntities::from_metadata(&metadata), Some(entities));
+    assert!(TextEntities::default().to_metadata().is_empty());
+    assert_eq!(TextEntities::from_metadata(b"garbage"), None);
+}
+
+#[test]
+fn test_entities_must_fit_the_text() {
+    let text = "hé @bob";
+    assert!(entity(4, 8, EntityKind::Url).fits(text));
+    assert_eq!(entity(4, 8, EntityKind::Url).slice(text), "@bob");
+    // Empty, reversed, out of bounds, inside a character.
+    for (start, end) in [(4, 4), (5, 4), (4, 9), (2, 4)] {
+        assert!(!entity(start, end, EntityKind::Url).fits(text));
+    }
+}
+
+/// Bob's engine in a room of Alice and Bob.
+fn bob_engine(room: &TestRoom) -> (MerkleToxEngine, InMemoryStore) {
+    let bob = &room.identities[1];
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engine =
+        MerkleToxEngine::new(bob.device_pk, bob.master_pk, StdRng::seed_from_u64(1), tp);
+    let store = InMemoryStore::new();
+    room.setup_engine(&mut engine, &store);
+    (engine, store)
+}
+
+/// A text message of Alice on top of the heads of `store`.
+fn alice_says(
+    room: &TestRoom,
+    store: &InMemoryStore,
+    text: &str,
+    entities: Vec<TextEntity>,
+) -> MerkleNode {
+    let cid = room.conv_id;
+    let alice = &room.identities[0];
+    let mut parents = store.get_heads(&cid);
+    parents.extend(store.get_admin_heads(&cid));
+    parents.sort_unstable();
+    parents.dedup();
+    let rank = parents
+        .iter()
+        .map(|h| store.get_node(h).unwrap().topological_rank)
+        .max()
+        .unwrap();
+    let mut node = create_signed_content_node(
+        &cid,
+        &room.keys,
+        alice.master_pk,
+        alice.device_pk,
+        parents,
+        Content::Text(text.to_string()),
+        rank + 1,
+        2,
+        1000,
+    );
+    node.metadata = TextEntities { entities }.to_metadata();
+    sign_content_node(&mut node, &cid, &room.keys);
+    node
+}
+
+#[test]
+fn test_mention_raises_event() {
+    let room = TestRoom::new(2);
+    let cid = room.conv_id;
+    let (alice, bob) = (&room.identities[0], &room.identities[1]);
+    let (mut engine, store) = bob_engine(&room);
+
+    let node = alice_says(
+        &room,
+        &store,
+        "hi @bob",
+        vec![entity(3, 7, EntityKind::Mention(bob.master_pk))],
+    );
+    assert!(mentions(&node, &bob.master_pk));
+    assert!(!mentions(&node, &alice.master_pk));
+
+    let hash = node.hash();
+    let effects = engine.handle_node(cid, node, &store, None).unwrap();
+    let mentioned: Vec<_> = effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::Mentioned {
+                conversation_id,
+                hash,
+                author_pk,
+            }) => Some((*conversation_id, *hash, *author_pk)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(mentioned, vec![(cid, hash, alice.master_pk)]);
+}
+
+#[test]
+fn test_entities_nest_canonically() {
+    let text = "bold italic `code` link";
+    let link = || EntityKind::Link("https://tox.chat".to_string());
+    let valid = [
+        vec![],
+        vec![
+            entity(0, 11, EntityKind::Bold),
+            entity(5, 11, EntityKind::Italic),
+        ],
+        // Equal ranges: the later one is inside.
+        vec![
+            entity(0, 4, EntityKind::Bold),
+            entity(0, 4, EntityKind::Code),
+        ],
+        vec![entity(19, 23, link()), entity(19, 23, EntityKind::Spoiler)],
+        vec![
+            entity(0, 4, EntityKind::Bold),
+            entity(12, 18, EntityKind::Code),
+        ],
+    ];
+    for entities in valid {
+        assert!(TextEntities { entities }.validate(text).is_ok());
+    }
+    let invalid = [
+        // Out of bounds.
+        vec![entity(19, 24, EntityKind::Bold)],
+        // Out of order.
+        vec![
+            entity(5, 11, EntityKind::Italic),
+            entity(0, 11, EntityKind::Bold),
+        ],
+        // Partial overlap.
+        vec![
+            entity(0, 6, EntityKind::Bold),
+            entity(5, 11, EntityKind::Italic),
+        ],
+        // Code holds no other entities.
+        vec![
+            entity(12, 18, EntityKind::Code),
+            entity(13, 17, EntityKind::Bold),
+        ],
+        // Same kind.
+        vec![
+            entity(0, 11, EntityKind::Bold),
+            entity(5, 11, EntityKind::Bold),
+        ],
+        // Links do not nest in links.
+        vec![entity(0, 23, link()), entity(19, 23, EntityKind::Url)],
+        vec![entity(19, 23, EntityKind::Link(String::new()))],
+    ];
+    for entities in invalid {
+        assert!(matches!(
+            TextEntities { entities }.validate(text),
+            Err(ValidationError::InvalidTextEntity(_))
+        ));
+    }
+    assert!(
+        TextEntities {
+            entities: vec![entity(0, 5, EntityKind::Bold)]
+        }
+        .validate("hé")
+        .is_err()
+    );
+}
+
+#[test]
+fn test_engine_rejects_invalid_entities() {
+    let room = TestRoom::new(2);
+    let (mut engine, store) = bob_engine(&room);
+
+    let node = alice_says(
+        &room,
+        &store,
+        "**",
+        vec![
+            entity(0, 2, EntityKind::Code),
+            entity(0, 1, EntityKind::Bold),
+        ],
+    );
+    assert!(TextEntities::of(&node).is_empty());
+    assert!(matches!(
+        engine.handle_node(room.conv_id, node, &store, None),
+        Err(MerkleToxError::Validation(
+            ValidationError::InvalidTextEntity(_)
+        ))
+    ));
+}
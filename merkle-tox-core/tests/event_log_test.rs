@@ -0,0 +1,110 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, KConv, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::session::{Handshake, PeerSession, SyncSession};
+use merkle_tox_core::engine::{Conversation, ConversationData, MerkleToxEngine, conversation};
+use merkle_tox_core::event_log::{ProtocolEventKind, ProtocolEventLog};
+use merkle_tox_core::sync::SyncHeads;
+use merkle_tox_core::testing::InMemoryStore;
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+#[test]
+fn test_event_log_keeps_most_recent_events() {
+    let peer = PhysicalDevicePk::from([0xABu8; 32]);
+    let conv = ConversationId::from([0xCDu8; 32]);
+    let mut log = ProtocolEventLog::new(2);
+    log.record(
+        1,
+        Some(peer),
+        None,
+        ProtocolEventKind::Received("CapsAnnounce"),
+    );
+    log.record(
+        2,
+        Some(peer),
+        Some(conv),
+        ProtocolEventKind::Sent("SyncHeads"),
+    );
+    log.record(
+        3,
+        Some(peer),
+        Some(conv),
+        ProtocolEventKind::FetchRequested { count: 4 },
+    );
+
+    let seqs: Vec<u64> = log.events().map(|e| e.seq).collect();
+    assert_eq!(seqs, vec![1, 2]);
+    assert_eq!(
+        log.dump(),
+        "#1 t=2 peer=abababab conv=cdcdcdcd send SyncHeads\n\
+         #2 t=3 peer=abababab conv=cdcdcdcd fetch 4 nodes\n"
+    );
+    assert!(
+        log.dump_conversation(&ConversationId::from([0u8; 32]))
+            .is_empty()
+    );
+
+    log.set_capacity(1);
+    assert_eq!(log.len(), 1);
+    log.set_capacity(0);
+    log.record(4, None, None, ProtocolEventKind::Error("x".into()));
+    assert!(log.is_empty());
+}
+
+#[test]
+fn test_engine_logs_handled_messages() {
+    let self_pk = PhysicalDevicePk::from([1u8; 32]);
+    let peer = PhysicalDevicePk::from([2u8; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine =
+        MerkleToxEngine::new(self_pk, self_pk.to_logical(), StdRng::seed_from_u64(0), tp);
+    let store = InMemoryStore::new();
+    let conv_id = ConversationId::from([0xAAu8; 32]);
+    engine.conversations.insert(
+        conv_id,
+        Conversation::Established(ConversationData::<conversation::Established>::new(
+            conv_id,
+            KConv::from([0xBBu8; 32]),
+            0,
+        )),
+    );
+    let session = SyncSession::<Handshake>::new(conv_id, &store, false, Instant::now());
+    engine
+        .sessions
+        .insert((peer, conv_id), PeerSession::Active(session.activate(0)));
+
+    let heads = SyncHeads {
+        conversation_id: conv_id,
+        heads: vec![NodeHash::from([0xCCu8; 32])],
+        flags: 0,
+        anchor_hash: None,
+    };
+    engine
+        .handle_message(peer, ProtocolMessage::SyncHeads(heads), &store, None)
+        .unwrap();
+
+    let kinds: Vec<&ProtocolEventKind> = engine.event_log.events().map(|e| &e.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            &ProtocolEventKind::Received("SyncHeads"),
+            &ProtocolEventKind::FetchRequested { count: 1 },
+        ]
+    );
+    assert!(
+        engine
+            .event_log
+            .events()
+            .all(|e| e.peer_pk == Some(peer) && e.conversation_id == Some(conv_id))
+    );
+
+    engine.poll(Instant::now(), &store).unwrap();
+    assert!(
+        engine
+            .event_log
+            .events()
+            .any(|e| e.kind == ProtocolEventKind::Sent("SyncShardChecksums"))
+    );
+}
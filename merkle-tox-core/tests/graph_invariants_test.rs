@@ -0,0 +1,203 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, GraphViolation, MerkleNode, NodeHash, PhysicalDeviceSk, validate_graph,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{
+    InMemoryStore, SimConfig, SimNetwork, TestRoom, apply_effects, create_msg,
+};
+use proptest::prelude::*;
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Shape of a random DAG: for each node, its author and parent selectors.
+fn arb_dag_shape() -> impl Strategy<Value = Vec<(usize, Vec<usize>)>> {
+    prop::collection::vec(
+        (0usize..3, prop::collection::vec(any::<usize>(), 1..4)),
+        1..25,
+    )
+}
+
+/// Builds signed content nodes for `shape` on top of the room's admin chain.
+/// Returns the nodes in topological order and the expected heads.
+fn build_dag(
+    room: &TestRoom,
+    store: &InMemoryStore,
+    shape: &[(usize, Vec<usize>)],
+) -> (Vec<MerkleNode>, HashSet<NodeHash>) {
+    let admin_heads = store.get_admin_heads(&room.conv_id);
+    let mut heads: HashSet<NodeHash> = store.get_heads(&room.conv_id).into_iter().collect();
+    let mut nodes: Vec<MerkleNode> = Vec::new();
+    let mut seqs = [0u64; 3];
+    for (i, (author, selectors)) in shape.iter().enumerate() {
+        let parents: Vec<NodeHash> = if i == 0 {
+            admin_heads.clone()
+        } else {
+            let mut picked: Vec<NodeHash> = selectors.iter().map(|s| nodes[s % i].hash()).collect();
+            picked.sort_unstable();
+            picked.dedup();
+            picked
+        };
+        let rank = if i == 0 {
+            2
+        } else {
+            parents
+                .iter()
+                .filter_map(|p| nodes.iter().find(|n| &n.hash() == p))
+                .map(|n| n.topological_rank)
+                .max()
+                .unwrap()
+                + 1
+        };
+        seqs[*author] += 1;
+        let node = create_msg(
+            &room.conv_id,
+            &room.keys,
+            &room.identities[*author],
+            parents.clone(),
+            &format!("m{}", i),
+            rank,
+            seqs[*author],
+            1000 + i as i64,
+        );
+        for p in &parents {
+            heads.remove(p);
+        }
+        heads.insert(node.hash());
+        nodes.push(node);
+    }
+    (nodes, heads)
+}
+
+fn setup(room: &TestRoom) -> (MerkleToxEngine, InMemoryStore) {
+    let id = &room.identities[0];
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engine = MerkleToxEngine::with_sk(
+        id.device_pk,
+        id.master_pk,
+        PhysicalDeviceSk::from(id.device_sk.to_bytes()),
+        StdRng::seed_from_u64(0),
+        tp,
+    );
+    let store = InMemoryStore::new();
+    room.setup_engine(&mut engine, &store);
+    (engine, store)
+}
+
+#[test]
+fn test_validate_graph_reports_violations() {
+    let room = TestRoom::new(3);
+    let (_engine, store) = setup(&room);
+    assert_eq!(validate_graph(&store, &room.conv_id), Ok(()));
+
+    let (nodes, _) = build_dag(&room, &store, &[(0, vec![]), (0, vec![0])]);
+    // Second node skips a sequence number and claims the wrong rank.
+    let mut bad = nodes[1].clone();
+    bad.sequence_number = 3;
+    bad.topological_rank = 7;
+    store
+        .put_node(&room.conv_id, nodes[0].clone(), true)
+        .unwrap();
+    store.put_node(&room.conv_id, bad.clone(), true).unwrap();
+    // Heads still point at the first node.
+    store
+        .set_heads(&room.conv_id, vec![nodes[0].hash()])
+        .unwrap();
+
+    let violations = validate_graph(&store, &room.conv_id).unwrap_err();
+    let sender = room.identities[0].device_pk;
+    assert!(violations.contains(&GraphViolation::RankViolation {
+        node: bad.hash(),
+        actual: 7,
+        expected: 3,
+    }));
+    assert!(violations.contains(&GraphViolation::SequenceGap {
+        sender,
+        after: 1,
+        next: 3,
+    }));
+    assert!(violations.contains(&GraphViolation::StaleHead {
+        head: nodes[0].hash(),
+        child: bad.hash(),
+        admin: false,
+    }));
+    assert!(violations.contains(&GraphViolation::MissingHead {
+        node: bad.hash(),
+        admin: false,
+    }));
+
+    let orphan = MerkleNode {
+        parents: vec![NodeHash::from([9u8; 32])],
+        ..nodes[1].clone()
+    };
+    store
+        .put_node(&room.conv_id, orphan.clone(), false)
+        .unwrap();
+    let violations = validate_graph(&store, &room.conv_id).unwrap_err();
+    assert!(violations.contains(&GraphViolation::MissingParent {
+        node: orphan.hash(),
+        parent: NodeHash::from([9u8; 32]),
+    }));
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn test_random_dag_any_delivery_order(shape in arb_dag_shape(), order_seed in any::<u64>()) {
+        let room = TestRoom::new(3);
+        let (mut engine, store) = setup(&room);
+        let (nodes, expected_heads) = build_dag(&room, &store, &shape);
+
+        // Deliver in a random order, retrying nodes whose parents are not
+        // yet known, as a sync session would re-fetch them.
+        let mut pending = nodes.clone();
+        pending.shuffle(&mut StdRng::seed_from_u64(order_seed));
+        while !pending.is_empty() {
+            let before = pending.len();
+            pending.retain(|node| {
+                match engine.handle_node(room.conv_id, node.clone(), &store, None) {
+                    Ok(effects) => {
+                        apply_effects(effects, &store);
+                        !store.is_verified(&node.hash())
+                    }
+                    Err(_) => true,
+                }
+            });
+            prop_assert!(pending.len() < before, "delivery made no progress");
+        }
+
+        prop_assert_eq!(validate_graph(&store, &room.conv_id), Ok(()));
+        let heads: HashSet<NodeHash> = store.get_heads(&room.conv_id).into_iter().collect();
+        prop_assert_eq!(heads, expected_heads);
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(4))]
+
+    #[test]
+    fn test_sync_interleavings_keep_invariants(
+        seed in any::<u64>(),
+        authors in prop::collection::vec((0usize..3, 0u64..300), 1..8),
+    ) {
+        let room = TestRoom::new(3);
+        let config = SimConfig {
+            jitter: Duration::from_millis(80),
+            ..SimConfig::default()
+        };
+        let mut sim = SimNetwork::with_room(&room, seed, config);
+        for (i, (author, pause_ms)) in authors.iter().enumerate() {
+            sim.author(*author, room.conv_id, Content::Text(format!("m{}", i)))
+                .unwrap();
+            sim.run_for(Duration::from_millis(*pause_ms));
+        }
+        prop_assert!(sim.run_until_converged(room.conv_id, Duration::from_secs(60)));
+        for peer in &sim.peers {
+            prop_assert_eq!(validate_graph(&peer.store, &room.conv_id), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ControlAction, ConversationId, HistoryVisibility, InviteAction, LogicalIdentityPk,
+    NodeHash, NodeLookup, PhysicalDevicePk,
+};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::sync::{FetchBatchReq, NodeStore, SyncHeads, SyncRange, Tier};
+use merkle_tox_core::testing::{TestIdentity, TestPeer, TestRoom};
+use std::sync::Arc;
+use std::time::Instant;
+use tox_reconcile::{IbltSketch, SyncSketch};
+
+fn visibility(visibility: HistoryVisibility, history_access: Vec<LogicalIdentityPk>) -> Content {
+    Content::Control(ControlAction::SetHistoryVisibility {
+        visibility,
+        history_access,
+    })
+}
+
+/// Hashes `p` serves to `requester` out of `hashes`.
+fn served(
+    p: &mut TestPeer,
+    cid: ConversationId,
+    requester: PhysicalDevicePk,
+    hashes: &[NodeHash],
+) -> Vec<NodeHash> {
+    p.engine.start_sync(cid, Some(requester), &p.store);
+    let effects = p
+        .engine
+        .handle_message(
+            requester,
+            ProtocolMessage::FetchBatchReq(FetchBatchReq {
+                conversation_id: cid,
+                hashes: hashes.to_vec(),
+            }),
+            &p.store,
+            None,
+        )
+        .unwrap();
+    effects
+        .into_iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(to, ProtocolMessage::MerkleNode { hash, .. }) if to == requester => {
+                Some(hash)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_since_join_withholds_earlier_messages() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let newcomer = TestIdentity::new();
+    let newcomer_device = newcomer.master_pk.to_physical();
+
+    let old = a.author_text(cid, "before");
+    let setting = a.author_hash(cid, visibility(HistoryVisibility::SinceJoin, vec![]));
+    assert_eq!(
+        a.engine.history_visibility(&cid),
+        HistoryVisibility::SinceJoin
+    );
+    let invite = a.author_hash(
+        cid,
+        Content::Control(ControlAction::Invite(InviteAction {
+            invitee_pk: newcomer.master_pk,
+            role: 0,
+        })),
+    );
+    let new = a.author_text(cid, "after");
+    let all = [old, setting, invite, new];
+
+    assert!(a.engine.history_floor(cid, &newcomer_device).is_some());
+    assert_eq!(
+        served(&mut a, cid, newcomer_device, &all),
+        vec![setting, invite, new]
+    );
+
+    // Existing members are not restricted.
+    let b = room.identities[1].device_pk;
+    assert_eq!(a.engine.history_floor(cid, &b), None);
+    assert_eq!(served(&mut a, cid, b, &all), all.to_vec());
+
+    // Unknown devices only get admin nodes.
+    let stranger = TestIdentity::new().master_pk.to_physical();
+    assert_eq!(served(&mut a, cid, stranger, &all), vec![setting, invite]);
+}
+
+#[test]
+fn test_history_access_grant() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let newcomer = TestIdentity::new();
+    let newcomer_device = newcomer.master_pk.to_physical();
+
+    let old = a.author_text(cid, "before");
+    a.author_hash(cid, visibility(HistoryVisibility::SinceJoin, vec![]));
+    a.author_hash(
+        cid,
+        Content::Control(ControlAction::Invite(InviteAction {
+            invitee_pk: newcomer.master_pk,
+            role: 0,
+        })),
+    );
+    assert!(served(&mut a, cid, newcomer_device, &[old]).is_empty());
+
+    a.author_hash(
+        cid,
+        visibility(HistoryVisibility::SinceJoin, vec![newcomer.master_pk]),
+    );
+    assert_eq!(a.engine.history_floor(cid, &newcomer_device), None);
+    assert_eq!(served(&mut a, cid, newcomer_device, &[old]), vec![old]);
+
+    a.author_hash(cid, visibility(HistoryVisibility::Shared, vec![]));
+    assert_eq!(a.engine.history_visibility(&cid), HistoryVisibility::Shared);
+    assert_eq!(served(&mut a, cid, newcomer_device, &[old]), vec![old]);
+}
+
+#[test]
+fn test_push_history_withholds_earlier_messages() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let newcomer = TestIdentity::new();
+    let newcomer_device = newcomer.master_pk.to_physical();
+
+    let old = a.author_text(cid, "before");
+    let setting = a.author_hash(cid, visibility(HistoryVisibility::SinceJoin, vec![]));
+    let invite = a.author_hash(
+        cid,
+        Content::Control(ControlAction::Invite(InviteAction {
+            invitee_pk: newcomer.master_pk,
+            role: 0,
+        })),
+    );
+    let new = a.author_text(cid, "after");
+
+    let effects = a.engine.push_history(newcomer_device, cid, 10, &a.store);
+    let pushed: Vec<NodeHash> = effects
+        .into_iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(to, ProtocolMessage::MerkleNode { hash, .. })
+                if to == newcomer_device =>
+            {
+                Some(hash)
+            }
+            _ => None,
+        })
+        .collect();
+    assert!(pushed.contains(&setting));
+    assert!(pushed.contains(&invite));
+    assert!(pushed.contains(&new));
+    assert!(!pushed.contains(&old));
+}
+
+/// Nodes in `effects` sent to `to`.
+fn sent_nodes(effects: Vec<Effect>, to: PhysicalDevicePk) -> Vec<NodeHash> {
+    effects
+        .into_iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(pk, ProtocolMessage::MerkleNode { hash, .. }) if pk == to => {
+                Some(hash)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// A SinceJoin conversation that a newcomer joins after `old` was written,
+/// followed by `new`. The newcomer then claims to have the room's first
+/// nodes. Returns the newcomer's device, `old` and `new`.
+fn joined_claiming_old_heads(
+    a: &mut TestPeer,
+    cid: ConversationId,
+) -> (PhysicalDevicePk, NodeHash, NodeHash) {
+    let newcomer = TestIdentity::new();
+    let newcomer_device = newcomer.master_pk.to_physical();
+    let old = a.author_text(cid, "before");
+    a.author_hash(cid, visibility(HistoryVisibility::SinceJoin, vec![]));
+    a.author_hash(
+        cid,
+        Content::Control(ControlAction::Invite(InviteAction {
+            invitee_pk: newcomer.master_pk,
+            role: 0,
+        })),
+    );
+    let new = a.author_text(cid, "after");
+
+    let first = a.store.get_node(&old).unwrap().parents;
+    assert!(!first.is_empty());
+    let effects = a
+        .engine
+        .handle_message(
+            newcomer_device,
+            ProtocolMessage::SyncHeads(SyncHeads {
+                conversation_id: cid,
+                heads: first,
+                flags: 0,
+                anchor_hash: None,
+            }),
+            &a.store,
+            None,
+        )
+        .unwrap();
+    assert!(!sent_nodes(effects, newcomer_device).contains(&old));
+    (newcomer_device, old, new)
+}
+
+#[test]
+fn test_outbox_withholds_earlier_messages() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let (newcomer_device, old, new) = joined_claiming_old_heads(&mut a, cid);
+
+    let pending = a.engine.outbox_pending(&newcomer_device, &cid, &a.store);
+    assert!(pending.contains(&new));
+    assert!(!pending.contains(&old));
+    let pushed = sent_nodes(
+        a.engine.flush_outbox(newcomer_device, &a.store),
+        newcomer_device,
+    );
+    assert!(pushed.contains(&new));
+    assert!(!pushed.contains(&old));
+}
+
+#[test]
+fn test_resend_withholds_earlier_messages() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let (newcomer_device, old, new) = joined_claiming_old_heads(&mut a, cid);
+
+    let effects = a.engine.resend(cid, &[old, new], &a.store);
+    assert_eq!(sent_nodes(effects, newcomer_device), vec![new]);
+}
+
+#[test]
+fn test_sketch_withholds_earlier_messages() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let (newcomer_device, old, new) = joined_claiming_old_heads(&mut a, cid);
+
+    // An empty sketch over the whole range makes every node look missing.
+    // Tiny tables fail to decode this many nodes now and then.
+    let max_rank = a.store.get_rank(&new).unwrap();
+    let effects = a
+        .engine
+        .handle_message(
+            newcomer_device,
+            ProtocolMessage::SyncSketch(SyncSketch {
+                conversation_id: cid,
+                cells: IbltSketch::new(Tier::Small.cell_count()).into_cells(),
+                range: SyncRange {
+                    min_rank: 0,
+                    max_rank,
+                },
+            }),
+            &a.store,
+            None,
+        )
+        .unwrap();
+    let sent = sent_nodes(effects, newcomer_device);
+    assert!(sent.contains(&new));
+    assert!(!sent.contains(&old));
+}
@@ -0,0 +1,163 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, ControlAction, MerkleNode, Permissions, PhysicalDeviceSk};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::identity::IdentityError;
+use merkle_tox_core::invite::{InviteProof, InviteToken};
+use merkle_tox_core::testing::{
+    InMemoryStore, TestIdentity, TestRoom, apply_effects, get_all_nodes_from_effects,
+};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+const NOW_MS: i64 = 1_000_000;
+const EXPIRES_AT: i64 = NOW_MS + 60_000;
+
+fn new_engine(id: &TestIdentity, seed: u64) -> MerkleToxEngine {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), NOW_MS));
+    MerkleToxEngine::with_sk(
+        id.device_pk,
+        id.master_pk,
+        PhysicalDeviceSk::from(id.device_sk.to_bytes()),
+        StdRng::seed_from_u64(seed),
+        tp,
+    )
+}
+
+/// Alice, an admin of a two-person room, issues an invite. Returns her
+/// store with the invite applied.
+fn alice_invite() -> (MerkleToxEngine, InMemoryStore, TestRoom, InviteToken) {
+    let room = TestRoom::new(2);
+    let alice = &room.identities[0];
+    let store = InMemoryStore::new();
+    let mut engine = new_engine(alice, 1);
+    room.setup_engine(&mut engine, &store);
+
+    let cert = alice.make_device_cert_for(Permissions::ALL, i64::MAX, room.conv_id);
+    let (token, effects) = engine
+        .create_invite(cert, vec![alice.device_pk], 0, EXPIRES_AT)
+        .unwrap();
+    apply_effects(effects, &store);
+    (engine, store, room, token)
+}
+
+fn announcement(effects: &[merkle_tox_core::engine::Effect]) -> MerkleNode {
+    get_all_nodes_from_effects(effects)
+        .into_iter()
+        .find(|n| {
+            matches!(
+                n.content,
+                Content::Control(ControlAction::Announcement { .. })
+            )
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_invite_link_roundtrip() {
+    let (_, _, room, token) = alice_invite();
+    let link = token.to_link();
+    assert!(link.starts_with("mtox-invite:"));
+
+    let parsed = InviteToken::from_link(&link).unwrap();
+    assert_eq!(parsed, token);
+    assert_eq!(parsed.conversation_id, room.conv_id);
+    assert_eq!(parsed.inviter_pk, room.identities[0].master_pk);
+    assert_eq!(parsed.bootstrap_devices, vec![room.identities[0].device_pk]);
+    parsed.verify(NOW_MS).unwrap();
+
+    assert!(InviteToken::from_link("tox:1234").is_err());
+    assert!(InviteToken::from_link("mtox-invite:!!!").is_err());
+}
+
+#[test]
+fn test_invite_verify_rejects_tampering_and_expiry() {
+    let (_, _, room, token) = alice_invite();
+
+    assert!(matches!(
+        token.verify(EXPIRES_AT + 1),
+        Err(IdentityError::Expired(..))
+    ));
+
+    let mut promoted = token.clone();
+    promoted.role = 1;
+    assert!(matches!(
+        promoted.verify(NOW_MS),
+        Err(IdentityError::InvalidSignature)
+    ));
+
+    // A device without ADMIN permission cannot invite.
+    let bob = &room.identities[1];
+    let mut engine = new_engine(bob, 2);
+    let cert = bob.make_device_cert_for(Permissions::MESSAGE, i64::MAX, room.conv_id);
+    let (token, _) = engine
+        .create_invite(cert, vec![bob.device_pk], 0, EXPIRES_AT)
+        .unwrap();
+    assert!(matches!(
+        token.verify(NOW_MS),
+        Err(IdentityError::PermissionEscalation)
+    ));
+
+    // The certificate must belong to the issuing device.
+    let cert = room.identities[0].make_device_cert_for(Permissions::ALL, i64::MAX, room.conv_id);
+    assert!(engine.create_invite(cert, vec![], 0, EXPIRES_AT).is_err());
+}
+
+#[test]
+fn test_invite_proof_admits_joiner() {
+    let (mut alice_engine, _, room, token) = alice_invite();
+
+    let carol = TestIdentity::new();
+    let store = InMemoryStore::new();
+    let mut carol_engine = new_engine(&carol, 3);
+    let effects = carol_engine.accept_invite(&token, &store).unwrap();
+    let node = announcement(&effects);
+    assert_eq!(
+        InviteProof::from_metadata(&node.metadata),
+        Some(token.proof(&carol.device_pk))
+    );
+
+    assert_eq!(
+        alice_engine.check_invite_proof(room.conv_id, &node),
+        Some(0)
+    );
+
+    // The proof is bound to the announcing device.
+    let mut replayed = node.clone();
+    replayed.sender_pk = room.identities[1].device_pk;
+    assert_eq!(
+        alice_engine.check_invite_proof(room.conv_id, &replayed),
+        None
+    );
+
+    // Nodes without a proof are ignored.
+    let mut plain = node.clone();
+    plain.metadata = vec![];
+    assert_eq!(alice_engine.check_invite_proof(room.conv_id, &plain), None);
+
+    assert!(!alice_engine.revoke_invite(&token.id()).is_empty());
+    assert!(alice_engine.revoke_invite(&token.id()).is_empty());
+    assert_eq!(alice_engine.check_invite_proof(room.conv_id, &node), None);
+}
+
+#[test]
+fn test_invite_survives_restart() {
+    let (_, store, room, token) = alice_invite();
+
+    let carol = TestIdentity::new();
+    let mut carol_engine = new_engine(&carol, 3);
+    let effects = carol_engine
+        .accept_invite(&token, &InMemoryStore::new())
+        .unwrap();
+    let node = announcement(&effects);
+
+    let mut restarted = new_engine(&room.identities[0], 4);
+    assert_eq!(restarted.load_invite_grants(&store), 1);
+    assert_eq!(restarted.check_invite_proof(room.conv_id, &node), Some(0));
+
+    let effects = restarted.revoke_invite(&token.id());
+    apply_effects(effects, &store);
+    let mut restarted = new_engine(&room.identities[0], 5);
+    assert_eq!(restarted.load_invite_grants(&store), 0);
+    assert_eq!(restarted.check_invite_proof(room.conv_id, &node), None);
+}
@@ -0,0 +1,118 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ControlAction, ConversationId, MerkleNode, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::engine::conversation::{join_pow_difficulty, join_pow_flags};
+use merkle_tox_core::invite::InviteProof;
+use merkle_tox_core::join_pow::JoinProof;
+use merkle_tox_core::testing::{InMemoryStore, TestIdentity, TestRoom, get_all_nodes_from_effects};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+const DIFFICULTY: u8 = 8;
+
+fn new_engine(id: &TestIdentity, seed: u64) -> MerkleToxEngine {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1_000_000));
+    MerkleToxEngine::with_sk(
+        id.device_pk,
+        id.master_pk,
+        PhysicalDeviceSk::from(id.device_sk.to_bytes()),
+        StdRng::seed_from_u64(seed),
+        tp,
+    )
+}
+
+/// Alice, an admin of a room requiring a join proof-of-work.
+fn open_room(flags: u64) -> (MerkleToxEngine, TestRoom) {
+    let room = TestRoom::new(2);
+    let store = InMemoryStore::new();
+    let mut engine = new_engine(&room.identities[0], 1);
+    room.setup_engine(&mut engine, &store);
+    engine
+        .conversations
+        .get_mut(&room.conv_id)
+        .unwrap()
+        .set_genesis_flags(flags);
+    (engine, room)
+}
+
+/// Carol's Announcement after joining by proof-of-work.
+fn join(room: &TestRoom, difficulty: u32) -> MerkleNode {
+    let carol = TestIdentity::new();
+    let store = InMemoryStore::new();
+    let mut engine = new_engine(&carol, 3);
+    let effects = engine
+        .join_with_pow(
+            room.conv_id,
+            &[room.identities[0].device_pk],
+            difficulty,
+            &store,
+        )
+        .unwrap();
+    get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| {
+            matches!(
+                n.content,
+                Content::Control(ControlAction::Announcement { .. })
+            )
+        })
+        .unwrap()
+}
+
+#[test]
+fn test_join_pow_flags_roundtrip() {
+    let flags = join_pow_flags(DIFFICULTY) | 0x02;
+    assert_eq!(join_pow_difficulty(flags), DIFFICULTY as u32);
+    assert_eq!(join_pow_difficulty(0x03), 0);
+}
+
+#[test]
+fn test_join_proof_is_bound_to_device() {
+    let cid = ConversationId::from([0xAAu8; 32]);
+    let device = PhysicalDevicePk::from([1u8; 32]);
+    let proof = JoinProof::solve(&cid, &device, 12);
+    assert!(proof.verify(&cid, &device, 12));
+    assert!(!proof.verify(&cid, &PhysicalDevicePk::from([2u8; 32]), 12));
+    assert!(!proof.verify(&ConversationId::from([0xBBu8; 32]), &device, 12));
+    assert_eq!(JoinProof::from_metadata(&proof.to_metadata()), Some(proof));
+}
+
+#[test]
+fn test_join_proof_admits_joiner() {
+    let (alice_engine, room) = open_room(join_pow_flags(DIFFICULTY));
+    let node = join(&room, DIFFICULTY as u32);
+    assert!(JoinProof::from_metadata(&node.metadata).is_some());
+    assert!(alice_engine.check_join_proof(room.conv_id, &node));
+
+    // The proof is bound to the announcing device.
+    let mut replayed = node.clone();
+    replayed.sender_pk = room.identities[1].device_pk;
+    assert!(!alice_engine.check_join_proof(room.conv_id, &replayed));
+
+    // Nodes without a proof, or with an invite proof, are ignored.
+    let mut plain = node.clone();
+    plain.metadata = vec![];
+    assert!(!alice_engine.check_join_proof(room.conv_id, &plain));
+    let mut invited = node.clone();
+    invited.metadata = InviteProof {
+        invite_id: [1u8; 32],
+        mac: [2u8; 32],
+    }
+    .to_metadata();
+    assert!(!alice_engine.check_join_proof(room.conv_id, &invited));
+}
+
+#[test]
+fn test_join_proof_requires_conversation_difficulty() {
+    // Conversations without a join difficulty only admit invited devices.
+    let (alice_engine, room) = open_room(0);
+    let node = join(&room, DIFFICULTY as u32);
+    assert!(!alice_engine.check_join_proof(room.conv_id, &node));
+
+    let (alice_engine, room) = open_room(join_pow_flags(24));
+    let node = join(&room, DIFFICULTY as u32);
+    assert!(!alice_engine.check_join_proof(room.conv_id, &node));
+}
@@ -0,0 +1,218 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, MerkleNode, PhysicalDevicePk, PhysicalDeviceSk, WireNode};
+use merkle_tox_core::engine::vault::{KeyVault, open_vault, seal_vault};
+use merkle_tox_core::engine::{Conversation, Effect, MerkleToxEngine};
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, TestIdentity, TestRoom, apply_effects};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+const PASSPHRASE: &[u8] = b"correct horse battery staple";
+
+/// Alice authors a few messages. Returns her engine, the room and the
+/// authored nodes together with their wire form.
+fn alice_history() -> (MerkleToxEngine, TestRoom, Vec<(MerkleNode, WireNode)>) {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1_000_000));
+    let room = TestRoom::new(2);
+    let alice = &room.identities[0];
+    let store = InMemoryStore::new();
+    let mut engine = MerkleToxEngine::with_sk(
+        alice.device_pk,
+        alice.master_pk,
+        PhysicalDeviceSk::from(alice.device_sk.to_bytes()),
+        StdRng::seed_from_u64(1),
+        tp,
+    );
+    room.setup_engine(&mut engine, &store);
+    // Seal the history under a random SenderKey rather than the one derived
+    // from the conversation key.
+    let effects = engine.sender_rekey(room.conv_id, &store).unwrap();
+    apply_effects(effects, &store);
+
+    let mut history = Vec::new();
+    for i in 0..3 {
+        let effects = engine
+            .author_node(
+                room.conv_id,
+                Content::Text(format!("secret {}", i)),
+                vec![],
+                &store,
+            )
+            .unwrap();
+        let node = merkle_tox_core::testing::get_all_nodes_from_effects(&effects)
+            .pop()
+            .unwrap();
+        let wire = effects
+            .iter()
+            .rev()
+            .find_map(|e| match e {
+                Effect::WriteWireNode(_, hash, wire) if *hash == node.hash() => Some(wire.clone()),
+                _ => None,
+            })
+            .unwrap();
+        apply_effects(effects, &store);
+        history.push((node, wire));
+    }
+    (engine, room, history)
+}
+
+fn decrypt_all(
+    engine: &MerkleToxEngine,
+    room: &TestRoom,
+    history: &[(MerkleNode, WireNode)],
+) -> Vec<Option<MerkleNode>> {
+    let alice = &room.identities[0];
+    let Some(Conversation::Established(em)) = engine.conversations.get(&room.conv_id) else {
+        return vec![None; history.len()];
+    };
+    history
+        .iter()
+        .map(|(_, wire)| em.identify_sender_and_unpack(wire, &[(alice.device_pk, alice.master_pk)]))
+        .collect()
+}
+
+#[test]
+fn test_key_vault_roundtrip() {
+    let (mut engine, room, _) = alice_history();
+    let alice = &room.identities[0];
+    let blob = engine.export_key_vault(PASSPHRASE).unwrap();
+
+    let vault = open_vault(&blob, PASSPHRASE).unwrap();
+    assert_eq!(vault.device_pk, alice.device_pk);
+    assert_eq!(vault.conversations.len(), 1);
+    let conv = &vault.conversations[0];
+    assert_eq!(conv.conversation_id, room.conv_id);
+    assert!(conv.epochs.iter().any(|(epoch, _)| *epoch == 0));
+    assert!(
+        conv.sender_keys
+            .iter()
+            .any(|(pk, epoch, _)| *pk == alice.device_pk && *epoch == conv.current_epoch)
+    );
+    assert!(conv.ratchets.iter().any(|r| r.sender_pk == alice.device_pk));
+
+    // A second export uses a fresh salt and nonce.
+    assert_ne!(engine.export_key_vault(PASSPHRASE).unwrap(), blob);
+}
+
+#[test]
+fn test_key_vault_key_derivation_is_pinned() {
+    // Vaults are long-lived backups: the same inputs must always produce the
+    // same blob, whatever the argon2 crate's defaults are.
+    let vault = KeyVault {
+        device_pk: PhysicalDevicePk::from([1; 32]),
+        created_at_ms: 0,
+        conversations: Vec::new(),
+    };
+    let blob = seal_vault(&vault, PASSPHRASE, [2; 16], [3; 24]).unwrap();
+    assert_eq!(
+        hex::encode(&blob),
+        concat!(
+            "4d54584b01",
+            "02020202020202020202020202020202",
+            "030303030303030303030303030303030303030303030303",
+            "87e23c1c6bd3f055dd4cd095b6935398d896ef18476d47659148f2dad1a1cefc",
+            "80b24edd75a153e5b7be9fbcb999f747793b11420663",
+        )
+    );
+    assert_eq!(open_vault(&blob, PASSPHRASE).unwrap(), vault);
+}
+
+#[test]
+fn test_key_vault_rejects_wrong_passphrase_and_tampering() {
+    let (mut engine, _, _) = alice_history();
+    let blob = engine.export_key_vault(PASSPHRASE).unwrap();
+
+    assert!(matches!(
+        open_vault(&blob, b"wrong passphrase"),
+        Err(MerkleToxError::Crypto(_))
+    ));
+    let mut tampered = blob.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        open_vault(&tampered, PASSPHRASE),
+        Err(MerkleToxError::Crypto(_))
+    ));
+    assert!(matches!(
+        open_vault(b"MTXK", PASSPHRASE),
+        Err(MerkleToxError::Crypto(_))
+    ));
+}
+
+#[test]
+fn test_restore_from_vault_decrypts_history_on_new_device() {
+    let (mut engine, room, history) = alice_history();
+    let alice = &room.identities[0];
+    let blob = engine.export_key_vault(PASSPHRASE).unwrap();
+
+    // The replacement device only knows the conversation key, which is not
+    // enough to read messages sealed under Alice's random sender key.
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 2_000_000));
+    let new_device = TestIdentity::new();
+    let store = InMemoryStore::new();
+    let mut restored = MerkleToxEngine::with_sk(
+        new_device.device_pk,
+        alice.master_pk,
+        PhysicalDeviceSk::from(new_device.device_sk.to_bytes()),
+        StdRng::seed_from_u64(2),
+        tp,
+    );
+    room.setup_engine(&mut restored, &store);
+    restored
+        .load_conversation_state(room.conv_id, &store)
+        .unwrap();
+    assert!(
+        decrypt_all(&restored, &room, &history)
+            .iter()
+            .all(Option::is_none)
+    );
+
+    assert!(restored.restore_from_vault(&blob, b"wrong").is_err());
+    let effects = restored.restore_from_vault(&blob, PASSPHRASE).unwrap();
+    assert!(
+        effects
+            .iter()
+            .any(|e| matches!(e, Effect::WriteRatchetKey(cid, ..) if *cid == room.conv_id))
+    );
+    apply_effects(effects, &store);
+
+    let decrypted = decrypt_all(&restored, &room, &history);
+    for ((node, _), restored_node) in history.iter().zip(decrypted) {
+        let restored_node = restored_node.expect("history must decrypt after restore");
+        assert_eq!(restored_node.content, node.content);
+        assert_eq!(restored_node.hash(), node.hash());
+    }
+    assert!(
+        !store
+            .get_conversation_keys(&room.conv_id)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_restore_from_vault_into_empty_engine() {
+    let (mut engine, room, history) = alice_history();
+    let alice = &room.identities[0];
+    let blob = engine.export_key_vault(PASSPHRASE).unwrap();
+
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut restored = MerkleToxEngine::new(
+        TestIdentity::new().device_pk,
+        alice.master_pk,
+        StdRng::seed_from_u64(3),
+        tp,
+    );
+    let effects = restored.restore_from_vault(&blob, PASSPHRASE).unwrap();
+    assert!(
+        effects
+            .iter()
+            .any(|e| matches!(e, Effect::WriteConversationKey(cid, 0, _) if *cid == room.conv_id))
+    );
+    assert!(
+        decrypt_all(&restored, &room, &history)
+            .iter()
+            .all(Option::is_some)
+    );
+}
@@ -0,0 +1,146 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Ed25519Signature, MerkleNode, NodeAuth, PhysicalDeviceSk};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::engine::config::VerificationLevel;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{
+    InMemoryStore, TestRoom, apply_effects, create_msg, is_verified_in_effects,
+};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Bob's engine in a two-person room, verifying at `level`.
+fn bob_engine(room: &TestRoom, level: VerificationLevel) -> (MerkleToxEngine, InMemoryStore) {
+    let bob = &room.identities[1];
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1_000_000));
+    let store = InMemoryStore::new();
+    let mut engine = MerkleToxEngine::with_sk(
+        bob.device_pk,
+        bob.master_pk,
+        PhysicalDeviceSk::from(bob.device_sk.to_bytes()),
+        StdRng::seed_from_u64(0),
+        tp,
+    );
+    engine.config.verification_level = level;
+    room.setup_engine(&mut engine, &store);
+    (engine, store)
+}
+
+/// A message from Alice, optionally with a forged signature.
+fn alice_msg(room: &TestRoom, store: &InMemoryStore, seq: u64, forged: bool) -> MerkleNode {
+    let mut node = create_msg(
+        &room.conv_id,
+        &room.keys,
+        &room.identities[0],
+        store.get_admin_heads(&room.conv_id),
+        &format!("message {}", seq),
+        2,
+        seq,
+        1_000_000,
+    );
+    if forged {
+        node.authentication = NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64]));
+    }
+    node
+}
+
+fn accepts(
+    engine: &mut MerkleToxEngine,
+    store: &InMemoryStore,
+    room: &TestRoom,
+    node: MerkleNode,
+) -> bool {
+    let effects = engine.handle_node(room.conv_id, node, store, None).unwrap();
+    let verified = is_verified_in_effects(&effects);
+    apply_effects(effects, store);
+    verified
+}
+
+#[test]
+fn test_full_verification_rejects_forged_content() {
+    let room = TestRoom::new(2);
+    let (mut engine, store) = bob_engine(&room, VerificationLevel::Full);
+    assert!(accepts(
+        &mut engine,
+        &store,
+        &room,
+        alice_msg(&room, &store, 1, false)
+    ));
+    assert!(!accepts(
+        &mut engine,
+        &store,
+        &room,
+        alice_msg(&room, &store, 2, true)
+    ));
+    // Failures are only tracked in light mode.
+    assert!(engine.spot_check_failures.is_empty());
+}
+
+#[test]
+fn test_light_verification_skips_unsampled_content() {
+    let room = TestRoom::new(2);
+    let (mut engine, store) = bob_engine(
+        &room,
+        VerificationLevel::Light {
+            spot_check_interval: u32::MAX,
+        },
+    );
+    // Practically never sampled: both are stored, but only speculatively,
+    // since an unchecked signature proves nothing about the author.
+    for (seq, forged) in [(1, false), (2, true)] {
+        let node = alice_msg(&room, &store, seq, forged);
+        let hash = node.hash();
+        assert!(!accepts(&mut engine, &store, &room, node));
+        assert!(store.has_node(&hash));
+        assert!(!store.is_verified(&hash));
+        assert!(!store.get_heads(&room.conv_id).contains(&hash));
+    }
+    assert!(engine.spot_check_failures.is_empty());
+    assert_eq!(engine.unchecked_nodes[&room.conv_id].len(), 2);
+
+    // Verifying an admin node re-checks speculative nodes, but not these.
+    let effects = engine.reverify_speculative_for_conversation(room.conv_id, &store);
+    assert!(!is_verified_in_effects(&effects));
+}
+
+#[test]
+fn test_failed_spot_check_restores_full_verification() {
+    let room = TestRoom::new(2);
+    let alice = &room.identities[0];
+    // Every node is sampled.
+    let (mut engine, store) = bob_engine(
+        &room,
+        VerificationLevel::Light {
+            spot_check_interval: 1,
+        },
+    );
+    assert!(!accepts(
+        &mut engine,
+        &store,
+        &room,
+        alice_msg(&room, &store, 1, true)
+    ));
+    assert!(
+        engine
+            .spot_check_failures
+            .contains(&(room.conv_id, alice.device_pk))
+    );
+
+    // Sampling no longer applies to Alice's device.
+    engine.config.verification_level = VerificationLevel::Light {
+        spot_check_interval: u32::MAX,
+    };
+    assert!(!accepts(
+        &mut engine,
+        &store,
+        &room,
+        alice_msg(&room, &store, 2, true)
+    ));
+    assert!(accepts(
+        &mut engine,
+        &store,
+        &room,
+        alice_msg(&room, &store, 3, false)
+    ));
+}
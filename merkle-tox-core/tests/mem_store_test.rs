@@ -0,0 +1,439 @@
+use merkle_tox_core::cas::{BlobData, BlobStatus};
+use merkle_tox_core::dag::{
+    ChainKey, Content, ControlAction, ConversationId, KConv, NodeHash, NodeLookup, NodeType,
+    PhysicalDevicePk, WireFlags, WireNode,
+};
+use merkle_tox_core::engine::archive::ArchivedConversation;
+use merkle_tox_core::engine::outbox::AckedHeads;
+use merkle_tox_core::engine::quarantine::BlacklistEntry;
+use merkle_tox_core::engine::scheduled::ScheduledMessage;
+use merkle_tox_core::suspend::SuspendedState;
+use merkle_tox_core::sync::{
+    BlobStore, GlobalStore, NodeStore, ReconciliationStore, StateSnapshot, StoreWrite, SyncRange,
+};
+use merkle_tox_core::testing::{MemStore, create_blob_info, create_dummy_node};
+
+#[test]
+fn test_mem_store_scopes_by_conversation() {
+    let store = MemStore::new();
+    let conv_a = ConversationId::from([1u8; 32]);
+    let conv_b = ConversationId::from([2u8; 32]);
+
+    let mut node_a = create_dummy_node(vec![]);
+    node_a.sequence_number = 7;
+    let hash_a = node_a.hash();
+    store.put_node(&conv_a, node_a, true).unwrap();
+
+    let mut node_b = create_dummy_node(vec![]);
+    node_b.content = Content::Text("other".to_string());
+    node_b.sequence_number = 3;
+    store.put_node(&conv_b, node_b, false).unwrap();
+
+    let sender = PhysicalDevicePk::from([0u8; 32]);
+    assert_eq!(store.get_last_sequence_number(&conv_a, &sender), 7);
+    assert_eq!(store.get_last_sequence_number(&conv_b, &sender), 3);
+    assert_eq!(store.get_node_counts(&conv_a), (1, 0));
+    assert_eq!(store.get_node_counts(&conv_b), (0, 1));
+    assert!(store.get_speculative_nodes(&conv_a).is_empty());
+    assert_eq!(store.get_speculative_nodes(&conv_b).len(), 1);
+
+    let range = SyncRange {
+        min_rank: 0,
+        max_rank: u64::MAX,
+    };
+    assert_eq!(
+        store.get_node_hashes_in_range(&conv_a, &range).unwrap(),
+        vec![hash_a]
+    );
+    assert!(
+        store
+            .get_node_hashes_in_range(&conv_b, &range)
+            .unwrap()
+            .is_empty()
+    );
+
+    store.set_heads(&conv_a, vec![hash_a]).unwrap();
+    assert_eq!(store.get_heads(&conv_a), vec![hash_a]);
+    assert!(store.get_heads(&conv_b).is_empty());
+}
+
+#[test]
+fn test_mem_store_admin_distance_and_children() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let orphan = create_dummy_node(vec![NodeHash::from([9u8; 32])]);
+    let orphan_hash = orphan.hash();
+    store.put_node(&conv_id, orphan, true).unwrap();
+    assert_eq!(store.get_admin_distance(&orphan_hash), Some(u64::MAX));
+
+    let mut root = create_dummy_node(vec![]);
+    root.content = Content::Control(ControlAction::RevokeDevice {
+        target_device_pk: PhysicalDevicePk::from([3u8; 32]),
+        reason: "test".to_string(),
+    });
+    let root_hash = root.hash();
+    store.put_node(&conv_id, root, true).unwrap();
+    assert_eq!(store.get_admin_distance(&root_hash), Some(0));
+    assert!(!store.has_children(&root_hash));
+
+    let mut child = create_dummy_node(vec![root_hash]);
+    child.topological_rank = 1;
+    let child_hash = child.hash();
+    store.put_node(&conv_id, child, true).unwrap();
+
+    assert!(store.has_children(&root_hash));
+    assert_eq!(store.get_rank(&child_hash), Some(1));
+    assert_eq!(store.get_node_type(&child_hash), Some(NodeType::Content));
+    assert_eq!(store.get_admin_distance(&child_hash), Some(1));
+}
+
+#[test]
+fn test_mem_store_opaque_nodes() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let node = create_dummy_node(vec![]);
+    let hash = node.hash();
+    let wire = WireNode {
+        parents: vec![],
+        sender_hint: [0u8; 4],
+        encrypted_routing: vec![],
+        payload_data: vec![1, 2, 3],
+        topological_rank: 0,
+        flags: WireFlags::empty(),
+        authentication: node.authentication.clone(),
+    };
+
+    store.put_wire_node(&conv_id, &hash, wire.clone()).unwrap();
+    assert_eq!(store.get_opaque_node_hashes(&conv_id).unwrap(), vec![hash]);
+
+    // Once unpacked the node is no longer opaque, but the wire form is kept
+    // for forwarding.
+    store.put_node(&conv_id, node, false).unwrap();
+    assert!(store.get_opaque_node_hashes(&conv_id).unwrap().is_empty());
+    assert_eq!(store.get_wire_node(&hash), Some(wire));
+
+    store.remove_wire_node(&conv_id, &hash).unwrap();
+    assert!(store.get_wire_node(&hash).is_none());
+}
+
+#[test]
+fn test_mem_store_blob_roundtrip_with_proof() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+
+    store
+        .put_blob_info(create_blob_info(hash, data.len() as u64))
+        .unwrap();
+    assert!(!store.has_blob(&hash));
+
+    store.put_chunk(&conv_id, &hash, 0, &data, None).unwrap();
+    assert!(store.has_blob(&hash));
+
+    let info = store.get_blob_info(&hash).unwrap();
+    assert_eq!(info.status, BlobStatus::Available);
+    let bao_root = info.bao_root.expect("bao root computed on completion");
+
+    let (chunk, proof) = store.get_chunk_with_proof(&hash, 65536, 1024).unwrap();
+    assert_eq!(chunk, &data[65536..65536 + 1024]);
+    let blob_data = BlobData {
+        hash,
+        offset: 65536,
+        data: chunk,
+        proof,
+    };
+    assert!(blob_data.verify(&bao_root));
+
+    assert!(store.get_chunk(&hash, data.len() as u64, 1).is_err());
+}
+
+#[test]
+fn test_mem_store_keys_sketches_and_global_state() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    store
+        .put_conversation_key(&conv_id, 2, KConv::from([2u8; 32]))
+        .unwrap();
+    store
+        .put_conversation_key(&conv_id, 1, KConv::from([1u8; 32]))
+        .unwrap();
+    let epochs: Vec<u64> = store
+        .get_conversation_keys(&conv_id)
+        .unwrap()
+        .into_iter()
+        .map(|(e, _)| e)
+        .collect();
+    assert_eq!(epochs, vec![1, 2]);
+
+    store.update_epoch_metadata(&conv_id, 5, 1234).unwrap();
+    assert_eq!(store.get_epoch_metadata(&conv_id).unwrap(), Some((5, 1234)));
+
+    let range = SyncRange {
+        min_rank: 0,
+        max_rank: 100,
+    };
+    store.put_sketch(&conv_id, &range, &[1, 2, 3]).unwrap();
+    assert_eq!(
+        store.get_sketch(&conv_id, &range).unwrap(),
+        Some(vec![1, 2, 3])
+    );
+
+    assert_eq!(store.get_global_offset(), None);
+    store.set_global_offset(-42).unwrap();
+    assert_eq!(store.get_global_offset(), Some(-42));
+}
+
+#[test]
+fn test_mem_store_blob_refs() {
+    let store = MemStore::new();
+    let conv_a = ConversationId::from([1u8; 32]);
+    let conv_b = ConversationId::from([2u8; 32]);
+    let hash = NodeHash::from([7u8; 32]);
+    assert_eq!(store.blob_ref_count(&hash), 0);
+
+    store.add_blob_ref(&conv_a, &hash).unwrap();
+    store.add_blob_ref(&conv_a, &hash).unwrap();
+    assert_eq!(store.blob_ref_count(&hash), 1);
+
+    store.add_blob_ref(&conv_b, &hash).unwrap();
+    assert_eq!(store.blob_ref_count(&hash), 2);
+}
+
+#[test]
+fn test_mem_store_scheduled_messages() {
+    let store = MemStore::new();
+    let scheduled = |id| ScheduledMessage {
+        id,
+        conversation_id: ConversationId::from([1u8; 32]),
+        deliver_at_ms: 1000 * id as i64,
+        content: Content::Text(format!("later {}", id)),
+        metadata: vec![],
+    };
+    store.put_scheduled_message(&scheduled(2)).unwrap();
+    store.put_scheduled_message(&scheduled(1)).unwrap();
+    assert_eq!(
+        store.get_scheduled_messages(),
+        vec![scheduled(1), scheduled(2)]
+    );
+
+    store.remove_scheduled_message(1).unwrap();
+    assert_eq!(store.get_scheduled_messages(), vec![scheduled(2)]);
+}
+
+#[test]
+fn test_mem_store_prune_node_keeps_header() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let node = create_dummy_node(vec![]);
+    let hash = node.hash();
+    store.put_node(&conv_id, node.clone(), true).unwrap();
+
+    assert!(
+        !store
+            .prune_node(&conv_id, &NodeHash::from([9u8; 32]))
+            .unwrap()
+    );
+    assert!(store.prune_node(&conv_id, &hash).unwrap());
+    assert!(!store.prune_node(&conv_id, &hash).unwrap());
+
+    assert!(store.has_node(&hash));
+    assert_eq!(store.get_rank(&hash), Some(node.topological_rank));
+    assert!(store.get_node(&hash).is_none());
+    assert!(
+        store
+            .get_verified_nodes_by_type(&conv_id, node.node_type())
+            .unwrap()
+            .is_empty()
+    );
+
+    // Storing the node again does not restore the payload.
+    store.put_node(&conv_id, node, true).unwrap();
+    assert!(store.get_node(&hash).is_none());
+}
+
+#[test]
+fn test_mem_store_history_horizon() {
+    let store = MemStore::new();
+    let conv_a = ConversationId::from([1u8; 32]);
+    let conv_b = ConversationId::from([2u8; 32]);
+    assert_eq!(store.get_history_horizon(&conv_a), None);
+
+    store.set_history_horizon(&conv_a, Some(42)).unwrap();
+    assert_eq!(store.get_history_horizon(&conv_a), Some(42));
+    assert_eq!(store.get_history_horizon(&conv_b), None);
+
+    store.set_history_horizon(&conv_a, None).unwrap();
+    assert_eq!(store.get_history_horizon(&conv_a), None);
+}
+
+#[test]
+fn test_mem_store_acked_heads() {
+    let store = MemStore::new();
+    let acked = |conv: u8, heads: Vec<NodeHash>| AckedHeads {
+        conversation_id: ConversationId::from([conv; 32]),
+        device_pk: PhysicalDevicePk::from([5u8; 32]),
+        heads,
+    };
+    store
+        .put_acked_heads(&acked(1, vec![NodeHash::from([1u8; 32])]))
+        .unwrap();
+    store.put_acked_heads(&acked(2, vec![])).unwrap();
+
+    // A new record replaces the device's previous one in the conversation.
+    let latest = acked(1, vec![NodeHash::from([2u8; 32])]);
+    store.put_acked_heads(&latest).unwrap();
+    let mut all = store.get_acked_heads();
+    all.sort_by_key(|a| a.conversation_id);
+    assert_eq!(all, vec![latest, acked(2, vec![])]);
+}
+
+#[test]
+fn test_mem_store_archived_conversation_roundtrip() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    assert!(store.get_archived_conversation(&conv_id).is_none());
+
+    let archive = |genesis_flags| ArchivedConversation {
+        conversation_id: conv_id,
+        genesis_flags,
+        keys: None,
+    };
+    store.put_archived_conversation(&archive(1)).unwrap();
+    store.put_archived_conversation(&archive(2)).unwrap();
+    assert_eq!(store.get_archived_conversation(&conv_id), Some(archive(2)));
+    assert!(
+        store
+            .get_archived_conversation(&ConversationId::from([2u8; 32]))
+            .is_none()
+    );
+}
+
+#[test]
+fn test_mem_store_write_batch() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let node = create_dummy_node(vec![]);
+    let hash = node.hash();
+    store
+        .put_ratchet_key(
+            &conv_id,
+            &NodeHash::from([3u8; 32]),
+            ChainKey::from([3u8; 32]),
+            0,
+        )
+        .unwrap();
+
+    store
+        .write_batch(vec![
+            StoreWrite::Node(conv_id, node.clone(), true),
+            StoreWrite::RatchetKey(conv_id, hash, ChainKey::from([1u8; 32]), 1),
+            StoreWrite::DeleteRatchetKey(conv_id, NodeHash::from([3u8; 32])),
+            StoreWrite::Heads(conv_id, vec![hash], false),
+            StoreWrite::Heads(conv_id, vec![hash], true),
+        ])
+        .unwrap();
+
+    assert_eq!(store.get_node(&hash), Some(node));
+    assert!(store.is_verified(&hash));
+    assert_eq!(
+        store.get_ratchet_key(&conv_id, &hash).unwrap().unwrap().1,
+        1
+    );
+    assert!(
+        store
+            .get_ratchet_key(&conv_id, &NodeHash::from([3u8; 32]))
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(store.get_heads(&conv_id), vec![hash]);
+    assert_eq!(store.get_admin_heads(&conv_id), vec![hash]);
+}
+
+#[test]
+fn test_mem_store_put_and_get_nodes() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let parent = create_dummy_node(vec![]);
+    let parent_hash = parent.hash();
+    let mut child = create_dummy_node(vec![parent_hash]);
+    child.topological_rank = 1;
+    let child_hash = child.hash();
+
+    store
+        .put_nodes(
+            &conv_id,
+            vec![(parent.clone(), true), (child.clone(), false)],
+        )
+        .unwrap();
+    assert_eq!(store.get_node_counts(&conv_id), (1, 1));
+    assert!(store.has_children(&parent_hash));
+
+    let missing = NodeHash::from([9u8; 32]);
+    assert_eq!(
+        store.get_nodes(&[child_hash, missing, parent_hash]),
+        vec![Some(child), None, Some(parent)]
+    );
+}
+
+#[test]
+fn test_mem_store_blacklist() {
+    let store = MemStore::new();
+    let device = PhysicalDevicePk::from([4u8; 32]);
+    let entry = |tier| BlacklistEntry {
+        device_pk: device,
+        tier,
+        expires_at_ms: 1000 * tier as i64,
+    };
+    store.put_blacklist_entry(&entry(1)).unwrap();
+    store.put_blacklist_entry(&entry(2)).unwrap();
+    assert_eq!(store.get_blacklist(), vec![entry(2)]);
+
+    store.remove_blacklist_entry(&device).unwrap();
+    assert!(store.get_blacklist().is_empty());
+}
+
+#[test]
+fn test_mem_store_state_snapshots() {
+    let store = MemStore::new();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let snapshot = |rank| StateSnapshot {
+        conversation_id: conv_id,
+        rank,
+        verified_nodes: rank,
+        heads: vec![NodeHash::from([rank as u8; 32])],
+        data: vec![rank as u8],
+    };
+    assert!(store.get_state_snapshot(&conv_id).is_none());
+
+    store.put_state_snapshot(&snapshot(3)).unwrap();
+    store.put_state_snapshot(&snapshot(5)).unwrap();
+    assert_eq!(store.get_state_snapshot(&conv_id), Some(snapshot(5)));
+    assert!(
+        store
+            .get_state_snapshot(&ConversationId::from([2u8; 32]))
+            .is_none()
+    );
+}
+
+#[test]
+fn test_mem_store_suspended_state() {
+    let store = MemStore::new();
+    let state = |suspended_at_ms| SuspendedState {
+        suspended_at_ms,
+        sessions: vec![],
+        blob_downloads: vec![],
+        paths: vec![],
+    };
+    assert!(store.get_suspended_state().is_none());
+
+    store.put_suspended_state(&state(1)).unwrap();
+    store.put_suspended_state(&state(2)).unwrap();
+    assert_eq!(store.get_suspended_state(), Some(state(2)));
+
+    store.remove_suspended_state().unwrap();
+    assert!(store.get_suspended_state().is_none());
+}
@@ -0,0 +1,167 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ControlAction, InviteAction, LogicalIdentityPk, NodeHash, ValidationError,
+};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::testing::{TestIdentity, TestRoom, create_msg, get_all_nodes_from_effects};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn joined(effects: &[Effect]) -> Vec<(LogicalIdentityPk, u8)> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::MemberJoined {
+                member_pk, role, ..
+            }) => Some((*member_pk, *role)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn left(effects: &[Effect]) -> Vec<(LogicalIdentityPk, NodeHash)> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::MemberLeft {
+                member_pk, hash, ..
+            }) => Some((*member_pk, *hash)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_invite_and_leave_emit_member_events() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let carol = TestIdentity::new();
+
+    let invite = Content::Control(ControlAction::Invite(InviteAction {
+        invitee_pk: carol.master_pk,
+        role: 0,
+    }));
+    let effects = alice.author(room.conv_id, invite.clone());
+    assert_eq!(joined(&effects), vec![(carol.master_pk, 0)]);
+
+    // Inviting a member again is no join.
+    let effects = alice.author(room.conv_id, invite);
+    assert!(joined(&effects).is_empty());
+
+    let effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::Leave(carol.master_pk)),
+    );
+    let leave = get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| matches!(n.content, Content::Control(ControlAction::Leave(_))))
+        .unwrap();
+    assert_eq!(left(&effects), vec![(carol.master_pk, leave.hash())]);
+
+    // Nor is removing a non-member a departure.
+    let effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::Leave(carol.master_pk)),
+    );
+    assert!(left(&effects).is_empty());
+}
+
+#[test]
+fn test_revoking_last_device_emits_member_left() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let bob = &room.identities[1];
+
+    let effects = alice.author(
+        room.conv_id,
+        Content::Control(ControlAction::RevokeDevice {
+            target_device_pk: bob.device_pk,
+            reason: "lost".to_string(),
+        }),
+    );
+    assert_eq!(
+        left(&effects)
+            .into_iter()
+            .map(|(pk, _)| pk)
+            .collect::<Vec<_>>(),
+        vec![bob.master_pk]
+    );
+}
+
+#[test]
+fn test_closed_conversation_rejects_new_content() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = room.peer(0, &tp);
+    let mut bob = room.peer(1, &tp);
+
+    // Written concurrently with the close.
+    let effects = alice.author(room.conv_id, Content::Text("before".to_string()));
+    let before = get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| matches!(n.content, Content::Text(_)))
+        .unwrap();
+
+    let effects = bob.author(
+        room.conv_id,
+        Content::Control(ControlAction::CloseConversation),
+    );
+    let close = get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| {
+            matches!(
+                n.content,
+                Content::Control(ControlAction::CloseConversation)
+            )
+        })
+        .unwrap();
+    assert!(bob.engine.is_closed(room.conv_id));
+    let result = bob.engine.author_node(
+        room.conv_id,
+        Content::Text("after".to_string()),
+        Vec::new(),
+        &bob.store,
+    );
+    assert!(matches!(
+        result,
+        Err(MerkleToxError::Validation(
+            ValidationError::ConversationClosed
+        ))
+    ));
+
+    let result = bob
+        .engine
+        .handle_node(room.conv_id, before, &bob.store, None);
+    assert!(result.is_ok());
+
+    // Written after it.
+    let after = create_msg(
+        &room.conv_id,
+        &room.keys,
+        &room.identities[0],
+        vec![close.hash()],
+        "after",
+        close.topological_rank + 1,
+        2,
+        close.network_timestamp + 1,
+    );
+    let result = bob
+        .engine
+        .handle_node(room.conv_id, after, &bob.store, None);
+    assert!(matches!(
+        result,
+        Err(MerkleToxError::Validation(
+            ValidationError::ConversationClosed
+        ))
+    ));
+
+    // The close survives a reload.
+    bob.engine
+        .load_conversation_state(room.conv_id, &bob.store)
+        .unwrap();
+    assert!(bob.engine.is_closed(room.conv_id));
+}
@@ -0,0 +1,266 @@
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, KConv, LogicalIdentityPk, MerkleNode, NodeAuth,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::engine::metrics::SyncPhase;
+use merkle_tox_core::engine::priority::SyncPriority;
+use merkle_tox_core::engine::{Conversation, ConversationData, MerkleToxEngine, conversation};
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{HubStats, InMemoryStore, SimulatedTransport, VirtualHub};
+use merkle_tox_core::{MessageDirection, MessageObserver, ProtocolMessage};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn make_node(seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([3u8; 32]),
+        sender_pk: PhysicalDevicePk::from([3u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000,
+        content: Content::Text(format!("message {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+#[test]
+fn test_metrics_snapshot() {
+    let self_pk = PhysicalDevicePk::from([1u8; 32]);
+    let peer_pk = PhysicalDevicePk::from([2u8; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine =
+        MerkleToxEngine::new(self_pk, self_pk.to_logical(), StdRng::seed_from_u64(0), tp);
+    let store = InMemoryStore::new();
+
+    let established = ConversationId::from([0xAAu8; 32]);
+    let pending = ConversationId::from([0xBBu8; 32]);
+    engine.start_sync(established, Some(peer_pk), &store);
+    engine.conversations.insert(
+        established,
+        Conversation::Established(ConversationData::<conversation::Established>::new(
+            established,
+            KConv::from([0xCCu8; 32]),
+            0,
+        )),
+    );
+    engine.conversations.insert(
+        pending,
+        Conversation::Pending(ConversationData::<conversation::Pending>::new(pending)),
+    );
+    store.put_node(&established, make_node(1), true).unwrap();
+    store.put_node(&established, make_node(2), true).unwrap();
+    store.put_node(&established, make_node(3), false).unwrap();
+    engine.set_foreground_conversation(Some(established));
+    engine.record_sent(peer_pk, 100);
+    engine.record_sent(peer_pk, 20);
+    engine.record_received(peer_pk, 7);
+
+    let metrics = engine.metrics(&store);
+
+    assert_eq!(metrics.conversations.len(), 2);
+    let conv = &metrics.conversations[0];
+    assert_eq!(conv.conversation_id, established);
+    assert!(conv.established);
+    assert_eq!(conv.current_epoch, Some(0));
+    assert_eq!((conv.verified_nodes, conv.speculative_nodes), (2, 1));
+    assert_eq!(conv.sync_priority, SyncPriority::Foreground);
+    let conv = &metrics.conversations[1];
+    assert!(!conv.established);
+    assert_eq!(conv.current_epoch, None);
+    assert_eq!(conv.sync_priority, SyncPriority::Archived);
+
+    assert_eq!(metrics.sessions.len(), 1);
+    let session = &metrics.sessions[0];
+    assert_eq!(
+        (session.peer_pk, session.conversation_id),
+        (peer_pk, established)
+    );
+    assert_eq!(session.phase, SyncPhase::Handshake);
+    assert_eq!(session.recon_rounds, 0);
+
+    let traffic = metrics.traffic[&peer_pk];
+    assert_eq!((traffic.messages_sent, traffic.bytes_sent), (2, 120));
+    assert_eq!((traffic.messages_received, traffic.bytes_received), (1, 7));
+    assert_eq!(metrics.clock_offset_ms, 0);
+}
+
+#[test]
+fn test_node_reports_traffic_and_recon_rounds() {
+    let time_provider = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time_provider.clone()));
+    let conv_id = ConversationId::from([0x42u8; 32]);
+    let alice_pk = PhysicalDevicePk::from([1u8; 32]);
+    let bob_pk = PhysicalDevicePk::from([2u8; 32]);
+
+    let mut nodes = Vec::new();
+    for (pk, peer) in [(alice_pk, bob_pk), (bob_pk, alice_pk)] {
+        let engine = MerkleToxEngine::new(
+            pk,
+            pk.to_logical(),
+            StdRng::seed_from_u64(pk.as_bytes()[0] as u64),
+            time_provider.clone(),
+        );
+        let rx = hub.register(pk);
+        let mut node = MerkleToxNode::new(
+            engine,
+            SimulatedTransport::new(pk, hub.clone()),
+            InMemoryStore::new(),
+            time_provider.clone(),
+        );
+        node.store
+            .put_conversation_key(&conv_id, 0, KConv::from([0xAAu8; 32]))
+            .unwrap();
+        node.engine
+            .load_conversation_state(conv_id, &node.store)
+            .unwrap();
+        let effects = node.engine.start_sync(conv_id, Some(peer), &node.store);
+        let now = time_provider.now_instant();
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node.process_effect(effect, now, 0, &mut dummy_wakeup)
+                .unwrap();
+        }
+        nodes.push((node, rx));
+    }
+
+    for _ in 0..20 {
+        for (node, rx) in &mut nodes {
+            node.poll();
+            while let Ok((from, data)) = rx.try_recv() {
+                node.handle_packet(from, &data);
+            }
+        }
+        hub.poll();
+        time_provider.advance(Duration::from_millis(100));
+    }
+
+    let (alice, _) = &nodes[0];
+    let metrics = alice.engine.metrics(&alice.store);
+    let session = &metrics.sessions[0];
+    assert_eq!(session.peer_pk, bob_pk);
+    assert_eq!(session.phase, SyncPhase::Active);
+    assert!(session.recon_rounds >= 1);
+
+    let traffic = metrics.traffic[&bob_pk];
+    assert!(traffic.messages_sent > 0 && traffic.bytes_sent > 0);
+    assert!(traffic.messages_received > 0 && traffic.bytes_received > 0);
+}
+
+#[test]
+fn test_hub_stats_count_traffic_and_drops() {
+    let time_provider = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let hub = VirtualHub::new(time_provider.clone());
+    let alice_pk = PhysicalDevicePk::from([1u8; 32]);
+    let bob_pk = PhysicalDevicePk::from([2u8; 32]);
+    let bob_rx = hub.register(bob_pk);
+
+    hub.route(alice_pk, bob_pk, vec![0u8; 10]);
+    assert_eq!(bob_rx.try_recv().unwrap().1.len(), 10);
+
+    hub.set_blackout(bob_pk, time_provider.now_instant() + Duration::from_secs(1));
+    hub.route(alice_pk, bob_pk, vec![0u8; 5]);
+    assert!(bob_rx.try_recv().is_err());
+
+    assert_eq!(
+        hub.stats(),
+        HubStats {
+            packets_sent: 2,
+            bytes_sent: 15,
+            packets_dropped: 1,
+        }
+    );
+}
+
+#[derive(Default)]
+struct RecordingObserver {
+    seen: Mutex<Vec<(MessageDirection, PhysicalDevicePk, &'static str)>>,
+}
+
+impl MessageObserver for RecordingObserver {
+    fn observe(
+        &self,
+        direction: MessageDirection,
+        peer_pk: PhysicalDevicePk,
+        msg: &ProtocolMessage,
+    ) {
+        self.seen
+            .lock()
+            .unwrap()
+            .push((direction, peer_pk, msg.name()));
+    }
+}
+
+#[test]
+fn test_message_observer_sees_both_directions() {
+    let time_provider = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time_provider.clone()));
+    let conv_id = ConversationId::from([0x42u8; 32]);
+    let alice_pk = PhysicalDevicePk::from([1u8; 32]);
+    let bob_pk = PhysicalDevicePk::from([2u8; 32]);
+    let observer = Arc::new(RecordingObserver::default());
+
+    let mut nodes = Vec::new();
+    for (pk, peer) in [(alice_pk, bob_pk), (bob_pk, alice_pk)] {
+        let engine = MerkleToxEngine::new(
+            pk,
+            pk.to_logical(),
+            StdRng::seed_from_u64(pk.as_bytes()[0] as u64),
+            time_provider.clone(),
+        );
+        let rx = hub.register(pk);
+        let mut node = MerkleToxNode::new(
+            engine,
+            SimulatedTransport::new(pk, hub.clone()),
+            InMemoryStore::new(),
+            time_provider.clone(),
+        );
+        if pk == alice_pk {
+            node.set_message_observer(observer.clone());
+        }
+        node.store
+            .put_conversation_key(&conv_id, 0, KConv::from([0xAAu8; 32]))
+            .unwrap();
+        node.engine
+            .load_conversation_state(conv_id, &node.store)
+            .unwrap();
+        let effects = node.engine.start_sync(conv_id, Some(peer), &node.store);
+        let now = time_provider.now_instant();
+        let mut dummy_wakeup = now;
+        for effect in effects {
+            node.process_effect(effect, now, 0, &mut dummy_wakeup)
+                .unwrap();
+        }
+        nodes.push((node, rx));
+    }
+
+    for _ in 0..20 {
+        for (node, rx) in &mut nodes {
+            node.poll();
+            while let Ok((from, data)) = rx.try_recv() {
+                node.handle_packet(from, &data);
+            }
+        }
+        hub.poll();
+        time_provider.advance(Duration::from_millis(100));
+    }
+
+    let seen = observer.seen.lock().unwrap();
+    assert!(seen.iter().all(|(_, peer, _)| *peer == bob_pk));
+    let sent = seen
+        .iter()
+        .filter(|(d, _, _)| *d == MessageDirection::Sent)
+        .count();
+    let received = seen.len() - sent;
+    assert!(sent > 0 && received > 0);
+
+    // Received messages are exactly the ones the traffic metrics count.
+    let (alice, _) = &nodes[0];
+    let traffic = alice.engine.metrics(&alice.store).traffic[&bob_pk];
+    assert_eq!(received as u64, traffic.messages_received);
+}
@@ -260,6 +260,7 @@ fn test_node_blob_sync() {
                     mime_type: "application/octet-stream".to_string(),
                     size: blob_data.len() as u64,
                     metadata: vec![],
+                    preview: None,
                 },
                 vec![],
                 &node_ref.store,
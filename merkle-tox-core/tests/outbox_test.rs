@@ -0,0 +1,222 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, NodeHash, NodeType, PhysicalDevicePk};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::sync::{NodeStore, SyncHeads};
+use merkle_tox_core::testing::{TestPeer, TestRoom, apply_effects};
+use merkle_tox_core::{NodeEvent, ProtocolMessage};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn setup() -> (TestRoom, Arc<ManualTimeProvider>, TestPeer, TestPeer) {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+
+    a.handshake(&mut b, cid);
+    // B has written since the admin nodes, so its heads cover them.
+    let node = b.author_text(cid, "hi");
+    a.receive_from(&b, cid, node);
+    (room, tp, a, b)
+}
+
+/// `from` announces its heads to `to`. Returns the nodes `to` learns were
+/// delivered to `from`.
+fn announce_heads(from: &TestPeer, to: &mut TestPeer, cid: ConversationId) -> Vec<NodeHash> {
+    let msg = ProtocolMessage::SyncHeads(SyncHeads {
+        conversation_id: cid,
+        heads: from.store.get_heads(&cid),
+        flags: 0,
+        anchor_hash: from.store.get_admin_heads(&cid).first().copied(),
+    });
+    let effects = to
+        .engine
+        .handle_message(from.pk, msg, &to.store, None)
+        .unwrap();
+    let delivered = effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::NodeDelivered {
+                conversation_id,
+                hash,
+                device_pk,
+            }) => {
+                assert_eq!((*conversation_id, *device_pk), (cid, from.pk));
+                Some(*hash)
+            }
+            _ => None,
+        })
+        .collect();
+    apply_effects(effects, &to.store);
+    delivered
+}
+
+fn pushed_nodes(effects: &[Effect], to: PhysicalDevicePk) -> Vec<NodeHash> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(pk, ProtocolMessage::MerkleNode { hash, .. }) if *pk == to => {
+                Some(*hash)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_announced_heads_are_persisted() {
+    let (room, _tp, mut a, b) = setup();
+    let cid = room.conv_id;
+
+    announce_heads(&b, &mut a, cid);
+    let acked = a.store.get_acked_heads();
+    assert_eq!(acked.len(), 1);
+    assert_eq!(acked[0].conversation_id, cid);
+    assert_eq!(acked[0].device_pk, b.pk);
+    let mut heads = b.store.get_heads(&cid);
+    heads.extend(b.store.get_admin_heads(&cid).first());
+    heads.sort_unstable();
+    assert_eq!(acked[0].heads, heads);
+
+    // Announcing the same heads again writes nothing.
+    let msg = ProtocolMessage::SyncHeads(SyncHeads {
+        conversation_id: cid,
+        heads: b.store.get_heads(&cid),
+        flags: 0,
+        anchor_hash: None,
+    });
+    let effects = a.engine.handle_message(b.pk, msg, &a.store, None).unwrap();
+    assert!(
+        !effects
+            .iter()
+            .any(|e| matches!(e, Effect::WriteAckedHeads(_)))
+    );
+}
+
+#[test]
+fn test_acknowledged_nodes_are_delivered() {
+    let (room, _tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    announce_heads(&b, &mut a, cid);
+
+    let written: Vec<NodeHash> = (0..2)
+        .map(|i| a.author_text(cid, &format!("msg {}", i)))
+        .collect();
+    assert!(announce_heads(&b, &mut a, cid).is_empty());
+    let pushed = pushed_nodes(&a.engine.flush_outbox(b.pk, &a.store), b.pk);
+    for hash in &pushed {
+        b.receive_from(&a, cid, *hash);
+    }
+    // B's reply is not reported: A did not send it.
+    let reply = b.author_text(cid, "got them");
+    assert!(announce_heads(&b, &mut a, cid).is_empty());
+    a.receive_from(&b, cid, reply);
+    let delivered = announce_heads(&b, &mut a, cid);
+    assert!(delivered.ends_with(&written));
+    assert!(
+        delivered
+            .iter()
+            .all(|h| pushed.contains(h) && a.store.get_node(h).unwrap().sender_pk == a.pk)
+    );
+    assert!(announce_heads(&b, &mut a, cid).is_empty());
+
+    let acknowledged = a.engine.acknowledged_nodes(&cid, 0, &a.store);
+    assert!(written.iter().all(|h| acknowledged[&b.pk].contains(h)));
+}
+
+#[test]
+fn test_resend_skips_acknowledged_nodes() {
+    let (room, tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    announce_heads(&b, &mut a, cid);
+
+    let first = a.author_text(cid, "first");
+    let pushed = pushed_nodes(&a.engine.flush_outbox(b.pk, &a.store), b.pk);
+    for hash in &pushed {
+        b.receive_from(&a, cid, *hash);
+    }
+    announce_heads(&b, &mut a, cid);
+    let second = a.author_text(cid, "second");
+
+    let resent = pushed_nodes(&a.engine.resend(cid, &[first, second], &a.store), b.pk);
+    assert_eq!(resent, vec![second]);
+
+    // Peers without a session get nothing.
+    let mut lone = room.peer(0, &tp);
+    let node = lone.author_text(cid, "alone");
+    assert!(lone.engine.resend(cid, &[node], &lone.store).is_empty());
+}
+
+#[test]
+fn test_flush_pushes_unacknowledged_nodes() {
+    let (room, _tp, mut a, mut b) = setup();
+    let cid = room.conv_id;
+    announce_heads(&b, &mut a, cid);
+    assert!(a.engine.flush_outbox(b.pk, &a.store).is_empty());
+
+    // B goes offline while A keeps writing.
+    let written: Vec<NodeHash> = (0..3)
+        .map(|i| a.author_text(cid, &format!("msg {}", i)))
+        .collect();
+    let pushed = pushed_nodes(&a.engine.flush_outbox(b.pk, &a.store), b.pk);
+    assert!(written.iter().all(|h| pushed.contains(h)));
+    assert!(pushed.iter().all(|h| !b.store.has_node(h)));
+
+    for hash in &pushed {
+        b.receive_from(&a, cid, *hash);
+    }
+    assert!(b.store.get_heads(&cid).contains(&written[2]));
+
+    // Once B acknowledges them there is nothing left to push.
+    announce_heads(&b, &mut a, cid);
+    assert!(a.engine.flush_outbox(b.pk, &a.store).is_empty());
+}
+
+#[test]
+fn test_outbox_survives_restart() {
+    let (room, tp, mut a, b) = setup();
+    let cid = room.conv_id;
+    announce_heads(&b, &mut a, cid);
+    let node = a.author_text(cid, "while you were away");
+
+    let mut restarted = room.identities[0].engine(0, &tp);
+    assert_eq!(restarted.load_outbox(&a.store), 1);
+    let pushed = pushed_nodes(&restarted.flush_outbox(b.pk, &a.store), b.pk);
+    assert_eq!(pushed.last(), Some(&node));
+
+    // Devices that never acknowledged anything get nothing.
+    let stranger = PhysicalDevicePk::from([9u8; 32]);
+    assert!(restarted.flush_outbox(stranger, &a.store).is_empty());
+}
+
+#[test]
+fn test_push_history_to_new_device() {
+    let (room, _tp, mut a, b) = setup();
+    let cid = room.conv_id;
+    let written: Vec<NodeHash> = (0..5)
+        .map(|i| a.author_text(cid, &format!("msg {}", i)))
+        .collect();
+
+    let effects = a.engine.push_history(b.pk, cid, 3, &a.store);
+    assert!(effects.iter().any(|e| matches!(
+        e,
+        Effect::SendPacket(pk, ProtocolMessage::CapsAnnounce { .. }) if *pk == b.pk
+    )));
+    let pushed = pushed_nodes(&effects, b.pk);
+    let admin: Vec<NodeHash> = a
+        .store
+        .get_verified_nodes_by_type(&cid, NodeType::Admin)
+        .unwrap()
+        .iter()
+        .map(|n| n.hash())
+        .collect();
+    assert!(!admin.is_empty());
+    assert!(admin.iter().all(|h| pushed.contains(h)));
+    assert_eq!(pushed[pushed.len() - 3..], written[2..]);
+    assert!(!pushed.contains(&written[1]));
+
+    // Devices that already acknowledged heads are left to the outbox.
+    announce_heads(&b, &mut a, cid);
+    assert!(a.engine.push_history(b.pk, cid, 3, &a.store).is_empty());
+}
@@ -113,6 +113,68 @@ fn test_node_poll_wakeup() {
     assert!(wakeup <= now + Duration::from_millis(1));
 }
 
+#[test]
+fn test_node_poll_follows_transport_path() {
+    use merkle_tox_core::Transport;
+    use merkle_tox_core::engine::MerkleToxEngine;
+    use merkle_tox_core::node::MerkleToxNode;
+    use rand::{SeedableRng, rngs::StdRng};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tox_sequenced::AlgorithmType;
+
+    struct RelayTransport(Arc<AtomicBool>);
+    impl Transport for RelayTransport {
+        fn local_pk(&self) -> PhysicalDevicePk {
+            PhysicalDevicePk::from([0u8; 32])
+        }
+        fn send_raw(
+            &self,
+            _to: PhysicalDevicePk,
+            _data: Vec<u8>,
+        ) -> Result<(), merkle_tox_core::TransportError> {
+            Ok(())
+        }
+        fn is_relayed(&self, _to: &PhysicalDevicePk) -> bool {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    let now = Instant::now();
+    let tp = Arc::new(merkle_tox_core::clock::ManualTimeProvider::new(now, 0));
+    let self_pk = PhysicalDevicePk::from([0u8; 32]);
+    let peer = PhysicalDevicePk::from([1u8; 32]);
+    let engine = MerkleToxEngine::new(
+        self_pk,
+        self_pk.to_logical(),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let relayed = Arc::new(AtomicBool::new(true));
+    let mut node = MerkleToxNode::new(
+        engine,
+        RelayTransport(relayed.clone()),
+        InMemoryStore::new(),
+        tp,
+    );
+
+    node.send_message(
+        peer,
+        merkle_tox_core::ProtocolMessage::CapsAnnounce {
+            version: 1,
+            features: 0,
+        },
+    );
+    assert!(node.sessions[&peer].path().relayed);
+    assert_eq!(node.sessions[&peer].algo_type(), AlgorithmType::Cubic);
+
+    // The peer got a direct connection.
+    relayed.store(false, Ordering::Relaxed);
+    node.poll();
+    assert!(!node.sessions[&peer].path().relayed);
+    assert_eq!(node.sessions[&peer].algo_type(), AlgorithmType::Aimd);
+}
+
 #[test]
 fn test_next_wakeup_active_session_past() {
     let now = Instant::now();
@@ -0,0 +1,207 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ConversationId, MerkleNode, NodeHash, NodeLookup, ValidationError,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{
+    InMemoryStore, TestRoom, apply_effects, create_signed_content_node, is_verified_in_effects,
+    test_node,
+};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The engine of the last member of a room, receiving nodes of the others.
+struct Receiver {
+    room: TestRoom,
+    engine: MerkleToxEngine,
+    store: InMemoryStore,
+    seq: Vec<u64>,
+}
+
+impl Receiver {
+    fn new() -> Self {
+        let room = TestRoom::new(3);
+        let me = &room.identities[2];
+        let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+        let mut engine =
+            MerkleToxEngine::new(me.device_pk, me.master_pk, StdRng::seed_from_u64(2), tp);
+        let store = InMemoryStore::new();
+        room.setup_engine(&mut engine, &store);
+        Self {
+            room,
+            engine,
+            store,
+            seq: vec![1; 2],
+        }
+    }
+
+    fn cid(&self) -> ConversationId {
+        self.room.conv_id
+    }
+
+    /// Builds a node of member `idx` on top of the current heads.
+    fn node(&mut self, idx: usize, content: Content, timestamp: i64) -> MerkleNode {
+        let cid = self.cid();
+        let mut parents = self.store.get_heads(&cid);
+        parents.extend(self.store.get_admin_heads(&cid));
+        parents.sort_unstable();
+        parents.dedup();
+        let rank = parents
+            .iter()
+            .filter_map(|h| self.store.get_rank(h))
+            .max()
+            .unwrap();
+        self.seq[idx] += 1;
+        let id = &self.room.identities[idx];
+        create_signed_content_node(
+            &cid,
+            &self.room.keys,
+            id.master_pk,
+            id.device_pk,
+            parents,
+            content,
+            rank + 1,
+            self.seq[idx],
+            timestamp,
+        )
+    }
+
+    /// Hands a node of member `idx` to the engine. Returns its hash.
+    fn receive(
+        &mut self,
+        idx: usize,
+        content: Content,
+        timestamp: i64,
+    ) -> MerkleToxResult<NodeHash> {
+        let node = self.node(idx, content, timestamp);
+        let hash = node.hash();
+        let effects = self
+            .engine
+            .handle_node(self.cid(), node, &self.store, None)?;
+        assert!(is_verified_in_effects(&effects));
+        apply_effects(effects, &self.store);
+        Ok(hash)
+    }
+}
+
+fn poll(multi_choice: bool, closes_at: Option<i64>) -> Content {
+    Content::Poll {
+        question: "Lunch?".to_string(),
+        options: vec![
+            "pizza".to_string(),
+            "sushi".to_string(),
+            "salad".to_string(),
+        ],
+        multi_choice,
+        closes_at,
+    }
+}
+
+fn vote(poll_hash: NodeHash, choices: &[u32]) -> Content {
+    Content::PollVote {
+        poll_hash,
+        choices: choices.to_vec(),
+    }
+}
+
+fn rejected(result: MerkleToxResult<NodeHash>) -> ValidationError {
+    match result {
+        Err(MerkleToxError::Validation(e)) => e,
+        other => panic!("expected a validation error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_poll_content_is_validated() {
+    let lookup = InMemoryStore::new();
+    let cid = ConversationId::from([0xAAu8; 32]);
+    let invalid = [
+        Content::Poll {
+            question: "?".to_string(),
+            options: vec!["only".to_string()],
+            multi_choice: false,
+            closes_at: None,
+        },
+        Content::Poll {
+            question: "?".to_string(),
+            options: vec!["same".to_string(), "same".to_string()],
+            multi_choice: false,
+            closes_at: None,
+        },
+        Content::Poll {
+            question: String::new(),
+            options: vec!["a".to_string(), "b".to_string()],
+            multi_choice: false,
+            closes_at: None,
+        },
+    ];
+    for content in invalid {
+        let mut node = test_node();
+        node.content = content;
+        assert!(matches!(
+            node.validate(&cid, &lookup),
+            Err(ValidationError::InvalidPoll(_))
+        ));
+    }
+    for choices in [&[][..], &[1, 0], &[1, 1]] {
+        let mut node = test_node();
+        node.content = vote(NodeHash::from([1u8; 32]), choices);
+        assert!(matches!(
+            node.validate(&cid, &lookup),
+            Err(ValidationError::InvalidPollVote(_))
+        ));
+    }
+}
+
+#[test]
+fn test_one_vote_per_identity() {
+    let mut r = Receiver::new();
+    let single = r.receive(0, poll(false, None), 1000).unwrap();
+    let multi = r.receive(0, poll(true, None), 1000).unwrap();
+
+    assert!(matches!(
+        rejected(r.receive(1, vote(single, &[3]), 1000)),
+        ValidationError::InvalidPollVote(_)
+    ));
+    assert!(matches!(
+        rejected(r.receive(1, vote(single, &[0, 1]), 1000)),
+        ValidationError::InvalidPollVote(_)
+    ));
+    r.receive(1, vote(single, &[1]), 1000).unwrap();
+    r.receive(1, vote(multi, &[0, 2]), 1000).unwrap();
+    // The poll's author votes too.
+    r.receive(0, vote(single, &[0]), 1000).unwrap();
+
+    assert_eq!(
+        rejected(r.receive(1, vote(single, &[0]), 1000)),
+        ValidationError::DuplicatePollVote
+    );
+    let text = r.receive(0, Content::Text("hi".to_string()), 1000).unwrap();
+    assert_eq!(
+        rejected(r.receive(1, vote(text, &[0]), 1000)),
+        ValidationError::InvalidPollTarget
+    );
+}
+
+#[test]
+fn test_no_votes_after_close() {
+    let mut r = Receiver::new();
+    let timed = r.receive(0, poll(false, Some(2000)), 1000).unwrap();
+    let closed = r.receive(0, poll(false, None), 1000).unwrap();
+
+    r.receive(1, vote(timed, &[0]), 2000).unwrap();
+    assert_eq!(
+        rejected(r.receive(0, vote(timed, &[0]), 2001)),
+        ValidationError::PollClosed
+    );
+
+    r.receive(0, Content::PollClose { poll_hash: closed }, 1000)
+        .unwrap();
+    assert_eq!(
+        rejected(r.receive(1, vote(closed, &[0]), 1000)),
+        ValidationError::PollClosed
+    );
+}
@@ -0,0 +1,183 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ControlAction, EphemeralX25519Pk, HistoryVisibility, MerkleNode, NodeAuth,
+    ValidationError, WireFlags, WireNode,
+};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::engine::conversation::FLAG_PUBLIC;
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::testing::{
+    TestIdentity, TestPeer, TestRoom, apply_effects, get_all_nodes_from_effects,
+    is_verified_in_effects,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Marks the room as public in `p`'s engine.
+fn public(mut p: TestPeer, room: &TestRoom) -> TestPeer {
+    p.engine
+        .conversations
+        .get_mut(&room.conv_id)
+        .unwrap()
+        .set_genesis_flags(FLAG_PUBLIC);
+    p
+}
+
+/// The node and wire node Alice authors for a text message.
+fn author_text(alice: &mut TestPeer, room: &TestRoom) -> (MerkleNode, WireNode, Vec<Effect>) {
+    let effects = alice
+        .engine
+        .author_node(
+            room.conv_id,
+            Content::Text("hello world".to_string()),
+            Vec::new(),
+            &alice.store,
+        )
+        .unwrap();
+    let node = get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| matches!(n.content, Content::Text(_)))
+        .unwrap();
+    let wire = effects
+        .iter()
+        .find_map(|e| match e {
+            Effect::WriteWireNode(_, hash, wire) if *hash == node.hash() => Some(wire.clone()),
+            _ => None,
+        })
+        .unwrap();
+    (node, wire, effects)
+}
+
+#[test]
+fn test_public_content_is_signed_cleartext() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = public(room.peer(0, &tp), &room);
+    let (node, wire, effects) = author_text(&mut alice, &room);
+
+    assert!(matches!(node.authentication, NodeAuth::Signature(_)));
+    assert!(!wire.flags.contains(WireFlags::ENCRYPTED));
+    assert_eq!(
+        MerkleNode::unpack_wire_exception(&wire).unwrap().content,
+        node.content
+    );
+
+    // Members verify the cleartext wire node.
+    let mut bob = public(room.peer(1, &tp), &room);
+    let received = bob
+        .engine
+        .handle_message(
+            room.identities[0].device_pk,
+            ProtocolMessage::MerkleNode {
+                conversation_id: room.conv_id,
+                hash: node.hash(),
+                node: wire,
+            },
+            &bob.store,
+            None,
+        )
+        .unwrap();
+    assert!(is_verified_in_effects(&received));
+
+    // No sender keys are distributed for the message.
+    assert!(
+        !get_all_nodes_from_effects(&effects)
+            .iter()
+            .any(|n| matches!(n.content, Content::SenderKeyDistribution { .. }))
+    );
+}
+
+#[test]
+fn test_public_content_verifies_only_in_public_conversations() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut alice = public(room.peer(0, &tp), &room);
+    let (node, _, _) = author_text(&mut alice, &room);
+
+    let mut bob = public(room.peer(1, &tp), &room);
+    let effects = bob
+        .engine
+        .handle_node(room.conv_id, node.clone(), &bob.store, None)
+        .unwrap();
+    assert!(is_verified_in_effects(&effects));
+    apply_effects(effects, &bob.store);
+
+    // A forged signature is rejected.
+    let mut forged = node.clone();
+    forged.content = Content::Text("goodbye".to_string());
+    let result = bob
+        .engine
+        .handle_node(room.conv_id, forged, &bob.store, None);
+    assert!(matches!(
+        result,
+        Err(MerkleToxError::Validation(
+            ValidationError::InvalidAdminSignature
+        ))
+    ));
+
+    // Private conversations still require ephemerally signed content.
+    let mut carol = TestPeer::new(&room, &room.identities[1], 2, &tp);
+    let result = carol
+        .engine
+        .handle_node(room.conv_id, node, &carol.store, None);
+    assert!(matches!(
+        result,
+        Err(MerkleToxError::Validation(
+            ValidationError::ContentNodeShouldUseMac
+        ))
+    ));
+}
+
+#[test]
+fn test_public_conversation_skips_key_exchange() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let cid = room.conv_id;
+    let mut alice = public(room.peer(0, &tp), &room);
+    let mut private = TestPeer::new(&room, &room.identities[0], 1, &tp);
+    assert!(alice.engine.is_public(cid));
+    assert!(!private.engine.is_public(cid));
+
+    let effects = alice
+        .engine
+        .author_x3dh_key_exchange(
+            cid,
+            room.identities[1].device_pk,
+            EphemeralX25519Pk::from([7u8; 32]),
+            &alice.store,
+        )
+        .unwrap();
+    assert!(effects.is_empty());
+
+    // Keys of public conversations never age out.
+    tp.advance(Duration::from_secs(30 * 24 * 60 * 60));
+    assert!(!alice.engine.check_rotation_triggers(cid));
+    assert!(!alice.engine.check_sender_rekey_triggers(cid));
+    assert!(private.engine.check_rotation_triggers(cid));
+
+    // Sync sketches are unkeyed, and history is open to anyone.
+    assert_eq!(alice.engine.conversations[&cid].iblt_key(), None);
+    assert!(private.engine.conversations[&cid].iblt_key().is_some());
+    let stranger = TestIdentity::new();
+    for p in [&mut alice, &mut private] {
+        let effects = p
+            .engine
+            .author_node(
+                cid,
+                Content::Control(ControlAction::SetHistoryVisibility {
+                    visibility: HistoryVisibility::SinceJoin,
+                    history_access: vec![],
+                }),
+                Vec::new(),
+                &p.store,
+            )
+            .unwrap();
+        apply_effects(effects, &p.store);
+    }
+    assert_eq!(alice.engine.history_floor(cid, &stranger.device_pk), None);
+    assert_eq!(
+        private.engine.history_floor(cid, &stranger.device_pk),
+        Some(u64::MAX)
+    );
+}
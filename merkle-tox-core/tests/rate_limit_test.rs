@@ -0,0 +1,131 @@
+use merkle_tox_core::cas::BlobReq;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::rate_limit::{
+    Admission, LimitedMessage, RateLimit, RateLimitPolicy, RateLimiter,
+};
+use merkle_tox_core::sync::SyncHeads;
+use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport, VirtualHub};
+use merkle_tox_core::{NodeEvent, NodeEventHandler, ProtocolMessage};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn blob_req(i: u64) -> ProtocolMessage {
+    ProtocolMessage::BlobReq(BlobReq {
+        hash: NodeHash::from([7u8; 32]),
+        offset: i * 1024,
+        length: 1024,
+    })
+}
+
+fn heads() -> ProtocolMessage {
+    ProtocolMessage::SyncHeads(SyncHeads {
+        conversation_id: ConversationId::from([1u8; 32]),
+        heads: vec![],
+        flags: 0,
+        anchor_hash: None,
+    })
+}
+
+#[test]
+fn test_bucket_refills_over_time() {
+    let peer = PhysicalDevicePk::from([1u8; 32]);
+    let other = PhysicalDevicePk::from([2u8; 32]);
+    let mut limiter = RateLimiter::new(RateLimitPolicy {
+        blob_req: RateLimit::new(2, Duration::from_secs(1)),
+        ..RateLimitPolicy::default()
+    });
+    let t0 = Instant::now();
+
+    assert_eq!(limiter.check(peer, &blob_req(0), t0), Admission::Allowed);
+    assert_eq!(limiter.check(peer, &blob_req(1), t0), Admission::Allowed);
+    assert_eq!(limiter.check(peer, &blob_req(2), t0), Admission::Throttled);
+    assert_eq!(
+        limiter.check(peer, &blob_req(3), t0),
+        Admission::StillThrottled
+    );
+    assert!(limiter.is_throttled(&peer, LimitedMessage::BlobReq));
+
+    // Other peers and unlimited messages are unaffected.
+    assert_eq!(limiter.check(other, &blob_req(0), t0), Admission::Allowed);
+    assert_eq!(limiter.check(peer, &heads(), t0), Admission::Allowed);
+
+    let t1 = t0 + Duration::from_secs(1);
+    assert_eq!(limiter.check(peer, &blob_req(4), t1), Admission::Allowed);
+    assert!(!limiter.is_throttled(&peer, LimitedMessage::BlobReq));
+    assert_eq!(limiter.check(peer, &blob_req(5), t1), Admission::Throttled);
+}
+
+struct EventLog(Mutex<Vec<NodeEvent>>);
+
+impl NodeEventHandler for EventLog {
+    fn handle_event(&self, event: NodeEvent) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+#[test]
+fn test_node_throttles_request_flood() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(tp.clone()));
+    let alice_pk = PhysicalDevicePk::from([1u8; 32]);
+    let bob_pk = PhysicalDevicePk::from([2u8; 32]);
+
+    let mut nodes = Vec::new();
+    for pk in [alice_pk, bob_pk] {
+        let engine = MerkleToxEngine::new(
+            pk,
+            pk.to_logical(),
+            StdRng::seed_from_u64(pk.as_bytes()[0] as u64),
+            tp.clone(),
+        );
+        let rx = hub.register(pk);
+        let node = MerkleToxNode::new(
+            engine,
+            SimulatedTransport::new(pk, hub.clone()),
+            InMemoryStore::new(),
+            tp.clone(),
+        );
+        nodes.push((node, rx));
+    }
+    let events = Arc::new(EventLog(Mutex::new(Vec::new())));
+    nodes[1].0.set_event_handler(events.clone());
+    nodes[1].0.set_rate_limit_policy(RateLimitPolicy {
+        blob_req: RateLimit::new(4, Duration::from_secs(3600)),
+        ..RateLimitPolicy::default()
+    });
+
+    for i in 0..20 {
+        nodes[0].0.send_message(bob_pk, blob_req(i));
+    }
+    for _ in 0..50 {
+        for (node, rx) in &mut nodes {
+            node.poll();
+            while let Ok((from, data)) = rx.try_recv() {
+                node.handle_packet(from, &data);
+            }
+        }
+        hub.poll();
+        tp.advance(Duration::from_millis(50));
+    }
+
+    let bob = &nodes[1].0;
+    assert!(
+        bob.rate_limiter
+            .is_throttled(&alice_pk, LimitedMessage::BlobReq)
+    );
+    let throttled: Vec<_> = events
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|e| match e {
+            NodeEvent::PeerThrottled { device_pk, message } => Some((*device_pk, *message)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(throttled, vec![(alice_pk, LimitedMessage::BlobReq)]);
+}
@@ -0,0 +1,129 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, NodeLookup, PhysicalDevicePk};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::sync::{FEATURE_READ_ONLY, NodeStore};
+use merkle_tox_core::testing::{
+    TestIdentity, TestPeer, TestRoom, apply_effects, create_signed_content_node,
+    is_verified_in_effects,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn assert_read_only<T: std::fmt::Debug>(result: Result<T, MerkleToxError>) {
+    assert!(
+        matches!(result, Err(MerkleToxError::ReadOnly)),
+        "expected a read-only error, got {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_observer_verifies_but_cannot_author() {
+    let room = TestRoom::new(2);
+    // The auditor holds the conversation key but is not a member.
+    let auditor = TestIdentity::new();
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut observer = TestPeer::new(&room, &auditor, 3, &tp);
+    observer.engine.config.read_only = true;
+    let cid = room.conv_id;
+
+    let mut parents = observer.store.get_heads(&cid);
+    parents.extend(observer.store.get_admin_heads(&cid));
+    parents.sort_unstable();
+    parents.dedup();
+    let rank = parents
+        .iter()
+        .filter_map(|h| observer.store.get_rank(h))
+        .max()
+        .unwrap();
+    let alice = &room.identities[0];
+    let node = create_signed_content_node(
+        &cid,
+        &room.keys,
+        alice.master_pk,
+        alice.device_pk,
+        parents,
+        Content::Text("minutes".to_string()),
+        rank + 1,
+        2,
+        1000,
+    );
+    let effects = observer
+        .engine
+        .handle_node(cid, node, &observer.store, None)
+        .unwrap();
+    assert!(is_verified_in_effects(&effects));
+    apply_effects(effects, &observer.store);
+
+    assert_read_only(observer.engine.author_node(
+        cid,
+        Content::Text("hi".to_string()),
+        Vec::new(),
+        &observer.store,
+    ));
+    assert_read_only(observer.engine.author_announcement(cid, &observer.store));
+    assert_read_only(
+        observer
+            .engine
+            .rotate_conversation_key(cid, &observer.store),
+    );
+}
+
+#[test]
+fn test_observer_advertises_read_only() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut member = room.peer(0, &tp);
+    let mut observer = room.peer(1, &tp);
+    observer.engine.config.read_only = true;
+    assert_ne!(
+        member.engine.advertised_features() & FEATURE_READ_ONLY,
+        FEATURE_READ_ONLY
+    );
+    assert_eq!(
+        observer.engine.advertised_features() & FEATURE_READ_ONLY,
+        FEATURE_READ_ONLY
+    );
+
+    let cid = room.conv_id;
+    let from_member = member
+        .engine
+        .start_sync(cid, Some(observer.pk), &member.store);
+    let from_observer = observer
+        .engine
+        .start_sync(cid, Some(member.pk), &observer.store);
+    let caps = |effects: &[Effect], to: PhysicalDevicePk| {
+        effects
+            .iter()
+            .find_map(|e| match e {
+                Effect::SendPacket(pk, msg @ ProtocolMessage::CapsAnnounce { .. }) if *pk == to => {
+                    Some(msg.clone())
+                }
+                _ => None,
+            })
+            .unwrap()
+    };
+    member
+        .engine
+        .handle_message(
+            observer.pk,
+            caps(&from_observer, member.pk),
+            &member.store,
+            None,
+        )
+        .unwrap();
+    observer
+        .engine
+        .handle_message(
+            member.pk,
+            caps(&from_member, observer.pk),
+            &observer.store,
+            None,
+        )
+        .unwrap();
+
+    assert!(member.engine.peer_is_read_only(&observer.pk));
+    assert!(!observer.engine.peer_is_read_only(&member.pk));
+}
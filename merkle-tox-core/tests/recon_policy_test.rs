@@ -0,0 +1,215 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{ConversationId, PhysicalDevicePk};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::engine::config::ReconPolicy;
+use merkle_tox_core::engine::session::PeerSession;
+use merkle_tox_core::engine::session::active::solve_challenge;
+use merkle_tox_core::sync::{SyncRange, Tier};
+use merkle_tox_core::testing::{TestRoom, find_sent};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_reconcile::{IbltSketch, SyncSketch};
+
+fn sketch(cid: ConversationId, tier: Tier) -> ProtocolMessage {
+    ProtocolMessage::SyncSketch(SyncSketch {
+        conversation_id: cid,
+        cells: IbltSketch::new(tier.cell_count()).into_cells(),
+        range: SyncRange {
+            min_rank: 0,
+            max_rank: 0,
+        },
+    })
+}
+
+fn checksums(cid: ConversationId) -> ProtocolMessage {
+    ProtocolMessage::SyncShardChecksums {
+        conversation_id: cid,
+        shards: vec![],
+    }
+}
+
+fn is_rate_limited(m: &ProtocolMessage) -> bool {
+    matches!(m, ProtocolMessage::SyncRateLimited { .. })
+}
+
+fn challenge_difficulty(effects: &[Effect], to: PhysicalDevicePk) -> Option<u32> {
+    find_sent(effects, to, |m| {
+        matches!(m, ProtocolMessage::ReconPowChallenge { .. })
+    })
+    .map(|m| match m {
+        ProtocolMessage::ReconPowChallenge { difficulty, .. } => difficulty,
+        _ => unreachable!(),
+    })
+}
+
+#[test]
+fn test_difficulty_tracks_peer_load() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+    a.engine.config.recon = ReconPolicy {
+        max_difficulty: 14,
+        free_sketches_per_window: 2,
+        ..ReconPolicy::default()
+    };
+    a.handshake(&mut b, cid);
+
+    // Tiny sketches within the allowance are decoded without a challenge.
+    for _ in 0..2 {
+        let effects = a
+            .engine
+            .handle_message(b.pk, sketch(cid, Tier::Tiny), &a.store, None)
+            .unwrap();
+        assert_eq!(challenge_difficulty(&effects, b.pk), None);
+    }
+    assert_eq!(a.engine.recon_difficulty(&b.pk), 12);
+
+    // Past the allowance every sketch is challenged, one bit harder each
+    // time, up to the maximum.
+    let mut seen = Vec::new();
+    for _ in 0..4 {
+        let effects = a
+            .engine
+            .handle_message(b.pk, sketch(cid, Tier::Tiny), &a.store, None)
+            .unwrap();
+        seen.push(challenge_difficulty(&effects, b.pk).unwrap());
+    }
+    assert_eq!(seen, vec![13, 14, 14, 14]);
+
+    // Load decays once the peer backs off.
+    tp.advance(Duration::from_secs(600));
+    let effects = a
+        .engine
+        .handle_message(b.pk, sketch(cid, Tier::Tiny), &a.store, None)
+        .unwrap();
+    assert_eq!(challenge_difficulty(&effects, b.pk), None);
+}
+
+#[test]
+fn test_raised_difficulty_is_enforced() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+    a.engine.config.recon = ReconPolicy {
+        max_difficulty: 16,
+        free_sketches_per_window: 0,
+        ..ReconPolicy::default()
+    };
+    a.handshake(&mut b, cid);
+
+    for _ in 0..9 {
+        a.engine
+            .handle_message(b.pk, sketch(cid, Tier::Tiny), &a.store, None)
+            .unwrap();
+    }
+    let effects = a
+        .engine
+        .handle_message(b.pk, sketch(cid, Tier::Medium), &a.store, None)
+        .unwrap();
+    let (nonce, difficulty) = match find_sent(&effects, b.pk, |m| {
+        matches!(m, ProtocolMessage::ReconPowChallenge { .. })
+    }) {
+        Some(ProtocolMessage::ReconPowChallenge {
+            nonce, difficulty, ..
+        }) => (nonce, difficulty),
+        other => panic!("expected challenge, got {:?}", other),
+    };
+    assert_eq!(difficulty, 16);
+
+    let Some(PeerSession::Active(session)) = a.engine.sessions.get_mut(&(b.pk, cid)) else {
+        panic!("session not active");
+    };
+    assert_eq!(session.challenge_difficulty(&nonce), difficulty);
+    let now = tp.now_instant();
+    let solution = solve_challenge(nonce, difficulty);
+    assert!(session.verify_solution(nonce, solution, now));
+}
+
+#[test]
+fn test_round_cooldown() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+    a.handshake(&mut b, cid);
+
+    let effects = a
+        .engine
+        .handle_message(b.pk, checksums(cid), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, b.pk, is_rate_limited).is_none());
+
+    // Restarting the round straight away is refused.
+    let effects = a
+        .engine
+        .handle_message(b.pk, checksums(cid), &a.store, None)
+        .unwrap();
+    match find_sent(&effects, b.pk, is_rate_limited) {
+        Some(ProtocolMessage::SyncRateLimited { retry_after_ms, .. }) => {
+            assert!(retry_after_ms > 0 && retry_after_ms <= 5000);
+        }
+        other => panic!("expected rate limit, got {:?}", other),
+    }
+
+    tp.advance(ReconPolicy::default().round_cooldown);
+    let effects = a
+        .engine
+        .handle_message(b.pk, checksums(cid), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, b.pk, is_rate_limited).is_none());
+}
+
+#[test]
+fn test_concurrent_recon_cap() {
+    let room = TestRoom::new(3);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let mut c = room.peer(2, &tp);
+    let cid = room.conv_id;
+    a.engine.config.recon = ReconPolicy {
+        max_concurrent_recons: 1,
+        ..ReconPolicy::default()
+    };
+    a.handshake(&mut b, cid);
+    a.handshake(&mut c, cid);
+
+    let effects = a
+        .engine
+        .handle_message(b.pk, checksums(cid), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, b.pk, is_rate_limited).is_none());
+
+    // B holds the only slot: C is turned away, for rounds and sketches alike.
+    let effects = a
+        .engine
+        .handle_message(c.pk, checksums(cid), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, c.pk, is_rate_limited).is_some());
+    let effects = a
+        .engine
+        .handle_message(c.pk, sketch(cid, Tier::Small), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, c.pk, is_rate_limited).is_some());
+
+    // Tiny gossip sketches do not need a slot.
+    let effects = a
+        .engine
+        .handle_message(c.pk, sketch(cid, Tier::Tiny), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, c.pk, is_rate_limited).is_none());
+
+    // Once B's reconciliation goes idle, C gets in.
+    tp.advance(ReconPolicy::default().recon_idle_timeout);
+    let effects = a
+        .engine
+        .handle_message(c.pk, checksums(cid), &a.store, None)
+        .unwrap();
+    assert!(find_sent(&effects, c.pk, is_rate_limited).is_none());
+}
@@ -0,0 +1,139 @@
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{Content, ConversationId, KConv, PhysicalDevicePk, PhysicalDeviceSk};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::replay::{RecordedInput, read_recording};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport, VirtualHub};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+type Node = MerkleToxNode<SimulatedTransport, InMemoryStore>;
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([0x42u8; 32])
+}
+
+/// A store holding the conversation key, as every node starts with.
+fn store() -> InMemoryStore {
+    let store = InMemoryStore::new();
+    store
+        .put_conversation_key(&conv_id(), 0, KConv::from([0xAAu8; 32]))
+        .unwrap();
+    store
+}
+
+fn node(seed: u8, hub: &Arc<VirtualHub>, time: &Arc<ManualTimeProvider>) -> Node {
+    let sk = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+    let pk = PhysicalDevicePk::from(sk.verifying_key().to_bytes());
+    let engine = MerkleToxEngine::with_sk(
+        pk,
+        pk.to_logical(),
+        PhysicalDeviceSk::from(sk.to_bytes()),
+        StdRng::seed_from_u64(seed as u64),
+        time.clone(),
+    );
+    let mut node = MerkleToxNode::new(
+        engine,
+        SimulatedTransport::new(pk, hub.clone()),
+        store(),
+        time.clone(),
+    );
+    node.engine
+        .load_conversation_state(conv_id(), &node.store)
+        .unwrap();
+    node
+}
+
+#[test]
+fn test_replay_rebuilds_recorded_state() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("bob.mtxr");
+    let time = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time.clone()));
+
+    let mut alice = node(1, &hub, &time);
+    let mut bob = node(2, &hub, &time);
+    let alice_rx = hub.register(alice.engine.self_pk);
+    let bob_rx = hub.register(bob.engine.self_pk);
+    bob.enable_recording(&path).unwrap();
+
+    let effects = alice
+        .engine
+        .author_node(
+            conv_id(),
+            Content::Text("hello".to_string()),
+            vec![],
+            &alice.store,
+        )
+        .unwrap();
+    let now = time.now_instant();
+    let mut wakeup = now;
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+    let effects = alice
+        .engine
+        .start_sync(conv_id(), Some(bob.engine.self_pk), &alice.store);
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+
+    let start = time.now_instant();
+    while bob.store.get_node_counts(&conv_id()) == (0, 0) {
+        assert!(
+            time.now_instant().duration_since(start) < Duration::from_secs(10),
+            "Bob never received Alice's message"
+        );
+        alice.poll();
+        while let Ok((from, data)) = alice_rx.try_recv() {
+            alice.handle_packet(from, &data);
+        }
+        bob.poll();
+        while let Ok((from, data)) = bob_rx.try_recv() {
+            bob.handle_packet(from, &data);
+        }
+        hub.poll();
+        time.advance(Duration::from_millis(100));
+    }
+    bob.disable_recording();
+
+    let (header, records) = read_recording(&path).unwrap();
+    assert_eq!(header.self_pk, bob.engine.self_pk);
+    assert_eq!(header.conversations, vec![conv_id()]);
+    assert!(
+        records
+            .iter()
+            .any(|r| matches!(r.input, RecordedInput::Packet { .. }))
+    );
+
+    let replayed = MerkleToxNode::replay(&path, store()).unwrap();
+    assert_eq!(
+        replayed.store.get_heads(&conv_id()),
+        bob.store.get_heads(&conv_id())
+    );
+    assert_eq!(
+        replayed.store.get_node_counts(&conv_id()),
+        bob.store.get_node_counts(&conv_id())
+    );
+
+    // Replay is deterministic down to the packets sent.
+    let again = MerkleToxNode::replay(&path, store()).unwrap();
+    assert!(!replayed.transport.sent.lock().is_empty());
+    assert_eq!(
+        *replayed.transport.sent.lock(),
+        *again.transport.sent.lock()
+    );
+}
+
+#[test]
+fn test_replay_rejects_foreign_file() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("not-a-recording");
+    std::fs::write(&path, b"hello").unwrap();
+    let result = MerkleToxNode::replay(&path, InMemoryStore::new());
+    assert!(matches!(result, Err(MerkleToxError::Other(_))));
+}
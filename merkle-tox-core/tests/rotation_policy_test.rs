@@ -0,0 +1,108 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{Content, ControlAction, InviteAction};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::engine::config::{RotationPolicy, RotationTrigger};
+use merkle_tox_core::testing::{TestIdentity, TestRoom, apply_effects, get_all_nodes_from_effects};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn rotations(effects: &[Effect]) -> Vec<(u64, Option<RotationTrigger>)> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::EpochRotated { epoch, trigger, .. }) => {
+                Some((*epoch, *trigger))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_message_limit_rotates_key() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    a.engine.set_rotation_policy(
+        cid,
+        RotationPolicy {
+            max_messages: 3,
+            ..RotationPolicy::default()
+        },
+    );
+
+    let mut seen = Vec::new();
+    for i in 0..5 {
+        seen.extend(rotations(
+            &a.author(cid, Content::Text(format!("message {}", i))),
+        ));
+    }
+    assert_eq!(seen, vec![(1, Some(RotationTrigger::MessageCount))]);
+    assert_eq!(a.engine.get_current_generation(&cid), 1);
+}
+
+#[test]
+fn test_age_limit_rotates_key_and_peers_see_it() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+    let policy = RotationPolicy {
+        max_age: Duration::from_secs(3600),
+        ..RotationPolicy::default()
+    };
+    a.engine.set_rotation_policy(cid, policy);
+    assert_eq!(a.engine.rotation_policy(&cid), policy);
+    assert_eq!(b.engine.rotation_policy(&cid), RotationPolicy::default());
+    assert_eq!(a.engine.rotation_trigger(cid), None);
+
+    tp.advance(Duration::from_secs(2 * 3600));
+    assert_eq!(a.engine.rotation_trigger(cid), Some(RotationTrigger::Age));
+    let now = tp.now_instant();
+    let effects = a.engine.poll(now, &a.store).unwrap();
+    assert_eq!(rotations(&effects), vec![(1, Some(RotationTrigger::Age))]);
+    apply_effects(effects.clone(), &a.store);
+    assert_eq!(a.engine.rotation_trigger(cid), None);
+
+    let mut seen = Vec::new();
+    for node in get_all_nodes_from_effects(&effects) {
+        let effects = b.engine.handle_node(cid, node, &b.store, None).unwrap();
+        seen.extend(rotations(&effects));
+        apply_effects(effects, &b.store);
+    }
+    assert_eq!(seen, vec![(1, None)]);
+    assert_eq!(b.engine.get_current_generation(&cid), 1);
+}
+
+#[test]
+fn test_membership_change_rotation_is_opt_in() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let cid = room.conv_id;
+    let invite = |pk| {
+        Content::Control(ControlAction::Invite(InviteAction {
+            invitee_pk: pk,
+            role: 1,
+        }))
+    };
+
+    let effects = a.author(cid, invite(TestIdentity::new().master_pk));
+    assert!(rotations(&effects).is_empty());
+
+    a.engine.set_rotation_policy(
+        cid,
+        RotationPolicy {
+            rotate_on_membership_change: true,
+            ..RotationPolicy::default()
+        },
+    );
+    let effects = a.author(cid, invite(TestIdentity::new().master_pk));
+    assert_eq!(
+        rotations(&effects),
+        vec![(1, Some(RotationTrigger::MembershipChange))]
+    );
+}
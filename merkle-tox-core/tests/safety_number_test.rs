@@ -0,0 +1,89 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, ControlAction, ConversationId, LogicalIdentityPk, NodeType};
+use merkle_tox_core::fingerprint::{FINGERPRINT_DIGITS, SafetyNumber, fingerprint};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{TestPeer, TestRoom, apply_effects, get_all_nodes_from_effects};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn identity(i: u8) -> LogicalIdentityPk {
+    LogicalIdentityPk::from([i; 32])
+}
+
+#[test]
+fn test_safety_number_is_symmetric() {
+    let (a, b, c) = (identity(1), identity(2), identity(3));
+    let number = SafetyNumber::new(&a, &b);
+    assert_eq!(number, SafetyNumber::new(&b, &a));
+    assert_ne!(number, SafetyNumber::new(&a, &c));
+    assert_eq!(number.digits().len(), 2 * FINGERPRINT_DIGITS);
+    assert!(number.digits().starts_with(&fingerprint(&a, None)));
+    assert!(number.digits().ends_with(&fingerprint(&b, None)));
+}
+
+#[test]
+fn test_safety_number_per_conversation() {
+    let (a, b) = (identity(1), identity(2));
+    let c1 = ConversationId::from([0x11; 32]);
+    let c2 = ConversationId::from([0x22; 32]);
+    let n1 = SafetyNumber::for_conversation(&c1, &a, &b);
+    assert_eq!(n1, SafetyNumber::for_conversation(&c1, &b, &a));
+    assert_ne!(n1, SafetyNumber::for_conversation(&c2, &a, &b));
+    assert_ne!(n1, SafetyNumber::new(&a, &b));
+}
+
+#[test]
+fn test_safety_number_display_and_qr() {
+    let number = SafetyNumber::new(&identity(1), &identity(2));
+    let shown = number.to_string();
+    let groups: Vec<&str> = shown.split(' ').collect();
+    assert_eq!(groups.len(), 12);
+    assert!(groups.iter().all(|g| g.len() == 5));
+    assert_eq!(groups.concat(), number.digits());
+
+    let payload = number.to_qr_payload();
+    assert_eq!(SafetyNumber::from_qr_payload(&payload), Some(number));
+    assert_eq!(SafetyNumber::from_qr_payload("mtox-verify:12345"), None);
+    assert_eq!(
+        SafetyNumber::from_qr_payload(&payload.replace("mtox-verify:", "mtox-invite:")),
+        None
+    );
+}
+
+#[test]
+fn test_verified_identity_is_admin_node() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let TestPeer {
+        engine: mut a,
+        store: a_store,
+        ..
+    } = room.peer(0, &tp);
+    let TestPeer {
+        engine: mut b,
+        store: b_store,
+        ..
+    } = room.peer(1, &tp);
+    let cid = room.conv_id;
+
+    let content = Content::Control(ControlAction::VerifiedIdentity(
+        room.identities[1].master_pk,
+    ));
+    assert_eq!(content.node_type(), NodeType::Admin);
+    let effects = a.author_node(cid, content, vec![], &a_store).unwrap();
+    apply_effects(effects.clone(), &a_store);
+
+    let node = get_all_nodes_from_effects(&effects)
+        .into_iter()
+        .find(|n| {
+            matches!(
+                n.content,
+                Content::Control(ControlAction::VerifiedIdentity(_))
+            )
+        })
+        .unwrap();
+    let hash = node.hash();
+    let effects = b.handle_node(cid, node, &b_store, None).unwrap();
+    apply_effects(effects, &b_store);
+    assert!(b_store.is_verified(&hash));
+}
@@ -0,0 +1,134 @@
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{Content, PhysicalDeviceSk};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, TestRoom, apply_effects};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn setup() -> (
+    MerkleToxEngine,
+    InMemoryStore,
+    TestRoom,
+    Arc<ManualTimeProvider>,
+) {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1_000_000));
+    let room = TestRoom::new(2);
+    let alice = &room.identities[0];
+    let store = InMemoryStore::new();
+    let mut engine = MerkleToxEngine::with_sk(
+        alice.device_pk,
+        alice.master_pk,
+        PhysicalDeviceSk::from(alice.device_sk.to_bytes()),
+        StdRng::seed_from_u64(1),
+        tp.clone(),
+    );
+    room.setup_engine(&mut engine, &store);
+    (engine, store, room, tp)
+}
+
+fn authored_texts(effects: &[Effect]) -> Vec<String> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::WriteStore(_, node, _) => match &node.content {
+                Content::Text(text) => Some(text.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_scheduled_message_delivered_when_due() {
+    let (mut engine, store, room, tp) = setup();
+    let deliver_at = engine.clock.network_time_ms() + 60_000;
+    let (id, effects) = engine.schedule_message(
+        room.conv_id,
+        Content::Text("reminder".to_string()),
+        vec![],
+        deliver_at,
+    );
+    apply_effects(effects, &store);
+    assert_eq!(store.get_scheduled_messages().len(), 1);
+
+    let now = tp.now_instant();
+    let effects = engine.poll(now, &store).unwrap();
+    assert!(authored_texts(&effects).is_empty());
+    let wakeup = effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::ScheduleWakeup(_, t) => Some(*t),
+            _ => None,
+        })
+        .min()
+        .unwrap();
+    assert!(wakeup <= now + Duration::from_secs(60));
+
+    tp.advance(Duration::from_secs(61));
+    let effects = engine.poll(tp.now_instant(), &store).unwrap();
+    assert_eq!(authored_texts(&effects), vec!["reminder".to_string()]);
+    assert!(
+        effects
+            .iter()
+            .any(|e| matches!(e, Effect::DeleteScheduledMessage(i) if *i == id))
+    );
+    apply_effects(effects, &store);
+    assert!(store.get_scheduled_messages().is_empty());
+    assert!(engine.scheduled_messages.is_empty());
+}
+
+#[test]
+fn test_cancelled_message_is_not_sent() {
+    let (mut engine, store, room, tp) = setup();
+    let deliver_at = engine.clock.network_time_ms() + 1_000;
+    let (id, effects) = engine.schedule_message(
+        room.conv_id,
+        Content::Text("never".to_string()),
+        vec![],
+        deliver_at,
+    );
+    apply_effects(effects, &store);
+
+    let effects = engine.cancel_scheduled_message(id);
+    assert!(matches!(effects.as_slice(), [Effect::DeleteScheduledMessage(i)] if *i == id));
+    apply_effects(effects, &store);
+    assert!(engine.cancel_scheduled_message(id).is_empty());
+    assert!(store.get_scheduled_messages().is_empty());
+
+    tp.advance(Duration::from_secs(2));
+    let effects = engine.poll(tp.now_instant(), &store).unwrap();
+    assert!(authored_texts(&effects).is_empty());
+}
+
+#[test]
+fn test_scheduled_messages_survive_restart() {
+    let (mut engine, store, room, tp) = setup();
+    let deliver_at = engine.clock.network_time_ms() + 5_000;
+    let (id, effects) = engine.schedule_message(
+        room.conv_id,
+        Content::Text("later".to_string()),
+        vec![],
+        deliver_at,
+    );
+    apply_effects(effects, &store);
+    drop(engine);
+
+    let alice = &room.identities[0];
+    let mut engine = MerkleToxEngine::with_sk(
+        alice.device_pk,
+        alice.master_pk,
+        PhysicalDeviceSk::from(alice.device_sk.to_bytes()),
+        StdRng::seed_from_u64(2),
+        tp.clone(),
+    );
+    room.setup_engine(&mut engine, &store);
+    assert_eq!(engine.load_scheduled_messages(&store), 1);
+    assert!(engine.scheduled_messages.contains_key(&id));
+
+    tp.advance(Duration::from_secs(6));
+    let effects = engine.poll(tp.now_instant(), &store).unwrap();
+    assert_eq!(authored_texts(&effects), vec!["later".to_string()]);
+}
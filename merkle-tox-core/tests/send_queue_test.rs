@@ -0,0 +1,192 @@
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk, PhysicalDeviceSk};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::node::{
+    BACKPRESSURE_HIGH, BACKPRESSURE_LOW, MAX_QUEUED_MESSAGES, MerkleToxNode, STALE_SYNC_AGE,
+    SendQueue,
+};
+use merkle_tox_core::sync::SyncHeads;
+use merkle_tox_core::testing::{InMemoryStore, TestRoom};
+use merkle_tox_core::{ProtocolMessage, Transport, TransportError};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::protocol::MAX_CONCURRENT_OUTGOING;
+
+struct NullTransport;
+impl Transport for NullTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        PhysicalDevicePk::from([0u8; 32])
+    }
+    fn send_raw(&self, _to: PhysicalDevicePk, _data: Vec<u8>) -> Result<(), TransportError> {
+        Ok(())
+    }
+}
+
+fn node(tp: &Arc<ManualTimeProvider>) -> MerkleToxNode<NullTransport, InMemoryStore> {
+    let self_pk = PhysicalDevicePk::from([0u8; 32]);
+    let engine = MerkleToxEngine::new(
+        self_pk,
+        self_pk.to_logical(),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    MerkleToxNode::new(engine, NullTransport, InMemoryStore::new(), tp.clone())
+}
+
+fn keywrap_ack(i: u8) -> ProtocolMessage {
+    ProtocolMessage::KeywrapAck {
+        keywrap_hash: NodeHash::from([i; 32]),
+        recipient_pk: PhysicalDevicePk::from([i; 32]),
+    }
+}
+
+fn heads(cid: u8, head: u8) -> ProtocolMessage {
+    ProtocolMessage::SyncHeads(SyncHeads {
+        conversation_id: ConversationId::from([cid; 32]),
+        heads: vec![NodeHash::from([head; 32])],
+        flags: 0,
+        anchor_hash: None,
+    })
+}
+
+#[test]
+fn test_messages_wait_for_offline_peer() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut node = node(&tp);
+    let peer = PhysicalDevicePk::from([1u8; 32]);
+
+    node.set_peer_available(peer, false);
+    node.send_message(peer, keywrap_ack(1));
+    node.send_message(peer, keywrap_ack(2));
+    assert_eq!(node.queued_messages(&peer), 2);
+    assert!(!node.sessions.contains_key(&peer));
+
+    node.set_peer_available(peer, true);
+    node.poll();
+    assert_eq!(node.queued_messages(&peer), 0);
+    assert!(node.sessions.contains_key(&peer));
+}
+
+#[test]
+fn test_full_session_applies_backpressure() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut node = node(&tp);
+    let peer = PhysicalDevicePk::from([1u8; 32]);
+
+    let total = MAX_CONCURRENT_OUTGOING + BACKPRESSURE_HIGH;
+    for i in 0..total {
+        node.send_message(peer, keywrap_ack(i as u8));
+        if i + 1 < total {
+            assert!(!node.engine.backpressured_peers.contains(&peer));
+        }
+    }
+    assert_eq!(node.queued_messages(&peer), BACKPRESSURE_HIGH);
+    assert!(node.engine.backpressured_peers.contains(&peer));
+
+    // Each fresh session takes another window of messages off the queue.
+    while node.queued_messages(&peer) > BACKPRESSURE_LOW {
+        let before = node.queued_messages(&peer);
+        assert!(node.engine.backpressured_peers.contains(&peer));
+        node.set_peer_available(peer, false);
+        node.set_peer_available(peer, true);
+        node.poll();
+        assert_eq!(
+            node.queued_messages(&peer),
+            before.saturating_sub(MAX_CONCURRENT_OUTGOING)
+        );
+    }
+    assert!(!node.engine.backpressured_peers.contains(&peer));
+}
+
+#[test]
+fn test_send_queue_eviction() {
+    let now = Instant::now();
+    let mut queue = SendQueue::default();
+
+    // A newer head announcement replaces the queued one.
+    assert!(queue.push(heads(1, 1), 100, now));
+    assert!(queue.push(heads(2, 1), 100, now));
+    assert!(queue.push(heads(1, 2), 100, now));
+    assert_eq!(queue.len(), 2);
+    assert_eq!(queue.iter().last().unwrap().msg, heads(1, 2));
+
+    // A full queue sheds sync messages before refusing others.
+    for i in 0..MAX_QUEUED_MESSAGES - 2 {
+        assert!(queue.push(keywrap_ack(i as u8), 100, now));
+    }
+    assert!(queue.push(keywrap_ack(0), 100, now));
+    assert!(queue.push(keywrap_ack(0), 100, now));
+    assert_eq!(queue.len(), MAX_QUEUED_MESSAGES);
+    assert!(!queue.push(keywrap_ack(0), 100, now));
+    assert!(
+        queue
+            .iter()
+            .all(|q| matches!(q.msg, ProtocolMessage::KeywrapAck { .. }))
+    );
+    assert_eq!(queue.bytes(), MAX_QUEUED_MESSAGES * 100);
+}
+
+#[test]
+fn test_stale_sync_messages_expire() {
+    let now = Instant::now();
+    let mut queue = SendQueue::default();
+    queue.push(heads(1, 1), 100, now);
+    queue.push(keywrap_ack(1), 50, now);
+
+    queue.expire(now + STALE_SYNC_AGE - Duration::from_millis(1));
+    assert_eq!(queue.len(), 2);
+    queue.expire(now + STALE_SYNC_AGE);
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.bytes(), 50);
+}
+
+#[test]
+fn test_backpressure_pauses_sync_tasks() {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engines = Vec::new();
+    let mut stores = Vec::new();
+    for (idx, id) in room.identities.iter().enumerate() {
+        let mut engine = MerkleToxEngine::with_sk(
+            id.device_pk,
+            id.master_pk,
+            PhysicalDeviceSk::from(id.device_sk.to_bytes()),
+            StdRng::seed_from_u64(idx as u64),
+            tp.clone(),
+        );
+        let store = InMemoryStore::new();
+        room.setup_engine(&mut engine, &store);
+        engines.push(engine);
+        stores.push(store);
+    }
+    let a_pk = room.identities[0].device_pk;
+    let b_pk = room.identities[1].device_pk;
+    let cid = room.conv_id;
+
+    let from_a = engines[0].start_sync(cid, Some(b_pk), &stores[0]);
+    let from_b = engines[1].start_sync(cid, Some(a_pk), &stores[1]);
+    for (effects, to, from) in [(from_a, 1, a_pk), (from_b, 0, b_pk)] {
+        for effect in effects {
+            if let Effect::SendPacket(_, msg @ ProtocolMessage::CapsAnnounce { .. }) = effect {
+                engines[to]
+                    .handle_message(from, msg, &stores[to], None)
+                    .unwrap();
+            }
+        }
+    }
+
+    let sends_to_b = |effects: &[Effect]| {
+        effects
+            .iter()
+            .any(|e| matches!(e, Effect::SendPacket(pk, _) if *pk == b_pk))
+    };
+
+    engines[0].set_peer_backpressure(b_pk, true);
+    let effects = engines[0].poll(tp.now_instant(), &stores[0]).unwrap();
+    assert!(!sends_to_b(&effects));
+
+    engines[0].set_peer_backpressure(b_pk, false);
+    let effects = engines[0].poll(tp.now_instant(), &stores[0]).unwrap();
+    assert!(sends_to_b(&effects));
+}
@@ -0,0 +1,126 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{
+    Content, ConversationId, KConv, NodeHash, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport, VirtualHub};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type Node = MerkleToxNode<SimulatedTransport, InMemoryStore>;
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([0x42u8; 32])
+}
+
+fn node(seed: u8, hub: &Arc<VirtualHub>, time: &Arc<ManualTimeProvider>) -> Node {
+    let sk = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+    let pk = PhysicalDevicePk::from(sk.verifying_key().to_bytes());
+    let engine = MerkleToxEngine::with_sk(
+        pk,
+        pk.to_logical(),
+        PhysicalDeviceSk::from(sk.to_bytes()),
+        StdRng::seed_from_u64(seed as u64),
+        time.clone(),
+    );
+    let store = InMemoryStore::new();
+    store
+        .put_conversation_key(&conv_id(), 0, KConv::from([0xAAu8; 32]))
+        .unwrap();
+    let mut node = MerkleToxNode::new(
+        engine,
+        SimulatedTransport::new(pk, hub.clone()),
+        store,
+        time.clone(),
+    );
+    node.engine
+        .load_conversation_state(conv_id(), &node.store)
+        .unwrap();
+    node
+}
+
+#[test]
+fn test_shutdown_flushes_and_stops_taking_work() {
+    let time = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time.clone()));
+    let mut alice = node(1, &hub, &time);
+    let mut bob = node(2, &hub, &time);
+    let alice_rx = hub.register(alice.engine.self_pk);
+    let bob_rx = hub.register(bob.engine.self_pk);
+
+    let now = time.now_instant();
+    let mut wakeup = now;
+    let effects = alice
+        .engine
+        .author_node(
+            conv_id(),
+            Content::Text("hello".to_string()),
+            vec![],
+            &alice.store,
+        )
+        .unwrap();
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+    let effects = alice
+        .engine
+        .start_sync(conv_id(), Some(bob.engine.self_pk), &alice.store);
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+
+    let start = time.now_instant();
+    while bob.store.get_node_counts(&conv_id()) == (0, 0) {
+        assert!(time.now_instant().duration_since(start) < Duration::from_secs(10));
+        alice.poll();
+        while let Ok((from, data)) = alice_rx.try_recv() {
+            alice.handle_packet(from, &data);
+        }
+        bob.poll();
+        while let Ok((from, data)) = bob_rx.try_recv() {
+            bob.handle_packet(from, &data);
+        }
+        hub.poll();
+        time.advance(Duration::from_millis(100));
+    }
+
+    let summary = bob.shutdown(time.now_instant() + Duration::from_secs(5));
+    assert!(summary.store_flushed);
+    assert!(!summary.deadline_exceeded);
+    assert_eq!(summary.sessions_flushed, bob.sessions.len());
+    assert!(summary.sessions_flushed > 0);
+    assert_eq!(summary.nodes_dropped, 0);
+
+    // Nothing new is taken in.
+    let counts = bob.store.get_node_counts(&conv_id());
+    let carol_pk = PhysicalDevicePk::from([3u8; 32]);
+    bob.send_message(
+        carol_pk,
+        ProtocolMessage::KeywrapAck {
+            keywrap_hash: NodeHash::from([1u8; 32]),
+            recipient_pk: carol_pk,
+        },
+    );
+    assert!(!bob.sessions.contains_key(&carol_pk));
+    assert_eq!(bob.queued_messages(&carol_pk), 0);
+
+    alice.poll();
+    hub.poll();
+    time.advance(Duration::from_millis(100));
+    hub.poll();
+    while let Ok((from, data)) = bob_rx.try_recv() {
+        bob.handle_packet(from, &data);
+    }
+    bob.poll();
+    assert_eq!(bob.store.get_node_counts(&conv_id()), counts);
+
+    // A deadline already passed skips the sessions but still flushes.
+    let summary = bob.shutdown(time.now_instant());
+    assert!(summary.deadline_exceeded);
+    assert_eq!(summary.sessions_flushed, 0);
+    assert!(summary.store_flushed);
+}
@@ -0,0 +1,93 @@
+use merkle_tox_core::dag::{Content, validate_graph};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{SimConfig, SimEvent, SimNetwork, TestRoom};
+use proptest::prelude::*;
+use std::time::Duration;
+
+#[test]
+fn test_sim_network_converges() {
+    let room = TestRoom::new(3);
+    let mut sim = SimNetwork::with_room(&room, 7, SimConfig::default());
+
+    sim.author(0, room.conv_id, Content::Text("hello".to_string()))
+        .unwrap();
+    sim.author(2, room.conv_id, Content::Text("hi".to_string()))
+        .unwrap();
+
+    assert!(sim.run_until_converged(room.conv_id, Duration::from_secs(30)));
+    let heads = sim.heads(1, &room.conv_id);
+    assert_eq!(sim.heads(0, &room.conv_id), heads);
+    assert_eq!(heads.len(), 2);
+    assert!(sim.stats().delivered > 0);
+}
+
+#[test]
+fn test_sim_network_partition_heals() {
+    let room = TestRoom::new(4);
+    let mut sim = SimNetwork::with_room(&room, 11, SimConfig::default());
+    sim.apply(SimEvent::Partition(vec![vec![0, 1], vec![2, 3]]));
+    sim.schedule(Duration::from_secs(5), SimEvent::Heal);
+
+    let a = sim
+        .author(0, room.conv_id, Content::Text("left".to_string()))
+        .unwrap();
+    let b = sim
+        .author(3, room.conv_id, Content::Text("right".to_string()))
+        .unwrap();
+
+    sim.run_for(Duration::from_secs(3));
+    assert!(sim.peers[1].store.has_node(&a));
+    assert!(!sim.peers[2].store.has_node(&a));
+    assert!(!sim.peers[1].store.has_node(&b));
+    assert!(!sim.is_converged(&room.conv_id));
+
+    assert!(sim.run_until_converged(room.conv_id, Duration::from_secs(30)));
+    assert!(sim.peers[2].store.has_node(&a));
+    assert!(sim.peers[1].store.has_node(&b));
+}
+
+#[test]
+fn test_sim_network_is_deterministic() {
+    let room = TestRoom::new(3);
+    let config = SimConfig {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(30),
+        loss: 0.2,
+        ..SimConfig::default()
+    };
+    let run = |seed| {
+        let mut sim = SimNetwork::with_room(&room, seed, config);
+        sim.run_for(Duration::from_secs(2));
+        sim.stats()
+    };
+    assert_eq!(run(3), run(3));
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(8))]
+
+    #[test]
+    fn test_sim_network_eventual_consistency(
+        seed in any::<u64>(),
+        loss in 0.0f64..0.3,
+        authors in prop::collection::vec(0usize..3, 1..6),
+    ) {
+        let room = TestRoom::new(3);
+        let config = SimConfig {
+            jitter: Duration::from_millis(50),
+            loss,
+            ..SimConfig::default()
+        };
+        let mut sim = SimNetwork::with_room(&room, seed, config);
+        for (i, author) in authors.iter().enumerate() {
+            sim.author(*author, room.conv_id, Content::Text(format!("m{}", i)))
+                .unwrap();
+            sim.run_for(Duration::from_millis(200));
+        }
+        sim.apply(SimEvent::SetLoss(0.0));
+        prop_assert!(sim.run_until_converged(room.conv_id, Duration::from_secs(60)));
+        for peer in &sim.peers {
+            prop_assert_eq!(validate_graph(&peer.store, &room.conv_id), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{
+    ConversationId, Ed25519Signature, NodeAuth, NodeHash, PhysicalDevicePk, WireFlags, WireNode,
+};
+use merkle_tox_core::engine::config::QuarantinePolicy;
+use merkle_tox_core::engine::quarantine::{BlacklistEntry, SpamOffense};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, apply_effects};
+use merkle_tox_core::{NodeEvent, ProtocolMessage};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn engine(tp: &Arc<ManualTimeProvider>) -> MerkleToxEngine {
+    let pk = PhysicalDevicePk::from([9u8; 32]);
+    MerkleToxEngine::new(pk, pk.to_logical(), StdRng::seed_from_u64(0), tp.clone())
+}
+
+fn peer(i: u8) -> PhysicalDevicePk {
+    PhysicalDevicePk::from([i; 32])
+}
+
+/// A wire node no key unpacks, so it is kept opaque.
+fn opaque(
+    engine: &mut MerkleToxEngine,
+    from: PhysicalDevicePk,
+    i: u8,
+    store: &InMemoryStore,
+) -> Vec<Effect> {
+    let node = WireNode {
+        sender_hint: [0xFF; 4],
+        flags: WireFlags::ENCRYPTED,
+        parents: vec![],
+        encrypted_routing: vec![],
+        payload_data: vec![i; 100],
+        topological_rank: 1,
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+    };
+    let effects = engine
+        .handle_message(
+            from,
+            ProtocolMessage::MerkleNode {
+                conversation_id: ConversationId::from([1u8; 32]),
+                hash: NodeHash::from([i; 32]),
+                node,
+            },
+            store,
+            None,
+        )
+        .unwrap();
+    apply_effects(effects.clone(), store);
+    effects
+}
+
+fn stored(effects: &[Effect]) -> bool {
+    effects
+        .iter()
+        .any(|e| matches!(e, Effect::WriteWireNode(..)))
+}
+
+#[test]
+fn test_opaque_quota_per_sender() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engine = engine(&tp);
+    let store = InMemoryStore::new();
+    engine.config.quarantine = QuarantinePolicy {
+        max_opaque_nodes_per_sender: 2,
+        ..QuarantinePolicy::default()
+    };
+
+    assert!(stored(&opaque(&mut engine, peer(1), 1, &store)));
+    assert!(stored(&opaque(&mut engine, peer(1), 2, &store)));
+    assert!(!stored(&opaque(&mut engine, peer(1), 3, &store)));
+    assert!(store.get_wire_node(&NodeHash::from([3u8; 32])).is_none());
+    // Other senders have their own quota.
+    assert!(stored(&opaque(&mut engine, peer(2), 4, &store)));
+
+    let report = engine.quarantine_report(&peer(1));
+    assert_eq!(report.opaque_nodes, 2);
+    assert_eq!(report.opaque_bytes, 200);
+    assert_eq!(report.spam_score, SpamOffense::QuotaExceeded.weight());
+    assert_eq!(report.blacklisted_until, None);
+    assert_eq!(
+        engine
+            .quarantine_reports()
+            .iter()
+            .map(|r| r.device_pk)
+            .collect::<Vec<_>>(),
+        vec![peer(1), peer(2)]
+    );
+
+    let effects = engine.clear_quarantine(&peer(1));
+    assert_eq!(
+        effects
+            .iter()
+            .filter(|e| matches!(e, Effect::DeleteWireNode(..)))
+            .count(),
+        2
+    );
+    let report = engine.quarantine_report(&peer(1));
+    assert_eq!((report.opaque_nodes, report.spam_score), (0, 0));
+}
+
+#[test]
+fn test_spam_score_decays() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engine = engine(&tp);
+    let half_life = engine.config.quarantine.score_half_life;
+
+    for _ in 0..4 {
+        assert!(!engine.report_spam(peer(1), SpamOffense::InvalidPow));
+    }
+    assert_eq!(engine.spam_score(&peer(1)), 80);
+    tp.advance(half_life);
+    assert_eq!(engine.spam_score(&peer(1)), 40);
+    // Still below the limit after the points halved.
+    assert!(!engine.report_spam(peer(1), SpamOffense::InvalidPow));
+    assert_eq!(engine.spam_score(&peer(1)), 60);
+    tp.advance(half_life * 40);
+    assert_eq!(engine.spam_score(&peer(1)), 0);
+}
+
+#[test]
+fn test_spam_blacklists_and_persists() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engine = engine(&tp);
+    let store = InMemoryStore::new();
+
+    let mut blacklisted = false;
+    for _ in 0..10 {
+        blacklisted = engine.report_spam(peer(1), SpamOffense::DecodeFailure);
+    }
+    assert!(blacklisted);
+    let now_ms = tp.now_system_ms();
+    assert!(engine.is_blacklisted(&peer(1), now_ms));
+    assert_eq!(engine.spam_score(&peer(1)), 0);
+    assert!(opaque(&mut engine, peer(1), 1, &store).is_empty());
+
+    let effects = engine.poll(tp.now_instant(), &store).unwrap();
+    let expires_at_ms = effects
+        .iter()
+        .find_map(|e| match e {
+            Effect::EmitEvent(NodeEvent::PeerBlacklisted {
+                device_pk,
+                tier: 1,
+                expires_at_ms,
+            }) if *device_pk == peer(1) => Some(*expires_at_ms),
+            _ => None,
+        })
+        .unwrap();
+    apply_effects(effects, &store);
+    assert_eq!(
+        store.get_blacklist(),
+        vec![BlacklistEntry {
+            device_pk: peer(1),
+            tier: 1,
+            expires_at_ms,
+        }]
+    );
+    assert_eq!(
+        engine.quarantine_report(&peer(1)).blacklisted_until,
+        Some(expires_at_ms)
+    );
+
+    // A restarted engine keeps the peer blacklisted.
+    let mut restarted = self::engine(&tp);
+    assert_eq!(restarted.load_blacklist(&store), 1);
+    assert!(restarted.is_blacklisted(&peer(1), now_ms));
+
+    apply_effects(restarted.clear_quarantine(&peer(1)), &store);
+    assert!(!restarted.is_blacklisted(&peer(1), now_ms));
+    assert!(store.get_blacklist().is_empty());
+}
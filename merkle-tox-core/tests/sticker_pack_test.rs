@@ -0,0 +1,105 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ConversationId, NodeHash, PhysicalDevicePk, Sticker, ValidationError,
+};
+use merkle_tox_core::engine::session::{Handshake, PeerSession, SyncSession};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::sync::BlobStore;
+use merkle_tox_core::testing::{InMemoryStore, create_available_blob_info, test_node};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn sticker(shortcode: &str) -> Sticker {
+    Sticker {
+        shortcode: shortcode.to_string(),
+        hash: NodeHash::from([7u8; 32]),
+        size: 100,
+        mime_type: "image/webp".to_string(),
+    }
+}
+
+fn pack(name: &str, stickers: Vec<Sticker>) -> Content {
+    Content::StickerPack {
+        name: name.to_string(),
+        stickers,
+    }
+}
+
+#[test]
+fn test_sticker_pack_is_validated() {
+    let lookup = InMemoryStore::new();
+    let cid = ConversationId::from([0xAAu8; 32]);
+    let invalid = [
+        pack("", vec![sticker("cat")]),
+        pack("cats", vec![]),
+        pack("cats", vec![sticker("cat"), sticker("cat")]),
+        pack("cats", vec![sticker("")]),
+        pack("cats", vec![sticker("cat face")]),
+        pack("cats", vec![sticker("cat:")]),
+    ];
+    for content in invalid {
+        let mut node = test_node();
+        node.content = content;
+        assert!(matches!(
+            node.validate(&cid, &lookup),
+            Err(ValidationError::InvalidStickerPack(_))
+        ));
+    }
+
+    let mut node = test_node();
+    node.content = pack("cats", vec![sticker("cat"), sticker("cat_2-b")]);
+    assert!(!matches!(
+        node.validate(&cid, &lookup),
+        Err(ValidationError::InvalidStickerPack(_))
+    ));
+}
+
+#[test]
+fn test_request_blob_queries_conversation_peers() {
+    let self_pk = PhysicalDevicePk::from([1u8; 32]);
+    let peer = PhysicalDevicePk::from([2u8; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut engine =
+        MerkleToxEngine::new(self_pk, self_pk.to_logical(), StdRng::seed_from_u64(0), tp);
+    let store = InMemoryStore::new();
+    let cid = ConversationId::from([0xAAu8; 32]);
+    let other_cid = ConversationId::from([0xBBu8; 32]);
+    let hash = NodeHash::from([7u8; 32]);
+
+    // Without connected peers there is nobody to ask.
+    assert!(engine.request_blob(cid, hash, &store).is_empty());
+
+    for (pk, conversation_id) in [(peer, cid), (PhysicalDevicePk::from([3u8; 32]), other_cid)] {
+        let session = SyncSession::<Handshake>::new(conversation_id, &store, false, Instant::now());
+        engine.sessions.insert(
+            (pk, conversation_id),
+            PeerSession::Active(session.activate(0)),
+        );
+    }
+    let effects = engine.request_blob(cid, hash, &store);
+    assert!(matches!(
+        effects.as_slice(),
+        [Effect::SendPacket(pk, ProtocolMessage::BlobQuery(h))] if *pk == peer && *h == hash
+    ));
+
+    // The peer's answer starts the download.
+    engine
+        .handle_message(
+            peer,
+            ProtocolMessage::BlobAvail(create_available_blob_info(hash, 100)),
+            &store,
+            Some(&store),
+        )
+        .unwrap();
+    assert!(engine.blob_syncs.contains_key(&hash));
+    assert!(engine.request_blob(cid, hash, &store).is_empty());
+
+    // Stored blobs are not requested.
+    let stored = NodeHash::from([8u8; 32]);
+    store
+        .put_blob_info(create_available_blob_info(stored, 0))
+        .unwrap();
+    assert!(engine.request_blob(cid, stored, &store).is_empty());
+}
@@ -0,0 +1,129 @@
+use merkle_tox_core::NodeEvent;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::subscription::{EventFilter, EventKind};
+use merkle_tox_core::testing::InMemoryStore;
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+struct DummyTransport(PhysicalDevicePk);
+impl merkle_tox_core::Transport for DummyTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.0
+    }
+    fn send_raw(
+        &self,
+        _to: PhysicalDevicePk,
+        _data: Vec<u8>,
+    ) -> Result<(), merkle_tox_core::TransportError> {
+        Ok(())
+    }
+}
+
+fn make_node() -> MerkleToxNode<DummyTransport, InMemoryStore> {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let pk = PhysicalDevicePk::from([2u8; 32]);
+    let engine = MerkleToxEngine::new(pk, pk.to_logical(), StdRng::seed_from_u64(0), tp.clone());
+    MerkleToxNode::new(engine, DummyTransport(pk), InMemoryStore::new(), tp)
+}
+
+fn emit(node: &mut MerkleToxNode<DummyTransport, InMemoryStore>, events: Vec<NodeEvent>) {
+    let now = node.time_provider.now_instant();
+    let now_ms = node.time_provider.now_system_ms() as u64;
+    let mut next_wakeup = now;
+    node.process_effects(
+        events.into_iter().map(Effect::EmitEvent).collect(),
+        now,
+        now_ms,
+        &mut next_wakeup,
+    )
+    .unwrap();
+}
+
+fn expired(conversation_id: ConversationId, n: u8) -> NodeEvent {
+    NodeEvent::NodeExpired {
+        conversation_id,
+        hash: NodeHash::from([n; 32]),
+    }
+}
+
+#[test]
+fn test_filtered_subscribers() {
+    let mut node = make_node();
+    let conv_a = ConversationId::from([0xA; 32]);
+    let conv_b = ConversationId::from([0xB; 32]);
+    let all = node.subscribe(EventFilter::default());
+    let only_a = node.subscribe(EventFilter::conversation(conv_a));
+    let peers = node.subscribe(EventFilter::kinds([EventKind::PeerHandshakeComplete]));
+    let a_invalidated = node.subscribe(EventFilter {
+        conversation: Some(conv_a),
+        kinds: vec![EventKind::NodeInvalidated],
+    });
+
+    let handshake = NodeEvent::PeerHandshakeComplete {
+        peer_pk: PhysicalDevicePk::from([3u8; 32]),
+    };
+    emit(
+        &mut node,
+        vec![
+            expired(conv_a, 1),
+            expired(conv_b, 2),
+            handshake,
+            NodeEvent::NodeInvalidated {
+                conversation_id: conv_a,
+                hash: NodeHash::from([4u8; 32]),
+            },
+        ],
+    );
+
+    let kinds = |rx: &std::sync::mpsc::Receiver<NodeEvent>| -> Vec<_> {
+        rx.try_iter()
+            .map(|e| (e.kind(), e.conversation_id()))
+            .collect()
+    };
+    assert_eq!(
+        kinds(&all),
+        vec![
+            (EventKind::NodeExpired, Some(conv_a)),
+            (EventKind::NodeExpired, Some(conv_b)),
+            (EventKind::PeerHandshakeComplete, None),
+            (EventKind::NodeInvalidated, Some(conv_a)),
+        ]
+    );
+    assert_eq!(
+        kinds(&only_a),
+        vec![
+            (EventKind::NodeExpired, Some(conv_a)),
+            (EventKind::NodeInvalidated, Some(conv_a)),
+        ]
+    );
+    assert_eq!(
+        kinds(&peers),
+        vec![(EventKind::PeerHandshakeComplete, None)]
+    );
+    assert_eq!(
+        kinds(&a_invalidated),
+        vec![(EventKind::NodeInvalidated, Some(conv_a))]
+    );
+}
+
+#[test]
+fn test_dropped_subscriber_is_removed() {
+    let mut node = make_node();
+    let conv_a = ConversationId::from([0xA; 32]);
+    let conv_b = ConversationId::from([0xB; 32]);
+    let kept = node.subscribe(EventFilter::default());
+    let dropped = node.subscribe(EventFilter::conversation(conv_a));
+    assert_eq!(node.subscribers.len(), 2);
+    drop(dropped);
+
+    // Not noticed until an event matches the subscription.
+    emit(&mut node, vec![expired(conv_b, 1)]);
+    assert_eq!(node.subscribers.len(), 2);
+    emit(&mut node, vec![expired(conv_a, 2)]);
+    assert_eq!(node.subscribers.len(), 1);
+    assert_eq!(kept.try_iter().count(), 2);
+}
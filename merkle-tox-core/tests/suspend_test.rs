@@ -0,0 +1,186 @@
+use merkle_tox_core::cas::{BlobInfo, BlobStatus, SwarmSync};
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{
+    Content, ConversationId, KConv, NodeHash, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::engine::session::PeerSession;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport, VirtualHub};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+type Node = MerkleToxNode<SimulatedTransport, InMemoryStore>;
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([0x42u8; 32])
+}
+
+fn node_with_store(
+    seed: u8,
+    store: InMemoryStore,
+    hub: &Arc<VirtualHub>,
+    time: &Arc<ManualTimeProvider>,
+) -> Node {
+    let sk = ed25519_dalek::SigningKey::from_bytes(&[seed; 32]);
+    let pk = PhysicalDevicePk::from(sk.verifying_key().to_bytes());
+    let engine = MerkleToxEngine::with_sk(
+        pk,
+        pk.to_logical(),
+        PhysicalDeviceSk::from(sk.to_bytes()),
+        StdRng::seed_from_u64(seed as u64),
+        time.clone(),
+    );
+    let mut node = MerkleToxNode::new(
+        engine,
+        SimulatedTransport::new(pk, hub.clone()),
+        store,
+        time.clone(),
+    );
+    node.engine
+        .load_conversation_state(conv_id(), &node.store)
+        .unwrap();
+    node
+}
+
+fn node(seed: u8, hub: &Arc<VirtualHub>, time: &Arc<ManualTimeProvider>) -> Node {
+    let store = InMemoryStore::new();
+    store
+        .put_conversation_key(&conv_id(), 0, KConv::from([0xAAu8; 32]))
+        .unwrap();
+    node_with_store(seed, store, hub, time)
+}
+
+/// Steps both nodes and the hub until `done` or 10 seconds pass.
+fn run(
+    alice: &mut Node,
+    bob: &mut Node,
+    hub: &Arc<VirtualHub>,
+    time: &Arc<ManualTimeProvider>,
+    done: impl Fn(&Node) -> bool,
+) {
+    let alice_rx = hub.register(alice.engine.self_pk);
+    let bob_rx = hub.register(bob.engine.self_pk);
+    let start = time.now_instant();
+    while !done(bob) {
+        assert!(time.now_instant().duration_since(start) < Duration::from_secs(10));
+        alice.poll();
+        while let Ok((from, data)) = alice_rx.try_recv() {
+            alice.handle_packet(from, &data);
+        }
+        bob.poll();
+        while let Ok((from, data)) = bob_rx.try_recv() {
+            bob.handle_packet(from, &data);
+        }
+        hub.poll();
+        time.advance(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn test_resume_restores_sync_progress() {
+    let time = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time.clone()));
+    let mut alice = node(1, &hub, &time);
+    let mut bob = node(2, &hub, &time);
+    let alice_pk = alice.engine.self_pk;
+
+    let now = time.now_instant();
+    let mut wakeup = now;
+    let effects = alice
+        .engine
+        .author_node(
+            conv_id(),
+            Content::Text("hello".to_string()),
+            vec![],
+            &alice.store,
+        )
+        .unwrap();
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+    let effects = alice
+        .engine
+        .start_sync(conv_id(), Some(bob.engine.self_pk), &alice.store);
+    alice
+        .process_effects(effects, now, 1000, &mut wakeup)
+        .unwrap();
+    run(&mut alice, &mut bob, &hub, &time, |bob| {
+        bob.store.get_node_counts(&conv_id()) != (0, 0)
+    });
+
+    // Bob is halfway through fetching from Alice.
+    let in_flight = NodeHash::from([1u8; 32]);
+    let cold = NodeHash::from([2u8; 32]);
+    let common = bob
+        .engine
+        .sessions
+        .get_mut(&(alice_pk, conv_id()))
+        .unwrap()
+        .common_mut();
+    common.in_flight_fetches.insert(in_flight);
+    common.missing_nodes_cold.push_back(cold);
+    let blob_hash = NodeHash::from([3u8; 32]);
+    let mut sync = SwarmSync::new(BlobInfo {
+        hash: blob_hash,
+        size: 1024,
+        bao_root: None,
+        status: BlobStatus::Downloading,
+        received_mask: None,
+        decryption_key: None,
+    });
+    sync.add_seeder(alice_pk);
+    bob.engine.blob_syncs.insert(blob_hash, sync);
+
+    bob.suspend().unwrap();
+    let state = bob.store.get_suspended_state().unwrap();
+    assert_eq!(state.sessions.len(), 1);
+    assert_eq!(state.sessions[0].missing_nodes_hot, vec![in_flight]);
+    assert_eq!(state.sessions[0].missing_nodes_cold, vec![cold]);
+    assert_eq!(state.blob_downloads[0].seeders, vec![alice_pk]);
+    let path = state.paths.iter().find(|p| p.peer_pk == alice_pk).unwrap();
+    assert!(path.estimates.rtt.is_some());
+
+    // The process is killed; a new node starts on the same store.
+    let bob_pk = bob.engine.self_pk;
+    let mut bob = node_with_store(2, bob.store, &hub, &time);
+    // Alice sees Bob go offline and come back.
+    alice.set_peer_available(bob_pk, false);
+    alice.set_peer_available(bob_pk, true);
+    assert!(bob.resume().unwrap());
+    assert!(bob.store.get_suspended_state().is_none());
+    assert!(!bob.resume().unwrap());
+
+    let session = bob.engine.sessions.get(&(alice_pk, conv_id())).unwrap();
+    assert!(matches!(session, PeerSession::Handshake(_)));
+    assert!(session.common().missing_nodes_hot.contains(&in_flight));
+    assert!(session.common().missing_nodes_cold.contains(&cold));
+    assert!(
+        bob.engine.blob_syncs[&blob_hash]
+            .seeders
+            .contains(&alice_pk)
+    );
+    assert_eq!(
+        bob.sessions[&alice_pk].mtu().confirmed(),
+        path.estimates.mtu
+    );
+
+    // The handshake completes without a new sync from Alice.
+    run(&mut alice, &mut bob, &hub, &time, |bob| {
+        matches!(
+            bob.engine.sessions.get(&(alice_pk, conv_id())),
+            Some(PeerSession::Active(_))
+        )
+    });
+}
+
+#[test]
+fn test_resume_without_suspend_does_nothing() {
+    let time = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let hub = Arc::new(VirtualHub::new(time.clone()));
+    let mut bob = node(2, &hub, &time);
+    assert!(!bob.resume().unwrap());
+    assert!(bob.engine.sessions.is_empty());
+}
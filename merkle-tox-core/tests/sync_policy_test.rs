@@ -0,0 +1,174 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{
+    Content, ControlAction, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode,
+    NodeAuth, NodeHash, PhysicalDevicePk, ShardHash,
+};
+use merkle_tox_core::engine::session::{Active, Handshake, SyncSession};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine};
+use merkle_tox_core::sync::{NodeStore, SyncPolicy, SyncRange};
+use merkle_tox_core::testing::{InMemoryStore, apply_effects};
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+fn node(rank: u64, parent: u8, content: Content) -> MerkleNode {
+    MerkleNode {
+        parents: vec![NodeHash::from([parent; 32])],
+        author_pk: LogicalIdentityPk::from([0u8; 32]),
+        sender_pk: PhysicalDevicePk::from([0u8; 32]),
+        sequence_number: rank,
+        topological_rank: rank,
+        network_timestamp: 1000,
+        content,
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn recent_session(store: &InMemoryStore, window: u64) -> SyncSession<Active> {
+    let mut session =
+        SyncSession::<Handshake>::new(conv_id(), store, false, Instant::now()).activate(0);
+    session.common.rank_window = Some(window);
+    session
+}
+
+fn missing(session: &SyncSession<Active>) -> usize {
+    session.common.missing_nodes_hot.len() + session.common.missing_nodes_cold.len()
+}
+
+#[test]
+fn test_recent_policy_stops_fetching_below_horizon() {
+    let store = InMemoryStore::new();
+    let mut session = recent_session(&store, 10);
+
+    // Heads arrive first and move the horizon up.
+    session.on_node_received(&node(100, 0x63, Content::Text("tip".into())), &store, None);
+    assert_eq!(session.common.min_rank, 90);
+    assert_eq!(missing(&session), 1);
+    assert_eq!(session.common.truncated_at, None);
+
+    // A node at the horizon is kept but its parents are not fetched.
+    session.on_node_received(&node(90, 0x59, Content::Text("edge".into())), &store, None);
+    assert_eq!(missing(&session), 1);
+    assert_eq!(session.common.truncated_at, Some(90));
+
+    // The Admin track is followed past the horizon.
+    let admin = node(
+        85,
+        0x54,
+        Content::Control(ControlAction::SetRetention { max_age_ms: 1000 }),
+    );
+    session.on_node_received(&admin, &store, None);
+    assert_eq!(missing(&session), 2);
+    assert_eq!(session.common.truncated_at, Some(90));
+}
+
+#[test]
+fn test_full_policy_fetches_everything() {
+    let store = InMemoryStore::new();
+    let mut session =
+        SyncSession::<Handshake>::new(conv_id(), &store, false, Instant::now()).activate(0);
+    session.on_node_received(&node(100, 0x63, Content::Text("tip".into())), &store, None);
+    session.on_node_received(&node(1, 0x00, Content::Text("old".into())), &store, None);
+    assert_eq!(missing(&session), 2);
+    assert_eq!(session.common.min_rank, 0);
+    assert_eq!(session.common.truncated_at, None);
+}
+
+#[test]
+fn test_recent_policy_reconciles_only_inside_horizon() {
+    let store = InMemoryStore::new();
+    let mut session = recent_session(&store, 500);
+    session.common.min_rank = 1500;
+
+    let shards = session.make_sync_shard_checksums(&store).unwrap();
+    assert!(shards.iter().all(|(range, _)| range.min_rank >= 1000));
+
+    let remote = vec![
+        (
+            SyncRange {
+                min_rank: 0,
+                max_rank: 999,
+            },
+            ShardHash::from([0xAA; 32]),
+        ),
+        (
+            SyncRange {
+                min_rank: 1000,
+                max_rank: 1999,
+            },
+            ShardHash::from([0xAA; 32]),
+        ),
+    ];
+    let different = session.handle_sync_shard_checksums(remote, &store).unwrap();
+    assert_eq!(
+        different,
+        vec![SyncRange {
+            min_rank: 1500,
+            max_rank: 1999,
+        }]
+    );
+}
+
+#[test]
+fn test_engine_records_history_horizon() {
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine = MerkleToxEngine::new(
+        PhysicalDevicePk::from([1u8; 32]),
+        LogicalIdentityPk::from([1u8; 32]),
+        StdRng::seed_from_u64(0),
+        tp.clone(),
+    );
+    let store = InMemoryStore::new();
+    let peer = PhysicalDevicePk::from([2u8; 32]);
+
+    assert!(
+        engine
+            .set_sync_policy(
+                conv_id(),
+                SyncPolicy::Recent {
+                    max_rank_window: 10
+                }
+            )
+            .is_empty()
+    );
+    engine.start_sync(conv_id(), Some(peer), &store);
+    let session = engine.sessions.get_mut(&(peer, conv_id())).unwrap();
+    assert_eq!(session.common().rank_window, Some(10));
+
+    let horizon_effects = |effects: &[Effect]| -> Vec<Option<u64>> {
+        effects
+            .iter()
+            .filter_map(|e| match e {
+                Effect::WriteHistoryHorizon(_, rank) => Some(*rank),
+                _ => None,
+            })
+            .collect()
+    };
+
+    session.common_mut().truncated_at = Some(50);
+    let effects = engine.poll(Instant::now(), &store).unwrap();
+    assert_eq!(horizon_effects(&effects), vec![Some(50)]);
+    apply_effects(effects, &store);
+    assert_eq!(store.get_history_horizon(&conv_id()), Some(50));
+
+    // A later cut above the marker does not move it.
+    let session = engine.sessions.get_mut(&(peer, conv_id())).unwrap();
+    session.common_mut().truncated_at = Some(60);
+    let effects = engine.poll(Instant::now(), &store).unwrap();
+    assert!(horizon_effects(&effects).is_empty());
+
+    // Returning to full sync clears the marker.
+    let effects = engine.set_sync_policy(conv_id(), SyncPolicy::Full);
+    assert_eq!(horizon_effects(&effects), vec![None]);
+    apply_effects(effects, &store);
+    assert_eq!(store.get_history_horizon(&conv_id()), None);
+    let session = engine.sessions.get(&(peer, conv_id())).unwrap();
+    assert_eq!(session.common().rank_window, None);
+    assert_eq!(engine.sync_policy(&conv_id()), SyncPolicy::Full);
+}
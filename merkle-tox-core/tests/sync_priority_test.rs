@@ -0,0 +1,160 @@
+use merkle_tox_core::ProtocolMessage;
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::priority::{FETCH_DEFER_INTERVAL, SyncPriority, SyncScheduler};
+use merkle_tox_core::engine::session::{Handshake, PeerSession, SyncSession};
+use merkle_tox_core::engine::{Effect, MerkleToxEngine, Task};
+use merkle_tox_core::sync::SyncHeads;
+use merkle_tox_core::testing::InMemoryStore;
+use rand::{SeedableRng, rngs::StdRng};
+use std::sync::Arc;
+use std::time::Instant;
+
+fn peer() -> PhysicalDevicePk {
+    PhysicalDevicePk::from([2u8; 32])
+}
+
+fn setup(store: &InMemoryStore, convs: &[ConversationId]) -> MerkleToxEngine {
+    let self_pk = PhysicalDevicePk::from([1u8; 32]);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let mut engine =
+        MerkleToxEngine::new(self_pk, self_pk.to_logical(), StdRng::seed_from_u64(0), tp);
+    for (i, &cid) in convs.iter().enumerate() {
+        let mut session =
+            SyncSession::<Handshake>::new(cid, store, false, Instant::now()).activate(0);
+        session
+            .common
+            .missing_nodes_hot
+            .push_back(NodeHash::from([0x10 + i as u8; 32]));
+        session.common.heads_dirty = false;
+        engine
+            .sessions
+            .insert((peer(), cid), PeerSession::Active(session));
+    }
+    engine
+}
+
+fn fetched(effects: &[Effect]) -> Vec<ConversationId> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::SendPacket(_, ProtocolMessage::FetchBatchReq(req)) => Some(req.conversation_id),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_scheduler_priority_levels() {
+    let (a, b, c) = (
+        ConversationId::from([1u8; 32]),
+        ConversationId::from([2u8; 32]),
+        ConversationId::from([3u8; 32]),
+    );
+    let mut scheduler = SyncScheduler::default();
+    let now = 10 * scheduler.recent_window_ms;
+
+    scheduler.record_activity(b, now - 1000);
+    scheduler.record_activity(c, now - 2 * scheduler.recent_window_ms);
+    scheduler.set_foreground(Some(c));
+
+    assert_eq!(scheduler.priority(&c, now), SyncPriority::Foreground);
+    assert_eq!(scheduler.priority(&b, now), SyncPriority::Recent);
+    assert_eq!(scheduler.priority(&a, now), SyncPriority::Archived);
+    assert_eq!(
+        scheduler.order(vec![(peer(), a), (peer(), b), (peer(), c)], now),
+        vec![(peer(), c), (peer(), b), (peer(), a)]
+    );
+
+    // An archive hint overrides recent activity but not the foreground.
+    scheduler.set_archived(b, true);
+    scheduler.set_archived(c, true);
+    assert_eq!(scheduler.priority(&b, now), SyncPriority::Archived);
+    assert_eq!(scheduler.priority(&c, now), SyncPriority::Foreground);
+    scheduler.set_archived(b, false);
+    assert_eq!(scheduler.priority(&b, now), SyncPriority::Recent);
+}
+
+#[test]
+fn test_foreground_fetches_first_under_peer_limit() {
+    let store = InMemoryStore::new();
+    let convs: Vec<ConversationId> = (1..=3).map(|i| ConversationId::from([i; 32])).collect();
+    let mut engine = setup(&store, &convs);
+    engine.sync_scheduler.max_fetches_per_peer = 1;
+    engine.set_foreground_conversation(Some(convs[2]));
+
+    let now = Instant::now();
+    let effects = engine.poll(now, &store).unwrap();
+    assert_eq!(fetched(&effects), vec![convs[2]]);
+
+    // Deferred conversations back off instead of spinning.
+    for effect in &effects {
+        if let Effect::ScheduleWakeup(Task::SessionPoll(_, cid), at) = effect
+            && *cid != convs[2]
+        {
+            assert!(*at >= now + FETCH_DEFER_INTERVAL);
+        }
+    }
+
+    // The foreground conversation still holds the slot.
+    assert!(fetched(&engine.poll(now, &store).unwrap()).is_empty());
+
+    // Once it is answered, the next conversation gets the slot.
+    engine
+        .sessions
+        .get_mut(&(peer(), convs[2]))
+        .unwrap()
+        .common_mut()
+        .in_flight_fetches
+        .clear();
+    assert_eq!(fetched(&engine.poll(now, &store).unwrap()), vec![convs[0]]);
+}
+
+#[test]
+fn test_foreground_ignores_peer_limit() {
+    let store = InMemoryStore::new();
+    let convs: Vec<ConversationId> = (1..=2).map(|i| ConversationId::from([i; 32])).collect();
+    let mut engine = setup(&store, &convs);
+    engine.sync_scheduler.max_fetches_per_peer = 1;
+
+    let now = Instant::now();
+    assert_eq!(fetched(&engine.poll(now, &store).unwrap()), vec![convs[0]]);
+
+    engine.set_foreground_conversation(Some(convs[1]));
+    assert_eq!(engine.sync_priority(&convs[1]), SyncPriority::Foreground);
+    assert_eq!(fetched(&engine.poll(now, &store).unwrap()), vec![convs[1]]);
+}
+
+#[test]
+fn test_sync_heads_fetch_waits_for_slot() {
+    let store = InMemoryStore::new();
+    let convs: Vec<ConversationId> = (1..=2).map(|i| ConversationId::from([i; 32])).collect();
+    let mut engine = setup(&store, &convs[..1]);
+    engine.sync_scheduler.max_fetches_per_peer = 1;
+    assert_eq!(
+        fetched(&engine.poll(Instant::now(), &store).unwrap()),
+        vec![convs[0]]
+    );
+
+    let heads = |cid| {
+        ProtocolMessage::SyncHeads(SyncHeads {
+            conversation_id: cid,
+            heads: vec![NodeHash::from([0xCCu8; 32])],
+            flags: 0,
+            anchor_hash: None,
+        })
+    };
+
+    // The peer's only slot is taken, so the new conversation waits.
+    let effects = engine
+        .handle_message(peer(), heads(convs[1]), &store, None)
+        .unwrap();
+    assert!(fetched(&effects).is_empty());
+
+    // The foreground conversation fetches right away.
+    engine.set_foreground_conversation(Some(convs[1]));
+    let effects = engine
+        .handle_message(peer(), heads(convs[1]), &store, None)
+        .unwrap();
+    assert_eq!(fetched(&effects), vec![convs[1]]);
+}
@@ -29,6 +29,7 @@ fn test_blob_discovery() {
             mime_type: "image/png".to_string(),
             size: 1024,
             metadata: vec![],
+            preview: None,
         },
         metadata: vec![],
         authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
@@ -82,6 +82,7 @@ fn test_edit_must_target_text() {
             mime_type: "application/octet-stream".to_string(),
             size: 1024,
             metadata: vec![],
+            preview: None,
         },
         parent_rank + 1,
         2,
@@ -0,0 +1,153 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{Content, NodeHash};
+use merkle_tox_core::engine::Effect;
+use merkle_tox_core::engine::processor::batch::{VerifyBatch, VerifyResult};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{TestPeer, TestRoom, apply_effects};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Two peers with active sync sessions; `a` batches `batch_size` nodes.
+fn setup(batch_size: usize) -> (TestRoom, TestPeer, TestPeer) {
+    let room = TestRoom::new(2);
+    let tp = Arc::new(ManualTimeProvider::new(Instant::now(), 1000));
+    let mut a = room.peer(0, &tp);
+    let mut b = room.peer(1, &tp);
+    let cid = room.conv_id;
+
+    a.handshake(&mut b, cid);
+
+    // The first message also distributes B's sender key.
+    for hash in b.author_nodes(cid, Content::Text("hi".to_string())) {
+        a.receive_from(&b, cid, hash);
+        assert!(a.store.is_verified(&hash));
+    }
+    a.engine.config.verify_batch_size = batch_size;
+    (room, a, b)
+}
+
+fn batches(effects: &[Effect]) -> Vec<VerifyBatch> {
+    effects
+        .iter()
+        .filter_map(|e| match e {
+            Effect::VerifyBatch(batch) => Some(batch.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_full_queue_emits_batch() {
+    let (room, mut a, mut b) = setup(3);
+    let cid = room.conv_id;
+    let hashes: Vec<NodeHash> = ["one", "two", "three"]
+        .iter()
+        .flat_map(|text| b.author_nodes(cid, Content::Text(text.to_string())))
+        .collect();
+
+    for hash in &hashes[..2] {
+        let effects = a.receive_from(&b, cid, *hash);
+        assert!(effects.is_empty());
+    }
+    assert_eq!(a.engine.verify_pipeline.pending(), 2);
+
+    let effects = a.receive_from(&b, cid, hashes[2]);
+    let batch = match batches(&effects).as_slice() {
+        [batch] => batch.clone(),
+        other => panic!("expected one batch, got {:?}", other),
+    };
+    assert_eq!(batch.checks.len(), 3);
+    assert!(a.engine.verify_pipeline.is_in_flight());
+    assert!(!hashes.iter().any(|h| a.store.has_node(h)));
+
+    let result = batch.verify_parallel(2);
+    assert_eq!(result, batch.verify());
+    assert_eq!(result.valid, vec![true; 3]);
+    let effects = a
+        .engine
+        .handle_verify_result(result, &a.store, None)
+        .unwrap();
+    apply_effects(effects, &a.store);
+
+    assert_eq!(a.engine.verify_pipeline.pending(), 0);
+    for hash in &hashes {
+        assert!(a.store.is_verified(hash));
+    }
+}
+
+#[test]
+fn test_flush_sends_partial_batch() {
+    let (room, mut a, mut b) = setup(16);
+    let cid = room.conv_id;
+    let [hash] = b
+        .author_nodes(cid, Content::Text("hello".to_string()))
+        .try_into()
+        .unwrap();
+    assert!(a.receive_from(&b, cid, hash).is_empty());
+
+    let effects = a.engine.flush_verify_queue(&a.store, None).unwrap();
+    let [batch] = batches(&effects).try_into().unwrap();
+    assert_eq!(batch.checks[0].node_hash, hash);
+
+    // Nodes arriving while a batch is in flight wait for the next one.
+    let [next] = b
+        .author_nodes(cid, Content::Text("world".to_string()))
+        .try_into()
+        .unwrap();
+    assert!(a.receive_from(&b, cid, next).is_empty());
+    assert!(
+        a.engine
+            .flush_verify_queue(&a.store, None)
+            .unwrap()
+            .is_empty()
+    );
+
+    let effects = a
+        .engine
+        .handle_verify_result(batch.verify(), &a.store, None)
+        .unwrap();
+    let [next_batch] = batches(&effects).try_into().unwrap();
+    assert_eq!(next_batch.checks[0].node_hash, next);
+    apply_effects(effects, &a.store);
+    assert!(a.store.is_verified(&hash));
+}
+
+#[test]
+fn test_stale_result_is_ignored() {
+    let (room, mut a, mut b) = setup(1);
+    let cid = room.conv_id;
+    let [hash] = b
+        .author_nodes(cid, Content::Text("hello".to_string()))
+        .try_into()
+        .unwrap();
+    let [batch] = batches(&a.receive_from(&b, cid, hash)).try_into().unwrap();
+
+    let stale = VerifyResult {
+        id: batch.id + 1,
+        valid: vec![true],
+    };
+    assert!(
+        a.engine
+            .handle_verify_result(stale, &a.store, None)
+            .unwrap()
+            .is_empty()
+    );
+    assert!(a.engine.verify_pipeline.is_in_flight());
+}
+
+#[test]
+fn test_tampered_message_fails_check() {
+    let (room, mut a, mut b) = setup(1);
+    let cid = room.conv_id;
+    let [hash] = b
+        .author_nodes(cid, Content::Text("hello".to_string()))
+        .try_into()
+        .unwrap();
+    let [mut batch] = batches(&a.receive_from(&b, cid, hash)).try_into().unwrap();
+
+    assert!(batch.checks[0].verify());
+    let last = batch.checks[0].message.len() - 1;
+    batch.checks[0].message[last] ^= 1;
+    assert!(!batch.checks[0].verify());
+    assert_eq!(batch.verify().valid, vec![false]);
+}
@@ -0,0 +1,81 @@
+use merkle_tox_core::dag::{Content, ConversationId, LogicalIdentityPk, MerkleNode, NodeType};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::testing::{InMemoryStore, test_node};
+use merkle_tox_core::viz::{export_dot, export_json, visit_nodes, write_json_lines};
+
+fn node(parents: &[&MerkleNode], rank: u64, author: u8, text: &str) -> MerkleNode {
+    let mut node = test_node();
+    node.parents = parents.iter().map(|p| p.hash()).collect();
+    node.topological_rank = rank;
+    node.author_pk = LogicalIdentityPk::from([author; 32]);
+    node.content = Content::Text(text.to_string());
+    node
+}
+
+fn setup() -> (ConversationId, InMemoryStore, Vec<MerkleNode>) {
+    let conv_id = ConversationId::from([0x11u8; 32]);
+    let store = InMemoryStore::new();
+    let root = node(&[], 0, 1, "root");
+    let a = node(&[&root], 1, 1, "say \"hi\"\nthere");
+    let b = node(&[&root], 1, 2, "b");
+    let merge = node(&[&a, &b], 2, 2, "merge");
+    for n in [&root, &a, &b] {
+        store.put_node(&conv_id, n.clone(), true).unwrap();
+    }
+    store.put_node(&conv_id, merge.clone(), false).unwrap();
+    store.set_heads(&conv_id, vec![a.hash(), b.hash()]).unwrap();
+    (conv_id, store, vec![root, a, b, merge])
+}
+
+#[test]
+fn test_visit_nodes_covers_full_graph() {
+    let (conv_id, store, nodes) = setup();
+    let mut visited = Vec::new();
+    visit_nodes::<()>(&conv_id, &store, |n| {
+        visited.push(n);
+        Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(visited.len(), 4);
+    assert_eq!(visited[0].hash, nodes[0].hash());
+    assert!(visited.windows(2).take(2).all(|w| w[0].rank <= w[1].rank));
+    let last = &visited[3];
+    assert_eq!(last.hash, nodes[3].hash());
+    assert!(!last.verified);
+    assert_eq!(last.node_type, NodeType::Content);
+    assert_eq!(last.parents.len(), 2);
+    assert_ne!(visited[1].author_color(), visited[2].author_color());
+}
+
+#[test]
+fn test_export_dot_and_json() {
+    let (conv_id, store, nodes) = setup();
+
+    let dot = export_dot(&conv_id, &store);
+    assert!(dot.starts_with("digraph DAG {\n"));
+    let merge_id = hex::encode(nodes[3].hash().as_bytes());
+    assert!(dot.contains(&format!("\"{}\" [label=", merge_id)));
+    assert!(dot.contains("style=\"filled,dashed\""));
+    assert!(dot.contains("say \\\"hi\\\"\\nthere"));
+    assert_eq!(dot.matches(" -> ").count(), 4);
+
+    let json: serde_json::Value = serde_json::from_str(&export_json(&conv_id, &store)).unwrap();
+    assert_eq!(json["conversation_id"], hex::encode([0x11u8; 32]));
+    let exported = json["nodes"].as_array().unwrap();
+    assert_eq!(exported.len(), 4);
+    assert_eq!(exported[1]["summary"], "Text: say \"hi\"\nthere");
+    assert_eq!(exported[3]["hash"], merge_id);
+    assert_eq!(exported[3]["verified"], false);
+    assert_eq!(exported[3]["rank"], 2);
+    assert_eq!(exported[3]["type"], "content");
+
+    let mut lines = Vec::new();
+    write_json_lines(&conv_id, &store, &mut lines).unwrap();
+    let lines: Vec<serde_json::Value> = String::from_utf8(lines)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(&lines, exported);
+}
@@ -0,0 +1,239 @@
+#ifndef MERKLE_TOX_H
+#define MERKLE_TOX_H
+
+/* Generated by cbindgen from src/lib.rs. Do not edit. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * First byte of Tox lossy custom packets carrying Merkle-Tox data, as in
+ * `merkle-tox-tox`. The byte is not part of the data exchanged with the
+ * node: strip it before `mtox_node_handle_packet` and prepend it to what
+ * `send_packet` is given.
+ */
+#define MTOX_TOX_PACKET_ID 200
+
+/**
+ * Size of public keys, conversation IDs and node hashes.
+ */
+#define MTOX_KEY_SIZE 32
+
+/**
+ * Result of API calls.
+ */
+typedef enum MtoxErr {
+  MTOX_ERR_OK = 0,
+  /**
+   * A required pointer argument was NULL.
+   */
+  MTOX_ERR_NULL,
+  /**
+   * A string argument was not valid UTF-8.
+   */
+  MTOX_ERR_UTF8,
+  /**
+   * The store directory could not be opened.
+   */
+  MTOX_ERR_STORAGE,
+  /**
+   * The invite link is malformed or expired.
+   */
+  MTOX_ERR_INVALID_INVITE,
+  /**
+   * The conversation was not joined on this node.
+   */
+  MTOX_ERR_UNKNOWN_CONVERSATION,
+  /**
+   * The node rejected the operation.
+   */
+  MTOX_ERR_FAILED,
+} MtoxErr;
+
+/**
+ * A Merkle-Tox node and the conversations it joined.
+ */
+typedef struct MtoxNode MtoxNode;
+
+/**
+ * Sends `length` bytes of `data` to the friend with Tox public key
+ * `public_key` as a lossy custom packet, prefixed with
+ * `MTOX_TOX_PACKET_ID`. Returns false if the friend is unknown or the
+ * packet could not be sent.
+ */
+typedef bool (*MtoxSendPacketCb)(void *user_data,
+                                 const uint8_t *public_key,
+                                 const uint8_t *data,
+                                 size_t length);
+
+/**
+ * A text message from another device was verified. `author_pk` is the
+ * author's logical identity, which is `mtox_node_identity` for messages
+ * from this identity's other devices. `message` is UTF-8 and not
+ * NUL-terminated. Messages sent with `mtox_send_message` are not reported.
+ */
+typedef void (*MtoxMessageCb)(MtoxNode *node,
+                              const uint8_t *conversation_id,
+                              const uint8_t *hash,
+                              const uint8_t *author_pk,
+                              int64_t timestamp_ms,
+                              const uint8_t *message,
+                              size_t length,
+                              void *user_data);
+
+/**
+ * The Merkle-Tox handshake with the friend `public_key` completed.
+ */
+typedef void (*MtoxPeerConnectedCb)(MtoxNode *node, const uint8_t *public_key, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif  // __cplusplus
+
+/**
+ * Creates a node for the Tox instance with keys `secret_key` and
+ * `public_key`, storing conversations in `store_dir`. Conversations joined
+ * in earlier runs are loaded. `send_packet` is called with `user_data` for
+ * every packet the node sends.
+ *
+ * Returns NULL and sets `error` on failure.
+ *
+ * # Safety
+ *
+ * Keys point to MTOX_KEY_SIZE bytes; `store_dir` is a NUL-terminated
+ * string; `error` is NULL or writable. `send_packet` and `user_data` stay
+ * valid until `mtox_node_free`.
+ */
+MtoxNode *mtox_node_new(const uint8_t *secret_key,
+                        const uint8_t *public_key,
+                        const char *store_dir,
+                        MtoxSendPacketCb send_packet,
+                        void *user_data,
+                        MtoxErr *error);
+
+/**
+ * Destroys a node. Does nothing if `node` is NULL.
+ *
+ * # Safety
+ *
+ * `node` is NULL or was returned by `mtox_node_new` and not freed yet.
+ */
+void mtox_node_free(MtoxNode *node);
+
+/**
+ * Writes this node's logical identity, the author key of its messages, to
+ * `identity_pk`.
+ *
+ * # Safety
+ *
+ * `node` is a live node; `identity_pk` points to MTOX_KEY_SIZE writable
+ * bytes.
+ */
+void mtox_node_identity(const MtoxNode *node, uint8_t *identity_pk);
+
+/**
+ * Registers the callback for verified text messages.
+ *
+ * # Safety
+ *
+ * `node` is a live node.
+ */
+void mtox_callback_message(MtoxNode *node, MtoxMessageCb callback);
+
+/**
+ * Registers the callback for completed handshakes.
+ *
+ * # Safety
+ *
+ * `node` is a live node.
+ */
+void mtox_callback_peer_connected(MtoxNode *node, MtoxPeerConnectedCb callback);
+
+/**
+ * Passes the node a packet received from the friend `public_key`, without
+ * its MTOX_TOX_PACKET_ID byte.
+ *
+ * # Safety
+ *
+ * `node` is a live node; `public_key` points to MTOX_KEY_SIZE bytes and
+ * `data` to `length` bytes.
+ */
+MtoxErr mtox_node_handle_packet(MtoxNode *node,
+                                const uint8_t *public_key,
+                                const uint8_t *data,
+                                size_t length);
+
+/**
+ * Reports a change of the friend `public_key`'s connection status. On
+ * connecting, the node starts its handshake with the friend.
+ *
+ * # Safety
+ *
+ * `node` is a live node; `public_key` points to MTOX_KEY_SIZE bytes.
+ */
+MtoxErr mtox_node_set_peer_connected(MtoxNode *node, const uint8_t *public_key, bool connected);
+
+/**
+ * Runs retransmissions and pacing, then delivers the queued events to the
+ * registered callbacks with `user_data`. Returns the number of
+ * milliseconds until it should be called again.
+ *
+ * # Safety
+ *
+ * `node` is a live node.
+ */
+uint32_t mtox_iterate(MtoxNode *node, void *user_data);
+
+/**
+ * Joins the conversation `conversation_id`, syncing it from the friend
+ * `public_key` if not NULL. Use this for conversations this node is a
+ * member of, or whose members admit it without an invite.
+ *
+ * # Safety
+ *
+ * `node` is a live node; `conversation_id` points to MTOX_KEY_SIZE bytes;
+ * `public_key` is NULL or points to MTOX_KEY_SIZE bytes.
+ */
+MtoxErr mtox_join_conversation(MtoxNode *node,
+                               const uint8_t *conversation_id,
+                               const uint8_t *public_key);
+
+/**
+ * Joins a conversation from an `mtox-invite:` link and writes its ID to
+ * `conversation_id`. The inviter admits this node once it receives the
+ * node's announcement, which is sent to the devices named in the link.
+ *
+ * # Safety
+ *
+ * `node` is a live node; `link` is a NUL-terminated string;
+ * `conversation_id` is NULL or points to MTOX_KEY_SIZE writable bytes.
+ */
+MtoxErr mtox_join_invite(MtoxNode *node, const char *link, uint8_t *conversation_id);
+
+/**
+ * Sends the UTF-8 text `message` of `length` bytes to a joined
+ * conversation and writes the hash of its node to `hash`.
+ *
+ * # Safety
+ *
+ * `node` is a live node; `conversation_id` points to MTOX_KEY_SIZE bytes;
+ * `message` to `length` bytes; `hash` is NULL or points to MTOX_KEY_SIZE
+ * writable bytes.
+ */
+MtoxErr mtox_send_message(MtoxNode *node,
+                          const uint8_t *conversation_id,
+                          const uint8_t *message,
+                          size_t length,
+                          uint8_t *hash);
+
+/**
+ * Describes an error code. The string is static.
+ */
+const char *mtox_err_string(MtoxErr error);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MERKLE_TOX_H */
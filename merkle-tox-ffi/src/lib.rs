@@ -0,0 +1,693 @@
+//! C API for Merkle-Tox group chats.
+//!
+//! Lets C and C++ Tox clients run a Merkle-Tox node next to their Tox
+//! instance. The client keeps driving Tox itself: it passes the node the
+//! Merkle-Tox lossy packets it receives from friends, and sends the packets
+//! the node hands to its `send_packet` callback. Events are delivered to
+//! the registered callbacks from `mtox_iterate`, which is meant to be called
+//! next to `tox_iterate`.
+//!
+//! The header `include/merkle_tox.h` is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/merkle_tox.h`.
+//!
+//! A node is not thread-safe. Its functions must not be called from the
+//! `send_packet` callback; they may be called from event callbacks.
+
+use merkle_tox_client::MerkleToxClient;
+use merkle_tox_core::clock::SystemTimeProvider;
+use merkle_tox_core::crypto::ed25519_public_key_from_seed;
+use merkle_tox_core::dag::{Content, ConversationId, PhysicalDevicePk, PhysicalDeviceSk};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::error::MerkleToxResult;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport, TransportError};
+use merkle_tox_fs::FsStore;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::ffi::{CStr, c_char, c_void};
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
+use tokio::runtime::Runtime;
+use tokio::sync::Mutex;
+use tox_proto::time::Instant;
+use tracing::warn;
+
+/// First byte of Tox lossy custom packets carrying Merkle-Tox data, as in
+/// `merkle-tox-tox`. The byte is not part of the data exchanged with the
+/// node: strip it before `mtox_node_handle_packet` and prepend it to what
+/// `send_packet` is given.
+pub const MTOX_TOX_PACKET_ID: u8 = 200;
+
+/// Size of public keys, conversation IDs and node hashes.
+pub const MTOX_KEY_SIZE: usize = 32;
+
+/// Result of API calls.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtoxErr {
+    Ok = 0,
+    /// A required pointer argument was NULL.
+    Null,
+    /// A string argument was not valid UTF-8.
+    Utf8,
+    /// The store directory could not be opened.
+    Storage,
+    /// The invite link is malformed or expired.
+    InvalidInvite,
+    /// The conversation was not joined on this node.
+    UnknownConversation,
+    /// The node rejected the operation.
+    Failed,
+}
+
+/// Sends `length` bytes of `data` to the friend with Tox public key
+/// `public_key` as a lossy custom packet, prefixed with
+/// `MTOX_TOX_PACKET_ID`. Returns false if the friend is unknown or the
+/// packet could not be sent.
+pub type MtoxSendPacketCb = Option<
+    unsafe extern "C" fn(
+        user_data: *mut c_void,
+        public_key: *const u8,
+        data: *const u8,
+        length: usize,
+    ) -> bool,
+>;
+
+/// A text message from another device was verified. `author_pk` is the
+/// author's logical identity, which is `mtox_node_identity` for messages
+/// from this identity's other devices. `message` is UTF-8 and not
+/// NUL-terminated. Messages sent with `mtox_send_message` are not reported.
+pub type MtoxMessageCb = Option<
+    unsafe extern "C" fn(
+        node: *mut MtoxNode,
+        conversation_id: *const u8,
+        hash: *const u8,
+        author_pk: *const u8,
+        timestamp_ms: i64,
+        message: *const u8,
+        length: usize,
+        user_data: *mut c_void,
+    ),
+>;
+
+/// The Merkle-Tox handshake with the friend `public_key` completed.
+pub type MtoxPeerConnectedCb = Option<
+    unsafe extern "C" fn(node: *mut MtoxNode, public_key: *const u8, user_data: *mut c_void),
+>;
+
+/// Transport over the client's Tox instance, reached through its callback.
+struct CallbackTransport {
+    local_pk: PhysicalDevicePk,
+    send_packet: MtoxSendPacketCb,
+    user_data: *mut c_void,
+}
+
+// The callback and its user data are only used from the thread driving the
+// node; see the crate documentation.
+unsafe impl Send for CallbackTransport {}
+unsafe impl Sync for CallbackTransport {}
+
+impl Transport for CallbackTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.local_pk
+    }
+
+    fn send_raw(&self, to: PhysicalDevicePk, data: Vec<u8>) -> Result<(), TransportError> {
+        let Some(send_packet) = self.send_packet else {
+            return Err(TransportError::Other("no send_packet callback".to_string()));
+        };
+        // SAFETY: the caller of `mtox_node_new` guarantees the callback is
+        // valid for the lifetime of the node.
+        let sent = unsafe {
+            send_packet(
+                self.user_data,
+                to.as_bytes().as_ptr(),
+                data.as_ptr(),
+                data.len(),
+            )
+        };
+        if sent {
+            Ok(())
+        } else {
+            Err(TransportError::PeerNotFound(hex::encode(to.as_bytes())))
+        }
+    }
+}
+
+/// Queues node events until `mtox_iterate` drains them.
+struct EventQueue {
+    tx: mpsc::Sender<NodeEvent>,
+}
+
+impl NodeEventHandler for EventQueue {
+    fn handle_event(&self, event: NodeEvent) {
+        let _ = self.tx.send(event);
+    }
+}
+
+type Node = MerkleToxNode<CallbackTransport, FsStore>;
+type Client = MerkleToxClient<CallbackTransport, FsStore>;
+
+#[derive(Clone, Copy, Default)]
+struct Callbacks {
+    message: MtoxMessageCb,
+    peer_connected: MtoxPeerConnectedCb,
+}
+
+/// Event copied out of the node, delivered once no state is borrowed.
+enum CallbackEvent {
+    Message {
+        conversation_id: ConversationId,
+        hash: [u8; 32],
+        author_pk: [u8; 32],
+        timestamp_ms: i64,
+        text: String,
+    },
+    PeerConnected {
+        public_key: [u8; 32],
+    },
+}
+
+/// A Merkle-Tox node and the conversations it joined.
+pub struct MtoxNode {
+    runtime: Runtime,
+    node: Arc<Mutex<Node>>,
+    clients: RefCell<HashMap<ConversationId, Client>>,
+    events: mpsc::Receiver<NodeEvent>,
+    callbacks: Cell<Callbacks>,
+}
+
+impl MtoxNode {
+    fn open(
+        secret_key: [u8; 32],
+        public_key: PhysicalDevicePk,
+        store_dir: PathBuf,
+        send_packet: MtoxSendPacketCb,
+        user_data: *mut c_void,
+    ) -> Result<Self, MtoxErr> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|_| MtoxErr::Failed)?;
+        // The protocol identity is derived from the Tox secret key, as in
+        // `ToxMerkleBridge`; the transport is addressed by Tox public keys.
+        let self_pk = PhysicalDevicePk::from(ed25519_public_key_from_seed(&secret_key));
+        let engine = MerkleToxEngine::with_sk(
+            self_pk,
+            self_pk.to_logical(),
+            PhysicalDeviceSk::from(secret_key),
+            rand::SeedableRng::from_entropy(),
+            Arc::new(SystemTimeProvider),
+        );
+        let store =
+            FsStore::new(store_dir, Arc::new(StdFileSystem)).map_err(|_| MtoxErr::Storage)?;
+        let conversations = store.conversations();
+        let transport = CallbackTransport {
+            local_pk: public_key,
+            send_packet,
+            user_data,
+        };
+        let mut node = MerkleToxNode::new(engine, transport, store, Arc::new(SystemTimeProvider));
+        let (tx, events) = mpsc::channel();
+        node.set_event_handler(Arc::new(EventQueue { tx }));
+
+        let this = Self {
+            runtime,
+            node: Arc::new(Mutex::new(node)),
+            clients: RefCell::new(HashMap::new()),
+            events,
+            callbacks: Cell::new(Callbacks::default()),
+        };
+        for conversation_id in conversations {
+            if let Err(e) = this.open_conversation(conversation_id) {
+                warn!("Failed to load conversation {:?}: {}", conversation_id, e);
+            }
+        }
+        Ok(this)
+    }
+
+    /// Creates the client of a conversation and loads its state, if not
+    /// done yet.
+    fn open_conversation(&self, conversation_id: ConversationId) -> MerkleToxResult<()> {
+        if self.clients.borrow().contains_key(&conversation_id) {
+            return Ok(());
+        }
+        let client = MerkleToxClient::new(self.node.clone(), conversation_id);
+        let result = self.runtime.block_on(client.refresh_state());
+        self.clients.borrow_mut().insert(conversation_id, client);
+        result
+    }
+
+    fn join(
+        &self,
+        conversation_id: ConversationId,
+        peer_pk: Option<PhysicalDevicePk>,
+    ) -> MerkleToxResult<()> {
+        {
+            let mut node = self.node.blocking_lock();
+            let node = &mut *node;
+            let effects = node
+                .engine
+                .start_sync(conversation_id, peer_pk, &node.store);
+            let now = node.time_provider.now_instant();
+            let now_ms = node.time_provider.now_system_ms() as u64;
+            let mut next_wakeup = now;
+            node.process_effects(effects, now, now_ms, &mut next_wakeup)?;
+        }
+        self.open_conversation(conversation_id)
+    }
+
+    /// Applies the queued node events to the clients and returns the ones
+    /// to report.
+    fn drain(&self) -> Vec<CallbackEvent> {
+        let mut reported = Vec::new();
+        while let Ok(event) = self.events.try_recv() {
+            let conversation_id = match &event {
+                NodeEvent::NodeVerified {
+                    conversation_id,
+                    hash,
+                    node,
+                } => {
+                    if let Content::Text(text) = &node.content {
+                        reported.push(CallbackEvent::Message {
+                            conversation_id: *conversation_id,
+                            hash: *hash.as_bytes(),
+                            author_pk: *node.author_pk.as_bytes(),
+                            timestamp_ms: node.network_timestamp,
+                            text: text.clone(),
+                        });
+                    }
+                    *conversation_id
+                }
+                NodeEvent::NodeInvalidated {
+                    conversation_id, ..
+                }
+                | NodeEvent::NodeExpired {
+                    conversation_id, ..
+                } => *conversation_id,
+                NodeEvent::PeerHandshakeComplete { peer_pk } => {
+                    reported.push(CallbackEvent::PeerConnected {
+                        public_key: *peer_pk.as_bytes(),
+                    });
+                    for client in self.clients.borrow().values() {
+                        let event = NodeEvent::PeerHandshakeComplete { peer_pk: *peer_pk };
+                        if let Err(e) = self.runtime.block_on(client.handle_event(event)) {
+                            warn!("Failed to apply handshake: {}", e);
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            // A conversation we were admitted to after an invite has no
+            // client yet.
+            let mut clients = self.clients.borrow_mut();
+            let client = clients
+                .entry(conversation_id)
+                .or_insert_with(|| MerkleToxClient::new(self.node.clone(), conversation_id));
+            if let Err(e) = self.runtime.block_on(client.handle_event(event)) {
+                warn!("Failed to apply event: {}", e);
+            }
+        }
+        reported
+    }
+}
+
+fn read_key(ptr: *const u8) -> Option<[u8; 32]> {
+    if ptr.is_null() {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    // SAFETY: the caller passes MTOX_KEY_SIZE readable bytes.
+    unsafe { std::ptr::copy_nonoverlapping(ptr, key.as_mut_ptr(), key.len()) };
+    Some(key)
+}
+
+fn write_key(ptr: *mut u8, key: &[u8; 32]) {
+    if !ptr.is_null() {
+        // SAFETY: the caller passes MTOX_KEY_SIZE writable bytes.
+        unsafe { std::ptr::copy_nonoverlapping(key.as_ptr(), ptr, key.len()) };
+    }
+}
+
+fn read_str<'a>(ptr: *const c_char) -> Result<&'a str, MtoxErr> {
+    if ptr.is_null() {
+        return Err(MtoxErr::Null);
+    }
+    // SAFETY: the caller passes a NUL-terminated string.
+    unsafe { CStr::from_ptr(ptr) }
+        .to_str()
+        .map_err(|_| MtoxErr::Utf8)
+}
+
+fn set_error(error: *mut MtoxErr, value: MtoxErr) {
+    if !error.is_null() {
+        // SAFETY: the caller passes NULL or a writable MtoxErr.
+        unsafe { *error = value };
+    }
+}
+
+fn result(r: Result<(), MtoxErr>) -> MtoxErr {
+    r.err().unwrap_or(MtoxErr::Ok)
+}
+
+/// Creates a node for the Tox instance with keys `secret_key` and
+/// `public_key`, storing conversations in `store_dir`. Conversations joined
+/// in earlier runs are loaded. `send_packet` is called with `user_data` for
+/// every packet the node sends.
+///
+/// Returns NULL and sets `error` on failure.
+///
+/// # Safety
+///
+/// Keys point to MTOX_KEY_SIZE bytes; `store_dir` is a NUL-terminated
+/// string; `error` is NULL or writable. `send_packet` and `user_data` stay
+/// valid until `mtox_node_free`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_node_new(
+    secret_key: *const u8,
+    public_key: *const u8,
+    store_dir: *const c_char,
+    send_packet: MtoxSendPacketCb,
+    user_data: *mut c_void,
+    error: *mut MtoxErr,
+) -> *mut MtoxNode {
+    let opened = (|| {
+        let secret_key = read_key(secret_key).ok_or(MtoxErr::Null)?;
+        let public_key = read_key(public_key).ok_or(MtoxErr::Null)?;
+        let store_dir = PathBuf::from(read_str(store_dir)?);
+        MtoxNode::open(
+            secret_key,
+            PhysicalDevicePk::from(public_key),
+            store_dir,
+            send_packet,
+            user_data,
+        )
+    })();
+    match opened {
+        Ok(node) => {
+            set_error(error, MtoxErr::Ok);
+            Box::into_raw(Box::new(node))
+        }
+        Err(e) => {
+            set_error(error, e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Destroys a node. Does nothing if `node` is NULL.
+///
+/// # Safety
+///
+/// `node` is NULL or was returned by `mtox_node_new` and not freed yet.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_node_free(node: *mut MtoxNode) {
+    if !node.is_null() {
+        // SAFETY: see above.
+        drop(unsafe { Box::from_raw(node) });
+    }
+}
+
+/// Writes this node's logical identity, the author key of its messages, to
+/// `identity_pk`.
+///
+/// # Safety
+///
+/// `node` is a live node; `identity_pk` points to MTOX_KEY_SIZE writable
+/// bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_node_identity(node: *const MtoxNode, identity_pk: *mut u8) {
+    // SAFETY: see above.
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return;
+    };
+    let identity = node.node.blocking_lock().engine.self_logical_pk;
+    write_key(identity_pk, identity.as_bytes());
+}
+
+/// Registers the callback for verified text messages.
+///
+/// # Safety
+///
+/// `node` is a live node.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_callback_message(node: *mut MtoxNode, callback: MtoxMessageCb) {
+    // SAFETY: see above.
+    if let Some(node) = unsafe { node.as_ref() } {
+        node.callbacks.set(Callbacks {
+            message: callback,
+            ..node.callbacks.get()
+        });
+    }
+}
+
+/// Registers the callback for completed handshakes.
+///
+/// # Safety
+///
+/// `node` is a live node.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_callback_peer_connected(
+    node: *mut MtoxNode,
+    callback: MtoxPeerConnectedCb,
+) {
+    // SAFETY: see above.
+    if let Some(node) = unsafe { node.as_ref() } {
+        node.callbacks.set(Callbacks {
+            peer_connected: callback,
+            ..node.callbacks.get()
+        });
+    }
+}
+
+/// Passes the node a packet received from the friend `public_key`, without
+/// its MTOX_TOX_PACKET_ID byte.
+///
+/// # Safety
+///
+/// `node` is a live node; `public_key` points to MTOX_KEY_SIZE bytes and
+/// `data` to `length` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_node_handle_packet(
+    node: *mut MtoxNode,
+    public_key: *const u8,
+    data: *const u8,
+    length: usize,
+) -> MtoxErr {
+    // SAFETY: see above.
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return MtoxErr::Null;
+    };
+    let Some(from) = read_key(public_key) else {
+        return MtoxErr::Null;
+    };
+    if data.is_null() {
+        return MtoxErr::Null;
+    }
+    // SAFETY: see above.
+    let data = unsafe { std::slice::from_raw_parts(data, length) };
+    node.node
+        .blocking_lock()
+        .handle_packet(PhysicalDevicePk::from(from), data);
+    MtoxErr::Ok
+}
+
+/// Reports a change of the friend `public_key`'s connection status. On
+/// connecting, the node starts its handshake with the friend.
+///
+/// # Safety
+///
+/// `node` is a live node; `public_key` points to MTOX_KEY_SIZE bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_node_set_peer_connected(
+    node: *mut MtoxNode,
+    public_key: *const u8,
+    connected: bool,
+) -> MtoxErr {
+    // SAFETY: see above.
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return MtoxErr::Null;
+    };
+    let Some(peer_pk) = read_key(public_key).map(PhysicalDevicePk::from) else {
+        return MtoxErr::Null;
+    };
+    let mut node = node.node.blocking_lock();
+    node.set_peer_available(peer_pk, connected);
+    if connected {
+        let caps = node.engine.caps_announce();
+        node.send_message(peer_pk, caps);
+    }
+    MtoxErr::Ok
+}
+
+/// Runs retransmissions and pacing, then delivers the queued events to the
+/// registered callbacks with `user_data`. Returns the number of
+/// milliseconds until it should be called again.
+///
+/// # Safety
+///
+/// `node` is a live node.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_iterate(node: *mut MtoxNode, user_data: *mut c_void) -> u32 {
+    // SAFETY: see above.
+    let Some(this) = (unsafe { node.as_ref() }) else {
+        return 0;
+    };
+    let next_wakeup = this.node.blocking_lock().poll();
+    let events = this.drain();
+    let callbacks = this.callbacks.get();
+    for event in events {
+        match event {
+            CallbackEvent::Message {
+                conversation_id,
+                hash,
+                author_pk,
+                timestamp_ms,
+                text,
+            } => {
+                if let Some(callback) = callbacks.message {
+                    // SAFETY: the callback was registered for this node.
+                    unsafe {
+                        callback(
+                            node,
+                            conversation_id.as_bytes().as_ptr(),
+                            hash.as_ptr(),
+                            author_pk.as_ptr(),
+                            timestamp_ms,
+                            text.as_ptr(),
+                            text.len(),
+                            user_data,
+                        )
+                    };
+                }
+            }
+            CallbackEvent::PeerConnected { public_key } => {
+                if let Some(callback) = callbacks.peer_connected {
+                    // SAFETY: the callback was registered for this node.
+                    unsafe { callback(node, public_key.as_ptr(), user_data) };
+                }
+            }
+        }
+    }
+    let wait = next_wakeup.saturating_duration_since(Instant::now());
+    u32::try_from(wait.as_millis()).unwrap_or(u32::MAX)
+}
+
+/// Joins the conversation `conversation_id`, syncing it from the friend
+/// `public_key` if not NULL. Use this for conversations this node is a
+/// member of, or whose members admit it without an invite.
+///
+/// # Safety
+///
+/// `node` is a live node; `conversation_id` points to MTOX_KEY_SIZE bytes;
+/// `public_key` is NULL or points to MTOX_KEY_SIZE bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_join_conversation(
+    node: *mut MtoxNode,
+    conversation_id: *const u8,
+    public_key: *const u8,
+) -> MtoxErr {
+    // SAFETY: see above.
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return MtoxErr::Null;
+    };
+    let Some(conversation_id) = read_key(conversation_id).map(ConversationId::from) else {
+        return MtoxErr::Null;
+    };
+    let peer_pk = read_key(public_key).map(PhysicalDevicePk::from);
+    result(
+        node.join(conversation_id, peer_pk)
+            .map_err(|_| MtoxErr::Failed),
+    )
+}
+
+/// Joins a conversation from an `mtox-invite:` link and writes its ID to
+/// `conversation_id`. The inviter admits this node once it receives the
+/// node's announcement, which is sent to the devices named in the link.
+///
+/// # Safety
+///
+/// `node` is a live node; `link` is a NUL-terminated string;
+/// `conversation_id` is NULL or points to MTOX_KEY_SIZE writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_join_invite(
+    node: *mut MtoxNode,
+    link: *const c_char,
+    conversation_id: *mut u8,
+) -> MtoxErr {
+    // SAFETY: see above.
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return MtoxErr::Null;
+    };
+    result((|| {
+        let link = read_str(link)?;
+        let token = node
+            .node
+            .blocking_lock()
+            .join_from_invite(link)
+            .map_err(|_| MtoxErr::InvalidInvite)?;
+        write_key(conversation_id, token.conversation_id.as_bytes());
+        node.open_conversation(token.conversation_id)
+            .map_err(|_| MtoxErr::Failed)
+    })())
+}
+
+/// Sends the UTF-8 text `message` of `length` bytes to a joined
+/// conversation and writes the hash of its node to `hash`.
+///
+/// # Safety
+///
+/// `node` is a live node; `conversation_id` points to MTOX_KEY_SIZE bytes;
+/// `message` to `length` bytes; `hash` is NULL or points to MTOX_KEY_SIZE
+/// writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mtox_send_message(
+    node: *mut MtoxNode,
+    conversation_id: *const u8,
+    message: *const u8,
+    length: usize,
+    hash: *mut u8,
+) -> MtoxErr {
+    // SAFETY: see above.
+    let Some(node) = (unsafe { node.as_ref() }) else {
+        return MtoxErr::Null;
+    };
+    result((|| {
+        let conversation_id = read_key(conversation_id)
+            .map(ConversationId::from)
+            .ok_or(MtoxErr::Null)?;
+        if message.is_null() {
+            return Err(MtoxErr::Null);
+        }
+        // SAFETY: see above.
+        let bytes = unsafe { std::slice::from_raw_parts(message, length) };
+        let text = std::str::from_utf8(bytes).map_err(|_| MtoxErr::Utf8)?;
+        let clients = node.clients.borrow();
+        let client = clients
+            .get(&conversation_id)
+            .ok_or(MtoxErr::UnknownConversation)?;
+        let sent = node
+            .runtime
+            .block_on(client.send_message(text.to_string()))
+            .map_err(|_| MtoxErr::Failed)?;
+        write_key(hash, sent.as_bytes());
+        Ok(())
+    })())
+}
+
+/// Describes an error code. The string is static.
+#[unsafe(no_mangle)]
+pub extern "C" fn mtox_err_string(error: MtoxErr) -> *const c_char {
+    let s: &'static CStr = match error {
+        MtoxErr::Ok => c"success",
+        MtoxErr::Null => c"required argument is NULL",
+        MtoxErr::Utf8 => c"string is not valid UTF-8",
+        MtoxErr::Storage => c"store could not be opened",
+        MtoxErr::InvalidInvite => c"invite link is malformed or expired",
+        MtoxErr::UnknownConversation => c"conversation not joined",
+        MtoxErr::Failed => c"operation failed",
+    };
+    s.as_ptr()
+}
@@ -0,0 +1,321 @@
+use merkle_tox_client::MerkleToxClient;
+use merkle_tox_core::clock::{SystemTimeProvider, TimeProvider};
+use merkle_tox_core::crypto::ed25519_public_key_from_seed;
+use merkle_tox_core::dag::{
+    ConversationId, LogicalIdentityPk, NodeHash, Permissions, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::identity::{CausalContext, sign_delegation};
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport, TransportError};
+use merkle_tox_ffi::*;
+use merkle_tox_sqlite::Storage;
+use rand::{SeedableRng, rngs::StdRng};
+use std::ffi::{CStr, CString, c_void};
+use std::sync::{Arc, Mutex, mpsc};
+
+/// Packets sent by one side, as (to, data).
+type Outbox = Mutex<Vec<([u8; 32], Vec<u8>)>>;
+
+struct QueueTransport {
+    local_pk: PhysicalDevicePk,
+    outbox: Arc<Outbox>,
+}
+
+impl Transport for QueueTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.local_pk
+    }
+    fn send_raw(&self, to: PhysicalDevicePk, data: Vec<u8>) -> Result<(), TransportError> {
+        self.outbox.lock().unwrap().push((*to.as_bytes(), data));
+        Ok(())
+    }
+}
+
+struct EventQueue(mpsc::Sender<NodeEvent>);
+
+impl NodeEventHandler for EventQueue {
+    fn handle_event(&self, event: NodeEvent) {
+        let _ = self.0.send(event);
+    }
+}
+
+unsafe extern "C" fn send_packet(
+    user_data: *mut c_void,
+    public_key: *const u8,
+    data: *const u8,
+    length: usize,
+) -> bool {
+    let outbox = unsafe { &*(user_data as *const Outbox) };
+    let to = unsafe { *(public_key as *const [u8; 32]) };
+    let data = unsafe { std::slice::from_raw_parts(data, length) }.to_vec();
+    outbox.lock().unwrap().push((to, data));
+    true
+}
+
+#[derive(Default)]
+struct Received {
+    messages: Vec<(ConversationId, [u8; 32], String)>,
+    peers: Vec<[u8; 32]>,
+}
+
+unsafe extern "C" fn on_message(
+    _node: *mut MtoxNode,
+    conversation_id: *const u8,
+    _hash: *const u8,
+    author_pk: *const u8,
+    _timestamp_ms: i64,
+    message: *const u8,
+    length: usize,
+    user_data: *mut c_void,
+) {
+    let received = unsafe { &mut *(user_data as *mut Received) };
+    let conversation_id = ConversationId::from(unsafe { *(conversation_id as *const [u8; 32]) });
+    let author_pk = unsafe { *(author_pk as *const [u8; 32]) };
+    let text = unsafe { std::slice::from_raw_parts(message, length) };
+    received.messages.push((
+        conversation_id,
+        author_pk,
+        String::from_utf8(text.to_vec()).unwrap(),
+    ));
+}
+
+unsafe extern "C" fn on_peer_connected(
+    _node: *mut MtoxNode,
+    public_key: *const u8,
+    user_data: *mut c_void,
+) {
+    let received = unsafe { &mut *(user_data as *mut Received) };
+    received
+        .peers
+        .push(unsafe { *(public_key as *const [u8; 32]) });
+}
+
+fn new_node(secret_key: &[u8; 32], dir: &tempfile::TempDir, outbox: &Outbox) -> *mut MtoxNode {
+    let public_key = ed25519_public_key_from_seed(secret_key);
+    let store_dir = CString::new(dir.path().to_str().unwrap()).unwrap();
+    let mut error = MtoxErr::Failed;
+    let node = unsafe {
+        mtox_node_new(
+            secret_key.as_ptr(),
+            public_key.as_ptr(),
+            store_dir.as_ptr(),
+            Some(send_packet),
+            outbox as *const Outbox as *mut c_void,
+            &mut error,
+        )
+    };
+    assert_eq!(error, MtoxErr::Ok);
+    assert!(!node.is_null());
+    node
+}
+
+#[test]
+fn test_node_lifecycle_and_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let outbox = Outbox::default();
+    let secret_key = [7u8; 32];
+    let node = new_node(&secret_key, &dir, &outbox);
+
+    let mut identity = [0u8; 32];
+    unsafe { mtox_node_identity(node, identity.as_mut_ptr()) };
+    assert_eq!(identity, ed25519_public_key_from_seed(&secret_key));
+
+    let conversation_id = [0xAAu8; 32];
+    let text = b"hello";
+    let err = unsafe {
+        mtox_send_message(
+            node,
+            conversation_id.as_ptr(),
+            text.as_ptr(),
+            text.len(),
+            std::ptr::null_mut(),
+        )
+    };
+    assert_eq!(err, MtoxErr::UnknownConversation);
+    let invalid = [0xFFu8, 0xFE];
+    let err = unsafe {
+        mtox_send_message(
+            node,
+            conversation_id.as_ptr(),
+            invalid.as_ptr(),
+            invalid.len(),
+            std::ptr::null_mut(),
+        )
+    };
+    assert_eq!(err, MtoxErr::Utf8);
+
+    let link = CString::new("mtox-invite:AAAA").unwrap();
+    let err = unsafe { mtox_join_invite(node, link.as_ptr(), std::ptr::null_mut()) };
+    assert_eq!(err, MtoxErr::InvalidInvite);
+    let err = unsafe { mtox_join_invite(node, std::ptr::null(), std::ptr::null_mut()) };
+    assert_eq!(err, MtoxErr::Null);
+
+    let description = unsafe { CStr::from_ptr(mtox_err_string(MtoxErr::UnknownConversation)) };
+    assert_eq!(description.to_str().unwrap(), "conversation not joined");
+
+    unsafe { mtox_node_free(node) };
+    unsafe { mtox_node_free(std::ptr::null_mut()) };
+
+    let mut error = MtoxErr::Ok;
+    let node = unsafe {
+        mtox_node_new(
+            secret_key.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            None,
+            std::ptr::null_mut(),
+            &mut error,
+        )
+    };
+    assert!(node.is_null());
+    assert_eq!(error, MtoxErr::Null);
+}
+
+#[test]
+fn test_join_invite() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let conversation_id = ConversationId::from([0xAA; 32]);
+
+    // Alice runs a Rust node and founds the conversation.
+    let alice_sk = [10u8; 32];
+    let alice_signing_key = ed25519_dalek::SigningKey::from_bytes(&alice_sk);
+    let alice_master_pk = LogicalIdentityPk::from(alice_signing_key.verifying_key().to_bytes());
+    let alice_device_pk = PhysicalDevicePk::from(alice_signing_key.verifying_key().to_bytes());
+    let alice_outbox = Arc::new(Outbox::default());
+    let tp = Arc::new(SystemTimeProvider);
+    let mut alice = MerkleToxNode::new(
+        MerkleToxEngine::with_sk(
+            alice_device_pk,
+            alice_master_pk,
+            PhysicalDeviceSk::from(alice_sk),
+            StdRng::seed_from_u64(0),
+            tp.clone(),
+        ),
+        QueueTransport {
+            local_pk: alice_device_pk,
+            outbox: alice_outbox.clone(),
+        },
+        Storage::open_in_memory().unwrap(),
+        tp,
+    );
+    let (alice_tx, alice_events) = mpsc::channel();
+    alice.set_event_handler(Arc::new(EventQueue(alice_tx)));
+    {
+        let MerkleToxNode { engine, store, .. } = &mut alice;
+        engine
+            .identity_manager
+            .add_member(conversation_id, alice_master_pk, 1, 0);
+        let cert = sign_delegation(
+            &alice_signing_key,
+            alice_device_pk,
+            Permissions::ALL,
+            i64::MAX,
+            conversation_id,
+        );
+        engine
+            .identity_manager
+            .authorize_device(
+                &CausalContext::global(),
+                conversation_id,
+                alice_master_pk,
+                &cert,
+                0,
+                0,
+                NodeHash::from([0u8; 32]),
+            )
+            .unwrap();
+        let effects = engine
+            .rotate_conversation_key(conversation_id, store)
+            .unwrap();
+        let now = alice.time_provider.now_instant();
+        let now_ms = alice.time_provider.now_system_ms() as u64;
+        let mut next_wakeup = now;
+        alice
+            .process_effects(effects, now, now_ms, &mut next_wakeup)
+            .unwrap();
+    }
+    let alice = Arc::new(tokio::sync::Mutex::new(alice));
+    let alice_client = MerkleToxClient::new(alice.clone(), conversation_id);
+    let link = runtime
+        .block_on(alice_client.create_invite_link(SystemTimeProvider.now_system_ms() + 3_600_000))
+        .unwrap();
+
+    // Bob drives his node through the C API.
+    let bob_sk = [20u8; 32];
+    let bob_pk = ed25519_public_key_from_seed(&bob_sk);
+    let dir = tempfile::tempdir().unwrap();
+    let bob_outbox = Outbox::default();
+    let bob = new_node(&bob_sk, &dir, &bob_outbox);
+    let mut received = Received::default();
+    unsafe {
+        mtox_callback_message(bob, Some(on_message));
+        mtox_callback_peer_connected(bob, Some(on_peer_connected));
+    }
+
+    let link = CString::new(link).unwrap();
+    let mut joined = [0u8; 32];
+    let err = unsafe { mtox_join_invite(bob, link.as_ptr(), joined.as_mut_ptr()) };
+    assert_eq!(err, MtoxErr::Ok);
+    assert_eq!(joined, *conversation_id.as_bytes());
+    let err =
+        unsafe { mtox_node_set_peer_connected(bob, alice_device_pk.as_bytes().as_ptr(), true) };
+    assert_eq!(err, MtoxErr::Ok);
+
+    // Relays packets between Alice and Bob. Bob's packets to anyone but
+    // Alice are dropped, as Tox would for non-friends.
+    let relay = |received: &mut Received| {
+        for (to, data) in std::mem::take(&mut *bob_outbox.lock().unwrap()) {
+            if to == *alice_device_pk.as_bytes() {
+                alice
+                    .blocking_lock()
+                    .handle_packet(PhysicalDevicePk::from(bob_pk), &data);
+            }
+        }
+        alice.blocking_lock().poll();
+        while let Ok(event) = alice_events.try_recv() {
+            runtime.block_on(alice_client.handle_event(event)).unwrap();
+        }
+        for (to, data) in std::mem::take(&mut *alice_outbox.lock().unwrap()) {
+            assert_eq!(to, bob_pk);
+            let err = unsafe {
+                mtox_node_handle_packet(
+                    bob,
+                    alice_device_pk.as_bytes().as_ptr(),
+                    data.as_ptr(),
+                    data.len(),
+                )
+            };
+            assert_eq!(err, MtoxErr::Ok);
+        }
+        unsafe { mtox_iterate(bob, received as *mut Received as *mut c_void) };
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    for _ in 0..100 {
+        relay(&mut received);
+        if !received.peers.is_empty() {
+            break;
+        }
+    }
+    assert!(received.peers.contains(alice_device_pk.as_bytes()));
+
+    // Bob is not admitted until Alice receives his Announcement and shares
+    // the conversation key, so he cannot write yet.
+    let text = "Hello";
+    let err = unsafe {
+        mtox_send_message(
+            bob,
+            joined.as_ptr(),
+            text.as_ptr(),
+            text.len(),
+            std::ptr::null_mut(),
+        )
+    };
+    assert_eq!(err, MtoxErr::Failed);
+    assert!(received.messages.is_empty());
+
+    unsafe { mtox_node_free(bob) };
+}
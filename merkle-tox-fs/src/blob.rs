@@ -1,6 +1,8 @@
+use crate::decode_hex_32;
 use merkle_tox_core::cas::{BlobInfo, BlobStatus, CHUNK_SIZE};
-use merkle_tox_core::dag::NodeHash;
+use merkle_tox_core::dag::{ConversationId, NodeHash};
 use merkle_tox_core::vfs::FileSystem;
+use parking_lot::Mutex;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -9,11 +11,17 @@ use tox_proto;
 pub struct BlobStore<F: FileSystem> {
     root: PathBuf,
     fs: Arc<F>,
+    // Serializes read-modify-write cycles on `.refs` files.
+    refs_lock: Mutex<()>,
 }
 
 impl<F: FileSystem> BlobStore<F> {
     pub fn new(root: PathBuf, fs: Arc<F>) -> Self {
-        Self { root, fs }
+        Self {
+            root,
+            fs,
+            refs_lock: Mutex::new(()),
+        }
     }
 
     fn get_blob_path(&self, hash: &NodeHash) -> PathBuf {
@@ -31,6 +39,137 @@ impl<F: FileSystem> BlobStore<F> {
         self.root.join(&hex[0..2]).join(format!("{}.bao", hex))
     }
 
+    fn get_refs_path(&self, hash: &NodeHash) -> PathBuf {
+        let hex = encode_hex_32(hash.as_bytes());
+        self.root.join(&hex[0..2]).join(format!("{}.refs", hex))
+    }
+
+    fn get_staged_path(&self, staging_id: u64) -> PathBuf {
+        self.root
+            .join("staging")
+            .join(format!("{}.data", staging_id))
+    }
+
+    fn load_refs(&self, hash: &NodeHash) -> io::Result<Vec<ConversationId>> {
+        let path = self.get_refs_path(hash);
+        if !self.fs.exists(&path) {
+            return Ok(Vec::new());
+        }
+        let data = self.fs.read(&path)?;
+        tox_proto::deserialize(&data).map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn save_refs(&self, hash: &NodeHash, refs: &[ConversationId]) -> io::Result<()> {
+        let path = self.get_refs_path(hash);
+        if let Some(parent) = path.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        let data =
+            tox_proto::serialize(&refs.to_vec()).map_err(|e| io::Error::other(e.to_string()))?;
+        let mut tmp_path = path.clone();
+        tmp_path.set_extension("refs.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    /// Records that `conversation_id` references the blob. Returns false if
+    /// the reference already existed.
+    pub fn add_ref(&self, hash: &NodeHash, conversation_id: &ConversationId) -> io::Result<bool> {
+        let _guard = self.refs_lock.lock();
+        let mut refs = self.load_refs(hash)?;
+        if refs.contains(conversation_id) {
+            return Ok(false);
+        }
+        refs.push(*conversation_id);
+        self.save_refs(hash, &refs)?;
+        Ok(true)
+    }
+
+    pub fn ref_count(&self, hash: &NodeHash) -> io::Result<usize> {
+        Ok(self.load_refs(hash)?.len())
+    }
+
+    /// Drops every reference held by `conversation_id` and deletes the blobs
+    /// that are no longer referenced. Returns the hashes of deleted blobs.
+    pub fn release_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> io::Result<Vec<NodeHash>> {
+        let _guard = self.refs_lock.lock();
+        let mut deleted = Vec::new();
+        for hash in self.list_refs()? {
+            let mut refs = self.load_refs(&hash)?;
+            let before = refs.len();
+            refs.retain(|c| c != conversation_id);
+            if refs.len() == before {
+                continue;
+            }
+            if refs.is_empty() {
+                self.delete(&hash)?;
+                deleted.push(hash);
+            } else {
+                self.save_refs(&hash, &refs)?;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Returns the hashes of all blobs that have a reference file.
+    fn list_refs(&self) -> io::Result<Vec<NodeHash>> {
+        self.list_with_extension("refs")
+    }
+
+    /// Returns metadata of blobs that are still pending or downloading.
+    pub fn list_incomplete(&self) -> io::Result<Vec<BlobInfo>> {
+        let mut infos = Vec::new();
+        for hash in self.list_with_extension("info")? {
+            if let Some(info) = self.get_info(&hash)?
+                && info.status.is_incomplete()
+            {
+                infos.push(info);
+            }
+        }
+        Ok(infos)
+    }
+
+    fn list_with_extension(&self, ext: &str) -> io::Result<Vec<NodeHash>> {
+        let mut hashes = Vec::new();
+        let Ok(dirs) = self.fs.read_dir(&self.root) else {
+            return Ok(hashes);
+        };
+        for dir in dirs {
+            if !self.fs.metadata(&dir)?.is_dir {
+                continue;
+            }
+            for path in self.fs.read_dir(&dir)? {
+                if path.extension().is_some_and(|e| e == ext)
+                    && let Some(stem) = path.file_stem()
+                    && let Some(bytes) = decode_hex_32(&stem.to_string_lossy())
+                {
+                    hashes.push(NodeHash::from(bytes));
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Removes the data, outboard, metadata and reference files of a blob.
+    pub fn delete(&self, hash: &NodeHash) -> io::Result<()> {
+        for path in [
+            self.get_blob_path(hash),
+            self.get_bao_path(hash),
+            self.get_info_path(hash),
+            self.get_refs_path(hash),
+        ] {
+            match self.fs.remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_info(&self, hash: &NodeHash) -> io::Result<Option<BlobInfo>> {
         let path = self.get_info_path(hash);
         if !self.fs.exists(&path) {
@@ -65,6 +204,40 @@ impl<F: FileSystem> BlobStore<F> {
         Ok(())
     }
 
+    /// Writes a chunk of a blob whose hash is not known yet. Writing at
+    /// offset 0 starts the staged blob over.
+    pub fn put_staged_chunk(&self, staging_id: u64, offset: u64, data: &[u8]) -> io::Result<()> {
+        let path = self.get_staged_path(staging_id);
+        if let Some(parent) = path.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        let mut handle = self.fs.open(&path, true, true, offset == 0)?;
+        handle.seek(SeekFrom::Start(offset))?;
+        handle.write_all(data)?;
+        Ok(())
+    }
+
+    /// Moves a staged blob into place as the data of `hash`.
+    pub fn commit_staged(&self, staging_id: u64, hash: &NodeHash) -> io::Result<()> {
+        let path = self.get_blob_path(hash);
+        if let Some(parent) = path.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        let staged_path = self.get_staged_path(staging_id);
+        if !self.fs.exists(&staged_path) {
+            // Empty blobs have no chunks to stage.
+            return self.fs.write(&path, &[]);
+        }
+        self.fs.rename(&staged_path, &path)
+    }
+
+    pub fn discard_staged(&self, staging_id: u64) -> io::Result<()> {
+        match self.fs.remove_file(&self.get_staged_path(staging_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
     pub fn get_chunk(&self, hash: &NodeHash, offset: u64, length: u32) -> io::Result<Vec<u8>> {
         let path = self.get_blob_path(hash);
         let mut handle = self.fs.open(&path, false, false, false)?;
@@ -80,44 +253,38 @@ impl<F: FileSystem> BlobStore<F> {
         offset: u64,
         length: u32,
     ) -> io::Result<(Vec<u8>, Vec<u8>)> {
-        let blob_path = self.get_blob_path(hash);
-        let outboard_path = self.get_bao_path(hash);
-
-        let blob_data = self.fs.read(&blob_path)?;
-        let outboard_data = match self.fs.read(&outboard_path) {
-            Ok(data) => data,
-            Err(e) if e.kind() == io::ErrorKind::NotFound => {
-                let mut raw_data = vec![0u8; length as usize];
-                raw_data.copy_from_slice(
-                    &blob_data[offset as usize..(offset + length as u64) as usize],
-                );
-                return Ok((raw_data, Vec::new()));
-            }
+        let raw_data = self.get_chunk(hash, offset, length)?;
+        let outboard = match self.fs.open(&self.get_bao_path(hash), false, false, false) {
+            Ok(handle) => handle,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((raw_data, Vec::new())),
             Err(e) => return Err(e),
         };
+        let data = self
+            .fs
+            .open(&self.get_blob_path(hash), false, false, false)?;
 
         let mut slice = Vec::new();
-        let mut extractor = bao::encode::SliceExtractor::new_outboard(
-            std::io::Cursor::new(&blob_data),
-            std::io::Cursor::new(&outboard_data),
-            offset,
-            length as u64,
-        );
+        let mut extractor =
+            bao::encode::SliceExtractor::new_outboard(data, outboard, offset, length as u64);
         extractor.read_to_end(&mut slice)?;
 
-        let mut raw_data = vec![0u8; length as usize];
-        raw_data.copy_from_slice(&blob_data[offset as usize..(offset + length as u64) as usize]);
-
         Ok((raw_data, slice))
     }
 
-    pub fn finalize(&self, hash: &NodeHash) -> io::Result<()> {
-        let data_path = self.get_blob_path(hash);
-        let data = self.fs.read(&data_path)?;
-        let (outboard, root) = bao::encode::outboard(&data);
+    /// Length of the data file written so far.
+    pub fn data_len(&self, hash: &NodeHash) -> io::Result<u64> {
+        Ok(self.fs.metadata(&self.get_blob_path(hash))?.len)
+    }
 
-        let bao_path = self.get_bao_path(hash);
-        self.fs.write(&bao_path, &outboard)?;
+    pub fn finalize(&self, hash: &NodeHash) -> io::Result<()> {
+        // Stream the data through the encoder; blobs may not fit in memory.
+        let mut data = self
+            .fs
+            .open(&self.get_blob_path(hash), false, false, false)?;
+        let outboard = self.fs.open(&self.get_bao_path(hash), true, true, true)?;
+        let mut encoder = bao::encode::Encoder::new_outboard(outboard);
+        io::copy(&mut data, &mut encoder)?;
+        let root = encoder.finalize()?;
 
         if let Some(mut info) = self.get_info(hash)? {
             info.status = BlobStatus::Available;
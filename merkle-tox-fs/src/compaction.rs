@@ -0,0 +1,428 @@
+use crate::journal::{Journal, JournalRecordType};
+use crate::pack::{self, Pack};
+use crate::state::{self, StateFile};
+use crate::{COMPACT_THRESHOLD, ConversationContext, FsStore};
+
+use merkle_tox_core::dag::{
+    ChainKey, ConversationId, MerkleNode, NodeHash, NodeType, PhysicalDevicePk,
+};
+use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use merkle_tox_core::vfs::FileSystem;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io::{Error, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+
+/// Controls when and how [`FsStore`] moves journal records into packs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionConfig {
+    /// Number of journal nodes in a conversation that triggers compaction.
+    pub threshold: usize,
+    /// Upper bound on the size of a single pack file in bytes. Larger
+    /// compactions are split over several packs.
+    pub max_pack_size: u64,
+    /// Never compact on the write path. Compaction is then left to a worker
+    /// started with [`FsStore::spawn_compaction_worker`], or to the caller
+    /// via [`FsStore::needs_compaction`] and [`FsStore::compact_step`].
+    pub background: bool,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: COMPACT_THRESHOLD,
+            max_pack_size: 64 * 1024 * 1024,
+            background: false,
+        }
+    }
+}
+
+/// Handle to a background compaction thread. The thread finishes its current
+/// step and exits when the handle is dropped.
+pub struct CompactionWorker {
+    sender: Arc<Mutex<Option<Sender<ConversationId>>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for CompactionWorker {
+    fn drop(&mut self) {
+        self.sender.lock().take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A snapshot of the journal that is being moved into packs.
+pub(crate) struct CompactionJob {
+    generation_id: u64,
+    record_count: usize,
+    nodes: Vec<(pack::IndexRecord, Vec<u8>)>,
+    /// Prune records for nodes that are already packed. They are carried
+    /// over into the next journal generation until GC tombstones the nodes.
+    prunes: Vec<NodeHash>,
+    ratchet_updates: HashMap<PhysicalDevicePk, (ChainKey, u64, u64)>,
+}
+
+/// File a compaction writes the next journal generation to before it
+/// replaces `journal.bin`.
+pub(crate) const NEXT_JOURNAL: &str = "journal.bin.new";
+
+/// Returns the generation of the next journal left in `dir` by a compaction.
+pub(crate) fn next_journal_generation<F: FileSystem>(fs: &F, dir: &Path) -> Option<u64> {
+    let data = fs.read(&dir.join(NEXT_JOURNAL)).ok()?;
+    Some(u64::from_le_bytes(data.get(..8)?.try_into().ok()?))
+}
+
+/// Finishes a compaction interrupted after committing state.bin by moving
+/// its next journal into place, or discards the next journal of one that did
+/// not get that far.
+pub(crate) fn recover_next_journal<F: FileSystem>(
+    fs: &F,
+    dir: &Path,
+    active_journal_id: u64,
+) -> std::io::Result<()> {
+    let next_path = dir.join(NEXT_JOURNAL);
+    if !fs.exists(&next_path) {
+        return Ok(());
+    }
+    if active_journal_id != 0 && next_journal_generation(fs, dir) == Some(active_journal_id) {
+        fs.rename(&next_path, &dir.join("journal.bin"))
+    } else {
+        fs.remove_file(&next_path)
+    }
+}
+
+impl<F: FileSystem> FsStore<F> {
+    /// Starts a thread that compacts conversations whose journal reached the
+    /// configured threshold. Only useful with [`CompactionConfig::background`].
+    pub fn spawn_compaction_worker(&self) -> CompactionWorker
+    where
+        F: 'static,
+    {
+        let (tx, rx) = mpsc::channel::<ConversationId>();
+        *self.compaction_tx.lock() = Some(tx);
+        let store = self.clone();
+        let thread = std::thread::spawn(move || {
+            while let Ok(id) = rx.recv() {
+                if store.needs_compaction(&id) {
+                    let _ = store.compact_step(&id);
+                }
+            }
+        });
+        CompactionWorker {
+            sender: self.compaction_tx.clone(),
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns true if the journal of the conversation has reached the
+    /// compaction threshold.
+    pub fn needs_compaction(&self, id: &ConversationId) -> bool {
+        let inner = self.inner.read();
+        inner
+            .conversations
+            .get(id)
+            .is_some_and(|ctx| ctx.volatile_nodes.len() >= self.compaction.threshold)
+    }
+
+    /// Moves the current journal contents into packs without holding the
+    /// store lock while the packs are written. Writes that arrive in the
+    /// meantime are carried over into the new journal generation.
+    ///
+    /// Returns false if there was nothing to compact or a concurrent
+    /// compaction got there first.
+    pub fn compact_step(&self, id: &ConversationId) -> MerkleToxResult<bool> {
+        self.ensure_conversation(id)?;
+        let _compacting = self.compaction_lock.lock();
+        let (mut job, packs_dir) = {
+            let inner = self.inner.read();
+            let ctx = inner.conversations.get(id).unwrap();
+            match self.plan_compaction(ctx)? {
+                Some(job) => (job, ctx.path.join("packs")),
+                None => return Ok(false),
+            }
+        };
+
+        let pack_ids = self.write_packs(&packs_dir, &mut job)?;
+
+        let mut inner = self.inner.write();
+        let ctx = inner.conversations.get_mut(id).unwrap();
+        ctx.lock_file.try_lock_exclusive().map_err(|_| {
+            MerkleToxError::Io(Error::other(
+                "Failed to upgrade to exclusive lock for compaction",
+            ))
+        })?;
+        let res = self.commit_compaction(ctx, job, &pack_ids);
+        let _ = ctx.lock_file.try_lock_shared(); // downgrade back
+        res
+    }
+
+    pub(crate) fn plan_compaction(
+        &self,
+        ctx: &ConversationContext<F>,
+    ) -> MerkleToxResult<Option<CompactionJob>> {
+        let mut journal = ctx.journal.lock();
+        let generation_id = journal.generation_id();
+        let records = journal.read_all()?;
+        drop(journal);
+
+        let mut nodes = Vec::new();
+        let mut prunes = Vec::new();
+        let mut ratchet_updates = HashMap::new();
+
+        for rec in &records {
+            if rec.record_type == JournalRecordType::Node {
+                let (status, node): (u8, MerkleNode) = tox_proto::deserialize(&rec.payload)?;
+                let node_hash = node.hash();
+                let info = ctx.volatile_nodes.get(&node_hash);
+
+                // Track latest sequence number even if no ratchet advance
+                let entry = ratchet_updates.entry(node.sender_pk).or_insert((
+                    ChainKey::from([0u8; 32]),
+                    0u64,
+                    0u64,
+                ));
+                if node.sequence_number > entry.1 {
+                    entry.1 = node.sequence_number;
+                }
+
+                // Promotions are separate journal records; fold them in.
+                let pruned = ctx.pruned.contains(&node_hash);
+                let status = if pruned {
+                    pack::STATUS_TOMBSTONE
+                } else if info.is_some_and(|i| i.verified) {
+                    pack::STATUS_VERIFIED
+                } else {
+                    status
+                };
+                let record = pack::IndexRecord {
+                    hash: node_hash,
+                    offset: 0, // will be set during pack creation
+                    rank: node.topological_rank,
+                    payload_length: 0, // will be set during pack creation
+                    node_type: if node.node_type() == NodeType::Admin {
+                        0x01
+                    } else {
+                        0x02
+                    },
+                    status,
+                    admin_distance: info.map(|i| i.admin_distance).unwrap_or(0),
+                };
+                let payload = if pruned {
+                    Vec::new()
+                } else {
+                    tox_proto::serialize(&(status, node))?
+                };
+                nodes.push((record, payload));
+            } else if rec.record_type == JournalRecordType::Prune {
+                let hash: NodeHash = tox_proto::deserialize(&rec.payload)?;
+                let tombstoned = ctx.packs.iter().any(|p| {
+                    p.index
+                        .lookup(&hash)
+                        .is_some_and(|r| r.status == pack::STATUS_TOMBSTONE)
+                });
+                if !ctx.volatile_nodes.contains_key(&hash) && !tombstoned {
+                    prunes.push(hash);
+                }
+            } else if rec.record_type == JournalRecordType::RatchetAdvance {
+                let (hash, key, epoch): (NodeHash, ChainKey, u64) =
+                    tox_proto::deserialize(&rec.payload)?;
+                if let Some(info) = ctx.volatile_nodes.get(&hash) {
+                    let entry = ratchet_updates.entry(info.sender_pk).or_insert((
+                        ChainKey::from([0u8; 32]),
+                        0u64,
+                        0u64,
+                    ));
+                    entry.0 = key;
+                    entry.1 = info.sequence_number;
+                    entry.2 = epoch;
+                }
+            }
+        }
+
+        if nodes.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(CompactionJob {
+            generation_id,
+            record_count: records.len(),
+            nodes,
+            prunes,
+            ratchet_updates,
+        }))
+    }
+
+    /// Writes the nodes of `job` into one or more packs and returns their ids.
+    /// The packs are synced under temporary names and only renamed into
+    /// place when the job is committed.
+    pub(crate) fn write_packs(
+        &self,
+        packs_dir: &Path,
+        job: &mut CompactionJob,
+    ) -> MerkleToxResult<Vec<u64>> {
+        let base_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let mut pack_ids = Vec::new();
+        let mut nodes = job.nodes.iter_mut().peekable();
+
+        while nodes.peek().is_some() {
+            let pack_id = base_id + pack_ids.len() as u64;
+            let data_tmp = packs_dir.join(format!("{:016x}.pack.tmp", pack_id));
+            let index_tmp = packs_dir.join(format!("{:016x}.idx.tmp", pack_id));
+
+            let mut data_file = self.fs.open(&data_tmp, true, true, true)?;
+            let mut index_records = Vec::new();
+            loop {
+                let offset = data_file.stream_position()?;
+                if !index_records.is_empty() && offset >= self.compaction.max_pack_size {
+                    break;
+                }
+                let Some((record, payload)) = nodes.next() else {
+                    break;
+                };
+                record.offset = offset;
+                record.payload_length = payload.len() as u32;
+
+                let record_type = 0x01u8; // Node
+                data_file.write_all(&(payload.len() as u32).to_le_bytes())?;
+                data_file.write_all(record.hash.as_bytes())?;
+                data_file.write_all(&[record_type])?;
+                data_file.write_all(payload)?;
+                index_records.push(*record);
+            }
+            data_file.sync()?;
+
+            let pack_index = pack::PackIndex::build(index_records, pack::DEFAULT_FANOUT_BITS, 2);
+            pack_index.save(&*self.fs, &index_tmp)?;
+            pack_ids.push(pack_id);
+        }
+        Ok(pack_ids)
+    }
+
+    /// Makes the packs written for `job` active and starts a new journal
+    /// generation holding only the records appended after the snapshot.
+    pub(crate) fn commit_compaction(
+        &self,
+        ctx: &mut ConversationContext<F>,
+        job: CompactionJob,
+        pack_ids: &[u64],
+    ) -> MerkleToxResult<bool> {
+        let mut journal = ctx.journal.lock();
+        let records = journal.read_all()?;
+        if journal.generation_id() != job.generation_id || records.len() < job.record_count {
+            drop(journal);
+            self.remove_packs(&ctx.path.join("packs"), pack_ids);
+            return Ok(false);
+        }
+        drop(journal);
+
+        // 1. Move the packs into place. Until state.bin names them they are
+        // orphans, which GC removes.
+        let packs_dir = ctx.path.join("packs");
+        for &pack_id in pack_ids {
+            for ext in ["pack", "idx"] {
+                self.fs.rename(
+                    &packs_dir.join(format!("{:016x}.{}.tmp", pack_id, ext)),
+                    &packs_dir.join(format!("{:016x}.{}", pack_id, ext)),
+                )?;
+            }
+        }
+
+        // 2. Write the next journal generation beside the current one: the
+        // prunes of the job and the records appended after the snapshot.
+        let next_gen_id = *pack_ids.last().unwrap();
+        let next_path = ctx.path.join(NEXT_JOURNAL);
+        {
+            let mut next = Journal::<F>::open(self.fs.clone(), next_path.clone())?;
+            next.truncate(next_gen_id)?;
+            for hash in &job.prunes {
+                next.append(JournalRecordType::Prune, &tox_proto::serialize(hash)?)?;
+            }
+            for rec in &records[job.record_count..] {
+                next.append(rec.record_type, &rec.payload)?;
+            }
+            next.sync()?;
+        }
+
+        // 3. Commit. Once state.bin names the new packs and journal
+        // generation, startup finishes the switch to the next journal (see
+        // `recover_next_journal`); before that, it is discarded.
+        ctx.state.active_packs.extend_from_slice(pack_ids);
+        ctx.state.active_journal_id = next_gen_id;
+
+        // Update ratchet.bin and in-memory caches
+        let mut current_ratchets = ctx.ratchet.lock().load()?;
+        for (pk, (key, seq, epoch)) in job.ratchet_updates {
+            let entry = ctx.last_seq_numbers.entry(pk).or_insert(0);
+            if seq > *entry {
+                *entry = seq;
+            }
+            if key != ChainKey::from([0u8; 32]) {
+                ctx.latest_ratchets
+                    .insert(pk, (key.clone(), seq, NodeHash::from([0u8; 32]), epoch));
+            }
+
+            if let Some(slot) = current_ratchets.iter_mut().find(|s| s.device_pk == pk) {
+                if seq > slot.last_sequence_number {
+                    if key != ChainKey::from([0u8; 32]) {
+                        slot.chain_key = key;
+                        slot.epoch_id = epoch;
+                    }
+                    slot.last_sequence_number = seq;
+                }
+            } else {
+                current_ratchets.push(state::RatchetSlot {
+                    device_pk: pk,
+                    chain_key: key,
+                    last_sequence_number: seq,
+                    epoch_id: epoch,
+                });
+            }
+        }
+        ctx.ratchet.lock().save(&current_ratchets)?;
+
+        let state_file = StateFile::new(self.fs.clone(), ctx.path.join("state.bin"));
+        state_file.save(&ctx.state)?;
+
+        // 4. Switch to the next journal generation.
+        let journal_path = ctx.path.join("journal.bin");
+        self.fs.rename(&next_path, &journal_path)?;
+        *ctx.journal.lock() = Journal::open(self.fs.clone(), journal_path)?;
+
+        // Update ctx
+        for &pack_id in pack_ids {
+            ctx.packs.push(Pack::open(
+                self.fs.clone(),
+                packs_dir.join(format!("{:016x}.pack", pack_id)),
+                &packs_dir.join(format!("{:016x}.idx", pack_id)),
+            )?);
+        }
+        ctx.volatile_nodes.clear();
+        ctx.hot_ratchets.clear();
+        if records.len() > job.record_count {
+            // Node hashes are already mapped to this conversation.
+            ctx.replay_journal(&mut HashMap::new())?;
+        }
+
+        Ok(true)
+    }
+
+    /// Removes the uncommitted packs of a discarded job.
+    fn remove_packs(&self, packs_dir: &Path, pack_ids: &[u64]) {
+        for pack_id in pack_ids {
+            let _ = self
+                .fs
+                .remove_file(&packs_dir.join(format!("{:016x}.pack.tmp", pack_id)));
+            let _ = self
+                .fs
+                .remove_file(&packs_dir.join(format!("{:016x}.idx.tmp", pack_id)));
+        }
+    }
+}
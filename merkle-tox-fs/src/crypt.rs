@@ -0,0 +1,673 @@
+//! Encryption-at-rest layer for [`FsStore`](crate::FsStore).
+//!
+//! [`EncryptedFileSystem`] wraps another [`FileSystem`] and transparently
+//! encrypts every file written through it. Files are split into fixed-size
+//! blocks, each sealed with XChaCha20-Poly1305 under a fresh random nonce.
+//! The block index and a per-file random ID are bound as associated data, so
+//! blocks cannot be reordered within a file or transplanted between files.
+//! So is whether the block is the file's last: every file keeps at least one
+//! block, possibly empty, and cutting whole blocks off the end leaves a last
+//! block that was sealed as an inner one.
+//!
+//! Block `i` is stored in slot `i`, except the last block, which may also sit
+//! in the spare slot after its own. Sealed data is never overwritten when the
+//! last block changes: its new version goes into whichever of the two slots
+//! it is not in, and the file is then cut off right after it. A write torn by
+//! a crash thus leaves the previous version intact, and a final slot that is
+//! cut short is skipped as the remains of such a write.
+//!
+//! On-disk layout:
+//! `[magic "MTXE"][version u8][reserved 3][file_id 16]` followed by slots of
+//! `[nonce 24][length u32][ciphertext <= BLOCK_SIZE][tag 16]`.
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use merkle_tox_core::vfs::{FileHandle, FileMetadata, FileSystem};
+use rand::RngCore;
+use rand::rngs::OsRng;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8; 4] = b"MTXE";
+const VERSION: u8 = 2;
+const HEADER_LEN: u64 = 24;
+const BLOCK_SIZE: u64 = 4096;
+const NONCE_LEN: u64 = 24;
+const LENGTH_LEN: u64 = 4;
+const TAG_LEN: u64 = 16;
+const BLOCK_OVERHEAD: u64 = NONCE_LEN + LENGTH_LEN + TAG_LEN;
+const STORED_BLOCK_SIZE: u64 = BLOCK_SIZE + BLOCK_OVERHEAD;
+
+/// Name of the file (relative to the store root) holding the passphrase salt
+/// and key derivation cost.
+pub const SALT_FILE: &str = "salt.bin";
+/// Name of the file (relative to the store root) used to detect a wrong key.
+pub const KEY_CHECK_FILE: &str = "keycheck.bin";
+const KEY_CHECK_PLAINTEXT: &[u8] = b"merkle-tox storage key check v1";
+
+const KDF_MAGIC: &[u8; 4] = b"MTXS";
+const KDF_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const KDF_PARAMS_LEN: usize = KDF_MAGIC.len() + 1 + 3 * 4 + SALT_LEN;
+
+// Argon2id cost of new stores. Existing stores keep the cost recorded in
+// their salt file, so changing these does not lock anyone out.
+const KDF_M_COST_KIB: u32 = 19 * 1024;
+const KDF_T_COST: u32 = 2;
+const KDF_P_COST: u32 = 1;
+
+/// Salt and Argon2id cost a passphrase is stretched with.
+///
+/// Stored as `[magic "MTXS"][version u8][m_cost u32][t_cost u32][p_cost u32]
+/// [salt 16]`. A bare 16-byte salt is read with the cost it was created
+/// with, which was the argon2 crate's default at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub salt: [u8; SALT_LEN],
+    pub m_cost_kib: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    /// Returns the current cost with a fresh random salt.
+    pub fn generate() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            salt,
+            m_cost_kib: KDF_M_COST_KIB,
+            t_cost: KDF_T_COST,
+            p_cost: KDF_P_COST,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; KDF_PARAMS_LEN] {
+        let mut out = [0u8; KDF_PARAMS_LEN];
+        out[0..4].copy_from_slice(KDF_MAGIC);
+        out[4] = KDF_VERSION;
+        out[5..9].copy_from_slice(&self.m_cost_kib.to_le_bytes());
+        out[9..13].copy_from_slice(&self.t_cost.to_le_bytes());
+        out[13..17].copy_from_slice(&self.p_cost.to_le_bytes());
+        out[17..].copy_from_slice(&self.salt);
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        if let Ok(salt) = <[u8; SALT_LEN]>::try_from(data) {
+            return Ok(Self {
+                salt,
+                m_cost_kib: KDF_M_COST_KIB,
+                t_cost: KDF_T_COST,
+                p_cost: KDF_P_COST,
+            });
+        }
+        if data.len() != KDF_PARAMS_LEN || &data[0..4] != KDF_MAGIC || data[4] != KDF_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid salt file",
+            ));
+        }
+        let word = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        Ok(Self {
+            salt: data[17..].try_into().unwrap(),
+            m_cost_kib: word(5),
+            t_cost: word(9),
+            p_cost: word(13),
+        })
+    }
+}
+
+/// A 256-bit storage encryption key.
+#[derive(Clone)]
+pub struct StorageKey(Zeroizing<[u8; 32]>);
+
+impl StorageKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    /// Derives a key from a user passphrase using Argon2id version 0x13.
+    ///
+    /// The parameters should be persisted alongside the store, see
+    /// [`load_or_create_kdf_params`].
+    pub fn from_passphrase(passphrase: &[u8], params: &KdfParams) -> io::Result<Self> {
+        let invalid = |e: argon2::Error| io::Error::new(io::ErrorKind::InvalidInput, e.to_string());
+        let cost = argon2::Params::new(params.m_cost_kib, params.t_cost, params.p_cost, Some(32))
+            .map_err(invalid)?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, cost)
+            .hash_password_into(passphrase, &params.salt, key.as_mut())
+            .map_err(invalid)?;
+        Ok(Self(key))
+    }
+}
+
+impl fmt::Debug for StorageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StorageKey(..)")
+    }
+}
+
+/// Reads the passphrase parameters stored under `root`, creating them with a
+/// random salt if none exist yet. They are not secret and are stored in
+/// plaintext.
+pub fn load_or_create_kdf_params<F: FileSystem>(fs: &F, root: &Path) -> io::Result<KdfParams> {
+    let path = root.join(SALT_FILE);
+    if fs.exists(&path) {
+        return KdfParams::from_bytes(&fs.read(&path)?);
+    }
+    fs.create_dir_all(root)?;
+    let params = KdfParams::generate();
+    fs.write(&path, &params.to_bytes())?;
+    Ok(params)
+}
+
+/// A [`FileSystem`] that encrypts file contents with a [`StorageKey`].
+///
+/// Directory structure and file names are passed through unchanged.
+pub struct EncryptedFileSystem<F: FileSystem> {
+    inner: Arc<F>,
+    cipher: Arc<XChaCha20Poly1305>,
+}
+
+impl<F: FileSystem> EncryptedFileSystem<F> {
+    pub fn new(inner: Arc<F>, key: &StorageKey) -> Self {
+        Self {
+            inner,
+            cipher: Arc::new(XChaCha20Poly1305::new(key.0.as_ref().into())),
+        }
+    }
+
+    /// Returns the underlying (ciphertext) file system.
+    pub fn inner(&self) -> &Arc<F> {
+        &self.inner
+    }
+
+    /// Checks that `key` matches the one the store under `root` was created
+    /// with, writing the check file on first use.
+    pub fn verify_key(&self, root: &Path) -> io::Result<()> {
+        let path = root.join(KEY_CHECK_FILE);
+        if !self.inner.exists(&path) {
+            return self.write(&path, KEY_CHECK_PLAINTEXT);
+        }
+        match self.read(&path) {
+            Ok(data) if data == KEY_CHECK_PLAINTEXT => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Wrong storage encryption key",
+            )),
+        }
+    }
+}
+
+impl<F: FileSystem> fmt::Debug for EncryptedFileSystem<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedFileSystem")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F: FileSystem> FileSystem for EncryptedFileSystem<F> {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut handle = self.open(path, false, false, false)?;
+        let mut data = Vec::new();
+        handle.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut handle = self.open(path, true, true, true)?;
+        handle.write_all(contents)?;
+        handle.flush()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let meta = self.inner.metadata(path)?;
+        if meta.is_dir {
+            return Ok(meta);
+        }
+        // The length is only known once the last block is found.
+        self.open(path, false, false, false)?.metadata()
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn open(
+        &self,
+        path: &Path,
+        write: bool,
+        create: bool,
+        truncate: bool,
+    ) -> io::Result<Box<dyn FileHandle>> {
+        let handle = self.inner.open(path, write, create, truncate)?;
+        Ok(Box::new(EncryptedHandle::new(
+            handle,
+            self.cipher.clone(),
+            write,
+        )?))
+    }
+}
+
+fn slot_offset(slot: u64) -> u64 {
+    HEADER_LEN + slot * STORED_BLOCK_SIZE
+}
+
+fn corrupt(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn auth_failed() -> io::Error {
+    corrupt("Encrypted block failed authentication")
+}
+
+/// Random-access handle over a block-encrypted file.
+///
+/// Writes are re-sealed and written through to the underlying handle
+/// immediately; only the most recently decrypted block is cached.
+struct EncryptedHandle {
+    inner: Box<dyn FileHandle>,
+    cipher: Arc<XChaCha20Poly1305>,
+    file_id: [u8; 16],
+    pos: u64,
+    len: u64,
+    /// Whether the last block is in the spare slot after its own.
+    spare: bool,
+    /// Length to cut the underlying file to before the next write, dropping
+    /// the remains of a torn one.
+    torn: Option<u64>,
+    cache: Option<(u64, Zeroizing<Vec<u8>>)>,
+}
+
+impl fmt::Debug for EncryptedHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedHandle")
+            .field("inner", &self.inner)
+            .field("pos", &self.pos)
+            .field("len", &self.len)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedHandle {
+    fn new(
+        mut inner: Box<dyn FileHandle>,
+        cipher: Arc<XChaCha20Poly1305>,
+        write: bool,
+    ) -> io::Result<Self> {
+        let raw_len = inner.metadata()?.len;
+        let mut file_id = [0u8; 16];
+        let mut create = false;
+        if raw_len == 0 {
+            OsRng.fill_bytes(&mut file_id);
+            if write {
+                let mut header = [0u8; HEADER_LEN as usize];
+                header[0..4].copy_from_slice(MAGIC);
+                header[4] = VERSION;
+                header[8..24].copy_from_slice(&file_id);
+                inner.seek(SeekFrom::Start(0))?;
+                inner.write_all(&header)?;
+                create = true;
+            }
+        } else if raw_len <= HEADER_LEN {
+            return Err(corrupt("Truncated encrypted file"));
+        } else {
+            let mut header = [0u8; HEADER_LEN as usize];
+            inner.seek(SeekFrom::Start(0))?;
+            inner
+                .read_exact(&mut header)
+                .map_err(|_| corrupt("Truncated encrypted file header"))?;
+            if &header[0..4] != MAGIC || header[4] != VERSION {
+                return Err(corrupt("Not an encrypted storage file"));
+            }
+            file_id.copy_from_slice(&header[8..24]);
+        }
+
+        let mut handle = Self {
+            inner,
+            cipher,
+            file_id,
+            pos: 0,
+            len: 0,
+            spare: false,
+            torn: None,
+            cache: None,
+        };
+        if create {
+            handle.seal_block(0, 0, Zeroizing::new(Vec::new()), true)?;
+        } else if raw_len > 0 {
+            handle.find_last_block(raw_len)?;
+        }
+        Ok(handle)
+    }
+
+    /// Finds the last block in the final slot of the file, or in the one
+    /// before if it sits in its spare slot. A final slot that is cut short
+    /// is skipped once, as the remains of a torn write.
+    fn find_last_block(&mut self, raw_len: u64) -> io::Result<()> {
+        let mut end = raw_len;
+        while end > HEADER_LEN {
+            let slot = (end - HEADER_LEN).div_ceil(STORED_BLOCK_SIZE) - 1;
+            let Some((nonce, ciphertext)) = self.read_slot(slot, end)? else {
+                if end < raw_len {
+                    break;
+                }
+                end = slot_offset(slot);
+                continue;
+            };
+            for index in [Some(slot), slot.checked_sub(1)].into_iter().flatten() {
+                if let Some(plain) = self.unseal(index, true, &nonce, &ciphertext) {
+                    let slot_end = slot_offset(slot) + BLOCK_OVERHEAD + plain.len() as u64;
+                    self.len = index * BLOCK_SIZE + plain.len() as u64;
+                    self.spare = slot != index;
+                    self.torn = (slot_end < raw_len).then_some(slot_end);
+                    self.cache = Some((index, plain));
+                    return Ok(());
+                }
+            }
+            break;
+        }
+        Err(auth_failed())
+    }
+
+    fn aad(&self, index: u64, last: bool) -> [u8; 25] {
+        let mut aad = [0u8; 25];
+        aad[0..16].copy_from_slice(&self.file_id);
+        aad[16..24].copy_from_slice(&index.to_le_bytes());
+        aad[24] = last as u8;
+        aad
+    }
+
+    /// Index of the last block of a file of `len` bytes; an empty file has
+    /// one empty block.
+    fn last_block(len: u64) -> u64 {
+        len.saturating_sub(1) / BLOCK_SIZE
+    }
+
+    fn block_len(&self, index: u64) -> u64 {
+        self.len.saturating_sub(index * BLOCK_SIZE).min(BLOCK_SIZE)
+    }
+
+    fn slot(&self, index: u64) -> u64 {
+        if self.spare && index == Self::last_block(self.len) {
+            index + 1
+        } else {
+            index
+        }
+    }
+
+    /// Reads the nonce and ciphertext in `slot`, or `None` if they do not
+    /// fit before `end`.
+    fn read_slot(&mut self, slot: u64, end: u64) -> io::Result<Option<([u8; 24], Vec<u8>)>> {
+        let offset = slot_offset(slot);
+        if offset + BLOCK_OVERHEAD > end {
+            return Ok(None);
+        }
+        let mut prefix = [0u8; (NONCE_LEN + LENGTH_LEN) as usize];
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut prefix)?;
+        let (nonce, length) = prefix.split_at(NONCE_LEN as usize);
+        let plain_len = u32::from_le_bytes(length.try_into().unwrap()) as u64;
+        if plain_len > BLOCK_SIZE || offset + BLOCK_OVERHEAD + plain_len > end {
+            return Ok(None);
+        }
+        let mut ciphertext = vec![0u8; (plain_len + TAG_LEN) as usize];
+        self.inner.read_exact(&mut ciphertext)?;
+        Ok(Some((nonce.try_into().unwrap(), ciphertext)))
+    }
+
+    fn unseal(
+        &self,
+        index: u64,
+        last: bool,
+        nonce: &[u8; 24],
+        ciphertext: &[u8],
+    ) -> Option<Zeroizing<Vec<u8>>> {
+        let aad = self.aad(index, last);
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .ok()
+            .map(Zeroizing::new)
+    }
+
+    fn load_block(&mut self, index: u64) -> io::Result<Zeroizing<Vec<u8>>> {
+        if let Some((cached, data)) = &self.cache
+            && *cached == index
+        {
+            return Ok(data.clone());
+        }
+
+        let plain_len = self.block_len(index);
+        if plain_len == 0 {
+            return Ok(Zeroizing::new(Vec::new()));
+        }
+
+        let (nonce, ciphertext) = self
+            .read_slot(self.slot(index), u64::MAX)?
+            .filter(|(_, ciphertext)| ciphertext.len() as u64 == plain_len + TAG_LEN)
+            .ok_or_else(auth_failed)?;
+        let last = index == Self::last_block(self.len);
+        let plain = self
+            .unseal(index, last, &nonce, &ciphertext)
+            .ok_or_else(auth_failed)?;
+        self.cache = Some((index, plain.clone()));
+        Ok(plain)
+    }
+
+    fn repair(&mut self) -> io::Result<()> {
+        match self.torn.take() {
+            Some(end) => self.inner.set_len(end),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes block `index`, which may be one past the current last block.
+    ///
+    /// Inner blocks are rewritten in place. The last block goes into the one
+    /// of its two slots it is not in, and the file is cut off after it.
+    fn store_block(&mut self, index: u64, plain: Zeroizing<Vec<u8>>) -> io::Result<()> {
+        self.repair()?;
+        let last = Self::last_block(self.len);
+        if index < last {
+            return self.seal_block(index, index, plain, false);
+        }
+        let slot = if index > last {
+            // The old last block is sealed as an inner one in its own slot,
+            // which must not hold its only copy meanwhile.
+            let block = self.load_block(last)?;
+            if !self.spare {
+                self.seal_block(last, last + 1, block.clone(), true)?;
+            }
+            self.seal_block(last, last, block, false)?;
+            index + 1
+        } else if self.spare {
+            index
+        } else {
+            index + 1
+        };
+        self.len = index * BLOCK_SIZE + plain.len() as u64;
+        self.spare = slot != index;
+        let end = slot_offset(slot) + BLOCK_OVERHEAD + plain.len() as u64;
+        self.seal_block(index, slot, plain, true)?;
+        self.inner.set_len(end)
+    }
+
+    fn seal_block(
+        &mut self,
+        index: u64,
+        slot: u64,
+        plain: Zeroizing<Vec<u8>>,
+        last: bool,
+    ) -> io::Result<()> {
+        let mut nonce = [0u8; NONCE_LEN as usize];
+        OsRng.fill_bytes(&mut nonce);
+        let aad = self.aad(index, last);
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plain,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| io::Error::other("Block encryption failed"))?;
+
+        let mut stored = Vec::with_capacity((BLOCK_OVERHEAD as usize) + plain.len());
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&(plain.len() as u32).to_le_bytes());
+        stored.extend_from_slice(&ciphertext);
+        self.inner.seek(SeekFrom::Start(slot_offset(slot)))?;
+        self.inner.write_all(&stored)?;
+        self.cache = Some((index, plain));
+        Ok(())
+    }
+
+    /// Zero-fills the file from its current end up to `target`.
+    fn extend_to(&mut self, target: u64) -> io::Result<()> {
+        while self.len < target {
+            let index = self.len / BLOCK_SIZE;
+            let mut block = self.load_block(index)?;
+            let block_end = ((index + 1) * BLOCK_SIZE).min(target);
+            block.resize((block_end - index * BLOCK_SIZE) as usize, 0);
+            self.store_block(index, block)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for EncryptedHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let index = self.pos / BLOCK_SIZE;
+        let offset = (self.pos % BLOCK_SIZE) as usize;
+        let block = self.load_block(index)?;
+        let n = (block.len() - offset).min(buf.len());
+        buf[..n].copy_from_slice(&block[offset..offset + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for EncryptedHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos > self.len {
+            self.extend_to(self.pos)?;
+        }
+        let index = self.pos / BLOCK_SIZE;
+        let offset = (self.pos % BLOCK_SIZE) as usize;
+        let mut block = self.load_block(index)?;
+        let n = (BLOCK_SIZE as usize - offset).min(buf.len());
+        if block.len() < offset + n {
+            block.resize(offset + n, 0);
+        }
+        block[offset..offset + n].copy_from_slice(&buf[..n]);
+        self.store_block(index, block)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for EncryptedHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(d) => self.len.checked_add_signed(d),
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Invalid seek to negative position",
+            )
+        })?;
+        self.pos = new_pos;
+        Ok(new_pos)
+    }
+}
+
+impl FileHandle for EncryptedHandle {
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        if size > self.len {
+            return self.extend_to(size);
+        }
+        if size == self.len {
+            return Ok(());
+        }
+
+        let last = Self::last_block(size);
+        let mut block = self.load_block(last)?;
+        block.truncate((size - last * BLOCK_SIZE) as usize);
+        if last == Self::last_block(self.len) {
+            return self.store_block(last, block);
+        }
+
+        // The new last block goes into its spare slot, over a block that is
+        // cut off anyway. A crash meanwhile leaves that block unreadable.
+        self.repair()?;
+        self.len = size;
+        self.spare = true;
+        let end = slot_offset(last + 1) + BLOCK_OVERHEAD + block.len() as u64;
+        self.seal_block(last, last + 1, block, true)?;
+        self.inner.set_len(end)
+    }
+
+    fn metadata(&self) -> io::Result<FileMetadata> {
+        let mut meta = self.inner.metadata()?;
+        meta.len = self.len;
+        Ok(meta)
+    }
+
+    fn try_lock_exclusive(&self) -> io::Result<()> {
+        self.inner.try_lock_exclusive()
+    }
+
+    fn try_lock_shared(&self) -> io::Result<()> {
+        self.inner.try_lock_shared()
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.inner.sync()
+    }
+}
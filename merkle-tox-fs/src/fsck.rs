@@ -0,0 +1,1171 @@
+//! Offline integrity check and repair of a store directory.
+//!
+//! [`fsck`] reads the files of a store that is not open instead of going
+//! through [`FsStore`](crate::FsStore), which truncates torn journals and
+//! skips conversations it cannot load without saying so. With
+//! [`FsckMode::Repair`] it rebuilds pack indexes and `state.bin`, drops the
+//! records that cannot be salvaged and keeps the dropped bytes under
+//! `quarantine/<run>/` for inspection.
+
+use crate::blob::BlobStore;
+use crate::compaction::{NEXT_JOURNAL, next_journal_generation, recover_next_journal};
+use crate::journal::{
+    JOURNAL_FOOTER_MAGIC, JOURNAL_FOOTER_SIZE, Journal, JournalRecordType, RECORD_HEADER_SIZE,
+    record_checksum,
+};
+use crate::pack::{self, IndexRecord, PackIndex};
+use crate::state::{ConvState, StateFile};
+use crate::{decode_hex_32, encode_hex_32};
+
+use merkle_tox_core::cas::{BlobStatus, CHUNK_SIZE};
+use merkle_tox_core::dag::{ChainKey, ConversationId, MerkleNode, NodeHash, NodeType};
+use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use merkle_tox_core::vfs::FileSystem;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Error};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Journal and pack frames share the record header layout.
+const FRAME_HEADER_SIZE: usize = RECORD_HEADER_SIZE as usize;
+const JOURNAL_HEADER_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckMode {
+    /// Only report; nothing is written.
+    Check,
+    /// Repair what can be repaired.
+    Repair,
+}
+
+/// A problem found by [`fsck`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// `state.bin` cannot be decoded. Repair rebuilds it from the packs and
+    /// the journal; the counters and the history horizon start over.
+    BadState { conversation_id: ConversationId },
+    /// The journal belongs to another generation than `state.bin` names, so
+    /// its records are discarded when the store is opened.
+    JournalGeneration {
+        conversation_id: ConversationId,
+        journal: u64,
+        state: u64,
+    },
+    /// The journal is torn or fails its checksum from `offset` on.
+    CorruptJournal {
+        conversation_id: ConversationId,
+        offset: u64,
+        bytes: u64,
+    },
+    /// A journal record whose checksum is valid but whose payload cannot be
+    /// decoded. The store refuses to load the conversation.
+    BadJournalRecord {
+        conversation_id: ConversationId,
+        offset: u64,
+    },
+    /// A promotion or prune record for a node the conversation does not
+    /// store.
+    DanglingRecord {
+        conversation_id: ConversationId,
+        offset: u64,
+        hash: NodeHash,
+    },
+    /// A pack named by `state.bin` does not exist.
+    MissingPack {
+        conversation_id: ConversationId,
+        pack_id: u64,
+    },
+    /// A pack frame that is torn, cannot be decoded or holds a node that
+    /// does not hash to the hash of the frame.
+    BadPackNode {
+        conversation_id: ConversationId,
+        pack_id: u64,
+        offset: u64,
+    },
+    /// The pack index cannot be loaded or does not describe the pack.
+    BadPackIndex {
+        conversation_id: ConversationId,
+        pack_id: u64,
+        reason: String,
+    },
+    /// Pack files not named by `state.bin`, e.g. left by an interrupted
+    /// compaction.
+    UnreferencedPack {
+        conversation_id: ConversationId,
+        pack_id: u64,
+    },
+    /// A node stored by several conversations. The store maps each node to
+    /// one conversation, so the other copies cannot be looked up. Not
+    /// repaired.
+    DuplicateNode {
+        hash: NodeHash,
+        conversations: Vec<ConversationId>,
+    },
+    /// Blob metadata that cannot be decoded.
+    BadBlobInfo { hash: NodeHash },
+    /// The received mask does not hold one bit per chunk.
+    BlobMask {
+        hash: NodeHash,
+        len: usize,
+        expected: usize,
+    },
+    /// Chunks marked as received that lie past the end of the data file.
+    BlobChunks { hash: NodeHash, missing: u64 },
+    /// The data file of a blob does not have the size of the blob.
+    BlobSize {
+        hash: NodeHash,
+        size: u64,
+        data_len: u64,
+    },
+}
+
+impl FsckIssue {
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, FsckIssue::DuplicateNode { .. })
+    }
+}
+
+fn short(bytes: &[u8; 32]) -> String {
+    encode_hex_32(bytes)[..8].to_string()
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::BadState { conversation_id } => write!(
+                f,
+                "conv={}: state.bin cannot be decoded",
+                short(conversation_id.as_bytes())
+            ),
+            FsckIssue::JournalGeneration {
+                conversation_id,
+                journal,
+                state,
+            } => write!(
+                f,
+                "conv={}: journal generation {:016x} does not match state.bin ({:016x})",
+                short(conversation_id.as_bytes()),
+                journal,
+                state
+            ),
+            FsckIssue::CorruptJournal {
+                conversation_id,
+                offset,
+                bytes,
+            } => write!(
+                f,
+                "conv={}: {} corrupt journal bytes at offset {}",
+                short(conversation_id.as_bytes()),
+                bytes,
+                offset
+            ),
+            FsckIssue::BadJournalRecord {
+                conversation_id,
+                offset,
+            } => write!(
+                f,
+                "conv={}: undecodable journal record at offset {}",
+                short(conversation_id.as_bytes()),
+                offset
+            ),
+            FsckIssue::DanglingRecord {
+                conversation_id,
+                offset,
+                hash,
+            } => write!(
+                f,
+                "conv={}: journal record at offset {} refers to missing node {}",
+                short(conversation_id.as_bytes()),
+                offset,
+                short(hash.as_bytes())
+            ),
+            FsckIssue::MissingPack {
+                conversation_id,
+                pack_id,
+            } => write!(
+                f,
+                "conv={}: pack {:016x} is missing",
+                short(conversation_id.as_bytes()),
+                pack_id
+            ),
+            FsckIssue::BadPackNode {
+                conversation_id,
+                pack_id,
+                offset,
+            } => write!(
+                f,
+                "conv={}: pack {:016x} has a corrupt node at offset {}",
+                short(conversation_id.as_bytes()),
+                pack_id,
+                offset
+            ),
+            FsckIssue::BadPackIndex {
+                conversation_id,
+                pack_id,
+                reason,
+            } => write!(
+                f,
+                "conv={}: index of pack {:016x}: {}",
+                short(conversation_id.as_bytes()),
+                pack_id,
+                reason
+            ),
+            FsckIssue::UnreferencedPack {
+                conversation_id,
+                pack_id,
+            } => write!(
+                f,
+                "conv={}: pack {:016x} is not referenced by state.bin",
+                short(conversation_id.as_bytes()),
+                pack_id
+            ),
+            FsckIssue::DuplicateNode {
+                hash,
+                conversations,
+            } => write!(
+                f,
+                "node {} is stored by {} conversations",
+                short(hash.as_bytes()),
+                conversations.len()
+            ),
+            FsckIssue::BadBlobInfo { hash } => {
+                write!(
+                    f,
+                    "blob {}: metadata cannot be decoded",
+                    short(hash.as_bytes())
+                )
+            }
+            FsckIssue::BlobMask {
+                hash,
+                len,
+                expected,
+            } => write!(
+                f,
+                "blob {}: received mask has {} bytes, expected {}",
+                short(hash.as_bytes()),
+                len,
+                expected
+            ),
+            FsckIssue::BlobChunks { hash, missing } => write!(
+                f,
+                "blob {}: {} chunk(s) marked received are missing",
+                short(hash.as_bytes()),
+                missing
+            ),
+            FsckIssue::BlobSize {
+                hash,
+                size,
+                data_len,
+            } => write!(
+                f,
+                "blob {}: data file has {} bytes, expected {}",
+                short(hash.as_bytes()),
+                data_len,
+                size
+            ),
+        }
+    }
+}
+
+/// Result of a [`fsck`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    pub conversations: usize,
+    /// Distinct nodes found in journals and packs.
+    pub nodes: usize,
+    pub blobs: usize,
+    /// Issues in the order they were found.
+    pub issues: Vec<FsckIssue>,
+    /// Whether the repairable issues were repaired.
+    pub repaired: bool,
+    /// Directory holding what the repair removed, if it removed anything.
+    pub quarantine: Option<PathBuf>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Issues still present in the store.
+    pub fn remaining(&self) -> Vec<&FsckIssue> {
+        self.issues
+            .iter()
+            .filter(|i| !(self.repaired && i.is_repairable()))
+            .collect()
+    }
+}
+
+/// Checks the store under `root` and, in [`FsckMode::Repair`], repairs it.
+///
+/// The store must not be open; `root` is locked exclusively for the run.
+/// Encrypted stores are checked by passing their
+/// [`EncryptedFileSystem`](crate::crypt::EncryptedFileSystem).
+pub fn fsck<F: FileSystem>(root: &Path, fs: Arc<F>, mode: FsckMode) -> MerkleToxResult<FsckReport> {
+    if !fs.exists(root) {
+        return Err(MerkleToxError::Io(Error::new(
+            io::ErrorKind::NotFound,
+            "Storage root does not exist",
+        )));
+    }
+    let lock_file = fs.open(&root.join(".lock"), true, true, false)?;
+    lock_file.try_lock_exclusive().map_err(|_| {
+        MerkleToxError::Io(Error::other("Storage root is in use by another process"))
+    })?;
+
+    let mut checker = Checker {
+        fs: fs.clone(),
+        root: root.to_path_buf(),
+        repair: mode == FsckMode::Repair,
+        run_id: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64,
+        report: FsckReport::default(),
+        owners: HashMap::new(),
+    };
+
+    let mut dirs = fs.read_dir(&root.join("conversations")).unwrap_or_default();
+    dirs.sort();
+    for dir in dirs {
+        let Some(id) = dir
+            .file_name()
+            .and_then(|name| decode_hex_32(&name.to_string_lossy()))
+        else {
+            continue;
+        };
+        if fs.metadata(&dir)?.is_dir {
+            checker.check_conversation(ConversationId::from(id), &dir)?;
+        }
+    }
+    checker.check_duplicates();
+    checker.check_blobs()?;
+
+    checker.report.repaired = checker.repair;
+    Ok(checker.report)
+}
+
+struct Checker<F: FileSystem> {
+    fs: Arc<F>,
+    root: PathBuf,
+    repair: bool,
+    run_id: u64,
+    report: FsckReport,
+    /// Conversations storing each node.
+    owners: HashMap<NodeHash, Vec<ConversationId>>,
+}
+
+/// A journal parsed without modifying it.
+struct JournalScan {
+    generation_id: u64,
+    records: Vec<ScannedRecord>,
+    /// Start of the torn or corrupt tail.
+    corrupt_from: Option<usize>,
+}
+
+struct ScannedRecord {
+    range: Range<usize>,
+    record_type: JournalRecordType,
+}
+
+fn scan_journal(data: &[u8]) -> JournalScan {
+    let generation_id = u64::from_le_bytes(data[0..8].try_into().unwrap());
+    let version = data[8];
+    let mut records = Vec::new();
+    let mut corrupt_from = None;
+    let mut pos = JOURNAL_HEADER_SIZE;
+    while pos < data.len() {
+        if is_footer(data, pos) {
+            break;
+        }
+        if pos + FRAME_HEADER_SIZE > data.len() {
+            corrupt_from = Some(pos);
+            break;
+        }
+        let length = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + FRAME_HEADER_SIZE + length;
+        if end > data.len() {
+            corrupt_from = Some(pos);
+            break;
+        }
+        let checksum = NodeHash::from(<[u8; 32]>::try_from(&data[pos + 4..pos + 36]).unwrap());
+        let payload = &data[pos + FRAME_HEADER_SIZE..end];
+        let record_type = JournalRecordType::try_from(data[pos + 36]);
+        match record_type {
+            Ok(record_type) if record_checksum(version, data[pos + 36], payload) == checksum => {
+                records.push(ScannedRecord {
+                    range: pos..end,
+                    record_type,
+                });
+            }
+            _ => {
+                corrupt_from = Some(pos);
+                break;
+            }
+        }
+        pos = end;
+    }
+    JournalScan {
+        generation_id,
+        records,
+        corrupt_from,
+    }
+}
+
+fn is_footer(data: &[u8], pos: usize) -> bool {
+    pos + JOURNAL_FOOTER_SIZE as usize == data.len()
+        && u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) == JOURNAL_FOOTER_MAGIC
+}
+
+/// The decoded contents of a journal.
+struct JournalCheck {
+    data: Vec<u8>,
+    scan: JournalScan,
+    /// Whether each scanned record is kept by a repair.
+    keep: Vec<bool>,
+    nodes: Vec<(MerkleNode, bool)>,
+    /// Promotion and prune records: record index and node.
+    refs: Vec<(usize, NodeHash)>,
+}
+
+/// A pack frame holding a node, or a tombstone if `node` is `None`.
+struct Frame {
+    range: Range<usize>,
+    hash: NodeHash,
+    node: Option<(u8, MerkleNode)>,
+}
+
+struct PackScan {
+    frames: Vec<Frame>,
+    /// Frames that cannot be used, including a torn tail.
+    bad: Vec<Range<usize>>,
+}
+
+fn scan_pack(data: &[u8]) -> PackScan {
+    let mut frames = Vec::new();
+    let mut bad = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        if pos + FRAME_HEADER_SIZE > data.len() {
+            bad.push(pos..data.len());
+            break;
+        }
+        let length = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let end = pos + FRAME_HEADER_SIZE + length;
+        if end > data.len() {
+            bad.push(pos..data.len());
+            break;
+        }
+        let hash = NodeHash::from(<[u8; 32]>::try_from(&data[pos + 4..pos + 36]).unwrap());
+        let payload = &data[pos + FRAME_HEADER_SIZE..end];
+        if payload.is_empty() {
+            frames.push(Frame {
+                range: pos..end,
+                hash,
+                node: None,
+            });
+        } else {
+            match tox_proto::deserialize::<(u8, MerkleNode)>(payload) {
+                Ok(decoded) if decoded.1.hash() == hash => frames.push(Frame {
+                    range: pos..end,
+                    hash,
+                    node: Some(decoded),
+                }),
+                _ => bad.push(pos..end),
+            }
+        }
+        pos = end;
+    }
+    PackScan { frames, bad }
+}
+
+/// Why `index` does not describe the pack, if it does not.
+fn index_mismatch(index: &PackIndex, scan: &PackScan) -> Option<String> {
+    if index.records.windows(2).any(|w| w[0].hash > w[1].hash) {
+        return Some("records are not sorted".to_string());
+    }
+    if index.bloom_k == 0 || index.bloom_k > 32 {
+        return Some(format!("invalid bloom filter hash count {}", index.bloom_k));
+    }
+    let rebuilt = PackIndex::build(index.records.clone(), index.fanout_bits, index.bloom_k);
+    if rebuilt.fanout_table != index.fanout_table {
+        return Some("fanout table does not match the records".to_string());
+    }
+    if rebuilt.bloom_filter != index.bloom_filter {
+        return Some("bloom filter does not match the records".to_string());
+    }
+
+    let frames: HashMap<u64, &Frame> = scan
+        .frames
+        .iter()
+        .map(|f| (f.range.start as u64, f))
+        .collect();
+    // Records of corrupt frames are reported as corrupt nodes.
+    let bad: HashSet<u64> = scan.bad.iter().map(|r| r.start as u64).collect();
+    for record in &index.records {
+        if bad.contains(&record.offset) {
+            continue;
+        }
+        match frames.get(&record.offset) {
+            Some(frame)
+                if frame.hash == record.hash
+                    && frame.range.len() - FRAME_HEADER_SIZE == record.payload_length as usize => {}
+            _ => {
+                return Some(format!(
+                    "record {} does not match a node in the pack",
+                    short(record.hash.as_bytes())
+                ));
+            }
+        }
+    }
+    let indexed: HashSet<u64> = index.records.iter().map(|r| r.offset).collect();
+    if let Some(frame) = scan
+        .frames
+        .iter()
+        .find(|f| !indexed.contains(&(f.range.start as u64)))
+    {
+        return Some(format!(
+            "node {} at offset {} is not indexed",
+            short(frame.hash.as_bytes()),
+            frame.range.start
+        ));
+    }
+    None
+}
+
+fn pack_id_of(path: &Path) -> Option<u64> {
+    let name = path.file_name()?.to_str()?;
+    let stem = name
+        .strip_suffix(".pack")
+        .or_else(|| name.strip_suffix(".idx"))?;
+    if stem.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(stem, 16).ok()
+}
+
+fn admin_distance(node: &MerkleNode, distances: &HashMap<NodeHash, u16>) -> u16 {
+    if node.node_type() != NodeType::Content {
+        return 0;
+    }
+    match node.parents.iter().filter_map(|p| distances.get(p)).min() {
+        Some(&d) => d.saturating_add(1),
+        None => u16::MAX,
+    }
+}
+
+/// Heads and admin heads among the verified `nodes`.
+fn heads_of(nodes: &[(MerkleNode, bool)]) -> (Vec<NodeHash>, Vec<NodeHash>) {
+    let verified: Vec<&MerkleNode> = nodes.iter().filter(|(_, v)| *v).map(|(n, _)| n).collect();
+    let parents: HashSet<NodeHash> = verified
+        .iter()
+        .flat_map(|n| n.parents.iter().copied())
+        .collect();
+    let admin_parents: HashSet<NodeHash> = verified
+        .iter()
+        .filter(|n| n.node_type() == NodeType::Admin)
+        .flat_map(|n| n.parents.iter().copied())
+        .collect();
+    let mut heads = BTreeSet::new();
+    let mut admin_heads = BTreeSet::new();
+    for node in verified {
+        let hash = node.hash();
+        if !parents.contains(&hash) {
+            heads.insert(hash);
+        }
+        if node.node_type() == NodeType::Admin && !admin_parents.contains(&hash) {
+            admin_heads.insert(hash);
+        }
+    }
+    (
+        heads.into_iter().collect(),
+        admin_heads.into_iter().collect(),
+    )
+}
+
+impl<F: FileSystem> Checker<F> {
+    fn issue(&mut self, issue: FsckIssue) {
+        self.report.issues.push(issue);
+    }
+
+    /// Keeps `data` as `quarantine/<run>/<name>`.
+    fn quarantine(&mut self, name: &Path, data: &[u8]) -> MerkleToxResult<()> {
+        let dir = self
+            .root
+            .join("quarantine")
+            .join(format!("{:016x}", self.run_id));
+        let path = dir.join(name);
+        if let Some(parent) = path.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        self.fs.write(&path, data)?;
+        self.report.quarantine = Some(dir);
+        Ok(())
+    }
+
+    /// Moves the file at `path` into the quarantine as `name`.
+    fn quarantine_file(&mut self, path: &Path, name: &Path) -> MerkleToxResult<()> {
+        let data = self.fs.read(path)?;
+        self.quarantine(name, &data)?;
+        self.fs.remove_file(path)?;
+        Ok(())
+    }
+
+    fn replace(&self, path: &Path, data: &[u8]) -> MerkleToxResult<()> {
+        let mut tmp_path = path.to_path_buf();
+        tmp_path.set_extension("tmp");
+        self.fs.write(&tmp_path, data)?;
+        self.fs.rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn check_conversation(&mut self, id: ConversationId, dir: &Path) -> MerkleToxResult<()> {
+        self.report.conversations += 1;
+        let name = PathBuf::from(encode_hex_32(id.as_bytes()));
+
+        let state_path = dir.join("state.bin");
+        let state_file = StateFile::new(self.fs.clone(), state_path.clone());
+        let state = if self.fs.exists(&state_path) {
+            match state_file.load() {
+                Ok(state) => Some(state),
+                Err(_) => {
+                    self.issue(FsckIssue::BadState {
+                        conversation_id: id,
+                    });
+                    None
+                }
+            }
+        } else {
+            Some(ConvState {
+                heads: Vec::new(),
+                admin_heads: Vec::new(),
+                message_count: 0,
+                last_rotation_time: -1,
+                active_packs: Vec::new(),
+                history_horizon: 0,
+                active_journal_id: 0,
+            })
+        };
+
+        let mut journal = self.check_journal(&id, &name, dir, state.as_ref())?;
+
+        // Packs. Without a readable state.bin every pack on disk counts.
+        let packs_dir = dir.join("packs");
+        let mut on_disk = BTreeSet::new();
+        for path in self.fs.read_dir(&packs_dir).unwrap_or_default() {
+            if let Some(pack_id) = pack_id_of(&path) {
+                on_disk.insert(pack_id);
+            }
+        }
+        let active: Vec<u64> = match &state {
+            Some(state) => state.active_packs.clone(),
+            None => on_disk
+                .iter()
+                .copied()
+                .filter(|id| self.fs.exists(&packs_dir.join(format!("{:016x}.pack", id))))
+                .collect(),
+        };
+        for &pack_id in on_disk.iter().filter(|id| !active.contains(id)) {
+            self.issue(FsckIssue::UnreferencedPack {
+                conversation_id: id,
+                pack_id,
+            });
+            if self.repair {
+                for ext in ["pack", "idx"] {
+                    let file = format!("{:016x}.{}", pack_id, ext);
+                    if self.fs.exists(&packs_dir.join(&file)) {
+                        self.quarantine_file(&packs_dir.join(&file), &name.join(&file))?;
+                    }
+                }
+            }
+        }
+
+        let mut distances = HashMap::new();
+        for &pack_id in &active {
+            let index_path = packs_dir.join(format!("{:016x}.idx", pack_id));
+            if let Ok(index) = PackIndex::load(&*self.fs, &index_path) {
+                for record in index.records {
+                    distances.insert(record.hash, record.admin_distance);
+                }
+            }
+        }
+
+        let mut stored = HashSet::new();
+        let mut nodes = Vec::new();
+        let mut missing = Vec::new();
+        for &pack_id in &active {
+            if !self
+                .fs
+                .exists(&packs_dir.join(format!("{:016x}.pack", pack_id)))
+            {
+                self.issue(FsckIssue::MissingPack {
+                    conversation_id: id,
+                    pack_id,
+                });
+                missing.push(pack_id);
+                continue;
+            }
+            for frame in self.check_pack(&id, &name, &packs_dir, pack_id, &mut distances)? {
+                stored.insert(frame.hash);
+                if let Some((status, node)) = frame.node {
+                    nodes.push((node, status == pack::STATUS_VERIFIED));
+                }
+            }
+        }
+
+        if let Some(journal) = &mut journal {
+            for (node, _) in &journal.nodes {
+                stored.insert(node.hash());
+            }
+            for &(i, hash) in &journal.refs {
+                if !stored.contains(&hash) {
+                    journal.keep[i] = false;
+                    self.issue(FsckIssue::DanglingRecord {
+                        conversation_id: id,
+                        offset: journal.scan.records[i].range.start as u64,
+                        hash,
+                    });
+                }
+            }
+            if self.repair && (journal.scan.corrupt_from.is_some() || journal.keep.contains(&false))
+            {
+                self.repair_journal(&name, &dir.join("journal.bin"), journal)?;
+            }
+            nodes.append(&mut journal.nodes);
+        }
+
+        for hash in &stored {
+            self.owners.entry(*hash).or_default().push(id);
+        }
+        self.report.nodes += stored.len();
+
+        if !self.repair {
+            return Ok(());
+        }
+        match state {
+            None => {
+                let (heads, admin_heads) = heads_of(&nodes);
+                let state = ConvState {
+                    heads,
+                    admin_heads,
+                    message_count: 0,
+                    last_rotation_time: -1,
+                    active_packs: active
+                        .into_iter()
+                        .filter(|id| !missing.contains(id))
+                        .collect(),
+                    history_horizon: 0,
+                    active_journal_id: journal.map_or(0, |j| j.scan.generation_id),
+                };
+                self.quarantine_file(&state_path, &name.join("state.bin"))?;
+                state_file.save(&state)?;
+            }
+            Some(mut state) if !missing.is_empty() => {
+                state.active_packs.retain(|id| !missing.contains(id));
+                state_file.save(&state)?;
+            }
+            Some(_) => {}
+        }
+        Ok(())
+    }
+
+    fn check_journal(
+        &mut self,
+        id: &ConversationId,
+        name: &Path,
+        dir: &Path,
+        state: Option<&ConvState>,
+    ) -> MerkleToxResult<Option<JournalCheck>> {
+        let mut path = dir.join("journal.bin");
+        // A compaction interrupted after its commit left the current
+        // journal in journal.bin.new.
+        if let Some(state) = state
+            && self.fs.exists(&dir.join(NEXT_JOURNAL))
+        {
+            if self.repair {
+                recover_next_journal(&*self.fs, dir, state.active_journal_id)?;
+            } else if next_journal_generation(&*self.fs, dir) == Some(state.active_journal_id) {
+                path = dir.join(NEXT_JOURNAL);
+            }
+        }
+        if !self.fs.exists(&path) {
+            return Ok(None);
+        }
+        let data = self.fs.read(&path)?;
+        if data.len() < JOURNAL_HEADER_SIZE {
+            if !data.is_empty() {
+                self.issue(FsckIssue::CorruptJournal {
+                    conversation_id: *id,
+                    offset: 0,
+                    bytes: data.len() as u64,
+                });
+                if self.repair {
+                    self.quarantine_file(&path, &name.join("journal.bin"))?;
+                }
+            }
+            return Ok(None);
+        }
+
+        let scan = scan_journal(&data);
+        if let Some(state) = state
+            && state.active_journal_id != 0
+            && scan.generation_id != state.active_journal_id
+        {
+            self.issue(FsckIssue::JournalGeneration {
+                conversation_id: *id,
+                journal: scan.generation_id,
+                state: state.active_journal_id,
+            });
+            if self.repair {
+                self.quarantine(&name.join("journal.bin"), &data)?;
+                Journal::open(self.fs.clone(), path)?.truncate(state.active_journal_id)?;
+            }
+            return Ok(None);
+        }
+        if let Some(offset) = scan.corrupt_from {
+            self.issue(FsckIssue::CorruptJournal {
+                conversation_id: *id,
+                offset: offset as u64,
+                bytes: (data.len() - offset) as u64,
+            });
+        }
+
+        let mut keep = vec![true; scan.records.len()];
+        let mut nodes = Vec::new();
+        let mut refs = Vec::new();
+        let mut promoted = HashSet::new();
+        for (i, record) in scan.records.iter().enumerate() {
+            let payload = &data[record.range.start + FRAME_HEADER_SIZE..record.range.end];
+            let ok = match record.record_type {
+                JournalRecordType::Node => {
+                    match tox_proto::deserialize::<(u8, MerkleNode)>(payload) {
+                        Ok((status, node)) => {
+                            nodes.push((node, status == 0x01));
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                }
+                JournalRecordType::Promotion | JournalRecordType::Prune => {
+                    match tox_proto::deserialize::<NodeHash>(payload) {
+                        Ok(hash) => {
+                            if record.record_type == JournalRecordType::Promotion {
+                                promoted.insert(hash);
+                            }
+                            refs.push((i, hash));
+                            true
+                        }
+                        Err(_) => false,
+                    }
+                }
+                JournalRecordType::RatchetAdvance => {
+                    tox_proto::deserialize::<(NodeHash, ChainKey, u64)>(payload).is_ok()
+                }
+                JournalRecordType::Vouch | JournalRecordType::Blacklist => true,
+            };
+            if !ok {
+                keep[i] = false;
+                self.issue(FsckIssue::BadJournalRecord {
+                    conversation_id: *id,
+                    offset: record.range.start as u64,
+                });
+            }
+        }
+        for (node, verified) in &mut nodes {
+            *verified |= promoted.contains(&node.hash());
+        }
+
+        Ok(Some(JournalCheck {
+            data,
+            scan,
+            keep,
+            nodes,
+            refs,
+        }))
+    }
+
+    /// Rewrites the journal with only the records to keep; the footer is
+    /// written again when the store closes it.
+    fn repair_journal(
+        &mut self,
+        name: &Path,
+        path: &Path,
+        journal: &JournalCheck,
+    ) -> MerkleToxResult<()> {
+        let data = &journal.data;
+        let mut kept = data[..JOURNAL_HEADER_SIZE].to_vec();
+        let mut dropped = Vec::new();
+        for (record, &keep) in journal.scan.records.iter().zip(&journal.keep) {
+            if keep {
+                kept.extend_from_slice(&data[record.range.clone()]);
+            } else {
+                dropped.extend_from_slice(&data[record.range.clone()]);
+            }
+        }
+        if let Some(offset) = journal.scan.corrupt_from {
+            dropped.extend_from_slice(&data[offset..]);
+        }
+        self.quarantine(&name.join("journal.bin"), &dropped)?;
+        self.replace(path, &kept)
+    }
+
+    /// Checks a pack against its index and returns its usable frames. A
+    /// repair drops the corrupt frames and rebuilds the index, keeping the
+    /// status and admin distance recorded in the old index.
+    fn check_pack(
+        &mut self,
+        id: &ConversationId,
+        name: &Path,
+        packs_dir: &Path,
+        pack_id: u64,
+        distances: &mut HashMap<NodeHash, u16>,
+    ) -> MerkleToxResult<Vec<Frame>> {
+        let data_path = packs_dir.join(format!("{:016x}.pack", pack_id));
+        let index_path = packs_dir.join(format!("{:016x}.idx", pack_id));
+        let data = self.fs.read(&data_path)?;
+        let scan = scan_pack(&data);
+        for range in &scan.bad {
+            self.issue(FsckIssue::BadPackNode {
+                conversation_id: *id,
+                pack_id,
+                offset: range.start as u64,
+            });
+        }
+
+        let index = if self.fs.exists(&index_path) {
+            PackIndex::load(&*self.fs, &index_path).map_err(|e| e.to_string())
+        } else {
+            Err("index file is missing".to_string())
+        };
+        let problem = match &index {
+            Ok(index) => index_mismatch(index, &scan),
+            Err(e) => Some(e.clone()),
+        };
+        if let Some(reason) = &problem {
+            self.issue(FsckIssue::BadPackIndex {
+                conversation_id: *id,
+                pack_id,
+                reason: reason.clone(),
+            });
+        }
+        if !self.repair || (problem.is_none() && scan.bad.is_empty()) {
+            return Ok(scan.frames);
+        }
+
+        let old: HashMap<NodeHash, IndexRecord> = index
+            .map(|index| index.records.into_iter().map(|r| (r.hash, r)).collect())
+            .unwrap_or_default();
+        let mut kept = Vec::with_capacity(data.len());
+        let mut dropped = Vec::new();
+        for range in &scan.bad {
+            dropped.extend_from_slice(&data[range.clone()]);
+        }
+        let mut records = Vec::new();
+        let mut frames = Vec::new();
+        for frame in scan.frames {
+            let bytes = &data[frame.range.clone()];
+            let offset = kept.len() as u64;
+            let payload_length = (bytes.len() - FRAME_HEADER_SIZE) as u32;
+            let old_record = old
+                .get(&frame.hash)
+                .filter(|r| (r.status == pack::STATUS_TOMBSTONE) == frame.node.is_none());
+            let record = match (old_record, &frame.node) {
+                (Some(record), _) => IndexRecord {
+                    offset,
+                    payload_length,
+                    ..*record
+                },
+                (None, Some((status, node))) => IndexRecord {
+                    hash: frame.hash,
+                    offset,
+                    rank: node.topological_rank,
+                    payload_length,
+                    node_type: if node.node_type() == NodeType::Admin {
+                        0x01
+                    } else {
+                        0x02
+                    },
+                    status: *status,
+                    admin_distance: admin_distance(node, distances),
+                },
+                // A tombstone keeps nothing its record could be rebuilt from.
+                (None, None) => {
+                    dropped.extend_from_slice(bytes);
+                    continue;
+                }
+            };
+            distances.insert(record.hash, record.admin_distance);
+            kept.extend_from_slice(bytes);
+            records.push(record);
+            frames.push(frame);
+        }
+
+        if !dropped.is_empty() {
+            self.quarantine(&name.join(format!("{:016x}.pack", pack_id)), &dropped)?;
+            self.replace(&data_path, &kept)?;
+        }
+        let mut tmp_path = index_path.clone();
+        tmp_path.set_extension("idx.tmp");
+        PackIndex::build(records, pack::DEFAULT_FANOUT_BITS, 2).save(&*self.fs, &tmp_path)?;
+        self.fs.rename(&tmp_path, &index_path)?;
+        Ok(frames)
+    }
+
+    fn check_duplicates(&mut self) {
+        let mut duplicates: Vec<_> = self
+            .owners
+            .drain()
+            .filter(|(_, conversations)| conversations.len() > 1)
+            .collect();
+        duplicates.sort_by_key(|(hash, _)| *hash);
+        for (hash, conversations) in duplicates {
+            self.issue(FsckIssue::DuplicateNode {
+                hash,
+                conversations,
+            });
+        }
+    }
+
+    fn check_blobs(&mut self) -> MerkleToxResult<()> {
+        let objects = self.root.join("objects");
+        let blobs = BlobStore::new(objects.clone(), self.fs.clone());
+        let mut dirs = self.fs.read_dir(&objects).unwrap_or_default();
+        dirs.sort();
+        for dir in dirs {
+            if !self.fs.metadata(&dir)?.is_dir {
+                continue;
+            }
+            let mut paths = self.fs.read_dir(&dir)?;
+            paths.sort();
+            for path in paths {
+                if path.extension().is_some_and(|e| e == "info")
+                    && let Some(stem) = path.file_stem()
+                    && let Some(bytes) = decode_hex_32(&stem.to_string_lossy())
+                {
+                    self.report.blobs += 1;
+                    self.check_blob(&blobs, &dir, NodeHash::from(bytes))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_blob(
+        &mut self,
+        blobs: &BlobStore<F>,
+        dir: &Path,
+        hash: NodeHash,
+    ) -> MerkleToxResult<()> {
+        let hex = encode_hex_32(hash.as_bytes());
+        let mut info = match blobs.get_info(&hash) {
+            Ok(Some(info)) => info,
+            Ok(None) => return Ok(()),
+            Err(_) => {
+                self.issue(FsckIssue::BadBlobInfo { hash });
+                if self.repair {
+                    // References are kept; the blob is fetched again.
+                    for ext in ["info", "data", "bao"] {
+                        let file = format!("{}.{}", hex, ext);
+                        if self.fs.exists(&dir.join(&file)) {
+                            self.quarantine_file(
+                                &dir.join(&file),
+                                &Path::new("objects").join(&file),
+                            )?;
+                        }
+                    }
+                }
+                return Ok(());
+            }
+        };
+
+        let data_path = dir.join(format!("{}.data", hex));
+        let data_len = if self.fs.exists(&data_path) {
+            self.fs.metadata(&data_path)?.len
+        } else {
+            0
+        };
+        let num_chunks = info.size.div_ceil(CHUNK_SIZE);
+        let expected = (num_chunks as usize).div_ceil(8);
+        let mut changed = false;
+        let mut refinalize = false;
+
+        if let Some(mask) = &mut info.received_mask
+            && mask.len() != expected
+        {
+            self.issue(FsckIssue::BlobMask {
+                hash,
+                len: mask.len(),
+                expected,
+            });
+            mask.resize(expected, 0);
+            for chunk in num_chunks..expected as u64 * 8 {
+                mask[(chunk / 8) as usize] &= !(1 << (chunk % 8));
+            }
+            changed = true;
+        }
+
+        let available = info.status == BlobStatus::Available;
+        if (available && data_len != info.size) || data_len > info.size {
+            self.issue(FsckIssue::BlobSize {
+                hash,
+                size: info.size,
+                data_len,
+            });
+            if data_len > info.size {
+                if self.repair {
+                    let mut file = self.fs.open(&data_path, false, true, false)?;
+                    file.set_len(info.size)?;
+                }
+                refinalize = available;
+            } else {
+                // Download the rest again.
+                info.status = BlobStatus::Downloading;
+                info.bao_root = None;
+                clear_chunks_past(&mut info.received_mask, data_len, info.size);
+                changed = true;
+            }
+        } else if !available {
+            let missing = clear_chunks_past(&mut info.received_mask, data_len, info.size);
+            if missing > 0 {
+                self.issue(FsckIssue::BlobChunks { hash, missing });
+                changed = true;
+            }
+        }
+
+        if !self.repair {
+            return Ok(());
+        }
+        if changed {
+            let bao_path = dir.join(format!("{}.bao", hex));
+            if info.bao_root.is_none() && self.fs.exists(&bao_path) {
+                self.fs.remove_file(&bao_path)?;
+            }
+            blobs.put_info(&info)?;
+        }
+        if refinalize {
+            blobs.finalize(&hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// Unmarks the chunks of a blob of `size` bytes that are not completely
+/// within the first `data_len` bytes. Returns how many were marked.
+fn clear_chunks_past(mask: &mut Option<Vec<u8>>, data_len: u64, size: u64) -> u64 {
+    let Some(mask) = mask else {
+        return 0;
+    };
+    let mut cleared = 0;
+    for chunk in 0..mask.len() as u64 * 8 {
+        let end = ((chunk + 1) * CHUNK_SIZE).min(size);
+        let bit = 1 << (chunk % 8);
+        let byte = &mut mask[(chunk / 8) as usize];
+        if *byte & bit != 0 && end > data_len {
+            *byte &= !bit;
+            cleared += 1;
+        }
+    }
+    cleared
+}
@@ -0,0 +1,288 @@
+use crate::pack::{self, Pack};
+use crate::state::StateFile;
+use crate::{FsInner, FsStore};
+
+use merkle_tox_core::dag::{Content, ConversationId, MerkleNode, NodeHash};
+use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use merkle_tox_core::vfs::FileSystem;
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, Write};
+use std::path::Path;
+
+/// Controls what [`FsStore::gc`] reclaims.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Packs holding fewer records than this are merged into a single pack.
+    pub merge_below_records: usize,
+    /// Replace the payload of nodes targeted by a verified `Redaction` with a
+    /// tombstone. The index entry is kept so the node still counts as present
+    /// for reconciliation, but its content can no longer be served.
+    pub drop_redacted: bool,
+    /// Drop packed nodes that were never verified.
+    pub drop_speculative: bool,
+    /// Delete files in `packs/` that are not referenced by `state.bin`, e.g.
+    /// leftovers of an interrupted compaction or GC run.
+    pub remove_orphans: bool,
+}
+
+impl Default for GcPolicy {
+    fn default() -> Self {
+        Self {
+            merge_below_records: 4 * crate::COMPACT_THRESHOLD,
+            drop_redacted: true,
+            drop_speculative: false,
+            remove_orphans: true,
+        }
+    }
+}
+
+/// Summary of a [`FsStore::gc`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// Number of packs that were rewritten into the new pack.
+    pub packs_rewritten: usize,
+    /// Records removed entirely (duplicates and dropped speculative nodes).
+    pub records_dropped: usize,
+    /// Records whose payload was replaced with a tombstone.
+    pub records_tombstoned: usize,
+    /// Files removed from `packs/` that were not referenced by `state.bin`.
+    pub orphans_removed: usize,
+}
+
+impl<F: FileSystem> FsStore<F> {
+    /// Reclaims space in the packs of a conversation.
+    ///
+    /// The journal is compacted first so that every node lives in a pack.
+    /// Packs that contain superseded or tombstoned records, or that are
+    /// smaller than [`GcPolicy::merge_below_records`], are rewritten into a
+    /// single new pack. The new pack is written under a temporary name,
+    /// synced and renamed into place before `state.bin` is replaced, so a
+    /// crash at any point leaves either the old or the new set of packs
+    /// active.
+    pub fn gc(&self, id: &ConversationId, policy: &GcPolicy) -> MerkleToxResult<GcStats> {
+        self.ensure_conversation(id)?;
+        // A background compaction step must not write packs that look like
+        // orphans while this runs.
+        let _compacting = self.compaction_lock.lock();
+        let mut inner = self.inner.write();
+        {
+            let ctx = inner.conversations.get_mut(id).unwrap();
+            ctx.lock_file.try_lock_exclusive().map_err(|_| {
+                MerkleToxError::Io(Error::other(
+                    "Failed to upgrade to exclusive lock for garbage collection",
+                ))
+            })?;
+        }
+        let res = self
+            .compact_internal(&mut inner, id)
+            .and_then(|_| self.gc_internal(&mut inner, id, policy));
+        {
+            if let Some(ctx) = inner.conversations.get_mut(id) {
+                let _ = ctx.lock_file.try_lock_shared(); // downgrade back
+            }
+        }
+        res
+    }
+
+    fn gc_internal(
+        &self,
+        inner: &mut FsInner<F>,
+        id: &ConversationId,
+        policy: &GcPolicy,
+    ) -> MerkleToxResult<GcStats> {
+        let mut stats = GcStats::default();
+        let ctx = inner.conversations.get_mut(id).unwrap();
+
+        // 1. Pick a single surviving record per hash. A verified copy
+        // supersedes a speculative one; a tombstone supersedes both.
+        let mut winners: HashMap<NodeHash, (usize, usize)> = HashMap::new();
+        for (p, pack) in ctx.packs.iter().enumerate() {
+            for (r, record) in pack.index.records.iter().enumerate() {
+                match winners.get(&record.hash) {
+                    Some(&(wp, wr))
+                        if status_priority(ctx.packs[wp].index.records[wr].status)
+                            >= status_priority(record.status) => {}
+                    _ => {
+                        winners.insert(record.hash, (p, r));
+                    }
+                }
+            }
+        }
+
+        // 2. Collect redaction targets from verified nodes.
+        let mut redacted = HashSet::new();
+        if policy.drop_redacted {
+            for pack in &ctx.packs {
+                for record in &pack.index.records {
+                    if record.status != pack::STATUS_VERIFIED {
+                        continue;
+                    }
+                    if let Some(data) = pack.get_node_data(&record.hash)?
+                        && let Ok((_, node)) = tox_proto::deserialize::<(u8, MerkleNode)>(&data)
+                        && let Content::Redaction { target_hash, .. } = node.content
+                    {
+                        redacted.insert(target_hash);
+                    }
+                }
+            }
+        }
+
+        // Expired nodes lose their payload regardless of the policy.
+        redacted.extend(ctx.pruned.iter().copied());
+
+        // 3. Decide which packs need rewriting.
+        let mut dropped = Vec::new();
+        let mut dirty = vec![false; ctx.packs.len()];
+        for (p, pack) in ctx.packs.iter().enumerate() {
+            for (r, record) in pack.index.records.iter().enumerate() {
+                let superseded = winners.get(&record.hash) != Some(&(p, r));
+                let speculative =
+                    policy.drop_speculative && record.status == pack::STATUS_SPECULATIVE;
+                let tombstone =
+                    redacted.contains(&record.hash) && record.status == pack::STATUS_VERIFIED;
+                if superseded || speculative || tombstone {
+                    dirty[p] = true;
+                }
+                if speculative && !superseded {
+                    dropped.push(record.hash);
+                }
+            }
+        }
+        let small = ctx
+            .packs
+            .iter()
+            .filter(|p| p.index.records.len() < policy.merge_below_records)
+            .count();
+        for (p, pack) in ctx.packs.iter().enumerate() {
+            if small > 1 && pack.index.records.len() < policy.merge_below_records {
+                dirty[p] = true;
+            }
+        }
+
+        let packs_dir = ctx.path.join("packs");
+        if dirty.iter().any(|&d| d) {
+            // 4. Shadow write the merged pack under temporary names.
+            let pack_id = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64;
+            let data_path = packs_dir.join(format!("{:016x}.pack", pack_id));
+            let index_path = packs_dir.join(format!("{:016x}.idx", pack_id));
+            let data_tmp = packs_dir.join(format!("{:016x}.pack.tmp", pack_id));
+            let index_tmp = packs_dir.join(format!("{:016x}.idx.tmp", pack_id));
+
+            let mut index_records = Vec::new();
+            {
+                let mut data_file = self.fs.open(&data_tmp, true, true, true)?;
+                let mut offset = 0u64;
+                for (p, pack) in ctx.packs.iter().enumerate() {
+                    if !dirty[p] {
+                        continue;
+                    }
+                    for (r, record) in pack.index.records.iter().enumerate() {
+                        if winners.get(&record.hash) != Some(&(p, r)) {
+                            stats.records_dropped += 1;
+                            continue;
+                        }
+                        if policy.drop_speculative && record.status == pack::STATUS_SPECULATIVE {
+                            stats.records_dropped += 1;
+                            continue;
+                        }
+
+                        let mut new_record = *record;
+                        let payload = if redacted.contains(&record.hash)
+                            && record.status == pack::STATUS_VERIFIED
+                        {
+                            stats.records_tombstoned += 1;
+                            new_record.status = pack::STATUS_TOMBSTONE;
+                            Vec::new()
+                        } else {
+                            pack.get_node_data(&record.hash)?.unwrap_or_default()
+                        };
+
+                        new_record.offset = offset;
+                        new_record.payload_length = payload.len() as u32;
+                        let record_type = 0x01u8; // Node
+                        data_file.write_all(&(payload.len() as u32).to_le_bytes())?;
+                        data_file.write_all(record.hash.as_bytes())?;
+                        data_file.write_all(&[record_type])?;
+                        data_file.write_all(&payload)?;
+                        offset += 4 + 32 + 1 + payload.len() as u64;
+                        index_records.push(new_record);
+                    }
+                    stats.packs_rewritten += 1;
+                }
+                data_file.sync()?;
+            }
+            let pack_index = pack::PackIndex::build(index_records, pack::DEFAULT_FANOUT_BITS, 2);
+            pack_index.save(&*self.fs, &index_tmp)?;
+            self.fs.rename(&data_tmp, &data_path)?;
+            self.fs.rename(&index_tmp, &index_path)?;
+
+            // 5. Commit. Until state.bin is replaced the old packs stay
+            // active and the new files are orphans.
+            let old_packs = std::mem::take(&mut ctx.state.active_packs);
+            let mut kept_packs = Vec::new();
+            let mut kept_ids = Vec::new();
+            for (p, (pack, old_id)) in std::mem::take(&mut ctx.packs)
+                .into_iter()
+                .zip(old_packs)
+                .enumerate()
+            {
+                if !dirty[p] {
+                    kept_packs.push(pack);
+                    kept_ids.push(old_id);
+                }
+            }
+            kept_ids.push(pack_id);
+            ctx.state.active_packs = kept_ids;
+
+            let state_file = StateFile::new(self.fs.clone(), ctx.path.join("state.bin"));
+            state_file.save(&ctx.state)?;
+
+            kept_packs.push(Pack::open(self.fs.clone(), data_path, &index_path)?);
+            ctx.packs = kept_packs;
+        }
+
+        // 6. Remove everything in packs/ that state.bin no longer references.
+        if policy.remove_orphans {
+            let active: HashSet<u64> = ctx.state.active_packs.iter().copied().collect();
+            if let Ok(entries) = self.fs.read_dir(&packs_dir) {
+                for path in entries {
+                    if !is_active_pack_file(&path, &active) && self.fs.remove_file(&path).is_ok() {
+                        stats.orphans_removed += 1;
+                    }
+                }
+            }
+        }
+
+        for hash in dropped {
+            inner.node_to_conv.remove(&hash);
+        }
+
+        Ok(stats)
+    }
+}
+
+fn status_priority(status: u8) -> u8 {
+    match status {
+        pack::STATUS_TOMBSTONE => 2,
+        pack::STATUS_VERIFIED => 1,
+        _ => 0,
+    }
+}
+
+fn is_active_pack_file(path: &Path, active: &HashSet<u64>) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+    let stem = match name
+        .strip_suffix(".pack")
+        .or_else(|| name.strip_suffix(".idx"))
+    {
+        Some(stem) => stem,
+        None => return false,
+    };
+    u64::from_str_radix(stem, 16).is_ok_and(|id| active.contains(&id))
+}
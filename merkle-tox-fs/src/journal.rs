@@ -12,6 +12,7 @@ pub enum JournalRecordType {
     Blacklist = 0x03,
     Promotion = 0x04,
     RatchetAdvance = 0x05,
+    Prune = 0x06,
 }
 
 impl TryFrom<u8> for JournalRecordType {
@@ -23,6 +24,7 @@ impl TryFrom<u8> for JournalRecordType {
             0x03 => Ok(JournalRecordType::Blacklist),
             0x04 => Ok(JournalRecordType::Promotion),
             0x05 => Ok(JournalRecordType::RatchetAdvance),
+            0x06 => Ok(JournalRecordType::Prune),
             _ => Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Invalid journal record type",
@@ -39,10 +41,31 @@ pub struct JournalRecord {
 }
 
 pub const JOURNAL_FOOTER_MAGIC: u32 = 0x454E4421;
+pub(crate) const JOURNAL_FOOTER_SIZE: u64 = 4 + 4 + 32;
+pub(crate) const RECORD_HEADER_SIZE: u64 = 4 + 32 + 1;
+
+/// Record checksums cover only the payload.
+pub const JOURNAL_VERSION_LEGACY: u8 = 0;
+/// Record checksums also cover the length and type, so a torn or corrupted
+/// record header is detected rather than misread.
+pub const JOURNAL_VERSION_CHECKED: u8 = 1;
+
+/// Checksum stored in the header of a record in a journal of `version`.
+pub fn record_checksum(version: u8, record_type: u8, payload: &[u8]) -> NodeHash {
+    if version == JOURNAL_VERSION_LEGACY {
+        return NodeHash::from(*blake3::hash(payload).as_bytes());
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&(payload.len() as u32).to_le_bytes());
+    hasher.update(&[record_type]);
+    hasher.update(payload);
+    NodeHash::from(*hasher.finalize().as_bytes())
+}
 
 pub struct Journal<F: FileSystem> {
     handle: Box<dyn FileHandle>,
     generation_id: u64,
+    version: u8,
     has_footer: bool,
     _marker: std::marker::PhantomData<F>,
 }
@@ -52,6 +75,7 @@ impl<F: FileSystem> Journal<F> {
         let mut handle = fs.open(&path, true, true, false)?;
         let metadata = handle.metadata()?;
         let generation_id;
+        let version;
         let mut has_footer = false;
 
         if metadata.len < 16 {
@@ -60,17 +84,19 @@ impl<F: FileSystem> Journal<F> {
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_nanos() as u64;
+            version = JOURNAL_VERSION_CHECKED;
             handle.set_len(0)?;
             handle.write_all(&generation_id.to_le_bytes())?;
-            handle.write_all(&[0u8; 8])?; // reserved
+            handle.write_all(&[version, 0, 0, 0, 0, 0, 0, 0])?; // version + reserved
         } else {
             let mut header = [0u8; 16];
             handle.read_exact(&mut header)?;
             generation_id = u64::from_le_bytes(header[0..8].try_into().unwrap());
+            version = header[8];
 
             // Check for footer
-            if metadata.len > 16 + 4 + 4 + 32 {
-                handle.seek(SeekFrom::Start(metadata.len - 40))?;
+            if metadata.len >= 16 + JOURNAL_FOOTER_SIZE {
+                handle.seek(SeekFrom::Start(metadata.len - JOURNAL_FOOTER_SIZE))?;
                 let mut footer_buf = [0u8; 4];
                 handle.read_exact(&mut footer_buf)?;
                 if u32::from_le_bytes(footer_buf) == JOURNAL_FOOTER_MAGIC {
@@ -82,6 +108,7 @@ impl<F: FileSystem> Journal<F> {
         Ok(Journal {
             handle,
             generation_id,
+            version,
             has_footer,
             _marker: std::marker::PhantomData,
         })
@@ -91,18 +118,36 @@ impl<F: FileSystem> Journal<F> {
         self.generation_id
     }
 
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    fn checksum(&self, record_type: u8, payload: &[u8]) -> NodeHash {
+        record_checksum(self.version, record_type, payload)
+    }
+
     pub fn append(
         &mut self,
         record_type: JournalRecordType,
         payload: &[u8],
     ) -> io::Result<(NodeHash, u64)> {
+        let mut appended = self.append_batch(&[(record_type, payload)])?;
+        Ok(appended.pop().expect("one record appended"))
+    }
+
+    /// Appends several records with a single write. Returns the checksum and
+    /// offset of each record.
+    pub fn append_batch<P: AsRef<[u8]>>(
+        &mut self,
+        records: &[(JournalRecordType, P)],
+    ) -> io::Result<Vec<(NodeHash, u64)>> {
         if self.has_footer {
             // SPEC: Section 4.1 - Cleanup: ftruncate() the file to remove the footer.
             // We find the data end offset by reading all records. While slightly
             // inefficient, it guarantees we truncate at the correct boundary.
             let records = self.read_all()?;
             let end_offset = if let Some(last) = records.last() {
-                last.offset + 4 + 32 + 1 + last.payload.len() as u64
+                last.offset + RECORD_HEADER_SIZE + last.payload.len() as u64
             } else {
                 16
             };
@@ -110,21 +155,30 @@ impl<F: FileSystem> Journal<F> {
             self.has_footer = false;
         }
 
-        let hash = blake3::hash(payload);
-        let node_hash = NodeHash::from(*hash.as_bytes());
-
-        let length = payload.len() as u32;
-
-        let offset = self.handle.seek(SeekFrom::End(0))?;
-        self.handle.write_all(&length.to_le_bytes())?;
-        self.handle.write_all(node_hash.as_bytes())?;
-        self.handle.write_all(&[record_type as u8])?;
-        self.handle.write_all(payload)?;
+        let mut offset = self.handle.seek(SeekFrom::End(0))?;
+        let mut buf = Vec::new();
+        let mut appended = Vec::with_capacity(records.len());
+        for (record_type, payload) in records {
+            let record_type = *record_type;
+            let payload = payload.as_ref();
+            let node_hash = self.checksum(record_type as u8, payload);
+            let length = payload.len() as u32;
+            buf.extend_from_slice(&length.to_le_bytes());
+            buf.extend_from_slice(node_hash.as_bytes());
+            buf.push(record_type as u8);
+            buf.extend_from_slice(payload);
+            appended.push((node_hash, offset));
+            offset += RECORD_HEADER_SIZE + payload.len() as u64;
+        }
+        self.handle.write_all(&buf)?;
 
-        Ok((node_hash, offset))
+        Ok(appended)
     }
 
     pub fn write_footer(&mut self) -> io::Result<()> {
+        if self.has_footer {
+            return Ok(());
+        }
         let records = self.read_all()?;
         let mut hasher = blake3::Hasher::new();
         for rec in &records {
@@ -145,7 +199,23 @@ impl<F: FileSystem> Journal<F> {
         Ok(())
     }
 
+    /// Writes the footer and flushes the file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_footer()?;
+        self.handle.flush()
+    }
+
+    /// Writes the footer and syncs the file to disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.write_footer()?;
+        self.handle.sync()
+    }
+
+    /// Reads every intact record. Reading stops at the first torn or
+    /// corrupted record, and the journal is truncated there so that new
+    /// records are not appended after garbage.
     pub fn read_all(&mut self) -> io::Result<Vec<JournalRecord>> {
+        let file_len = self.handle.metadata()?.len;
         self.handle.seek(SeekFrom::Start(16))?;
         let mut records = Vec::new();
 
@@ -153,10 +223,25 @@ impl<F: FileSystem> Journal<F> {
             let offset = self.handle.stream_position()?;
             let mut len_buf = [0u8; 4];
             if self.handle.read_exact(&mut len_buf).is_err() {
+                if offset < file_len {
+                    self.handle.set_len(offset)?;
+                }
                 break;
             }
             let length = u32::from_le_bytes(len_buf);
 
+            // The footer is a valid end of the journal, not a torn record.
+            if length == JOURNAL_FOOTER_MAGIC && offset + JOURNAL_FOOTER_SIZE == file_len {
+                break;
+            }
+
+            // A length running past the end of the file means the record was
+            // torn; don't try to allocate it.
+            if offset + RECORD_HEADER_SIZE + length as u64 > file_len {
+                self.handle.set_len(offset)?;
+                break;
+            }
+
             let mut hash_buf = [0u8; 32];
             if self.handle.read_exact(&mut hash_buf).is_err() {
                 self.handle.set_len(offset)?;
@@ -169,13 +254,6 @@ impl<F: FileSystem> Journal<F> {
                 self.handle.set_len(offset)?;
                 break;
             }
-            let record_type = match JournalRecordType::try_from(type_buf[0]) {
-                Ok(t) => t,
-                Err(_) => {
-                    self.handle.set_len(offset)?;
-                    break;
-                }
-            };
 
             let mut payload = vec![0u8; length as usize];
             if self.handle.read_exact(&mut payload).is_err() {
@@ -183,14 +261,21 @@ impl<F: FileSystem> Journal<F> {
                 break;
             }
 
-            // Verify hash
-            let actual_hash = blake3::hash(&payload);
-            if actual_hash.as_bytes() != hash.as_bytes() {
+            // Verify checksum
+            if self.checksum(type_buf[0], &payload) != hash {
                 // Stop and truncate at corruption as per Section 4.1 "Recovery"
                 self.handle.set_len(offset)?;
                 break;
             }
 
+            let record_type = match JournalRecordType::try_from(type_buf[0]) {
+                Ok(t) => t,
+                Err(_) => {
+                    self.handle.set_len(offset)?;
+                    break;
+                }
+            };
+
             records.push(JournalRecord {
                 hash,
                 record_type,
@@ -232,8 +317,11 @@ impl<F: FileSystem> Journal<F> {
         self.handle.set_len(16)?;
         self.handle.seek(SeekFrom::Start(0))?;
         self.handle.write_all(&generation_id.to_le_bytes())?;
-        self.handle.write_all(&[0u8; 8])?; // reserved
+        self.handle
+            .write_all(&[JOURNAL_VERSION_CHECKED, 0, 0, 0, 0, 0, 0, 0])?; // version + reserved
         self.generation_id = generation_id;
+        self.version = JOURNAL_VERSION_CHECKED;
+        self.has_footer = false;
         Ok(())
     }
 }
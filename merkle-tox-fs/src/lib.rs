@@ -1,10 +1,18 @@
 pub mod blob;
+pub mod compaction;
+pub mod crypt;
+pub mod fsck;
+pub mod gc;
 pub mod journal;
 pub mod opaque;
 pub mod pack;
 pub mod state;
 
 use crate::blob::BlobStore;
+pub use crate::compaction::{CompactionConfig, CompactionWorker};
+use crate::crypt::{EncryptedFileSystem, StorageKey};
+pub use crate::fsck::{FsckIssue, FsckMode, FsckReport, fsck};
+pub use crate::gc::{GcPolicy, GcStats};
 use crate::journal::{Journal, JournalRecordType};
 use crate::opaque::OpaqueStore;
 use crate::pack::Pack;
@@ -15,23 +23,53 @@ use merkle_tox_core::dag::{
     ChainKey, ConversationId, KConv, MerkleNode, NodeHash, NodeLookup, NodeType, PhysicalDevicePk,
     WireNode,
 };
+use merkle_tox_core::engine::archive::ArchivedConversation;
+use merkle_tox_core::engine::outbox::AckedHeads;
+use merkle_tox_core::engine::quarantine::BlacklistEntry;
+use merkle_tox_core::engine::scheduled::ScheduledMessage;
 use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use merkle_tox_core::invite::{InviteGrant, InviteId};
+use merkle_tox_core::suspend::SuspendedState;
 use merkle_tox_core::sync::{
-    BlobStore as BlobStoreTrait, GlobalStore, NodeStore, ReconciliationStore, SyncRange,
+    BlobStore as BlobStoreTrait, GlobalStore, NodeStore, ReconciliationStore, StateSnapshot,
+    StoreWrite, SyncRange,
 };
 use merkle_tox_core::vfs::{FileHandle, FileSystem, StdFileSystem};
 use parking_lot::{Mutex, RwLock};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Error};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{Arc, mpsc};
+
+/// Record type and payload of a journal record to append.
+type JournalEntry = (JournalRecordType, Vec<u8>);
 
-#[derive(Clone)]
 pub struct FsStore<F: FileSystem = StdFileSystem> {
     root: PathBuf,
     fs: Arc<F>,
     inner: Arc<RwLock<FsInner<F>>>,
     blob_store: Arc<BlobStore<F>>,
+    compaction: CompactionConfig,
+    compaction_tx: Arc<Mutex<Option<mpsc::Sender<ConversationId>>>>,
+    /// Held by [`FsStore::compact_step`] from planning to commit and by
+    /// [`FsStore::gc`], so GC never takes the packs of a compaction in
+    /// flight for orphans.
+    compaction_lock: Arc<Mutex<()>>,
+}
+
+// Manual impl: deriving would require `F: Clone`.
+impl<F: FileSystem> Clone for FsStore<F> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            fs: self.fs.clone(),
+            inner: self.inner.clone(),
+            blob_store: self.blob_store.clone(),
+            compaction: self.compaction.clone(),
+            compaction_tx: self.compaction_tx.clone(),
+            compaction_lock: self.compaction_lock.clone(),
+        }
+    }
 }
 
 const COMPACT_THRESHOLD: usize = 500;
@@ -40,6 +78,10 @@ struct FsInner<F: FileSystem> {
     conversations: HashMap<ConversationId, ConversationContext<F>>,
     node_to_conv: HashMap<NodeHash, ConversationId>,
     global_offset: Option<i64>,
+    scheduled: HashMap<u64, ScheduledMessage>,
+    acked_heads: HashMap<(ConversationId, PhysicalDevicePk), AckedHeads>,
+    blacklist: HashMap<PhysicalDevicePk, BlacklistEntry>,
+    invite_grants: HashMap<InviteId, InviteGrant>,
     _lock_file: Box<dyn FileHandle>,
 }
 
@@ -59,6 +101,9 @@ struct ConversationContext<F: FileSystem> {
     latest_ratchets: HashMap<PhysicalDevicePk, (ChainKey, u64, NodeHash, u64)>, // (key, seq, hash, epoch_id)
     last_seq_numbers: HashMap<PhysicalDevicePk, u64>,
     child_index: HashMap<NodeHash, Vec<NodeHash>>,
+    /// Nodes whose payload was pruned but may still be stored in the journal
+    /// or a pack. Compaction and GC turn them into tombstones.
+    pruned: HashSet<NodeHash>,
 }
 
 struct JournalNodeInfo {
@@ -71,8 +116,29 @@ struct JournalNodeInfo {
     offset: u64,
 }
 
+impl<F: FileSystem> FsStore<EncryptedFileSystem<F>> {
+    /// Opens a store whose journal, packs, ratchet state and blobs are
+    /// encrypted at rest with `key`.
+    ///
+    /// Fails if the store under `root` was created with a different key.
+    pub fn new_encrypted(root: PathBuf, fs: Arc<F>, key: StorageKey) -> MerkleToxResult<Self> {
+        fs.create_dir_all(&root)?;
+        let fs = Arc::new(EncryptedFileSystem::new(fs, &key));
+        fs.verify_key(&root)?;
+        Self::new(root, fs)
+    }
+}
+
 impl<F: FileSystem> FsStore<F> {
     pub fn new(root: PathBuf, fs: Arc<F>) -> MerkleToxResult<Self> {
+        Self::with_compaction(root, fs, CompactionConfig::default())
+    }
+
+    pub fn with_compaction(
+        root: PathBuf,
+        fs: Arc<F>,
+        compaction: CompactionConfig,
+    ) -> MerkleToxResult<Self> {
         if !fs.exists(&root) {
             fs.create_dir_all(&root)?;
         } else if !fs.metadata(&root)?.is_dir {
@@ -105,9 +171,16 @@ impl<F: FileSystem> FsStore<F> {
                 conversations: HashMap::new(),
                 node_to_conv: HashMap::new(),
                 global_offset: None,
+                scheduled: HashMap::new(),
+                acked_heads: HashMap::new(),
+                blacklist: HashMap::new(),
+                invite_grants: HashMap::new(),
                 _lock_file: lock_file,
             })),
             blob_store,
+            compaction,
+            compaction_tx: Arc::new(Mutex::new(None)),
+            compaction_lock: Arc::new(Mutex::new(())),
         };
 
         store.load_global_state()?;
@@ -140,6 +213,91 @@ impl<F: FileSystem> FsStore<F> {
                 self.inner.write().global_offset = Some(offset);
             }
         }
+        let path = self.root.join("scheduled.bin");
+        if self.fs.exists(&path) {
+            let data = self.fs.read(&path)?;
+            let msgs: Vec<ScheduledMessage> =
+                tox_proto::deserialize(&data).map_err(|e| Error::other(e.to_string()))?;
+            self.inner.write().scheduled = msgs.into_iter().map(|m| (m.id, m)).collect();
+        }
+        let path = self.root.join("outbox.bin");
+        if self.fs.exists(&path) {
+            let data = self.fs.read(&path)?;
+            let acked: Vec<AckedHeads> =
+                tox_proto::deserialize(&data).map_err(|e| Error::other(e.to_string()))?;
+            self.inner.write().acked_heads = acked
+                .into_iter()
+                .map(|a| ((a.conversation_id, a.device_pk), a))
+                .collect();
+        }
+        let path = self.root.join("blacklist.bin");
+        // Created empty with the storage root.
+        let data = self.fs.read(&path).unwrap_or_default();
+        if !data.is_empty() {
+            let entries: Vec<BlacklistEntry> =
+                tox_proto::deserialize(&data).map_err(|e| Error::other(e.to_string()))?;
+            self.inner.write().blacklist = entries.into_iter().map(|e| (e.device_pk, e)).collect();
+        }
+        let path = self.root.join("invites.bin");
+        if self.fs.exists(&path) {
+            let data = self.fs.read(&path)?;
+            let grants: Vec<InviteGrant> =
+                tox_proto::deserialize(&data).map_err(|e| Error::other(e.to_string()))?;
+            self.inner.write().invite_grants = grants.into_iter().map(|g| (g.id(), g)).collect();
+        }
+        Ok(())
+    }
+
+    fn save_scheduled(&self, scheduled: &HashMap<u64, ScheduledMessage>) -> MerkleToxResult<()> {
+        let mut msgs: Vec<ScheduledMessage> = scheduled.values().cloned().collect();
+        msgs.sort_by_key(|m| m.id);
+        let data = tox_proto::serialize(&msgs)?;
+        let path = self.root.join("scheduled.bin");
+        let tmp_path = self.root.join("scheduled.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn save_acked_heads(
+        &self,
+        acked_heads: &HashMap<(ConversationId, PhysicalDevicePk), AckedHeads>,
+    ) -> MerkleToxResult<()> {
+        let mut acked: Vec<AckedHeads> = acked_heads.values().cloned().collect();
+        acked.sort_by_key(|a| (a.conversation_id, a.device_pk));
+        let data = tox_proto::serialize(&acked)?;
+        let path = self.root.join("outbox.bin");
+        let tmp_path = self.root.join("outbox.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn save_blacklist(
+        &self,
+        blacklist: &HashMap<PhysicalDevicePk, BlacklistEntry>,
+    ) -> MerkleToxResult<()> {
+        let mut entries: Vec<BlacklistEntry> = blacklist.values().cloned().collect();
+        entries.sort_by_key(|e| e.device_pk);
+        let data = tox_proto::serialize(&entries)?;
+        let path = self.root.join("blacklist.bin");
+        let tmp_path = self.root.join("blacklist.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn save_invite_grants(
+        &self,
+        invite_grants: &HashMap<InviteId, InviteGrant>,
+    ) -> MerkleToxResult<()> {
+        let mut grants: Vec<InviteGrant> = invite_grants.values().cloned().collect();
+        grants.sort_by_key(|g| g.id());
+        let data = tox_proto::serialize(&grants)?;
+        let path = self.root.join("invites.bin");
+        let tmp_path = self.root.join("invites.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
         Ok(())
     }
 
@@ -165,143 +323,24 @@ impl<F: FileSystem> FsStore<F> {
 
     fn compact_internal(&self, inner: &mut FsInner<F>, id: &ConversationId) -> MerkleToxResult<()> {
         let ctx = inner.conversations.get_mut(id).unwrap();
-
-        // 1. Shadow Write
-        let records = ctx.journal.lock().read_all()?;
-        let mut index_records = Vec::new();
-        let mut nodes_to_pack = Vec::new();
-        let mut ratchet_updates = HashMap::new();
-
-        for rec in &records {
-            if rec.record_type == JournalRecordType::Node {
-                let (status, node): (u8, MerkleNode) = tox_proto::deserialize(&rec.payload)?;
-                let node_hash = node.hash();
-                nodes_to_pack.push((node_hash, node.clone()));
-
-                // Track latest sequence number even if no ratchet advance
-                let entry = ratchet_updates.entry(node.sender_pk).or_insert((
-                    ChainKey::from([0u8; 32]),
-                    0u64,
-                    0u64,
-                ));
-                if node.sequence_number > entry.1 {
-                    entry.1 = node.sequence_number;
-                }
-
-                index_records.push(pack::IndexRecord {
-                    hash: node_hash,
-                    offset: 0, // will be set during pack creation
-                    rank: node.topological_rank,
-                    payload_length: 0, // will be set during pack creation
-                    node_type: if node.node_type() == NodeType::Admin {
-                        0x01
-                    } else {
-                        0x02
-                    },
-                    status,
-                    admin_distance: ctx
-                        .volatile_nodes
-                        .get(&node_hash)
-                        .map(|i| i.admin_distance)
-                        .unwrap_or(0),
-                });
-            } else if rec.record_type == JournalRecordType::RatchetAdvance {
-                let (hash, key, epoch): (NodeHash, ChainKey, u64) =
-                    tox_proto::deserialize(&rec.payload)?;
-                if let Some(info) = ctx.volatile_nodes.get(&hash) {
-                    let entry = ratchet_updates.entry(info.sender_pk).or_insert((
-                        ChainKey::from([0u8; 32]),
-                        0u64,
-                        0u64,
-                    ));
-                    entry.0 = key;
-                    entry.1 = info.sequence_number;
-                    entry.2 = epoch;
-                }
-            }
-        }
-
-        if nodes_to_pack.is_empty() {
-            return Ok(());
+        if let Some(mut job) = self.plan_compaction(ctx)? {
+            let pack_ids = self.write_packs(&ctx.path.join("packs"), &mut job)?;
+            self.commit_compaction(ctx, job, &pack_ids)?;
         }
+        Ok(())
+    }
 
-        let pack_id = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as u64;
-        let data_path = ctx
-            .path
-            .join("packs")
-            .join(format!("{:016x}.pack", pack_id));
-        let index_path = ctx.path.join("packs").join(format!("{:016x}.idx", pack_id));
-
-        // Actually create the data.pack
-        let mut data_file = self.fs.open(&data_path, true, true, true)?;
-        for (i, (hash, node)) in nodes_to_pack.iter().enumerate() {
-            let offset = data_file.stream_position()?;
-            index_records[i].offset = offset;
-
-            let status = index_records[i].status;
-            let payload = tox_proto::serialize(&(status, node))?;
-            index_records[i].payload_length = payload.len() as u32;
-
-            let record_type = 0x01u8; // Node
-            data_file.write_all(&(payload.len() as u32).to_le_bytes())?;
-            data_file.write_all(hash.as_bytes())?;
-            data_file.write_all(&[record_type])?;
-            data_file.write_all(&payload)?;
-        }
-
-        let pack_index = pack::PackIndex::build(index_records, pack::DEFAULT_FANOUT_BITS, 2);
-        pack_index.save(&*self.fs, &index_path)?;
-
-        // 2. Commit
-        ctx.state.active_packs.push(pack_id);
-
-        let next_gen_id = pack_id; // use same timestamp for generation id
-        ctx.journal.lock().truncate(next_gen_id)?;
-        ctx.state.active_journal_id = next_gen_id;
-
-        // Update ratchet.bin and in-memory caches
-        let mut current_ratchets = ctx.ratchet.lock().load()?;
-        for (pk, (key, seq, epoch)) in ratchet_updates {
-            let entry = ctx.last_seq_numbers.entry(pk).or_insert(0);
-            if seq > *entry {
-                *entry = seq;
-            }
-            if key != ChainKey::from([0u8; 32]) {
-                ctx.latest_ratchets
-                    .insert(pk, (key.clone(), seq, NodeHash::from([0u8; 32]), epoch));
-            }
-
-            if let Some(slot) = current_ratchets.iter_mut().find(|s| s.device_pk == pk) {
-                if seq > slot.last_sequence_number {
-                    if key != ChainKey::from([0u8; 32]) {
-                        slot.chain_key = key;
-                        slot.epoch_id = epoch;
-                    }
-                    slot.last_sequence_number = seq;
-                }
-            } else {
-                current_ratchets.push(state::RatchetSlot {
-                    device_pk: pk,
-                    chain_key: key,
-                    last_sequence_number: seq,
-                    epoch_id: epoch,
-                });
+    /// Compacts the journal of a conversation once it holds enough nodes, or
+    /// hands it to the compaction worker in background mode.
+    fn maybe_compact(&self, inner: &mut FsInner<F>, id: &ConversationId) -> MerkleToxResult<()> {
+        let num_volatile = inner.conversations.get(id).unwrap().volatile_nodes.len();
+        if num_volatile >= self.compaction.threshold {
+            if !self.compaction.background {
+                self.compact_internal(inner, id)?;
+            } else if let Some(tx) = &*self.compaction_tx.lock() {
+                let _ = tx.send(*id);
             }
         }
-        ctx.ratchet.lock().save(&current_ratchets)?;
-
-        let state_file = StateFile::new(self.fs.clone(), ctx.path.join("state.bin"));
-        state_file.save(&ctx.state)?;
-
-        // Update ctx
-        ctx.packs
-            .push(Pack::open(self.fs.clone(), data_path, &index_path)?);
-        ctx.volatile_nodes.clear();
-        ctx.hot_ratchets.clear();
-
         Ok(())
     }
 
@@ -333,10 +372,12 @@ impl<F: FileSystem> FsStore<F> {
                 message_count: 0,
                 last_rotation_time: -1,
                 active_packs: Vec::new(),
+                history_horizon: 0,
                 active_journal_id: 0,
             }
         };
 
+        crate::compaction::recover_next_journal(&*self.fs, &conv_dir, state.active_journal_id)?;
         let mut journal = Journal::open(self.fs.clone(), conv_dir.join("journal.bin"))?;
 
         // SPEC: Section 4.1 - Startup: If IDs mismatch, truncate the journal immediately.
@@ -378,6 +419,7 @@ impl<F: FileSystem> FsStore<F> {
             latest_ratchets: HashMap::new(),
             last_seq_numbers: HashMap::new(),
             child_index: HashMap::new(),
+            pruned: HashSet::new(),
         };
 
         // Load ratchet checkpoints
@@ -421,6 +463,81 @@ impl<F: FileSystem> Drop for ConversationContext<F> {
 }
 
 impl<F: FileSystem> ConversationContext<F> {
+    /// Indexes a node appended to the journal at `offset`.
+    fn index_node(&mut self, node: &MerkleNode, verified: bool, offset: u64) {
+        let hash = node.hash();
+        // Parents appended earlier are already indexed.
+        let admin_distance = self.admin_distance(node);
+        self.volatile_nodes.insert(
+            hash,
+            JournalNodeInfo {
+                node_type: node.node_type(),
+                rank: node.topological_rank,
+                admin_distance,
+                sender_pk: node.sender_pk,
+                sequence_number: node.sequence_number,
+                verified,
+                offset,
+            },
+        );
+        let entry = self.last_seq_numbers.entry(node.sender_pk).or_insert(0);
+        if node.sequence_number > *entry {
+            *entry = node.sequence_number;
+        }
+        for parent in &node.parents {
+            self.child_index.entry(*parent).or_default().push(hash);
+        }
+    }
+
+    /// Caches a ratchet key appended to the journal.
+    fn index_ratchet_key(&mut self, node_hash: &NodeHash, chain_key: ChainKey, epoch_id: u64) {
+        self.hot_ratchets
+            .insert(*node_hash, (chain_key.clone(), epoch_id));
+        if let Some(info) = self.volatile_nodes.get(node_hash) {
+            self.latest_ratchets.insert(
+                info.sender_pk,
+                (chain_key, info.sequence_number, *node_hash, epoch_id),
+            );
+        }
+    }
+
+    /// Appends `records` to the journal with a single write, holding the
+    /// conversation's exclusive lock. Returns the offset of each record.
+    fn append_records(&mut self, records: &[JournalEntry]) -> MerkleToxResult<Vec<u64>> {
+        self.lock_file.try_lock_exclusive().map_err(|_| {
+            MerkleToxError::Io(Error::other("Failed to acquire exclusive lock for write"))
+        })?;
+        let appended = self.journal.lock().append_batch(records);
+        self.lock_file.try_lock_shared().ok(); // downgrade back
+        Ok(appended?.into_iter().map(|(_, offset)| offset).collect())
+    }
+
+    /// Distance of `node` to the nearest Admin node, from its indexed parents.
+    fn admin_distance(&self, node: &MerkleNode) -> u16 {
+        if node.node_type() != NodeType::Content {
+            return 0;
+        }
+        let min_dist = node
+            .parents
+            .iter()
+            .filter_map(|parent| {
+                self.volatile_nodes
+                    .get(parent)
+                    .map(|info| info.admin_distance as u64)
+                    .or_else(|| {
+                        self.packs
+                            .iter()
+                            .find_map(|pack| pack.index.lookup(parent))
+                            .map(|record| record.admin_distance as u64)
+                    })
+            })
+            .min();
+        match min_dist {
+            Some(d) => (d + 1).min(u16::MAX as u64) as u16,
+            None => u16::MAX,
+        }
+    }
+
     fn replay_journal(
         &mut self,
         node_to_conv: &mut HashMap<NodeHash, ConversationId>,
@@ -479,7 +596,10 @@ impl<F: FileSystem> ConversationContext<F> {
                         *entry = node.sequence_number;
                     }
                     for parent in &node.parents {
-                        self.child_index.entry(*parent).or_default().push(node_hash);
+                        let children = self.child_index.entry(*parent).or_default();
+                        if !children.contains(&node_hash) {
+                            children.push(node_hash);
+                        }
                     }
                     node_to_conv.insert(node_hash, self.id);
                 }
@@ -504,6 +624,11 @@ impl<F: FileSystem> ConversationContext<F> {
                         );
                     }
                 }
+                JournalRecordType::Prune => {
+                    let node_hash: NodeHash = tox_proto::deserialize(&rec.payload)
+                        .map_err(|e| io::Error::other(e.to_string()))?;
+                    self.pruned.insert(node_hash);
+                }
                 _ => {}
             }
         }
@@ -668,39 +793,14 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
 
         for pack in &ctx.packs {
             if let Some(record) = pack.index.lookup(hash) {
-                return record.status == 0x01;
+                return record.status != pack::STATUS_SPECULATIVE;
             }
         }
         false
     }
 
     fn get_node(&self, hash: &NodeHash) -> Option<MerkleNode> {
-        let inner = self.inner.read();
-        let conv_id = inner.node_to_conv.get(hash)?;
-        let ctx = inner.conversations.get(conv_id)?;
-
-        // Check journal
-        if let Some(info) = ctx.volatile_nodes.get(hash) {
-            let record = ctx.journal.lock().read_record_at(info.offset).ok()?;
-            let decoded: (u8, MerkleNode) = tox_proto::deserialize(&record.payload).ok()?;
-            if decoded.1.hash() != *hash {
-                return None;
-            }
-            return Some(decoded.1);
-        }
-
-        // Check packs
-        for pack in &ctx.packs {
-            if let Ok(Some(data)) = pack.get_node_data(hash) {
-                let data: Vec<u8> = data;
-                let decoded: (u8, MerkleNode) = tox_proto::deserialize(&data).ok()?;
-                if decoded.1.hash() != *hash {
-                    continue;
-                }
-                return Some(decoded.1);
-            }
-        }
-        None
+        Self::read_node(&self.inner.read(), hash)
     }
 
     fn get_wire_node(&self, hash: &NodeHash) -> Option<WireNode> {
@@ -721,86 +821,133 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
         node: MerkleNode,
         verified: bool,
     ) -> MerkleToxResult<()> {
+        self.put_nodes(conversation_id, vec![(node, verified)])
+    }
+
+    fn put_nodes(
+        &self,
+        conversation_id: &ConversationId,
+        nodes: Vec<(MerkleNode, bool)>,
+    ) -> MerkleToxResult<()> {
+        if nodes.is_empty() {
+            return Ok(());
+        }
         self.ensure_conversation(conversation_id)?;
+        for (node, _) in &nodes {
+            if let Some(hash) = node.content.blob_hash() {
+                self.blob_store.add_ref(hash, conversation_id)?;
+            }
+        }
+        let records = nodes
+            .iter()
+            .map(|(node, verified)| node_record(node, *verified))
+            .collect::<Result<Vec<_>, _>>()?;
+
         let mut inner = self.inner.write();
+        let ctx = inner.conversations.get_mut(conversation_id).unwrap();
+        let offsets = ctx.append_records(&records)?;
+        for ((node, verified), offset) in nodes.iter().zip(offsets) {
+            ctx.index_node(node, *verified, offset);
+        }
+        for (node, _) in &nodes {
+            inner.node_to_conv.insert(node.hash(), *conversation_id);
+        }
+        self.maybe_compact(&mut inner, conversation_id)
+    }
 
-        let hash = node.hash();
-        let status = if verified { 0x01u8 } else { 0x02u8 };
-        let payload = tox_proto::serialize(&(status, node.clone()))?;
-
-        let mut admin_distance = 0u16;
-        if node.node_type() == NodeType::Content {
-            let mut min_dist = u64::MAX;
-            let ctx = inner.conversations.get(conversation_id).unwrap();
-            for parent in &node.parents {
-                let dist = if let Some(info) = ctx.volatile_nodes.get(parent) {
-                    Some(info.admin_distance as u64)
-                } else {
-                    let mut found = None;
-                    for pack in &ctx.packs {
-                        if let Some(record) = pack.index.lookup(parent) {
-                            found = Some(record.admin_distance as u64);
-                            break;
-                        }
+    /// Appends the nodes and ratchet keys of each conversation to its journal
+    /// with a single write, under one lock of the store.
+    fn write_batch(&self, writes: Vec<StoreWrite>) -> MerkleToxResult<()> {
+        let mut records: Vec<(ConversationId, Vec<JournalEntry>)> = Vec::new();
+        for write in &writes {
+            let (cid, record) = match write {
+                StoreWrite::Node(cid, node, verified) => {
+                    self.ensure_conversation(cid)?;
+                    if let Some(hash) = node.content.blob_hash() {
+                        self.blob_store.add_ref(hash, cid)?;
                     }
-                    found
-                };
-                if let Some(d) = dist {
-                    min_dist = min_dist.min(d);
+                    (cid, node_record(node, *verified)?)
                 }
-            }
-            if min_dist != u64::MAX {
-                admin_distance = (min_dist + 1).min(u16::MAX as u64) as u16;
-            } else {
-                admin_distance = u16::MAX;
+                StoreWrite::RatchetKey(cid, hash, key, epoch_id) => {
+                    self.ensure_conversation(cid)?;
+                    (cid, ratchet_record(hash, key, *epoch_id)?)
+                }
+                StoreWrite::WireNode(cid, ..) | StoreWrite::Heads(cid, ..) => {
+                    self.ensure_conversation(cid)?;
+                    continue;
+                }
+                StoreWrite::DeleteRatchetKey(..) => continue,
+            };
+            match records.iter_mut().find(|(id, _)| id == cid) {
+                Some((_, conv_records)) => conv_records.push(record),
+                None => records.push((*cid, vec![record])),
             }
         }
 
-        {
-            let ctx = inner.conversations.get_mut(conversation_id).unwrap();
-            ctx.lock_file.try_lock_exclusive().map_err(|_| {
-                MerkleToxError::Io(Error::other("Failed to acquire exclusive lock for write"))
-            })?;
-            let (_, offset) = ctx
-                .journal
-                .lock()
-                .append(JournalRecordType::Node, &payload)?;
-            ctx.volatile_nodes.insert(
-                hash,
-                JournalNodeInfo {
-                    node_type: node.node_type(),
-                    rank: node.topological_rank,
-                    admin_distance,
-                    sender_pk: node.sender_pk,
-                    sequence_number: node.sequence_number,
-                    verified,
-                    offset,
-                },
-            );
-            let entry = ctx.last_seq_numbers.entry(node.sender_pk).or_insert(0);
-            if node.sequence_number > *entry {
-                *entry = node.sequence_number;
-            }
-            for parent in &node.parents {
-                ctx.child_index.entry(*parent).or_default().push(hash);
-            }
-            ctx.lock_file.try_lock_shared().ok(); // downgrade back
+        let mut inner = self.inner.write();
+        let mut offsets = HashMap::new();
+        for (cid, conv_records) in &records {
+            let ctx = inner.conversations.get_mut(cid).unwrap();
+            let conv_offsets = ctx.append_records(conv_records)?;
+            offsets.insert(*cid, conv_offsets.into_iter());
         }
-        inner.node_to_conv.insert(hash, *conversation_id);
 
-        let num_volatile = inner
-            .conversations
-            .get(conversation_id)
-            .unwrap()
-            .volatile_nodes
-            .len();
-        if num_volatile >= COMPACT_THRESHOLD {
-            self.compact_internal(&mut inner, conversation_id)?;
+        let mut next_offset = |cid: &ConversationId| {
+            offsets
+                .get_mut(cid)
+                .and_then(|o| o.next())
+                .expect("one offset per journaled record")
+        };
+        for write in writes {
+            match write {
+                StoreWrite::Node(cid, node, verified) => {
+                    let offset = next_offset(&cid);
+                    let ctx = inner.conversations.get_mut(&cid).unwrap();
+                    ctx.index_node(&node, verified, offset);
+                    inner.node_to_conv.insert(node.hash(), cid);
+                }
+                StoreWrite::RatchetKey(cid, hash, key, epoch_id) => {
+                    next_offset(&cid);
+                    let ctx = inner.conversations.get_mut(&cid).unwrap();
+                    ctx.index_ratchet_key(&hash, key, epoch_id);
+                }
+                StoreWrite::DeleteRatchetKey(cid, hash) => {
+                    if let Some(ctx) = inner.conversations.get_mut(&cid) {
+                        ctx.hot_ratchets.remove(&hash);
+                    }
+                }
+                StoreWrite::WireNode(cid, hash, node) => {
+                    let ctx = inner.conversations.get(&cid).unwrap();
+                    ctx.opaque.put_node(&hash, &tox_proto::serialize(&node)?)?;
+                    inner.node_to_conv.insert(hash, cid);
+                }
+                StoreWrite::Heads(cid, heads, is_admin) => {
+                    let ctx = inner.conversations.get_mut(&cid).unwrap();
+                    if is_admin {
+                        ctx.state.admin_heads = heads;
+                    } else {
+                        ctx.state.heads = heads;
+                    }
+                    let state_file = StateFile::new(self.fs.clone(), ctx.path.join("state.bin"));
+                    state_file.save(&ctx.state)?;
+                }
+            }
         }
 
+        for (cid, _) in &records {
+            self.maybe_compact(&mut inner, cid)?;
+        }
         Ok(())
     }
 
+    fn get_nodes(&self, hashes: &[NodeHash]) -> Vec<Option<MerkleNode>> {
+        let inner = self.inner.read();
+        hashes
+            .iter()
+            .map(|hash| Self::read_node(&inner, hash))
+            .collect()
+    }
+
     fn put_wire_node(
         &self,
         conversation_id: &ConversationId,
@@ -829,6 +976,27 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
         Ok(())
     }
 
+    fn prune_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<bool> {
+        if self.get_node(hash).is_none() {
+            return Ok(false);
+        }
+        let mut inner = self.inner.write();
+        if inner.node_to_conv.get(hash) != Some(conversation_id) {
+            return Ok(false);
+        }
+        let ctx = inner.conversations.get_mut(conversation_id).unwrap();
+        ctx.journal
+            .lock()
+            .append(JournalRecordType::Prune, &tox_proto::serialize(hash)?)?;
+        ctx.pruned.insert(*hash);
+        ctx.opaque.remove_node(hash)?;
+        Ok(true)
+    }
+
     fn get_speculative_nodes(&self, conversation_id: &ConversationId) -> Vec<MerkleNode> {
         let _ = self.ensure_conversation(conversation_id);
         let inner = self.inner.read();
@@ -908,10 +1076,10 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
         }
         for pack in &ctx.packs {
             for record in &pack.index.records {
-                if record.status == 0x01 {
-                    ver += 1;
-                } else {
+                if record.status == pack::STATUS_SPECULATIVE {
                     spec += 1;
+                } else {
+                    ver += 1;
                 }
             }
         }
@@ -995,7 +1163,7 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
         }
         for pack in &ctx.packs {
             for record in &pack.index.records {
-                if record.status == 0x01
+                if record.status != pack::STATUS_SPECULATIVE
                     && record.rank >= range.min_rank
                     && record.rank <= range.max_rank
                 {
@@ -1108,18 +1276,9 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
         self.ensure_conversation(conversation_id)?;
         let mut inner = self.inner.write();
         let ctx = inner.conversations.get_mut(conversation_id).unwrap();
-        ctx.journal.lock().append(
-            JournalRecordType::RatchetAdvance,
-            &tox_proto::serialize(&(node_hash, chain_key.clone(), epoch_id))?,
-        )?;
-        ctx.hot_ratchets
-            .insert(*node_hash, (chain_key.clone(), epoch_id));
-        if let Some(info) = ctx.volatile_nodes.get(node_hash) {
-            ctx.latest_ratchets.insert(
-                info.sender_pk,
-                (chain_key, info.sequence_number, *node_hash, epoch_id),
-            );
-        }
+        let (record_type, payload) = ratchet_record(node_hash, &chain_key, epoch_id)?;
+        ctx.journal.lock().append(record_type, &payload)?;
+        ctx.index_ratchet_key(node_hash, chain_key, epoch_id);
         Ok(())
     }
 
@@ -1155,13 +1314,260 @@ impl<F: FileSystem> NodeStore for FsStore<F> {
         }
         Ok(())
     }
+
+    fn put_scheduled_message(&self, msg: &ScheduledMessage) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        inner.scheduled.insert(msg.id, msg.clone());
+        self.save_scheduled(&inner.scheduled)
+    }
+
+    fn remove_scheduled_message(&self, id: u64) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        if inner.scheduled.remove(&id).is_some() {
+            self.save_scheduled(&inner.scheduled)?;
+        }
+        Ok(())
+    }
+
+    fn get_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        self.inner.read().scheduled.values().cloned().collect()
+    }
+
+    fn put_acked_heads(&self, acked: &AckedHeads) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        inner
+            .acked_heads
+            .insert((acked.conversation_id, acked.device_pk), acked.clone());
+        self.save_acked_heads(&inner.acked_heads)
+    }
+
+    fn get_acked_heads(&self) -> Vec<AckedHeads> {
+        self.inner.read().acked_heads.values().cloned().collect()
+    }
+
+    fn put_blacklist_entry(&self, entry: &BlacklistEntry) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        inner.blacklist.insert(entry.device_pk, entry.clone());
+        self.save_blacklist(&inner.blacklist)
+    }
+
+    fn remove_blacklist_entry(&self, device_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        if inner.blacklist.remove(device_pk).is_none() {
+            return Ok(());
+        }
+        self.save_blacklist(&inner.blacklist)
+    }
+
+    fn get_blacklist(&self) -> Vec<BlacklistEntry> {
+        self.inner.read().blacklist.values().cloned().collect()
+    }
+
+    fn put_invite_grant(&self, grant: &InviteGrant) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        inner.invite_grants.insert(grant.id(), grant.clone());
+        self.save_invite_grants(&inner.invite_grants)
+    }
+
+    fn remove_invite_grant(&self, id: &InviteId) -> MerkleToxResult<()> {
+        let mut inner = self.inner.write();
+        if inner.invite_grants.remove(id).is_none() {
+            return Ok(());
+        }
+        self.save_invite_grants(&inner.invite_grants)
+    }
+
+    fn get_invite_grants(&self) -> Vec<InviteGrant> {
+        self.inner.read().invite_grants.values().cloned().collect()
+    }
+
+    fn put_suspended_state(&self, state: &SuspendedState) -> MerkleToxResult<()> {
+        let data = tox_proto::serialize(state)?;
+        let path = self.root.join("suspended.bin");
+        let tmp_path = self.root.join("suspended.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn get_suspended_state(&self) -> Option<SuspendedState> {
+        let data = self.fs.read(&self.root.join("suspended.bin")).ok()?;
+        tox_proto::deserialize(&data).ok()
+    }
+
+    fn remove_suspended_state(&self) -> MerkleToxResult<()> {
+        let path = self.root.join("suspended.bin");
+        if self.fs.exists(&path) {
+            self.fs.remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    fn set_history_horizon(
+        &self,
+        conversation_id: &ConversationId,
+        min_rank: Option<u64>,
+    ) -> MerkleToxResult<()> {
+        self.ensure_conversation(conversation_id)?;
+        let mut inner = self.inner.write();
+        let ctx = inner.conversations.get_mut(conversation_id).unwrap();
+        ctx.state.history_horizon = min_rank.unwrap_or(0);
+        let state_file = StateFile::new(self.fs.clone(), ctx.path.join("state.bin"));
+        state_file.save(&ctx.state)?;
+        Ok(())
+    }
+
+    fn get_history_horizon(&self, conversation_id: &ConversationId) -> Option<u64> {
+        self.ensure_conversation(conversation_id).ok()?;
+        let inner = self.inner.read();
+        let ctx = inner.conversations.get(conversation_id)?;
+        (ctx.state.history_horizon > 0).then_some(ctx.state.history_horizon)
+    }
+
+    fn put_archived_conversation(&self, archive: &ArchivedConversation) -> MerkleToxResult<()> {
+        self.ensure_conversation(&archive.conversation_id)?;
+        let inner = self.inner.read();
+        let ctx = inner.conversations.get(&archive.conversation_id).unwrap();
+        let data = tox_proto::serialize(archive)?;
+        let path = ctx.path.join("archive.bin");
+        let tmp_path = ctx.path.join("archive.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn get_archived_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<ArchivedConversation> {
+        self.ensure_conversation(conversation_id).ok()?;
+        let inner = self.inner.read();
+        let ctx = inner.conversations.get(conversation_id)?;
+        let data = self.fs.read(&ctx.path.join("archive.bin")).ok()?;
+        tox_proto::deserialize(&data).ok()
+    }
+
+    fn put_state_snapshot(&self, snapshot: &StateSnapshot) -> MerkleToxResult<()> {
+        self.ensure_conversation(&snapshot.conversation_id)?;
+        let inner = self.inner.read();
+        let ctx = inner.conversations.get(&snapshot.conversation_id).unwrap();
+        let data = tox_proto::serialize(snapshot)?;
+        let path = ctx.path.join("snapshot.bin");
+        let tmp_path = ctx.path.join("snapshot.bin.tmp");
+        self.fs.write(&tmp_path, &data)?;
+        self.fs.rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn get_state_snapshot(&self, conversation_id: &ConversationId) -> Option<StateSnapshot> {
+        self.ensure_conversation(conversation_id).ok()?;
+        let inner = self.inner.read();
+        let ctx = inner.conversations.get(conversation_id)?;
+        let data = self.fs.read(&ctx.path.join("snapshot.bin")).ok()?;
+        tox_proto::deserialize(&data).ok()
+    }
+
+    /// Seals every open journal with its footer, as dropping the store
+    /// would. The next write reopens the journal.
+    fn flush(&self) -> MerkleToxResult<()> {
+        let inner = self.inner.read();
+        for ctx in inner.conversations.values() {
+            ctx.journal.lock().flush()?;
+        }
+        Ok(())
+    }
 }
 
 impl<F: FileSystem> FsStore<F> {
+    fn read_node(inner: &FsInner<F>, hash: &NodeHash) -> Option<MerkleNode> {
+        let conv_id = inner.node_to_conv.get(hash)?;
+        let ctx = inner.conversations.get(conv_id)?;
+        if ctx.pruned.contains(hash) {
+            return None;
+        }
+
+        // Check journal
+        if let Some(info) = ctx.volatile_nodes.get(hash) {
+            let record = ctx.journal.lock().read_record_at(info.offset).ok()?;
+            let decoded: (u8, MerkleNode) = tox_proto::deserialize(&record.payload).ok()?;
+            if decoded.1.hash() != *hash {
+                return None;
+            }
+            return Some(decoded.1);
+        }
+
+        // Check packs
+        for pack in &ctx.packs {
+            if let Ok(Some(data)) = pack.get_node_data(hash) {
+                let data: Vec<u8> = data;
+                let decoded: (u8, MerkleNode) = tox_proto::deserialize(&data).ok()?;
+                if decoded.1.hash() != *hash {
+                    continue;
+                }
+                return Some(decoded.1);
+            }
+        }
+        None
+    }
+
     pub fn finalize_blob(&self, hash: &NodeHash) -> MerkleToxResult<()> {
         self.blob_store.finalize(hash).map_err(MerkleToxError::Io)
     }
 
+    /// Returns the ids of all conversations in the store.
+    pub fn conversations(&self) -> Vec<ConversationId> {
+        let mut ids: Vec<_> = self.inner.read().conversations.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Returns the bytes on disk used by a conversation's journal, packs and
+    /// keys. Blobs are shared between conversations and not included.
+    pub fn conversation_size_bytes(&self, id: &ConversationId) -> u64 {
+        let conv_dir = self
+            .root
+            .join("conversations")
+            .join(encode_hex_32(id.as_bytes()));
+        self.calculate_size(&conv_dir).unwrap_or(0)
+    }
+
+    /// Removes a conversation and everything stored for it, then deletes the
+    /// blobs that no other conversation references. Returns the hashes of the
+    /// deleted blobs.
+    pub fn delete_conversation(&self, id: &ConversationId) -> MerkleToxResult<Vec<NodeHash>> {
+        {
+            let mut inner = self.inner.write();
+            if let Some(ctx) = inner.conversations.get(id) {
+                ctx.lock_file.try_lock_exclusive().map_err(|_| {
+                    MerkleToxError::Io(Error::other("Conversation is in use by another process"))
+                })?;
+            }
+            inner.conversations.remove(id);
+            inner.node_to_conv.retain(|_, conv| conv != id);
+        }
+
+        let conv_dir = self
+            .root
+            .join("conversations")
+            .join(encode_hex_32(id.as_bytes()));
+        if self.fs.exists(&conv_dir) {
+            self.remove_tree(&conv_dir)?;
+        }
+
+        Ok(self.blob_store.release_conversation(id)?)
+    }
+
+    fn remove_tree(&self, dir: &std::path::Path) -> io::Result<()> {
+        for path in self.fs.read_dir(dir)? {
+            if self.fs.metadata(&path)?.is_dir {
+                self.remove_tree(&path)?;
+            } else {
+                self.fs.remove_file(&path)?;
+            }
+        }
+        self.fs.remove_dir(dir)
+    }
+
     pub fn prune_vault(&self, max_age: std::time::Duration) -> MerkleToxResult<()> {
         let vault_dir = self.root.join("vault");
         if let Ok(entries) = self.fs.read_dir(&vault_dir) {
@@ -1215,7 +1621,7 @@ impl<F: FileSystem> BlobStoreTrait for FsStore<F> {
 
     fn put_chunk(
         &self,
-        _conversation_id: &ConversationId,
+        conversation_id: &ConversationId,
         hash: &NodeHash,
         offset: u64,
         data: &[u8],
@@ -1225,6 +1631,12 @@ impl<F: FileSystem> BlobStoreTrait for FsStore<F> {
             .blob_store
             .get_info(hash)?
             .ok_or(MerkleToxError::BlobNotFound(*hash))?;
+        // The first chunk of a download records the conversation's
+        // reference, as does a chunk of a blob already stored for another
+        // conversation. Later chunks of a download leave `.refs` alone.
+        if info.status != BlobStatus::Downloading {
+            self.blob_store.add_ref(hash, conversation_id)?;
+        }
 
         self.blob_store.put_chunk(hash, offset, data)?;
 
@@ -1254,10 +1666,7 @@ impl<F: FileSystem> BlobStoreTrait for FsStore<F> {
                 break;
             }
         }
-        if complete
-            && let Ok(data) = self.blob_store.get_chunk(hash, 0, info.size as u32)
-            && data.len() as u64 == info.size
-        {
+        if complete && self.blob_store.data_len(hash).ok() == Some(info.size) {
             return self.finalize_blob(hash);
         }
 
@@ -1295,6 +1704,58 @@ impl<F: FileSystem> BlobStoreTrait for FsStore<F> {
             .get_chunk_with_proof(hash, offset, length)
             .map_err(MerkleToxError::Io)
     }
+
+    fn add_blob_ref(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        self.blob_store.add_ref(hash, conversation_id)?;
+        Ok(())
+    }
+
+    fn blob_ref_count(&self, hash: &NodeHash) -> usize {
+        self.blob_store.ref_count(hash).unwrap_or(0)
+    }
+
+    fn get_incomplete_blobs(&self) -> Vec<BlobInfo> {
+        self.blob_store.list_incomplete().unwrap_or_default()
+    }
+
+    fn put_staged_chunk(&self, staging_id: u64, offset: u64, data: &[u8]) -> MerkleToxResult<()> {
+        self.blob_store.put_staged_chunk(staging_id, offset, data)?;
+        Ok(())
+    }
+
+    fn commit_staged_blob(
+        &self,
+        conversation_id: &ConversationId,
+        staging_id: u64,
+        hash: &NodeHash,
+        size: u64,
+    ) -> MerkleToxResult<()> {
+        if self.has_blob(hash) {
+            self.blob_store.discard_staged(staging_id)?;
+            self.blob_store.add_ref(hash, conversation_id)?;
+            return Ok(());
+        }
+        self.blob_store.put_info(&BlobInfo {
+            hash: *hash,
+            size,
+            bao_root: None,
+            status: BlobStatus::Downloading,
+            received_mask: None,
+            decryption_key: None,
+        })?;
+        self.blob_store.commit_staged(staging_id, hash)?;
+        self.blob_store.add_ref(hash, conversation_id)?;
+        self.finalize_blob(hash)
+    }
+
+    fn discard_staged_blob(&self, staging_id: u64) -> MerkleToxResult<()> {
+        self.blob_store.discard_staged(staging_id)?;
+        Ok(())
+    }
 }
 
 impl<F: FileSystem> GlobalStore for FsStore<F> {
@@ -1349,6 +1810,23 @@ impl<F: FileSystem> ReconciliationStore for FsStore<F> {
     }
 }
 
+/// Journal record of a node.
+fn node_record(node: &MerkleNode, verified: bool) -> MerkleToxResult<JournalEntry> {
+    let status = if verified { 0x01u8 } else { 0x02u8 };
+    let payload = tox_proto::serialize(&(status, node))?;
+    Ok((JournalRecordType::Node, payload))
+}
+
+/// Journal record of a ratchet key.
+fn ratchet_record(
+    node_hash: &NodeHash,
+    chain_key: &ChainKey,
+    epoch_id: u64,
+) -> MerkleToxResult<JournalEntry> {
+    let payload = tox_proto::serialize(&(node_hash, chain_key, epoch_id))?;
+    Ok((JournalRecordType::RatchetAdvance, payload))
+}
+
 pub fn encode_hex_32(bytes: &[u8; 32]) -> String {
     let mut s = String::with_capacity(64);
     for &b in bytes {
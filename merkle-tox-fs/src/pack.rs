@@ -7,8 +7,15 @@ use tox_proto;
 
 pub const INDEX_MAGIC: u32 = 0x4D544F58;
 pub const DEFAULT_FANOUT_BITS: u32 = 8;
+/// Largest fanout accepted when loading an index; bounds the table size.
+pub const MAX_FANOUT_BITS: u32 = 24;
 pub const RECORD_SIZE: usize = 56;
 
+pub const STATUS_VERIFIED: u8 = 0x01;
+pub const STATUS_SPECULATIVE: u8 = 0x02;
+/// The node is known but its payload was reclaimed by GC.
+pub const STATUS_TOMBSTONE: u8 = 0x03;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct IndexRecord {
     pub hash: NodeHash,
@@ -145,7 +152,7 @@ impl PackIndex {
             r.to_bytes(&mut buf);
             file.write_all(&buf)?;
         }
-        Ok(())
+        file.sync()
     }
 
     pub fn load<F: FileSystem>(fs: &F, path: &Path) -> io::Result<Self> {
@@ -163,6 +170,12 @@ impl PackIndex {
 
         file.read_exact(&mut u32_buf)?;
         let fanout_bits = u32::from_le_bytes(u32_buf);
+        if fanout_bits > MAX_FANOUT_BITS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid index fanout bits",
+            ));
+        }
 
         file.read_exact(&mut u32_buf)?;
         let bloom_k = u32::from_le_bytes(u32_buf);
@@ -1,6 +1,6 @@
 use merkle_tox_core::dag::{ChainKey, NodeHash, PhysicalDevicePk};
-use merkle_tox_core::vfs::{FileHandle, FileSystem};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use merkle_tox_core::vfs::FileSystem;
+use std::io::{self, Write};
 use std::path::PathBuf;
 use std::sync::Arc;
 use tox_proto::{self, ToxProto};
@@ -12,6 +12,8 @@ pub struct ConvState {
     pub message_count: u32,
     pub last_rotation_time: i64,
     pub active_packs: Vec<u64>,
+    /// Rank before which history was not synced; 0 if complete.
+    pub history_horizon: u64,
     pub active_journal_id: u64,
 }
 
@@ -74,87 +76,92 @@ impl RatchetSlot {
 }
 
 pub struct RatchetFile<F: FileSystem> {
-    handle: Box<dyn FileHandle>,
-    _marker: std::marker::PhantomData<F>,
+    fs: Arc<F>,
+    path: PathBuf,
 }
 
 // Section 6.2: Ratchet Checkpoints
-// Atomic In-Place double-buffered pattern
+// Double-buffered table, replaced as a whole through a temporary file (see
+// Section 9.9), so a crash never leaves it half-written.
 // [Header: u32 magic, u32 active_buffer, u32 count0, u32 count1]
 // [Buffer 0: RatchetSlot * N]
 // [Buffer 1: RatchetSlot * N]
 
 pub const RATCHET_MAGIC: u32 = 0x52415443; // 'RATC'
+const RATCHET_HEADER_LEN: usize = 16;
+const RATCHET_BUFFER_LEN: usize = 1024 * RatchetSlot::SIZE; // Assume a maximum of 1024 slots
 
 impl<F: FileSystem> RatchetFile<F> {
     pub fn open(fs: Arc<F>, path: PathBuf) -> io::Result<Self> {
-        let mut handle = fs.open(&path, true, true, false)?;
-        let meta = handle.metadata()?;
-        if meta.len == 0 {
-            handle.write_all(&RATCHET_MAGIC.to_le_bytes())?;
-            handle.write_all(&0u32.to_le_bytes())?; // active_buffer
-            handle.write_all(&0u32.to_le_bytes())?; // count0
-            handle.write_all(&0u32.to_le_bytes())?; // count1
+        Ok(Self { fs, path })
+    }
+
+    fn read(&self) -> io::Result<Vec<u8>> {
+        let mut data = if self.fs.exists(&self.path) {
+            self.fs.read(&self.path)?
+        } else {
+            Vec::new()
+        };
+        if data.len() < RATCHET_HEADER_LEN {
+            // New file, or the header write was torn by a crash.
+            data.clear();
+            data.extend_from_slice(&RATCHET_MAGIC.to_le_bytes());
+            data.resize(RATCHET_HEADER_LEN, 0); // active_buffer, count0, count1
         }
-        Ok(Self {
-            handle,
-            _marker: std::marker::PhantomData,
-        })
+        Ok(data)
     }
 
     pub fn load(&mut self) -> io::Result<Vec<RatchetSlot>> {
-        self.handle.seek(SeekFrom::Start(4))?;
-        let mut buf = [0u8; 4];
-        self.handle.read_exact(&mut buf)?;
-        let active = u32::from_le_bytes(buf);
-
-        self.handle.read_exact(&mut buf)?;
-        let count0 = u32::from_le_bytes(buf);
-        self.handle.read_exact(&mut buf)?;
-        let count1 = u32::from_le_bytes(buf);
-
+        let data = self.read()?;
+        let word = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let active = word(4);
         let (count, offset) = if active == 0 {
-            (count0, 16)
+            (word(8), RATCHET_HEADER_LEN)
         } else {
-            (count1, 16 + 1024 * RatchetSlot::SIZE) // Assume a maximum of 1024 slots
+            (word(12), RATCHET_HEADER_LEN + RATCHET_BUFFER_LEN)
         };
 
-        self.handle.seek(SeekFrom::Start(offset as u64))?;
-        let mut slots = Vec::with_capacity(count as usize);
-        let mut slot_buf = [0u8; RatchetSlot::SIZE];
-        for _ in 0..count {
-            self.handle.read_exact(&mut slot_buf)?;
-            slots.push(RatchetSlot::from_bytes(&slot_buf));
+        if count == 0 {
+            return Ok(Vec::new());
         }
-        Ok(slots)
+        let end = offset + count as usize * RatchetSlot::SIZE;
+        let buffer = data
+            .get(offset..end)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        Ok(buffer
+            .chunks_exact(RatchetSlot::SIZE)
+            .map(RatchetSlot::from_bytes)
+            .collect())
     }
 
     pub fn save(&mut self, slots: &[RatchetSlot]) -> io::Result<()> {
-        self.handle.seek(SeekFrom::Start(4))?;
-        let mut buf = [0u8; 4];
-        self.handle.read_exact(&mut buf)?;
-        let active = u32::from_le_bytes(buf);
+        let mut data = self.read()?;
+        let active = u32::from_le_bytes(data[4..8].try_into().unwrap());
         let next = 1 - active;
 
-        let offset = 16 + (next as usize * 1024 * RatchetSlot::SIZE);
-        self.handle.seek(SeekFrom::Start(offset as u64))?;
-        let mut slot_buf = [0u8; RatchetSlot::SIZE];
-        for slot in slots {
-            slot.to_bytes(&mut slot_buf);
-            self.handle.write_all(&slot_buf)?;
+        let offset = RATCHET_HEADER_LEN + next as usize * RATCHET_BUFFER_LEN;
+        let end = offset + slots.len() * RatchetSlot::SIZE;
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        for (slot, buf) in slots
+            .iter()
+            .zip(data[offset..end].chunks_exact_mut(RatchetSlot::SIZE))
+        {
+            slot.to_bytes(buf);
         }
 
         // Update counts and active buffer
-        if next == 0 {
-            self.handle.seek(SeekFrom::Start(8))?;
-            self.handle.write_all(&(slots.len() as u32).to_le_bytes())?;
-        } else {
-            self.handle.seek(SeekFrom::Start(12))?;
-            self.handle.write_all(&(slots.len() as u32).to_le_bytes())?;
-        }
+        let count_at = if next == 0 { 8 } else { 12 };
+        data[count_at..count_at + 4].copy_from_slice(&(slots.len() as u32).to_le_bytes());
+        data[4..8].copy_from_slice(&next.to_le_bytes());
 
-        self.handle.seek(SeekFrom::Start(4))?;
-        self.handle.write_all(&next.to_le_bytes())?;
-        Ok(())
+        let mut tmp_path = self.path.clone();
+        tmp_path.set_extension("tmp");
+        let mut handle = self.fs.open(&tmp_path, true, true, true)?;
+        handle.write_all(&data)?;
+        handle.sync()?;
+        drop(handle);
+        self.fs.rename(&tmp_path, &self.path)
     }
 }
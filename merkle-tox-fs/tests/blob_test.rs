@@ -494,3 +494,86 @@ fn test_fs_store_partial_data_with_info() {
     assert!(res.is_ok(), "Should return Ok(zeros), but got {:?}", res);
     assert_eq!(res.unwrap(), vec![0u8; 512]);
 }
+
+#[test]
+fn test_fs_store_download_adds_one_ref() {
+    let fs = Arc::new(MemFileSystem::new());
+    let store = FsStore::new(std::path::PathBuf::from("/root"), fs).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let data = vec![3u8; (CHUNK_SIZE * 2) as usize];
+    let blob_hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+    store
+        .put_blob_info(BlobInfo {
+            hash: blob_hash,
+            size: data.len() as u64,
+            bao_root: None,
+            status: BlobStatus::Pending,
+            received_mask: None,
+            decryption_key: None,
+        })
+        .unwrap();
+    assert_eq!(store.blob_ref_count(&blob_hash), 0);
+
+    store
+        .put_chunk(&conv_id, &blob_hash, 0, &data[..CHUNK_SIZE as usize], None)
+        .unwrap();
+    assert_eq!(store.blob_ref_count(&blob_hash), 1);
+    assert_eq!(
+        store.get_blob_info(&blob_hash).unwrap().status,
+        BlobStatus::Downloading
+    );
+
+    store
+        .put_chunk(
+            &conv_id,
+            &blob_hash,
+            CHUNK_SIZE,
+            &data[CHUNK_SIZE as usize..],
+            None,
+        )
+        .unwrap();
+    assert_eq!(store.blob_ref_count(&blob_hash), 1);
+}
+
+#[test]
+fn test_fs_store_commit_staged_blob() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_a = ConversationId::from([1u8; 32]);
+    let conv_b = ConversationId::from([2u8; 32]);
+
+    let data: Vec<u8> = (0..CHUNK_SIZE as u32 + 100).map(|i| i as u8).collect();
+    let hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+    for (i, chunk) in data.chunks(CHUNK_SIZE as usize).enumerate() {
+        store
+            .put_staged_chunk(1, i as u64 * CHUNK_SIZE, chunk)
+            .unwrap();
+    }
+    assert!(!store.has_blob(&hash));
+    store
+        .commit_staged_blob(&conv_a, 1, &hash, data.len() as u64)
+        .unwrap();
+
+    assert!(store.has_blob(&hash));
+    let info = store.get_blob_info(&hash).unwrap();
+    assert_eq!(info.bao_root, Some(*hash.as_bytes()));
+    assert_eq!(
+        store.get_chunk(&hash, CHUNK_SIZE, 100).unwrap(),
+        data[CHUNK_SIZE as usize..]
+    );
+    assert!(!root.join("objects/staging/1.data").exists());
+
+    // Staging a blob that is already stored only adds a reference.
+    store.put_staged_chunk(2, 0, &data).unwrap();
+    store
+        .commit_staged_blob(&conv_b, 2, &hash, data.len() as u64)
+        .unwrap();
+    assert_eq!(store.blob_ref_count(&hash), 2);
+    assert!(!root.join("objects/staging/2.data").exists());
+
+    store.put_staged_chunk(3, 0, b"abandoned").unwrap();
+    store.discard_staged_blob(3).unwrap();
+    assert!(!root.join("objects/staging/3.data").exists());
+}
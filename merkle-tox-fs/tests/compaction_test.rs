@@ -4,7 +4,8 @@ use merkle_tox_core::dag::{
 };
 use merkle_tox_core::sync::NodeStore;
 use merkle_tox_core::vfs::StdFileSystem;
-use merkle_tox_fs::FsStore;
+use merkle_tox_fs::{CompactionConfig, FsStore, encode_hex_32};
+use std::path::Path;
 use std::sync::Arc;
 use tempfile::TempDir;
 
@@ -64,3 +65,120 @@ fn test_compaction_basic() {
         assert_eq!(retrieved.sequence_number, i);
     }
 }
+
+fn text_node(seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq - 1,
+        network_timestamp: 100,
+        content: Content::Text(format!("Node {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn file_count(root: &Path, conv_id: &ConversationId, extension: &str) -> usize {
+    let dir = root
+        .join("conversations")
+        .join(encode_hex_32(conv_id.as_bytes()))
+        .join("packs");
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == extension)
+        .count()
+}
+
+fn pack_count(root: &Path, conv_id: &ConversationId) -> usize {
+    file_count(root, conv_id, "pack")
+}
+
+#[test]
+fn test_compaction_custom_threshold() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let config = CompactionConfig {
+        threshold: 4,
+        ..CompactionConfig::default()
+    };
+    let store = FsStore::with_compaction(root.clone(), Arc::new(StdFileSystem), config).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    for seq in 1..=3 {
+        store.put_node(&conv_id, text_node(seq), true).unwrap();
+    }
+    assert_eq!(pack_count(&root, &conv_id), 0);
+    store.put_node(&conv_id, text_node(4), true).unwrap();
+    assert_eq!(pack_count(&root, &conv_id), 1);
+    assert!(!store.needs_compaction(&conv_id));
+}
+
+#[test]
+fn test_compaction_step_in_background_mode() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let config = CompactionConfig {
+        threshold: 4,
+        max_pack_size: 1,
+        background: true,
+    };
+    let store = FsStore::with_compaction(root.clone(), Arc::new(StdFileSystem), config).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    for seq in 1..=6 {
+        store.put_node(&conv_id, text_node(seq), true).unwrap();
+    }
+    // Writes never compact in background mode.
+    assert_eq!(pack_count(&root, &conv_id), 0);
+    assert!(store.needs_compaction(&conv_id));
+
+    assert!(store.compact_step(&conv_id).unwrap());
+    assert!(!store.needs_compaction(&conv_id));
+    assert!(!store.compact_step(&conv_id).unwrap());
+
+    // A tiny max_pack_size puts every node into its own pack. The packs
+    // are written under temporary names and renamed on commit.
+    assert_eq!(pack_count(&root, &conv_id), 6);
+    assert_eq!(file_count(&root, &conv_id, "tmp"), 0);
+    assert_eq!(store.get_node_counts(&conv_id), (6, 0));
+
+    store.put_node(&conv_id, text_node(7), true).unwrap();
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    assert_eq!(store.get_node_counts(&conv_id), (7, 0));
+    for seq in 1..=7 {
+        assert!(store.get_node(&text_node(seq).hash()).is_some());
+    }
+}
+
+#[test]
+fn test_compaction_worker() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let config = CompactionConfig {
+        threshold: 8,
+        background: true,
+        ..CompactionConfig::default()
+    };
+    let store = FsStore::with_compaction(root.clone(), Arc::new(StdFileSystem), config).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let worker = store.spawn_compaction_worker();
+
+    for seq in 1..=50 {
+        store.put_node(&conv_id, text_node(seq), true).unwrap();
+    }
+    drop(worker);
+
+    assert!(pack_count(&root, &conv_id) >= 1);
+    assert_eq!(store.get_node_counts(&conv_id), (50, 0));
+    for seq in 1..=50 {
+        assert!(store.get_node(&text_node(seq).hash()).is_some());
+    }
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    assert_eq!(store.get_node_counts(&conv_id), (50, 0));
+}
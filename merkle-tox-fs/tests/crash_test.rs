@@ -0,0 +1,294 @@
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::{FaultInjectingFileSystem, FileSystem, StdFileSystem};
+use merkle_tox_fs::crypt::{EncryptedFileSystem, StorageKey};
+use merkle_tox_fs::journal::{Journal, JournalRecordType};
+use merkle_tox_fs::{CompactionConfig, FsStore};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+const NODES: u64 = 12;
+
+fn text_node(seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq - 1,
+        network_timestamp: 100,
+        content: Content::Text(format!("Node {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn config() -> CompactionConfig {
+    CompactionConfig {
+        threshold: 5,
+        ..CompactionConfig::default()
+    }
+}
+
+/// A file system over `StdFileSystem` that can lose power.
+fn faulty() -> Arc<FaultInjectingFileSystem> {
+    Arc::new(FaultInjectingFileSystem::new(Arc::new(StdFileSystem)))
+}
+
+fn plain(fs: Arc<FaultInjectingFileSystem>) -> Arc<FaultInjectingFileSystem> {
+    fs
+}
+
+/// Encrypts on top of `fs`, so crashes tear the ciphertext.
+fn encrypted(
+    fs: Arc<FaultInjectingFileSystem>,
+) -> Arc<EncryptedFileSystem<FaultInjectingFileSystem>> {
+    Arc::new(EncryptedFileSystem::new(
+        fs,
+        &StorageKey::from_bytes([7u8; 32]),
+    ))
+}
+
+/// Writes nodes until the file system "loses power" after `crash_after`
+/// bytes and returns how many writes were acknowledged.
+fn run_until_crash<F: FileSystem>(
+    wrap: impl Fn(Arc<FaultInjectingFileSystem>) -> Arc<F>,
+    root: &Path,
+    conv_id: &ConversationId,
+    crash_after: Option<u64>,
+) -> u64 {
+    let fs = faulty();
+    let store = FsStore::with_compaction(root.to_path_buf(), wrap(fs.clone()), config()).unwrap();
+    if let Some(n) = crash_after {
+        fs.set_crash_at(fs.bytes_written() + n);
+    }
+    let mut acked = 0;
+    for seq in 1..=NODES {
+        if store.put_node(conv_id, text_node(seq), true).is_err() {
+            break;
+        }
+        acked = seq;
+    }
+    acked
+}
+
+fn store_survives_crash_at_every_point<F: FileSystem>(
+    wrap: impl Fn(Arc<FaultInjectingFileSystem>) -> Arc<F>,
+) {
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    // Measure how much a full run writes.
+    let total = {
+        let tmp_dir = TempDir::new().unwrap();
+        let fs = faulty();
+        let store =
+            FsStore::with_compaction(tmp_dir.path().to_path_buf(), wrap(fs.clone()), config())
+                .unwrap();
+        let start = fs.bytes_written();
+        for seq in 1..=NODES {
+            store.put_node(&conv_id, text_node(seq), true).unwrap();
+        }
+        fs.bytes_written() - start
+    };
+
+    let step = (total / 200).max(1);
+    let mut crash_after = 0;
+    while crash_after < total {
+        let tmp_dir = TempDir::new().unwrap();
+        let root = tmp_dir.path().to_path_buf();
+        let acked = run_until_crash(&wrap, &root, &conv_id, Some(crash_after));
+
+        let store = FsStore::with_compaction(root.clone(), wrap(faulty()), config())
+            .unwrap_or_else(|e| panic!("unloadable after crash at {}: {:?}", crash_after, e));
+        for seq in 1..=acked {
+            assert!(
+                store.get_node(&text_node(seq).hash()).is_some(),
+                "acknowledged node {} lost after crash at {}",
+                seq,
+                crash_after
+            );
+        }
+
+        // The store keeps working after recovery.
+        let next = NODES + 1;
+        store
+            .put_node(&conv_id, text_node(next), true)
+            .unwrap_or_else(|e| panic!("write failed after crash at {}: {:?}", crash_after, e));
+        drop(store);
+        let store = FsStore::new(root, wrap(faulty())).unwrap();
+        assert!(store.get_node(&text_node(next).hash()).is_some());
+
+        crash_after += step;
+    }
+}
+
+#[test]
+fn test_store_survives_crash_at_every_point() {
+    store_survives_crash_at_every_point(plain);
+}
+
+#[test]
+fn test_encrypted_store_survives_crash_at_every_point() {
+    store_survives_crash_at_every_point(encrypted);
+}
+
+fn compaction_survives_crash_at_every_point<F: FileSystem>(
+    wrap: impl Fn(Arc<FaultInjectingFileSystem>) -> Arc<F>,
+) {
+    let conv_id = ConversationId::from([1u8; 32]);
+    let config = CompactionConfig {
+        threshold: 4,
+        background: true,
+        ..CompactionConfig::default()
+    };
+    // Packs nodes 1-4, prunes node 1 and journals nodes 5-8, so the
+    // compaction carries a prune record over into the next generation.
+    let setup = |root: &Path| {
+        let store =
+            FsStore::with_compaction(root.to_path_buf(), wrap(faulty()), config.clone()).unwrap();
+        for seq in 1..=4 {
+            store.put_node(&conv_id, text_node(seq), true).unwrap();
+        }
+        assert!(store.compact_step(&conv_id).unwrap());
+        assert!(store.prune_node(&conv_id, &text_node(1).hash()).unwrap());
+        for seq in 5..=8 {
+            store.put_node(&conv_id, text_node(seq), true).unwrap();
+        }
+    };
+    let compact = |root: &Path, crash_after: Option<u64>| {
+        let fs = faulty();
+        let store =
+            FsStore::with_compaction(root.to_path_buf(), wrap(fs.clone()), config.clone()).unwrap();
+        let start = fs.bytes_written();
+        if let Some(n) = crash_after {
+            fs.set_crash_at(start + n);
+        }
+        let _ = store.compact_step(&conv_id);
+        fs.bytes_written() - start
+    };
+
+    let total = {
+        let tmp_dir = TempDir::new().unwrap();
+        setup(tmp_dir.path());
+        compact(tmp_dir.path(), None)
+    };
+
+    let step = (total / 100).max(1);
+    let mut crash_after = 0;
+    while crash_after <= total {
+        let tmp_dir = TempDir::new().unwrap();
+        setup(tmp_dir.path());
+        compact(tmp_dir.path(), Some(crash_after));
+
+        let store = FsStore::new(tmp_dir.path().to_path_buf(), wrap(faulty()))
+            .unwrap_or_else(|e| panic!("unloadable after crash at {}: {:?}", crash_after, e));
+        assert!(
+            store.get_node(&text_node(1).hash()).is_none(),
+            "pruned node restored after crash at {}",
+            crash_after
+        );
+        for seq in 2..=8 {
+            assert!(
+                store.get_node(&text_node(seq).hash()).is_some(),
+                "node {} lost after crash at {}",
+                seq,
+                crash_after
+            );
+        }
+        crash_after += step;
+    }
+}
+
+#[test]
+fn test_compaction_survives_crash_at_every_point() {
+    compaction_survives_crash_at_every_point(plain);
+}
+
+#[test]
+fn test_encrypted_compaction_survives_crash_at_every_point() {
+    compaction_survives_crash_at_every_point(encrypted);
+}
+
+#[test]
+fn test_journal_stops_at_corrupted_record() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("journal.bin");
+    let fs = Arc::new(FaultInjectingFileSystem::new(Arc::new(StdFileSystem)));
+
+    let mut journal = Journal::open(fs.clone(), path.clone()).unwrap();
+    journal
+        .append(JournalRecordType::Node, b"record-1")
+        .unwrap();
+    // Corrupt the type byte of the second record. Its payload is intact, so
+    // only a checksum over the header catches this.
+    fs.set_corrupt_at(fs.bytes_written() + 4 + 32);
+    journal
+        .append(JournalRecordType::Node, b"record-2")
+        .unwrap();
+    journal
+        .append(JournalRecordType::Node, b"record-3")
+        .unwrap();
+    drop(journal);
+
+    let mut journal = Journal::open(Arc::new(StdFileSystem), path).unwrap();
+    let records = journal.read_all().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].payload, b"record-1");
+}
+
+#[test]
+fn test_journal_torn_length_does_not_allocate() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("journal.bin");
+
+    {
+        let mut journal = Journal::open(Arc::new(StdFileSystem), path.clone()).unwrap();
+        journal
+            .append(JournalRecordType::Node, b"record-1")
+            .unwrap();
+    }
+
+    // A torn append that only got a huge length field to disk.
+    let mut data = std::fs::read(&path).unwrap();
+    let valid_len = data.len();
+    data.extend_from_slice(&u32::MAX.to_le_bytes());
+    data.extend_from_slice(&[0xAB; 10]);
+    std::fs::write(&path, &data).unwrap();
+
+    let mut journal = Journal::open(Arc::new(StdFileSystem), path.clone()).unwrap();
+    let records = journal.read_all().unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_len as u64);
+}
+
+#[test]
+fn test_journal_footer_is_not_treated_as_corruption() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("journal.bin");
+
+    {
+        let mut journal = Journal::open(Arc::new(StdFileSystem), path.clone()).unwrap();
+        journal
+            .append(JournalRecordType::Node, b"record-1")
+            .unwrap();
+        journal.write_footer().unwrap();
+        journal.write_footer().unwrap();
+    }
+    let with_footer = std::fs::metadata(&path).unwrap().len();
+
+    let mut journal = Journal::open(Arc::new(StdFileSystem), path.clone()).unwrap();
+    assert_eq!(journal.read_all().unwrap().len(), 1);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), with_footer);
+
+    journal
+        .append(JournalRecordType::Node, b"record-2")
+        .unwrap();
+    drop(journal);
+    let mut journal = Journal::open(Arc::new(StdFileSystem), path).unwrap();
+    assert_eq!(journal.read_all().unwrap().len(), 2);
+}
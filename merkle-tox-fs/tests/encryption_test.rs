@@ -0,0 +1,286 @@
+use merkle_tox_core::cas::{BlobInfo, BlobStatus};
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::vfs::{FileSystem, StdFileSystem};
+use merkle_tox_fs::FsStore;
+use merkle_tox_fs::crypt::{
+    EncryptedFileSystem, KdfParams, SALT_FILE, StorageKey, load_or_create_kdf_params,
+};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+const SECRET: &str = "attack at dawn, bring snacks";
+
+fn make_node(seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: 0,
+        network_timestamp: 100,
+        content: Content::Text(format!("{} #{}", SECRET, seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn collect_files(dir: &Path, out: &mut Vec<std::path::PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_files(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+#[test]
+fn test_encrypted_store_roundtrip_and_no_plaintext() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let key = StorageKey::from_bytes([7u8; 32]);
+    let conv_id = ConversationId::from([0x55u8; 32]);
+    let mut hashes = Vec::new();
+
+    {
+        let store =
+            FsStore::new_encrypted(root.clone(), Arc::new(StdFileSystem), key.clone()).unwrap();
+        for seq in 1..=20 {
+            let node = make_node(seq);
+            hashes.push(node.hash());
+            store.put_node(&conv_id, node, true).unwrap();
+        }
+        store.set_heads(&conv_id, vec![hashes[19]]).unwrap();
+        store.compact(&conv_id).unwrap();
+        store.put_node(&conv_id, make_node(21), true).unwrap();
+    }
+
+    let mut files = Vec::new();
+    collect_files(&root, &mut files);
+    assert!(!files.is_empty());
+    for path in &files {
+        let raw = std::fs::read(path).unwrap();
+        assert!(
+            !raw.windows(SECRET.len()).any(|w| w == SECRET.as_bytes()),
+            "plaintext leaked into {:?}",
+            path
+        );
+    }
+
+    let store = FsStore::new_encrypted(root, Arc::new(StdFileSystem), key).unwrap();
+    assert_eq!(store.get_heads(&conv_id), vec![hashes[19]]);
+    for (i, hash) in hashes.iter().enumerate() {
+        let node = store.get_node(hash).expect("node should survive reopen");
+        assert_eq!(node.sequence_number, i as u64 + 1);
+    }
+}
+
+#[test]
+fn test_encrypted_store_rejects_wrong_key() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+
+    {
+        let _store = FsStore::new_encrypted(
+            root.clone(),
+            Arc::new(StdFileSystem),
+            StorageKey::from_bytes([1u8; 32]),
+        )
+        .unwrap();
+    }
+
+    let res = FsStore::new_encrypted(
+        root,
+        Arc::new(StdFileSystem),
+        StorageKey::from_bytes([2u8; 32]),
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_encrypted_store_passphrase_key() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let fs = Arc::new(StdFileSystem);
+    let conv_id = ConversationId::from([0x11u8; 32]);
+
+    let params = load_or_create_kdf_params(fs.as_ref(), &root).unwrap();
+    assert_eq!(
+        load_or_create_kdf_params(fs.as_ref(), &root).unwrap(),
+        params
+    );
+
+    let node = make_node(1);
+    let hash = node.hash();
+    {
+        let key = StorageKey::from_passphrase(b"correct horse", &params).unwrap();
+        let store = FsStore::new_encrypted(root.clone(), fs.clone(), key).unwrap();
+        store.put_node(&conv_id, node, true).unwrap();
+    }
+
+    let key = StorageKey::from_passphrase(b"correct horse", &params).unwrap();
+    let store = FsStore::new_encrypted(root.clone(), fs.clone(), key).unwrap();
+    assert!(store.get_node(&hash).is_some());
+    drop(store);
+
+    let wrong = StorageKey::from_passphrase(b"battery staple", &params).unwrap();
+    assert!(FsStore::new_encrypted(root, fs, wrong).is_err());
+}
+
+#[test]
+fn test_kdf_params_are_recorded() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let fs = StdFileSystem;
+
+    let params = load_or_create_kdf_params(&fs, &root).unwrap();
+    assert_eq!(
+        (params.m_cost_kib, params.t_cost, params.p_cost),
+        (19 * 1024, 2, 1)
+    );
+    let raw = std::fs::read(root.join(SALT_FILE)).unwrap();
+    assert_eq!(KdfParams::from_bytes(&raw).unwrap(), params);
+
+    // A changed cost is read back rather than replaced by the default.
+    let slow = KdfParams {
+        t_cost: 3,
+        ..params
+    };
+    std::fs::write(root.join(SALT_FILE), slow.to_bytes()).unwrap();
+    assert_eq!(load_or_create_kdf_params(&fs, &root).unwrap(), slow);
+
+    // Salt files from before the cost was recorded keep deriving the same key.
+    std::fs::write(root.join(SALT_FILE), params.salt).unwrap();
+    assert_eq!(load_or_create_kdf_params(&fs, &root).unwrap(), params);
+}
+
+#[test]
+fn test_encrypted_store_blobs() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new_encrypted(
+        root.clone(),
+        Arc::new(StdFileSystem),
+        StorageKey::from_bytes([9u8; 32]),
+    )
+    .unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let data = SECRET.repeat(400).into_bytes();
+    let hash = NodeHash::from(*blake3::hash(&data).as_bytes());
+    store
+        .put_blob_info(BlobInfo {
+            hash,
+            size: data.len() as u64,
+            bao_root: None,
+            status: BlobStatus::Pending,
+            received_mask: None,
+            decryption_key: None,
+        })
+        .unwrap();
+    store.put_chunk(&conv_id, &hash, 0, &data, None).unwrap();
+
+    assert!(store.has_blob(&hash));
+    assert_eq!(store.get_chunk(&hash, 100, 50).unwrap(), &data[100..150]);
+
+    let mut files = Vec::new();
+    collect_files(&root.join("objects"), &mut files);
+    for path in &files {
+        let raw = std::fs::read(path).unwrap();
+        assert!(!raw.windows(SECRET.len()).any(|w| w == SECRET.as_bytes()));
+    }
+}
+
+#[test]
+fn test_encrypted_handle_random_access() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("file.bin");
+    let fs = EncryptedFileSystem::new(Arc::new(StdFileSystem), &StorageKey::from_bytes([3u8; 32]));
+
+    let mut expected = vec![0u8; 10_000];
+    {
+        let mut handle = fs.open(&path, true, true, false).unwrap();
+        let first: Vec<u8> = (0..6000u32).map(|i| i as u8).collect();
+        handle.write_all(&first).unwrap();
+        expected[..6000].copy_from_slice(&first);
+
+        // Overwrite across a block boundary.
+        handle.seek(SeekFrom::Start(4090)).unwrap();
+        handle.write_all(&[0xAA; 20]).unwrap();
+        expected[4090..4110].fill(0xAA);
+
+        // Write past the end; the gap must read back as zeroes.
+        handle.seek(SeekFrom::Start(9990)).unwrap();
+        handle.write_all(&[0xBB; 10]).unwrap();
+        expected[9990..].fill(0xBB);
+    }
+
+    assert_eq!(fs.metadata(&path).unwrap().len, 10_000);
+    assert_eq!(fs.read(&path).unwrap(), expected);
+
+    {
+        let mut handle = fs.open(&path, true, false, false).unwrap();
+        handle.set_len(4100).unwrap();
+        handle.seek(SeekFrom::Start(4000)).unwrap();
+        let mut buf = Vec::new();
+        handle.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, &expected[4000..4100]);
+    }
+    assert_eq!(fs.read(&path).unwrap(), &expected[..4100]);
+}
+
+#[test]
+fn test_encrypted_file_detects_tampering() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("file.bin");
+    let fs = EncryptedFileSystem::new(Arc::new(StdFileSystem), &StorageKey::from_bytes([3u8; 32]));
+    fs.write(&path, SECRET.as_bytes()).unwrap();
+
+    let mut raw = std::fs::read(&path).unwrap();
+    let last = raw.len() - 1;
+    raw[last] ^= 0x01;
+    std::fs::write(&path, &raw).unwrap();
+
+    let err = fs.read(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_encrypted_file_detects_truncation() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("file.bin");
+    let fs = EncryptedFileSystem::new(Arc::new(StdFileSystem), &StorageKey::from_bytes([3u8; 32]));
+    // Header, two full blocks and a partial one.
+    const HEADER: usize = 24;
+    const STORED_BLOCK: usize = 4096 + 44;
+    let data = vec![0x5Au8; 9000];
+
+    for blocks in [0, 1, 2] {
+        fs.write(&path, &data).unwrap();
+        let raw = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &raw[..HEADER + blocks * STORED_BLOCK]).unwrap();
+        let err = fs.read(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // Files shrunk through the handle stay readable, down to empty.
+    for len in [8192, 5000, 0] {
+        fs.write(&path, &data).unwrap();
+        fs.open(&path, true, false, false)
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+        assert_eq!(fs.read(&path).unwrap(), &data[..len as usize]);
+    }
+    let raw = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &raw[..HEADER]).unwrap();
+    assert!(fs.read(&path).is_err());
+}
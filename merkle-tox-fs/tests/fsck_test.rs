@@ -0,0 +1,420 @@
+use merkle_tox_core::cas::{BlobInfo, BlobStatus, CHUNK_SIZE};
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::{FsStore, FsckIssue, FsckMode, FsckReport, encode_hex_32, fsck};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn text_node(seq: u64, parents: Vec<NodeHash>) -> MerkleNode {
+    MerkleNode {
+        parents,
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq - 1,
+        network_timestamp: 100,
+        content: Content::Text(format!("Node {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn conv_dir(root: &Path, conv_id: &ConversationId) -> PathBuf {
+    root.join("conversations")
+        .join(encode_hex_32(conv_id.as_bytes()))
+}
+
+fn only_pack(root: &Path, conv_id: &ConversationId, ext: &str) -> PathBuf {
+    let mut files: Vec<_> = std::fs::read_dir(conv_dir(root, conv_id).join("packs"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().unwrap() == ext)
+        .collect();
+    assert_eq!(files.len(), 1);
+    files.pop().unwrap()
+}
+
+/// Stores a chain of `count` verified nodes; the first `packed` are moved
+/// into a pack.
+fn populate(root: &Path, conv_id: &ConversationId, packed: u64, count: u64) -> Vec<NodeHash> {
+    let store = FsStore::new(root.to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    let mut hashes: Vec<NodeHash> = Vec::new();
+    for seq in 1..=count {
+        let node = text_node(seq, hashes.last().copied().into_iter().collect());
+        hashes.push(node.hash());
+        store.put_node(conv_id, node, true).unwrap();
+        if seq == packed {
+            store.compact(conv_id).unwrap();
+        }
+    }
+    hashes
+}
+
+fn check(root: &Path) -> FsckReport {
+    fsck(root, Arc::new(StdFileSystem), FsckMode::Check).unwrap()
+}
+
+fn repair(root: &Path) -> FsckReport {
+    fsck(root, Arc::new(StdFileSystem), FsckMode::Repair).unwrap()
+}
+
+fn assert_readable(root: &Path, hashes: &[NodeHash]) {
+    let store = FsStore::new(root.to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    for hash in hashes {
+        assert!(
+            store.get_node(hash).is_some(),
+            "node {:?} not readable",
+            hash
+        );
+    }
+}
+
+#[test]
+fn test_clean_store() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    populate(temp.path(), &conv_id, 3, 5);
+
+    let report = check(temp.path());
+    assert!(report.is_clean(), "{:?}", report.issues);
+    assert_eq!(report.conversations, 1);
+    assert_eq!(report.nodes, 5);
+    assert!(!report.repaired);
+    assert!(report.quarantine.is_none());
+}
+
+#[test]
+fn test_torn_journal_is_quarantined() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let hashes = populate(temp.path(), &conv_id, 0, 3);
+
+    let journal = conv_dir(temp.path(), &conv_id).join("journal.bin");
+    let mut data = std::fs::read(&journal).unwrap();
+    // Garbage after the footer written on close also invalidates the footer.
+    let clean_len = data.len() - 40;
+    data.extend_from_slice(&[0x50, 0, 0, 0, 7, 7, 7]);
+    std::fs::write(&journal, &data).unwrap();
+
+    // Checking does not modify the store.
+    let report = check(temp.path());
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::CorruptJournal {
+            conversation_id: conv_id,
+            offset: clean_len as u64,
+            bytes: 47,
+        }]
+    );
+    assert_eq!(std::fs::read(&journal).unwrap().len(), data.len());
+
+    let report = repair(temp.path());
+    assert_eq!(report.issues.len(), 1);
+    assert!(report.remaining().is_empty());
+    let quarantined = report
+        .quarantine
+        .unwrap()
+        .join(encode_hex_32(conv_id.as_bytes()))
+        .join("journal.bin");
+    assert_eq!(std::fs::read(quarantined).unwrap(), data[clean_len..]);
+    assert_eq!(std::fs::read(&journal).unwrap().len(), clean_len);
+
+    assert!(check(temp.path()).is_clean());
+    assert_readable(temp.path(), &hashes);
+}
+
+#[test]
+fn test_undecodable_record_and_dangling_promotion() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let hashes = populate(temp.path(), &conv_id, 0, 2);
+
+    let path = conv_dir(temp.path(), &conv_id).join("journal.bin");
+    // Appending drops the footer written on close.
+    let before = std::fs::metadata(&path).unwrap().len() - 40;
+    {
+        let mut journal =
+            merkle_tox_fs::journal::Journal::open(Arc::new(StdFileSystem), path.clone()).unwrap();
+        let missing = NodeHash::from([9u8; 32]);
+        journal
+            .append(
+                merkle_tox_fs::journal::JournalRecordType::Promotion,
+                &tox_proto::serialize(&missing).unwrap(),
+            )
+            .unwrap();
+        journal
+            .append(merkle_tox_fs::journal::JournalRecordType::Node, &[1, 2, 3])
+            .unwrap();
+    }
+    // The store cannot load the conversation any more.
+    {
+        let store = FsStore::new(temp.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+        assert!(store.get_node(&hashes[0]).is_none());
+    }
+
+    let report = repair(temp.path());
+    assert!(
+        matches!(report.issues[0], FsckIssue::BadJournalRecord { .. }),
+        "{:?}",
+        report.issues
+    );
+    assert!(
+        matches!(report.issues[1], FsckIssue::DanglingRecord { hash, .. } if hash == NodeHash::from([9u8; 32]))
+    );
+    assert_eq!(report.issues.len(), 2);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), before);
+
+    assert!(check(temp.path()).is_clean());
+    assert_readable(temp.path(), &hashes);
+}
+
+#[test]
+fn test_pack_index_is_rebuilt() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let hashes = populate(temp.path(), &conv_id, 4, 4);
+
+    let index = only_pack(temp.path(), &conv_id, "idx");
+    let original = std::fs::read(&index).unwrap();
+    let mut data = original.clone();
+    // Corrupt the offset of the last record.
+    let at = data.len() - 56 + 32;
+    data[at] ^= 0xFF;
+    std::fs::write(&index, &data).unwrap();
+
+    let report = check(temp.path());
+    assert_eq!(report.issues.len(), 1);
+    assert!(matches!(
+        &report.issues[0],
+        FsckIssue::BadPackIndex { reason, .. } if reason.contains("does not match a node")
+    ));
+
+    repair(temp.path());
+    assert_eq!(std::fs::read(&index).unwrap(), original);
+    assert!(check(temp.path()).is_clean());
+
+    std::fs::remove_file(&index).unwrap();
+    let report = repair(temp.path());
+    assert!(matches!(
+        &report.issues[..],
+        [FsckIssue::BadPackIndex { reason, .. }] if reason == "index file is missing"
+    ));
+    assert_eq!(std::fs::read(&index).unwrap(), original);
+    assert_readable(temp.path(), &hashes);
+}
+
+#[test]
+fn test_corrupt_pack_node_is_dropped() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let hashes = populate(temp.path(), &conv_id, 3, 3);
+
+    let pack = only_pack(temp.path(), &conv_id, "pack");
+    let mut data = std::fs::read(&pack).unwrap();
+    // The first frame holds the first node; flip a byte of its payload.
+    let len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+    let last = 4 + 32 + 1 + len - 1;
+    data[last] ^= 0xFF;
+    std::fs::write(&pack, &data).unwrap();
+
+    let report = check(temp.path());
+    assert!(matches!(
+        report.issues[0],
+        FsckIssue::BadPackNode { offset: 0, .. }
+    ));
+
+    let report = repair(temp.path());
+    let quarantine = report.quarantine.unwrap();
+    let pack_name = pack.file_name().unwrap();
+    let dropped = std::fs::read(
+        quarantine
+            .join(encode_hex_32(conv_id.as_bytes()))
+            .join(pack_name),
+    )
+    .unwrap();
+    assert_eq!(dropped, data[..4 + 32 + 1 + len]);
+
+    assert!(check(temp.path()).is_clean());
+    assert_readable(temp.path(), &hashes[1..]);
+    let store = FsStore::new(temp.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    assert!(store.get_node(&hashes[0]).is_none());
+}
+
+#[test]
+fn test_state_is_rebuilt() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let hashes = populate(temp.path(), &conv_id, 3, 5);
+
+    let state = conv_dir(temp.path(), &conv_id).join("state.bin");
+    std::fs::write(&state, [0xFF; 3]).unwrap();
+
+    let report = repair(temp.path());
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::BadState {
+            conversation_id: conv_id
+        }]
+    );
+    assert!(check(temp.path()).is_clean());
+
+    let store = FsStore::new(temp.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    assert_eq!(store.get_heads(&conv_id), vec![hashes[4]]);
+    for hash in &hashes {
+        assert!(store.get_node(hash).is_some());
+    }
+}
+
+#[test]
+fn test_unreferenced_and_missing_packs() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    populate(temp.path(), &conv_id, 3, 3);
+
+    let packs = conv_dir(temp.path(), &conv_id).join("packs");
+    let pack = only_pack(temp.path(), &conv_id, "pack");
+    let index = only_pack(temp.path(), &conv_id, "idx");
+    std::fs::copy(&pack, packs.join("00000000000000aa.pack")).unwrap();
+    std::fs::copy(&index, packs.join("00000000000000aa.idx")).unwrap();
+    std::fs::remove_file(&pack).unwrap();
+
+    let report = repair(temp.path());
+    assert_eq!(report.issues.len(), 2, "{:?}", report.issues);
+    assert!(matches!(
+        report.issues[0],
+        FsckIssue::UnreferencedPack { pack_id: 0xaa, .. }
+    ));
+    assert!(matches!(report.issues[1], FsckIssue::MissingPack { .. }));
+    assert!(!packs.join("00000000000000aa.pack").exists());
+
+    // The index of the missing pack is left for GC.
+    let report = check(temp.path());
+    assert!(matches!(
+        &report.issues[..],
+        [FsckIssue::UnreferencedPack { .. }]
+    ));
+}
+
+#[test]
+fn test_journal_generation_mismatch() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    populate(temp.path(), &conv_id, 2, 3);
+
+    let journal = conv_dir(temp.path(), &conv_id).join("journal.bin");
+    let mut data = std::fs::read(&journal).unwrap();
+    data[0] ^= 0xFF;
+    std::fs::write(&journal, &data).unwrap();
+
+    let report = repair(temp.path());
+    assert!(matches!(
+        &report.issues[..],
+        [FsckIssue::JournalGeneration { .. }]
+    ));
+    assert_eq!(std::fs::metadata(&journal).unwrap().len(), 16);
+    assert!(check(temp.path()).is_clean());
+}
+
+#[test]
+fn test_duplicate_node_is_not_repaired() {
+    let temp = TempDir::new().unwrap();
+    let a = ConversationId::from([1u8; 32]);
+    let b = ConversationId::from([2u8; 32]);
+    let hashes = populate(temp.path(), &a, 0, 1);
+    populate(temp.path(), &b, 0, 1);
+
+    let report = repair(temp.path());
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::DuplicateNode {
+            hash: hashes[0],
+            conversations: vec![a, b],
+        }]
+    );
+    assert_eq!(report.remaining().len(), 1);
+}
+
+#[test]
+fn test_blob_mask_and_size() {
+    let temp = TempDir::new().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let size = 3 * CHUNK_SIZE;
+    let partial = NodeHash::from([0xB1; 32]);
+    let broken = NodeHash::from([0xB2; 32]);
+    {
+        let store = FsStore::new(temp.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+        for hash in [partial, broken] {
+            store
+                .put_blob_info(BlobInfo {
+                    hash,
+                    size,
+                    bao_root: None,
+                    status: BlobStatus::Pending,
+                    received_mask: None,
+                    decryption_key: None,
+                })
+                .unwrap();
+        }
+        for offset in [0, CHUNK_SIZE] {
+            let chunk = vec![7u8; CHUNK_SIZE as usize];
+            store
+                .put_chunk(&conv_id, &partial, offset, &chunk, None)
+                .unwrap();
+        }
+        let mut info = store.get_blob_info(&broken).unwrap();
+        info.received_mask = Some(vec![0xFF; 4]);
+        info.status = BlobStatus::Available;
+        store.put_blob_info(info).unwrap();
+    }
+    let hex = encode_hex_32(partial.as_bytes());
+    let data = temp
+        .path()
+        .join("objects")
+        .join(&hex[..2])
+        .join(format!("{}.data", hex));
+    let file = std::fs::OpenOptions::new().write(true).open(&data).unwrap();
+    file.set_len(CHUNK_SIZE + 10).unwrap();
+
+    let report = repair(temp.path());
+    assert_eq!(report.blobs, 2);
+    assert_eq!(
+        report.issues,
+        vec![
+            FsckIssue::BlobChunks {
+                hash: partial,
+                missing: 1,
+            },
+            FsckIssue::BlobMask {
+                hash: broken,
+                len: 4,
+                expected: 1,
+            },
+            FsckIssue::BlobSize {
+                hash: broken,
+                size,
+                data_len: 0,
+            },
+        ]
+    );
+    assert!(check(temp.path()).is_clean());
+
+    let store = FsStore::new(temp.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    let info = store.get_blob_info(&partial).unwrap();
+    assert_eq!(info.received_mask, Some(vec![0b001]));
+    let info = store.get_blob_info(&broken).unwrap();
+    assert_eq!(info.status, BlobStatus::Downloading);
+    assert_eq!(info.received_mask, Some(vec![0]));
+}
+
+#[test]
+fn test_open_store_is_refused() {
+    let temp = TempDir::new().unwrap();
+    let _store = FsStore::new(temp.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    assert!(fsck(temp.path(), Arc::new(StdFileSystem), FsckMode::Check).is_err());
+}
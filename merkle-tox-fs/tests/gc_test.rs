@@ -0,0 +1,227 @@
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{NodeStore, SyncRange};
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::{FsStore, GcPolicy, encode_hex_32};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn make_node(seq: u64, content: Content) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq - 1,
+        network_timestamp: 100,
+        content,
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn text_node(seq: u64) -> MerkleNode {
+    make_node(seq, Content::Text(format!("Node {}", seq)))
+}
+
+fn packs_dir(root: &Path, conv_id: &ConversationId) -> PathBuf {
+    root.join("conversations")
+        .join(encode_hex_32(conv_id.as_bytes()))
+        .join("packs")
+}
+
+fn pack_count(dir: &Path) -> usize {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter(|e| e.as_ref().unwrap().path().extension().unwrap() == "pack")
+        .count()
+}
+
+#[test]
+fn test_gc_merges_small_packs() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    for seq in 1..=9 {
+        store.put_node(&conv_id, text_node(seq), true).unwrap();
+        if seq % 3 == 0 {
+            store.compact(&conv_id).unwrap();
+        }
+    }
+    assert_eq!(pack_count(&packs_dir(&root, &conv_id)), 3);
+
+    let stats = store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    assert_eq!(stats.packs_rewritten, 3);
+    assert_eq!(stats.records_dropped, 0);
+    assert_eq!(pack_count(&packs_dir(&root, &conv_id)), 1);
+    assert_eq!(store.get_node_counts(&conv_id), (9, 0));
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    for seq in 1..=9 {
+        let node = text_node(seq);
+        assert_eq!(store.get_node(&node.hash()), Some(node));
+    }
+}
+
+#[test]
+fn test_gc_dedups_and_drops_speculative() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    // Packed speculatively, then stored again once verified.
+    let promoted = text_node(1);
+    store.put_node(&conv_id, promoted.clone(), false).unwrap();
+    let abandoned = text_node(2);
+    store.put_node(&conv_id, abandoned.clone(), false).unwrap();
+    store.compact(&conv_id).unwrap();
+    store.put_node(&conv_id, promoted.clone(), true).unwrap();
+    store.compact(&conv_id).unwrap();
+    assert_eq!(store.get_node_counts(&conv_id), (1, 2));
+
+    let policy = GcPolicy {
+        drop_speculative: true,
+        ..GcPolicy::default()
+    };
+    let stats = store.gc(&conv_id, &policy).unwrap();
+    assert_eq!(stats.records_dropped, 2);
+    assert_eq!(store.get_node_counts(&conv_id), (1, 0));
+    assert!(store.is_verified(&promoted.hash()));
+    assert_eq!(store.get_node(&promoted.hash()), Some(promoted));
+    assert!(!store.has_node(&abandoned.hash()));
+}
+
+#[test]
+fn test_gc_tombstones_redacted_nodes() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let target = text_node(1);
+    let target_hash = target.hash();
+    store.put_node(&conv_id, target, true).unwrap();
+    let redaction = make_node(
+        2,
+        Content::Redaction {
+            target_hash,
+            reason: "oops".to_string(),
+        },
+    );
+    store.put_node(&conv_id, redaction.clone(), true).unwrap();
+
+    let stats = store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    assert_eq!(stats.records_tombstoned, 1);
+
+    // Still known for reconciliation, but the content is gone.
+    assert!(store.has_node(&target_hash));
+    assert!(store.is_verified(&target_hash));
+    assert!(store.get_node(&target_hash).is_none());
+    let range = SyncRange {
+        min_rank: 0,
+        max_rank: u64::MAX,
+    };
+    let hashes = store.get_node_hashes_in_range(&conv_id, &range).unwrap();
+    assert!(hashes.contains(&target_hash));
+    assert_eq!(store.get_node(&redaction.hash()), Some(redaction));
+
+    // A second run has nothing left to do.
+    let stats = store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    assert_eq!(stats.records_tombstoned, 0);
+    assert_eq!(stats.packs_rewritten, 0);
+}
+
+#[test]
+fn test_gc_removes_orphaned_pack_files() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    store.put_node(&conv_id, text_node(1), true).unwrap();
+    store.compact(&conv_id).unwrap();
+
+    // Leftovers of an interrupted run.
+    let dir = packs_dir(&root, &conv_id);
+    std::fs::write(dir.join("00000000deadbeef.pack"), b"junk").unwrap();
+    std::fs::write(dir.join("00000000deadbeef.idx"), b"junk").unwrap();
+    std::fs::write(dir.join("00000000cafebabe.pack.tmp"), b"junk").unwrap();
+
+    let stats = store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    assert_eq!(stats.orphans_removed, 3);
+    assert_eq!(pack_count(&dir), 1);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    assert!(store.get_node(&text_node(1).hash()).is_some());
+}
+
+#[test]
+fn test_gc_reclaims_pruned_payloads() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    let other_id = ConversationId::from([2u8; 32]);
+
+    let nodes: Vec<_> = (1..=8)
+        .map(|seq| make_node(seq, Content::Text("x".repeat(4096))))
+        .collect();
+    for node in &nodes {
+        store.put_node(&conv_id, node.clone(), true).unwrap();
+    }
+    store.put_node(&other_id, text_node(1), true).unwrap();
+    assert_eq!(store.conversations(), vec![conv_id, other_id]);
+
+    store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    let before = store.conversation_size_bytes(&conv_id);
+    assert!(before >= 8 * 4096);
+
+    for node in &nodes[..6] {
+        assert!(store.prune_node(&conv_id, &node.hash()).unwrap());
+    }
+    let stats = store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    assert_eq!(stats.records_tombstoned, 6);
+
+    let after = store.conversation_size_bytes(&conv_id);
+    assert!(before - after >= 6 * 4096);
+    assert!(store.has_node(&nodes[0].hash()));
+    assert!(store.get_node(&nodes[7].hash()).is_some());
+}
+
+#[test]
+fn test_gc_keeps_packs_of_concurrent_compaction() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let compactor = {
+        let store = store.clone();
+        std::thread::spawn(move || {
+            for seq in 1..=40 {
+                store.put_node(&conv_id, text_node(seq), true).unwrap();
+                store.compact_step(&conv_id).unwrap();
+            }
+        })
+    };
+    for _ in 0..40 {
+        store.gc(&conv_id, &GcPolicy::default()).unwrap();
+    }
+    compactor.join().unwrap();
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    for seq in 1..=40 {
+        let node = text_node(seq);
+        assert_eq!(store.get_node(&node.hash()), Some(node));
+    }
+}
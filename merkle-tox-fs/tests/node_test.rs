@@ -1,10 +1,11 @@
 use merkle_tox_core::dag::{
-    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
-    NodeLookup, PhysicalDevicePk,
+    ChainKey, Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth,
+    NodeHash, NodeLookup, PhysicalDevicePk,
 };
-use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::sync::{NodeStore, StoreWrite};
 use merkle_tox_core::vfs::StdFileSystem;
 use merkle_tox_fs::FsStore;
+use merkle_tox_fs::journal::JOURNAL_FOOTER_MAGIC;
 use std::fs;
 use std::sync::Arc;
 use tempfile::TempDir;
@@ -451,3 +452,88 @@ fn test_fs_store_mark_verified_idempotency() {
     let spec = store.get_speculative_nodes(&sync_key);
     assert!(spec.is_empty());
 }
+
+#[test]
+fn test_fs_store_flush_seals_journal() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let sync_key = ConversationId::from([0u8; 32]);
+    let node = |seq: u64| MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: 0,
+        network_timestamp: 12345,
+        content: Content::Text(format!("Message {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    };
+    let journal_path = root
+        .join("conversations")
+        .join(encode_hex_32(sync_key.as_bytes()))
+        .join("journal.bin");
+
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    store.put_node(&sync_key, node(1), true).unwrap();
+    store.flush().unwrap();
+    let data = fs::read(&journal_path).unwrap();
+    // Footer: magic, record count, checksum.
+    let footer = data.len() - (4 + 4 + 32);
+    assert_eq!(data[footer..footer + 4], JOURNAL_FOOTER_MAGIC.to_le_bytes());
+
+    // Writes after a flush reopen the journal.
+    store.put_node(&sync_key, node(2), true).unwrap();
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    assert!(store.has_node(&node(1).hash()));
+    assert!(store.has_node(&node(2).hash()));
+}
+
+#[test]
+fn test_fs_store_write_batch_survives_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    let sync_key = ConversationId::from([2u8; 32]);
+
+    let node = |seq: u64, parents: Vec<NodeHash>| MerkleNode {
+        parents,
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq - 1,
+        network_timestamp: 100,
+        content: Content::Text(format!("Batch {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    };
+    let first = node(1, vec![]);
+    let second = node(2, vec![first.hash()]);
+    let (h1, h2) = (first.hash(), second.hash());
+    let chain_key = ChainKey::from([3u8; 32]);
+
+    store
+        .write_batch(vec![
+            StoreWrite::Node(sync_key, first, true),
+            StoreWrite::Node(sync_key, second, false),
+            StoreWrite::RatchetKey(sync_key, h2, chain_key.clone(), 4),
+            StoreWrite::Heads(sync_key, vec![h2], false),
+        ])
+        .unwrap();
+    assert!(store.has_children(&h1));
+    assert_eq!(store.get_rank(&h2), Some(1));
+    assert_eq!(store.get_node_counts(&sync_key), (1, 1));
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    assert!(store.is_verified(&h1));
+    assert!(!store.is_verified(&h2));
+    assert_eq!(store.get_heads(&sync_key), vec![h2]);
+    assert_eq!(
+        store.get_ratchet_key(&sync_key, &h2).unwrap(),
+        Some((chain_key, 4))
+    );
+}
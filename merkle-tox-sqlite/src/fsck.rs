@@ -0,0 +1,460 @@
+//! Integrity check and repair of a [`Storage`] database.
+//!
+//! SQLite keeps the file itself consistent; [`Storage::fsck`] checks what
+//! the schema cannot express: that stored nodes decode and hash to their
+//! key, that the indexed columns and edges agree with the node, and that
+//! blob masks and data fit the blob size.
+
+use crate::{Storage, insert_node};
+use merkle_tox_core::cas::CHUNK_SIZE;
+use merkle_tox_core::dag::{ConversationId, MerkleNode, NodeHash, NodeType};
+use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
+use rusqlite::{Connection, Result, params};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckMode {
+    /// Only report; nothing is written.
+    Check,
+    /// Repair what can be repaired.
+    Repair,
+}
+
+/// A problem found by [`Storage::fsck`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// A line of `PRAGMA integrity_check` output. Not repaired.
+    Integrity(String),
+    /// A node that cannot be decoded or does not hash to its key. Repair
+    /// moves it to the `quarantined_nodes` table.
+    BadNode {
+        conversation_id: ConversationId,
+        hash: NodeHash,
+    },
+    /// The indexed columns or the edges of a node do not match the node.
+    /// Repair writes them again.
+    StaleIndex { hash: NodeHash },
+    /// An edge to a child that is not stored. Repair removes it.
+    DanglingEdge { parent: NodeHash, child: NodeHash },
+    /// The received mask does not hold one bit per chunk.
+    BlobMask {
+        hash: NodeHash,
+        len: usize,
+        expected: usize,
+    },
+    /// The blob data does not have the size of the blob. Repair drops the
+    /// data so that the blob is downloaded again.
+    BlobSize {
+        hash: NodeHash,
+        size: u64,
+        data_len: u64,
+    },
+}
+
+impl FsckIssue {
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, FsckIssue::Integrity(_))
+    }
+}
+
+fn short(hash: &NodeHash) -> String {
+    hex::encode(&hash.as_bytes()[..4])
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsckIssue::Integrity(line) => write!(f, "integrity check: {}", line),
+            FsckIssue::BadNode {
+                conversation_id,
+                hash,
+            } => write!(
+                f,
+                "conv={}: node {} is corrupt",
+                hex::encode(&conversation_id.as_bytes()[..4]),
+                short(hash)
+            ),
+            FsckIssue::StaleIndex { hash } => {
+                write!(f, "node {}: index does not match the node", short(hash))
+            }
+            FsckIssue::DanglingEdge { parent, child } => write!(
+                f,
+                "edge {} -> {} leads to a missing node",
+                short(parent),
+                short(child)
+            ),
+            FsckIssue::BlobMask {
+                hash,
+                len,
+                expected,
+            } => write!(
+                f,
+                "blob {}: received mask has {} bytes, expected {}",
+                short(hash),
+                len,
+                expected
+            ),
+            FsckIssue::BlobSize {
+                hash,
+                size,
+                data_len,
+            } => write!(
+                f,
+                "blob {}: data has {} bytes, expected {}",
+                short(hash),
+                data_len,
+                size
+            ),
+        }
+    }
+}
+
+/// Result of a [`Storage::fsck`] run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    pub nodes: usize,
+    pub blobs: usize,
+    /// Issues in the order they were found.
+    pub issues: Vec<FsckIssue>,
+    /// Whether the repairable issues were repaired.
+    pub repaired: bool,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Issues still present in the database.
+    pub fn remaining(&self) -> Vec<&FsckIssue> {
+        self.issues
+            .iter()
+            .filter(|i| !(self.repaired && i.is_repairable()))
+            .collect()
+    }
+}
+
+fn storage_err(e: rusqlite::Error) -> MerkleToxError {
+    MerkleToxError::Storage(e.to_string())
+}
+
+fn to_hash(bytes: Vec<u8>) -> Option<NodeHash> {
+    <[u8; 32]>::try_from(bytes).ok().map(NodeHash::from)
+}
+
+struct NodeRow {
+    hash: NodeHash,
+    conversation_id: ConversationId,
+    node_type: i64,
+    author_pk: Vec<u8>,
+    sender_pk: Vec<u8>,
+    network_timestamp: i64,
+    sequence_number: i64,
+    topological_rank: i64,
+    admin_distance: i64,
+    parents: Vec<u8>,
+    verified: bool,
+    raw_data: Vec<u8>,
+}
+
+impl NodeRow {
+    /// Whether the indexed columns hold what `insert_node` writes for `node`.
+    fn matches(&self, node: &MerkleNode) -> bool {
+        let node_type = if node.node_type() == NodeType::Admin {
+            0
+        } else {
+            1
+        };
+        self.node_type == node_type
+            && self.author_pk == node.author_pk.as_bytes()
+            && self.sender_pk == node.sender_pk.as_bytes()
+            && self.network_timestamp == node.network_timestamp
+            && self.sequence_number == (node.sequence_number as i64) ^ i64::MIN
+            && self.topological_rank == (node.topological_rank as i64) ^ i64::MIN
+            && tox_proto::serialize(&node.parents).is_ok_and(|p| p == self.parents)
+    }
+}
+
+impl Storage {
+    /// Checks the database and, in [`FsckMode::Repair`], repairs it in one
+    /// transaction.
+    pub fn fsck(&self, mode: FsckMode) -> MerkleToxResult<FsckReport> {
+        let repair = mode == FsckMode::Repair;
+        let mut report = FsckReport::default();
+        let mut conn = self.conn.lock().unwrap();
+
+        let integrity: Vec<String> = conn
+            .prepare("PRAGMA integrity_check")
+            .and_then(|mut stmt| stmt.query_map([], |r| r.get(0))?.collect())
+            .map_err(storage_err)?;
+        for line in integrity.into_iter().filter(|l| l != "ok") {
+            report.issues.push(FsckIssue::Integrity(line));
+        }
+
+        let tx = conn.transaction().map_err(storage_err)?;
+        check_nodes(&tx, repair, &mut report)?;
+        check_edges(&tx, repair, &mut report)?;
+        self.check_blobs(&tx, repair, &mut report)?;
+        if repair {
+            tx.commit().map_err(storage_err)?;
+        }
+        report.repaired = repair;
+        Ok(report)
+    }
+
+    fn check_blobs(
+        &self,
+        conn: &Connection,
+        repair: bool,
+        report: &mut FsckReport,
+    ) -> MerkleToxResult<()> {
+        type BlobRow = (
+            Vec<u8>,
+            String,
+            i64,
+            Option<Vec<u8>>,
+            Option<i64>,
+            Option<String>,
+        );
+        let rows: Vec<BlobRow> = conn
+            .prepare(
+                "SELECT hash, status, total_size, received_chunks, LENGTH(data), file_path
+                 FROM cas_blobs ORDER BY hash",
+            )
+            .and_then(|mut stmt| {
+                stmt.query_map([], |r| {
+                    Ok((
+                        r.get(0)?,
+                        r.get(1)?,
+                        r.get(2)?,
+                        r.get(3)?,
+                        r.get(4)?,
+                        r.get(5)?,
+                    ))
+                })?
+                .collect::<Result<_>>()
+            })
+            .map_err(storage_err)?;
+
+        for (hash_bytes, status, size, mask, inline_len, file_path) in rows {
+            let Some(hash) = to_hash(hash_bytes) else {
+                continue;
+            };
+            report.blobs += 1;
+            let size = size as u64;
+            let num_chunks = size.div_ceil(CHUNK_SIZE);
+            let expected = (num_chunks as usize).div_ceil(8);
+
+            if let Some(mut mask) = mask
+                && mask.len() != expected
+            {
+                report.issues.push(FsckIssue::BlobMask {
+                    hash,
+                    len: mask.len(),
+                    expected,
+                });
+                if repair {
+                    mask.resize(expected, 0);
+                    for chunk in num_chunks..expected as u64 * 8 {
+                        mask[(chunk / 8) as usize] &= !(1 << (chunk % 8));
+                    }
+                    conn.execute(
+                        "UPDATE cas_blobs SET received_chunks = ?1 WHERE hash = ?2",
+                        params![mask, hash.as_bytes()],
+                    )
+                    .map_err(storage_err)?;
+                }
+            }
+
+            let data_len = match &file_path {
+                Some(path) => Some(self.vfs.metadata(Path::new(path)).map_or(0, |m| m.len)),
+                None => inline_len.map(|l| l as u64),
+            };
+            let mismatch = data_len.map_or(status == "Available", |len| len != size);
+            if !mismatch {
+                continue;
+            }
+            report.issues.push(FsckIssue::BlobSize {
+                hash,
+                size,
+                data_len: data_len.unwrap_or(0),
+            });
+            if repair {
+                conn.execute(
+                    "UPDATE cas_blobs SET data = NULL, received_chunks = NULL, status = 'Pending',
+                     bao_root = NULL WHERE hash = ?1",
+                    params![hash.as_bytes()],
+                )
+                .map_err(storage_err)?;
+                if let Some(path) = &file_path {
+                    let _ = self.vfs.remove_file(Path::new(path));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_nodes(conn: &Connection, repair: bool, report: &mut FsckReport) -> MerkleToxResult<()> {
+    let rows: Vec<NodeRow> = conn
+        .prepare(
+            "SELECT hash, conversation_id, node_type, author_pk, sender_pk, network_timestamp,
+                    sequence_number, topological_rank, admin_distance, parents,
+                    verification_status, raw_data
+             FROM nodes ORDER BY hash",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |r| {
+                let hash: Vec<u8> = r.get(0)?;
+                let conversation_id: Vec<u8> = r.get(1)?;
+                Ok((
+                    hash,
+                    conversation_id,
+                    r.get(2)?,
+                    r.get(3)?,
+                    r.get(4)?,
+                    r.get(5)?,
+                    r.get(6)?,
+                    r.get(7)?,
+                    r.get(8)?,
+                    r.get(9)?,
+                    r.get::<_, i64>(10)?,
+                    r.get(11)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>>>()
+        })
+        .map_err(storage_err)?
+        .into_iter()
+        .filter_map(|r| {
+            let conversation_id: [u8; 32] = r.1.try_into().ok()?;
+            Some(NodeRow {
+                hash: to_hash(r.0)?,
+                conversation_id: ConversationId::from(conversation_id),
+                node_type: r.2,
+                author_pk: r.3,
+                sender_pk: r.4,
+                network_timestamp: r.5,
+                sequence_number: r.6,
+                topological_rank: r.7,
+                admin_distance: r.8,
+                parents: r.9,
+                verified: r.10 == 1,
+                raw_data: r.11,
+            })
+        })
+        .collect();
+
+    for row in rows {
+        report.nodes += 1;
+        // Pruned nodes keep only their indexed columns.
+        if row.raw_data.is_empty() {
+            continue;
+        }
+        let node = match tox_proto::deserialize::<MerkleNode>(&row.raw_data) {
+            Ok(node) if node.hash() == row.hash => node,
+            decoded => {
+                report.issues.push(FsckIssue::BadNode {
+                    conversation_id: row.conversation_id,
+                    hash: row.hash,
+                });
+                if repair {
+                    let reason = if decoded.is_ok() {
+                        "hash mismatch"
+                    } else {
+                        "undecodable"
+                    };
+                    quarantine_node(conn, &row, reason)?;
+                }
+                continue;
+            }
+        };
+
+        let edges: BTreeSet<Vec<u8>> = conn
+            .prepare_cached("SELECT parent_hash FROM edges WHERE child_hash = ?1")
+            .and_then(|mut stmt| {
+                stmt.query_map(params![row.hash.as_bytes()], |r| r.get(0))?
+                    .collect()
+            })
+            .map_err(storage_err)?;
+        let parents: BTreeSet<Vec<u8>> =
+            node.parents.iter().map(|p| p.as_bytes().to_vec()).collect();
+        if row.matches(&node) && edges == parents {
+            continue;
+        }
+        report.issues.push(FsckIssue::StaleIndex { hash: row.hash });
+        if repair {
+            conn.execute(
+                "DELETE FROM edges WHERE child_hash = ?1",
+                params![row.hash.as_bytes()],
+            )
+            .map_err(storage_err)?;
+            insert_node(
+                conn,
+                &row.conversation_id,
+                &node,
+                row.verified,
+                row.admin_distance as u64,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn quarantine_node(conn: &Connection, row: &NodeRow, reason: &str) -> MerkleToxResult<()> {
+    conn.execute(
+        "INSERT INTO quarantined_nodes (hash, conversation_id, raw_data, reason)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![
+            row.hash.as_bytes(),
+            row.conversation_id.as_bytes(),
+            row.raw_data,
+            reason
+        ],
+    )
+    .map_err(storage_err)?;
+    conn.execute(
+        "DELETE FROM edges WHERE child_hash = ?1",
+        params![row.hash.as_bytes()],
+    )
+    .map_err(storage_err)?;
+    conn.execute(
+        "DELETE FROM nodes WHERE hash = ?1",
+        params![row.hash.as_bytes()],
+    )
+    .map_err(storage_err)?;
+    Ok(())
+}
+
+fn check_edges(conn: &Connection, repair: bool, report: &mut FsckReport) -> MerkleToxResult<()> {
+    let dangling: Vec<(Vec<u8>, Vec<u8>)> = conn
+        .prepare(
+            "SELECT parent_hash, child_hash FROM edges
+             WHERE child_hash NOT IN (SELECT hash FROM nodes)
+             ORDER BY child_hash, parent_hash",
+        )
+        .and_then(|mut stmt| {
+            stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?
+                .collect()
+        })
+        .map_err(storage_err)?;
+    for (parent_bytes, child_bytes) in dangling {
+        if let (Some(parent), Some(child)) =
+            (to_hash(parent_bytes.clone()), to_hash(child_bytes.clone()))
+        {
+            report
+                .issues
+                .push(FsckIssue::DanglingEdge { parent, child });
+        }
+        if repair {
+            conn.execute(
+                "DELETE FROM edges WHERE parent_hash = ?1 AND child_hash = ?2",
+                params![parent_bytes, child_bytes],
+            )
+            .map_err(storage_err)?;
+        }
+    }
+    Ok(())
+}
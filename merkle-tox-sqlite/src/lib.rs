@@ -1,29 +1,90 @@
+pub mod fsck;
 pub mod schema;
 
 use merkle_tox_core::cas::{BlobInfo, BlobStatus};
 use merkle_tox_core::dag::{
-    ChainKey, ConversationId, KConv, MerkleNode, NodeHash, NodeLookup, NodeType, PhysicalDevicePk,
+    ChainKey, ConversationId, KConv, LogicalIdentityPk, MerkleNode, NodeHash, NodeLookup, NodeType,
+    PhysicalDevicePk,
 };
+use merkle_tox_core::engine::archive::ArchivedConversation;
+use merkle_tox_core::engine::outbox::AckedHeads;
+use merkle_tox_core::engine::quarantine::BlacklistEntry;
+use merkle_tox_core::engine::scheduled::ScheduledMessage;
 use merkle_tox_core::error::{MerkleToxError, MerkleToxResult};
-use merkle_tox_core::sync::{BlobStore, GlobalStore, NodeStore, ReconciliationStore, SyncRange};
+use merkle_tox_core::invite::{InviteGrant, InviteId};
+use merkle_tox_core::suspend::SuspendedState;
+use merkle_tox_core::sync::{
+    BlobStore, GlobalStore, NodeStore, ReconciliationStore, StateSnapshot, StoreWrite, SyncRange,
+};
 use merkle_tox_core::vfs::{FileSystem, StdFileSystem};
-use rusqlite::{Connection, OptionalExtension, Result, params};
+use rusqlite::{Connection, OpenFlags, OptionalExtension, Result, params};
+use std::collections::HashMap;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Number of read-only connections opened by [`Storage::open`].
+pub const DEFAULT_READERS: usize = 4;
+
+/// How long a connection waits for a lock held by another connection.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Storage {
+    /// Writer connection. Also serves reads when there is no read pool.
     conn: Mutex<Connection>,
+    readers: Vec<Mutex<Connection>>,
+    next_reader: AtomicUsize,
     blob_dir: Option<PathBuf>,
     vfs: Arc<dyn FileSystem>,
 }
 
+/// Whether each connection to `path` opens a separate, temporary database.
+fn is_private_database(path: &Path) -> bool {
+    path.as_os_str().is_empty() || path == Path::new(":memory:")
+}
+
 impl Storage {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_with_readers(path, DEFAULT_READERS)
+    }
+
+    /// Opens the database in WAL mode with a pool of `readers` read-only
+    /// connections, so reads proceed concurrently with the (serialized)
+    /// writer. With `readers == 0` all queries share the writer connection.
+    ///
+    /// Every connection to `":memory:"` or `""` opens a database of its own,
+    /// so such paths get no read pool.
+    pub fn open_with_readers<P: AsRef<Path>>(path: P, readers: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let readers = if is_private_database(path) {
+            0
+        } else {
+            readers
+        };
         let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        conn.execute_batch("PRAGMA synchronous = NORMAL;")?;
         conn.execute_batch(schema::CREATE_TABLES)?;
+
+        let mut pool = Vec::with_capacity(readers);
+        for _ in 0..readers {
+            let reader = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | OpenFlags::SQLITE_OPEN_NO_MUTEX
+                    | OpenFlags::SQLITE_OPEN_URI,
+            )?;
+            reader.busy_timeout(BUSY_TIMEOUT)?;
+            pool.push(Mutex::new(reader));
+        }
+
         Ok(Self {
             conn: Mutex::new(conn),
+            readers: pool,
+            next_reader: AtomicUsize::new(0),
             blob_dir: None,
             vfs: Arc::new(StdFileSystem),
         })
@@ -48,6 +109,8 @@ impl Storage {
         conn.execute_batch(schema::CREATE_TABLES)?;
         Ok(Self {
             conn: Mutex::new(conn),
+            readers: Vec::new(),
+            next_reader: AtomicUsize::new(0),
             blob_dir: None,
             vfs: Arc::new(StdFileSystem),
         })
@@ -56,15 +119,109 @@ impl Storage {
     pub fn from_connection(conn: Connection) -> Self {
         Self {
             conn: Mutex::new(conn),
+            readers: Vec::new(),
+            next_reader: AtomicUsize::new(0),
             blob_dir: None,
             vfs: Arc::new(StdFileSystem),
         }
     }
 
+    /// Removes a conversation and everything stored for it, then deletes the
+    /// blobs that no other conversation references. Returns the hashes of the
+    /// deleted blobs.
+    pub fn delete_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> MerkleToxResult<Vec<NodeHash>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        let cid = conversation_id.as_bytes();
+
+        // Blobs only this conversation references.
+        let orphans: Vec<(Vec<u8>, Option<String>)> = {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT c.hash, c.file_path FROM cas_blobs c
+                     JOIN blob_refs r ON r.hash = c.hash AND r.conversation_id = ?1
+                     WHERE NOT EXISTS (
+                         SELECT 1 FROM blob_refs o WHERE o.hash = c.hash AND o.conversation_id != ?1
+                     )",
+                )
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+            stmt.query_map(params![cid], |r| Ok((r.get(0)?, r.get(1)?)))
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?
+                .collect::<Result<_>>()
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?
+        };
+
+        for (hash, _) in &orphans {
+            tx.execute("DELETE FROM cas_blobs WHERE hash = ?1", params![hash])
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        }
+        tx.execute(
+            "DELETE FROM edges WHERE child_hash IN (SELECT hash FROM nodes WHERE conversation_id = ?1)",
+            params![cid],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        for table in [
+            "nodes",
+            "conversation_meta",
+            "conversation_keys",
+            "blob_refs",
+            "reconciliation_sketches",
+            "ratchet_keys",
+            "opaque_nodes",
+        ] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE conversation_id = ?1", table),
+                params![cid],
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        }
+        tx.commit()
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        drop(conn);
+
+        let mut deleted = Vec::with_capacity(orphans.len());
+        for (hash, file_path) in orphans {
+            if let Some(path) = file_path {
+                let _ = self.vfs.remove_file(Path::new(&path));
+            }
+            if let Ok(bytes) = <[u8; 32]>::try_from(hash) {
+                deleted.push(NodeHash::from(bytes));
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Returns the writer connection.
     pub fn connection(&self) -> &Mutex<Connection> {
         &self.conn
     }
 
+    /// Number of read-only connections in the pool.
+    pub fn reader_count(&self) -> usize {
+        self.readers.len()
+    }
+
+    /// Returns an idle read connection, falling back to the writer if there
+    /// is no read pool.
+    fn reader(&self) -> MutexGuard<'_, Connection> {
+        if self.readers.is_empty() {
+            return self.conn.lock().unwrap();
+        }
+        let start = self.next_reader.fetch_add(1, Ordering::Relaxed) % self.readers.len();
+        for i in 0..self.readers.len() {
+            let idx = (start + i) % self.readers.len();
+            if let Ok(conn) = self.readers[idx].try_lock() {
+                return conn;
+            }
+        }
+        self.readers[start].lock().unwrap()
+    }
+
     fn is_anchor(&self, data: &[u8]) -> bool {
         if let Ok(wire) = tox_proto::deserialize::<merkle_tox_core::dag::WireNode>(data) {
             if matches!(
@@ -155,7 +312,7 @@ impl Storage {
 
 impl NodeLookup for Storage {
     fn get_node_type(&self, hash: &NodeHash) -> Option<NodeType> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT node_type FROM nodes WHERE hash = ?1")
             .ok()?;
@@ -173,7 +330,7 @@ impl NodeLookup for Storage {
     }
 
     fn get_rank(&self, hash: &NodeHash) -> Option<u64> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT topological_rank FROM nodes WHERE hash = ?1")
             .ok()?;
@@ -185,19 +342,11 @@ impl NodeLookup for Storage {
     }
 
     fn get_admin_distance(&self, hash: &NodeHash) -> Option<u64> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn
-            .prepare_cached("SELECT admin_distance FROM nodes WHERE hash = ?1")
-            .ok()?;
-        let res: i64 = stmt
-            .query_row(params![hash.as_bytes()], |r| r.get(0))
-            .optional()
-            .ok()??;
-        Some(res as u64)
+        query_admin_distance(&self.reader(), hash)
     }
 
     fn contains_node(&self, hash: &NodeHash) -> bool {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT 1 FROM nodes WHERE hash = ?1")
             .ok()
@@ -206,7 +355,7 @@ impl NodeLookup for Storage {
     }
 
     fn has_children(&self, hash: &NodeHash) -> bool {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT 1 FROM edges WHERE parent_hash = ?1")
             .ok()
@@ -229,7 +378,7 @@ impl NodeLookup for Storage {
 
 impl NodeStore for Storage {
     fn get_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT heads FROM conversation_meta WHERE conversation_id = ?1")
             .ok()
@@ -248,19 +397,11 @@ impl NodeStore for Storage {
         conversation_id: &ConversationId,
         heads: Vec<NodeHash>,
     ) -> MerkleToxResult<()> {
-        let conn = self.conn.lock().unwrap();
-        let heads_data = tox_proto::serialize(&heads).map_err(MerkleToxError::Protocol)?;
-        conn.execute(
-            "INSERT INTO conversation_meta (conversation_id, heads) VALUES (?1, ?2)
-             ON CONFLICT(conversation_id) DO UPDATE SET heads = ?2",
-            params![conversation_id.as_bytes(), heads_data],
-        )
-        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
-        Ok(())
+        write_heads(&self.conn.lock().unwrap(), conversation_id, heads, false)
     }
 
     fn get_admin_heads(&self, conversation_id: &ConversationId) -> Vec<NodeHash> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT admin_heads FROM conversation_meta WHERE conversation_id = ?1")
             .ok()
@@ -279,15 +420,7 @@ impl NodeStore for Storage {
         conversation_id: &ConversationId,
         heads: Vec<NodeHash>,
     ) -> MerkleToxResult<()> {
-        let conn = self.conn.lock().unwrap();
-        let heads_data = tox_proto::serialize(&heads).map_err(MerkleToxError::Protocol)?;
-        conn.execute(
-            "INSERT INTO conversation_meta (conversation_id, admin_heads) VALUES (?1, ?2)
-             ON CONFLICT(conversation_id) DO UPDATE SET admin_heads = ?2",
-            params![conversation_id.as_bytes(), heads_data],
-        )
-        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
-        Ok(())
+        write_heads(&self.conn.lock().unwrap(), conversation_id, heads, true)
     }
 
     fn has_node(&self, hash: &NodeHash) -> bool {
@@ -295,7 +428,7 @@ impl NodeStore for Storage {
     }
 
     fn is_verified(&self, hash: &NodeHash) -> bool {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT verification_status FROM nodes WHERE hash = ?1")
             .ok()
@@ -308,23 +441,16 @@ impl NodeStore for Storage {
     }
 
     fn get_node(&self, hash: &NodeHash) -> Option<MerkleNode> {
-        let conn = self.conn.lock().unwrap();
-        let mut stmt = conn
-            .prepare_cached("SELECT raw_data FROM nodes WHERE hash = ?1")
-            .ok()?;
-        let raw_data: Vec<u8> = stmt
-            .query_row(params![hash.as_bytes()], |r| r.get(0))
-            .optional()
-            .ok()??;
-        let node: MerkleNode = tox_proto::deserialize(&raw_data).ok()?;
-        if node.hash() != *hash {
-            return None;
-        }
-        Some(node)
+        query_node(&self.reader(), hash)
+    }
+
+    fn get_nodes(&self, hashes: &[NodeHash]) -> Vec<Option<MerkleNode>> {
+        let conn = self.reader();
+        hashes.iter().map(|hash| query_node(&conn, hash)).collect()
     }
 
     fn get_wire_node(&self, hash: &NodeHash) -> Option<merkle_tox_core::dag::WireNode> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT raw_data FROM opaque_nodes WHERE hash = ?1")
             .ok()?;
@@ -341,68 +467,30 @@ impl NodeStore for Storage {
         node: MerkleNode,
         verified: bool,
     ) -> MerkleToxResult<()> {
-        let hash = node.hash();
-        let node_type = if node.node_type() == NodeType::Admin {
-            0
-        } else {
-            1
-        };
-
-        let mut admin_distance = 0;
-        if node_type == 1 {
-            // Content Node: Calculate distance from parents
-            let mut min_dist = u64::MAX;
-            for parent_hash in &node.parents {
-                if let Some(dist) = self.get_admin_distance(parent_hash) {
-                    min_dist = min_dist.min(dist);
-                }
-            }
-            if min_dist != u64::MAX {
-                admin_distance = min_dist + 1;
-            } else {
-                admin_distance = u64::MAX;
-            }
-        }
-
+        let admin_distance = admin_distance(&node, |h| self.get_admin_distance(h));
         let mut conn = self.conn.lock().unwrap();
-        let raw_data = tox_proto::serialize(&node).map_err(MerkleToxError::Protocol)?;
-        let parents_data = tox_proto::serialize(&node.parents).map_err(MerkleToxError::Protocol)?;
-        let status = if verified { 1 } else { 0 };
-
         let tx = conn
             .transaction()
             .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        insert_node(&tx, conversation_id, &node, verified, admin_distance)?;
+        tx.commit()
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
 
-        tx.execute(
-            "INSERT OR REPLACE INTO nodes (
-                hash, conversation_id, node_type, author_pk, sender_pk, network_timestamp,
-                sequence_number, topological_rank, admin_distance, parents, verification_status, raw_data
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
-            params![
-                hash.as_bytes(),
-                conversation_id.as_bytes(),
-                node_type,
-                node.author_pk.as_bytes(),
-                node.sender_pk.as_bytes(),
-                node.network_timestamp,
-                (node.sequence_number as i64) ^ i64::MIN,
-                (node.topological_rank as i64) ^ i64::MIN,
-                admin_distance as i64,
-                parents_data,
-                status,
-                raw_data,
-            ],
-        )
-        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
-
-        for parent_hash in &node.parents {
-            tx.execute(
-                "INSERT OR IGNORE INTO edges (parent_hash, child_hash) VALUES (?1, ?2)",
-                params![parent_hash.as_bytes(), hash.as_bytes()],
-            )
+    fn put_nodes(
+        &self,
+        conversation_id: &ConversationId,
+        nodes: Vec<(MerkleNode, bool)>,
+    ) -> MerkleToxResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
             .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        let mut distances = HashMap::new();
+        for (node, verified) in nodes {
+            insert_node_in_batch(&tx, &mut distances, conversation_id, &node, verified)?;
         }
-
         tx.commit()
             .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
         Ok(())
@@ -414,15 +502,7 @@ impl NodeStore for Storage {
         hash: &NodeHash,
         node: merkle_tox_core::dag::WireNode,
     ) -> MerkleToxResult<()> {
-        {
-            let conn = self.conn.lock().unwrap();
-            let raw_data = tox_proto::serialize(&node).map_err(MerkleToxError::Protocol)?;
-            conn.execute(
-                "INSERT OR REPLACE INTO opaque_nodes (hash, conversation_id, raw_data) VALUES (?1, ?2, ?3)",
-                params![hash.as_bytes(), conversation_id.as_bytes(), raw_data],
-            )
-            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
-        }
+        insert_wire_node(&self.conn.lock().unwrap(), conversation_id, hash, &node)?;
         self.check_opaque_eviction(conversation_id)?;
         Ok(())
     }
@@ -442,10 +522,10 @@ impl NodeStore for Storage {
     }
 
     fn get_speculative_nodes(&self, conversation_id: &ConversationId) -> Vec<MerkleNode> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
-                "SELECT raw_data FROM nodes WHERE conversation_id = ?1 AND verification_status = 0",
+                "SELECT raw_data FROM nodes WHERE conversation_id = ?1 AND verification_status = 0 AND LENGTH(raw_data) > 0",
             )
             .ok()
             .unwrap();
@@ -479,7 +559,7 @@ impl NodeStore for Storage {
         conversation_id: &ConversationId,
         sender_pk: &PhysicalDevicePk,
     ) -> u64 {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
                 "SELECT sequence_number FROM nodes 
@@ -501,7 +581,7 @@ impl NodeStore for Storage {
     }
 
     fn get_node_counts(&self, conversation_id: &ConversationId) -> (usize, usize) {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let ver: i64 = conn
             .query_row(
                 "SELECT count(*) FROM nodes WHERE conversation_id = ?1 AND verification_status = 1",
@@ -525,11 +605,12 @@ impl NodeStore for Storage {
         node_type: NodeType,
     ) -> MerkleToxResult<Vec<MerkleNode>> {
         let type_int = if node_type == NodeType::Admin { 0 } else { 1 };
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
                 "SELECT raw_data FROM nodes 
                  WHERE conversation_id = ?1 AND node_type = ?2 AND verification_status = 1
+                 AND LENGTH(raw_data) > 0
                  ORDER BY topological_rank ASC, hash ASC",
             )
             .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
@@ -554,7 +635,7 @@ impl NodeStore for Storage {
         conversation_id: &ConversationId,
         range: &SyncRange,
     ) -> MerkleToxResult<Vec<NodeHash>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
                 "SELECT hash FROM nodes 
@@ -586,11 +667,114 @@ impl NodeStore for Storage {
         Ok(hashes)
     }
 
+    fn get_nodes_by_author(
+        &self,
+        conversation_id: &ConversationId,
+        author_pk: &LogicalIdentityPk,
+        range: &SyncRange,
+    ) -> MerkleToxResult<Vec<MerkleNode>> {
+        let conn = self.reader();
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT raw_data FROM nodes
+                 WHERE conversation_id = ?1 AND author_pk = ?2
+                 AND topological_rank BETWEEN ?3 AND ?4
+                 AND verification_status = 1 AND LENGTH(raw_data) > 0
+                 ORDER BY topological_rank ASC, hash ASC",
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+
+        let rows = stmt
+            .query_map(
+                params![
+                    conversation_id.as_bytes(),
+                    author_pk.as_bytes(),
+                    (range.min_rank as i64) ^ i64::MIN,
+                    (range.max_rank as i64) ^ i64::MIN
+                ],
+                |r| r.get::<_, Vec<u8>>(0),
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+
+        let mut nodes = Vec::new();
+        for row in rows {
+            let data = row.map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+            nodes.push(tox_proto::deserialize(&data)?);
+        }
+        Ok(nodes)
+    }
+
+    fn get_nodes_by_time_range(
+        &self,
+        conversation_id: &ConversationId,
+        from_ms: i64,
+        to_ms: i64,
+        limit: usize,
+    ) -> MerkleToxResult<Vec<MerkleNode>> {
+        let conn = self.reader();
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT raw_data FROM nodes
+                 WHERE conversation_id = ?1
+                 AND network_timestamp >= ?2 AND network_timestamp < ?3
+                 AND verification_status = 1 AND LENGTH(raw_data) > 0
+                 ORDER BY network_timestamp ASC, hash ASC
+                 LIMIT ?4",
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+
+        let rows = stmt
+            .query_map(
+                params![
+                    conversation_id.as_bytes(),
+                    from_ms,
+                    to_ms,
+                    limit.min(i64::MAX as usize) as i64
+                ],
+                |r| r.get::<_, Vec<u8>>(0),
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+
+        let mut nodes = Vec::new();
+        for row in rows {
+            let data = row.map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+            nodes.push(tox_proto::deserialize(&data)?);
+        }
+        Ok(nodes)
+    }
+
+    fn prune_node(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<bool> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn
+            .transaction()
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        // An empty raw_data marks a pruned node; the indexed columns stay.
+        let pruned = tx
+            .execute(
+                "UPDATE nodes SET raw_data = X''
+                 WHERE hash = ?1 AND conversation_id = ?2 AND LENGTH(raw_data) > 0",
+                params![hash.as_bytes(), conversation_id.as_bytes()],
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        tx.execute(
+            "DELETE FROM opaque_nodes WHERE hash = ?1",
+            params![hash.as_bytes()],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        tx.commit()
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(pruned > 0)
+    }
+
     fn get_opaque_node_hashes(
         &self,
         conversation_id: &ConversationId,
     ) -> MerkleToxResult<Vec<NodeHash>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT hash FROM opaque_nodes WHERE conversation_id = ?1")
             .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
@@ -613,7 +797,7 @@ impl NodeStore for Storage {
     }
 
     fn size_bytes(&self) -> u64 {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let page_count: i64 = conn
             .query_row("PRAGMA page_count", [], |r| r.get(0))
             .unwrap_or(0);
@@ -647,7 +831,7 @@ impl NodeStore for Storage {
         &self,
         conversation_id: &ConversationId,
     ) -> MerkleToxResult<Vec<(u64, KConv)>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
                 "SELECT epoch, k_conv FROM conversation_keys WHERE conversation_id = ?1",
@@ -697,7 +881,7 @@ impl NodeStore for Storage {
         &self,
         conversation_id: &ConversationId,
     ) -> MerkleToxResult<Option<(u32, i64)>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT message_count, last_rotation_time FROM conversation_meta WHERE conversation_id = ?1")
             .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
@@ -718,18 +902,13 @@ impl NodeStore for Storage {
         chain_key: ChainKey,
         epoch_id: u64,
     ) -> MerkleToxResult<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT OR REPLACE INTO ratchet_keys (conversation_id, node_hash, chain_key, epoch_id) VALUES (?1, ?2, ?3, ?4)",
-            params![
-                conversation_id.as_bytes(),
-                node_hash.as_bytes(),
-                chain_key.as_bytes().to_vec(),
-                (epoch_id as i64) ^ i64::MIN
-            ],
+        insert_ratchet_key(
+            &self.conn.lock().unwrap(),
+            conversation_id,
+            node_hash,
+            &chain_key,
+            epoch_id,
         )
-        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
-        Ok(())
     }
 
     fn get_ratchet_key(
@@ -737,7 +916,7 @@ impl NodeStore for Storage {
         conversation_id: &ConversationId,
         node_hash: &NodeHash,
     ) -> MerkleToxResult<Option<(ChainKey, u64)>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
                 "SELECT chain_key, epoch_id FROM ratchet_keys WHERE conversation_id = ?1 AND node_hash = ?2",
@@ -768,19 +947,301 @@ impl NodeStore for Storage {
         conversation_id: &ConversationId,
         node_hash: &NodeHash,
     ) -> MerkleToxResult<()> {
+        delete_ratchet_key(&self.conn.lock().unwrap(), conversation_id, node_hash)
+    }
+
+    fn put_scheduled_message(&self, msg: &ScheduledMessage) -> MerkleToxResult<()> {
+        let raw_data = tox_proto::serialize(msg).map_err(MerkleToxError::Protocol)?;
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "DELETE FROM ratchet_keys WHERE conversation_id = ?1 AND node_hash = ?2",
-            params![conversation_id.as_bytes(), node_hash.as_bytes()],
+            "INSERT OR REPLACE INTO scheduled_messages (id, conversation_id, deliver_at, raw_data) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                msg.id as i64,
+                msg.conversation_id.as_bytes(),
+                msg.deliver_at_ms,
+                raw_data
+            ],
         )
         .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
         Ok(())
     }
+
+    fn remove_scheduled_message(&self, id: u64) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM scheduled_messages WHERE id = ?1",
+            params![id as i64],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        let conn = self.reader();
+        let Ok(mut stmt) =
+            conn.prepare_cached("SELECT raw_data FROM scheduled_messages ORDER BY deliver_at")
+        else {
+            return Vec::new();
+        };
+        stmt.query_map([], |r| r.get::<_, Vec<u8>>(0))
+            .map(|rows| {
+                rows.filter_map(|r| r.ok())
+                    .filter_map(|data| tox_proto::deserialize(&data).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn put_acked_heads(&self, acked: &AckedHeads) -> MerkleToxResult<()> {
+        let raw_data = tox_proto::serialize(acked).map_err(MerkleToxError::Protocol)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO acked_heads (conversation_id, device_pk, raw_data) VALUES (?1, ?2, ?3)",
+            params![
+                acked.conversation_id.as_bytes(),
+                acked.device_pk.as_bytes(),
+                raw_data
+            ],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_acked_heads(&self) -> Vec<AckedHeads> {
+        let conn = self.reader();
+        let Ok(mut stmt) = conn.prepare_cached("SELECT raw_data FROM acked_heads") else {
+            return Vec::new();
+        };
+        stmt.query_map([], |r| r.get::<_, Vec<u8>>(0))
+            .map(|rows| {
+                rows.filter_map(|r| r.ok())
+                    .filter_map(|data| tox_proto::deserialize(&data).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn put_blacklist_entry(&self, entry: &BlacklistEntry) -> MerkleToxResult<()> {
+        let raw_data = tox_proto::serialize(entry).map_err(MerkleToxError::Protocol)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO blacklist (device_pk, raw_data) VALUES (?1, ?2)",
+            params![entry.device_pk.as_bytes(), raw_data],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn remove_blacklist_entry(&self, device_pk: &PhysicalDevicePk) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM blacklist WHERE device_pk = ?1",
+            params![device_pk.as_bytes()],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_blacklist(&self) -> Vec<BlacklistEntry> {
+        let conn = self.reader();
+        let Ok(mut stmt) = conn.prepare_cached("SELECT raw_data FROM blacklist") else {
+            return Vec::new();
+        };
+        stmt.query_map([], |r| r.get::<_, Vec<u8>>(0))
+            .map(|rows| {
+                rows.filter_map(|r| r.ok())
+                    .filter_map(|data| tox_proto::deserialize(&data).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn put_invite_grant(&self, grant: &InviteGrant) -> MerkleToxResult<()> {
+        let raw_data = tox_proto::serialize(grant).map_err(MerkleToxError::Protocol)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO invite_grants (invite_id, raw_data) VALUES (?1, ?2)",
+            params![grant.id(), raw_data],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn remove_invite_grant(&self, id: &InviteId) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM invite_grants WHERE invite_id = ?1",
+            params![id],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_invite_grants(&self) -> Vec<InviteGrant> {
+        let conn = self.reader();
+        let Ok(mut stmt) = conn.prepare_cached("SELECT raw_data FROM invite_grants") else {
+            return Vec::new();
+        };
+        stmt.query_map([], |r| r.get::<_, Vec<u8>>(0))
+            .map(|rows| {
+                rows.filter_map(|r| r.ok())
+                    .filter_map(|data| tox_proto::deserialize(&data).ok())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn put_suspended_state(&self, state: &SuspendedState) -> MerkleToxResult<()> {
+        let data = tox_proto::serialize(state).map_err(MerkleToxError::Protocol)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO global_state (key, value) VALUES ('suspended_state', ?1)
+             ON CONFLICT(key) DO UPDATE SET value = ?1",
+            params![data],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_suspended_state(&self) -> Option<SuspendedState> {
+        let conn = self.reader();
+        let mut stmt = conn
+            .prepare_cached("SELECT value FROM global_state WHERE key = 'suspended_state'")
+            .ok()?;
+        let res: Vec<u8> = stmt.query_row([], |r| r.get(0)).optional().ok()??;
+        tox_proto::deserialize(&res).ok()
+    }
+
+    fn remove_suspended_state(&self) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM global_state WHERE key = 'suspended_state'", [])
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn set_history_horizon(
+        &self,
+        conversation_id: &ConversationId,
+        min_rank: Option<u64>,
+    ) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        match min_rank {
+            Some(rank) => conn.execute(
+                "INSERT OR REPLACE INTO history_horizons (conversation_id, min_rank) VALUES (?1, ?2)",
+                params![conversation_id.as_bytes(), rank as i64],
+            ),
+            None => conn.execute(
+                "DELETE FROM history_horizons WHERE conversation_id = ?1",
+                params![conversation_id.as_bytes()],
+            ),
+        }
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_history_horizon(&self, conversation_id: &ConversationId) -> Option<u64> {
+        let conn = self.reader();
+        conn.query_row(
+            "SELECT min_rank FROM history_horizons WHERE conversation_id = ?1",
+            params![conversation_id.as_bytes()],
+            |r| r.get::<_, i64>(0),
+        )
+        .ok()
+        .map(|rank| rank as u64)
+    }
+
+    fn put_archived_conversation(&self, archive: &ArchivedConversation) -> MerkleToxResult<()> {
+        let raw_data = tox_proto::serialize(archive).map_err(MerkleToxError::Protocol)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO archived_conversations (conversation_id, raw_data) VALUES (?1, ?2)",
+            params![archive.conversation_id.as_bytes(), raw_data],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_archived_conversation(
+        &self,
+        conversation_id: &ConversationId,
+    ) -> Option<ArchivedConversation> {
+        let conn = self.reader();
+        let data: Vec<u8> = conn
+            .query_row(
+                "SELECT raw_data FROM archived_conversations WHERE conversation_id = ?1",
+                params![conversation_id.as_bytes()],
+                |r| r.get(0),
+            )
+            .ok()?;
+        tox_proto::deserialize(&data).ok()
+    }
+
+    fn put_state_snapshot(&self, snapshot: &StateSnapshot) -> MerkleToxResult<()> {
+        let raw_data = tox_proto::serialize(snapshot).map_err(MerkleToxError::Protocol)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO state_snapshots (conversation_id, raw_data) VALUES (?1, ?2)",
+            params![snapshot.conversation_id.as_bytes(), raw_data],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn get_state_snapshot(&self, conversation_id: &ConversationId) -> Option<StateSnapshot> {
+        let conn = self.reader();
+        let data: Vec<u8> = conn
+            .query_row(
+                "SELECT raw_data FROM state_snapshots WHERE conversation_id = ?1",
+                params![conversation_id.as_bytes()],
+                |r| r.get(0),
+            )
+            .ok()?;
+        tox_proto::deserialize(&data).ok()
+    }
+
+    fn write_batch(&self, writes: Vec<StoreWrite>) -> MerkleToxResult<()> {
+        let mut evict = Vec::new();
+        {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn
+                .transaction()
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+            let mut distances = HashMap::new();
+            for write in writes {
+                match write {
+                    StoreWrite::Node(cid, node, verified) => {
+                        insert_node_in_batch(&tx, &mut distances, &cid, &node, verified)?;
+                    }
+                    StoreWrite::WireNode(cid, hash, node) => {
+                        insert_wire_node(&tx, &cid, &hash, &node)?;
+                        if !evict.contains(&cid) {
+                            evict.push(cid);
+                        }
+                    }
+                    StoreWrite::RatchetKey(cid, hash, key, epoch_id) => {
+                        insert_ratchet_key(&tx, &cid, &hash, &key, epoch_id)?;
+                    }
+                    StoreWrite::DeleteRatchetKey(cid, hash) => {
+                        delete_ratchet_key(&tx, &cid, &hash)?;
+                    }
+                    StoreWrite::Heads(cid, heads, is_admin) => {
+                        write_heads(&tx, &cid, heads, is_admin)?;
+                    }
+                }
+            }
+            tx.commit()
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        }
+        for cid in evict {
+            self.check_opaque_eviction(&cid)?;
+        }
+        Ok(())
+    }
 }
 
 impl BlobStore for Storage {
     fn has_blob(&self, hash: &NodeHash) -> bool {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT 1 FROM cas_blobs WHERE hash = ?1 AND status = 'Available' AND (data IS NOT NULL OR file_path IS NOT NULL)")
             .ok()
@@ -789,37 +1250,12 @@ impl BlobStore for Storage {
     }
 
     fn get_blob_info(&self, hash: &NodeHash) -> Option<BlobInfo> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn.prepare_cached("SELECT hash, total_size, bao_root, status, received_chunks FROM cas_blobs WHERE hash = ?1").ok()?;
-        stmt.query_row(params![hash.as_bytes()], |r| {
-            let hash_bytes: Vec<u8> = r.get(0)?;
-            let size: i64 = r.get(1)?;
-            let bao_root: Option<Vec<u8>> = r.get(2)?;
-            let status_str: String = r.get(3)?;
-            let received_mask: Option<Vec<u8>> = r.get(4)?;
-
-            let bytes: [u8; 32] = hash_bytes.try_into().map_err(|_| {
-                rusqlite::Error::InvalidColumnType(0, "hash".into(), rusqlite::types::Type::Blob)
-            })?;
-            let status = match status_str.as_str() {
-                "Pending" => BlobStatus::Pending,
-                "Downloading" => BlobStatus::Downloading,
-                "Available" => BlobStatus::Available,
-                _ => BlobStatus::Error,
-            };
-
-            Ok(BlobInfo {
-                hash: NodeHash::from(bytes),
-                size: size as u64,
-                bao_root: bao_root.and_then(|b| b.try_into().ok()),
-                status,
-                received_mask,
-                decryption_key: None,
-            })
-        })
-        .optional()
-        .ok()
-        .flatten()
+        stmt.query_row(params![hash.as_bytes()], blob_info_from_row)
+            .optional()
+            .ok()
+            .flatten()
     }
 
     fn put_blob_info(&self, info: BlobInfo) -> MerkleToxResult<()> {
@@ -829,6 +1265,7 @@ impl BlobStore for Storage {
             BlobStatus::Downloading => "Downloading",
             BlobStatus::Available => "Available",
             BlobStatus::Error => "Error",
+            BlobStatus::Cancelled => "Cancelled",
         };
         conn.execute(
             "INSERT INTO cas_blobs (hash, total_size, bao_root, status, received_chunks) VALUES (?1, ?2, ?3, ?4, ?5)
@@ -846,12 +1283,14 @@ impl BlobStore for Storage {
 
     fn put_chunk(
         &self,
-        _conversation_id: &ConversationId,
+        conversation_id: &ConversationId,
         hash: &NodeHash,
         offset: u64,
         data: &[u8],
         _proof: Option<&[u8]>,
     ) -> MerkleToxResult<()> {
+        // A blob already stored for another conversation is shared.
+        self.add_blob_ref(conversation_id, hash)?;
         if self.has_blob(hash) {
             return Ok(());
         }
@@ -932,12 +1371,13 @@ impl BlobStore for Storage {
             let status = if complete { "Available" } else { "Downloading" };
             let mut bao_root: Option<Vec<u8>> = None;
             if complete {
+                // The Bao root equals the BLAKE3 hash, which can be computed
+                // without reading the whole file into memory.
                 file.seek(SeekFrom::Start(0)).map_err(MerkleToxError::Io)?;
-                let mut full_data = vec![0u8; total_size as usize];
-                file.read_exact(&mut full_data)
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut (&mut file).take(total_size as u64), &mut hasher)
                     .map_err(MerkleToxError::Io)?;
-                let (_, root) = bao::encode::outboard(&full_data);
-                bao_root = Some(root.as_bytes().to_vec());
+                bao_root = Some(hasher.finalize().as_bytes().to_vec());
             }
 
             tx.execute(
@@ -1016,7 +1456,7 @@ impl BlobStore for Storage {
             return Ok(Vec::new());
         }
 
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let res: Option<(Option<Vec<u8>>, Option<String>, i64)> = conn
             .query_row(
                 "SELECT data, file_path, total_size FROM cas_blobs WHERE hash = ?1",
@@ -1069,7 +1509,7 @@ impl BlobStore for Storage {
         length: u32,
     ) -> MerkleToxResult<(Vec<u8>, Vec<u8>)> {
         let full_data = {
-            let conn = self.conn.lock().unwrap();
+            let conn = self.reader();
             let res: Option<(Option<Vec<u8>>, Option<String>, i64)> = conn
                 .query_row(
                     "SELECT data, file_path, total_size FROM cas_blobs WHERE hash = ?1",
@@ -1114,11 +1554,341 @@ impl BlobStore for Storage {
         let end = (offset as usize + length as usize).min(full_data.len());
         Ok((full_data[offset as usize..end].to_vec(), slice))
     }
+
+    fn add_blob_ref(
+        &self,
+        conversation_id: &ConversationId,
+        hash: &NodeHash,
+    ) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO blob_refs (conversation_id, hash) VALUES (?1, ?2)",
+            params![conversation_id.as_bytes(), hash.as_bytes()],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn blob_ref_count(&self, hash: &NodeHash) -> usize {
+        let conn = self.reader();
+        conn.query_row(
+            "SELECT COUNT(*) FROM blob_refs WHERE hash = ?1",
+            params![hash.as_bytes()],
+            |r| r.get::<_, i64>(0),
+        )
+        .map(|n| n as usize)
+        .unwrap_or(0)
+    }
+
+    fn get_incomplete_blobs(&self) -> Vec<BlobInfo> {
+        let conn = self.reader();
+        let Ok(mut stmt) = conn.prepare_cached("SELECT hash, total_size, bao_root, status, received_chunks FROM cas_blobs WHERE status IN ('Pending', 'Downloading')") else {
+            return Vec::new();
+        };
+        stmt.query_map([], blob_info_from_row)
+            .map(|rows| rows.filter_map(|r| r.ok()).collect())
+            .unwrap_or_default()
+    }
+
+    fn put_staged_chunk(&self, staging_id: u64, offset: u64, data: &[u8]) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        if offset == 0 {
+            conn.execute(
+                "DELETE FROM staged_chunks WHERE staging_id = ?1",
+                params![staging_id as i64],
+            )
+            .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        }
+        conn.execute(
+            "INSERT OR REPLACE INTO staged_chunks (staging_id, chunk_offset, data) VALUES (?1, ?2, ?3)",
+            params![staging_id as i64, offset as i64, data],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    fn commit_staged_blob(
+        &self,
+        conversation_id: &ConversationId,
+        staging_id: u64,
+        hash: &NodeHash,
+        size: u64,
+    ) -> MerkleToxResult<()> {
+        if self.has_blob(hash) {
+            self.add_blob_ref(conversation_id, hash)?;
+            return self.discard_staged_blob(staging_id);
+        }
+        self.put_blob_info(BlobInfo {
+            hash: *hash,
+            size,
+            bao_root: None,
+            status: if size == 0 {
+                BlobStatus::Available
+            } else {
+                BlobStatus::Pending
+            },
+            received_mask: None,
+            decryption_key: None,
+        })?;
+
+        let offsets: Vec<i64> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn
+                .prepare_cached(
+                    "SELECT chunk_offset FROM staged_chunks WHERE staging_id = ?1 ORDER BY chunk_offset",
+                )
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+            stmt.query_map(params![staging_id as i64], |r| r.get(0))
+                .and_then(|rows| rows.collect())
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?
+        };
+        // Moves one chunk at a time, so the blob never has to be held in
+        // memory.
+        for offset in offsets {
+            let data: Vec<u8> = self
+                .conn
+                .lock()
+                .unwrap()
+                .query_row(
+                    "SELECT data FROM staged_chunks WHERE staging_id = ?1 AND chunk_offset = ?2",
+                    params![staging_id as i64, offset],
+                    |r| r.get(0),
+                )
+                .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+            self.put_chunk(conversation_id, hash, offset as u64, &data, None)?;
+        }
+        self.add_blob_ref(conversation_id, hash)?;
+        self.discard_staged_blob(staging_id)
+    }
+
+    fn discard_staged_blob(&self, staging_id: u64) -> MerkleToxResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM staged_chunks WHERE staging_id = ?1",
+            params![staging_id as i64],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Distance to the nearest Admin node: 0 for Admin nodes, otherwise one more
+/// than the closest parent.
+fn admin_distance(node: &MerkleNode, parent_distance: impl Fn(&NodeHash) -> Option<u64>) -> u64 {
+    if node.node_type() == NodeType::Admin {
+        return 0;
+    }
+    node.parents
+        .iter()
+        .filter_map(parent_distance)
+        .min()
+        .map_or(u64::MAX, |d| d.saturating_add(1))
+}
+
+fn query_admin_distance(conn: &Connection, hash: &NodeHash) -> Option<u64> {
+    let mut stmt = conn
+        .prepare_cached("SELECT admin_distance FROM nodes WHERE hash = ?1")
+        .ok()?;
+    let res: i64 = stmt
+        .query_row(params![hash.as_bytes()], |r| r.get(0))
+        .optional()
+        .ok()??;
+    Some(res as u64)
+}
+
+fn query_node(conn: &Connection, hash: &NodeHash) -> Option<MerkleNode> {
+    let mut stmt = conn
+        .prepare_cached("SELECT raw_data FROM nodes WHERE hash = ?1")
+        .ok()?;
+    let raw_data: Vec<u8> = stmt
+        .query_row(params![hash.as_bytes()], |r| r.get(0))
+        .optional()
+        .ok()??;
+    let node: MerkleNode = tox_proto::deserialize(&raw_data).ok()?;
+    if node.hash() != *hash {
+        return None;
+    }
+    Some(node)
+}
+
+/// Inserts `node` within a transaction. Parents written earlier in the same
+/// transaction are only visible to `conn`, so their distances are kept in
+/// `distances`.
+fn insert_node_in_batch(
+    conn: &Connection,
+    distances: &mut HashMap<NodeHash, u64>,
+    conversation_id: &ConversationId,
+    node: &MerkleNode,
+    verified: bool,
+) -> MerkleToxResult<()> {
+    let distance = admin_distance(node, |h| {
+        distances
+            .get(h)
+            .copied()
+            .or_else(|| query_admin_distance(conn, h))
+    });
+    insert_node(conn, conversation_id, node, verified, distance)?;
+    distances.insert(node.hash(), distance);
+    Ok(())
+}
+
+fn insert_node(
+    conn: &Connection,
+    conversation_id: &ConversationId,
+    node: &MerkleNode,
+    verified: bool,
+    admin_distance: u64,
+) -> MerkleToxResult<()> {
+    let hash = node.hash();
+    let node_type = if node.node_type() == NodeType::Admin {
+        0
+    } else {
+        1
+    };
+    let raw_data = tox_proto::serialize(node).map_err(MerkleToxError::Protocol)?;
+    let parents_data = tox_proto::serialize(&node.parents).map_err(MerkleToxError::Protocol)?;
+    let status = if verified { 1 } else { 0 };
+
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO nodes (
+            hash, conversation_id, node_type, author_pk, sender_pk, network_timestamp,
+            sequence_number, topological_rank, admin_distance, parents, verification_status, raw_data
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+    )
+    .and_then(|mut stmt| {
+        stmt.execute(params![
+            hash.as_bytes(),
+            conversation_id.as_bytes(),
+            node_type,
+            node.author_pk.as_bytes(),
+            node.sender_pk.as_bytes(),
+            node.network_timestamp,
+            (node.sequence_number as i64) ^ i64::MIN,
+            (node.topological_rank as i64) ^ i64::MIN,
+            admin_distance as i64,
+            parents_data,
+            status,
+            raw_data,
+        ])
+    })
+    .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+
+    for parent_hash in &node.parents {
+        conn.prepare_cached(
+            "INSERT OR IGNORE INTO edges (parent_hash, child_hash) VALUES (?1, ?2)",
+        )
+        .and_then(|mut stmt| stmt.execute(params![parent_hash.as_bytes(), hash.as_bytes()]))
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+    }
+
+    if let Some(blob_hash) = node.content.blob_hash() {
+        conn.execute(
+            "INSERT OR IGNORE INTO blob_refs (conversation_id, hash) VALUES (?1, ?2)",
+            params![conversation_id.as_bytes(), blob_hash.as_bytes()],
+        )
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+    }
+    Ok(())
+}
+
+fn insert_wire_node(
+    conn: &Connection,
+    conversation_id: &ConversationId,
+    hash: &NodeHash,
+    node: &merkle_tox_core::dag::WireNode,
+) -> MerkleToxResult<()> {
+    let raw_data = tox_proto::serialize(node).map_err(MerkleToxError::Protocol)?;
+    conn.execute(
+        "INSERT OR REPLACE INTO opaque_nodes (hash, conversation_id, raw_data) VALUES (?1, ?2, ?3)",
+        params![hash.as_bytes(), conversation_id.as_bytes(), raw_data],
+    )
+    .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+    Ok(())
+}
+
+fn write_heads(
+    conn: &Connection,
+    conversation_id: &ConversationId,
+    heads: Vec<NodeHash>,
+    is_admin: bool,
+) -> MerkleToxResult<()> {
+    let heads_data = tox_proto::serialize(&heads).map_err(MerkleToxError::Protocol)?;
+    let sql = if is_admin {
+        "INSERT INTO conversation_meta (conversation_id, admin_heads) VALUES (?1, ?2)
+         ON CONFLICT(conversation_id) DO UPDATE SET admin_heads = ?2"
+    } else {
+        "INSERT INTO conversation_meta (conversation_id, heads) VALUES (?1, ?2)
+         ON CONFLICT(conversation_id) DO UPDATE SET heads = ?2"
+    };
+    conn.execute(sql, params![conversation_id.as_bytes(), heads_data])
+        .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+    Ok(())
+}
+
+fn insert_ratchet_key(
+    conn: &Connection,
+    conversation_id: &ConversationId,
+    node_hash: &NodeHash,
+    chain_key: &ChainKey,
+    epoch_id: u64,
+) -> MerkleToxResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ratchet_keys (conversation_id, node_hash, chain_key, epoch_id) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            conversation_id.as_bytes(),
+            node_hash.as_bytes(),
+            chain_key.as_bytes().to_vec(),
+            (epoch_id as i64) ^ i64::MIN
+        ],
+    )
+    .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+    Ok(())
+}
+
+fn delete_ratchet_key(
+    conn: &Connection,
+    conversation_id: &ConversationId,
+    node_hash: &NodeHash,
+) -> MerkleToxResult<()> {
+    conn.execute(
+        "DELETE FROM ratchet_keys WHERE conversation_id = ?1 AND node_hash = ?2",
+        params![conversation_id.as_bytes(), node_hash.as_bytes()],
+    )
+    .map_err(|e| MerkleToxError::Storage(e.to_string()))?;
+    Ok(())
+}
+
+fn blob_info_from_row(r: &rusqlite::Row) -> rusqlite::Result<BlobInfo> {
+    let hash_bytes: Vec<u8> = r.get(0)?;
+    let size: i64 = r.get(1)?;
+    let bao_root: Option<Vec<u8>> = r.get(2)?;
+    let status_str: String = r.get(3)?;
+    let received_mask: Option<Vec<u8>> = r.get(4)?;
+
+    let bytes: [u8; 32] = hash_bytes.try_into().map_err(|_| {
+        rusqlite::Error::InvalidColumnType(0, "hash".into(), rusqlite::types::Type::Blob)
+    })?;
+    let status = match status_str.as_str() {
+        "Pending" => BlobStatus::Pending,
+        "Downloading" => BlobStatus::Downloading,
+        "Available" => BlobStatus::Available,
+        "Cancelled" => BlobStatus::Cancelled,
+        _ => BlobStatus::Error,
+    };
+
+    Ok(BlobInfo {
+        hash: NodeHash::from(bytes),
+        size: size as u64,
+        bao_root: bao_root.and_then(|b| b.try_into().ok()),
+        status,
+        received_mask,
+        decryption_key: None,
+    })
 }
 
 impl GlobalStore for Storage {
     fn get_global_offset(&self) -> Option<i64> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached("SELECT value FROM global_state WHERE key = 'network_offset'")
             .ok()?;
@@ -1167,7 +1937,7 @@ impl ReconciliationStore for Storage {
         conversation_id: &ConversationId,
         range: &SyncRange,
     ) -> MerkleToxResult<Option<Vec<u8>>> {
-        let conn = self.conn.lock().unwrap();
+        let conn = self.reader();
         let mut stmt = conn
             .prepare_cached(
                 "SELECT sketch FROM reconciliation_sketches
@@ -15,6 +15,9 @@ pub const CREATE_TABLES: &str = "
     );
 
     CREATE INDEX IF NOT EXISTS idx_nodes_conv ON nodes(conversation_id);
+    CREATE INDEX IF NOT EXISTS idx_nodes_author
+        ON nodes(conversation_id, author_pk, topological_rank);
+    CREATE INDEX IF NOT EXISTS idx_nodes_time ON nodes(conversation_id, network_timestamp);
 
     CREATE TABLE IF NOT EXISTS edges (
         parent_hash BLOB NOT NULL,
@@ -56,6 +59,21 @@ pub const CREATE_TABLES: &str = "
         bao_root BLOB
     );
 
+    CREATE TABLE IF NOT EXISTS blob_refs (
+        conversation_id BLOB NOT NULL,
+        hash BLOB NOT NULL,
+        PRIMARY KEY (conversation_id, hash)
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_blob_refs_hash ON blob_refs(hash);
+
+    CREATE TABLE IF NOT EXISTS staged_chunks (
+        staging_id INTEGER NOT NULL,
+        chunk_offset INTEGER NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (staging_id, chunk_offset)
+    );
+
     CREATE TABLE IF NOT EXISTS reconciliation_sketches (
         conversation_id BLOB NOT NULL,
         min_rank INTEGER NOT NULL,
@@ -79,4 +97,50 @@ pub const CREATE_TABLES: &str = "
     );
 
     CREATE INDEX IF NOT EXISTS idx_opaque_nodes_conv ON opaque_nodes(conversation_id);
+
+    CREATE TABLE IF NOT EXISTS scheduled_messages (
+        id INTEGER PRIMARY KEY,
+        conversation_id BLOB NOT NULL,
+        deliver_at INTEGER NOT NULL,
+        raw_data BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS history_horizons (
+        conversation_id BLOB PRIMARY KEY,
+        min_rank INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS acked_heads (
+        conversation_id BLOB NOT NULL,
+        device_pk BLOB NOT NULL,
+        raw_data BLOB NOT NULL,
+        PRIMARY KEY (conversation_id, device_pk)
+    );
+
+    CREATE TABLE IF NOT EXISTS archived_conversations (
+        conversation_id BLOB PRIMARY KEY,
+        raw_data BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS blacklist (
+        device_pk BLOB PRIMARY KEY,
+        raw_data BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS invite_grants (
+        invite_id BLOB PRIMARY KEY,
+        raw_data BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS state_snapshots (
+        conversation_id BLOB PRIMARY KEY,
+        raw_data BLOB NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS quarantined_nodes (
+        hash BLOB NOT NULL,
+        conversation_id BLOB NOT NULL,
+        raw_data BLOB NOT NULL,
+        reason TEXT NOT NULL
+    );
 ";
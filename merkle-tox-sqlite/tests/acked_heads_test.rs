@@ -0,0 +1,55 @@
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::engine::outbox::AckedHeads;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn acked(cid: u8, device: u8, heads: &[u8]) -> AckedHeads {
+    AckedHeads {
+        conversation_id: ConversationId::from([cid; 32]),
+        device_pk: PhysicalDevicePk::from([device; 32]),
+        heads: heads.iter().map(|h| NodeHash::from([*h; 32])).collect(),
+    }
+}
+
+fn fill(store: &dyn NodeStore) {
+    assert!(store.get_acked_heads().is_empty());
+    store.put_acked_heads(&acked(1, 1, &[1])).unwrap();
+    store.put_acked_heads(&acked(1, 2, &[1])).unwrap();
+    store.put_acked_heads(&acked(1, 1, &[2, 3])).unwrap();
+}
+
+fn check(store: &dyn NodeStore) {
+    let mut all = store.get_acked_heads();
+    all.sort_by_key(|a| a.device_pk);
+    assert_eq!(all, vec![acked(1, 1, &[2, 3]), acked(1, 2, &[1])]);
+}
+
+#[test]
+fn test_sqlite_acked_heads() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_acked_heads_survive_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
@@ -0,0 +1,60 @@
+use merkle_tox_core::dag::PhysicalDevicePk;
+use merkle_tox_core::engine::quarantine::BlacklistEntry;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn entry(device: u8, tier: u8) -> BlacklistEntry {
+    BlacklistEntry {
+        device_pk: PhysicalDevicePk::from([device; 32]),
+        tier,
+        expires_at_ms: 1000 * tier as i64,
+    }
+}
+
+fn fill(store: &dyn NodeStore) {
+    assert!(store.get_blacklist().is_empty());
+    store.put_blacklist_entry(&entry(1, 1)).unwrap();
+    store.put_blacklist_entry(&entry(2, 1)).unwrap();
+    store.put_blacklist_entry(&entry(3, 1)).unwrap();
+    store.put_blacklist_entry(&entry(1, 2)).unwrap();
+    store
+        .remove_blacklist_entry(&entry(3, 1).device_pk)
+        .unwrap();
+}
+
+fn check(store: &dyn NodeStore) {
+    let mut all = store.get_blacklist();
+    all.sort_by_key(|e| e.device_pk);
+    assert_eq!(all, vec![entry(1, 2), entry(2, 1)]);
+}
+
+#[test]
+fn test_sqlite_blacklist() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_blacklist_survives_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+    assert!(root.join("blacklist.bin").exists());
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
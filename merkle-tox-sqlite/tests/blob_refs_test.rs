@@ -0,0 +1,128 @@
+use merkle_tox_core::cas::{BlobInfo, BlobStatus};
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn conv(id: u8) -> ConversationId {
+    ConversationId::from([id; 32])
+}
+
+fn blob_node(hash: NodeHash) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: 1,
+        topological_rank: 0,
+        network_timestamp: 1000,
+        content: Content::Blob {
+            hash,
+            name: "file.bin".to_string(),
+            mime_type: "application/octet-stream".to_string(),
+            size: 4,
+            metadata: vec![],
+            preview: None,
+        },
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn store_blob<S: BlobStore>(store: &S, conv_id: &ConversationId, data: &[u8]) -> NodeHash {
+    // Content addressed: identical data gets the same hash.
+    let hash = NodeHash::from([data[0]; 32]);
+    if store.get_blob_info(&hash).is_none() {
+        store
+            .put_blob_info(BlobInfo {
+                hash,
+                size: data.len() as u64,
+                bao_root: None,
+                status: BlobStatus::Pending,
+                received_mask: None,
+                decryption_key: None,
+            })
+            .unwrap();
+    }
+    store.put_chunk(conv_id, &hash, 0, data, None).unwrap();
+    hash
+}
+
+#[test]
+fn test_sqlite_shared_blob_survives_until_last_reference() {
+    let storage = Storage::open_in_memory().unwrap();
+
+    let shared = store_blob(&storage, &conv(1), b"same");
+    assert_eq!(store_blob(&storage, &conv(2), b"same"), shared);
+    let private = store_blob(&storage, &conv(1), b"priv");
+    assert_eq!(storage.blob_ref_count(&shared), 2);
+    assert_eq!(storage.blob_ref_count(&private), 1);
+
+    // Storing the node again does not add a second reference.
+    storage.put_node(&conv(1), blob_node(shared), true).unwrap();
+    assert_eq!(storage.blob_ref_count(&shared), 2);
+
+    let deleted = storage.delete_conversation(&conv(1)).unwrap();
+    assert_eq!(deleted, vec![private]);
+    assert!(!storage.has_blob(&private));
+    assert!(storage.get_blob_info(&private).is_none());
+    assert!(storage.has_blob(&shared));
+    assert_eq!(storage.blob_ref_count(&shared), 1);
+    assert!(!storage.has_node(&blob_node(shared).hash()));
+    assert_eq!(storage.get_node_counts(&conv(1)), (0, 0));
+
+    let deleted = storage.delete_conversation(&conv(2)).unwrap();
+    assert_eq!(deleted, vec![shared]);
+    assert!(!storage.has_blob(&shared));
+}
+
+#[test]
+fn test_sqlite_node_reference_keeps_blob_alive() {
+    let storage = Storage::open_in_memory().unwrap();
+    let hash = store_blob(&storage, &conv(1), b"data");
+    // B only knows the blob through a message referencing it.
+    storage.put_node(&conv(2), blob_node(hash), true).unwrap();
+    assert_eq!(storage.blob_ref_count(&hash), 2);
+
+    assert!(storage.delete_conversation(&conv(1)).unwrap().is_empty());
+    assert_eq!(storage.get_chunk(&hash, 0, 4).unwrap(), b"data");
+}
+
+#[test]
+fn test_fs_store_shared_blob_survives_until_last_reference() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+
+    let shared = store_blob(&store, &conv(1), b"same");
+    store_blob(&store, &conv(2), b"same");
+    let private = store_blob(&store, &conv(1), b"priv");
+    store.put_node(&conv(1), blob_node(shared), true).unwrap();
+    assert_eq!(store.blob_ref_count(&shared), 2);
+    assert_eq!(store.blob_ref_count(&private), 1);
+
+    let deleted = store.delete_conversation(&conv(1)).unwrap();
+    assert_eq!(deleted, vec![private]);
+    assert!(!store.has_blob(&private));
+    assert!(store.get_blob_info(&private).is_none());
+    assert!(store.has_blob(&shared));
+    assert!(!store.has_node(&blob_node(shared).hash()));
+
+    // The deletion is durable.
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    assert_eq!(store.get_node_counts(&conv(1)), (0, 0));
+    assert_eq!(store.blob_ref_count(&shared), 1);
+    assert_eq!(store.get_chunk(&shared, 0, 4).unwrap(), b"same");
+
+    let deleted = store.delete_conversation(&conv(2)).unwrap();
+    assert_eq!(deleted, vec![shared]);
+    assert!(!store.has_blob(&shared));
+}
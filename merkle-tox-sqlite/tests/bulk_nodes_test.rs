@@ -0,0 +1,104 @@
+use merkle_tox_core::dag::{
+    Content, ControlAction, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode,
+    NodeAuth, NodeHash, PhysicalDevicePk,
+};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn make_node(seq: u64, parents: Vec<NodeHash>, content: Content) -> MerkleNode {
+    MerkleNode {
+        parents,
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64 * 10,
+        content,
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+/// An Admin node followed by a chain of 50 text nodes.
+fn chain() -> Vec<MerkleNode> {
+    let mut nodes = vec![make_node(
+        0,
+        vec![],
+        Content::Control(ControlAction::SetRetention { max_age_ms: 1000 }),
+    )];
+    for seq in 1..=50 {
+        let parent = nodes.last().unwrap().hash();
+        nodes.push(make_node(
+            seq,
+            vec![parent],
+            Content::Text(format!("message {}", seq)),
+        ));
+    }
+    nodes
+}
+
+fn fill(store: &dyn NodeStore) {
+    let nodes = chain();
+    let batch = nodes
+        .iter()
+        .map(|n| (n.clone(), n.sequence_number != 50))
+        .collect();
+    store.put_nodes(&conv_id(), batch).unwrap();
+}
+
+fn check(store: &dyn NodeStore) {
+    let nodes = chain();
+    let mut hashes: Vec<NodeHash> = nodes.iter().map(|n| n.hash()).collect();
+    hashes.push(NodeHash::from([9u8; 32]));
+
+    let fetched = store.get_nodes(&hashes);
+    assert_eq!(fetched.len(), hashes.len());
+    for (node, got) in nodes.iter().zip(&fetched) {
+        assert_eq!(got.as_ref(), Some(node));
+    }
+    assert!(fetched.last().unwrap().is_none());
+
+    assert!(store.is_verified(&nodes[49].hash()));
+    assert!(!store.is_verified(&nodes[50].hash()));
+    assert_eq!(store.get_admin_distance(&nodes[0].hash()), Some(0));
+    assert_eq!(store.get_admin_distance(&nodes[50].hash()), Some(50));
+    assert_eq!(
+        store.get_last_sequence_number(&conv_id(), &PhysicalDevicePk::from([1u8; 32])),
+        50
+    );
+}
+
+#[test]
+fn test_sqlite_bulk_nodes() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_bulk_nodes_survive_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
@@ -0,0 +1,190 @@
+use merkle_tox_core::cas::{BlobInfo, BlobStatus};
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth, NodeHash,
+    NodeLookup, PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_sqlite::Storage;
+use merkle_tox_sqlite::fsck::{FsckIssue, FsckMode};
+use rusqlite::params;
+
+fn make_node(parents: &[&MerkleNode], seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: parents.iter().map(|p| p.hash()).collect(),
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64 * 10,
+        content: Content::Text(format!("message {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+fn filled() -> (Storage, Vec<MerkleNode>) {
+    let storage = Storage::open_in_memory().unwrap();
+    let root = make_node(&[], 1);
+    let a = make_node(&[&root], 2);
+    let b = make_node(&[&a], 3);
+    for node in [&root, &a, &b] {
+        storage.put_node(&conv_id(), node.clone(), true).unwrap();
+    }
+    (storage, vec![root, a, b])
+}
+
+fn execute(storage: &Storage, sql: &str, params: impl rusqlite::Params) {
+    storage
+        .connection()
+        .lock()
+        .unwrap()
+        .execute(sql, params)
+        .unwrap();
+}
+
+#[test]
+fn test_clean_database() {
+    let (storage, _) = filled();
+    let report = storage.fsck(FsckMode::Check).unwrap();
+    assert!(report.is_clean());
+    assert_eq!(report.nodes, 3);
+    assert_eq!(report.blobs, 0);
+}
+
+#[test]
+fn test_corrupt_node_is_quarantined() {
+    let (storage, nodes) = filled();
+    let hash = nodes[2].hash();
+    execute(
+        &storage,
+        "UPDATE nodes SET raw_data = X'C1C1' WHERE hash = ?1",
+        params![hash.as_bytes()],
+    );
+
+    let report = storage.fsck(FsckMode::Check).unwrap();
+    assert_eq!(
+        report.issues,
+        vec![FsckIssue::BadNode {
+            conversation_id: conv_id(),
+            hash
+        }]
+    );
+    assert_eq!(report.remaining().len(), 1);
+    // Checking does not write.
+    assert!(storage.has_node(&hash));
+
+    let report = storage.fsck(FsckMode::Repair).unwrap();
+    assert!(report.remaining().is_empty());
+    assert!(!storage.has_node(&hash));
+    assert!(!storage.has_children(&nodes[1].hash()));
+    let reason: String = storage
+        .connection()
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT reason FROM quarantined_nodes WHERE hash = ?1",
+            params![hash.as_bytes()],
+            |r| r.get(0),
+        )
+        .unwrap();
+    assert_eq!(reason, "undecodable");
+    assert!(storage.fsck(FsckMode::Check).unwrap().is_clean());
+}
+
+#[test]
+fn test_stale_index_and_dangling_edge() {
+    let (storage, nodes) = filled();
+    let a = nodes[1].hash();
+    execute(
+        &storage,
+        "UPDATE nodes SET topological_rank = 0 WHERE hash = ?1",
+        params![a.as_bytes()],
+    );
+    execute(
+        &storage,
+        "DELETE FROM edges WHERE child_hash = ?1",
+        params![nodes[2].hash().as_bytes()],
+    );
+    let missing = NodeHash::from([9u8; 32]);
+    execute(
+        &storage,
+        "INSERT INTO edges (parent_hash, child_hash) VALUES (?1, ?2)",
+        params![a.as_bytes(), missing.as_bytes()],
+    );
+
+    let report = storage.fsck(FsckMode::Repair).unwrap();
+    let mut stale = vec![a, nodes[2].hash()];
+    stale.sort();
+    let mut expected: Vec<_> = stale
+        .into_iter()
+        .map(|hash| FsckIssue::StaleIndex { hash })
+        .collect();
+    expected.push(FsckIssue::DanglingEdge {
+        parent: a,
+        child: missing,
+    });
+    assert_eq!(report.issues, expected);
+
+    assert_eq!(storage.get_rank(&a), Some(2));
+    assert!(storage.has_children(&a));
+    assert!(storage.fsck(FsckMode::Check).unwrap().is_clean());
+}
+
+#[test]
+fn test_blob_mask_and_size() {
+    let storage = Storage::open_in_memory().unwrap();
+    let conv = ConversationId::from([0u8; 32]);
+    let data = vec![0xBBu8; 100 * 1024];
+    let hash = NodeHash::from([0xAAu8; 32]);
+    storage
+        .put_blob_info(BlobInfo {
+            hash,
+            size: data.len() as u64,
+            bao_root: None,
+            status: BlobStatus::Pending,
+            received_mask: None,
+            decryption_key: None,
+        })
+        .unwrap();
+    storage
+        .put_chunk(&conv, &hash, 0, &data[..65536], None)
+        .unwrap();
+    storage
+        .put_chunk(&conv, &hash, 65536, &data[65536..], None)
+        .unwrap();
+    assert!(storage.fsck(FsckMode::Check).unwrap().is_clean());
+
+    execute(
+        &storage,
+        "UPDATE cas_blobs SET received_chunks = X'FFFF', data = SUBSTR(data, 1, 1000)
+         WHERE hash = ?1",
+        params![hash.as_bytes()],
+    );
+    let report = storage.fsck(FsckMode::Repair).unwrap();
+    assert_eq!(report.blobs, 1);
+    assert_eq!(
+        report.issues,
+        vec![
+            FsckIssue::BlobMask {
+                hash,
+                len: 2,
+                expected: 1
+            },
+            FsckIssue::BlobSize {
+                hash,
+                size: data.len() as u64,
+                data_len: 1000
+            },
+        ]
+    );
+
+    let info = storage.get_blob_info(&hash).unwrap();
+    assert_eq!(info.status, BlobStatus::Pending);
+    assert!(!storage.has_blob(&hash));
+    assert!(storage.fsck(FsckMode::Check).unwrap().is_clean());
+}
@@ -0,0 +1,57 @@
+use merkle_tox_core::dag::ConversationId;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+fn fill(store: &dyn NodeStore) {
+    assert_eq!(store.get_history_horizon(&conv_id()), None);
+    store.set_history_horizon(&conv_id(), Some(90)).unwrap();
+    store.set_history_horizon(&conv_id(), Some(40)).unwrap();
+    store
+        .set_history_horizon(&ConversationId::from([2u8; 32]), Some(7))
+        .unwrap();
+    store
+        .set_history_horizon(&ConversationId::from([2u8; 32]), None)
+        .unwrap();
+}
+
+fn check(store: &dyn NodeStore) {
+    assert_eq!(store.get_history_horizon(&conv_id()), Some(40));
+    assert_eq!(
+        store.get_history_horizon(&ConversationId::from([2u8; 32])),
+        None
+    );
+}
+
+#[test]
+fn test_sqlite_history_horizon() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_history_horizon_survives_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
@@ -0,0 +1,51 @@
+use merkle_tox_core::cas::{BlobInfo, BlobStatus};
+use merkle_tox_core::dag::NodeHash;
+use merkle_tox_core::sync::BlobStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn check_incomplete_blobs(store: &dyn BlobStore) {
+    for (id, status) in [
+        (1u8, BlobStatus::Pending),
+        (2, BlobStatus::Downloading),
+        (3, BlobStatus::Available),
+        (4, BlobStatus::Cancelled),
+    ] {
+        store
+            .put_blob_info(BlobInfo {
+                hash: NodeHash::from([id; 32]),
+                size: 100,
+                bao_root: None,
+                status,
+                received_mask: Some(vec![id]),
+                decryption_key: None,
+            })
+            .unwrap();
+    }
+
+    let mut incomplete = store.get_incomplete_blobs();
+    incomplete.sort_by_key(|i| i.hash);
+    let hashes: Vec<NodeHash> = incomplete.iter().map(|i| i.hash).collect();
+    assert_eq!(
+        hashes,
+        vec![NodeHash::from([1u8; 32]), NodeHash::from([2u8; 32])]
+    );
+    // The received mask survives so downloads resume where they stopped.
+    assert_eq!(incomplete[1].received_mask, Some(vec![2]));
+}
+
+#[test]
+fn test_sqlite_incomplete_blobs() {
+    let storage = Storage::open_in_memory().unwrap();
+    check_incomplete_blobs(&storage);
+}
+
+#[test]
+fn test_fs_store_incomplete_blobs() {
+    let tmp_dir = TempDir::new().unwrap();
+    let store = FsStore::new(tmp_dir.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    check_incomplete_blobs(&store);
+}
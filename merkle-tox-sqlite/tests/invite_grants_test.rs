@@ -0,0 +1,59 @@
+use merkle_tox_core::dag::ConversationId;
+use merkle_tox_core::invite::InviteGrant;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn grant(secret: u8, role: u8) -> InviteGrant {
+    InviteGrant {
+        conversation_id: ConversationId::from([1u8; 32]),
+        secret: [secret; 32],
+        role,
+        expires_at: 1000,
+    }
+}
+
+fn fill(store: &dyn NodeStore) {
+    assert!(store.get_invite_grants().is_empty());
+    store.put_invite_grant(&grant(1, 0)).unwrap();
+    store.put_invite_grant(&grant(2, 0)).unwrap();
+    store.put_invite_grant(&grant(3, 0)).unwrap();
+    store.put_invite_grant(&grant(1, 1)).unwrap();
+    store.remove_invite_grant(&grant(3, 0).id()).unwrap();
+}
+
+fn check(store: &dyn NodeStore) {
+    let mut all = store.get_invite_grants();
+    all.sort_by_key(|g| g.secret);
+    assert_eq!(all, vec![grant(1, 1), grant(2, 0)]);
+}
+
+#[test]
+fn test_sqlite_invite_grants() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_invite_grants_survive_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+    assert!(root.join("invites.bin").exists());
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
@@ -0,0 +1,95 @@
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn make_node(seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64 * 10,
+        content: Content::Text(format!("message {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+fn fill(store: &dyn NodeStore) {
+    for seq in 1..=3 {
+        store.put_node(&conv_id(), make_node(seq), true).unwrap();
+    }
+    let hash = make_node(2).hash();
+    // Pruning is scoped to the conversation.
+    assert!(
+        !store
+            .prune_node(&ConversationId::from([9u8; 32]), &hash)
+            .unwrap()
+    );
+    assert!(store.prune_node(&conv_id(), &hash).unwrap());
+    assert!(!store.prune_node(&conv_id(), &hash).unwrap());
+}
+
+fn check(store: &dyn NodeStore) {
+    let pruned = make_node(2).hash();
+    assert!(store.get_node(&pruned).is_none());
+    assert!(store.get_node(&make_node(1).hash()).is_some());
+    assert!(store.get_node(&make_node(3).hash()).is_some());
+
+    let seqs: Vec<u64> = store
+        .get_nodes_by_time_range(&conv_id(), i64::MIN, i64::MAX, 100)
+        .unwrap()
+        .iter()
+        .map(|n| n.sequence_number)
+        .collect();
+    assert_eq!(seqs, vec![1, 3]);
+    assert!(!store.prune_node(&conv_id(), &pruned).unwrap());
+}
+
+#[test]
+fn test_sqlite_prune_node() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+    assert!(storage.has_node(&make_node(2).hash()));
+    assert!(storage.is_verified(&make_node(2).hash()));
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_prune_node_survives_reopen_and_compaction() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+
+    drop(store);
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+
+    store.compact(&conv_id()).unwrap();
+    check(&store);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
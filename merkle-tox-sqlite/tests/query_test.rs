@@ -0,0 +1,136 @@
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{NodeStore, SyncRange};
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn make_node(author: u8, seq: u64, timestamp: i64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([author; 32]),
+        sender_pk: PhysicalDevicePk::from([author; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: timestamp,
+        content: Content::Text(format!("{} from {}", seq, author)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn populate(store: &dyn NodeStore, conv_id: &ConversationId) {
+    for seq in 1..=10 {
+        let author = if seq % 2 == 0 { 1 } else { 2 };
+        store
+            .put_node(
+                conv_id,
+                make_node(author, seq, 1000 + seq as i64 * 10),
+                true,
+            )
+            .unwrap();
+    }
+    // Speculative nodes are never returned.
+    store
+        .put_node(conv_id, make_node(1, 11, 1110), false)
+        .unwrap();
+    // Nor are nodes from other conversations.
+    store
+        .put_node(
+            &ConversationId::from([9u8; 32]),
+            make_node(1, 12, 1050),
+            true,
+        )
+        .unwrap();
+}
+
+fn check_queries(store: &dyn NodeStore, conv_id: &ConversationId) {
+    let author = LogicalIdentityPk::from([1u8; 32]);
+    let all = SyncRange {
+        min_rank: 0,
+        max_rank: u64::MAX,
+    };
+    let seqs: Vec<u64> = store
+        .get_nodes_by_author(conv_id, &author, &all)
+        .unwrap()
+        .iter()
+        .map(|n| n.sequence_number)
+        .collect();
+    assert_eq!(seqs, vec![2, 4, 6, 8, 10]);
+
+    let ranks = SyncRange {
+        min_rank: 3,
+        max_rank: 6,
+    };
+    let seqs: Vec<u64> = store
+        .get_nodes_by_author(conv_id, &author, &ranks)
+        .unwrap()
+        .iter()
+        .map(|n| n.sequence_number)
+        .collect();
+    assert_eq!(seqs, vec![4, 6]);
+
+    let timestamps: Vec<i64> = store
+        .get_nodes_by_time_range(conv_id, 1030, 1080, 100)
+        .unwrap()
+        .iter()
+        .map(|n| n.network_timestamp)
+        .collect();
+    assert_eq!(timestamps, vec![1030, 1040, 1050, 1060, 1070]);
+
+    let limited = store
+        .get_nodes_by_time_range(conv_id, i64::MIN, i64::MAX, 3)
+        .unwrap();
+    assert_eq!(limited.len(), 3);
+    assert_eq!(limited[0].network_timestamp, 1010);
+}
+
+#[test]
+fn test_sqlite_author_and_time_queries() {
+    let storage = Storage::open_in_memory().unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    populate(&storage, &conv_id);
+    check_queries(&storage, &conv_id);
+}
+
+#[test]
+fn test_sqlite_query_indexes_are_used() {
+    let storage = Storage::open_in_memory().unwrap();
+    let conn = storage.connection().lock().unwrap();
+    let plan = |sql: &str| -> String {
+        let mut stmt = conn
+            .prepare(&format!("EXPLAIN QUERY PLAN {}", sql))
+            .unwrap();
+        let rows = stmt
+            .query_map([], |r| r.get::<_, String>(3))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>();
+        rows.join("\n")
+    };
+    assert!(
+        plan("SELECT raw_data FROM nodes WHERE conversation_id = x'00' AND author_pk = x'01' AND topological_rank BETWEEN 0 AND 5")
+            .contains("idx_nodes_author")
+    );
+    assert!(
+        plan("SELECT raw_data FROM nodes WHERE conversation_id = x'00' AND network_timestamp >= 0 AND network_timestamp < 5 ORDER BY network_timestamp")
+            .contains("idx_nodes_time")
+    );
+}
+
+#[test]
+fn test_fs_store_author_and_time_queries() {
+    let tmp_dir = TempDir::new().unwrap();
+    let store = FsStore::new(tmp_dir.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+    populate(&store, &conv_id);
+    check_queries(&store, &conv_id);
+
+    store.compact(&conv_id).unwrap();
+    check_queries(&store, &conv_id);
+}
@@ -0,0 +1,59 @@
+use merkle_tox_core::dag::{Content, ConversationId};
+use merkle_tox_core::engine::scheduled::ScheduledMessage;
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn scheduled(id: u64, deliver_at_ms: i64) -> ScheduledMessage {
+    ScheduledMessage {
+        id,
+        conversation_id: ConversationId::from([1u8; 32]),
+        deliver_at_ms,
+        content: Content::Text(format!("message {}", id)),
+        metadata: vec![],
+    }
+}
+
+fn fill(store: &dyn NodeStore) {
+    store.put_scheduled_message(&scheduled(1, 3000)).unwrap();
+    store.put_scheduled_message(&scheduled(2, 1000)).unwrap();
+    store.put_scheduled_message(&scheduled(3, 2000)).unwrap();
+    store.remove_scheduled_message(3).unwrap();
+    // Removing an unknown id is not an error.
+    store.remove_scheduled_message(42).unwrap();
+}
+
+fn check(store: &dyn NodeStore) {
+    let mut msgs = store.get_scheduled_messages();
+    msgs.sort_by_key(|m| m.deliver_at_ms);
+    assert_eq!(msgs, vec![scheduled(2, 1000), scheduled(1, 3000)]);
+}
+
+#[test]
+fn test_sqlite_scheduled_messages_persist() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+    check(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check(&storage);
+}
+
+#[test]
+fn test_fs_scheduled_messages_persist() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+    check(&store);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check(&store);
+}
@@ -0,0 +1,74 @@
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::suspend::{SuspendedBlobDownload, SuspendedSession, SuspendedState};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_fs::FsStore;
+use merkle_tox_sqlite::Storage;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn state() -> SuspendedState {
+    SuspendedState {
+        suspended_at_ms: 1000,
+        sessions: vec![SuspendedSession {
+            peer_pk: PhysicalDevicePk::from([1u8; 32]),
+            conversation_id: ConversationId::from([2u8; 32]),
+            shallow: false,
+            min_rank: 0,
+            min_timestamp: 0,
+            max_backfill_nodes: 0,
+            backfill_count: 0,
+            remote_heads: vec![NodeHash::from([3u8; 32])],
+            remote_anchor_hash: None,
+            missing_admin_nodes: vec![],
+            missing_nodes_hot: vec![NodeHash::from([4u8; 32])],
+            missing_nodes_cold: vec![NodeHash::from([5u8; 32])],
+            missing_blobs: vec![],
+        }],
+        blob_downloads: vec![SuspendedBlobDownload {
+            hash: NodeHash::from([6u8; 32]),
+            seeders: vec![PhysicalDevicePk::from([1u8; 32])],
+        }],
+        paths: vec![],
+    }
+}
+
+fn fill(store: &dyn NodeStore) {
+    assert_eq!(store.get_suspended_state(), None);
+    let mut older = state();
+    older.suspended_at_ms = 1;
+    store.put_suspended_state(&older).unwrap();
+    store.put_suspended_state(&state()).unwrap();
+}
+
+fn check_and_remove(store: &dyn NodeStore) {
+    assert_eq!(store.get_suspended_state(), Some(state()));
+    store.remove_suspended_state().unwrap();
+    assert_eq!(store.get_suspended_state(), None);
+    // Removing twice is fine.
+    store.remove_suspended_state().unwrap();
+}
+
+#[test]
+fn test_sqlite_suspended_state() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+    fill(&storage);
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    check_and_remove(&storage);
+}
+
+#[test]
+fn test_fs_suspended_state_survives_reopen() {
+    let tmp_dir = TempDir::new().unwrap();
+    let root = tmp_dir.path().to_path_buf();
+    let store = FsStore::new(root.clone(), Arc::new(StdFileSystem)).unwrap();
+    fill(&store);
+
+    drop(store);
+    let store = FsStore::new(root, Arc::new(StdFileSystem)).unwrap();
+    check_and_remove(&store);
+}
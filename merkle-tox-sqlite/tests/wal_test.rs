@@ -0,0 +1,102 @@
+use merkle_tox_core::dag::{
+    Content, ConversationId, Ed25519Signature, LogicalIdentityPk, MerkleNode, NodeAuth,
+    PhysicalDevicePk,
+};
+use merkle_tox_core::sync::NodeStore;
+use merkle_tox_sqlite::{DEFAULT_READERS, Storage};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+fn make_node(seq: u64) -> MerkleNode {
+    MerkleNode {
+        parents: vec![],
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64,
+        content: Content::Text(format!("Node {}", seq)),
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+#[test]
+fn test_file_storage_uses_wal_and_read_pool() {
+    let tmp_dir = TempDir::new().unwrap();
+    let storage = Storage::open(tmp_dir.path().join("store.db")).unwrap();
+    assert_eq!(storage.reader_count(), DEFAULT_READERS);
+
+    let conn = storage.connection().lock().unwrap();
+    let mode: String = conn
+        .query_row("PRAGMA journal_mode", [], |r| r.get(0))
+        .unwrap();
+    assert_eq!(mode, "wal");
+    let timeout: i64 = conn
+        .query_row("PRAGMA busy_timeout", [], |r| r.get(0))
+        .unwrap();
+    assert!(timeout > 0);
+}
+
+#[test]
+fn test_in_memory_path_reads_its_own_writes() {
+    let storage = Storage::open(":memory:").unwrap();
+    assert_eq!(storage.reader_count(), 0);
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let node = make_node(1);
+    storage.put_node(&conv_id, node.clone(), true).unwrap();
+    assert_eq!(storage.get_node(&node.hash()), Some(node));
+}
+
+#[test]
+fn test_reads_see_writes_from_pool() {
+    let tmp_dir = TempDir::new().unwrap();
+    let storage = Storage::open(tmp_dir.path().join("store.db")).unwrap();
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let node = make_node(1);
+    storage.put_node(&conv_id, node.clone(), false).unwrap();
+    assert_eq!(storage.get_node(&node.hash()), Some(node.clone()));
+    assert!(!storage.is_verified(&node.hash()));
+
+    storage.mark_verified(&conv_id, &node.hash()).unwrap();
+    assert!(storage.is_verified(&node.hash()));
+}
+
+#[test]
+fn test_concurrent_reads_during_ingest() {
+    let tmp_dir = TempDir::new().unwrap();
+    let storage = Arc::new(Storage::open(tmp_dir.path().join("store.db")).unwrap());
+    let conv_id = ConversationId::from([1u8; 32]);
+
+    let writer = {
+        let storage = storage.clone();
+        std::thread::spawn(move || {
+            for seq in 1..=200 {
+                storage.put_node(&conv_id, make_node(seq), true).unwrap();
+            }
+        })
+    };
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                let mut last = 0;
+                for _ in 0..200 {
+                    let (verified, _) = storage.get_node_counts(&conv_id);
+                    assert!(verified >= last, "reader saw count go backwards");
+                    last = verified;
+                }
+            })
+        })
+        .collect();
+
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert_eq!(storage.get_node_counts(&conv_id), (200, 0));
+}
@@ -0,0 +1,76 @@
+use merkle_tox_core::dag::{
+    ChainKey, Content, ControlAction, ConversationId, Ed25519Signature, LogicalIdentityPk,
+    MerkleNode, NodeAuth, NodeHash, NodeLookup, PhysicalDevicePk,
+};
+use merkle_tox_core::sync::{NodeStore, StoreWrite};
+use merkle_tox_sqlite::Storage;
+use tempfile::TempDir;
+
+fn make_node(seq: u64, parents: Vec<NodeHash>, content: Content) -> MerkleNode {
+    MerkleNode {
+        parents,
+        author_pk: LogicalIdentityPk::from([1u8; 32]),
+        sender_pk: PhysicalDevicePk::from([1u8; 32]),
+        sequence_number: seq,
+        topological_rank: seq,
+        network_timestamp: 1000 + seq as i64 * 10,
+        content,
+        metadata: vec![],
+        authentication: NodeAuth::EphemeralSignature(Ed25519Signature::from([0u8; 64])),
+        pow_nonce: 0,
+    }
+}
+
+fn conv_id() -> ConversationId {
+    ConversationId::from([1u8; 32])
+}
+
+#[test]
+fn test_write_batch_commits_all_writes() {
+    let tmp_dir = TempDir::new().unwrap();
+    let path = tmp_dir.path().join("db.sqlite");
+    let storage = Storage::open(&path).unwrap();
+
+    let admin = make_node(
+        1,
+        vec![],
+        Content::Control(ControlAction::SetRetention { max_age_ms: 1000 }),
+    );
+    let first = make_node(2, vec![admin.hash()], Content::Text("one".to_string()));
+    let second = make_node(3, vec![first.hash()], Content::Text("two".to_string()));
+    let key = ChainKey::from([7u8; 32]);
+
+    storage
+        .write_batch(vec![
+            StoreWrite::Node(conv_id(), admin.clone(), true),
+            StoreWrite::Node(conv_id(), first.clone(), true),
+            StoreWrite::RatchetKey(conv_id(), first.hash(), key.clone(), 0),
+            StoreWrite::Node(conv_id(), second.clone(), false),
+            StoreWrite::RatchetKey(conv_id(), second.hash(), key.clone(), 0),
+            StoreWrite::DeleteRatchetKey(conv_id(), first.hash()),
+            StoreWrite::Heads(conv_id(), vec![second.hash()], false),
+            StoreWrite::Heads(conv_id(), vec![admin.hash()], true),
+        ])
+        .unwrap();
+
+    drop(storage);
+    let storage = Storage::open(&path).unwrap();
+    assert!(storage.is_verified(&first.hash()));
+    assert!(!storage.is_verified(&second.hash()));
+    // Distances of parents written earlier in the same batch are used.
+    assert_eq!(storage.get_admin_distance(&admin.hash()), Some(0));
+    assert_eq!(storage.get_admin_distance(&first.hash()), Some(1));
+    assert_eq!(storage.get_admin_distance(&second.hash()), Some(2));
+    assert_eq!(storage.get_heads(&conv_id()), vec![second.hash()]);
+    assert_eq!(storage.get_admin_heads(&conv_id()), vec![admin.hash()]);
+    assert!(
+        storage
+            .get_ratchet_key(&conv_id(), &first.hash())
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        storage.get_ratchet_key(&conv_id(), &second.hash()).unwrap(),
+        Some((key, 0))
+    );
+}
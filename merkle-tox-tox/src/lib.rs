@@ -1,19 +1,22 @@
 use merkle_tox_core::dag::{ConversationId, PhysicalDevicePk};
 use merkle_tox_core::error::MerkleToxResult;
-use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::node::{MerkleToxNode, ShutdownSummary};
 use merkle_tox_core::sync::{BlobStore, NodeStore};
-use merkle_tox_core::{ProtocolMessage, Transport, TransportError};
+use merkle_tox_core::{Transport, TransportError};
 use parking_lot::ReentrantMutex;
 use std::sync::Arc;
 use std::time::Instant;
 use tox_proto::PhysicalDeviceSk;
-use toxcore::tox::Tox;
 use toxcore::tox::events::Event;
+use toxcore::tox::{Tox, ToxConnection};
 use toxcore::types::PublicKey as ToxPublicKey;
 use tracing::debug;
 
 use tokio::sync::Mutex;
 
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
 pub const TOX_CUSTOM_PACKET_ID: u8 = 200;
 
 /// A transport implementation that uses real Tox custom packets.
@@ -39,6 +42,13 @@ impl Transport for ToxTransport {
             .map_err(|e| TransportError::Other(format!("{:?}", e)))?;
         Ok(())
     }
+
+    fn is_relayed(&self, to: &PhysicalDevicePk) -> bool {
+        let tox = self.tox.lock();
+        tox.lookup_friend(&ToxPublicKey(*to.as_bytes()))
+            .and_then(|friend| friend.connection_status())
+            .is_ok_and(|status| status == ToxConnection::TOX_CONNECTION_TCP)
+    }
 }
 
 /// A bridge between the Tox network and the Merkle-Tox engine.
@@ -137,10 +147,7 @@ impl<S: NodeStore + BlobStore> ToxMerkleBridge<S> {
                             peer_pk
                         );
                         node.set_peer_available(peer_pk, true);
-                        let caps = ProtocolMessage::CapsAnnounce {
-                            version: 1,
-                            features: 0,
-                        };
+                        let caps = node.engine.caps_announce();
                         node.send_message(peer_pk, caps);
                     } else {
                         debug!("Tox friend {:?} disconnected", peer_pk);
@@ -175,4 +182,9 @@ impl<S: NodeStore + BlobStore> ToxMerkleBridge<S> {
     pub async fn poll(&self) -> Instant {
         self.node.lock().await.poll()
     }
+
+    /// Flushes the node for process exit. See [`MerkleToxNode::shutdown`].
+    pub async fn shutdown(&self, deadline: Instant) -> ShutdownSummary {
+        self.node.lock().await.shutdown(deadline)
+    }
 }
@@ -0,0 +1,387 @@
+//! Prometheus metrics for long-running nodes.
+//!
+//! [`Metrics`] counts node events as they pass to the application's event
+//! handler and renders them, together with a snapshot of the engine, the
+//! store and the Tox instance, in the Prometheus text format. [`serve`]
+//! exposes the result at `/metrics` over plain HTTP; bind it to a local
+//! address only.
+
+use crate::ToxTransport;
+use merkle_tox_core::dag::NodeHash;
+use merkle_tox_core::engine::metrics::SyncPhase;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport};
+use parking_lot::Mutex as SyncMutex;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use toxcore::tox::ToxConnection;
+use toxcore::tox::events::Event;
+use tracing::debug;
+
+/// Largest request head read from a scrape.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Counters fed by node and Tox events.
+#[derive(Default)]
+pub struct Metrics {
+    nodes_verified: AtomicU64,
+    nodes_invalidated: AtomicU64,
+    blob_bytes_received: AtomicU64,
+    blobs_completed: AtomicU64,
+    /// Last progress of each blob being downloaded, to count only new bytes.
+    blob_progress: SyncMutex<HashMap<NodeHash, u64>>,
+    /// 0 for none, 1 for TCP, 2 for UDP.
+    tox_connection: AtomicU8,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Wraps `inner` so that events are counted before they reach it.
+    pub fn observe(
+        self: &Arc<Self>,
+        inner: Arc<dyn NodeEventHandler>,
+    ) -> Arc<dyn NodeEventHandler> {
+        Arc::new(ObservingHandler {
+            metrics: self.clone(),
+            inner,
+        })
+    }
+
+    /// Counts a node event.
+    pub fn record(&self, event: &NodeEvent) {
+        match event {
+            NodeEvent::NodeVerified { .. } => {
+                self.nodes_verified.fetch_add(1, Ordering::Relaxed);
+            }
+            NodeEvent::NodeInvalidated { .. } => {
+                self.nodes_invalidated.fetch_add(1, Ordering::Relaxed);
+            }
+            NodeEvent::BlobProgress { hash, received, .. } => {
+                let mut progress = self.blob_progress.lock();
+                let last = progress.insert(*hash, *received).unwrap_or(0);
+                self.blob_bytes_received
+                    .fetch_add(received.saturating_sub(last), Ordering::Relaxed);
+            }
+            NodeEvent::BlobAvailable { hash } => {
+                self.blob_progress.lock().remove(hash);
+                self.blobs_completed.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Tracks the connection status of the Tox instance. Call it with every
+    /// event read from Tox.
+    pub fn record_tox_event(&self, event: &Event<'_>) {
+        if let Event::SelfConnectionStatus(e) = event {
+            let status = match e.connection_status() {
+                ToxConnection::TOX_CONNECTION_NONE => 0,
+                ToxConnection::TOX_CONNECTION_TCP => 1,
+                ToxConnection::TOX_CONNECTION_UDP => 2,
+            };
+            self.tox_connection.store(status, Ordering::Relaxed);
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text format.
+    pub fn render<S: NodeStore + BlobStore>(
+        &self,
+        node: &MerkleToxNode<ToxTransport, S>,
+    ) -> String {
+        let mut out = Exposition::default();
+        self.render_node(node, &mut out);
+
+        let (friends, connected) = {
+            let tox = node.transport.tox.lock();
+            let friends = tox.friend_list();
+            let connected = friends
+                .iter()
+                .filter(|f| {
+                    f.connection_status()
+                        .is_ok_and(|s| s != ToxConnection::TOX_CONNECTION_NONE)
+                })
+                .count();
+            (friends.len(), connected)
+        };
+        out.family(
+            "tox_connection_status",
+            "gauge",
+            "Connection of the Tox instance: 0 none, 1 TCP, 2 UDP.",
+        );
+        out.sample(
+            "tox_connection_status",
+            &[],
+            self.tox_connection.load(Ordering::Relaxed),
+        );
+        out.family("tox_friends", "gauge", "Tox friends by connection state.");
+        out.sample("tox_friends", &[("connected", "true")], connected);
+        out.sample(
+            "tox_friends",
+            &[("connected", "false")],
+            friends - connected,
+        );
+        out.text
+    }
+
+    fn render_node<T: Transport, S: NodeStore + BlobStore>(
+        &self,
+        node: &MerkleToxNode<T, S>,
+        out: &mut Exposition,
+    ) {
+        let counters = [
+            (
+                "merkle_tox_nodes_verified_total",
+                "Nodes verified since start.",
+                &self.nodes_verified,
+            ),
+            (
+                "merkle_tox_nodes_invalidated_total",
+                "Nodes invalidated since start, e.g. by a revocation.",
+                &self.nodes_invalidated,
+            ),
+            (
+                "merkle_tox_blob_bytes_received_total",
+                "Verified blob bytes downloaded since start.",
+                &self.blob_bytes_received,
+            ),
+            (
+                "merkle_tox_blobs_completed_total",
+                "Blobs downloaded since start.",
+                &self.blobs_completed,
+            ),
+        ];
+        for (name, help, counter) in counters {
+            out.family(name, "counter", help);
+            out.sample(name, &[], counter.load(Ordering::Relaxed));
+        }
+
+        let metrics = node.engine.metrics(&node.store);
+        out.family(
+            "merkle_tox_conversations",
+            "gauge",
+            "Conversations known to the engine.",
+        );
+        out.sample("merkle_tox_conversations", &[], metrics.conversations.len());
+        out.family(
+            "merkle_tox_conversation_nodes",
+            "gauge",
+            "Nodes stored per conversation.",
+        );
+        for c in &metrics.conversations {
+            let id = hex::encode(c.conversation_id.as_bytes());
+            for (state, count) in [
+                ("verified", c.verified_nodes),
+                ("speculative", c.speculative_nodes),
+            ] {
+                out.sample(
+                    "merkle_tox_conversation_nodes",
+                    &[("conversation", &id), ("state", state)],
+                    count,
+                );
+            }
+        }
+
+        let handshake = metrics
+            .sessions
+            .iter()
+            .filter(|s| s.phase == SyncPhase::Handshake)
+            .count();
+        out.family(
+            "merkle_tox_sync_sessions",
+            "gauge",
+            "Sync sessions with peers by phase.",
+        );
+        out.sample(
+            "merkle_tox_sync_sessions",
+            &[("phase", "handshake")],
+            handshake,
+        );
+        out.sample(
+            "merkle_tox_sync_sessions",
+            &[("phase", "active")],
+            metrics.sessions.len() - handshake,
+        );
+        out.family(
+            "merkle_tox_sync_missing_nodes",
+            "gauge",
+            "Nodes known to be missing across all sessions.",
+        );
+        out.sample(
+            "merkle_tox_sync_missing_nodes",
+            &[],
+            metrics
+                .sessions
+                .iter()
+                .map(|s| s.missing_nodes)
+                .sum::<usize>(),
+        );
+        out.family(
+            "merkle_tox_sync_in_flight_fetches",
+            "gauge",
+            "Node fetches awaiting an answer across all sessions.",
+        );
+        out.sample(
+            "merkle_tox_sync_in_flight_fetches",
+            &[],
+            metrics
+                .sessions
+                .iter()
+                .map(|s| s.in_flight_fetches)
+                .sum::<usize>(),
+        );
+
+        let traffic = metrics.traffic.values().fold([0u64; 4], |mut sum, t| {
+            sum[0] += t.messages_sent;
+            sum[1] += t.messages_received;
+            sum[2] += t.bytes_sent;
+            sum[3] += t.bytes_received;
+            sum
+        });
+        out.family(
+            "merkle_tox_protocol_messages_total",
+            "counter",
+            "Protocol messages exchanged with peers.",
+        );
+        out.sample(
+            "merkle_tox_protocol_messages_total",
+            &[("direction", "sent")],
+            traffic[0],
+        );
+        out.sample(
+            "merkle_tox_protocol_messages_total",
+            &[("direction", "received")],
+            traffic[1],
+        );
+        out.family(
+            "merkle_tox_protocol_bytes_total",
+            "counter",
+            "Serialized protocol message bytes exchanged with peers.",
+        );
+        out.sample(
+            "merkle_tox_protocol_bytes_total",
+            &[("direction", "sent")],
+            traffic[2],
+        );
+        out.sample(
+            "merkle_tox_protocol_bytes_total",
+            &[("direction", "received")],
+            traffic[3],
+        );
+
+        out.family(
+            "merkle_tox_store_size_bytes",
+            "gauge",
+            "Size of the store, blobs included.",
+        );
+        out.sample("merkle_tox_store_size_bytes", &[], node.store.size_bytes());
+        out.family(
+            "merkle_tox_clock_offset_ms",
+            "gauge",
+            "Consensus offset of network time from local time.",
+        );
+        out.sample("merkle_tox_clock_offset_ms", &[], metrics.clock_offset_ms);
+    }
+}
+
+struct ObservingHandler {
+    metrics: Arc<Metrics>,
+    inner: Arc<dyn NodeEventHandler>,
+}
+
+impl NodeEventHandler for ObservingHandler {
+    fn handle_event(&self, event: NodeEvent) {
+        self.metrics.record(&event);
+        self.inner.handle_event(event);
+    }
+}
+
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, v))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+}
+
+/// Answers scrapes of `/metrics` on `listener` until it fails.
+pub async fn serve<S>(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    node: Arc<Mutex<MerkleToxNode<ToxTransport, S>>>,
+) -> io::Result<()>
+where
+    S: NodeStore + BlobStore + Send + 'static,
+{
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let metrics = metrics.clone();
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = scrape(stream, &metrics, &node).await {
+                debug!("Metrics scrape failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn scrape<S: NodeStore + BlobStore>(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    node: &Mutex<MerkleToxNode<ToxTransport, S>>,
+) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 || request.len() + n > MAX_REQUEST_BYTES {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request_line = request.split(|&b| b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&b| b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => {
+            let node = node.lock().await;
+            ("200 OK", metrics.render(&node))
+        }
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,94 @@
+use merkle_tox_core::dag::NodeHash;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_core::{NodeEvent, NodeEventHandler};
+use merkle_tox_fs::FsStore;
+use merkle_tox_tox::ToxMerkleBridge;
+use merkle_tox_tox::metrics::{self, Metrics};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use toxcore::tox::{Options, Tox};
+
+#[derive(Default)]
+struct EventLog(Mutex<Vec<NodeEvent>>);
+
+impl NodeEventHandler for EventLog {
+    fn handle_event(&self, event: NodeEvent) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+fn new_bridge(dir: &TempDir) -> ToxMerkleBridge<FsStore> {
+    let mut opts = Options::new().unwrap();
+    opts.set_local_discovery_enabled(false);
+    let tox = Tox::new(opts).unwrap();
+    let store = FsStore::new(dir.path().to_path_buf(), Arc::new(StdFileSystem)).unwrap();
+    ToxMerkleBridge::new(tox, store)
+}
+
+async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[test]
+fn test_events_are_counted_and_forwarded() {
+    let metrics = Metrics::new();
+    let log = Arc::new(EventLog::default());
+    let handler = metrics.observe(log.clone());
+
+    let hash = NodeHash::from([1u8; 32]);
+    handler.handle_event(NodeEvent::BlobProgress {
+        hash,
+        received: 100,
+        total: 300,
+    });
+    handler.handle_event(NodeEvent::BlobProgress {
+        hash,
+        received: 300,
+        total: 300,
+    });
+    handler.handle_event(NodeEvent::BlobAvailable { hash });
+    assert_eq!(log.0.lock().unwrap().len(), 3);
+
+    let dir = TempDir::new().unwrap();
+    let bridge = new_bridge(&dir);
+    let node = bridge.node.blocking_lock();
+    let text = metrics.render(&node);
+    // Progress is cumulative; each byte is counted once.
+    assert!(text.contains("\nmerkle_tox_blob_bytes_received_total 300\n"));
+    assert!(text.contains("\nmerkle_tox_blobs_completed_total 1\n"));
+    assert!(text.contains("\nmerkle_tox_nodes_verified_total 0\n"));
+    assert!(text.contains("\nmerkle_tox_sync_sessions{phase=\"active\"} 0\n"));
+    assert!(text.contains("\ntox_connection_status 0\n"));
+    assert!(text.contains("\ntox_friends{connected=\"true\"} 0\n"));
+    assert!(text.contains("# TYPE merkle_tox_store_size_bytes gauge\n"));
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let dir = TempDir::new().unwrap();
+    let bridge = new_bridge(&dir);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(metrics::serve(
+        listener,
+        Metrics::new(),
+        bridge.node.clone(),
+    ));
+
+    let response = get(addr, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    assert!(response.contains("\nmerkle_tox_conversations 0\n"));
+
+    let response = get(addr, "/").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
@@ -0,0 +1,798 @@
+//! Kotlin and Swift bindings for Merkle-Tox group chats, generated with
+//! UniFFI.
+//!
+//! An [`MtoxNode`] runs a Merkle-Tox node on its own tokio runtime. The app
+//! plugs in the transport: the node sends packets through the app's
+//! [`PacketTransport`], and the app passes it the packets it receives with
+//! [`MtoxNode::handle_packet`]. This can be a Tox instance (lossy custom
+//! packets starting with [`MTOX_TOX_PACKET_ID`]) or any other channel, such
+//! as a push relay, that delivers packets between devices by public key.
+//!
+//! Events are delivered to the app's [`EventListener`] on a dedicated
+//! thread, so listeners may call back into the node. All other methods block
+//! until done and should not be called from the UI thread.
+//!
+//! Bindings are generated from the built library, for example:
+//!
+//! ```text
+//! uniffi-bindgen generate --library libmerkle_tox_uniffi.so --language kotlin --out-dir out
+//! ```
+
+use merkle_tox_client::MerkleToxClient;
+use merkle_tox_client::state::{ChatMessage, ChatState, MemberRole};
+use merkle_tox_core::classify;
+use merkle_tox_core::clock::SystemTimeProvider;
+use merkle_tox_core::crypto::ed25519_public_key_from_seed;
+use merkle_tox_core::dag::{
+    Content, ConversationId, EmojiSource, NodeHash, PhysicalDevicePk, PhysicalDeviceSk,
+};
+use merkle_tox_core::engine::MerkleToxEngine;
+use merkle_tox_core::error::MerkleToxError;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport, TransportError};
+use merkle_tox_fs::FsStore;
+use std::collections::HashMap;
+use std::sync::{Arc, mpsc};
+use tokio::io::AsyncReadExt;
+use tokio::runtime::Runtime;
+use tokio::sync::{Mutex, Notify};
+use tracing::warn;
+
+uniffi::setup_scaffolding!();
+
+/// First byte of Tox lossy custom packets carrying Merkle-Tox data, as in
+/// `merkle-tox-tox`. It is not part of the packets exchanged with the node.
+pub const MTOX_TOX_PACKET_ID: u8 = 200;
+
+/// The Tox packet ID, exported for the bindings.
+#[uniffi::export]
+pub fn tox_packet_id() -> u8 {
+    MTOX_TOX_PACKET_ID
+}
+
+/// What a packet carries, from least to most in need of the app.
+#[derive(Debug, Clone, Copy, PartialEq, uniffi::Enum)]
+pub enum PacketKind {
+    /// Acknowledgments, pings and MTU probes.
+    Control,
+    /// A fragment of a message after the first.
+    Continuation,
+    Sync,
+    Blob,
+    Handshake,
+    /// Admin nodes and gossip, key wrap acknowledgments and re-inclusion.
+    Admin,
+    /// A new message in the conversation.
+    Content,
+    /// Malformed, or from a newer protocol version.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct PacketSummary {
+    pub kind: PacketKind,
+    pub conversation_id: Option<Vec<u8>>,
+    /// Whether the app should be woken to handle the packet.
+    pub should_wake: bool,
+}
+
+/// Classifies a packet as passed to [`MtoxNode::handle_packet`] without a
+/// node, for a push extension deciding whether to wake the app.
+#[uniffi::export]
+pub fn classify_packet(data: Vec<u8>) -> PacketSummary {
+    let summary = classify::classify_packet(&data);
+    PacketSummary {
+        kind: match summary.kind {
+            classify::PacketKind::Control => PacketKind::Control,
+            classify::PacketKind::Continuation => PacketKind::Continuation,
+            classify::PacketKind::Sync => PacketKind::Sync,
+            classify::PacketKind::Blob => PacketKind::Blob,
+            classify::PacketKind::Handshake => PacketKind::Handshake,
+            classify::PacketKind::Admin => PacketKind::Admin,
+            classify::PacketKind::Content => PacketKind::Content,
+            classify::PacketKind::Unknown => PacketKind::Unknown,
+        },
+        conversation_id: summary.conversation_id.map(|id| id.as_bytes().to_vec()),
+        should_wake: summary.should_wake(),
+    }
+}
+
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+pub enum MtoxError {
+    #[error("keys, hashes and conversation IDs are 32 bytes")]
+    InvalidKey,
+    #[error("store could not be opened: {reason}")]
+    Storage { reason: String },
+    #[error("invite link is malformed or expired")]
+    InvalidInvite,
+    #[error("conversation not joined")]
+    UnknownConversation,
+    #[error("blob not found")]
+    BlobNotFound,
+    #[error("{reason}")]
+    Failed { reason: String },
+}
+
+impl From<MerkleToxError> for MtoxError {
+    fn from(e: MerkleToxError) -> Self {
+        match e {
+            MerkleToxError::BlobNotFound(_) => MtoxError::BlobNotFound,
+            e => MtoxError::Failed {
+                reason: e.to_string(),
+            },
+        }
+    }
+}
+
+/// Delivers the node's packets. Implemented by the app.
+#[uniffi::export(with_foreign)]
+pub trait PacketTransport: Send + Sync {
+    /// Sends `data` to the device with `public_key`. Returns false if the
+    /// device is unknown or the packet could not be sent. Must not call
+    /// into the node.
+    fn send_packet(&self, public_key: Vec<u8>, data: Vec<u8>) -> bool;
+}
+
+/// Receives the node's events. Implemented by the app.
+#[uniffi::export(with_foreign)]
+pub trait EventListener: Send + Sync {
+    fn on_event(&self, event: MtoxEvent);
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Enum)]
+pub enum MtoxEvent {
+    /// A message from another device was verified. Messages sent from this
+    /// node are not reported.
+    Message {
+        conversation_id: Vec<u8>,
+        message: Message,
+    },
+    /// The conversation's state changed; read it again with
+    /// [`MtoxNode::conversation`].
+    ConversationChanged { conversation_id: Vec<u8> },
+    /// The Merkle-Tox handshake with the device `public_key` completed.
+    PeerConnected { public_key: Vec<u8> },
+    /// Part of a blob was downloaded. `received` and `total` are in bytes.
+    BlobProgress {
+        hash: Vec<u8>,
+        received: u64,
+        total: u64,
+    },
+    /// A blob was downloaded and can be read with [`MtoxNode::read_blob`].
+    BlobAvailable { hash: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Message {
+    pub hash: Vec<u8>,
+    pub author_pk: Vec<u8>,
+    pub timestamp_ms: i64,
+    pub body: MessageBody,
+    pub reactions: Vec<Reaction>,
+    pub is_redacted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Enum)]
+pub enum MessageBody {
+    Text {
+        text: String,
+    },
+    /// A file. Its data is downloaded in the background; `preview` is an
+    /// inline thumbnail available right away.
+    Blob {
+        hash: Vec<u8>,
+        name: String,
+        mime_type: String,
+        size: u64,
+        preview: Option<Vec<u8>>,
+    },
+    /// A kind of message these bindings do not expose.
+    Unsupported,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Reaction {
+    pub emoji: String,
+    pub author_pks: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Member {
+    pub public_key: Vec<u8>,
+    pub is_admin: bool,
+    pub joined_at_ms: i64,
+    pub devices: Vec<Vec<u8>>,
+}
+
+/// Materialized state of a conversation.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct Conversation {
+    pub conversation_id: Vec<u8>,
+    pub title: String,
+    pub topic: String,
+    pub members: Vec<Member>,
+    pub messages: Vec<Message>,
+}
+
+impl From<&ChatMessage> for Message {
+    fn from(m: &ChatMessage) -> Self {
+        let mut reactions: Vec<Reaction> = m
+            .reactions
+            .iter()
+            .map(|(emoji, authors)| {
+                let mut author_pks: Vec<Vec<u8>> =
+                    authors.iter().map(|pk| pk.as_bytes().to_vec()).collect();
+                author_pks.sort();
+                Reaction {
+                    emoji: emoji.clone(),
+                    author_pks,
+                }
+            })
+            .collect();
+        reactions.sort_by(|a, b| a.emoji.cmp(&b.emoji));
+        Self {
+            hash: m.hash.as_bytes().to_vec(),
+            author_pk: m.author_pk.as_bytes().to_vec(),
+            timestamp_ms: m.timestamp,
+            body: MessageBody::from(&m.content),
+            reactions,
+            is_redacted: m.is_redacted,
+        }
+    }
+}
+
+impl From<&Content> for MessageBody {
+    fn from(content: &Content) -> Self {
+        match content {
+            Content::Text(text) => MessageBody::Text { text: text.clone() },
+            Content::Blob {
+                hash,
+                name,
+                mime_type,
+                size,
+                preview,
+                ..
+            } => MessageBody::Blob {
+                hash: hash.as_bytes().to_vec(),
+                name: name.clone(),
+                mime_type: mime_type.clone(),
+                size: *size,
+                preview: preview.clone(),
+            },
+            _ => MessageBody::Unsupported,
+        }
+    }
+}
+
+impl From<&ChatState> for Conversation {
+    fn from(state: &ChatState) -> Self {
+        let mut members: Vec<Member> = state
+            .members
+            .values()
+            .map(|m| {
+                let mut devices: Vec<Vec<u8>> =
+                    m.devices.iter().map(|pk| pk.as_bytes().to_vec()).collect();
+                devices.sort();
+                Member {
+                    public_key: m.public_key.as_bytes().to_vec(),
+                    is_admin: m.role == MemberRole::Admin,
+                    joined_at_ms: m.joined_at,
+                    devices,
+                }
+            })
+            .collect();
+        members.sort_by(|a, b| a.public_key.cmp(&b.public_key));
+        Self {
+            conversation_id: state.conversation_id.as_bytes().to_vec(),
+            title: state.title.clone(),
+            topic: state.topic.clone(),
+            members,
+            messages: state.messages.iter().map(Message::from).collect(),
+        }
+    }
+}
+
+/// Transport over the app's [`PacketTransport`].
+struct ForeignTransport {
+    local_pk: PhysicalDevicePk,
+    transport: Arc<dyn PacketTransport>,
+}
+
+impl Transport for ForeignTransport {
+    fn local_pk(&self) -> PhysicalDevicePk {
+        self.local_pk
+    }
+
+    fn send_raw(&self, to: PhysicalDevicePk, data: Vec<u8>) -> Result<(), TransportError> {
+        if self.transport.send_packet(to.as_bytes().to_vec(), data) {
+            Ok(())
+        } else {
+            Err(TransportError::PeerNotFound(hex::encode(to.as_bytes())))
+        }
+    }
+}
+
+/// Queues node events for the driver task.
+struct EventQueue {
+    tx: tokio::sync::mpsc::UnboundedSender<NodeEvent>,
+    wake: Arc<Notify>,
+}
+
+impl NodeEventHandler for EventQueue {
+    fn handle_event(&self, event: NodeEvent) {
+        let _ = self.tx.send(event);
+        self.wake.notify_one();
+    }
+}
+
+type Node = MerkleToxNode<ForeignTransport, FsStore>;
+type Client = MerkleToxClient<ForeignTransport, FsStore>;
+type Clients = Arc<std::sync::Mutex<HashMap<ConversationId, Arc<Client>>>>;
+
+/// A Merkle-Tox node and the conversations it joined.
+#[derive(uniffi::Object)]
+pub struct MtoxNode {
+    /// Taken on drop to shut down without blocking.
+    runtime: Option<Runtime>,
+    node: Arc<Mutex<Node>>,
+    clients: Clients,
+    wake: Arc<Notify>,
+}
+
+#[uniffi::export]
+impl MtoxNode {
+    /// Opens a node with the 32-byte `secret_key`, storing conversations in
+    /// `store_dir`. `public_key` is the address of this device on the
+    /// transport, such as its Tox public key. Conversations joined in
+    /// earlier runs are loaded.
+    #[uniffi::constructor]
+    pub fn new(
+        secret_key: Vec<u8>,
+        public_key: Vec<u8>,
+        store_dir: String,
+        transport: Arc<dyn PacketTransport>,
+        listener: Arc<dyn EventListener>,
+    ) -> Result<Arc<Self>, MtoxError> {
+        let secret_key = key(&secret_key)?;
+        let public_key = PhysicalDevicePk::from(key(&public_key)?);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(|e| MtoxError::Failed {
+                reason: e.to_string(),
+            })?;
+        // The protocol identity is derived from the secret key, as in
+        // `ToxMerkleBridge`; the transport is addressed by `public_key`.
+        let self_pk = PhysicalDevicePk::from(ed25519_public_key_from_seed(&secret_key));
+        let engine = MerkleToxEngine::with_sk(
+            self_pk,
+            self_pk.to_logical(),
+            PhysicalDeviceSk::from(secret_key),
+            rand::SeedableRng::from_entropy(),
+            Arc::new(SystemTimeProvider),
+        );
+        let store = FsStore::new(store_dir.into(), Arc::new(StdFileSystem)).map_err(|e| {
+            MtoxError::Storage {
+                reason: e.to_string(),
+            }
+        })?;
+        let conversations = store.conversations();
+        let transport = ForeignTransport {
+            local_pk: public_key,
+            transport,
+        };
+        let mut node = MerkleToxNode::new(engine, transport, store, Arc::new(SystemTimeProvider));
+        let wake = Arc::new(Notify::new());
+        let (tx, events) = tokio::sync::mpsc::unbounded_channel();
+        node.set_event_handler(Arc::new(EventQueue {
+            tx,
+            wake: wake.clone(),
+        }));
+
+        let this = Self {
+            runtime: Some(runtime),
+            node: Arc::new(Mutex::new(node)),
+            clients: Default::default(),
+            wake,
+        };
+        for conversation_id in conversations {
+            if let Err(e) = this.block_on(this.open_conversation(conversation_id)) {
+                warn!("Failed to load conversation {:?}: {}", conversation_id, e);
+            }
+        }
+
+        let (listener_tx, listener_rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("mtox-events".to_string())
+            .spawn(move || {
+                for event in listener_rx {
+                    listener.on_event(event);
+                }
+            })
+            .map_err(|e| MtoxError::Failed {
+                reason: e.to_string(),
+            })?;
+        let driver = drive(
+            this.node.clone(),
+            this.clients.clone(),
+            events,
+            listener_tx,
+            this.wake.clone(),
+        );
+        this.runtime().spawn(driver);
+        Ok(Arc::new(this))
+    }
+
+    /// The logical identity of this node, the author key of its messages.
+    pub fn identity(&self) -> Vec<u8> {
+        let node = self.node.blocking_lock();
+        node.engine.self_logical_pk.as_bytes().to_vec()
+    }
+
+    /// Passes the node a packet received from the device `public_key`.
+    pub fn handle_packet(&self, public_key: Vec<u8>, data: Vec<u8>) -> Result<(), MtoxError> {
+        let from = PhysicalDevicePk::from(key(&public_key)?);
+        self.node.blocking_lock().handle_packet(from, &data);
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Reports a change of the device `public_key`'s reachability. On
+    /// connecting, the node starts its handshake with the device.
+    pub fn set_peer_connected(
+        &self,
+        public_key: Vec<u8>,
+        connected: bool,
+    ) -> Result<(), MtoxError> {
+        let peer_pk = PhysicalDevicePk::from(key(&public_key)?);
+        let mut node = self.node.blocking_lock();
+        node.set_peer_available(peer_pk, connected);
+        if connected {
+            let caps = node.engine.caps_announce();
+            node.send_message(peer_pk, caps);
+        }
+        drop(node);
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// IDs of the joined conversations.
+    pub fn conversations(&self) -> Vec<Vec<u8>> {
+        let clients = self.clients.lock().unwrap();
+        let mut ids: Vec<Vec<u8>> = clients.keys().map(|id| id.as_bytes().to_vec()).collect();
+        ids.sort();
+        ids
+    }
+
+    /// Joins the conversation `conversation_id`, syncing it from the device
+    /// `peer_pk` if given. Use this for conversations this node is a member
+    /// of, or whose members admit it without an invite.
+    pub fn join_conversation(
+        &self,
+        conversation_id: Vec<u8>,
+        peer_pk: Option<Vec<u8>>,
+    ) -> Result<(), MtoxError> {
+        let conversation_id = ConversationId::from(key(&conversation_id)?);
+        let peer_pk = peer_pk
+            .map(|pk| key(&pk).map(PhysicalDevicePk::from))
+            .transpose()?;
+        {
+            let mut node = self.node.blocking_lock();
+            let node = &mut *node;
+            let effects = node
+                .engine
+                .start_sync(conversation_id, peer_pk, &node.store);
+            let now = node.time_provider.now_instant();
+            let now_ms = node.time_provider.now_system_ms() as u64;
+            let mut next_wakeup = now;
+            node.process_effects(effects, now, now_ms, &mut next_wakeup)?;
+        }
+        self.block_on(self.open_conversation(conversation_id))?;
+        self.wake.notify_one();
+        Ok(())
+    }
+
+    /// Joins a conversation from an `mtox-invite:` link and returns its ID.
+    /// The inviter admits this node once it receives the node's
+    /// announcement, which is sent to the devices named in the link.
+    pub fn join_invite(&self, link: String) -> Result<Vec<u8>, MtoxError> {
+        let token = self
+            .node
+            .blocking_lock()
+            .join_from_invite(&link)
+            .map_err(|_| MtoxError::InvalidInvite)?;
+        self.block_on(self.open_conversation(token.conversation_id))?;
+        self.wake.notify_one();
+        Ok(token.conversation_id.as_bytes().to_vec())
+    }
+
+    /// Creates an invite link valid until `expires_at_ms` (UNIX time).
+    /// Requires this node to be an admin of the conversation.
+    pub fn create_invite_link(
+        &self,
+        conversation_id: Vec<u8>,
+        expires_at_ms: i64,
+    ) -> Result<String, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        Ok(self.block_on(client.create_invite_link(expires_at_ms))?)
+    }
+
+    /// Leaves a conversation. Returns the hash of the Leave node.
+    pub fn leave(&self, conversation_id: Vec<u8>) -> Result<Vec<u8>, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        self.sent(client.leave())
+    }
+
+    /// Current state of a joined conversation.
+    pub fn conversation(&self, conversation_id: Vec<u8>) -> Result<Conversation, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        Ok(Conversation::from(&self.block_on(client.state())))
+    }
+
+    /// Tells the node whether the conversation is on screen, so it syncs
+    /// first.
+    pub fn set_foreground(
+        &self,
+        conversation_id: Vec<u8>,
+        foreground: bool,
+    ) -> Result<(), MtoxError> {
+        let client = self.client(&conversation_id)?;
+        self.block_on(client.set_foreground(foreground));
+        Ok(())
+    }
+
+    /// Sends a text message. Returns the hash of its node.
+    pub fn send_message(
+        &self,
+        conversation_id: Vec<u8>,
+        text: String,
+    ) -> Result<Vec<u8>, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        self.sent(client.send_message(text))
+    }
+
+    /// Reacts to the message `target_hash` with a Unicode emoji.
+    pub fn send_reaction(
+        &self,
+        conversation_id: Vec<u8>,
+        target_hash: Vec<u8>,
+        emoji: String,
+    ) -> Result<Vec<u8>, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        let target_hash = NodeHash::from(key(&target_hash)?);
+        self.sent(client.send_reaction(target_hash, EmojiSource::Unicode(emoji)))
+    }
+
+    /// Redacts the message `target_hash`.
+    pub fn send_redaction(
+        &self,
+        conversation_id: Vec<u8>,
+        target_hash: Vec<u8>,
+        reason: String,
+    ) -> Result<Vec<u8>, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        let target_hash = NodeHash::from(key(&target_hash)?);
+        self.sent(client.send_redaction(target_hash, reason))
+    }
+
+    /// Sends a file, with an optional inline thumbnail of at most 32 KiB.
+    /// Returns the hash of its node.
+    pub fn send_blob(
+        &self,
+        conversation_id: Vec<u8>,
+        name: String,
+        mime_type: String,
+        data: Vec<u8>,
+        preview: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        let data = std::io::Cursor::new(data);
+        match preview {
+            Some(preview) => {
+                self.sent(client.send_blob_with_preview(name, mime_type, data, preview))
+            }
+            None => self.sent(client.send_blob_from_reader(name, mime_type, data)),
+        }
+    }
+
+    /// Reads a downloaded blob of a joined conversation.
+    pub fn read_blob(&self, conversation_id: Vec<u8>, hash: Vec<u8>) -> Result<Vec<u8>, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        let hash = NodeHash::from(key(&hash)?);
+        let data = self.block_on(async {
+            let mut reader = client.open_blob(&hash).await?;
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await?;
+            Ok::<_, MerkleToxError>(data)
+        })?;
+        Ok(data)
+    }
+
+    /// Stops downloading a blob. Returns false if it was not being
+    /// downloaded.
+    pub fn cancel_blob(&self, conversation_id: Vec<u8>, hash: Vec<u8>) -> Result<bool, MtoxError> {
+        let client = self.client(&conversation_id)?;
+        let hash = NodeHash::from(key(&hash)?);
+        Ok(self.block_on(client.cancel_blob(&hash))?)
+    }
+
+    /// Saves sync progress to the store before the app is suspended, as the
+    /// OS may kill it before the next wakeup. The node keeps running.
+    pub fn suspend(&self) -> Result<(), MtoxError> {
+        Ok(self.node.blocking_lock().suspend()?)
+    }
+
+    /// Restores the sync progress saved by the last `suspend`, once after
+    /// the node is created. Returns false if there was none.
+    pub fn resume(&self) -> Result<bool, MtoxError> {
+        let resumed = self.node.blocking_lock().resume()?;
+        self.wake.notify_one();
+        Ok(resumed)
+    }
+}
+
+impl MtoxNode {
+    fn runtime(&self) -> &Runtime {
+        self.runtime
+            .as_ref()
+            .expect("runtime is only taken on drop")
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime().block_on(future)
+    }
+
+    /// Runs an authoring call and wakes the driver to send the new node.
+    fn sent(
+        &self,
+        future: impl Future<Output = Result<NodeHash, MerkleToxError>>,
+    ) -> Result<Vec<u8>, MtoxError> {
+        let hash = self.block_on(future)?;
+        self.wake.notify_one();
+        Ok(hash.as_bytes().to_vec())
+    }
+
+    fn client(&self, conversation_id: &[u8]) -> Result<Arc<Client>, MtoxError> {
+        let conversation_id = ConversationId::from(key(conversation_id)?);
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&conversation_id)
+            .cloned()
+            .ok_or(MtoxError::UnknownConversation)
+    }
+
+    /// Creates the client of a conversation and loads its state, if not
+    /// done yet.
+    async fn open_conversation(&self, conversation_id: ConversationId) -> Result<(), MtoxError> {
+        if self.clients.lock().unwrap().contains_key(&conversation_id) {
+            return Ok(());
+        }
+        let client = Arc::new(MerkleToxClient::new(self.node.clone(), conversation_id));
+        let result = client.refresh_state().await;
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(conversation_id)
+            .or_insert(client);
+        Ok(result?)
+    }
+}
+
+impl Drop for MtoxNode {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+/// Polls the node until it is dropped, applying its events to the clients
+/// and forwarding them to the listener thread.
+async fn drive(
+    node: Arc<Mutex<Node>>,
+    clients: Clients,
+    mut events: tokio::sync::mpsc::UnboundedReceiver<NodeEvent>,
+    listener: mpsc::Sender<MtoxEvent>,
+    wake: Arc<Notify>,
+) {
+    loop {
+        let next_wakeup = node.lock().await.poll();
+        while let Ok(event) = events.try_recv() {
+            for reported in dispatch(&node, &clients, event).await {
+                if listener.send(reported).is_err() {
+                    return;
+                }
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_wakeup.into()) => {}
+            _ = wake.notified() => {}
+        }
+    }
+}
+
+/// Applies a node event to the clients and returns the events to report.
+async fn dispatch(node: &Arc<Mutex<Node>>, clients: &Clients, event: NodeEvent) -> Vec<MtoxEvent> {
+    let mut reported = Vec::new();
+    let conversation_id = match &event {
+        NodeEvent::NodeVerified {
+            conversation_id,
+            hash,
+            node,
+        } => {
+            if matches!(node.content, Content::Text(_) | Content::Blob { .. }) {
+                reported.push(MtoxEvent::Message {
+                    conversation_id: conversation_id.as_bytes().to_vec(),
+                    message: Message {
+                        hash: hash.as_bytes().to_vec(),
+                        author_pk: node.author_pk.as_bytes().to_vec(),
+                        timestamp_ms: node.network_timestamp,
+                        body: MessageBody::from(&node.content),
+                        reactions: Vec::new(),
+                        is_redacted: false,
+                    },
+                });
+            }
+            *conversation_id
+        }
+        NodeEvent::NodeInvalidated {
+            conversation_id, ..
+        }
+        | NodeEvent::NodeExpired {
+            conversation_id, ..
+        } => *conversation_id,
+        NodeEvent::PeerHandshakeComplete { peer_pk } => {
+            let all: Vec<Arc<Client>> = clients.lock().unwrap().values().cloned().collect();
+            for client in all {
+                let event = NodeEvent::PeerHandshakeComplete { peer_pk: *peer_pk };
+                if let Err(e) = client.handle_event(event).await {
+                    warn!("Failed to apply handshake: {}", e);
+                }
+            }
+            reported.push(MtoxEvent::PeerConnected {
+                public_key: peer_pk.as_bytes().to_vec(),
+            });
+            return reported;
+        }
+        NodeEvent::BlobProgress {
+            hash,
+            received,
+            total,
+        } => {
+            reported.push(MtoxEvent::BlobProgress {
+                hash: hash.as_bytes().to_vec(),
+                received: *received,
+                total: *total,
+            });
+            return reported;
+        }
+        NodeEvent::BlobAvailable { hash } => {
+            reported.push(MtoxEvent::BlobAvailable {
+                hash: hash.as_bytes().to_vec(),
+            });
+            return reported;
+        }
+        _ => return reported,
+    };
+    // A conversation we were admitted to after an invite has no client yet.
+    let client = clients
+        .lock()
+        .unwrap()
+        .entry(conversation_id)
+        .or_insert_with(|| Arc::new(MerkleToxClient::new(node.clone(), conversation_id)))
+        .clone();
+    if let Err(e) = client.handle_event(event).await {
+        warn!("Failed to apply event: {}", e);
+    }
+    reported.push(MtoxEvent::ConversationChanged {
+        conversation_id: conversation_id.as_bytes().to_vec(),
+    });
+    reported
+}
+
+fn key(bytes: &[u8]) -> Result<[u8; 32], MtoxError> {
+    bytes.try_into().map_err(|_| MtoxError::InvalidKey)
+}
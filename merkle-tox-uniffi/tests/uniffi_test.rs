@@ -0,0 +1,142 @@
+use merkle_tox_core::crypto::ed25519_public_key_from_seed;
+use merkle_tox_uniffi::*;
+use std::sync::{Arc, Mutex, mpsc};
+use std::time::Duration;
+
+/// Packets sent by all nodes, as (from, to, data).
+type Wire = Arc<Mutex<Vec<(Vec<u8>, Vec<u8>, Vec<u8>)>>>;
+
+struct WireTransport {
+    local_pk: Vec<u8>,
+    wire: Wire,
+}
+
+impl PacketTransport for WireTransport {
+    fn send_packet(&self, public_key: Vec<u8>, data: Vec<u8>) -> bool {
+        self.wire
+            .lock()
+            .unwrap()
+            .push((self.local_pk.clone(), public_key, data));
+        true
+    }
+}
+
+struct ChannelListener(Mutex<mpsc::Sender<MtoxEvent>>);
+
+impl EventListener for ChannelListener {
+    fn on_event(&self, event: MtoxEvent) {
+        let _ = self.0.lock().unwrap().send(event);
+    }
+}
+
+fn new_node(
+    secret_key: [u8; 32],
+    dir: &tempfile::TempDir,
+    wire: &Wire,
+) -> (Arc<MtoxNode>, mpsc::Receiver<MtoxEvent>) {
+    let public_key = ed25519_public_key_from_seed(&secret_key).to_vec();
+    let (tx, rx) = mpsc::channel();
+    let node = MtoxNode::new(
+        secret_key.to_vec(),
+        public_key.clone(),
+        dir.path().to_str().unwrap().to_string(),
+        Arc::new(WireTransport {
+            local_pk: public_key,
+            wire: wire.clone(),
+        }),
+        Arc::new(ChannelListener(Mutex::new(tx))),
+    )
+    .unwrap();
+    (node, rx)
+}
+
+#[test]
+fn test_node_lifecycle_and_errors() {
+    let dir = tempfile::tempdir().unwrap();
+    let wire = Wire::default();
+    let secret_key = [7u8; 32];
+    let (node, _events) = new_node(secret_key, &dir, &wire);
+
+    assert_eq!(
+        node.identity(),
+        ed25519_public_key_from_seed(&secret_key).to_vec()
+    );
+    assert!(node.conversations().is_empty());
+
+    let conversation_id = vec![0xAA; 32];
+    assert!(matches!(
+        node.send_message(conversation_id.clone(), "hello".to_string()),
+        Err(MtoxError::UnknownConversation)
+    ));
+    assert!(matches!(
+        node.send_message(vec![0xAA; 3], "hello".to_string()),
+        Err(MtoxError::InvalidKey)
+    ));
+    assert!(matches!(
+        node.join_invite("mtox-invite:AAAA".to_string()),
+        Err(MtoxError::InvalidInvite)
+    ));
+
+    node.join_conversation(conversation_id.clone(), None)
+        .unwrap();
+    assert_eq!(node.conversations(), vec![conversation_id.clone()]);
+    let conversation = node.conversation(conversation_id.clone()).unwrap();
+    assert_eq!(conversation.conversation_id, conversation_id);
+    assert!(conversation.members.is_empty());
+    assert!(conversation.messages.is_empty());
+    assert!(matches!(
+        node.read_blob(conversation_id, vec![0xBB; 32]),
+        Err(MtoxError::BlobNotFound)
+    ));
+
+    let (tx, _rx) = mpsc::channel();
+    let result = MtoxNode::new(
+        vec![1; 16],
+        vec![1; 32],
+        dir.path().to_str().unwrap().to_string(),
+        Arc::new(WireTransport {
+            local_pk: vec![1; 32],
+            wire: wire.clone(),
+        }),
+        Arc::new(ChannelListener(Mutex::new(tx))),
+    );
+    assert!(matches!(result, Err(MtoxError::InvalidKey)));
+}
+
+#[test]
+fn test_handshake_reported_to_listener() {
+    let wire = Wire::default();
+    let alice_dir = tempfile::tempdir().unwrap();
+    let bob_dir = tempfile::tempdir().unwrap();
+    let (alice, alice_events) = new_node([10u8; 32], &alice_dir, &wire);
+    let (bob, bob_events) = new_node([20u8; 32], &bob_dir, &wire);
+    let alice_pk = alice.identity();
+    let bob_pk = bob.identity();
+
+    alice.set_peer_connected(bob_pk.clone(), true).unwrap();
+    bob.set_peer_connected(alice_pk.clone(), true).unwrap();
+
+    let connected = |events: &mpsc::Receiver<MtoxEvent>, peer: &[u8]| {
+        events.try_iter().any(
+            |event| matches!(event, MtoxEvent::PeerConnected { public_key } if public_key == peer),
+        )
+    };
+    let (mut alice_connected, mut bob_connected) = (false, false);
+    for _ in 0..200 {
+        // Packets are delivered from this thread, never from send_packet.
+        let packets = std::mem::take(&mut *wire.lock().unwrap());
+        for (from, to, data) in packets {
+            assert_ne!(classify_packet(data.clone()).kind, PacketKind::Unknown);
+            let node = if to == alice_pk { &alice } else { &bob };
+            node.handle_packet(from, data).unwrap();
+        }
+        alice_connected |= connected(&alice_events, &bob_pk);
+        bob_connected |= connected(&bob_events, &alice_pk);
+        if alice_connected && bob_connected {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(alice_connected);
+    assert!(bob_connected);
+}
@@ -0,0 +1,286 @@
+//! Packet capture: every `ProtocolMessage` the simulated nodes exchange,
+//! stamped with virtual time, for the timeline tab and for export.
+
+use merkle_tox_core::clock::{ManualTimeProvider, TimeProvider};
+use merkle_tox_core::dag::{ConversationId, PhysicalDevicePk};
+use merkle_tox_core::{MessageDirection, MessageObserver, ProtocolMessage};
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::{self, Write as _};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Records kept before the oldest are dropped.
+pub const DEFAULT_CAPTURE_LIMIT: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord {
+    /// Virtual time since the capture started.
+    pub time: Duration,
+    /// The node that sent or received the message.
+    pub local: PhysicalDevicePk,
+    pub peer: PhysicalDevicePk,
+    pub direction: MessageDirection,
+    pub conversation_id: Option<ConversationId>,
+    pub kind: &'static str,
+    /// The serialized message.
+    pub data: Vec<u8>,
+    pub summary: String,
+}
+
+/// Which records the timeline shows. `None` matches everything.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptureFilter {
+    /// Matches records sent or received by this node.
+    pub peer: Option<PhysicalDevicePk>,
+    pub conversation_id: Option<ConversationId>,
+    pub kind: Option<&'static str>,
+}
+
+impl CaptureFilter {
+    pub fn matches(&self, record: &CaptureRecord) -> bool {
+        self.peer
+            .is_none_or(|pk| record.local == pk || record.peer == pk)
+            && self
+                .conversation_id
+                .is_none_or(|id| record.conversation_id == Some(id))
+            && self.kind.is_none_or(|kind| record.kind == kind)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub struct Capture {
+    time_provider: Arc<ManualTimeProvider>,
+    start: Instant,
+    limit: usize,
+    paused: Mutex<bool>,
+    records: Mutex<VecDeque<CaptureRecord>>,
+}
+
+impl Capture {
+    pub fn new(time_provider: Arc<ManualTimeProvider>) -> Self {
+        Self::with_limit(time_provider, DEFAULT_CAPTURE_LIMIT)
+    }
+
+    pub fn with_limit(time_provider: Arc<ManualTimeProvider>, limit: usize) -> Self {
+        let start = time_provider.now_instant();
+        Self {
+            time_provider,
+            start,
+            limit,
+            paused: Mutex::new(false),
+            records: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// An observer to install on the node `local`.
+    pub fn tap(self: &Arc<Self>, local: PhysicalDevicePk) -> Arc<dyn MessageObserver> {
+        Arc::new(CaptureTap {
+            capture: self.clone(),
+            local,
+        })
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        *self.paused.lock() = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.lock().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.records.lock().clear();
+    }
+
+    /// The records matching `filter`, oldest first.
+    pub fn records(&self, filter: &CaptureFilter) -> Vec<CaptureRecord> {
+        self.records
+            .lock()
+            .iter()
+            .filter(|r| filter.matches(r))
+            .cloned()
+            .collect()
+    }
+
+    /// Number of records matching `filter`.
+    pub fn count(&self, filter: &CaptureFilter) -> usize {
+        self.records
+            .lock()
+            .iter()
+            .filter(|r| filter.matches(r))
+            .count()
+    }
+
+    /// Up to `len` records matching `filter`, skipping the first `start`.
+    pub fn page(&self, filter: &CaptureFilter, start: usize, len: usize) -> Vec<CaptureRecord> {
+        self.records
+            .lock()
+            .iter()
+            .filter(|r| filter.matches(r))
+            .skip(start)
+            .take(len)
+            .cloned()
+            .collect()
+    }
+
+    /// Conversations seen so far, for cycling through filters.
+    pub fn conversations(&self) -> Vec<ConversationId> {
+        let seen: BTreeSet<_> = self
+            .records
+            .lock()
+            .iter()
+            .filter_map(|r| r.conversation_id)
+            .collect();
+        seen.into_iter().collect()
+    }
+
+    /// Message types seen so far, for cycling through filters.
+    pub fn kinds(&self) -> Vec<&'static str> {
+        let seen: BTreeSet<_> = self.records.lock().iter().map(|r| r.kind).collect();
+        seen.into_iter().collect()
+    }
+
+    /// Writes the records matching `filter` as a JSON document: a header
+    /// followed by one entry per message, like a pcap in text form.
+    pub fn export_json(&self, filter: &CaptureFilter, out: impl io::Write) -> io::Result<()> {
+        let records = self.records(filter);
+        let export = Export {
+            format: "merkle-tox-capture",
+            version: 1,
+            records: records.iter().map(ExportRecord::from).collect(),
+        };
+        serde_json::to_writer_pretty(out, &export).map_err(io::Error::other)
+    }
+
+    fn record(
+        &self,
+        local: PhysicalDevicePk,
+        direction: MessageDirection,
+        peer: PhysicalDevicePk,
+        msg: &ProtocolMessage,
+    ) {
+        if self.is_paused() {
+            return;
+        }
+        let record = CaptureRecord {
+            time: self
+                .time_provider
+                .now_instant()
+                .saturating_duration_since(self.start),
+            local,
+            peer,
+            direction,
+            conversation_id: msg.conversation_id(),
+            kind: msg.name(),
+            data: tox_proto::serialize(msg).unwrap_or_default(),
+            summary: summarize(msg),
+        };
+        let mut records = self.records.lock();
+        if records.len() >= self.limit {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+}
+
+struct CaptureTap {
+    capture: Arc<Capture>,
+    local: PhysicalDevicePk,
+}
+
+impl MessageObserver for CaptureTap {
+    fn observe(
+        &self,
+        direction: MessageDirection,
+        peer_pk: PhysicalDevicePk,
+        msg: &ProtocolMessage,
+    ) {
+        self.capture.record(self.local, direction, peer_pk, msg);
+    }
+}
+
+/// Length the `Debug` rendering of a message is cut to.
+const SUMMARY_LEN: usize = 160;
+
+/// The `Debug` rendering of `msg`, cut short without formatting all of a
+/// large message.
+fn summarize(msg: &ProtocolMessage) -> String {
+    let mut out = Truncated(String::new());
+    if write!(out, "{:?}", msg).is_err() {
+        out.0.push_str("...");
+    }
+    out.0
+}
+
+/// A writer that fails once `SUMMARY_LEN` is reached, aborting the
+/// formatting.
+struct Truncated(String);
+
+impl fmt::Write for Truncated {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = SUMMARY_LEN - self.0.len();
+        if s.len() <= room {
+            self.0.push_str(s);
+            return Ok(());
+        }
+        let mut end = room;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.0.push_str(&s[..end]);
+        Err(fmt::Error)
+    }
+}
+
+#[derive(Serialize)]
+struct Export {
+    format: &'static str,
+    version: u32,
+    records: Vec<ExportRecord>,
+}
+
+#[derive(Serialize)]
+struct ExportRecord {
+    time_us: u64,
+    local: String,
+    peer: String,
+    direction: &'static str,
+    conversation_id: Option<String>,
+    kind: &'static str,
+    len: usize,
+    summary: String,
+    data: String,
+}
+
+impl From<&CaptureRecord> for ExportRecord {
+    fn from(r: &CaptureRecord) -> Self {
+        Self {
+            time_us: r.time.as_micros() as u64,
+            local: hex::encode(r.local.as_bytes()),
+            peer: hex::encode(r.peer.as_bytes()),
+            direction: match r.direction {
+                MessageDirection::Sent => "sent",
+                MessageDirection::Received => "received",
+            },
+            conversation_id: r.conversation_id.map(|id| hex::encode(id.as_bytes())),
+            kind: r.kind,
+            len: r.data.len(),
+            summary: r.summary.clone(),
+            data: hex::encode(&r.data),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Runs a scenario without the UI and reports metrics, for CI performance
+//! regression checks.
+
+use crate::model::{Model, NodeWrapper, Scenario};
+use crate::msg::Msg;
+use crate::scenario::{Action, NetworkConditions, ScenarioFile};
+use crate::update;
+use merkle_tox_core::clock::TimeProvider;
+use merkle_tox_core::dag::{Content, KConv};
+use merkle_tox_core::engine::{Conversation, ConversationData, Effect, conversation};
+use merkle_tox_core::sync::NodeStore;
+use rand::RngCore;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Key every headless node starts with, standing in for the invite flow the
+/// scenarios are not about.
+const SHARED_K_CONV: [u8; 32] = [0x11; 32];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    pub scenario: String,
+    pub nodes: usize,
+    pub steps: u64,
+    pub virtual_secs: f64,
+    pub messages_authored: u64,
+    /// Whether every node ended up with the same, non-empty set of heads.
+    pub converged: bool,
+    /// Virtual seconds from the last scheduled event until the swarm
+    /// converged.
+    pub convergence_secs: Option<f64>,
+    pub heads: usize,
+    pub packets_sent: u64,
+    pub bytes_on_wire: u64,
+    pub packets_dropped: u64,
+    pub retransmits: u64,
+}
+
+impl Metrics {
+    pub const CSV_HEADER: &'static str = "scenario,nodes,steps,virtual_secs,messages_authored,\
+        converged,convergence_secs,heads,packets_sent,bytes_on_wire,packets_dropped,retransmits";
+
+    /// One CSV row matching `CSV_HEADER`.
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{:.3},{},{},{},{},{},{},{},{}",
+            self.scenario,
+            self.nodes,
+            self.steps,
+            self.virtual_secs,
+            self.messages_authored,
+            self.converged,
+            self.convergence_secs
+                .map(|s| format!("{:.3}", s))
+                .unwrap_or_default(),
+            self.heads,
+            self.packets_sent,
+            self.bytes_on_wire,
+            self.packets_dropped,
+            self.retransmits,
+        )
+    }
+}
+
+/// Runs `scenario` to completion: until its duration is up or, if it asks
+/// for it, until all events have fired and the swarm has converged.
+pub fn run(scenario: &ScenarioFile) -> Metrics {
+    let mut model = Model::new(
+        scenario.nodes,
+        0,
+        scenario.rate,
+        false,
+        scenario.seed,
+        scenario.topology,
+    );
+    apply_network(&mut model, scenario.network);
+
+    let dt = Duration::from_millis(scenario.tick_ms);
+    let duration = Duration::from_secs_f64(scenario.duration_secs);
+    let mut events = scenario.events.iter().peekable();
+    let mut messages_authored = 0;
+    let mut last_event = Duration::ZERO;
+    let mut converged_at = None;
+
+    while model.virtual_elapsed < duration {
+        establish_keys(&mut model);
+        while let Some(event) =
+            events.next_if(|e| Duration::from_secs_f64(e.at_secs) <= model.virtual_elapsed)
+        {
+            messages_authored += apply(&mut model, &event.action);
+            last_event = model.virtual_elapsed;
+            converged_at = None;
+        }
+        share_signing_keys(&mut model);
+
+        update::update(&mut model, Msg::Tick(dt));
+
+        let (synced, heads) = model.get_convergence_stats();
+        if synced == model.nodes.len() && heads > 0 {
+            converged_at.get_or_insert(model.virtual_elapsed);
+            if scenario.stop_when_converged && events.peek().is_none() {
+                break;
+            }
+        } else {
+            converged_at = None;
+        }
+    }
+
+    let hub = model.hub.stats();
+    let retransmits = model
+        .nodes
+        .iter()
+        .flat_map(|n| n.node.status(&model.conversation_id).sessions.into_values())
+        .map(|s| s.retransmit_count)
+        .sum();
+    let (_, heads) = model.get_convergence_stats();
+    Metrics {
+        scenario: scenario.name.clone(),
+        nodes: model.nodes.len(),
+        steps: model.steps,
+        virtual_secs: model.virtual_elapsed.as_secs_f64(),
+        messages_authored,
+        converged: converged_at.is_some(),
+        convergence_secs: converged_at.map(|t| t.saturating_sub(last_event).as_secs_f64()),
+        heads,
+        packets_sent: hub.packets_sent,
+        bytes_on_wire: hub.bytes_sent,
+        packets_dropped: hub.packets_dropped,
+        retransmits,
+    }
+}
+
+fn apply_network(model: &mut Model, network: NetworkConditions) {
+    model.loss_rate = network.loss;
+    model.latency_ms = network.latency_ms;
+    model.jitter_rate = network.jitter;
+}
+
+/// Applies one scheduled action. Returns the number of messages authored.
+fn apply(model: &mut Model, action: &Action) -> u64 {
+    let now = model.time_provider.now_instant();
+    match action {
+        Action::Network(network) => apply_network(model, *network),
+        Action::Rate { rate } => model.msg_rate = *rate,
+        Action::Messages { node, count } => {
+            let mut authored = 0;
+            for i in 0..*count {
+                let idx = node.unwrap_or(i % model.nodes.len());
+                if author(model, idx) {
+                    authored += 1;
+                }
+            }
+            return authored;
+        }
+        Action::Partition => model.partition_in_halves(),
+        Action::Heal => model.hub.clear_partitions(),
+        Action::Blackout { node, secs } => {
+            let pk = model.nodes[*node].node.engine.self_pk;
+            model
+                .hub
+                .set_blackout(pk, now + Duration::from_secs_f64(*secs));
+        }
+        Action::LateJoiner => model.active_scenario = Some(Scenario::LateJoiner),
+        Action::RotateKey { node } => {
+            let idx = node.unwrap_or(model.rng.next_u32() as usize % model.nodes.len());
+            let conversation_id = model.conversation_id;
+            let n = &mut model.nodes[idx];
+            if let Ok(effects) = n
+                .node
+                .engine
+                .rotate_conversation_key(conversation_id, &n.node.store)
+            {
+                process(n, effects);
+            }
+        }
+        Action::Blob => {
+            model.blob_hash = None;
+            model.active_scenario = Some(Scenario::LargeBlobSwarm);
+        }
+    }
+    0
+}
+
+fn author(model: &mut Model, idx: usize) -> bool {
+    let conversation_id = model.conversation_id;
+    let n = &mut model.nodes[idx];
+    let effects = n.node.engine.author_node(
+        conversation_id,
+        Content::Text(format!("Msg from {:?}", n.node.engine.self_pk)),
+        vec![],
+        &n.node.store,
+    );
+    match effects {
+        Ok(effects) => {
+            process(n, effects);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn process(n: &mut NodeWrapper, effects: Vec<Effect>) {
+    let now = n.node.time_provider.now_instant();
+    let now_ms = n.node.time_provider.now_system_ms() as u64;
+    let mut dummy_wakeup = now;
+    for effect in effects {
+        let _ = n
+            .node
+            .process_effect(effect, now, now_ms, &mut dummy_wakeup);
+    }
+}
+
+/// Gives every node, including late joiners, the shared conversation key.
+fn establish_keys(model: &mut Model) {
+    let k_conv = KConv::from(SHARED_K_CONV);
+    let now_ms = model.time_provider.now_system_ms();
+    let conversation_id = model.conversation_id;
+    for n in &mut model.nodes {
+        if matches!(
+            n.node.engine.conversations.get(&conversation_id),
+            Some(Conversation::Established(_))
+        ) {
+            continue;
+        }
+        let _ = n
+            .node
+            .store
+            .put_conversation_key(&conversation_id, 0, k_conv.clone());
+        n.node.engine.conversations.insert(
+            conversation_id,
+            Conversation::Established(ConversationData::<conversation::Established>::new(
+                conversation_id,
+                k_conv.clone(),
+                now_ms,
+            )),
+        );
+    }
+}
+
+/// Hands each node's ephemeral signing keys to every other node, standing in
+/// for SenderKeyDistribution.
+fn share_signing_keys(model: &mut Model) {
+    let keys: Vec<_> = model
+        .nodes
+        .iter()
+        .flat_map(|n| {
+            let pk = n.node.engine.self_pk;
+            n.node
+                .engine
+                .self_ephemeral_signing_keys
+                .iter()
+                .map(move |(epoch, sk)| ((pk, *epoch), sk.verifying_key().to_bytes()))
+        })
+        .collect();
+    for n in &mut model.nodes {
+        for (key, vk) in &keys {
+            if key.0 != n.node.engine.self_pk {
+                n.node
+                    .engine
+                    .peer_ephemeral_signing_keys
+                    .insert(*key, (*vk).into());
+            }
+        }
+    }
+}
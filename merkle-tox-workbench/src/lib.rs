@@ -1,4 +1,7 @@
+pub mod capture;
+pub mod headless;
 pub mod model;
 pub mod msg;
+pub mod scenario;
 pub mod ui;
 pub mod update;
@@ -6,16 +6,23 @@ use crossterm::{
 };
 use ratatui::{Terminal, backend::CrosstermBackend};
 use std::io;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
-use merkle_tox_workbench::model::{Args, Model};
+use merkle_tox_workbench::headless::{self, Metrics};
+use merkle_tox_workbench::model::{Args, Model, OutputFormat};
 use merkle_tox_workbench::msg::{Cmd, Msg};
+use merkle_tox_workbench::scenario::ScenarioFile;
 use merkle_tox_workbench::ui;
 use merkle_tox_workbench::update;
 
 fn main() -> Result<(), io::Error> {
     let args = Args::parse();
 
+    if let Some(path) = &args.headless {
+        return run_headless(path, args.format);
+    }
+
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
@@ -66,3 +73,25 @@ fn main() -> Result<(), io::Error> {
 
     Ok(())
 }
+
+fn run_headless(path: &Path, format: OutputFormat) -> Result<(), io::Error> {
+    let scenario = ScenarioFile::load(path).map_err(io::Error::other)?;
+    let metrics = headless::run(&scenario);
+    match format {
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&metrics).map_err(io::Error::other)?
+        ),
+        OutputFormat::Csv => {
+            println!("{}", Metrics::CSV_HEADER);
+            println!("{}", metrics.to_csv_row());
+        }
+    }
+    if !metrics.converged {
+        return Err(io::Error::other(format!(
+            "scenario {} did not converge within {}s",
+            scenario.name, scenario.duration_secs
+        )));
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use crate::capture::{Capture, CaptureFilter};
 use clap::Parser;
 use crossbeam::channel::Receiver;
 use merkle_tox_core::clock::ManualTimeProvider;
@@ -14,6 +15,7 @@ use parking_lot::ReentrantMutex;
 use rand::{RngCore, SeedableRng, rngs::StdRng};
 use ratatui::widgets::TableState;
 use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use toxcore::tox::{Options as ToxOptions, Tox};
@@ -45,9 +47,26 @@ pub struct Args {
     /// Network topology template.
     #[arg(short = 'T', long, value_enum, default_value_t = Topology::Mesh)]
     pub topology: Topology,
+
+    /// Run this scenario file without the UI and print its metrics. Exits
+    /// with an error if the swarm does not converge.
+    #[arg(long, value_name = "SCENARIO")]
+    pub headless: Option<PathBuf>,
+
+    /// Metrics format for headless runs.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+    pub format: OutputFormat,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    /// A header line followed by one row.
+    Csv,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Topology {
     /// Every node connected to every other node.
     Mesh,
@@ -162,6 +181,13 @@ pub struct Model {
     pub active_scenario: Option<Scenario>,
     pub scenario_timer: Option<Instant>,
     pub blob_hash: Option<NodeHash>,
+    pub capture: Arc<Capture>,
+    // Capture Tab State
+    pub capture_filter: CaptureFilter,
+    pub capture_state: TableState,
+    /// Keep the newest record selected as new ones arrive.
+    pub capture_follow: bool,
+    pub capture_status: Option<String>,
     // Settings Tab State
     pub settings_cursor: usize,
     pub edit_nodes: usize,
@@ -203,6 +229,7 @@ impl Model {
 
         let hub = Arc::new(VirtualHub::new(time_provider.clone()));
         hub.set_seed(seed);
+        let capture = Arc::new(Capture::new(time_provider.clone()));
         let mut seed_rng = StdRng::seed_from_u64(seed);
         let conversation_id = ConversationId::from([0x42u8; 32]);
         let mut nodes = Vec::new();
@@ -228,7 +255,7 @@ impl Model {
                     time_provider.clone(),
                 );
                 let store = InMemoryStore::new();
-                let node = MerkleToxNode::new(
+                let mut node = MerkleToxNode::new(
                     engine,
                     GenericTransport::Tox {
                         transport,
@@ -237,6 +264,7 @@ impl Model {
                     store,
                     time_provider.clone(),
                 );
+                node.set_message_observer(capture.tap(pk));
                 nodes.push(NodeWrapper {
                     node,
                     rx: None,
@@ -287,12 +315,13 @@ impl Model {
                 StdRng::seed_from_u64(seed_rng.next_u64()),
                 time_provider.clone(),
             );
-            let node = MerkleToxNode::new(
+            let mut node = MerkleToxNode::new(
                 engine,
                 GenericTransport::Sim(transport),
                 store,
                 time_provider.clone(),
             );
+            node.set_message_observer(capture.tap(pk));
 
             nodes.push(NodeWrapper {
                 node,
@@ -413,6 +442,11 @@ impl Model {
             active_scenario: None,
             scenario_timer: None,
             blob_hash: None,
+            capture,
+            capture_filter: CaptureFilter::default(),
+            capture_state: TableState::default(),
+            capture_follow: true,
+            capture_status: None,
             settings_cursor: 0,
             edit_nodes: num_nodes,
             edit_real_nodes: num_real,
@@ -455,6 +489,22 @@ impl Model {
         }
     }
 
+    /// Splits the swarm into two partitions, first half and second half.
+    pub fn partition_in_halves(&self) {
+        let mut p1 = HashSet::new();
+        let mut p2 = HashSet::new();
+        for (i, n) in self.nodes.iter().enumerate() {
+            if i < self.nodes.len() / 2 {
+                p1.insert(n.node.engine.self_pk);
+            } else {
+                p2.insert(n.node.engine.self_pk);
+            }
+        }
+        self.hub.clear_partitions();
+        self.hub.add_partition(p1);
+        self.hub.add_partition(p2);
+    }
+
     pub fn get_convergence_stats(&self) -> (usize, usize) {
         let mut all_heads = HashSet::new();
         for n in &self.nodes {
@@ -0,0 +1,222 @@
+//! Scripted scenarios for headless runs.
+//!
+//! A scenario is a TOML file describing the swarm and a schedule of network
+//! changes and workloads:
+//!
+//! ```toml
+//! name = "partition-heal"
+//! nodes = 6
+//! topology = "mesh"
+//! duration_secs = 60
+//!
+//! [network]
+//! latency_ms = 40
+//! jitter = 0.1
+//!
+//! [[event]]
+//! at_secs = 1
+//! action = "messages"
+//! count = 20
+//!
+//! [[event]]
+//! at_secs = 5
+//! action = "partition"
+//!
+//! [[event]]
+//! at_secs = 15
+//! action = "heal"
+//! ```
+
+use crate::model::Topology;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioFile {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_nodes")]
+    pub nodes: usize,
+    #[serde(default = "default_seed")]
+    pub seed: u64,
+    #[serde(default = "default_topology")]
+    pub topology: Topology,
+    /// Background authoring rate (messages per second), as in the UI.
+    #[serde(default)]
+    pub rate: f32,
+    /// Virtual time after which the run stops, converged or not.
+    pub duration_secs: f64,
+    /// Virtual time advanced per simulation step.
+    #[serde(default = "default_tick_ms")]
+    pub tick_ms: u64,
+    /// Stop as soon as every event has fired and the swarm has converged,
+    /// instead of running for the full duration.
+    #[serde(default = "default_true")]
+    pub stop_when_converged: bool,
+    /// Network conditions at the start of the run.
+    #[serde(default)]
+    pub network: NetworkConditions,
+    #[serde(default, rename = "event")]
+    pub events: Vec<ScheduledEvent>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConditions {
+    #[serde(default)]
+    pub loss: f32,
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub jitter: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ScheduledEvent {
+    pub at_secs: f64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Replaces the current loss, latency and jitter.
+    Network(NetworkConditions),
+    /// Changes the background authoring rate.
+    Rate {
+        rate: f32,
+    },
+    /// Authors `count` text messages at once, from `node` or round-robin
+    /// over all nodes.
+    Messages {
+        #[serde(default)]
+        node: Option<usize>,
+        count: usize,
+    },
+    /// Splits the swarm into two halves.
+    Partition,
+    Heal,
+    /// Cuts `node` off the network for `secs`.
+    Blackout {
+        node: usize,
+        secs: f64,
+    },
+    /// Adds a fresh node that syncs with everyone.
+    LateJoiner,
+    /// Rotates the conversation key on `node`, or on a random node.
+    RotateKey {
+        #[serde(default)]
+        node: Option<usize>,
+    },
+    /// Seeds a 1 MiB blob from the first node.
+    Blob,
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl std::error::Error for ScenarioError {}
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "failed to read scenario: {}", e),
+            ScenarioError::Parse(e) => write!(f, "failed to parse scenario: {}", e),
+            ScenarioError::Invalid(msg) => write!(f, "invalid scenario: {}", msg),
+        }
+    }
+}
+
+impl ScenarioFile {
+    pub fn load(path: &Path) -> Result<Self, ScenarioError> {
+        let text = std::fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        let mut scenario = Self::parse(&text)?;
+        if scenario.name.is_empty()
+            && let Some(stem) = path.file_stem()
+        {
+            scenario.name = stem.to_string_lossy().into_owned();
+        }
+        Ok(scenario)
+    }
+
+    /// Parses and validates a scenario. Events are sorted by time.
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        let mut scenario: Self = toml::from_str(text).map_err(ScenarioError::Parse)?;
+        scenario.validate()?;
+        scenario
+            .events
+            .sort_by(|a, b| a.at_secs.total_cmp(&b.at_secs));
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<(), ScenarioError> {
+        let invalid = |msg: String| Err(ScenarioError::Invalid(msg));
+        if self.nodes == 0 {
+            return invalid("nodes must be at least 1".to_string());
+        }
+        if self.duration_secs.is_nan() || self.duration_secs <= 0.0 {
+            return invalid("duration_secs must be positive".to_string());
+        }
+        if self.tick_ms == 0 {
+            return invalid("tick_ms must be positive".to_string());
+        }
+        for event in &self.events {
+            if event.at_secs.is_nan() || event.at_secs < 0.0 || event.at_secs > self.duration_secs {
+                return invalid(format!(
+                    "event at {}s is outside the run's duration",
+                    event.at_secs
+                ));
+            }
+            if let Action::Blackout { secs, .. } = event.action
+                && (secs.is_nan() || secs < 0.0)
+            {
+                return invalid(format!(
+                    "blackout at {}s has a negative length",
+                    event.at_secs
+                ));
+            }
+            // Late joiners may push the node count up, so only the initial
+            // nodes can be addressed.
+            let node = match &event.action {
+                Action::Messages { node, .. } | Action::RotateKey { node } => *node,
+                Action::Blackout { node, .. } => Some(*node),
+                _ => None,
+            };
+            if let Some(node) = node
+                && node >= self.nodes
+            {
+                return invalid(format!(
+                    "event at {}s targets node {} of {}",
+                    event.at_secs, node, self.nodes
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_nodes() -> usize {
+    5
+}
+
+fn default_seed() -> u64 {
+    4
+}
+
+fn default_topology() -> Topology {
+    Topology::Mesh
+}
+
+fn default_tick_ms() -> u64 {
+    50
+}
+
+fn default_true() -> bool {
+    true
+}
@@ -1,8 +1,9 @@
 use crate::model::{GenericTransport, Model};
-use merkle_tox_core::Transport;
 use merkle_tox_core::cas::{BlobStatus, CHUNK_SIZE};
-use merkle_tox_core::engine::session::PeerSession;
+use merkle_tox_core::dag::PhysicalDevicePk;
+use merkle_tox_core::engine::metrics::SyncPhase;
 use merkle_tox_core::sync::{BlobStore, NodeStore};
+use merkle_tox_core::{MessageDirection, Transport};
 use ratatui::{
     Frame,
     layout::{Constraint, Direction, Layout, Rect},
@@ -10,7 +11,8 @@ use ratatui::{
     prelude::Span,
     style::{Color, Modifier, Style},
     widgets::{
-        Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, Tabs, canvas,
+        Axis, Block, Borders, Cell, Chart, Dataset, GraphType, Paragraph, Row, Table, TableState,
+        Tabs, Wrap, canvas,
     },
 };
 use std::collections::{HashMap, HashSet};
@@ -75,6 +77,7 @@ pub fn ui(f: &mut Frame, model: &mut Model) {
         " DAG Viewer ",
         " Topology ",
         " Settings ",
+        " Capture ",
     ];
     let tabs = Tabs::new(titles)
         .block(Block::default().borders(Borders::ALL).title(" Tabs "))
@@ -105,6 +108,7 @@ pub fn ui(f: &mut Frame, model: &mut Model) {
         1 => render_dag_tab(f, model, rects[2], footer_chunks[1]),
         2 => render_topology_tab(f, model, rects[2], footer_chunks[1]),
         3 => render_settings_tab(f, model, rects[2], footer_chunks[1]),
+        4 => render_capture_tab(f, model, rects[2], footer_chunks[1]),
         _ => {}
     }
 
@@ -197,6 +201,131 @@ fn render_settings_tab(f: &mut Frame, model: &mut Model, area: Rect, info_area:
     f.render_widget(info, info_area);
 }
 
+fn render_capture_tab(f: &mut Frame, model: &mut Model, area: Rect, info_area: Rect) {
+    let short = |pk: &PhysicalDevicePk| hex::encode(&pk.as_bytes()[..4]);
+    let total = model.capture.count(&model.capture_filter);
+    // Borders and header row.
+    let visible = (area.height as usize).saturating_sub(3).max(1);
+    let selected = if model.capture_follow || model.capture_state.selected().is_none() {
+        total.saturating_sub(1)
+    } else {
+        model
+            .capture_state
+            .selected()
+            .unwrap_or(0)
+            .min(total.saturating_sub(1))
+    };
+    model.capture_state.select((total > 0).then_some(selected));
+
+    // Only the visible window is fetched; the capture can hold far more.
+    let start = (selected + 1).saturating_sub(visible);
+    let page = model.capture.page(&model.capture_filter, start, visible);
+
+    let header = Row::new(
+        ["Time", "From", "To", "Type", "Conv", "Len"]
+            .iter()
+            .map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow))),
+    )
+    .height(1);
+    let rows = page.iter().map(|r| {
+        let (from, to) = match r.direction {
+            MessageDirection::Sent => (&r.local, &r.peer),
+            MessageDirection::Received => (&r.peer, &r.local),
+        };
+        let style = match r.direction {
+            MessageDirection::Sent => Style::default().fg(Color::White),
+            MessageDirection::Received => Style::default().fg(Color::Gray),
+        };
+        Row::new(vec![
+            Cell::from(format!("{:>9.3}", r.time.as_secs_f64())),
+            Cell::from(short(from)),
+            Cell::from(short(to)),
+            Cell::from(r.kind),
+            Cell::from(
+                r.conversation_id
+                    .map_or("-".to_string(), |id| hex::encode(&id.as_bytes()[..4])),
+            ),
+            Cell::from(r.data.len().to_string()),
+        ])
+        .style(style)
+    });
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(10),
+            Constraint::Length(9),
+            Constraint::Length(9),
+            Constraint::Min(20),
+            Constraint::Length(9),
+            Constraint::Length(7),
+        ],
+    )
+    .header(header)
+    .block(Block::default().borders(Borders::ALL).title(format!(
+        " Message Timeline ({} of {} captured{}) ",
+        total,
+        model.capture.len(),
+        if model.capture.is_paused() {
+            ", PAUSED"
+        } else {
+            ""
+        }
+    )))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    let mut page_state = TableState::default();
+    page_state.select((total > 0).then_some(selected - start));
+    f.render_stateful_widget(table, area, &mut page_state);
+
+    let filter = &model.capture_filter;
+    let mut info_lines = vec![Line::from(format!(
+        " Filter: node {} | conv {} | type {}",
+        filter.peer.as_ref().map_or("*".to_string(), short),
+        filter
+            .conversation_id
+            .map_or("*".to_string(), |id| hex::encode(&id.as_bytes()[..4])),
+        filter.kind.unwrap_or("*"),
+    ))];
+    if let Some(r) = page.get(selected - start) {
+        info_lines.push(Line::from(format!(
+            " {:?} {} {} at {:.3}s, {} bytes",
+            r.direction,
+            if r.direction == MessageDirection::Sent {
+                "to"
+            } else {
+                "from"
+            },
+            short(&r.peer),
+            r.time.as_secs_f64(),
+            r.data.len()
+        )));
+        info_lines.push(Line::from(format!(" {}", r.summary)));
+    }
+    info_lines.push(Line::from(""));
+    info_lines.push(Line::from(vec![
+        Span::styled(" n/c/t", Style::default().fg(Color::Cyan)),
+        Span::raw(": Filter node/conv/type | "),
+        Span::styled("x", Style::default().fg(Color::Cyan)),
+        Span::raw(": Clear filter"),
+    ]));
+    info_lines.push(Line::from(vec![
+        Span::styled(" Up/Down/End", Style::default().fg(Color::Cyan)),
+        Span::raw(": Scroll/Follow | "),
+        Span::styled("e", Style::default().fg(Color::Cyan)),
+        Span::raw(": Export JSON | "),
+        Span::styled("z", Style::default().fg(Color::Cyan)),
+        Span::raw(": Pause | "),
+        Span::styled("C", Style::default().fg(Color::Cyan)),
+        Span::raw(": Clear"),
+    ]));
+    if let Some(status) = &model.capture_status {
+        info_lines.push(Line::from(format!(" {}", status)));
+    }
+    let info = Paragraph::new(info_lines)
+        .wrap(Wrap { trim: false })
+        .block(Block::default().borders(Borders::ALL).title(" Capture "));
+    f.render_widget(info, info_area);
+}
+
 fn render_impairment_panel(f: &mut Frame, model: &Model, area: Rect) {
     let loss_pct = (model.loss_rate * 100.0) as u64;
     let jitter_pct = (model.jitter_rate * 100.0) as u64;
@@ -734,18 +863,19 @@ fn render_topology_tab(f: &mut Frame, model: &mut Model, area: Rect, info_area:
 
             // Draw links
             for (i, n) in model.nodes.iter().enumerate() {
-                for ((peer_pk, cid), session) in &n.node.engine.sessions {
-                    if cid != &model.conversation_id {
+                let metrics = n.node.engine.metrics(&n.node.store);
+                for session in &metrics.sessions {
+                    if session.conversation_id != model.conversation_id {
                         continue;
                     }
                     // Find peer index
                     if let Some(j) = model
                         .nodes
                         .iter()
-                        .position(|pn| &pn.node.engine.self_pk == peer_pk)
+                        .position(|pn| pn.node.engine.self_pk == session.peer_pk)
                         && i < j
                     {
-                        let color = if matches!(session, PeerSession::Active(_)) {
+                        let color = if session.phase == SyncPhase::Active {
                             Color::Green
                         } else {
                             Color::DarkGray
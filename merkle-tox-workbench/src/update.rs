@@ -1,3 +1,4 @@
+use crate::capture::CaptureFilter;
 use crate::model::{GenericTransport, MetricHistory, Model, NodeWrapper, Scenario, Topology};
 use crate::msg::{Cmd, Msg};
 use crossterm::event::{Event as CrosstermEvent, KeyCode};
@@ -10,7 +11,8 @@ use merkle_tox_core::testing::gateway::TOX_BRIDGED_PACKET_ID;
 use merkle_tox_core::testing::{InMemoryStore, SimulatedTransport};
 use merkle_tox_tox::TOX_CUSTOM_PACKET_ID;
 use rand::{RngCore, SeedableRng, rngs::StdRng};
-use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufWriter;
 use std::time::Duration;
 use toxcore::tox::events::Event as ToxEvent;
 
@@ -28,10 +30,10 @@ fn handle_input(model: &mut Model, event: CrosstermEvent) -> Vec<Cmd> {
         match key.code {
             KeyCode::Char('q') => cmds.push(Cmd::Quit),
             KeyCode::Tab => {
-                model.current_tab = (model.current_tab + 1) % 4;
+                model.current_tab = (model.current_tab + 1) % 5;
             }
             KeyCode::BackTab => {
-                model.current_tab = (model.current_tab + 3) % 4;
+                model.current_tab = (model.current_tab + 4) % 5;
             }
             KeyCode::Char(' ') => {
                 model.is_paused = !model.is_paused;
@@ -94,6 +96,8 @@ fn handle_input(model: &mut Model, event: CrosstermEvent) -> Vec<Cmd> {
                 }
                 _ => {}
             }
+        } else if model.current_tab == 4 {
+            handle_capture_input(model, key.code);
         } else {
             match key.code {
                 KeyCode::Char('i') => {
@@ -146,21 +150,7 @@ fn handle_input(model: &mut Model, event: CrosstermEvent) -> Vec<Cmd> {
                         );
                     }
                 }
-                KeyCode::Char('p') => {
-                    // Split swarm into two partitions
-                    let mut p1 = HashSet::new();
-                    let mut p2 = HashSet::new();
-                    for (i, n) in model.nodes.iter().enumerate() {
-                        if i < model.nodes.len() / 2 {
-                            p1.insert(n.node.engine.self_pk);
-                        } else {
-                            p2.insert(n.node.engine.self_pk);
-                        }
-                    }
-                    model.hub.clear_partitions();
-                    model.hub.add_partition(p1);
-                    model.hub.add_partition(p2);
-                }
+                KeyCode::Char('p') => model.partition_in_halves(),
                 KeyCode::Char('R') => {
                     let is_paused = model.is_paused;
                     let rate = model.msg_rate;
@@ -223,6 +213,73 @@ fn handle_input(model: &mut Model, event: CrosstermEvent) -> Vec<Cmd> {
     cmds
 }
 
+fn handle_capture_input(model: &mut Model, code: KeyCode) {
+    let len = model.capture.count(&model.capture_filter);
+    match code {
+        KeyCode::Up => {
+            let i = model.capture_state.selected().unwrap_or(len);
+            model.capture_state.select(Some(i.saturating_sub(1)));
+            model.capture_follow = false;
+        }
+        KeyCode::Down => {
+            let i = model.capture_state.selected().map_or(0, |i| i + 1);
+            if i + 1 >= len {
+                model.capture_follow = true;
+            }
+            model
+                .capture_state
+                .select(Some(i.min(len.saturating_sub(1))));
+        }
+        KeyCode::End => model.capture_follow = true,
+        KeyCode::Char('n') => {
+            let pks: Vec<_> = model.nodes.iter().map(|n| n.node.engine.self_pk).collect();
+            model.capture_filter.peer = cycle(model.capture_filter.peer, &pks);
+        }
+        KeyCode::Char('c') => {
+            let ids = model.capture.conversations();
+            model.capture_filter.conversation_id =
+                cycle(model.capture_filter.conversation_id, &ids);
+        }
+        KeyCode::Char('t') => {
+            let kinds = model.capture.kinds();
+            model.capture_filter.kind = cycle(model.capture_filter.kind, &kinds);
+        }
+        KeyCode::Char('x') => model.capture_filter = CaptureFilter::default(),
+        KeyCode::Char('z') => model.capture.set_paused(!model.capture.is_paused()),
+        KeyCode::Char('C') => {
+            model.capture.clear();
+            model.capture_state.select(None);
+        }
+        KeyCode::Char('e') => {
+            let path = format!("capture-{}.json", model.steps);
+            let result = File::create(&path).and_then(|file| {
+                model
+                    .capture
+                    .export_json(&model.capture_filter, BufWriter::new(file))
+            });
+            model.capture_status = Some(match result {
+                Ok(()) => format!("Exported to {}", path),
+                Err(e) => format!("Export failed: {}", e),
+            });
+        }
+        _ => {}
+    }
+    if matches!(code, KeyCode::Char('n' | 'c' | 't' | 'x')) {
+        model.capture_follow = true;
+    }
+}
+
+/// Steps a filter through `options` and back to `None`.
+fn cycle<T: Copy + PartialEq>(current: Option<T>, options: &[T]) -> Option<T> {
+    match current {
+        None => options.first().copied(),
+        Some(current) => match options.iter().position(|o| *o == current) {
+            Some(i) => options.get(i + 1).copied(),
+            None => options.first().copied(),
+        },
+    }
+}
+
 fn tick(model: &mut Model, dt: Duration) -> Vec<Cmd> {
     if !model.is_paused || model.run_until_interesting {
         model.time_provider.advance(dt);
@@ -257,6 +314,7 @@ fn tick(model: &mut Model, dt: Duration) -> Vec<Cmd> {
                     store,
                     model.time_provider.clone(),
                 );
+                node.set_message_observer(model.capture.tap(pk));
 
                 // Peer with existing nodes
                 for existing in &model.nodes {
@@ -283,18 +341,7 @@ fn tick(model: &mut Model, dt: Duration) -> Vec<Cmd> {
             Scenario::PartitionHeal => {
                 if model.scenario_timer.is_none() {
                     // Start partition
-                    let mut p1 = HashSet::new();
-                    let mut p2 = HashSet::new();
-                    for (i, n) in model.nodes.iter().enumerate() {
-                        if i < model.nodes.len() / 2 {
-                            p1.insert(n.node.engine.self_pk);
-                        } else {
-                            p2.insert(n.node.engine.self_pk);
-                        }
-                    }
-                    model.hub.clear_partitions();
-                    model.hub.add_partition(p1);
-                    model.hub.add_partition(p2);
+                    model.partition_in_halves();
                     model.scenario_timer = Some(now + Duration::from_secs(10));
                 } else if now >= model.scenario_timer.unwrap() {
                     // Heal partition
@@ -369,6 +416,7 @@ fn tick(model: &mut Model, dt: Duration) -> Vec<Cmd> {
                             mime_type: "application/octet-stream".to_string(),
                             size: data.len() as u64,
                             metadata: vec![],
+                            preview: None,
                         },
                         vec![],
                         &seeder.node.store,
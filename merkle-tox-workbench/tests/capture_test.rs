@@ -0,0 +1,112 @@
+use merkle_tox_core::clock::ManualTimeProvider;
+use merkle_tox_core::dag::{ConversationId, NodeHash, PhysicalDevicePk};
+use merkle_tox_core::{MessageDirection, ProtocolMessage};
+use merkle_tox_workbench::capture::{Capture, CaptureFilter};
+use merkle_tox_workbench::model::{Model, Topology};
+use merkle_tox_workbench::msg::Msg;
+use merkle_tox_workbench::update::update;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_capture_records_and_filters() {
+    let time_provider = Arc::new(ManualTimeProvider::new(Instant::now(), 0));
+    let capture = Arc::new(Capture::with_limit(time_provider.clone(), 3));
+    let alice = PhysicalDevicePk::from([1u8; 32]);
+    let bob = PhysicalDevicePk::from([2u8; 32]);
+    let carol = PhysicalDevicePk::from([3u8; 32]);
+    let conv = ConversationId::from([0x42u8; 32]);
+    let tap = capture.tap(alice);
+
+    tap.observe(
+        MessageDirection::Sent,
+        bob,
+        &ProtocolMessage::CapsAnnounce {
+            version: 1,
+            features: 0,
+        },
+    );
+    time_provider.advance(Duration::from_millis(250));
+    tap.observe(
+        MessageDirection::Received,
+        carol,
+        &ProtocolMessage::SyncRateLimited {
+            conversation_id: conv,
+            retry_after_ms: 100,
+        },
+    );
+    capture.tap(bob).observe(
+        MessageDirection::Received,
+        alice,
+        &ProtocolMessage::BlobQuery(NodeHash::from([0u8; 32])),
+    );
+
+    let all = capture.records(&CaptureFilter::default());
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[0].kind, "CapsAnnounce");
+    assert_eq!(all[1].time, Duration::from_millis(250));
+    assert_eq!(all[1].conversation_id, Some(conv));
+    assert!(!all[0].data.is_empty());
+
+    let by_peer = CaptureFilter {
+        peer: Some(carol),
+        ..Default::default()
+    };
+    assert_eq!(capture.count(&by_peer), 1);
+    let by_conv = CaptureFilter {
+        conversation_id: Some(conv),
+        ..Default::default()
+    };
+    assert_eq!(capture.records(&by_conv)[0].kind, "SyncRateLimited");
+    let by_kind = CaptureFilter {
+        kind: Some("BlobQuery"),
+        ..Default::default()
+    };
+    assert_eq!(capture.page(&by_kind, 0, 10)[0].local, bob);
+
+    // The oldest record makes room once the limit is reached.
+    tap.observe(
+        MessageDirection::Sent,
+        bob,
+        &ProtocolMessage::BlobQuery(NodeHash::from([1u8; 32])),
+    );
+    assert_eq!(capture.len(), 3);
+    assert_eq!(capture.kinds(), vec!["BlobQuery", "SyncRateLimited"]);
+
+    let mut out = Vec::new();
+    capture.export_json(&by_kind, &mut out).unwrap();
+    let json = String::from_utf8(out).unwrap();
+    assert!(json.contains("\"format\": \"merkle-tox-capture\""));
+    assert_eq!(json.matches("\"kind\": \"BlobQuery\"").count(), 2);
+
+    capture.set_paused(true);
+    tap.observe(
+        MessageDirection::Sent,
+        bob,
+        &ProtocolMessage::BlobQuery(NodeHash::from([2u8; 32])),
+    );
+    assert_eq!(capture.kinds().len(), 2);
+    assert_eq!(capture.count(&by_kind), 2);
+}
+
+#[test]
+fn test_simulation_is_captured() {
+    let mut model = Model::new(3, 0, 0.0, false, 42, Topology::Mesh);
+    for _ in 0..10 {
+        update(&mut model, Msg::Tick(Duration::from_millis(50)));
+    }
+
+    let records = model.capture.records(&CaptureFilter::default());
+    assert!(!records.is_empty());
+    assert!(
+        records
+            .iter()
+            .any(|r| r.direction == MessageDirection::Sent)
+    );
+    assert!(
+        records
+            .iter()
+            .any(|r| r.direction == MessageDirection::Received)
+    );
+    assert!(records.windows(2).all(|w| w[0].time <= w[1].time));
+}
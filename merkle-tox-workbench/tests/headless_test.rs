@@ -0,0 +1,106 @@
+use merkle_tox_workbench::headless::{self, Metrics};
+use merkle_tox_workbench::model::Topology;
+use merkle_tox_workbench::scenario::{Action, NetworkConditions, ScenarioError, ScenarioFile};
+
+const PARTITION_HEAL: &str = r#"
+name = "partition-heal"
+nodes = 4
+topology = "star"
+duration_secs = 30
+
+[network]
+latency_ms = 20
+
+[[event]]
+at_secs = 4
+action = "heal"
+
+[[event]]
+at_secs = 0.5
+action = "messages"
+count = 4
+
+[[event]]
+at_secs = 0.2
+action = "partition"
+
+[[event]]
+at_secs = 1
+action = "network"
+loss = 0.05
+latency_ms = 40
+"#;
+
+#[test]
+fn test_parse_scenario() {
+    let scenario = ScenarioFile::parse(PARTITION_HEAL).unwrap();
+    assert_eq!(scenario.name, "partition-heal");
+    assert_eq!(scenario.nodes, 4);
+    assert_eq!(scenario.topology, Topology::Star);
+    assert_eq!(scenario.tick_ms, 50);
+    assert!(scenario.stop_when_converged);
+    assert_eq!(scenario.network.latency_ms, 20);
+
+    // Events come out in time order.
+    let actions: Vec<_> = scenario.events.iter().map(|e| e.action.clone()).collect();
+    assert_eq!(
+        actions,
+        vec![
+            Action::Partition,
+            Action::Messages {
+                node: None,
+                count: 4
+            },
+            Action::Network(NetworkConditions {
+                loss: 0.05,
+                latency_ms: 40,
+                jitter: 0.0,
+            }),
+            Action::Heal,
+        ]
+    );
+}
+
+#[test]
+fn test_parse_rejects_invalid_scenarios() {
+    assert!(matches!(
+        ScenarioFile::parse("nodes = 3"),
+        Err(ScenarioError::Parse(_))
+    ));
+    assert!(matches!(
+        ScenarioFile::parse("duration_secs = 10\nbogus = 1"),
+        Err(ScenarioError::Parse(_))
+    ));
+    assert!(matches!(
+        ScenarioFile::parse(
+            "nodes = 2\nduration_secs = 10\n[[event]]\nat_secs = 1\naction = \"blackout\"\nnode = 2\nsecs = 1"
+        ),
+        Err(ScenarioError::Invalid(_))
+    ));
+    assert!(matches!(
+        ScenarioFile::parse("duration_secs = 10\n[[event]]\nat_secs = 11\naction = \"heal\""),
+        Err(ScenarioError::Invalid(_))
+    ));
+}
+
+#[test]
+fn test_headless_run_converges_after_heal() {
+    let scenario = ScenarioFile::parse(PARTITION_HEAL).unwrap();
+    let metrics = headless::run(&scenario);
+
+    assert!(metrics.converged, "{:?}", metrics);
+    assert_eq!(metrics.messages_authored, 4);
+    assert!(metrics.heads >= 1);
+    assert!(metrics.virtual_secs >= 4.0 && metrics.virtual_secs < 30.0);
+    assert!(metrics.convergence_secs.is_some());
+    assert!(metrics.packets_sent > 0 && metrics.bytes_on_wire > 0);
+    // The partition drops traffic between the halves.
+    assert!(metrics.packets_dropped > 0);
+
+    let row = metrics.to_csv_row();
+    assert!(row.starts_with("partition-heal,4,"));
+    assert_eq!(
+        row.split(',').count(),
+        Metrics::CSV_HEADER.split(',').count()
+    );
+}
@@ -2,6 +2,28 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use syn::{Data, DeriveInput, Fields};
 
+/// Returns whether `ty` is spelled `Option<...>`.
+fn is_option(ty: &syn::Type) -> bool {
+    match ty {
+        syn::Type::Path(p) if p.qself.is_none() => p
+            .path
+            .segments
+            .last()
+            .is_some_and(|seg| seg.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Number of leading fields an encoding must carry. Trailing `Option` fields
+/// were appended after older senders were deployed and decode as `None` when
+/// missing.
+fn required_fields(types: &[&syn::Type]) -> usize {
+    types
+        .iter()
+        .rposition(|ty| !is_option(ty))
+        .map_or(0, |i| i + 1)
+}
+
 pub fn derive_tox_deserialize_impl(input: DeriveInput) -> TokenStream {
     let name = &input.ident;
     let mut is_flat = false;
@@ -178,15 +200,26 @@ pub fn derive_tox_deserialize_impl(input: DeriveInput) -> TokenStream {
                     }
                 }
             } else {
-                let field_deserializers: Vec<_> = field_names.iter().zip(field_types.iter()).map(|(name, ty)| {
-                    quote! { let #name = <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?; }
+                let required = required_fields(&field_types);
+                let field_deserializers: Vec<_> = field_names.iter().zip(field_types.iter()).enumerate().map(|(i, (name, ty))| {
+                    if i < required {
+                        quote! { let #name = <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?; }
+                    } else {
+                        quote! {
+                            let #name = if (len as usize) > #i {
+                                <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?
+                            } else {
+                                None
+                            };
+                        }
+                    }
                 }).collect();
 
                 quote! {
                     let len = ::tox_proto::rmp::decode::read_array_len(reader)
                         .map_err(|e| ::tox_proto::Error::Deserialize(e.to_string()))?;
-                    if (len as usize) < #field_count {
-                        return Err(::tox_proto::Error::Deserialize(format!("Too few fields for {}: expected {}, got {}", stringify!(#name), #field_count, len)));
+                    if (len as usize) < #required {
+                        return Err(::tox_proto::Error::Deserialize(format!("Too few fields for {}: expected {}, got {}", stringify!(#name), #required, len)));
                     }
                     #(#field_deserializers)*
                     for _ in #field_count..(len as usize) { ::tox_proto::skip_value(reader)?; }
@@ -255,10 +288,22 @@ pub fn derive_tox_deserialize_impl(input: DeriveInput) -> TokenStream {
                     Fields::Unnamed(f) => {
                         let bindings: Vec<_> = (0..f.unnamed.len()).map(|i| format_ident!("f{}", i)).collect();
                         let bindings_copy = bindings.clone();
-                        let field_deserializers: Vec<_> = f.unnamed.iter().enumerate().map(|(i, field)| {
-                            let ty = &field.ty;
+                        let types: Vec<_> = f.unnamed.iter().map(|field| &field.ty).collect();
+                        let required = required_fields(&types) as u32;
+                        let field_deserializers: Vec<_> = types.iter().enumerate().map(|(i, ty)| {
                             let binding = &bindings[i];
-                            quote! { let #binding = <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?; }
+                            let i = i as u32;
+                            if i < required {
+                                quote! { let #binding = <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?; }
+                            } else {
+                                quote! {
+                                    let #binding = if inner_len > #i {
+                                        <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?
+                                    } else {
+                                        None
+                                    };
+                                }
+                            }
                         }).collect();
                         quote! {
                             #idx => {
@@ -267,8 +312,8 @@ pub fn derive_tox_deserialize_impl(input: DeriveInput) -> TokenStream {
                                 }
                                 let inner_len = ::tox_proto::rmp::decode::read_array_len(reader)
                                     .map_err(|e| ::tox_proto::Error::Deserialize(e.to_string()))?;
-                                if inner_len < #expected_fields {
-                                    return Err(::tox_proto::Error::Deserialize(format!("Too few fields for enum variant {} payload: expected {}, got {}", stringify!(#v_ident), #expected_fields, inner_len)));
+                                if inner_len < #required {
+                                    return Err(::tox_proto::Error::Deserialize(format!("Too few fields for enum variant {} payload: expected {}, got {}", stringify!(#v_ident), #required, inner_len)));
                                 }
                                 #(#field_deserializers)*
                                 for _ in #expected_fields..inner_len { ::tox_proto::skip_value(reader)?; }
@@ -292,10 +337,23 @@ pub fn derive_tox_deserialize_impl(input: DeriveInput) -> TokenStream {
                     Fields::Named(f) => {
                         let idents: Vec<_> = f.named.iter().map(|f| &f.ident).collect();
                         let idents_copy = idents.clone();
-                        let field_deserializers: Vec<_> = f.named.iter().map(|f| {
+                        let types: Vec<_> = f.named.iter().map(|f| &f.ty).collect();
+                        let required = required_fields(&types) as u32;
+                        let field_deserializers: Vec<_> = f.named.iter().enumerate().map(|(i, f)| {
                             let ident = f.ident.as_ref().unwrap();
                             let ty = &f.ty;
-                            quote! { let #ident = <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?; }
+                            let i = i as u32;
+                            if i < required {
+                                quote! { let #ident = <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?; }
+                            } else {
+                                quote! {
+                                    let #ident = if inner_len > #i {
+                                        <#ty as ::tox_proto::ToxDeserialize>::deserialize(reader, ctx)?
+                                    } else {
+                                        None
+                                    };
+                                }
+                            }
                         }).collect();
                         quote! {
                             #idx => {
@@ -304,8 +362,8 @@ pub fn derive_tox_deserialize_impl(input: DeriveInput) -> TokenStream {
                                 }
                                 let inner_len = ::tox_proto::rmp::decode::read_array_len(reader)
                                     .map_err(|e| ::tox_proto::Error::Deserialize(e.to_string()))?;
-                                if inner_len < #expected_fields {
-                                    return Err(::tox_proto::Error::Deserialize(format!("Too few fields for enum variant {} payload: expected {}, got {}", stringify!(#v_ident), #expected_fields, inner_len)));
+                                if inner_len < #required {
+                                    return Err(::tox_proto::Error::Deserialize(format!("Too few fields for enum variant {} payload: expected {}, got {}", stringify!(#v_ident), #required, inner_len)));
                                 }
                                 #(#field_deserializers)*
                                 for _ in #expected_fields..inner_len { ::tox_proto::skip_value(reader)?; }
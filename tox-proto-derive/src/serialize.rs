@@ -2,6 +2,65 @@ use proc_macro2::TokenStream;
 use quote::quote;
 use syn::{Data, DeriveInput, Fields, Index};
 
+/// Returns whether a field carries `#[tox(omit_none)]`.
+fn has_omit_none(field: &syn::Field) -> bool {
+    let mut omit_none = false;
+    for attr in &field.attrs {
+        if attr.path().is_ident("tox") {
+            let _ = attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("omit_none") {
+                    omit_none = true;
+                }
+                Ok(())
+            });
+        }
+    }
+    omit_none
+}
+
+/// Writes a positional field array. Trailing `#[tox(omit_none)]` fields are
+/// left out while `None`, so values written before such a field was appended
+/// re-encode to the same bytes.
+fn write_positional_fields(accessors: &[TokenStream], omit_none: &[bool]) -> TokenStream {
+    let total = accessors.len();
+    let trailing = omit_none.iter().rev().take_while(|o| **o).count();
+    let required = total - trailing;
+    if omit_none[..required].iter().any(|o| *o) {
+        panic!("#[tox(omit_none)] is only supported on trailing fields");
+    }
+
+    let fixed = &accessors[..required];
+    let optional = &accessors[required..];
+    let total = total as u32;
+    if optional.is_empty() {
+        return quote! {
+            ::tox_proto::rmp::encode::write_array_len(writer, #total)
+                .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
+            #(#fixed.serialize(writer, ctx)?;)*
+        };
+    }
+
+    let positions: Vec<u32> = (required as u32 + 1..=total).rev().collect();
+    let optional_rev: Vec<_> = optional.iter().rev().collect();
+    let indices: Vec<u32> = (required as u32..total).collect();
+    quote! {
+        let mut field_count: u32 = #total;
+        #(
+            if field_count == #positions && #optional_rev.is_none() {
+                field_count -= 1;
+            }
+        )*
+        ::tox_proto::rmp::encode::write_array_len(writer, field_count)
+            .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
+        #(#fixed.serialize(writer, ctx)?;)*
+        #(
+            if field_count > #indices {
+                #optional.serialize(writer, ctx)?;
+            }
+        )*
+    }
+}
+
 pub fn derive_tox_serialize_impl(input: DeriveInput) -> TokenStream {
     let name = &input.ident;
     let mut is_flat = false;
@@ -62,14 +121,15 @@ pub fn derive_tox_serialize_impl(input: DeriveInput) -> TokenStream {
                             let index = Index::from(i);
                             quote!(self.#index)
                         };
-                        active_fields.push((accessor, &f.ty));
+                        active_fields.push((accessor, &f.ty, has_omit_none(f)));
                     }
                 }
 
                 let field_count = active_fields.len();
                 let field_accessors: Vec<_> =
-                    active_fields.iter().map(|(a, _)| a.clone()).collect();
-                let field_types: Vec<_> = active_fields.iter().map(|(_, t)| *t).collect();
+                    active_fields.iter().map(|(a, _, _)| a.clone()).collect();
+                let field_types: Vec<_> = active_fields.iter().map(|(_, t, _)| *t).collect();
+                let field_omit_none: Vec<_> = active_fields.iter().map(|(_, _, o)| *o).collect();
 
                 let serialize_flat_fields: Vec<_> = field_accessors
                     .iter()
@@ -149,16 +209,9 @@ pub fn derive_tox_serialize_impl(input: DeriveInput) -> TokenStream {
                         }
                     }
                 } else {
-                    let field_serialization: Vec<_> = field_accessors
-                        .iter()
-                        .map(|accessor| {
-                            quote! { #accessor.serialize(writer, ctx)?; }
-                        })
-                        .collect();
+                    let fields = write_positional_fields(&field_accessors, &field_omit_none);
                     quote! {
-                        ::tox_proto::rmp::encode::write_array_len(writer, #field_count as u32)
-                            .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
-                        #(#field_serialization)*
+                        #fields
                         Ok(())
                     }
                 };
@@ -238,15 +291,15 @@ pub fn derive_tox_serialize_impl(input: DeriveInput) -> TokenStream {
                             let bindings: Vec<_> = (0..f.unnamed.len())
                                 .map(|i| quote::format_ident!("f{}", i))
                                 .collect();
-                            let inner_count = f.unnamed.len() as u32;
+                            let accessors: Vec<_> = bindings.iter().map(|b| quote!(#b)).collect();
+                            let omit_none: Vec<_> = f.unnamed.iter().map(has_omit_none).collect();
+                            let fields = write_positional_fields(&accessors, &omit_none);
                             quote! {
                                 #name::#v_ident(#(#bindings),*) => {
                                     ::tox_proto::rmp::encode::write_array_len(writer, 2)
                                         .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
                                     #idx.serialize(writer, ctx)?;
-                                    ::tox_proto::rmp::encode::write_array_len(writer, #inner_count)
-                                        .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
-                                    #(#bindings.serialize(writer, ctx)?;)*
+                                    #fields
                                 }
                             }
                         }
@@ -263,15 +316,15 @@ pub fn derive_tox_serialize_impl(input: DeriveInput) -> TokenStream {
                         }
                         Fields::Named(f) => {
                             let idents: Vec<_> = f.named.iter().map(|f| &f.ident).collect();
-                            let inner_count = f.named.len() as u32;
+                            let accessors: Vec<_> = idents.iter().map(|i| quote!(#i)).collect();
+                            let omit_none: Vec<_> = f.named.iter().map(has_omit_none).collect();
+                            let fields = write_positional_fields(&accessors, &omit_none);
                             quote! {
                                 #name::#v_ident { #(#idents),* } => {
                                     ::tox_proto::rmp::encode::write_array_len(writer, 2)
                                         .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
                                     #idx.serialize(writer, ctx)?;
-                                    ::tox_proto::rmp::encode::write_array_len(writer, #inner_count)
-                                        .map_err(|e| ::tox_proto::Error::Serialize(e.to_string()))?;
-                                    #(#idents.serialize(writer, ctx)?;)*
+                                    #fields
                                 }
                             }
                         }
@@ -2,6 +2,7 @@ use std::io::{Read, Write};
 use std::sync::Arc;
 
 pub mod constants;
+pub mod time;
 pub use rmp;
 pub use tox_proto_derive::{ToxDeserialize, ToxProto, ToxSerialize};
 
@@ -253,7 +254,7 @@ impl NodeHash {
 }
 
 pub trait TimeProvider: Send + Sync + std::fmt::Debug {
-    fn now_instant(&self) -> std::time::Instant;
+    fn now_instant(&self) -> time::Instant;
     fn now_system_ms(&self) -> i64;
 }
 
@@ -272,6 +273,7 @@ impl ToxDeserialize for Arc<dyn TimeProvider> {
     }
 }
 
+/// The OS clocks; on wasm32, the host clock (see [`time`]).
 #[derive(Debug)]
 pub struct SystemTimeProvider;
 
@@ -283,16 +285,22 @@ impl ToxSerialize for SystemTimeProvider {
 }
 
 impl TimeProvider for SystemTimeProvider {
-    fn now_instant(&self) -> std::time::Instant {
-        std::time::Instant::now()
+    fn now_instant(&self) -> time::Instant {
+        time::Instant::now()
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     fn now_system_ms(&self) -> i64 {
         std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap_or(std::time::Duration::ZERO)
             .as_millis() as i64
     }
+
+    #[cfg(target_arch = "wasm32")]
+    fn now_system_ms(&self) -> i64 {
+        time::host_unix_ms()
+    }
 }
 
 pub struct ToxContext {
@@ -1467,8 +1475,8 @@ impl ToxDeserialize for std::time::Duration {
     }
 }
 
-impl ToxSize for std::time::Instant {}
-impl ToxSerialize for std::time::Instant {
+impl ToxSize for time::Instant {}
+impl ToxSerialize for time::Instant {
     fn serialize<W: Write>(&self, writer: &mut W, ctx: &ToxContext) -> Result<()> {
         let tp = ctx.time_provider.as_ref().ok_or_else(|| {
             Error::Serialize(
@@ -1494,7 +1502,7 @@ impl ToxSerialize for std::time::Instant {
     }
 }
 
-impl ToxDeserialize for std::time::Instant {
+impl ToxDeserialize for time::Instant {
     fn deserialize<R: Read>(reader: &mut R, ctx: &ToxContext) -> Result<Self> {
         let tp = ctx.time_provider.as_ref().ok_or_else(|| {
             Error::Deserialize(
@@ -0,0 +1,114 @@
+//! Monotonic time on every target.
+//!
+//! Native targets use `std::time::Instant`. On wasm32-unknown-unknown, where
+//! the standard clocks panic, [`Instant`] and `SystemTimeProvider` read the
+//! clock the host last reported through `set_host_clock`. A browser calls it
+//! with `performance.now()` and `Date.now()` before polling the node.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use self::host::{Instant, set_host_clock};
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) use self::host::host_unix_ms;
+
+#[cfg(target_arch = "wasm32")]
+mod host {
+    use std::ops::{Add, AddAssign, Sub, SubAssign};
+    use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+    use std::time::Duration;
+
+    static MONOTONIC_NANOS: AtomicU64 = AtomicU64::new(0);
+    static UNIX_MS: AtomicI64 = AtomicI64::new(0);
+
+    /// Records a reading of the host clocks: `monotonic_ms` from a clock
+    /// that never goes backwards, `unix_ms` since the UNIX epoch. Monotonic
+    /// readings older than the last one are ignored.
+    pub fn set_host_clock(monotonic_ms: f64, unix_ms: i64) {
+        let nanos = (monotonic_ms.max(0.0) * 1_000_000.0) as u64;
+        MONOTONIC_NANOS.fetch_max(nanos, Ordering::Relaxed);
+        UNIX_MS.store(unix_ms, Ordering::Relaxed);
+    }
+
+    pub(crate) fn host_unix_ms() -> i64 {
+        UNIX_MS.load(Ordering::Relaxed)
+    }
+
+    /// Point on the host's monotonic clock.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        /// The last reported host time.
+        pub fn now() -> Self {
+            Self(Duration::from_nanos(
+                MONOTONIC_NANOS.load(Ordering::Relaxed),
+            ))
+        }
+
+        /// Zero if `earlier` is later than `self`, like `std`.
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.saturating_duration_since(earlier)
+        }
+
+        pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+            self.0.checked_sub(earlier.0)
+        }
+
+        pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Self::now().duration_since(*self)
+        }
+
+        pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_add(duration).map(Self)
+        }
+
+        pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_sub(duration).map(Self)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, rhs: Duration) -> Instant {
+            self.checked_add(rhs)
+                .expect("overflow when adding duration to instant")
+        }
+    }
+
+    impl AddAssign<Duration> for Instant {
+        fn add_assign(&mut self, rhs: Duration) {
+            *self = *self + rhs;
+        }
+    }
+
+    impl Sub<Duration> for Instant {
+        type Output = Instant;
+
+        fn sub(self, rhs: Duration) -> Instant {
+            self.checked_sub(rhs)
+                .expect("overflow when subtracting duration from instant")
+        }
+    }
+
+    impl SubAssign<Duration> for Instant {
+        fn sub_assign(&mut self, rhs: Duration) {
+            *self = *self - rhs;
+        }
+    }
+
+    impl Sub<Instant> for Instant {
+        type Output = Duration;
+
+        fn sub(self, rhs: Instant) -> Duration {
+            self.duration_since(rhs)
+        }
+    }
+}
@@ -205,3 +205,93 @@ fn test_foreign_content_discriminant_from_wire() {
     let re_encoded = serialize(&content).unwrap();
     assert_eq!(re_encoded, wire, "Content foreign wire byte fidelity");
 }
+
+/// Struct as written before `note` was appended.
+#[derive(Debug, PartialEq, ToxProto)]
+struct RecordV1 {
+    id: u32,
+    name: String,
+}
+
+/// Struct with an appended optional field.
+#[derive(Debug, PartialEq, ToxProto)]
+struct RecordV2 {
+    id: u32,
+    name: String,
+    #[tox(omit_none)]
+    note: Option<String>,
+}
+
+#[test]
+fn test_missing_trailing_option_decodes_as_none() {
+    let old = RecordV1 {
+        id: 7,
+        name: "old".to_string(),
+    };
+    let encoded = serialize(&old).unwrap();
+
+    let new: RecordV2 = deserialize(&encoded).expect("older encoding should decode");
+    assert_eq!(new.note, None);
+
+    // An absent omit_none field is left out, so the old bytes round-trip.
+    assert_eq!(serialize(&new).unwrap(), encoded);
+
+    // A present one is written and still readable by the old type.
+    let with_note = RecordV2 {
+        note: Some("new".to_string()),
+        ..new
+    };
+    let encoded = serialize(&with_note).unwrap();
+    let old: RecordV1 = deserialize(&encoded).unwrap();
+    assert_eq!(old.id, 7);
+    assert_eq!(deserialize::<RecordV2>(&encoded).unwrap(), with_note);
+}
+
+#[test]
+fn test_missing_required_field_still_rejected() {
+    #[derive(Debug, PartialEq, ToxProto)]
+    struct Short {
+        id: u32,
+    }
+
+    let encoded = serialize(&Short { id: 7 }).unwrap();
+    let err = deserialize::<RecordV2>(&encoded).unwrap_err().to_string();
+    assert!(err.contains("Too few fields"), "got: {}", err);
+}
+
+#[test]
+fn test_legacy_blob_without_preview_roundtrip() {
+    // A Blob written before `preview` was appended: [6, [hash, name, mime, size, metadata]].
+    use merkle_tox_core::dag::{Content, NodeHash};
+
+    let hash = NodeHash::from([0x42; 32]);
+    let mut wire = Vec::new();
+    tox_proto::rmp::encode::write_array_len(&mut wire, 2).unwrap();
+    wire.extend(serialize(&6u8).unwrap());
+    tox_proto::rmp::encode::write_array_len(&mut wire, 5).unwrap();
+    wire.extend(serialize(&hash).unwrap());
+    wire.extend(serialize(&"cat.png".to_string()).unwrap());
+    wire.extend(serialize(&"image/png".to_string()).unwrap());
+    wire.extend(serialize(&1024u64).unwrap());
+    wire.extend(serialize(&Vec::<u8>::new()).unwrap());
+
+    let content: Content = deserialize(&wire).expect("legacy Blob should decode");
+    match &content {
+        Content::Blob {
+            hash: h,
+            name,
+            size,
+            preview,
+            ..
+        } => {
+            assert_eq!(*h, hash);
+            assert_eq!(name, "cat.png");
+            assert_eq!(*size, 1024);
+            assert_eq!(*preview, None);
+        }
+        other => panic!("Expected Blob, got {:?}", other),
+    }
+
+    // Node hashes cover the encoding, so it must not change on re-encode.
+    assert_eq!(serialize(&content).unwrap(), wire);
+}
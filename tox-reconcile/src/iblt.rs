@@ -28,7 +28,7 @@ pub struct IbltCell {
     pub hash_sum: u64,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tier {
     Tiny,   // 16 cells
     Small,  // 64 cells
@@ -55,6 +55,25 @@ impl Tier {
         }
     }
 
+    /// Next larger tier, or `None` for `Large`.
+    pub fn next(&self) -> Option<Self> {
+        match self {
+            Tier::Tiny => Some(Tier::Small),
+            Tier::Small => Some(Tier::Medium),
+            Tier::Medium => Some(Tier::Large),
+            Tier::Large => None,
+        }
+    }
+
+    /// Smallest tier able to decode `differences` symmetric differences,
+    /// or `Large` if none is.
+    pub fn for_differences(differences: usize) -> Self {
+        [Tier::Tiny, Tier::Small, Tier::Medium]
+            .into_iter()
+            .find(|t| t.d_max() >= differences)
+            .unwrap_or(Tier::Large)
+    }
+
     /// Maximum number of symmetric differences decodable for this tier.
     pub fn d_max(&self) -> usize {
         match self {
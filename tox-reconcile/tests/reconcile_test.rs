@@ -113,6 +113,18 @@ fn test_iblt_tiers_capacity() {
     }
 }
 
+#[test]
+fn test_tier_for_differences() {
+    assert_eq!(Tier::for_differences(0), Tier::Tiny);
+    assert_eq!(Tier::for_differences(10), Tier::Tiny);
+    assert_eq!(Tier::for_differences(11), Tier::Small);
+    assert_eq!(Tier::for_differences(170), Tier::Medium);
+    assert_eq!(Tier::for_differences(5000), Tier::Large);
+    assert_eq!(Tier::Medium.next(), Some(Tier::Large));
+    assert_eq!(Tier::Large.next(), None);
+    assert!(Tier::Tiny < Tier::Large);
+}
+
 #[test]
 fn test_iblt_remove() {
     let mut sketch = IbltSketch::new(Tier::Small.cell_count());
@@ -1,8 +1,9 @@
 use criterion::{Criterion, criterion_group, criterion_main};
 use std::hint::black_box;
 use std::time::Instant;
+use tox_sequenced::bitset::BitSet;
 use tox_sequenced::outgoing::OutgoingMessage;
-use tox_sequenced::protocol::{FragmentCount, FragmentIndex, MessageId, MessageType};
+use tox_sequenced::protocol::{BITSET_WORDS, FragmentCount, FragmentIndex, MessageId, MessageType};
 use tox_sequenced::quota::Priority;
 use tox_sequenced::reassembly::MessageReassembler;
 
@@ -80,5 +81,71 @@ fn bench_collect_acked_indices(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_create_ack, bench_collect_acked_indices);
+fn bench_bitset(c: &mut Criterion) {
+    let limit = BITSET_WORDS * 64;
+    let mut mostly_full = BitSet::<BITSET_WORDS>::new();
+    mostly_full.set_range(0, limit - 10);
+
+    // Advancing a cumulative ACK over a long run of received fragments.
+    c.bench_function("bitset_first_missing_bitwise", |b| {
+        b.iter(|| {
+            let bs = black_box(&mostly_full);
+            let mut idx = 1;
+            while idx < limit && bs.get(idx) {
+                idx += 1;
+            }
+            black_box(idx)
+        })
+    });
+    c.bench_function("bitset_first_missing_after", |b| {
+        b.iter(|| black_box(black_box(&mostly_full).first_missing_after(0)))
+    });
+
+    c.bench_function("bitset_set_bitwise", |b| {
+        b.iter(|| {
+            let mut bs = BitSet::<BITSET_WORDS>::new();
+            for i in 3..limit - 3 {
+                bs.set(i);
+            }
+            black_box(bs)
+        })
+    });
+    c.bench_function("bitset_set_range", |b| {
+        b.iter(|| {
+            let mut bs = BitSet::<BITSET_WORDS>::new();
+            bs.set_range(black_box(3), black_box(limit - 3));
+            black_box(bs)
+        })
+    });
+
+    let mut runs = BitSet::<BITSET_WORDS>::new();
+    for start in (0..limit).step_by(100) {
+        runs.set_range(start, start + 90);
+    }
+    c.bench_function("bitset_runs_bitwise", |b| {
+        b.iter(|| {
+            let bs = black_box(&runs);
+            let mut count = 0;
+            let mut in_run = false;
+            for i in 0..limit {
+                let set = bs.get(i);
+                if set && !in_run {
+                    count += 1;
+                }
+                in_run = set;
+            }
+            black_box(count)
+        })
+    });
+    c.bench_function("bitset_ranges", |b| {
+        b.iter(|| black_box(black_box(&runs).ranges(0, limit).count()))
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_create_ack,
+    bench_collect_acked_indices,
+    bench_bitset
+);
 criterion_main!(benches);
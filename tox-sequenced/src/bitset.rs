@@ -1,3 +1,4 @@
+use std::ops::Range;
 use tox_proto::ToxProto;
 
 /// received fragments using a bitset.
@@ -68,6 +69,42 @@ impl<const N: usize> BitSet<N> {
         self.words = [!0u64; N];
     }
 
+    /// Sets all bits in [start, end), a word at a time.
+    pub fn set_range(&mut self, start: usize, end: usize) {
+        self.apply_range(start, end, |word, mask| *word |= mask);
+    }
+
+    /// Clears all bits in [start, end), a word at a time.
+    pub fn unset_range(&mut self, start: usize, end: usize) {
+        self.apply_range(start, end, |word, mask| *word &= !mask);
+    }
+
+    fn apply_range(&mut self, start: usize, end: usize, f: impl Fn(&mut u64, u64)) {
+        let end = end.min(N * 64);
+        if start >= end {
+            return;
+        }
+        let start_word = start / 64;
+        let end_word = (end - 1) / 64;
+        let start_mask = !0u64 << (start % 64);
+        let end_mask = !0u64 >> (63 - (end - 1) % 64);
+
+        if start_word == end_word {
+            f(&mut self.words[start_word], start_mask & end_mask);
+            return;
+        }
+        f(&mut self.words[start_word], start_mask);
+        for word in &mut self.words[start_word + 1..end_word] {
+            f(word, !0u64);
+        }
+        f(&mut self.words[end_word], end_mask);
+    }
+
+    /// Returns the number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
     /// Returns the index of the first zero bit, clamped to `limit`.
     /// Useful for calculating the cumulative ACK base index.
     pub fn first_zero(&self, limit: usize) -> usize {
@@ -83,6 +120,31 @@ impl<const N: usize> BitSet<N> {
         base.min(limit)
     }
 
+    /// Returns the index of the first zero bit after `index`. Bits past the
+    /// end of the set count as zero, so this is at most `N * 64`.
+    ///
+    /// Skips whole words of ones, which is what advancing a cumulative ACK
+    /// over a long run of received fragments needs.
+    pub fn first_missing_after(&self, index: usize) -> usize {
+        let start = index + 1;
+        let mut word_idx = start / 64;
+        if word_idx >= N {
+            return start;
+        }
+        // Treat the bits before `start` as set.
+        let mut word = self.words[word_idx] | !(!0u64 << (start % 64));
+        loop {
+            if word != !0u64 {
+                return word_idx * 64 + word.trailing_ones() as usize;
+            }
+            word_idx += 1;
+            if word_idx == N {
+                return N * 64;
+            }
+            word = self.words[word_idx];
+        }
+    }
+
     /// Returns the index of the first zero bit starting from `start`, clamped to `limit`.
     /// Returns None if all bits in range [start, limit) are set.
     pub fn next_zero(&self, start: usize, limit: usize) -> Option<usize> {
@@ -154,6 +216,16 @@ impl<const N: usize> BitSet<N> {
         Some((start as u16, end as u16))
     }
 
+    /// Iterates over the runs of set bits within [start, limit), as
+    /// half-open ranges in ascending order.
+    pub fn ranges(&self, start: usize, limit: usize) -> Ranges<'_, N> {
+        Ranges {
+            bits: self,
+            pos: start,
+            limit: limit.min(N * 64),
+        }
+    }
+
     /// Returns the index of the highest set bit less than `limit`, or None.
     pub fn last_one(&self, limit: usize) -> Option<usize> {
         let limit = limit.min(N * 64);
@@ -247,3 +319,21 @@ impl<const N: usize> BitSet<N> {
         count
     }
 }
+
+/// Iterator over runs of set bits, see [`BitSet::ranges`].
+pub struct Ranges<'a, const N: usize> {
+    bits: &'a BitSet<N>,
+    pos: usize,
+    limit: usize,
+}
+
+impl<const N: usize> Iterator for Ranges<'_, N> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.bits.next_one(self.pos, self.limit)?;
+        let end = self.bits.next_zero(start, self.limit).unwrap_or(self.limit);
+        self.pos = end;
+        Some(start..end)
+    }
+}
@@ -0,0 +1,185 @@
+//! Optional packet encryption for transports that are not secure already.
+//!
+//! Over Tox, packets travel inside the encrypted friend connection and need
+//! no protection of their own. On other datagram transports a session can
+//! seal every packet with XChaCha20-Poly1305 under a key both ends agreed
+//! on out of band.
+//!
+//! Each sealed packet starts with a marker byte and the sender's 64-bit
+//! packet counter, from which the nonce is derived together with the
+//! sender's role, so the two directions never share a nonce. The receiver
+//! drops packets whose counter it has seen before. The marker lets a
+//! session tell a peer that seals its packets from one that does not, so an
+//! end configured without the other fails loudly instead of dropping
+//! everything as garbage.
+
+use crate::error::SequencedError;
+use crate::protocol::{self, Packet};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+pub const KEY_SIZE: usize = 32;
+/// First byte of every sealed packet. MessagePack never uses it, so no
+/// plain packet starts with it.
+pub const SEALED_MARKER: u8 = 0xC1;
+const MARKER_SIZE: usize = 1;
+const COUNTER_SIZE: usize = 8;
+const TAG_SIZE: usize = 16;
+/// Bytes sealing adds to a packet.
+pub const SEAL_OVERHEAD: usize = MARKER_SIZE + COUNTER_SIZE + TAG_SIZE;
+/// Counters this far behind the newest one received are still accepted.
+const REPLAY_WINDOW: u64 = 64;
+/// Authenticated with every packet, so keys shared with other protocols
+/// cannot be used to forge packets.
+const AAD: &[u8] = b"tox-sequenced packet v1";
+
+/// Whether `data` is a sealed packet rather than a plain one.
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.first() == Some(&SEALED_MARKER)
+}
+
+/// Which end of the session this is. The two ends must pick different
+/// roles, e.g. by comparing their public keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CipherRole {
+    Initiator,
+    Responder,
+}
+
+impl CipherRole {
+    fn peer(self) -> Self {
+        match self {
+            CipherRole::Initiator => CipherRole::Responder,
+            CipherRole::Responder => CipherRole::Initiator,
+        }
+    }
+
+    fn nonce(self, counter: u64) -> XNonce {
+        let mut nonce = [0u8; 24];
+        nonce[0] = match self {
+            CipherRole::Initiator => 0,
+            CipherRole::Responder => 1,
+        };
+        nonce[16..].copy_from_slice(&counter.to_be_bytes());
+        nonce.into()
+    }
+}
+
+/// Seals outgoing and opens incoming packets of one session.
+///
+/// A key must not be reused with a fresh `PacketCipher` in the same role:
+/// the counter would start over and repeat nonces. Ciphers are not saved
+/// with the session, so a restored session needs a new key.
+pub struct PacketCipher {
+    cipher: XChaCha20Poly1305,
+    role: CipherRole,
+    next_counter: u64,
+    /// Highest counter received so far, if any.
+    highest_received: Option<u64>,
+    /// Bit `i` is set if `highest_received - i` has been received.
+    received: u64,
+}
+
+impl PacketCipher {
+    pub fn new(key: &[u8; KEY_SIZE], role: CipherRole) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.into()),
+            role,
+            next_counter: 0,
+            highest_received: None,
+            received: 0,
+        }
+    }
+
+    pub fn role(&self) -> CipherRole {
+        self.role
+    }
+
+    /// Serializes and encrypts `packet`.
+    pub fn seal(&mut self, packet: &Packet) -> Result<Vec<u8>, SequencedError> {
+        let plaintext = protocol::serialize(packet)
+            .map_err(|e| SequencedError::SerializationError(e.to_string()))?;
+        let counter = self.next_counter;
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                &self.role.nonce(counter),
+                Payload {
+                    msg: &plaintext,
+                    aad: AAD,
+                },
+            )
+            .map_err(|e| SequencedError::SerializationError(e.to_string()))?;
+        self.next_counter += 1;
+
+        let mut out = Vec::with_capacity(MARKER_SIZE + COUNTER_SIZE + ciphertext.len());
+        out.push(SEALED_MARKER);
+        out.extend_from_slice(&counter.to_be_bytes());
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    /// Decrypts and deserializes a packet sealed by the peer. Forged,
+    /// corrupted and replayed packets are rejected, and plain packets fail
+    /// with [`SequencedError::CipherMismatch`].
+    pub fn open(&mut self, data: &[u8]) -> Result<Packet, SequencedError> {
+        if !is_sealed(data) {
+            return Err(SequencedError::CipherMismatch);
+        }
+        if data.len() < SEAL_OVERHEAD {
+            return Err(SequencedError::AuthenticationFailed);
+        }
+        let (counter, ciphertext) = data[MARKER_SIZE..].split_at(COUNTER_SIZE);
+        let counter = u64::from_be_bytes(counter.try_into().expect("split at COUNTER_SIZE"));
+        if self.is_replay(counter) {
+            return Err(SequencedError::ReplayedPacket);
+        }
+        let plaintext = self
+            .cipher
+            .decrypt(
+                &self.role.peer().nonce(counter),
+                Payload {
+                    msg: ciphertext,
+                    aad: AAD,
+                },
+            )
+            .map_err(|_| SequencedError::AuthenticationFailed)?;
+        let packet = protocol::deserialize(&plaintext)
+            .map_err(|e| SequencedError::SerializationError(e.to_string()))?;
+        // Only authentic packets move the window.
+        self.mark_received(counter);
+        Ok(packet)
+    }
+
+    fn is_replay(&self, counter: u64) -> bool {
+        match self.highest_received {
+            None => false,
+            Some(highest) if counter > highest => false,
+            Some(highest) => {
+                let age = highest - counter;
+                age >= REPLAY_WINDOW || self.received & (1 << age) != 0
+            }
+        }
+    }
+
+    fn mark_received(&mut self, counter: u64) {
+        match self.highest_received {
+            Some(highest) if counter <= highest => {
+                self.received |= 1 << (highest - counter);
+            }
+            Some(highest) => {
+                let shift = counter - highest;
+                self.received = if shift >= REPLAY_WINDOW {
+                    1
+                } else {
+                    (self.received << shift) | 1
+                };
+                self.highest_received = Some(counter);
+            }
+            None => {
+                self.received = 1;
+                self.highest_received = Some(counter);
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Coalescing of small messages.
+//!
+//! Many protocol messages (acks, head updates, capability announcements) are
+//! only a few dozen bytes, and each would otherwise cost a packet and an ACK
+//! of its own. A `Coalescer` holds such messages for up to `COALESCE_DELAY`
+//! and sends them together as one `MessageType::Batch` message: a list of
+//! envelopes, each carrying its message type and length-prefixed payload.
+//! The receiving session unpacks a batch into its messages.
+
+use crate::error::SequencedError;
+use crate::protocol::{self, InboundEnvelope, MessageType, OutboundEnvelope};
+use std::time::Duration;
+use tox_proto::ToxProto;
+use tox_proto::time::Instant;
+
+/// Longest time a message is held back waiting for others.
+pub const COALESCE_DELAY: Duration = Duration::from_millis(5);
+/// Messages larger than this are never coalesced.
+pub const MAX_COALESCED_MESSAGE: usize = 256;
+
+/// Small messages waiting to be sent together.
+#[derive(Debug, Clone, Default, ToxProto)]
+pub struct Coalescer {
+    pending: Vec<(MessageType, Vec<u8>)>,
+    /// Encoded size of the pending batch.
+    size: usize,
+    /// When the oldest pending message was queued.
+    first_at: Option<Instant>,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Encoded size of the batch if it were sent now.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Queues a message.
+    pub fn push(&mut self, message_type: MessageType, data: &[u8], now: Instant) {
+        // Array header, type and binary header of the envelope.
+        self.size += data.len() + 5;
+        self.pending.push((message_type, data.to_vec()));
+        self.first_at.get_or_insert(now);
+    }
+
+    /// Time at which the pending messages must be sent.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.first_at.map(|t| t + COALESCE_DELAY)
+    }
+
+    /// Whether the pending messages are due at `now`.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.deadline().is_some_and(|t| now >= t)
+    }
+
+    /// Takes the pending messages as one message: the message itself if only
+    /// one is pending, a batch otherwise.
+    pub fn take(&mut self) -> Result<Option<(MessageType, Vec<u8>)>, SequencedError> {
+        let pending = std::mem::take(&mut self.pending);
+        self.size = 0;
+        self.first_at = None;
+        match pending.len() {
+            0 => Ok(None),
+            1 => Ok(pending.into_iter().next()),
+            _ => encode_batch(&pending).map(|batch| Some((MessageType::Batch, batch))),
+        }
+    }
+}
+
+/// Encodes messages as the payload of a `MessageType::Batch` message.
+pub fn encode_batch(messages: &[(MessageType, Vec<u8>)]) -> Result<Vec<u8>, SequencedError> {
+    let envelopes: Vec<_> = messages
+        .iter()
+        .map(|(message_type, payload)| OutboundEnvelope {
+            message_type: *message_type,
+            payload,
+        })
+        .collect();
+    protocol::serialize(&envelopes).map_err(|e| SequencedError::SerializationError(e.to_string()))
+}
+
+/// Decodes the payload of a `MessageType::Batch` message.
+pub fn decode_batch(data: &[u8]) -> Result<Vec<(MessageType, Vec<u8>)>, SequencedError> {
+    let envelopes: Vec<InboundEnvelope> = protocol::deserialize(data)
+        .map_err(|e| SequencedError::SerializationError(e.to_string()))?;
+    Ok(envelopes
+        .into_iter()
+        .map(|e| (e.message_type, e.payload))
+        .collect())
+}
+
+/// Reads the messages of an encoded batch without copying them, as
+/// `protocol::peek_envelope` does: `data` may end inside a message, whose
+/// payload is then cut short, and the messages after it are left out.
+pub fn peek_batch(data: &[u8]) -> Vec<(MessageType, &[u8])> {
+    let Some((count, mut rest)) = protocol::split_array_header(data) else {
+        return Vec::new();
+    };
+    let mut messages = Vec::new();
+    while messages.len() < count
+        && let Some((message_type, payload, next)) = protocol::peek_envelope(rest)
+    {
+        messages.push((message_type, payload));
+        rest = next;
+    }
+    messages
+}
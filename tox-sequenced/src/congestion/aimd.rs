@@ -1,6 +1,7 @@
 use super::CongestionControl;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 const INITIAL_CWND: f32 = 10.0;
 const INITIAL_SSTHRESH: f32 = 64.0;
@@ -83,4 +84,12 @@ impl CongestionControl for Aimd {
     }
 
     fn on_fragment_sent(&mut self, _bytes: usize, _now: Instant) {}
+
+    fn state(&self) -> &'static str {
+        if self.cwnd < self.ssthresh {
+            "SlowStart"
+        } else {
+            "CongestionAvoidance"
+        }
+    }
 }
@@ -1,7 +1,8 @@
 use super::{CongestionControl, DeliverySample};
 use std::collections::VecDeque;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 const RT_PROP_FILTER_LEN: Duration = Duration::from_secs(10);
 const PROBE_RTT_INTERVAL: Duration = Duration::from_secs(10);
@@ -334,4 +335,13 @@ impl CongestionControl for Bbrv1 {
     }
 
     fn on_fragment_sent(&mut self, _bytes: usize, _now: Instant) {}
+
+    fn state(&self) -> &'static str {
+        match self.state {
+            Bbrv1State::Startup => "Startup",
+            Bbrv1State::Drain => "Drain",
+            Bbrv1State::ProbeBw => "ProbeBw",
+            Bbrv1State::ProbeRtt => "ProbeRtt",
+        }
+    }
 }
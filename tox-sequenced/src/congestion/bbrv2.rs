@@ -1,7 +1,8 @@
 use super::{CongestionControl, DeliverySample};
 use std::collections::VecDeque;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 // BBRv2 Constants
 const RT_PROP_FILTER_LEN: Duration = Duration::from_secs(10);
@@ -469,4 +470,16 @@ impl CongestionControl for Bbrv2 {
     fn on_fragment_sent(&mut self, _bytes: usize, now: Instant) {
         self.last_now = Some(now);
     }
+
+    fn state(&self) -> &'static str {
+        match self.state {
+            Bbrv2State::Startup => "Startup",
+            Bbrv2State::Drain => "Drain",
+            Bbrv2State::ProbeBwDown => "ProbeBwDown",
+            Bbrv2State::ProbeBwCruise => "ProbeBwCruise",
+            Bbrv2State::ProbeBwRefill => "ProbeBwRefill",
+            Bbrv2State::ProbeBwUp => "ProbeBwUp",
+            Bbrv2State::ProbeRtt => "ProbeRtt",
+        }
+    }
 }
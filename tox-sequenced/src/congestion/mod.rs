@@ -1,6 +1,7 @@
 use std::fmt;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 /// A sample of delivery information for a single ACKed fragment.
 #[derive(Debug, Clone, Copy, ToxProto)]
@@ -39,17 +40,22 @@ pub trait CongestionControl: Send {
 
     /// Called when a fragment is sent.
     fn on_fragment_sent(&mut self, bytes: usize, now: Instant);
+
+    /// Name of the phase the algorithm is in (e.g. "SlowStart", "ProbeRtt").
+    fn state(&self) -> &'static str;
 }
 
 pub mod aimd;
 pub mod bbrv1;
 pub mod bbrv2;
 pub mod cubic;
+pub mod trace;
 
 pub use aimd::Aimd;
 pub use bbrv1::Bbrv1;
 pub use bbrv2::Bbrv2;
 pub use cubic::Cubic;
+pub use trace::{CongestionTrace, TraceEvent, TraceEventKind, TraceTrigger};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ToxProto)]
 pub enum AlgorithmType {
@@ -153,4 +159,8 @@ impl CongestionControl for Algorithm {
     fn on_fragment_sent(&mut self, bytes: usize, now: Instant) {
         dispatch!(self, on_fragment_sent, bytes, now)
     }
+
+    fn state(&self) -> &'static str {
+        dispatch!(self, state)
+    }
 }
@@ -0,0 +1,303 @@
+//! Congestion control tracing for offline analysis.
+//!
+//! A [`CongestionTrace`] records the congestion window, RTT and bytes in
+//! flight after every ACK, NACK and timeout, plus the algorithm's phase
+//! transitions, into a bounded buffer. [`CongestionTrace::export_qlog`]
+//! writes the buffer as qlog JSON, which qvis and similar tools can plot.
+
+use super::CongestionControl;
+use crate::protocol::ESTIMATED_PAYLOAD_SIZE;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tox_proto::time::Instant;
+
+/// Events kept before the oldest are dropped.
+pub const DEFAULT_TRACE_CAPACITY: usize = 8192;
+
+/// What made the congestion controller update its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceTrigger {
+    /// The trace was started.
+    Start,
+    Ack,
+    Nack,
+    Timeout,
+}
+
+impl TraceTrigger {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceTrigger::Start => "start",
+            TraceTrigger::Ack => "ack",
+            TraceTrigger::Nack => "nack",
+            TraceTrigger::Timeout => "timeout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEventKind {
+    /// The controller's state after an update.
+    Metrics {
+        trigger: TraceTrigger,
+        /// Congestion window in fragments.
+        cwnd: usize,
+        bytes_in_flight: usize,
+        /// The RTT measured by this update, if any.
+        rtt_sample: Option<Duration>,
+        smoothed_rtt: Duration,
+        min_rtt: Duration,
+        /// Pacing rate in bytes per second.
+        pacing_rate: f32,
+    },
+    /// The algorithm moved to another phase, e.g. from "Startup" to "Drain".
+    StateChanged {
+        trigger: TraceTrigger,
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    /// Time since the trace was started.
+    pub time: Duration,
+    pub kind: TraceEventKind,
+}
+
+/// A bounded record of a session's congestion control decisions.
+#[derive(Debug, Clone)]
+pub struct CongestionTrace {
+    start: Instant,
+    capacity: usize,
+    events: VecDeque<TraceEvent>,
+    dropped: u64,
+    state: &'static str,
+}
+
+impl CongestionTrace {
+    /// Starts a trace of `cc`, keeping at most `capacity` events.
+    pub fn new<C: CongestionControl + ?Sized>(
+        cc: &C,
+        capacity: usize,
+        smoothed_rtt: Duration,
+        bytes_in_flight: usize,
+        now: Instant,
+    ) -> Self {
+        let mut trace = Self {
+            start: now,
+            capacity: capacity.max(1),
+            events: VecDeque::new(),
+            dropped: 0,
+            state: cc.state(),
+        };
+        trace.record(
+            cc,
+            TraceTrigger::Start,
+            None,
+            smoothed_rtt,
+            bytes_in_flight,
+            now,
+        );
+        trace
+    }
+
+    /// Records the state of `cc` after it handled `trigger`, and the phase
+    /// transition if there was one.
+    pub fn record<C: CongestionControl + ?Sized>(
+        &mut self,
+        cc: &C,
+        trigger: TraceTrigger,
+        rtt_sample: Option<Duration>,
+        smoothed_rtt: Duration,
+        bytes_in_flight: usize,
+        now: Instant,
+    ) {
+        let time = now.saturating_duration_since(self.start);
+        let state = cc.state();
+        if state != self.state {
+            self.push(TraceEvent {
+                time,
+                kind: TraceEventKind::StateChanged {
+                    trigger,
+                    from: self.state,
+                    to: state,
+                },
+            });
+            self.state = state;
+        }
+        self.push(TraceEvent {
+            time,
+            kind: TraceEventKind::Metrics {
+                trigger,
+                cwnd: cc.cwnd(),
+                bytes_in_flight,
+                rtt_sample,
+                smoothed_rtt,
+                min_rtt: cc.min_rtt(),
+                pacing_rate: cc.pacing_rate(),
+            },
+        });
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Number of events dropped because the buffer was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// The algorithm's phase as of the last recorded event.
+    pub fn state(&self) -> &'static str {
+        self.state
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.dropped = 0;
+    }
+
+    /// The trace as a qlog (JSON) document titled `title`.
+    ///
+    /// Updates become `recovery:metrics_updated` events and phase changes
+    /// `recovery:congestion_state_updated` events. Times are milliseconds
+    /// since the trace started, window and flight sizes are in bytes and the
+    /// pacing rate is in bits per second, as qlog expects. The window in
+    /// fragments and the update's trigger are added as extra fields.
+    pub fn export_qlog(&self, title: &str) -> String {
+        let qlog = Qlog {
+            qlog_version: "0.3",
+            qlog_format: "JSON",
+            title,
+            traces: [QlogTrace {
+                common_fields: CommonFields {
+                    time_format: "relative",
+                    reference_time: 0,
+                },
+                summary: Summary {
+                    events_dropped: self.dropped,
+                },
+                events: self.events.iter().map(QlogEvent::from).collect(),
+            }],
+        };
+        serde_json::to_string(&qlog).expect("qlog serialization cannot fail")
+    }
+
+    fn push(&mut self, event: TraceEvent) {
+        if self.events.len() >= self.capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+        self.events.push_back(event);
+    }
+}
+
+#[derive(Serialize)]
+struct Qlog<'a> {
+    qlog_version: &'static str,
+    qlog_format: &'static str,
+    title: &'a str,
+    traces: [QlogTrace; 1],
+}
+
+#[derive(Serialize)]
+struct QlogTrace {
+    common_fields: CommonFields,
+    summary: Summary,
+    events: Vec<QlogEvent>,
+}
+
+#[derive(Serialize)]
+struct CommonFields {
+    time_format: &'static str,
+    reference_time: u64,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    events_dropped: u64,
+}
+
+#[derive(Serialize)]
+struct QlogEvent {
+    time: f64,
+    name: &'static str,
+    data: QlogData,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum QlogData {
+    Metrics {
+        congestion_window: usize,
+        congestion_window_fragments: usize,
+        bytes_in_flight: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        latest_rtt: Option<f64>,
+        smoothed_rtt: f64,
+        min_rtt: f64,
+        pacing_rate: u64,
+        trigger: &'static str,
+    },
+    State {
+        old: &'static str,
+        new: &'static str,
+        trigger: &'static str,
+    },
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl From<&TraceEvent> for QlogEvent {
+    fn from(event: &TraceEvent) -> Self {
+        let (name, data) = match event.kind {
+            TraceEventKind::Metrics {
+                trigger,
+                cwnd,
+                bytes_in_flight,
+                rtt_sample,
+                smoothed_rtt,
+                min_rtt,
+                pacing_rate,
+            } => (
+                "recovery:metrics_updated",
+                QlogData::Metrics {
+                    congestion_window: cwnd * ESTIMATED_PAYLOAD_SIZE,
+                    congestion_window_fragments: cwnd,
+                    bytes_in_flight,
+                    latest_rtt: rtt_sample.map(millis),
+                    smoothed_rtt: millis(smoothed_rtt),
+                    min_rtt: millis(min_rtt),
+                    pacing_rate: (pacing_rate as f64 * 8.0) as u64,
+                    trigger: trigger.name(),
+                },
+            ),
+            TraceEventKind::StateChanged { trigger, from, to } => (
+                "recovery:congestion_state_updated",
+                QlogData::State {
+                    old: from,
+                    new: to,
+                    trigger: trigger.name(),
+                },
+            ),
+        };
+        QlogEvent {
+            time: millis(event.time),
+            name,
+            data,
+        }
+    }
+}
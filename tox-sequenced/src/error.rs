@@ -16,4 +16,12 @@ pub enum SequencedError {
     InvalidMtu,
     #[error("Invalid total fragments count")]
     InvalidTotalFragments,
+    /// A sealed packet was forged, corrupted or sealed under another key.
+    #[error("Packet authentication failed")]
+    AuthenticationFailed,
+    #[error("Replayed packet")]
+    ReplayedPacket,
+    /// One end of the session seals its packets and the other does not.
+    #[error("Packet sealing does not match the peer")]
+    CipherMismatch,
 }
@@ -10,13 +10,19 @@
 //! - **Reliability**: Uses Selective Repeat ARQ (Selective ACKs and NACKs).
 //! - **Congestion Control**: Pluggable algorithms including BBR, Cubic, and AIMD.
 //! - **Memory Management**: Shared reassembly quotas to prevent memory exhaustion.
+//! - **Security**: Optional packet sealing (XChaCha20-Poly1305) for transports
+//!   without their own encryption.
 //! - **Serialization**: Built on `rmp-serde` for efficient MessagePack encoding.
 
 pub mod bitset;
+pub mod cipher;
+pub mod coalesce;
 pub mod congestion;
 pub mod error;
 pub mod flat_map;
+pub mod mtu;
 pub mod outgoing;
+pub mod path;
 pub mod protocol;
 pub mod quota;
 pub mod reassembly;
@@ -31,7 +37,7 @@ use tox_proto::ToxProto;
 pub enum SessionEvent {
     /// A complete message has been received.
     MessageCompleted(protocol::MessageId, MessageType, Vec<u8>),
-    /// A message that was being sent has failed (e.g., timed out).
+    /// A message that was being sent has failed (e.g., timed out or cancelled).
     MessageFailed(protocol::MessageId, String),
     /// An outgoing message has been acknowledged by the peer.
     MessageAcked(protocol::MessageId),
@@ -42,12 +48,16 @@ pub enum SessionEvent {
 }
 
 pub use bitset::BitSet;
+pub use cipher::{CipherRole, PacketCipher};
+pub use coalesce::Coalescer;
 pub use congestion::aimd::Aimd;
 pub use congestion::bbrv1::Bbrv1;
 pub use congestion::bbrv2::Bbrv2;
 pub use congestion::cubic::Cubic;
 pub use congestion::{Algorithm, AlgorithmType, CongestionControl};
 pub use error::SequencedError;
+pub use mtu::MtuDiscovery;
+pub use path::{PathEstimates, PathQuality};
 pub use protocol::{MessageType, Packet};
 pub use reassembly::MessageReassembler;
-pub use session::SequenceSession;
+pub use session::{IncomingStatus, OutgoingStatus, SequenceSession};
@@ -0,0 +1,217 @@
+//! Packetization Layer Path MTU Discovery, after RFC 8899.
+//!
+//! A session starts out sending packets of the path's maximum size. Padded
+//! `MtuProbe` packets, answered by `MtuProbeAck`, confirm a size; a probe lost
+//! `MAX_PROBES` times rules it out. Probing starts once the peer has answered
+//! a Ping, so probes are not wasted on an unreachable peer. The first probe checks the maximum
+//! itself. If it is lost, the session falls back to the largest confirmed
+//! size and a binary search looks for the largest size the path delivers.
+//! Once the search converges it is repeated every `MTU_RAISE_INTERVAL`, as
+//! paths change.
+
+use crate::protocol::{self, Packet};
+use std::time::Duration;
+use tox_proto::ToxProto;
+use tox_proto::time::Instant;
+
+/// Packet size assumed to be deliverable on any path.
+pub const BASE_PACKET_SIZE: usize = 512;
+/// The search stops once the unexplored range is this small.
+pub const MTU_PROBE_GRANULARITY: usize = 32;
+/// Number of times a probe size is tried before it is ruled out.
+pub const MAX_PROBES: u32 = 3;
+/// Minimum time to wait for a probe acknowledgement.
+pub const MIN_PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time after which a converged search is repeated.
+pub const MTU_RAISE_INTERVAL: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToxProto)]
+struct PendingProbe {
+    probe_id: u32,
+    size: usize,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// MTU discovery state of one session.
+#[derive(Debug, Clone, ToxProto)]
+pub struct MtuDiscovery {
+    /// Largest packet the path may carry (from the transport).
+    max: usize,
+    /// Largest size known to be delivered.
+    confirmed: usize,
+    /// Smallest size known not to be delivered, or `max + 1`.
+    ceiling: usize,
+    /// Whether any probe has been lost, i.e. `max` can no longer be assumed.
+    black_hole: bool,
+    /// Whether the peer has been heard from; probing waits for it.
+    peer_confirmed: bool,
+    probe: Option<PendingProbe>,
+    next_probe_id: u32,
+    /// When to repeat a converged search.
+    raise_at: Option<Instant>,
+    /// When the last probe was resolved; the next one is due from then.
+    resolved_at: Instant,
+}
+
+impl MtuDiscovery {
+    pub fn new(max: usize, now: Instant) -> Self {
+        Self {
+            max,
+            confirmed: BASE_PACKET_SIZE.min(max),
+            ceiling: max + 1,
+            black_hole: false,
+            peer_confirmed: false,
+            probe: None,
+            next_probe_id: 0,
+            raise_at: None,
+            resolved_at: now,
+        }
+    }
+
+    /// Size of the packets to send: the path maximum until a probe has been
+    /// lost, the largest confirmed size after.
+    pub fn packet_size(&self) -> usize {
+        if self.black_hole {
+            self.confirmed
+        } else {
+            self.max
+        }
+    }
+
+    /// Largest size acknowledged by the peer so far.
+    pub fn confirmed(&self) -> usize {
+        self.confirmed
+    }
+
+    /// Whether a search is in progress.
+    pub fn is_searching(&self) -> bool {
+        self.probe.is_some() || self.next_probe_size().is_some()
+    }
+
+    /// Restarts discovery for a new path maximum.
+    pub fn set_max(&mut self, max: usize) {
+        if max != self.max {
+            *self = Self {
+                next_probe_id: self.next_probe_id,
+                peer_confirmed: self.peer_confirmed,
+                ..Self::new(max, self.resolved_at)
+            };
+        }
+    }
+
+    /// Starts from a size confirmed earlier on the same path, e.g. by a
+    /// previous run. Probing continues above it. Ignored if the search has
+    /// already confirmed more.
+    pub fn seed(&mut self, confirmed: usize) {
+        let confirmed = confirmed.min(self.max).min(self.ceiling - 1);
+        self.confirmed = self.confirmed.max(confirmed);
+    }
+
+    /// Allows probing to start, once the peer is known to be reachable.
+    pub fn confirm_peer(&mut self) {
+        self.peer_confirmed = true;
+    }
+
+    fn next_probe_size(&self) -> Option<usize> {
+        if !self.peer_confirmed {
+            return None;
+        }
+        if !self.black_hole && self.ceiling > self.max && self.confirmed < self.max {
+            // Check the optimistic assumption first.
+            return Some(self.max);
+        }
+        if self.ceiling - self.confirmed <= MTU_PROBE_GRANULARITY {
+            return None;
+        }
+        Some((self.confirmed + self.ceiling) / 2)
+    }
+
+    /// Returns the probe to send now, if any. `rto` is the session's current
+    /// retransmission timeout.
+    pub fn poll(&mut self, now: Instant, rto: Duration) -> Option<Packet> {
+        if let Some(probe) = &mut self.probe {
+            if now.saturating_duration_since(probe.sent_at) < rto.max(MIN_PROBE_TIMEOUT) {
+                return None;
+            }
+            if probe.attempts < MAX_PROBES {
+                probe.attempts += 1;
+                probe.sent_at = now;
+                return Some(probe_packet(probe.probe_id, probe.size));
+            }
+            let size = probe.size;
+            self.probe = None;
+            self.ceiling = size;
+            self.black_hole = true;
+            self.resolved_at = now;
+        }
+
+        if self.raise_at.is_some_and(|at| now >= at) {
+            self.raise_at = None;
+            self.ceiling = self.max + 1;
+        }
+
+        match self.next_probe_size() {
+            Some(size) => {
+                let probe_id = self.next_probe_id;
+                self.next_probe_id = self.next_probe_id.wrapping_add(1);
+                self.probe = Some(PendingProbe {
+                    probe_id,
+                    size,
+                    sent_at: now,
+                    attempts: 1,
+                });
+                Some(probe_packet(probe_id, size))
+            }
+            None => {
+                if self.raise_at.is_none() {
+                    self.raise_at = Some(now + MTU_RAISE_INTERVAL);
+                }
+                None
+            }
+        }
+    }
+
+    /// Handles the acknowledgement of a probe. Returns whether it confirmed
+    /// a new size.
+    pub fn on_ack(&mut self, probe_id: u32, now: Instant) -> bool {
+        match self.probe {
+            Some(probe) if probe.probe_id == probe_id => {
+                self.probe = None;
+                self.resolved_at = now;
+                self.confirmed = self.confirmed.max(probe.size);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Time at which `poll` has work to do.
+    pub fn next_check_time(&self, rto: Duration) -> Option<Instant> {
+        match &self.probe {
+            Some(probe) => Some(probe.sent_at + rto.max(MIN_PROBE_TIMEOUT)),
+            None if self.next_probe_size().is_some() => Some(self.resolved_at),
+            None => self.raise_at,
+        }
+    }
+}
+
+/// Builds an `MtuProbe` that serializes to exactly `size` bytes, or as
+/// close as the encoding allows.
+pub fn probe_packet(probe_id: u32, size: usize) -> Packet {
+    let make = |len: usize| Packet::MtuProbe {
+        probe_id,
+        padding: vec![0; len],
+    };
+    let encoded_len = |packet: &Packet| protocol::serialize(packet).map_or(0, |b| b.len());
+    let mut len = size.saturating_sub(encoded_len(&make(0)));
+    // The length prefix of the padding grows with its size; correct for it.
+    for _ in 0..2 {
+        let encoded = encoded_len(&make(len));
+        if encoded <= size {
+            break;
+        }
+        len = len.saturating_sub(encoded - size);
+    }
+    make(len)
+}
@@ -2,8 +2,9 @@ use crate::bitset::BitSet;
 use crate::error::SequencedError;
 use crate::protocol::{FragmentCount, FragmentIndex, MessageType};
 use std::collections::VecDeque;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 /// Result of processing an ACK for a message.
 pub struct AckResult {
@@ -113,6 +114,30 @@ impl OutgoingMessage {
         })
     }
 
+    /// Changes the fragment size of a message none of whose fragments have
+    /// been sent yet. Returns whether the message was re-fragmented.
+    pub fn refragment(&mut self, payload_mtu: usize) -> bool {
+        if self.next_fragment.0 != 0 || payload_mtu == 0 || payload_mtu == self.payload_mtu {
+            return false;
+        }
+        let num_fragments = self.data.len().div_ceil(payload_mtu);
+        if num_fragments > crate::protocol::MAX_FRAGMENTS_PER_MESSAGE as usize {
+            return false;
+        }
+        self.payload_mtu = payload_mtu;
+        self.num_fragments = FragmentCount(num_fragments as u16);
+        self.fragment_states = vec![
+            FragmentState {
+                last_sent: None,
+                delivery_info: None,
+                retransmit_count: 0,
+                rto_backoff: 0,
+            };
+            num_fragments
+        ];
+        true
+    }
+
     pub fn is_acked(&self, index: FragmentIndex) -> bool {
         if index.0 >= self.num_fragments.0 {
             return true;
@@ -434,31 +459,36 @@ impl OutgoingMessage {
 
         // We check for holes from highest_cumulative_ack up to highest_index.
         // Any fragment that has at least 3 fragments acked after it is considered lost.
-        let mut acked_so_far = self.highest_cumulative_ack.0 as usize;
         let search_limit = self
             .acked_bitset
             .last_one(self.num_fragments.0 as usize)
             .map(|i| i + 1)
             .unwrap_or(0);
 
-        for idx in (self.highest_cumulative_ack.0 as usize)..search_limit {
-            if self.acked_bitset.get(idx) {
-                acked_so_far += 1;
-            } else if !self.retransmit_bitset.get(idx) {
-                // It's a hole.
-                let acked_after = acked_count - acked_so_far;
-                if acked_after >= 3 {
-                    let should_trigger = if let Some(ls_this) = self.fragment_states[idx].last_sent
-                    {
-                        max_ls.is_some_and(|ls_acked| ls_acked >= ls_this)
-                    } else {
-                        false
-                    };
+        let mut acked_so_far = self.highest_cumulative_ack.0 as usize;
+        let mut counted_to = acked_so_far;
+        let mut curr = acked_so_far;
+        while let Some(idx) = self.acked_bitset.next_zero(curr, search_limit) {
+            curr = idx + 1;
+            if self.retransmit_bitset.get(idx) {
+                continue;
+            }
+            // It's a hole.
+            acked_so_far += self.acked_bitset.count_ones_between(counted_to, idx);
+            counted_to = idx;
+            let acked_after = acked_count - acked_so_far;
+            if acked_after < 3 {
+                // Fewer fragments are acked after every later hole.
+                break;
+            }
+            let should_trigger = if let Some(ls_this) = self.fragment_states[idx].last_sent {
+                max_ls.is_some_and(|ls_acked| ls_acked >= ls_this)
+            } else {
+                false
+            };
 
-                    if should_trigger {
-                        self.trigger_loss(FragmentIndex(idx as u16), to_remove, needs_cleanup, res);
-                    }
-                }
+            if should_trigger {
+                self.trigger_loss(FragmentIndex(idx as u16), to_remove, needs_cleanup, res);
             }
         }
     }
@@ -0,0 +1,65 @@
+//! Properties of the network path to a peer, as reported by the transport.
+
+use crate::congestion::AlgorithmType;
+use crate::protocol::{MAX_TOX_PACKET_SIZE, RELAYED_PACKET_SIZE};
+use crate::rtt::{Rfc6298Rto, RtoAlgorithm};
+use std::time::Duration;
+use tox_proto::ToxProto;
+
+/// What the transport knows about the path to a peer. The default describes
+/// a direct path with nothing known about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ToxProto)]
+pub struct PathQuality {
+    /// Largest packet the path carries, if known.
+    pub mtu: Option<usize>,
+    /// The path goes through a TCP relay rather than direct UDP.
+    pub relayed: bool,
+    /// Round-trip time measured by the transport, if known.
+    pub rtt: Option<Duration>,
+}
+
+impl PathQuality {
+    /// Largest packet that may be sent on this path.
+    pub fn max_packet_size(&self) -> usize {
+        self.mtu
+            .map_or(MAX_TOX_PACKET_SIZE, |mtu| mtu.min(MAX_TOX_PACKET_SIZE))
+    }
+
+    /// Preferred packet size for fragments. Relayed paths use smaller
+    /// packets, so a single fragment holds up less of the relay's TCP stream.
+    pub fn packet_size(&self) -> usize {
+        if self.relayed {
+            self.max_packet_size().min(RELAYED_PACKET_SIZE)
+        } else {
+            self.max_packet_size()
+        }
+    }
+
+    /// Congestion control suited to the path, or `None` to keep the
+    /// session's own. Relayed paths use Cubic: the relay's TCP buffering
+    /// adds queueing delay that the model-based algorithms misread.
+    pub fn preferred_algorithm(&self) -> Option<AlgorithmType> {
+        self.relayed.then_some(AlgorithmType::Cubic)
+    }
+
+    /// Retransmission timers suited to the path. Relayed paths wait longer
+    /// before retransmitting: the relay delivers reliably but with large
+    /// delay spikes.
+    pub fn rto_strategy(&self) -> RtoAlgorithm {
+        if self.relayed {
+            Rfc6298Rto::relayed().into()
+        } else {
+            RtoAlgorithm::default()
+        }
+    }
+}
+
+/// What a session has measured about its path, kept across restarts of the
+/// session (see `SequenceSession::estimates`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToxProto)]
+pub struct PathEstimates {
+    /// Smoothed RTT, if any has been measured.
+    pub rtt: Option<Duration>,
+    /// Largest packet size acknowledged by the peer.
+    pub mtu: usize,
+}
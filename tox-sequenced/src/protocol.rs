@@ -1,9 +1,9 @@
 use smallvec::SmallVec;
 use std::time::Duration;
-use tox_proto::ToxProto;
 pub use tox_proto::constants::{
     MAX_TOTAL_REASSEMBLY_BUFFER, MAX_TOX_PACKET_SIZE, MIN_TRANSPORT_SLOTS,
 };
+use tox_proto::{ToxContext, ToxDeserialize, ToxProto};
 
 macro_rules! protocol_newtype {
     ($name:ident, $inner:ty, $doc:expr) => {
@@ -32,6 +32,10 @@ protocol_newtype!(
     "Unique identifier for a reliable message within a session."
 );
 
+/// Half the message ID space. IDs closer than this are ordered by serial
+/// number arithmetic (RFC 1982); IDs exactly this far apart are unordered.
+pub const MESSAGE_ID_HALF_RANGE: u32 = 1 << 31;
+
 impl MessageId {
     pub fn wrapping_add(self, val: u32) -> Self {
         MessageId(self.0.wrapping_add(val))
@@ -39,6 +43,68 @@ impl MessageId {
     pub fn wrapping_sub(self, other: MessageId) -> u32 {
         self.0.wrapping_sub(other.0)
     }
+
+    /// Whether `self` was allocated after `other`, allowing for the ID
+    /// space wrapping around. Unlike `>`, this holds for `MessageId(1)`
+    /// against `MessageId(u32::MAX)`.
+    pub fn is_after(self, other: MessageId) -> bool {
+        let diff = self.wrapping_sub(other);
+        diff != 0 && diff < MESSAGE_ID_HALF_RANGE
+    }
+
+    /// Whether `self` was allocated before `other`, allowing for the ID
+    /// space wrapping around.
+    pub fn is_before(self, other: MessageId) -> bool {
+        other.is_after(self)
+    }
+
+    /// Number of IDs `self` lies behind `other`, or `None` if it is not
+    /// before it.
+    pub fn distance_behind(self, other: MessageId) -> Option<u32> {
+        self.is_before(other).then(|| other.wrapping_sub(self))
+    }
+}
+
+/// A message ID together with the number of times the 32-bit ID space
+/// wrapped before it, so IDs from different generations never compare
+/// equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, ToxProto)]
+#[tox(flat)]
+pub struct ExtendedMessageId(pub u64);
+
+impl ExtendedMessageId {
+    pub fn new(generation: u32, id: MessageId) -> Self {
+        ExtendedMessageId(((generation as u64) << 32) | id.0 as u64)
+    }
+
+    pub fn generation(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    pub fn id(self) -> MessageId {
+        MessageId(self.0 as u32)
+    }
+
+    /// Places `id` in the generation that puts it nearest to `self`, i.e.
+    /// within half the ID space either way. An ID just past the wrap point
+    /// lands in the next generation, one just before it in the previous.
+    pub fn extend(self, id: MessageId) -> ExtendedMessageId {
+        let generation = self.generation();
+        let generation = if id.is_after(self.id()) && id.0 < self.id().0 {
+            generation.wrapping_add(1)
+        } else if id.is_before(self.id()) && id.0 > self.id().0 {
+            generation.wrapping_sub(1)
+        } else {
+            generation
+        };
+        ExtendedMessageId::new(generation, id)
+    }
+}
+
+impl std::fmt::Display for ExtendedMessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.generation(), self.id())
+    }
 }
 
 protocol_newtype!(
@@ -90,6 +156,9 @@ pub enum PacketType {
     Ping = 0x03,
     Pong = 0x04,
     Datagram = 0x05,
+    MtuProbe = 0x06,
+    MtuProbeAck = 0x07,
+    Cancel = 0x08,
 }
 
 /// A selective acknowledgment for fragments of a message.
@@ -139,6 +208,9 @@ pub const REASSEMBLY_TIMEOUT_SECS: u64 = 30;
 /// Overhead for Packet::Data variant serialization (conservative estimate).
 pub const PACKET_OVERHEAD: usize = 20;
 
+/// Preferred packet size on paths relayed over TCP.
+pub const RELAYED_PACKET_SIZE: usize = 512;
+
 /// Pacing gain used by AIMD and Cubic (2.0x).
 pub const PACING_GAIN: f32 = 2.0;
 
@@ -177,6 +249,53 @@ pub enum Packet {
         message_type: MessageType,
         data: Vec<u8>,
     },
+    /// Padded packet testing whether the path delivers its size (Type 0x06).
+    MtuProbe {
+        probe_id: u32,
+        padding: Vec<u8>,
+    },
+    /// Acknowledges a received `MtuProbe` (Type 0x07).
+    MtuProbeAck {
+        probe_id: u32,
+    },
+    /// The sender gave up on a message; its reassembly can be dropped
+    /// (Type 0x08).
+    Cancel {
+        message_id: MessageId,
+    },
+}
+
+impl Packet {
+    /// Reliable message the packet belongs to, if any.
+    pub fn message_id(&self) -> Option<MessageId> {
+        match self {
+            Packet::Data { message_id, .. } => Some(*message_id),
+            Packet::Ack(ack) => Some(ack.message_id),
+            Packet::Nack(nack) => Some(nack.message_id),
+            Packet::Cancel { message_id } => Some(*message_id),
+            Packet::Ping { .. }
+            | Packet::Pong { .. }
+            | Packet::Datagram { .. }
+            | Packet::MtuProbe { .. }
+            | Packet::MtuProbeAck { .. } => None,
+        }
+    }
+
+    /// Type and leading payload bytes of the message this packet starts:
+    /// the first fragment of a `Data` message, or a `Datagram`. The payload
+    /// of a fragmented message is cut short at the end of the fragment.
+    /// `None` for other packets.
+    pub fn peek_message(&self) -> Option<(MessageType, &[u8])> {
+        match self {
+            Packet::Data {
+                fragment_index: FragmentIndex(0),
+                data,
+                ..
+            } => peek_envelope(data).map(|(message_type, payload, _)| (message_type, payload)),
+            Packet::Datagram { message_type, data } => Some((*message_type, data)),
+            _ => None,
+        }
+    }
 }
 
 /// High-level message types carried in the reassembled DATA payload.
@@ -203,6 +322,8 @@ pub enum MessageType {
     ReconPowChallenge = 0x12,
     ReconPowSolution = 0x13,
     AdminGossip = 0x14,
+    /// Several small messages sent together (see `coalesce`).
+    Batch = 0x15,
 }
 
 impl MessageType {
@@ -222,6 +343,7 @@ impl MessageType {
             MessageType::BlobData => Priority::Bulk,
             MessageType::ReinclusionRequest | MessageType::ReinclusionResponse => Priority::High,
             MessageType::AdminGossip => Priority::High,
+            MessageType::Batch => Priority::High,
         }
     }
 }
@@ -240,4 +362,49 @@ pub struct InboundEnvelope {
     pub payload: Vec<u8>,
 }
 
+/// Reads an encoded envelope from the start of `data` without copying it:
+/// returns the message type, the payload and the bytes after it. `data`
+/// may end inside the payload, which is then cut short.
+pub fn peek_envelope(data: &[u8]) -> Option<(MessageType, &[u8], &[u8])> {
+    let (2, mut rest) = split_array_header(data)? else {
+        return None;
+    };
+    let message_type = MessageType::deserialize(&mut rest, &ToxContext::empty()).ok()?;
+    let (len, rest) = split_bin_header(rest)?;
+    let (payload, rest) = rest.split_at(len.min(rest.len()));
+    Some((message_type, payload, rest))
+}
+
+/// Splits a MessagePack array header off `data`, returning the length.
+pub(crate) fn split_array_header(data: &[u8]) -> Option<(usize, &[u8])> {
+    let (&marker, rest) = data.split_first()?;
+    match marker {
+        0x90..=0x9f => Some(((marker & 0x0f) as usize, rest)),
+        0xdc => split_length(rest, 2),
+        0xdd => split_length(rest, 4),
+        _ => None,
+    }
+}
+
+/// Splits a MessagePack binary header off `data`, returning the length.
+fn split_bin_header(data: &[u8]) -> Option<(usize, &[u8])> {
+    let (&marker, rest) = data.split_first()?;
+    match marker {
+        0xc4 => split_length(rest, 1),
+        0xc5 => split_length(rest, 2),
+        0xc6 => split_length(rest, 4),
+        _ => None,
+    }
+}
+
+/// Splits a big-endian length of `width` bytes off `data`.
+fn split_length(data: &[u8], width: usize) -> Option<(usize, &[u8])> {
+    if data.len() < width {
+        return None;
+    }
+    let (len, rest) = data.split_at(width);
+    let len = len.iter().fold(0usize, |n, &b| (n << 8) | b as usize);
+    Some((len, rest))
+}
+
 pub use tox_proto::{deserialize, serialize};
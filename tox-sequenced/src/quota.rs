@@ -88,6 +88,13 @@ impl ReassemblyQuota {
         }
     }
 
+    /// Counts `amount` bytes against the quota even past its capacity, for
+    /// data that is already in memory (e.g. completed messages waiting for
+    /// the application).
+    pub fn reserve_unchecked(&self, amount: usize) {
+        self.used_bytes.fetch_add(amount, Ordering::SeqCst);
+    }
+
     /// Releases `amount` bytes back to the quota.
     pub fn release(&self, amount: usize) {
         loop {
@@ -79,12 +79,8 @@ impl FragmentBuffer {
         self.received_count.0 += 1;
 
         if index == self.base_index {
-            self.base_index.0 += 1;
-            while self.base_index.0 < self.total_fragments.0
-                && self.received_mask.get(self.base_index.0 as usize)
-            {
-                self.base_index.0 += 1;
-            }
+            let next = self.received_mask.first_missing_after(index.0 as usize);
+            self.base_index = FragmentIndex(next.min(self.total_fragments.0 as usize) as u16);
         }
 
         if index > self.highest_index {
@@ -7,8 +7,8 @@ use crate::protocol::{
     Priority, SelectiveAck,
 };
 use smallvec::SmallVec;
-use std::time::Instant;
 use tox_proto::ToxProto;
+use tox_proto::time::Instant;
 
 /// A reliable, fragmented message being reassembled.
 #[derive(Debug, Clone, ToxProto)]
@@ -18,6 +18,8 @@ pub struct MessageReassembler {
     pub priority: Priority,
     pub buffer: FragmentBuffer,
     pub reserved_bytes: usize,
+    /// When the first fragment arrived.
+    pub started_at: Instant,
     pub last_activity: Instant,
 }
 
@@ -38,6 +40,7 @@ impl MessageReassembler {
             priority,
             buffer: FragmentBuffer::new(total_fragments),
             reserved_bytes,
+            started_at: now,
             last_activity: now,
         })
     }
@@ -10,17 +10,187 @@ pub const RTT_ALPHA: f32 = 0.125;
 pub const RTT_BETA: f32 = 0.25;
 pub const RTT_K: u32 = 4;
 pub const MAX_BACKOFF_EXPONENT: u32 = 6;
+pub const RELAYED_MIN_RTO: Duration = Duration::from_millis(1000);
+pub const RELAYED_MAX_RTO: Duration = Duration::from_millis(15000);
+pub const RELAYED_MAX_BACKOFF_EXPONENT: u32 = 3;
+pub const MIN_TLP_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// How the retransmission timers are derived from the RTT estimate.
+///
+/// Direct UDP paths want short timers that recover from loss quickly, while
+/// TCP relays rarely lose packets but add large, bursty queueing delays, so
+/// an early timeout there is almost always spurious.
+pub trait RtoStrategy {
+    /// Timeout used before any RTT has been measured.
+    fn initial_rto(&self) -> Duration;
+
+    /// Timeout before the first retransmission of a fragment.
+    fn rto(&self, srtt: Duration, rttvar: Duration) -> Duration;
+
+    /// Timeout after `retries` retransmissions of the same fragment.
+    fn backoff(&self, rto: Duration, retries: u32) -> Duration;
+
+    /// Time without progress after which the last fragment in flight is
+    /// sent again as a tail loss probe.
+    fn tlp_timeout(&self, srtt: Duration) -> Duration;
+}
+
+/// The timer of RFC 6298: `SRTT + 4 * RTTVAR`, clamped to
+/// `[min_rto, max_rto]`, doubling on every retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToxProto)]
+pub struct Rfc6298Rto {
+    pub min_rto: Duration,
+    pub max_rto: Duration,
+    pub max_backoff_exponent: u32,
+}
+
+impl Default for Rfc6298Rto {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rfc6298Rto {
+    pub fn new() -> Self {
+        Self {
+            min_rto: MIN_RTO,
+            max_rto: MAX_RTO,
+            max_backoff_exponent: MAX_BACKOFF_EXPONENT,
+        }
+    }
+
+    /// Timers for paths through a TCP relay: a high floor, since the relay
+    /// retransmits on its own, and fewer doublings, since a stall there is
+    /// more often queueing than loss.
+    pub fn relayed() -> Self {
+        Self {
+            min_rto: RELAYED_MIN_RTO,
+            max_rto: RELAYED_MAX_RTO,
+            max_backoff_exponent: RELAYED_MAX_BACKOFF_EXPONENT,
+        }
+    }
+}
+
+impl RtoStrategy for Rfc6298Rto {
+    fn initial_rto(&self) -> Duration {
+        INITIAL_RTO.clamp(self.min_rto, self.max_rto)
+    }
+
+    fn rto(&self, srtt: Duration, rttvar: Duration) -> Duration {
+        (srtt + rttvar * RTT_K).clamp(self.min_rto, self.max_rto)
+    }
+
+    fn backoff(&self, rto: Duration, retries: u32) -> Duration {
+        rto * (1 << retries.min(self.max_backoff_exponent))
+    }
+
+    fn tlp_timeout(&self, srtt: Duration) -> Duration {
+        srtt.mul_f32(1.5).max(MIN_TLP_TIMEOUT)
+    }
+}
+
+/// The timer Linux TCP uses: the variance term is floored at `min_rto`
+/// rather than the whole timeout, so the timeout stays above the RTT by a
+/// margin even on very stable paths. Backed-off timeouts are capped at
+/// `max_rto`, and tail loss probes fire after two RTTs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToxProto)]
+pub struct LinuxRto {
+    pub min_rto: Duration,
+    pub max_rto: Duration,
+}
+
+impl Default for LinuxRto {
+    fn default() -> Self {
+        Self {
+            min_rto: MIN_RTO,
+            max_rto: MAX_RTO,
+        }
+    }
+}
+
+impl RtoStrategy for LinuxRto {
+    fn initial_rto(&self) -> Duration {
+        INITIAL_RTO.min(self.max_rto)
+    }
+
+    fn rto(&self, srtt: Duration, rttvar: Duration) -> Duration {
+        (srtt + (rttvar * RTT_K).max(self.min_rto)).min(self.max_rto)
+    }
+
+    fn backoff(&self, rto: Duration, retries: u32) -> Duration {
+        (rto * (1 << retries.min(MAX_BACKOFF_EXPONENT))).min(self.max_rto)
+    }
+
+    fn tlp_timeout(&self, srtt: Duration) -> Duration {
+        (srtt * 2).max(MIN_TLP_TIMEOUT)
+    }
+}
+
+/// The retransmission timers a session can use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ToxProto)]
+pub enum RtoAlgorithm {
+    Rfc6298(Rfc6298Rto),
+    Linux(LinuxRto),
+}
+
+impl Default for RtoAlgorithm {
+    fn default() -> Self {
+        RtoAlgorithm::Rfc6298(Rfc6298Rto::new())
+    }
+}
+
+impl From<Rfc6298Rto> for RtoAlgorithm {
+    fn from(rto: Rfc6298Rto) -> Self {
+        RtoAlgorithm::Rfc6298(rto)
+    }
+}
+
+impl From<LinuxRto> for RtoAlgorithm {
+    fn from(rto: LinuxRto) -> Self {
+        RtoAlgorithm::Linux(rto)
+    }
+}
+
+macro_rules! dispatch {
+    ($self:ident, $fn:ident $(, $args:expr)*) => {
+        match $self {
+            RtoAlgorithm::Rfc6298(r) => r.$fn($($args),*),
+            RtoAlgorithm::Linux(r) => r.$fn($($args),*),
+        }
+    };
+}
+
+impl RtoStrategy for RtoAlgorithm {
+    fn initial_rto(&self) -> Duration {
+        dispatch!(self, initial_rto)
+    }
+
+    fn rto(&self, srtt: Duration, rttvar: Duration) -> Duration {
+        dispatch!(self, rto, srtt, rttvar)
+    }
+
+    fn backoff(&self, rto: Duration, retries: u32) -> Duration {
+        dispatch!(self, backoff, rto, retries)
+    }
+
+    fn tlp_timeout(&self, srtt: Duration) -> Duration {
+        dispatch!(self, tlp_timeout, srtt)
+    }
+}
 
 /// An estimator for Round-Trip Time (RTT) and Retransmission Timeout (RTO).
 ///
-/// This implementation follows the algorithms defined in RFC 6298, using
-/// Smoothed RTT (SRTT) and RTT Variation (RTTVAR) to calculate the
-/// timeout for retransmissions.
+/// The RTT is tracked as in RFC 6298, using Smoothed RTT (SRTT) and RTT
+/// Variation (RTTVAR). The timeouts derived from it depend on the
+/// estimator's `RtoAlgorithm`, which defaults to RFC 6298's.
 #[derive(Debug, Clone, Copy, ToxProto)]
 pub struct RttEstimator {
     srtt: Duration,
     rttvar: Duration,
     rto: Duration,
+    /// Whether `update` has been called yet.
+    sampled: bool,
+    strategy: RtoAlgorithm,
 }
 
 impl Default for RttEstimator {
@@ -31,14 +201,37 @@ impl Default for RttEstimator {
 
 impl RttEstimator {
     pub fn new() -> Self {
+        Self::with_strategy(RtoAlgorithm::default())
+    }
+
+    pub fn with_strategy(strategy: RtoAlgorithm) -> Self {
         Self {
             srtt: INITIAL_SRTT,
             rttvar: INITIAL_RTTVAR,
-            rto: INITIAL_RTO,
+            rto: strategy.initial_rto(),
+            sampled: false,
+            strategy,
         }
     }
 
+    pub fn strategy(&self) -> RtoAlgorithm {
+        self.strategy
+    }
+
+    /// Switches to another timer strategy, keeping the RTT measured so far.
+    /// Before the first sample this resets the timeout to the strategy's
+    /// initial one, so seed the estimator afterwards.
+    pub fn set_strategy(&mut self, strategy: RtoAlgorithm) {
+        self.strategy = strategy;
+        self.rto = if self.sampled {
+            strategy.rto(self.srtt, self.rttvar)
+        } else {
+            strategy.initial_rto()
+        };
+    }
+
     pub fn update(&mut self, sample: Duration) {
+        self.sampled = true;
         let alpha = RTT_ALPHA;
         let beta = RTT_BETA;
 
@@ -47,8 +240,19 @@ impl RttEstimator {
         self.rttvar = self.rttvar.mul_f32(1.0 - beta) + diff.mul_f32(beta);
         self.srtt = self.srtt.mul_f32(1.0 - alpha) + sample.mul_f32(alpha);
 
-        let var_part = self.rttvar * RTT_K;
-        self.rto = (self.srtt + var_part).clamp(MIN_RTO, MAX_RTO);
+        self.rto = self.strategy.rto(self.srtt, self.rttvar);
+    }
+
+    /// Starts from an RTT known from elsewhere (e.g. the transport) instead
+    /// of the defaults, as for a first measurement (RFC 6298 §2.2). Ignored
+    /// once real samples have been taken.
+    pub fn seed(&mut self, rtt: Duration) {
+        if self.sampled {
+            return;
+        }
+        self.srtt = rtt;
+        self.rttvar = rtt / 2;
+        self.rto = self.strategy.rto(self.srtt, self.rttvar);
     }
 
     pub fn rto(&self) -> Duration {
@@ -56,10 +260,20 @@ impl RttEstimator {
     }
 
     pub fn rto_with_backoff(&self, retries: u32) -> Duration {
-        self.rto * (1 << retries.min(MAX_BACKOFF_EXPONENT))
+        self.strategy.backoff(self.rto, retries)
+    }
+
+    /// Time after which a tail loss probe is sent.
+    pub fn tlp_timeout(&self) -> Duration {
+        self.strategy.tlp_timeout(self.srtt)
     }
 
     pub fn srtt(&self) -> Duration {
         self.srtt
     }
+
+    /// Whether the RTT has been measured, rather than seeded or defaulted.
+    pub fn is_sampled(&self) -> bool {
+        self.sampled
+    }
 }
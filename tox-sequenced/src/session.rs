@@ -1,51 +1,116 @@
 use crate::SessionEvent;
 use crate::bitset::BitSet;
-use crate::congestion::{Algorithm, AlgorithmType, CongestionControl};
+use crate::cipher::{self, PacketCipher};
+use crate::coalesce::{self, Coalescer};
+use crate::congestion::{
+    Algorithm, AlgorithmType, CongestionControl, CongestionTrace, TraceTrigger,
+};
 use crate::error::SequencedError;
 use crate::flat_map::FlatMap;
+use crate::mtu::MtuDiscovery;
 use crate::outgoing::OutgoingMessage;
+use crate::path::{PathEstimates, PathQuality};
 use crate::protocol::{
-    self, ESTIMATED_PAYLOAD_SIZE, FragmentCount, FragmentIndex, MAX_CONCURRENT_INCOMING,
-    MAX_CONCURRENT_OUTGOING, MAX_TOX_PACKET_SIZE, MessageId, MessageType, Packet, Priority,
+    self, ESTIMATED_PAYLOAD_SIZE, ExtendedMessageId, FragmentCount, FragmentIndex,
+    MAX_CONCURRENT_INCOMING, MAX_CONCURRENT_OUTGOING, MessageId, MessageType, Packet, Priority,
     REASSEMBLY_TIMEOUT_SECS, SelectiveAck, TimestampMs,
 };
 use crate::quota::ReassemblyQuota;
 use crate::reassembly::MessageReassembler;
-use crate::rtt::RttEstimator;
+use crate::rtt::{RtoAlgorithm, RttEstimator};
 use crate::scheduler::PriorityScheduler;
 use crate::time::TimeProvider;
 use std::cmp;
 use std::collections::VecDeque;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tox_proto::ToxProto;
-use tracing::{debug, warn};
+use tox_proto::time::Instant;
+use tracing::{debug, trace_span, warn};
 
 pub const PING_INTERVAL_IDLE: Duration = Duration::from_secs(60);
 pub const PING_INTERVAL_ACTIVE: Duration = Duration::from_secs(10);
 pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(300); // 5 minutes
 const FAIR_SHARE_GUARANTEE: usize = 16 * 1024; // 16KB per peer guaranteed
+/// A window update is sent when the window reopens past this fraction of
+/// the session's maximum.
+const WINDOW_UPDATE_DIVISOR: usize = 4;
+
+/// Progress of a message being sent, as reported by
+/// `SequenceSession::outgoing_status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingStatus {
+    pub message_id: MessageId,
+    pub message_type: MessageType,
+    /// Size of the message on the wire, including its envelope.
+    pub total_bytes: usize,
+    pub fragments_total: FragmentCount,
+    /// Fragments sent at least once.
+    pub fragments_sent: FragmentCount,
+    pub fragments_acked: FragmentCount,
+    pub fragments_in_flight: usize,
+    /// Retransmissions of the message's fragments so far.
+    pub retransmits: u32,
+    /// Time since the message was queued.
+    pub age: Duration,
+    /// Time since the peer last acknowledged any of it.
+    pub since_last_ack: Duration,
+    /// Time without acknowledgements after which the message fails.
+    pub timeout: Duration,
+}
+
+/// Progress of a message being received, as reported by
+/// `SequenceSession::incoming_status`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingStatus {
+    pub message_id: MessageId,
+    pub priority: Priority,
+    pub fragments_total: FragmentCount,
+    pub fragments_received: FragmentCount,
+    pub bytes_received: usize,
+    /// Time since the first fragment arrived.
+    pub age: Duration,
+    /// Time since the last fragment arrived. Reassemblies idle for
+    /// `REASSEMBLY_TIMEOUT_SECS` are dropped.
+    pub idle: Duration,
+}
 
 /// A reliable synchronization session with a specific peer.
 #[derive(ToxProto)]
 pub struct SequenceSession<C: CongestionControl = Algorithm> {
     next_message_id: MessageId,
+    /// Times `next_message_id` wrapped around.
+    send_generation: u32,
     outgoing: FlatMap<MessageId, OutgoingMessage>,
     incoming: FlatMap<MessageId, MessageReassembler>,
     /// Shared memory quota for reassembly across all sessions.
     pub quota: ReassemblyQuota,
     /// Maximum memory allowed for this specific session.
     pub max_per_session: usize,
-    /// Total bytes currently buffered in all incoming reassemblers for this session.
+    /// Total bytes currently buffered in all incoming reassemblers for this
+    /// session, plus completed messages the application has not polled yet.
     incoming_buffer_size: usize,
+    /// Bytes of completed messages waiting in `events`. They count against
+    /// the advertised window until the application drains them.
+    undelivered_bytes: usize,
+    /// The window reopened after being nearly closed; the peer is told with
+    /// the next flush.
+    window_update_pending: bool,
     /// Stores the final state of recently completed messages to send final ACKs.
     completed_incoming: FlatMap<MessageId, (SelectiveAck, Instant)>,
+    /// Messages the peer cancelled, so late fragments do not start a new
+    /// reassembly.
+    cancelled_incoming: FlatMap<MessageId, Instant>,
     /// Pending ACKs: message_id -> (count_since_last_ack, first_pending_at)
     pending_acks: FlatMap<MessageId, (usize, Instant)>,
     /// Pending NACKs: message_id -> first_observed_at
     pending_nacks: FlatMap<MessageId, Instant>,
     /// Queue for outbound unreliable datagrams.
     datagram_queue: VecDeque<Packet>,
+    /// Cancelled messages the peer has not been told about yet.
+    pending_cancels: VecDeque<MessageId>,
+    /// Small messages waiting to be sent as one batch.
+    coalescer: Coalescer,
     /// Scheduler for fair sharing between concurrent messages.
     scheduler: PriorityScheduler,
     events: VecDeque<SessionEvent>,
@@ -64,12 +129,26 @@ pub struct SequenceSession<C: CongestionControl = Algorithm> {
     zero_window_probes_sent: u32,
     last_emitted_cwnd: usize,
     app_limited: bool,
-    highest_received_id: Option<MessageId>,
+    /// Newest message the peer started sending us.
+    highest_received_id: Option<ExtendedMessageId>,
     time_provider: Arc<dyn TimeProvider>,
     retransmit_count: u64,
     /// Estimated clock offset to the peer (ms).
     clock_offset: i64,
+    /// Path to the peer as last reported by the transport.
+    path: PathQuality,
+    /// Packet size discovery for the path.
+    mtu: MtuDiscovery,
+    /// Congestion control to return to when the path stops being relayed.
+    direct_algorithm: Option<AlgorithmType>,
     rng: rand::rngs::StdRng,
+    /// Congestion control trace, if enabled. Not persisted.
+    #[tox(skip)]
+    congestion_trace: Option<CongestionTrace>,
+    /// Seals packets on transports without their own encryption. Not
+    /// persisted.
+    #[tox(skip)]
+    cipher: Option<PacketCipher>,
 }
 
 impl SequenceSession<Algorithm> {
@@ -107,6 +186,51 @@ impl SequenceSession<Algorithm> {
             session_rng,
         )
     }
+
+    /// Adapts the session to the path reported by the transport: fragment
+    /// size, initial RTT, congestion control and retransmission timers (see
+    /// `PathQuality`). Messages already queued keep their fragment size.
+    pub fn set_path(&mut self, path: PathQuality) {
+        if path == self.path {
+            return;
+        }
+        match path.preferred_algorithm() {
+            Some(algo_type) => {
+                self.direct_algorithm
+                    .get_or_insert(self.congestion_control.algo_type());
+                self.switch_algorithm(algo_type);
+            }
+            None => {
+                if let Some(algo_type) = self.direct_algorithm.take() {
+                    self.switch_algorithm(algo_type);
+                }
+            }
+        }
+        // A strategy chosen with `set_rto_strategy` is kept.
+        let rto_strategy = path.rto_strategy();
+        if self.rtt.strategy() == self.path.rto_strategy() && self.rtt.strategy() != rto_strategy {
+            self.rtt.set_strategy(rto_strategy);
+        }
+        if let Some(rtt) = path.rtt {
+            self.rtt.seed(rtt);
+        }
+        self.path = path;
+        self.mtu.set_max(path.max_packet_size());
+        self.refragment_unsent();
+    }
+
+    pub fn algo_type(&self) -> AlgorithmType {
+        self.congestion_control.algo_type()
+    }
+
+    fn switch_algorithm(&mut self, algo_type: AlgorithmType) {
+        use rand::{RngCore, SeedableRng};
+        if self.congestion_control.algo_type() != algo_type {
+            let cc_rng = rand::rngs::StdRng::seed_from_u64(self.rng.next_u64());
+            self.congestion_control = Algorithm::new(algo_type, cc_rng);
+            self.check_cwnd_change();
+        }
+    }
 }
 
 impl<C: CongestionControl> SequenceSession<C> {
@@ -166,15 +290,21 @@ impl<C: CongestionControl> SequenceSession<C> {
         let next_message_id = MessageId(rng.next_u32());
         Self {
             next_message_id,
+            send_generation: 0,
             outgoing: FlatMap::new(),
             incoming: FlatMap::new(),
             quota: quota.clone(),
             max_per_session: crate::protocol::MAX_TOTAL_REASSEMBLY_BUFFER,
             incoming_buffer_size: 0,
+            undelivered_bytes: 0,
+            window_update_pending: false,
             completed_incoming: FlatMap::new(),
+            cancelled_incoming: FlatMap::new(),
             pending_acks: FlatMap::new(),
             pending_nacks: FlatMap::new(),
             datagram_queue: VecDeque::new(),
+            pending_cancels: VecDeque::new(),
+            coalescer: Coalescer::new(),
             scheduler: PriorityScheduler::new(),
             events: VecDeque::new(),
             rtt: RttEstimator::new(),
@@ -195,14 +325,117 @@ impl<C: CongestionControl> SequenceSession<C> {
             time_provider: time_provider.clone(),
             retransmit_count: 0,
             clock_offset: 0,
+            path: PathQuality::default(),
+            mtu: MtuDiscovery::new(PathQuality::default().max_packet_size(), now),
+            direct_algorithm: None,
             rng,
+            congestion_trace: None,
+            cipher: None,
         }
     }
 
+    pub fn path(&self) -> &PathQuality {
+        &self.path
+    }
+
+    pub fn mtu(&self) -> &MtuDiscovery {
+        &self.mtu
+    }
+
+    /// RTT and MTU measured on the path so far.
+    pub fn estimates(&self) -> PathEstimates {
+        PathEstimates {
+            rtt: self.rtt.is_sampled().then(|| self.rtt.srtt()),
+            mtu: self.mtu.confirmed(),
+        }
+    }
+
+    /// Starts from estimates taken from an earlier session on the same path,
+    /// as `set_path` does with the transport's. Measurements already taken
+    /// win. Call after `set_path`, which restarts MTU discovery when the
+    /// path maximum changes. The congestion window is not restored: after
+    /// an idle period it restarts anyway (RFC 5681 §4.1).
+    pub fn seed_estimates(&mut self, estimates: PathEstimates) {
+        if let Some(rtt) = estimates.rtt {
+            self.rtt.seed(rtt);
+        }
+        self.mtu.seed(estimates.mtu);
+    }
+
+    /// Re-fragments queued messages that have not started sending, after
+    /// the packet size changed.
+    fn refragment_unsent(&mut self) {
+        let packet_size = self.mtu.packet_size();
+        let overhead = self.packet_overhead();
+        for (_, msg) in self.outgoing.iter_mut() {
+            if let Some(payload_mtu) =
+                payload_mtu(&self.path, packet_size, overhead, msg.data.len())
+            {
+                msg.refragment(payload_mtu);
+            }
+        }
+    }
+
+    /// Seals all packets of the session with `cipher` from now on. Both ends
+    /// must switch before they exchange any packets; packets from an end
+    /// that has not fail to decode with [`SequencedError::CipherMismatch`].
+    pub fn set_cipher(&mut self, cipher: PacketCipher) {
+        self.cipher = Some(cipher);
+        self.refragment_unsent();
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Serializes `packet` for the transport, sealing it if the session is
+    /// encrypted.
+    pub fn encode_packet(&mut self, packet: &Packet) -> Result<Vec<u8>, SequencedError> {
+        match &mut self.cipher {
+            Some(cipher) => cipher.seal(packet),
+            None => protocol::serialize(packet)
+                .map_err(|e| SequencedError::SerializationError(e.to_string())),
+        }
+    }
+
+    /// Parses a packet from the transport, opening it if the session is
+    /// encrypted. Fails with [`SequencedError::CipherMismatch`] if only one
+    /// end seals its packets.
+    pub fn decode_packet(&mut self, data: &[u8]) -> Result<Packet, SequencedError> {
+        match &mut self.cipher {
+            Some(cipher) => cipher.open(data),
+            None if cipher::is_sealed(data) => Err(SequencedError::CipherMismatch),
+            None => protocol::deserialize(data)
+                .map_err(|e| SequencedError::SerializationError(e.to_string())),
+        }
+    }
+
+    /// Bytes a fragment's packet adds around its payload.
+    fn packet_overhead(&self) -> usize {
+        protocol::PACKET_OVERHEAD + self.cipher.as_ref().map_or(0, |_| cipher::SEAL_OVERHEAD)
+    }
+
     pub fn next_message_id(&self) -> MessageId {
         self.next_message_id
     }
 
+    /// Numbers new messages from `id` on, e.g. to continue a sequence
+    /// saved elsewhere. IDs still in use are skipped when sending.
+    pub fn set_next_message_id(&mut self, id: MessageId) {
+        self.next_message_id = id;
+    }
+
+    /// `next_message_id` with the number of times the ID space wrapped.
+    pub fn next_extended_message_id(&self) -> ExtendedMessageId {
+        ExtendedMessageId::new(self.send_generation, self.next_message_id)
+    }
+
+    /// The newest message the peer started sending, with the number of
+    /// times its ID space wrapped as seen by us.
+    pub fn highest_received_id(&self) -> Option<ExtendedMessageId> {
+        self.highest_received_id
+    }
+
     pub fn set_quota(&mut self, quota: ReassemblyQuota) {
         self.quota = quota;
         if self.incoming_buffer_size > 0 {
@@ -245,7 +478,11 @@ impl<C: CongestionControl> SequenceSession<C> {
             return Err(SequencedError::QueueFull);
         }
 
-        self.next_message_id = id.wrapping_add(1);
+        let next_id = id.wrapping_add(1);
+        if next_id.0 < self.next_message_id.0 {
+            self.send_generation = self.send_generation.wrapping_add(1);
+        }
+        self.next_message_id = next_id;
 
         let envelope = protocol::OutboundEnvelope {
             message_type,
@@ -259,19 +496,89 @@ impl<C: CongestionControl> SequenceSession<C> {
             return Err(SequencedError::MessageTooLarge);
         }
 
-        let payload_mtu = MAX_TOX_PACKET_SIZE.saturating_sub(crate::protocol::PACKET_OVERHEAD);
-        if payload_mtu == 0 {
-            return Err(SequencedError::MessageTooLarge);
-        }
+        let payload_mtu = payload_mtu(
+            &self.path,
+            self.mtu.packet_size(),
+            self.packet_overhead(),
+            full_payload.len(),
+        )
+        .ok_or(SequencedError::MessageTooLarge)?;
 
         let msg = OutgoingMessage::new(message_type, full_payload, payload_mtu, now)?;
 
+        let span = trace_span!("send_message", message_id = ?id, message_type = ?message_type);
+        let _enter = span.enter();
+        debug!("Queued {} bytes", msg.data.len());
         self.scheduler
             .update_message(id.0, message_type.priority() as u8);
         self.outgoing.insert(id, msg);
         Ok(id)
     }
 
+    /// Queues a message like `send_message`, but lets a small one wait up to
+    /// `COALESCE_DELAY` to share a packet with others. Larger messages first
+    /// send out whatever is waiting, keeping the order.
+    pub fn send_coalesced(
+        &mut self,
+        message_type: MessageType,
+        data: &[u8],
+        now: Instant,
+    ) -> Result<(), SequencedError> {
+        if data.len() > coalesce::MAX_COALESCED_MESSAGE {
+            self.flush_coalesced(now)?;
+            return self.send_message_at(message_type, data, now).map(|_| ());
+        }
+        self.coalescer.push(message_type, data, now);
+        let batch_limit = self
+            .mtu
+            .packet_size()
+            .saturating_sub(self.packet_overhead() + 8);
+        if self.coalescer.size() >= batch_limit {
+            self.flush_coalesced(now)?;
+        }
+        Ok(())
+    }
+
+    /// Queues the messages waiting in the coalescer, if any.
+    pub fn flush_coalesced(&mut self, now: Instant) -> Result<Option<MessageId>, SequencedError> {
+        match self.coalescer.take()? {
+            Some((message_type, data)) => self.send_message_at(message_type, &data, now).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Number of messages waiting in the coalescer.
+    pub fn coalesced_len(&self) -> usize {
+        self.coalescer.len()
+    }
+
+    /// Stops sending a message and drops its unacknowledged fragments.
+    ///
+    /// Emits `SessionEvent::MessageFailed` with the reason "Cancelled". If
+    /// `notify_peer` is set and any fragment went out, a `Packet::Cancel`
+    /// lets the peer free its reassembly buffer right away instead of
+    /// waiting for `REASSEMBLY_TIMEOUT_SECS`. The notification is not
+    /// retransmitted; if it is lost, the peer times out as before.
+    ///
+    /// Returns false if the message is not being sent (any more).
+    pub fn cancel(&mut self, message_id: MessageId, notify_peer: bool) -> bool {
+        let Some(mut msg) = self.outgoing.remove(&message_id) else {
+            return false;
+        };
+        debug!("Cancelled message {}", message_id);
+        self.scheduler.remove_message(message_id.0);
+        release_in_flight(&mut self.in_flight, &mut msg);
+        if notify_peer && msg.next_fragment.0 > 0 {
+            self.pending_cancels.push_back(message_id);
+        }
+        self.events.push_back(SessionEvent::MessageFailed(
+            message_id,
+            "Cancelled".to_string(),
+        ));
+        self.events.push_back(SessionEvent::ReadyToSend);
+        true
+    }
+
     pub fn set_message_timeout(&mut self, message_id: MessageId, timeout: Duration) {
         if let Some(msg) = self.outgoing.get_mut(&message_id) {
             msg.timeout = timeout;
@@ -287,8 +594,8 @@ impl<C: CongestionControl> SequenceSession<C> {
             return Err(SequencedError::QueueFull);
         }
 
-        let overhead = 10;
-        if data.len() + overhead > MAX_TOX_PACKET_SIZE {
+        let overhead = 10 + self.cipher.as_ref().map_or(0, |_| cipher::SEAL_OVERHEAD);
+        if data.len() + overhead > self.mtu.packet_size() {
             return Err(SequencedError::MessageTooLarge);
         }
 
@@ -312,6 +619,8 @@ impl<C: CongestionControl> SequenceSession<C> {
     }
 
     pub fn handle_packet(&mut self, packet: Packet, now: Instant) -> Vec<Packet> {
+        let span = trace_span!("handle_packet", message_id = ?packet.message_id());
+        let _enter = span.enter();
         self.last_activity = now;
         let replies = self.handle_packet_internal(packet, now);
         self.check_cwnd_change();
@@ -339,6 +648,7 @@ impl<C: CongestionControl> SequenceSession<C> {
             }
             Packet::Ack(ack) => self.handle_ack_packet(ack, now),
             Packet::Nack(nack) => self.handle_nack_packet(nack, now),
+            Packet::Cancel { message_id } => self.handle_cancel_packet(message_id, now),
             Packet::Ping { t1 } => {
                 use rand::Rng;
                 let jitter = self.rng.r#gen_range(-5..=5);
@@ -352,17 +662,24 @@ impl<C: CongestionControl> SequenceSession<C> {
                 if let Some(sent_time) = self.last_ping_sent.take() {
                     let rtt_sample = now.saturating_duration_since(sent_time);
                     self.rtt.update(rtt_sample);
+                    self.mtu.confirm_peer();
                     self.clock_offset = ((t2.0 - t1.0) + (t3.0 - t4)) / 2;
                     self.congestion_control
                         .on_ack(rtt_sample, None, 0, self.in_flight, now);
+                    self.trace_congestion(TraceTrigger::Ack, Some(rtt_sample), now);
                 }
             }
             Packet::Datagram { message_type, data } => {
-                self.events.push_back(SessionEvent::MessageCompleted(
-                    MessageId(0),
-                    message_type,
-                    data,
-                ));
+                self.push_undelivered(MessageId(0), message_type, data);
+            }
+            Packet::MtuProbe { probe_id, .. } => {
+                responses.push(Packet::MtuProbeAck { probe_id });
+            }
+            Packet::MtuProbeAck { probe_id } => {
+                let packet_size = self.mtu.packet_size();
+                if self.mtu.on_ack(probe_id, now) && self.mtu.packet_size() != packet_size {
+                    self.refragment_unsent();
+                }
             }
         }
 
@@ -378,7 +695,9 @@ impl<C: CongestionControl> SequenceSession<C> {
         now: Instant,
         responses: &mut Vec<Packet>,
     ) {
-        if self.check_completed_message(message_id, responses) {
+        if self.check_completed_message(message_id, responses)
+            || self.cancelled_incoming.contains_key(&message_id)
+        {
             return;
         }
 
@@ -405,6 +724,24 @@ impl<C: CongestionControl> SequenceSession<C> {
         self.process_fragment(message_id, fragment_index, data, now, responses);
     }
 
+    fn handle_cancel_packet(&mut self, message_id: MessageId, now: Instant) {
+        if self.completed_incoming.contains_key(&message_id) {
+            return;
+        }
+        if let Some(reassembler) = self.incoming.remove(&message_id) {
+            debug!("Peer cancelled message {}", message_id);
+            self.incoming_buffer_size -= reassembler.reserved_bytes;
+            self.quota.release(reassembler.reserved_bytes);
+        }
+        self.pending_acks.remove(&message_id);
+        self.pending_nacks.remove(&message_id);
+        self.cancelled_incoming.insert(message_id, now);
+        if self.cancelled_incoming.len() > protocol::MAX_COMPLETED_INCOMING {
+            let oldest_id = *self.cancelled_incoming.keys().next().unwrap();
+            self.cancelled_incoming.remove(&oldest_id);
+        }
+    }
+
     fn check_completed_message(&self, message_id: MessageId, responses: &mut Vec<Packet>) -> bool {
         if let Some((ack, _)) = self.completed_incoming.get(&message_id) {
             let mut ack = ack.clone();
@@ -417,10 +754,8 @@ impl<C: CongestionControl> SequenceSession<C> {
     }
 
     fn is_ancient_message(&self, message_id: MessageId) -> bool {
-        if let Some(highest) = self.highest_received_id {
-            let diff = message_id.wrapping_sub(highest);
-            if diff > 0x80000000 {
-                let offset = highest.wrapping_sub(message_id);
+        if let Some(highest) = self.highest_received_id.map(ExtendedMessageId::id) {
+            if let Some(offset) = message_id.distance_behind(highest) {
                 if offset >= crate::protocol::MAX_COMPLETED_INCOMING as u32 {
                     return true;
                 }
@@ -430,7 +765,7 @@ impl<C: CongestionControl> SequenceSession<C> {
                 {
                     return true;
                 }
-            } else if diff == 0
+            } else if message_id == highest
                 && !self.incoming.contains_key(&message_id)
                 && !self.completed_incoming.contains_key(&message_id)
             {
@@ -495,11 +830,14 @@ impl<C: CongestionControl> SequenceSession<C> {
             Ok(re) => {
                 self.incoming_buffer_size += initial_reservation;
                 self.incoming.insert(message_id, re);
-                if self
-                    .highest_received_id
-                    .is_none_or(|h| message_id.wrapping_sub(h) < 0x80000000)
-                {
-                    self.highest_received_id = Some(message_id);
+                match self.highest_received_id {
+                    None => {
+                        self.highest_received_id = Some(ExtendedMessageId::new(0, message_id));
+                    }
+                    Some(highest) if message_id.is_after(highest.id()) => {
+                        self.highest_received_id = Some(highest.extend(message_id));
+                    }
+                    Some(_) => {}
                 }
                 true
             }
@@ -579,11 +917,11 @@ impl<C: CongestionControl> SequenceSession<C> {
                         if let Some(assembled) = reassembler.assemble() {
                             match protocol::deserialize::<protocol::InboundEnvelope>(&assembled) {
                                 Ok(envelope) => {
-                                    self.events.push_back(SessionEvent::MessageCompleted(
+                                    self.push_completed(
                                         message_id,
                                         envelope.message_type,
                                         envelope.payload,
-                                    ));
+                                    );
                                     self.completed_incoming.insert(message_id, (ack, now));
 
                                     if self.completed_incoming.len()
@@ -663,11 +1001,10 @@ impl<C: CongestionControl> SequenceSession<C> {
                 .in_flight
                 .saturating_sub(res.newly_completed_in_flight_bytes);
 
+            message_fully_acked = msg.all_acked();
             if res.loss_detected {
                 self.congestion_control.on_nack(now);
-            }
-            if msg.all_acked() {
-                message_fully_acked = true;
+                self.trace_congestion(TraceTrigger::Nack, None, now);
             }
             ack_res = Some(res);
         }
@@ -696,9 +1033,11 @@ impl<C: CongestionControl> SequenceSession<C> {
                 self.in_flight,
                 now,
             );
+            self.trace_congestion(TraceTrigger::Ack, res.first_rtt, now);
         } else {
             self.congestion_control
                 .on_ack(self.rtt.srtt(), None, 0, self.in_flight, now);
+            self.trace_congestion(TraceTrigger::Ack, None, now);
         }
 
         if message_fully_acked {
@@ -740,20 +1079,95 @@ impl<C: CongestionControl> SequenceSession<C> {
 
             if nack_triggered {
                 self.congestion_control.on_nack(now);
+                self.trace_congestion(TraceTrigger::Nack, None, now);
+            }
+        }
+    }
+
+    /// Emits a completed message, unpacking batches.
+    fn push_completed(
+        &mut self,
+        message_id: MessageId,
+        message_type: MessageType,
+        payload: Vec<u8>,
+    ) {
+        if message_type != MessageType::Batch {
+            self.push_undelivered(message_id, message_type, payload);
+            return;
+        }
+        match coalesce::decode_batch(&payload) {
+            Ok(messages) => {
+                for (message_type, payload) in messages {
+                    self.push_undelivered(message_id, message_type, payload);
+                }
             }
+            Err(e) => warn!("Failed to decode batch {}: {}", message_id, e),
+        }
+    }
+
+    /// Queues a received message for the application. Its bytes stay
+    /// reserved, shrinking the receive window, until `poll_event` hands it
+    /// out.
+    fn push_undelivered(
+        &mut self,
+        message_id: MessageId,
+        message_type: MessageType,
+        payload: Vec<u8>,
+    ) {
+        let len = payload.len();
+        self.quota.reserve_unchecked(len);
+        self.incoming_buffer_size += len;
+        self.undelivered_bytes += len;
+        self.events.push_back(SessionEvent::MessageCompleted(
+            message_id,
+            message_type,
+            payload,
+        ));
+    }
+
+    /// Releases a message the application has taken, and schedules a window
+    /// update if that reopens a nearly closed window.
+    fn release_delivered(&mut self, len: usize) {
+        let threshold = self.window_update_threshold();
+        let before = self.current_rwnd();
+        let len = len.min(self.undelivered_bytes);
+        self.undelivered_bytes -= len;
+        self.incoming_buffer_size -= len;
+        self.quota.release(len);
+        if before < threshold && self.current_rwnd() >= threshold {
+            self.window_update_pending = true;
         }
     }
 
+    /// Window below which the peer is assumed to be held back, so that
+    /// reopening it is worth an unsolicited ACK.
+    fn window_update_threshold(&self) -> FragmentCount {
+        let capacity = self.max_per_session.min(self.quota.capacity());
+        FragmentCount(
+            (capacity / WINDOW_UPDATE_DIVISOR / ESTIMATED_PAYLOAD_SIZE).clamp(1, u16::MAX as usize)
+                as u16,
+        )
+    }
+
     pub fn next_check_time(&self) -> Instant {
         let mut min_time = self
             .next_pacing_time
             .min(self.last_ping + PING_INTERVAL_ACTIVE);
+        if let Some(deadline) = self.coalescer.deadline() {
+            min_time = min_time.min(deadline);
+        }
+        if let Some(probe_at) = self.mtu.next_check_time(self.rtt.rto()) {
+            min_time = min_time.min(probe_at);
+        }
 
         if self.peer_rwnd < ESTIMATED_PAYLOAD_SIZE {
             let probe_delay = self.rtt.rto_with_backoff(self.zero_window_probes_sent);
             min_time = min_time.min(self.last_rwnd_probe + probe_delay);
         }
 
+        if self.window_update_pending || !self.pending_cancels.is_empty() {
+            return self.last_activity;
+        }
         for (count, first_pending_at) in self.pending_acks.values() {
             if *count < 2 {
                 min_time = min_time.min(*first_pending_at + crate::protocol::DELAYED_ACK_TIMEOUT);
@@ -811,21 +1225,19 @@ impl<C: CongestionControl> SequenceSession<C> {
             }
         }
 
-        let rto_est = self.rtt.rto();
         for msg in self.outgoing.values() {
             if let Some(&(idx, last_sent)) = msg.in_flight_queue.front() {
                 let state = &msg.fragment_states[idx.0 as usize];
                 if state.last_sent.is_none_or(|s| s <= last_sent) {
                     let retries = state.rto_backoff;
-                    let current_rto = rto_est * (1 << 6.min(retries));
+                    let current_rto = self.rtt.rto_with_backoff(retries);
                     next = next.min(last_sent + current_rto);
                 }
             }
         }
 
         if self.in_flight > 0 {
-            let srtt = self.rtt.srtt();
-            let tlp_threshold = srtt.mul_f32(1.5).max(Duration::from_millis(10));
+            let tlp_threshold = self.rtt.tlp_timeout();
             for msg in self.outgoing.values() {
                 if let Some(&(idx, last_sent)) = msg.in_flight_queue.back() {
                     let state = &msg.fragment_states[idx.0 as usize];
@@ -842,6 +1254,9 @@ impl<C: CongestionControl> SequenceSession<C> {
             next = next.min(probe_at.max(self.next_pacing_time));
         }
 
+        if self.window_update_pending || !self.pending_cancels.is_empty() {
+            return now;
+        }
         for (_, (count, pending_at)) in self.pending_acks.iter() {
             let timeout = *pending_at + crate::protocol::DELAYED_ACK_TIMEOUT;
             if *count >= 2 || timeout <= now {
@@ -867,6 +1282,12 @@ impl<C: CongestionControl> SequenceSession<C> {
 
         next = next.min(self.last_ping + ping_interval);
         next = next.min(self.last_activity + CONNECTION_TIMEOUT);
+        if let Some(probe_at) = self.mtu.next_check_time(self.rtt.rto()) {
+            next = next.min(probe_at);
+        }
+        if let Some(deadline) = self.coalescer.deadline() {
+            next = next.min(deadline);
+        }
 
         next.max(now)
     }
@@ -891,6 +1312,12 @@ impl<C: CongestionControl> SequenceSession<C> {
     where
         F: FnMut(Packet) -> bool,
     {
+        if self.coalescer.is_due(now)
+            && let Err(e) = self.flush_coalesced(now)
+        {
+            warn!("Failed to queue coalesced messages: {}", e);
+        }
+
         let is_active = !self.outgoing.is_empty() || !self.incoming.is_empty();
         let ping_interval = if is_active {
             PING_INTERVAL_ACTIVE
@@ -910,6 +1337,25 @@ impl<C: CongestionControl> SequenceSession<C> {
             }
         }
 
+        // MTU probe
+        let packet_size = self.mtu.packet_size();
+        if let Some(mut probe) = self.mtu.poll(now, self.rtt.rto()) {
+            // Sealing grows the probe; shrink it to test the same size on
+            // the wire.
+            if self.cipher.is_some()
+                && let Packet::MtuProbe { probe_id, .. } = probe
+            {
+                let size = protocol::serialize(&probe).map_or(0, |b| b.len());
+                probe =
+                    crate::mtu::probe_packet(probe_id, size.saturating_sub(cipher::SEAL_OVERHEAD));
+            }
+            // A probe the transport refused counts as lost.
+            sender(probe);
+        }
+        if self.mtu.packet_size() != packet_size {
+            self.refragment_unsent();
+        }
+
         // Datagrams
         while self.datagram_queue.front().is_some() {
             if now < self.next_pacing_time {
@@ -973,7 +1419,7 @@ impl<C: CongestionControl> SequenceSession<C> {
             let peer_rwnd = self.peer_rwnd;
             let in_flight = self.in_flight;
             let cwnd = self.congestion_control.cwnd();
-            let rto_est = self.rtt.rto();
+            let rtt = self.rtt;
 
             let outgoing = &self.outgoing;
 
@@ -1005,7 +1451,7 @@ impl<C: CongestionControl> SequenceSession<C> {
                 // B. Check timeouts (RTO)
                 if let Some(&(idx, last_sent)) = msg.in_flight_queue.front() {
                     let retries = msg.fragment_states[idx.0 as usize].rto_backoff;
-                    let current_rto = rto_est * (1 << 6.min(retries));
+                    let current_rto = rtt.rto_with_backoff(retries);
                     let elapsed = now.saturating_duration_since(last_sent);
                     if elapsed >= current_rto
                         && !msg.is_acked(idx)
@@ -1057,8 +1503,7 @@ impl<C: CongestionControl> SequenceSession<C> {
 
         // Tail Loss Probe
         if !any_data_sent && self.in_flight > 0 {
-            let srtt = self.rtt.srtt();
-            let tlp_threshold = srtt.mul_f32(1.5).max(Duration::from_millis(10));
+            let tlp_threshold = self.rtt.tlp_timeout();
             let mut tlp_target = None;
             for (id, msg) in self.outgoing.iter() {
                 if let Some(&(idx, last_sent)) = msg.in_flight_queue.back()
@@ -1073,8 +1518,16 @@ impl<C: CongestionControl> SequenceSession<C> {
             }
         }
 
+        // Cancellations
+        while let Some(&message_id) = self.pending_cancels.front() {
+            if !sender(Packet::Cancel { message_id }) {
+                break;
+            }
+            self.pending_cancels.pop_front();
+        }
+
         // ACKs and NACKs
-        self.flush_pending_acks(now, sender);
+        self.flush_pending_acks(now, false, sender);
         self.flush_pending_nacks(now, sender);
 
         // App-limited detection
@@ -1137,6 +1590,7 @@ impl<C: CongestionControl> SequenceSession<C> {
                         msg.fragment_states[idx.0 as usize].rto_backoff += 1;
                         msg.in_flight_queue.pop_front();
                         self.congestion_control.on_timeout(now);
+                        self.trace_congestion(TraceTrigger::Timeout, None, now);
                     }
                     return true;
                 }
@@ -1191,13 +1645,7 @@ impl<C: CongestionControl> SequenceSession<C> {
                 let reason = "Timed out".to_string();
                 events.push_back(SessionEvent::MessageFailed(*id, reason));
                 scheduler.remove_message(id.0);
-                for (idx, state) in m.fragment_states.iter().enumerate() {
-                    if state.last_sent.is_some() {
-                        *in_flight =
-                            in_flight.saturating_sub(m.fragment_len(FragmentIndex(idx as u16)));
-                    }
-                }
-                m.in_flight_queue.clear();
+                release_in_flight(in_flight, m);
                 events.push_back(SessionEvent::ReadyToSend);
                 false
             } else {
@@ -1206,6 +1654,8 @@ impl<C: CongestionControl> SequenceSession<C> {
         });
         self.completed_incoming
             .retain(|_, (_, time)| now.saturating_duration_since(*time) < Duration::from_secs(30));
+        self.cancelled_incoming
+            .retain(|_, time| now.saturating_duration_since(*time) < Duration::from_secs(30));
     }
 
     pub fn is_dead(&self, now: Instant) -> bool {
@@ -1220,7 +1670,7 @@ impl<C: CongestionControl> SequenceSession<C> {
     }
 
     pub fn current_rwnd(&self) -> FragmentCount {
-        let mut planned_size = 0;
+        let mut planned_size = self.undelivered_bytes;
         for r in self.incoming.values() {
             planned_size += r.planned_total_size();
         }
@@ -1241,6 +1691,14 @@ impl<C: CongestionControl> SequenceSession<C> {
     pub fn current_rto(&self) -> Duration {
         self.rtt.rto()
     }
+    pub fn rto_strategy(&self) -> RtoAlgorithm {
+        self.rtt.strategy()
+    }
+    /// Replaces the retransmission timer strategy, keeping the RTT measured
+    /// so far.
+    pub fn set_rto_strategy(&mut self, strategy: impl Into<RtoAlgorithm>) {
+        self.rtt.set_strategy(strategy.into());
+    }
     pub fn retransmit_count(&self) -> u64 {
         self.retransmit_count
     }
@@ -1251,8 +1709,103 @@ impl<C: CongestionControl> SequenceSession<C> {
             .sum()
     }
 
+    /// Starts recording congestion control decisions, keeping the last
+    /// `capacity` events. Restarts the trace if one is running.
+    pub fn enable_congestion_trace(&mut self, capacity: usize, now: Instant) {
+        self.congestion_trace = Some(CongestionTrace::new(
+            &self.congestion_control,
+            capacity,
+            self.rtt.srtt(),
+            self.in_flight,
+            now,
+        ));
+    }
+
+    /// Stops recording and returns the trace so far.
+    pub fn disable_congestion_trace(&mut self) -> Option<CongestionTrace> {
+        self.congestion_trace.take()
+    }
+
+    pub fn congestion_trace(&self) -> Option<&CongestionTrace> {
+        self.congestion_trace.as_ref()
+    }
+
+    /// The congestion trace as a qlog document, if tracing is enabled.
+    pub fn export_qlog(&self, title: &str) -> Option<String> {
+        self.congestion_trace.as_ref().map(|t| t.export_qlog(title))
+    }
+
+    fn trace_congestion(
+        &mut self,
+        trigger: TraceTrigger,
+        rtt_sample: Option<Duration>,
+        now: Instant,
+    ) {
+        if let Some(trace) = &mut self.congestion_trace {
+            trace.record(
+                &self.congestion_control,
+                trigger,
+                rtt_sample,
+                self.rtt.srtt(),
+                self.in_flight,
+                now,
+            );
+        }
+    }
+
+    /// Progress of every message still being sent, in the order they were
+    /// queued.
+    pub fn outgoing_status(&self, now: Instant) -> Vec<OutgoingStatus> {
+        self.outgoing
+            .iter()
+            .map(|(id, msg)| OutgoingStatus {
+                message_id: *id,
+                message_type: msg.message_type,
+                total_bytes: msg.data.len(),
+                fragments_total: msg.num_fragments,
+                fragments_sent: FragmentCount(msg.next_fragment.0),
+                fragments_acked: msg.acked_count,
+                fragments_in_flight: msg
+                    .fragment_states
+                    .iter()
+                    .filter(|s| s.last_sent.is_some())
+                    .count(),
+                retransmits: msg.fragment_states.iter().map(|s| s.retransmit_count).sum(),
+                age: now.saturating_duration_since(msg.created_at),
+                since_last_ack: now.saturating_duration_since(msg.last_ack_at),
+                timeout: msg.timeout,
+            })
+            .collect()
+    }
+
+    /// Progress of every message partially received, in the order their
+    /// first fragments arrived.
+    pub fn incoming_status(&self, now: Instant) -> Vec<IncomingStatus> {
+        self.incoming
+            .iter()
+            .map(|(id, r)| IncomingStatus {
+                message_id: *id,
+                priority: r.priority,
+                fragments_total: r.total_fragments,
+                fragments_received: r.received_count(),
+                bytes_received: r.current_size(),
+                age: now.saturating_duration_since(r.started_at),
+                idle: now.saturating_duration_since(r.last_activity),
+            })
+            .collect()
+    }
+
     pub fn poll_event(&mut self) -> Option<SessionEvent> {
-        self.events.pop_front()
+        let event = self.events.pop_front()?;
+        if let SessionEvent::MessageCompleted(_, _, data) = &event {
+            self.release_delivered(data.len());
+        }
+        Some(event)
+    }
+
+    /// Bytes of received messages not yet taken with `poll_event`.
+    pub fn undelivered_bytes(&self) -> usize {
+        self.undelivered_bytes
     }
     pub fn find_outgoing(&self, id: MessageId) -> Option<&OutgoingMessage> {
         self.outgoing.get(&id)
@@ -1339,14 +1892,39 @@ impl<C: CongestionControl> SequenceSession<C> {
         true
     }
 
-    fn flush_pending_acks<F>(&mut self, now: Instant, sender: &mut F)
+    /// Sends every pending ACK now, without waiting for a second packet or
+    /// the delayed-ACK timeout. For sessions about to be closed, so the peer
+    /// does not retransmit what was already received.
+    pub fn flush_acks<F>(&mut self, now: Instant, sender: &mut F)
+    where
+        F: FnMut(Packet) -> bool,
+    {
+        self.flush_pending_acks(now, true, sender);
+    }
+
+    fn flush_pending_acks<F>(&mut self, now: Instant, immediate: bool, sender: &mut F)
     where
         F: FnMut(Packet) -> bool,
     {
         let current_rwnd = self.current_rwnd();
+        if self.window_update_pending {
+            // Any ACK carries the window; repeat the final ACK of a
+            // completed message.
+            match self.completed_incoming.values().last() {
+                Some((ack, _)) => {
+                    let mut ack = ack.clone();
+                    ack.rwnd = current_rwnd;
+                    if sender(Packet::Ack(ack)) {
+                        self.window_update_pending = false;
+                    }
+                }
+                None => self.window_update_pending = false,
+            }
+        }
         let mut ids_to_ack = Vec::new();
         for (id, (count, first_pending_at)) in self.pending_acks.iter() {
-            if *count >= 2
+            if immediate
+                || *count >= 2
                 || now.saturating_duration_since(*first_pending_at)
                     >= crate::protocol::DELAYED_ACK_TIMEOUT
             {
@@ -1412,6 +1990,17 @@ impl<C: CongestionControl> Drop for SequenceSession<C> {
     }
 }
 
+/// Takes the in-flight fragments of a message that is given up on out of
+/// the session's in-flight count.
+fn release_in_flight(in_flight: &mut usize, msg: &mut OutgoingMessage) {
+    for (idx, state) in msg.fragment_states.iter().enumerate() {
+        if state.last_sent.is_some() {
+            *in_flight = in_flight.saturating_sub(msg.fragment_len(FragmentIndex(idx as u16)));
+        }
+    }
+    msg.in_flight_queue.clear();
+}
+
 fn peek_message_type(data: &[u8]) -> Option<MessageType> {
     if data.len() < 2 {
         return None;
@@ -1440,6 +2029,29 @@ fn peek_message_type(data: &[u8]) -> Option<MessageType> {
         0x12 => Some(MessageType::ReconPowChallenge),
         0x13 => Some(MessageType::ReconPowSolution),
         0x14 => Some(MessageType::AdminGossip),
+        0x15 => Some(MessageType::Batch),
         _ => None,
     }
 }
+
+/// Payload size of the fragments for a message of `len` bytes. Prefers the
+/// path's packet size, but grows fragments up to `packet_size` rather than
+/// exceed the fragment limit.
+fn payload_mtu(
+    path: &PathQuality,
+    packet_size: usize,
+    overhead: usize,
+    len: usize,
+) -> Option<usize> {
+    let max_payload = packet_size.saturating_sub(overhead);
+    if max_payload == 0 {
+        return None;
+    }
+    let min_payload = len.div_ceil(protocol::MAX_FRAGMENTS_PER_MESSAGE as usize);
+    Some(
+        path.packet_size()
+            .saturating_sub(overhead)
+            .max(min_payload)
+            .clamp(1, max_payload),
+    )
+}
@@ -1,5 +1,6 @@
 use std::fmt::Debug;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use tox_proto::time::Instant;
 pub use tox_proto::{SystemTimeProvider, TimeProvider};
 
 use tox_proto::ToxProto;
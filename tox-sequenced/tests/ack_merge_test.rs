@@ -58,3 +58,38 @@ fn test_ack_merging_logic() {
 }
 
 // end of tests
+
+#[test]
+fn test_flush_acks_skips_delay() {
+    let now = Instant::now();
+    let tp = std::sync::Arc::new(tox_sequenced::time::ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut bob = SequenceSession::new_at(now, tp, &mut rng);
+    let msg_id = MessageId(7);
+
+    bob.handle_packet(
+        Packet::Data {
+            message_id: msg_id,
+            fragment_index: FragmentIndex(0),
+            total_fragments: FragmentCount(3),
+            data: vec![1, 2, 3],
+        },
+        now,
+    );
+    // One fragment within the delayed-ACK timeout: nothing is due yet.
+    assert!(
+        !bob.get_packets_to_send(now, 0)
+            .iter()
+            .any(|p| matches!(p, Packet::Ack(_)))
+    );
+
+    let mut sent = Vec::new();
+    bob.flush_acks(now, &mut |p| {
+        sent.push(p);
+        true
+    });
+    assert!(
+        sent.iter()
+            .any(|p| matches!(p, Packet::Ack(ack) if ack.message_id == msg_id))
+    );
+}
@@ -224,3 +224,63 @@ fn test_last_one_overflow() {
 }
 
 // end of tests
+
+#[test]
+fn test_set_and_unset_range() {
+    let mut bs = BitSet::<4>::new();
+    bs.set_range(10, 200);
+    for i in 0..256 {
+        assert_eq!(bs.get(i), (10..200).contains(&i), "bit {}", i);
+    }
+    assert_eq!(bs.count_ones(), 190);
+
+    bs.unset_range(60, 130);
+    assert_eq!(
+        bs.ranges(0, 256).collect::<Vec<_>>(),
+        vec![10..60, 130..200]
+    );
+
+    // Within one word, empty and out of bounds.
+    bs.set_range(3, 5);
+    bs.set_range(7, 7);
+    bs.set_range(250, 1000);
+    assert_eq!(
+        bs.ranges(0, 256).collect::<Vec<_>>(),
+        vec![3..5, 10..60, 130..200, 250..256]
+    );
+    bs.unset_range(0, usize::MAX);
+    assert_eq!(bs.count_ones(), 0);
+}
+
+#[test]
+fn test_ranges_respects_bounds() {
+    let mut bs = BitSet::<2>::new();
+    bs.set_range(0, 10);
+    bs.set_range(64, 70);
+    bs.set(127);
+
+    assert_eq!(bs.ranges(5, 66).collect::<Vec<_>>(), vec![5..10, 64..66]);
+    assert_eq!(bs.ranges(0, 1000).last(), Some(127..128));
+    assert_eq!(bs.ranges(20, 60).next(), None);
+}
+
+#[test]
+fn test_first_missing_after() {
+    let mut bs = BitSet::<3>::new();
+    assert_eq!(bs.first_missing_after(0), 1);
+
+    bs.set_range(0, 150);
+    assert_eq!(bs.first_missing_after(0), 150);
+    assert_eq!(bs.first_missing_after(63), 150);
+    assert_eq!(bs.first_missing_after(149), 150);
+    assert_eq!(bs.first_missing_after(150), 151);
+
+    bs.unset(100);
+    assert_eq!(bs.first_missing_after(5), 100);
+    assert_eq!(bs.first_missing_after(100), 150);
+
+    bs.fill();
+    assert_eq!(bs.first_missing_after(10), 192);
+    assert_eq!(bs.first_missing_after(191), 192);
+    assert_eq!(bs.first_missing_after(500), 501);
+}
@@ -0,0 +1,113 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::protocol::{MessageType, Packet};
+use tox_sequenced::quota::ReassemblyQuota;
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{SequenceSession, SessionEvent};
+
+fn session(quota: ReassemblyQuota, now: Instant, seed: u64) -> SequenceSession {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    SequenceSession::with_quota_at(quota, now, tp, &mut rng)
+}
+
+fn failures(session: &mut SequenceSession) -> Vec<(tox_sequenced::protocol::MessageId, String)> {
+    let mut failed = Vec::new();
+    while let Some(event) = session.poll_event() {
+        if let SessionEvent::MessageFailed(id, reason) = event {
+            failed.push((id, reason));
+        }
+    }
+    failed
+}
+
+#[test]
+fn test_cancel_queued_message() {
+    let now = Instant::now();
+    let mut alice = session(ReassemblyQuota::new(1024 * 1024), now, 1);
+    let id = alice
+        .send_message(MessageType::BlobData, &[1; 5000], now)
+        .unwrap();
+
+    assert!(alice.cancel(id, true));
+    assert!(!alice.cancel(id, true));
+    assert_eq!(failures(&mut alice), vec![(id, "Cancelled".to_string())]);
+    assert!(alice.outgoing_status(now).is_empty());
+
+    // Nothing was sent, so the peer needs no notification.
+    let packets = alice.get_packets_to_send(now, 0);
+    assert!(
+        !packets
+            .iter()
+            .any(|p| matches!(p, Packet::Cancel { .. } | Packet::Data { .. }))
+    );
+}
+
+#[test]
+fn test_cancel_frees_peer_reassembly() {
+    let now = Instant::now();
+    let bob_quota = ReassemblyQuota::new(1024 * 1024);
+    let mut alice = session(ReassemblyQuota::new(1024 * 1024), now, 1);
+    let mut bob = session(bob_quota.clone(), now, 2);
+
+    let id = alice
+        .send_message(MessageType::BlobData, &vec![1; 50_000], now)
+        .unwrap();
+
+    // Hold back one fragment so the transfer cannot complete.
+    let mut t = now;
+    let mut held = None;
+    while bob.incoming_status(t).is_empty() || held.is_none() {
+        t += Duration::from_millis(10);
+        for packet in alice.get_packets_to_send(t, 0) {
+            if matches!(packet, Packet::Data { .. }) && held.is_none() {
+                held = Some(packet);
+                continue;
+            }
+            bob.handle_packet(packet, t);
+        }
+    }
+    assert!(bob_quota.available() < 1024 * 1024);
+    assert!(alice.in_flight() > 0);
+
+    assert!(alice.cancel(id, true));
+    assert_eq!(alice.in_flight(), 0);
+    assert_eq!(failures(&mut alice), vec![(id, "Cancelled".to_string())]);
+
+    let packets = alice.get_packets_to_send(t, 0);
+    assert!(packets.contains(&Packet::Cancel { message_id: id }));
+    assert!(!packets.iter().any(|p| matches!(p, Packet::Data { .. })));
+    for packet in packets {
+        bob.handle_packet(packet, t);
+    }
+    assert!(bob.incoming_status(t).is_empty());
+    assert_eq!(bob_quota.available(), 1024 * 1024);
+
+    // A fragment arriving late does not start the reassembly over.
+    assert!(bob.handle_packet(held.unwrap(), t).is_empty());
+    assert!(bob.incoming_status(t).is_empty());
+    assert_eq!(bob_quota.available(), 1024 * 1024);
+}
+
+#[test]
+fn test_cancel_without_notification() {
+    let now = Instant::now();
+    let mut alice = session(ReassemblyQuota::new(1024 * 1024), now, 1);
+    let id = alice
+        .send_message(MessageType::BlobData, &vec![1; 50_000], now)
+        .unwrap();
+    let mut t = now;
+    while alice.in_flight() == 0 {
+        t += Duration::from_millis(10);
+        alice.get_packets_to_send(t, 0);
+    }
+
+    assert!(alice.cancel(id, false));
+    assert!(
+        !alice
+            .get_packets_to_send(t, 0)
+            .iter()
+            .any(|p| matches!(p, Packet::Cancel { .. }))
+    );
+}
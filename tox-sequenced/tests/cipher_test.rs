@@ -0,0 +1,151 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::cipher::SEAL_OVERHEAD;
+use tox_sequenced::protocol::{MessageType, Packet, TimestampMs};
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{CipherRole, PacketCipher, SequenceSession, SequencedError, SessionEvent};
+
+const KEY: [u8; 32] = [7; 32];
+
+fn ping(t: i64) -> Packet {
+    Packet::Ping { t1: TimestampMs(t) }
+}
+
+fn pair() -> (PacketCipher, PacketCipher) {
+    (
+        PacketCipher::new(&KEY, CipherRole::Initiator),
+        PacketCipher::new(&KEY, CipherRole::Responder),
+    )
+}
+
+#[test]
+fn test_seal_and_open() {
+    let (mut alice, mut bob) = pair();
+    let sealed = alice.seal(&ping(1)).unwrap();
+    assert_eq!(
+        sealed.len(),
+        tox_proto::serialize(&ping(1)).unwrap().len() + SEAL_OVERHEAD
+    );
+    assert_eq!(bob.open(&sealed).unwrap(), ping(1));
+
+    // The same packet sealed twice looks different.
+    assert_ne!(alice.seal(&ping(1)).unwrap(), sealed);
+
+    let reply = bob.seal(&ping(2)).unwrap();
+    assert_eq!(alice.open(&reply).unwrap(), ping(2));
+}
+
+#[test]
+fn test_open_rejects_tampering_and_wrong_keys() {
+    let (mut alice, mut bob) = pair();
+    let mut sealed = alice.seal(&ping(1)).unwrap();
+    let last = sealed.len() - 1;
+    sealed[last] ^= 1;
+    assert_eq!(bob.open(&sealed), Err(SequencedError::AuthenticationFailed));
+    assert_eq!(
+        bob.open(&sealed[..10]),
+        Err(SequencedError::AuthenticationFailed)
+    );
+
+    // A packet reflected back to its sender does not open.
+    let sealed = alice.seal(&ping(2)).unwrap();
+    assert_eq!(
+        alice.open(&sealed),
+        Err(SequencedError::AuthenticationFailed)
+    );
+
+    let mut mallory = PacketCipher::new(&[8; 32], CipherRole::Responder);
+    assert_eq!(
+        mallory.open(&sealed),
+        Err(SequencedError::AuthenticationFailed)
+    );
+    // A failed attempt does not burn the counter.
+    assert_eq!(bob.open(&sealed).unwrap(), ping(2));
+}
+
+#[test]
+fn test_open_rejects_replays_but_allows_reordering() {
+    let (mut alice, mut bob) = pair();
+    let sealed: Vec<_> = (0..100).map(|i| alice.seal(&ping(i)).unwrap()).collect();
+
+    assert_eq!(bob.open(&sealed[10]).unwrap(), ping(10));
+    assert_eq!(bob.open(&sealed[5]).unwrap(), ping(5));
+    assert_eq!(bob.open(&sealed[10]), Err(SequencedError::ReplayedPacket));
+    assert_eq!(bob.open(&sealed[5]), Err(SequencedError::ReplayedPacket));
+
+    assert_eq!(bob.open(&sealed[99]).unwrap(), ping(99));
+    // Too old to tell whether it was seen.
+    assert_eq!(bob.open(&sealed[20]), Err(SequencedError::ReplayedPacket));
+    assert_eq!(bob.open(&sealed[40]).unwrap(), ping(40));
+}
+
+#[test]
+fn test_encrypted_sessions_deliver_messages() {
+    let now = Instant::now();
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut alice = SequenceSession::new_at(now, tp.clone(), &mut rng);
+    let mut bob = SequenceSession::new_at(now, tp, &mut rng);
+    let (alice_cipher, bob_cipher) = pair();
+    alice.set_cipher(alice_cipher);
+    bob.set_cipher(bob_cipher);
+    assert!(alice.is_encrypted());
+
+    let data = vec![0x5A; 10_000];
+    alice
+        .send_message(MessageType::MerkleNode, &data, now)
+        .unwrap();
+
+    let mut t = now;
+    let mut received = None;
+    for _ in 0..100 {
+        t += Duration::from_millis(10);
+        for packet in alice.get_packets_to_send(t, 0) {
+            let wire = alice.encode_packet(&packet).unwrap();
+            // Fragments still fit the path once sealed.
+            assert!(wire.len() <= tox_sequenced::protocol::MAX_TOX_PACKET_SIZE);
+            let packet = bob.decode_packet(&wire).unwrap();
+            for reply in bob.handle_packet(packet, t) {
+                let wire = bob.encode_packet(&reply).unwrap();
+                let reply = alice.decode_packet(&wire).unwrap();
+                alice.handle_packet(reply, t);
+            }
+        }
+        for packet in bob.get_packets_to_send(t, 0) {
+            let wire = bob.encode_packet(&packet).unwrap();
+            let packet = alice.decode_packet(&wire).unwrap();
+            alice.handle_packet(packet, t);
+        }
+        while let Some(event) = bob.poll_event() {
+            if let SessionEvent::MessageCompleted(_, _, payload) = event {
+                received = Some(payload);
+            }
+        }
+        if received.is_some() {
+            break;
+        }
+    }
+    assert_eq!(received, Some(data));
+}
+
+#[test]
+fn test_one_sided_cipher_fails_loudly() {
+    let now = Instant::now();
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut alice = SequenceSession::new_at(now, tp.clone(), &mut rng);
+    let mut bob = SequenceSession::new_at(now, tp, &mut rng);
+    alice.set_cipher(PacketCipher::new(&KEY, CipherRole::Initiator));
+
+    let sealed = alice.encode_packet(&ping(1)).unwrap();
+    assert_eq!(
+        bob.decode_packet(&sealed),
+        Err(SequencedError::CipherMismatch)
+    );
+    let plain = bob.encode_packet(&ping(2)).unwrap();
+    assert_eq!(
+        alice.decode_packet(&plain),
+        Err(SequencedError::CipherMismatch)
+    );
+}
@@ -0,0 +1,204 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::coalesce::{
+    COALESCE_DELAY, MAX_COALESCED_MESSAGE, decode_batch, encode_batch, peek_batch,
+};
+use tox_sequenced::protocol::{MessageId, MessageType, Packet};
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{SequenceSession, SessionEvent};
+
+fn session(now: Instant, seed: u64) -> SequenceSession {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    SequenceSession::new_at(now, tp, &mut rng)
+}
+
+fn data_packets(packets: &[Packet]) -> usize {
+    packets
+        .iter()
+        .filter(|p| matches!(p, Packet::Data { .. }))
+        .count()
+}
+
+/// Runs both sessions for a second, returning the number of data packets
+/// Alice sent.
+fn exchange(alice: &mut SequenceSession, bob: &mut SequenceSession, start: Instant) -> usize {
+    let mut sent = 0;
+    for step in 0..100 {
+        let t = start + Duration::from_millis(step * 10);
+        let packets = alice.get_packets_to_send(t, 0);
+        sent += data_packets(&packets);
+        for packet in packets {
+            for reply in bob.handle_packet(packet, t) {
+                alice.handle_packet(reply, t);
+            }
+        }
+        for packet in bob.get_packets_to_send(t, 0) {
+            alice.handle_packet(packet, t);
+        }
+    }
+    sent
+}
+
+fn completed(session: &mut SequenceSession) -> Vec<(MessageId, MessageType, Vec<u8>)> {
+    let mut messages = Vec::new();
+    while let Some(event) = session.poll_event() {
+        if let SessionEvent::MessageCompleted(id, mtype, payload) = event {
+            messages.push((id, mtype, payload));
+        }
+    }
+    messages
+}
+
+#[test]
+fn test_batch_round_trip() {
+    let messages = vec![
+        (MessageType::SyncHeads, vec![1, 2, 3]),
+        (MessageType::CapsAck, vec![]),
+        (MessageType::FetchBatchReq, vec![0xAB; 200]),
+    ];
+    let encoded = encode_batch(&messages).unwrap();
+    assert_eq!(decode_batch(&encoded).unwrap(), messages);
+    assert!(decode_batch(&[0xC1]).is_err());
+}
+
+#[test]
+fn test_small_messages_share_a_packet() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+
+    for i in 0..10u8 {
+        alice
+            .send_coalesced(MessageType::SyncHeads, &[i; 40], now)
+            .unwrap();
+    }
+    assert_eq!(alice.coalesced_len(), 10);
+
+    // Held back until the delay expires.
+    assert_eq!(data_packets(&alice.get_packets_to_send(now, 0)), 0);
+    assert_eq!(alice.next_wakeup(now), now + COALESCE_DELAY);
+
+    let t = now + COALESCE_DELAY;
+    let packets = alice.get_packets_to_send(t, 0);
+    assert_eq!(data_packets(&packets), 1);
+    assert_eq!(alice.coalesced_len(), 0);
+
+    for packet in packets {
+        bob.handle_packet(packet, t);
+    }
+    let received = completed(&mut bob);
+    assert_eq!(received.len(), 10);
+    for (i, (_, mtype, payload)) in received.iter().enumerate() {
+        assert_eq!(*mtype, MessageType::SyncHeads);
+        assert_eq!(*payload, vec![i as u8; 40]);
+    }
+}
+
+#[test]
+fn test_single_message_is_not_wrapped() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+
+    alice
+        .send_coalesced(MessageType::CapsAck, &[7; 10], now)
+        .unwrap();
+    let t = now + COALESCE_DELAY;
+    for packet in alice.get_packets_to_send(t, 0) {
+        bob.handle_packet(packet, t);
+    }
+    let received = completed(&mut bob);
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].1, MessageType::CapsAck);
+    assert_eq!(received[0].2, vec![7; 10]);
+}
+
+#[test]
+fn test_full_batch_flushes_immediately() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+
+    // About a packet's worth of messages goes out without waiting.
+    for _ in 0..6 {
+        alice
+            .send_coalesced(MessageType::SyncHeads, &[0; 250], now)
+            .unwrap();
+    }
+    assert!(alice.coalesced_len() < 6);
+    assert!(data_packets(&alice.get_packets_to_send(now, 0)) >= 1);
+}
+
+#[test]
+fn test_large_message_keeps_order() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+
+    alice
+        .send_coalesced(MessageType::SyncHeads, &[1; 20], now)
+        .unwrap();
+    alice
+        .send_coalesced(MessageType::SyncHeads, &[2; 20], now)
+        .unwrap();
+    // A large message pushes out the small ones queued before it.
+    alice
+        .send_coalesced(
+            MessageType::MerkleNode,
+            &[3; MAX_COALESCED_MESSAGE + 1],
+            now,
+        )
+        .unwrap();
+    assert_eq!(alice.coalesced_len(), 0);
+
+    exchange(&mut alice, &mut bob, now);
+    let received = completed(&mut bob);
+    let payloads: Vec<u8> = received.iter().map(|(_, _, p)| p[0]).collect();
+    assert_eq!(payloads, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_coalescing_reduces_packets() {
+    let now = Instant::now();
+    let mut plain = session(now, 1);
+    let mut batched = session(now, 2);
+    let mut bob = session(now, 3);
+    let mut carol = session(now, 4);
+    for _ in 0..20 {
+        plain
+            .send_message(MessageType::SyncHeads, &[0; 30], now)
+            .unwrap();
+        batched
+            .send_coalesced(MessageType::SyncHeads, &[0; 30], now)
+            .unwrap();
+    }
+    assert_eq!(exchange(&mut plain, &mut bob, now), 20);
+    assert_eq!(exchange(&mut batched, &mut carol, now), 1);
+    assert_eq!(completed(&mut bob).len(), 20);
+    assert_eq!(completed(&mut carol).len(), 20);
+}
+
+#[test]
+fn test_peek_batch_reads_truncated_batch() {
+    let messages = vec![
+        (MessageType::SyncHeads, vec![1; 40]),
+        (MessageType::AdminGossip, vec![2; 40]),
+        (MessageType::KeywrapAck, vec![3; 40]),
+    ];
+    let batch = encode_batch(&messages).unwrap();
+    let peeked = peek_batch(&batch);
+    assert_eq!(peeked.len(), 3);
+    for ((t, p), (message_type, payload)) in messages.iter().zip(&peeked) {
+        assert_eq!(t, message_type);
+        assert_eq!(&p[..], *payload);
+    }
+
+    // Cut inside the second message.
+    let peeked = peek_batch(&batch[..60]);
+    assert_eq!(peeked.len(), 2);
+    assert_eq!(peeked[0], (MessageType::SyncHeads, &[1u8; 40][..]));
+    assert_eq!(peeked[1].0, MessageType::AdminGossip);
+    assert!(peeked[1].1.len() < 40);
+    assert!(peek_batch(&[]).is_empty());
+}
@@ -0,0 +1,137 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::congestion::{
+    Aimd, CongestionControl, CongestionTrace, TraceEventKind, TraceTrigger,
+};
+use tox_sequenced::protocol::MessageType;
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{SequenceSession, SessionEvent};
+
+#[test]
+fn test_trace_records_state_transitions() {
+    let now = Instant::now();
+    let mut aimd = Aimd::new();
+    let srtt = Duration::from_millis(100);
+    let mut trace = CongestionTrace::new(&aimd, 16, srtt, 0, now);
+    assert_eq!(trace.state(), "SlowStart");
+
+    let later = now + Duration::from_millis(50);
+    aimd.on_nack(later);
+    trace.record(&aimd, TraceTrigger::Nack, None, srtt, 0, later);
+
+    assert_eq!(trace.state(), "CongestionAvoidance");
+    let kinds: Vec<_> = trace.events().map(|e| e.kind.clone()).collect();
+    assert_eq!(kinds.len(), 3);
+    assert!(matches!(
+        kinds[0],
+        TraceEventKind::Metrics {
+            trigger: TraceTrigger::Start,
+            ..
+        }
+    ));
+    assert_eq!(
+        kinds[1],
+        TraceEventKind::StateChanged {
+            trigger: TraceTrigger::Nack,
+            from: "SlowStart",
+            to: "CongestionAvoidance",
+        }
+    );
+    assert!(matches!(
+        kinds[2],
+        TraceEventKind::Metrics {
+            trigger: TraceTrigger::Nack,
+            ..
+        }
+    ));
+    assert_eq!(
+        trace.events().last().unwrap().time,
+        Duration::from_millis(50)
+    );
+}
+
+#[test]
+fn test_trace_is_bounded() {
+    let now = Instant::now();
+    let aimd = Aimd::new();
+    let srtt = Duration::from_millis(100);
+    let mut trace = CongestionTrace::new(&aimd, 4, srtt, 0, now);
+    for i in 0..10 {
+        let t = now + Duration::from_millis(i);
+        trace.record(&aimd, TraceTrigger::Ack, Some(srtt), srtt, 0, t);
+    }
+    assert_eq!(trace.len(), 4);
+    assert_eq!(trace.dropped(), 7);
+    // The oldest events went first.
+    assert_eq!(
+        trace.events().next().unwrap().time,
+        Duration::from_millis(6)
+    );
+}
+
+#[test]
+fn test_session_trace_exports_qlog() {
+    let now = Instant::now();
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut alice = SequenceSession::new_at(now, tp.clone(), &mut rng);
+    let mut bob = SequenceSession::new_at(now, tp.clone(), &mut rng);
+    assert!(alice.export_qlog("alice").is_none());
+
+    alice.enable_congestion_trace(1024, now);
+    let data = vec![0xAB; 20_000];
+    alice
+        .send_message(MessageType::MerkleNode, &data, now)
+        .unwrap();
+
+    let mut t = now;
+    let mut delivered = false;
+    for _ in 0..200 {
+        t += Duration::from_millis(10);
+        for packet in alice.get_packets_to_send(t, 0) {
+            bob.handle_packet(packet, t);
+        }
+        for packet in bob.get_packets_to_send(t, 0) {
+            alice.handle_packet(packet, t);
+        }
+        while let Some(event) = alice.poll_event() {
+            if matches!(event, SessionEvent::MessageAcked(_)) {
+                delivered = true;
+            }
+        }
+        if delivered {
+            break;
+        }
+    }
+    assert!(delivered);
+
+    let trace = alice.congestion_trace().unwrap();
+    let acks = trace
+        .events()
+        .filter(|e| {
+            matches!(
+                e.kind,
+                TraceEventKind::Metrics {
+                    trigger: TraceTrigger::Ack,
+                    rtt_sample: Some(_),
+                    ..
+                }
+            )
+        })
+        .count();
+    assert!(acks > 0);
+
+    let qlog: serde_json::Value =
+        serde_json::from_str(&alice.export_qlog("alice").unwrap()).unwrap();
+    assert_eq!(qlog["title"], "alice");
+    let events = qlog["traces"][0]["events"].as_array().unwrap();
+    assert_eq!(events.len(), trace.len());
+    assert_eq!(events[0]["name"], "recovery:metrics_updated");
+    assert_eq!(events[0]["data"]["trigger"], "start");
+    assert!(events[0]["data"]["congestion_window"].as_u64().unwrap() > 0);
+
+    let trace = alice.disable_congestion_trace().unwrap();
+    assert!(!trace.is_empty());
+    assert!(alice.congestion_trace().is_none());
+}
@@ -0,0 +1,133 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::protocol::{ESTIMATED_PAYLOAD_SIZE, MessageType, Packet};
+use tox_sequenced::quota::ReassemblyQuota;
+use tox_sequenced::session::SequenceSession;
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{Aimd, SessionEvent};
+
+const QUOTA: usize = 20_000;
+
+/// Alice sends `count` messages of `len` bytes to Bob, who never polls his
+/// events. Returns the last window Bob advertised.
+fn deliver(
+    alice: &mut SequenceSession,
+    bob: &mut SequenceSession,
+    count: usize,
+    len: usize,
+    now: &mut Instant,
+) -> usize {
+    for i in 0..count {
+        alice
+            .send_message_at(MessageType::MerkleNode, &vec![i as u8; len], *now)
+            .unwrap();
+    }
+    let mut acked = 0;
+    let mut rwnd = usize::MAX;
+    for _ in 0..500 {
+        *now += Duration::from_millis(5);
+        for p in alice.get_packets_to_send(*now, 0) {
+            for reply in bob.handle_packet(p, *now) {
+                alice.handle_packet(reply, *now);
+            }
+        }
+        for p in bob.get_packets_to_send(*now, 0) {
+            if let Packet::Ack(ack) = &p {
+                rwnd = ack.rwnd.0 as usize;
+            }
+            alice.handle_packet(p, *now);
+        }
+        while let Some(event) = alice.poll_event() {
+            if matches!(event, SessionEvent::MessageAcked(_)) {
+                acked += 1;
+            }
+        }
+        if acked == count {
+            return rwnd;
+        }
+    }
+    panic!("only {} of {} messages were acknowledged", acked, count);
+}
+
+fn sessions(now: Instant) -> (SequenceSession, SequenceSession, ReassemblyQuota) {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let quota = ReassemblyQuota::new(QUOTA);
+    let bob = SequenceSession::with_quota_at(quota.clone(), now, tp.clone(), &mut rng);
+    let alice = SequenceSession::with_congestion_control_at(
+        tox_sequenced::congestion::Algorithm::Aimd(Aimd::with_cwnd(20.0)),
+        now,
+        tp,
+        &mut rng,
+    );
+    (alice, bob, quota)
+}
+
+#[test]
+fn test_undelivered_messages_shrink_window() {
+    let mut now = Instant::now();
+    let (mut alice, mut bob, quota) = sessions(now);
+
+    let rwnd = deliver(&mut alice, &mut bob, 3, 5000, &mut now);
+
+    // The completed messages still hold their memory until Bob's
+    // application takes them.
+    assert_eq!(bob.undelivered_bytes(), 15_000);
+    assert!(quota.used() >= 15_000);
+    assert!(rwnd <= (QUOTA - 15_000) / ESTIMATED_PAYLOAD_SIZE);
+    assert_eq!(bob.current_rwnd().0 as usize, rwnd);
+
+    while bob.poll_event().is_some() {}
+    assert_eq!(bob.undelivered_bytes(), 0);
+    assert_eq!(quota.used(), 0);
+    assert_eq!(
+        bob.current_rwnd().0 as usize,
+        QUOTA / ESTIMATED_PAYLOAD_SIZE
+    );
+}
+
+#[test]
+fn test_draining_events_sends_window_update() {
+    let mut now = Instant::now();
+    let (mut alice, mut bob, _quota) = sessions(now);
+
+    deliver(&mut alice, &mut bob, 3, 5000, &mut now);
+    let rwnd = deliver(&mut alice, &mut bob, 1, 1500, &mut now);
+    assert!(rwnd <= 2, "window should be nearly closed, got {}", rwnd);
+
+    // Nothing to say while the messages sit in Bob's queue.
+    now += Duration::from_millis(100);
+    assert!(
+        bob.get_packets_to_send(now, 0)
+            .iter()
+            .all(|p| !matches!(p, Packet::Ack(_)))
+    );
+
+    let mut completed = 0;
+    while let Some(event) = bob.poll_event() {
+        if matches!(event, SessionEvent::MessageCompleted(..)) {
+            completed += 1;
+        }
+    }
+    assert_eq!(completed, 4);
+    assert_eq!(bob.next_wakeup(now), now);
+
+    let updates: Vec<_> = bob
+        .get_packets_to_send(now, 0)
+        .into_iter()
+        .filter_map(|p| match p {
+            Packet::Ack(ack) => Some(ack),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(updates[0].rwnd.0 as usize, QUOTA / ESTIMATED_PAYLOAD_SIZE);
+
+    // Only one update per reopening.
+    assert!(
+        bob.get_packets_to_send(now, 0)
+            .iter()
+            .all(|p| !matches!(p, Packet::Ack(_)))
+    );
+}
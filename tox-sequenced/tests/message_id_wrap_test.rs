@@ -0,0 +1,135 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::protocol::{ExtendedMessageId, MessageId, MessageType, Packet};
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{SequenceSession, SessionEvent};
+
+fn session(now: Instant, seed: u64) -> SequenceSession {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    SequenceSession::new_at(now, tp, &mut rng)
+}
+
+/// Runs the exchange until no packets are left, returning every packet
+/// Alice sent and the messages Bob completed.
+fn exchange(
+    alice: &mut SequenceSession,
+    bob: &mut SequenceSession,
+    now: &mut Instant,
+) -> (Vec<Packet>, Vec<(MessageId, Vec<u8>)>) {
+    let mut sent = Vec::new();
+    let mut completed = Vec::new();
+    for _ in 0..100 {
+        *now += Duration::from_millis(10);
+        for packet in alice.get_packets_to_send(*now, 0) {
+            sent.push(packet.clone());
+            for reply in bob.handle_packet(packet, *now) {
+                alice.handle_packet(reply, *now);
+            }
+        }
+        for packet in bob.get_packets_to_send(*now, 0) {
+            alice.handle_packet(packet, *now);
+        }
+        while let Some(event) = bob.poll_event() {
+            if let SessionEvent::MessageCompleted(id, _, data) = event {
+                completed.push((id, data));
+            }
+        }
+    }
+    (sent, completed)
+}
+
+#[test]
+fn test_serial_comparison() {
+    let max = MessageId(u32::MAX);
+    assert!(MessageId(1).is_after(max));
+    assert!(max.is_before(MessageId(1)));
+    assert!(!MessageId(1).is_before(max));
+    assert!(!max.is_after(max));
+    assert_eq!(max.distance_behind(MessageId(1)), Some(2));
+    assert_eq!(MessageId(1).distance_behind(max), None);
+
+    // Exactly half the space apart is unordered.
+    let half = MessageId(1 << 31);
+    assert!(!half.is_after(MessageId(0)));
+    assert!(!half.is_before(MessageId(0)));
+}
+
+#[test]
+fn test_extended_id_crosses_generations() {
+    let before_wrap = ExtendedMessageId::new(3, MessageId(u32::MAX - 1));
+    assert_eq!(before_wrap.generation(), 3);
+    assert_eq!(before_wrap.id(), MessageId(u32::MAX - 1));
+
+    let after_wrap = before_wrap.extend(MessageId(5));
+    assert_eq!(after_wrap, ExtendedMessageId::new(4, MessageId(5)));
+    assert!(after_wrap > before_wrap);
+
+    // Late IDs from before the wrap go back to the previous generation.
+    assert_eq!(after_wrap.extend(MessageId(u32::MAX)).generation(), 3);
+    assert_eq!(after_wrap.extend(MessageId(2)).generation(), 4);
+    assert_eq!(after_wrap.to_string(), "4:5");
+}
+
+#[test]
+fn test_session_sends_across_wrap() {
+    let mut now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    alice.set_next_message_id(MessageId(u32::MAX - 2));
+
+    let mut expected = Vec::new();
+    for i in 0..6u8 {
+        let data = vec![i; 3000];
+        let id = alice
+            .send_message(MessageType::MerkleNode, &data, now)
+            .unwrap();
+        expected.push((id, data));
+        let (_, completed) = exchange(&mut alice, &mut bob, &mut now);
+        assert_eq!(completed, vec![expected.last().unwrap().clone()]);
+    }
+    let ids: Vec<_> = expected.iter().map(|(id, _)| id.0).collect();
+    assert_eq!(ids, vec![u32::MAX - 2, u32::MAX - 1, u32::MAX, 0, 1, 2]);
+
+    assert_eq!(
+        alice.next_extended_message_id(),
+        ExtendedMessageId::new(1, MessageId(3))
+    );
+    assert_eq!(
+        bob.highest_received_id(),
+        Some(ExtendedMessageId::new(1, MessageId(2)))
+    );
+}
+
+#[test]
+fn test_concurrent_messages_straddling_wrap() {
+    let mut now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    alice.set_next_message_id(MessageId(u32::MAX - 1));
+
+    let mut expected = Vec::new();
+    for i in 0..4u8 {
+        let data = vec![i; 5000];
+        let id = alice
+            .send_message(MessageType::MerkleNode, &data, now)
+            .unwrap();
+        expected.push((id, data));
+    }
+    let (sent, mut completed) = exchange(&mut alice, &mut bob, &mut now);
+    completed.sort_by_key(|(id, _)| id.0.wrapping_sub(u32::MAX - 1));
+    assert_eq!(completed, expected);
+
+    // A fragment of a message from before the wrap arrives again: it is
+    // acknowledged but not delivered a second time.
+    let replay = sent
+        .into_iter()
+        .find(
+            |p| matches!(p, Packet::Data { message_id, .. } if *message_id == MessageId(u32::MAX)),
+        )
+        .unwrap();
+    let replies = bob.handle_packet(replay, now);
+    assert!(matches!(replies.as_slice(), [Packet::Ack(_)]));
+    assert!(bob.poll_event().is_none());
+}
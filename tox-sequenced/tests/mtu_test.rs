@@ -0,0 +1,140 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::mtu::{BASE_PACKET_SIZE, MTU_PROBE_GRANULARITY, probe_packet};
+use tox_sequenced::protocol::{self, MessageType, PACKET_OVERHEAD, Packet};
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{PathQuality, SequenceSession, SessionEvent};
+
+const PATH_MAX: usize = 1373;
+
+fn session(now: Instant, seed: u64) -> SequenceSession {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    SequenceSession::new_at(now, tp, &mut rng)
+}
+
+/// Delivers `packet` unless it is larger than `limit` bytes on the wire.
+fn deliver(to: &mut SequenceSession, packet: Packet, t: Instant, limit: usize) -> Vec<Packet> {
+    let size = protocol::serialize(&packet).unwrap().len();
+    if size > limit {
+        return Vec::new();
+    }
+    to.handle_packet(packet, t)
+}
+
+/// Runs both sessions over a path that drops packets over `limit` bytes,
+/// returning the messages Bob completed.
+fn run(
+    alice: &mut SequenceSession,
+    bob: &mut SequenceSession,
+    start: Instant,
+    duration: Duration,
+    limit: usize,
+) -> Vec<Vec<u8>> {
+    let mut received = Vec::new();
+    let step = Duration::from_millis(20);
+    let mut t = start;
+    while t < start + duration {
+        for packet in alice.get_packets_to_send(t, 0) {
+            for reply in deliver(bob, packet, t, limit) {
+                deliver(alice, reply, t, limit);
+            }
+        }
+        for packet in bob.get_packets_to_send(t, 0) {
+            for reply in deliver(alice, packet, t, limit) {
+                deliver(bob, reply, t, limit);
+            }
+        }
+        while let Some(event) = bob.poll_event() {
+            if let SessionEvent::MessageCompleted(_, _, payload) = event {
+                received.push(payload);
+            }
+        }
+        t += step;
+    }
+    received
+}
+
+#[test]
+fn test_probe_packet_has_requested_size() {
+    for size in [BASE_PACKET_SIZE, 700, 1000, PATH_MAX] {
+        let packet = probe_packet(7, size);
+        assert_eq!(protocol::serialize(&packet).unwrap().len(), size);
+    }
+}
+
+#[test]
+fn test_probe_ack_confirms_path_maximum() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    assert!(!alice.mtu().is_searching());
+
+    run(&mut alice, &mut bob, now, Duration::from_secs(5), PATH_MAX);
+    assert_eq!(alice.mtu().confirmed(), PATH_MAX);
+    assert_eq!(alice.mtu().packet_size(), PATH_MAX);
+    assert!(!alice.mtu().is_searching());
+}
+
+#[test]
+fn test_black_hole_converges_below_limit() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    let limit = 900;
+
+    run(&mut alice, &mut bob, now, Duration::from_secs(60), limit);
+    let size = alice.mtu().packet_size();
+    assert!(size <= limit, "size {} above limit", size);
+    assert!(
+        size + MTU_PROBE_GRANULARITY > limit,
+        "size {} too far below limit",
+        size
+    );
+    assert!(!alice.mtu().is_searching());
+}
+
+#[test]
+fn test_messages_fit_discovered_size() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    let limit = 900;
+    run(&mut alice, &mut bob, now, Duration::from_secs(60), limit);
+
+    let t = now + Duration::from_secs(60);
+    let data = vec![0x42; 5000];
+    let id = alice.send_message(MessageType::BlobData, &data, t).unwrap();
+    assert_eq!(
+        alice.find_outgoing(id).unwrap().payload_mtu,
+        alice.mtu().packet_size() - PACKET_OVERHEAD
+    );
+    let received = run(&mut alice, &mut bob, t, Duration::from_secs(10), limit);
+    assert_eq!(received, vec![data]);
+}
+
+#[test]
+fn test_path_change_refragments_unsent_messages() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let data = vec![0; 4000];
+    let id = alice
+        .send_message(MessageType::BlobData, &data, now)
+        .unwrap();
+    assert_eq!(
+        alice.find_outgoing(id).unwrap().payload_mtu,
+        PATH_MAX - PACKET_OVERHEAD
+    );
+
+    alice.set_path(PathQuality {
+        mtu: Some(600),
+        ..PathQuality::default()
+    });
+    let msg = alice.find_outgoing(id).unwrap();
+    assert_eq!(msg.payload_mtu, 600 - PACKET_OVERHEAD);
+    assert_eq!(
+        msg.num_fragments.0 as usize,
+        data.len().div_ceil(600 - PACKET_OVERHEAD)
+    );
+}
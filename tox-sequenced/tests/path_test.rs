@@ -0,0 +1,174 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::protocol::{
+    MAX_FRAGMENTS_PER_MESSAGE, MessageType, PACKET_OVERHEAD, Packet, RELAYED_PACKET_SIZE,
+};
+use tox_sequenced::rtt::{LinuxRto, RELAYED_MIN_RTO, Rfc6298Rto, RtoAlgorithm};
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{
+    AlgorithmType, PathEstimates, PathQuality, SequenceSession, SequencedError, SessionEvent,
+};
+
+fn session(now: Instant, seed: u64) -> SequenceSession {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    SequenceSession::new_at(now, tp, &mut rng)
+}
+
+fn relayed() -> PathQuality {
+    PathQuality {
+        relayed: true,
+        ..PathQuality::default()
+    }
+}
+
+#[test]
+fn test_relayed_path_uses_small_fragments() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    alice.set_path(relayed());
+
+    let data = vec![0x42; 4000];
+    let id = alice
+        .send_message(MessageType::BlobData, &data, now)
+        .unwrap();
+    assert_eq!(
+        alice.find_outgoing(id).unwrap().payload_mtu,
+        RELAYED_PACKET_SIZE - PACKET_OVERHEAD
+    );
+
+    let mut received = None;
+    for step in 0..100 {
+        let t = now + Duration::from_millis(step * 20);
+        for packet in alice.get_packets_to_send(t, 0) {
+            if let Packet::Data { data, .. } = &packet {
+                assert!(data.len() <= RELAYED_PACKET_SIZE - PACKET_OVERHEAD);
+            }
+            for reply in bob.handle_packet(packet, t) {
+                alice.handle_packet(reply, t);
+            }
+        }
+        for reply in bob.get_packets_to_send(t, 0) {
+            alice.handle_packet(reply, t);
+        }
+        while let Some(event) = bob.poll_event() {
+            if let SessionEvent::MessageCompleted(_, _, payload) = event {
+                received = Some(payload);
+            }
+        }
+        if received.is_some() {
+            break;
+        }
+    }
+    assert!(received == Some(data), "message not delivered");
+}
+
+#[test]
+fn test_large_message_grows_fragments_on_relayed_path() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    alice.set_path(relayed());
+
+    let data = vec![0; 900 * 1024];
+    let id = alice
+        .send_message(MessageType::BlobData, &data, now)
+        .unwrap();
+    let msg = alice.find_outgoing(id).unwrap();
+    assert!(msg.payload_mtu > RELAYED_PACKET_SIZE - PACKET_OVERHEAD);
+    assert!(msg.num_fragments.0 <= MAX_FRAGMENTS_PER_MESSAGE);
+}
+
+#[test]
+fn test_path_mtu_limits_datagrams() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    alice.set_path(PathQuality {
+        mtu: Some(200),
+        ..PathQuality::default()
+    });
+
+    assert!(matches!(
+        alice.send_datagram(MessageType::SyncHeads, &[0; 300]),
+        Err(SequencedError::MessageTooLarge)
+    ));
+    alice
+        .send_datagram(MessageType::SyncHeads, &[0; 100])
+        .unwrap();
+}
+
+#[test]
+fn test_relayed_path_switches_congestion_control() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    assert_eq!(alice.algo_type(), AlgorithmType::Aimd);
+
+    alice.set_path(relayed());
+    assert_eq!(alice.algo_type(), AlgorithmType::Cubic);
+
+    // Back on a direct path, the session's own algorithm returns.
+    alice.set_path(PathQuality::default());
+    assert_eq!(alice.algo_type(), AlgorithmType::Aimd);
+}
+
+#[test]
+fn test_transport_rtt_seeds_estimator() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    alice.set_path(PathQuality {
+        rtt: Some(Duration::from_millis(800)),
+        ..PathQuality::default()
+    });
+    // RTO = SRTT + 4 * SRTT / 2, capped.
+    assert_eq!(alice.current_rto(), Duration::from_millis(2400));
+}
+
+#[test]
+fn test_relayed_path_switches_rto_strategy() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    alice.set_path(relayed());
+    assert_eq!(alice.rto_strategy(), Rfc6298Rto::relayed().into());
+    assert!(alice.current_rto() >= RELAYED_MIN_RTO);
+
+    alice.set_path(PathQuality::default());
+    assert_eq!(alice.rto_strategy(), RtoAlgorithm::default());
+}
+
+#[test]
+fn test_chosen_rto_strategy_survives_path_changes() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    alice.set_rto_strategy(LinuxRto::default());
+    alice.set_path(relayed());
+    assert_eq!(alice.rto_strategy(), LinuxRto::default().into());
+}
+
+#[test]
+fn test_estimates_seed_a_new_session() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    assert_eq!(alice.estimates().rtt, None);
+
+    let estimates = PathEstimates {
+        rtt: Some(Duration::from_millis(800)),
+        mtu: 1000,
+    };
+    alice.seed_estimates(estimates);
+    assert_eq!(alice.current_rto(), Duration::from_millis(2400));
+    assert_eq!(alice.mtu().confirmed(), 1000);
+    // Seeding is not measuring.
+    assert_eq!(alice.estimates().rtt, None);
+
+    // Sizes beyond the path maximum are capped.
+    let mut bob = session(now, 2);
+    bob.seed_estimates(PathEstimates {
+        rtt: None,
+        mtu: usize::MAX,
+    });
+    assert_eq!(
+        bob.mtu().confirmed(),
+        PathQuality::default().max_packet_size()
+    );
+}
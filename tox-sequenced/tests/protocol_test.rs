@@ -1,6 +1,7 @@
 use smallvec::smallvec;
 use tox_sequenced::protocol::{
-    FragmentCount, FragmentIndex, MessageId, MessageType, Nack, Packet, SelectiveAck, TimestampMs,
+    FragmentCount, FragmentIndex, MessageId, MessageType, Nack, OutboundEnvelope, Packet,
+    SelectiveAck, TimestampMs, peek_envelope,
 };
 
 #[test]
@@ -73,4 +74,62 @@ fn test_message_type_enum() {
     assert_eq!(MessageType::MerkleNode as u8, 0x05);
 }
 
+#[test]
+fn test_packet_message_id() {
+    let data = Packet::Data {
+        message_id: MessageId(7),
+        fragment_index: FragmentIndex(0),
+        total_fragments: FragmentCount(1),
+        data: vec![],
+    };
+    let nack = Packet::Nack(Nack {
+        message_id: MessageId(8),
+        missing_indices: smallvec![],
+    });
+    let ping = Packet::Ping { t1: TimestampMs(0) };
+    assert_eq!(data.message_id(), Some(MessageId(7)));
+    assert_eq!(nack.message_id(), Some(MessageId(8)));
+    assert_eq!(ping.message_id(), None);
+}
+
+#[test]
+fn test_peek_message_reads_first_fragment() {
+    let payload: Vec<u8> = (0..=255).collect();
+    let envelope = tox_proto::serialize(&OutboundEnvelope {
+        message_type: MessageType::MerkleNode,
+        payload: &payload,
+    })
+    .unwrap();
+    let (head, tail) = envelope.split_at(100);
+    let fragment = |index: u16, data: &[u8]| Packet::Data {
+        message_id: MessageId(1),
+        fragment_index: FragmentIndex(index),
+        total_fragments: FragmentCount(2),
+        data: data.to_vec(),
+    };
+
+    let first = fragment(0, head);
+    let (message_type, prefix) = first.peek_message().unwrap();
+    assert_eq!(message_type, MessageType::MerkleNode);
+    assert!(!prefix.is_empty());
+    assert!(payload.starts_with(prefix));
+    assert_eq!(fragment(1, tail).peek_message(), None);
+    assert_eq!(Packet::Ping { t1: TimestampMs(0) }.peek_message(), None);
+
+    let datagram = Packet::Datagram {
+        message_type: MessageType::AdminGossip,
+        data: vec![1, 2, 3],
+    };
+    assert_eq!(
+        datagram.peek_message(),
+        Some((MessageType::AdminGossip, &[1u8, 2, 3][..]))
+    );
+
+    let (_, whole, rest) = peek_envelope(&envelope).unwrap();
+    assert_eq!(whole, &payload[..]);
+    assert!(rest.is_empty());
+    assert_eq!(peek_envelope(&[0x92]), None);
+    assert_eq!(peek_envelope(&envelope[1..]), None);
+}
+
 // end of tests
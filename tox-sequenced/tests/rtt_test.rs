@@ -1,5 +1,8 @@
 use std::time::Duration;
-use tox_sequenced::rtt::{MAX_RTO, MIN_RTO, RttEstimator};
+use tox_sequenced::rtt::{
+    LinuxRto, MAX_RTO, MIN_RTO, RELAYED_MAX_BACKOFF_EXPONENT, RELAYED_MIN_RTO, Rfc6298Rto,
+    RtoAlgorithm, RttEstimator,
+};
 
 #[test]
 fn test_rtt_update() {
@@ -43,4 +46,46 @@ fn test_backoff() {
     assert_eq!(rtt.rto_with_backoff(10), base_rto * 64);
 }
 
+#[test]
+fn test_relayed_rto_has_higher_floor_and_fewer_doublings() {
+    let mut rtt = RttEstimator::with_strategy(Rfc6298Rto::relayed().into());
+    for _ in 0..50 {
+        rtt.update(Duration::from_millis(20));
+    }
+    assert_eq!(rtt.rto(), RELAYED_MIN_RTO);
+    assert_eq!(
+        rtt.rto_with_backoff(10),
+        RELAYED_MIN_RTO * (1 << RELAYED_MAX_BACKOFF_EXPONENT)
+    );
+}
+
+#[test]
+fn test_linux_rto_floors_variance_and_caps_backoff() {
+    let mut rtt = RttEstimator::with_strategy(LinuxRto::default().into());
+    for _ in 0..200 {
+        rtt.update(Duration::from_millis(300));
+    }
+    // The variance has vanished, but the timeout stays `MIN_RTO` above SRTT.
+    let rto = rtt.rto();
+    assert!(rto >= rtt.srtt() + MIN_RTO);
+    assert!(rto < Duration::from_millis(600));
+    assert_eq!(rtt.rto_with_backoff(10), MAX_RTO);
+    assert_eq!(rtt.tlp_timeout(), rtt.srtt() * 2);
+}
+
+#[test]
+fn test_set_strategy_keeps_measurements() {
+    let mut rtt = RttEstimator::new();
+    for _ in 0..50 {
+        rtt.update(Duration::from_millis(100));
+    }
+    let srtt = rtt.srtt();
+    rtt.set_strategy(Rfc6298Rto::relayed().into());
+    assert_eq!(rtt.srtt(), srtt);
+    assert_eq!(rtt.rto(), RELAYED_MIN_RTO);
+
+    rtt.set_strategy(RtoAlgorithm::default());
+    assert!(rtt.rto() < RELAYED_MIN_RTO);
+}
+
 // end of tests
@@ -0,0 +1,99 @@
+use rand::SeedableRng;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tox_sequenced::protocol::{
+    ESTIMATED_PAYLOAD_SIZE, FragmentCount, FragmentIndex, MessageId, MessageType, Packet,
+};
+use tox_sequenced::quota::Priority;
+use tox_sequenced::time::ManualTimeProvider;
+use tox_sequenced::{IncomingStatus, SequenceSession};
+
+fn session(now: Instant, seed: u64) -> SequenceSession {
+    let tp = Arc::new(ManualTimeProvider::new(now, 0));
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    SequenceSession::new_at(now, tp, &mut rng)
+}
+
+#[test]
+fn test_outgoing_status_tracks_progress() {
+    let now = Instant::now();
+    let mut alice = session(now, 1);
+    let mut bob = session(now, 2);
+    assert!(alice.outgoing_status(now).is_empty());
+
+    let id = alice
+        .send_message(MessageType::BlobData, &vec![1; 20_000], now)
+        .unwrap();
+    let status = &alice.outgoing_status(now)[0];
+    assert_eq!(status.message_id, id);
+    assert_eq!(status.message_type, MessageType::BlobData);
+    assert!(status.total_bytes > 20_000);
+    assert!(status.fragments_total.0 as usize >= 20_000 / ESTIMATED_PAYLOAD_SIZE);
+    assert_eq!(status.fragments_sent, FragmentCount(0));
+    assert_eq!(status.fragments_acked, FragmentCount(0));
+
+    // Deliver only the first fragment; the rest get lost.
+    let mut t = now;
+    for _ in 0..20 {
+        t += Duration::from_millis(10);
+        for packet in alice.get_packets_to_send(t, 0) {
+            if let Packet::Data { fragment_index, .. } = &packet
+                && fragment_index.0 != 0
+            {
+                continue;
+            }
+            bob.handle_packet(packet, t);
+        }
+        for packet in bob.get_packets_to_send(t, 0) {
+            alice.handle_packet(packet, t);
+        }
+    }
+
+    let status = &alice.outgoing_status(t)[0];
+    assert!(status.fragments_sent.0 > 1);
+    assert_eq!(status.fragments_acked, FragmentCount(1));
+    assert!(status.fragments_in_flight > 0);
+    assert!(status.fragments_in_flight < status.fragments_sent.0 as usize);
+    assert_eq!(status.age, Duration::from_millis(200));
+    assert!(status.since_last_ack < status.age);
+
+    // The lost fragments go out again after the RTO.
+    let later = t + alice.current_rto() * 2;
+    alice.get_packets_to_send(later, 0);
+    let status = &alice.outgoing_status(later)[0];
+    assert!(status.retransmits > 0);
+    assert!(status.since_last_ack >= alice.current_rto());
+}
+
+#[test]
+fn test_incoming_status_tracks_reassembly() {
+    let now = Instant::now();
+    let mut bob = session(now, 2);
+    assert!(bob.incoming_status(now).is_empty());
+
+    for i in [0, 2] {
+        bob.handle_packet(
+            Packet::Data {
+                message_id: MessageId(7),
+                fragment_index: FragmentIndex(i),
+                total_fragments: FragmentCount(4),
+                data: vec![0; 100],
+            },
+            now + Duration::from_millis(i as u64 * 10),
+        );
+    }
+
+    let later = now + Duration::from_millis(100);
+    assert_eq!(
+        bob.incoming_status(later),
+        vec![IncomingStatus {
+            message_id: MessageId(7),
+            priority: Priority::Standard,
+            fragments_total: FragmentCount(4),
+            fragments_received: FragmentCount(2),
+            bytes_received: 200,
+            age: Duration::from_millis(100),
+            idle: Duration::from_millis(80),
+        }]
+    );
+}
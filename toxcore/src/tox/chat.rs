@@ -0,0 +1,131 @@
+use crate::tox::friend::Friend;
+use crate::tox::{Conference, Group};
+use crate::types::*;
+
+/// Identifies a conference or a group, for code that handles both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ChatNumber {
+    Conference(ConferenceNumber),
+    Group(GroupNumber),
+}
+
+impl From<ConferenceNumber> for ChatNumber {
+    fn from(number: ConferenceNumber) -> Self {
+        ChatNumber::Conference(number)
+    }
+}
+
+impl From<GroupNumber> for ChatNumber {
+    fn from(number: GroupNumber) -> Self {
+        ChatNumber::Group(number)
+    }
+}
+
+/// A peer in a conference or a group. Only meaningful together with the
+/// `Chat` it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ChatPeer {
+    Conference(ConferencePeerNumber),
+    Group(GroupPeerNumber),
+}
+
+/// A conference or a group, with the operations both support.
+#[derive(Debug, Clone, Copy)]
+pub enum Chat<'a> {
+    Conference(Conference<'a>),
+    Group(Group<'a>),
+}
+
+impl<'a> From<Conference<'a>> for Chat<'a> {
+    fn from(conference: Conference<'a>) -> Self {
+        Chat::Conference(conference)
+    }
+}
+
+impl<'a> From<Group<'a>> for Chat<'a> {
+    fn from(group: Group<'a>) -> Self {
+        Chat::Group(group)
+    }
+}
+
+impl<'a> Chat<'a> {
+    pub fn number(&self) -> ChatNumber {
+        match self {
+            Chat::Conference(c) => ChatNumber::Conference(c.number()),
+            Chat::Group(g) => ChatNumber::Group(g.get_number()),
+        }
+    }
+
+    pub fn send_message(&self, message_type: MessageType, message: &[u8]) -> Result<()> {
+        match self {
+            Chat::Conference(c) => c.send_message(message_type, message),
+            Chat::Group(g) => g.send_message(message_type, message).map(|_| ()),
+        }
+    }
+
+    pub fn invite(&self, friend: &Friend) -> Result<()> {
+        match self {
+            Chat::Conference(c) => c.invite(friend),
+            Chat::Group(g) => g.invite_friend(friend),
+        }
+    }
+
+    /// The conference title or the group name.
+    pub fn title(&self) -> Result<Vec<u8>> {
+        match self {
+            Chat::Conference(c) => c.title(),
+            Chat::Group(g) => g.name(),
+        }
+    }
+
+    /// Peers currently in the chat. `None` for groups, which only announce
+    /// their peers through join and exit events.
+    pub fn peers(&self) -> Result<Option<Vec<ChatPeer>>> {
+        match self {
+            Chat::Conference(c) => Ok(Some(
+                c.peer_list()?
+                    .into_iter()
+                    .map(ChatPeer::Conference)
+                    .collect(),
+            )),
+            Chat::Group(_) => Ok(None),
+        }
+    }
+
+    pub fn peer_name(&self, peer: ChatPeer) -> Result<Vec<u8>> {
+        match (self, peer) {
+            (Chat::Conference(c), ChatPeer::Conference(p)) => c.peer_name(p),
+            (Chat::Group(g), ChatPeer::Group(p)) => g.peer_name(p),
+            _ => Err(self.peer_not_found()),
+        }
+    }
+
+    pub fn peer_public_key(&self, peer: ChatPeer) -> Result<PublicKey> {
+        match (self, peer) {
+            (Chat::Conference(c), ChatPeer::Conference(p)) => c.peer_public_key(p),
+            (Chat::Group(g), ChatPeer::Group(p)) => g.peer_public_key(p),
+            _ => Err(self.peer_not_found()),
+        }
+    }
+
+    /// Deletes the conference or leaves the group with `part_message`, which
+    /// conferences do not support.
+    pub fn leave(self, part_message: Option<&[u8]>) -> Result<()> {
+        match self {
+            Chat::Conference(c) => c.delete(),
+            Chat::Group(g) => g.leave(part_message),
+        }
+    }
+
+    /// The error a lookup of a peer from the other kind of chat gets.
+    fn peer_not_found(&self) -> ToxError {
+        match self {
+            Chat::Conference(_) => ToxError::ConferencePeerQuery(
+                Tox_Err_Conference_Peer_Query::TOX_ERR_CONFERENCE_PEER_QUERY_PEER_NOT_FOUND,
+            ),
+            Chat::Group(_) => ToxError::GroupPeerQuery(
+                Tox_Err_Group_Peer_Query::TOX_ERR_GROUP_PEER_QUERY_PEER_NOT_FOUND,
+            ),
+        }
+    }
+}
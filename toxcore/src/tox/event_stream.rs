@@ -0,0 +1,631 @@
+use crate::core::ToxHandler;
+use crate::types::*;
+
+/// An owned copy of a Tox callback. Unlike `events::Event`, it does not
+/// borrow from the event batch, so it can be kept, sent to another task or
+/// yielded from a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedEvent {
+    SelfConnectionStatus {
+        status: ToxConnection,
+    },
+    FriendRequest {
+        public_key: PublicKey,
+        message: Vec<u8>,
+    },
+    FriendConnectionStatus {
+        friend: FriendNumber,
+        status: ToxConnection,
+    },
+    FriendLossyPacket {
+        friend: FriendNumber,
+        data: Vec<u8>,
+    },
+    FriendLosslessPacket {
+        friend: FriendNumber,
+        data: Vec<u8>,
+    },
+    FriendName {
+        friend: FriendNumber,
+        name: Vec<u8>,
+    },
+    FriendStatus {
+        friend: FriendNumber,
+        status: ToxUserStatus,
+    },
+    FriendStatusMessage {
+        friend: FriendNumber,
+        message: Vec<u8>,
+    },
+    FriendMessage {
+        friend: FriendNumber,
+        message_type: MessageType,
+        message: Vec<u8>,
+    },
+    FriendReadReceipt {
+        friend: FriendNumber,
+        message_id: FriendMessageId,
+    },
+    FriendTyping {
+        friend: FriendNumber,
+        typing: bool,
+    },
+    FileChunkRequest {
+        friend: FriendNumber,
+        file: FileNumber,
+        position: u64,
+        length: usize,
+    },
+    FileRecv {
+        friend: FriendNumber,
+        file: FileNumber,
+        kind: u32,
+        file_size: u64,
+        filename: Vec<u8>,
+    },
+    FileRecvChunk {
+        friend: FriendNumber,
+        file: FileNumber,
+        position: u64,
+        data: Vec<u8>,
+    },
+    FileRecvControl {
+        friend: FriendNumber,
+        file: FileNumber,
+        control: ToxFileControl,
+    },
+    ConferenceInvite {
+        friend: FriendNumber,
+        conference_type: ToxConferenceType,
+        cookie: Vec<u8>,
+    },
+    ConferenceConnected {
+        conference: ConferenceNumber,
+    },
+    ConferencePeerListChanged {
+        conference: ConferenceNumber,
+    },
+    ConferencePeerName {
+        conference: ConferenceNumber,
+        peer: ConferencePeerNumber,
+        name: Vec<u8>,
+    },
+    ConferenceTitle {
+        conference: ConferenceNumber,
+        peer: ConferencePeerNumber,
+        title: Vec<u8>,
+    },
+    ConferenceMessage {
+        conference: ConferenceNumber,
+        peer: ConferencePeerNumber,
+        message_type: MessageType,
+        message: Vec<u8>,
+    },
+    GroupPeerName {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        name: Vec<u8>,
+    },
+    GroupPeerStatus {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        status: ToxUserStatus,
+    },
+    GroupTopic {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        topic: Vec<u8>,
+    },
+    GroupPrivacyState {
+        group: GroupNumber,
+        privacy_state: ToxGroupPrivacyState,
+    },
+    GroupVoiceState {
+        group: GroupNumber,
+        voice_state: ToxGroupVoiceState,
+    },
+    GroupTopicLock {
+        group: GroupNumber,
+        topic_lock: ToxGroupTopicLock,
+    },
+    GroupPeerLimit {
+        group: GroupNumber,
+        peer_limit: u32,
+    },
+    GroupPassword {
+        group: GroupNumber,
+        password: Vec<u8>,
+    },
+    GroupMessage {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        message_type: MessageType,
+        message: Vec<u8>,
+        message_id: GroupMessageId,
+    },
+    GroupPrivateMessage {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        message_type: MessageType,
+        message: Vec<u8>,
+        message_id: GroupMessageId,
+    },
+    GroupCustomPacket {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        data: Vec<u8>,
+    },
+    GroupCustomPrivatePacket {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        data: Vec<u8>,
+    },
+    GroupInvite {
+        friend: FriendNumber,
+        invite_data: Vec<u8>,
+        group_name: Vec<u8>,
+    },
+    GroupPeerJoin {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+    },
+    GroupPeerExit {
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        exit_type: ToxGroupExitType,
+        name: Vec<u8>,
+        part_message: Vec<u8>,
+    },
+    GroupSelfJoin {
+        group: GroupNumber,
+    },
+    GroupJoinFail {
+        group: GroupNumber,
+        fail_type: ToxGroupJoinFail,
+    },
+    GroupModeration {
+        group: GroupNumber,
+        source_peer: GroupPeerNumber,
+        target_peer: GroupPeerNumber,
+        mod_type: ToxGroupModEvent,
+    },
+}
+
+/// A `ToxHandler` that queues every callback as an `OwnedEvent`.
+#[derive(Debug, Default)]
+pub struct OwnedEvents {
+    events: Vec<OwnedEvent>,
+}
+
+impl OwnedEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes the queued events.
+    pub fn take(&mut self) -> Vec<OwnedEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+impl ToxHandler for OwnedEvents {
+    fn on_friend_message(
+        &mut self,
+        friend: FriendNumber,
+        message_type: MessageType,
+        message: &[u8],
+    ) {
+        self.events.push(OwnedEvent::FriendMessage {
+            friend,
+            message_type,
+            message: message.to_vec(),
+        });
+    }
+
+    fn on_friend_name(&mut self, friend: FriendNumber, name: &[u8]) {
+        self.events.push(OwnedEvent::FriendName {
+            friend,
+            name: name.to_vec(),
+        });
+    }
+
+    fn on_friend_status_message(&mut self, friend: FriendNumber, message: &[u8]) {
+        self.events.push(OwnedEvent::FriendStatusMessage {
+            friend,
+            message: message.to_vec(),
+        });
+    }
+
+    fn on_friend_status(&mut self, friend: FriendNumber, status: ToxUserStatus) {
+        self.events
+            .push(OwnedEvent::FriendStatus { friend, status });
+    }
+
+    fn on_self_connection_status(&mut self, status: ToxConnection) {
+        self.events
+            .push(OwnedEvent::SelfConnectionStatus { status });
+    }
+
+    fn on_friend_connection_status(&mut self, friend: FriendNumber, status: ToxConnection) {
+        self.events
+            .push(OwnedEvent::FriendConnectionStatus { friend, status });
+    }
+
+    fn on_friend_typing(&mut self, friend: FriendNumber, typing: bool) {
+        self.events
+            .push(OwnedEvent::FriendTyping { friend, typing });
+    }
+
+    fn on_friend_read_receipt(&mut self, friend: FriendNumber, message_id: FriendMessageId) {
+        self.events
+            .push(OwnedEvent::FriendReadReceipt { friend, message_id });
+    }
+
+    fn on_friend_request(&mut self, public_key: PublicKey, message: &[u8]) {
+        self.events.push(OwnedEvent::FriendRequest {
+            public_key,
+            message: message.to_vec(),
+        });
+    }
+
+    fn on_file_recv(
+        &mut self,
+        friend: FriendNumber,
+        file: FileNumber,
+        kind: u32,
+        file_size: u64,
+        filename: &[u8],
+    ) {
+        self.events.push(OwnedEvent::FileRecv {
+            friend,
+            file,
+            kind,
+            file_size,
+            filename: filename.to_vec(),
+        });
+    }
+
+    fn on_file_chunk_request(
+        &mut self,
+        friend: FriendNumber,
+        file: FileNumber,
+        position: u64,
+        length: usize,
+    ) {
+        self.events.push(OwnedEvent::FileChunkRequest {
+            friend,
+            file,
+            position,
+            length,
+        });
+    }
+
+    fn on_file_recv_chunk(
+        &mut self,
+        friend: FriendNumber,
+        file: FileNumber,
+        position: u64,
+        data: &[u8],
+    ) {
+        self.events.push(OwnedEvent::FileRecvChunk {
+            friend,
+            file,
+            position,
+            data: data.to_vec(),
+        });
+    }
+
+    fn on_file_recv_control(
+        &mut self,
+        friend: FriendNumber,
+        file: FileNumber,
+        control: ToxFileControl,
+    ) {
+        self.events.push(OwnedEvent::FileRecvControl {
+            friend,
+            file,
+            control,
+        });
+    }
+
+    fn on_conference_invite(
+        &mut self,
+        friend: FriendNumber,
+        conference_type: ToxConferenceType,
+        cookie: &[u8],
+    ) {
+        self.events.push(OwnedEvent::ConferenceInvite {
+            friend,
+            conference_type,
+            cookie: cookie.to_vec(),
+        });
+    }
+
+    fn on_conference_connected(&mut self, conference: ConferenceNumber) {
+        self.events
+            .push(OwnedEvent::ConferenceConnected { conference });
+    }
+
+    fn on_conference_message(
+        &mut self,
+        conference: ConferenceNumber,
+        peer: ConferencePeerNumber,
+        message_type: MessageType,
+        message: &[u8],
+    ) {
+        self.events.push(OwnedEvent::ConferenceMessage {
+            conference,
+            peer,
+            message_type,
+            message: message.to_vec(),
+        });
+    }
+
+    fn on_conference_title(
+        &mut self,
+        conference: ConferenceNumber,
+        peer: ConferencePeerNumber,
+        title: &[u8],
+    ) {
+        self.events.push(OwnedEvent::ConferenceTitle {
+            conference,
+            peer,
+            title: title.to_vec(),
+        });
+    }
+
+    fn on_conference_peer_name(
+        &mut self,
+        conference: ConferenceNumber,
+        peer: ConferencePeerNumber,
+        name: &[u8],
+    ) {
+        self.events.push(OwnedEvent::ConferencePeerName {
+            conference,
+            peer,
+            name: name.to_vec(),
+        });
+    }
+
+    fn on_conference_peer_list_changed(&mut self, conference: ConferenceNumber) {
+        self.events
+            .push(OwnedEvent::ConferencePeerListChanged { conference });
+    }
+
+    fn on_friend_lossy_packet(&mut self, friend: FriendNumber, data: &[u8]) {
+        self.events.push(OwnedEvent::FriendLossyPacket {
+            friend,
+            data: data.to_vec(),
+        });
+    }
+
+    fn on_friend_lossless_packet(&mut self, friend: FriendNumber, data: &[u8]) {
+        self.events.push(OwnedEvent::FriendLosslessPacket {
+            friend,
+            data: data.to_vec(),
+        });
+    }
+
+    fn on_group_invite(&mut self, friend: FriendNumber, invite_data: &[u8], group_name: &[u8]) {
+        self.events.push(OwnedEvent::GroupInvite {
+            friend,
+            invite_data: invite_data.to_vec(),
+            group_name: group_name.to_vec(),
+        });
+    }
+
+    fn on_group_message(
+        &mut self,
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        message_type: MessageType,
+        message: &[u8],
+        message_id: GroupMessageId,
+    ) {
+        self.events.push(OwnedEvent::GroupMessage {
+            group,
+            peer,
+            message_type,
+            message: message.to_vec(),
+            message_id,
+        });
+    }
+
+    fn on_group_private_message(
+        &mut self,
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        message_type: MessageType,
+        message: &[u8],
+        message_id: GroupMessageId,
+    ) {
+        self.events.push(OwnedEvent::GroupPrivateMessage {
+            group,
+            peer,
+            message_type,
+            message: message.to_vec(),
+            message_id,
+        });
+    }
+
+    fn on_group_custom_packet(&mut self, group: GroupNumber, peer: GroupPeerNumber, data: &[u8]) {
+        self.events.push(OwnedEvent::GroupCustomPacket {
+            group,
+            peer,
+            data: data.to_vec(),
+        });
+    }
+
+    fn on_group_custom_private_packet(
+        &mut self,
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        data: &[u8],
+    ) {
+        self.events.push(OwnedEvent::GroupCustomPrivatePacket {
+            group,
+            peer,
+            data: data.to_vec(),
+        });
+    }
+
+    fn on_group_peer_join(&mut self, group: GroupNumber, peer: GroupPeerNumber) {
+        self.events.push(OwnedEvent::GroupPeerJoin { group, peer });
+    }
+
+    fn on_group_peer_exit(
+        &mut self,
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        exit_type: ToxGroupExitType,
+        name: &[u8],
+        part_message: &[u8],
+    ) {
+        self.events.push(OwnedEvent::GroupPeerExit {
+            group,
+            peer,
+            exit_type,
+            name: name.to_vec(),
+            part_message: part_message.to_vec(),
+        });
+    }
+
+    fn on_group_self_join(&mut self, group: GroupNumber) {
+        self.events.push(OwnedEvent::GroupSelfJoin { group });
+    }
+
+    fn on_group_join_fail(&mut self, group: GroupNumber, fail_type: ToxGroupJoinFail) {
+        self.events
+            .push(OwnedEvent::GroupJoinFail { group, fail_type });
+    }
+
+    fn on_group_topic(&mut self, group: GroupNumber, peer: GroupPeerNumber, topic: &[u8]) {
+        self.events.push(OwnedEvent::GroupTopic {
+            group,
+            peer,
+            topic: topic.to_vec(),
+        });
+    }
+
+    fn on_group_privacy_state(&mut self, group: GroupNumber, privacy_state: ToxGroupPrivacyState) {
+        self.events.push(OwnedEvent::GroupPrivacyState {
+            group,
+            privacy_state,
+        });
+    }
+
+    fn on_group_voice_state(&mut self, group: GroupNumber, voice_state: ToxGroupVoiceState) {
+        self.events
+            .push(OwnedEvent::GroupVoiceState { group, voice_state });
+    }
+
+    fn on_group_topic_lock(&mut self, group: GroupNumber, topic_lock: ToxGroupTopicLock) {
+        self.events
+            .push(OwnedEvent::GroupTopicLock { group, topic_lock });
+    }
+
+    fn on_group_peer_limit(&mut self, group: GroupNumber, peer_limit: u32) {
+        self.events
+            .push(OwnedEvent::GroupPeerLimit { group, peer_limit });
+    }
+
+    fn on_group_password(&mut self, group: GroupNumber, password: &[u8]) {
+        self.events.push(OwnedEvent::GroupPassword {
+            group,
+            password: password.to_vec(),
+        });
+    }
+
+    fn on_group_peer_name(&mut self, group: GroupNumber, peer: GroupPeerNumber, name: &[u8]) {
+        self.events.push(OwnedEvent::GroupPeerName {
+            group,
+            peer,
+            name: name.to_vec(),
+        });
+    }
+
+    fn on_group_peer_status(
+        &mut self,
+        group: GroupNumber,
+        peer: GroupPeerNumber,
+        status: ToxUserStatus,
+    ) {
+        self.events.push(OwnedEvent::GroupPeerStatus {
+            group,
+            peer,
+            status,
+        });
+    }
+
+    fn on_group_moderation(
+        &mut self,
+        group: GroupNumber,
+        source_peer: GroupPeerNumber,
+        target_peer: GroupPeerNumber,
+        mod_type: ToxGroupModEvent,
+    ) {
+        self.events.push(OwnedEvent::GroupModeration {
+            group,
+            source_peer,
+            target_peer,
+            mod_type,
+        });
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use stream::EventStream;
+
+#[cfg(feature = "tokio")]
+mod stream {
+    use super::{OwnedEvent, OwnedEvents};
+    use crate::tox::Tox;
+    use futures::Stream;
+    use std::collections::VecDeque;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use tokio::time::{Instant, Sleep};
+
+    /// Runs `Tox::iterate` every `iteration_interval` and yields the events
+    /// it produced. Returned by `Tox::event_stream`; never ends.
+    pub struct EventStream<'a> {
+        tox: &'a Tox,
+        handler: OwnedEvents,
+        pending: VecDeque<OwnedEvent>,
+        sleep: Pin<Box<Sleep>>,
+    }
+
+    impl<'a> EventStream<'a> {
+        pub(crate) fn new(tox: &'a Tox) -> Self {
+            Self {
+                tox,
+                handler: OwnedEvents::new(),
+                pending: VecDeque::new(),
+                // The first iteration runs right away.
+                sleep: Box::pin(tokio::time::sleep(Duration::ZERO)),
+            }
+        }
+    }
+
+    impl Stream for EventStream<'_> {
+        type Item = OwnedEvent;
+
+        fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<OwnedEvent>> {
+            let this = self.get_mut();
+            loop {
+                if let Some(event) = this.pending.pop_front() {
+                    return Poll::Ready(Some(event));
+                }
+                if this.sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.tox.iterate(&mut this.handler);
+                this.pending.extend(this.handler.take());
+                let interval = Duration::from_millis(this.tox.iteration_interval().into());
+                this.sleep.as_mut().reset(Instant::now() + interval);
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use super::Tox;
+use crate::types::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// A friend as listed by `FriendManager::list`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FriendEntry {
+    pub number: FriendNumber,
+    pub public_key: PublicKey,
+    pub alias: Option<String>,
+    pub name: Vec<u8>,
+    pub connection: ToxConnection,
+}
+
+impl FriendEntry {
+    /// The alias if one is set, otherwise the friend's own name.
+    pub fn display_name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => String::from_utf8_lossy(&self.name).into_owned(),
+        }
+    }
+}
+
+/// A change in the friend list, reported by `FriendManager::sync` and
+/// `FriendManager::on_connection_status`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FriendChange {
+    Added {
+        number: FriendNumber,
+        public_key: PublicKey,
+    },
+    Removed {
+        number: FriendNumber,
+        public_key: PublicKey,
+    },
+    Connection {
+        number: FriendNumber,
+        public_key: PublicKey,
+        old: ToxConnection,
+        new: ToxConnection,
+    },
+}
+
+impl FriendChange {
+    /// Whether this is a friend going from offline to online.
+    pub fn came_online(&self) -> bool {
+        matches!(
+            self,
+            FriendChange::Connection { old, new, .. }
+                if *old == ToxConnection::TOX_CONNECTION_NONE
+                    && *new != ToxConnection::TOX_CONNECTION_NONE
+        )
+    }
+
+    /// Whether this is a friend going from online to offline.
+    pub fn went_offline(&self) -> bool {
+        matches!(
+            self,
+            FriendChange::Connection { old, new, .. }
+                if *old != ToxConnection::TOX_CONNECTION_NONE
+                    && *new == ToxConnection::TOX_CONNECTION_NONE
+        )
+    }
+}
+
+type SaveHook = Box<dyn FnMut(&Tox) + Send>;
+
+/// Friend bookkeeping on top of a `Tox` instance: adding and removing by
+/// public key, local aliases, and connection tracking. Every mutation of the
+/// friend list runs the save hook, so the savedata on disk never lags behind
+/// a `friend_add_norequest`.
+#[derive(Default)]
+pub struct FriendManager {
+    aliases: BTreeMap<PublicKey, String>,
+    known: HashMap<FriendNumber, (PublicKey, ToxConnection)>,
+    save: Option<SaveHook>,
+}
+
+impl FriendManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `save` after every change to the friend list, typically
+    /// `Profile::save`.
+    pub fn with_save_hook(mut self, save: impl FnMut(&Tox) + Send + 'static) -> Self {
+        self.save = Some(Box::new(save));
+        self
+    }
+
+    /// Restores aliases persisted from `aliases`.
+    pub fn with_aliases(mut self, aliases: BTreeMap<PublicKey, String>) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    /// The aliases, for the application to persist alongside its config.
+    pub fn aliases(&self) -> &BTreeMap<PublicKey, String> {
+        &self.aliases
+    }
+
+    /// Sends a friend request to `address`.
+    pub fn add(&mut self, tox: &Tox, address: &Address, message: &[u8]) -> Result<FriendNumber> {
+        let number = tox.friend_add(address, message)?.get_number();
+        self.track(tox, number);
+        Ok(number)
+    }
+
+    /// Adds `public_key` without a request, e.g. to accept one.
+    pub fn add_norequest(&mut self, tox: &Tox, public_key: &PublicKey) -> Result<FriendNumber> {
+        let number = tox.friend_add_norequest(public_key)?.get_number();
+        self.track(tox, number);
+        Ok(number)
+    }
+
+    /// Deletes the friend and their alias.
+    pub fn remove(&mut self, tox: &Tox, public_key: &PublicKey) -> Result<()> {
+        let friend = tox.lookup_friend(public_key)?;
+        let number = friend.get_number();
+        friend.delete()?;
+        self.known.remove(&number);
+        self.aliases.remove(public_key);
+        self.persist(tox);
+        Ok(())
+    }
+
+    /// Sets or, with `None` or an empty alias, clears the local name for
+    /// `public_key`.
+    pub fn set_alias(&mut self, public_key: PublicKey, alias: Option<String>) {
+        match alias.filter(|a| !a.is_empty()) {
+            Some(alias) => self.aliases.insert(public_key, alias),
+            None => self.aliases.remove(&public_key),
+        };
+    }
+
+    pub fn alias(&self, public_key: &PublicKey) -> Option<&str> {
+        self.aliases.get(public_key).map(String::as_str)
+    }
+
+    /// Looks a friend up by alias, ignoring case.
+    pub fn find_by_alias(&self, tox: &Tox, alias: &str) -> Option<FriendNumber> {
+        let (public_key, _) = self
+            .aliases
+            .iter()
+            .find(|(_, a)| a.eq_ignore_ascii_case(alias))?;
+        tox.lookup_friend(public_key).ok().map(|f| f.get_number())
+    }
+
+    /// All friends, ordered by friend number.
+    pub fn list(&self, tox: &Tox) -> Vec<FriendEntry> {
+        let mut entries: Vec<FriendEntry> = tox
+            .friend_list()
+            .into_iter()
+            .filter_map(|friend| {
+                let public_key = friend.public_key().ok()?;
+                Some(FriendEntry {
+                    number: friend.get_number(),
+                    public_key,
+                    alias: self.aliases.get(&public_key).cloned(),
+                    name: friend.name().unwrap_or_default(),
+                    connection: friend
+                        .connection_status()
+                        .unwrap_or(ToxConnection::TOX_CONNECTION_NONE),
+                })
+            })
+            .collect();
+        entries.sort_by_key(|e| e.number);
+        entries
+    }
+
+    /// Records a `FriendConnectionStatus` event. Returns the change, or
+    /// `None` if the status is the one already known.
+    pub fn on_connection_status(
+        &mut self,
+        tox: &Tox,
+        number: FriendNumber,
+        connection: ToxConnection,
+    ) -> Option<FriendChange> {
+        if !self.known.contains_key(&number) {
+            // Not seen before, so it was offline until now.
+            let public_key = tox.friend(number).public_key().ok()?;
+            self.known
+                .insert(number, (public_key, ToxConnection::TOX_CONNECTION_NONE));
+        }
+        let (public_key, old) = self.known.get_mut(&number)?;
+        if *old == connection {
+            return None;
+        }
+        let change = FriendChange::Connection {
+            number,
+            public_key: *public_key,
+            old: *old,
+            new: connection,
+        };
+        *old = connection;
+        Some(change)
+    }
+
+    /// Compares the friend list with what was last seen and returns the
+    /// differences. Useful at startup and for friends added or removed
+    /// behind the manager's back.
+    pub fn sync(&mut self, tox: &Tox) -> Vec<FriendChange> {
+        let list = self.list(tox);
+        let mut removed: Vec<_> = self
+            .known
+            .iter()
+            .filter(|(number, _)| !list.iter().any(|e| e.number == **number))
+            .map(|(number, (public_key, _))| (*number, *public_key))
+            .collect();
+        removed.sort_by_key(|(number, _)| *number);
+        let mut changes: Vec<_> = removed
+            .into_iter()
+            .map(|(number, public_key)| FriendChange::Removed { number, public_key })
+            .collect();
+
+        let mut current = HashMap::new();
+        for entry in list {
+            current.insert(entry.number, (entry.public_key, entry.connection));
+            match self.known.get(&entry.number) {
+                Some((public_key, _)) if *public_key != entry.public_key => {
+                    // The number was reused for a different friend.
+                    changes.push(FriendChange::Removed {
+                        number: entry.number,
+                        public_key: *public_key,
+                    });
+                    changes.push(FriendChange::Added {
+                        number: entry.number,
+                        public_key: entry.public_key,
+                    });
+                }
+                Some((_, old)) if *old != entry.connection => {
+                    changes.push(FriendChange::Connection {
+                        number: entry.number,
+                        public_key: entry.public_key,
+                        old: *old,
+                        new: entry.connection,
+                    });
+                }
+                Some(_) => {}
+                None => changes.push(FriendChange::Added {
+                    number: entry.number,
+                    public_key: entry.public_key,
+                }),
+            }
+        }
+        self.known = current;
+        changes
+    }
+
+    fn track(&mut self, tox: &Tox, number: FriendNumber) {
+        let friend = tox.friend(number);
+        if let Ok(public_key) = friend.public_key() {
+            let connection = friend
+                .connection_status()
+                .unwrap_or(ToxConnection::TOX_CONNECTION_NONE);
+            self.known.insert(number, (public_key, connection));
+        }
+        self.persist(tox);
+    }
+
+    fn persist(&mut self, tox: &Tox) {
+        if let Some(save) = &mut self.save {
+            save(tox);
+        }
+    }
+}
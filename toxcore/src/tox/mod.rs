@@ -2,20 +2,30 @@ use crate::core;
 use crate::toxav::ToxAVConferenceHandler;
 pub use crate::types::*;
 
+mod chat;
 mod conference;
 mod conference_scope;
 pub mod encryptsave;
+mod event_stream;
 pub mod events;
 mod file;
 mod friend;
+mod friend_manager;
 mod group;
+mod profile;
 
+pub use chat::{Chat, ChatNumber, ChatPeer};
 pub use conference::Conference;
 pub use conference_scope::ConferenceAvScope;
+#[cfg(feature = "tokio")]
+pub use event_stream::EventStream;
+pub use event_stream::{OwnedEvent, OwnedEvents};
 use events::ToxEvents;
 pub use file::File;
 pub use friend::Friend;
+pub use friend_manager::{FriendChange, FriendEntry, FriendManager};
 pub use group::Group;
+pub use profile::{Profile, ProfileError};
 
 // Re-export traits
 pub use crate::core::ToxHandler;
@@ -144,6 +154,14 @@ impl Tox {
             .map_err(ToxError::EventsIterate)
     }
 
+    /// Iterates at `iteration_interval` and yields the resulting events,
+    /// so an async client can replace its polling loop with
+    /// `while let Some(event) = stream.next().await`.
+    #[cfg(feature = "tokio")]
+    pub fn event_stream(&self) -> EventStream<'_> {
+        EventStream::new(self)
+    }
+
     pub fn bootstrap(&self, host: &str, port: u16, public_key: &DhtId) -> Result<()> {
         self.inner
             .core
@@ -398,6 +416,22 @@ impl Tox {
             .collect()
     }
 
+    pub fn chat(&self, number: ChatNumber) -> Chat<'_> {
+        match number {
+            ChatNumber::Conference(n) => Chat::Conference(self.conference(n)),
+            ChatNumber::Group(n) => Chat::Group(self.group(n)),
+        }
+    }
+
+    /// All conferences followed by all groups.
+    pub fn chats(&self) -> Vec<Chat<'_>> {
+        self.conference_chatlist()
+            .into_iter()
+            .map(Chat::Conference)
+            .chain((0..self.group_count()).map(|n| Chat::Group(self.group(GroupNumber(n)))))
+            .collect()
+    }
+
     pub fn add_av_groupchat<'a, H: ToxAVConferenceHandler>(
         &'a self,
         handler: &H,
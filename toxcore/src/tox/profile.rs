@@ -0,0 +1,209 @@
+//! Savedata files on disk.
+//!
+//! A `Profile` owns the path of a Tox savedata file and the password it is
+//! encrypted with, if any. Saves go to a temporary file that is synced and
+//! then renamed over the profile, so a crash mid-write never leaves a
+//! truncated profile behind. The previous versions are kept as numbered
+//! backups next to it (`<path>.bak.1` is the most recent).
+
+use super::{Options, Tox, encryptsave};
+use crate::types::{ToxError, ToxSavedataType};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(io::Error),
+    Tox(ToxError),
+    /// The profile is encrypted but no password was given.
+    PasswordRequired,
+}
+
+impl error::Error for ProfileError {}
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "profile I/O error: {}", e),
+            ProfileError::Tox(e) => write!(f, "profile encryption error: {}", e),
+            ProfileError::PasswordRequired => write!(f, "password required to decrypt profile"),
+        }
+    }
+}
+
+impl From<io::Error> for ProfileError {
+    fn from(e: io::Error) -> Self {
+        ProfileError::Io(e)
+    }
+}
+
+impl From<ToxError> for ProfileError {
+    fn from(e: ToxError) -> Self {
+        ProfileError::Tox(e)
+    }
+}
+
+pub struct Profile {
+    path: PathBuf,
+    password: Option<Vec<u8>>,
+    backups: usize,
+}
+
+impl Profile {
+    /// Number of backups kept unless configured otherwise.
+    pub const DEFAULT_BACKUPS: usize = 2;
+
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            password: None,
+            backups: Self::DEFAULT_BACKUPS,
+        }
+    }
+
+    /// Encrypts saves with `password`. An empty password means none.
+    pub fn with_password(mut self, password: Option<&[u8]>) -> Self {
+        self.password = password.filter(|p| !p.is_empty()).map(<[u8]>::to_vec);
+        self
+    }
+
+    /// Keeps `backups` previous versions of the profile. Zero disables
+    /// backups.
+    pub fn with_backups(mut self, backups: usize) -> Self {
+        self.backups = backups;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// Path of the `n`th most recent backup, starting at 1.
+    pub fn backup_path(&self, n: usize) -> PathBuf {
+        self.sibling(&format!(".bak.{}", n))
+    }
+
+    /// Reads and, if needed, decrypts the profile. Returns `None` if it does
+    /// not exist yet. A plaintext profile loads even when a password is set;
+    /// it is encrypted on the next save.
+    pub fn load(&self) -> Result<Option<Vec<u8>>, ProfileError> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if !encryptsave::is_data_encrypted(&data) {
+            return Ok(Some(data));
+        }
+        match &self.password {
+            Some(password) => Ok(Some(encryptsave::decrypt(&data, password)?)),
+            None => Err(ProfileError::PasswordRequired),
+        }
+    }
+
+    /// Loads the profile into `opts`. Returns whether there was one to load;
+    /// if not, the options are left untouched and Tox creates a new identity.
+    pub fn apply(&self, opts: &mut Options) -> Result<bool, ProfileError> {
+        let Some(data) = self.load()? else {
+            return Ok(false);
+        };
+        opts.set_savedata_type(ToxSavedataType::TOX_SAVEDATA_TYPE_TOX_SAVE);
+        opts.set_savedata_data(&data)?;
+        Ok(true)
+    }
+
+    pub fn save(&self, tox: &Tox) -> Result<(), ProfileError> {
+        self.save_data(&tox.savedata())
+    }
+
+    /// Writes `savedata`, encrypted if a password is set, rotating the
+    /// previous version into the backups.
+    pub fn save_data(&self, savedata: &[u8]) -> Result<(), ProfileError> {
+        let data = self.seal(savedata)?;
+        let tmp = self.write_tmp(&data)?;
+        if self.backups > 0 && self.path.exists() {
+            self.rotate_backups()?;
+            // Copied rather than renamed so the profile exists at every
+            // point, even if we crash before the final rename.
+            fs::copy(&self.path, self.backup_path(1))?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        self.sync_dir();
+        Ok(())
+    }
+
+    /// Re-encrypts the profile with `password`, or stores it in plaintext if
+    /// `None`. The backups are removed, since they are still readable with
+    /// the old password.
+    pub fn change_password(&mut self, password: Option<&[u8]>) -> Result<(), ProfileError> {
+        let current = self.load()?;
+        self.password = password.filter(|p| !p.is_empty()).map(<[u8]>::to_vec);
+        if let Some(savedata) = current {
+            let data = self.seal(&savedata)?;
+            let tmp = self.write_tmp(&data)?;
+            fs::rename(&tmp, &self.path)?;
+            self.sync_dir();
+        }
+        for n in 1..=self.backups {
+            match fs::remove_file(self.backup_path(n)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn seal(&self, savedata: &[u8]) -> Result<Vec<u8>, ProfileError> {
+        match &self.password {
+            Some(password) => Ok(encryptsave::encrypt(savedata, password)?),
+            None => Ok(savedata.to_vec()),
+        }
+    }
+
+    fn write_tmp(&self, data: &[u8]) -> Result<PathBuf, ProfileError> {
+        let tmp = self.sibling(".tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        Ok(tmp)
+    }
+
+    /// Shifts `.bak.N` to `.bak.N+1`, dropping the oldest.
+    fn rotate_backups(&self) -> Result<(), ProfileError> {
+        for n in (1..self.backups).rev() {
+            let from = self.backup_path(n);
+            if from.exists() {
+                fs::rename(&from, self.backup_path(n + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the rename durable. Best effort: not every platform can open a
+    /// directory for syncing.
+    fn sync_dir(&self) {
+        let parent = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+}
@@ -0,0 +1,187 @@
+use crate::core::ToxAVHandler;
+use crate::types::*;
+
+/// Call state flags reported for a friend. A finished or failed call has no
+/// other flags set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CallState(pub u32);
+
+impl CallState {
+    pub const ERROR: Self = Self(1);
+    pub const FINISHED: Self = Self(2);
+    /// The friend is sending audio.
+    pub const SENDING_A: Self = Self(4);
+    /// The friend is sending video.
+    pub const SENDING_V: Self = Self(8);
+    /// The friend accepts our audio.
+    pub const ACCEPTING_A: Self = Self(16);
+    /// The friend accepts our video.
+    pub const ACCEPTING_V: Self = Self(32);
+
+    pub fn contains(self, flags: Self) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    /// Whether the call is over, either normally or because of an error.
+    pub fn is_ended(self) -> bool {
+        self.contains(Self::ERROR) || self.contains(Self::FINISHED)
+    }
+}
+
+impl std::ops::BitOr for CallState {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// An owned copy of a ToxAV callback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToxAVEvent {
+    Call {
+        friend: FriendNumber,
+        audio_enabled: bool,
+        video_enabled: bool,
+    },
+    CallState {
+        friend: FriendNumber,
+        state: CallState,
+    },
+    AudioBitRate {
+        friend: FriendNumber,
+        bit_rate: u32,
+    },
+    VideoBitRate {
+        friend: FriendNumber,
+        bit_rate: u32,
+    },
+    AudioFrame {
+        friend: FriendNumber,
+        /// Interleaved samples.
+        pcm: Vec<i16>,
+        channels: u8,
+        sampling_rate: u32,
+    },
+    /// A YUV420 frame. The planes are copied without their row padding, so
+    /// each row of `y` is `width` bytes and each row of `u` and `v` is
+    /// `width / 2` bytes.
+    VideoFrame {
+        friend: FriendNumber,
+        width: u16,
+        height: u16,
+        y: Vec<u8>,
+        u: Vec<u8>,
+        v: Vec<u8>,
+    },
+}
+
+/// A `ToxAVHandler` that queues every callback as a `ToxAVEvent`, to be
+/// taken with `ToxAV::events`.
+#[derive(Debug, Default)]
+pub struct ToxAVEvents {
+    events: Vec<ToxAVEvent>,
+    skip_frames: bool,
+}
+
+impl ToxAVEvents {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops received audio and video frames instead of queueing them, for
+    /// clients that only track call state.
+    pub fn without_frames() -> Self {
+        Self {
+            events: Vec::new(),
+            skip_frames: true,
+        }
+    }
+
+    /// Takes the queued events.
+    pub fn take(&mut self) -> Vec<ToxAVEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
+
+/// Copies `height` rows of `width` bytes out of a plane with row `stride`.
+fn copy_plane(plane: &[u8], width: usize, height: usize, stride: i32) -> Vec<u8> {
+    let stride = (stride.unsigned_abs() as usize).max(width);
+    plane
+        .chunks(stride)
+        .take(height)
+        .flat_map(|row| &row[..width.min(row.len())])
+        .copied()
+        .collect()
+}
+
+impl ToxAVHandler for ToxAVEvents {
+    fn on_call(&mut self, friend: FriendNumber, audio_enabled: bool, video_enabled: bool) {
+        self.events.push(ToxAVEvent::Call {
+            friend,
+            audio_enabled,
+            video_enabled,
+        });
+    }
+
+    fn on_call_state(&mut self, friend: FriendNumber, state: u32) {
+        self.events.push(ToxAVEvent::CallState {
+            friend,
+            state: CallState(state),
+        });
+    }
+
+    fn on_audio_bit_rate(&mut self, friend: FriendNumber, bit_rate: u32) {
+        self.events
+            .push(ToxAVEvent::AudioBitRate { friend, bit_rate });
+    }
+
+    fn on_video_bit_rate(&mut self, friend: FriendNumber, bit_rate: u32) {
+        self.events
+            .push(ToxAVEvent::VideoBitRate { friend, bit_rate });
+    }
+
+    fn on_audio_receive_frame(
+        &mut self,
+        friend: FriendNumber,
+        pcm: &[i16],
+        channels: u8,
+        sampling_rate: u32,
+    ) {
+        if self.skip_frames {
+            return;
+        }
+        self.events.push(ToxAVEvent::AudioFrame {
+            friend,
+            pcm: pcm.to_vec(),
+            channels,
+            sampling_rate,
+        });
+    }
+
+    fn on_video_receive_frame(
+        &mut self,
+        friend: FriendNumber,
+        width: u16,
+        height: u16,
+        y: &[u8],
+        u: &[u8],
+        v: &[u8],
+        ystride: i32,
+        ustride: i32,
+        vstride: i32,
+    ) {
+        if self.skip_frames {
+            return;
+        }
+        let (w, h) = (width as usize, height as usize);
+        self.events.push(ToxAVEvent::VideoFrame {
+            friend,
+            width,
+            height,
+            y: copy_plane(y, w, h, ystride),
+            u: copy_plane(u, w / 2, h / 2, ustride),
+            v: copy_plane(v, w / 2, h / 2, vstride),
+        });
+    }
+}
@@ -1,8 +1,11 @@
 use crate::core;
 use crate::types::*;
 
+mod events;
+
 // Re-export handlers from core
 pub use crate::core::{ToxAVConferenceHandler, ToxAVHandler};
+pub use events::{CallState, ToxAVEvent, ToxAVEvents};
 
 pub struct ToxAV<'a, H: ToxAVHandler> {
     pub(crate) inner: core::ToxAV<'a, H>,
@@ -19,6 +22,14 @@ impl<'a, H: ToxAVHandler> ToxAV<'a, H> {
         })
     }
 
+    pub fn handler(&self) -> &H {
+        &self.inner.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.inner.handler
+    }
+
     pub fn iterate(&mut self) {
         self.inner.iterate();
     }
@@ -75,6 +86,21 @@ impl<'a, H: ToxAVHandler> ToxAV<'a, H> {
             .map_err(ToxError::AvCallControl)
     }
 
+    /// Ends a call, or rejects an incoming one.
+    pub fn hangup(&self, friend_number: FriendNumber) -> Result<()> {
+        self.call_control(friend_number, ToxavCallControl::TOXAV_CALL_CONTROL_CANCEL)
+    }
+
+    /// Stops or resumes sending audio to the friend.
+    pub fn set_audio_muted(&self, friend_number: FriendNumber, muted: bool) -> Result<()> {
+        let control = if muted {
+            ToxavCallControl::TOXAV_CALL_CONTROL_MUTE_AUDIO
+        } else {
+            ToxavCallControl::TOXAV_CALL_CONTROL_UNMUTE_AUDIO
+        };
+        self.call_control(friend_number, control)
+    }
+
     pub fn audio_set_bit_rate(&self, friend_number: FriendNumber, bit_rate: u32) -> Result<()> {
         self.inner
             .audio_set_bit_rate(friend_number, bit_rate)
@@ -115,3 +141,11 @@ impl<'a, H: ToxAVHandler> ToxAV<'a, H> {
             .map_err(ToxError::AvSendFrame)
     }
 }
+
+impl<'a> ToxAV<'a, ToxAVEvents> {
+    /// Runs one iteration and returns the events it produced.
+    pub fn events(&mut self) -> Vec<ToxAVEvent> {
+        self.iterate();
+        self.inner.handler.take()
+    }
+}
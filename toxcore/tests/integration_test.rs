@@ -1,3 +1,5 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
 use toxcore::tox::*;
@@ -38,9 +40,11 @@ fn integration_suite() {
     suite::group::subtest_group_management(&mut harness);
     suite::group_av::subtest_group_av(&mut harness);
     suite::av::subtest_toxav_call(&mut harness);
+    suite::av::subtest_toxav_events(&mut harness);
     suite::dht::subtest_dht_nodes(&mut harness);
     suite::persistence::subtest_persistence();
     suite::encryptsave::subtest_encryptsave();
+    suite::profile::subtest_profile();
 }
 
 // Standalone unit tests (fast)
@@ -74,6 +78,172 @@ fn test_toxav_lifecycle() {
     let _av = ToxAV::new(&tox, DummyHandler).expect("Failed to create ToxAV instance");
 }
 
+#[test]
+fn test_toxav_event_queue() {
+    let mut events = ToxAVEvents::new();
+    events.on_call_state(FriendNumber(3), 2 | 16);
+    // 2x2 frame with a padded luma stride of 4.
+    events.on_video_receive_frame(
+        FriendNumber(3),
+        2,
+        2,
+        &[1, 2, 0, 0, 3, 4, 0, 0],
+        &[5],
+        &[6],
+        4,
+        1,
+        1,
+    );
+
+    let queued = events.take();
+    assert!(events.take().is_empty());
+    match &queued[0] {
+        ToxAVEvent::CallState { friend, state } => {
+            assert_eq!(*friend, FriendNumber(3));
+            assert_eq!(*state, CallState::FINISHED | CallState::ACCEPTING_A);
+            assert!(state.is_ended() && state.contains(CallState::ACCEPTING_A));
+            assert!(!state.contains(CallState::ERROR) && !state.contains(CallState::SENDING_A));
+        }
+        e => panic!("Unexpected event {:?}", e),
+    }
+    assert_eq!(
+        queued[1],
+        ToxAVEvent::VideoFrame {
+            friend: FriendNumber(3),
+            width: 2,
+            height: 2,
+            y: vec![1, 2, 3, 4],
+            u: vec![5],
+            v: vec![6],
+        }
+    );
+
+    let mut events = ToxAVEvents::without_frames();
+    events.on_audio_receive_frame(FriendNumber(3), &[0; 960], 1, 48000);
+    assert!(events.take().is_empty());
+}
+
+#[test]
+fn test_owned_event_queue() {
+    let mut events = OwnedEvents::new();
+    events.on_friend_message(
+        FriendNumber(1),
+        MessageType::TOX_MESSAGE_TYPE_NORMAL,
+        b"hello",
+    );
+    events.on_group_peer_join(GroupNumber(2), GroupPeerNumber(7));
+
+    let queued = events.take();
+    assert!(events.take().is_empty());
+    assert_eq!(
+        queued,
+        vec![
+            OwnedEvent::FriendMessage {
+                friend: FriendNumber(1),
+                message_type: MessageType::TOX_MESSAGE_TYPE_NORMAL,
+                message: b"hello".to_vec(),
+            },
+            OwnedEvent::GroupPeerJoin {
+                group: GroupNumber(2),
+                peer: GroupPeerNumber(7),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_friend_manager() {
+    let mut opts = Options::new().unwrap();
+    opts.set_local_discovery_enabled(false);
+    let tox = Tox::new(opts).expect("Failed to create Tox instance");
+
+    let saves = Arc::new(AtomicUsize::new(0));
+    let counter = saves.clone();
+    let mut friends = FriendManager::new().with_save_hook(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let pk = PublicKey([7u8; 32]);
+    let number = friends.add_norequest(&tox, &pk).unwrap();
+    assert_eq!(saves.load(Ordering::SeqCst), 1);
+    // Friends added through the manager are already known.
+    assert!(friends.sync(&tox).is_empty());
+
+    friends.set_alias(pk, Some("Alice".to_string()));
+    assert_eq!(friends.find_by_alias(&tox, "alice"), Some(number));
+    let list = friends.list(&tox);
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].public_key, pk);
+    assert_eq!(list[0].display_name(), "Alice");
+
+    let change = friends
+        .on_connection_status(&tox, number, ToxConnection::TOX_CONNECTION_UDP)
+        .unwrap();
+    assert!(change.came_online());
+    assert!(
+        friends
+            .on_connection_status(&tox, number, ToxConnection::TOX_CONNECTION_UDP)
+            .is_none()
+    );
+
+    friends.remove(&tox, &pk).unwrap();
+    assert_eq!(saves.load(Ordering::SeqCst), 2);
+    assert!(friends.alias(&pk).is_none());
+    assert!(friends.list(&tox).is_empty());
+
+    // Changes made directly on the Tox instance show up on the next sync.
+    let number = tox.friend_add_norequest(&pk).unwrap().get_number();
+    assert_eq!(
+        friends.sync(&tox),
+        vec![FriendChange::Added {
+            number,
+            public_key: pk,
+        }]
+    );
+}
+
+#[test]
+fn test_chat_unification() {
+    let mut opts = Options::new().unwrap();
+    opts.set_local_discovery_enabled(false);
+    let tox = Tox::new(opts).expect("Failed to create Tox instance");
+    let conference = tox.conference_new().expect("Failed to create conference");
+    conference.set_title(b"Conference").unwrap();
+    let group = tox
+        .group_new(
+            ToxGroupPrivacyState::TOX_GROUP_PRIVACY_STATE_PRIVATE,
+            b"Group",
+            b"PeerName",
+        )
+        .expect("Failed to create group");
+
+    let chats = tox.chats();
+    let numbers: Vec<ChatNumber> = chats.iter().map(|c| c.number()).collect();
+    assert_eq!(
+        numbers,
+        vec![
+            ChatNumber::from(conference.number()),
+            ChatNumber::from(group.get_number()),
+        ]
+    );
+    assert_eq!(chats[0].title().unwrap(), b"Conference");
+    assert_eq!(chats[1].title().unwrap(), b"Group");
+
+    // Conferences list their peers; we are the only one.
+    let peers = chats[0].peers().unwrap().unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(
+        chats[0].peer_public_key(peers[0]).unwrap(),
+        tox.public_key()
+    );
+    assert!(chats[1].peers().unwrap().is_none());
+    assert!(chats[1].peer_name(peers[0]).is_err());
+
+    let chat = tox.chat(ChatNumber::from(conference.number()));
+    chat.leave(None).unwrap();
+    assert_eq!(tox.chats().len(), 1);
+}
+
 #[test]
 fn test_group_lifecycle() {
     let mut opts = Options::new().unwrap();
@@ -107,3 +107,77 @@ pub fn subtest_toxav_call(harness: &mut TestHarness) {
     assert!(received_audio.load(Ordering::SeqCst), "Audio not received");
     assert!(received_video.load(Ordering::SeqCst), "Video not received");
 }
+
+pub fn subtest_toxav_events(harness: &mut TestHarness) {
+    println!("Running subtest_toxav_events...");
+
+    struct IdleHandler;
+    impl ToxHandler for IdleHandler {}
+    let mut core_handler = IdleHandler;
+
+    let mut av0 = ToxAV::new(&harness.toxes[0].tox, ToxAVEvents::new()).unwrap();
+    let mut av1 = ToxAV::new(&harness.toxes[1].tox, ToxAVEvents::without_frames()).unwrap();
+
+    let pk0 = harness.toxes[0].tox.public_key();
+    let pk1 = harness.toxes[1].tox.public_key();
+    let f0 = harness.toxes[0]
+        .tox
+        .lookup_friend(&pk1)
+        .unwrap()
+        .get_number();
+    let f1 = harness.toxes[1]
+        .tox
+        .lookup_friend(&pk0)
+        .unwrap()
+        .get_number();
+
+    av0.call(f0, 48, 0).expect("Call failed");
+
+    let mut events0 = Vec::new();
+    let mut events1 = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) && events1.is_empty() {
+        events0.extend(av0.events());
+        events1.extend(av1.events());
+        harness.iterate(&mut core_handler);
+    }
+    assert_eq!(
+        events1,
+        vec![ToxAVEvent::Call {
+            friend: f1,
+            audio_enabled: true,
+            video_enabled: false,
+        }]
+    );
+
+    av1.answer(f1, 48, 0).expect("Answer failed");
+    let accepted = |events: &[ToxAVEvent]| {
+        events.iter().any(|e| {
+            matches!(e, ToxAVEvent::CallState { friend, state }
+                if *friend == f0 && state.contains(CallState::ACCEPTING_A) && !state.is_ended())
+        })
+    };
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) && !accepted(&events0) {
+        events0.extend(av0.events());
+        av1.events();
+        harness.iterate(&mut core_handler);
+    }
+    assert!(accepted(&events0), "Caller did not see the call accepted");
+
+    av0.hangup(f0).expect("Hangup failed");
+    let finished = |events: &[ToxAVEvent]| {
+        events.iter().any(|e| {
+            matches!(e, ToxAVEvent::CallState { friend, state }
+                if *friend == f1 && state.contains(CallState::FINISHED))
+        })
+    };
+    let mut events1 = Vec::new();
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(10) && !finished(&events1) {
+        av0.events();
+        events1.extend(av1.events());
+        harness.iterate(&mut core_handler);
+    }
+    assert!(finished(&events1), "Callee did not see the call end");
+}
@@ -9,4 +9,5 @@ pub mod group;
 pub mod group_av;
 pub mod message;
 pub mod persistence;
+pub mod profile;
 pub mod setup;
@@ -0,0 +1,61 @@
+use std::fs;
+use toxcore::tox::*;
+
+pub fn subtest_profile() {
+    println!("Running subtest_profile...");
+    let dir = std::env::temp_dir().join(format!("toxcore-profile-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("profile.tox");
+
+    // 1. A missing profile leaves the options alone
+    let mut profile = Profile::new(&path)
+        .with_password(Some(b"hunter2"))
+        .with_backups(2);
+    assert!(profile.load().unwrap().is_none());
+    let mut opts = Options::new().unwrap();
+    opts.set_local_discovery_enabled(false);
+    assert!(!profile.apply(&mut opts).unwrap());
+    let tox = Tox::new(opts).unwrap();
+    tox.set_name(b"ProfileUser").unwrap();
+    let pk = tox.public_key();
+
+    // 2. Saves are encrypted and need the password to load
+    profile.save(&tox).unwrap();
+    assert!(encryptsave::is_data_encrypted(&fs::read(&path).unwrap()));
+    assert!(matches!(
+        Profile::new(&path).load(),
+        Err(ProfileError::PasswordRequired)
+    ));
+
+    // 3. Previous versions rotate through the backups
+    assert!(!profile.backup_path(1).exists());
+    profile.save(&tox).unwrap();
+    assert!(profile.backup_path(1).exists());
+    profile.save(&tox).unwrap();
+    profile.save(&tox).unwrap();
+    assert!(profile.backup_path(2).exists());
+    assert!(!profile.backup_path(3).exists());
+    drop(tox);
+
+    // 4. Changing the password drops the backups under the old one
+    profile.change_password(Some(b"correct horse")).unwrap();
+    assert!(!profile.backup_path(1).exists());
+    assert!(matches!(
+        Profile::new(&path).with_password(Some(b"hunter2")).load(),
+        Err(ProfileError::Tox(_))
+    ));
+    let mut opts = Options::new().unwrap();
+    opts.set_local_discovery_enabled(false);
+    assert!(profile.apply(&mut opts).unwrap());
+    let tox = Tox::new(opts).unwrap();
+    assert_eq!(tox.public_key(), pk);
+    assert_eq!(tox.name(), b"ProfileUser");
+
+    // 5. Removing the password stores it in plaintext
+    profile.change_password(None).unwrap();
+    assert!(!profile.has_password());
+    assert_eq!(fs::read(&path).unwrap(), tox.savedata());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,4 +1,4 @@
-use crate::model::{MessageContent, MessageStatus, WindowId};
+use crate::model::{CallStatus, MessageContent, MessageStatus, WindowId};
 use crate::msg::{Cmd, IOAction, ToxAction};
 use toxcore::tox::{FriendNumber, ToxConnection};
 use toxcore::types::MessageType;
@@ -217,6 +217,12 @@ pub const COMMANDS: &[CommandDef] = &[
                 ));
                 return vec![];
             }
+            if let WindowId::MerkleTox(_) = window_id {
+                model.add_error_message(MessageContent::Text(
+                    "Merkle-Tox conversations have no action messages.".to_owned(),
+                ));
+                return vec![];
+            }
 
             let (internal_id, msg_obj) = model.add_outgoing_message(
                 window_id,
@@ -262,6 +268,166 @@ pub const COMMANDS: &[CommandDef] = &[
         },
         complete: None,
     },
+    CommandDef {
+        name: "react",
+        args: (None, "<n> <emoji>"),
+        desc: (
+            Some("React to a Merkle-Tox message"),
+            "React to the n-th most recent message of a Merkle-Tox conversation",
+        ),
+        exec: |model, args| {
+            let window_id = model.active_window_id();
+            let WindowId::MerkleTox(id) = window_id else {
+                model.add_error_message(MessageContent::Text(
+                    "Reactions are only supported in Merkle-Tox conversations.".to_owned(),
+                ));
+                return vec![];
+            };
+            if args.len() < 2 {
+                model.add_error_message(MessageContent::Text(
+                    "Usage: /react <n> <emoji>".to_owned(),
+                ));
+                return vec![];
+            }
+            match args[0]
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| model.merkle_message_hash(id, n))
+            {
+                Some(hash) => vec![Cmd::Tox(ToxAction::MerkleReact(
+                    id,
+                    hash,
+                    args[1..].join(" "),
+                ))],
+                None => {
+                    model.add_error_message(MessageContent::Text(format!(
+                        "No message {} in this conversation.",
+                        args[0]
+                    )));
+                    vec![]
+                }
+            }
+        },
+        complete: None,
+    },
+    CommandDef {
+        name: "redact",
+        args: (None, "<n>"),
+        desc: (
+            Some("Redact a Merkle-Tox message"),
+            "Redact the n-th most recent message of a Merkle-Tox conversation",
+        ),
+        exec: |model, args| {
+            let window_id = model.active_window_id();
+            let WindowId::MerkleTox(id) = window_id else {
+                model.add_error_message(MessageContent::Text(
+                    "Redactions are only supported in Merkle-Tox conversations.".to_owned(),
+                ));
+                return vec![];
+            };
+            if args.len() != 1 {
+                model.add_error_message(MessageContent::Text("Usage: /redact <n>".to_owned()));
+                return vec![];
+            }
+            match args[0]
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| model.merkle_message_hash(id, n))
+            {
+                Some(hash) => vec![Cmd::Tox(ToxAction::MerkleRedact(id, hash))],
+                None => {
+                    model.add_error_message(MessageContent::Text(format!(
+                        "No message {} in this conversation.",
+                        args[0]
+                    )));
+                    vec![]
+                }
+            }
+        },
+        complete: None,
+    },
+    CommandDef {
+        name: "call",
+        args: (None, "[hangup | mute | unmute]"),
+        desc: (
+            Some("Audio call the current friend"),
+            "Call the friend of the current window, answer their call, or control it",
+        ),
+        exec: |model, args| {
+            let WindowId::Friend(pk) = model.active_window_id() else {
+                model.add_error_message(MessageContent::Text(
+                    "Calls are only supported in friend windows.".to_owned(),
+                ));
+                return vec![];
+            };
+            let call = model.session.calls.get(&pk).copied();
+            match (args.first().copied(), call) {
+                (None, None) => {
+                    model.session.calls.insert(pk, CallStatus::Ringing);
+                    model.add_status_message(MessageContent::Text("Calling...".to_owned()));
+                    vec![Cmd::Tox(ToxAction::StartCall(pk))]
+                }
+                (None, Some(CallStatus::Incoming)) => {
+                    model
+                        .session
+                        .calls
+                        .insert(pk, CallStatus::Active { muted: false });
+                    model.add_status_message(MessageContent::Text("Call answered.".to_owned()));
+                    vec![Cmd::Tox(ToxAction::AnswerCall(pk))]
+                }
+                (None, Some(_)) => {
+                    model.add_error_message(MessageContent::Text(
+                        "Already in a call with this friend.".to_owned(),
+                    ));
+                    vec![]
+                }
+                (Some("hangup"), Some(_)) => {
+                    model.session.calls.remove(&pk);
+                    model.add_status_message(MessageContent::Text("Call ended.".to_owned()));
+                    vec![Cmd::Tox(ToxAction::HangUp(pk))]
+                }
+                (Some(sub @ ("mute" | "unmute")), Some(CallStatus::Active { .. })) => {
+                    let muted = sub == "mute";
+                    model.session.calls.insert(pk, CallStatus::Active { muted });
+                    let text = if muted {
+                        "Microphone muted."
+                    } else {
+                        "Microphone unmuted."
+                    };
+                    model.add_status_message(MessageContent::Text(text.to_owned()));
+                    vec![Cmd::Tox(ToxAction::SetCallMuted(pk, muted))]
+                }
+                (Some("hangup" | "mute" | "unmute"), _) => {
+                    model.add_error_message(MessageContent::Text(
+                        "No active call with this friend.".to_owned(),
+                    ));
+                    vec![]
+                }
+                (Some(_), _) => {
+                    model.add_error_message(MessageContent::Text(
+                        "Usage: /call [hangup | mute | unmute]".to_owned(),
+                    ));
+                    vec![]
+                }
+            }
+        },
+        complete: Some(|_model, args| {
+            if args.len() <= 1 {
+                let prefix = args.first().unwrap_or(&"");
+                let subs = [
+                    ("hangup", "End or reject the call"),
+                    ("mute", "Stop sending audio"),
+                    ("unmute", "Resume sending audio"),
+                ];
+                return subs
+                    .iter()
+                    .filter(|(s, _)| s.starts_with(prefix))
+                    .map(|(s, d)| (s.to_string(), d.to_string()))
+                    .collect();
+            }
+            vec![]
+        }),
+    },
     CommandDef {
         name: "friends",
         args: (None, ""),
@@ -1,9 +1,10 @@
-use crate::model::{LogFilters, MessageContent, TransferStatus, WindowId};
+use crate::config::AutoAcceptRule;
+use crate::model::{LogFilters, MessageContent, Model, WindowId};
 use crate::msg::{Cmd, IOAction, ToxAction};
 use std::fs::metadata;
 use std::path::PathBuf;
 use toxcore::tox::FriendNumber;
-use toxcore::types::{FileId, ToxFileControl, ToxLogLevel};
+use toxcore::types::{FileId, PublicKey, ToxFileControl, ToxLogLevel};
 
 use super::CommandDef;
 
@@ -55,12 +56,226 @@ fn complete_path(prefix: &str) -> Vec<String> {
     }
 }
 
+/// Accepts an incoming file, saving it as `filename` or under the offered
+/// name. An existing partial file is resumed.
+pub fn accept_file(
+    model: &mut Model,
+    pk: PublicKey,
+    file_id: FileId,
+    filename: Option<String>,
+) -> Vec<Cmd> {
+    let (default_name, size) = model
+        .domain
+        .file_transfers
+        .get(&file_id)
+        .map(|p| (p.filename.clone(), p.total_size))
+        .unwrap_or_else(|| (format!("recv_{}", file_id), 0));
+    let filename = filename.unwrap_or(default_name);
+
+    model.add_status_message(MessageContent::Text(format!(
+        "Accepting file {} as {}",
+        file_id, filename
+    )));
+    model
+        .domain
+        .file_transfers
+        .control(&file_id, ToxFileControl::TOX_FILE_CONTROL_RESUME);
+
+    let mut cmds = Vec::new();
+
+    // Check if file exists to determine resume offset
+    let start_offset = if let Ok(m) = metadata(&filename) {
+        m.len()
+    } else {
+        0
+    };
+
+    cmds.push(Cmd::IO(IOAction::OpenFileForReceiving(
+        pk, file_id, filename, size,
+    )));
+
+    if start_offset > 0 {
+        model.add_status_message(MessageContent::Text(format!(
+            "Resuming download from offset {}",
+            start_offset
+        )));
+        cmds.push(Cmd::Tox(ToxAction::FileSeek(pk, file_id, start_offset)));
+    }
+
+    cmds.push(Cmd::Tox(ToxAction::FileControl(
+        pk,
+        file_id,
+        ToxFileControl::TOX_FILE_CONTROL_RESUME,
+    )));
+
+    cmds
+}
+
+fn show_transfer_stats(model: &mut Model) {
+    let now = model.time_provider.now();
+    let mut transfers: Vec<_> = model
+        .domain
+        .file_transfers
+        .iter()
+        .filter_map(|(id, p)| {
+            let t = model.domain.file_transfers.throughput(id, now)?;
+            Some((p.filename.clone(), p.transferred, p.total_size, t))
+        })
+        .collect();
+    if transfers.is_empty() {
+        model.add_info_message(MessageContent::Text("No active transfers.".to_owned()));
+        return;
+    }
+    transfers.sort_by(|a, b| a.0.cmp(&b.0));
+    let list = transfers
+        .into_iter()
+        .map(|(filename, transferred, total, t)| {
+            format!(
+                "{}: {}/{}, now {}, avg {}, peak {} ({}s)",
+                filename,
+                crate::utils::format_size(transferred),
+                crate::utils::format_size(total),
+                crate::utils::format_speed(t.current),
+                crate::utils::format_speed(t.average),
+                crate::utils::format_speed(t.peak),
+                t.elapsed.as_secs(),
+            )
+        })
+        .collect();
+    model.add_info_message(MessageContent::List(list));
+}
+
+fn describe_rule(model: &Model, rule: &AutoAcceptRule) -> String {
+    let friend = match &rule.friend {
+        Some(pk) => model
+            .domain
+            .friends
+            .get(pk)
+            .map(|f| f.name.clone())
+            .unwrap_or_else(|| pk.to_string()),
+        None => "anyone".to_owned(),
+    };
+    let extensions = if rule.extensions.is_empty() {
+        "any type".to_owned()
+    } else {
+        rule.extensions.join(",")
+    };
+    match rule.max_size {
+        Some(max) => format!(
+            "from {}: {} up to {}",
+            friend,
+            extensions,
+            crate::utils::format_size(max)
+        ),
+        None => format!("from {}: {}", friend, extensions),
+    }
+}
+
+fn auto_accept_command(model: &mut Model, args: &[&str]) -> Vec<Cmd> {
+    match args.first().copied() {
+        Some("add") if args.len() >= 2 => {
+            let friend = if args[1] == "*" {
+                None
+            } else {
+                let pk = args[1]
+                    .parse::<u32>()
+                    .ok()
+                    .and_then(|fid| model.session.friend_numbers.get(&FriendNumber(fid)));
+                match pk {
+                    Some(pk) => Some(*pk),
+                    None => {
+                        model.add_error_message(MessageContent::Text(format!(
+                            "Unknown friend: {}",
+                            args[1]
+                        )));
+                        return vec![];
+                    }
+                }
+            };
+            let extensions = match args.get(2) {
+                None | Some(&"*") => Vec::new(),
+                Some(list) => list
+                    .split(',')
+                    .map(|e| e.trim_start_matches('.').to_lowercase())
+                    .filter(|e| !e.is_empty())
+                    .collect(),
+            };
+            let max_size = match args.get(3).map(|s| s.parse::<u64>()) {
+                None => None,
+                Some(Ok(max)) => Some(max),
+                Some(Err(_)) => {
+                    model.add_error_message(MessageContent::Text(format!(
+                        "Invalid size: {}",
+                        args[3]
+                    )));
+                    return vec![];
+                }
+            };
+            let rule = AutoAcceptRule {
+                friend,
+                extensions,
+                max_size,
+            };
+            let description = describe_rule(model, &rule);
+            model.config.auto_accept.push(rule.clone());
+            model.saved_config.auto_accept.push(rule);
+            model.add_status_message(MessageContent::Text(format!(
+                "Auto-accepting files {}",
+                description
+            )));
+            vec![Cmd::IO(IOAction::SaveConfig(None))]
+        }
+        Some("remove" | "del" | "rm") if args.len() >= 2 => {
+            let index = args[1]
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n >= 1 && n <= model.config.auto_accept.len());
+            let Some(n) = index else {
+                model.add_error_message(MessageContent::Text(format!(
+                    "No auto-accept rule {}",
+                    args[1]
+                )));
+                return vec![];
+            };
+            let rule = model.config.auto_accept.remove(n - 1);
+            model.saved_config.auto_accept.retain(|r| r != &rule);
+            model.add_status_message(MessageContent::Text(format!(
+                "Removed auto-accept rule {}",
+                n
+            )));
+            vec![Cmd::IO(IOAction::SaveConfig(None))]
+        }
+        Some("list") | None => {
+            if model.config.auto_accept.is_empty() {
+                model.add_info_message(MessageContent::Text("No auto-accept rules.".to_owned()));
+            } else {
+                let list = model
+                    .config
+                    .auto_accept
+                    .iter()
+                    .enumerate()
+                    .map(|(i, rule)| format!("{}. {}", i + 1, describe_rule(model, rule)))
+                    .collect();
+                model.add_info_message(MessageContent::List(list));
+            }
+            vec![]
+        }
+        _ => {
+            model.add_error_message(MessageContent::Text(
+                "Usage: /file autoaccept add <friend_id|*> [ext,...|*] [max_bytes] | remove <n> | list"
+                    .to_owned(),
+            ));
+            vec![]
+        }
+    }
+}
+
 pub const COMMANDS: &[CommandDef] = &[
     CommandDef {
         name: "file",
         args: (
             Some("send <fid> <path> | accept ..."),
-            "send <fid> <path> | accept <fid> <file_id> | pause <fid> <file_id> | resume <fid> <file_id> | cancel <fid> <file_id> | list",
+            "send <fid> <path> | accept <fid> <file_id> | pause <fid> <file_id> | resume <fid> <file_id> | cancel <fid> <file_id> | list | stats | autoaccept ...",
         ),
         desc: (None, "Manage file transfers"),
         exec: |model, args| {
@@ -74,6 +289,13 @@ pub const COMMANDS: &[CommandDef] = &[
                 }
                 return vec![];
             }
+            if !args.is_empty() && args[0] == "stats" {
+                show_transfer_stats(model);
+                return vec![];
+            }
+            if !args.is_empty() && args[0] == "autoaccept" {
+                return auto_accept_command(model, &args[1..]);
+            }
             if args.len() >= 3 {
                 let sub = args[0];
                 if let Ok(fid) = args[1].parse::<u32>() {
@@ -176,93 +398,18 @@ pub const COMMANDS: &[CommandDef] = &[
                                     let file_id = FileId(arr);
 
                                     if sub == "accept" {
-                                        let (mut filename, size) = model
-                                            .domain
-                                            .file_transfers
-                                            .get(&file_id)
-                                            .map(|p| (p.filename.clone(), p.total_size))
-                                            .unwrap_or_else(|| {
-                                                (format!("recv_{}", file_id_str), 0)
-                                            });
-
-                                        if args.len() >= 4 {
-                                            filename = args[3..].join(" ");
-                                        }
-
-                                        model.add_status_message(MessageContent::Text(format!(
-                                            "Accepting file {} as {}",
-                                            file_id, filename
-                                        )));
-
-                                        if let Some(p) =
-                                            model.domain.file_transfers.get_mut(&file_id)
-                                        {
-                                            p.status = TransferStatus::Active;
-                                        }
-
-                                        let mut cmds = Vec::new();
-
-                                        // Check if file exists to determine resume offset
-                                        let start_offset = if let Ok(m) = metadata(&filename) {
-                                            m.len()
-                                        } else {
-                                            0
-                                        };
-
-                                        cmds.push(Cmd::IO(IOAction::OpenFileForReceiving(
-                                            pk, file_id, filename, size,
-                                        )));
-
-                                        if start_offset > 0 {
-                                            model.add_status_message(MessageContent::Text(
-                                                format!(
-                                                    "Resuming download from offset {}",
-                                                    start_offset
-                                                ),
-                                            ));
-                                            cmds.push(Cmd::Tox(ToxAction::FileSeek(
-                                                pk,
-                                                file_id,
-                                                start_offset,
-                                            )));
-                                        }
-
-                                        cmds.push(Cmd::Tox(ToxAction::FileControl(
-                                            pk,
-                                            file_id,
-                                            ToxFileControl::TOX_FILE_CONTROL_RESUME,
-                                        )));
-
-                                        return cmds;
+                                        let filename =
+                                            (args.len() >= 4).then(|| args[3..].join(" "));
+                                        return accept_file(model, pk, file_id, filename);
                                     }
 
                                     let control = match sub {
-                                        "pause" => {
-                                            if let Some(p) =
-                                                model.domain.file_transfers.get_mut(&file_id)
-                                            {
-                                                p.status = TransferStatus::Paused;
-                                            }
-                                            ToxFileControl::TOX_FILE_CONTROL_PAUSE
-                                        }
-                                        "resume" => {
-                                            if let Some(p) =
-                                                model.domain.file_transfers.get_mut(&file_id)
-                                            {
-                                                p.status = TransferStatus::Active;
-                                            }
-                                            ToxFileControl::TOX_FILE_CONTROL_RESUME
-                                        }
-                                        "cancel" => {
-                                            if let Some(p) =
-                                                model.domain.file_transfers.get_mut(&file_id)
-                                            {
-                                                p.status = TransferStatus::Canceled;
-                                            }
-                                            ToxFileControl::TOX_FILE_CONTROL_CANCEL
-                                        }
+                                        "pause" => ToxFileControl::TOX_FILE_CONTROL_PAUSE,
+                                        "resume" => ToxFileControl::TOX_FILE_CONTROL_RESUME,
+                                        "cancel" => ToxFileControl::TOX_FILE_CONTROL_CANCEL,
                                         _ => unreachable!(),
                                     };
+                                    model.domain.file_transfers.control(&file_id, control);
                                     return vec![Cmd::Tox(ToxAction::FileControl(
                                         pk, file_id, control,
                                     ))];
@@ -286,6 +433,9 @@ pub const COMMANDS: &[CommandDef] = &[
                 "       /file resume <friend_id> <file_id>".to_owned(),
                 "       /file cancel <friend_id> <file_id>".to_owned(),
                 "       /file list".to_owned(),
+                "       /file stats".to_owned(),
+                "       /file autoaccept add <friend_id|*> [ext,...|*] [max_bytes]".to_owned(),
+                "       /file autoaccept remove <n> | list".to_owned(),
             ]));
             vec![]
         },
@@ -299,6 +449,8 @@ pub const COMMANDS: &[CommandDef] = &[
                     ("resume", "Resume a transfer"),
                     ("cancel", "Cancel a transfer"),
                     ("list", "List active transfers"),
+                    ("stats", "Show transfer throughput"),
+                    ("autoaccept", "Manage auto-accept rules"),
                 ];
                 return subs
                     .iter()
@@ -306,6 +458,21 @@ pub const COMMANDS: &[CommandDef] = &[
                     .map(|(s, d)| (s.to_string(), d.to_string()))
                     .collect();
             }
+            if args[0] == "autoaccept" {
+                if args.len() == 2 {
+                    let subs = [
+                        ("add", "Add a rule"),
+                        ("remove", "Remove a rule"),
+                        ("list", "List rules"),
+                    ];
+                    return subs
+                        .iter()
+                        .filter(|(s, _)| s.starts_with(args[1]))
+                        .map(|(s, d)| (s.to_string(), d.to_string()))
+                        .collect();
+                }
+                return vec![];
+            }
             if args.len() == 2 {
                 let prefix = args[1];
                 let mut ids: Vec<_> = model
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::io::Write;
 use std::{fs, io};
+use toxcore::types::PublicKey;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub enum SystemMessageType {
@@ -9,6 +10,34 @@ pub enum SystemMessageType {
     NickChange,
 }
 
+/// Incoming files matching a rule are accepted without asking. Unset
+/// criteria match anything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AutoAcceptRule {
+    pub friend: Option<PublicKey>,
+    /// File extensions without the dot, compared case-insensitively.
+    pub extensions: Vec<String>,
+    pub max_size: Option<u64>,
+}
+
+impl AutoAcceptRule {
+    pub fn matches(&self, friend: &PublicKey, filename: &str, size: u64) -> bool {
+        if self.friend.is_some_and(|pk| &pk != friend) {
+            return false;
+        }
+        if self.max_size.is_some_and(|max| size > max) {
+            return false;
+        }
+        if self.extensions.is_empty() {
+            return true;
+        }
+        let Some((_, ext)) = filename.rsplit_once('.') else {
+            return false;
+        };
+        self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     // Network Settings
@@ -22,6 +51,8 @@ pub struct Config {
     pub enabled_system_messages: Vec<SystemMessageType>,
     pub downloads_directory: Option<String>,
     pub timezone: Option<String>,
+    #[serde(default)]
+    pub auto_accept: Vec<AutoAcceptRule>,
 }
 
 impl Default for Config {
@@ -41,6 +72,7 @@ impl Default for Config {
             ],
             downloads_directory: None,
             timezone: None,
+            auto_accept: Vec::new(),
         }
     }
 }
@@ -6,6 +6,7 @@ pub mod config;
 pub mod emojis;
 pub mod export;
 pub mod io;
+pub mod merkle;
 pub mod model;
 pub mod msg;
 pub mod screenshot;
@@ -11,7 +11,7 @@ use std::time::{Duration, Instant};
 
 use toxxi::model::{self, ConsoleMessageType};
 use toxxi::msg::{AppCmd, Cmd, IOEvent, Msg, SystemEvent, ToxEvent};
-use toxxi::script::{ScriptController, ScriptRequest, ScriptResponse};
+use toxxi::script::{ScriptController, ScriptRequest, ScriptResponse, answer_query};
 use toxxi::terminal::TerminalHandle;
 use toxxi::ui::draw;
 use toxxi::update::{handle_enter, update};
@@ -299,6 +299,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 && let Some(ctrl) = &mut script_ctrl
             {
                 ctrl.pending_req = Some(req.clone());
+                if let ScriptRequest::Query(query) = req {
+                    ctrl.pending_req = None;
+                    let _ = ctrl.tx_script_res.send(answer_query(&model, query));
+                }
                 if let ScriptRequest::Command(cmd_str) = req {
                     let cmds = handle_enter(&mut model, cmd_str);
                     let res = ctx.execute(cmds, &mut model).await;
@@ -0,0 +1,268 @@
+//! Merkle-Tox conversations, driven from the Tox worker thread.
+//!
+//! The session shares the worker's Tox instance with a sans-IO node through
+//! `ToxMerkleBridge`, the same way groupbot does. Node events are queued and
+//! applied to one `MerkleToxClient` per conversation; after each batch the
+//! conversations that changed are sent to the UI as snapshots.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, mpsc};
+use std::time::Instant;
+
+use merkle_tox_client::MerkleToxClient;
+use merkle_tox_client::state::ChatState;
+use merkle_tox_core::dag::{
+    Content, ConversationId, EmojiSource, LogicalIdentityPk, NodeHash, PhysicalDeviceSk,
+};
+use merkle_tox_core::error::MerkleToxResult;
+use merkle_tox_core::node::MerkleToxNode;
+use merkle_tox_core::vfs::StdFileSystem;
+use merkle_tox_core::{NodeEvent, NodeEventHandler, Transport};
+use merkle_tox_fs::FsStore;
+use merkle_tox_tox::{ToxMerkleBridge, ToxTransport};
+use parking_lot::ReentrantMutex;
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+use toxcore::tox::Tox;
+use toxcore::tox::events::Event;
+
+use crate::model::{MerkleChatMessage, MerkleChatSnapshot, MerkleConversationId};
+use crate::msg::{LogContext, LogSeverity, Msg, SystemEvent, ToxEvent};
+
+type Client = MerkleToxClient<ToxTransport, FsStore>;
+
+/// Queues node events until the worker drains them.
+struct EventQueue {
+    tx: mpsc::Sender<NodeEvent>,
+}
+
+impl NodeEventHandler for EventQueue {
+    fn handle_event(&self, event: NodeEvent) {
+        let _ = self.tx.send(event);
+    }
+}
+
+pub struct MerkleSession {
+    bridge: ToxMerkleBridge<FsStore>,
+    clients: HashMap<ConversationId, Client>,
+    events: mpsc::Receiver<NodeEvent>,
+    self_pk: LogicalIdentityPk,
+    runtime: Handle,
+    tx: mpsc::Sender<Msg>,
+}
+
+impl MerkleSession {
+    /// Opens the store in `store_dir` and sends the conversations it holds to
+    /// the UI. Must be called from the worker thread, inside the runtime.
+    pub fn open(
+        tox: Arc<ReentrantMutex<Tox>>,
+        store_dir: &Path,
+        tx: mpsc::Sender<Msg>,
+    ) -> MerkleToxResult<Self> {
+        let self_sk = tox.lock().secret_key();
+        let transport = ToxTransport { tox };
+        let self_pk = transport.local_pk();
+        let engine = merkle_tox_core::engine::MerkleToxEngine::with_sk(
+            self_pk,
+            self_pk.to_logical(),
+            PhysicalDeviceSk::from(self_sk),
+            rand::SeedableRng::from_entropy(),
+            Arc::new(merkle_tox_core::clock::SystemTimeProvider),
+        );
+        let store = FsStore::new(store_dir.to_path_buf(), Arc::new(StdFileSystem))?;
+        let conversations = store.conversations();
+        let mut node = MerkleToxNode::new(
+            engine,
+            transport,
+            store,
+            Arc::new(merkle_tox_core::clock::SystemTimeProvider),
+        );
+        let (events_tx, events) = mpsc::channel();
+        node.set_event_handler(Arc::new(EventQueue { tx: events_tx }));
+
+        let mut session = Self {
+            bridge: ToxMerkleBridge::with_node(Arc::new(Mutex::new(node))),
+            clients: HashMap::new(),
+            events,
+            self_pk: self_pk.to_logical(),
+            runtime: Handle::current(),
+            tx,
+        };
+        for conversation_id in conversations {
+            let client = MerkleToxClient::new(session.bridge.node.clone(), conversation_id);
+            if let Err(e) = session.runtime.block_on(client.refresh_state()) {
+                session.log_error(format!("Failed to load Merkle-Tox conversation: {}", e));
+            }
+            session.clients.insert(conversation_id, client);
+            session.publish(&conversation_id);
+        }
+        Ok(session)
+    }
+
+    /// Offers a Tox event to the node. Friend connection changes and
+    /// Merkle-Tox packets are consumed here; the caller still handles every
+    /// event as usual.
+    pub fn handle_event(&mut self, event: &Event<'_>) {
+        let mut node = self.bridge.node.blocking_lock();
+        ToxMerkleBridge::handle_event_locked(&mut node, event);
+    }
+
+    /// Runs retransmissions and pacing. Returns the next wakeup time.
+    pub fn poll(&mut self) -> Instant {
+        self.bridge.node.blocking_lock().poll()
+    }
+
+    /// Applies the queued node events to the clients and sends the
+    /// conversations that changed to the UI.
+    pub fn drain(&mut self) {
+        let mut changed = BTreeSet::new();
+        while let Ok(event) = self.events.try_recv() {
+            let conversation_id = match &event {
+                NodeEvent::NodeVerified {
+                    conversation_id, ..
+                }
+                | NodeEvent::NodeExpired {
+                    conversation_id, ..
+                } => *conversation_id,
+                NodeEvent::PeerHandshakeComplete { peer_pk } => {
+                    for client in self.clients.values() {
+                        let event = NodeEvent::PeerHandshakeComplete { peer_pk: *peer_pk };
+                        let result = self.runtime.block_on(client.handle_event(event));
+                        self.report(result, "apply Merkle-Tox handshake");
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            // Conversations in the store were loaded at startup, so a new
+            // one has no history yet beyond the events still queued.
+            let client = self
+                .clients
+                .entry(conversation_id)
+                .or_insert_with(|| MerkleToxClient::new(self.bridge.node.clone(), conversation_id));
+            let result = self.runtime.block_on(client.handle_event(event));
+            self.report(result, "apply Merkle-Tox event");
+            changed.insert(conversation_id);
+        }
+        for conversation_id in changed {
+            self.publish(&conversation_id);
+        }
+    }
+
+    pub fn send_message(&self, id: MerkleConversationId, text: String) {
+        if let Some(client) = self.client(id) {
+            let result = self.runtime.block_on(client.send_message(text));
+            self.report(result, "send message");
+        }
+    }
+
+    pub fn send_reaction(&self, id: MerkleConversationId, target: [u8; 32], emoji: String) {
+        if let Some(client) = self.client(id) {
+            let result = self.runtime.block_on(
+                client.send_reaction(NodeHash::from(target), EmojiSource::Unicode(emoji)),
+            );
+            self.report(result, "send reaction");
+        }
+    }
+
+    pub fn send_redaction(&self, id: MerkleConversationId, target: [u8; 32]) {
+        if let Some(client) = self.client(id) {
+            let result = self
+                .runtime
+                .block_on(client.send_redaction(NodeHash::from(target), String::new()));
+            self.report(result, "redact message");
+        }
+    }
+
+    fn client(&self, id: MerkleConversationId) -> Option<&Client> {
+        let client = self.clients.get(&ConversationId::from(id.0));
+        if client.is_none() {
+            self.log_error("Unknown Merkle-Tox conversation.".to_owned());
+        }
+        client
+    }
+
+    fn report<T>(&self, result: MerkleToxResult<T>, what: &str) {
+        if let Err(e) = result {
+            self.log_error(format!("Failed to {}: {}", what, e));
+        }
+    }
+
+    fn publish(&self, conversation_id: &ConversationId) {
+        if let Some(client) = self.clients.get(conversation_id) {
+            let state = self.runtime.block_on(client.state());
+            let _ = self.tx.send(Msg::Tox(ToxEvent::MerkleState(
+                MerkleConversationId(*conversation_id.as_bytes()),
+                snapshot(&state, &self.self_pk),
+            )));
+        }
+    }
+
+    fn log_error(&self, message: String) {
+        let _ = self.tx.send(Msg::System(SystemEvent::Log {
+            severity: LogSeverity::Error,
+            context: LogContext::Global,
+            message,
+        }));
+    }
+}
+
+/// Converts the materialized state of a conversation into what the UI shows.
+pub fn snapshot(state: &ChatState, self_pk: &LogicalIdentityPk) -> MerkleChatSnapshot {
+    let messages = state
+        .messages
+        .iter()
+        .map(|m| {
+            let mut reactions: Vec<(String, usize)> = m
+                .reactions
+                .iter()
+                .map(|(emoji, who)| (emoji.clone(), who.len()))
+                .collect();
+            reactions.sort();
+            MerkleChatMessage {
+                hash: *m.hash.as_bytes(),
+                sender: state
+                    .members
+                    .get(&m.author_pk)
+                    .and_then(|member| member.display_name())
+                    .map_or_else(
+                        || crate::utils::encode_hex(&m.author_pk.as_bytes()[..4]),
+                        str::to_owned,
+                    ),
+                is_self: m.author_pk == *self_pk,
+                text: content_text(&m.content),
+                timestamp_ms: m.timestamp,
+                reactions,
+                is_redacted: m.is_redacted,
+            }
+        })
+        .collect();
+    MerkleChatSnapshot {
+        title: state.title.clone(),
+        topic: state.topic.clone(),
+        messages,
+    }
+}
+
+fn content_text(content: &Content) -> String {
+    match content {
+        Content::Text(text) => text.clone(),
+        Content::Blob { name, size, .. } => format!("[File: {} ({} bytes)]", name, size),
+        Content::Voice { duration_ms, .. } => {
+            format!("[Voice message: {}s]", duration_ms.div_ceil(1000))
+        }
+        Content::Poll {
+            question, options, ..
+        } => format!("[Poll: {} ({})]", question, options.join(" / ")),
+        Content::Location {
+            latitude,
+            longitude,
+            title,
+        } => match title {
+            Some(title) => format!("[Location: {} ({}, {})]", title, latitude, longitude),
+            None => format!("[Location: {}, {}]", latitude, longitude),
+        },
+        _ => "[Unsupported message]".to_owned(),
+    }
+}
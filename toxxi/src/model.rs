@@ -12,13 +12,14 @@ use std::fs;
 use std::io;
 use std::path::Path;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use toxcore::tox::{
     ConferenceNumber, FriendNumber, GroupNumber, GroupPeerNumber, ToxConferenceType, ToxConnection,
     ToxUserStatus,
 };
 use toxcore::types::{
-    Address, ChatId, ConferenceId, FileId, MessageType, PublicKey, ToxGroupRole, ToxLogLevel,
+    Address, ChatId, ConferenceId, FileId, MessageType, PublicKey, ToxFileControl, ToxGroupRole,
+    ToxLogLevel,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
@@ -120,12 +121,39 @@ pub struct Conversation {
     pub ignored_peers: HashSet<PublicKey>,
 }
 
+/// A Merkle-Tox conversation, identified by its conversation id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct MerkleConversationId(pub [u8; 32]);
+
+/// A Merkle-Tox conversation as materialized by its client, in the form the
+/// UI displays it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleChatSnapshot {
+    pub title: String,
+    pub topic: String,
+    pub messages: Vec<MerkleChatMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleChatMessage {
+    pub hash: [u8; 32],
+    pub sender: String,
+    pub is_self: bool,
+    pub text: String,
+    /// Network time in milliseconds since the Unix epoch.
+    pub timestamp_ms: i64,
+    /// Emoji and the number of members who reacted with it.
+    pub reactions: Vec<(String, usize)>,
+    pub is_redacted: bool,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum WindowId {
     Console,
     Friend(PublicKey),
     Group(ChatId),
     Conference(ConferenceId),
+    MerkleTox(MerkleConversationId),
     Logs,
     Files,
 }
@@ -289,6 +317,157 @@ impl FileTransferProgress {
     }
 }
 
+/// Throughput of one transfer since it was first seen this session.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransferThroughput {
+    /// Bytes per second over the last update interval.
+    pub current: f64,
+    pub average: f64,
+    pub peak: f64,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TransferStats {
+    started: Instant,
+    /// Bytes already transferred when tracking started, e.g. on resume.
+    start_offset: u64,
+    peak_speed: f64,
+}
+
+/// All inbound and outbound file transfers, keyed by file ID. The progress
+/// records are persisted; throughput stats only cover the current session.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(transparent)]
+pub struct TransferManager {
+    #[serde(with = "vectorize_map")]
+    transfers: HashMap<FileId, FileTransferProgress>,
+    #[serde(skip)]
+    stats: HashMap<FileId, TransferStats>,
+}
+
+impl PartialEq for TransferManager {
+    fn eq(&self, other: &Self) -> bool {
+        self.transfers == other.transfers
+    }
+}
+
+impl From<HashMap<FileId, FileTransferProgress>> for TransferManager {
+    fn from(transfers: HashMap<FileId, FileTransferProgress>) -> Self {
+        Self {
+            transfers,
+            stats: HashMap::new(),
+        }
+    }
+}
+
+impl TransferManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.transfers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+
+    pub fn contains_key(&self, file_id: &FileId) -> bool {
+        self.transfers.contains_key(file_id)
+    }
+
+    pub fn get(&self, file_id: &FileId) -> Option<&FileTransferProgress> {
+        self.transfers.get(file_id)
+    }
+
+    pub fn get_mut(&mut self, file_id: &FileId) -> Option<&mut FileTransferProgress> {
+        self.transfers.get_mut(file_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&FileId, &FileTransferProgress)> {
+        self.transfers.iter()
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut FileTransferProgress> {
+        self.transfers.values_mut()
+    }
+
+    /// Starts tracking a transfer, replacing any previous one with the same ID.
+    pub fn insert(
+        &mut self,
+        file_id: FileId,
+        progress: FileTransferProgress,
+    ) -> Option<FileTransferProgress> {
+        self.stats.insert(
+            file_id,
+            TransferStats {
+                started: progress.last_update,
+                start_offset: progress.transferred,
+                peak_speed: 0.0,
+            },
+        );
+        self.transfers.insert(file_id, progress)
+    }
+
+    pub fn remove(&mut self, file_id: &FileId) -> Option<FileTransferProgress> {
+        self.stats.remove(file_id);
+        self.transfers.remove(file_id)
+    }
+
+    /// Records that `transferred` bytes are done and updates the speed.
+    pub fn record_progress(
+        &mut self,
+        file_id: &FileId,
+        now: Instant,
+        transferred: u64,
+    ) -> Option<&FileTransferProgress> {
+        let p = self.transfers.get_mut(file_id)?;
+        let stats = self.stats.entry(*file_id).or_insert(TransferStats {
+            started: now,
+            start_offset: p.transferred,
+            peak_speed: 0.0,
+        });
+        p.update_speed(now, transferred);
+        p.transferred = transferred;
+        stats.peak_speed = stats.peak_speed.max(p.speed);
+        Some(p)
+    }
+
+    /// Applies a pause, resume or cancel to the transfer's status. Returns
+    /// false if the transfer is unknown.
+    pub fn control(&mut self, file_id: &FileId, control: ToxFileControl) -> bool {
+        let Some(p) = self.transfers.get_mut(file_id) else {
+            return false;
+        };
+        p.status = match control {
+            ToxFileControl::TOX_FILE_CONTROL_PAUSE => TransferStatus::Paused,
+            ToxFileControl::TOX_FILE_CONTROL_RESUME => TransferStatus::Active,
+            ToxFileControl::TOX_FILE_CONTROL_CANCEL => TransferStatus::Canceled,
+        };
+        true
+    }
+
+    pub fn throughput(&self, file_id: &FileId, now: Instant) -> Option<TransferThroughput> {
+        let p = self.transfers.get(file_id)?;
+        let stats = self.stats.get(file_id)?;
+        let elapsed = now.saturating_duration_since(stats.started);
+        let secs = elapsed.as_secs_f64();
+        let average = if secs > 0.0 {
+            p.transferred.saturating_sub(stats.start_offset) as f64 / secs
+        } else {
+            0.0
+        };
+        Some(TransferThroughput {
+            current: p.speed,
+            average,
+            peak: stats.peak_speed,
+            elapsed,
+        })
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct DomainState {
     pub tox_id: Address,
@@ -308,8 +487,19 @@ pub struct DomainState {
     pub pending_items: Vec<PendingItem>,
 
     pub next_internal_id: InternalMessageId,
-    #[serde(with = "vectorize_map")]
-    pub file_transfers: HashMap<FileId, FileTransferProgress>,
+    pub file_transfers: TransferManager,
+}
+
+/// An audio call with a friend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallStatus {
+    /// The friend is calling.
+    Incoming,
+    /// We are calling the friend.
+    Ringing,
+    Active {
+        muted: bool,
+    },
 }
 
 #[derive(Default)]
@@ -318,6 +508,10 @@ pub struct SessionState {
     pub group_numbers: HashMap<GroupNumber, ChatId>,
     pub conference_numbers: HashMap<ConferenceNumber, ConferenceId>,
     pub group_peer_numbers: HashMap<(GroupNumber, GroupPeerNumber), PublicKey>,
+    /// Node hashes of the messages shown in each Merkle-Tox window, oldest
+    /// first.
+    pub merkle_message_hashes: HashMap<MerkleConversationId, Vec<[u8; 32]>>,
+    pub calls: HashMap<PublicKey, CallStatus>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
@@ -399,7 +593,7 @@ impl DomainState {
             tox_logs: HashMap::new(),
             pending_items: Vec::new(),
             next_internal_id: InternalMessageId(1),
-            file_transfers: HashMap::new(),
+            file_transfers: TransferManager::new(),
         }
     }
 }
@@ -660,6 +854,11 @@ impl Model {
         self.ensure_conversation_window(WindowId::Conference(conference_id), name);
     }
 
+    pub fn ensure_merkle_window(&mut self, id: MerkleConversationId) {
+        let name = format!("Merkle-Tox {}", crate::utils::encode_hex(&id.0[0..4]));
+        self.ensure_conversation_window(WindowId::MerkleTox(id), name);
+    }
+
     fn ensure_conversation_window(&mut self, window_id: WindowId, default_name: String) {
         if !self.ui.window_ids.contains(&window_id) {
             self.ui.window_ids.push(window_id);
@@ -854,11 +1053,89 @@ impl Model {
         }
     }
 
+    /// Replaces the messages of a Merkle-Tox window with `snapshot`. The
+    /// store is the history of these conversations, so nothing is logged.
+    pub fn apply_merkle_snapshot(
+        &mut self,
+        id: MerkleConversationId,
+        snapshot: MerkleChatSnapshot,
+    ) {
+        let window_id = WindowId::MerkleTox(id);
+        self.ensure_merkle_window(id);
+        let active_id = self.active_window_id();
+        let now = self.time_provider.now_local();
+
+        let mut messages = Vec::with_capacity(snapshot.messages.len());
+        for m in &snapshot.messages {
+            let mut text = if m.is_redacted {
+                "[redacted]".to_owned()
+            } else {
+                m.text.clone()
+            };
+            if !m.reactions.is_empty() {
+                let reactions: Vec<String> = m
+                    .reactions
+                    .iter()
+                    .map(|(emoji, count)| format!("{} {}", emoji, count))
+                    .collect();
+                text = format!("{}  [{}]", text, reactions.join(", "));
+            }
+            let highlighted = !m.is_self && self.should_highlight(window_id, &text);
+            let internal_id = self.domain.next_internal_id;
+            self.domain.next_internal_id.0 += 1;
+            messages.push(Message {
+                internal_id,
+                sender: m.sender.clone(),
+                sender_pk: None,
+                is_self: m.is_self,
+                content: MessageContent::Text(text),
+                timestamp: DateTime::from_timestamp_millis(m.timestamp_ms)
+                    .map(|t| t.with_timezone(now.offset()))
+                    .unwrap_or(now),
+                status: if m.is_self {
+                    MessageStatus::Received
+                } else {
+                    MessageStatus::Incoming
+                },
+                message_type: MessageType::TOX_MESSAGE_TYPE_NORMAL,
+                highlighted,
+            });
+        }
+
+        if let Some(conv) = self.domain.conversations.get_mut(&window_id) {
+            if !snapshot.title.is_empty() {
+                conv.name = snapshot.title;
+            }
+            conv.topic = (!snapshot.topic.is_empty()).then_some(snapshot.topic);
+            let added = messages.len().saturating_sub(conv.messages.len());
+            conv.messages = messages;
+            if active_id != window_id && added > 0 {
+                let state = self.ui.window_state.entry(window_id).or_default();
+                state.unread_count += added;
+            }
+        }
+        self.session
+            .merkle_message_hashes
+            .insert(id, snapshot.messages.iter().map(|m| m.hash).collect());
+        self.invalidate_full_window_cache(window_id);
+        self.invalidate_sidebar_cache();
+    }
+
+    /// Hash of the `n`-th most recent message of a Merkle-Tox window, counting
+    /// from 1.
+    pub fn merkle_message_hash(&self, id: MerkleConversationId, n: usize) -> Option<[u8; 32]> {
+        let hashes = self.session.merkle_message_hashes.get(&id)?;
+        n.checked_sub(1)
+            .and_then(|i| hashes.len().checked_sub(i + 1))
+            .map(|i| hashes[i])
+    }
+
     pub fn ensure_window(&mut self, window_id: WindowId) {
         match window_id {
             WindowId::Friend(pk) => self.ensure_friend_window(pk),
             WindowId::Group(g) => self.ensure_group_window(g),
             WindowId::Conference(c) => self.ensure_conference_window(c),
+            WindowId::MerkleTox(id) => self.ensure_merkle_window(id),
             WindowId::Console | WindowId::Logs | WindowId::Files => {
                 if !self.ui.window_ids.contains(&window_id) {
                     self.ui.window_ids.push(window_id);
@@ -1,10 +1,11 @@
-use crate::model::{InternalMessageId, WindowId};
+use crate::model::{InternalMessageId, MerkleChatSnapshot, MerkleConversationId, WindowId};
 use crate::script::ScriptRequest;
 use crossterm::event::Event as CrosstermEvent;
 use toxcore::tox::{
     Address, ConferenceNumber, ConferencePeerNumber, FriendMessageId, FriendNumber, GroupNumber,
     GroupPeerNumber, ToxConferenceType, ToxConnection, ToxUserStatus,
 };
+use toxcore::toxav::CallState;
 use toxcore::types::{
     ChatId, ConferenceId, DhtId, FileId, MessageType, PublicKey, ToxFileControl, ToxGroupModEvent,
     ToxGroupRole, ToxLogLevel,
@@ -46,6 +47,8 @@ pub enum ToxEvent {
     ConferenceMessageSent(ConferenceNumber, InternalMessageId),
     MessageSendFailed(WindowId, InternalMessageId),
     FriendRequest(PublicKey, String),
+    CallIncoming(FriendNumber, bool, bool), // Audio, Video
+    CallState(FriendNumber, CallState),
     GroupInvite(FriendNumber, String, String),
     ConferenceInvite(FriendNumber, ToxConferenceType, String),
     GroupCreated(GroupNumber, ChatId, Option<String>),
@@ -86,6 +89,7 @@ pub enum ToxEvent {
     FileRecvControl(FriendNumber, FileId, ToxFileControl),
     FileChunkSent(FriendNumber, FileId, u64, usize),
     Log(ToxLogLevel, String, u32, String, String),
+    MerkleState(MerkleConversationId, MerkleChatSnapshot),
 }
 
 #[derive(Debug, Clone)]
@@ -187,6 +191,13 @@ pub enum ToxAction {
     FileControl(PublicKey, FileId, ToxFileControl),
     FileSendChunk(PublicKey, FileId, u64, Vec<u8>),
     FileSeek(PublicKey, FileId, u64),
+    MerkleSend(MerkleConversationId, String),
+    MerkleReact(MerkleConversationId, [u8; 32], String), // Target message hash, Emoji
+    MerkleRedact(MerkleConversationId, [u8; 32]),
+    StartCall(PublicKey),
+    AnswerCall(PublicKey),
+    HangUp(PublicKey),
+    SetCallMuted(PublicKey, bool),
     Reload(Box<crate::config::Config>),
     Shutdown,
 }
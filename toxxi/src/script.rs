@@ -1,12 +1,18 @@
 use crate::commands;
-use crate::model::Model;
+use crate::model::{MessageContent, Model, WindowId};
 use crate::msg::{Msg, SystemEvent, ToxEvent};
 use crate::waits;
-use rhai::{Engine, EvalAltResult, Position, Scope};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, NativeCallContext, Position, Scope};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
 use std::time::{Duration, Instant};
+use toxcore::tox::{FriendNumber, ToxConnection};
+use toxcore::types::{FileId, MessageType, PublicKey};
+
+/// How often `wait_until` re-evaluates its condition.
+const WAIT_UNTIL_POLL_MS: u64 = 50;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScriptRequest {
@@ -17,12 +23,107 @@ pub enum ScriptRequest {
     WaitFriendMessage(u32, String),
     WaitFileRecv(u32),
     Sleep(u64),
+    Query(ScriptQuery),
+}
+
+/// Model state a script can read. Answered right away by `answer_query`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptQuery {
+    Friends,
+    /// The last N messages in the window of a friend.
+    Messages(u32, usize),
+    SelfConnection,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptFriend {
+    pub id: u32,
+    pub name: String,
+    pub status_message: String,
+    pub online: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptMessage {
+    pub sender: String,
+    pub text: String,
+    pub is_self: bool,
 }
 
 #[derive(Debug)]
 pub enum ScriptResponse {
     Ok,
     FileId(String),
+    Friends(Vec<ScriptFriend>),
+    Messages(Vec<ScriptMessage>),
+    Connection(String),
+}
+
+fn connection_name(connection: ToxConnection) -> &'static str {
+    match connection {
+        ToxConnection::TOX_CONNECTION_NONE => "none",
+        ToxConnection::TOX_CONNECTION_TCP => "tcp",
+        ToxConnection::TOX_CONNECTION_UDP => "udp",
+    }
+}
+
+fn message_text(content: &MessageContent) -> String {
+    match content {
+        MessageContent::Text(text) => text.clone(),
+        MessageContent::List(lines) => lines.join("\n"),
+        MessageContent::FileTransfer { name, .. } => format!("[File: {}]", name),
+        MessageContent::GameInvite { game_type, .. } => format!("[Game invite: {}]", game_type),
+    }
+}
+
+pub fn answer_query(model: &Model, query: &ScriptQuery) -> ScriptResponse {
+    match query {
+        ScriptQuery::Friends => {
+            let mut friends: Vec<_> = model
+                .session
+                .friend_numbers
+                .iter()
+                .filter_map(|(fid, pk)| {
+                    let info = model.domain.friends.get(pk)?;
+                    Some(ScriptFriend {
+                        id: fid.0,
+                        name: info.name.clone(),
+                        status_message: info.status_message.clone(),
+                        online: info.connection != ToxConnection::TOX_CONNECTION_NONE,
+                    })
+                })
+                .collect();
+            friends.sort_by_key(|f| f.id);
+            ScriptResponse::Friends(friends)
+        }
+        ScriptQuery::Messages(id, count) => {
+            let messages = model
+                .session
+                .friend_numbers
+                .get(&FriendNumber(*id))
+                .and_then(|pk| model.domain.conversations.get(&WindowId::Friend(*pk)))
+                .map(|conv| {
+                    let start = conv.messages.len().saturating_sub(*count);
+                    conv.messages[start..]
+                        .iter()
+                        .map(|m| ScriptMessage {
+                            sender: m.sender.clone(),
+                            text: message_text(&m.content),
+                            is_self: m.is_self,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            ScriptResponse::Messages(messages)
+        }
+        ScriptQuery::SelfConnection => ScriptResponse::Connection(
+            connection_name(model.domain.self_connection_status).to_owned(),
+        ),
+    }
+}
+
+fn runtime_error(message: String) -> Box<EvalAltResult> {
+    Box::new(EvalAltResult::ErrorRuntime(message.into(), Position::NONE))
 }
 
 pub struct ScriptChannels {
@@ -30,6 +131,27 @@ pub struct ScriptChannels {
     pub res_rx: Mutex<mpsc::Receiver<ScriptResponse>>,
 }
 
+impl ScriptChannels {
+    /// Sends `req` to the main loop and blocks until it is answered.
+    fn request(&self, req: ScriptRequest) -> Result<ScriptResponse, Box<EvalAltResult>> {
+        self.req_tx
+            .send(Msg::System(SystemEvent::ScriptRequest(req)))
+            .map_err(|e| runtime_error(format!("Send error: {}", e)))?;
+        self.res_rx
+            .lock()
+            .unwrap()
+            .recv()
+            .map_err(|e| runtime_error(format!("Recv error: {}", e)))
+    }
+
+    /// Feeds `event` to the main loop as if it came from Tox.
+    fn inject(&self, event: ToxEvent) -> Result<(), Box<EvalAltResult>> {
+        self.req_tx
+            .send(Msg::Tox(event))
+            .map_err(|e| runtime_error(format!("Send error: {}", e)))
+    }
+}
+
 pub fn spawn_script(
     path: PathBuf,
     tx: mpsc::Sender<Msg>,
@@ -49,43 +171,13 @@ pub fn spawn_script(
 
         let c = channels.clone();
         engine.register_fn("cmd", move |s: String| -> Result<(), Box<EvalAltResult>> {
-            c.req_tx
-                .send(Msg::System(SystemEvent::ScriptRequest(
-                    ScriptRequest::Command(s),
-                )))
-                .map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Send error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
-            c.res_rx.lock().unwrap().recv().map_err(|e| {
-                Box::new(EvalAltResult::ErrorRuntime(
-                    format!("Recv error: {}", e).into(),
-                    Position::NONE,
-                ))
-            })?;
+            c.request(ScriptRequest::Command(s))?;
             Ok(())
         });
 
         let c = channels.clone();
         engine.register_fn("wait_online", move || -> Result<(), Box<EvalAltResult>> {
-            c.req_tx
-                .send(Msg::System(SystemEvent::ScriptRequest(
-                    ScriptRequest::WaitOnline,
-                )))
-                .map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Send error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
-            c.res_rx.lock().unwrap().recv().map_err(|e| {
-                Box::new(EvalAltResult::ErrorRuntime(
-                    format!("Recv error: {}", e).into(),
-                    Position::NONE,
-                ))
-            })?;
+            c.request(ScriptRequest::WaitOnline)?;
             Ok(())
         });
 
@@ -93,22 +185,7 @@ pub fn spawn_script(
         engine.register_fn(
             "wait_friend_online",
             move |id: i64| -> Result<(), Box<EvalAltResult>> {
-                c.req_tx
-                    .send(Msg::System(SystemEvent::ScriptRequest(
-                        ScriptRequest::WaitFriendOnline(id as u32),
-                    )))
-                    .map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Send error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
-                c.res_rx.lock().unwrap().recv().map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Recv error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
+                c.request(ScriptRequest::WaitFriendOnline(id as u32))?;
                 Ok(())
             },
         );
@@ -117,22 +194,7 @@ pub fn spawn_script(
         engine.register_fn(
             "wait_friend_msg",
             move |id: i64, sub: String| -> Result<(), Box<EvalAltResult>> {
-                c.req_tx
-                    .send(Msg::System(SystemEvent::ScriptRequest(
-                        ScriptRequest::WaitFriendMessage(id as u32, sub),
-                    )))
-                    .map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Send error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
-                c.res_rx.lock().unwrap().recv().map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Recv error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
+                c.request(ScriptRequest::WaitFriendMessage(id as u32, sub))?;
                 Ok(())
             },
         );
@@ -141,44 +203,14 @@ pub fn spawn_script(
         engine.register_fn(
             "wait_read_receipt",
             move |id: i64| -> Result<(), Box<EvalAltResult>> {
-                c.req_tx
-                    .send(Msg::System(SystemEvent::ScriptRequest(
-                        ScriptRequest::WaitReadReceipt(id as u32),
-                    )))
-                    .map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Send error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
-                c.res_rx.lock().unwrap().recv().map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Recv error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
+                c.request(ScriptRequest::WaitReadReceipt(id as u32))?;
                 Ok(())
             },
         );
 
         let c = channels.clone();
         engine.register_fn("sleep", move |ms: i64| -> Result<(), Box<EvalAltResult>> {
-            c.req_tx
-                .send(Msg::System(SystemEvent::ScriptRequest(
-                    ScriptRequest::Sleep(ms as u64),
-                )))
-                .map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Send error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
-            c.res_rx.lock().unwrap().recv().map_err(|e| {
-                Box::new(EvalAltResult::ErrorRuntime(
-                    format!("Recv error: {}", e).into(),
-                    Position::NONE,
-                ))
-            })?;
+            c.request(ScriptRequest::Sleep(ms as u64))?;
             Ok(())
         });
 
@@ -186,22 +218,7 @@ pub fn spawn_script(
         engine.register_fn(
             "timeout",
             move |ms: i64| -> Result<(), Box<EvalAltResult>> {
-                c.req_tx
-                    .send(Msg::System(SystemEvent::ScriptRequest(
-                        ScriptRequest::Command(format!("/timeout {}", ms)),
-                    )))
-                    .map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Send error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
-                c.res_rx.lock().unwrap().recv().map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Recv error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
+                c.request(ScriptRequest::Command(format!("/timeout {}", ms)))?;
                 Ok(())
             },
         );
@@ -210,29 +227,166 @@ pub fn spawn_script(
         engine.register_fn(
             "wait_file_recv",
             move |id: i64| -> Result<String, Box<EvalAltResult>> {
-                c.req_tx
-                    .send(Msg::System(SystemEvent::ScriptRequest(
-                        ScriptRequest::WaitFileRecv(id as u32),
-                    )))
-                    .map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Send error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
-                let res = c.res_rx.lock().unwrap().recv().map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Recv error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
-                match res {
+                match c.request(ScriptRequest::WaitFileRecv(id as u32))? {
                     ScriptResponse::FileId(fid) => Ok(fid),
                     _ => Ok(String::new()),
                 }
             },
         );
 
+        // State queries
+        let c = channels.clone();
+        engine.register_fn("friends", move || -> Result<Array, Box<EvalAltResult>> {
+            match c.request(ScriptRequest::Query(ScriptQuery::Friends))? {
+                ScriptResponse::Friends(friends) => Ok(friends
+                    .into_iter()
+                    .map(|f| {
+                        let mut map = Map::new();
+                        map.insert("id".into(), Dynamic::from(f.id as i64));
+                        map.insert("name".into(), Dynamic::from(f.name));
+                        map.insert("status_message".into(), Dynamic::from(f.status_message));
+                        map.insert("online".into(), Dynamic::from(f.online));
+                        Dynamic::from_map(map)
+                    })
+                    .collect()),
+                _ => Ok(Array::new()),
+            }
+        });
+
+        let c = channels.clone();
+        engine.register_fn(
+            "messages",
+            move |id: i64, count: i64| -> Result<Array, Box<EvalAltResult>> {
+                let query = ScriptQuery::Messages(id as u32, count.max(0) as usize);
+                match c.request(ScriptRequest::Query(query))? {
+                    ScriptResponse::Messages(messages) => Ok(messages
+                        .into_iter()
+                        .map(|m| {
+                            let mut map = Map::new();
+                            map.insert("sender".into(), Dynamic::from(m.sender));
+                            map.insert("text".into(), Dynamic::from(m.text));
+                            map.insert("is_self".into(), Dynamic::from(m.is_self));
+                            Dynamic::from_map(map)
+                        })
+                        .collect()),
+                    _ => Ok(Array::new()),
+                }
+            },
+        );
+
+        let c = channels.clone();
+        engine.register_fn(
+            "connection",
+            move || -> Result<String, Box<EvalAltResult>> {
+                match c.request(ScriptRequest::Query(ScriptQuery::SelfConnection))? {
+                    ScriptResponse::Connection(status) => Ok(status),
+                    _ => Ok(String::new()),
+                }
+            },
+        );
+
+        // Assertions
+        engine.register_fn(
+            "assert",
+            |cond: bool, message: String| -> Result<(), Box<EvalAltResult>> {
+                if cond {
+                    Ok(())
+                } else {
+                    Err(runtime_error(format!("Assertion failed: {}", message)))
+                }
+            },
+        );
+        engine.register_fn("assert", |cond: bool| -> Result<(), Box<EvalAltResult>> {
+            if cond {
+                Ok(())
+            } else {
+                Err(runtime_error("Assertion failed".to_owned()))
+            }
+        });
+
+        let c = channels.clone();
+        engine.register_fn(
+            "wait_until",
+            move |ctx: NativeCallContext,
+                  condition: FnPtr,
+                  timeout_ms: i64|
+                  -> Result<(), Box<EvalAltResult>> {
+                let deadline = Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64);
+                loop {
+                    if condition.call_within_context::<bool>(&ctx, ())? {
+                        return Ok(());
+                    }
+                    if Instant::now() >= deadline {
+                        return Err(runtime_error(format!(
+                            "Condition not met within {} ms",
+                            timeout_ms
+                        )));
+                    }
+                    c.request(ScriptRequest::Sleep(WAIT_UNTIL_POLL_MS))?;
+                }
+            },
+        );
+
+        // Simulated inbound events
+        let c = channels.clone();
+        engine.register_fn(
+            "inject_friend_msg",
+            move |id: i64, text: String| -> Result<(), Box<EvalAltResult>> {
+                c.inject(ToxEvent::Message(
+                    FriendNumber(id as u32),
+                    MessageType::TOX_MESSAGE_TYPE_NORMAL,
+                    text,
+                ))
+            },
+        );
+
+        let c = channels.clone();
+        engine.register_fn(
+            "inject_friend_online",
+            move |id: i64, online: bool| -> Result<(), Box<EvalAltResult>> {
+                let connection = if online {
+                    ToxConnection::TOX_CONNECTION_UDP
+                } else {
+                    ToxConnection::TOX_CONNECTION_NONE
+                };
+                c.inject(ToxEvent::FriendStatus(
+                    FriendNumber(id as u32),
+                    connection,
+                    None,
+                ))
+            },
+        );
+
+        let c = channels.clone();
+        engine.register_fn(
+            "inject_friend_request",
+            move |pk: String, message: String| -> Result<(), Box<EvalAltResult>> {
+                let bytes = crate::utils::decode_hex(&pk)
+                    .filter(|b| b.len() == 32)
+                    .ok_or_else(|| runtime_error(format!("Invalid public key: {}", pk)))?;
+                let mut key = [0u8; 32];
+                key.copy_from_slice(&bytes);
+                c.inject(ToxEvent::FriendRequest(PublicKey(key), message))
+            },
+        );
+
+        let c = channels.clone();
+        let next_file = Arc::new(AtomicU8::new(1));
+        engine.register_fn(
+            "inject_file_recv",
+            move |id: i64, filename: String, size: i64| -> Result<String, Box<EvalAltResult>> {
+                let file_id = FileId([next_file.fetch_add(1, Ordering::Relaxed); 32]);
+                c.inject(ToxEvent::FileRecv(
+                    FriendNumber(id as u32),
+                    file_id,
+                    0,
+                    size.max(0) as u64,
+                    filename,
+                ))?;
+                Ok(file_id.to_string())
+            },
+        );
+
         // Dynamically register all commands from the registry
         for cmd_def in commands::COMMANDS.iter() {
             let c = channels.clone();
@@ -240,22 +394,7 @@ pub fn spawn_script(
             engine.register_fn(
                 name,
                 move |args: String| -> Result<(), Box<EvalAltResult>> {
-                    c.req_tx
-                        .send(Msg::System(SystemEvent::ScriptRequest(
-                            ScriptRequest::Command(format!("/{} {}", name, args)),
-                        )))
-                        .map_err(|e| {
-                            Box::new(EvalAltResult::ErrorRuntime(
-                                format!("Send error: {}", e).into(),
-                                Position::NONE,
-                            ))
-                        })?;
-                    c.res_rx.lock().unwrap().recv().map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Recv error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
+                    c.request(ScriptRequest::Command(format!("/{} {}", name, args)))?;
                     Ok(())
                 },
             );
@@ -263,22 +402,7 @@ pub fn spawn_script(
             // Also register a version with no args if useful
             let c = channels.clone();
             engine.register_fn(name, move || -> Result<(), Box<EvalAltResult>> {
-                c.req_tx
-                    .send(Msg::System(SystemEvent::ScriptRequest(
-                        ScriptRequest::Command(format!("/{}", name)),
-                    )))
-                    .map_err(|e| {
-                        Box::new(EvalAltResult::ErrorRuntime(
-                            format!("Send error: {}", e).into(),
-                            Position::NONE,
-                        ))
-                    })?;
-                c.res_rx.lock().unwrap().recv().map_err(|e| {
-                    Box::new(EvalAltResult::ErrorRuntime(
-                        format!("Recv error: {}", e).into(),
-                        Position::NONE,
-                    ))
-                })?;
+                c.request(ScriptRequest::Command(format!("/{}", name)))?;
                 Ok(())
             });
         }
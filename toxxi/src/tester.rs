@@ -14,7 +14,7 @@ use crate::app::AppContext;
 use crate::config::Config;
 use crate::model::{DomainState, Model, WindowId};
 use crate::msg::{AppCmd, Cmd, Msg, SystemEvent, ToxAction, ToxEvent};
-use crate::script::{ScriptController, ScriptRequest, ScriptResponse};
+use crate::script::{ScriptController, ScriptRequest, ScriptResponse, answer_query};
 use crate::update::{handle_enter, update};
 use crate::worker;
 
@@ -344,6 +344,10 @@ impl TestClient {
             if let Some(ctrl) = &mut self.script_ctrl {
                 if let Msg::System(SystemEvent::ScriptRequest(req)) = &msg {
                     ctrl.pending_req = Some(req.clone());
+                    if let ScriptRequest::Query(query) = req {
+                        ctrl.pending_req = None;
+                        let _ = ctrl.tx_script_res.send(answer_query(&self.model, query));
+                    }
                     if let ScriptRequest::Command(cmd_str) = req {
                         let cmds = handle_enter(&mut self.model, cmd_str);
                         if self.ctx.execute(cmds, &mut self.model).await.should_quit {
@@ -52,7 +52,7 @@ impl TestContext {
             tox_logs: HashMap::new(),
             pending_items: Vec::new(),
             next_internal_id: crate::model::InternalMessageId(1),
-            file_transfers: HashMap::new(),
+            file_transfers: crate::model::TransferManager::new(),
         };
 
         Model {
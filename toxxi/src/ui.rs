@@ -1,4 +1,4 @@
-use crate::model::{MessageContent, MessageStatus, Model, TransferStatus, WindowId};
+use crate::model::{CallStatus, MessageContent, MessageStatus, Model, TransferStatus, WindowId};
 use crate::widgets::info_pane::Participant;
 use crate::widgets::message_list::{
     ChatMessage, MessageContent as WidgetContent, MessageStatus as WidgetStatus,
@@ -598,7 +598,21 @@ fn draw_status_bar(f: &mut Frame, area: Rect, model: &Model) {
         });
     }
 
-    let status_bar = StatusBar::new(
+    // The call with the active friend, or else any ongoing call.
+    let call = match active_id {
+        WindowId::Friend(pk) => model.session.calls.get(&pk).map(|c| (pk, *c)),
+        _ => None,
+    }
+    .or_else(|| {
+        model
+            .session
+            .calls
+            .iter()
+            .map(|(pk, c)| (*pk, *c))
+            .min_by_key(|(pk, _)| pk.0)
+    });
+
+    let mut status_bar = StatusBar::new(
         self_name,
         status_type_str.to_string(),
         model.domain.tox_id.to_string(),
@@ -610,6 +624,22 @@ fn draw_status_bar(f: &mut Frame, area: Rect, model: &Model) {
     .windows(windows);
     // .dht_health(...) // TODO: Add DHT health history to Model
 
+    if let Some((pk, status)) = call {
+        let name = model
+            .domain
+            .friends
+            .get(&pk)
+            .map(|f| f.name.clone())
+            .unwrap_or_else(|| crate::utils::encode_hex(&pk.0[0..4]));
+        let state = match status {
+            CallStatus::Incoming => "incoming",
+            CallStatus::Ringing => "ringing",
+            CallStatus::Active { muted: false } => "active",
+            CallStatus::Active { muted: true } => "muted",
+        };
+        status_bar = status_bar.call(format!("CALL {}: {}", name, state));
+    }
+
     f.render_widget(status_bar, area);
 }
 
@@ -920,6 +950,42 @@ fn draw_sidebar(f: &mut Frame, area: Rect, model: &mut Model) {
             );
         }
 
+        // 5. Merkle-Tox, only once there are conversations
+        let mut merkle_ids: Vec<_> = model
+            .domain
+            .conversations
+            .keys()
+            .filter_map(|k| match k {
+                WindowId::MerkleTox(id) => Some(id),
+                _ => None,
+            })
+            .collect();
+        merkle_ids.sort();
+
+        if !merkle_ids.is_empty() {
+            items.push(SidebarItem::new("Merkle-Tox", SidebarItemType::Category));
+        }
+        for mid in merkle_ids {
+            let win_id = WindowId::MerkleTox(*mid);
+            let unread = model
+                .ui
+                .window_state
+                .get(&win_id)
+                .map(|s| s.unread_count as u32)
+                .unwrap_or(0);
+            let name = if let Some(conv) = model.domain.conversations.get(&win_id) {
+                conv.name.clone()
+            } else {
+                format!("Merkle-Tox {}", crate::utils::encode_hex(&mid.0[0..4]))
+            };
+
+            items.push(
+                SidebarItem::new(name, SidebarItemType::MerkleTox)
+                    .status(crate::widgets::sidebar::ContactStatus::Online)
+                    .unread(unread),
+            );
+        }
+
         model.ui.sidebar_cache = Some(items);
     }
 
@@ -940,6 +1006,7 @@ fn draw_sidebar(f: &mut Frame, area: Rect, model: &mut Model) {
                 "Friends" => SidebarItemType::Friend,
                 "Groups" => SidebarItemType::Group,
                 "Conferences" => SidebarItemType::Conference,
+                "Merkle-Tox" => SidebarItemType::MerkleTox,
                 _ => SidebarItemType::Category,
             });
 
@@ -1004,6 +1071,20 @@ fn draw_sidebar(f: &mut Frame, area: Rect, model: &mut Model) {
                     item.name == name
                 }
             }
+            WindowId::MerkleTox(m) => {
+                if item.item_type != SidebarItemType::MerkleTox {
+                    false
+                } else {
+                    let name = if let Some(conv) =
+                        model.domain.conversations.get(&WindowId::MerkleTox(m))
+                    {
+                        conv.name.clone()
+                    } else {
+                        format!("Merkle-Tox {}", crate::utils::encode_hex(&m.0[0..4]))
+                    };
+                    item.name == name
+                }
+            }
         };
 
         if is_match {
@@ -2,8 +2,8 @@ use crate::commands;
 use crate::completion;
 use crate::config::SystemMessageType;
 use crate::model::{
-    ConsoleMessageType, FileTransferProgress, FriendInfo, InputMode, MessageStatus, Model, PeerId,
-    PeerInfo, PendingItem, TransferStatus, WindowId,
+    CallStatus, ConsoleMessageType, FileTransferProgress, FriendInfo, InputMode, MessageContent,
+    MessageStatus, Model, PeerId, PeerInfo, PendingItem, TransferStatus, WindowId,
 };
 use crate::msg::{AppCmd, Cmd, IOAction, IOEvent, Msg, SystemEvent, ToxAction, ToxEvent};
 use crate::utils::split_message;
@@ -12,6 +12,7 @@ use crate::widgets::{
 };
 use crossterm::event::{Event as CrosstermEvent, KeyCode, KeyModifiers};
 use std::time::Duration;
+use toxcore::toxav::CallState;
 use toxcore::types::{
     GROUP_MAX_MESSAGE_LENGTH, MAX_MESSAGE_LENGTH, MessageType, ToxFileControl, ToxUserStatus,
 };
@@ -157,6 +158,16 @@ fn handle_key_event(model: &mut Model, key: crossterm::event::KeyEvent) -> Vec<C
                                             )
                                         }
                                     }
+                                    WindowId::MerkleTox(id) => {
+                                        if let Some(conv) = model.domain.conversations.get(win_id) {
+                                            conv.name.clone()
+                                        } else {
+                                            format!(
+                                                "Merkle-Tox {}",
+                                                crate::utils::encode_hex(&id.0[0..4])
+                                            )
+                                        }
+                                    }
                                     _ => String::new(),
                                 };
                                 if &name == target_name {
@@ -603,6 +614,7 @@ fn handle_key_event(model: &mut Model, key: crossterm::event::KeyEvent) -> Vec<C
                 match (a, b) {
                     (WindowId::Group(ga), WindowId::Group(gb)) => ga.0.cmp(&gb.0),
                     (WindowId::Conference(ca), WindowId::Conference(cb)) => ca.0.cmp(&cb.0),
+                    (WindowId::MerkleTox(ma), WindowId::MerkleTox(mb)) => ma.cmp(mb),
                     // Put Groups before Conferences
                     (WindowId::Group(_), WindowId::Conference(_)) => std::cmp::Ordering::Less,
                     (WindowId::Conference(_), WindowId::Group(_)) => std::cmp::Ordering::Greater,
@@ -627,6 +639,13 @@ fn handle_key_event(model: &mut Model, key: crossterm::event::KeyEvent) -> Vec<C
                             prefix: "c".to_owned(),
                         });
                     }
+                    if let WindowId::MerkleTox(_) = id {
+                        items.push(QuickSwitcherItem {
+                            name: conv.name.clone(),
+                            description: conv.topic.clone().unwrap_or_default(),
+                            prefix: "m".to_owned(),
+                        });
+                    }
                 }
             }
 
@@ -1000,6 +1019,9 @@ fn handle_tox_event(model: &mut Model, event: ToxEvent) -> Vec<Cmd> {
         ToxEvent::Log(level, file, line, func, message) => {
             model.add_tox_log(level, file, line, func, message);
         }
+        ToxEvent::MerkleState(id, snapshot) => {
+            model.apply_merkle_snapshot(id, snapshot);
+        }
         ToxEvent::ConnectionStatus(status) => {
             model.domain.self_connection_status = status;
             model.invalidate_sidebar_cache();
@@ -1162,6 +1184,66 @@ fn handle_tox_event(model: &mut Model, event: ToxEvent) -> Vec<Cmd> {
                 .pending_items
                 .push(PendingItem::FriendRequest { pk, message: msg });
         }
+        ToxEvent::CallIncoming(friend, _audio, video) => {
+            let Some(pk) = model.session.friend_numbers.get(&friend).cloned() else {
+                return cmds;
+            };
+            model.session.calls.insert(pk, CallStatus::Incoming);
+            model.ensure_friend_window(pk);
+            if video {
+                // Video is not supported; the call is answered audio only.
+                model.add_system_message_to(
+                    WindowId::Friend(pk),
+                    ConsoleMessageType::Info,
+                    MessageContent::Text("The call includes video, which is not shown.".to_owned()),
+                );
+            }
+            model.add_system_message_to(
+                WindowId::Friend(pk),
+                ConsoleMessageType::Info,
+                MessageContent::Text(
+                    "Incoming call. Type /call to answer or /call hangup to reject.".to_owned(),
+                ),
+            );
+            let name = model
+                .domain
+                .friends
+                .get(&pk)
+                .map(|f| f.name.clone())
+                .unwrap_or_else(|| format!("friend {}", friend.0));
+            model.add_console_message(
+                ConsoleMessageType::Info,
+                format!("Incoming call from {}", name),
+            );
+        }
+        ToxEvent::CallState(friend, state) => {
+            let Some(pk) = model.session.friend_numbers.get(&friend).cloned() else {
+                return cmds;
+            };
+            if state.is_ended() {
+                if model.session.calls.remove(&pk).is_some() {
+                    let text = if state.contains(CallState::ERROR) {
+                        "Call failed."
+                    } else {
+                        "Call ended."
+                    };
+                    model.add_system_message_to(
+                        WindowId::Friend(pk),
+                        ConsoleMessageType::Info,
+                        MessageContent::Text(text.to_owned()),
+                    );
+                }
+            } else if let Some(call) = model.session.calls.get_mut(&pk)
+                && *call == CallStatus::Ringing
+            {
+                *call = CallStatus::Active { muted: false };
+                model.add_system_message_to(
+                    WindowId::Friend(pk),
+                    ConsoleMessageType::Info,
+                    MessageContent::Text("Call connected.".to_owned()),
+                );
+            }
+        }
         ToxEvent::GroupInvite(f, d, n) => {
             if let Some(pk) = model.session.friend_numbers.get(&f).cloned() {
                 model.add_console_message(
@@ -1547,6 +1629,12 @@ fn handle_tox_event(model: &mut Model, event: ToxEvent) -> Vec<Cmd> {
                     },
                 );
 
+                let auto_accept = model
+                    .config
+                    .auto_accept
+                    .iter()
+                    .any(|rule| rule.matches(&pk, &filename, size));
+
                 // Add inline message
                 let window_id = WindowId::Friend(pk);
                 if let Some(msg) =
@@ -1554,6 +1642,10 @@ fn handle_tox_event(model: &mut Model, event: ToxEvent) -> Vec<Cmd> {
                 {
                     cmds.push(Cmd::IO(IOAction::LogMessage(window_id, msg)));
                 }
+
+                if auto_accept {
+                    cmds.extend(crate::commands::io::accept_file(model, pk, file_id, None));
+                }
             } else {
                 model.add_console_message(
                     ConsoleMessageType::Error,
@@ -1584,18 +1676,10 @@ fn handle_tox_event(model: &mut Model, event: ToxEvent) -> Vec<Cmd> {
                         }
                     }
                 }
-                ToxFileControl::TOX_FILE_CONTROL_PAUSE => {
-                    if let Some(_pk) = pk_opt
-                        && let Some(p) = model.domain.file_transfers.get_mut(&file_id)
-                    {
-                        p.status = TransferStatus::Paused;
-                    }
-                }
-                ToxFileControl::TOX_FILE_CONTROL_RESUME => {
-                    if let Some(_pk) = pk_opt
-                        && let Some(p) = model.domain.file_transfers.get_mut(&file_id)
-                    {
-                        p.status = TransferStatus::Active;
+                ToxFileControl::TOX_FILE_CONTROL_PAUSE
+                | ToxFileControl::TOX_FILE_CONTROL_RESUME => {
+                    if pk_opt.is_some() {
+                        model.domain.file_transfers.control(&file_id, control);
                     }
                 }
             }
@@ -1605,10 +1689,12 @@ fn handle_tox_event(model: &mut Model, event: ToxEvent) -> Vec<Cmd> {
             let pk_opt = model.session.friend_numbers.get(&friend).cloned();
 
             if let Some(pk) = pk_opt
-                && let Some(p) = model.domain.file_transfers.get_mut(&file_id)
+                && let Some(p) = model.domain.file_transfers.record_progress(
+                    &file_id,
+                    model.time_provider.now(),
+                    position + len as u64,
+                )
             {
-                p.update_speed(model.time_provider.now(), position + len as u64);
-                p.transferred = position + len as u64;
                 update = Some((
                     p.transferred,
                     p.total_size,
@@ -1685,9 +1771,11 @@ fn handle_io_event(model: &mut Model, event: IOEvent) -> Vec<Cmd> {
         }
         IOEvent::FileChunkRead(pk, file_id, position, len) => {
             let mut update = None;
-            if let Some(p) = model.domain.file_transfers.get_mut(&file_id) {
-                p.update_speed(model.time_provider.now(), position + len as u64);
-                p.transferred = position + len as u64;
+            if let Some(p) = model.domain.file_transfers.record_progress(
+                &file_id,
+                model.time_provider.now(),
+                position + len as u64,
+            ) {
                 update = Some((
                     p.transferred,
                     p.total_size,
@@ -1700,9 +1788,11 @@ fn handle_io_event(model: &mut Model, event: IOEvent) -> Vec<Cmd> {
         }
         IOEvent::FileChunkWritten(pk, file_id, position, len) => {
             let mut update = None;
-            if let Some(p) = model.domain.file_transfers.get_mut(&file_id) {
-                p.update_speed(model.time_provider.now(), position + len as u64);
-                p.transferred = position + len as u64;
+            if let Some(p) = model.domain.file_transfers.record_progress(
+                &file_id,
+                model.time_provider.now(),
+                position + len as u64,
+            ) {
                 update = Some((
                     p.transferred,
                     p.total_size,
@@ -1896,6 +1986,9 @@ pub fn handle_enter(model: &mut Model, input_line: &str) -> Vec<Cmd> {
                 "Unknown command. Type /help for help.".to_owned(),
             );
             vec![]
+        } else if let WindowId::MerkleTox(id) = window_id {
+            // The message shows up once the client has authored it.
+            vec![Cmd::Tox(ToxAction::MerkleSend(id, input_line.to_owned()))]
         } else {
             let limit = match window_id {
                 WindowId::Group(_) => GROUP_MAX_MESSAGE_LENGTH,
@@ -61,6 +61,7 @@ pub enum SidebarItemType {
     Friend,
     Group,
     Conference,
+    MerkleTox,
     Category,
 }
 
@@ -147,6 +148,7 @@ impl<'a> StatefulWidget for Sidebar<'a> {
                     "Friends" => SidebarItemType::Friend,
                     "Groups" => SidebarItemType::Group,
                     "Conferences" => SidebarItemType::Conference,
+                    "Merkle-Tox" => SidebarItemType::MerkleTox,
                     _ => SidebarItemType::Category,
                 });
 
@@ -165,6 +167,7 @@ impl<'a> StatefulWidget for Sidebar<'a> {
                         Some(SidebarItemType::Friend) => format!("{} 👤 Friends", symbol),
                         Some(SidebarItemType::Group) => format!("{} 👥 Groups", symbol),
                         Some(SidebarItemType::Conference) => format!("{} 🧑‍🤝‍🧑 Conferences", symbol),
+                        Some(SidebarItemType::MerkleTox) => format!("{} 🌳 Merkle-Tox", symbol),
                         _ => format!("{} {}", symbol, item.name),
                     };
                     list_items.push(
@@ -215,6 +218,7 @@ impl<'a> StatefulWidget for Sidebar<'a> {
                     SidebarItemType::Friend => status_symbol,
                     SidebarItemType::Group => "#",
                     SidebarItemType::Conference => "&",
+                    SidebarItemType::MerkleTox => "*",
                     _ => status_symbol,
                 };
                 spans.push(Span::styled(icon, style));
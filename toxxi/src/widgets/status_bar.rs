@@ -28,6 +28,7 @@ pub struct StatusBar {
     pub connection_status: Option<(String, Style)>,
     pub pending_count: usize,
     pub multi_line: bool,
+    pub call: Option<String>,
     pub windows: Vec<StatusWindow>,
 }
 
@@ -44,6 +45,7 @@ impl StatusBar {
             connection_status: None,
             pending_count: 0,
             multi_line: false,
+            call: None,
             windows: Vec::new(),
         }
     }
@@ -83,6 +85,11 @@ impl StatusBar {
         self
     }
 
+    pub fn call(mut self, call: String) -> Self {
+        self.call = Some(call);
+        self
+    }
+
     pub fn windows(mut self, windows: Vec<StatusWindow>) -> Self {
         self.windows = windows;
         self
@@ -164,7 +171,16 @@ impl Widget for StatusBar {
             x += 2;
         }
 
-        // 7. Sparkline (Health)
+        // 7. Call
+        if let Some(call) = &self.call {
+            let call_style = Style::default()
+                .bg(Color::Green)
+                .fg(Color::Black)
+                .add_modifier(Modifier::BOLD);
+            x = draw_bracketed_at(buf, x, y, call, bracket_style, call_style);
+        }
+
+        // 8. Sparkline (Health)
         if !self.dht_health.is_empty() && area.width > 60 {
             let sparkline_text = render_sparkline(
                 &self.dht_health,
@@ -180,7 +196,7 @@ impl Widget for StatusBar {
             }
         }
 
-        // 8. Window List
+        // 9. Window List
         let remaining_width = area.right().saturating_sub(x);
         if remaining_width > 5 && !self.windows.is_empty() {
             draw_window_list(buf, x, y, remaining_width, self.windows);
@@ -1,17 +1,18 @@
 use std::collections::{HashMap, VecDeque};
 use std::error::Error;
-use std::fs;
 use std::path::PathBuf;
-use std::sync::mpsc;
+use std::sync::{Arc, mpsc};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use parking_lot::ReentrantMutex;
 use toxcore::tox::events::Event;
 use toxcore::tox::{
     ADDRESS_SIZE, Address, ConferenceNumber, ConferencePeerNumber, DhtId, FileNumber, FriendNumber,
-    GroupNumber, GroupPeerNumber, Options, Tox, ToxConnection, ToxGroupPrivacyState, ToxLogger,
-    ToxSavedataType, ToxUserStatus,
+    GroupNumber, GroupPeerNumber, Options, Profile, Tox, ToxConnection, ToxGroupPrivacyState,
+    ToxLogger, ToxUserStatus,
 };
+use toxcore::toxav::{CallState, ToxAV, ToxAVEvent, ToxAVEvents};
 use toxcore::types::{
     ChatId, ConferenceId, FileId, GROUP_CHAT_ID_SIZE, PublicKey, Tox_Err_File_Send_Chunk,
     Tox_Err_Friend_Send_Message, ToxError, ToxGroupRole, ToxLogLevel,
@@ -19,10 +20,14 @@ use toxcore::types::{
 
 use crate::bootstrap;
 use crate::config;
+use crate::merkle::MerkleSession;
 use crate::model::{ConferenceReconcileInfo, FriendInfo, GroupReconcileInfo, WindowId};
 use crate::msg::{IOAction, Msg, SystemEvent, ToxAction, ToxEvent};
 use crate::utils::{decode_hex, encode_hex};
 
+/// Opus bit rate for calls, in kbit/s.
+const AUDIO_BIT_RATE: u32 = 48;
+
 fn find_friend(tox: &Tox, pk: &PublicKey) -> Option<FriendNumber> {
     tox.lookup_friend(pk).ok().map(|f| f.get_number())
 }
@@ -63,12 +68,8 @@ pub fn get_initial_state(savedata_path: &Option<PathBuf>) -> Result<InitialState
     temp_opts.set_local_discovery_enabled(false);
     temp_opts.set_experimental_groups_persistence(true);
 
-    if let Some(path) = savedata_path
-        && path.exists()
-        && let Ok(data) = fs::read(path)
-    {
-        temp_opts.set_savedata_type(ToxSavedataType::TOX_SAVEDATA_TYPE_TOX_SAVE);
-        let _ = temp_opts.set_savedata_data(&data);
+    if let Some(path) = savedata_path {
+        Profile::new(path).apply(&mut temp_opts)?;
     }
 
     let temp_tox = Tox::new(temp_opts)?;
@@ -175,6 +176,18 @@ pub fn spawn_tox(
     let mut config = config.clone();
 
     tokio::task::spawn_blocking(move || {
+        let profile = savedata_path.map(Profile::new);
+        let save = |tox: &Tox| {
+            if let Some(profile) = &profile
+                && let Err(e) = profile.save(tox)
+            {
+                let _ = tx.send(Msg::System(SystemEvent::Log {
+                    severity: crate::msg::LogSeverity::Error,
+                    context: crate::msg::LogContext::Global,
+                    message: format!("Failed to save profile: {}", e),
+                }));
+            }
+        };
         let mut exit_thread = false;
         loop {
             let mut opts = match Options::new() {
@@ -196,12 +209,15 @@ pub fn spawn_tox(
             opts.set_end_port(config.end_port);
             opts.set_logger(Logger { tx: tx.clone() });
 
-            if let Some(path) = &savedata_path
-                && path.exists()
-                && let Ok(data) = fs::read(path)
+            if let Some(profile) = &profile
+                && let Err(e) = profile.apply(&mut opts)
             {
-                opts.set_savedata_type(ToxSavedataType::TOX_SAVEDATA_TYPE_TOX_SAVE);
-                let _ = opts.set_savedata_data(&data);
+                let _ = tx.send(Msg::System(SystemEvent::Log {
+                    severity: crate::msg::LogSeverity::Error,
+                    context: crate::msg::LogContext::Global,
+                    message: format!("Failed to load profile: {}", e),
+                }));
+                return;
             }
 
             let tox = match Tox::new(opts) {
@@ -215,6 +231,10 @@ pub fn spawn_tox(
                     return;
                 }
             };
+            // The Merkle-Tox node sends through the same instance. All of it
+            // runs on this thread, so the lock is held for the whole session.
+            let tox_shared = Arc::new(ReentrantMutex::new(tox));
+            let tox = tox_shared.lock();
 
             let _ = tx.send(Msg::Tox(ToxEvent::Address(tox.address())));
             let _ = tx.send(Msg::Tox(ToxEvent::DhtId(tox.dht_id())));
@@ -249,6 +269,36 @@ pub fn spawn_tox(
                 // Logs suppressed from UI to avoid spam
             }
 
+            let mut merkle = match MerkleSession::open(
+                tox_shared.clone(),
+                &config_dir.join("merkle_tox"),
+                tx.clone(),
+            ) {
+                Ok(session) => Some(session),
+                Err(e) => {
+                    let _ = tx.send(Msg::System(SystemEvent::Log {
+                        severity: crate::msg::LogSeverity::Error,
+                        context: crate::msg::LogContext::Global,
+                        message: format!("Failed to open Merkle-Tox store: {}", e),
+                    }));
+                    None
+                }
+            };
+
+            // Frames are dropped: there is no audio device support yet, so
+            // only call state is tracked.
+            let mut av = match ToxAV::new(&tox, ToxAVEvents::without_frames()) {
+                Ok(av) => Some(av),
+                Err(e) => {
+                    let _ = tx.send(Msg::System(SystemEvent::Log {
+                        severity: crate::msg::LogSeverity::Error,
+                        context: crate::msg::LogContext::Global,
+                        message: format!("Failed to create ToxAV: {:?}", e),
+                    }));
+                    None
+                }
+            };
+
             let mut last_online = Instant::now();
             let mut last_bootstrap = Instant::now();
             let mut is_connected = false;
@@ -343,10 +393,7 @@ pub fn spawn_tox(
                 while let Ok(action) = rx_tox_action.try_recv() {
                     match action {
                         ToxAction::Reload(new_config) => {
-                            if let Some(path) = &savedata_path {
-                                let data = tox.savedata();
-                                let _ = fs::write(path, data);
-                            }
+                            save(&tox);
                             config = *new_config;
                             reload_requested = true;
                         }
@@ -933,11 +980,56 @@ pub fn spawn_tox(
                                     .seek(position);
                             }
                         }
-                        ToxAction::Shutdown => {
-                            if let Some(path) = &savedata_path {
-                                let data = tox.savedata();
-                                let _ = fs::write(path, data);
+                        ToxAction::MerkleSend(id, text) => {
+                            if let Some(merkle) = &merkle {
+                                merkle.send_message(id, text);
+                            }
+                        }
+                        ToxAction::MerkleReact(id, target, emoji) => {
+                            if let Some(merkle) = &merkle {
+                                merkle.send_reaction(id, target, emoji);
+                            }
+                        }
+                        ToxAction::MerkleRedact(id, target) => {
+                            if let Some(merkle) = &merkle {
+                                merkle.send_redaction(id, target);
                             }
+                        }
+                        ToxAction::StartCall(pk)
+                        | ToxAction::AnswerCall(pk)
+                        | ToxAction::HangUp(pk)
+                        | ToxAction::SetCallMuted(pk, _) => {
+                            let (Some(av), Some(friend_number)) = (&av, find_friend(&tox, &pk))
+                            else {
+                                continue;
+                            };
+                            let result = match action {
+                                ToxAction::StartCall(_) => {
+                                    av.call(friend_number, AUDIO_BIT_RATE, 0)
+                                }
+                                ToxAction::AnswerCall(_) => {
+                                    av.answer(friend_number, AUDIO_BIT_RATE, 0)
+                                }
+                                ToxAction::HangUp(_) => av.hangup(friend_number),
+                                ToxAction::SetCallMuted(_, muted) => {
+                                    av.set_audio_muted(friend_number, muted)
+                                }
+                                _ => unreachable!(),
+                            };
+                            if let Err(e) = result {
+                                let _ = tx.send(Msg::System(SystemEvent::Log {
+                                    severity: crate::msg::LogSeverity::Error,
+                                    context: crate::msg::LogContext::Friend(pk),
+                                    message: format!("Call control failed: {:?}", e),
+                                }));
+                                let _ = tx.send(Msg::Tox(ToxEvent::CallState(
+                                    friend_number,
+                                    CallState::ERROR,
+                                )));
+                            }
+                        }
+                        ToxAction::Shutdown => {
+                            save(&tox);
                             reload_requested = true;
                             exit_thread = true;
                         }
@@ -953,6 +1045,9 @@ pub fn spawn_tox(
                 // Process Tox Events
                 if let Ok(events) = tox.events() {
                     for event in &events {
+                        if let Some(merkle) = &mut merkle {
+                            merkle.handle_event(&event);
+                        }
                         match event {
                             Event::SelfConnectionStatus(e) => {
                                 let status = e.connection_status();
@@ -1393,8 +1488,40 @@ pub fn spawn_tox(
                     }
                 }
 
-                let interval = tox.iteration_interval();
-                thread::sleep(Duration::from_millis(interval.max(1) as u64));
+                let mut interval = tox.iteration_interval();
+                if let Some(av) = &mut av {
+                    for event in av.events() {
+                        match event {
+                            ToxAVEvent::Call {
+                                friend,
+                                audio_enabled,
+                                video_enabled,
+                            } => {
+                                let _ = tx.send(Msg::Tox(ToxEvent::CallIncoming(
+                                    friend,
+                                    audio_enabled,
+                                    video_enabled,
+                                )));
+                            }
+                            ToxAVEvent::CallState { friend, state } => {
+                                let _ = tx.send(Msg::Tox(ToxEvent::CallState(friend, state)));
+                            }
+                            _ => {}
+                        }
+                    }
+                    interval = interval.min(av.iteration_interval());
+                }
+                let mut sleep = Duration::from_millis(interval.max(1) as u64);
+                if let Some(merkle) = &mut merkle {
+                    let wakeup = merkle.poll();
+                    merkle.drain();
+                    sleep = sleep.min(
+                        wakeup
+                            .saturating_duration_since(Instant::now())
+                            .max(Duration::from_millis(1)),
+                    );
+                }
+                thread::sleep(sleep);
             }
             if exit_thread {
                 break;
@@ -0,0 +1,163 @@
+use toxcore::tox::{Address, FriendNumber, ToxConnection, ToxUserStatus};
+use toxcore::toxav::CallState;
+use toxcore::types::PublicKey;
+use toxxi::config::Config;
+use toxxi::model::{CallStatus, DomainState, FriendInfo, Model, WindowId};
+use toxxi::msg::{Cmd, Msg, ToxAction, ToxEvent};
+use toxxi::update::{handle_command, update};
+
+fn create_test_model() -> Model {
+    let config = Config::default();
+    let domain = DomainState::new(
+        Address([0u8; 38]),
+        PublicKey([0u8; 32]),
+        "Tester".to_string(),
+        "I am a test".to_string(),
+        ToxUserStatus::TOX_USER_STATUS_NONE,
+    );
+    Model::new(domain, config.clone(), config)
+}
+
+fn add_test_friend(model: &mut Model, fid: FriendNumber, pk: PublicKey) {
+    model.session.friend_numbers.insert(fid, pk);
+    model.domain.friends.insert(
+        pk,
+        FriendInfo {
+            name: "Alice".to_string(),
+            public_key: Some(pk),
+            status_message: "".to_string(),
+            connection: ToxConnection::TOX_CONNECTION_UDP,
+            last_sent_message_id: None,
+            last_read_receipt: None,
+            is_typing: false,
+        },
+    );
+    model.ensure_friend_window(pk);
+}
+
+fn open_friend_window(model: &mut Model, pk: PublicKey) {
+    let pos = model
+        .ui
+        .window_ids
+        .iter()
+        .position(|w| *w == WindowId::Friend(pk))
+        .unwrap();
+    model.set_active_window(pos);
+}
+
+#[test]
+fn test_outgoing_call_lifecycle() {
+    let mut model = create_test_model();
+    let fid = FriendNumber(1);
+    let pk = PublicKey([1u8; 32]);
+    add_test_friend(&mut model, fid, pk);
+    open_friend_window(&mut model, pk);
+
+    let cmds = handle_command(&mut model, "/call");
+    assert_eq!(cmds, vec![Cmd::Tox(ToxAction::StartCall(pk))]);
+    assert_eq!(model.session.calls.get(&pk), Some(&CallStatus::Ringing));
+
+    // Calling again while ringing is refused.
+    assert!(handle_command(&mut model, "/call").is_empty());
+    // Muting only applies once the call is up.
+    assert!(handle_command(&mut model, "/call mute").is_empty());
+
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::CallState(
+            fid,
+            CallState::SENDING_A | CallState::ACCEPTING_A,
+        )),
+    );
+    assert_eq!(
+        model.session.calls.get(&pk),
+        Some(&CallStatus::Active { muted: false })
+    );
+
+    let cmds = handle_command(&mut model, "/call mute");
+    assert_eq!(cmds, vec![Cmd::Tox(ToxAction::SetCallMuted(pk, true))]);
+    assert_eq!(
+        model.session.calls.get(&pk),
+        Some(&CallStatus::Active { muted: true })
+    );
+
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::CallState(fid, CallState::FINISHED)),
+    );
+    assert!(model.session.calls.is_empty());
+}
+
+#[test]
+fn test_incoming_call_answer_and_hangup() {
+    let mut model = create_test_model();
+    let fid = FriendNumber(1);
+    let pk = PublicKey([1u8; 32]);
+    add_test_friend(&mut model, fid, pk);
+
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::CallIncoming(fid, true, false)),
+    );
+    assert_eq!(model.session.calls.get(&pk), Some(&CallStatus::Incoming));
+    let conv = model
+        .domain
+        .conversations
+        .get(&WindowId::Friend(pk))
+        .unwrap();
+    assert!(
+        conv.messages
+            .last()
+            .unwrap()
+            .content
+            .contains("Incoming call")
+    );
+
+    open_friend_window(&mut model, pk);
+    let cmds = handle_command(&mut model, "/call");
+    assert_eq!(cmds, vec![Cmd::Tox(ToxAction::AnswerCall(pk))]);
+    assert_eq!(
+        model.session.calls.get(&pk),
+        Some(&CallStatus::Active { muted: false })
+    );
+
+    let cmds = handle_command(&mut model, "/call hangup");
+    assert_eq!(cmds, vec![Cmd::Tox(ToxAction::HangUp(pk))]);
+    assert!(model.session.calls.is_empty());
+    assert!(handle_command(&mut model, "/call hangup").is_empty());
+}
+
+#[test]
+fn test_call_failure_clears_state() {
+    let mut model = create_test_model();
+    let fid = FriendNumber(1);
+    let pk = PublicKey([1u8; 32]);
+    add_test_friend(&mut model, fid, pk);
+    open_friend_window(&mut model, pk);
+
+    handle_command(&mut model, "/call");
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::CallState(fid, CallState::ERROR)),
+    );
+    assert!(model.session.calls.is_empty());
+    let conv = model
+        .domain
+        .conversations
+        .get(&WindowId::Friend(pk))
+        .unwrap();
+    assert!(
+        conv.messages
+            .last()
+            .unwrap()
+            .content
+            .contains("Call failed")
+    );
+}
+
+#[test]
+fn test_call_outside_friend_window() {
+    let mut model = create_test_model();
+    assert!(handle_command(&mut model, "/call").is_empty());
+    assert!(model.session.calls.is_empty());
+}
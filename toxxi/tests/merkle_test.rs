@@ -0,0 +1,179 @@
+use crossterm::event::{Event as CrosstermEvent, KeyCode, KeyEvent, KeyModifiers};
+use toxcore::tox::{Address, ToxUserStatus};
+use toxcore::types::PublicKey;
+use toxxi::config::Config;
+use toxxi::model::{
+    DomainState, MerkleChatMessage, MerkleChatSnapshot, MerkleConversationId, MessageContent,
+    Model, WindowId,
+};
+use toxxi::msg::{Cmd, Msg, ToxAction, ToxEvent};
+use toxxi::update::{handle_command, update};
+
+fn create_test_model() -> Model {
+    let config = Config::default();
+    let domain = DomainState::new(
+        Address([0u8; 38]),
+        PublicKey([0u8; 32]),
+        "Tester".to_string(),
+        "I am a test".to_string(),
+        ToxUserStatus::TOX_USER_STATUS_NONE,
+    );
+    Model::new(domain, config.clone(), config)
+}
+
+fn send_key(model: &mut Model, code: KeyCode) -> Vec<Cmd> {
+    let event = CrosstermEvent::Key(KeyEvent::new(code, KeyModifiers::empty()));
+    update(model, Msg::Input(event))
+}
+
+fn message(hash: u8, sender: &str, is_self: bool, text: &str) -> MerkleChatMessage {
+    MerkleChatMessage {
+        hash: [hash; 32],
+        sender: sender.to_string(),
+        is_self,
+        text: text.to_string(),
+        timestamp_ms: 1_700_000_000_000,
+        reactions: Vec::new(),
+        is_redacted: false,
+    }
+}
+
+fn open_window(model: &mut Model, id: MerkleConversationId) {
+    let window_id = WindowId::MerkleTox(id);
+    let pos = model
+        .ui
+        .window_ids
+        .iter()
+        .position(|w| *w == window_id)
+        .unwrap();
+    model.set_active_window(pos);
+}
+
+#[test]
+fn test_snapshot_populates_window() {
+    let mut model = create_test_model();
+    let id = MerkleConversationId([7u8; 32]);
+
+    let mut hello = message(1, "aabbccdd", false, "hello");
+    hello.reactions = vec![("👍".to_string(), 2)];
+    let mut gone = message(2, "aabbccdd", false, "oops");
+    gone.is_redacted = true;
+    let snapshot = MerkleChatSnapshot {
+        title: "Project".to_string(),
+        topic: "Planning".to_string(),
+        messages: vec![hello, gone, message(3, "01020304", true, "hi")],
+    };
+    update(&mut model, Msg::Tox(ToxEvent::MerkleState(id, snapshot)));
+
+    let window_id = WindowId::MerkleTox(id);
+    assert!(model.ui.window_ids.contains(&window_id));
+    let conv = model.domain.conversations.get(&window_id).unwrap();
+    assert_eq!(conv.name, "Project");
+    assert_eq!(conv.topic.as_deref(), Some("Planning"));
+    assert_eq!(conv.messages.len(), 3);
+    assert_eq!(
+        conv.messages[0].content,
+        MessageContent::Text("hello  [👍 2]".to_string())
+    );
+    assert_eq!(
+        conv.messages[1].content,
+        MessageContent::Text("[redacted]".to_string())
+    );
+    assert!(conv.messages[2].is_self);
+    assert_eq!(
+        model.ui.window_state.get(&window_id).unwrap().unread_count,
+        3
+    );
+}
+
+#[test]
+fn test_snapshot_replaces_messages() {
+    let mut model = create_test_model();
+    let id = MerkleConversationId([7u8; 32]);
+
+    let first = MerkleChatSnapshot {
+        title: String::new(),
+        topic: String::new(),
+        messages: vec![message(1, "aabbccdd", false, "one")],
+    };
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::MerkleState(id, first.clone())),
+    );
+    let mut second = first;
+    second.messages.push(message(2, "aabbccdd", false, "two"));
+    update(&mut model, Msg::Tox(ToxEvent::MerkleState(id, second)));
+
+    let window_id = WindowId::MerkleTox(id);
+    let conv = model.domain.conversations.get(&window_id).unwrap();
+    assert_eq!(conv.name, "Merkle-Tox 07070707");
+    assert_eq!(conv.messages.len(), 2);
+    assert_eq!(
+        model.ui.window_state.get(&window_id).unwrap().unread_count,
+        2
+    );
+}
+
+#[test]
+fn test_enter_sends_to_merkle_conversation() {
+    let mut model = create_test_model();
+    let id = MerkleConversationId([7u8; 32]);
+    model.ensure_merkle_window(id);
+    open_window(&mut model, id);
+
+    for c in "hello tree".chars() {
+        send_key(&mut model, KeyCode::Char(c));
+    }
+    let cmds = send_key(&mut model, KeyCode::Enter);
+
+    assert!(cmds.contains(&Cmd::Tox(ToxAction::MerkleSend(
+        id,
+        "hello tree".to_string()
+    ))));
+    // Shown once the client reports it back.
+    let conv = model
+        .domain
+        .conversations
+        .get(&WindowId::MerkleTox(id))
+        .unwrap();
+    assert!(conv.messages.is_empty());
+}
+
+#[test]
+fn test_react_and_redact_commands() {
+    let mut model = create_test_model();
+    let id = MerkleConversationId([7u8; 32]);
+    let snapshot = MerkleChatSnapshot {
+        title: String::new(),
+        topic: String::new(),
+        messages: vec![
+            message(1, "aabbccdd", false, "older"),
+            message(2, "aabbccdd", false, "newer"),
+        ],
+    };
+    update(&mut model, Msg::Tox(ToxEvent::MerkleState(id, snapshot)));
+    open_window(&mut model, id);
+
+    let cmds = handle_command(&mut model, "/react 1 🎉");
+    assert_eq!(
+        cmds,
+        vec![Cmd::Tox(ToxAction::MerkleReact(
+            id,
+            [2u8; 32],
+            "🎉".to_string()
+        ))]
+    );
+
+    let cmds = handle_command(&mut model, "/redact 2");
+    assert_eq!(cmds, vec![Cmd::Tox(ToxAction::MerkleRedact(id, [1u8; 32]))]);
+
+    let cmds = handle_command(&mut model, "/redact 3");
+    assert!(cmds.is_empty());
+}
+
+#[test]
+fn test_react_outside_merkle_conversation() {
+    let mut model = create_test_model();
+    let cmds = handle_command(&mut model, "/react 1 🎉");
+    assert!(cmds.is_empty());
+}
@@ -374,7 +374,7 @@ prop_compose! {
             tox_logs,
             pending_items,
             next_internal_id,
-            file_transfers,
+            file_transfers: file_transfers.into(),
         }
     }
 }
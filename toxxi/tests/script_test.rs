@@ -2,8 +2,15 @@ use std::fs::File;
 use std::io::Write;
 use std::sync::mpsc;
 use tempfile::tempdir;
-use toxxi::msg::{Msg, SystemEvent};
-use toxxi::script::{ScriptRequest, ScriptResponse, spawn_script};
+use toxcore::tox::{Address, FriendNumber, ToxConnection, ToxUserStatus};
+use toxcore::types::{MessageType, PublicKey};
+use toxxi::config::Config;
+use toxxi::model::{DomainState, FriendInfo, Model};
+use toxxi::msg::{Msg, SystemEvent, ToxEvent};
+use toxxi::script::{
+    ScriptFriend, ScriptQuery, ScriptRequest, ScriptResponse, answer_query, spawn_script,
+};
+use toxxi::update::update;
 
 #[test]
 fn test_script_execution_flow() {
@@ -64,3 +71,141 @@ fn test_script_execution_flow() {
     // Script should finish
     handle.join().unwrap().expect("Script execution failed");
 }
+
+fn create_test_model() -> Model {
+    let config = Config::default();
+    let domain = DomainState::new(
+        Address([0u8; 38]),
+        PublicKey([0u8; 32]),
+        "Tester".to_string(),
+        "I am a test".to_string(),
+        ToxUserStatus::TOX_USER_STATUS_NONE,
+    );
+    let mut model = Model::new(domain, config.clone(), config);
+    let pk = PublicKey([1u8; 32]);
+    model.session.friend_numbers.insert(FriendNumber(0), pk);
+    model.domain.friends.insert(
+        pk,
+        FriendInfo {
+            name: "Alice".to_string(),
+            public_key: Some(pk),
+            status_message: "Busy".to_string(),
+            connection: ToxConnection::TOX_CONNECTION_NONE,
+            last_sent_message_id: None,
+            last_read_receipt: None,
+            is_typing: false,
+        },
+    );
+    model.ensure_friend_window(pk);
+    model
+}
+
+/// Runs `source` against `model`, answering its requests the way the main
+/// loop does.
+fn run_script(model: &mut Model, source: &str) -> Result<(), String> {
+    let dir = tempdir().unwrap();
+    let script_path = dir.path().join("test.rhai");
+    File::create(&script_path)
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+
+    let (tx_msg, rx_msg) = mpsc::channel();
+    let (handle, tx_res) = spawn_script(script_path, tx_msg);
+    while let Ok(msg) = rx_msg.recv_timeout(std::time::Duration::from_secs(5)) {
+        match msg {
+            Msg::System(SystemEvent::ScriptRequest(ScriptRequest::Query(query))) => {
+                tx_res.send(answer_query(model, &query)).unwrap();
+            }
+            Msg::System(SystemEvent::ScriptRequest(_)) => {
+                tx_res.send(ScriptResponse::Ok).unwrap();
+            }
+            msg => {
+                update(model, msg);
+            }
+        }
+    }
+    handle.join().unwrap().map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_answer_query() {
+    let mut model = create_test_model();
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::Message(
+            FriendNumber(0),
+            MessageType::TOX_MESSAGE_TYPE_NORMAL,
+            "hello".to_string(),
+        )),
+    );
+
+    match answer_query(&model, &ScriptQuery::Friends) {
+        ScriptResponse::Friends(friends) => assert_eq!(
+            friends,
+            vec![ScriptFriend {
+                id: 0,
+                name: "Alice".to_string(),
+                status_message: "Busy".to_string(),
+                online: false,
+            }]
+        ),
+        r => panic!("Unexpected response {:?}", r),
+    }
+    match answer_query(&model, &ScriptQuery::Messages(0, 5)) {
+        ScriptResponse::Messages(messages) => {
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].text, "hello");
+            assert!(!messages[0].is_self);
+        }
+        r => panic!("Unexpected response {:?}", r),
+    }
+    match answer_query(&model, &ScriptQuery::Messages(7, 5)) {
+        ScriptResponse::Messages(messages) => assert!(messages.is_empty()),
+        r => panic!("Unexpected response {:?}", r),
+    }
+    match answer_query(&model, &ScriptQuery::SelfConnection) {
+        ScriptResponse::Connection(status) => assert_eq!(status, "none"),
+        r => panic!("Unexpected response {:?}", r),
+    }
+}
+
+#[test]
+fn test_script_queries_and_injected_events() {
+    let mut model = create_test_model();
+    run_script(
+        &mut model,
+        r#"
+        assert(connection() == "none", "offline at start");
+        let f = friends();
+        assert(f.len() == 1 && f[0].name == "Alice" && !f[0].online);
+
+        inject_friend_online(0, true);
+        inject_friend_msg(0, "ping");
+        wait_until(|| friends()[0].online, 1000);
+        wait_until(|| messages(0, 1).len() == 1, 1000);
+        assert(messages(0, 1)[0].text == "ping", "last message");
+    "#,
+    )
+    .expect("Script execution failed");
+
+    let pk = PublicKey([1u8; 32]);
+    assert_eq!(
+        model.domain.friends.get(&pk).unwrap().connection,
+        ToxConnection::TOX_CONNECTION_UDP
+    );
+}
+
+#[test]
+fn test_script_assertion_failures() {
+    let mut model = create_test_model();
+    let err = run_script(
+        &mut model,
+        r#"assert(friends().len() == 2, "two friends");"#,
+    )
+    .unwrap_err();
+    assert!(err.contains("Assertion failed: two friends"), "{}", err);
+
+    let err = run_script(&mut model, r#"wait_until(|| friends()[0].online, 100);"#).unwrap_err();
+    assert!(err.contains("Condition not met within 100 ms"), "{}", err);
+}
@@ -0,0 +1,229 @@
+use std::time::{Duration, Instant};
+use toxcore::tox::{Address, FriendNumber, ToxConnection, ToxUserStatus};
+use toxcore::types::{FileId, PublicKey, ToxFileControl};
+use toxxi::config::{AutoAcceptRule, Config};
+use toxxi::model::{
+    DomainState, FileTransferProgress, FriendInfo, Model, TransferManager, TransferStatus,
+};
+use toxxi::msg::{Cmd, IOAction, Msg, ToxAction, ToxEvent};
+use toxxi::update::{handle_command, update};
+
+fn create_test_model() -> Model {
+    let config = Config::default();
+    let domain = DomainState::new(
+        Address([0u8; 38]),
+        PublicKey([0u8; 32]),
+        "Tester".to_string(),
+        "I am a test".to_string(),
+        ToxUserStatus::TOX_USER_STATUS_NONE,
+    );
+    Model::new(domain, config.clone(), config)
+}
+
+fn add_test_friend(model: &mut Model, fid: FriendNumber, pk: PublicKey) {
+    model.session.friend_numbers.insert(fid, pk);
+    model.domain.friends.insert(
+        pk,
+        FriendInfo {
+            name: format!("Friend {}", fid.0),
+            public_key: Some(pk),
+            status_message: "".to_string(),
+            connection: ToxConnection::TOX_CONNECTION_TCP,
+            last_sent_message_id: None,
+            last_read_receipt: None,
+            is_typing: false,
+        },
+    );
+    model.ensure_friend_window(pk);
+}
+
+fn progress(start: Instant, total_size: u64) -> FileTransferProgress {
+    FileTransferProgress {
+        filename: "data.bin".to_string(),
+        total_size,
+        transferred: 0,
+        is_receiving: true,
+        status: TransferStatus::Active,
+        file_kind: 0,
+        file_path: None,
+        speed: 0.0,
+        last_update: start,
+        last_transferred: 0,
+        friend_pk: PublicKey([1u8; 32]),
+    }
+}
+
+fn accepted(cmds: &[Cmd], file_id: FileId) -> bool {
+    cmds.iter().any(|c| {
+        matches!(c, Cmd::Tox(ToxAction::FileControl(_, id, ToxFileControl::TOX_FILE_CONTROL_RESUME)) if *id == file_id)
+    })
+}
+
+#[test]
+fn test_throughput_tracks_average_and_peak() {
+    let start = Instant::now();
+    let file_id = FileId([1u8; 32]);
+    let mut transfers = TransferManager::new();
+    transfers.insert(file_id, progress(start, 10_000));
+
+    transfers.record_progress(&file_id, start + Duration::from_secs(1), 4000);
+    transfers.record_progress(&file_id, start + Duration::from_secs(2), 5000);
+
+    let t = transfers
+        .throughput(&file_id, start + Duration::from_secs(2))
+        .unwrap();
+    assert_eq!(t.current, 1000.0);
+    assert_eq!(t.peak, 4000.0);
+    assert_eq!(t.average, 2500.0);
+    assert_eq!(t.elapsed, Duration::from_secs(2));
+    assert_eq!(transfers.get(&file_id).unwrap().transferred, 5000);
+
+    transfers.remove(&file_id);
+    assert!(transfers.throughput(&file_id, start).is_none());
+}
+
+#[test]
+fn test_control_updates_status() {
+    let file_id = FileId([1u8; 32]);
+    let mut transfers = TransferManager::new();
+    transfers.insert(file_id, progress(Instant::now(), 100));
+
+    assert!(transfers.control(&file_id, ToxFileControl::TOX_FILE_CONTROL_PAUSE));
+    assert_eq!(
+        transfers.get(&file_id).unwrap().status,
+        TransferStatus::Paused
+    );
+    assert!(transfers.control(&file_id, ToxFileControl::TOX_FILE_CONTROL_RESUME));
+    assert_eq!(
+        transfers.get(&file_id).unwrap().status,
+        TransferStatus::Active
+    );
+    assert!(!transfers.control(&FileId([2u8; 32]), ToxFileControl::TOX_FILE_CONTROL_CANCEL));
+}
+
+#[test]
+fn test_auto_accept_rule_matching() {
+    let alice = PublicKey([1u8; 32]);
+    let bob = PublicKey([2u8; 32]);
+    let rule = AutoAcceptRule {
+        friend: Some(alice),
+        extensions: vec!["jpg".to_string(), "png".to_string()],
+        max_size: Some(1000),
+    };
+
+    assert!(rule.matches(&alice, "cat.PNG", 1000));
+    assert!(!rule.matches(&bob, "cat.png", 10));
+    assert!(!rule.matches(&alice, "cat.png", 1001));
+    assert!(!rule.matches(&alice, "notes.txt", 10));
+    assert!(!rule.matches(&alice, "png", 10));
+    assert!(AutoAcceptRule::default().matches(&bob, "anything", u64::MAX));
+}
+
+#[test]
+fn test_file_offer_auto_accepted_by_rule() {
+    let mut model = create_test_model();
+    let fid = FriendNumber(1);
+    let pk = PublicKey([1u8; 32]);
+    add_test_friend(&mut model, fid, pk);
+    model.config.auto_accept.push(AutoAcceptRule {
+        friend: Some(pk),
+        extensions: vec!["txt".to_string()],
+        max_size: None,
+    });
+
+    let small = FileId([10u8; 32]);
+    let cmds = update(
+        &mut model,
+        Msg::Tox(ToxEvent::FileRecv(
+            fid,
+            small,
+            0,
+            10,
+            "notes.txt".to_string(),
+        )),
+    );
+    assert!(accepted(&cmds, small));
+    assert!(cmds.iter().any(
+        |c| matches!(c, Cmd::IO(IOAction::OpenFileForReceiving(_, id, name, 10)) if *id == small && name == "notes.txt")
+    ));
+
+    let other = FileId([11u8; 32]);
+    let cmds = update(
+        &mut model,
+        Msg::Tox(ToxEvent::FileRecv(
+            fid,
+            other,
+            0,
+            10,
+            "movie.mkv".to_string(),
+        )),
+    );
+    assert!(!accepted(&cmds, other));
+}
+
+#[test]
+fn test_autoaccept_command_persists_rules() {
+    let mut model = create_test_model();
+    let pk = PublicKey([1u8; 32]);
+    add_test_friend(&mut model, FriendNumber(1), pk);
+
+    let cmds = handle_command(&mut model, "/file autoaccept add 1 .JPG,png 5000");
+    assert_eq!(cmds, vec![Cmd::IO(IOAction::SaveConfig(None))]);
+    let rule = AutoAcceptRule {
+        friend: Some(pk),
+        extensions: vec!["jpg".to_string(), "png".to_string()],
+        max_size: Some(5000),
+    };
+    assert_eq!(model.config.auto_accept, vec![rule.clone()]);
+    assert_eq!(model.saved_config.auto_accept, vec![rule]);
+
+    let cmds = handle_command(&mut model, "/file autoaccept add * *");
+    assert_eq!(cmds, vec![Cmd::IO(IOAction::SaveConfig(None))]);
+    assert_eq!(model.config.auto_accept[1], AutoAcceptRule::default());
+
+    assert!(handle_command(&mut model, "/file autoaccept add 7").is_empty());
+    assert_eq!(model.config.auto_accept.len(), 2);
+
+    let cmds = handle_command(&mut model, "/file autoaccept remove 1");
+    assert_eq!(cmds, vec![Cmd::IO(IOAction::SaveConfig(None))]);
+    assert_eq!(model.config.auto_accept, vec![AutoAcceptRule::default()]);
+    assert_eq!(
+        model.saved_config.auto_accept,
+        vec![AutoAcceptRule::default()]
+    );
+
+    assert!(handle_command(&mut model, "/file autoaccept remove 5").is_empty());
+}
+
+#[test]
+fn test_pause_command_updates_transfer() {
+    let mut model = create_test_model();
+    let pk = PublicKey([1u8; 32]);
+    add_test_friend(&mut model, FriendNumber(1), pk);
+    let file_id = FileId([10u8; 32]);
+    update(
+        &mut model,
+        Msg::Tox(ToxEvent::FileRecv(
+            FriendNumber(1),
+            file_id,
+            0,
+            10,
+            "notes.txt".to_string(),
+        )),
+    );
+
+    let cmd = format!("/file pause 1 {}", file_id);
+    let cmds = handle_command(&mut model, &cmd);
+    assert_eq!(
+        cmds,
+        vec![Cmd::Tox(ToxAction::FileControl(
+            pk,
+            file_id,
+            ToxFileControl::TOX_FILE_CONTROL_PAUSE
+        ))]
+    );
+    assert_eq!(
+        model.domain.file_transfers.get(&file_id).unwrap().status,
+        TransferStatus::Paused
+    );
+}